@@ -9,11 +9,22 @@ impl<T: Debug> Bvh<T> {
     /// Returns the closest element hit by the ray and the intersection distance (t) along the ray.
     ///
     /// If no element is hit, returns `None`.
-    #[allow(clippy::excessive_nesting)]
     pub fn first_ray_collision(
         &self,
         ray: Ray,
         get_aabb: impl Fn(&T) -> Aabb,
+    ) -> Option<(&T, NotNan<f32>)> {
+        self.first_ray_collision_where(ray, get_aabb, |_| true)
+    }
+
+    /// Like [`Bvh::first_ray_collision`], but the ray passes through elements for which `accept`
+    /// returns `false`.
+    #[allow(clippy::excessive_nesting)]
+    pub fn first_ray_collision_where(
+        &self,
+        ray: Ray,
+        get_aabb: impl Fn(&T) -> Aabb,
+        accept: impl Fn(&T) -> bool,
     ) -> Option<(&T, NotNan<f32>)> {
         let mut closest_t = NotNan::new(f32::INFINITY).unwrap();
         let mut closest_elem = None;
@@ -27,6 +38,7 @@ impl<T: Debug> Bvh<T> {
                     if let Some(t) = get_aabb(elem).intersect_ray(&ray)
                         && t < closest_t
                         && t.into_inner() >= 0.0
+                        && accept(elem)
                     {
                         closest_t = t;
                         closest_elem = Some(elem);
@@ -73,6 +85,7 @@ impl<T: Debug> Bvh<T> {
                                     if let Some(t) = get_aabb(elem).intersect_ray(&ray)
                                         && t < closest_t
                                         && t.into_inner() >= 0.0
+                                        && accept(elem)
                                     {
                                         closest_t = t;
                                         closest_elem = Some(elem);
@@ -62,6 +62,27 @@ fn test_multiple_aabbs_along_ray() {
     assert!(dist < NotNan::new(2.0).unwrap());
 }
 
+#[test]
+fn test_ray_passes_through_rejected_aabbs() {
+    let elements = vec![
+        Aabb::new(Vec3::new(1.0, -0.5, -0.5), Vec3::new(2.0, 0.5, 0.5)),
+        Aabb::new(Vec3::new(3.0, -1.0, -1.0), Vec3::new(4.0, 1.0, 1.0)),
+        Aabb::new(Vec3::new(5.0, -0.5, -0.5), Vec3::new(6.0, 0.5, 0.5)),
+    ];
+
+    let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+    let bvh = Bvh::build(elements.clone(), |x| *x);
+    let (closest, _) = bvh
+        .first_ray_collision_where(ray, |x| *x, |x| *x != elements[0])
+        .expect("Should find intersection");
+    assert_eq!(closest, &elements[1]);
+
+    assert!(
+        bvh.first_ray_collision_where(ray, |x| *x, |_| false)
+            .is_none()
+    );
+}
+
 proptest! {
     #[test]
     fn test_rays_origin_inside_aabb(
@@ -0,0 +1,191 @@
+//! What bots do after they have joined.
+
+use std::{f64::consts::TAU, time::Duration};
+
+use glam::DVec3;
+use rand::{Rng, rngs::StdRng};
+
+/// The distance a bot walks every tick, which is slightly below the walking speed of a player
+pub const WALK_SPEED: f64 = 0.2;
+
+/// Random walks turn back towards the spawn once they are this far away from it
+const MAX_WANDER_DISTANCE: f64 = 32.0;
+
+/// Following bots stop once they are this close to their target
+const FOLLOW_DISTANCE: f64 = 2.0;
+
+/// How a bot moves every tick
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Movement {
+    /// Stands still at the spawn
+    #[default]
+    Idle,
+    /// Walks in a random direction which changes now and then, without wandering far from the
+    /// spawn
+    RandomWalk,
+    /// Walks in a circle with the given radius which passes through the spawn
+    Circle { radius: f64 },
+    /// Walks towards the closest player it can see
+    Follow,
+}
+
+/// What every bot of a load test does
+#[derive(Clone, Debug, PartialEq)]
+pub struct Behavior {
+    pub movement: Movement,
+    /// How often every bot sends a chat message, if at all
+    pub chat_interval: Option<Duration>,
+}
+
+impl Default for Behavior {
+    fn default() -> Self {
+        Self {
+            movement: Movement::RandomWalk,
+            chat_interval: None,
+        }
+    }
+}
+
+/// The position of a bot as it moves according to a [`Movement`]
+#[derive(Clone, Debug)]
+pub struct Walker {
+    spawn: DVec3,
+    position: DVec3,
+    /// The direction the bot walks in, in radians
+    heading: f64,
+    /// The angle around the center of a [`Movement::Circle`], in radians
+    angle: f64,
+}
+
+impl Walker {
+    #[must_use]
+    pub const fn new(spawn: DVec3) -> Self {
+        Self {
+            spawn,
+            position: spawn,
+            heading: 0.0,
+            angle: 0.0,
+        }
+    }
+
+    #[must_use]
+    pub const fn position(&self) -> DVec3 {
+        self.position
+    }
+
+    /// Moves the bot to `position`, such as after the server teleported it
+    pub const fn teleport(&mut self, position: DVec3) {
+        self.position = position;
+    }
+
+    /// Moves the bot by one tick and returns its new position. `players` are the positions of the
+    /// players the bot can see.
+    pub fn step(
+        &mut self,
+        movement: Movement,
+        rng: &mut StdRng,
+        players: impl IntoIterator<Item = DVec3>,
+    ) -> DVec3 {
+        match movement {
+            Movement::Idle => {}
+            Movement::RandomWalk => {
+                let offset = self.position - self.spawn;
+                if offset.x.hypot(offset.z) > MAX_WANDER_DISTANCE {
+                    self.heading = (-offset.z).atan2(-offset.x);
+                } else if rng.random_bool(0.05) {
+                    self.heading = rng.random_range(0.0..TAU);
+                }
+
+                self.walk_towards_heading(WALK_SPEED);
+            }
+            Movement::Circle { radius } if radius > 0.0 => {
+                // The circle starts at the spawn, so the bot does not jump to it
+                let center = self.spawn - DVec3::new(radius, 0.0, 0.0);
+                self.angle = (self.angle + WALK_SPEED / radius) % TAU;
+                self.position = DVec3::new(
+                    center.x + radius * self.angle.cos(),
+                    self.position.y,
+                    center.z + radius * self.angle.sin(),
+                );
+            }
+            Movement::Circle { .. } => {}
+            Movement::Follow => {
+                let closest = players.into_iter().min_by(|a, b| {
+                    a.distance_squared(self.position)
+                        .total_cmp(&b.distance_squared(self.position))
+                });
+
+                if let Some(target) = closest {
+                    let offset = target - self.position;
+                    let distance = offset.x.hypot(offset.z);
+                    if distance > FOLLOW_DISTANCE {
+                        self.heading = offset.z.atan2(offset.x);
+                        self.walk_towards_heading(WALK_SPEED.min(distance - FOLLOW_DISTANCE));
+                    }
+                }
+            }
+        }
+
+        self.position
+    }
+
+    fn walk_towards_heading(&mut self, distance: f64) {
+        self.position.x += distance * self.heading.cos();
+        self.position.z += distance * self.heading.sin();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+
+    #[test]
+    fn bots_never_move_faster_than_walking() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let movements = [
+            Movement::RandomWalk,
+            Movement::Circle { radius: 8.0 },
+            Movement::Follow,
+        ];
+
+        for movement in movements {
+            let mut walker = Walker::new(DVec3::new(0.5, 64.0, 0.5));
+            let target = DVec3::new(50.0, 64.0, -20.0);
+
+            for _ in 0..1000 {
+                let before = walker.position();
+                let after = walker.step(movement, &mut rng, [target]);
+                assert!(before.distance(after) <= WALK_SPEED + 1e-9, "{movement:?}");
+                assert!((after.y - 64.0).abs() < f64::EPSILON);
+            }
+        }
+    }
+
+    #[test]
+    fn random_walks_stay_near_the_spawn() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let spawn = DVec3::new(0.0, 64.0, 0.0);
+        let mut walker = Walker::new(spawn);
+
+        for _ in 0..10_000 {
+            let position = walker.step(Movement::RandomWalk, &mut rng, []);
+            assert!(position.distance(spawn) <= MAX_WANDER_DISTANCE + WALK_SPEED);
+        }
+    }
+
+    #[test]
+    fn following_bots_stop_near_their_target() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let target = DVec3::new(10.0, 64.0, 0.0);
+        let mut walker = Walker::new(DVec3::new(0.0, 64.0, 0.0));
+
+        for _ in 0..100 {
+            walker.step(Movement::Follow, &mut rng, [target]);
+        }
+
+        let distance = walker.position().distance(target);
+        assert!((distance - FOLLOW_DISTANCE).abs() < 1e-6, "{distance}");
+    }
+}
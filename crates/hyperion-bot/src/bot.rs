@@ -0,0 +1,353 @@
+//! A single bot, from connecting to disconnecting.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::bail;
+use glam::DVec3;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use tokio::sync::watch;
+use tracing::{debug, warn};
+use valence_protocol::{
+    Bounded, CompressionThreshold, Hand, PROTOCOL_VERSION, Packet, VarInt,
+    packets::{
+        handshaking::{HandshakeC2s, handshake_c2s::HandshakeNextState},
+        login::{LoginCompressionS2c, LoginDisconnectS2c, LoginHelloC2s, LoginSuccessS2c},
+        play::{
+            ChatMessageC2s, DisconnectS2c, EntitiesDestroyS2c, EntityPositionS2c, KeepAliveC2s,
+            KeepAliveS2c, MoveRelativeS2c, PlayerActionResponseS2c, PlayerInteractItemC2s,
+            PlayerPositionLookS2c, PlayerSpawnS2c, PositionAndOnGroundC2s,
+            RotateAndMoveRelativeS2c, TeleportConfirmC2s,
+        },
+    },
+};
+
+use crate::{
+    behavior::{Behavior, Walker},
+    connection::{Connection, Frame},
+    stats::Stats,
+};
+
+/// The time between two ticks of a bot, which is the same as a tick of the server
+pub const TICK: Duration = Duration::from_millis(50);
+
+/// How often a bot measures its round trip time
+const RTT_PROBE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The number of unanswered round trip probes a bot remembers
+const MAX_PENDING_PROBES: usize = 16;
+
+/// Everything a bot needs to know to join
+#[derive(Clone, Debug)]
+pub struct BotConfig {
+    /// The index of the bot in its load test
+    pub index: usize,
+    /// The username of the bot, which is at most 16 characters long
+    pub username: String,
+    /// The address of the proxy the bot connects to
+    pub address: SocketAddr,
+    pub behavior: Behavior,
+    /// The seed of the random movement of the bot
+    pub seed: u64,
+}
+
+/// Round trip time measurements which have been sent but not answered yet.
+///
+/// Keep alive IDs are chosen by the server, which uses its tick rather than a timestamp, so the
+/// bot can't tell how long ago a keep alive was sent. Instead, the bot uses an empty hand with a
+/// sequence number of its own, which the server acknowledges like every other interaction, and
+/// remembers when it sent each sequence number.
+#[derive(Debug, Default)]
+struct RttProbes {
+    /// The sequence numbers of the probes by when they were sent, from oldest to newest
+    pending: VecDeque<(i32, Instant)>,
+    next_sequence: i32,
+    last_sent: Option<Instant>,
+}
+
+impl RttProbes {
+    /// Returns the sequence number of a new probe if the last one was sent long enough ago
+    fn next(&mut self, now: Instant) -> Option<i32> {
+        if self
+            .last_sent
+            .is_some_and(|last_sent| now - last_sent < RTT_PROBE_INTERVAL)
+        {
+            return None;
+        }
+
+        if self.pending.len() == MAX_PENDING_PROBES {
+            self.pending.pop_front();
+        }
+
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+        self.pending.push_back((self.next_sequence, now));
+        self.last_sent = Some(now);
+        Some(self.next_sequence)
+    }
+
+    /// Returns the round trip time of the probe with the sequence number `sequence`. The server
+    /// only acknowledges the highest sequence number it received in a tick, so older probes are
+    /// answered as well.
+    fn answer(&mut self, sequence: i32, now: Instant) -> Option<Duration> {
+        let answered = self.pending.iter().position(|&(id, _)| id == sequence)?;
+        let (_, sent) = self.pending.drain(..=answered).last()?;
+        Some(now - sent)
+    }
+}
+
+/// The current time as a timestamp of a chat message
+fn chat_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| u64::try_from(now.as_millis()).unwrap_or(u64::MAX))
+}
+
+/// Runs a bot until `shutdown` changes to `true` or the server disconnects it
+pub async fn run_bot(config: BotConfig, stats: Arc<Stats>, mut shutdown: watch::Receiver<bool>) {
+    let mut bot = match Bot::join(&config, stats.clone()).await {
+        Ok(bot) => {
+            stats.add_connected();
+            bot
+        }
+        Err(e) => {
+            warn!("{} failed to join: {e:#}", config.username);
+            stats.add_failed();
+            return;
+        }
+    };
+
+    let result = tokio::select! {
+        result = bot.play(&config) => result,
+        _ = shutdown.wait_for(|&shutdown| shutdown) => Ok(()),
+    };
+
+    if let Err(e) = result {
+        warn!("{} was disconnected: {e:#}", config.username);
+        stats.add_disconnected();
+    }
+}
+
+struct Bot {
+    connection: Connection,
+    stats: Arc<Stats>,
+    rng: StdRng,
+    /// The position of the bot, which is only known after the server has teleported it
+    walker: Option<Walker>,
+    /// The positions of the players the bot can see by their entity ID
+    players: HashMap<i32, DVec3>,
+    last_chat: Instant,
+    chat_count: u64,
+    probes: RttProbes,
+}
+
+impl Bot {
+    /// Connects to the server and logs in
+    async fn join(config: &BotConfig, stats: Arc<Stats>) -> anyhow::Result<Self> {
+        let mut connection = Connection::connect(config.address, stats.clone()).await?;
+
+        let server_address = config.address.ip().to_string();
+        connection
+            .send(&HandshakeC2s {
+                protocol_version: VarInt(PROTOCOL_VERSION),
+                server_address: Bounded(server_address.as_str()),
+                server_port: config.address.port(),
+                next_state: HandshakeNextState::Login,
+            })
+            .await?;
+
+        connection
+            .send(&LoginHelloC2s {
+                username: Bounded(config.username.as_str()),
+                // Bots can only join offline-mode servers, which use the offline UUID
+                profile_id: None,
+            })
+            .await?;
+
+        loop {
+            let frame = connection.recv().await?;
+            match frame.id {
+                LoginCompressionS2c::ID => {
+                    let packet: LoginCompressionS2c = frame.decode()?;
+                    connection.set_compression(CompressionThreshold(packet.threshold.0));
+                }
+                LoginSuccessS2c::ID => break,
+                LoginDisconnectS2c::ID => {
+                    let packet: LoginDisconnectS2c<'_> = frame.decode()?;
+                    bail!("kicked while logging in: {}", packet.reason);
+                }
+                id => debug!("ignoring login packet {id:#x}"),
+            }
+        }
+
+        Ok(Self {
+            connection,
+            stats,
+            rng: StdRng::seed_from_u64(config.seed),
+            walker: None,
+            players: HashMap::new(),
+            last_chat: Instant::now(),
+            chat_count: 0,
+            probes: RttProbes::default(),
+        })
+    }
+
+    /// Plays until the server disconnects the bot
+    async fn play(&mut self, config: &BotConfig) -> anyhow::Result<()> {
+        let mut ticks = tokio::time::interval(TICK);
+        // Spread the ticks of different bots so they do not all send at the same time
+        ticks.reset_after(TICK.mul_f64(self.rng.random()));
+
+        loop {
+            tokio::select! {
+                frame = self.connection.recv() => self.handle(config, &frame?).await?,
+                _ = ticks.tick() => self.tick(config).await?,
+            }
+        }
+    }
+
+    async fn handle(&mut self, config: &BotConfig, frame: &Frame) -> anyhow::Result<()> {
+        match frame.id {
+            KeepAliveS2c::ID => {
+                let packet: KeepAliveS2c = frame.decode()?;
+                self.connection
+                    .send(&KeepAliveC2s { id: packet.id })
+                    .await?;
+            }
+            PlayerActionResponseS2c::ID => {
+                let packet: PlayerActionResponseS2c = frame.decode()?;
+                if let Some(rtt) = self.probes.answer(packet.sequence.0, Instant::now()) {
+                    self.stats.set_rtt(config.index, rtt);
+                }
+            }
+            PlayerPositionLookS2c::ID => {
+                let packet: PlayerPositionLookS2c = frame.decode()?;
+                match &mut self.walker {
+                    Some(walker) => walker.teleport(packet.position),
+                    None => self.walker = Some(Walker::new(packet.position)),
+                }
+                self.connection
+                    .send(&TeleportConfirmC2s {
+                        teleport_id: packet.teleport_id,
+                    })
+                    .await?;
+            }
+            PlayerSpawnS2c::ID => {
+                let packet: PlayerSpawnS2c = frame.decode()?;
+                self.players.insert(packet.entity_id.0, packet.position);
+            }
+            EntityPositionS2c::ID => {
+                let packet: EntityPositionS2c = frame.decode()?;
+                if let Some(position) = self.players.get_mut(&packet.entity_id.0) {
+                    *position = packet.position;
+                }
+            }
+            MoveRelativeS2c::ID => {
+                let packet: MoveRelativeS2c = frame.decode()?;
+                self.move_player(packet.entity_id, packet.delta);
+            }
+            RotateAndMoveRelativeS2c::ID => {
+                let packet: RotateAndMoveRelativeS2c = frame.decode()?;
+                self.move_player(packet.entity_id, packet.delta);
+            }
+            EntitiesDestroyS2c::ID => {
+                let packet: EntitiesDestroyS2c<'_> = frame.decode()?;
+                for id in &*packet.entity_ids {
+                    self.players.remove(&id.0);
+                }
+            }
+            DisconnectS2c::ID => {
+                let packet: DisconnectS2c<'_> = frame.decode()?;
+                bail!("kicked: {}", packet.reason);
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Applies a relative move in 1/4096 blocks to a player the bot can see
+    fn move_player(&mut self, entity_id: VarInt, delta: [i16; 3]) {
+        if let Some(position) = self.players.get_mut(&entity_id.0) {
+            *position += DVec3::new(delta[0].into(), delta[1].into(), delta[2].into()) / 4096.0;
+        }
+    }
+
+    async fn tick(&mut self, config: &BotConfig) -> anyhow::Result<()> {
+        if let Some(walker) = &mut self.walker {
+            let before = walker.position();
+            let position = walker.step(
+                config.behavior.movement,
+                &mut self.rng,
+                self.players.values().copied(),
+            );
+
+            if position != before {
+                self.connection
+                    .send(&PositionAndOnGroundC2s {
+                        position,
+                        on_ground: true,
+                    })
+                    .await?;
+            }
+        }
+
+        if let Some(sequence) = self.probes.next(Instant::now()) {
+            self.connection
+                .send(&PlayerInteractItemC2s {
+                    hand: Hand::Main,
+                    sequence: VarInt(sequence),
+                })
+                .await?;
+        }
+
+        if let Some(interval) = config.behavior.chat_interval
+            && self.last_chat.elapsed() >= interval
+        {
+            self.last_chat = Instant::now();
+            self.chat_count += 1;
+
+            let message = format!("message {} from {}", self.chat_count, config.username);
+            self.connection
+                .send(&ChatMessageC2s {
+                    message: Bounded(message.as_str()),
+                    timestamp: chat_timestamp(),
+                    salt: self.rng.random(),
+                    signature: None,
+                    message_count: VarInt(0),
+                    acknowledgement: [0; 3],
+                })
+                .await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn probes_measure_the_answered_sequence() {
+        let start = Instant::now();
+        let mut probes = RttProbes::default();
+
+        assert_eq!(probes.next(start), Some(1));
+        assert_eq!(probes.next(start + TICK), None);
+
+        let second = start + RTT_PROBE_INTERVAL;
+        assert_eq!(probes.next(second), Some(2));
+
+        // Acknowledging the second probe answers the first one as well
+        let rtt = probes.answer(2, second + Duration::from_millis(30));
+        assert_eq!(rtt, Some(Duration::from_millis(30)));
+        assert!(probes.pending.is_empty());
+
+        // Sequence numbers the bot did not send are ignored
+        assert_eq!(probes.answer(7, second), None);
+    }
+}
@@ -0,0 +1,195 @@
+//! Framing of packets on the connection of a bot to the server.
+
+use std::sync::Arc;
+
+use anyhow::{Context, bail, ensure};
+use bytes::{Buf, Bytes, BytesMut};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+use valence_protocol::{
+    CompressionThreshold, DecodeBytes, Encode, MAX_PACKET_SIZE, Packet, VarInt,
+};
+
+use crate::stats::Stats;
+
+const READ_BUF_SIZE: usize = 4096;
+
+/// A packet received from the server
+pub struct Frame {
+    pub id: i32,
+    /// The uncompressed contents of the packet after the ID
+    pub body: Bytes,
+}
+
+impl Frame {
+    /// Decodes the packet as `P`. The caller must have checked that the ID matches.
+    pub fn decode<P: Packet + DecodeBytes>(&self) -> anyhow::Result<P> {
+        let mut body = self.body.clone();
+        let packet = P::decode_bytes(&mut body)?;
+        ensure!(
+            body.is_empty(),
+            "missed {} bytes while decoding '{}'",
+            body.len(),
+            P::NAME
+        );
+        Ok(packet)
+    }
+}
+
+/// Reads the [`VarInt`] at the start of `buf` and its size, or returns `None` if `buf` ends before
+/// the [`VarInt`] does
+fn read_var_int(buf: &[u8]) -> anyhow::Result<Option<(i32, usize)>> {
+    let mut value = 0;
+    for (i, &byte) in buf.iter().take(VarInt::MAX_SIZE).enumerate() {
+        value |= i32::from(byte & 0x7F) << (i * 7);
+        if byte & 0x80 == 0 {
+            return Ok(Some((value, i + 1)));
+        }
+    }
+
+    ensure!(buf.len() < VarInt::MAX_SIZE, "VarInt is too large");
+    Ok(None)
+}
+
+/// Converts a length read from the server to a `usize`, checking that it is in bounds
+fn checked_len(len: i32) -> anyhow::Result<usize> {
+    ensure!(
+        (0..=MAX_PACKET_SIZE).contains(&len),
+        "packet length of {len} is out of bounds"
+    );
+    Ok(usize::try_from(len)?)
+}
+
+pub struct Connection {
+    stream: TcpStream,
+    read_buf: BytesMut,
+    threshold: CompressionThreshold,
+    compressor: libdeflater::Compressor,
+    decompressor: libdeflater::Decompressor,
+    stats: Arc<Stats>,
+}
+
+impl Connection {
+    pub async fn connect(address: std::net::SocketAddr, stats: Arc<Stats>) -> anyhow::Result<Self> {
+        let stream = TcpStream::connect(address).await?;
+        stream.set_nodelay(true)?;
+
+        Ok(Self {
+            stream,
+            read_buf: BytesMut::new(),
+            threshold: CompressionThreshold::default(),
+            compressor: libdeflater::Compressor::new(libdeflater::CompressionLvl::default()),
+            decompressor: libdeflater::Decompressor::new(),
+            stats,
+        })
+    }
+
+    pub const fn set_compression(&mut self, threshold: CompressionThreshold) {
+        self.threshold = threshold;
+    }
+
+    /// Waits for the next packet from the server. This is cancel safe.
+    pub async fn recv(&mut self) -> anyhow::Result<Frame> {
+        loop {
+            if let Some(frame) = self.try_next_frame()? {
+                return Ok(frame);
+            }
+
+            self.read_buf.reserve(READ_BUF_SIZE);
+            let read = self.stream.read_buf(&mut self.read_buf).await?;
+            if read == 0 {
+                bail!("server closed the connection");
+            }
+
+            self.stats.add_bytes_received(read);
+        }
+    }
+
+    fn try_next_frame(&mut self) -> anyhow::Result<Option<Frame>> {
+        let Some((len, len_size)) = read_var_int(&self.read_buf)? else {
+            return Ok(None);
+        };
+        let len = checked_len(len)?;
+
+        if self.read_buf.len() < len_size + len {
+            return Ok(None);
+        }
+
+        self.read_buf.advance(len_size);
+        let mut data = self.read_buf.split_to(len).freeze();
+
+        if self.threshold.0 >= 0 {
+            let (data_len, size) = read_var_int(&data)?.context("missing data length")?;
+            data.advance(size);
+
+            if data_len > 0 {
+                let mut decompressed = vec![0; checked_len(data_len)?];
+                let written = self
+                    .decompressor
+                    .zlib_decompress(&data, &mut decompressed)?;
+                ensure!(
+                    written == decompressed.len(),
+                    "packet decompressed to {written} bytes instead of {}",
+                    decompressed.len()
+                );
+                data = Bytes::from(decompressed);
+            }
+        }
+
+        let (id, size) = read_var_int(&data)?.context("missing packet ID")?;
+        data.advance(size);
+
+        Ok(Some(Frame { id, body: data }))
+    }
+
+    /// Sends `packet` to the server, compressing it if compression is enabled
+    pub async fn send<P: Packet + Encode>(&mut self, packet: &P) -> anyhow::Result<()> {
+        let mut data = Vec::new();
+        packet.encode_with_id(&mut data)?;
+
+        let mut frame = Vec::with_capacity(data.len() + 2 * VarInt::MAX_SIZE);
+
+        if let Ok(threshold) = usize::try_from(self.threshold.0) {
+            let data_len = VarInt(i32::try_from(data.len())?);
+
+            if data.len() > threshold {
+                let mut compressed = vec![0; self.compressor.zlib_compress_bound(data.len())];
+                let compressed_len = self.compressor.zlib_compress(&data, &mut compressed)?;
+
+                VarInt(i32::try_from(data_len.written_size() + compressed_len)?)
+                    .encode(&mut frame)?;
+                data_len.encode(&mut frame)?;
+                frame.extend_from_slice(&compressed[..compressed_len]);
+            } else {
+                // A data length of 0 marks the packet as uncompressed
+                let uncompressed = VarInt(0);
+                VarInt(i32::try_from(uncompressed.written_size() + data.len())?)
+                    .encode(&mut frame)?;
+                uncompressed.encode(&mut frame)?;
+                frame.extend_from_slice(&data);
+            }
+        } else {
+            VarInt(i32::try_from(data.len())?).encode(&mut frame)?;
+            frame.extend_from_slice(&data);
+        }
+
+        self.stream.write_all(&frame).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn var_ints_are_read_incrementally() {
+        assert_eq!(read_var_int(&[]).unwrap(), None);
+        assert_eq!(read_var_int(&[0x05]).unwrap(), Some((5, 1)));
+        assert_eq!(read_var_int(&[0xDD, 0xC7]).unwrap(), None);
+        assert_eq!(read_var_int(&[0xDD, 0xC7, 0x01]).unwrap(), Some((25565, 3)));
+        assert!(read_var_int(&[0xFF; 6]).is_err());
+    }
+}
@@ -0,0 +1,136 @@
+//! Spawning many bots against a server. See [`LoadTest`].
+
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use tokio::{sync::watch, task::JoinSet};
+
+use crate::{
+    behavior::Behavior,
+    bot::{BotConfig, run_bot},
+    stats::{Report, Stats},
+};
+
+/// A number of bots which join a server and play on it until the load test is stopped
+#[derive(Clone, Debug)]
+pub struct LoadTest {
+    address: SocketAddr,
+    bots: usize,
+    ramp_up: Duration,
+    behavior: Behavior,
+    username_prefix: String,
+    seed: u64,
+}
+
+impl LoadTest {
+    /// Creates a load test of `bots` bots connecting to the proxy at `address`
+    #[must_use]
+    pub fn new(address: SocketAddr, bots: usize) -> Self {
+        Self {
+            address,
+            bots,
+            ramp_up: Duration::ZERO,
+            behavior: Behavior::default(),
+            username_prefix: String::from("bot_"),
+            seed: 0,
+        }
+    }
+
+    /// Spreads the joins of the bots evenly over `ramp_up` instead of joining all at once
+    #[must_use]
+    pub const fn ramp_up(mut self, ramp_up: Duration) -> Self {
+        self.ramp_up = ramp_up;
+        self
+    }
+
+    #[must_use]
+    pub fn behavior(mut self, behavior: Behavior) -> Self {
+        self.behavior = behavior;
+        self
+    }
+
+    /// Names the bots `<prefix><index>`. Usernames are at most 16 characters long, so the prefix
+    /// should leave enough room for the index.
+    #[must_use]
+    pub fn username_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.username_prefix = prefix.into();
+        self
+    }
+
+    /// The seed the random movement of every bot is derived from
+    #[must_use]
+    pub const fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Starts joining the bots. This must be called from within a tokio runtime.
+    #[must_use]
+    pub fn start(self) -> RunningLoadTest {
+        let stats = Arc::new(Stats::new(self.bots));
+        let (shutdown, shutdown_rx) = watch::channel(false);
+        let mut tasks = JoinSet::new();
+
+        let spacing = self
+            .ramp_up
+            .checked_div(u32::try_from(self.bots).unwrap_or(u32::MAX))
+            .unwrap_or_default();
+
+        let mut delay = Duration::ZERO;
+        for index in 0..self.bots {
+            let config = BotConfig {
+                index,
+                username: format!("{}{index}", self.username_prefix),
+                address: self.address,
+                behavior: self.behavior.clone(),
+                seed: self.seed.wrapping_add(index as u64),
+            };
+            let stats = stats.clone();
+            let mut shutdown = shutdown_rx.clone();
+
+            tasks.spawn(async move {
+                tokio::select! {
+                    () = tokio::time::sleep(delay) => {}
+                    _ = shutdown.wait_for(|&shutdown| shutdown) => return,
+                }
+                run_bot(config, stats, shutdown).await;
+            });
+
+            delay += spacing;
+        }
+
+        RunningLoadTest {
+            stats,
+            shutdown,
+            tasks,
+        }
+    }
+
+    /// Runs the load test for `duration`, including the ramp up, and reports its statistics
+    pub async fn run(self, duration: Duration) -> Report {
+        let running = self.start();
+        tokio::time::sleep(duration).await;
+        running.stop().await
+    }
+}
+
+/// A [`LoadTest`] whose bots are joining or playing
+pub struct RunningLoadTest {
+    stats: Arc<Stats>,
+    shutdown: watch::Sender<bool>,
+    tasks: JoinSet<()>,
+}
+
+impl RunningLoadTest {
+    /// The statistics of the load test so far
+    #[must_use]
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    /// Disconnects every bot and reports the statistics of the load test
+    pub async fn stop(mut self) -> Report {
+        self.shutdown.send_replace(true);
+        while self.tasks.join_next().await.is_some() {}
+        self.stats.report()
+    }
+}
@@ -0,0 +1,19 @@
+//! Headless Minecraft 1.20.1 clients for load testing a Hyperion server.
+//!
+//! Bots log in offline through a proxy, answer keep alives, and move according to a
+//! [`Behavior`]. A [`LoadTest`] joins many bots with an optional ramp up and aggregates their
+//! [`Stats`], so load tests can be run from the command line as well as from tests.
+//!
+//! This should only be used to test your own server.
+
+mod connection;
+
+pub mod behavior;
+pub mod bot;
+pub mod driver;
+pub mod stats;
+
+pub use behavior::{Behavior, Movement};
+pub use bot::{BotConfig, run_bot};
+pub use driver::{LoadTest, RunningLoadTest};
+pub use stats::{Report, Stats};
@@ -0,0 +1,109 @@
+use std::{net::SocketAddr, time::Duration};
+
+use clap::{Parser, ValueEnum};
+use hyperion_bot::{Behavior, LoadTest, Movement};
+use tracing::info;
+use tracing_subscriber::EnvFilter;
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+enum MovementArg {
+    Idle,
+    RandomWalk,
+    Circle,
+    Follow,
+}
+
+#[derive(Debug, Parser)]
+#[clap(version)]
+struct Params {
+    /// The address of the proxy to connect to
+    #[clap(short, long, default_value = "127.0.0.1:25565")]
+    address: SocketAddr,
+
+    /// The number of bots to connect
+    #[clap(short, long, default_value_t = 100)]
+    bots: usize,
+
+    /// The number of seconds over which the joins of the bots are spread
+    #[clap(long, default_value_t = 10)]
+    ramp_up: u64,
+
+    /// The number of seconds to run the load test for, including the ramp up. Runs until
+    /// interrupted if not set.
+    #[clap(short, long)]
+    duration: Option<u64>,
+
+    #[clap(short, long, value_enum, default_value_t = MovementArg::RandomWalk)]
+    movement: MovementArg,
+
+    /// The radius of the circles walked with `--movement circle`
+    #[clap(long, default_value_t = 8.0)]
+    radius: f64,
+
+    /// Makes every bot send a chat message every this many milliseconds
+    #[clap(long)]
+    chat_interval: Option<u64>,
+
+    /// The seed the random movement of every bot is derived from
+    #[clap(long, default_value_t = 0)]
+    seed: u64,
+}
+
+fn setup_logging() {
+    tracing_subscriber::fmt()
+        .with_target(false)
+        .with_env_filter(
+            EnvFilter::builder()
+                .with_default_directive(tracing::Level::INFO.into())
+                .from_env_lossy(),
+        )
+        .init();
+}
+
+#[tokio::main]
+async fn main() {
+    setup_logging();
+
+    let params = Params::parse();
+
+    let movement = match params.movement {
+        MovementArg::Idle => Movement::Idle,
+        MovementArg::RandomWalk => Movement::RandomWalk,
+        MovementArg::Circle => Movement::Circle {
+            radius: params.radius,
+        },
+        MovementArg::Follow => Movement::Follow,
+    };
+
+    let load_test = LoadTest::new(params.address, params.bots)
+        .ramp_up(Duration::from_secs(params.ramp_up))
+        .behavior(Behavior {
+            movement,
+            chat_interval: params.chat_interval.map(Duration::from_millis),
+        })
+        .seed(params.seed);
+
+    info!("connecting {} bots to {}", params.bots, params.address);
+    let running = load_test.start();
+
+    let mut reports = tokio::time::interval(Duration::from_secs(5));
+    let deadline = params
+        .duration
+        .map(|duration| tokio::time::Instant::now() + Duration::from_secs(duration));
+
+    loop {
+        tokio::select! {
+            _ = reports.tick() => info!("{}", running.stats().report()),
+            () = async {
+                match deadline {
+                    Some(deadline) => tokio::time::sleep_until(deadline).await,
+                    None => std::future::pending().await,
+                }
+            } => break,
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+
+    let report = running.stop().await;
+    info!("finished: {report}");
+}
@@ -0,0 +1,181 @@
+//! Statistics aggregated over every bot of a load test.
+
+use std::{
+    fmt,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+/// Counters shared by every bot of a load test
+#[derive(Debug)]
+pub struct Stats {
+    connected: AtomicUsize,
+    failed: AtomicUsize,
+    disconnected: AtomicUsize,
+    bytes_received: AtomicU64,
+    /// The last round trip time of every bot in microseconds, or 0 if it has not been measured
+    rtts: Box<[AtomicU64]>,
+}
+
+impl Stats {
+    #[must_use]
+    pub fn new(bots: usize) -> Self {
+        Self {
+            connected: AtomicUsize::new(0),
+            failed: AtomicUsize::new(0),
+            disconnected: AtomicUsize::new(0),
+            bytes_received: AtomicU64::new(0),
+            rtts: (0..bots).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+
+    /// Records that a bot has logged in
+    pub fn add_connected(&self) {
+        self.connected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records that a bot failed to connect or log in
+    pub fn add_failed(&self) {
+        self.failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records that a bot which had logged in was disconnected before the load test ended
+    pub fn add_disconnected(&self) {
+        self.disconnected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add_bytes_received(&self, bytes: usize) {
+        self.bytes_received
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Records the round trip time of the bot with the index `bot`
+    pub fn set_rtt(&self, bot: usize, rtt: Duration) {
+        if let Some(slot) = self.rtts.get(bot) {
+            // 0 is reserved for bots without a measurement
+            let micros = u64::try_from(rtt.as_micros()).unwrap_or(u64::MAX).max(1);
+            slot.store(micros, Ordering::Relaxed);
+        }
+    }
+
+    /// The number of bots which are currently logged in
+    #[must_use]
+    pub fn online(&self) -> usize {
+        self.connected
+            .load(Ordering::Relaxed)
+            .saturating_sub(self.disconnected.load(Ordering::Relaxed))
+    }
+
+    /// Takes a snapshot of the statistics
+    #[must_use]
+    pub fn report(&self) -> Report {
+        Report {
+            bots: self.rtts.len(),
+            connected: self.connected.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            disconnected: self.disconnected.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            rtts: self
+                .rtts
+                .iter()
+                .map(|rtt| rtt.load(Ordering::Relaxed))
+                .filter(|&micros| micros != 0)
+                .map(Duration::from_micros)
+                .collect(),
+        }
+    }
+}
+
+/// A snapshot of the [`Stats`] of a load test
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Report {
+    /// The number of bots of the load test
+    pub bots: usize,
+    /// The number of bots which logged in
+    pub connected: usize,
+    /// The number of bots which failed to connect or log in
+    pub failed: usize,
+    /// The number of bots which were disconnected after logging in
+    pub disconnected: usize,
+    /// The number of bytes all bots received from the server
+    pub bytes_received: u64,
+    /// The last round trip time of every bot which measured one
+    pub rtts: Vec<Duration>,
+}
+
+impl Report {
+    /// The fraction of bots which logged in, from 0 to 1
+    #[must_use]
+    #[expect(
+        clippy::cast_precision_loss,
+        reason = "the number of bots is far below 2^52"
+    )]
+    pub fn connect_success_rate(&self) -> f64 {
+        if self.bots == 0 {
+            return 1.0;
+        }
+        self.connected as f64 / self.bots as f64
+    }
+
+    #[must_use]
+    pub fn mean_rtt(&self) -> Option<Duration> {
+        let count = u32::try_from(self.rtts.len())
+            .ok()
+            .filter(|&count| count != 0)?;
+        Some(self.rtts.iter().sum::<Duration>() / count)
+    }
+
+    #[must_use]
+    pub fn max_rtt(&self) -> Option<Duration> {
+        self.rtts.iter().max().copied()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}/{} bots connected ({:.1}%), {} failed, {} disconnected, {} bytes received",
+            self.connected,
+            self.bots,
+            self.connect_success_rate() * 100.0,
+            self.failed,
+            self.disconnected,
+            self.bytes_received
+        )?;
+
+        if let (Some(mean), Some(max)) = (self.mean_rtt(), self.max_rtt()) {
+            write!(f, ", rtt mean {mean:?} max {max:?}")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_aggregates_bots() {
+        let stats = Stats::new(4);
+        stats.add_connected();
+        stats.add_connected();
+        stats.add_connected();
+        stats.add_failed();
+        stats.add_disconnected();
+        stats.add_bytes_received(100);
+        stats.set_rtt(0, Duration::from_millis(10));
+        stats.set_rtt(2, Duration::from_millis(30));
+        // Bots which do not exist are ignored
+        stats.set_rtt(7, Duration::from_millis(1000));
+
+        assert_eq!(stats.online(), 2);
+
+        let report = stats.report();
+        assert!((report.connect_success_rate() - 0.75).abs() < f64::EPSILON);
+        assert_eq!(report.bytes_received, 100);
+        assert_eq!(report.mean_rtt(), Some(Duration::from_millis(20)));
+        assert_eq!(report.max_rtt(), Some(Duration::from_millis(30)));
+    }
+}
@@ -0,0 +1,157 @@
+use std::{
+    net::{SocketAddr, TcpListener as StdTcpListener},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use bevy_app::App;
+use hyperion::{
+    Crypto, Endpoint, HyperionCore, PlayerCount, ServerInfo,
+    ingress::auth::AuthMode,
+    storage::LocalDb,
+    util::mojang::{MojangClient, StubProfiles},
+};
+use hyperion_bot::{Behavior, LoadTest, Movement};
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, ExtendedKeyUsagePurpose, IsCa, KeyPair,
+};
+use tokio::net::TcpListener;
+
+const BOTS: usize = 100;
+
+/// Generous, because CI runners are slow and tests are usually built without optimizations
+const MAX_TICK_MS: f32 = 100.0;
+
+/// Writes a certificate for `usage` signed by the certificate authority to `<name>.pem` and its
+/// private key to `<name>.key`
+fn write_signed_cert(
+    dir: &Path,
+    name: &str,
+    usage: ExtendedKeyUsagePurpose,
+    ca: &Certificate,
+    ca_key: &KeyPair,
+) {
+    let key = KeyPair::generate().unwrap();
+    let mut params = CertificateParams::new(vec!["localhost".to_owned()]).unwrap();
+    params.extended_key_usages = vec![usage];
+    let cert = params.signed_by(&key, ca, ca_key).unwrap();
+
+    std::fs::write(dir.join(format!("{name}.pem")), cert.pem()).unwrap();
+    std::fs::write(dir.join(format!("{name}.key")), key.serialize_pem()).unwrap();
+}
+
+fn free_port() -> u16 {
+    StdTcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+#[test]
+fn hundred_bots_keep_ticks_short() {
+    let dir = std::env::temp_dir().join(format!("hyperion-bot-load-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let ca_key = KeyPair::generate().unwrap();
+    let mut ca_params = CertificateParams::new(Vec::new()).unwrap();
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let ca = ca_params.self_signed(&ca_key).unwrap();
+    std::fs::write(dir.join("ca.pem"), ca.pem()).unwrap();
+
+    write_signed_cert(
+        &dir,
+        "server",
+        ExtendedKeyUsagePurpose::ServerAuth,
+        &ca,
+        &ca_key,
+    );
+    write_signed_cert(
+        &dir,
+        "proxy",
+        ExtendedKeyUsagePurpose::ClientAuth,
+        &ca,
+        &ca_key,
+    );
+
+    let server_addr = SocketAddr::from(([127, 0, 0, 1], free_port()));
+
+    let mut app = App::new();
+    app.insert_resource(Endpoint::from(server_addr));
+    app.insert_resource(
+        Crypto::new(
+            &dir.join("ca.pem"),
+            &dir.join("server.pem"),
+            &dir.join("server.key"),
+        )
+        .unwrap(),
+    );
+    app.insert_resource(LocalDb::builder().path(dir.join("db")).build().unwrap());
+    app.insert_resource(MojangClient::stub(StubProfiles::default()));
+    // Bots cannot authenticate with the session server
+    app.insert_resource(AuthMode::Offline);
+    app.add_plugins(HyperionCore);
+    app.finish();
+    app.cleanup();
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let listener = runtime.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+    let proxy_addr = listener.local_addr().unwrap();
+    let proxy_dir = dir.clone();
+    runtime.spawn(async move {
+        hyperion_proxy::run_proxy(
+            listener,
+            server_addr,
+            format!("localhost:{}", server_addr.port()),
+            &proxy_dir.join("ca.pem"),
+            &proxy_dir.join("proxy.pem"),
+            &proxy_dir.join("proxy.key"),
+        )
+        .await
+        .unwrap();
+    });
+
+    let load = runtime.spawn(
+        LoadTest::new(proxy_addr, BOTS)
+            .ramp_up(Duration::from_secs(2))
+            .behavior(Behavior {
+                movement: Movement::RandomWalk,
+                chat_interval: Some(Duration::from_secs(1)),
+            })
+            .run(Duration::from_secs(8)),
+    );
+
+    let mut max_tick_ms: f32 = 0.0;
+    let mut full_since = None;
+    while !load.is_finished() {
+        app.update();
+
+        if app.world().resource::<PlayerCount>().get() == BOTS {
+            let since = *full_since.get_or_insert_with(Instant::now);
+            // The ticks in which the last bots join are allowed to be slower
+            if since.elapsed() > Duration::from_millis(500) {
+                let tick_ms = app.world().resource::<ServerInfo>().last_mspt();
+                max_tick_ms = max_tick_ms.max(tick_ms);
+            }
+        }
+
+        std::thread::sleep(Duration::from_millis(1));
+    }
+
+    let report = runtime.block_on(load).unwrap();
+    let _removed = std::fs::remove_dir_all(&dir);
+
+    assert_eq!(report.connected, BOTS, "{report}");
+    assert_eq!(report.disconnected, 0, "{report}");
+    assert!(report.bytes_received > 0, "{report}");
+    assert!(
+        !report.rtts.is_empty(),
+        "no bot measured its round trip time: {report}"
+    );
+    assert!(full_since.is_some(), "not every bot was online at once");
+    assert!(
+        max_tick_ms < MAX_TICK_MS,
+        "a tick took {max_tick_ms} ms with {BOTS} bots online"
+    );
+}
@@ -0,0 +1,432 @@
+use std::fmt::{self, Display};
+
+use bevy_ecs::{component::Component, entity::Entity, name::Name, world::World};
+use clap::{Parser, Subcommand};
+use hyperion::{
+    net::ConnectionId,
+    simulation::{
+        ChunkPosition, Flight, IgnMap, MovementTracking, PendingTeleportation, Pitch, Position,
+        Velocity, Yaw, max_health::MaxHealth, metadata::living_entity::Health, packet_state,
+    },
+    snapshot::SnapshotExtractors,
+};
+use hyperion_command::CommandCaller;
+use hyperion_permission::Group;
+use valence_protocol::text::{Color, IntoText};
+
+use crate::{CommandPermission, CommandReply, MinecraftCommand};
+
+/// The number of characters after which a line is wrapped, not counting formatting codes
+const MAX_LINE_LENGTH: usize = 50;
+
+/// The number of lines shown per page, not counting the header and the footer
+const PAGE_LINES: usize = 10;
+
+/// Continuation lines of a wrapped value are indented by this
+const INDENT: &str = "  ";
+
+#[derive(Subcommand, Debug)]
+enum DebugTarget {
+    /// Shows the state of a player
+    Player { name: String, page: Option<usize> },
+}
+
+/// Shows the state of the server for debugging
+#[derive(Parser, CommandPermission, Debug)]
+#[command(name = "debug")]
+#[command_permission(group = "Admin")]
+pub struct DebugCommand {
+    #[command(subcommand)]
+    target: DebugTarget,
+}
+
+/// Shows a field of an entity, or [`None`] if the entity is missing the components of the field
+type Field = fn(world: &World, entity: Entity) -> Option<String>;
+
+/// The fields shown for every player, followed by the fields of the [`SnapshotExtractors`] which
+/// are not shown here already, such as the position and health of the default extractors
+const FIELDS: &[(&str, Field)] = &[
+    ("state", |world, entity| {
+        let entity = world.get_entity(entity).ok()?;
+        let state = if entity.contains::<packet_state::Play>() {
+            "play"
+        } else if entity.contains::<packet_state::Login>() {
+            "login"
+        } else if entity.contains::<packet_state::Status>() {
+            "status"
+        } else if entity.contains::<packet_state::Handshake>() {
+            "handshake"
+        } else {
+            "none (changing state)"
+        };
+        Some(state.to_owned())
+    }),
+    ("connection", shown::<ConnectionId>),
+    ("group", shown::<Group>),
+    ("position", shown::<Position>),
+    ("chunk", shown::<ChunkPosition>),
+    ("rotation", |world, entity| {
+        let yaw = **world.get::<Yaw>(entity)?;
+        let pitch = **world.get::<Pitch>(entity)?;
+        Some(format!("yaw {yaw:.1}, pitch {pitch:.1}"))
+    }),
+    ("velocity", shown::<Velocity>),
+    ("movement", shown::<MovementTracking>),
+    ("teleport", |world, entity| {
+        // Players are only missing this while they are not being teleported
+        Some(world.get::<PendingTeleportation>(entity).map_or_else(
+            || "none pending".to_owned(),
+            |teleport| Shown(teleport).to_string(),
+        ))
+    }),
+    ("health", |world, entity| {
+        let health = **world.get::<Health>(entity)?;
+        let max_health = world.get::<MaxHealth>(entity).copied().unwrap_or_default();
+        Some(format!("{health:.1} / {:.1}", *max_health))
+    }),
+    ("flight", shown::<Flight>),
+];
+
+/// Shows a component in the output of [`DebugCommand`]
+struct Shown<'a, T>(&'a T);
+
+fn shown<T: Component>(world: &World, entity: Entity) -> Option<String>
+where
+    for<'a> Shown<'a, T>: Display,
+{
+    Some(Shown(world.get::<T>(entity)?).to_string())
+}
+
+/// A vector with two decimal places per coordinate
+struct Vector<T>([T; 3]);
+
+impl<T: Display> Display for Vector<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [x, y, z] = &self.0;
+        write!(f, "{x:.2}, {y:.2}, {z:.2}")
+    }
+}
+
+impl Display for Shown<'_, ConnectionId> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "stream {}, proxy {}",
+            self.0.inner(),
+            self.0.proxy_id().inner()
+        )
+    }
+}
+
+impl Display for Shown<'_, Group> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+impl Display for Shown<'_, Position> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Vector(self.0.to_array()).fmt(f)
+    }
+}
+
+impl Display for Shown<'_, ChunkPosition> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0.position {
+            Some(position) => write!(f, "{}, {}", position.x, position.y),
+            None => f.write_str("none sent"),
+        }
+    }
+}
+
+impl Display for Shown<'_, Velocity> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Vector(self.0.0.to_array()).fmt(f)
+    }
+}
+
+impl Display for Shown<'_, MovementTracking> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tracking = self.0;
+        write!(
+            f,
+            "last position {}, on ground {}, fall start y {:.2}, sprinting {}, flying last tick \
+             {}, server velocity {}, {} packets received, {} coalesced",
+            Vector(tracking.last_tick_position.to_array()),
+            tracking.was_on_ground,
+            tracking.fall_start_y,
+            tracking.sprinting,
+            tracking.last_tick_flying,
+            Vector(tracking.server_velocity.to_array()),
+            tracking.received_movement_packets,
+            tracking.coalesced_movement_packets,
+        )?;
+
+        if let Some(landing_y) = tracking.coalesced_landing_y {
+            write!(f, ", coalesced landing y {landing_y:.2}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Display for Shown<'_, PendingTeleportation> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "#{} to {}, {} ticks left",
+            self.0.teleport_id,
+            Vector(self.0.destination.to_array()),
+            self.0.ttl
+        )
+    }
+}
+
+impl Display for Shown<'_, Flight> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "allowed {}, flying {}", self.0.allow, self.0.is_flying)
+    }
+}
+
+/// Splits `text` into lines of at most `first` characters for the first line and `rest`
+/// characters for the others. Lines are broken between words where possible.
+fn wrap(text: &str, first: usize, rest: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut len = 0;
+
+    for word in text.split_whitespace() {
+        let mut word = word;
+        loop {
+            let width = (if lines.is_empty() { first } else { rest }).max(1);
+            let word_len = word.chars().count();
+            let needed = if len == 0 {
+                word_len
+            } else {
+                len + 1 + word_len
+            };
+
+            if needed <= width {
+                if len != 0 {
+                    line.push(' ');
+                }
+                line.push_str(word);
+                len = needed;
+                break;
+            }
+
+            if len != 0 {
+                lines.push(std::mem::take(&mut line));
+                len = 0;
+                continue;
+            }
+
+            // The word does not fit on a line of its own
+            let split = word
+                .char_indices()
+                .nth(width)
+                .map_or(word.len(), |(index, _)| index);
+            lines.push(word[..split].to_owned());
+            word = &word[split..];
+        }
+    }
+
+    if len != 0 || lines.is_empty() {
+        lines.push(line);
+    }
+
+    lines
+}
+
+/// Formats a field as `label: value`, wrapped to [`MAX_LINE_LENGTH`]. Missing values are shown
+/// as such instead of leaving out the field.
+fn field_lines(label: &str, value: Option<&str>, lines: &mut Vec<String>) {
+    let Some(value) = value else {
+        lines.push(format!("§7{label}: §8missing"));
+        return;
+    };
+
+    let first = MAX_LINE_LENGTH.saturating_sub(label.chars().count() + 2);
+    let rest = MAX_LINE_LENGTH - INDENT.len();
+    for (i, part) in wrap(value, first, rest).into_iter().enumerate() {
+        if i == 0 {
+            lines.push(format!("§7{label}: §f{part}"));
+        } else {
+            lines.push(format!("§f{INDENT}{part}"));
+        }
+    }
+}
+
+/// The lines showing the state of `player`, without pagination
+fn player_lines(world: &World, player: Entity) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    for (label, field) in FIELDS {
+        field_lines(label, field(world, player).as_deref(), &mut lines);
+    }
+
+    if let Some(extractors) = world.get_resource::<SnapshotExtractors>() {
+        for (label, value) in extractors.extract_each(world, player) {
+            if FIELDS.iter().any(|(shown, _)| *shown == label) {
+                continue;
+            }
+
+            let value = value.map(|value| {
+                value
+                    .as_str()
+                    .map_or_else(|| value.to_string(), str::to_owned)
+            });
+            field_lines(label, value.as_deref(), &mut lines);
+        }
+    }
+
+    lines
+}
+
+/// The lines of page `page` of `lines`, counting from 1, or [`None`] if there is no such page
+fn page(lines: &[String], page: usize) -> Option<&[String]> {
+    lines.chunks(PAGE_LINES).nth(page.checked_sub(1)?)
+}
+
+impl DebugCommand {
+    fn player(reply: CommandReply<'_>, world: &World, name: &str, page_number: usize) {
+        let Some(player) = world.resource::<IgnMap>().get_ignore_case(name) else {
+            reply.reply_error(format!("{name} not found"));
+            return;
+        };
+
+        let name = world.get::<Name>(player).map_or(name, |name| name.as_str());
+        let lines = player_lines(world, player);
+        let pages = lines.len().div_ceil(PAGE_LINES);
+
+        let Some(shown) = page(&lines, page_number) else {
+            reply.reply_error(format!(
+                "Page {page_number} does not exist, there are {pages} pages"
+            ));
+            return;
+        };
+
+        reply.reply(format!(
+            "§7State of §f{name}§7, page §f{page_number}§7 of §f{pages}"
+        ));
+        for line in shown {
+            reply.reply(line.as_str());
+        }
+
+        if page_number < pages {
+            let next = page_number + 1;
+            reply.reply(
+                "[Next page]"
+                    .into_text()
+                    .color(Color::AQUA)
+                    .on_click_run_command(format!("/debug player {name} {next}"))
+                    .on_hover_show_text(format!("Show page {next} of {pages}")),
+            );
+        }
+    }
+}
+
+impl MinecraftCommand for DebugCommand {
+    type State = ();
+
+    fn execute(self, world: &World, state: &mut Self::State, caller: Entity) {
+        self.execute_as(world, state, CommandCaller::Player(caller));
+    }
+
+    fn execute_as(self, world: &World, _state: &mut Self::State, caller: CommandCaller) {
+        let reply = CommandReply::new(world, caller);
+        match self.target {
+            DebugTarget::Player { name, page } => {
+                Self::player(reply, world, &name, page.unwrap_or(1));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_values_are_wrapped_between_words() {
+        assert_eq!(wrap("", 10, 10), [""]);
+        assert_eq!(wrap("on ground true", 10, 8), ["on ground", "true"]);
+        assert_eq!(wrap("abcdefghij klm", 4, 6), ["abcd", "efghij", "klm"]);
+
+        // Formatting codes are not shown
+        let visible = |line: &str| line.chars().count() - 2 * line.matches('§').count();
+        let mut lines = Vec::new();
+        field_lines("movement", Some(&"x".repeat(MAX_LINE_LENGTH)), &mut lines);
+        assert_eq!(lines.len(), 2);
+        assert!(lines.iter().all(|line| visible(line) <= MAX_LINE_LENGTH));
+    }
+
+    #[test]
+    fn missing_components_are_shown_as_missing() {
+        let mut world = World::new();
+        let player = world
+            .spawn((
+                Group::Admin,
+                Position::new(0.5, 64.0, -2.25),
+                Velocity::new(0.0, 0.0, 0.0),
+            ))
+            .id();
+
+        let lines = player_lines(&world, player);
+        assert_eq!(lines.len(), FIELDS.len());
+        assert!(lines.contains(&"§7state: §fnone (changing state)".to_owned()));
+        assert!(lines.contains(&"§7group: §fAdmin".to_owned()));
+        assert!(lines.contains(&"§7position: §f0.50, 64.00, -2.25".to_owned()));
+        assert!(lines.contains(&"§7connection: §8missing".to_owned()));
+        assert!(lines.contains(&"§7teleport: §fnone pending".to_owned()));
+        assert!(lines.contains(&"§7health: §8missing".to_owned()));
+    }
+
+    #[test]
+    fn registered_snapshot_fields_are_shown() {
+        let mut world = World::new();
+        let mut extractors = SnapshotExtractors::empty();
+        extractors.register("kit", |_, _| Some("archer".into()));
+        extractors.register("kills", |_, _| Some(3.into()));
+        extractors.register("team", |_, _| None);
+        world.insert_resource(extractors);
+        let player = world.spawn(Group::Normal).id();
+
+        let lines = player_lines(&world, player);
+        assert_eq!(&lines[FIELDS.len()..], [
+            "§7kit: §farcher",
+            "§7kills: §f3",
+            "§7team: §8missing"
+        ]);
+    }
+
+    #[test]
+    fn default_snapshot_fields_are_not_shown_twice() {
+        let mut world = World::new();
+        world.init_resource::<SnapshotExtractors>();
+        let player = world
+            .spawn((
+                Group::Normal,
+                Position::new(0.5, 64.0, -2.25),
+                Health::new(20.0),
+            ))
+            .id();
+
+        let lines = player_lines(&world, player);
+        for label in ["position", "health"] {
+            let prefix = format!("§7{label}: ");
+            let shown = lines.iter().filter(|line| line.starts_with(&prefix));
+            assert_eq!(shown.count(), 1, "{label} is shown more than once");
+        }
+    }
+
+    #[test]
+    fn lines_are_split_into_pages() {
+        let lines: Vec<String> = (0..PAGE_LINES * 2 + 1).map(|i| i.to_string()).collect();
+
+        assert_eq!(page(&lines, 1).map(<[_]>::len), Some(PAGE_LINES));
+        assert_eq!(page(&lines, 3), Some(&[(PAGE_LINES * 2).to_string()][..]));
+        assert_eq!(page(&lines, 4), None);
+        assert_eq!(page(&lines, 0), None);
+    }
+}
@@ -0,0 +1,79 @@
+use bevy_ecs::{
+    entity::Entity,
+    system::{Commands, SystemState},
+    world::World,
+};
+use clap::{Parser, ValueEnum};
+use hyperion::simulation::game_rules::GameRules;
+use hyperion_command::CommandCaller;
+
+use crate::{CommandPermission, CommandReply, MinecraftCommand};
+
+/// A rule of [`GameRules`], named like in vanilla
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum GameRule {
+    #[value(name = "doImmediateRespawn")]
+    ImmediateRespawn,
+    #[value(name = "reducedDebugInfo")]
+    ReducedDebugInfo,
+    #[value(name = "showDeathMessages")]
+    ShowDeathMessages,
+}
+
+impl GameRule {
+    const fn name(self) -> &'static str {
+        match self {
+            Self::ImmediateRespawn => "doImmediateRespawn",
+            Self::ReducedDebugInfo => "reducedDebugInfo",
+            Self::ShowDeathMessages => "showDeathMessages",
+        }
+    }
+
+    const fn value(self, rules: &mut GameRules) -> &mut bool {
+        match self {
+            Self::ImmediateRespawn => &mut rules.immediate_respawn,
+            Self::ReducedDebugInfo => &mut rules.reduced_debug_info,
+            Self::ShowDeathMessages => &mut rules.show_death_messages,
+        }
+    }
+}
+
+/// Shows or changes a game rule
+#[derive(Parser, CommandPermission, Debug)]
+#[command(name = "gamerule")]
+#[command_permission(group = "Admin")]
+pub struct GameRuleCommand {
+    rule: GameRule,
+    /// The new value. The current value is shown if this is left out.
+    value: Option<bool>,
+}
+
+impl MinecraftCommand for GameRuleCommand {
+    type State = SystemState<Commands<'static, 'static>>;
+
+    fn execute(self, world: &World, state: &mut Self::State, caller: Entity) {
+        self.execute_as(world, state, CommandCaller::Player(caller));
+    }
+
+    fn execute_as(self, world: &World, state: &mut Self::State, caller: CommandCaller) {
+        let mut commands = state.get(world);
+        let reply = CommandReply::new(world, caller);
+        let mut rules = *world.resource::<GameRules>();
+        let current = self.rule.value(&mut rules);
+
+        let Some(value) = self.value else {
+            reply.reply(format!(
+                "§7Game rule §f{}§7 is currently set to §f{current}",
+                self.rule.name()
+            ));
+            return;
+        };
+
+        *current = value;
+        commands.insert_resource(rules);
+        reply.reply(format!(
+            "§7Game rule §f{}§7 is now set to §f{value}",
+            self.rule.name()
+        ));
+    }
+}
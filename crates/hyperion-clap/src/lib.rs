@@ -1,5 +1,3 @@
-use std::iter::zip;
-
 use bevy_app::{App, Plugin};
 use bevy_ecs::{
     entity::Entity,
@@ -8,63 +6,81 @@ use bevy_ecs::{
     world::{FromWorld, World},
 };
 use clap::{Arg as ClapArg, Parser, ValueEnum, ValueHint, error::ErrorKind};
+pub use debug::DebugCommand;
+pub use gamerule::GameRuleCommand;
 use hyperion::{
-    net::{Compose, ConnectionId, DataBundle, agnostic},
+    net::{Compose, SendResultExt},
     simulation::{IgnMap, command::RootCommand, packet::play},
 };
 pub use hyperion_clap_macros::CommandPermission;
 pub use hyperion_command;
-use hyperion_command::{CommandHandler, CommandRegistry, ExecutableCommand};
-use hyperion_permission::Group;
+use hyperion_command::{
+    CommandCaller, CommandHandler, CommandLimits, CommandRegistry, ExecutableCommand,
+};
+use hyperion_permission::{Group, set_group};
 use hyperion_utils::ApplyWorld;
+pub use netstat::NetstatCommand;
+pub use region::RegionCommand;
+pub use reply::CommandReply;
+pub use sudo::SudoCommand;
 use tracing::error;
 use valence_bytes::Utf8Bytes;
 use valence_protocol::{
     VarInt,
-    packets::play::{
-        command_suggestions_s2c::{CommandSuggestionsMatch, CommandSuggestionsS2c},
-        command_tree_s2c::StringArg,
-    },
+    packets::play::command_suggestions_s2c::{CommandSuggestionsMatch, CommandSuggestionsS2c},
 };
 
+mod debug;
+mod gamerule;
+mod netstat;
+mod region;
+mod reply;
+mod sudo;
+mod tree;
+
 struct GenericExecutableCommand<Command: MinecraftCommand> {
     state: Command::State,
 }
 
 impl<Command: MinecraftCommand> ExecutableCommand for GenericExecutableCommand<Command> {
-    fn execute(&mut self, world: &World, packet: &play::CommandExecution) {
-        let compose = world.resource::<Compose>();
-        let input = packet.command.split_whitespace();
+    fn execute(&mut self, world: &World, caller: CommandCaller, command: &str) {
+        self.execute_with_permissions(world, caller, caller, command);
+    }
 
-        match Command::try_parse_from(input) {
+    fn execute_with_permissions(
+        &mut self,
+        world: &World,
+        caller: CommandCaller,
+        permissions: CommandCaller,
+        command: &str,
+    ) {
+        match parse_command::<Command>(command) {
             Ok(elem) => {
-                let Some(group) = world.entity(packet.sender()).get::<Group>() else {
-                    error!("failed to execute command: player is missing Group component");
-                    return;
+                let has_permission = match permissions {
+                    CommandCaller::Player(entity) => {
+                        let Some(group) = world.entity(entity).get::<Group>() else {
+                            error!("failed to execute command: player is missing Group component");
+                            return;
+                        };
+
+                        Command::has_required_permission(*group)
+                    }
+                    CommandCaller::Console => true,
                 };
 
-                if Command::has_required_permission(*group) {
-                    elem.execute(world, &mut self.state, packet.sender());
+                if has_permission {
+                    elem.execute_as(world, &mut self.state, caller);
                 } else {
-                    let chat = agnostic::chat("§cYou do not have permission to use this command!");
-
-                    let mut bundle = DataBundle::new(compose);
-                    bundle.add_packet(&chat).unwrap();
-                    bundle.unicast(packet.connection_id()).unwrap();
+                    CommandReply::new(world, caller)
+                        .reply_error("You do not have permission to use this command!");
                 }
             }
             Err(e) => {
-                // add red if not display help
-                let prefix = match e.kind() {
-                    ErrorKind::DisplayHelp => "",
-                    _ => "§c",
-                };
-
-                // minecraft red
-                let msg = format!("{prefix}{e}");
-
-                let msg = agnostic::chat(msg);
-                compose.unicast(&msg, packet.connection_id()).unwrap();
+                let reply = CommandReply::new(world, caller);
+                match e.kind() {
+                    ErrorKind::DisplayHelp => reply.reply(e.to_string()),
+                    _ => reply.reply_error(e.to_string()),
+                }
 
                 tracing::warn!("could not parse command {e}");
             }
@@ -87,8 +103,27 @@ pub trait MinecraftCommand: Parser + CommandPermission + 'static {
 
     fn execute(self, world: &World, state: &mut Self::State, caller: Entity);
 
+    /// Executes the command for any caller. By default, this runs [`MinecraftCommand::execute`]
+    /// for players and rejects the console. Commands which support the console should override
+    /// this.
+    fn execute_as(self, world: &World, state: &mut Self::State, caller: CommandCaller) {
+        match caller {
+            CommandCaller::Player(entity) => self.execute(world, state, entity),
+            CommandCaller::Console => {
+                CommandReply::new(world, caller)
+                    .reply_error("This command can only be used by players");
+            }
+        }
+    }
+
     fn pre_register(_world: &World) {}
 
+    /// How often a player may execute this command. Commands are not limited by default.
+    #[must_use]
+    fn limits() -> Option<CommandLimits> {
+        None
+    }
+
     fn register(world: &mut World) {
         Self::pre_register(world);
 
@@ -106,24 +141,11 @@ pub trait MinecraftCommand: Parser + CommandPermission + 'static {
             Self::has_required_permission(*group)
         };
 
-        let node_to_register =
-            hyperion::simulation::command::Command::literal(name.clone(), has_permissions);
-
-        let root_command = world.resource::<RootCommand>();
-        let mut on = **root_command;
-        on = world.spawn((node_to_register, ChildOf(on))).id();
-
-        for arg in cmd.get_arguments() {
-            use valence_protocol::packets::play::command_tree_s2c::Parser as ValenceParser;
-            let name = arg.get_value_names().unwrap().first().unwrap();
-            let name = name.to_ascii_lowercase();
-            let node_to_register = hyperion::simulation::command::Command::argument(
-                name,
-                ValenceParser::String(StringArg::SingleWord),
-            );
-
-            on = world.spawn((node_to_register, ChildOf(on))).id();
-        }
+        let root_command = **world.resource::<RootCommand>();
+        let node = world
+            .spawn((tree::literal(&cmd, has_permissions), ChildOf(root_command)))
+            .id();
+        tree::spawn_children(world, node, &cmd, has_permissions);
 
         let executable = Box::new(GenericExecutableCommand::<Self> { state });
 
@@ -132,85 +154,18 @@ pub trait MinecraftCommand: Parser + CommandPermission + 'static {
             let full_query = &completion.text;
             let id = completion.transaction_id;
 
-            let Some(query) = full_query.strip_prefix('/') else {
+            if !full_query.starts_with('/') {
                 // todo: send error message to player
                 tracing::warn!("could not parse command {full_query}");
                 return;
-            };
-
-            let mut query = query.split_whitespace();
-            let _command_name = query.next().unwrap();
-
-            let command = Self::command();
-            let mut positionals = command.get_positionals();
-
-            'positionals: for (input_arg, cmd_arg) in zip(query, positionals.by_ref()) {
-                // see if anything matches
-                let possible_values = cmd_arg.get_possible_values();
-                for possible in &possible_values {
-                    if possible.matches(input_arg, true) {
-                        continue 'positionals;
-                    }
-                }
-
-                // nothing matches! let's see if a substring matches
-                let mut substring_matches = possible_values
-                    .iter()
-                    .filter(|possible| {
-                        // todo: this is inefficient
-                        possible
-                            .get_name()
-                            .to_lowercase()
-                            .starts_with(&input_arg.to_lowercase())
-                    })
-                    .peekable();
-
-                if substring_matches.peek().is_none() {
-                    // no matches
-                    return;
-                }
-
-                let matches = substring_matches
-                    .map(clap::builder::PossibleValue::get_name)
-                    .map(|name| CommandSuggestionsMatch {
-                        suggested_match: name.into(),
-                        tooltip: None,
-                    })
-                    .collect();
-
-                let start = input_arg.as_ptr() as usize - full_query.as_ptr() as usize;
-                let len = input_arg.len();
-
-                let start = i32::try_from(start).unwrap();
-                let len = i32::try_from(len).unwrap();
-
-                let packet = CommandSuggestionsS2c {
-                    id,
-                    start: VarInt(start),
-                    length: VarInt(len),
-                    matches,
-                };
-
-                compose
-                    .unicast(&packet, completion.connection_id())
-                    .unwrap();
-
-                // todo: send possible matches to player
-                return;
             }
 
-            let Some(remaining_positional) = positionals.next() else {
+            let Some((range, matches)) = tree::suggest(&Self::command(), full_query) else {
                 // we are all done completing
                 return;
             };
 
-            let possible_values = remaining_positional.get_possible_values();
-
-            let names = possible_values
-                .iter()
-                .map(clap::builder::PossibleValue::get_name);
-
-            let matches = names
+            let matches = matches
                 .into_iter()
                 .map(|name| CommandSuggestionsMatch {
                     suggested_match: name.into(),
@@ -218,19 +173,19 @@ pub trait MinecraftCommand: Parser + CommandPermission + 'static {
                 })
                 .collect();
 
-            let start = full_query.len();
-            let start = i32::try_from(start).unwrap();
+            let start = i32::try_from(range.start).unwrap();
+            let len = i32::try_from(range.len()).unwrap();
 
             let packet = CommandSuggestionsS2c {
                 id,
                 start: VarInt(start),
-                length: VarInt(0),
+                length: VarInt(len),
                 matches,
             };
 
             compose
                 .unicast(&packet, completion.connection_id())
-                .unwrap();
+                .unwrap_or_disconnected();
         };
 
         let handler = CommandHandler {
@@ -242,13 +197,31 @@ pub trait MinecraftCommand: Parser + CommandPermission + 'static {
         tracing::info!("registering command {name}");
 
         let mut registry = world.resource_mut::<CommandRegistry>();
-        registry
-            .get_mut()
-            .unwrap()
-            .register(name.as_str().to_string(), handler);
+        let registry = registry.get_mut().unwrap();
+        let name = name.as_str().to_string();
+
+        match Self::limits() {
+            Some(limits) => registry.register_with_limits(name, handler, limits),
+            None => registry.register(name, handler),
+        }
     }
 }
 
+/// Parses `command`, which starts with the name of the command, the way it is parsed when it is
+/// executed. See [`MinecraftArg::greedy`] for how it is split into arguments.
+///
+/// # Errors
+/// If `command` does not match the arguments of `C`
+pub fn parse_command<C: Parser>(command: &str) -> Result<C, clap::Error> {
+    C::try_parse_from(tree::split_args(&C::command(), command))
+}
+
+/// A [`CommandLimits::bypass`] function which lets admins ignore command limits
+#[must_use]
+pub fn admin_bypass(world: &World, caller: Entity) -> bool {
+    world.get::<Group>(caller) == Some(&Group::Admin)
+}
+
 pub enum Arg {
     Player,
 }
@@ -257,6 +230,22 @@ pub enum Arg {
 pub trait MinecraftArg {
     #[must_use]
     fn minecraft(self, parser: Arg) -> Self;
+
+    /// Makes a string argument take the rest of the command, including its whitespace, like the
+    /// message of `/broadcast <message...>`. The argument must be the last positional argument.
+    ///
+    /// ```
+    /// use hyperion_clap::MinecraftArg as _;
+    ///
+    /// #[derive(clap::Parser)]
+    /// #[command(name = "broadcast")]
+    /// struct BroadcastCommand {
+    ///     #[arg(greedy = true)]
+    ///     message: String,
+    /// }
+    /// ```
+    #[must_use]
+    fn greedy(self, greedy: bool) -> Self;
 }
 
 // Implement the trait for Arg
@@ -266,6 +255,15 @@ impl MinecraftArg for ClapArg {
             Arg::Player => self.value_hint(ValueHint::Username),
         }
     }
+
+    fn greedy(self, greedy: bool) -> Self {
+        if greedy {
+            self.value_hint(ValueHint::CommandString)
+                .allow_hyphen_values(true)
+        } else {
+            self
+        }
+    }
 }
 
 pub trait CommandPermission {
@@ -303,37 +301,42 @@ impl MinecraftCommand for PermissionCommand {
     type State = SystemState<Commands<'static, 'static>>;
 
     fn execute(self, world: &World, state: &mut Self::State, caller: Entity) {
+        self.execute_as(world, state, CommandCaller::Player(caller));
+    }
+
+    fn execute_as(self, world: &World, state: &mut Self::State, caller: CommandCaller) {
         let mut commands = state.get(world);
-        let compose = world.resource::<Compose>();
         let ign_map = world.resource::<IgnMap>();
-        let Some(&connection_id) = world.entity(caller).get::<ConnectionId>() else {
-            error!("permission command failed: caller is missing ConnectionId component");
-            return;
-        };
+        let reply = CommandReply::new(world, caller);
         match self {
             Self::Set(cmd) => {
                 // Handle setting permissions
-                let Some(&entity) = ign_map.get(cmd.player.as_str()) else {
-                    let msg = format!("§c{} not found", cmd.player);
-                    let chat = hyperion::net::agnostic::chat(msg);
-                    compose.unicast(&chat, connection_id).unwrap();
+                let Some(entity) = ign_map.get_ignore_case(&cmd.player) else {
+                    reply.reply_error(format!("{} not found", cmd.player));
                     return;
                 };
 
-                commands.entity(entity).insert(cmd.group);
+                // Going through set_group saves the group and records it in the audit log
+                let actor = caller.entity();
+                let group = cmd.group;
+                commands.queue(move |world: &mut World| {
+                    if let Err(e) = set_group(world, actor, entity, group) {
+                        error!("failed to set the group of {entity}: {e}");
+                    }
+                });
 
-                let msg = format!(
+                reply.reply(format!(
                     "§b{}§r's group has been set to §e{:?}",
                     cmd.player, cmd.group
-                );
-                let chat = hyperion::net::agnostic::chat(msg);
-                compose.unicast(&chat, connection_id).unwrap();
+                ));
+                reply.broadcast_to_ops(format!(
+                    "Set the group of {} to {:?}",
+                    cmd.player, cmd.group
+                ));
             }
             Self::Get(cmd) => {
-                let Some(&entity) = ign_map.get(cmd.player.as_str()) else {
-                    let msg = format!("§c{} not found", cmd.player);
-                    let chat = hyperion::net::agnostic::chat(msg);
-                    compose.unicast(&chat, connection_id).unwrap();
+                let Some(entity) = ign_map.get_ignore_case(&cmd.player) else {
+                    reply.reply_error(format!("{} not found", cmd.player));
                     return;
                 };
 
@@ -342,9 +345,7 @@ impl MinecraftCommand for PermissionCommand {
                     return;
                 };
 
-                let msg = format!("§b{}§r's group is §e{:?}", cmd.player, group);
-                let chat = hyperion::net::agnostic::chat(msg);
-                compose.unicast(&chat, connection_id).unwrap();
+                reply.reply(format!("§b{}§r's group is §e{:?}", cmd.player, group));
             }
         }
     }
@@ -356,5 +357,10 @@ impl Plugin for ClapCommandPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(hyperion_command::CommandPlugin);
         PermissionCommand::register(app.world_mut());
+        SudoCommand::register(app.world_mut());
+        NetstatCommand::register(app.world_mut());
+        GameRuleCommand::register(app.world_mut());
+        RegionCommand::register(app.world_mut());
+        DebugCommand::register(app.world_mut());
     }
 }
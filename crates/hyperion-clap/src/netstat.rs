@@ -0,0 +1,77 @@
+use bevy_ecs::{
+    entity::Entity,
+    system::{Commands, SystemState},
+    world::World,
+};
+use clap::Parser;
+use hyperion::{
+    ingress::counters::{InspectedPackets, inspect_packets},
+    simulation::IgnMap,
+};
+use hyperion_command::CommandCaller;
+
+use crate::{CommandPermission, CommandReply, MinecraftCommand};
+
+/// The number of packet types shown
+const TOP_PACKETS: usize = 10;
+
+/// Shows which packets a player sent most often over the last minute
+#[derive(Parser, CommandPermission, Debug)]
+#[command(name = "netstat")]
+#[command_permission(group = "Admin")]
+pub struct NetstatCommand {
+    player: String,
+}
+
+impl MinecraftCommand for NetstatCommand {
+    type State = SystemState<Commands<'static, 'static>>;
+
+    fn execute(self, world: &World, state: &mut Self::State, caller: Entity) {
+        self.execute_as(world, state, CommandCaller::Player(caller));
+    }
+
+    fn execute_as(self, world: &World, state: &mut Self::State, caller: CommandCaller) {
+        let mut commands = state.get(world);
+        let reply = CommandReply::new(world, caller);
+        let Some(target) = world.resource::<IgnMap>().get_ignore_case(&self.player) else {
+            reply.reply_error(format!("{} not found", self.player));
+            return;
+        };
+
+        // Packets are only counted per player while someone looks at them
+        let inspected = world.get::<InspectedPackets>(target);
+        commands.queue(inspect_packets(target));
+
+        let Some(inspected) = inspected else {
+            reply.reply(format!(
+                "§7Counting the packets of §f{}§7, run this again in a few seconds to see them",
+                self.player
+            ));
+            return;
+        };
+
+        let top = inspected.top();
+        if top.is_empty() {
+            reply.reply(format!(
+                "§7No packets of §f{}§7 were counted yet",
+                self.player
+            ));
+            return;
+        }
+
+        reply.reply(format!(
+            "§7Packets of §f{}§7 over the last §f{}s§7:",
+            self.player,
+            inspected.seconds()
+        ));
+        for rate in top.iter().take(TOP_PACKETS) {
+            reply.reply(format!(
+                "§f{} §8({:#04x}) §7{} §8({:.1}/s)",
+                rate.name.unwrap_or("Unknown"),
+                rate.id,
+                rate.total,
+                rate.per_second
+            ));
+        }
+    }
+}
@@ -0,0 +1,306 @@
+use bevy_ecs::{
+    entity::Entity,
+    system::{Commands, SystemState},
+    world::World,
+};
+use clap::{Parser, Subcommand, ValueEnum};
+use hyperion::{
+    simulation::{
+        Pitch, Position, Yaw,
+        blocks::Blocks,
+        region::{self, ProtectedRegions, RegionFlag, RegionSelection},
+        world::WorldId,
+    },
+    storage::{GroupSet, Region},
+};
+use hyperion_command::CommandCaller;
+use hyperion_permission::Group;
+use tracing::error;
+
+use crate::{CommandPermission, CommandReply, MinecraftCommand};
+
+/// A flag of a region which allows an action per group
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Flag {
+    Build,
+    Interact,
+    Entry,
+}
+
+impl From<Flag> for RegionFlag {
+    fn from(flag: Flag) -> Self {
+        match flag {
+            Flag::Build => Self::Build,
+            Flag::Interact => Self::Interact,
+            Flag::Entry => Self::Entry,
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum RegionAction {
+    /// Selects the block you look at as a corner of the next region
+    Select,
+    /// Defines a region between the last two selected corners. Redefining a region keeps its
+    /// flags.
+    Define {
+        id: String,
+        #[arg(allow_negative_numbers = true)]
+        priority: Option<i32>,
+    },
+    /// Changes which of the overlapping regions decides a flag; the highest priority wins
+    Priority {
+        id: String,
+        #[arg(allow_negative_numbers = true)]
+        priority: i32,
+    },
+    /// Allows a group to build, interact or enter
+    Allow {
+        id: String,
+        flag: Flag,
+        group: Group,
+    },
+    /// Denies a group to build, interact or enter
+    Deny {
+        id: String,
+        flag: Flag,
+        group: Group,
+    },
+    /// Enables or disables PvP. Leaving out the value leaves PvP to other regions.
+    Pvp { id: String, enabled: Option<bool> },
+    /// Leaves a flag to other regions
+    Clear { id: String, flag: Flag },
+    /// Removes a region
+    Remove { id: String },
+    /// Lists all regions
+    List,
+}
+
+/// Manages protected regions
+#[derive(Parser, CommandPermission, Debug)]
+#[command(name = "region")]
+#[command_permission(group = "Admin")]
+pub struct RegionCommand {
+    #[command(subcommand)]
+    action: RegionAction,
+}
+
+fn describe(id: &str, region: &Region) -> String {
+    let groups = |groups: Option<GroupSet>| {
+        groups.map_or_else(
+            || "-".to_owned(),
+            |groups| {
+                let names = groups
+                    .iter()
+                    .filter_map(Group::from_u8)
+                    .map(|group| format!("{group:?}"))
+                    .collect::<Vec<_>>();
+                format!("[{}]", names.join(", "))
+            },
+        )
+    };
+    let pvp = region
+        .flags
+        .pvp
+        .map_or_else(|| "-".to_owned(), |pvp| pvp.to_string());
+
+    format!(
+        "§f{id}§7 from §f{}§7 to §f{}§7, priority §f{}§7, build §f{}§7, interact §f{}§7, entry \
+         §f{}§7, pvp §f{pvp}",
+        region.min,
+        region.max,
+        region.priority,
+        groups(region.flags.build),
+        groups(region.flags.interact),
+        groups(region.flags.entry),
+    )
+}
+
+/// Allows or denies `group` what `flag` controls in `region`
+fn allow(region: &mut Region, flag: Flag, group: Group, allowed: bool) {
+    let groups = RegionFlag::from(flag).get_mut(&mut region.flags);
+
+    // An unset flag allows every group
+    let current = groups.unwrap_or(GroupSet::ALL);
+    *groups = Some(if allowed {
+        current.with(group.to_u8())
+    } else {
+        current.without(group.to_u8())
+    });
+}
+
+/// Defines `region` as `id` once the command is applied and replies with the new definition
+fn define(commands: &mut Commands<'_, '_>, caller: CommandCaller, id: String, region: Region) {
+    commands.queue(move |world: &mut World| {
+        let result = world.resource_mut::<ProtectedRegions>().define(&id, region);
+
+        let reply = CommandReply::new(world, caller);
+        match result {
+            Ok(()) => reply.reply(describe(&id, &region)),
+            Err(e) => {
+                error!("failed to define region {id}: {e}");
+                reply.reply_error(format!("Failed to save region {id}"));
+            }
+        }
+    });
+}
+
+impl RegionCommand {
+    fn select(world: &World, commands: &mut Commands<'_, '_>, reply: CommandReply<'_>) {
+        let CommandCaller::Player(player) = reply.caller() else {
+            reply.reply_error("Only players can select corners");
+            return;
+        };
+
+        let entity = world.entity(player);
+        if entity
+            .get::<WorldId>()
+            .is_some_and(|&id| id != WorldId::PRIMARY)
+        {
+            reply.reply_error("Regions can only be defined in the primary world");
+            return;
+        }
+
+        let (Some(position), Some(yaw), Some(pitch)) = (
+            entity.get::<Position>(),
+            entity.get::<Yaw>(),
+            entity.get::<Pitch>(),
+        ) else {
+            error!("failed to select corner: player is missing Position, Yaw or Pitch");
+            return;
+        };
+
+        let blocks = world.resource::<Blocks>();
+        let Some(corner) = region::targeted_block(blocks, **position, **yaw, **pitch) else {
+            reply.reply_error("Look at the block you want to select");
+            return;
+        };
+
+        let mut selection = entity.get::<RegionSelection>().copied().unwrap_or_default();
+        selection.push(corner);
+        commands.entity(player).insert(selection);
+
+        reply.reply(format!("§7Selected §f{corner}"));
+    }
+}
+
+impl MinecraftCommand for RegionCommand {
+    type State = SystemState<Commands<'static, 'static>>;
+
+    fn execute(self, world: &World, state: &mut Self::State, caller: Entity) {
+        self.execute_as(world, state, CommandCaller::Player(caller));
+    }
+
+    fn execute_as(self, world: &World, state: &mut Self::State, caller: CommandCaller) {
+        let mut commands = state.get(world);
+        let reply = CommandReply::new(world, caller);
+        let regions = world.resource::<ProtectedRegions>();
+
+        let existing = |id: &str| {
+            let region = regions.get(id).copied();
+            if region.is_none() {
+                reply.reply_error(format!("Region {id} does not exist"));
+            }
+            region
+        };
+
+        let (id, region) = match self.action {
+            RegionAction::Select => {
+                Self::select(world, &mut commands, reply);
+                return;
+            }
+            RegionAction::Define { id, priority } => {
+                let corners = match caller {
+                    CommandCaller::Player(player) => world
+                        .get::<RegionSelection>(player)
+                        .and_then(RegionSelection::corners),
+                    CommandCaller::Console => None,
+                };
+
+                let Some((a, b)) = corners else {
+                    reply.reply_error("Select two corners with /region select first");
+                    return;
+                };
+
+                let mut region = Region::new(a, b);
+                if let Some(previous) = regions.get(&id) {
+                    region.priority = previous.priority;
+                    region.flags = previous.flags;
+                }
+                if let Some(priority) = priority {
+                    region.priority = priority;
+                }
+
+                (id, region)
+            }
+            RegionAction::Priority { id, priority } => {
+                let Some(mut region) = existing(&id) else {
+                    return;
+                };
+
+                region.priority = priority;
+                (id, region)
+            }
+            RegionAction::Allow { id, flag, group } => {
+                let Some(mut region) = existing(&id) else {
+                    return;
+                };
+
+                allow(&mut region, flag, group, true);
+                (id, region)
+            }
+            RegionAction::Deny { id, flag, group } => {
+                let Some(mut region) = existing(&id) else {
+                    return;
+                };
+
+                allow(&mut region, flag, group, false);
+                (id, region)
+            }
+            RegionAction::Pvp { id, enabled } => {
+                let Some(mut region) = existing(&id) else {
+                    return;
+                };
+
+                region.flags.pvp = enabled;
+                (id, region)
+            }
+            RegionAction::Clear { id, flag } => {
+                let Some(mut region) = existing(&id) else {
+                    return;
+                };
+
+                *RegionFlag::from(flag).get_mut(&mut region.flags) = None;
+                (id, region)
+            }
+            RegionAction::Remove { id } => {
+                commands.queue(move |world: &mut World| {
+                    let result = world.resource_mut::<ProtectedRegions>().remove(&id);
+
+                    let reply = CommandReply::new(world, caller);
+                    match result {
+                        Ok(Some(_)) => reply.reply(format!("§7Removed region §f{id}")),
+                        Ok(None) => reply.reply_error(format!("Region {id} does not exist")),
+                        Err(e) => {
+                            error!("failed to remove region {id}: {e}");
+                            reply.reply_error(format!("Failed to remove region {id}"));
+                        }
+                    }
+                });
+                return;
+            }
+            RegionAction::List => {
+                if regions.iter().next().is_none() {
+                    reply.reply("§7There are no regions");
+                }
+
+                for (id, region) in regions.iter() {
+                    reply.reply(describe(id, region));
+                }
+                return;
+            }
+        };
+
+        define(&mut commands, caller, id, region);
+    }
+}
@@ -0,0 +1,152 @@
+use bevy_ecs::{entity::Entity, name::Name, world::World};
+use clap::CommandFactory;
+use hyperion::net::{Compose, ConnectionId, SendResultExt};
+use hyperion_command::{CommandCaller, strip_formatting};
+use hyperion_permission::Group;
+use tracing::{error, info};
+use valence_protocol::{
+    packets::play,
+    text::{Color, IntoText, Text},
+};
+
+/// Sends the output of a command to its caller. Players receive messages in chat, while messages
+/// to the console are logged without their formatting.
+///
+/// Messages to players which disconnected are dropped, so none of the methods panic.
+#[derive(Copy, Clone)]
+pub struct CommandReply<'w> {
+    world: &'w World,
+    caller: CommandCaller,
+}
+
+impl<'w> CommandReply<'w> {
+    #[must_use]
+    pub const fn new(world: &'w World, caller: CommandCaller) -> Self {
+        Self { world, caller }
+    }
+
+    #[must_use]
+    pub const fn caller(&self) -> CommandCaller {
+        self.caller
+    }
+
+    pub fn reply<'t>(&self, message: impl IntoText<'t>) {
+        let message = message.into_cow_text();
+
+        match self.caller {
+            CommandCaller::Player(entity) => {
+                let Some(&connection_id) = self.world.get::<ConnectionId>(entity) else {
+                    error!("failed to reply to command: caller is missing ConnectionId component");
+                    return;
+                };
+
+                self.send(connection_id, &play::GameMessageS2c {
+                    chat: message,
+                    overlay: false,
+                });
+            }
+            CommandCaller::Console => {
+                info!("{}", strip_formatting(&message.to_legacy_lossy()));
+            }
+        }
+    }
+
+    /// Replies with an error, which is shown in red and prefixed with `Error: `
+    pub fn reply_error<'t>(&self, message: impl IntoText<'t>) {
+        self.reply(error_text(message));
+    }
+
+    /// Replies with the usage of `C` generated by clap, such as after the arguments of a command
+    /// turned out to be invalid while executing it
+    pub fn reply_usage<C: CommandFactory>(&self) {
+        let usage = C::command().render_usage().to_string();
+        self.reply_error(usage);
+    }
+
+    /// Shows `message` to every admin other than the caller in gray italics as
+    /// `[<caller>: <message>]`, like vanilla shows the commands of operators to other operators.
+    /// The message is logged to the console as well.
+    pub fn broadcast_to_ops<'t>(&self, message: impl IntoText<'t>) {
+        let name = match self.caller {
+            CommandCaller::Player(entity) => self
+                .world
+                .get::<Name>(entity)
+                .map_or_else(|| "Unknown".to_owned(), |name| name.as_str().to_owned()),
+            CommandCaller::Console => "Server".to_owned(),
+        };
+
+        let message = ops_text(&name, message);
+
+        // The console already knows about its own actions
+        if !self.caller.is_console() {
+            info!("{}", strip_formatting(&message.to_legacy_lossy()));
+        }
+
+        let Some(mut query) = self.world.try_query::<(Entity, &Group, &ConnectionId)>() else {
+            return;
+        };
+
+        let packet = play::GameMessageS2c {
+            chat: message.into(),
+            overlay: false,
+        };
+
+        for (entity, group, &connection_id) in query.iter(self.world) {
+            if *group == Group::Admin && self.caller.entity() != Some(entity) {
+                self.send(connection_id, &packet);
+            }
+        }
+    }
+
+    fn send(&self, connection_id: ConnectionId, packet: &play::GameMessageS2c<'_>) {
+        let compose = self.world.resource::<Compose>();
+        compose
+            .unicast(packet, connection_id)
+            .unwrap_or_disconnected();
+    }
+}
+
+fn error_text<'t>(message: impl IntoText<'t>) -> Text {
+    ("Error: ".into_text() + message.into_text()).color(Color::RED)
+}
+
+fn ops_text<'t>(name: &str, message: impl IntoText<'t>) -> Text {
+    (format!("[{name}: ").into_text() + message.into_text() + "]")
+        .color(Color::GRAY)
+        .italic()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PermissionCommand;
+
+    #[test]
+    fn replies_to_callers_without_a_connection_are_dropped() {
+        let mut world = World::new();
+        let player = world.spawn((Group::Admin, Name::new("Notch"))).id();
+
+        for caller in [CommandCaller::Console, CommandCaller::Player(player)] {
+            let reply = CommandReply::new(&world, caller);
+            reply.reply("§aDone");
+            reply.reply_error("Player not found");
+            reply.reply_usage::<PermissionCommand>();
+            reply.broadcast_to_ops("Set the group of Notch to Admin");
+        }
+    }
+
+    #[test]
+    fn replies_are_formatted() {
+        let plain = |text: Text| strip_formatting(&text.to_legacy_lossy());
+
+        let error = error_text("Player not found");
+        assert!(error.to_legacy_lossy().starts_with("§c"));
+        assert_eq!(plain(error), "Error: Player not found");
+
+        let usage = PermissionCommand::command().render_usage().to_string();
+        assert!(plain(error_text(usage)).starts_with("Error: Usage: "));
+
+        let broadcast = ops_text("Notch", "Set the group of Notch to Admin");
+        assert_eq!(plain(broadcast), "[Notch: Set the group of Notch to Admin]");
+    }
+}
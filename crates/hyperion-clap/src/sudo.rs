@@ -0,0 +1,77 @@
+use bevy_ecs::{entity::Entity, system::SystemState, world::World};
+use clap::Parser;
+use hyperion::{
+    simulation::{IgnMap, Uuid},
+    storage::{AuditAction, AuditEntry, AuditLog},
+};
+use hyperion_command::{CommandCaller, CommandDispatcher};
+use tracing::warn;
+
+use crate::{CommandPermission, CommandReply, MinecraftCommand};
+
+/// Runs a command as another player
+#[derive(Parser, CommandPermission, Debug)]
+#[command(name = "sudo")]
+#[command_permission(group = "Admin")]
+pub struct SudoCommand {
+    /// Check the permissions of the command against yourself instead of the player
+    #[arg(long)]
+    as_admin: bool,
+
+    player: String,
+
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true, required = true)]
+    command: Vec<String>,
+}
+
+impl MinecraftCommand for SudoCommand {
+    type State = SystemState<()>;
+
+    fn execute(self, world: &World, state: &mut Self::State, caller: Entity) {
+        self.execute_as(world, state, CommandCaller::Player(caller));
+    }
+
+    fn execute_as(self, world: &World, _state: &mut Self::State, caller: CommandCaller) {
+        let reply = CommandReply::new(world, caller);
+        let Some(target) = world.resource::<IgnMap>().get_ignore_case(&self.player) else {
+            reply.reply_error(format!("{} not found", self.player));
+            return;
+        };
+
+        let command = self.command.join(" ");
+        let command = command.strip_prefix('/').unwrap_or(&command);
+        let permissions = if self.as_admin {
+            caller
+        } else {
+            CommandCaller::Player(target)
+        };
+
+        let dispatcher = world.resource::<CommandDispatcher>();
+        if let Err(e) = dispatcher.dispatch(CommandCaller::Player(target), permissions, command) {
+            reply.reply_error(e.to_string());
+            return;
+        }
+
+        if let Some(log) = world.get_resource::<AuditLog>() {
+            let uuid = |entity: Entity| world.get::<Uuid>(entity).map(|uuid| **uuid);
+            let actor = caller.entity().and_then(uuid);
+            let detail = if self.as_admin {
+                format!("{command} (with own permissions)")
+            } else {
+                command.to_owned()
+            };
+
+            if let Err(e) = log.record(AuditEntry::now(
+                actor,
+                AuditAction::Command,
+                uuid(target),
+                detail,
+            )) {
+                warn!("failed to record sudo in audit log: {e}");
+            }
+        }
+
+        reply.reply(format!("§7Running §f/{command}§7 as {}", self.player));
+        reply.broadcast_to_ops(format!("Ran /{command} as {}", self.player));
+    }
+}
@@ -0,0 +1,302 @@
+//! Converting clap commands to the command tree sent to clients, and parsing and completing
+//! commands the same way the client validates them.
+
+use std::ops::Range;
+
+use bevy_ecs::{entity::Entity, hierarchy::ChildOf, world::World};
+use clap::{Arg, Command as ClapCommand, ValueHint};
+use hyperion::simulation::command::Command;
+use valence_bytes::Utf8Bytes;
+use valence_protocol::packets::play::command_tree_s2c::{Parser, StringArg};
+
+/// Whether `arg` is a string containing the rest of the command. See
+/// [`MinecraftArg::greedy`](crate::MinecraftArg::greedy).
+fn joins_rest(arg: &Arg) -> bool {
+    arg.get_value_hint() == ValueHint::CommandString
+}
+
+/// Whether `arg` takes the rest of the command, either as a single string or as a trailing var arg
+fn is_greedy(arg: &Arg) -> bool {
+    joins_rest(arg) || arg.is_trailing_var_arg_set()
+}
+
+/// Whether `command` may be executed without any further arguments
+fn runs_without_args(command: &ClapCommand) -> bool {
+    if command.has_subcommands()
+        && (command.is_subcommand_required_set() || command.is_arg_required_else_help_set())
+    {
+        return false;
+    }
+
+    command
+        .get_positionals()
+        .next()
+        .is_none_or(|arg| !arg.is_required_set())
+}
+
+fn argument_name(arg: &Arg) -> String {
+    arg.get_value_names()
+        .and_then(|names| names.first())
+        .map_or_else(
+            || arg.get_id().to_string(),
+            |name| name.to_ascii_lowercase(),
+        )
+}
+
+/// The literal node of `command`, which is executable if the command needs no arguments
+pub(crate) fn literal(
+    command: &ClapCommand,
+    has_permission: fn(world: &World, caller: Entity) -> bool,
+) -> Command {
+    Command::literal(Utf8Bytes::copy_from_str(command.get_name()), has_permission)
+        .executable(runs_without_args(command))
+}
+
+/// Spawns the nodes of the subcommands and positional arguments of `command` below `parent`, the
+/// node of `command`.
+///
+/// Optional arguments make the node before them executable, so the client accepts the command
+/// without them. Options and flags are not part of the tree, since the client cannot parse them.
+pub(crate) fn spawn_children(
+    world: &mut World,
+    parent: Entity,
+    command: &ClapCommand,
+    has_permission: fn(world: &World, caller: Entity) -> bool,
+) {
+    for subcommand in command.get_subcommands() {
+        if subcommand.is_hide_set() {
+            continue;
+        }
+
+        let node = literal(subcommand, has_permission);
+        let node = world.spawn((node, ChildOf(parent))).id();
+        spawn_children(world, node, subcommand, has_permission);
+    }
+
+    let mut on = parent;
+    let mut positionals = command.get_positionals().peekable();
+    while let Some(arg) = positionals.next() {
+        let greedy = is_greedy(arg);
+        let string = if greedy {
+            StringArg::GreedyPhrase
+        } else {
+            StringArg::SingleWord
+        };
+        let executable = greedy
+            || positionals
+                .peek()
+                .is_none_or(|next| !next.is_required_set());
+
+        let node =
+            Command::argument(argument_name(arg), Parser::String(string)).executable(executable);
+        on = world.spawn((node, ChildOf(on))).id();
+
+        // A greedy argument takes the rest of the command
+        if greedy {
+            break;
+        }
+    }
+}
+
+/// Returns whether `word` is an option or a flag rather than a value
+fn is_option(word: &str) -> bool {
+    word.len() > 1 && word.starts_with('-') && word.parse::<f64>().is_err()
+}
+
+/// Returns the option of `command` that `word` sets, if any
+fn find_option<'a>(command: &'a ClapCommand, word: &str) -> Option<&'a Arg> {
+    if let Some(long) = word.strip_prefix("--") {
+        let long = long.split_once('=').map_or(long, |(long, _)| long);
+        return command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(long));
+    }
+
+    let mut short = word.strip_prefix('-')?.chars();
+    let short = short.next().filter(|_| short.as_str().is_empty())?;
+    command
+        .get_arguments()
+        .find(|arg| arg.get_short() == Some(short))
+}
+
+/// Splits `input`, which starts with the name of `command`, into the arguments passed to clap.
+///
+/// Arguments are separated by whitespace, except that a string argument marked with
+/// [`MinecraftArg::greedy`](crate::MinecraftArg::greedy) receives the rest of the input as a
+/// single argument.
+pub(crate) fn split_args<'a>(command: &ClapCommand, input: &'a str) -> Vec<&'a str> {
+    let mut args = Vec::new();
+    let mut words = input.split_whitespace();
+    let mut command = command;
+    let mut position = 0;
+
+    // The name of the command
+    args.extend(words.next());
+
+    while let Some(word) = words.next() {
+        if position == 0
+            && let Some(subcommand) = command.find_subcommand(word)
+        {
+            command = subcommand;
+            args.push(word);
+            continue;
+        }
+
+        if is_option(word) {
+            args.push(word);
+            if !word.contains('=')
+                && find_option(command, word).is_some_and(|arg| arg.get_action().takes_values())
+            {
+                args.extend(words.next());
+            }
+            continue;
+        }
+
+        let arg = command.get_positionals().nth(position);
+        position += 1;
+
+        if arg.is_some_and(joins_rest) {
+            let start = word.as_ptr() as usize - input.as_ptr() as usize;
+            args.push(input[start..].trim_end());
+            break;
+        }
+
+        args.push(word);
+    }
+
+    args
+}
+
+/// The names in `names` that start with `word`, ignoring case
+fn starting_with<'a>(names: impl Iterator<Item = &'a str>, word: &str) -> Vec<String> {
+    let word = word.to_lowercase();
+    names
+        .filter(|name| name.to_lowercase().starts_with(&word))
+        .map(str::to_owned)
+        .collect()
+}
+
+fn subcommand_names(command: &ClapCommand) -> impl Iterator<Item = &str> {
+    command
+        .get_subcommands()
+        .filter(|subcommand| !subcommand.is_hide_set())
+        .map(ClapCommand::get_name)
+}
+
+/// Returns the suggestions for `query`, which starts with the name of `command`, and the range of
+/// `query` they replace.
+///
+/// If a word of `query` is not a valid subcommand or value, the names starting with it are
+/// suggested. Otherwise, every name for the argument after `query` is suggested. Arguments
+/// without possible values, such as strings, accept any word.
+pub(crate) fn suggest(command: &ClapCommand, query: &str) -> Option<(Range<usize>, Vec<String>)> {
+    let mut command = command;
+    let mut position = 0;
+
+    for word in query.split_whitespace().skip(1) {
+        let start = word.as_ptr() as usize - query.as_ptr() as usize;
+        let range = start..start + word.len();
+
+        if position == 0 && command.has_subcommands() {
+            if let Some(subcommand) = command.find_subcommand(word) {
+                command = subcommand;
+                continue;
+            }
+
+            let matches = starting_with(subcommand_names(command), word);
+            return (!matches.is_empty()).then_some((range, matches));
+        }
+
+        let arg = command
+            .get_positionals()
+            .nth(position)
+            .filter(|arg| !is_greedy(arg))?;
+        position += 1;
+
+        let possible_values = arg.get_possible_values();
+        if possible_values.is_empty()
+            || possible_values
+                .iter()
+                .any(|possible| possible.matches(word, true))
+        {
+            continue;
+        }
+
+        let names = possible_values
+            .iter()
+            .filter(|possible| !possible.is_hide_set())
+            .map(clap::builder::PossibleValue::get_name);
+        let matches = starting_with(names, word);
+        return (!matches.is_empty()).then_some((range, matches));
+    }
+
+    let end = query.len();
+
+    if position == 0 && command.has_subcommands() {
+        let names = subcommand_names(command).map(str::to_owned).collect();
+        return Some((end..end, names));
+    }
+
+    let arg = command
+        .get_positionals()
+        .nth(position)
+        .filter(|arg| !is_greedy(arg))?;
+    let names = arg
+        .get_possible_values()
+        .iter()
+        .filter(|possible| !possible.is_hide_set())
+        .map(|possible| possible.get_name().to_owned())
+        .collect();
+
+    Some((end..end, names))
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::{CommandFactory, Parser, ValueEnum};
+
+    use super::*;
+    use crate::MinecraftArg as _;
+
+    #[derive(Clone, Debug, ValueEnum)]
+    enum Color {
+        #[value(name = "light-blue")]
+        LightBlue,
+        Red,
+        #[value(hide = true)]
+        Secret,
+    }
+
+    #[derive(Parser, Debug)]
+    #[command(name = "paint")]
+    struct Paint {
+        player: String,
+        color: Color,
+        #[arg(greedy = true)]
+        note: Option<String>,
+    }
+
+    #[test]
+    fn greedy_strings_are_split_after_options() {
+        let command = Paint::command();
+        assert_eq!(split_args(&command, "paint Bob red a   b c "), [
+            "paint", "Bob", "red", "a   b c"
+        ]);
+        assert_eq!(split_args(&command, "paint -h Bob"), ["paint", "-h", "Bob"]);
+    }
+
+    #[test]
+    fn custom_names_are_suggested() {
+        let command = Paint::command();
+        assert_eq!(
+            suggest(&command, "/paint Bob "),
+            Some((11..11, vec!["light-blue".to_owned(), "red".to_owned()]))
+        );
+        assert_eq!(
+            suggest(&command, "/paint Bob LIG"),
+            Some((11..14, vec!["light-blue".to_owned()]))
+        );
+        assert_eq!(suggest(&command, "/paint Bob red "), None);
+        assert_eq!(suggest(&command, "/paint Bob blue"), None);
+    }
+}
@@ -0,0 +1,143 @@
+//! Commands using optional arguments, greedy strings and enums with custom names must be parsed
+//! like the command tree sent to the client describes them.
+
+use bevy_app::App;
+use bevy_ecs::{entity::Entity, system::SystemState, world::World};
+use clap::{Parser, ValueEnum};
+use hyperion::simulation::command::get_command_packet;
+use hyperion_clap::{
+    CommandPermission, MinecraftArg as _, MinecraftCommand, PermissionCommand, parse_command,
+};
+use valence_protocol::packets::play::{
+    CommandTreeS2c,
+    command_tree_s2c::{NodeData, Parser as NodeParser, StringArg},
+};
+
+#[derive(Clone, Debug, ValueEnum, PartialEq, Eq)]
+enum Mob {
+    #[value(name = "iron-golem")]
+    IronGolem,
+    Zombie,
+}
+
+#[derive(Parser, CommandPermission, Debug)]
+#[command(name = "summon")]
+#[command_permission(group = "Normal")]
+struct SummonCommand {
+    mob: Mob,
+    count: Option<u32>,
+}
+
+impl MinecraftCommand for SummonCommand {
+    type State = SystemState<()>;
+
+    fn execute(self, _world: &World, _state: &mut Self::State, _caller: Entity) {}
+}
+
+#[derive(Parser, CommandPermission, Debug)]
+#[command(name = "broadcast")]
+#[command_permission(group = "Normal")]
+struct BroadcastCommand {
+    #[arg(long)]
+    title: Option<String>,
+
+    #[arg(greedy = true)]
+    message: String,
+}
+
+impl MinecraftCommand for BroadcastCommand {
+    type State = SystemState<()>;
+
+    fn execute(self, _world: &World, _state: &mut Self::State, _caller: Entity) {}
+}
+
+/// Registers `C` and returns the command tree sent to clients
+fn command_tree<C: MinecraftCommand>() -> CommandTreeS2c {
+    let mut app = App::new();
+    app.add_plugins((
+        hyperion::simulation::command::CommandPlugin,
+        hyperion_clap::hyperion_command::CommandPlugin,
+    ));
+    C::register(app.world_mut());
+    get_command_packet(app.world(), None)
+}
+
+fn is_string_arg(data: &NodeData, name: &str, kind: StringArg) -> bool {
+    matches!(
+        data,
+        NodeData::Argument {
+            name: arg_name,
+            parser: NodeParser::String(arg_kind),
+            ..
+        } if arg_name.as_str() == name && *arg_kind == kind
+    )
+}
+
+#[test]
+fn optional_arguments_are_executable_at_their_parent() {
+    let tree = command_tree::<SummonCommand>();
+    let [_, summon, mob, count] = &tree.commands[..] else {
+        panic!("unexpected command tree: {tree:?}");
+    };
+
+    // The mob is required, but the count is not
+    assert!(!summon.executable);
+    assert!(is_string_arg(&mob.data, "mob", StringArg::SingleWord));
+    assert!(mob.executable);
+    assert!(is_string_arg(&count.data, "count", StringArg::SingleWord));
+    assert!(count.executable);
+
+    let summon = parse_command::<SummonCommand>("summon zombie").unwrap();
+    assert_eq!(summon.mob, Mob::Zombie);
+    assert_eq!(summon.count, None);
+    assert!(parse_command::<SummonCommand>("summon").is_err());
+}
+
+#[test]
+fn enums_are_parsed_from_their_custom_names() {
+    let summon = parse_command::<SummonCommand>("summon iron-golem 3").unwrap();
+    assert_eq!(summon.mob, Mob::IronGolem);
+    assert_eq!(summon.count, Some(3));
+
+    assert!(parse_command::<SummonCommand>("summon IronGolem").is_err());
+}
+
+#[test]
+fn greedy_strings_take_the_rest_of_the_command() {
+    let tree = command_tree::<BroadcastCommand>();
+    let [_, broadcast, message] = &tree.commands[..] else {
+        panic!("unexpected command tree: {tree:?}");
+    };
+
+    assert!(!broadcast.executable);
+    assert!(is_string_arg(
+        &message.data,
+        "message",
+        StringArg::GreedyPhrase
+    ));
+    assert!(message.executable);
+
+    let broadcast =
+        parse_command::<BroadcastCommand>("broadcast --title News  the  server -restarts ")
+            .unwrap();
+    assert_eq!(broadcast.title.as_deref(), Some("News"));
+    assert_eq!(broadcast.message, "the  server -restarts");
+
+    assert!(parse_command::<BroadcastCommand>("broadcast").is_err());
+}
+
+#[test]
+fn subcommands_are_literals() {
+    let tree = command_tree::<PermissionCommand>();
+    let [_, perms, ..] = &tree.commands[..] else {
+        panic!("unexpected command tree: {tree:?}");
+    };
+
+    assert!(!perms.executable);
+    assert_eq!(perms.children.len(), 2);
+    for child in &perms.children {
+        let child = &tree.commands[usize::try_from(child.0).unwrap()];
+        assert!(matches!(child.data, NodeData::Literal { .. }));
+        assert!(!child.executable);
+    }
+}
@@ -0,0 +1,83 @@
+use bevy_ecs::{entity::Entity, world::World};
+use hyperion::net::{Compose, ConnectionId, SendResultExt, agnostic};
+use tracing::{error, info};
+
+/// Who executed a command
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CommandCaller {
+    /// A player who sent the command from their client
+    Player(Entity),
+    /// The server console. The console has every permission.
+    Console,
+}
+
+impl CommandCaller {
+    /// Returns the player entity, or [`None`] for the console
+    #[must_use]
+    pub const fn entity(self) -> Option<Entity> {
+        match self {
+            Self::Player(entity) => Some(entity),
+            Self::Console => None,
+        }
+    }
+
+    #[must_use]
+    pub const fn is_console(self) -> bool {
+        matches!(self, Self::Console)
+    }
+
+    /// Sends a message to the caller. Players receive it in chat, while messages to the console
+    /// are logged without their formatting codes.
+    pub fn reply(self, world: &World, message: impl Into<String>) {
+        let message = message.into();
+
+        match self {
+            Self::Player(entity) => {
+                let Some(&connection_id) = world.get::<ConnectionId>(entity) else {
+                    error!("failed to reply to command: caller is missing ConnectionId component");
+                    return;
+                };
+
+                let compose = world.resource::<Compose>();
+                compose
+                    .unicast(&agnostic::chat(message), connection_id)
+                    .unwrap_or_disconnected();
+            }
+            Self::Console => {
+                info!("{}", strip_formatting(&message));
+            }
+        }
+    }
+}
+
+/// Removes `§` formatting codes, which are meaningless in a terminal
+#[must_use]
+pub fn strip_formatting(message: &str) -> String {
+    let mut stripped = String::with_capacity(message.len());
+    let mut chars = message.chars();
+
+    while let Some(c) = chars.next() {
+        if c == '§' {
+            chars.next();
+        } else {
+            stripped.push(c);
+        }
+    }
+
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formatting_is_stripped() {
+        assert_eq!(
+            strip_formatting("§cAvailable commands: §r[a, b]"),
+            "Available commands: [a, b]"
+        );
+        assert_eq!(strip_formatting("plain"), "plain");
+        assert_eq!(strip_formatting("trailing §"), "trailing ");
+    }
+}
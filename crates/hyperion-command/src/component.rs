@@ -1,4 +1,4 @@
-use std::sync::Mutex;
+use std::{collections::HashMap, sync::Mutex};
 
 use bevy_app::{App, Plugin};
 use bevy_ecs::{entity::Entity, resource::Resource, world::World};
@@ -8,9 +8,29 @@ use indexmap::IndexMap;
 #[cfg(feature = "reflect")]
 use {bevy_ecs::reflect::ReflectResource, bevy_reflect::Reflect};
 
+use crate::{
+    CommandCaller, CommandDispatcher,
+    limit::{CommandLimits, CommandUsage},
+};
+
 pub trait ExecutableCommand: ApplyWorld {
-    /// Executes a command triggered by a player
-    fn execute(&mut self, world: &World, execution: &play::CommandExecution);
+    /// Executes a command triggered by a player or the console. `command` is the full command
+    /// without the leading `/`.
+    fn execute(&mut self, world: &World, caller: CommandCaller, command: &str);
+
+    /// Executes a command as `caller` while checking permissions against `permissions`. This is
+    /// used for commands dispatched on behalf of another caller. By default, permissions are not
+    /// checked by the command itself, so this runs [`ExecutableCommand::execute`].
+    fn execute_with_permissions(
+        &mut self,
+        world: &World,
+        caller: CommandCaller,
+        permissions: CommandCaller,
+        command: &str,
+    ) {
+        let _ = permissions;
+        self.execute(world, caller, command);
+    }
 }
 
 pub struct CommandHandler {
@@ -22,6 +42,12 @@ pub struct CommandHandler {
 #[derive(Default)]
 pub struct CommandRegistryInner {
     pub(crate) commands: IndexMap<String, CommandHandler>,
+    pub(crate) limits: HashMap<String, CommandLimits>,
+    /// Usage of limited commands, keyed by the player and the command name. This is kept separate
+    /// from the command tree so resending the tree does not reset cooldowns.
+    pub(crate) usage: HashMap<(Entity, String), CommandUsage>,
+    /// The number of ticks commands have been executed for
+    pub(crate) tick: u64,
 }
 
 impl CommandRegistryInner {
@@ -30,6 +56,57 @@ impl CommandRegistryInner {
         self.commands.insert(name, handler);
     }
 
+    /// Registers a command which players may only execute as often as `limits` allows
+    pub fn register_with_limits(
+        &mut self,
+        name: impl Into<String>,
+        handler: CommandHandler,
+        limits: CommandLimits,
+    ) {
+        let name = name.into();
+        self.limits.insert(name.clone(), limits);
+        self.commands.insert(name, handler);
+    }
+
+    /// Sets the limits of an already registered command. Existing usage is kept.
+    pub fn set_limits(&mut self, name: impl Into<String>, limits: CommandLimits) {
+        self.limits.insert(name.into(), limits);
+    }
+
+    /// Checks the limits of `command` for `caller`, recording the execution if it is allowed.
+    ///
+    /// Returns the rejection message if the execution is not allowed.
+    pub(crate) fn check_limits(
+        &mut self,
+        world: &World,
+        caller: CommandCaller,
+        command: &str,
+    ) -> Option<&str> {
+        // The console is never limited
+        let CommandCaller::Player(caller) = caller else {
+            return None;
+        };
+
+        let limits = self.limits.get(command)?;
+
+        if (limits.bypass)(world, caller) {
+            return None;
+        }
+
+        let usage = self.usage.entry((caller, command.to_owned())).or_default();
+
+        if usage.try_use(limits, self.tick) {
+            None
+        } else {
+            Some(&limits.message)
+        }
+    }
+
+    /// Removes all usage of `caller`
+    pub(crate) fn forget(&mut self, caller: Entity) {
+        self.usage.retain(|(entity, _), _| *entity != caller);
+    }
+
     pub fn all(&self) -> impl Iterator<Item = &str> {
         self.commands.keys().map(String::as_str)
     }
@@ -57,7 +134,7 @@ impl CommandRegistryInner {
 #[derive(Resource)]
 #[cfg_attr(feature = "reflect", derive(Reflect), reflect(Resource))]
 pub struct CommandRegistry(
-    #[cfg_attr(feature = "reflect", reflect(ignore))] Mutex<CommandRegistryInner>,
+    #[cfg_attr(feature = "reflect", reflect(ignore))] pub(crate) Mutex<CommandRegistryInner>,
 );
 
 impl std::ops::Deref for CommandRegistry {
@@ -78,8 +155,7 @@ pub struct CommandComponentPlugin;
 
 impl Plugin for CommandComponentPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(CommandRegistry(Mutex::new(CommandRegistryInner {
-            commands: IndexMap::default(),
-        })));
+        app.insert_resource(CommandRegistry(Mutex::new(CommandRegistryInner::default())));
+        app.init_resource::<CommandDispatcher>();
     }
 }
@@ -0,0 +1,54 @@
+use std::io::BufRead;
+
+use bevy_app::{App, Plugin};
+use bevy_ecs::{message::Message, world::World};
+use hyperion::command_channel::CommandChannel;
+use tracing::{error, info};
+
+/// A command typed into the server console, without the leading `/`
+#[derive(Message, Clone, Debug, PartialEq, Eq)]
+pub struct ConsoleCommand(pub String);
+
+/// Reads commands from stdin and executes them as [`crate::CommandCaller::Console`].
+///
+/// This must be added after [`hyperion::HyperionCore`].
+pub struct ConsolePlugin;
+
+impl Plugin for ConsolePlugin {
+    fn build(&self, app: &mut App) {
+        let command_channel = app.world().resource::<CommandChannel>().clone();
+
+        // Reading stdin blocks until a line is entered, so this uses a dedicated thread rather
+        // than a task on the AsyncRuntime, which would otherwise wait for it on shutdown
+        let result = std::thread::Builder::new()
+            .name("console".to_owned())
+            .spawn(move || {
+                for line in std::io::stdin().lock().lines() {
+                    let line = match line {
+                        Ok(line) => line,
+                        Err(e) => {
+                            error!("failed to read console input: {e}");
+                            return;
+                        }
+                    };
+
+                    let line = line.trim();
+                    let line = line.strip_prefix('/').unwrap_or(line);
+                    if line.is_empty() {
+                        continue;
+                    }
+
+                    let command = ConsoleCommand(line.to_owned());
+                    command_channel.push(move |world: &mut World| {
+                        world.write_message(command);
+                    });
+                }
+
+                info!("console input closed");
+            });
+
+        if let Err(e) = result {
+            error!("failed to start console thread: {e}");
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use std::sync::Mutex;
+
+use bevy_ecs::resource::Resource;
+
+use crate::CommandCaller;
+
+/// The maximum nesting of dispatched commands, such as `/sudo a sudo b ...`. Commands sent by
+/// players and the console have a depth of 0.
+pub const MAX_DISPATCH_DEPTH: u8 = 4;
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum DispatchError {
+    #[error("commands cannot be nested more than {MAX_DISPATCH_DEPTH} levels deep")]
+    TooDeep,
+}
+
+/// A command queued through [`CommandDispatcher::dispatch`]
+#[derive(Debug)]
+pub(crate) struct Dispatch {
+    pub(crate) caller: CommandCaller,
+    pub(crate) permissions: CommandCaller,
+    pub(crate) command: String,
+    pub(crate) depth: u8,
+}
+
+#[derive(Default, Debug)]
+struct DispatcherInner {
+    pending: Vec<Dispatch>,
+    /// The depth of the command that is currently being executed
+    depth: u8,
+}
+
+/// Queues commands to be executed on behalf of a caller.
+///
+/// Command handlers only have access to a `&World`, so commands dispatched from a handler are
+/// queued and executed after the current batch of commands, within the same tick.
+#[derive(Resource, Default, Debug)]
+pub struct CommandDispatcher(Mutex<DispatcherInner>);
+
+impl CommandDispatcher {
+    /// Queues `command` to be executed as `caller`. Permission checks are evaluated against
+    /// `permissions`, which is usually the same as `caller`.
+    ///
+    /// `command` may start with a `/`.
+    pub fn dispatch(
+        &self,
+        caller: CommandCaller,
+        permissions: CommandCaller,
+        command: impl Into<String>,
+    ) -> Result<(), DispatchError> {
+        let mut inner = self.0.lock().unwrap();
+
+        let depth = inner.depth + 1;
+        if depth > MAX_DISPATCH_DEPTH {
+            return Err(DispatchError::TooDeep);
+        }
+
+        let mut command: String = command.into();
+        if command.starts_with('/') {
+            command.remove(0);
+        }
+
+        inner.pending.push(Dispatch {
+            caller,
+            permissions,
+            command,
+            depth,
+        });
+
+        Ok(())
+    }
+
+    /// Takes all queued commands
+    pub(crate) fn take(&self) -> Vec<Dispatch> {
+        std::mem::take(&mut self.0.lock().unwrap().pending)
+    }
+
+    /// Sets the depth of the command that is about to be executed
+    pub(crate) fn set_depth(&self, depth: u8) {
+        self.0.lock().unwrap().depth = depth;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn depth_is_limited() {
+        let dispatcher = CommandDispatcher::default();
+
+        for depth in 0..MAX_DISPATCH_DEPTH {
+            dispatcher.set_depth(depth);
+            dispatcher
+                .dispatch(CommandCaller::Console, CommandCaller::Console, "/sudo a b")
+                .unwrap();
+        }
+
+        dispatcher.set_depth(MAX_DISPATCH_DEPTH);
+        assert_eq!(
+            dispatcher.dispatch(CommandCaller::Console, CommandCaller::Console, "b"),
+            Err(DispatchError::TooDeep)
+        );
+
+        let dispatched = dispatcher.take();
+        assert_eq!(dispatched.len(), usize::from(MAX_DISPATCH_DEPTH));
+        assert_eq!(dispatched[0].command, "sudo a b");
+        assert_eq!(dispatched[0].depth, 1);
+        assert!(dispatcher.take().is_empty());
+    }
+}
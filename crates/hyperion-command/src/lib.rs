@@ -1,9 +1,17 @@
 use bevy_app::{App, Plugin};
 
+mod caller;
 mod component;
+mod console;
+mod dispatch;
+mod limit;
 mod system;
 
+pub use caller::{CommandCaller, strip_formatting};
 pub use component::{CommandHandler, CommandRegistry, ExecutableCommand};
+pub use console::{ConsoleCommand, ConsolePlugin};
+pub use dispatch::{CommandDispatcher, DispatchError, MAX_DISPATCH_DEPTH};
+pub use limit::CommandLimits;
 
 pub struct CommandPlugin;
 
@@ -0,0 +1,117 @@
+use std::collections::VecDeque;
+
+use bevy_ecs::{entity::Entity, world::World};
+
+/// Minecraft runs at 20 ticks per second
+const TICKS_PER_MINUTE: u64 = 20 * 60;
+
+/// Restricts how often a single player may execute a command.
+///
+/// Limits are tracked per player and per command, and they are kept until the player disconnects.
+pub struct CommandLimits {
+    /// The number of ticks that must pass between two executions. 0 disables the cooldown.
+    pub cooldown_ticks: u32,
+    /// The maximum number of executions within any 60 second window. 0 disables this limit.
+    pub max_per_minute: u32,
+    /// Returns whether the caller ignores these limits, such as admins
+    pub bypass: fn(&World, Entity) -> bool,
+    /// The chat message sent to a player whose execution was rejected
+    pub message: String,
+}
+
+impl Default for CommandLimits {
+    fn default() -> Self {
+        Self {
+            cooldown_ticks: 0,
+            max_per_minute: 0,
+            bypass: |_, _| false,
+            message: "§cYou are using this command too quickly!".to_owned(),
+        }
+    }
+}
+
+/// The recent executions of a command by one player
+#[derive(Default, Debug)]
+pub(crate) struct CommandUsage {
+    /// Ticks of the executions within the last minute, oldest first
+    recent: VecDeque<u64>,
+}
+
+impl CommandUsage {
+    /// Records an execution at `tick` if it is allowed by `limits`. Returns whether the execution
+    /// is allowed.
+    pub(crate) fn try_use(&mut self, limits: &CommandLimits, tick: u64) -> bool {
+        while self
+            .recent
+            .front()
+            .is_some_and(|&used| used + TICKS_PER_MINUTE <= tick)
+        {
+            self.recent.pop_front();
+        }
+
+        if let Some(&last) = self.recent.back()
+            && tick < last + u64::from(limits.cooldown_ticks)
+        {
+            return false;
+        }
+
+        if limits.max_per_minute != 0
+            && self.recent.len() >= usize::try_from(limits.max_per_minute).unwrap_or(usize::MAX)
+        {
+            return false;
+        }
+
+        self.recent.push_back(tick);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allowed_exactly_when_cooldown_expires() {
+        let limits = CommandLimits {
+            cooldown_ticks: 20,
+            ..CommandLimits::default()
+        };
+        let mut usage = CommandUsage::default();
+
+        assert!(usage.try_use(&limits, 100));
+        assert!(!usage.try_use(&limits, 101));
+        assert!(!usage.try_use(&limits, 119));
+        assert!(usage.try_use(&limits, 120));
+        assert!(!usage.try_use(&limits, 139));
+    }
+
+    #[test]
+    fn rejected_executions_do_not_extend_cooldown() {
+        let limits = CommandLimits {
+            cooldown_ticks: 10,
+            ..CommandLimits::default()
+        };
+        let mut usage = CommandUsage::default();
+
+        assert!(usage.try_use(&limits, 0));
+        assert!(!usage.try_use(&limits, 9));
+        assert!(usage.try_use(&limits, 10));
+    }
+
+    #[test]
+    fn max_per_minute_uses_sliding_window() {
+        let limits = CommandLimits {
+            max_per_minute: 2,
+            ..CommandLimits::default()
+        };
+        let mut usage = CommandUsage::default();
+
+        assert!(usage.try_use(&limits, 0));
+        assert!(usage.try_use(&limits, 1));
+        assert!(!usage.try_use(&limits, 2));
+        assert!(!usage.try_use(&limits, TICKS_PER_MINUTE - 1));
+        assert!(usage.try_use(&limits, TICKS_PER_MINUTE));
+        assert!(!usage.try_use(&limits, TICKS_PER_MINUTE));
+        assert!(usage.try_use(&limits, TICKS_PER_MINUTE + 1));
+    }
+}
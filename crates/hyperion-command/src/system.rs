@@ -1,16 +1,23 @@
 use std::{fmt::Write, sync::TryLockError};
 
 use bevy_app::{App, FixedUpdate, Plugin};
-use bevy_ecs::{message::MessageReader, schedule::IntoScheduleConfigs, system::Res, world::World};
+use bevy_ecs::{
+    lifecycle::Remove,
+    message::MessageReader,
+    observer::On,
+    schedule::IntoScheduleConfigs,
+    system::{Res, ResMut},
+    world::World,
+};
 use hyperion::{
     ingress,
-    net::{Compose, agnostic},
-    simulation::packet::play,
+    net::{Compose, SendResultExt, agnostic},
+    simulation::{packet::play, packet_state},
 };
 use itertools::Itertools;
-use tracing::{debug, warn};
+use tracing::{debug, info, warn};
 
-use crate::component::CommandRegistry;
+use crate::{CommandCaller, CommandDispatcher, ConsoleCommand, component::CommandRegistry};
 
 /// Executes commands sent by the client.
 ///
@@ -44,12 +51,27 @@ fn execute_commands(
         }
     };
 
+    registry.tick += 1;
+
     for packet in packets.read() {
         let Some(first_word) = packet.command.split_whitespace().next() else {
             warn!("command is empty");
             continue;
         };
 
+        let caller = CommandCaller::Player(packet.sender());
+
+        if let Some(message) = registry.check_limits(world, caller, first_word) {
+            debug!("rejecting command {first_word}: rate limited");
+
+            let chat = agnostic::chat(message.to_owned());
+            compose
+                .unicast(&chat, packet.connection_id())
+                .unwrap_or_disconnected();
+
+            continue;
+        }
+
         let Some(command) = registry.commands.get_mut(first_word) else {
             debug!("command {first_word} not found");
 
@@ -64,15 +86,98 @@ fn execute_commands(
 
             let chat = agnostic::chat(msg);
 
-            compose.unicast(&chat, packet.connection_id()).unwrap();
+            compose
+                .unicast(&chat, packet.connection_id())
+                .unwrap_or_disconnected();
 
             continue;
         };
 
         debug!("executing command {first_word}");
 
-        command.executable.execute(world, packet);
+        command.executable.execute(world, caller, &packet.command);
+    }
+}
+
+/// Executes commands entered in the server console
+#[expect(
+    clippy::significant_drop_tightening,
+    reason = "the mutex should not be contended and the lock guard lifetime cannot be tightened"
+)]
+fn execute_console_commands(
+    mut console_commands: MessageReader<'_, '_, ConsoleCommand>,
+    registry: Res<'_, CommandRegistry>,
+    world: &World,
+) {
+    if console_commands.is_empty() {
+        return;
     }
+
+    let mut registry = registry.lock().unwrap();
+
+    for ConsoleCommand(command) in console_commands.read() {
+        let Some(first_word) = command.split_whitespace().next() else {
+            continue;
+        };
+
+        let Some(handler) = registry.commands.get_mut(first_word) else {
+            let available = registry.all().join(", ");
+            info!("unknown command {first_word}. Available commands: [{available}]");
+            continue;
+        };
+
+        info!("console executing command {command}");
+
+        handler
+            .executable
+            .execute(world, CommandCaller::Console, command);
+    }
+}
+
+/// Executes commands queued through the [`CommandDispatcher`], including commands dispatched by
+/// those commands
+#[expect(
+    clippy::significant_drop_tightening,
+    reason = "the mutex should not be contended and the lock guard lifetime cannot be tightened"
+)]
+fn execute_dispatched_commands(
+    registry: Res<'_, CommandRegistry>,
+    dispatcher: Res<'_, CommandDispatcher>,
+    world: &World,
+) {
+    let mut registry = registry.lock().unwrap();
+
+    loop {
+        let dispatched = dispatcher.take();
+        if dispatched.is_empty() {
+            break;
+        }
+
+        for dispatch in dispatched {
+            let Some(first_word) = dispatch.command.split_whitespace().next() else {
+                continue;
+            };
+
+            let Some(handler) = registry.commands.get_mut(first_word) else {
+                dispatch
+                    .caller
+                    .reply(world, format!("§cUnknown command {first_word}"));
+                continue;
+            };
+
+            debug!("executing dispatched command {}", dispatch.command);
+
+            dispatcher.set_depth(dispatch.depth);
+            handler.executable.execute_with_permissions(
+                world,
+                dispatch.caller,
+                dispatch.permissions,
+                &dispatch.command,
+            );
+        }
+    }
+
+    dispatcher.set_depth(0);
 }
 
 fn apply_deferred_changes(world: &mut World) {
@@ -140,17 +245,167 @@ fn complete_commands(
     }
 }
 
+/// Removes the command usage of players who disconnected
+fn forget_disconnected_players(
+    disconnected: On<'_, '_, Remove, packet_state::Play>,
+    mut registry: ResMut<'_, CommandRegistry>,
+) {
+    registry.get_mut().unwrap().forget(disconnected.entity);
+}
+
 pub struct CommandSystemPlugin;
 
 impl Plugin for CommandSystemPlugin {
     fn build(&self, app: &mut App) {
         // The ordering constraint between execute_command and complete_commands isn't necessary,
         // but they avoid lock contention on the CommandRegistry.
+        app.add_message::<ConsoleCommand>();
+        app.add_observer(forget_disconnected_players);
         app.add_systems(
             FixedUpdate,
-            (execute_commands, apply_deferred_changes, complete_commands)
+            (
+                execute_commands,
+                execute_console_commands,
+                execute_dispatched_commands,
+                apply_deferred_changes,
+                complete_commands,
+            )
                 .chain()
                 .after(ingress::decode::play),
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fmt::Debug,
+        sync::{Arc, Mutex},
+    };
+
+    use bevy_ecs::{message::Messages, system::RunSystemOnce};
+    use hyperion_utils::ApplyWorld;
+    use tracing::{
+        Event, Id, Metadata, Subscriber,
+        field::{Field, Visit},
+        span::{Attributes, Record},
+    };
+
+    use super::*;
+    use crate::{CommandHandler, ExecutableCommand, component::CommandRegistryInner};
+
+    type Calls = Arc<Mutex<Vec<(CommandCaller, String)>>>;
+
+    struct Echo(Calls);
+
+    impl ApplyWorld for Echo {
+        fn apply(&mut self, _world: &mut World) {}
+    }
+
+    impl ExecutableCommand for Echo {
+        fn execute(&mut self, world: &World, caller: CommandCaller, command: &str) {
+            // Replies to the console are logged, so this does not need a Compose
+            caller.reply(world, format!("§aecho: {command}"));
+            self.0.lock().unwrap().push((caller, command.to_owned()));
+        }
+    }
+
+    /// Records the message of every event this crate logs while it is the default subscriber
+    #[derive(Clone, Default)]
+    struct Logs(Arc<Mutex<Vec<String>>>);
+
+    struct MessageVisitor(String);
+
+    impl Visit for MessageVisitor {
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            if field.name() == "message" {
+                self.0 = format!("{value:?}");
+            }
+        }
+    }
+
+    impl Subscriber for Logs {
+        fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+            metadata.target().starts_with(env!("CARGO_CRATE_NAME"))
+        }
+
+        fn new_span(&self, _: &Attributes<'_>) -> Id {
+            Id::from_u64(1)
+        }
+
+        fn record(&self, _: &Id, _: &Record<'_>) {}
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let mut visitor = MessageVisitor(String::new());
+            event.record(&mut visitor);
+            self.0.lock().unwrap().push(visitor.0);
+        }
+
+        fn enter(&self, _: &Id) {}
+
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[test]
+    fn console_commands_are_executed() {
+        let calls = Calls::default();
+
+        let mut registry = CommandRegistryInner::default();
+        registry.register("echo", CommandHandler {
+            executable: Box::new(Echo(Arc::clone(&calls))),
+            tab_complete: |_, _| {},
+            // Players could not run this, but the console has every permission
+            has_permissions: |_, _| false,
+        });
+
+        let mut world = World::new();
+        world.insert_resource(CommandRegistry(Mutex::new(registry)));
+        world.init_resource::<Messages<ConsoleCommand>>();
+        world.write_message(ConsoleCommand("echo hello world".to_owned()));
+        world.write_message(ConsoleCommand("missing".to_owned()));
+
+        let logs = Logs::default();
+        tracing::subscriber::with_default(logs.clone(), || {
+            world.run_system_once(execute_console_commands).unwrap();
+        });
+
+        assert_eq!(*calls.lock().unwrap(), [(
+            CommandCaller::Console,
+            "echo hello world".to_owned()
+        )]);
+
+        // The console receives the reply without its formatting codes
+        assert_eq!(*logs.0.lock().unwrap(), [
+            "console executing command echo hello world",
+            "echo: echo hello world",
+            "unknown command missing. Available commands: [echo]",
+        ]);
+    }
+
+    #[test]
+    fn dispatched_commands_are_executed_as_caller() {
+        let calls = Calls::default();
+
+        let mut registry = CommandRegistryInner::default();
+        registry.register("echo", CommandHandler {
+            executable: Box::new(Echo(Arc::clone(&calls))),
+            tab_complete: |_, _| {},
+            has_permissions: |_, _| true,
+        });
+
+        let mut world = World::new();
+        let target = CommandCaller::Player(world.spawn_empty().id());
+        world.insert_resource(CommandRegistry(Mutex::new(registry)));
+        world.init_resource::<CommandDispatcher>();
+        world
+            .resource::<CommandDispatcher>()
+            .dispatch(target, CommandCaller::Console, "/echo forced")
+            .unwrap();
+
+        world.run_system_once(execute_dispatched_commands).unwrap();
+
+        assert_eq!(*calls.lock().unwrap(), [(target, "echo forced".to_owned())]);
+    }
+}
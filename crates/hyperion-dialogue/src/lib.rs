@@ -0,0 +1,575 @@
+//! Multi-step conversations in chat, such as those of lobby NPCs.
+//!
+//! A [`Dialogue`] is a list of [`DialogueStep`]s registered in [`Dialogues`] and started for a
+//! player with [`start_dialogue`]. Each step is sent to the player after its delay. Steps with
+//! choices wait until the player clicks one of them, while steps without choices continue with
+//! the next step right away.
+//!
+//! Clicking a choice runs a hidden command with a token which is randomly generated for every
+//! step the player is sent, so players can't skip ahead by typing the command themselves.
+
+use std::collections::HashMap;
+
+use bevy_app::{App, FixedUpdate, Plugin};
+use bevy_ecs::{
+    component::Component,
+    entity::Entity,
+    lifecycle::Remove,
+    message::{Message, MessageReader, MessageWriter},
+    observer::On,
+    resource::Resource,
+    system::{Commands, Query, Res},
+    world::World,
+};
+use hyperion::{
+    Tick,
+    net::{Compose, ConnectionId, DataBundle, SendResultExt},
+    simulation::packet_state,
+};
+use hyperion_command::{
+    CommandCaller, CommandDispatcher, CommandHandler, CommandRegistry, ExecutableCommand,
+};
+use hyperion_inventory::PlayerInventory;
+use hyperion_item::builder::ItemBuilder;
+use hyperion_utils::ApplyWorld;
+use tracing::{debug, error, warn};
+use valence_protocol::packets::play;
+use valence_text::{IntoText, Text};
+#[cfg(feature = "reflect")]
+use {
+    bevy_ecs::reflect::{ReflectComponent, ReflectResource},
+    bevy_reflect::Reflect,
+};
+
+/// The command run by clicking a choice
+pub const CHOICE_COMMAND: &str = "dialogue_choice";
+
+pub struct DialoguePlugin;
+
+impl Plugin for DialoguePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Dialogues>();
+        app.add_message::<ChoiceClicked>();
+        app.add_message::<DialogueMessage>();
+        app.add_message::<DialogueEnded>();
+        app.add_observer(forget_disconnected_players);
+        app.add_systems(FixedUpdate, (handle_choices, send_due_steps));
+    }
+
+    fn finish(&self, app: &mut App) {
+        // The command is registered here because the registry is inserted by another plugin
+        let mut registry = app.world_mut().resource_mut::<CommandRegistry>();
+        registry
+            .get_mut()
+            .unwrap()
+            .register(CHOICE_COMMAND, CommandHandler {
+                executable: Box::new(ChoiceCommand::default()),
+                tab_complete: |_, _| {},
+                // Hides the command from players
+                has_permissions: |_, _| false,
+            });
+    }
+}
+
+/// A conversation made of steps which are sent in order, unless a choice jumps to another step
+#[derive(Clone, Debug, Default)]
+#[must_use]
+pub struct Dialogue {
+    steps: Vec<DialogueStep>,
+}
+
+impl Dialogue {
+    pub const fn new() -> Self {
+        Self { steps: Vec::new() }
+    }
+
+    /// Adds `step` after the existing steps. Steps are indexed in the order they are added.
+    pub fn step(mut self, step: DialogueStep) -> Self {
+        self.steps.push(step);
+        self
+    }
+
+    /// Returns the step to continue with after `step` according to `next`, or [`None`] if the
+    /// dialogue is over
+    #[must_use]
+    fn next_step(&self, step: usize, next: DialogueNext) -> Option<usize> {
+        let next = match next {
+            DialogueNext::Continue => step + 1,
+            DialogueNext::Goto(step) => step,
+            DialogueNext::End => return None,
+        };
+
+        (next < self.steps.len()).then_some(next)
+    }
+}
+
+/// Lines sent together, followed by the clickable choices of the step
+#[derive(Clone, Debug, Default)]
+#[must_use]
+pub struct DialogueStep {
+    lines: Vec<Text>,
+    choices: Vec<(Text, DialogueNext)>,
+    delay: u32,
+    actions: Vec<DialogueAction>,
+}
+
+impl DialogueStep {
+    pub const fn new() -> Self {
+        Self {
+            lines: Vec::new(),
+            choices: Vec::new(),
+            delay: 0,
+            actions: Vec::new(),
+        }
+    }
+
+    pub fn line(mut self, line: impl IntoText<'static>) -> Self {
+        self.lines.push(line.into_text());
+        self
+    }
+
+    /// Adds a line which continues the dialogue as chosen by `next` when clicked. The step waits
+    /// for a choice if it has any.
+    pub fn choice(mut self, label: impl IntoText<'static>, next: DialogueNext) -> Self {
+        self.choices.push((label.into_text(), next));
+        self
+    }
+
+    /// Sets the number of ticks to wait before sending the step
+    pub const fn delay(mut self, ticks: u32) -> Self {
+        self.delay = ticks;
+        self
+    }
+
+    /// Adds an action which is performed when the step is sent
+    pub fn action(mut self, action: DialogueAction) -> Self {
+        self.actions.push(action);
+        self
+    }
+}
+
+/// The step to continue with after a choice
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DialogueNext {
+    /// Continues with the step after the current one
+    Continue,
+    /// Jumps to the step with this index
+    Goto(usize),
+    End,
+}
+
+#[derive(Clone, Debug)]
+pub enum DialogueAction {
+    /// Runs a command, without the leading `/`, as the player and with their permissions
+    RunCommand(String),
+    /// Adds the built item to the player's inventory
+    GiveItem(ItemBuilder),
+    /// Sends a [`DialogueMessage`] with this message
+    Message(String),
+}
+
+/// The dialogues which can be started with [`start_dialogue`], by their ids
+#[derive(Resource, Default)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Resource))]
+pub struct Dialogues(#[cfg_attr(feature = "reflect", reflect(ignore))] HashMap<String, Dialogue>);
+
+impl Dialogues {
+    pub fn insert(&mut self, id: impl Into<String>, dialogue: Dialogue) {
+        self.0.insert(id.into(), dialogue);
+    }
+
+    #[must_use]
+    pub fn get(&self, id: &str) -> Option<&Dialogue> {
+        self.0.get(id)
+    }
+}
+
+/// Sent when a player reaches a step with a [`DialogueAction::Message`]
+#[derive(Message, Clone, Debug, PartialEq, Eq)]
+pub struct DialogueMessage {
+    pub player: Entity,
+    pub dialogue: String,
+    pub message: String,
+}
+
+/// Sent when a dialogue is over for a player, either because it ran out of steps, because a
+/// choice ended it, or because another dialogue was started for the player
+#[derive(Message, Clone, Debug, PartialEq, Eq)]
+pub struct DialogueEnded {
+    pub player: Entity,
+    pub dialogue: String,
+}
+
+/// The dialogue a player is in
+#[derive(Component, Debug)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+pub struct DialogueProgress {
+    dialogue: String,
+    step: usize,
+    /// The tick the current step is sent at
+    due: i64,
+    /// The tokens of the choices the player was sent for the current step. The step has not been
+    /// sent while this is empty.
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    tokens: Vec<(u64, DialogueNext)>,
+}
+
+impl DialogueProgress {
+    #[must_use]
+    pub const fn dialogue(&self) -> &str {
+        self.dialogue.as_str()
+    }
+
+    #[must_use]
+    pub const fn step(&self) -> usize {
+        self.step
+    }
+
+    /// Returns the choice with `token` if the player was sent it for the current step
+    fn choice(&self, token: u64) -> Option<DialogueNext> {
+        self.tokens
+            .iter()
+            .find(|(choice_token, _)| *choice_token == token)
+            .map(|&(_, next)| next)
+    }
+
+    /// Moves on to the step chosen by `next`. Returns `false` if the dialogue is over.
+    fn advance(&mut self, dialogue: &Dialogue, next: DialogueNext, now: i64) -> bool {
+        self.tokens.clear();
+
+        let Some(step) = dialogue.next_step(self.step, next) else {
+            return false;
+        };
+
+        self.step = step;
+        self.due = now + i64::from(dialogue.steps[step].delay);
+        true
+    }
+}
+
+/// Returns a command starting the dialogue `dialogue` for `player`. A dialogue the player is
+/// already in is replaced.
+pub fn start_dialogue(
+    player: Entity,
+    dialogue: impl Into<String>,
+) -> impl FnOnce(&mut World) + Send + 'static {
+    let dialogue = dialogue.into();
+
+    move |world: &mut World| {
+        let Some(first) = world
+            .resource::<Dialogues>()
+            .get(&dialogue)
+            .map(|dialogue| dialogue.steps.first().map_or(0, |step| step.delay))
+        else {
+            error!("failed to start dialogue {dialogue}: dialogue does not exist");
+            return;
+        };
+
+        let now = world.resource::<Tick>().0;
+
+        let Ok(mut entity) = world.get_entity_mut(player) else {
+            return;
+        };
+
+        let replaced = entity.take::<DialogueProgress>();
+        entity.insert(DialogueProgress {
+            dialogue,
+            step: 0,
+            due: now + i64::from(first),
+            tokens: Vec::new(),
+        });
+
+        if let Some(replaced) = replaced {
+            world.write_message(DialogueEnded {
+                player,
+                dialogue: replaced.dialogue,
+            });
+        }
+    }
+}
+
+/// A click on a choice, with the token that was sent with it
+#[derive(Message, Copy, Clone, Debug)]
+struct ChoiceClicked {
+    player: Entity,
+    token: u64,
+}
+
+#[derive(Default)]
+struct ChoiceCommand {
+    clicked: Vec<ChoiceClicked>,
+}
+
+impl ApplyWorld for ChoiceCommand {
+    fn apply(&mut self, world: &mut World) {
+        for clicked in self.clicked.drain(..) {
+            world.write_message(clicked);
+        }
+    }
+}
+
+impl ExecutableCommand for ChoiceCommand {
+    fn execute(&mut self, _world: &World, caller: CommandCaller, command: &str) {
+        let Some(player) = caller.entity() else {
+            return;
+        };
+
+        let token = command
+            .strip_prefix(CHOICE_COMMAND)
+            .and_then(|token| u64::from_str_radix(token.trim(), 16).ok());
+
+        let Some(token) = token else {
+            debug!("ignoring malformed dialogue choice {command}");
+            return;
+        };
+
+        self.clicked.push(ChoiceClicked { player, token });
+    }
+}
+
+fn handle_choices(
+    mut clicked: MessageReader<'_, '_, ChoiceClicked>,
+    mut query: Query<'_, '_, &mut DialogueProgress>,
+    dialogues: Res<'_, Dialogues>,
+    tick: Res<'_, Tick>,
+    mut ended: MessageWriter<'_, DialogueEnded>,
+    mut commands: Commands<'_, '_>,
+) {
+    for clicked in clicked.read() {
+        let Ok(mut progress) = query.get_mut(clicked.player) else {
+            continue;
+        };
+
+        // Outdated choices and guessed tokens are ignored
+        let Some(next) = progress.choice(clicked.token) else {
+            continue;
+        };
+
+        let Some(dialogue) = dialogues.get(&progress.dialogue) else {
+            continue;
+        };
+
+        if !progress.advance(dialogue, next, tick.0) {
+            end_dialogue(&mut commands, &mut ended, clicked.player, &progress);
+        }
+    }
+}
+
+fn send_due_steps(
+    mut query: Query<
+        '_,
+        '_,
+        (
+            Entity,
+            &ConnectionId,
+            &mut DialogueProgress,
+            Option<&mut PlayerInventory>,
+        ),
+    >,
+    dialogues: Res<'_, Dialogues>,
+    tick: Res<'_, Tick>,
+    compose: Res<'_, Compose>,
+    dispatcher: Res<'_, CommandDispatcher>,
+    mut messages: MessageWriter<'_, DialogueMessage>,
+    mut ended: MessageWriter<'_, DialogueEnded>,
+    mut commands: Commands<'_, '_>,
+) {
+    for (player, &connection_id, mut progress, mut inventory) in &mut query {
+        if !progress.tokens.is_empty() || progress.due > tick.0 {
+            continue;
+        }
+
+        let Some(dialogue) = dialogues.get(&progress.dialogue) else {
+            warn!(
+                "ending dialogue {} for {player}: dialogue was removed",
+                progress.dialogue
+            );
+            end_dialogue(&mut commands, &mut ended, player, &progress);
+            continue;
+        };
+
+        // Dialogues without steps end right away
+        let Some(step) = dialogue.steps.get(progress.step) else {
+            end_dialogue(&mut commands, &mut ended, player, &progress);
+            continue;
+        };
+
+        let mut bundle = DataBundle::new(&compose);
+
+        for line in &step.lines {
+            let packet = play::GameMessageS2c {
+                chat: line.clone().into(),
+                overlay: false,
+            };
+            bundle.add_packet(&packet).unwrap();
+        }
+
+        for (label, next) in &step.choices {
+            let token = rand::random::<u64>();
+            progress.tokens.push((token, *next));
+
+            let label = label
+                .clone()
+                .on_click_run_command(format!("/{CHOICE_COMMAND} {token:016x}"))
+                .on_hover_show_text("Click to choose");
+            let packet = play::GameMessageS2c {
+                chat: label.into(),
+                overlay: false,
+            };
+            bundle.add_packet(&packet).unwrap();
+        }
+
+        bundle.unicast(connection_id).unwrap_or_disconnected();
+
+        for action in &step.actions {
+            match action {
+                DialogueAction::RunCommand(command) => {
+                    let caller = CommandCaller::Player(player);
+                    if let Err(e) = dispatcher.dispatch(caller, caller, command.clone()) {
+                        error!("failed to run dialogue command {command}: {e}");
+                    }
+                }
+                DialogueAction::GiveItem(item) => {
+                    let item = item.clone().build();
+                    let Some(inventory) = inventory.as_mut() else {
+                        error!(
+                            "failed to give {:?} to {player} in dialogue {}: player is missing an \
+                             inventory",
+                            item.item, progress.dialogue
+                        );
+                        continue;
+                    };
+
+                    if let Some(remaining) = inventory.try_add_item(item).remaining {
+                        debug!(
+                            "inventory of {player} is full, {} of {:?} from dialogue {} were not \
+                             given",
+                            remaining.count, remaining.item, progress.dialogue
+                        );
+                    }
+                }
+                DialogueAction::Message(message) => {
+                    messages.write(DialogueMessage {
+                        player,
+                        dialogue: progress.dialogue.clone(),
+                        message: message.clone(),
+                    });
+                }
+            }
+        }
+
+        if step.choices.is_empty() && !progress.advance(dialogue, DialogueNext::Continue, tick.0) {
+            end_dialogue(&mut commands, &mut ended, player, &progress);
+        }
+    }
+}
+
+fn end_dialogue(
+    commands: &mut Commands<'_, '_>,
+    ended: &mut MessageWriter<'_, DialogueEnded>,
+    player: Entity,
+    progress: &DialogueProgress,
+) {
+    commands.entity(player).remove::<DialogueProgress>();
+    ended.write(DialogueEnded {
+        player,
+        dialogue: progress.dialogue.clone(),
+    });
+}
+
+fn forget_disconnected_players(
+    disconnected: On<'_, '_, Remove, packet_state::Play>,
+    mut commands: Commands<'_, '_>,
+) {
+    commands
+        .entity(disconnected.entity)
+        .try_remove::<DialogueProgress>();
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_ecs::message::Messages;
+
+    use super::*;
+
+    fn branching() -> Dialogue {
+        Dialogue::new()
+            .step(
+                DialogueStep::new()
+                    .line("Do you want to play?")
+                    .choice("Yes", DialogueNext::Goto(2))
+                    .choice("No", DialogueNext::Continue),
+            )
+            .step(DialogueStep::new().line("Maybe later then"))
+            .step(DialogueStep::new().line("Have fun!").delay(40))
+    }
+
+    fn progress() -> DialogueProgress {
+        DialogueProgress {
+            dialogue: "branching".to_owned(),
+            step: 0,
+            due: 0,
+            tokens: vec![(1, DialogueNext::Goto(2)), (2, DialogueNext::Continue)],
+        }
+    }
+
+    #[test]
+    fn choices_branch_to_their_step() {
+        let dialogue = branching();
+        let mut progress = progress();
+
+        let next = progress.choice(1).unwrap();
+        assert!(progress.advance(&dialogue, next, 100));
+        assert_eq!(progress.step(), 2);
+        assert_eq!(progress.due, 140);
+
+        // The tokens of a step can't be used once the player moved on
+        assert_eq!(progress.choice(1), None);
+
+        assert!(!progress.advance(&dialogue, DialogueNext::Continue, 140));
+    }
+
+    #[test]
+    fn unknown_tokens_are_rejected() {
+        let progress = progress();
+
+        assert_eq!(progress.choice(3), None);
+        assert_eq!(progress.choice(2), Some(DialogueNext::Continue));
+    }
+
+    #[test]
+    fn starting_a_dialogue_ends_the_previous_one() {
+        let mut app = App::new();
+        app.add_message::<DialogueEnded>();
+        app.init_resource::<Tick>();
+        let mut dialogues = Dialogues::default();
+        dialogues.insert("branching", branching());
+        dialogues.insert("empty", Dialogue::new());
+        app.insert_resource(dialogues);
+        let world = app.world_mut();
+        let player = world.spawn_empty().id();
+
+        start_dialogue(player, "branching")(world);
+        assert!(world.resource::<Messages<DialogueEnded>>().is_empty());
+
+        start_dialogue(player, "empty")(world);
+        let progress = world.get::<DialogueProgress>(player).unwrap();
+        assert_eq!(progress.dialogue(), "empty");
+
+        let ended = world.resource::<Messages<DialogueEnded>>();
+        let ended: Vec<_> = ended.iter_current_update_messages().cloned().collect();
+        assert_eq!(ended, [DialogueEnded {
+            player,
+            dialogue: "branching".to_owned()
+        }]);
+    }
+
+    #[test]
+    fn jumping_past_the_last_step_ends_the_dialogue() {
+        let dialogue = branching();
+
+        assert_eq!(dialogue.next_step(0, DialogueNext::Goto(3)), None);
+        assert_eq!(dialogue.next_step(0, DialogueNext::End), None);
+        assert_eq!(dialogue.next_step(0, DialogueNext::Continue), Some(1));
+    }
+}
@@ -1,22 +1,275 @@
-use bevy_app::{App, Plugin};
-use hyperion::{runtime::AsyncRuntime, simulation::blocks::Blocks};
+//! Loads the map used by the events into the primary world.
+//!
+//! The map is downloaded and extracted in the background while the server already runs with an
+//! empty world, see [`hyperion::simulation::loading`]. A map which fails to load is retried with
+//! a growing delay, and the world stays empty until then.
 
+use std::{
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use bevy_app::{App, Plugin, Update};
+use bevy_ecs::{
+    resource::Resource,
+    schedule::{IntoScheduleConfigs, common_conditions::not},
+    system::ResMut,
+    world::World,
+};
+use hyperion::{
+    command_channel::CommandChannel,
+    runtime::AsyncRuntime,
+    simulation::{
+        blocks::{Blocks, CorruptChunkFallback, generator::VoidGenerator},
+        loading::{self, MapLoadProgress, MapLoadStage, MapSource, world_ready},
+    },
+};
+use hyperion_utils::{AppId, CachedSave, SaveProgress, SaveRequest, SaveSource, SaveStage};
+use tokio::sync::oneshot;
+use tracing::{error, info};
+
+/// How long the first retry of a map which failed to load waits
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// The longest delay between retries, which doubles after each failure until then
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+/// Where the map is downloaded from. This is taken from the [`MapSource`] of the configuration,
+/// but may also be inserted before [`GenMapPlugin`].
+#[derive(Resource, Clone, Debug, PartialEq, Eq)]
+pub struct GenMapConfig {
+    /// Where the Anvil save is fetched from, see [`SaveRequest::new`]
+    pub url: String,
+    /// The SHA-256 checksum of the archive in hex, which is required for HTTP(S) URLs
+    pub sha256: Option<String>,
+}
+
+impl Default for GenMapConfig {
+    fn default() -> Self {
+        Self {
+            url: "https://github.com/andrewgazelka/maps/raw/main/GenMap.tar.gz".to_owned(),
+            sha256: None,
+        }
+    }
+}
+
+impl From<MapSource> for GenMapConfig {
+    fn from(source: MapSource) -> Self {
+        Self {
+            url: source.url.unwrap_or_else(|| Self::default().url),
+            sha256: source.sha256,
+        }
+    }
+}
+
+/// The save the primary world is loaded from. The blocks are read from it lazily, so it is kept
+/// to prevent it from being evicted from the cache.
+#[derive(Resource)]
+struct MapSave {
+    _save: CachedSave,
+}
+
+/// The download which [`MapLoadProgress`] is updated from
+#[derive(Resource)]
+struct MapDownload {
+    progress: Arc<SaveProgress>,
+    started: Instant,
+    /// The stage and the tenth of the download which were logged last
+    logged: (MapLoadStage, u64),
+}
+
+#[expect(
+    clippy::cast_precision_loss,
+    reason = "the estimate does not need to be exact"
+)]
+fn estimate_remaining(elapsed: Duration, downloaded: u64, total: u64) -> Option<Duration> {
+    if downloaded == 0 || downloaded > total {
+        return None;
+    }
+
+    let remaining = (total - downloaded) as f64 / downloaded as f64;
+    Some(elapsed.mul_f64(remaining))
+}
+
+/// The completed tenths of a percentage, such as 3 for 37%
+#[expect(
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss,
+    reason = "the percentage is between 0 and 100"
+)]
+fn tenth(percent: f64) -> u64 {
+    (percent / 10.0) as u64
+}
+
+fn update_progress(mut download: ResMut<'_, MapDownload>, mut map: ResMut<'_, MapLoadProgress>) {
+    let progress = &download.progress;
+
+    // The map becomes ready once its blocks are swapped in, not when the save is extracted
+    let stage = match progress.stage() {
+        SaveStage::Downloading => MapLoadStage::Downloading,
+        SaveStage::Verifying => MapLoadStage::Verifying,
+        SaveStage::Extracting | SaveStage::Done => MapLoadStage::Extracting,
+    };
+    let downloaded_bytes = progress.downloaded_bytes();
+    let total_bytes = progress.total_bytes();
+    let eta = total_bytes
+        .filter(|_| stage == MapLoadStage::Downloading)
+        .and_then(|total| estimate_remaining(download.started.elapsed(), downloaded_bytes, total));
+
+    map.set_if_neq(MapLoadProgress {
+        stage,
+        downloaded_bytes,
+        total_bytes,
+        eta,
+    });
+
+    let tenth = map.percent().map_or(0, tenth);
+    if download.logged == (stage, tenth) {
+        return;
+    }
+    download.logged = (stage, tenth);
+
+    match stage {
+        MapLoadStage::Downloading => {
+            let mib = |bytes: u64| bytes / (1024 * 1024);
+            match (total_bytes, eta) {
+                (Some(total), Some(eta)) => info!(
+                    "downloading map: {}% ({} of {} MiB), about {}s left",
+                    tenth * 10,
+                    mib(downloaded_bytes),
+                    mib(total),
+                    eta.as_secs()
+                ),
+                _ => info!("downloading map: {} MiB", mib(downloaded_bytes)),
+            }
+        }
+        MapLoadStage::Verifying => info!("verifying map"),
+        MapLoadStage::Extracting => info!("extracting map"),
+        MapLoadStage::Ready => {}
+    }
+}
+
+/// Fetches the save and replaces the primary world with it
+async fn load(
+    request: SaveRequest,
+    cache: PathBuf,
+    command_channel: &CommandChannel,
+) -> anyhow::Result<()> {
+    let save = request.fetch_into(cache).await?;
+
+    if save.freshly_downloaded {
+        info!("downloaded map from {}", save.source);
+    } else {
+        info!("using cached map from {}", save.source);
+    }
+
+    let (loaded, result) = oneshot::channel();
+    command_channel
+        .send_priority(move |world: &mut World| {
+            let fallback = world
+                .get_resource::<CorruptChunkFallback>()
+                .copied()
+                .unwrap_or_default();
+            let runtime = world.resource::<AsyncRuntime>();
+            let blocks = Blocks::with_fallback(runtime, &save.path, VoidGenerator, fallback);
+            let blocks = blocks.map(|blocks| {
+                world.insert_resource(MapSave { _save: save });
+                loading::finish_loading(world, blocks);
+            });
+
+            // The task only stops waiting for this if the server shuts down
+            drop(loaded.send(blocks));
+        })
+        .await;
+
+    result
+        .await
+        .context("the server shut down before the map was loaded")?
+        .context("failed to load the save")
+}
+
+/// Loads the map of the [`GenMapConfig`] in the background
 pub struct GenMapPlugin;
 
 impl Plugin for GenMapPlugin {
     fn build(&self, app: &mut App) {
-        const URL: &str = "https://github.com/andrewgazelka/maps/raw/main/GenMap.tar.gz";
+        // A `GenMapConfig` for another map or with a checksum may be inserted before this plugin
+        let config = app
+            .world_mut()
+            .remove_resource::<GenMapConfig>()
+            .unwrap_or_else(|| {
+                let source = app.world().get_resource::<MapSource>().cloned();
+                GenMapConfig::from(source.unwrap_or_default())
+            });
+
+        let progress = Arc::<SaveProgress>::default();
+        app.insert_resource(MapLoadProgress::default());
+        app.insert_resource(MapDownload {
+            progress: progress.clone(),
+            started: Instant::now(),
+            logged: (MapLoadStage::Ready, 0),
+        });
+        app.add_systems(Update, update_progress.run_if(not(world_ready)));
+
+        let remote = matches!(SaveSource::parse(&config.url), Ok(SaveSource::Remote(_)));
+        if remote && config.sha256.is_none() {
+            // Retrying would not help, so the world stays empty
+            error!(
+                "the map {} is not loaded because its checksum is not configured, set \
+                 `map.sha256` in the configuration",
+                config.url
+            );
+            app.insert_resource(config);
+            return;
+        }
+
+        let mut request = SaveRequest::new(config.url.clone()).progress(progress);
+        if let Some(sha256) = &config.sha256 {
+            request = request.sha256(sha256.clone());
+        }
 
+        let cache = app.world().resource::<AppId>().cache_dir();
         let runtime = app
             .world()
             .get_resource::<AsyncRuntime>()
             .expect("AsyncRuntime resource must exist");
-        let f = hyperion_utils::cached_save(app.world(), URL);
+        let command_channel = app.world().resource::<CommandChannel>().clone();
+        let url = config.url.clone();
 
-        let save = runtime.block_on(f).unwrap_or_else(|e| {
-            panic!("failed to download map {URL}: {e}");
+        runtime.spawn(async move {
+            let mut delay = RETRY_DELAY;
+            while let Err(e) = load(request.clone(), cache.clone(), &command_channel).await {
+                error!(
+                    "failed to load map {url}, retrying in {}s: {e:?}",
+                    delay.as_secs()
+                );
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(MAX_RETRY_DELAY);
+            }
         });
 
-        app.insert_resource(Blocks::new(runtime, &save).unwrap());
+        app.insert_resource(config);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimates_the_remaining_time_from_the_rate_so_far() {
+        let elapsed = Duration::from_secs(10);
+
+        assert_eq!(
+            estimate_remaining(elapsed, 250, 1000),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            estimate_remaining(elapsed, 1000, 1000),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(estimate_remaining(elapsed, 0, 1000), None);
     }
 }
@@ -10,6 +10,10 @@ use valence_protocol::packets::play::{
 #[cfg(feature = "reflect")]
 use {bevy_ecs::reflect::ReflectComponent, bevy_reflect::Reflect};
 
+pub mod text_prompt;
+
+pub use text_prompt::{TextPrompt, TextPromptCancelled, TextPromptPlugin, TextPromptSubmitted};
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct InventoryItem {
     pub id: String,
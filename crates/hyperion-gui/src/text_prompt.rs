@@ -0,0 +1,271 @@
+//! Text input through the rename field of an anvil, for asking players to type something such as
+//! the name of an island.
+//!
+//! [`TextPrompt::open`] opens an anvil for the player. The typed text is available through the
+//! [`TextPrompt`] component while the anvil is open and is delivered as a [`TextPromptSubmitted`]
+//! once the player clicks the result slot. Closing the anvil in any other way, including opening
+//! another prompt or inventory, sends a [`TextPromptCancelled`] instead.
+
+use bevy_app::{App, FixedUpdate, Plugin};
+use bevy_ecs::{
+    component::Component,
+    entity::Entity,
+    lifecycle::Replace,
+    message::{Message, MessageReader, MessageWriter},
+    observer::On,
+    schedule::IntoScheduleConfigs,
+    system::{Commands, Query},
+    world::World,
+};
+use hyperion::{ingress, simulation::packet};
+use hyperion_inventory::{Inventory, InventoryState, OpenInventory};
+use valence_protocol::{
+    ItemKind, ItemStack,
+    nbt::{Compound, Value},
+    packets::play::open_screen_s2c::WindowType,
+};
+use valence_text::Text;
+#[cfg(feature = "reflect")]
+use {bevy_ecs::reflect::ReflectComponent, bevy_reflect::Reflect};
+
+/// The longest text the rename field of an anvil accepts
+pub const MAX_TEXT_LENGTH: usize = 50;
+
+const INPUT_SLOT: u16 = 0;
+const RESULT_SLOT: u16 = 2;
+
+pub struct TextPromptPlugin;
+
+impl Plugin for TextPromptPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_message::<TextPromptSubmitted>();
+        app.add_message::<TextPromptCancelled>();
+        app.add_observer(cancel_on_close);
+        app.add_systems(
+            FixedUpdate,
+            (update_text, submit_text)
+                .chain()
+                .after(ingress::decode::play),
+        );
+    }
+}
+
+/// A prompt opened for a player with [`TextPrompt::open`]. It is removed once the prompt is
+/// submitted or cancelled.
+#[derive(Component, Debug)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+pub struct TextPrompt {
+    inventory: Entity,
+    text: String,
+}
+
+impl TextPrompt {
+    /// Returns a command opening an anvil titled `title` for `player`, with `initial` in the
+    /// rename field. A prompt the player already has open is cancelled.
+    pub fn open(
+        player: Entity,
+        title: Text,
+        initial: String,
+    ) -> impl FnOnce(&mut World) + Send + 'static {
+        move |world: &mut World| {
+            let Ok(mut entity) = world.get_entity_mut(player) else {
+                return;
+            };
+
+            let previous = entity.take::<Self>();
+
+            let text = sanitize(&initial);
+            let mut inventory = Inventory::new(3, title.to_legacy_lossy(), WindowType::Anvil, true);
+            let mut input = ItemStack::new(ItemKind::Paper, 1, None);
+            set_name(&mut input, &text);
+            inventory
+                .set(INPUT_SLOT, input)
+                .expect("anvils have an input slot");
+
+            let inventory = world.spawn(inventory).id();
+            world
+                .entity_mut(player)
+                .insert((OpenInventory::new(inventory), Self { inventory, text }));
+
+            if let Some(previous) = previous {
+                world.despawn(previous.inventory);
+                world.write_message(TextPromptCancelled { player });
+            }
+        }
+    }
+
+    /// The text currently in the rename field, without illegal characters
+    #[must_use]
+    pub const fn text(&self) -> &str {
+        self.text.as_str()
+    }
+}
+
+/// Sent when a player submits its [`TextPrompt`] by clicking the result slot
+#[derive(Message, Clone, Debug, PartialEq, Eq)]
+pub struct TextPromptSubmitted {
+    pub player: Entity,
+    pub text: String,
+}
+
+/// Sent when a [`TextPrompt`] is closed without being submitted
+#[derive(Message, Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TextPromptCancelled {
+    pub player: Entity,
+}
+
+/// Removes the characters which can't be typed in chat, such as formatting codes, and shortens
+/// `text` to [`MAX_TEXT_LENGTH`] characters
+#[must_use]
+pub fn sanitize(text: &str) -> String {
+    text.chars()
+        .filter(|&c| c != '§' && c >= ' ' && c != '\u{7f}')
+        .take(MAX_TEXT_LENGTH)
+        .collect()
+}
+
+/// Gives `stack` the custom name `name`, as renaming it in an anvil would
+pub fn set_name(stack: &mut ItemStack, name: &str) {
+    let nbt = stack.nbt.get_or_insert_with(Compound::new);
+
+    let mut display = match nbt.remove("display") {
+        Some(Value::Compound(display)) => display,
+        _ => Compound::new(),
+    };
+
+    let name = serde_json::json!({ "text": name }).to_string();
+    display.insert("Name", Value::String(name));
+
+    nbt.insert("display", Value::Compound(display));
+}
+
+fn cancel_on_close(
+    closed: On<'_, '_, Replace, OpenInventory>,
+    query: Query<'_, '_, (&OpenInventory, &TextPrompt)>,
+    mut cancelled: MessageWriter<'_, TextPromptCancelled>,
+    mut commands: Commands<'_, '_>,
+) {
+    let Ok((open_inventory, prompt)) = query.get(closed.entity) else {
+        return;
+    };
+
+    if open_inventory.inventory != prompt.inventory {
+        return;
+    }
+
+    commands.entity(closed.entity).try_remove::<TextPrompt>();
+    commands.entity(prompt.inventory).despawn();
+    cancelled.write(TextPromptCancelled {
+        player: closed.entity,
+    });
+}
+
+fn update_text(
+    mut packets: MessageReader<'_, '_, packet::play::RenameItem>,
+    mut prompts: Query<'_, '_, &mut TextPrompt>,
+    mut inventories: Query<'_, '_, &mut Inventory>,
+) {
+    for packet in packets.read() {
+        let Ok(mut prompt) = prompts.get_mut(packet.sender()) else {
+            continue;
+        };
+
+        let Ok(mut inventory) = inventories.get_mut(prompt.inventory) else {
+            continue;
+        };
+
+        let text = sanitize(packet.item_name);
+
+        // Show the renamed item as the result like a real anvil does
+        let mut result = ItemStack::new(ItemKind::Paper, 1, None);
+        set_name(&mut result, &text);
+        inventory
+            .set(RESULT_SLOT, result)
+            .expect("anvils have a result slot");
+
+        prompt.text = text;
+    }
+}
+
+fn submit_text(
+    mut packets: MessageReader<'_, '_, packet::play::ClickSlot>,
+    prompts: Query<'_, '_, (&TextPrompt, &InventoryState)>,
+    mut submitted: MessageWriter<'_, TextPromptSubmitted>,
+    mut commands: Commands<'_, '_>,
+) {
+    let mut submitters = Vec::new();
+
+    for packet in packets.read() {
+        let player = packet.sender();
+
+        let Ok((prompt, inv_state)) = prompts.get(player) else {
+            continue;
+        };
+
+        if packet.window_id != inv_state.window_id()
+            || packet.slot_idx != RESULT_SLOT.cast_signed()
+            || submitters.contains(&player)
+        {
+            continue;
+        }
+
+        submitters.push(player);
+        submitted.write(TextPromptSubmitted {
+            player,
+            text: prompt.text.clone(),
+        });
+
+        // The prompt is removed first so that closing the inventory does not cancel it
+        commands
+            .entity(player)
+            .remove::<TextPrompt>()
+            .remove::<OpenInventory>();
+        commands.entity(prompt.inventory).despawn();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_ecs::message::Messages;
+
+    use super::*;
+
+    #[test]
+    fn sanitize_strips_and_limits_text() {
+        assert_eq!(sanitize("§cRed\nIsland\u{7f}"), "cRedIsland");
+        assert_eq!(sanitize(&"a".repeat(80)).len(), MAX_TEXT_LENGTH);
+        assert_eq!(sanitize("ünicode ok"), "ünicode ok");
+    }
+
+    #[test]
+    fn opening_a_prompt_cancels_the_previous_one() {
+        let mut app = App::new();
+        app.add_message::<TextPromptCancelled>();
+        app.add_observer(cancel_on_close);
+        let world = app.world_mut();
+        let player = world.spawn_empty().id();
+
+        TextPrompt::open(player, Text::text("first"), "one".to_owned())(world);
+        let first = world.get::<TextPrompt>(player).unwrap().inventory;
+
+        TextPrompt::open(player, Text::text("second"), "two§".to_owned())(world);
+        world.flush();
+
+        let prompt = world.get::<TextPrompt>(player).unwrap();
+        assert_eq!(prompt.text(), "two");
+        assert_eq!(
+            world.get::<OpenInventory>(player).unwrap().inventory,
+            prompt.inventory
+        );
+        assert!(world.get_entity(first).is_err());
+
+        let cancelled = world.resource::<Messages<TextPromptCancelled>>();
+        assert_eq!(cancelled.len(), 1);
+
+        world.entity_mut(player).remove::<OpenInventory>();
+        world.flush();
+
+        assert!(world.get::<TextPrompt>(player).is_none());
+        assert_eq!(world.resource::<Messages<TextPromptCancelled>>().len(), 2);
+    }
+}
@@ -1,5 +1,5 @@
 #![expect(clippy::transmute_ptr_to_ptr)]
-use std::{cell::Cell, cmp::min, num::Wrapping};
+use std::{cell::Cell, cmp::min, collections::BTreeMap, num::Wrapping};
 
 use bevy_ecs::{component::Component, entity::Entity};
 use thiserror::Error;
@@ -7,7 +7,7 @@ use thread_local::ThreadLocal;
 use tracing::debug;
 use valence_generated::item::EquipmentSlot;
 use valence_protocol::{
-    ItemKind, ItemStack,
+    Hand, ItemKind, ItemStack,
     nbt::Compound,
     packets::play::{click_slot_c2s::ClickMode, open_screen_s2c::WindowType},
 };
@@ -61,7 +61,34 @@ pub enum ClickModeRemote {
 
 pub type PlayerInventory = Inventory;
 
-#[derive(Component, Clone, Debug, PartialEq)]
+/// Who changed a slot of an [`Inventory`]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "reflect", derive(Reflect))]
+pub enum SlotChangeCause {
+    /// The player moved, dropped or picked items in the inventory. See [`Inventory::by_player`].
+    Player,
+    /// The server changed the inventory, such as by giving the player an item or by clearing the
+    /// inventory of a player who died
+    #[default]
+    Server,
+}
+
+/// A slot which changed since the last call to [`Inventory::take_changes`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChangedSlot {
+    pub index: u16,
+    /// The stack before the first change
+    pub old: ItemStack,
+    pub new: ItemStack,
+    /// The cause of the first change
+    pub cause: SlotChangeCause,
+}
+
+/// The slots of an inventory.
+///
+/// Every method which can change a slot records the stack it had before, so the changes can be
+/// retrieved with [`Inventory::take_changes`]. There is no other way to change a slot.
+#[derive(Component, Clone, Debug)]
 #[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component, Default))]
 pub struct Inventory {
     /// The slots in the inventory
@@ -74,6 +101,50 @@ pub struct Inventory {
     #[cfg_attr(feature = "reflect", reflect(remote = WindowTypeRemote))]
     kind: WindowType,
     readonly: bool,
+    /// The stacks the slots which may have changed since the last call to
+    /// [`Inventory::take_changes`] had before, and the cause of the first change
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    original: BTreeMap<u16, (ItemStack, SlotChangeCause)>,
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    cause: SlotChangeCause,
+}
+
+impl PartialEq for Inventory {
+    fn eq(&self, other: &Self) -> bool {
+        // Changes which have not been taken yet do not matter
+        self.slots == other.slots
+            && self.hand_slot == other.hand_slot
+            && self.title == other.title
+            && self.kind == other.kind
+            && self.readonly == other.readonly
+    }
+}
+
+/// An [`Inventory`] whose changes are recorded as made by the player. See
+/// [`Inventory::by_player`].
+pub struct PlayerChanges<'a> {
+    inventory: &'a mut Inventory,
+    previous: SlotChangeCause,
+}
+
+impl std::ops::Deref for PlayerChanges<'_> {
+    type Target = Inventory;
+
+    fn deref(&self) -> &Self::Target {
+        self.inventory
+    }
+}
+
+impl std::ops::DerefMut for PlayerChanges<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.inventory
+    }
+}
+
+impl Drop for PlayerChanges<'_> {
+    fn drop(&mut self) {
+        self.inventory.cause = self.previous;
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -244,6 +315,104 @@ pub struct AddItemResult {
     pub remaining: Option<ItemStack>,
 }
 
+/// The result of [`Inventory::give`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct GiveResult {
+    /// How many items of the stack were added to the inventory
+    pub inserted: i8,
+    /// The part of the stack which did not fit into the inventory
+    pub remainder: Option<ItemStack>,
+}
+
+/// The armor slots of a [`PlayerInventory`], see [`PlayerInventory::armor`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ArmorSlots<T> {
+    pub head: T,
+    pub chest: T,
+    pub legs: T,
+    pub feet: T,
+}
+
+/// One of the armor slots of a [`PlayerInventory`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "reflect", derive(Reflect))]
+pub enum ArmorSlot {
+    Head,
+    Chest,
+    Legs,
+    Feet,
+}
+
+impl ArmorSlot {
+    /// The protocol slot id of the armor slot
+    #[must_use]
+    pub const fn to_protocol(self) -> u16 {
+        match self {
+            Self::Head => PlayerInventory::HELMET_SLOT,
+            Self::Chest => PlayerInventory::CHESTPLATE_SLOT,
+            Self::Legs => PlayerInventory::LEGGINGS_SLOT,
+            Self::Feet => PlayerInventory::BOOTS_SLOT,
+        }
+    }
+}
+
+/// A slot of a [`PlayerInventory`] by the region it belongs to, instead of by its protocol slot
+/// id. The indices are relative to the start of the region.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "reflect", derive(Reflect))]
+pub enum PlayerSlot {
+    CraftingResult,
+    /// A slot of the 2x2 crafting grid, from 0 to 3
+    Crafting(u8),
+    Armor(ArmorSlot),
+    /// A slot of the main inventory above the hotbar, from 0 to 26
+    Main(u8),
+    /// A slot of the hotbar, from 0 to 8
+    Hotbar(u8),
+    Offhand,
+}
+
+impl PlayerSlot {
+    /// Returns the slot with the protocol slot id `id`, or [`None`] if the player inventory has
+    /// no such slot
+    #[must_use]
+    pub fn from_protocol(id: u16) -> Option<Self> {
+        let index =
+            |start: u16| u8::try_from(id - start).expect("regions have less than 256 slots");
+
+        let slot = match id {
+            0 => Self::CraftingResult,
+            1..=4 => Self::Crafting(index(1)),
+            PlayerInventory::HELMET_SLOT => Self::Armor(ArmorSlot::Head),
+            PlayerInventory::CHESTPLATE_SLOT => Self::Armor(ArmorSlot::Chest),
+            PlayerInventory::LEGGINGS_SLOT => Self::Armor(ArmorSlot::Legs),
+            PlayerInventory::BOOTS_SLOT => Self::Armor(ArmorSlot::Feet),
+            MAIN_START_SLOT..HAND_START_SLOT => Self::Main(index(MAIN_START_SLOT)),
+            HAND_START_SLOT..HAND_END_SLOT => Self::Hotbar(index(HAND_START_SLOT)),
+            OFFHAND_SLOT => Self::Offhand,
+            _ => return None,
+        };
+
+        Some(slot)
+    }
+
+    /// Returns the protocol slot id of the slot, or [`None`] if the index is outside of its region
+    #[must_use]
+    pub fn to_protocol(self) -> Option<u16> {
+        let (start, end, index) = match self {
+            Self::CraftingResult => return Some(0),
+            Self::Crafting(index) => (1, PlayerInventory::HELMET_SLOT, index),
+            Self::Armor(slot) => return Some(slot.to_protocol()),
+            Self::Main(index) => (MAIN_START_SLOT, HAND_START_SLOT, index),
+            Self::Hotbar(index) => (HAND_START_SLOT, HAND_END_SLOT, index),
+            Self::Offhand => return Some(OFFHAND_SLOT),
+        };
+
+        let id = start + u16::from(index);
+        (id < end).then_some(id)
+    }
+}
+
 impl Default for Inventory {
     fn default() -> Self {
         Self::new(46, "Inventory".to_string(), WindowType::Generic9x3, false)
@@ -264,6 +433,7 @@ enum AddSlot {
     Skipped,
 }
 
+const MAIN_START_SLOT: u16 = 9;
 const HAND_START_SLOT: u16 = 36;
 const HAND_END_SLOT: u16 = 45;
 
@@ -277,9 +447,62 @@ impl Inventory {
             kind,
             hand_slot: 36,
             readonly,
+            original: BTreeMap::new(),
+            cause: SlotChangeCause::Server,
+        }
+    }
+
+    /// Records changes made through the returned [`PlayerChanges`] as made by the player
+    pub fn by_player(&mut self) -> PlayerChanges<'_> {
+        let previous = std::mem::replace(&mut self.cause, SlotChangeCause::Player);
+        PlayerChanges {
+            inventory: self,
+            previous,
+        }
+    }
+
+    /// Records the stack of the slot at `index` before it is changed, unless it has been
+    /// recorded already
+    fn record(&mut self, index: u16) {
+        let Some(slot) = self.slots.get(usize::from(index)) else {
+            return;
+        };
+
+        let cause = self.cause;
+        self.original
+            .entry(index)
+            .or_insert_with(|| (slot.stack.clone(), cause));
+    }
+
+    fn record_range(&mut self, range: impl Iterator<Item = usize>) {
+        for index in range {
+            self.record(u16::try_from(index).unwrap());
         }
     }
 
+    /// Returns the slots which changed since the last call. Slots which were changed back to
+    /// their previous stack are not included.
+    pub fn take_changes(&mut self) -> impl Iterator<Item = ChangedSlot> + '_ {
+        let slots = &self.slots;
+        std::mem::take(&mut self.original)
+            .into_iter()
+            .filter_map(move |(index, (old, cause))| {
+                let new = &slots[usize::from(index)].stack;
+                (old != *new).then(|| ChangedSlot {
+                    index,
+                    old,
+                    new: new.clone(),
+                    cause,
+                })
+            })
+    }
+
+    /// Whether any slot may have changed since the last call to [`Inventory::take_changes`]
+    #[must_use]
+    pub fn has_changes(&self) -> bool {
+        !self.original.is_empty()
+    }
+
     #[must_use]
     pub const fn kind(&self) -> WindowType {
         self.kind
@@ -335,19 +558,46 @@ impl Inventory {
         &self.slots
     }
 
+    /// Returns all slots mutably. Every slot is recorded as possibly changed, so prefer
+    /// [`Inventory::get_mut`] to change a single slot.
     #[must_use]
-    pub const fn slots_mut(&mut self) -> &mut [ItemSlot] {
+    pub fn slots_mut(&mut self) -> &mut [ItemSlot] {
+        self.record_range(0..self.slots.len());
         &mut self.slots
     }
 
-    pub fn clear(&mut self) {
+    /// Sets [`ItemSlot::changed`] for every slot, so that all of them are sent to the client again
+    pub fn mark_all_changed(&mut self) {
+        for slot in &mut self.slots {
+            slot.changed = true;
+        }
+    }
+
+    /// Clears [`ItemSlot::changed`] for every slot after the slots have been sent to the client
+    pub fn clear_changed(&mut self) {
         for slot in &mut self.slots {
-            if slot.stack.is_empty() {
+            slot.changed = false;
+        }
+    }
+
+    /// Empties every slot whose stack matches `filter` and returns how many items were removed
+    pub fn clear(&mut self, filter: impl Fn(&ItemStack) -> bool) -> u32 {
+        let mut removed = 0;
+
+        for index in 0..self.slots.len() {
+            let stack = &self.slots[index].stack;
+            if stack.is_empty() || !filter(stack) {
                 continue;
             }
+
+            self.record(u16::try_from(index).unwrap());
+            let slot = &mut self.slots[index];
+            removed += u32::from(slot.stack.count.unsigned_abs());
             slot.stack = ItemStack::EMPTY;
             slot.changed = true;
         }
+
+        removed
     }
 
     pub fn set_cursor(&mut self, index: u16) -> Result<(), InventoryAccessError> {
@@ -383,6 +633,9 @@ impl Inventory {
     }
 
     pub fn swap(&mut self, index_a: u16, index_b: u16) {
+        self.record(index_a);
+        self.record(index_b);
+
         let index_a = usize::from(index_a);
         let index_b = usize::from(index_b);
 
@@ -417,6 +670,7 @@ impl Inventory {
         &mut self,
         index: u16,
     ) -> Result<&mut ItemSlot, InventoryAccessError> {
+        self.record(index);
         self.slots
             .get_mut(usize::from(index))
             .ok_or(InventoryAccessError::InvalidSlot { index })
@@ -425,19 +679,18 @@ impl Inventory {
     /// Returns remaining [`ItemStack`] if not all of the item was added to the slot
     fn add_to_slot(
         &mut self,
-        slot: u16,
+        index: u16,
         to_add: &mut ItemStack,
         can_add_to_empty: bool,
     ) -> Result<AddSlot, InventoryAccessError> {
-        let slot = self.get_mut_maybe_change(slot)?;
+        let stack = &self.get(index)?.stack;
         let max_stack_size: i8 = to_add.item.max_stack();
 
-        if slot.stack.is_empty() {
+        if stack.is_empty() {
             return if can_add_to_empty {
                 let new_count = min(to_add.count, max_stack_size);
                 to_add.count -= new_count;
-                slot.stack = to_add.clone().with_count(new_count);
-                slot.changed = true;
+                self.set(index, to_add.clone().with_count(new_count))?;
                 return if to_add.count > 0 {
                     Ok(AddSlot::Partial)
                 } else {
@@ -448,9 +701,10 @@ impl Inventory {
             };
         }
 
-        let stackable = slot.stack.item == to_add.item && slot.stack.nbt == to_add.nbt;
+        let stackable = stack.item == to_add.item && stack.nbt == to_add.nbt;
 
-        if stackable && slot.stack.count < max_stack_size {
+        if stackable && stack.count < max_stack_size {
+            let slot = self.get_mut(index)?;
             let space_left = max_stack_size - slot.stack.count;
 
             return if to_add.count <= space_left {
@@ -474,6 +728,9 @@ impl Inventory {
             return;
         }
 
+        self.record(slot);
+        self.record(other_slot);
+
         let slot = usize::from(slot);
         let other_slot = usize::from(other_slot);
 
@@ -515,16 +772,148 @@ impl PlayerInventory {
             .unwrap_or(ItemStack::EMPTY)
     }
 
+    /// The `N` slots starting at the protocol slot id `start`
+    fn region<const N: usize>(&self, start: u16) -> &[ItemSlot; N] {
+        let start = usize::from(start);
+        self.slots[start..start + N]
+            .try_into()
+            .expect("the region has N slots")
+    }
+
+    /// The `N` slots starting at the protocol slot id `start` mutably. Every slot is recorded as
+    /// possibly changed and sent to the client again.
+    fn region_mut<const N: usize>(&mut self, start: u16) -> &mut [ItemSlot; N] {
+        let start = usize::from(start);
+        self.record_range(start..start + N);
+
+        let region: &mut [ItemSlot; N] = (&mut self.slots[start..start + N])
+            .try_into()
+            .expect("the region has N slots");
+        for slot in region.iter_mut() {
+            slot.changed = true;
+        }
+        region
+    }
+
+    /// The slots of the hotbar from left to right
+    #[must_use]
+    pub fn hotbar(&self) -> &[ItemSlot; 9] {
+        self.region(HAND_START_SLOT)
+    }
+
+    /// The slots of the hotbar mutably. Every slot is recorded as possibly changed.
+    #[must_use]
+    pub fn hotbar_mut(&mut self) -> &mut [ItemSlot; 9] {
+        self.region_mut(HAND_START_SLOT)
+    }
+
+    /// The slots of the main inventory above the hotbar, row by row from the top left
+    #[must_use]
+    pub fn main(&self) -> &[ItemSlot; 27] {
+        self.region(MAIN_START_SLOT)
+    }
+
+    /// The slots of the main inventory mutably. Every slot is recorded as possibly changed.
+    #[must_use]
+    pub fn main_mut(&mut self) -> &mut [ItemSlot; 27] {
+        self.region_mut(MAIN_START_SLOT)
+    }
+
+    /// The armor slots from the helmet to the boots
+    #[must_use]
+    pub fn armor(&self) -> ArmorSlots<&ItemSlot> {
+        let [head, chest, legs, feet] = self.region::<4>(Self::HELMET_SLOT);
+        ArmorSlots {
+            head,
+            chest,
+            legs,
+            feet,
+        }
+    }
+
+    /// The armor slots mutably. Every slot is recorded as possibly changed.
+    #[must_use]
+    pub fn armor_mut(&mut self) -> ArmorSlots<&mut ItemSlot> {
+        let [head, chest, legs, feet] = self.region_mut::<4>(Self::HELMET_SLOT);
+        ArmorSlots {
+            head,
+            chest,
+            legs,
+            feet,
+        }
+    }
+
+    #[must_use]
+    pub fn offhand(&self) -> &ItemSlot {
+        self.get(Self::OFFHAND_SLOT).unwrap()
+    }
+
+    /// The offhand slot mutably. The slot is recorded as possibly changed.
+    #[must_use]
+    pub fn offhand_mut(&mut self) -> &mut ItemSlot {
+        self.get_mut(Self::OFFHAND_SLOT).unwrap()
+    }
+
+    /// The slot of `slot`, or [`None`] if its index is outside of its region
+    #[must_use]
+    pub fn slot(&self, slot: PlayerSlot) -> Option<&ItemSlot> {
+        self.get(slot.to_protocol()?).ok()
+    }
+
+    /// The slot held in `hand`
+    #[must_use]
+    pub fn held(&self, hand: Hand) -> &ItemSlot {
+        match hand {
+            Hand::Main => &self.hotbar()[usize::from(self.selected_hotbar_index())],
+            Hand::Off => self.offhand(),
+        }
+    }
+
+    /// The slot held in `hand` mutably. The slot is recorded as possibly changed.
+    #[must_use]
+    pub fn held_mut(&mut self, hand: Hand) -> &mut ItemSlot {
+        let index = match hand {
+            Hand::Main => self.hand_slot,
+            Hand::Off => Self::OFFHAND_SLOT,
+        };
+        self.get_mut(index).expect("held slots are valid indices")
+    }
+
+    /// The index of the selected hotbar slot, from 0 to 8
+    #[must_use]
+    pub fn selected_hotbar_index(&self) -> u8 {
+        u8::try_from(self.hand_slot - HAND_START_SLOT).expect("hand_slot is in the hotbar")
+    }
+
+    /// Selects the hotbar slot `index`, from 0 to 8.
+    ///
+    /// This only changes the inventory. Use
+    /// `hyperion::simulation::inventory::set_selected_hotbar_index` to also tell the client.
+    pub fn set_selected_hotbar_index(&mut self, index: u8) -> Result<(), InventoryAccessError> {
+        self.set_cursor(u16::from(index))
+    }
+
     #[must_use]
     pub fn slots_inventory(&self) -> &[ItemSlot] {
         &self.slots[9..=44]
     }
 
+    /// Returns the slots of the inventory without the crafting grid, armor and offhand mutably.
+    /// Every returned slot is recorded as possibly changed.
     #[must_use]
     pub fn slots_inventory_mut(&mut self) -> &mut [ItemSlot] {
+        self.record_range(9..=44);
         &mut self.slots[9..=44]
     }
 
+    /// Clears [`ItemSlot::changed`] for the slots returned by
+    /// [`PlayerInventory::slots_inventory`] after they have been sent to the client
+    pub fn clear_changed_inventory(&mut self) {
+        for slot in &mut self.slots[9..=44] {
+            slot.changed = false;
+        }
+    }
+
     pub fn set_hotbar(&mut self, idx: u16, stack: ItemStack) -> Result<(), InventoryAccessError> {
         self.get_hand_slot_mut(idx)?.stack = stack;
         Ok(())
@@ -575,32 +964,42 @@ impl PlayerInventory {
         self.get(Self::OFFHAND_SLOT).unwrap()
     }
 
-    pub fn try_add_item(&mut self, mut item: ItemStack) -> AddItemResult {
-        let mut result = AddItemResult { remaining: None };
+    /// Adds `stack` to the inventory like picking it up would. It is merged into existing stacks
+    /// first, and then put into empty slots. The hotbar is filled before the main inventory, and
+    /// no slot gets more items than the maximum stack size of the item.
+    pub fn give(&mut self, mut stack: ItemStack) -> GiveResult {
+        let count = stack.count;
 
-        // Try to add to hot bar (36-45) first, then the rest of the inventory (9-35)
-        // try to stack first
-        for can_add_to_empty in [false, true] {
+        if stack.is_empty() {
+            return GiveResult {
+                inserted: 0,
+                remainder: None,
+            };
+        }
+
+        'add: for can_add_to_empty in [false, true] {
             for slot in (36..=44).chain(9..36) {
                 let add_slot = self
-                    .add_to_slot(slot, &mut item, can_add_to_empty)
+                    .add_to_slot(slot, &mut stack, can_add_to_empty)
                     .expect("slot index is in bounds");
 
-                match add_slot {
-                    AddSlot::Complete => {
-                        return result;
-                    }
-                    AddSlot::Partial | AddSlot::Skipped => {}
+                if matches!(add_slot, AddSlot::Complete) {
+                    break 'add;
                 }
             }
         }
 
-        // If there's any remaining item, set it in the result
-        if item.count > 0 {
-            result.remaining = Some(item);
+        let remainder = (!stack.is_empty()).then_some(stack);
+        GiveResult {
+            inserted: count - remainder.as_ref().map_or(0, |stack| stack.count),
+            remainder,
         }
+    }
 
-        result
+    pub fn try_add_item(&mut self, item: ItemStack) -> AddItemResult {
+        AddItemResult {
+            remaining: self.give(item).remainder,
+        }
     }
 }
 
@@ -653,3 +1052,153 @@ impl ItemKindExt for ItemKind {
         self.equippable().is_some()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changes_are_recorded_with_their_cause() {
+        let mut inventory = PlayerInventory::default();
+        let stone = ItemStack::new(ItemKind::Stone, 1, None);
+
+        inventory.set(36, stone.clone()).unwrap();
+        inventory.by_player().swap_slot(36, 37);
+        inventory.by_player().set(9, stone.clone()).unwrap();
+        // Slots which were changed back are not reported
+        inventory.set(9, ItemStack::EMPTY).unwrap();
+
+        let changes: Vec<_> = inventory.take_changes().collect();
+        assert_eq!(changes, [ChangedSlot {
+            index: 37,
+            old: ItemStack::EMPTY,
+            new: stone,
+            cause: SlotChangeCause::Player,
+        }]);
+        assert!(!inventory.has_changes());
+    }
+
+    #[test]
+    fn give_fills_existing_stacks_then_hotbar_then_main_inventory() {
+        let mut inventory = PlayerInventory::default();
+        inventory
+            .set(20, ItemStack::new(ItemKind::Stone, 60, None))
+            .unwrap();
+        for slot in 37..=44 {
+            inventory
+                .set(slot, ItemStack::new(ItemKind::Dirt, 64, None))
+                .unwrap();
+        }
+
+        let result = inventory.give(ItemStack::new(ItemKind::Stone, 100, None));
+        assert_eq!(result, GiveResult {
+            inserted: 100,
+            remainder: None,
+        });
+        assert_eq!(inventory.get(20).unwrap().stack.count, 64);
+        assert_eq!(inventory.get(36).unwrap().stack.count, 64);
+        assert_eq!(inventory.get(9).unwrap().stack.count, 32);
+
+        inventory.clear(|stack| stack.item == ItemKind::Dirt);
+        let result = inventory.give(ItemStack::new(ItemKind::Dirt, 127, None));
+        assert_eq!(result.inserted, 127);
+
+        // Fill every storage slot so that nothing fits anymore
+        for slot in (36..=44).chain(9..36) {
+            inventory
+                .set(slot, ItemStack::new(ItemKind::Bedrock, 64, None))
+                .unwrap();
+        }
+        let stack = ItemStack::new(ItemKind::Stone, 5, None);
+        let result = inventory.give(stack.clone());
+        assert_eq!(result, GiveResult {
+            inserted: 0,
+            remainder: Some(stack),
+        });
+    }
+
+    #[test]
+    fn clear_counts_removed_items() {
+        let mut inventory = PlayerInventory::default();
+        inventory
+            .set(9, ItemStack::new(ItemKind::Stone, 10, None))
+            .unwrap();
+        inventory
+            .set(10, ItemStack::new(ItemKind::Stone, 5, None))
+            .unwrap();
+        inventory
+            .set(36, ItemStack::new(ItemKind::Dirt, 3, None))
+            .unwrap();
+
+        assert_eq!(inventory.clear(|stack| stack.item == ItemKind::Stone), 15);
+        assert_eq!(inventory.get(9).unwrap().stack, ItemStack::EMPTY);
+        assert_eq!(inventory.get(36).unwrap().stack.count, 3);
+        assert_eq!(inventory.clear(|_| true), 3);
+    }
+
+    #[test]
+    fn protocol_slot_ids_round_trip() {
+        for id in 0..46 {
+            let slot = PlayerSlot::from_protocol(id).unwrap();
+            assert_eq!(slot.to_protocol(), Some(id), "{slot:?}");
+        }
+        assert_eq!(PlayerSlot::from_protocol(46), None);
+
+        assert_eq!(PlayerSlot::from_protocol(36), Some(PlayerSlot::Hotbar(0)));
+        assert_eq!(PlayerSlot::from_protocol(35), Some(PlayerSlot::Main(26)));
+        assert_eq!(
+            PlayerSlot::from_protocol(8),
+            Some(PlayerSlot::Armor(ArmorSlot::Feet))
+        );
+
+        // Indices outside of their region have no slot id
+        assert_eq!(PlayerSlot::Crafting(4).to_protocol(), None);
+        assert_eq!(PlayerSlot::Main(27).to_protocol(), None);
+        assert_eq!(PlayerSlot::Hotbar(9).to_protocol(), None);
+    }
+
+    #[test]
+    fn regions_match_their_protocol_slot_ids() {
+        let mut inventory = PlayerInventory::default();
+        let stack = |count| ItemStack::new(ItemKind::Stone, count, None);
+
+        inventory.hotbar_mut()[2].stack = stack(1);
+        inventory.main_mut()[26].stack = stack(2);
+        inventory.armor_mut().chest.stack = stack(3);
+        inventory.offhand_mut().stack = stack(4);
+
+        for (slot, count) in [
+            (PlayerSlot::Hotbar(2), 1),
+            (PlayerSlot::Main(26), 2),
+            (PlayerSlot::Armor(ArmorSlot::Chest), 3),
+            (PlayerSlot::Offhand, 4),
+        ] {
+            let id = slot.to_protocol().unwrap();
+            assert_eq!(inventory.get(id).unwrap().stack, stack(count));
+            assert_eq!(inventory.slot(slot).unwrap().stack, stack(count));
+        }
+        assert_eq!(inventory.armor().chest.stack, stack(3));
+
+        let changed: Vec<_> = inventory
+            .take_changes()
+            .map(|change| change.index)
+            .collect();
+        assert_eq!(changed, [6, 35, 38, 45]);
+    }
+
+    #[test]
+    fn selected_hotbar_slot_is_held() {
+        let mut inventory = PlayerInventory::default();
+        let sword = ItemStack::new(ItemKind::DiamondSword, 1, None);
+        inventory.hotbar_mut()[4].stack = sword.clone();
+
+        assert_eq!(inventory.selected_hotbar_index(), 0);
+        inventory.set_selected_hotbar_index(4).unwrap();
+        assert_eq!(inventory.selected_hotbar_index(), 4);
+        assert_eq!(inventory.held(Hand::Main).stack, sword);
+        assert_eq!(inventory.get_cursor_index(), 40);
+
+        assert!(inventory.set_selected_hotbar_index(9).is_err());
+        assert_eq!(inventory.selected_hotbar_index(), 4);
+    }
+}
@@ -1,6 +1,7 @@
-use bevy_ecs::entity::Entity;
 use valence_protocol::{ItemKind, ItemStack, nbt, nbt::Value};
 
+use crate::{HANDLER_KEY, registry::HandlerId};
+
 mod book;
 pub use book::BookBuilder;
 
@@ -150,14 +151,15 @@ impl ItemBuilder {
         self
     }
 
-    pub fn handler(mut self, handler: Entity) -> Self {
+    /// Sets the handler which receives an [`NbtInteractEvent`](crate::NbtInteractEvent) when the
+    /// item is used. See [`HandlerRegistry`](crate::registry::HandlerRegistry).
+    pub fn handler(mut self, handler: HandlerId) -> Self {
         let nbt = self.nbt.get_or_insert_with(nbt::Compound::new);
-        let id = handler.to_bits();
 
         // we are explicitly casting to i64 because although sign might be lost, when we read it back,
         // we will revert it back to a u64.
-        let id: i64 = bytemuck::cast(id);
-        nbt.insert("Handler", Value::Long(id));
+        let id: i64 = bytemuck::cast(handler.get());
+        nbt.insert(HANDLER_KEY, Value::Long(id));
         self
     }
 
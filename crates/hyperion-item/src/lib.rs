@@ -3,18 +3,33 @@ use bevy_ecs::{
     entity::Entity,
     message::{Message, MessageReader, MessageWriter},
     schedule::IntoScheduleConfigs,
-    system::Query,
+    system::{Query, Res},
 };
-use hyperion::{ingress, simulation::event::InteractEvent};
+use hyperion::{ingress, simulation::event::InteractEvent, storage::LocalDb};
 use hyperion_inventory::PlayerInventory;
-use tracing::error;
+use tracing::{error, warn};
 use valence_protocol::nbt;
 
+use crate::registry::{HandlerId, HandlerRegistry};
+
 pub mod builder;
+pub mod registry;
+mod storage;
+
+/// The NBT long in which items store the [`HandlerId`] of their handler
+const HANDLER_KEY: &str = "HandlerId";
+
+/// The NBT long in which items stored the bits of their handler entity before handlers had stable
+/// ids. Such items are still handled, see [`legacy_handler`], but never rewritten to
+/// [`HANDLER_KEY`], since their handler cannot be known for sure.
+const LEGACY_HANDLER_KEY: &str = "Handler";
 
+/// Adds the [`HandlerRegistry`] and sends [`NbtInteractEvent`]s. This must be added after
+/// [`hyperion::HyperionCore`] has inserted the [`LocalDb`], and before the plugins which register
+/// handlers.
 pub struct ItemPlugin;
 
-/// Event sent when an item with an NBT is clicked in the hotbar
+/// Event sent when an item with an NBT handler is used from the main hand or the offhand
 #[derive(Message)]
 pub struct NbtInteractEvent {
     pub handler: Entity,
@@ -29,9 +44,25 @@ impl std::ops::Deref for NbtInteractEvent {
     }
 }
 
+/// Resolves the bits of a handler entity stored by items from before handlers had stable ids.
+/// They still work if the entity is alive and registered, which is usually the case since
+/// handlers are spawned in the same order every time the server starts. If the order changed,
+/// this is another handler, so the item is left as it is instead of being bound to it for good.
+fn legacy_handler(
+    bits: u64,
+    registry: &HandlerRegistry,
+    entities: &Query<'_, '_, Entity>,
+) -> Option<Entity> {
+    let handler = Entity::try_from_bits(bits)?;
+    let registered = entities.contains(handler) && registry.id_of(handler).is_some();
+    registered.then_some(handler)
+}
+
 fn handle_interact(
     mut events: MessageReader<'_, '_, InteractEvent>,
     query: Query<'_, '_, &PlayerInventory>,
+    registry: Res<'_, HandlerRegistry>,
+    entities: Query<'_, '_, Entity>,
     mut event_writer: MessageWriter<'_, NbtInteractEvent>,
 ) {
     for event in events.read() {
@@ -43,32 +74,42 @@ fn handle_interact(
             }
         };
 
-        let stack = &inventory.get_cursor().stack;
+        let stack = &inventory.held(event.hand).stack;
 
         if stack.is_empty() {
-            return;
+            continue;
         }
 
         let Some(nbt) = stack.nbt.as_ref() else {
-            return;
+            continue;
         };
 
-        let Some(handler) = nbt.get("Handler") else {
-            return;
-        };
-
-        let nbt::Value::Long(id) = handler else {
-            return;
-        };
+        let handler = if let Some(nbt::Value::Long(id)) = nbt.get(HANDLER_KEY) {
+            let id = HandlerId::new(bytemuck::cast(*id));
+            let Some(handler) = registry.get(id) else {
+                warn!(
+                    "item used by {} refers to handler id {}, which is not registered",
+                    event.client,
+                    id.get()
+                );
+                continue;
+            };
 
-        let id: u64 = bytemuck::cast(*id);
+            handler
+        } else if let Some(nbt::Value::Long(bits)) = nbt.get(LEGACY_HANDLER_KEY) {
+            let bits: u64 = bytemuck::cast(*bits);
+            let Some(handler) = legacy_handler(bits, &registry, &entities) else {
+                warn!(
+                    "item used by {} refers to handler entity {bits} from before handlers had \
+                     stable ids, which is not a registered handler anymore",
+                    event.client
+                );
+                continue;
+            };
 
-        let Some(handler) = Entity::try_from_bits(id) else {
-            error!(
-                "failed to handle interact event: nbt handler field contains an invalid entity id \
-                 {id}"
-            );
-            return;
+            handler
+        } else {
+            continue;
         };
 
         event_writer.write(NbtInteractEvent {
@@ -80,7 +121,130 @@ fn handle_interact(
 
 impl Plugin for ItemPlugin {
     fn build(&self, app: &mut App) {
+        let registry = match app.world().get_resource::<LocalDb>() {
+            Some(db) => HandlerRegistry::load(db).unwrap_or_else(|e| {
+                error!("failed to load item handler ids, they will not be saved: {e}");
+                HandlerRegistry::default()
+            }),
+            None => {
+                warn!("no LocalDb, item handler ids will not be saved");
+                HandlerRegistry::default()
+            }
+        };
+
+        app.insert_resource(registry);
         app.add_systems(FixedUpdate, handle_interact.after(ingress::decode::play));
         app.add_message::<NbtInteractEvent>();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bevy_ecs::{message::Messages, system::RunSystemOnce, world::World};
+    use valence_protocol::{Hand, ItemKind, ItemStack};
+
+    use super::*;
+    use crate::builder::ItemBuilder;
+
+    fn interact(world: &mut World, client: Entity, hand: Hand) -> Vec<Entity> {
+        world.write_message(InteractEvent {
+            client,
+            hand,
+            sequence: 0,
+        });
+        world.run_system_once(handle_interact).unwrap();
+
+        world
+            .resource_mut::<Messages<NbtInteractEvent>>()
+            .drain()
+            .map(|event| event.handler)
+            .collect()
+    }
+
+    fn setup(world: &mut World, registry: HandlerRegistry) {
+        world.insert_resource(registry);
+        world.init_resource::<Messages<InteractEvent>>();
+        world.init_resource::<Messages<NbtInteractEvent>>();
+    }
+
+    #[test]
+    fn handlers_are_found_after_a_restart() {
+        let path = std::env::temp_dir().join(format!("hyperion-item-{}", fastrand::u64(..)));
+
+        let saved = {
+            let db = LocalDb::builder().path(&path).build().unwrap();
+            let mut world = World::new();
+            let handler = world.spawn_empty().id();
+
+            let mut registry = HandlerRegistry::load(&db).unwrap();
+            let id = registry.register("fireball", handler).unwrap();
+            let item = ItemBuilder::new(ItemKind::FireCharge).handler(id).build();
+
+            let mut bytes = Vec::new();
+            nbt::to_binary(item.nbt.as_ref().unwrap(), &mut bytes, "").unwrap();
+            bytes
+        };
+
+        // The handler is a different entity after the restart
+        let db = LocalDb::builder().path(&path).build().unwrap();
+        let mut world = World::new();
+        for _ in 0..3 {
+            world.spawn_empty();
+        }
+        let handler = world.spawn_empty().id();
+
+        let mut registry = HandlerRegistry::load(&db).unwrap();
+        registry
+            .register("other", world.spawn_empty().id())
+            .unwrap();
+        registry.register("fireball", handler).unwrap();
+        setup(&mut world, registry);
+
+        let nbt = nbt::from_binary(&mut saved.as_slice()).unwrap().0;
+        let mut inventory = PlayerInventory::default();
+        inventory
+            .set_hotbar(0, ItemStack::new(ItemKind::FireCharge, 1, Some(nbt)))
+            .unwrap();
+        let player = world.spawn(inventory).id();
+
+        assert_eq!(interact(&mut world, player, Hand::Main), [handler]);
+
+        drop(world);
+        drop(db);
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn legacy_items_are_handled_but_not_rewritten() {
+        let mut world = World::new();
+        let handler = world.spawn_empty().id();
+        let mut registry = HandlerRegistry::default();
+        registry.register("fireball", handler).unwrap();
+        setup(&mut world, registry);
+
+        let legacy = |bits: u64| {
+            let mut nbt = nbt::Compound::new();
+            nbt.insert(LEGACY_HANDLER_KEY, nbt::Value::Long(bytemuck::cast(bits)));
+            ItemStack::new(ItemKind::FireCharge, 1, Some(nbt))
+        };
+
+        let mut inventory = PlayerInventory::default();
+        inventory.set_offhand(legacy(handler.to_bits()));
+        inventory.set_hotbar(0, legacy(u64::MAX)).unwrap();
+        let player = world.spawn(inventory).id();
+
+        assert_eq!(interact(&mut world, player, Hand::Off), [handler]);
+
+        // The handler may be another one after the next restart, so the item is kept as it is
+        let inventory = world.get::<PlayerInventory>(player).unwrap();
+        let nbt = inventory.offhand().stack.nbt.as_ref().unwrap();
+        assert_eq!(nbt.get(HANDLER_KEY), None);
+        assert_eq!(
+            nbt.get(LEGACY_HANDLER_KEY),
+            Some(&nbt::Value::Long(bytemuck::cast(handler.to_bits())))
+        );
+
+        // Entities which are not alive are not handlers
+        assert!(interact(&mut world, player, Hand::Main).is_empty());
+    }
+}
@@ -0,0 +1,95 @@
+//! Stable ids for the entities which handle interactions with items.
+//!
+//! Items refer to their handler by a [`HandlerId`] in their NBT instead of by the handler entity,
+//! since entity ids change whenever the server restarts. Handlers are registered under a name
+//! every time the server starts, and the [`HandlerRegistry`] stores the id of every name in the
+//! [`LocalDb`], so that a name gets the same id as before and items from before the restart keep
+//! working.
+
+use std::collections::HashMap;
+
+use bevy_ecs::{entity::Entity, resource::Resource};
+use hyperion::storage::LocalDb;
+
+use crate::storage::HandlerStorage;
+
+/// The id of an item handler, which stays the same across restarts. See the
+/// [module documentation](self).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct HandlerId(u64);
+
+impl HandlerId {
+    #[must_use]
+    pub const fn new(id: u64) -> Self {
+        Self(id)
+    }
+
+    #[must_use]
+    pub const fn get(self) -> u64 {
+        self.0
+    }
+}
+
+/// The handler entity of every [`HandlerId`]. See the [module documentation](self).
+#[derive(Resource, Debug, Default)]
+pub struct HandlerRegistry {
+    /// The id of every handler name which was ever registered, including those which are not
+    /// registered since the last restart
+    ids: HashMap<String, HandlerId>,
+    handlers: HashMap<HandlerId, Entity>,
+    /// Where new ids are persisted. Ids are only kept in memory without it.
+    storage: Option<HandlerStorage>,
+}
+
+impl HandlerRegistry {
+    /// Loads the ids stored in `db`. New ids are written back to it.
+    pub fn load(db: &LocalDb) -> anyhow::Result<Self> {
+        let storage = HandlerStorage::new(db)?;
+
+        Ok(Self {
+            ids: storage.load()?,
+            handlers: HashMap::new(),
+            storage: Some(storage),
+        })
+    }
+
+    /// Registers `handler` under `name` and returns the id which items should store to refer to
+    /// it. A name keeps its id across restarts, and registering a name again replaces its handler.
+    pub fn register(&mut self, name: &str, handler: Entity) -> anyhow::Result<HandlerId> {
+        let id = match self.ids.get(name) {
+            Some(&id) => id,
+            None => {
+                let id = self
+                    .ids
+                    .values()
+                    .map(|id| id.0 + 1)
+                    .max()
+                    .map_or(HandlerId(1), HandlerId);
+
+                if let Some(storage) = &self.storage {
+                    storage.put(name, id)?;
+                }
+
+                self.ids.insert(name.to_owned(), id);
+                id
+            }
+        };
+
+        self.handlers.insert(id, handler);
+        Ok(id)
+    }
+
+    /// The handler entity registered for `id`
+    #[must_use]
+    pub fn get(&self, id: HandlerId) -> Option<Entity> {
+        self.handlers.get(&id).copied()
+    }
+
+    /// The id of the registered handler entity `handler`
+    #[must_use]
+    pub fn id_of(&self, handler: Entity) -> Option<HandlerId> {
+        self.handlers
+            .iter()
+            .find_map(|(&id, &entity)| (entity == handler).then_some(id))
+    }
+}
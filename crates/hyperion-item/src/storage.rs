@@ -0,0 +1,41 @@
+use std::collections::HashMap;
+
+use heed::{Database, byteorder::NativeEndian, types};
+use hyperion::storage::LocalDb;
+
+use crate::registry::HandlerId;
+
+/// Stores the [`HandlerId`] of every handler name in the [`LocalDb`]
+#[derive(Debug, Clone)]
+pub struct HandlerStorage {
+    db: LocalDb,
+    ids: Database<types::Str, types::U64<NativeEndian>>,
+}
+
+impl HandlerStorage {
+    pub fn new(db: &LocalDb) -> anyhow::Result<Self> {
+        let ids = db.write(|wtxn| db.create_database(wtxn, Some("item-handlers")))?;
+
+        Ok(Self {
+            db: db.clone(),
+            ids,
+        })
+    }
+
+    /// Loads the id of every handler name which was ever registered
+    pub fn load(&self) -> anyhow::Result<HashMap<String, HandlerId>> {
+        let rtxn = self.db.read_txn()?;
+        let mut ids = HashMap::new();
+
+        for entry in self.ids.iter(&rtxn)? {
+            let (name, id) = entry?;
+            ids.insert(name.to_owned(), HandlerId::new(id));
+        }
+
+        Ok(ids)
+    }
+
+    pub fn put(&self, name: &str, id: HandlerId) -> anyhow::Result<()> {
+        self.db.write(|wtxn| self.ids.put(wtxn, name, &id.get()))
+    }
+}
@@ -3,6 +3,7 @@ mod storage;
 use bevy_app::{App, Plugin};
 use bevy_ecs::{
     component::Component,
+    entity::Entity,
     lifecycle::{Add, Despawn, Insert},
     observer::On,
     query::With,
@@ -11,9 +12,9 @@ use bevy_ecs::{
 };
 use clap::ValueEnum;
 use hyperion::{
-    net::{Compose, ConnectionId},
-    simulation::{Uuid, command::get_command_packet},
-    storage::LocalDb,
+    net::{Compose, ConnectionId, SendResultExt},
+    simulation::{Uuid, command::get_command_packet, region::RegionGroup, vanish::SeesVanished},
+    storage::{AuditAction, AuditEntry, AuditLog, LocalDb},
 };
 use storage::PermissionStorage;
 use tracing::error;
@@ -46,6 +47,53 @@ impl Group {
     pub const fn to_u8(self) -> u8 {
         self as u8
     }
+
+    /// Whether players in this group are staff, who can see vanished players
+    #[must_use]
+    pub const fn is_staff(self) -> bool {
+        matches!(self, Self::Moderator | Self::Admin)
+    }
+}
+
+/// Changes the group of a player and saves it immediately. If an [`AuditLog`] exists, the change
+/// is recorded with `actor` as the player who made it, or the console if `actor` is [`None`].
+///
+/// Moving a player into or out of [`Group::Banned`] is recorded as a ban or unban.
+pub fn set_group(
+    world: &mut World,
+    actor: Option<Entity>,
+    target: Entity,
+    group: Group,
+) -> anyhow::Result<()> {
+    let Some(&uuid) = world.get::<Uuid>(target) else {
+        anyhow::bail!("entity {target} does not have a Uuid");
+    };
+
+    let previous = world.get::<Group>(target).copied().unwrap_or_default();
+
+    world.resource::<PermissionStorage>().set(*uuid, group)?;
+    world.entity_mut(target).insert(group);
+
+    if previous == group {
+        return Ok(());
+    }
+
+    let Some(log) = world.get_resource::<AuditLog>() else {
+        return Ok(());
+    };
+
+    let action = match (previous, group) {
+        (_, Group::Banned) => AuditAction::Ban,
+        (Group::Banned, _) => AuditAction::Unban,
+        _ => AuditAction::GroupChange,
+    };
+
+    let actor = actor
+        .and_then(|actor| world.get::<Uuid>(actor))
+        .map(|uuid| **uuid);
+    let detail = format!("{previous:?} -> {group:?}");
+
+    log.record(AuditEntry::now(actor, action, Some(*uuid), detail))
 }
 
 fn load_permissions(
@@ -89,7 +137,42 @@ fn initialize_commands(
         error!("failed to initialize commands: player is missing ConnectionId");
         return;
     };
-    compose.unicast(&cmd_pkt, connection_id).unwrap();
+    compose
+        .unicast(&cmd_pkt, connection_id)
+        .unwrap_or_disconnected();
+}
+
+/// Lets staff see vanished players, see [`SeesVanished`]
+fn update_sees_vanished(
+    new_group: On<'_, '_, Insert, Group>,
+    query: Query<'_, '_, &Group>,
+    mut commands: Commands<'_, '_>,
+) {
+    let Ok(group) = query.get(new_group.entity) else {
+        return;
+    };
+
+    let mut player = commands.entity(new_group.entity);
+    if group.is_staff() {
+        player.insert(SeesVanished);
+    } else {
+        player.remove::<SeesVanished>();
+    }
+}
+
+/// Lets region flags allow actions per group, see [`RegionGroup`]
+fn update_region_group(
+    new_group: On<'_, '_, Insert, Group>,
+    query: Query<'_, '_, &Group>,
+    mut commands: Commands<'_, '_>,
+) {
+    let Ok(group) = query.get(new_group.entity) else {
+        return;
+    };
+
+    commands
+        .entity(new_group.entity)
+        .insert(RegionGroup(group.to_u8()));
 }
 
 impl Plugin for PermissionPlugin {
@@ -99,5 +182,49 @@ impl Plugin for PermissionPlugin {
         app.add_observer(load_permissions);
         app.add_observer(store_permissions);
         app.add_observer(initialize_commands);
+        app.add_observer(update_sees_vanished);
+        app.add_observer(update_region_group);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::IVec3;
+    use hyperion::{
+        simulation::{
+            region::{ProtectedRegions, RegionFlag},
+            world::WorldId,
+        },
+        storage::{GroupSet, Region},
+    };
+
+    use super::*;
+
+    #[test]
+    fn only_admins_may_build_in_the_lobby() {
+        let mut world = World::new();
+        world.add_observer(update_region_group);
+
+        let mut lobby = Region::new(IVec3::splat(-10), IVec3::splat(10));
+        lobby.flags.build = Some(GroupSet::NONE.with(Group::Admin.to_u8()));
+        let mut regions = ProtectedRegions::default();
+        regions.define("lobby", lobby).unwrap();
+
+        let normal = world.spawn(Group::Normal).id();
+        let admin = world.spawn(Group::Admin).id();
+        world.flush();
+
+        let may_build = |world: &World, player| {
+            let group = *world.get::<RegionGroup>(player).unwrap();
+            regions.allows(RegionFlag::Build, group, WorldId::PRIMARY, IVec3::ZERO)
+        };
+
+        assert!(!may_build(&world, normal));
+        assert!(may_build(&world, admin));
+
+        // Changing the group takes effect immediately
+        world.entity_mut(normal).insert(Group::Admin);
+        world.flush();
+        assert!(may_build(&world, normal));
     }
 }
@@ -1,34 +1,28 @@
 use bevy_ecs::resource::Resource;
-use heed::{Database, Env, byteorder::NativeEndian, types};
+use heed::{Database, byteorder::NativeEndian, types};
 use hyperion::storage::LocalDb;
 
 use crate::Group;
 
 #[derive(Resource)]
 pub struct PermissionStorage {
-    env: Env,
+    db: LocalDb,
     perms: Database<types::U128<NativeEndian>, types::U8>,
 }
 
 impl PermissionStorage {
     pub fn new(db: &LocalDb) -> anyhow::Result<Self> {
-        // We open the default unnamed database
-        let perms = {
-            let mut wtxn = db.write_txn()?;
-            let db = db.create_database(&mut wtxn, Some("uuid-to-perms"))?;
-            wtxn.commit()?;
-            db
-        };
+        let perms = db.write(|wtxn| db.create_database(wtxn, Some("uuid-to-perms")))?;
 
         Ok(Self {
-            env: (**db).clone(),
+            db: db.clone(),
             perms,
         })
     }
 
     pub fn get(&self, uuid: uuid::Uuid) -> Group {
         let uuid = uuid.as_u128();
-        let rtxn = self.env.read_txn().unwrap();
+        let rtxn = self.db.read_txn().unwrap();
         let Some(perms) = self.perms.get(&rtxn, &uuid).unwrap() else {
             return Group::default();
         };
@@ -43,9 +37,7 @@ impl PermissionStorage {
 
     pub fn set(&self, uuid: uuid::Uuid, group: Group) -> anyhow::Result<()> {
         let uuid = uuid.as_u128();
-        let mut wtxn = self.env.write_txn()?;
-        self.perms.put(&mut wtxn, &uuid, &group.to_u8())?;
-        wtxn.commit()?;
-        Ok(())
+        self.db
+            .write(|wtxn| self.perms.put(wtxn, &uuid, &group.to_u8()))
     }
 }
@@ -0,0 +1,239 @@
+//! The first message sent in each direction of a connection between a server and a proxy.
+//!
+//! Unlike every other message, a [`Hello`] is not encoded with rkyv and its layout never changes,
+//! so a server and a proxy built from different commits can always tell which protocol the other
+//! side speaks instead of failing to decode messages later on.
+
+use std::fmt;
+
+/// The version of the proxy protocol. This must be incremented whenever a message changes in a
+/// way that the other side cannot decode, such as adding a field or a variant.
+pub const PROTOCOL_VERSION: u32 = 4;
+
+/// Marks the start of a [`Hello`]
+const MAGIC: [u8; 4] = *b"HYPX";
+
+/// The length of a [`Hello`] without the crate version
+const HEADER_LEN: usize = MAGIC.len() + size_of::<u32>() + size_of::<u64>() + size_of::<u16>();
+
+/// Optional capabilities of a server or proxy. Messages which belong to a feature are only sent
+/// if both sides support it, so a newer server keeps working with a slightly older proxy.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Features(u64);
+
+impl Features {
+    /// Every feature supported by this version
+    pub const ALL: Self = Self(Self::BACKLOG_REPORTS.0 | Self::RESUBSCRIBE_CHANNELS.0);
+    /// The proxy sends [`StreamBacklog`](crate::StreamBacklog) messages
+    pub const BACKLOG_REPORTS: Self = Self(1 << 0);
+    pub const NONE: Self = Self(0);
+    /// The server sends [`ResubscribeChannels`](crate::ResubscribeChannels) messages
+    pub const RESUBSCRIBE_CHANNELS: Self = Self(1 << 1);
+
+    /// Creates features from their bits. Bits of features unknown to this version are kept, so
+    /// they can be logged.
+    #[must_use]
+    pub const fn from_bits(bits: u64) -> Self {
+        Self(bits)
+    }
+
+    #[must_use]
+    pub const fn bits(self) -> u64 {
+        self.0
+    }
+
+    /// Whether every feature in `other` is also in `self`
+    #[must_use]
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// The features in both `self` and `other`
+    #[must_use]
+    pub const fn intersection(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+}
+
+/// The side of a connection between a server and a proxy
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Side {
+    Server,
+    Proxy,
+}
+
+impl Side {
+    /// The side on the other end of the connection
+    #[must_use]
+    pub const fn other(self) -> Self {
+        match self {
+            Self::Server => Self::Proxy,
+            Self::Proxy => Self::Server,
+        }
+    }
+}
+
+impl fmt::Display for Side {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Server => f.write_str("server"),
+            Self::Proxy => f.write_str("proxy"),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum HelloError {
+    #[error(
+        "the first message is not a hello, so the other side speaks a proxy protocol from before \
+         version negotiation was added; rebuild both sides from the same commit"
+    )]
+    NotAHello,
+    #[error("the hello is truncated")]
+    Truncated,
+    #[error(
+        "proxy protocol mismatch: {local} speaks proxy-proto {local_version} ({local_crate}), \
+         {remote} speaks proxy-proto {remote_version} ({remote_crate}); rebuild both sides from \
+         the same commit"
+    )]
+    VersionMismatch {
+        local: Side,
+        remote: Side,
+        local_version: u32,
+        remote_version: u32,
+        local_crate: String,
+        remote_crate: String,
+    },
+}
+
+/// The first message sent in each direction of a connection between a server and a proxy
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Hello {
+    pub protocol_version: u32,
+    /// The version of the crate the sender was built with
+    pub crate_version: String,
+    pub features: Features,
+}
+
+impl Hello {
+    /// The hello of this version, which supports every feature
+    #[must_use]
+    pub fn current() -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            crate_version: env!("CARGO_PKG_VERSION").to_owned(),
+            features: Features::ALL,
+        }
+    }
+
+    /// Encodes the hello without a length prefix
+    #[must_use]
+    pub fn encode(&self) -> Vec<u8> {
+        // Crate versions are far shorter than the maximum length
+        let crate_version = self.crate_version.as_bytes();
+        let crate_version_len = u16::try_from(crate_version.len()).unwrap_or(u16::MAX);
+        let crate_version = crate_version
+            .get(..usize::from(crate_version_len))
+            .unwrap_or_default();
+
+        let mut bytes = Vec::with_capacity(HEADER_LEN + crate_version.len());
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&self.protocol_version.to_be_bytes());
+        bytes.extend_from_slice(&self.features.bits().to_be_bytes());
+        bytes.extend_from_slice(&crate_version_len.to_be_bytes());
+        bytes.extend_from_slice(crate_version);
+        bytes
+    }
+
+    /// Decodes a hello. Bytes after the hello are ignored, so that later versions may append
+    /// fields.
+    ///
+    /// # Errors
+    /// If `bytes` does not start with a hello
+    pub fn decode(bytes: &[u8]) -> Result<Self, HelloError> {
+        let rest = bytes.strip_prefix(&MAGIC).ok_or(HelloError::NotAHello)?;
+        let (protocol_version, rest) =
+            rest.split_first_chunk::<4>().ok_or(HelloError::Truncated)?;
+        let (features, rest) = rest.split_first_chunk::<8>().ok_or(HelloError::Truncated)?;
+        let (crate_version_len, rest) =
+            rest.split_first_chunk::<2>().ok_or(HelloError::Truncated)?;
+        let crate_version = rest
+            .get(..usize::from(u16::from_be_bytes(*crate_version_len)))
+            .ok_or(HelloError::Truncated)?;
+
+        Ok(Self {
+            protocol_version: u32::from_be_bytes(*protocol_version),
+            crate_version: String::from_utf8_lossy(crate_version).into_owned(),
+            features: Features::from_bits(u64::from_be_bytes(*features)),
+        })
+    }
+
+    /// Checks that `remote`, the hello received from the other side, speaks the same protocol as
+    /// this hello sent by `local`, and returns the features both sides support
+    ///
+    /// # Errors
+    /// If the protocol versions differ
+    pub fn negotiate(&self, local: Side, remote: &Self) -> Result<Features, HelloError> {
+        if self.protocol_version != remote.protocol_version {
+            return Err(HelloError::VersionMismatch {
+                local,
+                remote: local.other(),
+                local_version: self.protocol_version,
+                remote_version: remote.protocol_version,
+                local_crate: self.crate_version.clone(),
+                remote_crate: remote.crate_version.clone(),
+            });
+        }
+
+        Ok(self.features.intersection(remote.features))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hellos_round_trip() {
+        let hello = Hello::current();
+        let mut bytes = hello.encode();
+        assert_eq!(Hello::decode(&bytes), Ok(hello.clone()));
+
+        // Later versions may append fields
+        bytes.extend_from_slice(&[1, 2, 3]);
+        assert_eq!(Hello::decode(&bytes), Ok(hello));
+
+        assert_eq!(Hello::decode(&bytes[..10]), Err(HelloError::Truncated));
+        assert_eq!(Hello::decode(&[0; 32]), Err(HelloError::NotAHello));
+    }
+
+    #[test]
+    fn mismatched_versions_are_refused() {
+        let server = Hello {
+            protocol_version: 7,
+            crate_version: "0.2.0".to_owned(),
+            features: Features::ALL,
+        };
+        let proxy = Hello {
+            protocol_version: 6,
+            crate_version: "0.1.0".to_owned(),
+            features: Features::BACKLOG_REPORTS,
+        };
+
+        let error = server.negotiate(Side::Server, &proxy).unwrap_err();
+        assert!(
+            error.to_string().contains(
+                "server speaks proxy-proto 7 (0.2.0), proxy speaks proxy-proto 6 (0.1.0)"
+            )
+        );
+
+        let proxy = Hello {
+            protocol_version: 7,
+            ..proxy
+        };
+        assert_eq!(
+            server.negotiate(Side::Server, &proxy),
+            Ok(Features::BACKLOG_REPORTS)
+        );
+    }
+}
@@ -5,10 +5,12 @@
     hidden_glob_reexports
 )]
 
+mod hello;
 mod proxy_to_server;
 mod server_to_proxy;
 mod shared;
 
+pub use hello::*;
 pub use proxy_to_server::*;
 pub use server_to_proxy::*;
 pub use shared::*;
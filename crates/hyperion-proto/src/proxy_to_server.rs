@@ -1,3 +1,5 @@
+use std::net::IpAddr;
+
 use rkyv::{Archive, Deserialize, Serialize, with::InlineAsBox};
 
 #[derive(Archive, Deserialize, Serialize, Clone, PartialEq, Debug)]
@@ -11,6 +13,9 @@ pub struct PlayerPackets<'a> {
 #[derive(Archive, Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
 pub struct PlayerConnect {
     pub stream: u64,
+    /// The IP address the player connected from, or [`None`] if the player did not connect over
+    /// IP, such as through a Unix socket
+    pub ip: Option<IpAddr>,
 }
 
 #[derive(Archive, Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
@@ -35,10 +40,19 @@ pub struct RequestSubscribeChannelPackets<'a> {
     pub channels: &'a [u32],
 }
 
+/// Sent periodically for each stream whose backlog changed since it was last reported
+#[derive(Archive, Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+pub struct StreamBacklog {
+    pub stream: u64,
+    /// The number of bytes queued for the stream that have not been written to it yet
+    pub bytes: u64,
+}
+
 #[derive(Archive, Deserialize, Serialize, Clone, PartialEq, Debug)]
 pub enum ProxyToServerMessage<'a> {
     PlayerConnect(PlayerConnect),
     PlayerDisconnect(PlayerDisconnect<'a>),
     PlayerPackets(PlayerPackets<'a>),
     RequestSubscribeChannelPackets(RequestSubscribeChannelPackets<'a>),
+    StreamBacklog(StreamBacklog),
 }
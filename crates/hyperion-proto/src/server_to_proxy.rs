@@ -22,6 +22,11 @@ pub struct AddChannel<'a> {
 pub struct UpdateChannelPosition {
     pub channel_id: u32,
     pub position: ChunkPosition,
+    /// Maximum chunk distance between the channel and a player for that player to be subscribed
+    pub radius: i16,
+    /// Whether only streams which see hidden channels are subscribed, see
+    /// [`SetSeeHiddenChannels`]
+    pub hidden: bool,
 }
 
 #[derive(Archive, Deserialize, Serialize, Clone, PartialEq)]
@@ -51,9 +56,34 @@ pub struct SetReceiveBroadcasts {
     pub stream: u64,
 }
 
+/// The streams that do not receive a broadcast
+#[derive(Archive, Deserialize, Serialize, Clone, PartialEq, Eq, Default, Debug)]
+#[rkyv(derive(Debug))]
+pub struct Exclude {
+    /// The first excluded stream, or 0 if no stream is excluded
+    pub first: u64,
+    /// The other excluded streams. This is empty unless more than one stream is excluded.
+    pub rest: Vec<u64>,
+}
+
+impl ArchivedExclude {
+    /// Whether `stream` is excluded
+    #[must_use]
+    pub fn contains(&self, stream: u64) -> bool {
+        u64::from(self.first) == stream
+            || self
+                .rest
+                .iter()
+                .any(|&excluded| u64::from(excluded) == stream)
+    }
+}
+
 #[derive(Archive, Deserialize, Serialize, Clone, PartialEq)]
 pub struct BroadcastGlobal<'a> {
-    pub exclude: u64,
+    pub exclude: Exclude,
+
+    /// If set, only players in this world receive the broadcast
+    pub world: Option<u16>,
 
     #[rkyv(with = InlineAsBox)]
     pub data: &'a [u8],
@@ -62,7 +92,7 @@ pub struct BroadcastGlobal<'a> {
 #[derive(Archive, Deserialize, Serialize, Clone, PartialEq)]
 pub struct BroadcastLocal<'a> {
     pub center: ChunkPosition,
-    pub exclude: u64,
+    pub exclude: Exclude,
 
     #[rkyv(with = InlineAsBox)]
     pub data: &'a [u8],
@@ -71,7 +101,7 @@ pub struct BroadcastLocal<'a> {
 #[derive(Archive, Deserialize, Serialize, Clone, PartialEq)]
 pub struct BroadcastChannel<'a> {
     pub channel_id: u32,
-    pub exclude: u64,
+    pub exclude: Exclude,
 
     #[rkyv(with = InlineAsBox)]
     pub data: &'a [u8],
@@ -85,6 +115,19 @@ pub struct Unicast<'a> {
     pub data: &'a [u8],
 }
 
+/// Sends the same data to several streams. Unlike the broadcasts, the streams are chosen by the
+/// server, so they receive the data even if they do not receive broadcasts yet.
+#[derive(Archive, Deserialize, Serialize, Clone, PartialEq)]
+pub struct Multicast<'a> {
+    pub streams: Vec<u64>,
+
+    /// If set, only streams of players in this world receive the data
+    pub world: Option<u16>,
+
+    #[rkyv(with = InlineAsBox)]
+    pub data: &'a [u8],
+}
+
 /// The server must be prepared to handle other additional packets with this stream from the proxy after the server
 /// sends [`Shutdown`] until the server receives [`crate::PlayerDisconnect`] because proxy to server packets may
 /// already be in transit.
@@ -93,6 +136,35 @@ pub struct Shutdown {
     pub stream: u64,
 }
 
+/// Unsubscribes the stream from every channel it is subscribed to, so that the client forgets
+/// every entity it was sent. The stream is subscribed again to the channels in range on the next
+/// [`UpdateChannelPositions`].
+#[derive(Archive, Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+pub struct ResubscribeChannels {
+    pub stream: u64,
+}
+
+/// Sets whether the stream is subscribed to the hidden channels in range. Streams do not see
+/// hidden channels until this is sent.
+#[derive(Archive, Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+pub struct SetSeeHiddenChannels {
+    pub stream: u64,
+    pub see: bool,
+}
+
+/// Encrypts everything the proxy sends to the stream and decrypts everything it receives from it
+/// with AES/CFB8, using `key` as both the key and the initialization vector like vanilla
+/// Minecraft.
+///
+/// The server sends this after receiving the encryption response of the client and before sending
+/// the stream any other packet. The client does not send anything else until it receives a packet
+/// from the server, so neither direction has data in flight when encryption starts.
+#[derive(Archive, Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+pub struct SetEncryption {
+    pub stream: u64,
+    pub key: [u8; 16],
+}
+
 #[derive(Archive, Deserialize, Serialize, Clone, PartialEq)]
 pub enum ServerToProxyMessage<'a> {
     UpdatePlayerPositions(UpdatePlayerPositions),
@@ -104,6 +176,10 @@ pub enum ServerToProxyMessage<'a> {
     BroadcastLocal(BroadcastLocal<'a>),
     BroadcastChannel(BroadcastChannel<'a>),
     Unicast(Unicast<'a>),
+    Multicast(Multicast<'a>),
     SetReceiveBroadcasts(SetReceiveBroadcasts),
     Shutdown(Shutdown),
+    ResubscribeChannels(ResubscribeChannels),
+    SetSeeHiddenChannels(SetSeeHiddenChannels),
+    SetEncryption(SetEncryption),
 }
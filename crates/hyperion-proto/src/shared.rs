@@ -1,26 +1,32 @@
 use glam::I16Vec2;
 use rkyv::{Archive, Deserialize, Serialize};
 
+/// A chunk position within a world. Positions in different worlds never overlap, so the proxy
+/// only considers players and channels with the same `world` to be near each other.
 #[derive(Archive, Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
 #[rkyv(derive(Debug))]
 pub struct ChunkPosition {
     pub x: i16,
     pub z: i16,
+    pub world: u16,
 }
 
 impl ChunkPosition {
+    /// Creates a chunk position in the primary world (world `0`)
     #[must_use]
     pub const fn new(x: i16, z: i16) -> Self {
-        Self { x, z }
+        Self { x, z, world: 0 }
+    }
+
+    #[must_use]
+    pub const fn with_world(self, world: u16) -> Self {
+        Self { world, ..self }
     }
 }
 
 impl From<I16Vec2> for ChunkPosition {
     fn from(value: I16Vec2) -> Self {
-        Self {
-            x: value.x,
-            z: value.y,
-        }
+        Self::new(value.x, value.y)
     }
 }
 
@@ -7,7 +7,7 @@ use hyperion_proto::ArchivedServerToProxyMessage;
 use rustc_hash::FxHashMap;
 use tracing::{debug, error};
 
-use crate::egress::Egress;
+use crate::{data::PlayerHandle, egress::Egress};
 
 /// Maximum chunk distance between a packet's center and a player for a local broadcast to be sent to
 /// that player
@@ -40,12 +40,33 @@ pub struct Channel {
     /// List of connection ids that are currently subscribed to this channel
     subscribed_connections: HashSet<u64>,
 
+    /// Whether only streams which see hidden channels are subscribed to this channel, as of the
+    /// last position update
+    hidden: bool,
+
     unsubscribe_packets: Bytes,
 }
 
 #[derive(Default)]
 pub struct ChannelManager {
     channels: FxHashMap<u32, Channel>,
+
+    /// The channels each connection is subscribed to, which are the entities the client was sent
+    subscriptions: FxHashMap<u64, HashSet<u32>>,
+}
+
+/// Forgets that `stream` is subscribed to `channel_id` in `subscriptions`
+fn remove_subscription(
+    subscriptions: &mut FxHashMap<u64, HashSet<u32>>,
+    stream: u64,
+    channel_id: u32,
+) {
+    if let Some(channels) = subscriptions.get_mut(&stream) {
+        channels.remove(&channel_id);
+        if channels.is_empty() {
+            subscriptions.remove(&stream);
+        }
+    }
 }
 
 /// Buffers egress operations for optimized processing.
@@ -54,7 +75,10 @@ pub struct BufferedEgress {
     channel_manager: ChannelManager,
     /// Reference to the underlying egress handler.
     egress: Egress,
-    player_bvh: Bvh<Vec<u64>>,
+    /// Player positions, partitioned by world
+    player_bvh: FxHashMap<u16, Bvh<Vec<u64>>>,
+    /// The world of each player as of the last position update
+    player_worlds: FxHashMap<u64, u16>,
 }
 
 impl BufferedEgress {
@@ -64,7 +88,8 @@ impl BufferedEgress {
         Self {
             channel_manager: ChannelManager::default(),
             egress,
-            player_bvh: Bvh::default(),
+            player_bvh: FxHashMap::default(),
+            player_worlds: FxHashMap::default(),
         }
     }
 
@@ -74,20 +99,26 @@ impl BufferedEgress {
     pub fn handle_packet(&mut self, message: &ArchivedServerToProxyMessage<'_>) {
         match message {
             ArchivedServerToProxyMessage::UpdatePlayerPositions(packet) => {
-                let mut players = Vec::with_capacity(packet.stream.len());
+                let mut players = FxHashMap::<u16, Vec<Player>>::default();
+                self.player_worlds.clear();
 
                 for (stream, position) in packet.stream.iter().zip(packet.positions.iter()) {
                     let Ok(stream) = rkyv::deserialize::<u64, std::convert::Infallible>(stream);
                     let Ok(position) = rkyv::deserialize::<_, std::convert::Infallible>(position);
+                    let world = position.world;
                     let position = I16Vec2::from(position);
 
-                    players.push(Player {
+                    self.player_worlds.insert(stream, world);
+                    players.entry(world).or_default().push(Player {
                         stream,
                         chunk_position: position,
                     });
                 }
 
-                self.player_bvh = Bvh::build(&mut players, ());
+                self.player_bvh = players
+                    .into_iter()
+                    .map(|(world, mut players)| (world, Bvh::build(&mut players, ())))
+                    .collect();
             }
             ArchivedServerToProxyMessage::AddChannel(packet) => {
                 let unsubscribe_packets = match rkyv::deserialize::<_, rkyv::rancor::Error>(
@@ -109,6 +140,7 @@ impl BufferedEgress {
                         .insert(packet.channel_id.into(), Channel {
                             pending_connections: HashSet::new(),
                             subscribed_connections: HashSet::new(),
+                            hidden: false,
                             unsubscribe_packets: Bytes::from(unsubscribe_packets),
                         });
 
@@ -134,19 +166,30 @@ impl BufferedEgress {
 
                     let Ok(channel_position) =
                         rkyv::deserialize::<_, std::convert::Infallible>(&update.position);
+                    let world = channel_position.world;
                     let channel_position = I16Vec2::from(channel_position);
+                    let radius: i16 = update.radius.into();
+                    let hidden = update.hidden;
+                    channel.hidden = hidden;
 
-                    let min = channel_position - I16Vec2::splat(RADIUS);
-                    let max = channel_position + I16Vec2::splat(RADIUS);
+                    let min = channel_position.saturating_sub(I16Vec2::splat(radius));
+                    let max = channel_position.saturating_add(I16Vec2::splat(radius));
 
                     let aabb = Aabb::new(min, max);
 
-                    let slices = self.player_bvh.get_in(aabb);
-
                     let mut should_remain_subscribed = HashSet::new();
+                    let mut should_remain_pending = HashSet::new();
+
+                    // If no players are in the channel's world, every subscriber is unsubscribed
+                    let slices = self.player_bvh.get(&world).into_iter().flat_map(|player_bvh| {
+                        player_bvh
+                            .get_in(aabb)
+                            .into_iter()
+                            .map(move |slice| (player_bvh, slice))
+                    });
 
-                    for slice in slices {
-                        let (_, streams) = self.player_bvh.inner();
+                    for (player_bvh, slice) in slices {
+                        let (_, streams) = player_bvh.inner();
 
                         let start = slice.start as usize;
                         let end = slice.end as usize;
@@ -162,6 +205,12 @@ impl BufferedEgress {
                                 continue;
                             }
 
+                            // Subscribed and pending streams which cannot see the channel
+                            // anymore are unsubscribed below
+                            if hidden && !player.can_see_hidden_channels() {
+                                continue;
+                            }
+
                             // This stream should be subscribed to this channel...
                             if channel.subscribed_connections.contains(&stream) {
                                 // ... and should remain subscribed to this channel
@@ -173,10 +222,17 @@ impl BufferedEgress {
                                     requested_subscriptions.push(channel_id);
                                 }
                                 channel.pending_connections.insert(stream);
+                                should_remain_pending.insert(stream);
                             }
                         }
                     }
 
+                    // Streams which are still waiting for the subscribe packets are not sent any
+                    // unsubscribe packets
+                    channel
+                        .pending_connections
+                        .retain(|stream| should_remain_pending.contains(stream));
+
                     channel.subscribed_connections.retain(|stream| {
                         let should_remain = should_remain_subscribed.contains(stream);
 
@@ -185,6 +241,11 @@ impl BufferedEgress {
                             // packets and removed from the subscribed connections set
                             self.egress
                                 .unicast(*stream, channel.unsubscribe_packets.clone());
+                            remove_subscription(
+                                &mut self.channel_manager.subscriptions,
+                                *stream,
+                                channel_id,
+                            );
                             debug!("unsubscribing player {stream} from channel {channel_id}");
                         }
 
@@ -210,12 +271,9 @@ impl BufferedEgress {
                 }
             }
             ArchivedServerToProxyMessage::RemoveChannel(packet) => {
-                debug!("removing channel {}", u32::from(packet.channel_id));
-                let Some(channel) = self
-                    .channel_manager
-                    .channels
-                    .remove(&packet.channel_id.into())
-                else {
+                let channel_id: u32 = packet.channel_id.into();
+                debug!("removing channel {channel_id}");
+                let Some(channel) = self.channel_manager.channels.remove(&channel_id) else {
                     error!("server sent RemoveChannel for a channel that does not exist");
                     return;
                 };
@@ -224,6 +282,7 @@ impl BufferedEgress {
                 for &stream in &channel.subscribed_connections {
                     self.egress
                         .unicast(stream, channel.unsubscribe_packets.clone());
+                    remove_subscription(&mut self.channel_manager.subscriptions, stream, channel_id);
                 }
             }
             ArchivedServerToProxyMessage::SubscribeChannelPackets(packet) => {
@@ -236,6 +295,16 @@ impl BufferedEgress {
                     return;
                 };
 
+                // Streams could have stopped seeing hidden channels since they started waiting
+                if channel.hidden {
+                    let players = self.egress.player_registry.pin();
+                    channel.pending_connections.retain(|stream| {
+                        players
+                            .get(stream)
+                            .is_some_and(PlayerHandle::can_see_hidden_channels)
+                    });
+                }
+
                 for &stream in &channel.pending_connections {
                     if stream == exclude {
                         continue;
@@ -245,6 +314,14 @@ impl BufferedEgress {
                     self.egress.unicast(stream, data.clone());
                 }
 
+                for &stream in &channel.pending_connections {
+                    self.channel_manager
+                        .subscriptions
+                        .entry(stream)
+                        .or_default()
+                        .insert(channel_id);
+                }
+
                 channel
                     .subscribed_connections
                     .extend(channel.pending_connections.iter().copied());
@@ -253,13 +330,17 @@ impl BufferedEgress {
             ArchivedServerToProxyMessage::BroadcastGlobal(packet) => {
                 let data =
                     Bytes::from(rkyv::deserialize::<_, rkyv::rancor::Error>(&packet.data).unwrap());
-                let Ok(exclude) =
-                    rkyv::deserialize::<u64, std::convert::Infallible>(&packet.exclude);
+                let Ok(world) =
+                    rkyv::deserialize::<Option<u16>, std::convert::Infallible>(&packet.world);
 
                 let players = self.egress.player_registry.pin_owned();
 
                 for (&stream, player) in &players {
-                    if !player.can_receive_broadcasts() || stream == exclude {
+                    if !player.can_receive_broadcasts() || packet.exclude.contains(stream) {
+                        continue;
+                    }
+
+                    if world.is_some() && self.player_worlds.get(&stream).copied() != world {
                         continue;
                     }
 
@@ -271,8 +352,8 @@ impl BufferedEgress {
                     rkyv::deserialize::<i16, std::convert::Infallible>(&packet.center.x);
                 let Ok(center_z) =
                     rkyv::deserialize::<i16, std::convert::Infallible>(&packet.center.z);
-                let Ok(player_id_to_exclude) =
-                    rkyv::deserialize::<u64, std::convert::Infallible>(&packet.exclude);
+                let Ok(world) =
+                    rkyv::deserialize::<u16, std::convert::Infallible>(&packet.center.world);
                 let data =
                     Bytes::from(rkyv::deserialize::<_, rkyv::rancor::Error>(&packet.data).unwrap());
 
@@ -282,17 +363,21 @@ impl BufferedEgress {
 
                 let aabb = Aabb::new(min, max);
 
-                let slices = self.player_bvh.get_in(aabb);
+                let Some(player_bvh) = self.player_bvh.get(&world) else {
+                    return;
+                };
+
+                let slices = player_bvh.get_in(aabb);
 
                 for slice in slices {
-                    let (_, streams) = self.player_bvh.inner();
+                    let (_, streams) = player_bvh.inner();
 
                     let start = slice.start as usize;
                     let end = slice.end as usize;
 
                     let streams = &streams[start..end];
                     for &stream in streams {
-                        if stream == player_id_to_exclude {
+                        if packet.exclude.contains(stream) {
                             continue;
                         }
 
@@ -301,7 +386,6 @@ impl BufferedEgress {
                 }
             }
             ArchivedServerToProxyMessage::BroadcastChannel(packet) => {
-                let exclude = u64::from(packet.exclude);
                 let data =
                     Bytes::from(rkyv::deserialize::<_, rkyv::rancor::Error>(&packet.data).unwrap());
 
@@ -312,7 +396,7 @@ impl BufferedEgress {
                 };
 
                 for &stream in &channel.subscribed_connections {
-                    if stream == exclude {
+                    if packet.exclude.contains(stream) {
                         continue;
                     }
 
@@ -324,12 +408,318 @@ impl BufferedEgress {
                 self.egress
                     .unicast(unicast.stream.into(), Bytes::from(data));
             }
+            ArchivedServerToProxyMessage::Multicast(packet) => {
+                let data =
+                    Bytes::from(rkyv::deserialize::<_, rkyv::rancor::Error>(&packet.data).unwrap());
+                let Ok(world) =
+                    rkyv::deserialize::<Option<u16>, std::convert::Infallible>(&packet.world);
+
+                for &stream in packet.streams.iter() {
+                    let stream = u64::from(stream);
+
+                    if world.is_some() && self.player_worlds.get(&stream).copied() != world {
+                        continue;
+                    }
+
+                    self.egress.unicast(stream, data.clone());
+                }
+            }
             ArchivedServerToProxyMessage::SetReceiveBroadcasts(pkt) => {
                 self.egress.handle_set_receive_broadcasts(pkt);
             }
             ArchivedServerToProxyMessage::Shutdown(pkt) => {
                 self.egress.handle_shutdown(pkt);
             }
+            ArchivedServerToProxyMessage::ResubscribeChannels(pkt) => {
+                let stream: u64 = pkt.stream.into();
+                let Some(channel_ids) = self.channel_manager.subscriptions.remove(&stream) else {
+                    return;
+                };
+
+                for channel_id in channel_ids {
+                    let Some(channel) = self.channel_manager.channels.get_mut(&channel_id) else {
+                        continue;
+                    };
+
+                    // The next channel position update subscribes the player again
+                    channel.subscribed_connections.remove(&stream);
+                    self.egress
+                        .unicast(stream, channel.unsubscribe_packets.clone());
+                }
+
+                debug!("resubscribing player {stream} to its channels");
+            }
+            ArchivedServerToProxyMessage::SetSeeHiddenChannels(pkt) => {
+                // The next channel position update subscribes or unsubscribes the player
+                self.egress.handle_set_see_hidden_channels(pkt);
+            }
+            ArchivedServerToProxyMessage::SetEncryption(pkt) => {
+                self.egress.handle_set_encryption(pkt);
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use hyperion_proto::{
+        AddChannel, ChunkPosition, Multicast, ServerToProxyMessage, SubscribeChannelPackets,
+        UpdateChannelPosition, UpdateChannelPositions, UpdatePlayerPositions,
+    };
+    use rkyv::util::AlignedVec;
+    use rustc_hash::FxBuildHasher;
+
+    use super::*;
+
+    const CHANNEL: u32 = 1;
+    const STAFF: u64 = 1;
+    const PLAYER: u64 = 2;
+
+    fn handle(egress: &mut BufferedEgress, message: &ServerToProxyMessage<'_>) {
+        let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(message).unwrap();
+        let mut aligned = AlignedVec::<16>::new();
+        aligned.extend_from_slice(&bytes);
+        // SAFETY: the message was just encoded
+        let message =
+            unsafe { rkyv::access_unchecked::<ArchivedServerToProxyMessage<'_>>(&aligned) };
+        egress.handle_packet(message);
+    }
+
+    fn update_channel(egress: &mut BufferedEgress, hidden: bool) {
+        let updates = [UpdateChannelPosition {
+            channel_id: CHANNEL,
+            position: ChunkPosition::new(0, 0),
+            radius: 2,
+            hidden,
+        }];
+        handle(
+            egress,
+            &ServerToProxyMessage::UpdateChannelPositions(UpdateChannelPositions {
+                updates: &updates,
+            }),
+        );
+    }
+
+    fn subscribe_packets(egress: &mut BufferedEgress) {
+        handle(
+            egress,
+            &ServerToProxyMessage::SubscribeChannelPackets(SubscribeChannelPackets {
+                channel_id: CHANNEL,
+                exclude: 0,
+                data: b"subscribe",
+            }),
+        );
+    }
+
+    fn received(receiver: &kanal::AsyncReceiver<Bytes>) -> Vec<Bytes> {
+        std::iter::from_fn(|| receiver.try_recv().unwrap()).collect()
+    }
+
+    #[tokio::test]
+    async fn hidden_channels_are_only_sent_to_streams_which_see_them() {
+        let registry = Box::leak(Box::new(
+            papaya::HashMap::<u64, PlayerHandle, FxBuildHasher>::default(),
+        ));
+        let (server_sender, _server_receiver) = kanal::bounded_async(16);
+        let mut egress = BufferedEgress::new(Egress::new(registry, server_sender));
+
+        let (staff_writer, staff) = kanal::bounded_async(16);
+        let (player_writer, player) = kanal::bounded_async(16);
+        {
+            let players = registry.pin();
+            players.insert(STAFF, PlayerHandle::new(staff_writer));
+            players.insert(PLAYER, PlayerHandle::new(player_writer));
+            for (_, handle) in &players {
+                handle.enable_receive_broadcasts();
+            }
+        }
+        let set_see = |stream, see| {
+            registry
+                .pin()
+                .get(&stream)
+                .unwrap()
+                .set_see_hidden_channels(see);
+        };
+        set_see(STAFF, true);
+
+        handle(
+            &mut egress,
+            &ServerToProxyMessage::AddChannel(AddChannel {
+                channel_id: CHANNEL,
+                unsubscribe_packets: b"unsubscribe",
+            }),
+        );
+        handle(
+            &mut egress,
+            &ServerToProxyMessage::UpdatePlayerPositions(UpdatePlayerPositions {
+                stream: vec![STAFF, PLAYER],
+                positions: vec![ChunkPosition::new(0, 0); 2],
+            }),
+        );
+
+        update_channel(&mut egress, true);
+        subscribe_packets(&mut egress);
+        assert_eq!(received(&staff), [Bytes::from_static(b"subscribe")]);
+        assert!(received(&player).is_empty());
+
+        // The channel is hidden while the player is still waiting for its subscribe packets
+        update_channel(&mut egress, false);
+        update_channel(&mut egress, true);
+        subscribe_packets(&mut egress);
+        assert!(received(&staff).is_empty());
+        assert!(received(&player).is_empty());
+
+        // The player starts and stops seeing hidden channels while waiting for the subscribe
+        // packets
+        set_see(PLAYER, true);
+        update_channel(&mut egress, true);
+        set_see(PLAYER, false);
+        subscribe_packets(&mut egress);
+        assert!(received(&player).is_empty());
+
+        // Subscribed streams which stop seeing hidden channels are unsubscribed
+        set_see(STAFF, false);
+        update_channel(&mut egress, true);
+        assert_eq!(received(&staff), [Bytes::from_static(b"unsubscribe")]);
+        assert!(received(&player).is_empty());
+    }
+
+    #[tokio::test]
+    async fn multicasts_are_sent_to_the_listed_streams_in_the_world() {
+        const OTHER_WORLD: u64 = 3;
+
+        let registry = Box::leak(Box::new(
+            papaya::HashMap::<u64, PlayerHandle, FxBuildHasher>::default(),
+        ));
+        let (server_sender, _server_receiver) = kanal::bounded_async(16);
+        let mut egress = BufferedEgress::new(Egress::new(registry, server_sender));
+
+        let (staff_writer, staff) = kanal::bounded_async(16);
+        let (player_writer, player) = kanal::bounded_async(16);
+        let (other_writer, other) = kanal::bounded_async(16);
+        {
+            // The streams do not receive broadcasts, which multicasts do not depend on
+            let players = registry.pin();
+            players.insert(STAFF, PlayerHandle::new(staff_writer));
+            players.insert(PLAYER, PlayerHandle::new(player_writer));
+            players.insert(OTHER_WORLD, PlayerHandle::new(other_writer));
+        }
+
+        handle(
+            &mut egress,
+            &ServerToProxyMessage::UpdatePlayerPositions(UpdatePlayerPositions {
+                stream: vec![STAFF, PLAYER, OTHER_WORLD],
+                positions: vec![
+                    ChunkPosition::new(0, 0),
+                    ChunkPosition::new(0, 0),
+                    ChunkPosition::new(0, 0).with_world(1),
+                ],
+            }),
+        );
+
+        let multicast = |egress: &mut BufferedEgress, world| {
+            handle(
+                egress,
+                &ServerToProxyMessage::Multicast(Multicast {
+                    streams: vec![STAFF, OTHER_WORLD],
+                    world,
+                    data: b"data",
+                }),
+            );
+        };
+
+        multicast(&mut egress, None);
+        assert_eq!(received(&staff), [Bytes::from_static(b"data")]);
+        assert!(received(&player).is_empty());
+        assert_eq!(received(&other), [Bytes::from_static(b"data")]);
+
+        multicast(&mut egress, Some(0));
+        assert_eq!(received(&staff), [Bytes::from_static(b"data")]);
+        assert!(received(&player).is_empty());
+        assert!(received(&other).is_empty());
+    }
+
+    /// Subscribes one player in every chunk within 16 chunks of the origin to 500 item channels
+    /// in the origin chunk, and returns how many subscribe packets the players received
+    fn subscribe_packets_for_items(radius: i16) -> usize {
+        const ITEMS: u32 = 500;
+        const DISTANCE: i16 = 16;
+
+        let registry = Box::leak(Box::new(
+            papaya::HashMap::<u64, PlayerHandle, FxBuildHasher>::default(),
+        ));
+        let (server_sender, _server_receiver) = kanal::bounded_async(16);
+        let mut egress = BufferedEgress::new(Egress::new(registry, server_sender));
+
+        let mut streams = Vec::new();
+        let mut positions = Vec::new();
+        let mut receivers = Vec::new();
+        for x in -DISTANCE..=DISTANCE {
+            for z in -DISTANCE..=DISTANCE {
+                let stream = u64::try_from(streams.len()).unwrap() + 1;
+                let (writer, receiver) = kanal::bounded_async(ITEMS as usize);
+                let players = registry.pin();
+                players.insert(stream, PlayerHandle::new(writer));
+                players.get(&stream).unwrap().enable_receive_broadcasts();
+
+                streams.push(stream);
+                positions.push(ChunkPosition::new(x, z));
+                receivers.push(receiver);
+            }
+        }
+
+        handle(
+            &mut egress,
+            &ServerToProxyMessage::UpdatePlayerPositions(UpdatePlayerPositions {
+                stream: streams,
+                positions,
+            }),
+        );
+
+        let updates: Vec<_> = (1..=ITEMS)
+            .map(|channel_id| UpdateChannelPosition {
+                channel_id,
+                position: ChunkPosition::new(0, 0),
+                radius,
+                hidden: false,
+            })
+            .collect();
+        for channel_id in 1..=ITEMS {
+            handle(
+                &mut egress,
+                &ServerToProxyMessage::AddChannel(AddChannel {
+                    channel_id,
+                    unsubscribe_packets: b"unsubscribe",
+                }),
+            );
+        }
+        handle(
+            &mut egress,
+            &ServerToProxyMessage::UpdateChannelPositions(UpdateChannelPositions {
+                updates: &updates,
+            }),
+        );
+        for channel_id in 1..=ITEMS {
+            handle(
+                &mut egress,
+                &ServerToProxyMessage::SubscribeChannelPackets(SubscribeChannelPackets {
+                    channel_id,
+                    exclude: 0,
+                    data: b"spawn",
+                }),
+            );
+        }
+
+        receivers.iter().map(|receiver| received(receiver).len()).sum()
+    }
+
+    #[tokio::test]
+    async fn item_tracking_range_reduces_the_packets_sent() {
+        // Before per-entity tracking ranges, every channel used the radius of players
+        let before = subscribe_packets_for_items(16);
+        let after = subscribe_packets_for_items(6);
+
+        assert_eq!(before, 500 * 33 * 33);
+        assert_eq!(after, 500 * 13 * 13);
+    }
+}
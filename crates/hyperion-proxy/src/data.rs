@@ -1,4 +1,7 @@
-use std::sync::{atomic, atomic::AtomicBool};
+use std::sync::{
+    Arc, OnceLock, atomic,
+    atomic::{AtomicBool, AtomicU64},
+};
 
 use anyhow::bail;
 use bytes::Bytes;
@@ -26,17 +29,63 @@ pub struct PlayerHandle {
     /// they will get packets that it deems are invalid because the broadcasts are using the play
     /// state and play IDs.
     can_receive_broadcasts: AtomicBool,
+
+    /// Whether the player is subscribed to hidden channels, such as the ones of vanished players
+    can_see_hidden_channels: AtomicBool,
+
+    /// The number of bytes sent to the player that have not been written to its socket yet
+    backlog: Arc<AtomicU64>,
+
+    /// The backlog that was last reported to the server
+    reported_backlog: AtomicU64,
+
+    /// The encryption key of the connection, which is set once the server enables encryption
+    encryption: Arc<OnceLock<[u8; 16]>>,
 }
 
 impl PlayerHandle {
     #[must_use]
-    pub const fn new(writer: kanal::AsyncSender<Bytes>) -> Self {
+    pub fn new(writer: kanal::AsyncSender<Bytes>) -> Self {
         Self {
             writer,
             can_receive_broadcasts: AtomicBool::new(false),
+            can_see_hidden_channels: AtomicBool::new(false),
+            backlog: Arc::new(AtomicU64::new(0)),
+            reported_backlog: AtomicU64::new(0),
+            encryption: Arc::default(),
         }
     }
 
+    /// The encryption key of the player, which the reader and writer of the connection use once
+    /// it is set
+    #[must_use]
+    pub fn encryption(&self) -> Arc<OnceLock<[u8; 16]>> {
+        self.encryption.clone()
+    }
+
+    /// Enables encryption with `key`. Encryption cannot be changed once it is enabled.
+    pub fn enable_encryption(&self, key: [u8; 16]) -> anyhow::Result<()> {
+        if self.encryption.set(key).is_err() {
+            bail!("encryption is already enabled");
+        }
+        Ok(())
+    }
+
+    /// The counter for the backlog of the player, which the writer decreases once data is written
+    #[must_use]
+    pub fn backlog(&self) -> Arc<AtomicU64> {
+        self.backlog.clone()
+    }
+
+    /// Returns the current backlog if it changed since the last call
+    pub fn take_backlog_report(&self) -> Option<u64> {
+        let backlog = self.backlog.load(atomic::Ordering::Relaxed);
+        let reported = self
+            .reported_backlog
+            .swap(backlog, atomic::Ordering::Relaxed);
+        (backlog != reported).then_some(backlog)
+    }
+
     pub fn shutdown(&self) {
         // Ignore error for if the channel is already closed
         let _ = self.writer.close();
@@ -51,16 +100,32 @@ impl PlayerHandle {
         self.can_receive_broadcasts.load(atomic::Ordering::Relaxed)
     }
 
+    pub fn set_see_hidden_channels(&self, see: bool) {
+        self.can_see_hidden_channels
+            .store(see, atomic::Ordering::Relaxed);
+    }
+
+    pub fn can_see_hidden_channels(&self) -> bool {
+        self.can_see_hidden_channels.load(atomic::Ordering::Relaxed)
+    }
+
     pub fn send(&self, bytes: Bytes) -> anyhow::Result<()> {
+        let len = bytes.len() as u64;
+
+        // This is added before sending so that the writer never subtracts more than was added
+        self.backlog.fetch_add(len, atomic::Ordering::Relaxed);
+
         match self.writer.try_send(bytes) {
             Ok(true) => Ok(()),
 
             Ok(false) => {
+                self.backlog.fetch_sub(len, atomic::Ordering::Relaxed);
                 let is_full = self.writer.is_full();
                 self.shutdown();
                 bail!("failed to send packet to player, channel is full: {is_full}");
             }
             Err(e) => {
+                self.backlog.fetch_sub(len, atomic::Ordering::Relaxed);
                 self.shutdown();
                 bail!("failed to send packet to player: {e}");
             }
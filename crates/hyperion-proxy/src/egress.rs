@@ -1,5 +1,8 @@
 use bytes::Bytes;
-use hyperion_proto::{ArchivedSetReceiveBroadcasts, ArchivedShutdown};
+use hyperion_proto::{
+    ArchivedSetEncryption, ArchivedSetReceiveBroadcasts, ArchivedSetSeeHiddenChannels,
+    ArchivedShutdown,
+};
 use rustc_hash::FxBuildHasher;
 use tracing::{error, instrument, warn};
 
@@ -55,6 +58,38 @@ impl Egress {
         player.enable_receive_broadcasts();
     }
 
+    #[instrument(skip_all)]
+    pub fn handle_set_see_hidden_channels(&self, pkt: &ArchivedSetSeeHiddenChannels) {
+        let player_registry = self.player_registry;
+        let players = player_registry.pin();
+        let Ok(stream) = rkyv::deserialize::<u64, std::convert::Infallible>(&pkt.stream);
+
+        let Some(player) = players.get(&stream) else {
+            error!("Player not found for stream {stream:?}");
+            return;
+        };
+
+        player.set_see_hidden_channels(pkt.see);
+    }
+
+    #[instrument(skip_all)]
+    pub fn handle_set_encryption(&self, pkt: &ArchivedSetEncryption) {
+        let player_registry = self.player_registry;
+        let players = player_registry.pin();
+        let Ok(stream) = rkyv::deserialize::<u64, std::convert::Infallible>(&pkt.stream);
+
+        let Some(player) = players.get(&stream) else {
+            error!("Player not found for stream {stream:?}");
+            return;
+        };
+
+        // The client would not be able to read anything sent with the wrong key
+        if let Err(e) = player.enable_encryption(pkt.key) {
+            warn!("failed to enable encryption for stream {stream:?}: {e}");
+            player.shutdown();
+        }
+    }
+
     #[instrument(skip_all)]
     pub fn handle_shutdown(&self, pkt: &ArchivedShutdown) {
         let player_registry = self.player_registry;
@@ -0,0 +1,75 @@
+//! Encryption of player connections, see [`hyperion_proto::SetEncryption`].
+
+use openssl::symm::{Cipher, Crypter, Mode};
+
+/// One direction of an encrypted player connection. Minecraft uses AES/CFB8 with the shared
+/// secret as both the key and the initialization vector.
+pub struct StreamCipher {
+    crypter: Crypter,
+    buffer: Vec<u8>,
+}
+
+impl StreamCipher {
+    #[must_use]
+    pub fn encrypt(key: &[u8; 16]) -> Self {
+        Self::new(key, Mode::Encrypt)
+    }
+
+    #[must_use]
+    pub fn decrypt(key: &[u8; 16]) -> Self {
+        Self::new(key, Mode::Decrypt)
+    }
+
+    fn new(key: &[u8; 16], mode: Mode) -> Self {
+        let crypter = Crypter::new(Cipher::aes_128_cfb8(), mode, key, Some(key))
+            .expect("OpenSSL should support AES/CFB8");
+
+        Self {
+            crypter,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Encrypts or decrypts `data` in place. CFB8 works on single bytes, so data can be passed in
+    /// chunks of any size.
+    pub fn apply(&mut self, data: &mut [u8]) {
+        // OpenSSL requires room for one more block than the input, which is one byte for CFB8
+        self.buffer.resize(data.len() + 1, 0);
+
+        let len = self
+            .crypter
+            .update(data, &mut self.buffer)
+            .expect("AES/CFB8 should not fail on any input");
+        debug_assert_eq!(len, data.len());
+
+        data.copy_from_slice(&self.buffer[..len]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_round_trip() {
+        let key = [7; 16];
+        let message = b"the quick brown fox jumps over the lazy dog".to_vec();
+
+        let mut encrypted = message.clone();
+        let mut encrypt = StreamCipher::encrypt(&key);
+        let (first, second) = encrypted.split_at_mut(5);
+        encrypt.apply(first);
+        encrypt.apply(second);
+        assert_ne!(encrypted, message);
+
+        let mut whole = message.clone();
+        StreamCipher::encrypt(&key).apply(&mut whole);
+        assert_eq!(whole, encrypted);
+
+        let mut decrypt = StreamCipher::decrypt(&key);
+        let (first, second) = encrypted.split_at_mut(20);
+        decrypt.apply(first);
+        decrypt.apply(second);
+        assert_eq!(encrypted, message);
+    }
+}
@@ -10,16 +10,18 @@
     clippy::future_not_send
 )]
 
-use std::{fmt::Debug, path::Path, sync::Arc};
+use std::{fmt::Debug, net::IpAddr, path::Path, sync::Arc};
 
 use anyhow::Context;
 use colored::Colorize;
-use hyperion_proto::ArchivedServerToProxyMessage;
+use hyperion_proto::{
+    ArchivedServerToProxyMessage, Features, Hello, ProxyToServerMessage, Side, StreamBacklog,
+};
 use rustc_hash::FxBuildHasher;
-use rustls::{RootCertStore, client::ClientConfig};
+use rustls::{HandshakeKind, RootCertStore, client::ClientConfig};
 use rustls_pki_types::{CertificateDer, PrivateKeyDer, ServerName, pem::PemObject};
 use tokio::{
-    io::{AsyncRead, AsyncReadExt, BufReader},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
     net::{TcpStream, ToSocketAddrs},
 };
 use tokio_rustls::TlsConnector;
@@ -27,8 +29,11 @@ use tokio_util::net::Listener;
 use tracing::{Instrument, debug, error, info, info_span, instrument, trace, warn};
 
 use crate::{
-    cache::BufferedEgress, data::PlayerHandle, egress::Egress, player::initiate_player_connection,
-    server_sender::launch_server_writer,
+    cache::BufferedEgress,
+    data::PlayerHandle,
+    egress::Egress,
+    player::initiate_player_connection,
+    server_sender::{ServerSender, launch_server_writer},
 };
 
 /// 4 KiB
@@ -39,9 +44,16 @@ const DEFAULT_BUFFER_SIZE: usize = 4 * 1024;
 /// memory exhaustion from slow or unresponsive clients.
 const MAX_PLAYER_PENDING_MESSAGES: usize = 1_024;
 
+/// The maximum length of the hello of the server. Anything longer is not a hello.
+const MAX_HELLO_LEN: usize = 64 * 1024;
+
+/// How often the backlog of each player is reported to the server
+const BACKLOG_REPORT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
 pub mod cache;
 pub mod data;
 pub mod egress;
+pub mod encryption;
 pub mod player;
 pub mod server_sender;
 pub mod util;
@@ -64,6 +76,33 @@ pub enum ShutdownType {
 }
 
 #[tracing::instrument(level = "trace", skip_all)]
+/// Creates the TLS config the proxy connects to the game server with. The proxy authenticates
+/// with `proxy_cert`, and only trusts servers whose certificate is signed by `root_ca_cert`.
+///
+/// rustls keeps the sessions of a config in memory for resumption by default, so the proxy uses
+/// one config for every reconnect to resume the previous session instead of doing a full
+/// handshake. Sessions cannot be persisted across proxy restarts because rustls does not expose
+/// a way to serialize them.
+pub fn tls_client_config(
+    root_ca_cert: CertificateDer<'static>,
+    proxy_cert: CertificateDer<'static>,
+    proxy_key: PrivateKeyDer<'static>,
+) -> anyhow::Result<ClientConfig> {
+    let root_cert_store = Arc::new(RootCertStore {
+        roots: vec![
+            webpki::anchor_from_trusted_cert(&root_ca_cert)
+                .context("failed to create trust anchor")?
+                .to_owned(),
+        ],
+    });
+
+    let cert_chain = vec![proxy_cert, root_ca_cert];
+    ClientConfig::builder()
+        .with_root_certificates(root_cert_store)
+        .with_client_auth_cert(cert_chain, proxy_key)
+        .context("failed to create tls client config")
+}
+
 pub async fn run_proxy(
     mut listener: impl HyperionListener,
     server_addr: impl ToSocketAddrs + Debug + Clone,
@@ -85,24 +124,10 @@ pub async fn run_proxy(
     let proxy_cert = CertificateDer::from_pem_file(proxy_cert_path)
         .context("failed to load proxy certificate")?;
 
-    let root_cert_store = Arc::new(RootCertStore {
-        roots: vec![
-            webpki::anchor_from_trusted_cert(&root_ca_cert)
-                .context("failed to create trust anchor")?
-                .to_owned(),
-        ],
-    });
-
-    let cert_chain = vec![proxy_cert, root_ca_cert];
     let key_der = PrivateKeyDer::from_pem_file(proxy_private_key_path)
         .context("failed to load proxy private key")?;
 
-    let config = Arc::new(
-        ClientConfig::builder()
-            .with_root_certificates(root_cert_store)
-            .with_client_auth_cert(cert_chain, key_der)
-            .context("failed to create tls client config")?,
-    );
+    let config = Arc::new(tls_client_config(root_ca_cert, proxy_cert, key_der)?);
 
     let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(None);
 
@@ -164,6 +189,37 @@ pub async fn run_proxy(
     }
 }
 
+/// Sends the [`Hello`] of this proxy to the server and checks the hello the server sends,
+/// returning the features both support
+async fn exchange_hello(
+    server: &mut (impl AsyncRead + AsyncWrite + Unpin),
+) -> anyhow::Result<Features> {
+    let local = Hello::current();
+    let encoded = local.encode();
+    server.write_u64(encoded.len() as u64).await?;
+    server.write_all(&encoded).await?;
+    server.flush().await?;
+
+    let len = usize::try_from(server.read_u64().await?)?;
+    anyhow::ensure!(
+        len <= MAX_HELLO_LEN,
+        "the first message of the server is {len} bytes long, which is too long for a hello. Are \
+         you connected to a valid hyperion server?"
+    );
+    let mut remote = vec![0; len];
+    server.read_exact(&mut remote).await?;
+    let remote = Hello::decode(&remote)?;
+
+    let features = local.negotiate(Side::Proxy, &remote)?;
+    info!(
+        "🤝 Server speaks proxy-proto {} ({}), negotiated features {:#x}",
+        remote.protocol_version,
+        remote.crate_version,
+        features.bits()
+    );
+    Ok(features)
+}
+
 #[tracing::instrument(level = "trace", skip_all)]
 async fn connect_to_server_and_run_proxy(
     listener: &mut impl HyperionListener,
@@ -176,11 +232,23 @@ async fn connect_to_server_and_run_proxy(
     info!("🔗 Connected to server, accepting connections");
 
     let connector = TlsConnector::from(config);
-    let server_stream = connector
+    let handshake_start = std::time::Instant::now();
+    let mut server_stream = connector
         .connect(server_name, server_socket)
         .await
         .context("failed to connect to game server")?;
 
+    let resumed = server_stream.get_ref().1.handshake_kind() == Some(HandshakeKind::Resumed);
+    info!(
+        "🔒 Finished {}tls handshake in {:?}",
+        if resumed { "resumed " } else { "" },
+        handshake_start.elapsed()
+    );
+
+    let features = exchange_hello(&mut server_stream)
+        .await
+        .context("refusing to connect to game server")?;
+
     let (server_read, server_write) = tokio::io::split(server_stream);
     let server_sender = launch_server_writer(server_write);
 
@@ -188,6 +256,14 @@ async fn connect_to_server_and_run_proxy(
     let player_registry: &'static papaya::HashMap<u64, PlayerHandle, FxBuildHasher> =
         Box::leak(Box::new(player_registry));
 
+    // Servers which do not support backlog reports would not be able to decode them
+    if features.contains(Features::BACKLOG_REPORTS) {
+        tokio::spawn(
+            report_backlogs(player_registry, server_sender.clone(), shutdown_rx.clone())
+                .instrument(info_span!("backlog_reporter")),
+        );
+    }
+
     let egress = Egress::new(player_registry, server_sender.clone());
 
     let egress = BufferedEgress::new(egress);
@@ -229,13 +305,13 @@ async fn connect_to_server_and_run_proxy(
 
     loop {
         let mut shutdown_rx = shutdown_rx.clone();
-        let socket = tokio::select! {
+        let (socket, ip) = tokio::select! {
             _ = shutdown_rx.wait_for(Option::is_some) => {
                 return Ok(())
             }
             Ok((socket, addr)) = listener.accept() => {
                 info!("New client connection from {addr:?}");
-                socket
+                (socket, addr.ip())
             }
         };
 
@@ -243,7 +319,10 @@ async fn connect_to_server_and_run_proxy(
 
         // todo: re-add bounding but issues if have MASSIVE number of packets
         let (tx, rx) = kanal::bounded_async(MAX_PLAYER_PENDING_MESSAGES);
-        registry.insert(player_id_on, PlayerHandle::new(tx));
+        let handle = PlayerHandle::new(tx);
+        let backlog = handle.backlog();
+        let encryption = handle.encryption();
+        registry.insert(player_id_on, handle);
 
         // todo: some SlotMap like thing
         debug!("got player with id {player_id_on:?}");
@@ -252,15 +331,59 @@ async fn connect_to_server_and_run_proxy(
             socket,
             shutdown_rx.clone(),
             player_id_on,
+            ip,
             rx,
             server_sender.clone(),
             player_registry,
+            backlog,
+            encryption,
         );
 
         player_id_on += 1;
     }
 }
 
+/// Periodically reports the backlog of every player whose backlog changed to the server, so that
+/// it can stop sending low priority data to slow players
+async fn report_backlogs(
+    player_registry: &'static papaya::HashMap<u64, PlayerHandle, FxBuildHasher>,
+    server_sender: ServerSender,
+    mut shutdown_rx: tokio::sync::watch::Receiver<Option<ShutdownType>>,
+) {
+    let mut interval = tokio::time::interval(BACKLOG_REPORT_INTERVAL);
+    let mut reports = Vec::new();
+
+    loop {
+        tokio::select! {
+            _ = shutdown_rx.wait_for(Option::is_some) => return,
+            _ = interval.tick() => {}
+        }
+
+        // The registry guard cannot be held across the sends below
+        reports.extend(
+            player_registry
+                .pin()
+                .iter()
+                .filter_map(|(&stream, player)| {
+                    player
+                        .take_backlog_report()
+                        .map(|bytes| StreamBacklog { stream, bytes })
+                }),
+        );
+
+        for report in reports.drain(..) {
+            let message =
+                rkyv::to_bytes::<rkyv::rancor::Error>(&ProxyToServerMessage::StreamBacklog(report))
+                    .unwrap();
+
+            if let Err(e) = server_sender.send(message).await {
+                warn!("failed to send stream backlog to server: {e}");
+                return;
+            }
+        }
+    }
+}
+
 struct IngressHandler<R> {
     server_read: BufReader<R>,
     buffer: Vec<u8>,
@@ -327,6 +450,25 @@ where
     }
 }
 
-pub trait HyperionListener: Listener<Io: Send, Addr: Debug> + 'static {}
+pub trait HyperionListener: Listener<Io: Send, Addr: ClientAddr> + 'static {}
+
+impl<L: Listener<Io: Send, Addr: ClientAddr> + 'static> HyperionListener for L {}
+
+/// The address of a client accepted by a [`HyperionListener`]
+pub trait ClientAddr: Debug {
+    /// The IP address of the client, or [`None`] if it did not connect over IP
+    fn ip(&self) -> Option<IpAddr>;
+}
+
+impl ClientAddr for std::net::SocketAddr {
+    fn ip(&self) -> Option<IpAddr> {
+        Some(Self::ip(self))
+    }
+}
 
-impl<L: Listener<Io: Send, Addr: Debug> + 'static> HyperionListener for L {}
+#[cfg(unix)]
+impl ClientAddr for tokio::net::unix::SocketAddr {
+    fn ip(&self) -> Option<IpAddr> {
+        None
+    }
+}
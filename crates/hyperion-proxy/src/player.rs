@@ -1,6 +1,13 @@
 //! Player connection handling and packet processing.
 
-use std::io::IoSlice;
+use std::{
+    io::IoSlice,
+    net::IpAddr,
+    sync::{
+        Arc, OnceLock,
+        atomic::{AtomicU64, Ordering},
+    },
+};
 
 use arrayvec::ArrayVec;
 use bytes::Bytes;
@@ -10,13 +17,14 @@ use hyperion_proto::{
 use rkyv::ser::allocator::Arena;
 use rustc_hash::FxBuildHasher;
 use tokio::{
-    io::{AsyncReadExt, AsyncWrite},
+    io::{AsyncReadExt, AsyncWrite, AsyncWriteExt},
     task::JoinHandle,
 };
 use tracing::{info, info_span, instrument, warn};
 
 use crate::{
-    ShutdownType, data::PlayerHandle, server_sender::ServerSender, util::AsyncWriteVectoredExt,
+    ShutdownType, data::PlayerHandle, encryption::StreamCipher, server_sender::ServerSender,
+    util::AsyncWriteVectoredExt,
 };
 
 /// Default buffer size for reading player packets, set to 8 KiB.
@@ -28,15 +36,20 @@ const DEFAULT_READ_BUFFER_SIZE: usize = 8 * 1024;
 /// 1. A reader task that processes incoming packets from the player.
 /// 2. A writer task that sends outgoing packets to the player.
 ///
-/// It also handles player disconnection and shutdown scenarios.
+/// It also handles player disconnection and shutdown scenarios. Once the writer task has written
+/// packets to the player, their length is subtracted from `backlog`. Both tasks encrypt the
+/// connection once `encryption` is set.
 #[instrument(skip_all, fields(player_id = player_id))]
 pub fn initiate_player_connection(
     socket: impl tokio::io::AsyncRead + AsyncWrite + Send + 'static,
     mut shutdown_signal: tokio::sync::watch::Receiver<Option<ShutdownType>>,
     player_id: u64,
+    ip: Option<IpAddr>,
     incoming_packet_receiver: kanal::AsyncReceiver<Bytes>,
     server_sender: ServerSender,
     player_registry: &'static papaya::HashMap<u64, PlayerHandle, FxBuildHasher>,
+    backlog: Arc<AtomicU64>,
+    encryption: Arc<OnceLock<[u8; 16]>>,
 ) -> JoinHandle<()> {
     let span = info_span!("player_connection", player_id);
     let _enter = span.enter();
@@ -50,13 +63,16 @@ pub fn initiate_player_connection(
     // Task for handling incoming packets (player -> proxy)
     let mut packet_reader_task = tokio::spawn({
         let server_sender = server_sender.clone();
+        let encryption = encryption.clone();
         async move {
             let mut read_buffer = Vec::new();
+            let mut decrypt = None;
             let player_stream_id = player_id;
 
             let connect = rkyv::to_bytes::<rkyv::rancor::Error>(
                 &ProxyToServerMessage::PlayerConnect(PlayerConnect {
                     stream: player_stream_id,
+                    ip,
                 }),
             )
             .unwrap();
@@ -85,6 +101,19 @@ pub fn initiate_player_connection(
                     return;
                 }
 
+                // The client sends nothing between its encryption response and the first
+                // encrypted packet of the server, so no bytes read before the key was set are
+                // encrypted
+                if decrypt.is_none()
+                    && let Some(key) = encryption.get()
+                {
+                    decrypt = Some(StreamCipher::decrypt(key));
+                }
+
+                if let Some(decrypt) = &mut decrypt {
+                    decrypt.apply(&mut read_buffer);
+                }
+
                 let player_packets = ProxyToServerMessage::PlayerPackets(PlayerPackets {
                     stream: player_id,
                     data: &read_buffer,
@@ -108,6 +137,9 @@ pub fn initiate_player_connection(
 
     // Task for handling outgoing packets (proxy -> player)
     let mut packet_writer_task = tokio::spawn(async move {
+        let mut encrypt = None;
+        let mut encrypted = Vec::new();
+
         while let Ok(outgoing_packet) = incoming_packet_receiver.recv().await {
             let mut bytes = ArrayVec::<_, 16>::new();
             bytes.push(outgoing_packet);
@@ -120,16 +152,40 @@ pub fn initiate_player_connection(
                 bytes.push(outgoing_packet);
             }
 
-            // Convert the bytes into slices
-            let mut slices = ArrayVec::<_, 16>::new();
-            for slice in &bytes {
-                slices.push(IoSlice::new(slice));
+            // The server enables encryption before sending any packet which must be encrypted,
+            // and everything sent before that has already been received by the client
+            if encrypt.is_none()
+                && let Some(key) = encryption.get()
+            {
+                encrypt = Some(StreamCipher::encrypt(key));
             }
 
-            if let Err(e) = socket_writer.write_vectored_all(&mut slices).await {
+            let result = if let Some(encrypt) = &mut encrypt {
+                // Packets may be shared with other players, so they are encrypted in a copy
+                encrypted.clear();
+                for bytes in &bytes {
+                    encrypted.extend_from_slice(bytes);
+                }
+                encrypt.apply(&mut encrypted);
+
+                socket_writer.write_all(&encrypted).await
+            } else {
+                // Convert the bytes into slices
+                let mut slices = ArrayVec::<_, 16>::new();
+                for slice in &bytes {
+                    slices.push(IoSlice::new(slice));
+                }
+
+                socket_writer.write_vectored_all(&mut slices).await
+            };
+
+            if let Err(e) = result {
                 warn!("Error writing packets to player: {e:?}");
                 return;
             }
+
+            let written: usize = bytes.iter().map(Bytes::len).sum();
+            backlog.fetch_sub(written as u64, Ordering::Relaxed);
         }
     });
 
@@ -1,66 +1,864 @@
-use std::path::PathBuf;
+use std::{
+    fmt,
+    fs::{File, OpenOptions},
+    io::{BufReader, Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicU8, AtomicU64, Ordering},
+    },
+    time::SystemTime,
+};
 
 use anyhow::Context;
 use bevy_ecs::world::World;
 use futures_util::stream::StreamExt;
-use sha2::Digest;
+use reqwest::{StatusCode, header};
+use sha2::{Digest, Sha256};
 use tar::Archive;
 use tokio_util::io::{StreamReader, SyncIoBridge};
-use tracing::info;
+use tracing::{info, warn};
 
 use crate::AppId;
 
-pub fn cached_save<U: reqwest::IntoUrl + 'static>(
-    world: &World,
-    url: U,
-) -> impl Future<Output = anyhow::Result<PathBuf>> + 'static {
-    let project_dirs = world.resource::<AppId>();
+/// What a [`SaveRequest::fetch`] is currently doing
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum SaveStage {
+    #[default]
+    Downloading,
+    /// Comparing the checksum of the downloaded archive to the expected one
+    Verifying,
+    Extracting,
+    /// The save is extracted, or was already cached
+    Done,
+}
 
-    let cache = project_dirs.cache_dir();
+impl SaveStage {
+    const ALL: [Self; 4] = [
+        Self::Downloading,
+        Self::Verifying,
+        Self::Extracting,
+        Self::Done,
+    ];
+}
 
-    let mut hasher = sha2::Sha256::new();
-    let url_str = url.as_str().to_string();
-    Digest::update(&mut hasher, url_str.as_bytes());
-    // Get the final hash result
-    let url_sha = hasher.finalize();
-    let url_sha = hex::encode(url_sha);
+/// The progress of a [`SaveRequest::fetch`], which can be read while it runs on another thread
+#[derive(Debug, Default)]
+pub struct SaveProgress {
+    stage: AtomicU8,
+    downloaded: AtomicU64,
+    /// The size of the archive, or 0 if the server did not send it
+    total: AtomicU64,
+}
 
-    let directory = cache.join(url_sha);
+impl SaveProgress {
+    #[must_use]
+    pub fn stage(&self) -> SaveStage {
+        let stage = self.stage.load(Ordering::Relaxed);
+        SaveStage::ALL[usize::from(stage)]
+    }
+
+    /// The bytes of the archive downloaded so far, including those of an earlier, interrupted
+    /// download
+    #[must_use]
+    pub fn downloaded_bytes(&self) -> u64 {
+        self.downloaded.load(Ordering::Relaxed)
+    }
 
-    async move {
-        if directory.exists() {
-            info!("using cached NewYork load");
+    /// The size of the archive, if the server sent it
+    #[must_use]
+    pub fn total_bytes(&self) -> Option<u64> {
+        Some(self.total.load(Ordering::Relaxed)).filter(|&total| total != 0)
+    }
+
+    fn set_stage(&self, stage: SaveStage) {
+        self.stage.store(stage as u8, Ordering::Relaxed);
+    }
+}
+
+/// Where a save comes from
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum SaveSource {
+    /// A `.tar.gz` archive downloaded over HTTP or HTTPS
+    Remote(String),
+    /// A local `.tar.gz` archive, given as a path or a `file://` URL
+    Archive(PathBuf),
+    /// A local directory, which is used in place instead of being copied into the cache
+    Directory(PathBuf),
+}
+
+impl SaveSource {
+    /// Parses `location`, which is an HTTP(S) URL, a `file://` URL or a path
+    pub fn parse(location: &str) -> anyhow::Result<Self> {
+        if location.starts_with("http://") || location.starts_with("https://") {
+            return Ok(Self::Remote(location.to_owned()));
+        }
+
+        let path = if location.starts_with("file://") {
+            reqwest::Url::parse(location)
+                .ok()
+                .and_then(|url| url.to_file_path().ok())
+                .with_context(|| format!("invalid file URL {location}"))?
         } else {
-            // download
-            let response = reqwest::get(url)
-                .await
-                .with_context(|| format!("failed to get {url_str}"))?;
+            PathBuf::from(location)
+        };
+
+        if path.is_dir() {
+            Ok(Self::Directory(path))
+        } else {
+            Ok(Self::Archive(path))
+        }
+    }
+}
+
+impl fmt::Display for SaveSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Remote(url) => f.write_str(url),
+            Self::Archive(path) | Self::Directory(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
 
-            let byte_stream = response.bytes_stream();
-            // Convert the byte stream into an AsyncRead
+/// A save returned by [`SaveRequest::fetch`]. The save is not evicted from the cache while this
+/// or a clone of it exists, so it has to be kept for as long as the save is read.
+#[derive(Clone, Debug)]
+pub struct CachedSave {
+    /// The directory of the save
+    pub path: PathBuf,
+    pub source: SaveSource,
+    /// Whether the save was downloaded or extracted by this fetch instead of taken from the cache
+    pub freshly_downloaded: bool,
+    /// The shared lock on the in-use file of a cached save
+    _in_use: Option<Arc<File>>,
+}
 
-            let reader =
-                StreamReader::new(byte_stream.map(|result| result.map_err(std::io::Error::other)));
+/// Fetches the save at `location` with the default options of [`SaveRequest`]
+pub fn cached_save(
+    world: &World,
+    location: impl Into<String>,
+) -> impl Future<Output = anyhow::Result<CachedSave>> + 'static {
+    SaveRequest::new(location).fetch(world)
+}
 
-            let directory = directory.clone();
-            let handle = tokio::task::spawn_blocking(move || {
-                let reader = SyncIoBridge::new(reader);
-                let reader = std::io::BufReader::new(reader);
-                let reader = flate2::read::GzDecoder::new(reader);
+/// Builder for fetching a save into the cache
+///
+/// Each save is extracted into its own directory of the cache, named after the hash of its
+/// location, or after its checksum if one is given. Fetches of the same save wait for each other
+/// through a lock file, even across processes, so the save is only extracted once.
+#[derive(Debug, Clone)]
+#[must_use]
+pub struct SaveRequest {
+    location: String,
+    name: Option<String>,
+    sha256: Option<String>,
+    force_refresh: bool,
+    max_cache_bytes: Option<u64>,
+    progress: Arc<SaveProgress>,
+}
 
-                // Create the archive in the blocking context
-                let mut archive = Archive::new(reader);
+impl SaveRequest {
+    /// A request for the save at `location`, which is the HTTP(S) URL, `file://` URL or path of
+    /// a `.tar.gz` archive, or the path of a directory
+    pub fn new(location: impl Into<String>) -> Self {
+        Self {
+            location: location.into(),
+            name: None,
+            sha256: None,
+            force_refresh: false,
+            max_cache_bytes: None,
+            progress: Arc::default(),
+        }
+    }
 
-                archive
-                    .unpack(&directory)
-                    .context("failed to unpack archive")?;
+    /// A human-readable name which is added to the name of the cache directory
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
 
-                anyhow::Ok(())
+    /// The SHA-256 checksum of the archive in hex. The archive is only extracted if it matches,
+    /// and the save is cached by the checksum instead of the location. This is required for
+    /// HTTP(S) URLs, while directories cannot be verified.
+    pub fn sha256(mut self, sha256: impl Into<String>) -> Self {
+        self.sha256 = Some(sha256.into());
+        self
+    }
+
+    /// Whether the cached save is discarded and fetched again
+    pub const fn force_refresh(mut self, force_refresh: bool) -> Self {
+        self.force_refresh = force_refresh;
+        self
+    }
+
+    /// The maximum size of all cached saves together. Once it is exceeded, the saves which were
+    /// used least recently are removed, except the fetched one and saves which are being fetched.
+    pub const fn max_cache_bytes(mut self, max_cache_bytes: u64) -> Self {
+        self.max_cache_bytes = Some(max_cache_bytes);
+        self
+    }
+
+    /// Where the progress of the fetch is reported
+    pub fn progress(mut self, progress: Arc<SaveProgress>) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Fetches the save into the cache of the [`AppId`] of `world`. An interrupted download is
+    /// resumed from where it stopped if the server supports it.
+    pub fn fetch(
+        self,
+        world: &World,
+    ) -> impl Future<Output = anyhow::Result<CachedSave>> + 'static {
+        let cache = world.resource::<AppId>().cache_dir();
+        self.fetch_into(cache)
+    }
+
+    /// Fetches the save into the cache directory `cache`, like [`SaveRequest::fetch`]
+    pub async fn fetch_into(self, cache: PathBuf) -> anyhow::Result<CachedSave> {
+        let source = SaveSource::parse(&self.location)?;
+        let expected = self.sha256.as_deref().map(parse_checksum).transpose()?;
+        let progress = self.progress;
+
+        if let SaveSource::Directory(path) = &source {
+            if expected.is_some() {
+                anyhow::bail!("the checksum of the directory {source} cannot be verified");
+            }
+
+            progress.set_stage(SaveStage::Done);
+            return Ok(CachedSave {
+                path: path.clone(),
+                source,
+                freshly_downloaded: false,
+                _in_use: None,
             });
+        }
+
+        if matches!(source, SaveSource::Remote(_)) && expected.is_none() {
+            anyhow::bail!("a checksum is required to download {source}");
+        }
+
+        let key = cache_key(self.name.as_deref(), expected.as_deref(), &self.location);
+        let directory = cache.join(&key);
 
-            handle.await??;
+        std::fs::create_dir_all(&cache)
+            .with_context(|| format!("failed to create {}", cache.display()))?;
+
+        let lock = lock_save(cache.join(format!("{key}.lock"))).await?;
+        let in_use = open_lock(&in_use_path(&directory))?;
+
+        if self.force_refresh && directory.exists() {
+            if in_use.try_lock().is_err() {
+                anyhow::bail!("the cached save of {source} cannot be refreshed while it is used");
+            }
+            info!("discarding cached save of {source}");
+            std::fs::remove_dir_all(&directory)
+                .with_context(|| format!("failed to remove {}", directory.display()))?;
+            in_use.unlock()?;
+        }
+
+        let freshly_downloaded = !directory.exists();
+        if freshly_downloaded {
+            let (archive, downloaded) = match &source {
+                SaveSource::Remote(url) => {
+                    let part = cache.join(format!("{key}.tar.gz.part"));
+                    if self.force_refresh {
+                        remove_if_exists(&part)?;
+                    }
+
+                    let checksum = download(url, part.clone(), progress.clone()).await?;
+                    progress.set_stage(SaveStage::Verifying);
+                    if let Err(e) = verify(expected.as_deref(), &checksum, &source) {
+                        // The archive cannot be resumed either, so it is downloaded again the
+                        // next time
+                        remove_if_exists(&part)?;
+                        return Err(e);
+                    }
+
+                    (part, true)
+                }
+                SaveSource::Archive(path) => {
+                    if expected.is_some() {
+                        progress.set_stage(SaveStage::Verifying);
+                        let checksum = hash_file(path.clone(), progress.clone()).await?;
+                        verify(expected.as_deref(), &checksum, &source)?;
+                    }
+
+                    (path.clone(), false)
+                }
+                SaveSource::Directory(_) => unreachable!("directories are not cached"),
+            };
+
+            progress.set_stage(SaveStage::Extracting);
+            let target = directory.clone();
+            tokio::task::spawn_blocking(move || {
+                extract(&archive, &target)?;
+                if downloaded && let Err(e) = std::fs::remove_file(&archive) {
+                    warn!("failed to remove {}: {e}", archive.display());
+                }
+                anyhow::Ok(())
+            })
+            .await??;
+        } else {
+            info!("using cached save of {source}");
+        }
+
+        // The modification time of the lock file is when the save was used last
+        if let Err(e) = lock.set_modified(SystemTime::now()) {
+            warn!("failed to mark save {key} as used: {e}");
+        }
+
+        if let Some(max_bytes) = self.max_cache_bytes {
+            let cache = cache.clone();
+            let keep = key.clone();
+            let result =
+                tokio::task::spawn_blocking(move || evict(&cache, &keep, max_bytes)).await?;
+            if let Err(e) = result {
+                warn!("failed to evict cached saves: {e:?}");
+            }
+        }
+
+        // Eviction needs both locks, so it cannot happen between releasing the lock of the fetch
+        // and taking the shared lock
+        in_use
+            .try_lock_shared()
+            .with_context(|| format!("failed to lock the cached save of {source}"))?;
+        drop(lock);
+        progress.set_stage(SaveStage::Done);
+
+        Ok(CachedSave {
+            path: directory,
+            source,
+            freshly_downloaded,
+            _in_use: Some(Arc::new(in_use)),
+        })
+    }
+}
+
+/// Validates a SHA-256 checksum in hex and converts it to lowercase
+fn parse_checksum(checksum: &str) -> anyhow::Result<String> {
+    let checksum = checksum.to_ascii_lowercase();
+    if checksum.len() != 64 || !checksum.bytes().all(|b| b.is_ascii_hexdigit()) {
+        anyhow::bail!("invalid SHA-256 checksum {checksum}");
+    }
+    Ok(checksum)
+}
+
+/// The name of the cache directory of a save
+fn cache_key(name: Option<&str>, checksum: Option<&str>, location: &str) -> String {
+    let hash = checksum.map_or_else(
+        || hex::encode(Sha256::digest(location.as_bytes())),
+        str::to_owned,
+    );
+
+    let Some(name) = name else {
+        return hash;
+    };
+
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("{name}-{hash}")
+}
+
+/// Compares the checksum of the archive of `source` to the expected one, if there is one
+fn verify(expected: Option<&str>, checksum: &str, source: &SaveSource) -> anyhow::Result<()> {
+    match expected {
+        Some(expected) if expected != checksum => {
+            anyhow::bail!("checksum mismatch for {source}: expected {expected}, got {checksum}")
         }
+        Some(_) => {}
+        None => warn!("{source} has the checksum {checksum}, which was not verified"),
+    }
+    Ok(())
+}
+
+fn remove_if_exists(path: &Path) -> anyhow::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            Err(e).with_context(|| format!("failed to remove {}", path.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Opens the lock file at `path`, creating it if it does not exist
+fn open_lock(path: &Path) -> anyhow::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .with_context(|| format!("failed to open {}", path.display()))
+}
+
+/// The file which every [`CachedSave`] of the save in `directory` holds a shared lock on
+fn in_use_path(directory: &Path) -> PathBuf {
+    directory.with_extension("use")
+}
+
+/// Opens and locks the lock file of a save, waiting until no other fetch holds it
+async fn lock_save(path: PathBuf) -> anyhow::Result<File> {
+    tokio::task::spawn_blocking(move || {
+        let file = open_lock(&path)?;
+        file.lock()
+            .with_context(|| format!("failed to lock {}", path.display()))?;
+        anyhow::Ok(file)
+    })
+    .await?
+}
+
+/// The SHA-256 checksum of the file at `path` in hex
+async fn hash_file(path: PathBuf, progress: Arc<SaveProgress>) -> anyhow::Result<String> {
+    tokio::task::spawn_blocking(move || {
+        let file =
+            File::open(&path).with_context(|| format!("failed to open {}", path.display()))?;
+        let mut hasher = Sha256::new();
+        copy_hashed(file, std::io::sink(), &mut hasher, &progress.downloaded)?;
+        anyhow::Ok(hex::encode(hasher.finalize()))
+    })
+    .await?
+}
+
+/// The total size of the files in `path` and its subdirectories
+fn dir_size(path: &Path) -> std::io::Result<u64> {
+    let mut size = 0;
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        size += if metadata.is_dir() {
+            dir_size(&entry.path())?
+        } else {
+            metadata.len()
+        };
+    }
+    Ok(size)
+}
+
+/// Removes the saves in `cache` which were used least recently until all saves take at most
+/// `max_bytes`. The save `keep`, saves which are being fetched and saves with a [`CachedSave`]
+/// are never removed.
+fn evict(cache: &Path, keep: &str, max_bytes: u64) -> anyhow::Result<()> {
+    let mut saves = Vec::new();
+    for entry in std::fs::read_dir(cache)? {
+        let lock = entry?.path();
+        if lock.extension().is_none_or(|extension| extension != "lock") {
+            continue;
+        }
+
+        let directory = lock.with_extension("");
+        if !directory.is_dir() {
+            continue;
+        }
+
+        let used = std::fs::metadata(&lock)?.modified()?;
+        saves.push((used, dir_size(&directory)?, lock, directory));
+    }
+
+    let mut total: u64 = saves.iter().map(|(_, size, ..)| size).sum();
+    saves.sort_by_key(|(used, ..)| *used);
+
+    for (_, size, lock, directory) in saves {
+        if total <= max_bytes {
+            break;
+        }
+
+        if directory.file_name().is_some_and(|name| name == keep) {
+            continue;
+        }
+
+        // The save is being fetched right now
+        let lock = File::open(&lock)?;
+        if lock.try_lock().is_err() {
+            continue;
+        }
+
+        // The save is being read right now. Both locks are held until it is removed.
+        let in_use = match File::open(in_use_path(&directory)) {
+            Ok(in_use) => Some(in_use),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        if in_use
+            .as_ref()
+            .is_some_and(|in_use| in_use.try_lock().is_err())
+        {
+            continue;
+        }
+
+        info!(
+            "evicting cached save {} ({size} bytes)",
+            directory.display()
+        );
+        std::fs::remove_dir_all(&directory)
+            .with_context(|| format!("failed to remove {}", directory.display()))?;
+        total -= size;
+    }
+
+    Ok(())
+}
+
+/// The size of the whole file in a `Content-Range` header of the form `bytes */<size>`, which is
+/// sent with `416 Range Not Satisfiable`
+fn unsatisfied_range_size(content_range: &str) -> Option<u64> {
+    content_range.strip_prefix("bytes */")?.trim().parse().ok()
+}
+
+/// Downloads `url` to `archive`, continuing a previous download if possible. Returns the
+/// SHA-256 checksum of the archive in hex.
+async fn download(
+    url: &str,
+    archive: PathBuf,
+    progress: Arc<SaveProgress>,
+) -> anyhow::Result<String> {
+    let client = reqwest::Client::new();
+
+    let (response, resume_from) = loop {
+        let resume_from = std::fs::metadata(&archive).map_or(0, |metadata| metadata.len());
+
+        let mut request = client.get(url);
+        if resume_from != 0 {
+            info!("resuming download of {url} at {resume_from} bytes");
+            request = request.header(header::RANGE, format!("bytes={resume_from}-"));
+        }
+
+        let response = request
+            .send()
+            .await
+            .with_context(|| format!("failed to get {url}"))?;
+
+        if resume_from == 0 || response.status() != StatusCode::RANGE_NOT_SATISFIABLE {
+            break (response, resume_from);
+        }
+
+        // The server has no bytes after the end of the archive. It was already complete if it
+        // is exactly as large as the file on the server, and is corrupt otherwise.
+        let size = response
+            .headers()
+            .get(header::CONTENT_RANGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(unsatisfied_range_size);
+        if size == Some(resume_from) {
+            progress.total.store(resume_from, Ordering::Relaxed);
+            return hash_file(archive, progress).await;
+        }
+
+        warn!("the partial download of {url} does not match the file on the server, restarting");
+        remove_if_exists(&archive)?;
+    };
+
+    let status = response.status();
+    let resume = resume_from != 0 && status == StatusCode::PARTIAL_CONTENT;
+
+    let response = response
+        .error_for_status()
+        .with_context(|| format!("failed to get {url}"))?;
+
+    let offset = if resume { resume_from } else { 0 };
+    let total = response.content_length().map_or(0, |len| len + offset);
+    progress.total.store(total, Ordering::Relaxed);
+
+    let byte_stream = response.bytes_stream();
+    // Convert the byte stream into an AsyncRead
+    let reader = StreamReader::new(byte_stream.map(|result| result.map_err(std::io::Error::other)));
+
+    let handle = tokio::task::spawn_blocking(move || {
+        let mut hasher = Sha256::new();
+
+        let file = if resume {
+            // The checksum covers the bytes downloaded before as well
+            let previous = File::open(&archive)?;
+            copy_hashed(previous, std::io::sink(), &mut hasher, &progress.downloaded)?;
+            OpenOptions::new().append(true).open(&archive)?
+        } else {
+            progress.downloaded.store(0, Ordering::Relaxed);
+            File::create(&archive)?
+        };
+
+        let reader = SyncIoBridge::new(reader);
+        copy_hashed(reader, file, &mut hasher, &progress.downloaded)
+            .context("failed to download archive")?;
+
+        anyhow::Ok(hex::encode(hasher.finalize()))
+    });
+
+    handle.await?
+}
+
+/// Copies `reader` to `writer` while hashing it and counting the bytes in `copied`
+fn copy_hashed(
+    mut reader: impl Read,
+    mut writer: impl Write,
+    hasher: &mut Sha256,
+    copied: &AtomicU64,
+) -> std::io::Result<()> {
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let len = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+
+        let bytes = &buf[..len];
+        writer.write_all(bytes)?;
+        Digest::update(hasher, bytes);
+        copied.fetch_add(u64::try_from(len).unwrap_or_default(), Ordering::Relaxed);
+    }
+
+    writer.flush()
+}
+
+/// Extracts `archive` into `directory`. The save is extracted next to `directory` first, so an
+/// interrupted extraction is never mistaken for a cached save.
+fn extract(archive: &Path, directory: &Path) -> anyhow::Result<()> {
+    let partial = directory.with_extension("partial");
+    if partial.exists() {
+        std::fs::remove_dir_all(&partial)
+            .with_context(|| format!("failed to remove {}", partial.display()))?;
+    }
+
+    let file =
+        File::open(archive).with_context(|| format!("failed to open {}", archive.display()))?;
+    let reader = BufReader::new(file);
+    let reader = flate2::read::GzDecoder::new(reader);
+
+    // Create the archive in the blocking context
+    let mut tar = Archive::new(reader);
+
+    tar.unpack(&partial).context("failed to unpack archive")?;
+
+    std::fs::rename(&partial, directory)
+        .with_context(|| format!("failed to move save to {}", directory.display()))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn progress_reports_every_stage() {
+        let progress = SaveProgress::default();
+        assert_eq!(progress.stage(), SaveStage::Downloading);
+        assert_eq!(progress.total_bytes(), None);
+
+        for stage in SaveStage::ALL {
+            progress.set_stage(stage);
+            assert_eq!(progress.stage(), stage);
+        }
+    }
+
+    #[test]
+    fn hashes_what_it_copies() {
+        let data = b"hyperion".repeat(20_000);
+        let mut copy = Vec::new();
+        let mut hasher = Sha256::new();
+        let copied = AtomicU64::new(3);
+
+        copy_hashed(&data[..], &mut copy, &mut hasher, &copied).unwrap();
+
+        assert_eq!(copy, data);
+        assert_eq!(hasher.finalize(), Sha256::digest(&data));
+        assert_eq!(copied.load(Ordering::Relaxed), 3 + 160_000);
+    }
+
+    /// A directory for a test, which is empty at first
+    fn temp_dir(test: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "hyperion-cached-save-{test}-{}",
+            std::process::id()
+        ));
+        if path.exists() {
+            std::fs::remove_dir_all(&path).unwrap();
+        }
+        std::fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    /// Writes a `.tar.gz` archive containing a region file with `contents`
+    fn write_archive(path: &Path, contents: &[u8]) {
+        let encoder = flate2::write::GzEncoder::new(
+            File::create(path).unwrap(),
+            flate2::Compression::default(),
+        );
+        let mut builder = tar::Builder::new(encoder);
+
+        let mut header = tar::Header::new_gnu();
+        header.set_size(u64::try_from(contents.len()).unwrap());
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "region/r.0.0.mca", contents)
+            .unwrap();
+
+        builder.into_inner().unwrap().finish().unwrap();
+    }
+
+    #[test]
+    fn parses_sources() {
+        let dir = temp_dir("sources");
+
+        assert_eq!(
+            SaveSource::parse("https://example.com/map.tar.gz").unwrap(),
+            SaveSource::Remote("https://example.com/map.tar.gz".to_owned())
+        );
+        assert_eq!(
+            SaveSource::parse("file:///srv/maps/map.tar.gz").unwrap(),
+            SaveSource::Archive(PathBuf::from("/srv/maps/map.tar.gz"))
+        );
+        assert_eq!(
+            SaveSource::parse(dir.to_str().unwrap()).unwrap(),
+            SaveSource::Directory(dir.clone())
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn names_cache_directories() {
+        let by_location = cache_key(None, None, "https://example.com/map.tar.gz");
+        assert_eq!(by_location.len(), 64);
+        assert_ne!(
+            by_location,
+            cache_key(None, None, "https://example.com/other.tar.gz")
+        );
+
+        let checksum = "ab".repeat(32);
+        assert_eq!(
+            cache_key(Some("Gen Map/1"), Some(&checksum), "map.tar.gz"),
+            format!("Gen_Map_1-{checksum}")
+        );
+    }
+
+    #[test]
+    fn parses_the_size_of_unsatisfiable_ranges() {
+        assert_eq!(unsatisfied_range_size("bytes */1234"), Some(1234));
+        assert_eq!(unsatisfied_range_size("bytes 0-99/1234"), None);
+        assert_eq!(unsatisfied_range_size("bytes */*"), None);
+    }
+
+    #[tokio::test]
+    async fn remote_saves_need_a_checksum() {
+        let dir = temp_dir("remote");
+        let cache = dir.join("cache");
+
+        // This fails before anything is downloaded
+        let unverified = SaveRequest::new("https://example.com/map.tar.gz");
+        assert!(unverified.fetch_into(cache.clone()).await.is_err());
+        assert!(!cache.exists());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn extracts_local_archives_once() {
+        let dir = temp_dir("local");
+        let cache = dir.join("cache");
+        let archive = dir.join("map.tar.gz");
+        write_archive(&archive, b"chunks");
+        let location = archive.to_str().unwrap().to_owned();
+
+        let request = SaveRequest::new(location.clone()).name("map");
+        let save = request.clone().fetch_into(cache.clone()).await.unwrap();
+        assert!(save.freshly_downloaded);
+        assert_eq!(save.source, SaveSource::Archive(archive.clone()));
+        assert_eq!(
+            std::fs::read(save.path.join("region/r.0.0.mca")).unwrap(),
+            b"chunks"
+        );
+        // Local archives are kept
+        assert!(archive.exists());
+
+        let cached = request.clone().fetch_into(cache.clone()).await.unwrap();
+        assert!(!cached.freshly_downloaded);
+        assert_eq!(cached.path, save.path);
+
+        // Saves cannot be refreshed while they are used
+        let refresh = request.force_refresh(true);
+        assert!(refresh.clone().fetch_into(cache.clone()).await.is_err());
+        assert!(save.path.exists());
+
+        drop((save, cached));
+        let refreshed = refresh.fetch_into(cache.clone()).await;
+        assert!(refreshed.unwrap().freshly_downloaded);
+
+        // Concurrent fetches of a new save extract it only once
+        let checksum = hex::encode(Sha256::digest(std::fs::read(&archive).unwrap()));
+        let verified = SaveRequest::new(location.clone()).sha256(checksum.to_uppercase());
+        let (a, b) = tokio::join!(
+            verified.clone().fetch_into(cache.clone()),
+            verified.clone().fetch_into(cache.clone())
+        );
+        let (a, b) = (a.unwrap(), b.unwrap());
+        assert_eq!(a.path, cache.join(&checksum));
+        assert_ne!(a.freshly_downloaded, b.freshly_downloaded);
+
+        let tampered = SaveRequest::new(location).sha256("00".repeat(32));
+        assert!(tampered.fetch_into(cache.clone()).await.is_err());
+        assert!(!cache.join("00".repeat(32)).exists());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn evicts_the_least_recently_used_saves() {
+        let dir = temp_dir("evict");
+        let cache = dir.join("cache");
+
+        let mut paths = Vec::new();
+        for name in ["old", "new", "current"] {
+            let archive = dir.join(format!("{name}.tar.gz"));
+            write_archive(&archive, &[0; 4096]);
+
+            let save = SaveRequest::new(archive.to_str().unwrap())
+                .name(name)
+                .fetch_into(cache.clone())
+                .await
+                .unwrap();
+            paths.push(save.path);
+
+            // Modification times may be too coarse to tell the saves apart otherwise
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+
+        // Only two saves fit into the cache
+        let current = paths[2].file_name().unwrap().to_str().unwrap();
+        evict(&cache, current, 2 * 4096).unwrap();
+
+        assert!(!paths[0].exists());
+        assert!(paths[1].exists());
+        assert!(paths[2].exists());
+
+        // The save which is kept is never removed
+        evict(&cache, current, 0).unwrap();
+        assert!(!paths[1].exists());
+        assert!(paths[2].exists());
+
+        // Neither are saves which are used
+        let used = SaveRequest::new(dir.join("new.tar.gz").to_str().unwrap())
+            .name("new")
+            .fetch_into(cache.clone())
+            .await
+            .unwrap();
+        evict(&cache, current, 0).unwrap();
+        assert!(used.path.exists());
+
+        let path = used.path.clone();
+        drop(used);
+        evict(&cache, current, 0).unwrap();
+        assert!(!path.exists());
 
-        Ok(directory)
+        std::fs::remove_dir_all(dir).unwrap();
     }
 }
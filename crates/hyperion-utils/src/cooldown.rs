@@ -0,0 +1,167 @@
+//! Cooldowns of actions, such as sending chat messages. See [`Cooldowns`].
+
+use bevy_ecs::component::Component;
+use rustc_hash::FxHashMap;
+#[cfg(feature = "reflect")]
+use {bevy_ecs::reflect::ReflectComponent, bevy_reflect::Reflect};
+
+/// Identifies the action a cooldown is for. Keys are hashes of a name, so they can be created in
+/// constants and checked without allocating:
+///
+/// ```
+/// use hyperion_utils::cooldown::CooldownKey;
+///
+/// const CHAT: CooldownKey = CooldownKey::new("chat");
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CooldownKey(u64);
+
+impl CooldownKey {
+    /// Creates the key for the action `name` with the 64-bit FNV-1a hash of the name
+    #[must_use]
+    pub const fn new(name: &str) -> Self {
+        const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+        const PRIME: u64 = 0x0100_0000_01b3;
+
+        let bytes = name.as_bytes();
+        let mut hash = OFFSET_BASIS;
+        let mut i = 0;
+        while i < bytes.len() {
+            hash ^= bytes[i] as u64;
+            hash = hash.wrapping_mul(PRIME);
+            i += 1;
+        }
+
+        Self(hash)
+    }
+}
+
+/// The number of ticks until a cooldown expires
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct RemainingTicks(pub i64);
+
+/// The cooldowns of an entity, by the action they are for.
+///
+/// Entries are kept after they expire until they are used again or [`Cooldowns::prune`] removes
+/// them.
+#[derive(Component, Default, Debug)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+pub struct Cooldowns {
+    /// The tick each cooldown expires at
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    expires: FxHashMap<CooldownKey, i64>,
+}
+
+impl Cooldowns {
+    /// Starts a cooldown of `duration_ticks` for `key` if it is not on cooldown at `current_tick`.
+    /// Otherwise, the cooldown is left unchanged and the remaining ticks are returned.
+    ///
+    /// A cooldown started at tick `t` with a duration of `d` ticks can be used again at tick
+    /// `t + d`.
+    pub fn try_use(
+        &mut self,
+        key: CooldownKey,
+        duration_ticks: i64,
+        current_tick: i64,
+    ) -> Result<(), RemainingTicks> {
+        if let Some(remaining) = self.peek(key, current_tick) {
+            return Err(remaining);
+        }
+
+        self.expires.insert(key, current_tick + duration_ticks);
+        Ok(())
+    }
+
+    /// Returns the remaining ticks of the cooldown of `key` if it is on cooldown at
+    /// `current_tick`
+    #[must_use]
+    pub fn peek(&self, key: CooldownKey, current_tick: i64) -> Option<RemainingTicks> {
+        let &expires = self.expires.get(&key)?;
+        (expires > current_tick).then_some(RemainingTicks(expires - current_tick))
+    }
+
+    /// Ends the cooldown of `key` so that it can be used immediately
+    pub fn reset(&mut self, key: CooldownKey) {
+        self.expires.remove(&key);
+    }
+
+    /// Removes the cooldowns which have expired at `current_tick`
+    pub fn prune(&mut self, current_tick: i64) {
+        self.expires
+            .retain(|_, &mut expires| expires > current_tick);
+    }
+
+    /// The number of cooldowns stored, including expired ones which have not been pruned
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.expires.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.expires.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHAT: CooldownKey = CooldownKey::new("chat");
+    const COMMAND: CooldownKey = CooldownKey::new("command");
+
+    #[test]
+    fn cooldown_expires_exactly_after_its_duration() {
+        let mut cooldowns = Cooldowns::default();
+
+        assert_eq!(cooldowns.try_use(CHAT, 60, 100), Ok(()));
+        assert_eq!(cooldowns.try_use(CHAT, 60, 100), Err(RemainingTicks(60)));
+        assert_eq!(cooldowns.try_use(CHAT, 60, 159), Err(RemainingTicks(1)));
+        assert_eq!(cooldowns.peek(CHAT, 159), Some(RemainingTicks(1)));
+        assert_eq!(cooldowns.peek(CHAT, 160), None);
+        assert_eq!(cooldowns.try_use(CHAT, 60, 160), Ok(()));
+        assert_eq!(cooldowns.peek(CHAT, 160), Some(RemainingTicks(60)));
+    }
+
+    #[test]
+    fn rejected_uses_do_not_extend_the_cooldown() {
+        let mut cooldowns = Cooldowns::default();
+
+        assert_eq!(cooldowns.try_use(CHAT, 10, 0), Ok(()));
+        for tick in 1..10 {
+            assert!(cooldowns.try_use(CHAT, 10, tick).is_err());
+        }
+        assert_eq!(cooldowns.try_use(CHAT, 10, 10), Ok(()));
+    }
+
+    #[test]
+    fn keys_are_independent() {
+        let mut cooldowns = Cooldowns::default();
+        assert_ne!(CHAT, COMMAND);
+
+        assert_eq!(cooldowns.try_use(CHAT, 20, 0), Ok(()));
+        assert_eq!(cooldowns.try_use(COMMAND, 5, 0), Ok(()));
+        assert_eq!(cooldowns.try_use(COMMAND, 5, 5), Ok(()));
+        assert!(cooldowns.try_use(CHAT, 20, 5).is_err());
+
+        cooldowns.reset(CHAT);
+        assert_eq!(cooldowns.try_use(CHAT, 20, 5), Ok(()));
+    }
+
+    #[test]
+    fn prune_removes_expired_cooldowns() {
+        let mut cooldowns = Cooldowns::default();
+        cooldowns.try_use(CHAT, 10, 0).unwrap();
+        cooldowns.try_use(COMMAND, 20, 0).unwrap();
+
+        cooldowns.prune(9);
+        assert_eq!(cooldowns.len(), 2);
+
+        cooldowns.prune(10);
+        assert_eq!(cooldowns.len(), 1);
+        assert_eq!(cooldowns.peek(COMMAND, 10), Some(RemainingTicks(10)));
+
+        cooldowns.prune(20);
+        assert!(cooldowns.is_empty());
+    }
+}
@@ -1,26 +1,28 @@
 mod cached_save;
+pub mod cooldown;
 pub mod iterator;
+pub mod localization;
 pub mod prev;
 use std::path::PathBuf;
 
 use bevy_app::{App, Plugin};
 use bevy_ecs::{
-    entity::{Entity, EntityIndex},
+    entity::Entity,
     resource::Resource,
     system::{SystemParam, SystemState},
     world::World,
 };
-pub use cached_save::cached_save;
+pub use cached_save::{CachedSave, SaveProgress, SaveRequest, SaveSource, SaveStage, cached_save};
+pub use cooldown::{CooldownKey, Cooldowns, RemainingTicks};
+pub use localization::{Args, Locale, Localizer, Translations};
 pub use prev::{Prev, track_prev};
 #[cfg(feature = "reflect")]
 use {bevy_ecs::reflect::ReflectResource, bevy_reflect::Reflect};
 
 pub trait EntityExt: Sized {
+    /// The entity index, which is reused once the entity is despawned. Ids which may outlive the
+    /// entity must be resolved through a registry which checks the generation.
     fn id(&self) -> u32;
-    fn from_id(id: u32, world: &World) -> anyhow::Result<Self>;
-
-    fn minecraft_id(&self) -> i32;
-    fn from_minecraft_id(id: i32, world: &World) -> anyhow::Result<Self>;
 }
 
 // TODO: How does this fit into the picture? Why are we using the internal id?
@@ -28,27 +30,6 @@ impl EntityExt for Entity {
     fn id(&self) -> u32 {
         self.index().index()
     }
-
-    fn from_id(id: u32, world: &World) -> anyhow::Result<Self> {
-        let Some(id) = EntityIndex::from_raw_u32(id) else {
-            anyhow::bail!("minecraft id is should not be u32::MAX")
-        };
-
-        let entities = world.entities();
-        if !entities.is_index_spawned(id) {
-            anyhow::bail!("minecraft id is invalid")
-        }
-
-        Ok(world.entities().resolve_from_index(id))
-    }
-
-    fn minecraft_id(&self) -> i32 {
-        bytemuck::cast(self.id())
-    }
-
-    fn from_minecraft_id(id: i32, world: &World) -> anyhow::Result<Self> {
-        Self::from_id(bytemuck::cast(id), world)
-    }
 }
 
 pub trait ApplyWorld {
@@ -102,6 +83,7 @@ impl Plugin for HyperionUtilsPlugin {
             organization: "hyperion-mc".to_string(),
             application: "generic".to_string(),
         });
+        app.init_resource::<Translations>();
     }
 }
 
@@ -113,10 +95,6 @@ mod tests {
     fn test_entity_id() {
         let mut world = World::new();
         let entity_id = world.spawn_empty().id();
-        let minecraft_id = entity_id.minecraft_id();
-        assert_eq!(
-            Entity::from_minecraft_id(minecraft_id, &world).unwrap(),
-            entity_id
-        );
+        assert_eq!(entity_id.id(), entity_id.index().index());
     }
 }
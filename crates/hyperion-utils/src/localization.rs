@@ -0,0 +1,436 @@
+//! Translations of the messages sent to players. See [`Translations`].
+//!
+//! Translations are TOML files named after the language they contain, such as `de.toml`, which map
+//! keys to templates. Nested tables are joined with dots, so `duplicate_login` in a `[kick]` table
+//! has the key `kick.duplicate_login`.
+//!
+//! Templates contain placeholders for ordered arguments, such as `{0}`, and for named arguments,
+//! such as `{name}`. `{{` and `}}` are literal braces. Arguments are inserted as plain text: their
+//! `§` formatting codes are removed, and the message is sent as a text component rather than
+//! parsed as JSON, so arguments cannot change the formatting of the rest of the message.
+
+use std::{collections::HashMap, fmt::Display, path::Path};
+
+use bevy_ecs::{
+    component::Component,
+    entity::Entity,
+    resource::Resource,
+    system::{Query, Res, SystemParam},
+};
+use valence_text::{IntoText, Text};
+#[cfg(feature = "reflect")]
+use {
+    bevy_ecs::reflect::{ReflectComponent, ReflectResource},
+    bevy_reflect::Reflect,
+};
+
+/// The language messages fall back to if they are not translated into the language of a player
+pub const DEFAULT_LANGUAGE: &str = "en";
+
+/// The language of a player's client, such as `de_at`, from the client settings
+#[derive(Component, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+pub struct Locale(String);
+
+impl Locale {
+    /// Creates a locale from the code a client sends, which is matched case-insensitively
+    #[must_use]
+    pub fn new(code: &str) -> Self {
+        Self(code.to_ascii_lowercase())
+    }
+
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Default for Locale {
+    fn default() -> Self {
+        Self::new("en_us")
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TranslationError {
+    #[error("failed to read translation file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid translation file: {0}")]
+    Toml(#[from] toml::de::Error),
+    #[error("translation file name {0:?} is not a language code")]
+    InvalidFileName(std::path::PathBuf),
+    #[error("translation {key:?} is not a string")]
+    NotAString { key: String },
+    #[error("translation {key:?} has an unclosed placeholder")]
+    UnclosedPlaceholder { key: String },
+    #[error("translation {key:?} has an unmatched closing brace")]
+    UnmatchedBrace { key: String },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Ordered(usize),
+    Named(String),
+}
+
+/// A parsed translation
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Template(Vec<Segment>);
+
+impl Template {
+    fn parse(key: &str, template: &str) -> Result<Self, TranslationError> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => {
+                                return Err(TranslationError::UnclosedPlaceholder {
+                                    key: key.to_owned(),
+                                });
+                            }
+                        }
+                    }
+
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+
+                    segments.push(match name.parse::<usize>() {
+                        Ok(index) => Segment::Ordered(index),
+                        Err(_) => Segment::Named(name),
+                    });
+                }
+                '}' => {
+                    return Err(TranslationError::UnmatchedBrace {
+                        key: key.to_owned(),
+                    });
+                }
+                c => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        Ok(Self(segments))
+    }
+
+    /// Fills in the placeholders. Placeholders without an argument are kept as they are, so that
+    /// the mistake shows up in the message.
+    fn render(&self, args: &Args) -> String {
+        let mut out = String::new();
+
+        for segment in &self.0 {
+            match segment {
+                Segment::Literal(literal) => out.push_str(literal),
+                Segment::Ordered(index) => match args.ordered.get(*index) {
+                    Some(value) => out.push_str(value),
+                    None => out.push_str(&format!("{{{index}}}")),
+                },
+                Segment::Named(name) => {
+                    let value = args
+                        .named
+                        .iter()
+                        .find(|(arg_name, _)| arg_name == name)
+                        .map(|(_, value)| value);
+
+                    match value {
+                        Some(value) => out.push_str(value),
+                        None => out.push_str(&format!("{{{name}}}")),
+                    }
+                }
+            }
+        }
+
+        out
+    }
+}
+
+/// The arguments of a translated message
+#[derive(Clone, Debug, Default)]
+pub struct Args {
+    ordered: Vec<String>,
+    named: Vec<(String, String)>,
+}
+
+impl Args {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the next ordered argument, starting at `{0}`
+    #[must_use]
+    pub fn arg(mut self, value: impl Display) -> Self {
+        self.ordered.push(sanitize_arg(&value.to_string()));
+        self
+    }
+
+    /// Adds the argument for `{name}`
+    #[must_use]
+    pub fn named(mut self, name: &str, value: impl Display) -> Self {
+        self.named
+            .push((name.to_owned(), sanitize_arg(&value.to_string())));
+        self
+    }
+}
+
+/// Removes the formatting codes from an argument so that it cannot change the formatting of the
+/// message it is inserted into
+fn sanitize_arg(value: &str) -> String {
+    value.chars().filter(|&c| c != '§').collect()
+}
+
+/// The languages to look a message up in for `locale`, from the most to the least specific
+fn fallback_chain(locale: &str) -> impl Iterator<Item = &str> {
+    let language = locale.split_once('_').map(|(language, _)| language);
+    std::iter::once(locale)
+        .chain(language)
+        .chain(std::iter::once(DEFAULT_LANGUAGE))
+}
+
+/// The templates of every message, by language and key.
+///
+/// Crates add their messages in [`DEFAULT_LANGUAGE`] with [`Translations::add_toml`] when they are
+/// built, so that a message always has a translation even if no files are loaded. Translation
+/// files loaded later with [`Translations::load_dir`] replace these.
+#[derive(Resource, Default, Debug)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Resource))]
+pub struct Translations {
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    languages: HashMap<String, HashMap<String, Template>>,
+}
+
+impl Translations {
+    /// Adds the templates of `toml` to `language`, replacing existing templates with the same key
+    pub fn add_toml(&mut self, language: &str, toml: &str) -> Result<(), TranslationError> {
+        let table: toml::Table = toml::from_str(toml)?;
+        let mut templates = Vec::new();
+        flatten("", &table, &mut templates)?;
+
+        let language = self
+            .languages
+            .entry(language.to_ascii_lowercase())
+            .or_default();
+        for (key, template) in templates {
+            let template = Template::parse(&key, template)?;
+            language.insert(key, template);
+        }
+
+        Ok(())
+    }
+
+    /// Loads every `<language>.toml` file in `dir`
+    pub fn load_dir(&mut self, dir: impl AsRef<Path>) -> Result<(), TranslationError> {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|extension| extension != "toml") {
+                continue;
+            }
+
+            let Some(language) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                return Err(TranslationError::InvalidFileName(path));
+            };
+
+            self.add_toml(language, &std::fs::read_to_string(&path)?)?;
+        }
+
+        Ok(())
+    }
+
+    /// Translates the message `key` into `locale`, falling back to less specific languages and
+    /// finally [`DEFAULT_LANGUAGE`]. The key itself is returned if no language has the message.
+    #[must_use]
+    pub fn translate(&self, locale: &str, key: &str, args: &Args) -> String {
+        let locale = locale.to_ascii_lowercase();
+        let template = fallback_chain(&locale).find_map(|language| {
+            self.languages
+                .get(language)
+                .and_then(|templates| templates.get(key))
+        });
+
+        match template {
+            Some(template) => template.render(args),
+            None => {
+                tracing::warn!("missing translation for {key:?}");
+                key.to_owned()
+            }
+        }
+    }
+
+    /// Like [`Translations::translate`], but returns the message as text
+    #[must_use]
+    pub fn tr(&self, locale: &str, key: &str, args: &Args) -> Text {
+        self.translate(locale, key, args).into_text()
+    }
+}
+
+/// Collects the strings of `table` with their dotted keys
+fn flatten<'a>(
+    prefix: &str,
+    table: &'a toml::Table,
+    out: &mut Vec<(String, &'a str)>,
+) -> Result<(), TranslationError> {
+    for (key, value) in table {
+        let key = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{prefix}.{key}")
+        };
+
+        match value {
+            toml::Value::String(template) => out.push((key, template.as_str())),
+            toml::Value::Table(table) => flatten(&key, table, out)?,
+            _ => return Err(TranslationError::NotAString { key }),
+        }
+    }
+
+    Ok(())
+}
+
+/// Whose language to translate a message into
+#[derive(Copy, Clone, Debug)]
+pub enum Language<'a> {
+    /// The language of a player's [`Locale`], or [`DEFAULT_LANGUAGE`] if it is not known yet
+    Player(Entity),
+    /// A language code, such as `de_at`
+    Code(&'a str),
+}
+
+impl From<Entity> for Language<'_> {
+    fn from(player: Entity) -> Self {
+        Self::Player(player)
+    }
+}
+
+impl<'a> From<&'a str> for Language<'a> {
+    fn from(code: &'a str) -> Self {
+        Self::Code(code)
+    }
+}
+
+/// Translates messages into the language of players
+#[derive(SystemParam)]
+pub struct Localizer<'w, 's> {
+    translations: Res<'w, Translations>,
+    locales: Query<'w, 's, &'static Locale>,
+}
+
+impl Localizer<'_, '_> {
+    /// Translates the message `key` into the language of a player or a language code. See
+    /// [`Translations::translate`].
+    #[must_use]
+    pub fn tr<'a>(&self, language: impl Into<Language<'a>>, key: &str, args: &Args) -> Text {
+        let locale = match language.into() {
+            Language::Player(player) => self
+                .locales
+                .get(player)
+                .map_or(DEFAULT_LANGUAGE, Locale::as_str),
+            Language::Code(code) => code,
+        };
+
+        self.translations.tr(locale, key, args)
+    }
+
+    #[must_use]
+    pub fn translations(&self) -> &Translations {
+        &self.translations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn translations() -> Translations {
+        let mut translations = Translations::default();
+        translations
+            .add_toml(
+                "en",
+                r#"
+                greeting = "Hello {0}, you have {count} points"
+                [kick]
+                slow = "Connection too slow"
+                "#,
+            )
+            .unwrap();
+        translations
+            .add_toml("de", r#"greeting = "Hallo {0}, du hast {count} Punkte""#)
+            .unwrap();
+        translations
+            .add_toml("de_AT", r#"greeting = "Servus {0}, {count} Punkte""#)
+            .unwrap();
+        translations
+    }
+
+    #[test]
+    fn languages_fall_back_to_less_specific_ones() {
+        let translations = translations();
+        let args = Args::new().arg("Steve").named("count", 3);
+
+        assert_eq!(
+            translations.translate("de_AT", "greeting", &args),
+            "Servus Steve, 3 Punkte"
+        );
+        assert_eq!(
+            translations.translate("de_de", "greeting", &args),
+            "Hallo Steve, du hast 3 Punkte"
+        );
+        assert_eq!(
+            translations.translate("de_de", "kick.slow", &args),
+            "Connection too slow"
+        );
+        assert_eq!(
+            translations.translate("fr_fr", "greeting", &args),
+            "Hello Steve, you have 3 points"
+        );
+        assert_eq!(translations.translate("fr_fr", "missing", &args), "missing");
+    }
+
+    #[test]
+    fn placeholders_are_filled_in() {
+        let template = Template::parse("key", "{1}{{{0}}} {name} {unknown}").unwrap();
+        let args = Args::new().arg("a").arg("b").named("name", "c");
+        assert_eq!(template.render(&args), "b{a} c {unknown}");
+
+        assert!(matches!(
+            Template::parse("key", "{0"),
+            Err(TranslationError::UnclosedPlaceholder { .. })
+        ));
+        assert!(matches!(
+            Template::parse("key", "a}b"),
+            Err(TranslationError::UnmatchedBrace { .. })
+        ));
+    }
+
+    #[test]
+    fn arguments_cannot_inject_formatting() {
+        let translations = translations();
+        let args = Args::new()
+            .arg(r#"§kSteve"},{"text":"x","clickEvent":{}}"#)
+            .named("count", "{0}");
+
+        assert_eq!(
+            translations.translate("en", "greeting", &args),
+            r#"Hello kSteve"},{"text":"x","clickEvent":{}}, you have {0} points"#
+        );
+    }
+}
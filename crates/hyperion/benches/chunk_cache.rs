@@ -0,0 +1,69 @@
+//! Sending the same modified chunk to 100 players, with and without the chunk packet cache.
+
+use std::{hint::black_box, sync::Arc};
+
+use divan::Bencher;
+use glam::I16Vec2;
+use hyperion::{
+    Shared,
+    net::{Compose, DataBundle, IoBuf},
+    simulation::blocks::{
+        chunk::Column, generator::SuperflatPlusGenerator, packet_cache::ChunkPacketCache,
+    },
+};
+use valence_protocol::CompressionThreshold;
+
+const PLAYERS: usize = 100;
+
+fn main() {
+    divan::main();
+}
+
+fn compose() -> Compose {
+    Compose::new(
+        libdeflater::CompressionLvl::default(),
+        Arc::new(Shared {
+            compression_threshold: CompressionThreshold(256),
+            compression_level: libdeflater::CompressionLvl::default(),
+        }),
+        IoBuf::default(),
+    )
+}
+
+/// A chunk whose packet from loading is out of date
+fn modified_column() -> Column {
+    let mut column = Column::generate(I16Vec2::ZERO, &SuperflatPlusGenerator::new(42));
+    column.mark_dirty();
+    column
+}
+
+#[divan::bench]
+fn encode_per_player(bencher: Bencher<'_, '_>) {
+    let compose = compose();
+    let column = modified_column();
+
+    bencher.counter(PLAYERS).bench_local(|| {
+        for _ in 0..PLAYERS {
+            let mut bundle = DataBundle::new(&compose);
+            bundle.add_raw(&column.encode_packet().unwrap());
+            black_box(&bundle);
+        }
+    });
+}
+
+#[divan::bench]
+fn cached(bencher: Bencher<'_, '_>) {
+    let compose = compose();
+    let column = modified_column();
+    let mut cache = ChunkPacketCache::default();
+    assert!(cache.get(&column).is_none());
+    cache.wait_for_encoded();
+
+    bencher.counter(PLAYERS).bench_local(|| {
+        for _ in 0..PLAYERS {
+            let mut bundle = DataBundle::new(&compose);
+            bundle.add_raw(&cache.get(&column).unwrap());
+            black_box(&bundle);
+        }
+    });
+}
@@ -0,0 +1,92 @@
+//! Broadcasting 1 MB to 3 proxies. Every proxy used to be sent its own encoding of a broadcast,
+//! which made 3 copies of the data. A broadcast is now encoded once and shared by every proxy,
+//! except for the proxy of an excluded connection, which is sent a second copy. Run with
+//! `cargo bench --bench proxy_broadcast --features test-util` to see the allocations of each case.
+
+use std::sync::Arc;
+
+use bytes::Bytes;
+use divan::{AllocProfiler, Bencher};
+use hyperion::{
+    Shared,
+    net::{Compose, ConnectionId, IoBuf, ProxyId},
+};
+use tokio::sync::mpsc::UnboundedReceiver;
+use valence_bytes::CowBytes;
+use valence_protocol::{CompressionThreshold, RawBytes, ident, packets::play};
+
+#[global_allocator]
+static ALLOC: AllocProfiler = AllocProfiler::system();
+
+const PROXIES: u64 = 3;
+
+/// The largest payload of a plugin message
+const PAYLOAD_LEN: usize = 1024 * 1024;
+
+fn main() {
+    divan::main();
+}
+
+fn compose() -> (Compose, Vec<UnboundedReceiver<Bytes>>) {
+    let mut io_buf = IoBuf::default();
+    let receivers = (0..PROXIES)
+        .map(|proxy_id| {
+            let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+            io_buf.add_test_proxy(ProxyId::new(proxy_id), tx);
+            rx
+        })
+        .collect();
+
+    // Compressing the payload would take longer than sending it
+    let compose = Compose::new(
+        libdeflater::CompressionLvl::default(),
+        Arc::new(Shared {
+            compression_threshold: CompressionThreshold(-1),
+            compression_level: libdeflater::CompressionLvl::default(),
+        }),
+        io_buf,
+    );
+
+    (compose, receivers)
+}
+
+/// Broadcasts the payload and returns the number of distinct buffers the proxies were sent
+fn broadcast(
+    compose: &Compose,
+    receivers: &mut [UnboundedReceiver<Bytes>],
+    payload: &[u8],
+    exclude: Option<ConnectionId>,
+) -> usize {
+    let pkt = play::CustomPayloadS2c {
+        channel: ident!("hyperion:bench"),
+        data: RawBytes(CowBytes::Borrowed(payload)).into(),
+    };
+    compose.broadcast(&pkt).exclude(exclude).send().unwrap();
+
+    let mut buffers: Vec<_> = receivers
+        .iter_mut()
+        .map(|rx| rx.try_recv().unwrap().as_ptr())
+        .collect();
+    buffers.sort_unstable();
+    buffers.dedup();
+    buffers.len()
+}
+
+#[divan::bench]
+fn shared_by_every_proxy(bencher: Bencher<'_, '_>) {
+    let (compose, mut receivers) = compose();
+    let payload = vec![7; PAYLOAD_LEN];
+    assert_eq!(broadcast(&compose, &mut receivers, &payload, None), 1);
+
+    bencher.bench_local(|| broadcast(&compose, &mut receivers, &payload, None));
+}
+
+#[divan::bench]
+fn excluding_a_connection(bencher: Bencher<'_, '_>) {
+    let (compose, mut receivers) = compose();
+    let payload = vec![7; PAYLOAD_LEN];
+    let exclude = Some(ConnectionId::new(1, ProxyId::new(1)));
+    assert_eq!(broadcast(&compose, &mut receivers, &payload, exclude), 2);
+
+    bencher.bench_local(|| broadcast(&compose, &mut receivers, &payload, exclude));
+}
@@ -0,0 +1,127 @@
+//! Allocations made while encoding the spawn packets of 1000 entities at once, such as when a
+//! player joins a crowded area. Run with `cargo bench --bench spawn_burst` to see the number of
+//! allocations of each approach. No proxy is connected, so only encoding is measured.
+
+use std::sync::Arc;
+
+use bytes::BytesMut;
+use divan::{AllocProfiler, Bencher};
+use hyperion::{
+    Shared,
+    net::{ChannelId, Compose, DataBundle, IoBuf},
+    simulation::Velocity,
+};
+use valence_bytes::CowBytes;
+use valence_protocol::{ByteAngle, CompressionThreshold, RawBytes, VarInt, packets::play};
+
+#[global_allocator]
+static ALLOC: AllocProfiler = AllocProfiler::system();
+
+const ENTITIES: u32 = 1000;
+
+/// The metadata of an entity which is on fire
+const METADATA: &[u8] = &[0, 0, 1, 0xff];
+
+fn main() {
+    divan::main();
+}
+
+fn compose() -> Compose {
+    Compose::new(
+        libdeflater::CompressionLvl::default(),
+        Arc::new(Shared {
+            compression_threshold: CompressionThreshold(256),
+            compression_level: libdeflater::CompressionLvl::default(),
+        }),
+        IoBuf::default(),
+    )
+}
+
+fn spawn_packets(
+    id: u32,
+) -> (
+    play::EntitySpawnS2c,
+    play::EntityVelocityUpdateS2c,
+    play::EntityTrackerUpdateS2c<'static>,
+) {
+    let entity_id = VarInt(i32::try_from(id).unwrap());
+    let velocity = Velocity::new(0.0, 1.0, 0.0).to_packet_units();
+
+    (
+        play::EntitySpawnS2c {
+            entity_id,
+            object_uuid: uuid::Uuid::from_u128(u128::from(id)),
+            kind: VarInt(0),
+            position: glam::DVec3::new(f64::from(id), 64.0, 0.0),
+            pitch: ByteAngle(0),
+            yaw: ByteAngle(0),
+            head_yaw: ByteAngle(0),
+            data: VarInt::default(),
+            velocity,
+        },
+        play::EntityVelocityUpdateS2c {
+            entity_id,
+            velocity,
+        },
+        play::EntityTrackerUpdateS2c {
+            entity_id,
+            tracked_values: RawBytes(CowBytes::Borrowed(METADATA)),
+        },
+    )
+}
+
+/// Encodes every packet into its own buffer and concatenates them, which is how bundles were
+/// built before they encoded into a single reused buffer
+#[divan::bench]
+fn owned_packets(bencher: Bencher<'_, '_>) {
+    let compose = compose();
+    let io_buf = compose.io_buf();
+
+    bencher.counter(ENTITIES).bench_local(|| {
+        for id in 0..ENTITIES {
+            let (spawn, velocity, metadata) = spawn_packets(id);
+            let mut data = BytesMut::new();
+            data.unsplit(io_buf.encode_packet(&spawn, &compose).unwrap());
+            data.unsplit(io_buf.encode_packet(&velocity, &compose).unwrap());
+            data.unsplit(io_buf.encode_packet(&metadata, &compose).unwrap());
+            divan::black_box(&data);
+        }
+    });
+}
+
+#[divan::bench]
+fn data_bundle(bencher: Bencher<'_, '_>) {
+    let compose = compose();
+
+    bencher.counter(ENTITIES).bench_local(|| {
+        for id in 0..ENTITIES {
+            let (spawn, velocity, metadata) = spawn_packets(id);
+            let mut bundle = DataBundle::new(&compose);
+            bundle.add_packet(&spawn).unwrap();
+            bundle.add_packet(&velocity).unwrap();
+            bundle.add_packet(&metadata).unwrap();
+            bundle.broadcast_channel(ChannelId::new(id)).unwrap();
+        }
+    });
+}
+
+#[divan::bench]
+fn broadcast_channel(bencher: Bencher<'_, '_>) {
+    let compose = compose();
+
+    bencher.counter(ENTITIES).bench_local(|| {
+        for id in 0..ENTITIES {
+            let (spawn, velocity, metadata) = spawn_packets(id);
+            let channel = ChannelId::new(id);
+            compose.broadcast_channel(&spawn, channel).send().unwrap();
+            compose
+                .broadcast_channel(&velocity, channel)
+                .send()
+                .unwrap();
+            compose
+                .broadcast_channel(&metadata, channel)
+                .send()
+                .unwrap();
+        }
+    });
+}
@@ -0,0 +1,104 @@
+//! The time of a tick with 5000 NPCs spread over 1000 × 1000 blocks around a single player, with
+//! and without [`TickRate`] scaling. Run with `cargo bench --bench tick_rate`.
+//!
+//! The work done for each NPC stands in for AI and physics.
+
+use bevy_app::{App, FixedMain, FixedUpdate};
+use bevy_ecs::{
+    entity::Entity,
+    query::With,
+    system::{Query, Res},
+};
+use divan::Bencher;
+use glam::Vec3;
+use hyperion::{
+    Tick,
+    simulation::{
+        EntitySize, Npc, Player, Position, Velocity,
+        tick_rate::{TickRate, TickRatePlugin, should_tick},
+    },
+    spatial::{Spatial, SpatialPlugin},
+    timings::TickTimings,
+};
+
+const NPCS: u32 = 5000;
+
+/// Half the side length of the area the NPCs are spread over
+const SPREAD: f32 = 500.0;
+
+fn main() {
+    divan::main();
+}
+
+/// Moves every NPC which is ticked this tick towards the origin with some drag and gravity
+fn think(
+    tick: Res<'_, Tick>,
+    mut npcs: Query<'_, '_, (Entity, &mut Position, &mut Velocity, Option<&TickRate>), With<Npc>>,
+) {
+    for (entity, mut position, mut velocity, tick_rate) in &mut npcs {
+        if !should_tick(tick_rate, tick.0, entity) {
+            continue;
+        }
+
+        let mut target = -**position;
+        for _ in 0..32 {
+            target = (target * 0.99 + Vec3::Y).normalize_or_zero();
+        }
+
+        velocity.0 = (velocity.0 + target * 0.1) * 0.98;
+        velocity.0.y -= 0.08;
+        **position += velocity.0;
+        position.y = position.y.max(0.0);
+    }
+}
+
+fn app(lod: bool) -> App {
+    let mut app = App::new();
+    app.insert_resource(Tick(0));
+    app.insert_resource(TickTimings::default());
+    app.add_plugins(SpatialPlugin);
+    app.add_systems(FixedUpdate, think);
+
+    if lod {
+        app.add_plugins(TickRatePlugin);
+    }
+
+    app.world_mut()
+        .spawn((Player, Position::new(0.0, 0.0, 0.0), EntitySize::default()));
+
+    let mut rng = fastrand::Rng::with_seed(0);
+    for _ in 0..NPCS {
+        let x = rng.f32().mul_add(2.0 * SPREAD, -SPREAD);
+        let z = rng.f32().mul_add(2.0 * SPREAD, -SPREAD);
+        let mut npc = app.world_mut().spawn((
+            Npc,
+            Spatial,
+            Position::new(x, 0.0, z),
+            Velocity::new(0.0, 0.0, 0.0),
+            EntitySize::default(),
+        ));
+
+        if lod {
+            npc.insert(TickRate::default());
+        }
+    }
+
+    app
+}
+
+fn tick(app: &mut App) {
+    FixedMain::run_fixed_main(app.world_mut());
+    app.world_mut().resource_mut::<Tick>().0 += 1;
+}
+
+#[divan::bench(args = [false, true])]
+fn tick_time(bencher: Bencher<'_, '_>, lod: bool) {
+    let mut app = app(lod);
+
+    // Runs a full scan so that the tick rates are calculated
+    for _ in 0..20 {
+        tick(&mut app);
+    }
+
+    bencher.bench_local(|| tick(&mut app));
+}
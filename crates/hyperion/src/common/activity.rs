@@ -0,0 +1,302 @@
+//! Saving power while nobody is online.
+//!
+//! Once the server has had no connections for [`IdleConfig::idle_after_secs`], it goes idle: it
+//! only updates about every [`IdleConfig::update_interval_ms`], each update runs at most one
+//! fixed tick, and systems which are only needed while players are online are skipped. These are
+//! added with the [`server_active`] run condition, or to the [`EntitySimulation`] set in the case
+//! of entity AI and physics. Systems which must always run, such as applying the
+//! [`CommandChannel`](crate::command_channel::CommandChannel) or saving scheduled block updates,
+//! simply do not use either.
+//!
+//! The proxy wakes the server through the [`IdleWaker`] as soon as a player connects, so the
+//! server is back at full speed long before the login of the player could time out.
+
+use std::{
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+use bevy_app::{App, First, FixedPostUpdate, FixedUpdate, Plugin};
+use bevy_ecs::{
+    resource::Resource,
+    schedule::{IntoScheduleConfigs, SystemSet},
+    system::{Res, ResMut},
+};
+use bevy_time::{Fixed, Time, TimeSystems, Virtual};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+#[cfg(feature = "reflect")]
+use {bevy_ecs::reflect::ReflectResource, bevy_reflect::Reflect};
+
+use crate::{
+    PlayerCount,
+    simulation::{StreamLookup, loading::world_ready},
+};
+
+/// When the server goes idle, see the [module documentation](self)
+#[derive(Resource, Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Resource))]
+#[serde(default)]
+pub struct IdleConfig {
+    /// Whether the server goes idle at all
+    pub enabled: bool,
+    /// How long the server has to be empty before it goes idle
+    pub idle_after_secs: u64,
+    /// How long the server waits between updates while idle
+    pub update_interval_ms: u64,
+}
+
+impl Default for IdleConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            idle_after_secs: 30,
+            update_interval_ms: 500,
+        }
+    }
+}
+
+/// Wakes up an idle server from another thread
+#[derive(Clone, Default, Debug)]
+pub struct IdleWaker {
+    /// Whether the server was woken up since it last checked
+    woken: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl IdleWaker {
+    /// Wakes up the server if it is idle, and keeps it awake for at least
+    /// [`IdleConfig::idle_after_secs`]
+    pub fn wake(&self) {
+        let (woken, condvar) = &*self.woken;
+        *woken.lock().unwrap() = true;
+        condvar.notify_all();
+    }
+
+    /// Waits until `timeout` passed or the server is woken up
+    fn sleep(&self, timeout: Duration) {
+        let (woken, condvar) = &*self.woken;
+        let woken = woken.lock().unwrap();
+        let _woken = condvar
+            .wait_timeout_while(woken, timeout, |woken| !*woken)
+            .unwrap();
+    }
+
+    /// Whether the server was woken up since this was last called
+    fn take(&self) -> bool {
+        std::mem::take(&mut *self.woken.0.lock().unwrap())
+    }
+}
+
+/// Whether the server is idle, see the [module documentation](self)
+#[derive(Resource, Clone, Debug, Default)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(opaque))]
+pub struct ServerActivity {
+    idle: bool,
+    /// When the server became empty, or [`None`] if somebody is online
+    empty_since: Option<Instant>,
+    /// The maximum delta of virtual time before the server went idle
+    max_delta: Option<Duration>,
+    waker: IdleWaker,
+}
+
+impl ServerActivity {
+    #[must_use]
+    pub const fn is_idle(&self) -> bool {
+        self.idle
+    }
+
+    /// The waker which the proxy uses to wake up the server when a player connects
+    #[must_use]
+    pub fn waker(&self) -> IdleWaker {
+        self.waker.clone()
+    }
+
+    /// Updates whether the server is idle at `now`. Returns whether this changed.
+    fn update(&mut self, config: &IdleConfig, empty: bool, now: Instant) -> bool {
+        let woken = self.waker.take();
+
+        if woken || !empty || !config.enabled {
+            self.empty_since = None;
+        } else if self.empty_since.is_none() {
+            self.empty_since = Some(now);
+        }
+
+        let idle = self.empty_since.is_some_and(|since| {
+            now.duration_since(since) >= Duration::from_secs(config.idle_after_secs)
+        });
+
+        let changed = idle != self.idle;
+        self.idle = idle;
+        changed
+    }
+}
+
+/// The systems which simulate entities, such as AI and physics, in [`FixedUpdate`] and
+/// [`FixedPostUpdate`]. They are skipped while the server is idle or the primary world is still
+/// [loading](crate::simulation::loading). Systems which tick distant entities less often also
+/// use [`should_tick`](crate::simulation::tick_rate::should_tick).
+#[derive(SystemSet, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct EntitySimulation;
+
+/// A run condition for systems which can be skipped while the server is idle
+#[must_use]
+pub fn server_active(activity: Option<Res<'_, ServerActivity>>) -> bool {
+    activity.is_none_or(|activity| !activity.idle)
+}
+
+fn update_activity(
+    mut activity: ResMut<'_, ServerActivity>,
+    config: Res<'_, IdleConfig>,
+    player_count: Res<'_, PlayerCount>,
+    streams: Res<'_, StreamLookup>,
+    mut virtual_time: ResMut<'_, Time<Virtual>>,
+    fixed_time: Res<'_, Time<Fixed>>,
+) {
+    // Connections which have not joined yet are not counted as players
+    let empty = player_count.get() == 0 && streams.is_empty();
+    if !activity.update(&config, empty, Instant::now()) {
+        return;
+    }
+
+    if activity.idle {
+        info!("nobody is online, going idle");
+        // One fixed tick per update, instead of catching up on the time spent sleeping
+        activity.max_delta = Some(virtual_time.max_delta());
+        virtual_time.set_max_delta(fixed_time.timestep());
+    } else {
+        info!("waking up");
+        if let Some(max_delta) = activity.max_delta.take() {
+            virtual_time.set_max_delta(max_delta);
+        }
+    }
+}
+
+fn sleep_while_idle(activity: Res<'_, ServerActivity>, config: Res<'_, IdleConfig>) {
+    if activity.idle {
+        activity
+            .waker
+            .sleep(Duration::from_millis(config.update_interval_ms));
+    }
+}
+
+/// Adds the [`ServerActivity`]. This is added by [`crate::HyperionCore`].
+pub struct ActivityPlugin;
+
+impl Plugin for ActivityPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<IdleConfig>();
+        app.init_resource::<ServerActivity>();
+        app.configure_sets(
+            FixedUpdate,
+            EntitySimulation.run_if(server_active).run_if(world_ready),
+        );
+        app.configure_sets(
+            FixedPostUpdate,
+            EntitySimulation.run_if(server_active).run_if(world_ready),
+        );
+        app.add_systems(
+            First,
+            (update_activity, sleep_while_idle)
+                .chain()
+                .before(TimeSystems),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::loading::MapLoadProgress;
+
+    #[test]
+    fn goes_idle_after_being_empty() {
+        let config = IdleConfig::default();
+        let mut activity = ServerActivity::default();
+        let start = Instant::now();
+        let idle_after = Duration::from_secs(config.idle_after_secs);
+
+        assert!(!activity.update(&config, true, start));
+        assert!(!activity.update(&config, true, start + idle_after / 2));
+        assert!(activity.update(&config, true, start + idle_after));
+        assert!(activity.is_idle());
+
+        // A player joining wakes the server up
+        assert!(activity.update(&config, false, start + idle_after * 2));
+        assert!(!activity.is_idle());
+
+        // The time until the server goes idle starts again once the server is empty
+        let empty = start + idle_after * 3;
+        assert!(!activity.update(&config, true, empty));
+        assert!(!activity.update(&config, true, empty + idle_after / 2));
+        assert!(activity.update(&config, true, empty + idle_after));
+    }
+
+    #[test]
+    fn wakes_up_before_the_player_joins() {
+        let config = IdleConfig::default();
+        let mut activity = ServerActivity::default();
+        let start = Instant::now();
+        let idle_after = Duration::from_secs(config.idle_after_secs);
+
+        activity.update(&config, true, start);
+        activity.update(&config, true, start + idle_after);
+        assert!(activity.is_idle());
+
+        let waker = activity.waker();
+        let sleeping = std::thread::spawn(move || {
+            let before = Instant::now();
+            activity.waker.sleep(Duration::from_secs(60));
+            (activity, before.elapsed())
+        });
+        waker.wake();
+
+        let (mut activity, slept) = sleeping.join().unwrap();
+        assert!(slept < Duration::from_secs(60));
+
+        // The player has not been spawned yet, but the server is awake
+        assert!(activity.update(&config, true, start + idle_after * 2));
+        assert!(!activity.is_idle());
+    }
+
+    #[test]
+    fn entity_simulation_is_skipped_while_idle_or_loading() {
+        #[derive(Resource, Default)]
+        struct Ticks(u32);
+
+        let mut app = App::new();
+        app.add_plugins(ActivityPlugin);
+        app.init_resource::<Ticks>();
+        app.add_systems(
+            FixedUpdate,
+            (|mut ticks: ResMut<'_, Ticks>| ticks.0 += 1).in_set(EntitySimulation),
+        );
+
+        let world = app.world_mut();
+        world.resource_mut::<ServerActivity>().idle = true;
+        world.run_schedule(FixedUpdate);
+        assert_eq!(world.resource::<Ticks>().0, 0);
+
+        world.resource_mut::<ServerActivity>().idle = false;
+        world.run_schedule(FixedUpdate);
+        assert_eq!(world.resource::<Ticks>().0, 1);
+
+        // Nor is anything simulated while the world is still loading
+        world.init_resource::<MapLoadProgress>();
+        world.run_schedule(FixedUpdate);
+        assert_eq!(world.resource::<Ticks>().0, 1);
+    }
+
+    #[test]
+    fn disabled_servers_never_go_idle() {
+        let config = IdleConfig {
+            enabled: false,
+            ..IdleConfig::default()
+        };
+        let mut activity = ServerActivity::default();
+        let start = Instant::now();
+
+        activity.update(&config, true, start);
+        activity.update(&config, true, start + Duration::from_secs(3600));
+        assert!(!activity.is_idle());
+    }
+}
@@ -5,7 +5,10 @@
 use std::{
     mem::MaybeUninit,
     ptr::NonNull,
-    sync::{Arc, Mutex},
+    sync::{
+        Arc, Condvar, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
 };
 
 use bevy_app::{App, Plugin, PreUpdate};
@@ -17,43 +20,44 @@ use bevy_ecs::{
 };
 #[cfg(feature = "reflect")]
 use bevy_reflect::Reflect;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
 use tracing::error;
 
+use crate::config::Config;
+
 struct CommandMeta {
     /// SAFETY: The `value` must point to a value of type `T: Command`,
     /// where `T` is some specific type that was used to produce this metadata.
     ///
-    /// Advances `cursor` by the size of `T` in bytes.
-    consume_command_and_get_size:
-        unsafe fn(value: OwningPtr<'_, Unaligned>, world: NonNull<World>, cursor: &mut usize),
+    /// Applies the command to `world`, or drops it if `world` is [`None`].
+    consume_command: unsafe fn(value: OwningPtr<'_, Unaligned>, world: Option<NonNull<World>>),
+    /// The size of `T` in bytes
+    size: usize,
 }
 
-#[derive(Default, Clone)]
-struct Inner {
+/// Densely stores a queue of heterogenous commands.
+///
+/// The queue is empty if and only if `bytes` is empty, in which case `head` is 0.
+#[derive(Default)]
+struct Queue {
     // This buffer densely stores all queued commands.
     //
     // For each command, one `CommandMeta` is stored, followed by zero or more bytes
     // to store the command itself. To interpret these bytes, a pointer must
-    // be passed to the corresponding `CommandMeta.apply_command_and_get_size` fn pointer.
+    // be passed to the corresponding `CommandMeta.consume_command` fn pointer.
     //
     // This is implemented via a `Vec<MaybeUninit<u8>>` instead of a `Vec<Box<dyn Command>>` as an
     // optimization.
-    pub(crate) bytes: Vec<MaybeUninit<u8>>,
-    pub(crate) cursor: usize,
-}
-
-/// Densely and efficiently stores a multiple-producer single-consumer channel of heterogenous types implementing [`Command`].
-#[derive(Resource, Default, Clone)]
-#[cfg_attr(feature = "reflect", derive(Reflect), reflect(opaque))]
-pub struct CommandChannel {
-    // TODO: Replace this Mutex with a lock-free alternative
-    inner: Arc<Mutex<Inner>>,
+    bytes: Vec<MaybeUninit<u8>>,
+    /// The offset of the first command that has not been consumed yet
+    head: usize,
+    /// The number of queued commands
+    len: usize,
 }
 
-impl CommandChannel {
-    /// Push a [`Command`] onto the channel.
-    #[inline]
-    pub fn push<C: Command>(&self, command: C) {
+impl Queue {
+    fn push<C: Command>(&mut self, command: C) {
         // Stores a command alongside its metadata.
         // `repr(C)` prevents the compiler from reordering the fields,
         // while `repr(packed)` prevents the compiler from inserting padding bytes.
@@ -63,33 +67,32 @@ impl CommandChannel {
             command: C,
         }
 
-        let mut inner = self.inner.lock().unwrap();
-
         let meta = CommandMeta {
-            consume_command_and_get_size: |command, mut world, cursor| {
-                *cursor += size_of::<C>();
-                // SAFETY: According to the invariants of `CommandMeta.consume_command_and_get_size`,
+            consume_command: |command, world| {
+                // SAFETY: According to the invariants of `CommandMeta.consume_command`,
                 // `command` must point to a value of type `C`.
                 let command: C = unsafe { command.read_unaligned() };
+                let Some(mut world) = world else {
+                    return;
+                };
                 // Apply command to the provided world
                 // SAFETY: Caller ensures pointer is not null
                 let world = unsafe { world.as_mut() };
                 command.apply(world);
                 // The command may have queued up world commands, which we flush here to ensure they are also picked up.
-                // If the current command queue already the World Command queue, this will still behave appropriately because the global cursor
-                // is still at the current `stop`, ensuring only the newly queued Commands will be applied.
                 world.flush();
             },
+            size: size_of::<C>(),
         };
 
-        let old_len = inner.bytes.len();
+        let old_len = self.bytes.len();
 
         // Reserve enough bytes for both the metadata and the command itself.
-        inner.bytes.reserve(size_of::<Packed<C>>());
+        self.bytes.reserve(size_of::<Packed<C>>());
 
         // Pointer to the bytes at the end of the buffer.
         // SAFETY: We know it is within bounds of the allocation, due to the call to `.reserve()`.
-        let ptr = unsafe { inner.bytes.as_mut_ptr().add(old_len) };
+        let ptr = unsafe { self.bytes.as_mut_ptr().add(old_len) };
         // Write the metadata into the buffer, followed by the command.
         // We are using a packed struct to write them both as one operation.
         // SAFETY: `ptr` must be non-null, since it is within a non-null buffer.
@@ -104,74 +107,354 @@ impl CommandChannel {
         // SAFETY: The new length is guaranteed to fit in the vector's capacity,
         // due to the call to `.reserve()` above.
         unsafe {
-            inner.bytes.set_len(old_len + size_of::<Packed<C>>());
+            self.bytes.set_len(old_len + size_of::<Packed<C>>());
         }
+
+        self.len += 1;
     }
 
-    /// Execute the queued [`Command`]s in the world after applying any commands in the world's internal queue.
+    /// Reads the metadata of the command at `offset`
+    ///
+    /// # Safety
+    /// `offset` must be the offset of a queued command
+    unsafe fn meta_at(&self, offset: usize) -> CommandMeta {
+        // SAFETY: The caller ensures that the offset points to the start of a command, which
+        // begins with its metadata
+        unsafe {
+            self.bytes
+                .as_ptr()
+                .add(offset)
+                .cast::<CommandMeta>()
+                .read_unaligned()
+        }
+    }
+
+    /// Removes the first command and applies it to `world`, or drops it if `world` is [`None`].
+    /// Returns `false` if the queue was empty.
+    ///
+    /// # Safety
+    /// `world` must be valid for mutable access for the duration of the call
+    unsafe fn consume_front(&mut self, world: Option<NonNull<World>>) -> bool {
+        if self.len == 0 {
+            return false;
+        }
+
+        // SAFETY: The queue is not empty, so `head` points to the start of a command.
+        let meta = unsafe { self.meta_at(self.head) };
+
+        // Construct an owned pointer to the command.
+        // SAFETY: `head` is advanced below, which guarantees that the command will not be
+        // observed again. `cmd` points to a valid address of a stored command, so it must be
+        // non-null.
+        let cmd = unsafe {
+            OwningPtr::<'_, Unaligned>::new(NonNull::new_unchecked(
+                self.bytes
+                    .as_mut_ptr()
+                    .add(self.head + size_of::<CommandMeta>())
+                    .cast(),
+            ))
+        };
+
+        self.head += size_of::<CommandMeta>() + meta.size;
+        self.len -= 1;
+        if self.len == 0 {
+            // SAFETY: No commands are left. The bytes of the command being consumed stay in the
+            // allocation, and nothing can be pushed onto this queue before it is read.
+            unsafe { self.bytes.set_len(0) };
+            self.head = 0;
+        }
+
+        // SAFETY: The data underneath the pointer must correspond to the type erased in metadata,
+        // since they were stored next to each other by `.push()`. The caller ensures that `world`
+        // is valid.
+        unsafe { (meta.consume_command)(cmd, world) };
+        true
+    }
+
+    /// Moves up to `count` commands from the front of this queue to the back of `other`
+    fn move_front(&mut self, other: &mut Self, count: usize) {
+        if count >= self.len && other.len == 0 {
+            std::mem::swap(self, other);
+            return;
+        }
+
+        let mut end = self.head;
+        let mut moved = 0;
+        while moved < count && moved < self.len {
+            // SAFETY: Fewer than `len` commands have been skipped, so `end` points to the start of
+            // a command.
+            let meta = unsafe { self.meta_at(end) };
+            end += size_of::<CommandMeta>() + meta.size;
+            moved += 1;
+        }
+
+        // Commands can be moved by copying their bytes, like any other Rust value
+        other.bytes.extend_from_slice(&self.bytes[self.head..end]);
+        other.len += moved;
+        self.head = end;
+        self.len -= moved;
+
+        if self.len == 0 {
+            self.bytes.clear();
+            self.head = 0;
+        } else if self.head > self.bytes.len() / 2 {
+            // Reclaim the space of the moved commands once they make up most of the buffer
+            self.bytes.copy_within(self.head.., 0);
+            self.bytes.truncate(self.bytes.len() - self.head);
+            self.head = 0;
+        }
+    }
+}
+
+impl Drop for Queue {
+    fn drop(&mut self) {
+        // SAFETY: No world is accessed when dropping commands
+        while unsafe { self.consume_front(None) } {}
+    }
+}
+
+/// What happens when a command is pushed onto a full [`CommandChannel`]
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect))]
+pub enum OverflowPolicy {
+    /// The sender waits until the next tick has made room
+    #[default]
+    Block,
+    /// The oldest queued command is dropped to make room
+    DropOldest,
+    /// The new command is dropped
+    DropNewest,
+}
+
+/// The configuration of the [`CommandChannel`]
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect))]
+#[serde(default)]
+pub struct CommandChannelConfig {
+    /// The maximum number of queued commands, not counting priority commands
+    pub capacity: usize,
+    /// What happens when a command is pushed while `capacity` commands are queued
+    pub overflow: OverflowPolicy,
+    /// The maximum number of commands applied per update, not counting priority commands. The
+    /// remaining commands are applied in later updates.
+    pub drain_budget: usize,
+    /// The maximum number of queued priority commands. Priority commands are never dropped, so
+    /// senders wait for room regardless of `overflow`.
+    pub priority_capacity: usize,
+}
+
+impl Default for CommandChannelConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 1 << 16,
+            overflow: OverflowPolicy::Block,
+            drain_budget: 1 << 14,
+            priority_capacity: 1 << 12,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Queues {
+    priority: Queue,
+    normal: Queue,
+}
+
+struct Shared {
+    queues: Mutex<Queues>,
+    /// Wakes up threads blocked in [`CommandChannel::push`] when commands are drained
+    drained: Condvar,
+    /// Wakes up tasks waiting in [`CommandChannel::send`] when commands are drained
+    drained_async: Notify,
+    config: CommandChannelConfig,
+    dropped: AtomicU64,
+}
+
+/// Densely and efficiently stores a bounded multiple-producer single-consumer channel of heterogenous types implementing [`Command`].
+///
+/// Commands that must never be dropped or delayed, such as players connecting or disconnecting,
+/// should be sent with [`CommandChannel::send_priority`] instead. These are stored in a
+/// separate queue that is always applied first in full.
+#[derive(Resource, Clone)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(opaque))]
+pub struct CommandChannel {
+    shared: Arc<Shared>,
+}
+
+impl Default for CommandChannel {
+    fn default() -> Self {
+        Self::new(CommandChannelConfig::default())
+    }
+}
+
+impl CommandChannel {
+    #[must_use]
+    pub fn new(config: CommandChannelConfig) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                queues: Mutex::default(),
+                drained: Condvar::new(),
+                drained_async: Notify::new(),
+                config,
+                dropped: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    #[must_use]
+    pub fn config(&self) -> &CommandChannelConfig {
+        &self.shared.config
+    }
+
+    /// Push a [`Command`] onto the channel.
+    ///
+    /// If the channel is full and the overflow policy is [`OverflowPolicy::Block`], this blocks
+    /// the current thread until the channel has been drained. Async tasks should use
+    /// [`CommandChannel::send`] instead.
+    pub fn push<C: Command>(&self, command: C) {
+        let capacity = self.shared.config.capacity;
+        let mut queues = self.shared.queues.lock().unwrap();
+
+        if queues.normal.len >= capacity {
+            match self.shared.config.overflow {
+                OverflowPolicy::Block => {
+                    queues = self
+                        .shared
+                        .drained
+                        .wait_while(queues, |queues| queues.normal.len >= capacity)
+                        .unwrap();
+                }
+                OverflowPolicy::DropOldest => {
+                    // SAFETY: No world is accessed when dropping commands
+                    unsafe { queues.normal.consume_front(None) };
+                    self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                OverflowPolicy::DropNewest => {
+                    self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+            }
+        }
+
+        queues.normal.push(command);
+    }
+
+    /// Push a [`Command`] onto the channel, waiting for room without blocking the thread if the
+    /// channel is full and the overflow policy is [`OverflowPolicy::Block`]
+    pub async fn send<C: Command>(&self, command: C) {
+        if self.shared.config.overflow != OverflowPolicy::Block {
+            self.push(command);
+            return;
+        }
+
+        loop {
+            // This is created before checking the length so that a drain in between is not missed
+            let drained = self.shared.drained_async.notified();
+
+            {
+                let mut queues = self.shared.queues.lock().unwrap();
+                if queues.normal.len < self.shared.config.capacity {
+                    queues.normal.push(command);
+                    return;
+                }
+            }
+
+            drained.await;
+        }
+    }
+
+    /// Push a [`Command`] onto the priority queue, which is always applied before any other
+    /// command.
+    ///
+    /// If [`CommandChannelConfig::priority_capacity`] commands are queued, this blocks the
+    /// current thread until the channel has been drained. Async tasks should use
+    /// [`CommandChannel::send_priority`] instead.
+    pub fn push_priority<C: Command>(&self, command: C) {
+        let capacity = self.shared.config.priority_capacity;
+        let queues = self.shared.queues.lock().unwrap();
+        let mut queues = self
+            .shared
+            .drained
+            .wait_while(queues, |queues| queues.priority.len >= capacity)
+            .unwrap();
+        queues.priority.push(command);
+    }
+
+    /// Push a [`Command`] onto the priority queue, waiting for room without blocking the thread
+    /// if [`CommandChannelConfig::priority_capacity`] commands are queued
+    pub async fn send_priority<C: Command>(&self, command: C) {
+        loop {
+            // This is created before checking the length so that a drain in between is not missed
+            let drained = self.shared.drained_async.notified();
+
+            {
+                let mut queues = self.shared.queues.lock().unwrap();
+                if queues.priority.len < self.shared.config.priority_capacity {
+                    queues.priority.push(command);
+                    return;
+                }
+            }
+
+            drained.await;
+        }
+    }
+
+    /// The number of queued commands, not counting priority commands
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.shared.queues.lock().unwrap().normal.len
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of queued priority commands
+    #[must_use]
+    pub fn priority_len(&self) -> usize {
+        self.shared.queues.lock().unwrap().priority.len
+    }
+
+    /// The number of commands dropped because the channel was full
+    #[must_use]
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Execute all queued [`Command`]s in the world after applying any commands in the world's internal queue.
     /// This clears the channel.
     #[inline]
     pub fn apply(&self, world: &mut World) {
-        world.flush();
-        self.apply_or_drop_queued(world.into());
+        self.apply_at_most(world, usize::MAX);
     }
 
-    /// This will apply the queued [commands](`Command`).
-    /// This clears the channel.
-    #[inline]
-    fn apply_or_drop_queued(&self, world: NonNull<World>) {
-        let mut inner = self.inner.lock().unwrap();
-
-        // SAFETY: If this is the command queue on world, world will not be dropped as we have a mutable reference
-        // If this is not the command queue on world we have exclusive ownership and self will not be mutated
-        let start = inner.cursor;
-        let stop = inner.bytes.len();
-        let mut local_cursor = start;
-
-        // SAFETY: we are setting the global cursor to the current length to prevent the executing commands from applying
-        // the remaining commands currently in this list. This is safe.
-        inner.cursor = stop;
-
-        while local_cursor < stop {
-            // SAFETY: The cursor is either at the start of the buffer, or just after the previous command.
-            // Since we know that the cursor is in bounds, it must point to the start of a new command.
-            let meta = unsafe {
-                inner
-                    .bytes
-                    .as_mut_ptr()
-                    .add(local_cursor)
-                    .cast::<CommandMeta>()
-                    .read_unaligned()
-            };
-
-            // Advance to the bytes just after `meta`, which represent a type-erased command.
-            local_cursor += size_of::<CommandMeta>();
-
-            // Construct an owned pointer to the command.
-            // SAFETY: It is safe to transfer ownership out of `inner.bytes`, since the increment of `cursor` above
-            // guarantees that nothing stored in the buffer will get observed after this function ends.
-            // `cmd` points to a valid address of a stored command, so it must be non-null.
-            let cmd = unsafe {
-                OwningPtr::<'_, Unaligned>::new(NonNull::new_unchecked(
-                    inner.bytes.as_mut_ptr().add(local_cursor).cast(),
-                ))
-            };
-
-            // SAFETY: The data underneath the cursor must correspond to the type erased in metadata,
-            // since they were stored next to each other by `.push()`.
-            // For ZSTs, the type doesn't matter as long as the pointer is non-null.
-            // This also advances the cursor past the command. For ZSTs, the cursor will not move.
-            // At this point, it will either point to the next `CommandMeta`,
-            // or the cursor will be out of bounds and the loop will end.
-            unsafe { (meta.consume_command_and_get_size)(cmd, world, &mut local_cursor) };
-        }
-        // Reset the buffer: all commands past the original `start` cursor have been applied.
-        // SAFETY: we are setting the length of bytes to the original length, minus the length of the original
-        // list of commands being considered. All bytes remaining in the Vec are still valid, unapplied commands.
-        unsafe {
-            inner.bytes.set_len(start);
-            inner.cursor = start;
+    /// Execute all queued priority [`Command`]s followed by up to `budget` other commands, after
+    /// applying any commands in the world's internal queue. Returns the number of commands left
+    /// in the channel.
+    pub fn apply_at_most(&self, world: &mut World, budget: usize) -> usize {
+        world.flush();
+
+        // The commands are moved out of the channel before being applied so that senders are not
+        // blocked while they run, and so that commands can push more commands
+        let mut batch = Queue::default();
+        let remaining = {
+            let mut queues = self.shared.queues.lock().unwrap();
+            let Queues { priority, normal } = &mut *queues;
+            priority.move_front(&mut batch, usize::MAX);
+            normal.move_front(&mut batch, budget);
+            normal.len
         };
+
+        self.shared.drained.notify_all();
+        self.shared.drained_async.notify_waiters();
+
+        let world = NonNull::from(world);
+        // SAFETY: `world` comes from a mutable reference which is not used otherwise while the
+        // commands are applied
+        while unsafe { batch.consume_front(Some(world)) } {}
+
+        remaining
     }
 }
 
@@ -179,7 +462,12 @@ pub struct CommandChannelPlugin;
 
 impl Plugin for CommandChannelPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(CommandChannel::default());
+        let config = app
+            .world()
+            .get_resource::<Config>()
+            .map(|config| config.command_channel)
+            .unwrap_or_default();
+        app.insert_resource(CommandChannel::new(config));
         app.add_systems(PreUpdate, sync_command_channel);
     }
 }
@@ -190,5 +478,116 @@ fn sync_command_channel(world: &mut World) {
         return;
     };
     let channel = channel.clone();
-    channel.apply(world);
+    channel.apply_at_most(world, channel.config().drain_budget);
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_ecs::resource::Resource;
+
+    use super::*;
+
+    #[derive(Resource, Default)]
+    struct Applied(Vec<u32>);
+
+    fn record(channel: &CommandChannel, id: u32) {
+        channel.push(move |world: &mut World| world.resource_mut::<Applied>().0.push(id));
+    }
+
+    fn applied(world: &mut World) -> Vec<u32> {
+        std::mem::take(&mut world.resource_mut::<Applied>().0)
+    }
+
+    #[test]
+    fn budget_carries_over_and_priority_goes_first() {
+        let mut world = World::new();
+        world.init_resource::<Applied>();
+        let channel = CommandChannel::default();
+
+        for id in 0..5 {
+            record(&channel, id);
+        }
+        channel.push_priority(|world: &mut World| world.resource_mut::<Applied>().0.push(100));
+
+        assert_eq!(channel.apply_at_most(&mut world, 2), 3);
+        assert_eq!(applied(&mut world), [100, 0, 1]);
+
+        record(&channel, 5);
+        assert_eq!(channel.apply_at_most(&mut world, 2), 2);
+        assert_eq!(applied(&mut world), [2, 3]);
+
+        channel.apply(&mut world);
+        assert_eq!(applied(&mut world), [4, 5]);
+        assert!(channel.is_empty());
+    }
+
+    #[test]
+    fn overflow_drops_commands() {
+        let mut world = World::new();
+        world.init_resource::<Applied>();
+
+        for (overflow, expected) in [
+            (OverflowPolicy::DropOldest, [2, 3]),
+            (OverflowPolicy::DropNewest, [0, 1]),
+        ] {
+            let channel = CommandChannel::new(CommandChannelConfig {
+                capacity: 2,
+                overflow,
+                ..CommandChannelConfig::default()
+            });
+
+            for id in 0..4 {
+                record(&channel, id);
+            }
+            assert_eq!(channel.len(), 2);
+            assert_eq!(channel.dropped(), 2);
+
+            channel.apply(&mut world);
+            assert_eq!(applied(&mut world), expected);
+        }
+    }
+
+    #[test]
+    fn priority_senders_wait_for_room() {
+        let mut world = World::new();
+        world.init_resource::<Applied>();
+        let channel = CommandChannel::new(CommandChannelConfig {
+            priority_capacity: 1,
+            overflow: OverflowPolicy::DropNewest,
+            ..CommandChannelConfig::default()
+        });
+
+        channel.push_priority(|world: &mut World| world.resource_mut::<Applied>().0.push(0));
+        let sender = std::thread::spawn({
+            let channel = channel.clone();
+            move || {
+                channel
+                    .push_priority(|world: &mut World| world.resource_mut::<Applied>().0.push(1));
+            }
+        });
+
+        // The second command is only queued once the first one was drained
+        while channel.priority_len() == 1 && !sender.is_finished() {
+            channel.apply(&mut world);
+        }
+        sender.join().unwrap();
+        channel.apply(&mut world);
+
+        assert_eq!(applied(&mut world), [0, 1]);
+        assert_eq!(channel.dropped(), 0);
+    }
+
+    #[test]
+    fn dropped_commands_are_not_leaked() {
+        let value = Arc::new(());
+        let channel = CommandChannel::default();
+
+        for _ in 0..3 {
+            let value = Arc::clone(&value);
+            channel.push(move |_: &mut World| drop(value));
+        }
+        drop(channel);
+
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
 }
@@ -1,13 +1,40 @@
 //! Configuration for the server.
 
-use std::{fmt::Debug, fs::File, io::Read, path::Path};
+use std::{
+    fmt::Debug,
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
 
-use bevy_ecs::resource::Resource;
+use bevy_app::{App, FixedUpdate, Plugin};
+use bevy_ecs::{
+    change_detection::DetectChangesMut,
+    resource::Resource,
+    system::{Res, ResMut},
+};
 use serde::{Deserialize, Serialize};
 use tracing::{info, instrument, warn};
 #[cfg(feature = "reflect")]
 use {bevy_ecs::reflect::ReflectResource, bevy_reflect::Reflect};
 
+use crate::{
+    KeepAliveConfig, Tick,
+    activity::IdleConfig,
+    command_channel::CommandChannelConfig,
+    ingress::{
+        auth::AuthMode, forwarding::Forwarding, pending::PendingConnectionLimits,
+        virtual_host::VirtualHosts,
+    },
+    net::ConnectionLimits,
+    simulation::{
+        afk::AfkConfig,
+        blocks::CorruptChunkFallback,
+        loading::{EarlyJoins, MapSource},
+    },
+};
+
 /// The configuration for the server representing a `toml` file.
 #[derive(Serialize, Deserialize, Debug, Resource)]
 #[cfg_attr(feature = "reflect", derive(Reflect), reflect(Resource))]
@@ -17,6 +44,45 @@ pub struct Config {
     pub view_distance: i16,
     pub simulation_distance: i32,
     pub server_desc: String,
+    /// Whether players are authenticated with Mojang
+    #[serde(default)]
+    pub auth_mode: AuthMode,
+    /// How players connecting through Velocity or BungeeCord are identified
+    #[serde(default)]
+    pub forwarding: Forwarding,
+    /// What happens to players depending on the hostname they connect with
+    #[serde(default)]
+    pub virtual_hosts: VirtualHosts,
+    /// Limits on the commands queued by async tasks for the world
+    #[serde(default)]
+    pub command_channel: CommandChannelConfig,
+    /// Limits on the data queued for each connection
+    #[serde(default)]
+    pub connection_limits: ConnectionLimits,
+    /// Limits on connections which have not joined yet
+    #[serde(default)]
+    pub pending_connections: PendingConnectionLimits,
+    /// When idle players are marked as AFK and kicked
+    #[serde(default)]
+    pub afk: AfkConfig,
+    /// How often keep alives are sent and how long players have to answer them
+    #[serde(default)]
+    pub keep_alive: KeepAliveConfig,
+    /// When the server saves power because nobody is online
+    #[serde(default)]
+    pub idle: IdleConfig,
+    /// What happens to players who log in while the world is still loading
+    #[serde(default)]
+    pub early_joins: EarlyJoins,
+    /// Where the map is loaded from
+    #[serde(default)]
+    pub map: MapSource,
+    /// What is loaded instead of chunks of the map which are corrupt
+    #[serde(default)]
+    pub corrupt_chunks: CorruptChunkFallback,
+    /// The seed of the [`GameRng`](crate::GameRng). A random seed is used if this is not set.
+    #[serde(default)]
+    pub rng_seed: Option<u64>,
     pub spawn: Spawn,
 }
 
@@ -45,6 +111,19 @@ impl Default for Config {
             view_distance: 32,
             simulation_distance: 10,
             server_desc: "Hyperion Test Server".to_owned(),
+            auth_mode: AuthMode::default(),
+            forwarding: Forwarding::default(),
+            virtual_hosts: VirtualHosts::default(),
+            command_channel: CommandChannelConfig::default(),
+            connection_limits: ConnectionLimits::default(),
+            pending_connections: PendingConnectionLimits::default(),
+            afk: AfkConfig::default(),
+            keep_alive: KeepAliveConfig::default(),
+            idle: IdleConfig::default(),
+            early_joins: EarlyJoins::default(),
+            map: MapSource::default(),
+            corrupt_chunks: CorruptChunkFallback::default(),
+            rng_seed: None,
             spawn: Spawn::default(),
         }
     }
@@ -71,11 +150,7 @@ impl Config {
         info!("loading configuration file");
 
         if path.as_ref().exists() {
-            let mut file = File::open(path)?;
-            let mut contents = String::default();
-            file.read_to_string(&mut contents)?;
-            let config = toml::from_str::<Self>(contents.as_str())?;
-            return Ok(config);
+            return Self::read(path.as_ref());
         }
 
         info!("configuration file not found, using defaults");
@@ -102,4 +177,137 @@ impl Config {
 
         Ok(Self::default())
     }
+
+    /// Reads and validates the configuration file at `path`
+    fn read(path: &Path) -> anyhow::Result<Self> {
+        let mut file = File::open(path)?;
+        let mut contents = String::default();
+        file.read_to_string(&mut contents)?;
+        let config = toml::from_str::<Self>(contents.as_str())?;
+        config.keep_alive.validate()?;
+        Ok(config)
+    }
+}
+
+/// How often [`ConfigReloadPlugin`] checks whether the configuration file was modified
+const RELOAD_CHECK_TICKS: i64 = 20;
+
+/// The configuration file and when it was last modified
+#[derive(Resource, Debug)]
+struct ConfigFile {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Reloads the configuration file whenever it is modified and applies the settings which can be
+/// changed at runtime, which is the [`KeepAliveConfig`]. Other settings only take effect once the
+/// server is restarted.
+///
+/// A file which fails to load is ignored, so the previous settings are kept until it is fixed.
+pub struct ConfigReloadPlugin {
+    pub path: PathBuf,
+}
+
+impl Plugin for ConfigReloadPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(ConfigFile {
+            modified: modified(&self.path),
+            path: self.path.clone(),
+        });
+        app.add_systems(FixedUpdate, reload_config);
+    }
+}
+
+fn reload_config(
+    tick: Res<'_, Tick>,
+    mut file: ResMut<'_, ConfigFile>,
+    mut config: ResMut<'_, Config>,
+    mut keep_alive: ResMut<'_, KeepAliveConfig>,
+) {
+    if tick.0 % RELOAD_CHECK_TICKS != 0 {
+        return;
+    }
+
+    let modified = modified(&file.path);
+    if modified == file.modified {
+        return;
+    }
+    file.modified = modified;
+
+    let reloaded = match Config::read(&file.path) {
+        Ok(reloaded) => reloaded,
+        Err(e) => {
+            warn!(
+                "failed to reload {:?}, keeping the previous settings: {e}",
+                file.path
+            );
+            return;
+        }
+    };
+
+    info!("reloaded {:?}", file.path);
+    config.keep_alive = reloaded.keep_alive;
+    keep_alive.set_if_neq(reloaded.keep_alive);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app(path: &Path) -> App {
+        let mut app = App::new();
+        app.init_resource::<Tick>();
+        app.insert_resource(Config::default());
+        app.insert_resource(KeepAliveConfig::default());
+        app.add_plugins(ConfigReloadPlugin {
+            path: path.to_owned(),
+        });
+        app
+    }
+
+    fn write(path: &Path, keep_alive: KeepAliveConfig) {
+        let config = Config {
+            keep_alive,
+            ..Config::default()
+        };
+        std::fs::write(path, toml::to_string(&config).unwrap()).unwrap();
+
+        // Make sure the modification time changes even on file systems with a coarse resolution
+        let file = File::options().write(true).open(path).unwrap();
+        let modified = file.metadata().unwrap().modified().unwrap();
+        file.set_modified(modified + std::time::Duration::from_secs(1))
+            .unwrap();
+    }
+
+    #[test]
+    fn modified_keep_alive_settings_are_applied() {
+        let path = std::env::temp_dir().join(format!("hyperion-config-{}.toml", fastrand::u64(..)));
+        write(&path, KeepAliveConfig::default());
+        let mut app = app(&path);
+
+        let changed = KeepAliveConfig {
+            interval_secs: 2,
+            timeout_secs: 5,
+        };
+        write(&path, changed);
+        app.world_mut().run_schedule(FixedUpdate);
+        assert_eq!(*app.world().resource::<KeepAliveConfig>(), changed);
+        assert_eq!(app.world().resource::<Config>().keep_alive, changed);
+
+        // An invalid file keeps the previous settings
+        write(&path, KeepAliveConfig {
+            interval_secs: 5,
+            timeout_secs: 5,
+        });
+        app.world_mut().run_schedule(FixedUpdate);
+        assert_eq!(*app.world().resource::<KeepAliveConfig>(), changed);
+
+        std::fs::remove_file(&path).unwrap();
+    }
 }
@@ -0,0 +1,65 @@
+//! The deprecated [`Global`] facade over the resources that replaced it.
+#![allow(deprecated, reason = "the facade has to name itself")]
+
+use std::time::Duration;
+
+use bevy_ecs::system::{Res, SystemParam};
+
+use crate::{CombatConfig, KeepAliveConfig, PlayerCount, Shared, Tick, TickDuration, net::Compose};
+
+/// Read-only access to the resources that used to be fields of `Global`.
+///
+/// Systems should depend on the resource they need instead, which lets systems that only read
+/// the [`Tick`] run in parallel with systems that write the other resources.
+#[deprecated(
+    note = "use the `Tick`, `KeepAliveConfig`, `CombatConfig`, `TickDuration` and `PlayerCount` \
+            resources instead"
+)]
+#[derive(SystemParam)]
+pub struct Global<'w> {
+    pub tick: Res<'w, Tick>,
+    pub keep_alive: Res<'w, KeepAliveConfig>,
+    pub combat: Res<'w, CombatConfig>,
+    pub tick_duration: Res<'w, TickDuration>,
+    pub player_count: Res<'w, PlayerCount>,
+    pub compose: Res<'w, Compose>,
+}
+
+// These methods are named after the fields `Global` used to have
+impl Global<'_> {
+    /// The current tick of the game, see [`Tick`]
+    #[must_use]
+    pub fn tick(&self) -> i64 {
+        self.tick.0
+    }
+
+    /// See [`CombatConfig::max_hurt_resistant_time`]
+    #[must_use]
+    pub fn max_hurt_resistant_time(&self) -> u16 {
+        self.combat.max_hurt_resistant_time
+    }
+
+    /// See [`KeepAliveConfig::timeout`]
+    #[must_use]
+    pub fn keep_alive_timeout(&self) -> Duration {
+        self.keep_alive.timeout()
+    }
+
+    /// See [`TickDuration::ms_last_tick`]
+    #[must_use]
+    pub fn ms_last_tick(&self) -> f32 {
+        self.tick_duration.ms_last_tick
+    }
+
+    /// The number of players in the play state, see [`PlayerCount`]
+    #[must_use]
+    pub fn player_count(&self) -> usize {
+        self.player_count.get()
+    }
+
+    /// Data shared between the IO thread and the ECS framework, see [`Compose::shared`]
+    #[must_use]
+    pub fn shared(&self) -> &Shared {
+        self.compose.shared()
+    }
+}
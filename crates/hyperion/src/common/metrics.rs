@@ -0,0 +1,538 @@
+//! A Prometheus endpoint for monitoring the server. See [`MetricsPlugin`].
+
+use std::{
+    fmt::Write as _,
+    net::SocketAddr,
+    sync::{Arc, Mutex, atomic::Ordering},
+    time::Duration,
+};
+
+use bevy_app::{App, AppExit, Last, Plugin};
+use bevy_ecs::{message::MessageReader, resource::Resource, system::Res, world::World};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::watch,
+};
+use tracing::{error, info, warn};
+
+use crate::{
+    PlayerCount, ProxyPlayerCounts, ServerInfo, Tick,
+    command_channel::CommandChannel,
+    ingress::counters::play_packet_name,
+    net::{Compose, metrics::NetworkMetrics},
+    runtime::AsyncRuntime,
+    simulation::{
+        blocks::{Blocks, packet_cache::ChunkPacketCacheStats},
+        world::Worlds,
+    },
+};
+
+/// The upper bounds of the tick duration histogram buckets in milliseconds
+const TICK_MS_BUCKETS: [f64; 9] = [1.0, 2.5, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 1000.0];
+
+/// How long a client may take to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Serves `/metrics` in the Prometheus text format.
+///
+/// The metrics are collected into a [`MetricsSnapshot`] at the end of every frame, so scrapes
+/// never wait for the world. The endpoint stops when [`AppExit`] is written.
+///
+/// This must be added after [`crate::HyperionCore`].
+pub struct MetricsPlugin {
+    /// The address the HTTP listener binds to
+    pub address: SocketAddr,
+}
+
+impl Default for MetricsPlugin {
+    fn default() -> Self {
+        Self {
+            address: SocketAddr::from(([127, 0, 0, 1], 9464)),
+        }
+    }
+}
+
+impl Plugin for MetricsPlugin {
+    fn build(&self, app: &mut App) {
+        let snapshot = Arc::new(Mutex::new(Arc::new(MetricsSnapshot::default())));
+        let (shutdown, shutdown_rx) = watch::channel(false);
+
+        let runtime = app.world().resource::<AsyncRuntime>();
+        runtime.spawn(serve(self.address, Arc::clone(&snapshot), shutdown_rx));
+
+        app.insert_resource(Metrics {
+            snapshot,
+            tick_ms: Mutex::new(Histogram::default()),
+            shutdown,
+        });
+        app.add_systems(Last, (collect_metrics, stop_on_exit));
+    }
+}
+
+#[derive(Resource)]
+struct Metrics {
+    /// The latest snapshot, shared with the HTTP listener
+    snapshot: Arc<Mutex<Arc<MetricsSnapshot>>>,
+    tick_ms: Mutex<Histogram>,
+    shutdown: watch::Sender<bool>,
+}
+
+/// A cumulative histogram with the buckets in [`TICK_MS_BUCKETS`]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Histogram {
+    /// The number of observations in each bucket, plus one for observations above the last bucket
+    counts: [u64; TICK_MS_BUCKETS.len() + 1],
+    sum: f64,
+}
+
+impl Histogram {
+    pub fn observe(&mut self, value: f64) {
+        let bucket = TICK_MS_BUCKETS
+            .iter()
+            .position(|&bound| value <= bound)
+            .unwrap_or(TICK_MS_BUCKETS.len());
+        self.counts[bucket] += 1;
+        self.sum += value;
+    }
+
+    #[must_use]
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    fn render(&self, out: &mut String, name: &str) {
+        let mut cumulative = 0;
+        for (bound, count) in TICK_MS_BUCKETS.iter().zip(self.counts) {
+            cumulative += count;
+            writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {cumulative}").unwrap();
+        }
+        writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {}", self.count()).unwrap();
+        writeln!(out, "{name}_sum {}", self.sum).unwrap();
+        writeln!(out, "{name}_count {}", self.count()).unwrap();
+    }
+}
+
+/// The values served by the metrics endpoint
+#[derive(Clone, Debug, Default)]
+pub struct MetricsSnapshot {
+    pub tick: i64,
+    pub player_count: usize,
+    pub entity_count: u32,
+    pub loaded_chunks: usize,
+    /// Chunks of Anvil saves which could not be loaded, see
+    /// [`CorruptChunkFallback`](crate::simulation::blocks::CorruptChunkFallback)
+    pub corrupt_chunks: u64,
+    /// The chunk packet caches of every world combined
+    pub chunk_packet_cache: ChunkPacketCacheStats,
+    pub tick_ms: Histogram,
+    pub packets_received: u64,
+    pub bytes_received: u64,
+    pub bytes_sent: u64,
+    pub rejected_packets: u64,
+    pub deferrals: u64,
+    pub flood_kicks: u64,
+    pub slow_kicks: u64,
+    pub state_timeouts: u64,
+    pub ip_limit_rejections: u64,
+    pub proxy_handshakes: u64,
+    pub resumed_proxy_handshakes: u64,
+    /// The duration of the last TLS handshake with a proxy
+    pub last_proxy_handshake_ms: f64,
+    /// The number of commands waiting in the [`CommandChannel`], not counting priority commands
+    pub queued_commands: usize,
+    pub queued_priority_commands: usize,
+    /// The number of commands dropped because the [`CommandChannel`] was full
+    pub dropped_commands: u64,
+    /// The number of decoded packets of each type
+    pub decoded_packets: Vec<(&'static str, u64)>,
+    /// The number of play packets received with each packet ID
+    pub play_packets: Vec<(i32, u64)>,
+    /// Each proxy and whether it is connected
+    pub proxies: Vec<(u64, bool)>,
+    /// Each proxy with players and its number of players in the play state
+    pub proxy_players: Vec<(u64, usize)>,
+}
+
+impl MetricsSnapshot {
+    /// Renders the snapshot in the Prometheus text exposition format
+    #[must_use]
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        let mut metric = |name: &str, kind: &str, help: &str, value: &dyn std::fmt::Display| {
+            writeln!(out, "# HELP {name} {help}").unwrap();
+            writeln!(out, "# TYPE {name} {kind}").unwrap();
+            writeln!(out, "{name} {value}").unwrap();
+        };
+
+        metric("hyperion_tick", "counter", "The current tick", &self.tick);
+        metric(
+            "hyperion_players",
+            "gauge",
+            "The number of players in the play state",
+            &self.player_count,
+        );
+        metric(
+            "hyperion_entities",
+            "gauge",
+            "The number of entities in the world",
+            &self.entity_count,
+        );
+        metric(
+            "hyperion_loaded_chunks",
+            "gauge",
+            "The number of chunks loaded in memory",
+            &self.loaded_chunks,
+        );
+        metric(
+            "hyperion_corrupt_chunks_total",
+            "counter",
+            "Chunks of the save which could not be loaded and were replaced",
+            &self.corrupt_chunks,
+        );
+        metric(
+            "hyperion_chunk_packet_cache_hits_total",
+            "counter",
+            "Chunks sent with an up to date cached packet",
+            &self.chunk_packet_cache.hits,
+        );
+        metric(
+            "hyperion_chunk_packet_cache_misses_total",
+            "counter",
+            "Chunks that had to be encoded again before being sent",
+            &self.chunk_packet_cache.misses,
+        );
+        metric(
+            "hyperion_chunk_packet_cache_hit_rate",
+            "gauge",
+            "The fraction of chunks sent with an up to date cached packet",
+            &self.chunk_packet_cache.hit_rate(),
+        );
+        metric(
+            "hyperion_chunk_packet_cache_bytes",
+            "gauge",
+            "Bytes of chunk packets in the cache",
+            &self.chunk_packet_cache.bytes,
+        );
+        metric(
+            "hyperion_packets_received_total",
+            "counter",
+            "Packets received from clients",
+            &self.packets_received,
+        );
+        metric(
+            "hyperion_bytes_received_total",
+            "counter",
+            "Bytes received from clients",
+            &self.bytes_received,
+        );
+        metric(
+            "hyperion_bytes_sent_total",
+            "counter",
+            "Bytes sent to proxies",
+            &self.bytes_sent,
+        );
+        metric(
+            "hyperion_rejected_packets_total",
+            "counter",
+            "Packets rejected for exceeding size limits",
+            &self.rejected_packets,
+        );
+        metric(
+            "hyperion_packet_deferrals_total",
+            "counter",
+            "Times a connection exceeded its per-tick packet budget",
+            &self.deferrals,
+        );
+        metric(
+            "hyperion_flood_kicks_total",
+            "counter",
+            "Connections kicked for sending too many packets",
+            &self.flood_kicks,
+        );
+        metric(
+            "hyperion_slow_kicks_total",
+            "counter",
+            "Connections kicked for not keeping up with the data sent to them",
+            &self.slow_kicks,
+        );
+        metric(
+            "hyperion_state_timeouts_total",
+            "counter",
+            "Connections disconnected for taking too long to handshake, ping or log in",
+            &self.state_timeouts,
+        );
+        metric(
+            "hyperion_ip_limit_rejections_total",
+            "counter",
+            "Connections refused because their IP address had too many pending connections",
+            &self.ip_limit_rejections,
+        );
+        metric(
+            "hyperion_proxy_handshakes_total",
+            "counter",
+            "TLS handshakes with proxies",
+            &self.proxy_handshakes,
+        );
+        metric(
+            "hyperion_resumed_proxy_handshakes_total",
+            "counter",
+            "TLS handshakes with proxies that resumed an earlier session",
+            &self.resumed_proxy_handshakes,
+        );
+        metric(
+            "hyperion_last_proxy_handshake_ms",
+            "gauge",
+            "Duration of the last TLS handshake with a proxy",
+            &self.last_proxy_handshake_ms,
+        );
+        metric(
+            "hyperion_queued_commands",
+            "gauge",
+            "Commands waiting to be applied to the world",
+            &self.queued_commands,
+        );
+        metric(
+            "hyperion_queued_priority_commands",
+            "gauge",
+            "Priority commands waiting to be applied to the world",
+            &self.queued_priority_commands,
+        );
+        metric(
+            "hyperion_dropped_commands_total",
+            "counter",
+            "Commands dropped because the command channel was full",
+            &self.dropped_commands,
+        );
+
+        out.push_str("# HELP hyperion_tick_duration_ms The duration of each tick\n");
+        out.push_str("# TYPE hyperion_tick_duration_ms histogram\n");
+        self.tick_ms.render(&mut out, "hyperion_tick_duration_ms");
+
+        out.push_str("# HELP hyperion_packets_decoded_total Decoded packets by type\n");
+        out.push_str("# TYPE hyperion_packets_decoded_total counter\n");
+        for (packet, count) in &self.decoded_packets {
+            writeln!(
+                out,
+                "hyperion_packets_decoded_total{{packet=\"{packet}\"}} {count}"
+            )
+            .unwrap();
+        }
+
+        out.push_str("# HELP hyperion_play_packets_total Received play packets by packet ID\n");
+        out.push_str("# TYPE hyperion_play_packets_total counter\n");
+        for &(id, count) in &self.play_packets {
+            let packet = play_packet_name(id).unwrap_or("unknown");
+            writeln!(
+                out,
+                "hyperion_play_packets_total{{id=\"{id:#04x}\",packet=\"{packet}\"}} {count}"
+            )
+            .unwrap();
+        }
+
+        out.push_str("# HELP hyperion_proxy_connected Whether a proxy is connected\n");
+        out.push_str("# TYPE hyperion_proxy_connected gauge\n");
+        for (proxy, connected) in &self.proxies {
+            writeln!(
+                out,
+                "hyperion_proxy_connected{{proxy=\"{proxy}\"}} {}",
+                u8::from(*connected)
+            )
+            .unwrap();
+        }
+
+        out.push_str("# HELP hyperion_proxy_players Players connected through a proxy\n");
+        out.push_str("# TYPE hyperion_proxy_players gauge\n");
+        for (proxy, players) in &self.proxy_players {
+            writeln!(out, "hyperion_proxy_players{{proxy=\"{proxy}\"}} {players}").unwrap();
+        }
+
+        out
+    }
+}
+
+fn collect_metrics(world: &World) {
+    let metrics = world.resource::<Metrics>();
+    let compose = world.resource::<Compose>();
+    let tick = world.resource::<Tick>().0;
+
+    let previous_tick = metrics.snapshot.lock().unwrap().tick;
+    let tick_ms = {
+        let mut tick_ms = metrics.tick_ms.lock().unwrap();
+        // Frames without a fixed update would otherwise observe the same tick again
+        if tick != previous_tick {
+            tick_ms.observe(f64::from(world.resource::<ServerInfo>().last_mspt()));
+        }
+        tick_ms.clone()
+    };
+
+    let mut loaded_chunks = 0;
+    let mut corrupt_chunks = 0;
+    let mut chunk_packet_cache = ChunkPacketCacheStats::default();
+    let worlds = world.get_resource::<Worlds>();
+    let all_blocks = worlds
+        .into_iter()
+        .flat_map(|worlds| worlds.iter().map(|(_, blocks)| blocks))
+        .chain(world.get_resource::<Blocks>());
+    for blocks in all_blocks {
+        loaded_chunks += blocks.loaded_chunk_count();
+        corrupt_chunks += blocks.corrupt_chunk_count();
+        chunk_packet_cache.add(blocks.packet_cache_stats());
+    }
+
+    let mut snapshot = MetricsSnapshot {
+        tick,
+        player_count: world.resource::<PlayerCount>().get(),
+        entity_count: world.entities().len(),
+        loaded_chunks,
+        corrupt_chunks,
+        chunk_packet_cache,
+        tick_ms,
+        bytes_sent: compose.io_buf().bytes_sent(),
+        proxies: compose
+            .io_buf()
+            .proxies()
+            .map(|(proxy_id, connected)| (proxy_id.inner(), connected))
+            .collect(),
+        proxy_players: world
+            .get_resource::<ProxyPlayerCounts>()
+            .into_iter()
+            .flat_map(ProxyPlayerCounts::iter)
+            .map(|(proxy_id, players)| (proxy_id.inner(), players))
+            .collect(),
+        ..MetricsSnapshot::default()
+    };
+
+    if let Some(network) = world.get_resource::<NetworkMetrics>() {
+        snapshot.packets_received = network.packets_received.load(Ordering::Relaxed);
+        snapshot.bytes_received = network.bytes_received.load(Ordering::Relaxed);
+        snapshot.rejected_packets = network.rejected_packets.load(Ordering::Relaxed);
+        snapshot.deferrals = network.deferrals.load(Ordering::Relaxed);
+        snapshot.flood_kicks = network.flood_kicks.load(Ordering::Relaxed);
+        snapshot.slow_kicks = network.slow_kicks.load(Ordering::Relaxed);
+        snapshot.state_timeouts = network.state_timeouts.load(Ordering::Relaxed);
+        snapshot.ip_limit_rejections = network.ip_limit_rejections.load(Ordering::Relaxed);
+        snapshot.proxy_handshakes = network.proxy_handshakes.load(Ordering::Relaxed);
+        snapshot.resumed_proxy_handshakes =
+            network.resumed_proxy_handshakes.load(Ordering::Relaxed);
+        #[expect(
+            clippy::cast_precision_loss,
+            reason = "handshakes are far shorter than 2^52 µs"
+        )]
+        let last_handshake_micros =
+            network.last_proxy_handshake_micros.load(Ordering::Relaxed) as f64;
+        snapshot.last_proxy_handshake_ms = last_handshake_micros / 1000.0;
+        snapshot.decoded_packets = network.decoded_packets();
+        snapshot.play_packets = network.play_packets();
+    }
+
+    if let Some(channel) = world.get_resource::<CommandChannel>() {
+        snapshot.queued_commands = channel.len();
+        snapshot.queued_priority_commands = channel.priority_len();
+        snapshot.dropped_commands = channel.dropped();
+    }
+
+    *metrics.snapshot.lock().unwrap() = Arc::new(snapshot);
+}
+
+fn stop_on_exit(mut exits: MessageReader<'_, '_, AppExit>, metrics: Res<'_, Metrics>) {
+    if exits.read().next().is_some() {
+        metrics.shutdown.send_replace(true);
+    }
+}
+
+async fn serve(
+    address: SocketAddr,
+    snapshot: Arc<Mutex<Arc<MetricsSnapshot>>>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let listener = match TcpListener::bind(address).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("failed to bind metrics endpoint to {address}: {e}");
+            return;
+        }
+    };
+
+    info!("serving metrics on http://{address}/metrics");
+
+    loop {
+        let stream = tokio::select! {
+            result = listener.accept() => match result {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    warn!("failed to accept metrics connection: {e}");
+                    continue;
+                }
+            },
+            // This also stops if the sender was dropped along with the world
+            _ = shutdown.wait_for(|&stop| stop) => break,
+        };
+
+        let snapshot = Arc::clone(&snapshot.lock().unwrap());
+        tokio::spawn(respond(stream, snapshot));
+    }
+
+    info!("metrics endpoint stopped");
+}
+
+async fn respond(mut stream: TcpStream, snapshot: Arc<MetricsSnapshot>) {
+    let mut request = [0; 1024];
+    let Ok(Ok(len)) = tokio::time::timeout(REQUEST_TIMEOUT, stream.read(&mut request)).await else {
+        return;
+    };
+
+    let request = &request[..len];
+    let response = if request.starts_with(b"GET /metrics ") {
+        let body = snapshot.render();
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: \
+             {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )
+    } else {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_owned()
+    };
+
+    if let Err(e) = stream.write_all(response.as_bytes()).await {
+        warn!("failed to send metrics: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_renders_prometheus_text() {
+        let mut tick_ms = Histogram::default();
+        tick_ms.observe(0.5);
+        tick_ms.observe(7.0);
+        tick_ms.observe(2000.0);
+
+        let snapshot = MetricsSnapshot {
+            player_count: 3,
+            tick_ms,
+            decoded_packets: vec![("play::KeepAlive", 12)],
+            play_packets: vec![(0x12, 12)],
+            proxies: vec![(0, true)],
+            proxy_players: vec![(0, 2)],
+            ..MetricsSnapshot::default()
+        };
+        let text = snapshot.render();
+
+        assert!(text.contains("# TYPE hyperion_players gauge\nhyperion_players 3\n"));
+        assert!(text.contains("hyperion_tick_duration_ms_bucket{le=\"1\"} 1\n"));
+        assert!(text.contains("hyperion_tick_duration_ms_bucket{le=\"10\"} 2\n"));
+        assert!(text.contains("hyperion_tick_duration_ms_bucket{le=\"1000\"} 2\n"));
+        assert!(text.contains("hyperion_tick_duration_ms_bucket{le=\"+Inf\"} 3\n"));
+        assert!(text.contains("hyperion_tick_duration_ms_count 3\n"));
+        assert!(text.contains("hyperion_packets_decoded_total{packet=\"play::KeepAlive\"} 12\n"));
+        assert!(
+            text.contains("hyperion_play_packets_total{id=\"0x12\",packet=\"KeepAlive\"} 12\n")
+        );
+        assert!(text.contains("hyperion_proxy_connected{proxy=\"0\"} 1\n"));
+        assert!(text.contains("hyperion_proxy_players{proxy=\"0\"} 2\n"));
+    }
+}
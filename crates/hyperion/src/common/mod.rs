@@ -1,19 +1,38 @@
-//! Defined the [`Global`] struct which is used to store global data which defines a [`crate::HyperionCore`]
+//! Resources which store global data which defines a [`crate::HyperionCore`], such as the [`Tick`]
 use std::{
-    sync::{Arc, atomic::AtomicUsize},
+    sync::atomic::{AtomicUsize, Ordering},
     time::Duration,
 };
 
-#[cfg(feature = "reflect")]
-use bevy_reflect::Reflect;
+use bevy_ecs::resource::Resource;
 use libdeflater::CompressionLvl;
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
 use valence_protocol::CompressionThreshold;
+#[cfg(feature = "reflect")]
+use {bevy_ecs::reflect::ReflectResource, bevy_reflect::Reflect};
 
+use crate::{ingress::pending::secs_to_ticks, net::ProxyId};
+
+pub mod activity;
 pub mod command_channel;
 pub mod config;
+mod global;
+pub mod metrics;
+pub mod rng;
 pub mod runtime;
+pub mod server_info;
+pub mod snapshot;
+pub mod timings;
+pub mod translations;
 pub mod util;
 
+#[allow(deprecated, reason = "the facade is re-exported until it is removed")]
+pub use global::Global;
+pub use rng::GameRng;
+pub use server_info::{MsptPercentiles, ServerInfo};
+
 /// Shared data that is shared between the ECS framework and the IO thread.
 #[cfg_attr(feature = "reflect", derive(Reflect))]
 pub struct Shared {
@@ -26,51 +45,167 @@ pub struct Shared {
     pub compression_level: CompressionLvl,
 }
 
-#[cfg_attr(feature = "reflect", derive(Reflect))]
-pub struct Global {
-    /// The current tick of the game. This is incremented every 50 ms.
-    pub tick: i64,
+#[cfg(feature = "reflect")]
+pub(crate) fn dummy_reflect_shared() -> std::sync::Arc<Shared> {
+    std::sync::Arc::new(Shared {
+        compression_threshold: CompressionThreshold::default(),
+        compression_level: CompressionLvl::default(),
+    })
+}
+
+/// The current tick of the game. This is incremented every 50 ms.
+#[derive(Resource, Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Resource))]
+pub struct Tick(pub i64);
+
+/// The number of ticks in a second
+pub const TICKS_PER_SECOND: i64 = 20;
 
+/// How often keep alives are sent to players, and how long the server waits for them to be
+/// answered. See [`keep_alive`](crate::simulation::keep_alive).
+///
+/// The resource may be changed at runtime, and is reloaded from the configuration file by
+/// [`ConfigReloadPlugin`](config::ConfigReloadPlugin) when it changes. Players are never kicked
+/// earlier than the previous timeout allowed for the keep alive they are answering, so reducing
+/// the timeout does not kick players right away.
+#[derive(Resource, Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Resource))]
+#[serde(default)]
+pub struct KeepAliveConfig {
+    /// How long to wait after sending a keep alive before sending the next one. Only one keep
+    /// alive is sent at a time, so the next one waits for the previous one to be answered.
+    pub interval_secs: u64,
+    /// How long after answering the last keep alive, or after entering the play state, a player
+    /// is kicked if they did not answer a newer one
+    pub timeout_secs: u64,
+}
+
+impl Default for KeepAliveConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: 10,
+            timeout_secs: 20,
+        }
+    }
+}
+
+/// A [`KeepAliveConfig`] which would kick players before the next keep alive is sent to them
+#[derive(Error, Copy, Clone, Debug, PartialEq, Eq)]
+#[error(
+    "the keep alive interval of {interval_secs}s must be shorter than the timeout of \
+     {timeout_secs}s"
+)]
+pub struct KeepAliveConfigError {
+    pub interval_secs: u64,
+    pub timeout_secs: u64,
+}
+
+impl KeepAliveConfig {
+    /// Checks that the interval is shorter than the timeout
+    pub const fn validate(&self) -> Result<(), KeepAliveConfigError> {
+        if self.interval_secs < self.timeout_secs {
+            Ok(())
+        } else {
+            Err(KeepAliveConfigError {
+                interval_secs: self.interval_secs,
+                timeout_secs: self.timeout_secs,
+            })
+        }
+    }
+
+    #[must_use]
+    pub const fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs)
+    }
+
+    #[must_use]
+    pub const fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_secs)
+    }
+
+    pub(crate) fn interval_ticks(&self) -> i64 {
+        secs_to_ticks(self.interval_secs)
+    }
+
+    pub(crate) fn timeout_ticks(&self) -> i64 {
+        secs_to_ticks(self.timeout_secs)
+    }
+}
+
+/// Configuration of how players take damage
+#[derive(Resource, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Resource))]
+pub struct CombatConfig {
     /// The maximum amount of time a player is resistant to being hurt. This is weird as this is 20 in vanilla
     /// Minecraft.
     /// However, the check to determine if a player can be hurt actually looks at this value divided by 2
     pub max_hurt_resistant_time: u16,
+}
 
-    /// Data shared between the IO thread and the ECS framework.
-    #[cfg_attr(feature = "reflect", reflect(ignore, default = "dummy_reflect_shared"))]
-    pub shared: Arc<Shared>,
-
-    /// The amount of time from the last packet a player has sent before the server will kick them.
-    pub keep_alive_timeout: Duration,
+impl Default for CombatConfig {
+    fn default() -> Self {
+        Self {
+            max_hurt_resistant_time: 20, // actually kinda like 10 vanilla mc is weird
+        }
+    }
+}
 
+/// How long the last tick took. Game code should use [`ServerInfo::last_mspt`] instead.
+#[derive(Resource, Copy, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Resource))]
+pub struct TickDuration {
     /// The amount of time the last tick took in milliseconds.
+    #[doc(hidden)]
     pub ms_last_tick: f32,
-
-    #[cfg_attr(feature = "reflect", reflect(ignore))]
-    pub player_count: AtomicUsize,
 }
 
-// Note: Caution! The Arc and AtomicUsize did not throw errors for me, it just didn't work
+/// The number of players in the play state. Game code should use [`ServerInfo::player_count`]
+/// instead.
+#[derive(Resource, Default, Debug)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Resource))]
+pub struct PlayerCount(#[doc(hidden)] pub AtomicUsize);
 
-#[cfg(feature = "reflect")]
-fn dummy_reflect_shared() -> Arc<Shared> {
-    Arc::new(Shared {
-        compression_threshold: CompressionThreshold::default(),
-        compression_level: CompressionLvl::default(),
-    })
+impl PlayerCount {
+    #[must_use]
+    pub fn get(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
 }
 
-impl Global {
-    /// Creates a new [`Global`] with the given shared data.
+/// The number of players in the play state connected through each proxy. Proxies without
+/// players are left out.
+#[derive(Resource, Clone, Default, Debug)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(opaque))]
+pub struct ProxyPlayerCounts(FxHashMap<ProxyId, usize>);
+
+impl ProxyPlayerCounts {
+    /// The number of players connected through `proxy_id`
     #[must_use]
-    pub const fn new(shared: Arc<Shared>) -> Self {
-        Self {
-            tick: 0,
-            max_hurt_resistant_time: 20, // actually kinda like 10 vanilla mc is weird
-            shared,
-            keep_alive_timeout: Duration::from_secs(20),
-            ms_last_tick: 0.0,
-            player_count: AtomicUsize::new(0),
+    pub fn get(&self, proxy_id: ProxyId) -> usize {
+        self.0.get(&proxy_id).copied().unwrap_or_default()
+    }
+
+    /// Each proxy with players and its number of players
+    pub fn iter(&self) -> impl Iterator<Item = (ProxyId, usize)> + '_ {
+        self.0.iter().map(|(&proxy_id, &count)| (proxy_id, count))
+    }
+
+    pub(crate) fn add(&mut self, proxy_id: ProxyId) {
+        *self.0.entry(proxy_id).or_default() += 1;
+    }
+
+    pub(crate) fn remove(&mut self, proxy_id: ProxyId) {
+        if let Some(count) = self.0.get_mut(&proxy_id) {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                self.0.remove(&proxy_id);
+            }
         }
     }
+
+    /// Forgets `proxy_id` after it disconnected, returning how many players were still counted
+    /// for it
+    pub(crate) fn remove_proxy(&mut self, proxy_id: ProxyId) -> usize {
+        self.0.remove(&proxy_id).unwrap_or_default()
+    }
 }
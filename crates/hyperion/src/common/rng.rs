@@ -0,0 +1,115 @@
+//! Deterministic randomness for gameplay. See [`GameRng`].
+
+use std::hash::{Hash, Hasher};
+
+use bevy_ecs::resource::Resource;
+use rustc_hash::FxHasher;
+#[cfg(feature = "reflect")]
+use {bevy_ecs::reflect::ReflectResource, bevy_reflect::Reflect};
+
+/// The source of every random number that affects gameplay, such as teleport IDs and entity
+/// UUIDs.
+///
+/// Two servers with the same seed that receive the same events draw the same numbers, which keeps
+/// tests and replays deterministic. The seed is taken from
+/// [`Config::rng_seed`](crate::config::Config::rng_seed), or chosen randomly and logged at startup
+/// if it is not set.
+///
+/// Systems that would contend on [`GameRng::rng`] should draw from a substream created with
+/// [`GameRng::fork`] instead, which is usually kept in a [`Local`](bevy_ecs::system::Local):
+///
+/// ```
+/// use bevy_ecs::system::{Local, Res};
+/// use hyperion::GameRng;
+///
+/// fn roll(game_rng: Res<'_, GameRng>, mut rng: Local<'_, Option<fastrand::Rng>>) {
+///     let rng = rng.get_or_insert_with(|| game_rng.fork("roll"));
+///     let _damage = rng.u32(1..=6);
+/// }
+/// ```
+#[derive(Resource, Debug)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Resource))]
+pub struct GameRng {
+    seed: u64,
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    rng: fastrand::Rng,
+}
+
+impl GameRng {
+    #[must_use]
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            rng: fastrand::Rng::with_seed(seed),
+        }
+    }
+
+    /// Creates a [`GameRng`] with a random seed
+    #[must_use]
+    pub fn from_entropy() -> Self {
+        Self::new(fastrand::u64(..))
+    }
+
+    #[must_use]
+    pub const fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// The main stream, for systems and commands with exclusive access to this resource
+    pub const fn rng(&mut self) -> &mut fastrand::Rng {
+        &mut self.rng
+    }
+
+    /// Creates a substream which only depends on the seed and `label`, so it is independent of
+    /// how many numbers were drawn from the main stream or other substreams. Every system should
+    /// use its own label.
+    #[must_use]
+    pub fn fork(&self, label: &str) -> fastrand::Rng {
+        self.fork_keyed(label, ())
+    }
+
+    /// Creates a substream which only depends on the seed, `label` and `key`.
+    ///
+    /// This is meant for parallel iterations and observers, which run in an unspecified order and
+    /// therefore cannot share a stream. Keying the substream by something that identifies the
+    /// draw, such as an [`Entity`](bevy_ecs::entity::Entity) and the current
+    /// [`Tick`](crate::Tick), keeps the numbers independent of that order.
+    #[must_use]
+    pub fn fork_keyed(&self, label: &str, key: impl Hash) -> fastrand::Rng {
+        let mut hasher = FxHasher::default();
+        self.seed.hash(&mut hasher);
+        label.hash(&mut hasher);
+        key.hash(&mut hasher);
+        fastrand::Rng::with_seed(hasher.finish())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forks_only_depend_on_the_seed_and_label() {
+        let mut a = GameRng::new(42);
+        let b = GameRng::new(42);
+
+        // Drawing from the main stream does not change the substreams
+        a.rng().u64(..);
+
+        assert_eq!(a.fork("teleport").u64(..), b.fork("teleport").u64(..));
+        assert_eq!(
+            a.fork_keyed("uuid", 7_u64).u128(..),
+            b.fork_keyed("uuid", 7_u64).u128(..)
+        );
+
+        assert_ne!(b.fork("teleport").u64(..), b.fork("uuid").u64(..));
+        assert_ne!(
+            b.fork_keyed("uuid", 7_u64).u64(..),
+            b.fork_keyed("uuid", 8_u64).u64(..)
+        );
+        assert_ne!(
+            GameRng::new(1).fork("teleport").u64(..),
+            GameRng::new(2).fork("teleport").u64(..)
+        );
+    }
+}
@@ -0,0 +1,189 @@
+//! See [`ServerInfo`].
+
+use std::{collections::VecDeque, time::Duration};
+
+use bevy_ecs::resource::Resource;
+#[cfg(feature = "reflect")]
+use {bevy_ecs::reflect::ReflectResource, bevy_reflect::Reflect};
+
+/// The number of ticks [`ServerInfo::tps`] is averaged over
+pub const TPS_WINDOW: usize = 100;
+
+/// The number of ticks [`ServerInfo::mspt_percentiles`] is calculated over, one minute at 20 TPS
+pub const MSPT_WINDOW: usize = 20 * 60;
+
+/// The player count and performance of the server, updated at the end of every tick.
+///
+/// Game code should read these from here instead of from the resources they are calculated from,
+/// whose layout may change.
+#[derive(Resource, Debug, Default)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Resource))]
+pub struct ServerInfo {
+    player_count: usize,
+    uptime_ticks: u64,
+    /// The time between the starts of the last [`TPS_WINDOW`] ticks
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    intervals: VecDeque<Duration>,
+    /// The time the last [`MSPT_WINDOW`] ticks took in milliseconds
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    mspt: VecDeque<f32>,
+}
+
+/// Percentiles of the time ticks took in milliseconds. See [`ServerInfo::mspt_percentiles`].
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct MsptPercentiles {
+    pub p50: f32,
+    pub p95: f32,
+    pub p99: f32,
+}
+
+impl ServerInfo {
+    /// The number of players in the play state
+    #[must_use]
+    pub const fn player_count(&self) -> usize {
+        self.player_count
+    }
+
+    /// The number of ticks that have finished since the server started
+    #[must_use]
+    pub const fn uptime_ticks(&self) -> u64 {
+        self.uptime_ticks
+    }
+
+    /// The number of ticks per second, averaged over the last [`TPS_WINDOW`] ticks. This is 0
+    /// until the second tick has started.
+    #[must_use]
+    pub fn tps(&self) -> f32 {
+        let total: Duration = self.intervals.iter().sum();
+        if total.is_zero() {
+            return 0.0;
+        }
+
+        #[expect(clippy::cast_precision_loss, reason = "the window is far below 2^23")]
+        let ticks = self.intervals.len() as f32;
+        ticks / total.as_secs_f32()
+    }
+
+    /// How long the last tick took in milliseconds
+    #[must_use]
+    pub fn last_mspt(&self) -> f32 {
+        self.mspt.back().copied().unwrap_or_default()
+    }
+
+    /// The 50th, 95th and 99th percentile of the time the last [`MSPT_WINDOW`] ticks took, using
+    /// the nearest-rank method. These are all 0 before the first tick has finished.
+    #[must_use]
+    pub fn mspt_percentiles(&self) -> MsptPercentiles {
+        if self.mspt.is_empty() {
+            return MsptPercentiles::default();
+        }
+
+        let mut sorted: Vec<f32> = self.mspt.iter().copied().collect();
+        sorted.sort_unstable_by(f32::total_cmp);
+
+        MsptPercentiles {
+            p50: percentile(&sorted, 50),
+            p95: percentile(&sorted, 95),
+            p99: percentile(&sorted, 99),
+        }
+    }
+
+    /// Records a finished tick which took `duration` and started `since_last_tick` after the
+    /// previous tick, or [`None`] if it is the first tick
+    pub(crate) fn record_tick(
+        &mut self,
+        duration: Duration,
+        since_last_tick: Option<Duration>,
+        player_count: usize,
+    ) {
+        self.player_count = player_count;
+        self.uptime_ticks += 1;
+
+        if let Some(interval) = since_last_tick {
+            push_bounded(&mut self.intervals, interval, TPS_WINDOW);
+        }
+
+        push_bounded(&mut self.mspt, duration.as_secs_f32() * 1000.0, MSPT_WINDOW);
+    }
+}
+
+fn push_bounded<T>(buffer: &mut VecDeque<T>, value: T, capacity: usize) {
+    if buffer.len() == capacity {
+        buffer.pop_front();
+    }
+    buffer.push_back(value);
+}
+
+/// The value below which `percent` percent of the values in `sorted` are, which must not be empty
+fn percentile(sorted: &[f32], percent: usize) -> f32 {
+    let rank = (sorted.len() * percent).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TICK: Duration = Duration::from_millis(50);
+
+    #[test]
+    fn percentiles_use_the_nearest_rank() {
+        let mut info = ServerInfo::default();
+        assert_eq!(info.mspt_percentiles(), MsptPercentiles::default());
+
+        // Recorded out of order, so the percentiles have to sort them
+        for ms in (1..=100).rev() {
+            info.record_tick(Duration::from_millis(ms), Some(TICK), 0);
+        }
+
+        assert_eq!(info.mspt_percentiles(), MsptPercentiles {
+            p50: 50.0,
+            p95: 95.0,
+            p99: 99.0,
+        });
+        assert!((info.last_mspt() - 1.0).abs() < f32::EPSILON);
+
+        let mut info = ServerInfo::default();
+        info.record_tick(Duration::from_millis(7), None, 0);
+        assert_eq!(info.mspt_percentiles(), MsptPercentiles {
+            p50: 7.0,
+            p95: 7.0,
+            p99: 7.0,
+        });
+    }
+
+    #[test]
+    fn percentiles_only_include_the_window() {
+        let mut info = ServerInfo::default();
+
+        // A lag spike that has left the window
+        for _ in 0..MSPT_WINDOW {
+            info.record_tick(Duration::from_millis(500), Some(TICK), 0);
+        }
+        for _ in 0..MSPT_WINDOW {
+            info.record_tick(Duration::from_millis(10), Some(TICK), 0);
+        }
+
+        assert!((info.mspt_percentiles().p99 - 10.0).abs() < f32::EPSILON);
+        assert_eq!(info.uptime_ticks(), 2 * MSPT_WINDOW as u64);
+    }
+
+    #[test]
+    fn tps_is_averaged_over_the_window() {
+        let mut info = ServerInfo::default();
+        info.record_tick(TICK, None, 3);
+        assert!(info.tps().abs() < f32::EPSILON);
+        assert_eq!(info.player_count(), 3);
+
+        for _ in 0..TPS_WINDOW {
+            info.record_tick(TICK, Some(Duration::from_millis(100)), 3);
+        }
+        assert!((info.tps() - 10.0).abs() < 1e-3);
+
+        // Half of the window at 20 TPS
+        for _ in 0..TPS_WINDOW / 2 {
+            info.record_tick(TICK, Some(TICK), 3);
+        }
+        assert!((info.tps() - 100.0 / 7.5).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,406 @@
+//! JSON snapshots of the players for external dashboards. See [`SnapshotPlugin`].
+//!
+//! A snapshot is a document like the following, with one object per player in the play state:
+//!
+//! ```json
+//! {
+//!     "tick": 1200,
+//!     "players": [
+//!         {
+//!             "name": "Notch",
+//!             "uuid": "069a79f4-44e9-4726-a5be-fca90e38aaf5",
+//!             "position": { "x": 0.5, "y": 64.0, "z": 0.5 },
+//!             "health": 20.0,
+//!             "game_mode": "survival",
+//!             "ping": 50,
+//!             "team": "red"
+//!         }
+//!     ]
+//! }
+//! ```
+//!
+//! Each field of a player is produced by an [`Extractor`] in [`SnapshotExtractors`], which game
+//! crates can extend with their own fields. Fields whose extractor returns [`None`] are left out.
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use bevy_app::{App, FixedPostUpdate, Plugin};
+use bevy_ecs::{
+    entity::Entity, name::Name, query::With, resource::Resource, schedule::IntoScheduleConfigs,
+    system::Res, world::World,
+};
+use serde_json::{Map, Value, json};
+use tokio::sync::{oneshot, watch};
+use tracing::warn;
+
+use crate::{
+    Tick,
+    command_channel::CommandChannel,
+    simulation::{
+        Player, Position, Uuid,
+        join::PlayerGameMode,
+        keep_alive::KeepAliveStatus,
+        metadata::living_entity::Health,
+        packet_state,
+        team::{Team, TeamMember},
+    },
+};
+
+/// Produces a field of a player in a snapshot, or [`None`] to leave the field out
+pub type Extractor = fn(world: &World, player: Entity) -> Option<Value>;
+
+/// The fields of each player in a snapshot, keyed by field name. The fields are written in the
+/// order they were registered.
+///
+/// By default, this contains `name`, `uuid`, `position`, `health`, `game_mode`, `ping` in
+/// milliseconds and `team`.
+#[derive(Resource, Clone, Debug)]
+pub struct SnapshotExtractors {
+    fields: Vec<(String, Extractor)>,
+}
+
+impl SnapshotExtractors {
+    /// An empty registry without the default fields
+    #[must_use]
+    pub const fn empty() -> Self {
+        Self { fields: Vec::new() }
+    }
+
+    /// Registers `extractor` for `field`, replacing the extractor of the field if there is one
+    pub fn register(&mut self, field: impl Into<String>, extractor: Extractor) {
+        let field = field.into();
+        match self.fields.iter_mut().find(|(name, _)| *name == field) {
+            Some((_, existing)) => *existing = extractor,
+            None => self.fields.push((field, extractor)),
+        }
+    }
+
+    /// Removes the extractor of `field`, returning whether there was one
+    pub fn remove(&mut self, field: &str) -> bool {
+        let len = self.fields.len();
+        self.fields.retain(|(name, _)| name != field);
+        self.fields.len() != len
+    }
+
+    /// The names of the fields, in the order they are written
+    pub fn fields(&self) -> impl Iterator<Item = &str> {
+        self.fields.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Runs the extractor of every field for `player`, in the order the fields are written.
+    /// Fields which would be left out are [`None`].
+    pub fn extract_each<'a>(
+        &'a self,
+        world: &'a World,
+        player: Entity,
+    ) -> impl Iterator<Item = (&'a str, Option<Value>)> + 'a {
+        self.fields
+            .iter()
+            .map(move |(name, extractor)| (name.as_str(), extractor(world, player)))
+    }
+
+    fn extract(&self, world: &World, player: Entity) -> Map<String, Value> {
+        self.extract_each(world, player)
+            .filter_map(|(name, value)| Some((name.to_owned(), value?)))
+            .collect()
+    }
+}
+
+impl Default for SnapshotExtractors {
+    fn default() -> Self {
+        let mut extractors = Self::empty();
+        extractors.register("name", |world, player| {
+            Some(world.get::<Name>(player)?.as_str().into())
+        });
+        extractors.register("uuid", |world, player| {
+            Some(world.get::<Uuid>(player)?.0.to_string().into())
+        });
+        extractors.register("position", |world, player| {
+            let position = world.get::<Position>(player)?;
+            Some(json!({ "x": position.x, "y": position.y, "z": position.z }))
+        });
+        extractors.register("health", |world, player| {
+            Some(json!(**world.get::<Health>(player)?))
+        });
+        extractors.register("game_mode", |world, player| {
+            let game_mode = world.get::<PlayerGameMode>(player)?.0;
+            Some(format!("{game_mode:?}").to_lowercase().into())
+        });
+        extractors.register("ping", |world, player| {
+            Some(world.get::<KeepAliveStatus>(player)?.ping_ms()?.into())
+        });
+        extractors.register("team", |world, player| {
+            let team = world.get::<TeamMember>(player)?.0;
+            Some(world.get::<Team>(team)?.id.clone().into())
+        });
+        extractors
+    }
+}
+
+/// Limits on the work done on the tick thread for a snapshot
+#[derive(Resource, Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SnapshotLimits {
+    /// Snapshots are skipped if there are more players than this
+    pub max_players: usize,
+    /// Snapshots which take longer than this to extract are abandoned
+    pub budget: Duration,
+}
+
+impl Default for SnapshotLimits {
+    fn default() -> Self {
+        Self {
+            max_players: 10_000,
+            budget: Duration::from_millis(5),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotError {
+    #[error("snapshot skipped: {players} players exceed the limit of {max_players}")]
+    TooManyPlayers { players: usize, max_players: usize },
+    #[error(
+        "snapshot abandoned after {extracted} of {players} players: exceeded the budget of \
+         {budget:?}"
+    )]
+    OverBudget {
+        extracted: usize,
+        players: usize,
+        budget: Duration,
+    },
+    #[error("the server dropped the snapshot request")]
+    Dropped,
+}
+
+/// Extracts a snapshot of every player in the play state
+///
+/// # Errors
+/// If the snapshot is skipped or abandoned because of the [`SnapshotLimits`]
+pub fn take_snapshot(world: &mut World) -> Result<Value, SnapshotError> {
+    let start = Instant::now();
+    let limits = world
+        .get_resource::<SnapshotLimits>()
+        .copied()
+        .unwrap_or_default();
+
+    let players: Vec<Entity> = world
+        .query_filtered::<Entity, (With<Player>, With<packet_state::Play>)>()
+        .iter(world)
+        .collect();
+
+    if players.len() > limits.max_players {
+        return Err(SnapshotError::TooManyPlayers {
+            players: players.len(),
+            max_players: limits.max_players,
+        });
+    }
+
+    let world = &*world;
+    let extractors = world
+        .get_resource::<SnapshotExtractors>()
+        .cloned()
+        .unwrap_or_default();
+
+    let mut snapshot = Vec::with_capacity(players.len());
+    for &player in &players {
+        if start.elapsed() > limits.budget {
+            return Err(SnapshotError::OverBudget {
+                extracted: snapshot.len(),
+                players: players.len(),
+                budget: limits.budget,
+            });
+        }
+
+        snapshot.push(Value::Object(extractors.extract(world, player)));
+    }
+
+    let tick = world.get_resource::<Tick>().copied().unwrap_or_default().0;
+    Ok(json!({ "tick": tick, "players": snapshot }))
+}
+
+/// Requests snapshots from async tasks, such as the handlers of an HTTP server. This is cheap to
+/// clone.
+#[derive(Resource, Clone)]
+pub struct SnapshotHandle {
+    command_channel: CommandChannel,
+    latest: watch::Receiver<Option<Arc<Value>>>,
+}
+
+impl SnapshotHandle {
+    /// Takes a snapshot on the tick thread once it applies the [`CommandChannel`]
+    ///
+    /// # Errors
+    /// If the snapshot is skipped or abandoned, or the request is dropped because the
+    /// [`CommandChannel`] is full
+    pub async fn request(&self) -> Result<Value, SnapshotError> {
+        let (tx, rx) = oneshot::channel();
+        self.command_channel
+            .send(move |world: &mut World| {
+                // The requester may have given up already
+                drop(tx.send(take_snapshot(world)));
+            })
+            .await;
+
+        rx.await.map_err(|_| SnapshotError::Dropped)?
+    }
+
+    /// The latest periodic snapshot, if [`SnapshotPlugin::interval`] is set and one has been taken
+    #[must_use]
+    pub fn latest(&self) -> Option<Arc<Value>> {
+        self.latest.borrow().clone()
+    }
+
+    /// A receiver which is notified of every periodic snapshot
+    #[must_use]
+    pub fn subscribe(&self) -> watch::Receiver<Option<Arc<Value>>> {
+        self.latest.clone()
+    }
+}
+
+#[derive(Resource)]
+struct PeriodicSnapshots {
+    /// The number of ticks between snapshots
+    interval: u32,
+    elapsed: u32,
+    latest: watch::Sender<Option<Arc<Value>>>,
+}
+
+fn take_periodic_snapshot(world: &mut World) {
+    let due = {
+        let mut periodic = world.resource_mut::<PeriodicSnapshots>();
+        periodic.elapsed += 1;
+        let due = periodic.elapsed >= periodic.interval;
+        if due {
+            periodic.elapsed = 0;
+        }
+        due
+    };
+
+    if !due {
+        return;
+    }
+
+    match take_snapshot(world) {
+        Ok(snapshot) => {
+            world
+                .resource::<PeriodicSnapshots>()
+                .latest
+                .send_replace(Some(Arc::new(snapshot)));
+        }
+        Err(e) => warn!("{e}"),
+    }
+}
+
+fn is_periodic(periodic: Option<Res<'_, PeriodicSnapshots>>) -> bool {
+    periodic.is_some()
+}
+
+/// Lets async tasks request [`take_snapshot`] through the [`SnapshotHandle`] resource, and
+/// optionally takes a snapshot periodically.
+///
+/// This must be added after [`crate::HyperionCore`].
+pub struct SnapshotPlugin {
+    /// The number of ticks between periodic snapshots, which are available through
+    /// [`SnapshotHandle::latest`]. Snapshots are only taken on request if this is [`None`].
+    pub interval: Option<u32>,
+    pub limits: SnapshotLimits,
+}
+
+impl Default for SnapshotPlugin {
+    fn default() -> Self {
+        Self {
+            interval: None,
+            limits: SnapshotLimits::default(),
+        }
+    }
+}
+
+impl Plugin for SnapshotPlugin {
+    fn build(&self, app: &mut App) {
+        let (latest_tx, latest) = watch::channel(None);
+        let command_channel = app.world().resource::<CommandChannel>().clone();
+
+        app.insert_resource(self.limits);
+        app.init_resource::<SnapshotExtractors>();
+        app.insert_resource(SnapshotHandle {
+            command_channel,
+            latest,
+        });
+
+        if let Some(interval) = self.interval {
+            app.insert_resource(PeriodicSnapshots {
+                interval: interval.max(1),
+                elapsed: 0,
+                latest: latest_tx,
+            });
+        }
+
+        app.add_systems(FixedPostUpdate, take_periodic_snapshot.run_if(is_periodic));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spawn_player(world: &mut World, name: &str) -> Entity {
+        world
+            .spawn((
+                Player,
+                packet_state::Play,
+                Name::new(name.to_owned()),
+                Position::new(1.0, 64.0, -2.5),
+            ))
+            .id()
+    }
+
+    #[test]
+    fn registered_fields_replace_and_extend_the_defaults() {
+        let mut world = World::new();
+        spawn_player(&mut world, "Notch");
+
+        let mut extractors = SnapshotExtractors::default();
+        extractors.register("name", |world, player| {
+            Some(world.get::<Name>(player)?.to_uppercase().into())
+        });
+        extractors.register("kills", |_, _| Some(3.into()));
+        assert!(extractors.remove("uuid"));
+        assert!(!extractors.remove("uuid"));
+        world.insert_resource(extractors);
+
+        let snapshot = take_snapshot(&mut world).unwrap();
+        assert_eq!(
+            snapshot,
+            json!({
+                "tick": 0,
+                "players": [{
+                    "name": "NOTCH",
+                    "position": { "x": 1.0, "y": 64.0, "z": -2.5 },
+                    "kills": 3,
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn snapshots_of_too_many_players_are_skipped() {
+        let mut world = World::new();
+        world.insert_resource(SnapshotLimits {
+            max_players: 1,
+            ..SnapshotLimits::default()
+        });
+        spawn_player(&mut world, "Notch");
+        spawn_player(&mut world, "jeb_");
+
+        assert_eq!(
+            take_snapshot(&mut world),
+            Err(SnapshotError::TooManyPlayers {
+                players: 2,
+                max_players: 1,
+            })
+        );
+    }
+}
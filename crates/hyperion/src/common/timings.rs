@@ -0,0 +1,425 @@
+//! Per-tick timings of the most expensive systems. See [`TickTimings`].
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    path::PathBuf,
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use bevy_app::{App, FixedFirst, Last, Plugin};
+use bevy_ecs::{
+    resource::Resource,
+    system::{Res, ResMut},
+};
+use tracing::{error, warn};
+
+use crate::{Tick, runtime::AsyncRuntime};
+
+/// A part of the tick that is timed by [`TickTimings`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TimedSection {
+    /// Decoding packets from every connection
+    Decode,
+    /// Rebuilding the spatial index
+    SpatialIndex,
+    /// Generating and sending chunks to players
+    ChunkSending,
+    /// Updating the channel of each entity that moved
+    ChannelPositions,
+    /// Sending entity state changes to players
+    EntitySync,
+    /// Sending the packets batched during the tick to the proxies
+    EgressFlush,
+}
+
+impl TimedSection {
+    pub const ALL: [Self; 6] = [
+        Self::Decode,
+        Self::SpatialIndex,
+        Self::ChunkSending,
+        Self::ChannelPositions,
+        Self::EntitySync,
+        Self::EgressFlush,
+    ];
+
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Decode => "decode",
+            Self::SpatialIndex => "spatial_index",
+            Self::ChunkSending => "chunk_sending",
+            Self::ChannelPositions => "channel_positions",
+            Self::EntitySync => "entity_sync",
+            Self::EgressFlush => "egress_flush",
+        }
+    }
+}
+
+/// The time spent in one [`TimedSection`] during a tick
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SectionTiming {
+    /// The total time spent in the section. Sections that run on several threads at once can
+    /// take longer than the tick itself.
+    pub total: Duration,
+    /// The number of times the section was entered
+    pub calls: u32,
+    /// When the section was first entered, relative to the start of the tick
+    pub start: Duration,
+    /// When the section was last left, relative to the start of the tick
+    pub end: Duration,
+}
+
+/// The timings of a finished tick
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TickTiming {
+    pub tick: i64,
+    pub duration: Duration,
+    /// The timing of each section, in the order of [`TimedSection::ALL`]
+    pub sections: [SectionTiming; TimedSection::ALL.len()],
+}
+
+impl TickTiming {
+    /// Returns the sections that ran this tick, slowest first
+    #[must_use]
+    pub fn breakdown(&self) -> Vec<(TimedSection, SectionTiming)> {
+        let mut breakdown: Vec<_> = TimedSection::ALL
+            .into_iter()
+            .zip(self.sections)
+            .filter(|(_, timing)| timing.calls > 0)
+            .collect();
+        breakdown.sort_by(|(_, a), (_, b)| b.total.cmp(&a.total));
+        breakdown
+    }
+
+    /// Formats the tick in the [Trace Event Format](https://docs.google.com/document/d/1CvAClvFfyA5R-PhYUmn5OOQtYMH4h6I0nSsKchNAySU)
+    /// used by `chrome://tracing` and Perfetto
+    #[must_use]
+    pub fn to_chrome_trace(&self) -> serde_json::Value {
+        let micros = |duration: Duration| duration.as_secs_f64() * 1_000_000.0;
+
+        let mut events = vec![serde_json::json!({
+            "name": format!("tick {}", self.tick),
+            "ph": "X",
+            "ts": 0,
+            "dur": micros(self.duration),
+            "pid": 0,
+            "tid": 0,
+        })];
+
+        // Each section gets its own row because sections that run in parallel overlap
+        for (tid, (section, timing)) in TimedSection::ALL.into_iter().zip(self.sections).enumerate()
+        {
+            if timing.calls == 0 {
+                continue;
+            }
+
+            events.push(serde_json::json!({
+                "name": section.name(),
+                "ph": "X",
+                "ts": micros(timing.start),
+                "dur": micros(timing.end.saturating_sub(timing.start)),
+                "pid": 0,
+                "tid": tid + 1,
+                "args": {
+                    "total_ms": timing.total.as_secs_f64() * 1000.0,
+                    "calls": timing.calls,
+                },
+            }));
+        }
+
+        serde_json::json!({ "traceEvents": events })
+    }
+}
+
+impl fmt::Display for TickTiming {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "tick {} took {:.2}ms",
+            self.tick,
+            self.duration.as_secs_f64() * 1000.0
+        )?;
+
+        for (i, (section, timing)) in self.breakdown().into_iter().enumerate() {
+            let separator = if i == 0 { ": " } else { ", " };
+            write!(
+                f,
+                "{separator}{} {:.2}ms",
+                section.name(),
+                timing.total.as_secs_f64() * 1000.0
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Running totals for a section during the current tick
+struct SectionTimer {
+    total_ns: AtomicU64,
+    calls: AtomicU32,
+    start_ns: AtomicU64,
+    end_ns: AtomicU64,
+}
+
+impl Default for SectionTimer {
+    fn default() -> Self {
+        Self {
+            total_ns: AtomicU64::new(0),
+            calls: AtomicU32::new(0),
+            start_ns: AtomicU64::new(u64::MAX),
+            end_ns: AtomicU64::new(0),
+        }
+    }
+}
+
+impl SectionTimer {
+    fn take(&self) -> SectionTiming {
+        let calls = self.calls.swap(0, Ordering::Relaxed);
+        let total = self.total_ns.swap(0, Ordering::Relaxed);
+        let start = self.start_ns.swap(u64::MAX, Ordering::Relaxed);
+        let end = self.end_ns.swap(0, Ordering::Relaxed);
+
+        if calls == 0 {
+            return SectionTiming::default();
+        }
+
+        SectionTiming {
+            total: Duration::from_nanos(total),
+            calls,
+            start: Duration::from_nanos(start),
+            end: Duration::from_nanos(end),
+        }
+    }
+}
+
+/// Times the sections of each tick and keeps the timings of recent ticks.
+///
+/// Systems time themselves with [`TickTimings::time`], which only costs a few atomic operations.
+/// Ticks that take longer than [`TickTimings::slow_tick_threshold`] are logged with a breakdown
+/// of their sections.
+///
+/// A tick is timed from the start of [`FixedFirst`] until the batched packets were flushed in
+/// [`Last`], so the flush is part of the tick it sends the packets of. Sections which run between
+/// ticks are not recorded.
+#[derive(Resource)]
+pub struct TickTimings {
+    /// Ticks slower than this are logged
+    pub slow_tick_threshold: Duration,
+    /// If set, a `chrome://tracing` file is written to this directory for every slow tick
+    pub trace_dir: Option<PathBuf>,
+    tick_start: Instant,
+    /// Whether a tick was started and has not been finished yet
+    ticking: bool,
+    sections: [SectionTimer; TimedSection::ALL.len()],
+    history: VecDeque<TickTiming>,
+    history_len: usize,
+}
+
+impl Default for TickTimings {
+    fn default() -> Self {
+        Self::new(100)
+    }
+}
+
+impl TickTimings {
+    /// Creates timings that keep the last `history_len` ticks
+    #[must_use]
+    pub fn new(history_len: usize) -> Self {
+        Self {
+            slow_tick_threshold: Duration::from_millis(50),
+            trace_dir: None,
+            tick_start: Instant::now(),
+            ticking: false,
+            sections: Default::default(),
+            history: VecDeque::with_capacity(history_len),
+            history_len,
+        }
+    }
+
+    /// Starts timing `section`. The time is recorded when the returned guard is dropped.
+    #[must_use]
+    pub fn time(&self, section: TimedSection) -> SectionGuard<'_> {
+        SectionGuard {
+            timings: self,
+            section,
+            start: Instant::now(),
+        }
+    }
+
+    /// Returns the timings of up to the last `n` ticks, most recent first
+    pub fn last(&self, n: usize) -> impl Iterator<Item = &TickTiming> {
+        self.history.iter().rev().take(n)
+    }
+
+    fn record(&self, section: TimedSection, start: Instant) {
+        let end = Instant::now();
+        let nanos = |instant: Instant| {
+            u64::try_from(
+                instant
+                    .saturating_duration_since(self.tick_start)
+                    .as_nanos(),
+            )
+            .unwrap_or(u64::MAX)
+        };
+        let total = u64::try_from(end.duration_since(start).as_nanos()).unwrap_or(u64::MAX);
+
+        let timer = &self.sections[section as usize];
+        timer.total_ns.fetch_add(total, Ordering::Relaxed);
+        timer.calls.fetch_add(1, Ordering::Relaxed);
+        timer.start_ns.fetch_min(nanos(start), Ordering::Relaxed);
+        timer.end_ns.fetch_max(nanos(end), Ordering::Relaxed);
+    }
+
+    fn start_tick(&mut self) {
+        // Discard what was recorded between ticks
+        for section in &self.sections {
+            section.take();
+        }
+
+        self.tick_start = Instant::now();
+        self.ticking = true;
+    }
+
+    fn finish_tick(&mut self, tick: i64) -> TickTiming {
+        self.ticking = false;
+        let timing = TickTiming {
+            tick,
+            duration: self.tick_start.elapsed(),
+            sections: std::array::from_fn(|i| self.sections[i].take()),
+        };
+
+        if self.history_len > 0 {
+            if self.history.len() == self.history_len {
+                self.history.pop_front();
+            }
+            self.history.push_back(timing);
+        }
+
+        timing
+    }
+}
+
+/// Records the time spent in a [`TimedSection`] when dropped
+pub struct SectionGuard<'a> {
+    timings: &'a TickTimings,
+    section: TimedSection,
+    start: Instant,
+}
+
+impl Drop for SectionGuard<'_> {
+    fn drop(&mut self) {
+        self.timings.record(self.section, self.start);
+    }
+}
+
+pub struct TimingsPlugin;
+
+impl Plugin for TimingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TickTimings>();
+        app.add_systems(FixedFirst, start_tick);
+        app.add_systems(Last, finish_tick);
+    }
+}
+
+fn start_tick(
+    mut timings: ResMut<'_, TickTimings>,
+    tick: Res<'_, Tick>,
+    runtime: Res<'_, AsyncRuntime>,
+) {
+    // Several ticks run in one frame when the server catches up, but only the last one is
+    // followed by a flush
+    if timings.ticking {
+        report_tick(&mut timings, tick.0, &runtime);
+    }
+
+    timings.start_tick();
+}
+
+/// Finishes the tick after the batched packets were flushed
+pub(crate) fn finish_tick(
+    mut timings: ResMut<'_, TickTimings>,
+    tick: Res<'_, Tick>,
+    runtime: Res<'_, AsyncRuntime>,
+) {
+    if timings.ticking {
+        report_tick(&mut timings, tick.0, &runtime);
+    }
+}
+
+fn report_tick(timings: &mut TickTimings, tick: i64, runtime: &AsyncRuntime) {
+    let timing = timings.finish_tick(tick);
+
+    if timing.duration < timings.slow_tick_threshold {
+        return;
+    }
+
+    warn!("slow tick: {timing}");
+
+    if let Some(trace_dir) = &timings.trace_dir {
+        let path = trace_dir.join(format!("tick-{}.json", timing.tick));
+        // Writing the file could make the next tick slow too
+        runtime.spawn_blocking(move || {
+            let result = std::fs::create_dir_all(path.parent().unwrap())
+                .and_then(|()| std::fs::write(&path, timing.to_chrome_trace().to_string()));
+            if let Err(e) = result {
+                error!("failed to write tick trace to {}: {e}", path.display());
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sections_are_reset_each_tick() {
+        let mut timings = TickTimings::new(2);
+
+        drop(timings.time(TimedSection::Decode));
+        drop(timings.time(TimedSection::Decode));
+        drop(timings.time(TimedSection::EntitySync));
+
+        let first = timings.finish_tick(1);
+        assert_eq!(first.sections[TimedSection::Decode as usize].calls, 2);
+        assert_eq!(first.sections[TimedSection::EntitySync as usize].calls, 1);
+        assert_eq!(first.breakdown().len(), 2);
+
+        let second = timings.finish_tick(2);
+        assert!(second.breakdown().is_empty());
+        timings.finish_tick(3);
+
+        let ticks: Vec<_> = timings.last(5).map(|timing| timing.tick).collect();
+        assert_eq!(ticks, [3, 2]);
+    }
+
+    #[test]
+    fn sections_between_ticks_are_discarded() {
+        let mut timings = TickTimings::new(1);
+
+        drop(timings.time(TimedSection::EgressFlush));
+        timings.start_tick();
+        drop(timings.time(TimedSection::Decode));
+
+        let timing = timings.finish_tick(1);
+        assert_eq!(timing.sections[TimedSection::EgressFlush as usize].calls, 0);
+        assert_eq!(timing.sections[TimedSection::Decode as usize].calls, 1);
+    }
+
+    #[test]
+    fn chrome_trace_has_an_event_per_section() {
+        let mut timings = TickTimings::new(1);
+        drop(timings.time(TimedSection::SpatialIndex));
+
+        let trace = timings.finish_tick(7).to_chrome_trace();
+        let events = trace["traceEvents"].as_array().unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["name"], "tick 7");
+        assert_eq!(events[1]["name"], "spatial_index");
+        assert_eq!(events[1]["args"]["calls"], 1);
+    }
+}
@@ -0,0 +1,68 @@
+//! The built-in messages of Hyperion. See [`hyperion_utils::localization`].
+
+use std::path::Path;
+
+use hyperion_utils::{Translations, localization::DEFAULT_LANGUAGE};
+use tracing::error;
+
+/// The languages the built-in messages are translated into, with their translation file
+const BUILTIN: &[(&str, &str)] = &[
+    (DEFAULT_LANGUAGE, include_str!("../../lang/en.toml")),
+    ("de", include_str!("../../lang/de.toml")),
+];
+
+/// The directory translation files are loaded from, if it exists. These replace the built-in
+/// messages and may add messages for game code.
+pub const TRANSLATIONS_DIR: &str = "run/lang";
+
+/// Adds the built-in messages, followed by the translation files in [`TRANSLATIONS_DIR`]
+pub(crate) fn load(translations: &mut Translations) {
+    for (language, toml) in BUILTIN {
+        if let Err(e) = translations.add_toml(language, toml) {
+            error!("built-in {language} translations are invalid: {e}");
+        }
+    }
+
+    if Path::new(TRANSLATIONS_DIR).is_dir()
+        && let Err(e) = translations.load_dir(TRANSLATIONS_DIR)
+    {
+        error!("failed to load translations from {TRANSLATIONS_DIR}: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyperion_utils::Args;
+
+    use super::*;
+
+    #[test]
+    fn builtin_messages_are_translated() {
+        let mut translations = Translations::default();
+        for (language, toml) in BUILTIN {
+            translations.add_toml(language, toml).unwrap();
+        }
+
+        let german: toml::Table = toml::from_str(BUILTIN[1].1).unwrap();
+        let english: toml::Table = toml::from_str(BUILTIN[0].1).unwrap();
+        for (section, messages) in &english {
+            let german = german[section].as_table().unwrap();
+            for key in messages.as_table().unwrap().keys() {
+                assert!(
+                    german.contains_key(key),
+                    "{section}.{key} is not translated"
+                );
+            }
+        }
+
+        let args = Args::new().arg("Steve");
+        assert_eq!(
+            translations.translate("de_at", "player.joined", &args),
+            "Steve hat die Welt betreten"
+        );
+        assert_eq!(
+            translations.translate("fr_fr", "player.joined", &args),
+            "Steve joined the world"
+        );
+    }
+}
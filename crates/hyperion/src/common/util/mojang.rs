@@ -4,7 +4,8 @@ use std::{ops::Deref, sync::Arc, time::Duration};
 
 use anyhow::{Context, bail};
 use bevy_ecs::resource::Resource;
-use serde_json::Value;
+use rustc_hash::FxHashMap;
+use serde_json::{Value, json};
 use tokio::{
     sync::Semaphore,
     time::{MissedTickBehavior, interval},
@@ -59,6 +60,51 @@ impl ApiProvider {
     }
 }
 
+/// The profiles a [`MojangClient::stub`] answers lookups with instead of sending requests
+#[derive(Clone, Debug, Default)]
+pub struct StubProfiles {
+    by_uuid: FxHashMap<Uuid, Value>,
+    /// The UUIDs of the profiles by their lowercase username
+    by_username: FxHashMap<String, Uuid>,
+}
+
+impl StubProfiles {
+    /// Adds a profile without a skin
+    #[must_use]
+    pub fn with_profile(self, uuid: Uuid, username: &str) -> Self {
+        let data = json!({
+            "id": uuid.simple().to_string(),
+            "name": username,
+            "properties": [],
+        });
+        self.with_data(uuid, username, data)
+    }
+
+    /// Adds a profile which is answered with `data`, which should have the same format as the
+    /// responses of the Mojang API
+    #[must_use]
+    pub fn with_data(mut self, uuid: Uuid, username: &str, data: Value) -> Self {
+        self.by_uuid.insert(uuid, data);
+        self.by_username.insert(username.to_lowercase(), uuid);
+        self
+    }
+
+    fn by_uuid(&self, uuid: &Uuid) -> anyhow::Result<Value> {
+        self.by_uuid
+            .get(uuid)
+            .cloned()
+            .with_context(|| format!("{uuid} is not a stubbed profile"))
+    }
+
+    fn by_username(&self, username: &str) -> anyhow::Result<Value> {
+        let uuid = self
+            .by_username
+            .get(&username.to_lowercase())
+            .with_context(|| format!("{username} is not a stubbed profile"))?;
+        self.by_uuid(uuid)
+    }
+}
+
 /// A client to interface with the Minecraft profile API.
 ///
 /// Can use either the official Mojang API or [matdoes/mowojang](https://matdoes.dev/minecraft-uuids) as a data source.
@@ -72,6 +118,9 @@ pub struct MojangClient {
     #[cfg_attr(feature = "reflect", reflect(ignore))]
     rate_limit: RateLimiter,
     provider: ApiProvider,
+    /// If set, lookups are answered from these profiles instead of the API
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    stub: Option<Arc<StubProfiles>>,
 }
 
 // Wrapper to allow reflect(ignore) on a semaphore
@@ -128,13 +177,25 @@ impl MojangClient {
             req: reqwest::Client::new(),
             rate_limit,
             provider,
+            stub: None,
+        }
+    }
+
+    /// Creates a client which never sends requests and answers lookups from `profiles` instead,
+    /// such as for tests
+    #[must_use]
+    pub fn stub(profiles: StubProfiles) -> Self {
+        Self {
+            req: reqwest::Client::new(),
+            rate_limit: RateLimiter::default(),
+            provider: ApiProvider::MAT_DOES_DEV,
+            stub: Some(Arc::new(profiles)),
         }
     }
 
     /// Gets a player's UUID from their username.
     pub async fn get_uuid(&self, username: &str) -> anyhow::Result<Uuid> {
-        let url = self.provider.username_url(username);
-        let json_object = self.response_raw(&url).await?;
+        let json_object = self.data_from_username(username).await?;
 
         let id = json_object
             .get("id")
@@ -147,8 +208,7 @@ impl MojangClient {
 
     /// Gets a player's username from their UUID.
     pub async fn get_username(&self, uuid: Uuid) -> anyhow::Result<String> {
-        let url = self.provider.uuid_url(&uuid);
-        let json_object = self.response_raw(&url).await?;
+        let json_object = self.data_from_uuid(&uuid).await?;
 
         json_object
             .get("name")
@@ -160,16 +220,60 @@ impl MojangClient {
 
     /// Gets player data from their UUID.
     pub async fn data_from_uuid(&self, uuid: &Uuid) -> anyhow::Result<Value> {
+        if let Some(stub) = &self.stub {
+            return stub.by_uuid(uuid);
+        }
+
         let url = self.provider.uuid_url(uuid);
         self.response_raw(&url).await
     }
 
     /// Gets player data from their username.
     pub async fn data_from_username(&self, username: &str) -> anyhow::Result<Value> {
+        if let Some(stub) = &self.stub {
+            return stub.by_username(username);
+        }
+
         let url = self.provider.username_url(username);
         self.response_raw(&url).await
     }
 
+    /// Asks the Mojang session server whether `username` has joined the server identified by
+    /// `server_hash`, see [`crate::ingress::auth::server_hash`]. Returns the profile of the player
+    /// if they did.
+    ///
+    /// This always uses the official session server and is not rate limited, because every
+    /// online-mode login needs it.
+    pub async fn has_joined(
+        &self,
+        username: &str,
+        server_hash: &str,
+    ) -> anyhow::Result<Option<Value>> {
+        if let Some(stub) = &self.stub {
+            return Ok(stub.by_username(username).ok());
+        }
+
+        let response = self
+            .req
+            .get("https://sessionserver.mojang.com/session/minecraft/hasJoined")
+            .query(&[("username", username), ("serverId", server_hash)])
+            .send()
+            .await?;
+
+        // The session server answers with no content if the player has not joined
+        if response.status() == reqwest::StatusCode::NO_CONTENT {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            bail!("session server responded with {}", response.status());
+        }
+
+        let body = response.text().await?;
+        let json_object = serde_json::from_str::<Value>(&body)
+            .with_context(|| format!("failed to parse json from response: {body:?}"))?;
+        Ok(Some(json_object))
+    }
+
     async fn response_raw(&self, url: &str) -> anyhow::Result<Value> {
         self.rate_limit
             .acquire()
@@ -210,9 +314,27 @@ mod tests {
 
     use crate::{
         runtime::AsyncRuntime,
-        util::mojang::{ApiProvider, MojangClient},
+        util::mojang::{ApiProvider, MojangClient, StubProfiles},
     };
 
+    #[test]
+    fn stub_answers_lookups_without_requests() {
+        let tasks = AsyncRuntime::new();
+        let uuid = uuid::Uuid::from_str("86271406-1188-44a5-8496-7af10c906204").unwrap();
+        let mojang =
+            MojangClient::stub(StubProfiles::default().with_profile(uuid, "Emerald_Explorer"));
+
+        assert_eq!(
+            tasks.block_on(mojang.get_uuid("emerald_explorer")).unwrap(),
+            uuid
+        );
+        assert_eq!(
+            tasks.block_on(mojang.get_username(uuid)).unwrap(),
+            "Emerald_Explorer"
+        );
+        assert!(tasks.block_on(mojang.get_uuid("Someone_Else")).is_err());
+    }
+
     #[test]
     fn test_get_uuid() {
         let tasks = AsyncRuntime::new();
@@ -0,0 +1,174 @@
+//! Reacting to connections that do not keep up with the data sent to them. See [`Backlog`].
+
+use std::{
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
+use bevy_app::{App, FixedUpdate, Plugin};
+use bevy_ecs::{
+    component::Component,
+    query::Has,
+    system::{Query, Res, ResMut},
+};
+use hyperion_utils::{Args, Locale, Translations, localization::DEFAULT_LANGUAGE};
+use rustc_hash::FxHashSet;
+use tracing::warn;
+#[cfg(feature = "reflect")]
+use {bevy_ecs::reflect::ReflectComponent, bevy_reflect::Reflect};
+
+use crate::{
+    ingress::decode::disconnect,
+    net::{Compose, ConnectionId, ConnectionLimits, metrics::NetworkMetrics},
+    simulation::packet_state,
+};
+
+/// The number of bytes that the proxy has queued for a connection but not written to it yet, as
+/// last reported by the proxy.
+///
+/// Connections with a backlog above [`ConnectionLimits::soft_backlog_bytes`] are congested and
+/// are not sent chunks or low priority broadcasts until they catch up. Connections that stay above
+/// [`ConnectionLimits::hard_backlog_bytes`] for [`ConnectionLimits::hard_backlog_secs`] are
+/// disconnected.
+#[derive(Component, Default, Debug)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+pub struct Backlog {
+    bytes: u64,
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    over_hard_limit_since: Option<Instant>,
+}
+
+/// How a connection is doing according to its [`Backlog`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum BacklogState {
+    Normal,
+    Congested,
+    /// The connection has been above the hard limit for too long and should be disconnected
+    TooSlow,
+}
+
+impl Backlog {
+    #[must_use]
+    pub const fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    pub(crate) const fn report(&mut self, bytes: u64) {
+        self.bytes = bytes;
+    }
+
+    pub(crate) fn update(&mut self, limits: &ConnectionLimits, now: Instant) -> BacklogState {
+        if self.bytes <= limits.hard_backlog_bytes {
+            self.over_hard_limit_since = None;
+        } else {
+            let since = *self.over_hard_limit_since.get_or_insert(now);
+            if now.duration_since(since) >= Duration::from_secs(limits.hard_backlog_secs) {
+                // The connection is only kicked once, even if the disconnect takes a while
+                self.over_hard_limit_since = None;
+                return BacklogState::TooSlow;
+            }
+        }
+
+        if self.bytes > limits.soft_backlog_bytes {
+            BacklogState::Congested
+        } else {
+            BacklogState::Normal
+        }
+    }
+}
+
+pub struct BacklogPlugin;
+
+impl Plugin for BacklogPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(FixedUpdate, handle_backlogs);
+    }
+}
+
+fn handle_backlogs(
+    mut compose: ResMut<'_, Compose>,
+    metrics: Res<'_, NetworkMetrics>,
+    translations: Res<'_, Translations>,
+    mut query: Query<
+        '_,
+        '_,
+        (
+            &ConnectionId,
+            &mut Backlog,
+            Has<packet_state::Play>,
+            Option<&Locale>,
+        ),
+    >,
+) {
+    let limits = compose.io_buf().limits();
+    let now = Instant::now();
+    let mut congested = FxHashSet::default();
+    let mut backlogs = Vec::new();
+
+    for (&connection_id, mut backlog, is_playing, locale) in &mut query {
+        if backlog.bytes() > 0 {
+            backlogs.push((connection_id, backlog.bytes()));
+        }
+
+        match backlog.update(&limits, now) {
+            BacklogState::Normal => {}
+            BacklogState::Congested => {
+                congested.insert(connection_id);
+            }
+            BacklogState::TooSlow => {
+                warn!(
+                    "kicking {connection_id:?} for not keeping up, {} bytes are queued for it",
+                    backlog.bytes()
+                );
+                metrics.slow_kicks.fetch_add(1, Ordering::Relaxed);
+
+                if is_playing {
+                    let locale = locale.map_or(DEFAULT_LANGUAGE, Locale::as_str);
+                    let reason = translations.translate(locale, "kick.too_slow", &Args::new());
+                    disconnect::play(&compose, connection_id, &reason);
+                } else {
+                    compose.io_buf().shutdown(connection_id);
+                }
+            }
+        }
+    }
+
+    metrics.set_backlogs(backlogs);
+    compose.io_buf_mut().set_congested(congested);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connections_are_kicked_after_staying_above_the_hard_limit() {
+        let limits = ConnectionLimits {
+            soft_backlog_bytes: 10,
+            hard_backlog_bytes: 100,
+            hard_backlog_secs: 5,
+            ..ConnectionLimits::default()
+        };
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut backlog = Backlog::default();
+
+        backlog.report(5);
+        assert_eq!(backlog.update(&limits, at(0)), BacklogState::Normal);
+
+        backlog.report(50);
+        assert_eq!(backlog.update(&limits, at(0)), BacklogState::Congested);
+
+        backlog.report(500);
+        assert_eq!(backlog.update(&limits, at(1)), BacklogState::Congested);
+        assert_eq!(backlog.update(&limits, at(5)), BacklogState::Congested);
+
+        // Dropping below the hard limit resets the timeout
+        backlog.report(50);
+        assert_eq!(backlog.update(&limits, at(6)), BacklogState::Congested);
+        backlog.report(500);
+        assert_eq!(backlog.update(&limits, at(7)), BacklogState::Congested);
+        assert_eq!(backlog.update(&limits, at(12)), BacklogState::TooSlow);
+        assert_eq!(backlog.update(&limits, at(13)), BacklogState::Congested);
+    }
+}
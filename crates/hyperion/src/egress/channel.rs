@@ -1,59 +1,283 @@
+use std::borrow::Cow;
+
 use bevy_app::{App, FixedUpdate, Plugin};
 use bevy_ecs::{
+    component::Component,
     entity::Entity,
     lifecycle::{Add, Despawn},
     message::MessageReader,
+    name::Name,
     observer::On,
-    query::With,
-    system::{Query, Res},
-    world::World,
+    query::{Has, With, Without},
+    schedule::IntoScheduleConfigs,
+    system::{Commands, Local, Query, Res, ResMut},
+    world::{EntityRef, World},
 };
+use hyperion_inventory::{Inventory, PlayerInventory};
 use hyperion_proto::UpdateChannelPosition;
 use hyperion_utils::EntityExt;
 use tracing::error;
-use valence_bytes::CowBytes;
-use valence_protocol::{ByteAngle, RawBytes, VarInt, packets::play};
+use valence_bytes::{CowBytes, CowUtf8Bytes, Utf8Bytes};
+use valence_protocol::{
+    ByteAngle, GameMode, RawBytes, VarInt,
+    packets::{play, play::entity_equipment_update_s2c::EquipmentEntry},
+    profile::Property,
+};
 
 use crate::{
-    egress::metadata::show_all,
+    activity::server_active,
+    config::Config,
+    egress::{
+        metadata::show_all,
+        player_join::{PlayerListActions, PlayerListEntry, PlayerListS2c},
+    },
     net::{
-        Channel, ChannelId, Compose, ConnectionId,
+        Channel, ChannelId, Channels, Compose, ConnectionId, DataBundle,
         intermediate::{IntermediateServerToProxyMessage, UpdateChannelPositions},
     },
     simulation::{
-        Pitch, Position, RequestSubscribeChannelPackets, Uuid, Velocity, Yaw,
-        entity_kind::EntityKind,
-        metadata::{MetadataChanges, get_and_clear_metadata},
+        ObjectData, Pitch, Position, RequestSubscribeChannelPackets, Uuid, Velocity, Yaw,
+        death_drops::ExperienceOrbValue,
+        entity_kind::{EntityKind, TrackingRange},
+        event::SetSkin,
+        metadata::{MetadataChanges, MetadataRegistry, get_and_clear_metadata},
+        minecraft_id::MinecraftIdRegistry,
+        npc::{MAX_NAME_LEN, NpcTabList},
+        skin::PlayerSkin,
+        vanish::Vanished,
+        vehicle::{Passengers, Riding, passengers_packet},
+        world::WorldId,
     },
+    timings::{TickTimings, TimedSection},
 };
 
-fn add_channel(added_channel: On<'_, '_, Add, Channel>, compose: Res<'_, Compose>) {
+/// How many ticks an NPC stays in the player list of clients after being spawned. The client only
+/// needs the entry to resolve the NPC's skin while spawning it.
+const NPC_LIST_ENTRY_TICKS: u8 = 20;
+
+/// The tracking range in blocks of channels without a [`TrackingRange`] or [`EntityKind`]
+const DEFAULT_TRACKING_RANGE: f32 = 256.0;
+
+/// Marks an NPC whose player list entry should be removed once `ticks_left` reaches zero
+#[derive(Component, Debug)]
+struct PendingListRemoval {
+    ticks_left: u8,
+}
+
+/// Encodes the player list entry which gives an NPC its skin.
+///
+/// Unlike real players, NPCs are only shown in the tab list if they are `listed`, see
+/// [`NpcTabList`].
+fn add_npc_list_entry(
+    bundle: &mut DataBundle<'_>,
+    uuid: &Uuid,
+    name: Option<&Name>,
+    skin: Option<&PlayerSkin>,
+    listed: bool,
+) -> anyhow::Result<()> {
+    let username = name.map_or("", |name| {
+        let name = name.as_str();
+        name.char_indices()
+            .nth(MAX_NAME_LEN)
+            .map_or(name, |(end, _)| &name[..end])
+    });
+
+    let property = skin.map(|skin| Property::<Utf8Bytes> {
+        name: Utf8Bytes::from_static("textures"),
+        value: skin.textures.clone().into(),
+        signature: Some(skin.signature.clone().into()),
+    });
+
+    bundle.add_packet(&PlayerListS2c {
+        actions: PlayerListActions::default().with_add_player(true),
+        entries: Cow::Borrowed(&[PlayerListEntry {
+            player_uuid: **uuid,
+            username: CowUtf8Bytes::Borrowed(username),
+            properties: Cow::Borrowed(property.as_slice()),
+            chat_data: None,
+            listed,
+            ping: 0,
+            game_mode: GameMode::Survival,
+            display_name: None,
+        }]),
+    })
+}
+
+/// Encodes the spawn packets of a player entity, whether it is a real player or an NPC. The spawn
+/// packet of players has no head yaw, so it is sent separately.
+fn add_player_spawn(
+    bundle: &mut DataBundle<'_>,
+    minecraft_id: i32,
+    uuid: &Uuid,
+    position: &Position,
+    pitch: &Pitch,
+    yaw: &Yaw,
+) -> anyhow::Result<()> {
+    bundle.add_packet(&play::PlayerSpawnS2c {
+        entity_id: VarInt(minecraft_id),
+        player_uuid: **uuid,
+        position: position.as_dvec3(),
+        yaw: ByteAngle::from_degrees(**yaw),
+        pitch: ByteAngle::from_degrees(**pitch),
+    })?;
+
+    bundle.add_packet(&show_all(minecraft_id))?;
+
+    bundle.add_packet(&play::EntitySetHeadYawS2c {
+        entity_id: VarInt(minecraft_id),
+        head_yaw: ByteAngle::from_degrees(**yaw),
+    })
+}
+
+/// Encodes the items an entity holds and wears, if it has an inventory laid out like the one of
+/// players. Empty slots are left out, since they are empty on the client already.
+fn add_equipment(
+    bundle: &mut DataBundle<'_>,
+    minecraft_id: i32,
+    inventory: &Inventory,
+) -> anyhow::Result<()> {
+    // In the order of `EquipmentEntry::slot`
+    let slots = [
+        inventory.get_cursor_index(),
+        PlayerInventory::OFFHAND_SLOT,
+        PlayerInventory::BOOTS_SLOT,
+        PlayerInventory::LEGGINGS_SLOT,
+        PlayerInventory::CHESTPLATE_SLOT,
+        PlayerInventory::HELMET_SLOT,
+    ];
+
+    let equipment = slots
+        .into_iter()
+        .zip(0..)
+        .filter_map(|(index, slot)| {
+            let stack = &inventory.get(index).ok()?.stack;
+            (!stack.is_empty()).then(|| EquipmentEntry {
+                slot,
+                item: stack.clone(),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    if equipment.is_empty() {
+        return Ok(());
+    }
+
+    bundle.add_packet(&play::EntityEquipmentUpdateS2c {
+        entity_id: VarInt(minecraft_id),
+        equipment,
+    })
+}
+
+/// Encodes the metadata of the entity which differs from the defaults. `metadata` is only used
+/// as a scratch buffer and is empty again afterwards.
+fn add_metadata(
+    bundle: &mut DataBundle<'_>,
+    metadata: &mut MetadataChanges,
+    minecraft_id: i32,
+    entity: EntityRef<'_>,
+    registry: &MetadataRegistry,
+) -> anyhow::Result<()> {
+    metadata.encode_non_default_components(entity, registry);
+
+    if let Some(view) = get_and_clear_metadata(metadata) {
+        bundle.add_packet(&play::EntityTrackerUpdateS2c {
+            entity_id: VarInt(minecraft_id),
+            tracked_values: RawBytes(CowBytes::Borrowed(&view)),
+        })?;
+    }
+
+    Ok(())
+}
+
+fn add_channel(
+    added_channel: On<'_, '_, Add, Channel>,
+    compose: Res<'_, Compose>,
+    ids: Res<'_, MinecraftIdRegistry>,
+    mut channels: ResMut<'_, Channels>,
+    listed_npcs: Query<'_, '_, (&Uuid, &NpcTabList), Without<ConnectionId>>,
+) {
+    channels.insert(added_channel.entity);
+
+    let channel_id = ChannelId::from(added_channel.entity);
     let packet = play::EntitiesDestroyS2c {
-        entity_ids: vec![VarInt(added_channel.entity.minecraft_id())].into(),
+        entity_ids: Cow::Borrowed(&[VarInt(ids.minecraft_id(added_channel.entity))]),
     };
 
-    let packet_buf = compose.io_buf().encode_packet(&packet, &compose).unwrap();
+    let io_buf = compose.io_buf();
 
-    compose
-        .io_buf()
-        .add_channel(ChannelId::new(added_channel.entity.id()), &packet_buf);
+    // Listed NPCs also need to be removed from the tab list of players which stop seeing them
+    if let Ok((uuid, NpcTabList(true))) = listed_npcs.get(added_channel.entity) {
+        let mut unsubscribe = Vec::new();
+        io_buf
+            .encode_packet_into(&packet, &compose, &mut unsubscribe)
+            .unwrap();
+        io_buf
+            .encode_packet_into(
+                &play::PlayerRemoveS2c {
+                    uuids: Cow::Borrowed(&[**uuid]),
+                },
+                &compose,
+                &mut unsubscribe,
+            )
+            .unwrap();
+        io_buf.add_channel(channel_id, &unsubscribe);
+        return;
+    }
+
+    io_buf
+        .with_encoded_packet(&packet, &compose, |packet_buf| {
+            io_buf.add_channel(channel_id, packet_buf);
+        })
+        .unwrap();
 }
 
-fn remove_channel(removed_channel: On<'_, '_, Despawn, Channel>, compose: Res<'_, Compose>) {
+fn remove_channel(
+    removed_channel: On<'_, '_, Despawn, Channel>,
+    compose: Res<'_, Compose>,
+    mut channels: ResMut<'_, Channels>,
+) {
+    channels.remove(removed_channel.entity);
     compose
         .io_buf()
-        .remove_channel(ChannelId::new(removed_channel.entity.id()));
+        .remove_channel(ChannelId::from(removed_channel.entity));
 }
 
 fn update_channel_positions(
     compose: Res<'_, Compose>,
-    query: Query<'_, '_, (Entity, &Position), With<Channel>>,
+    query: Query<
+        '_,
+        '_,
+        (
+            Entity,
+            &Position,
+            Option<&WorldId>,
+            Option<&TrackingRange>,
+            Option<&EntityKind>,
+            Has<Vanished>,
+        ),
+        With<Channel>,
+    >,
+    timings: Res<'_, TickTimings>,
+    config: Res<'_, Config>,
 ) {
+    let _timing = timings.time(TimedSection::ChannelPositions);
     let updates = query
         .iter()
-        .map(|(entity, position)| UpdateChannelPosition {
-            channel_id: entity.id(),
-            position: position.to_chunk().into(),
+        .map(|(entity, position, world, range, kind, vanished)| {
+            let range = range.copied().unwrap_or_else(|| {
+                kind.map_or(TrackingRange(DEFAULT_TRACKING_RANGE), |&kind| {
+                    kind.default_tracking_range()
+                })
+            });
+
+            UpdateChannelPosition {
+                channel_id: entity.id(),
+                position: hyperion_proto::ChunkPosition::from(position.to_chunk())
+                    .with_world(world.copied().unwrap_or_default().inner()),
+                radius: range.chunks(config.view_distance),
+                // Only players who see vanished players are subscribed to their channels
+                hidden: vanished,
+            }
         })
         .collect::<Vec<_>>();
 
@@ -79,86 +303,236 @@ fn send_subscribe_channel_packets(
             &Velocity,
             &EntityKind,
             Option<&ConnectionId>,
+            Option<&Name>,
+            Option<&PlayerSkin>,
+            Option<&ObjectData>,
+            Option<&ExperienceOrbValue>,
+            Option<&NpcTabList>,
+            Option<&Inventory>,
         ),
     >,
     world: &World,
+    ids: Res<'_, MinecraftIdRegistry>,
+    mut commands: Commands<'_, '_>,
+    mut metadata: Local<'_, MetadataChanges>,
 ) {
     for event in events.read() {
-        let (entity, uuid, position, pitch, yaw, velocity, &entity_kind, connection_id) =
-            match query.get(event.0) {
-                Ok(data) => data,
-                Err(e) => {
-                    error!("failed to send subscribe channel packets: query failed: {e}");
-                    continue;
+        let (
+            entity,
+            uuid,
+            position,
+            pitch,
+            yaw,
+            velocity,
+            &entity_kind,
+            connection_id,
+            name,
+            skin,
+            object_data,
+            orb_value,
+            tab_list,
+            inventory,
+        ) = match query.get(event.0) {
+            Ok(data) => data,
+            Err(e) => {
+                error!("failed to send subscribe channel packets: query failed: {e}");
+                continue;
+            }
+        };
+
+        let mut bundle = DataBundle::new(&compose);
+        let minecraft_id = ids.minecraft_id(event.0);
+
+        // The client shows the entity only once everything about it arrived
+        bundle
+            .add_bundle(|bundle| {
+                if entity_kind == EntityKind::Player {
+                    // Real players are in the player list already, but the client needs an entry
+                    // for the NPC to know its skin
+                    if connection_id.is_none() {
+                        let listed = tab_list.is_some_and(|tab_list| tab_list.0);
+                        add_npc_list_entry(bundle, uuid, name, skin, listed)?;
+
+                        if !listed {
+                            commands.entity(entity).insert(PendingListRemoval {
+                                ticks_left: NPC_LIST_ENTRY_TICKS,
+                            });
+                        }
+                    }
+
+                    add_player_spawn(bundle, minecraft_id, uuid, position, pitch, yaw)?;
+                } else if let Some(&ExperienceOrbValue(value)) = orb_value {
+                    // Experience orbs have their own spawn packet, which includes their value
+                    bundle.add_packet(&play::ExperienceOrbSpawnS2c {
+                        entity_id: VarInt(minecraft_id),
+                        position: position.as_dvec3(),
+                        count: i16::try_from(value).unwrap_or(i16::MAX),
+                    })?;
+
+                    bundle.add_packet(&play::EntityVelocityUpdateS2c {
+                        entity_id: VarInt(minecraft_id),
+                        velocity: velocity.to_packet_units(),
+                    })?;
+                } else {
+                    let velocity = velocity.to_packet_units();
+
+                    bundle.add_packet(&play::EntitySpawnS2c {
+                        entity_id: VarInt(minecraft_id),
+                        object_uuid: uuid.0,
+                        kind: VarInt(entity_kind as i32),
+                        position: position.as_dvec3(),
+                        pitch: ByteAngle::from_degrees(**pitch),
+                        yaw: ByteAngle::from_degrees(**yaw),
+                        head_yaw: ByteAngle::from_degrees(**yaw),
+                        data: VarInt(object_data.map_or(0, |data| data.0)),
+                        velocity,
+                    })?;
+
+                    bundle.add_packet(&play::EntityVelocityUpdateS2c {
+                        entity_id: VarInt(minecraft_id),
+                        velocity,
+                    })?;
+
+                    bundle.add_packet(&play::EntitySetHeadYawS2c {
+                        entity_id: VarInt(minecraft_id),
+                        head_yaw: ByteAngle::from_degrees(**yaw),
+                    })?;
                 }
-            };
-
-        let mut packet_buf;
-        let minecraft_id = event.0.minecraft_id();
-
-        if entity_kind == EntityKind::Player {
-            let spawn_packet = play::PlayerSpawnS2c {
-                entity_id: VarInt(minecraft_id),
-                player_uuid: **uuid,
-                position: position.as_dvec3(),
-                yaw: ByteAngle::from_degrees(**yaw),
-                pitch: ByteAngle::from_degrees(**pitch),
-            };
-            packet_buf = compose
-                .io_buf()
-                .encode_packet(&spawn_packet, &compose)
-                .unwrap();
-
-            let show_all = show_all(minecraft_id);
-            packet_buf
-                .extend_from_slice(&compose.io_buf().encode_packet(&show_all, &compose).unwrap());
-        } else {
-            let velocity = velocity.to_packet_units();
-
-            let spawn_packet = play::EntitySpawnS2c {
-                entity_id: VarInt(minecraft_id),
-                object_uuid: uuid.0,
-                kind: VarInt(entity_kind as i32),
-                position: position.as_dvec3(),
-                pitch: ByteAngle::from_degrees(**pitch),
-                yaw: ByteAngle::from_degrees(**yaw),
-                head_yaw: ByteAngle::from_degrees(0.0), // todo:
-                data: VarInt::default(),                // todo:
-                velocity,
-            };
-            packet_buf = compose
-                .io_buf()
-                .encode_packet(&spawn_packet, &compose)
-                .unwrap();
-
-            let velocity_packet = play::EntityVelocityUpdateS2c {
-                entity_id: VarInt(minecraft_id),
-                velocity,
-            };
-            packet_buf.extend_from_slice(
-                &compose
-                    .io_buf()
-                    .encode_packet(&velocity_packet, &compose)
-                    .unwrap(),
-            );
+
+                if let Some(inventory) = inventory {
+                    add_equipment(bundle, minecraft_id, inventory)?;
+                }
+
+                add_metadata(
+                    bundle,
+                    &mut metadata,
+                    minecraft_id,
+                    world.entity(entity),
+                    world.resource::<MetadataRegistry>(),
+                )?;
+
+                // Whichever of the vehicle and its passengers is spawned last puts them together,
+                // since the client ignores passengers it does not know yet
+                if let Some(passengers) = world.get::<Passengers>(entity) {
+                    bundle.add_packet(&passengers_packet(entity, passengers, &ids))?;
+                }
+
+                if let Some(&Riding(vehicle)) = world.get::<Riding>(entity)
+                    && let Some(passengers) = world.get::<Passengers>(vehicle)
+                {
+                    bundle.add_packet(&passengers_packet(vehicle, passengers, &ids))?;
+                }
+
+                Ok(())
+            })
+            .unwrap();
+
+        bundle.send_subscribe_channel_packets(event.0.into(), connection_id.copied());
+    }
+}
+
+/// Respawns NPCs with their new skin for every player subscribed to their channel. The skins of
+/// real players are left to the game, since changing them requires respawning the player itself.
+fn set_npc_skin(
+    mut events: MessageReader<'_, '_, SetSkin>,
+    compose: Res<'_, Compose>,
+    query: Query<
+        '_,
+        '_,
+        (
+            &Uuid,
+            &Position,
+            &Pitch,
+            &Yaw,
+            &EntityKind,
+            Option<&Name>,
+            Option<&NpcTabList>,
+            Option<&Inventory>,
+        ),
+        (With<Channel>, Without<ConnectionId>),
+    >,
+    world: &World,
+    ids: Res<'_, MinecraftIdRegistry>,
+    mut commands: Commands<'_, '_>,
+    mut metadata: Local<'_, MetadataChanges>,
+) {
+    for event in events.read() {
+        let Ok((uuid, position, pitch, yaw, &entity_kind, name, tab_list, inventory)) =
+            query.get(event.by)
+        else {
+            continue;
+        };
+
+        if entity_kind != EntityKind::Player {
+            error!("failed to set skin: entity {:?} is not a player", event.by);
+            continue;
         }
 
-        let mut metadata = MetadataChanges::default();
-        metadata.encode_non_default_components(world.entity(entity));
+        let minecraft_id = ids.minecraft_id(event.by);
+        let mut bundle = DataBundle::new(&compose);
+
+        bundle
+            .add_packet(&play::EntitiesDestroyS2c {
+                entity_ids: Cow::Borrowed(&[VarInt(minecraft_id)]),
+            })
+            .unwrap();
+
+        // The client only applies a skin when the player is added to the list
+        bundle
+            .add_packet(&play::PlayerRemoveS2c {
+                uuids: Cow::Borrowed(&[**uuid]),
+            })
+            .unwrap();
 
-        if let Some(view) = get_and_clear_metadata(&mut metadata) {
-            let pkt = play::EntityTrackerUpdateS2c {
-                entity_id: VarInt(minecraft_id),
-                tracked_values: RawBytes(CowBytes::Borrowed(&view)),
-            };
-            packet_buf.extend_from_slice(&compose.io_buf().encode_packet(&pkt, &compose).unwrap());
+        let listed = tab_list.is_some_and(|tab_list| tab_list.0);
+        add_npc_list_entry(&mut bundle, uuid, name, Some(&event.skin), listed).unwrap();
+        add_player_spawn(&mut bundle, minecraft_id, uuid, position, pitch, yaw).unwrap();
+        if let Some(inventory) = inventory {
+            add_equipment(&mut bundle, minecraft_id, inventory).unwrap();
         }
+        add_metadata(
+            &mut bundle,
+            &mut metadata,
+            minecraft_id,
+            world.entity(event.by),
+            world.resource::<MetadataRegistry>(),
+        )
+        .unwrap();
 
-        compose.io_buf().send_subscribe_channel_packets(
-            event.0.into(),
-            &packet_buf,
-            connection_id.copied(),
-        );
+        bundle.broadcast_channel(event.by.into()).unwrap();
+
+        let mut npc = commands.entity(event.by);
+        npc.insert(event.skin.clone());
+
+        if !listed {
+            npc.insert(PendingListRemoval {
+                ticks_left: NPC_LIST_ENTRY_TICKS,
+            });
+        }
+    }
+}
+
+/// Removes NPCs from the player list once their skin has been loaded by the client
+fn remove_npc_list_entries(
+    compose: Res<'_, Compose>,
+    mut query: Query<'_, '_, (Entity, &Uuid, &mut PendingListRemoval), With<Channel>>,
+    mut commands: Commands<'_, '_>,
+) {
+    for (entity, uuid, mut pending) in &mut query {
+        pending.ticks_left = pending.ticks_left.saturating_sub(1);
+        if pending.ticks_left > 0 {
+            continue;
+        }
+
+        let mut bundle = DataBundle::new(&compose);
+        bundle
+            .add_packet(&play::PlayerRemoveS2c {
+                uuids: Cow::Borrowed(&[**uuid]),
+            })
+            .unwrap();
+        bundle.broadcast_channel(entity.into()).unwrap();
+
+        commands.entity(entity).remove::<PendingListRemoval>();
     }
 }
 
@@ -166,11 +540,108 @@ pub struct ChannelPlugin;
 
 impl Plugin for ChannelPlugin {
     fn build(&self, app: &mut App) {
+        app.init_resource::<Channels>();
         app.add_observer(add_channel);
         app.add_observer(remove_channel);
         app.add_systems(
             FixedUpdate,
-            (update_channel_positions, send_subscribe_channel_packets),
+            (
+                update_channel_positions.run_if(server_active),
+                send_subscribe_channel_packets,
+                set_npc_skin,
+                remove_npc_list_entries,
+            ),
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use libdeflater::CompressionLvl;
+    use valence_generated::item::ItemKind;
+    use valence_protocol::{CompressionThreshold, Encode, ItemStack, Packet};
+
+    use super::*;
+    use crate::{Shared, net::IoBuf};
+
+    /// Frames `body` as a packet with the id of `P`, like it is sent without compression
+    fn frame<P: Packet>(body: &[u8]) -> Vec<u8> {
+        let mut packet = Vec::new();
+        VarInt(P::ID).encode(&mut packet).unwrap();
+        packet.extend_from_slice(body);
+
+        let mut framed = Vec::new();
+        VarInt(i32::try_from(packet.len()).unwrap())
+            .encode(&mut framed)
+            .unwrap();
+        framed.extend_from_slice(&packet);
+        framed
+    }
+
+    /// The encoding of a stack of one `item` without NBT
+    fn stack(item: ItemKind) -> Vec<u8> {
+        let mut bytes = vec![1];
+        VarInt(i32::from(item.to_raw())).encode(&mut bytes).unwrap();
+        bytes.extend_from_slice(&[1, 0]);
+        bytes
+    }
+
+    #[test]
+    fn npc_spawn_includes_head_yaw_and_equipment() {
+        let compose = Compose::new(
+            CompressionLvl::default(),
+            Arc::new(Shared {
+                compression_threshold: CompressionThreshold(-1),
+                compression_level: CompressionLvl::default(),
+            }),
+            IoBuf::default(),
+        );
+
+        let uuid = Uuid(uuid::Uuid::from_u128(0x1659));
+        let position = Position::new(1.5, 64.0, -2.5);
+
+        let mut inventory = PlayerInventory::default();
+        inventory
+            .set_hotbar(0, ItemStack::new(ItemKind::DiamondSword, 1, None))
+            .unwrap();
+        inventory.set_helmet(ItemStack::new(ItemKind::DiamondHelmet, 1, None));
+
+        let mut bundle = DataBundle::new(&compose);
+        add_player_spawn(
+            &mut bundle,
+            7,
+            &uuid,
+            &position,
+            &Pitch::new(45.0),
+            &Yaw::new(90.0),
+        )
+        .unwrap();
+        add_equipment(&mut bundle, 7, &inventory).unwrap();
+
+        let mut spawn = vec![7];
+        spawn.extend_from_slice(&0x1659_u128.to_be_bytes());
+        for coordinate in [1.5_f64, 64.0, -2.5] {
+            spawn.extend_from_slice(&coordinate.to_be_bytes());
+        }
+        // 90° and 45° in 256ths of a turn
+        spawn.extend_from_slice(&[64, 32]);
+
+        // Every slot except the last has the top bit set
+        let mut equipment = vec![7, 0x80];
+        equipment.extend(stack(ItemKind::DiamondSword));
+        equipment.push(5);
+        equipment.extend(stack(ItemKind::DiamondHelmet));
+
+        let expected = [
+            frame::<play::PlayerSpawnS2c>(&spawn),
+            frame::<play::EntityTrackerUpdateS2c<'_>>(&[7, 17, 0, 0xff, 0xff]),
+            frame::<play::EntitySetHeadYawS2c>(&[7, 64]),
+            frame::<play::EntityEquipmentUpdateS2c>(&equipment),
+        ]
+        .concat();
+
+        assert_eq!(bundle.as_bytes(), expected);
+    }
+}
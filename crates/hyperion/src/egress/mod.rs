@@ -1,23 +1,37 @@
-use bevy_app::{App, Plugin, PostUpdate};
-use bevy_ecs::system::{Query, Res, ResMut};
+use bevy_app::{App, Last, Plugin, PostUpdate};
+use bevy_ecs::{
+    schedule::IntoScheduleConfigs,
+    system::{Query, Res, ResMut},
+};
+use glam::I16Vec2;
+use rustc_hash::FxHashSet;
 use tracing::error;
 use valence_protocol::{VarInt, packets::play::PlayerActionResponseS2c};
 
 use crate::{
     Blocks,
     net::{
-        Compose, ConnectionId,
+        Compose, ConnectionId, DataBundle, SendResultExt,
         intermediate::{IntermediateServerToProxyMessage, UpdatePlayerPositions},
+        recipients,
+    },
+    simulation::{
+        ChunkPosition, ConfirmBlockSequences, Position,
+        blocks::fake::FakeBlocks,
+        world::{WorldId, Worlds},
     },
-    simulation::Position,
+    timings::{self, TickTimings, TimedSection},
 };
+pub mod backlog;
 mod channel;
 pub mod metadata;
 pub mod player_join;
+pub mod resync;
 mod stats;
 pub mod sync_chunks;
 mod sync_entity_state;
 
+use backlog::BacklogPlugin;
 use channel::ChannelPlugin;
 use player_join::PlayerJoinPlugin;
 use stats::StatsPlugin;
@@ -26,15 +40,23 @@ use sync_entity_state::EntityStateSyncPlugin;
 
 fn send_chunk_positions(
     compose: Res<'_, Compose>,
-    query: Query<'_, '_, (&ConnectionId, &Position)>,
+    query: Query<'_, '_, (&ConnectionId, &Position, &ChunkPosition, Option<&WorldId>)>,
 ) {
     let count = query.iter().count();
     let mut stream = Vec::with_capacity(count);
     let mut positions = Vec::with_capacity(count);
 
-    for (&io, pos) in query.iter() {
+    for (&io, pos, chunk_position, world) in query.iter() {
+        // Players are not in the world until their chunks are being sent, so they should not
+        // receive local broadcasts or be subscribed to channels yet
+        if chunk_position.position.is_none() {
+            continue;
+        }
+
+        let world = world.copied().unwrap_or_default();
         stream.push(io);
-        positions.push(hyperion_proto::ChunkPosition::from(pos.to_chunk()));
+        positions
+            .push(hyperion_proto::ChunkPosition::from(pos.to_chunk()).with_world(world.inner()));
     }
 
     let packet = UpdatePlayerPositions { stream, positions };
@@ -44,14 +66,99 @@ fn send_chunk_positions(
     compose.io_buf().add_proxy_message(&chunk_positions);
 }
 
+fn flush_batch(mut compose: ResMut<'_, Compose>, timings: Res<'_, TickTimings>) {
+    let _timing = timings.time(TimedSection::EgressFlush);
+    compose.io_buf_mut().flush_batch();
+}
+
 fn broadcast_chunk_deltas(
     compose: Res<'_, Compose>,
     mut blocks: ResMut<'_, Blocks>,
-    query: Query<'_, '_, &ConnectionId>,
+    mut worlds: ResMut<'_, Worlds>,
+    mut fake_query: Query<'_, '_, (&ConnectionId, &mut FakeBlocks, Option<&WorldId>)>,
+    mut confirm_query: Query<'_, '_, (&ConnectionId, &mut ConfirmBlockSequences)>,
 ) {
+    let changed = broadcast_world_deltas(&compose, &mut blocks, WorldId::PRIMARY);
+    let mut changed = vec![(WorldId::PRIMARY, changed)];
+
+    for (world, blocks) in worlds.iter_mut() {
+        changed.push((world, broadcast_world_deltas(&compose, blocks, world)));
+    }
+
+    // The fake blocks are sent after the real blocks which they replace
+    for (&connection_id, mut fake_blocks, world) in &mut fake_query {
+        let world = world.copied().unwrap_or_default();
+        let Some((_, changed_chunks)) = changed.iter().find(|(id, _)| *id == world) else {
+            continue;
+        };
+        let blocks = if world == WorldId::PRIMARY {
+            &*blocks
+        } else {
+            let Some(blocks) = worlds.get(world) else {
+                continue;
+            };
+            blocks
+        };
+
+        let mut bundle = DataBundle::new(&compose);
+        fake_blocks.add_updates(&mut bundle, blocks, changed_chunks);
+        bundle.unicast(connection_id).unwrap_or_disconnected();
+    }
+
+    let to_confirm = blocks.to_confirm.drain(..).chain(
+        worlds
+            .iter_mut()
+            .flat_map(|(_, blocks)| blocks.to_confirm.drain(..)),
+    );
+
+    for to_confirm in to_confirm {
+        match confirm_query.get_mut(to_confirm.entity) {
+            Ok((_, mut sequences)) => sequences.push(to_confirm.sequence),
+            Err(e) => error!("failed to confirm block sequence: query failed: {e}"),
+        }
+    }
+
+    send_block_acks(&compose, &mut confirm_query);
+}
+
+/// Acknowledges the highest pending block sequence of every player. This is sent after the block
+/// updates, since the client reverts the blocks it predicted once their sequence is acknowledged.
+fn send_block_acks(
+    compose: &Compose,
+    query: &mut Query<'_, '_, (&ConnectionId, &mut ConfirmBlockSequences)>,
+) {
+    for (&connection_id, mut sequences) in query {
+        if sequences.is_empty() {
+            continue;
+        }
+
+        let Some(sequence) = sequences.take_highest() else {
+            continue;
+        };
+
+        let pkt = PlayerActionResponseS2c {
+            sequence: VarInt(sequence),
+        };
+
+        compose
+            .unicast(&pkt, connection_id)
+            .unwrap_or_disconnected();
+    }
+}
+
+/// Returns the chunks whose changes were sent
+fn broadcast_world_deltas(
+    compose: &Compose,
+    blocks: &mut Blocks,
+    world: WorldId,
+) -> FxHashSet<I16Vec2> {
+    let mut changed = FxHashSet::default();
+
     blocks.for_each_to_update_mut(|chunk| {
+        changed.insert(chunk.position.as_i16vec2());
+
         for packet in chunk.delta_drain_packets() {
-            if let Err(e) = compose.broadcast(packet).send() {
+            if let Err(e) = compose.broadcast(packet).world(world).send() {
                 error!("failed to send chunk delta packet: {e}");
                 return;
             }
@@ -59,23 +166,7 @@ fn broadcast_chunk_deltas(
     });
     blocks.clear_should_update();
 
-    for to_confirm in blocks.to_confirm.drain(..) {
-        let connection_id = match query.get(to_confirm.entity) {
-            Ok(connection_id) => *connection_id,
-            Err(e) => {
-                error!("failed to send player action response: query failed: {e}");
-                continue;
-            }
-        };
-
-        let pkt = PlayerActionResponseS2c {
-            sequence: VarInt(to_confirm.sequence),
-        };
-
-        if let Err(e) = compose.unicast(&pkt, connection_id) {
-            error!("failed to send player action response: {e}");
-        }
-    }
+    changed
 }
 
 pub struct EgressPlugin;
@@ -83,7 +174,11 @@ pub struct EgressPlugin;
 impl Plugin for EgressPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(PostUpdate, (send_chunk_positions, broadcast_chunk_deltas));
+        app.add_systems(Last, flush_batch.before(timings::finish_tick));
+        app.add_observer(recipients::enter_play);
+        app.add_observer(recipients::leave_play);
         app.add_plugins((
+            BacklogPlugin,
             PlayerJoinPlugin,
             StatsPlugin,
             SyncChunksPlugin,
@@ -92,3 +187,122 @@ impl Plugin for EgressPlugin {
         ));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bevy_ecs::{system::RunSystemOnce, world::World};
+    use glam::{I16Vec2, Vec3};
+    use valence_generated::block::BlockState;
+    use valence_protocol::{BlockPos, packets::play::BlockUpdateS2c};
+
+    use super::*;
+    use crate::{
+        Shared,
+        net::{IoBuf, ProxyId},
+        runtime::AsyncRuntime,
+    };
+
+    fn compose_with_proxy() -> (Compose, tokio::sync::mpsc::UnboundedReceiver<bytes::Bytes>) {
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut io_buf = IoBuf::default();
+        io_buf.add_proxy(ProxyId::new(0), tx.into());
+
+        let compose = Compose::new(
+            libdeflater::CompressionLvl::default(),
+            Arc::new(Shared {
+                compression_threshold: valence_protocol::CompressionThreshold(-1),
+                compression_level: libdeflater::CompressionLvl::default(),
+            }),
+            io_buf,
+        );
+
+        (compose, rx)
+    }
+
+    #[test]
+    fn players_without_a_view_are_not_sent_to_the_proxy() {
+        let (compose, mut rx) = compose_with_proxy();
+
+        let mut world = World::new();
+        world.insert_resource(compose);
+
+        let stream = ConnectionId::new(1, ProxyId::new(0));
+        let player = world
+            .spawn((stream, Position::from(Vec3::ZERO), ChunkPosition::default()))
+            .id();
+
+        let expected = |players: &[ConnectionId]| {
+            let message =
+                IntermediateServerToProxyMessage::UpdatePlayerPositions(UpdatePlayerPositions {
+                    stream: players.to_vec(),
+                    positions: players
+                        .iter()
+                        .map(|_| hyperion_proto::ChunkPosition::new(0, 0))
+                        .collect(),
+                });
+            IoBuf::encode_proxy_message(&message.transform_for_proxy(ProxyId::new(0)).unwrap())
+        };
+
+        // A freshly joined player has no position until its first chunks are sent
+        world.run_system_once(send_chunk_positions).unwrap();
+        assert_eq!(rx.try_recv().unwrap(), expected(&[]));
+
+        world.get_mut::<ChunkPosition>(player).unwrap().position = Some(I16Vec2::ZERO);
+        world.run_system_once(send_chunk_positions).unwrap();
+        assert_eq!(rx.try_recv().unwrap(), expected(&[stream]));
+    }
+
+    #[test]
+    fn block_sequences_are_acknowledged_once_after_corrections() {
+        let (compose, mut rx) = compose_with_proxy();
+        let runtime = AsyncRuntime::new();
+
+        let mut world = World::new();
+        world.insert_resource(compose);
+        world.insert_resource(Blocks::empty(&runtime));
+        world.insert_resource(Worlds::default());
+
+        let stream = ConnectionId::new(1, ProxyId::new(0));
+        let player = world.spawn((stream, ConfirmBlockSequences::default())).id();
+
+        let correction = BlockUpdateS2c {
+            position: BlockPos::new(0, 64, 0),
+            block_id: BlockState::AIR,
+        };
+
+        // Three placements in one tick, the second of which the game rejects
+        world
+            .get_mut::<ConfirmBlockSequences>(player)
+            .unwrap()
+            .extend([1, 2, 3]);
+        world
+            .resource::<Compose>()
+            .unicast(&correction, stream)
+            .unwrap();
+
+        world.run_system_once(broadcast_chunk_deltas).unwrap();
+
+        let (expected_compose, mut expected_rx) = compose_with_proxy();
+        expected_compose.unicast(&correction, stream).unwrap();
+        expected_compose
+            .unicast(
+                &PlayerActionResponseS2c {
+                    sequence: VarInt(3),
+                },
+                stream,
+            )
+            .unwrap();
+
+        assert_eq!(rx.try_recv().unwrap(), expected_rx.try_recv().unwrap());
+        assert_eq!(rx.try_recv().unwrap(), expected_rx.try_recv().unwrap());
+        assert!(rx.try_recv().is_err());
+        assert!(
+            world
+                .get::<ConfirmBlockSequences>(player)
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
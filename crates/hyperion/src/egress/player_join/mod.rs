@@ -8,13 +8,14 @@ use bevy_ecs::{
     message::{Message, MessageReader, MessageWriter},
     name::Name,
     observer::On,
+    query::{Has, With},
     system::{Local, ParallelCommands, Query, Res},
     world::{FromWorld, World},
 };
 use bytes::Bytes;
 use glam::DVec3;
 use hyperion_crafting::{Action, CraftingRegistry, RecipeBookState};
-use hyperion_utils::EntityExt;
+use hyperion_utils::{Args, Translations, localization::DEFAULT_LANGUAGE};
 use tracing::{error, info};
 use valence_bytes::{CowBytes, CowUtf8Bytes, Utf8Bytes};
 use valence_protocol::{
@@ -26,19 +27,31 @@ use valence_protocol::{
         team_s2c::{CollisionRule, Mode, NameTagVisibility, TeamColor, TeamFlags},
     },
 };
-use valence_registry::{BiomeRegistry, RegistryCodec};
+use valence_registry::BiomeRegistry;
 use valence_text::IntoText;
 
-use crate::simulation::{MovementTracking, packet_state};
+use crate::{
+    net::SendResultExt,
+    simulation::{
+        MovementTracking,
+        game_rules::GameRules,
+        join::{self, PlayerGameMode},
+        packet_state,
+        team::{NO_TAG_TEAM, TeamMember},
+        vanish::{SeesVanished, Vanished},
+    },
+};
 
 mod list;
 pub use list::*;
 
+use super::sync_entity_state::PositionSync;
 use crate::{
     config::Config,
     net::{Channel, Compose, ConnectionId, DataBundle},
     simulation::{
-        PendingTeleportation, Position, Uuid, Yaw, skin::PlayerSkin, util::registry_codec_raw,
+        PendingTeleportation, Position, Uuid, Yaw, minecraft_id::MinecraftIdRegistry,
+        registry::RegistryCodec, skin::PlayerSkin,
     },
 };
 
@@ -47,36 +60,83 @@ struct ProcessPlayerJoin(Entity);
 
 fn add_process_player_join(
     added_player_skin: On<'_, '_, Add, PlayerSkin>,
+    players: Query<'_, '_, (), With<ConnectionId>>,
     mut events: MessageWriter<'_, ProcessPlayerJoin>,
 ) {
-    events.write(ProcessPlayerJoin(added_player_skin.entity));
+    // NPCs have skins too, but they do not join
+    if players.contains(added_player_skin.entity) {
+        events.write(ProcessPlayerJoin(added_player_skin.entity));
+    }
 }
 
 fn process_player_join(
     mut events: MessageReader<'_, '_, ProcessPlayerJoin>,
     compose: Res<'_, Compose>,
     config: Res<'_, Config>,
-    target_query: Query<'_, '_, (&Uuid, &Name, &ConnectionId, &Position, &Yaw, &PlayerSkin)>,
-    others_query: Query<'_, '_, (Entity, &Uuid, &Name)>,
+    rules: Res<'_, GameRules>,
+    registry_codec: Res<'_, RegistryCodec>,
+    target_query: Query<
+        '_,
+        '_,
+        (
+            &Uuid,
+            &Name,
+            &ConnectionId,
+            &Position,
+            &Yaw,
+            &PlayerSkin,
+            Option<&PlayerGameMode>,
+            Has<TeamMember>,
+            Has<Vanished>,
+            Has<SeesVanished>,
+        ),
+    >,
+    others_query: Query<
+        '_,
+        '_,
+        (
+            Entity,
+            &Uuid,
+            &Name,
+            &ConnectionId,
+            Has<TeamMember>,
+            Has<Vanished>,
+            Has<SeesVanished>,
+            Has<packet_state::Play>,
+        ),
+    >,
     commands: ParallelCommands<'_, '_>,
+    ids: Res<'_, MinecraftIdRegistry>,
+    translations: Res<'_, Translations>,
     common_response: Local<'_, CommonPlayerJoinResponses>,
 ) {
     events.par_read().for_each(|event| {
         let mut bundle = DataBundle::new(&compose);
 
         let entity_id = event.0;
-        let id = entity_id.minecraft_id();
-
-        let (uuid, name, &connection_id, position, yaw, skin) = match target_query.get(entity_id) {
+        let id = ids.minecraft_id(entity_id);
+
+        let (
+            uuid,
+            name,
+            &connection_id,
+            position,
+            yaw,
+            skin,
+            game_mode,
+            in_team,
+            vanished,
+            sees_vanished,
+        ) = match target_query.get(entity_id) {
             Ok(components) => components,
             Err(e) => {
                 error!("player_join_world failed: {e}");
                 return;
             }
         };
+        let game_mode = game_mode.copied().unwrap_or_default().0;
 
-        let registry_codec = registry_codec_raw();
-        let codec = RegistryCodec::default();
+        let codec = valence_registry::RegistryCodec::default();
 
         let dimension_names: BTreeSet<Ident> = codec
             .registry(BiomeRegistry::KEY)
@@ -91,174 +151,231 @@ fn process_player_join(
             entity_id: id,
             is_hardcore: false,
             dimension_names: Cow::Owned(dimension_names),
-            registry_codec: Cow::Borrowed(registry_codec),
+            registry_codec: Cow::Borrowed(registry_codec.compound()),
             max_players: config.max_players.into(),
             view_distance: VarInt(i32::from(config.view_distance)),
             simulation_distance: config.simulation_distance.into(),
-            reduced_debug_info: false,
-            enable_respawn_screen: false,
+            reduced_debug_info: rules.reduced_debug_info,
+            enable_respawn_screen: !rules.immediate_respawn,
             dimension_name,
             hashed_seed: 0,
-            game_mode: GameMode::Survival,
+            game_mode,
             is_flat: false,
             last_death_location: None,
             portal_cooldown: 60.into(),
-            previous_game_mode: OptGameMode(Some(GameMode::Survival)),
-            dimension_type_name: ident!("minecraft:overworld"),
+            previous_game_mode: OptGameMode(Some(game_mode)),
+            dimension_type_name: registry_codec.join_dimension_type().clone(),
             is_debug: false,
         };
 
-        bundle.add_packet(&pkt).unwrap();
-
-        let center_chunk = position.to_chunk();
+        // Everything the client needs to show the world is applied at once
+        let result = bundle.add_bundle(|bundle| {
+            bundle.add_packet(&pkt)?;
 
-        let pkt = play::ChunkRenderDistanceCenterS2c {
-            chunk_x: VarInt(i32::from(center_chunk.x)),
-            chunk_z: VarInt(i32::from(center_chunk.y)),
-        };
+            let center_chunk = position.to_chunk();
 
-        bundle.add_packet(&pkt).unwrap();
-
-        let pkt = play::PlayerSpawnPositionS2c {
-            position: position.as_dvec3().into(),
-            angle: **yaw,
-        };
+            let pkt = play::ChunkRenderDistanceCenterS2c {
+                chunk_x: VarInt(i32::from(center_chunk.x)),
+                chunk_z: VarInt(i32::from(center_chunk.y)),
+            };
 
-        bundle.add_packet(&pkt).unwrap();
+            bundle.add_packet(&pkt)?;
 
-        bundle.add_raw(&common_response.cached_data);
+            let pkt = rules.spawn_position_packet(**position, **yaw);
 
-        let text = play::GameMessageS2c {
-            chat: format!("{name} joined the world").into_cow_text(),
-            overlay: false,
-        };
+            bundle.add_packet(&pkt)?;
 
-        compose.broadcast(&text).send().unwrap();
+            bundle.add_raw(&common_response.cached_data);
 
-        // Subtracts one to exclude current player
-        let others_len = others_query.iter().len() - 1;
-        let mut entries = Vec::with_capacity(others_len);
-        let mut all_player_names = Vec::with_capacity(others_len);
+            // The message is broadcast to everyone at once, so it cannot be in the language of
+            // each player
+            if !vanished {
+                let message =
+                    translations.tr(DEFAULT_LANGUAGE, "player.joined", &Args::new().arg(name));
+                let text = play::GameMessageS2c {
+                    chat: message.into_cow_text(),
+                    overlay: false,
+                };
 
-        let scope = tracing::info_span!("collect_others").entered();
-        for (current_entity, uuid, name) in others_query {
-            if entity_id == current_entity {
-                continue;
+                compose.broadcast(&text).batched().send()?;
             }
 
-            // Update player list entries
-            let entry = PlayerListEntry {
-                player_uuid: uuid.0,
-                username: CowUtf8Bytes::Borrowed(name),
-                properties: Cow::Owned(Vec::new()),
-                chat_data: None,
-                listed: true,
-                ping: 20,
-                game_mode: GameMode::Creative,
-                display_name: Some(name.to_string().into_cow_text()),
-            };
-
-            entries.push(entry);
-            all_player_names.push(name.to_string());
-        }
-        scope.exit();
-
-        let all_player_names = all_player_names
-            .iter()
-            .map(String::as_str)
-            .map(Into::into)
-            .collect();
-
-        let actions = PlayerListActions::default()
-            .with_add_player(true)
-            .with_update_listed(true)
-            .with_update_display_name(true);
-
-        {
-            let _scope = tracing::info_span!("unicasting_player_list").entered();
-            bundle
-                .add_packet(&PlayerListS2c {
+            // Subtracts one to exclude current player
+            let others_len = others_query.iter().len() - 1;
+            let mut entries = Vec::with_capacity(others_len);
+            let mut all_player_names = Vec::with_capacity(others_len);
+            // The players in the play state who see the player if they are vanished
+            let mut staff = Vec::new();
+
+            let scope = tracing::info_span!("collect_others").entered();
+            for (
+                current_entity,
+                uuid,
+                name,
+                &other_connection_id,
+                other_in_team,
+                other_vanished,
+                other_sees_vanished,
+                other_playing,
+            ) in others_query
+            {
+                if entity_id == current_entity {
+                    continue;
+                }
+
+                if vanished && other_sees_vanished && other_playing {
+                    staff.push(other_connection_id);
+                }
+
+                // Update player list entries
+                let entry = PlayerListEntry {
+                    player_uuid: uuid.0,
+                    username: CowUtf8Bytes::Borrowed(name),
+                    properties: Cow::Owned(Vec::new()),
+                    chat_data: None,
+                    listed: !other_vanished || sees_vanished,
+                    ping: 20,
+                    game_mode: GameMode::Creative,
+                    display_name: Some(name.to_string().into_cow_text()),
+                };
+
+                entries.push(entry);
+
+                // Players in a team are sent with the teams
+                if !other_in_team {
+                    all_player_names.push(name.to_string());
+                }
+            }
+            scope.exit();
+
+            let all_player_names = all_player_names
+                .iter()
+                .map(String::as_str)
+                .map(Into::into)
+                .collect();
+
+            let actions = PlayerListActions::default()
+                .with_add_player(true)
+                .with_update_listed(true)
+                .with_update_display_name(true);
+
+            {
+                let _scope = tracing::info_span!("unicasting_player_list").entered();
+                bundle.add_packet(&PlayerListS2c {
                     actions,
                     entries: Cow::Owned(entries),
-                })
-                .unwrap();
-        }
-
-        let PlayerSkin {
-            textures,
-            signature,
-        } = skin.clone();
+                })?;
+            }
 
-        // todo: in future, do not clone
-        let property = valence_protocol::profile::Property {
-            name: Utf8Bytes::from_static("textures"),
-            value: textures.into(),
-            signature: Some(signature.into()),
-        };
+            let PlayerSkin {
+                textures,
+                signature,
+            } = skin.clone();
 
-        let property = &[property];
-
-        let singleton_entry = &[PlayerListEntry {
-            player_uuid: **uuid,
-            username: CowUtf8Bytes::Borrowed(name),
-            properties: Cow::Borrowed(property),
-            chat_data: None,
-            listed: true,
-            ping: 20,
-            game_mode: GameMode::Survival,
-            display_name: Some(name.to_string().into_cow_text()),
-        }];
-
-        let pkt = PlayerListS2c {
-            actions,
-            entries: Cow::Borrowed(singleton_entry),
-        };
+            // todo: in future, do not clone
+            let property = valence_protocol::profile::Property {
+                name: Utf8Bytes::from_static("textures"),
+                value: textures.into(),
+                signature: Some(signature.into()),
+            };
 
-        compose.broadcast(&pkt).send().unwrap();
-        bundle.add_packet(&pkt).unwrap();
+            let property = &[property];
+
+            let singleton_entry = |listed| {
+                [PlayerListEntry {
+                    player_uuid: **uuid,
+                    username: CowUtf8Bytes::Borrowed(name),
+                    properties: Cow::Borrowed(property),
+                    chat_data: None,
+                    listed,
+                    ping: 20,
+                    game_mode,
+                    display_name: Some(name.to_string().into_cow_text()),
+                }]
+            };
 
-        let player_name = vec![CowUtf8Bytes::Borrowed(name.as_str())];
+            let listed_entry = singleton_entry(true);
+            let pkt = PlayerListS2c {
+                actions,
+                entries: Cow::Borrowed(&listed_entry),
+            };
 
-        compose
-            .broadcast(&play::TeamS2c {
-                team_name: Utf8Bytes::from_static("no_tag").into(),
-                mode: Mode::AddEntities {
-                    entities: player_name,
-                },
-            })
-            .exclude(connection_id)
-            .send()
-            .unwrap();
+            if vanished {
+                // The player and staff still see the vanished player in the player list
+                let unlisted_entry = singleton_entry(false);
+                let unlisted = PlayerListS2c {
+                    actions,
+                    entries: Cow::Borrowed(&unlisted_entry),
+                };
+                compose
+                    .broadcast(&unlisted)
+                    .exclude_many(&staff)
+                    .batched()
+                    .send()?;
+
+                for &viewer in &staff {
+                    compose.unicast(&pkt, viewer).unwrap_or_disconnected();
+                }
+            } else {
+                compose.broadcast(&pkt).batched().send()?;
+            }
+            bundle.add_packet(&pkt)?;
+
+            if !in_team {
+                let player_name = vec![CowUtf8Bytes::Borrowed(name.as_str())];
+
+                compose
+                    .broadcast(&play::TeamS2c {
+                        team_name: Utf8Bytes::from_static(NO_TAG_TEAM).into(),
+                        mode: Mode::AddEntities {
+                            entities: player_name,
+                        },
+                    })
+                    .exclude(connection_id)
+                    .batched()
+                    .send()?;
+            }
 
-        bundle
-            .add_packet(&play::TeamS2c {
-                team_name: Utf8Bytes::from_static("no_tag").into(),
+            bundle.add_packet(&play::TeamS2c {
+                team_name: Utf8Bytes::from_static(NO_TAG_TEAM).into(),
                 mode: Mode::AddEntities {
                     entities: all_player_names,
                 },
-            })
-            .unwrap();
+            })?;
 
-        bundle.unicast(connection_id).unwrap();
+            Ok(())
+        });
 
-        compose.io_buf().set_receive_broadcasts(connection_id);
+        if let Err(e) = result {
+            error!("failed to send the join packets of {entity_id:?}: {e:#}");
+            return;
+        }
+
+        // The proxy sends broadcasts to the player once it enters the play state after this
+        bundle.unicast(connection_id).unwrap_or_disconnected();
 
         let position = **position;
         commands.command_scope(move |mut commands| {
-            commands.entity(entity_id).insert((
-                Channel,
-                MovementTracking {
-                    received_movement_packets: 0,
-                    last_tick_flying: false,
-                    last_tick_position: position,
-                    fall_start_y: position.y,
-                    server_velocity: DVec3::ZERO,
-                    sprinting: false,
-                    was_on_ground: false,
-                },
-                PendingTeleportation::new(position),
-                packet_state::Play,
-            ));
+            join::enter_play(
+                &mut commands,
+                entity_id,
+                (
+                    Channel,
+                    MovementTracking {
+                        received_movement_packets: 0,
+                        last_tick_flying: false,
+                        last_tick_position: position,
+                        fall_start_y: position.y,
+                        server_velocity: DVec3::ZERO,
+                        sprinting: false,
+                        was_on_ground: false,
+                        coalesced_movement_packets: 0,
+                        coalesced_landing_y: None,
+                    },
+                    PositionSync::default(),
+                    PendingTeleportation::new(position),
+                ),
+            );
         });
 
         info!("{name} joined the world");
@@ -309,7 +426,7 @@ impl CommonPlayerJoinResponses {
 
         encoder
             .append_packet(&play::TeamS2c {
-                team_name: Utf8Bytes::from_static("no_tag").into(),
+                team_name: Utf8Bytes::from_static(NO_TAG_TEAM).into(),
                 mode: Mode::CreateTeam {
                     team_display_name: Cow::default(),
                     friendly_flags: TeamFlags::default(),
@@ -353,7 +470,7 @@ impl FromWorld for CommonPlayerJoinResponses {
         let crafting_registry = world.get_resource::<CraftingRegistry>().unwrap();
         let compose = world.get_resource::<Compose>().unwrap();
 
-        let compression_level = compose.global().shared.compression_threshold;
+        let compression_level = compose.shared().compression_threshold;
         let mut encoder = PacketEncoder::new();
         encoder.set_compression(compression_level);
 
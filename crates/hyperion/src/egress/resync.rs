@@ -0,0 +1,265 @@
+//! Sending a player everything its client should know again, for when the client got out of sync
+//! with the server, such as by missing entities or still showing entities which are gone. See
+//! [`resync_player`].
+
+use std::borrow::Cow;
+
+use bevy_ecs::{
+    entity::Entity,
+    event::EntityEvent,
+    name::Name,
+    query::{Has, With},
+    world::{EntityRef, World},
+};
+use hyperion_inventory::{Inventory, OpenInventory};
+use valence_bytes::{CowUtf8Bytes, Utf8Bytes};
+use valence_protocol::{
+    VarInt,
+    packets::play::{self, team_s2c::Mode},
+    profile::Property,
+};
+use valence_text::IntoText;
+#[cfg(feature = "reflect")]
+use {bevy_ecs::reflect::ReflectEvent, bevy_reflect::Reflect};
+
+use crate::{
+    egress::{
+        player_join::{PlayerListActions, PlayerListEntry, PlayerListS2c},
+        sync_chunks::ChunkSendQueue,
+    },
+    net::{Compose, ConnectionId, DataBundle, SendResultExt},
+    simulation::{
+        ChunkPosition, Flight, FlyingSpeed, Player, Uuid, Xp,
+        hunger::Hunger,
+        join::PlayerGameMode,
+        max_health::MaxHealth,
+        metadata::living_entity::Health,
+        packet_state,
+        skin::PlayerSkin,
+        team::{NO_TAG_TEAM, TeamMember},
+    },
+};
+
+/// Triggered by [`resync_player`] for state kept outside of the core to be sent to the player
+/// again
+#[derive(EntityEvent, Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Event))]
+pub struct PlayerResynced {
+    pub entity: Entity,
+}
+
+/// Returns a command sending `player` everything it should know again, without reconnecting.
+///
+/// Every entity the client was sent is destroyed and the entities in range are spawned again,
+/// every chunk in view is sent again, and so are the inventory, health, maximum health,
+/// experience, abilities, player list and teams of the player. [`PlayerResynced`] is triggered
+/// afterwards.
+pub fn resync_player(player: Entity) -> impl FnOnce(&mut World) + Send + 'static {
+    move |world: &mut World| {
+        let Ok(entity) = world.get_entity(player) else {
+            return;
+        };
+
+        let Some(&connection_id) = entity.get::<ConnectionId>() else {
+            return;
+        };
+
+        if !entity.contains::<packet_state::Play>() {
+            return;
+        }
+
+        let compose = world.resource::<Compose>();
+
+        // The proxy knows which entities the client was sent
+        compose.io_buf().resubscribe_channels(connection_id);
+
+        let mut bundle = DataBundle::new(compose);
+        add_status(&mut bundle, entity);
+        add_player_list(&mut bundle, world);
+        bundle.unicast(connection_id).unwrap_or_disconnected();
+
+        let mut entity = world.entity_mut(player);
+
+        // Forces all chunks in view to be sent again
+        if let Some(mut queue) = entity.get_mut::<ChunkSendQueue>() {
+            queue.reset();
+        }
+        entity.insert(ChunkPosition::default());
+
+        if let (Some(&flight), Some(&flying_speed)) =
+            (entity.get::<Flight>(), entity.get::<FlyingSpeed>())
+        {
+            entity.insert((flight, flying_speed));
+        }
+
+        if let Some(&max_health) = entity.get::<MaxHealth>() {
+            entity.insert(max_health);
+        }
+
+        let open_inventory = entity.get::<OpenInventory>().map(|open| open.inventory);
+        for inventory in std::iter::once(player).chain(open_inventory) {
+            if let Some(mut inventory) = world.get_mut::<Inventory>(inventory) {
+                inventory.mark_all_changed();
+            }
+        }
+
+        world.trigger(PlayerResynced { entity: player });
+    }
+}
+
+/// Adds the health, food and experience of `player` to `bundle`
+fn add_status(bundle: &mut DataBundle<'_>, player: EntityRef<'_>) {
+    if let Some(health) = player.get::<Health>() {
+        let hunger = player.get::<Hunger>().copied().unwrap_or_default();
+
+        bundle
+            .add_packet(&play::HealthUpdateS2c {
+                health: **health,
+                food: VarInt(i32::from(hunger.food)),
+                food_saturation: hunger.saturation,
+            })
+            .unwrap();
+    }
+
+    if let Some(xp) = player.get::<Xp>() {
+        let visual = xp.get_visual();
+
+        bundle
+            .add_packet(&play::ExperienceBarUpdateS2c {
+                bar: visual.prop,
+                level: VarInt(i32::from(visual.level)),
+                total_xp: VarInt::default(),
+            })
+            .unwrap();
+    }
+}
+
+type ListedPlayer = (
+    &'static Uuid,
+    &'static Name,
+    Option<&'static PlayerSkin>,
+    Option<&'static PlayerGameMode>,
+    Has<TeamMember>,
+);
+
+/// Adds every player to the player list of the client, and the players without a team to the
+/// team without name tags. The client keeps the entries it already has.
+fn add_player_list(bundle: &mut DataBundle<'_>, world: &World) {
+    let Some(mut query) =
+        world.try_query_filtered::<ListedPlayer, (With<Player>, With<packet_state::Play>)>()
+    else {
+        return;
+    };
+
+    let mut entries = Vec::new();
+    let mut names_without_team = Vec::new();
+
+    for (uuid, name, skin, game_mode, in_team) in query.iter(world) {
+        let properties = skin.map(|skin| Property::<Utf8Bytes> {
+            name: Utf8Bytes::from_static("textures"),
+            value: skin.textures.clone().into(),
+            signature: Some(skin.signature.clone().into()),
+        });
+
+        entries.push(PlayerListEntry {
+            player_uuid: **uuid,
+            username: CowUtf8Bytes::Borrowed(name.as_str()),
+            properties: Cow::Owned(properties.into_iter().collect()),
+            chat_data: None,
+            listed: true,
+            ping: 20,
+            game_mode: game_mode.copied().unwrap_or_default().0,
+            display_name: Some(name.to_string().into_cow_text()),
+        });
+
+        if !in_team {
+            names_without_team.push(CowUtf8Bytes::Borrowed(name.as_str()));
+        }
+    }
+
+    bundle
+        .add_packet(&PlayerListS2c {
+            actions: PlayerListActions::default()
+                .with_add_player(true)
+                .with_update_listed(true)
+                .with_update_display_name(true),
+            entries: Cow::Owned(entries),
+        })
+        .unwrap();
+
+    bundle
+        .add_packet(&play::TeamS2c {
+            team_name: Utf8Bytes::from_static(NO_TAG_TEAM).into(),
+            mode: Mode::AddEntities {
+                entities: names_without_team,
+            },
+        })
+        .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use glam::I16Vec2;
+
+    use super::*;
+    use crate::{
+        Shared,
+        net::{
+            IoBuf, ProxyId,
+            intermediate::{IntermediateServerToProxyMessage, ResubscribeChannels},
+        },
+    };
+
+    #[test]
+    fn resyncing_resends_entities_and_chunks() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut io_buf = IoBuf::default();
+        io_buf.add_proxy(ProxyId::new(0), tx.into());
+
+        let mut world = World::new();
+        world.insert_resource(Compose::new(
+            libdeflater::CompressionLvl::default(),
+            Arc::new(Shared {
+                compression_threshold: valence_protocol::CompressionThreshold(-1),
+                compression_level: libdeflater::CompressionLvl::default(),
+            }),
+            io_buf,
+        ));
+
+        let stream = ConnectionId::new(1, ProxyId::new(0));
+        let mut queue = ChunkSendQueue::default();
+        queue.push(I16Vec2::new(3, 3));
+        let player = world
+            .spawn((
+                stream,
+                packet_state::Play,
+                ChunkPosition {
+                    position: Some(I16Vec2::ZERO),
+                },
+                queue,
+                Inventory::default(),
+            ))
+            .id();
+
+        resync_player(player)(&mut world);
+
+        let message =
+            IntermediateServerToProxyMessage::ResubscribeChannels(ResubscribeChannels { stream });
+        let expected =
+            IoBuf::encode_proxy_message(&message.transform_for_proxy(ProxyId::new(0)).unwrap());
+        assert_eq!(rx.try_recv().unwrap(), expected);
+
+        assert_eq!(world.get::<ChunkPosition>(player).unwrap().position, None);
+        assert!(world.get::<ChunkSendQueue>(player).unwrap().is_empty());
+        assert!(
+            world
+                .get::<Inventory>(player)
+                .unwrap()
+                .slots()
+                .iter()
+                .all(|slot| slot.changed)
+        );
+    }
+}
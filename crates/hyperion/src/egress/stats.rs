@@ -1,45 +1,169 @@
-use bevy_app::{App, FixedUpdate, Plugin};
+use std::time::Instant;
+
+use bevy_app::{App, FixedFirst, FixedLast, FixedUpdate, Plugin};
 use bevy_ecs::{
     lifecycle::{Add, Remove},
     observer::On,
-    system::{Res, ResMut},
+    resource::Resource,
+    schedule::IntoScheduleConfigs,
+    system::{Commands, Query, Res, ResMut},
 };
 
 use crate::{
-    net::Compose,
-    simulation::{blocks::Blocks, packet_state},
+    PlayerCount, ProxyPlayerCounts, ServerInfo, Tick, TickDuration,
+    activity::server_active,
+    net::{Compose, ConnectionId, metrics::NetworkMetrics},
+    simulation::{
+        blocks::Blocks,
+        packet_state,
+        world::{WorldId, Worlds},
+    },
 };
 
+/// When the current and the previous tick started
+#[derive(Resource, Default)]
+struct TickStart {
+    current: Option<Instant>,
+    previous: Option<Instant>,
+}
+
 pub struct StatsPlugin;
 
 impl Plugin for StatsPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(FixedUpdate, (global_update, load_pending));
+        app.init_resource::<TickStart>();
+        app.init_resource::<ServerInfo>();
+        app.init_resource::<ProxyPlayerCounts>();
+        app.add_systems(FixedFirst, start_tick);
+        app.add_systems(
+            FixedUpdate,
+            (
+                global_update,
+                load_pending,
+                receive_encoded_packets.run_if(server_active),
+            ),
+        );
+        app.add_systems(FixedLast, finish_tick);
         app.add_observer(player_join_world);
         app.add_observer(player_leave_world);
     }
 }
 
-fn global_update(mut compose: ResMut<'_, Compose>) {
-    let global = compose.global_mut();
+fn global_update(mut tick: ResMut<'_, Tick>) {
+    tick.0 += 1;
+}
 
-    global.tick += 1;
+fn start_tick(mut start: ResMut<'_, TickStart>) {
+    start.previous = start.current.replace(Instant::now());
 }
 
-pub fn player_join_world(_: On<'_, '_, Add, packet_state::Play>, compose: Res<'_, Compose>) {
-    compose
-        .global()
-        .player_count
-        .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+fn finish_tick(
+    start: Res<'_, TickStart>,
+    compose: Res<'_, Compose>,
+    player_count: Res<'_, PlayerCount>,
+    mut duration: ResMut<'_, TickDuration>,
+    mut info: ResMut<'_, ServerInfo>,
+    metrics: Option<Res<'_, NetworkMetrics>>,
+) {
+    let Some(current) = start.current else {
+        return;
+    };
+
+    let elapsed = current.elapsed();
+    duration.ms_last_tick = elapsed.as_secs_f32() * 1000.0;
+
+    let since_last_tick = start
+        .previous
+        .map(|previous| current.duration_since(previous));
+    info.record_tick(elapsed, since_last_tick, player_count.get());
+
+    if let Some(metrics) = metrics {
+        metrics.set_bytes_sent(compose.io_buf().bytes_sent());
+    }
+}
+
+pub fn player_join_world(
+    joined: On<'_, '_, Add, packet_state::Play>,
+    connections: Query<'_, '_, &ConnectionId>,
+    count: Res<'_, PlayerCount>,
+    mut proxy_counts: ResMut<'_, ProxyPlayerCounts>,
+) {
+    count.0.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    if let Ok(connection_id) = connections.get(joined.entity) {
+        proxy_counts.add(connection_id.proxy_id());
+    }
 }
 
-pub fn player_leave_world(_: On<'_, '_, Remove, packet_state::Play>, compose: Res<'_, Compose>) {
-    compose
-        .global()
-        .player_count
-        .fetch_sub(1, std::sync::atomic::Ordering::Relaxed);
+pub fn player_leave_world(
+    left: On<'_, '_, Remove, packet_state::Play>,
+    connections: Query<'_, '_, &ConnectionId>,
+    count: Res<'_, PlayerCount>,
+    mut proxy_counts: ResMut<'_, ProxyPlayerCounts>,
+) {
+    count.0.fetch_sub(1, std::sync::atomic::Ordering::Relaxed);
+    if let Ok(connection_id) = connections.get(left.entity) {
+        proxy_counts.remove(connection_id.proxy_id());
+    }
 }
 
-fn load_pending(mut blocks: ResMut<'_, Blocks>) {
+fn load_pending(
+    mut blocks: ResMut<'_, Blocks>,
+    mut worlds: ResMut<'_, Worlds>,
+    mut commands: Commands<'_, '_>,
+) {
     blocks.load_pending();
+    for entity in blocks.take_loaded_entities() {
+        entity.spawn(&mut commands, WorldId::PRIMARY);
+    }
+
+    for (world, blocks) in worlds.iter_mut() {
+        blocks.load_pending();
+        for entity in blocks.take_loaded_entities() {
+            entity.spawn(&mut commands, world);
+        }
+    }
+}
+
+/// Nobody is sent chunks while the server is idle, so the encoded chunk packets are only received
+/// while it is active
+fn receive_encoded_packets(mut blocks: ResMut<'_, Blocks>, mut worlds: ResMut<'_, Worlds>) {
+    blocks.receive_encoded_packets();
+    for (_, blocks) in worlds.iter_mut() {
+        blocks.receive_encoded_packets();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_ecs::world::World;
+
+    use super::*;
+    use crate::net::ProxyId;
+
+    #[test]
+    fn players_are_counted_per_proxy() {
+        let mut world = World::new();
+        world.init_resource::<PlayerCount>();
+        world.init_resource::<ProxyPlayerCounts>();
+        world.add_observer(player_join_world);
+        world.add_observer(player_leave_world);
+
+        let [first, second] = [ProxyId::new(0), ProxyId::new(1)];
+        let mut join = |stream, proxy_id| {
+            world
+                .spawn((ConnectionId::new(stream, proxy_id), packet_state::Play))
+                .id()
+        };
+        join(1, first);
+        let leaving = join(2, second);
+        join(3, second);
+
+        let counts = world.resource::<ProxyPlayerCounts>();
+        assert_eq!((counts.get(first), counts.get(second)), (1, 2));
+
+        world.despawn(leaving);
+        let counts = world.resource::<ProxyPlayerCounts>();
+        assert_eq!((counts.get(first), counts.get(second)), (1, 1));
+        assert_eq!(world.resource::<PlayerCount>().get(), 2);
+    }
 }
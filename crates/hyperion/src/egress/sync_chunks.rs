@@ -1,13 +1,13 @@
-use std::cmp::Ordering;
-
 use bevy_app::{App, FixedUpdate, Plugin};
 use bevy_ecs::{
     component::Component,
     query::With,
-    system::{Query, Res},
+    schedule::IntoScheduleConfigs,
+    system::{Query, Res, ResMut},
 };
-use glam::I16Vec2;
+use glam::{I16Vec2, Vec2, Vec3};
 use itertools::Itertools;
+use rustc_hash::FxHashSet;
 use tracing::error;
 use valence_protocol::{
     ChunkPos, VarInt,
@@ -17,21 +17,106 @@ use valence_protocol::{
 use {bevy_ecs::reflect::ReflectComponent, bevy_reflect::Reflect};
 
 use crate::{
+    Blocks,
+    activity::server_active,
     config::Config,
-    net::{Compose, ConnectionId, DataBundle},
+    egress::backlog::Backlog,
+    net::{Compose, ConnectionId, DataBundle, SendError, SendResultExt},
     simulation::{
-        ChunkPosition, Position,
-        blocks::{Blocks, GetChunk},
-        packet_state,
+        ChunkPosition, Pitch, Position, Yaw,
+        blocks::{GetChunk, fake::FakeBlocks},
+        get_direction_from_rotation, packet_state,
+        world::{WorldBlocks, WorldId, Worlds},
     },
+    timings::{TickTimings, TimedSection},
 };
 
+/// The chunks of a player which are waiting to be sent, the chunks which were sent, and the
+/// chunks which are pinned.
+///
+/// Chunks within the view distance are queued and sent automatically. Pinned chunks are sent as
+/// well, even if they are out of view, and are not unloaded from the client until they are
+/// unpinned. This is useful for chunks the player is about to see, such as the destination of a
+/// teleport.
+///
+/// The queue dereferences to the chunks waiting to be sent, with the chunk sent next last.
 #[derive(Component, Default)]
 #[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
 pub struct ChunkSendQueue {
     #[cfg_attr(feature = "reflect", reflect(ignore))]
     // TODO: Should be reflectable once glam is updated everywhere
     changes: Vec<I16Vec2>,
+    /// The view direction that the queue was last sorted for
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    sorted_direction: Vec3,
+    /// The chunks which the client has, which are unloaded from the client once they leave its
+    /// view
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    sent: FxHashSet<I16Vec2>,
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    pinned: FxHashSet<I16Vec2>,
+    /// Chunks which were unpinned since the last tick, which are unloaded unless they are in view
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    unpinned: Vec<I16Vec2>,
+}
+
+impl ChunkSendQueue {
+    /// Keeps `chunk` sent to the player until it is [unpinned](Self::unpin), regardless of the
+    /// view distance. The chunk is loaded and queued if it was not sent yet. Returns whether the
+    /// chunk was not pinned already.
+    ///
+    /// The client discards chunks which are too far away from the player, see
+    /// [`is_kept_by_client`]. Such chunks are loaded right away, but only sent once the player
+    /// comes closer.
+    pub fn pin(&mut self, chunk: I16Vec2) -> bool {
+        self.unpinned.retain(|&unpinned| unpinned != chunk);
+
+        if !self.sent.contains(&chunk) && !self.changes.contains(&chunk) {
+            self.changes.push(chunk);
+        }
+
+        self.pinned.insert(chunk)
+    }
+
+    /// Stops keeping `chunk` sent to the player. It is unloaded from the client during the next
+    /// tick unless it is in view. Returns whether the chunk was pinned.
+    pub fn unpin(&mut self, chunk: I16Vec2) -> bool {
+        let pinned = self.pinned.remove(&chunk);
+        if pinned {
+            self.unpinned.push(chunk);
+        }
+        pinned
+    }
+
+    #[must_use]
+    pub fn is_pinned(&self, chunk: I16Vec2) -> bool {
+        self.pinned.contains(&chunk)
+    }
+
+    /// Whether the client currently has `chunk`
+    #[must_use]
+    pub fn is_sent(&self, chunk: I16Vec2) -> bool {
+        self.sent.contains(&chunk)
+    }
+
+    /// The chunks which the client currently has, in no particular order
+    pub fn sent_chunks(&self) -> impl Iterator<Item = I16Vec2> + '_ {
+        self.sent.iter().copied()
+    }
+
+    /// The pinned chunks, in no particular order
+    pub fn pinned_chunks(&self) -> impl Iterator<Item = I16Vec2> + '_ {
+        self.pinned.iter().copied()
+    }
+
+    /// Forgets which chunks were sent and queues the pinned chunks again. This is used once the
+    /// client unloaded all of its chunks, such as when it changes worlds.
+    pub fn reset(&mut self) {
+        self.sent.clear();
+        self.unpinned.clear();
+        self.changes.clear();
+        self.changes.extend(self.pinned.iter().copied());
+    }
 }
 
 impl std::ops::Deref for ChunkSendQueue {
@@ -50,11 +135,127 @@ impl std::ops::DerefMut for ChunkSendQueue {
 
 pub struct SyncChunksPlugin;
 
+/// Returns every chunk within `radius` of `center`
+fn chunks_in_view(center: I16Vec2, radius: i16) -> impl Iterator<Item = I16Vec2> {
+    ((center.x - radius)..(center.x + radius))
+        .cartesian_product((center.y - radius)..(center.y + radius))
+        .map(|(x, y)| I16Vec2::new(x, y))
+}
+
+/// Whether `chunk` is within `radius` of `center`. Nothing is in view without a center.
+fn is_in_view(center: Option<I16Vec2>, radius: i16, chunk: I16Vec2) -> bool {
+    center.is_some_and(|center| {
+        ((center.x - radius)..(center.x + radius)).contains(&chunk.x)
+            && ((center.y - radius)..(center.y + radius)).contains(&chunk.y)
+    })
+}
+
+/// How many chunks outside of its view distance the client keeps chunks which are sent to it
+const CLIENT_MARGIN: i16 = 2;
+
+/// Whether the client keeps `chunk` if it is sent while the client's view is centered on
+/// `center`. The vanilla client ignores chunks which are more than a few chunks outside of its
+/// view distance.
+fn is_kept_by_client(center: Option<I16Vec2>, radius: i16, chunk: I16Vec2) -> bool {
+    is_in_view(center, radius + CLIENT_MARGIN, chunk)
+}
+
+/// Returns the chunks to load when a client's view moves from `last_sent` to `current`
+fn chunks_to_load(
+    last_sent: Option<I16Vec2>,
+    current: I16Vec2,
+    radius: i16,
+) -> impl Iterator<Item = I16Vec2> {
+    chunks_in_view(current, radius).filter(move |&chunk| !is_in_view(last_sent, radius, chunk))
+}
+
+/// Returns the chunks of `sent` to unload once a client's view is centered on `current`. Pinned
+/// chunks are kept.
+fn chunks_to_unload<'a>(
+    sent: &'a FxHashSet<I16Vec2>,
+    pinned: &'a FxHashSet<I16Vec2>,
+    current: Option<I16Vec2>,
+    radius: i16,
+) -> impl Iterator<Item = I16Vec2> + 'a {
+    sent.iter()
+        .copied()
+        .filter(move |chunk| !is_in_view(current, radius, *chunk) && !pinned.contains(chunk))
+}
+
+/// Adds an unload packet for each of `chunks` to `bundle`
+fn unload_chunks(bundle: &mut DataBundle<'_>, chunks: impl IntoIterator<Item = I16Vec2>) {
+    for chunk in chunks {
+        let pos = ChunkPos::new(i32::from(chunk.x), i32::from(chunk.y));
+        bundle.add_packet(&play::UnloadChunkS2c { pos }).unwrap();
+    }
+}
+
+/// The change in view direction after which the queue is sorted again, as the cosine of the angle
+/// between the old and the new direction. This is about 30°.
+const RESORT_COS: f32 = 0.866;
+
+/// How much the view direction affects the order chunks are sent in. A chunk straight ahead is
+/// sent as if it was this fraction closer, and a chunk straight behind as if it was this fraction
+/// further away.
+const VIEW_WEIGHT: f32 = 0.5;
+
+/// Returns the key that chunks are sent in ascending order of. The chunk of the player and its 8
+/// neighbors come first, followed by the other chunks by distance, where chunks in the direction
+/// the player looks count as closer.
+fn send_priority(center: I16Vec2, direction: Vec3, chunk: I16Vec2) -> (bool, f32) {
+    let offset = (chunk - center).as_vec2();
+    let is_neighbor = offset.abs().max_element() <= 1.0;
+    let facing = offset
+        .normalize_or_zero()
+        .dot(Vec2::new(direction.x, direction.z));
+
+    (
+        !is_neighbor,
+        offset.length() * facing.mul_add(-VIEW_WEIGHT, 1.0),
+    )
+}
+
+/// Sorts `chunks` so that the chunk to send first is last and removes duplicates
+fn sort_by_priority(chunks: &mut Vec<I16Vec2>, center: I16Vec2, direction: Vec3) {
+    chunks.sort_unstable_by(|a, b| {
+        let (a_far, a_score) = send_priority(center, direction, *a);
+        let (b_far, b_score) = send_priority(center, direction, *b);
+
+        // reverse because we want to get the first chunks first and we are poping from the end
+        b_far
+            .cmp(&a_far)
+            .then(b_score.total_cmp(&a_score))
+            // so we can dedup properly (without same element could be scattered around)
+            .then_with(|| a.to_array().cmp(&b.to_array()))
+    });
+    chunks.dedup();
+}
+
+/// Returns the number of chunks that may be sent to a connection with `backlog` bytes queued in
+/// its proxy this tick. Fewer chunks are sent the closer the connection is to being congested.
+fn chunks_per_tick(max: usize, backlog: u64, soft_backlog: u64) -> usize {
+    if backlog == 0 || soft_backlog == 0 {
+        return max;
+    }
+
+    let free = soft_backlog.saturating_sub(backlog);
+    let limit = u128::from(free) * max as u128 / u128::from(soft_backlog);
+
+    // At least one chunk is sent as the connection is not congested yet
+    usize::try_from(limit).unwrap_or(max).max(1)
+}
+
 impl Plugin for SyncChunksPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(
             FixedUpdate,
-            (generate_chunk_changes, send_full_loaded_chunks),
+            (
+                generate_chunk_changes,
+                queue_resent_chunks,
+                send_full_loaded_chunks,
+            )
+                .chain()
+                .run_if(server_active),
         );
     }
 }
@@ -70,21 +271,47 @@ fn generate_chunk_changes(
             &mut ChunkPosition,
             &mut ChunkSendQueue,
             &Position,
+            &Yaw,
+            &Pitch,
         ),
         With<packet_state::Play>,
     >,
+    timings: Res<'_, TickTimings>,
 ) {
+    let _timing = timings.time(TimedSection::ChunkSending);
     let compose = compose.into_inner();
     let radius = config.view_distance;
     let liberal_radius = radius + 2;
-    query
-        .par_iter_mut()
-        .for_each(|(&stream_id, mut last_sent, mut chunk_changes, pose)| {
+    query.par_iter_mut().for_each(
+        |(&stream_id, mut last_sent, mut chunk_changes, pose, yaw, pitch)| {
             let last_sent_chunk = last_sent.position;
 
             let current_chunk = pose.to_chunk();
+            let direction = get_direction_from_rotation(**yaw, **pitch);
+
+            if !chunk_changes.unpinned.is_empty() {
+                let queue = &mut *chunk_changes;
+
+                // Unpinned chunks in view are kept, the others are unloaded like chunks which
+                // left the view
+                let mut unpinned = std::mem::take(&mut queue.unpinned);
+                unpinned.retain(|&chunk| !is_in_view(last_sent_chunk, radius, chunk));
+                queue.changes.retain(|chunk| !unpinned.contains(chunk));
+                unpinned.retain(|chunk| queue.sent.remove(chunk));
 
-            if last_sent_chunk == current_chunk {
+                let mut bundle = DataBundle::new(compose);
+                unload_chunks(&mut bundle, unpinned);
+                bundle.unicast(stream_id).unwrap_or_disconnected();
+            }
+
+            if last_sent_chunk == Some(current_chunk) {
+                // Chunks the player turned towards are sent first
+                if !chunk_changes.is_empty()
+                    && chunk_changes.sorted_direction.dot(direction) < RESORT_COS
+                {
+                    chunk_changes.sorted_direction = direction;
+                    sort_by_priority(&mut chunk_changes.changes, current_chunk, direction);
+                }
                 return;
             }
 
@@ -102,55 +329,55 @@ fn generate_chunk_changes(
                 return;
             }
 
-            last_sent.position = current_chunk;
-
-            let last_sent_range_x = (last_sent_chunk.x - radius)..(last_sent_chunk.x + radius);
-            let last_sent_range_z = (last_sent_chunk.y - radius)..(last_sent_chunk.y + radius);
-
-            let current_range_x = (current_chunk.x - radius)..(current_chunk.x + radius);
-            let current_range_z = (current_chunk.y - radius)..(current_chunk.y + radius);
+            last_sent.position = Some(current_chunk);
 
             let current_range_liberal_x =
                 (current_chunk.x - liberal_radius)..(current_chunk.x + liberal_radius);
             let current_range_liberal_z =
                 (current_chunk.y - liberal_radius)..(current_chunk.y + liberal_radius);
 
-            chunk_changes.retain(|elem| {
-                current_range_liberal_x.contains(&elem.x)
-                    && current_range_liberal_z.contains(&elem.y)
+            let queue = &mut *chunk_changes;
+            let pinned = &queue.pinned;
+            queue.changes.retain(|elem| {
+                (current_range_liberal_x.contains(&elem.x)
+                    && current_range_liberal_z.contains(&elem.y))
+                    || pinned.contains(elem)
             });
 
-            let removed_chunks = last_sent_range_x
-                .clone()
-                .cartesian_product(last_sent_range_z.clone())
-                .filter(|(x, y)| !current_range_x.contains(x) || !current_range_z.contains(y))
-                .map(|(x, y)| I16Vec2::new(x, y));
-
-            let mut bundle = DataBundle::new(compose);
-
-            for chunk in removed_chunks {
-                let pos = ChunkPos::new(i32::from(chunk.x), i32::from(chunk.y));
-                let unload_chunk = play::UnloadChunkS2c { pos };
-
-                bundle.add_packet(&unload_chunk).unwrap();
+            let unloaded: Vec<_> =
+                chunks_to_unload(&queue.sent, pinned, Some(current_chunk), radius).collect();
+            for chunk in &unloaded {
+                queue.sent.remove(chunk);
             }
 
-            bundle.unicast(stream_id).unwrap();
-
-            let added_chunks = current_range_x
-                .cartesian_product(current_range_z)
-                .filter(|(x, y)| !last_sent_range_x.contains(x) || !last_sent_range_z.contains(y))
-                .map(|(x, y)| I16Vec2::new(x, y));
+            let mut bundle = DataBundle::new(compose);
+            unload_chunks(&mut bundle, unloaded);
+            bundle.unicast(stream_id).unwrap_or_disconnected();
 
             let mut num_chunks_added = 0;
 
-            // drain all chunks not in current_{x,z} range
-
-            for chunk in added_chunks {
-                chunk_changes.push(chunk);
+            // The client discards the pinned chunks it is too far away from now, so they are sent
+            // again once it comes closer
+            let discarded: Vec<_> = queue
+                .sent
+                .iter()
+                .copied()
+                .filter(|&chunk| !is_kept_by_client(Some(current_chunk), radius, chunk))
+                .collect();
+            for chunk in discarded {
+                queue.sent.remove(&chunk);
+                queue.changes.push(chunk);
                 num_chunks_added += 1;
             }
 
+            // Pinned chunks may have been sent already
+            for chunk in chunks_to_load(last_sent_chunk, current_chunk, radius) {
+                if !queue.sent.contains(&chunk) {
+                    queue.changes.push(chunk);
+                    num_chunks_added += 1;
+                }
+            }
+
             if num_chunks_added > 0 {
                 // remove further than radius
 
@@ -162,80 +389,276 @@ fn generate_chunk_changes(
                 //     elem <= r2_very_liberal
                 // });
 
-                chunk_changes.sort_unstable_by(|a, b| {
-                    let r1 = a.distance_squared(current_chunk);
-                    let r2 = b.distance_squared(current_chunk);
+                queue.sorted_direction = direction;
+                sort_by_priority(&mut queue.changes, current_chunk, direction);
+            }
+        },
+    );
+}
 
-                    // reverse because we want to get the closest chunks first and we are poping from the end
-                    match r1.cmp(&r2).reverse() {
-                        Ordering::Less => Ordering::Less,
-                        Ordering::Greater => Ordering::Greater,
+/// Queues the chunks passed to [`Blocks::resend_chunk`] for every player who has them
+fn queue_resent_chunks(
+    mut blocks: ResMut<'_, Blocks>,
+    mut worlds: ResMut<'_, Worlds>,
+    mut query: Query<'_, '_, (&mut ChunkSendQueue, Option<&WorldId>), With<packet_state::Play>>,
+) {
+    let mut resent: Vec<(WorldId, FxHashSet<I16Vec2>)> = Vec::new();
+    resent.push((WorldId::PRIMARY, blocks.take_resent_chunks()));
+    for (world, blocks) in worlds.iter_mut() {
+        resent.push((world, blocks.take_resent_chunks()));
+    }
 
-                        // so we can dedup properly (without same element could be scattered around)
-                        Ordering::Equal => a.to_array().cmp(&b.to_array()),
-                    }
-                });
-                chunk_changes.dedup();
+    resent.retain(|(_, chunks)| !chunks.is_empty());
+    if resent.is_empty() {
+        return;
+    }
+
+    for (mut queue, world) in &mut query {
+        let world = world.copied().unwrap_or_default();
+        let Some((_, chunks)) = resent.iter().find(|(id, _)| *id == world) else {
+            continue;
+        };
+
+        for &chunk in chunks {
+            // Chunks which have not been sent yet are sent with the new blocks anyway
+            if queue.is_sent(chunk) && !queue.contains(&chunk) {
+                queue.push(chunk);
             }
-        });
+        }
+    }
 }
 
 fn send_full_loaded_chunks(
+    config: Res<'_, Config>,
     compose: Res<'_, Compose>,
-    blocks: Res<'_, Blocks>,
-    mut query: Query<'_, '_, (&ConnectionId, &mut ChunkSendQueue), With<packet_state::Play>>,
+    worlds: WorldBlocks<'_>,
+    mut query: Query<
+        '_,
+        '_,
+        (
+            &ConnectionId,
+            &ChunkPosition,
+            &mut ChunkSendQueue,
+            Option<&WorldId>,
+            Option<&FakeBlocks>,
+            Option<&Backlog>,
+        ),
+        With<packet_state::Play>,
+    >,
+    timings: Res<'_, TickTimings>,
 ) {
     const MAX_CHUNKS_PER_TICK: usize = 128;
 
-    query.par_iter_mut().for_each(|(&stream_id, mut queue)| {
-        let last = None;
-
-        let mut iter_count = 0;
-
-        let mut bundle = DataBundle::new(&compose);
+    let _timing = timings.time(TimedSection::ChunkSending);
+    let radius = config.view_distance;
 
-        #[expect(
-            clippy::cast_possible_wrap,
-            reason = "realistically queue.changes.len() will never be large enough to wrap"
-        )]
-        let mut idx = (queue.changes.len() as isize) - 1;
+    query.par_iter_mut().for_each(
+        |(&stream_id, chunk_position, mut queue, world, fake_blocks, backlog)| {
+            // Chunks are sent again once the client catches up with what was sent to it
+            if compose.io_buf().is_congested(stream_id) {
+                return;
+            }
 
-        while idx >= 0 {
-            #[expect(clippy::cast_sign_loss, reason = "we are checking if < 0")]
-            let Some(elem) = queue.changes.get(idx as usize).copied() else {
-                // should never happen but we do not want to panic if wrong
-                // logic/assumptions are made
-                error!("failed to get element from queue.changes");
-                continue;
+            let Some(blocks) = worlds.get(world) else {
+                error!("failed to send chunks: player is in world {world:?} which does not exist");
+                return;
             };
 
-            // de-duplicate. todo: there are cases where duplicate will not be removed properly
-            // since sort is unstable
-            if last == Some(elem) {
+            let last = None;
+
+            let mut iter_count = 0;
+
+            let mut bundle = DataBundle::new(&compose);
+            let mut sent = Vec::new();
+
+            // Chunks that do not fit in the pending bytes allowed for the connection wait until the
+            // next flush, so that a client which is not keeping up does not use unbounded memory
+            let io_buf = compose.io_buf();
+            let limits = io_buf.limits();
+            let mut budget = limits
+                .max_pending_bytes
+                .saturating_sub(io_buf.pending_bytes(stream_id));
+            let max_chunks = chunks_per_tick(
+                MAX_CHUNKS_PER_TICK,
+                backlog.map_or(0, Backlog::bytes),
+                limits.soft_backlog_bytes,
+            );
+
+            #[expect(
+                clippy::cast_possible_wrap,
+                reason = "realistically queue.changes.len() will never be large enough to wrap"
+            )]
+            let mut idx = (queue.changes.len() as isize) - 1;
+
+            while idx >= 0 {
                 #[expect(clippy::cast_sign_loss, reason = "we are checking if < 0")]
-                queue.changes.swap_remove(idx as usize);
-                idx -= 1;
-                continue;
-            }
+                let Some(elem) = queue.changes.get(idx as usize).copied() else {
+                    // should never happen but we do not want to panic if wrong
+                    // logic/assumptions are made
+                    error!("failed to get element from queue.changes");
+                    continue;
+                };
+
+                // de-duplicate. todo: there are cases where duplicate will not be removed properly
+                // since sort is unstable
+                if last == Some(elem) {
+                    #[expect(clippy::cast_sign_loss, reason = "we are checking if < 0")]
+                    queue.changes.swap_remove(idx as usize);
+                    idx -= 1;
+                    continue;
+                }
 
-            if iter_count >= MAX_CHUNKS_PER_TICK {
-                break;
-            }
+                if iter_count >= max_chunks {
+                    break;
+                }
 
-            match blocks.get_cached_or_load(elem) {
-                GetChunk::Loaded(chunk) => {
-                    bundle.add_raw(&chunk.base_packet_bytes);
+                // Pinned chunks which the client would discard are only loaded for now
+                if !is_kept_by_client(chunk_position.position, radius, elem) {
+                    drop(blocks.get_cached_or_load(elem));
+                    idx -= 1;
+                    continue;
+                }
+
+                let packet = match blocks.get_cached_or_load(elem) {
+                    GetChunk::Loaded(chunk) => blocks.chunk_packet(chunk),
+                    GetChunk::Loading => None,
+                };
+
+                // Chunks that are loading or being encoded stay queued
+                if let Some(packet) = packet {
+                    let Some(remaining) = budget.checked_sub(packet.len()) else {
+                        break;
+                    };
+                    budget = remaining;
+
+                    bundle.add_raw(&packet);
+                    if let Some(fake_blocks) = fake_blocks {
+                        fake_blocks.add_chunk_updates(&mut bundle, elem);
+                    }
+                    sent.push(elem);
 
                     iter_count += 1;
                     #[expect(clippy::cast_sign_loss, reason = "we are checking if < 0")]
                     queue.changes.swap_remove(idx as usize);
                 }
-                GetChunk::Loading => {}
+
+                idx -= 1;
             }
 
-            idx -= 1;
-        }
+            match bundle.try_unicast(stream_id) {
+                Ok(()) => queue.sent.extend(sent),
+                Err(SendError::Disconnected) => {}
+                Err(SendError::BufferFull { .. }) => {
+                    // Something else was sent to the connection in the meantime. The chunks are
+                    // queued again, closest last, to be sent after the next flush.
+                    queue.changes.extend(sent.into_iter().rev());
+                }
+                Err(e) => error!("failed to send chunks: {e}"),
+            }
+        },
+    );
+}
 
-        bundle.unicast(stream_id).unwrap();
-    });
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_view_loads_every_chunk_and_unloads_none() {
+        // The old sentinel center, which used to keep these chunks from being sent
+        let current = I16Vec2::new(128, 128);
+        let sent = FxHashSet::default();
+
+        let removed = chunks_to_unload(&sent, &FxHashSet::default(), Some(current), 4);
+        assert_eq!(removed.count(), 0);
+        let added: Vec<_> = chunks_to_load(None, current, 4).collect();
+        assert_eq!(added.len(), 8 * 8);
+        assert!(added.contains(&current));
+    }
+
+    #[test]
+    fn moving_one_chunk_changes_one_row() {
+        let current = I16Vec2::new(1, 0);
+        let sent = chunks_in_view(I16Vec2::ZERO, 4).collect();
+
+        let removed: Vec<_> =
+            chunks_to_unload(&sent, &FxHashSet::default(), Some(current), 4).collect();
+        let added: Vec<_> = chunks_to_load(Some(I16Vec2::ZERO), current, 4).collect();
+        assert_eq!(removed.len(), 8);
+        assert_eq!(added.len(), 8);
+        assert!(removed.iter().all(|chunk| chunk.x == -4));
+        assert!(added.iter().all(|chunk| chunk.x == 4));
+    }
+
+    #[test]
+    fn pinned_chunks_are_queued_and_never_unloaded() {
+        let far = I16Vec2::new(100, -100);
+        let mut queue = ChunkSendQueue::default();
+
+        assert!(queue.pin(far));
+        assert!(!queue.pin(far));
+        assert_eq!(*queue, [far]);
+        assert!(queue.is_pinned(far));
+        assert!(!queue.is_sent(far));
+
+        // It is only sent once the client would keep it
+        assert!(!is_kept_by_client(Some(I16Vec2::ZERO), 4, far));
+        assert!(is_kept_by_client(
+            Some(I16Vec2::ZERO),
+            4,
+            I16Vec2::new(5, -6)
+        ));
+
+        // Sending the chunk is recorded by `send_full_loaded_chunks`
+        queue.changes.clear();
+        queue.sent.extend([far, I16Vec2::ZERO]);
+        let removed: Vec<_> =
+            chunks_to_unload(&queue.sent, &queue.pinned, Some(I16Vec2::new(50, 50)), 4).collect();
+        assert_eq!(removed, [I16Vec2::ZERO]);
+
+        // Sent chunks are not queued again
+        assert!(queue.unpin(far));
+        assert!(!queue.unpin(far));
+        assert!(queue.pin(far));
+        assert!(queue.is_empty());
+        assert!(queue.unpinned.is_empty());
+
+        // The client forgets all chunks when it changes worlds, but it still needs the pinned ones
+        queue.reset();
+        assert_eq!(queue.sent_chunks().count(), 0);
+        assert_eq!(*queue, [far]);
+    }
+
+    #[test]
+    fn chunks_in_view_are_sent_first() {
+        let center = I16Vec2::new(3, 3);
+        // Looking north, towards negative z
+        let direction = get_direction_from_rotation(180.0, 0.0);
+
+        let mut queue: Vec<_> = chunks_in_view(center, 4).collect();
+        sort_by_priority(&mut queue, center, direction);
+        let order: Vec<_> = queue.iter().rev().map(|&chunk| chunk - center).collect();
+
+        // The neighborhood of the player comes first regardless of where it looks
+        assert!(
+            order[..9]
+                .iter()
+                .all(|offset| offset.abs().max_element() <= 1)
+        );
+        assert_eq!(order[0], I16Vec2::ZERO);
+
+        let position = |offset: I16Vec2| order.iter().position(|&o| o == offset).unwrap();
+        assert!(position(I16Vec2::new(0, -2)) < position(I16Vec2::new(2, 0)));
+        assert!(position(I16Vec2::new(2, 0)) < position(I16Vec2::new(0, 2)));
+        assert!(position(I16Vec2::new(0, -3)) < position(I16Vec2::new(0, 2)));
+        assert!(position(I16Vec2::new(-4, -4)) > position(I16Vec2::new(0, -4)));
+    }
+
+    #[test]
+    fn fewer_chunks_are_sent_to_backlogged_connections() {
+        assert_eq!(chunks_per_tick(128, 0, 1000), 128);
+        assert_eq!(chunks_per_tick(128, 500, 1000), 64);
+        assert_eq!(chunks_per_tick(128, 999, 1000), 1);
+        assert_eq!(chunks_per_tick(128, 5000, 1000), 1);
+    }
 }
@@ -1,12 +1,15 @@
 use bevy_app::{App, FixedPostUpdate, Plugin};
 use bevy_ecs::{
     batching::BatchingStrategy,
+    component::Component,
     entity::Entity,
     message::MessageWriter,
+    query::{Changed, With},
+    schedule::IntoScheduleConfigs,
     system::{ParallelCommands, ParamSet, Query, Res},
 };
-use glam::{IVec3, Vec3};
-use hyperion_utils::{EntityExt, Prev, track_prev};
+use glam::{I64Vec3, IVec3, Vec3};
+use hyperion_utils::{Prev, track_prev};
 use itertools::Either;
 use tracing::error;
 use valence_bytes::CowBytes;
@@ -14,10 +17,13 @@ use valence_protocol::{
     ByteAngle, RawBytes, VarInt,
     packets::play::{self},
 };
+#[cfg(feature = "reflect")]
+use {bevy_ecs::reflect::ReflectComponent, bevy_reflect::Reflect};
 
 use crate::{
-    Blocks,
-    net::{Compose, ConnectionId, DataBundle},
+    Blocks, Tick,
+    activity::EntitySimulation,
+    net::{Compose, ConnectionId, DataBundle, SendResultExt, batch::BatchOrder},
     simulation::{
         EntitySize, Flight, MovementTracking, Owner, PendingTeleportation, Pitch, Position,
         Velocity, Xp, Yaw,
@@ -26,12 +32,70 @@ use crate::{
         event::HitGroundEvent,
         handlers::is_grounded,
         metadata::{MetadataChanges, get_and_clear_metadata},
+        minecraft_id::MinecraftIdRegistry,
+        tick_rate::{TickRate, should_tick},
+        vanish::Vanished,
     },
     spatial::{SpatialIndex, get_first_collision},
+    timings::{TickTimings, TimedSection},
 };
 
 pub struct EntityStateSyncPlugin;
 
+/// Relative movement packets are only sent for this many ticks after the last absolute position,
+/// so that clients never drift from the real position for long
+const ABSOLUTE_SYNC_INTERVAL: u16 = 60;
+
+/// The position of an entity as last sent to other players, which relative movement packets are
+/// based on
+#[derive(Component, Default, Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+pub struct PositionSync {
+    /// The last sent position in 1/4096 blocks, the precision of relative movement packets, or
+    /// [`None`] if no position has been sent yet
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    sent: Option<I64Vec3>,
+    /// The number of ticks since the last absolute position was sent
+    ticks_since_absolute: u16,
+}
+
+impl PositionSync {
+    fn encode(position: Vec3) -> I64Vec3 {
+        (position.as_dvec3() * 4096.0).round().as_i64vec3()
+    }
+
+    /// Returns the delta to send in a relative movement packet to move the entity to `position`,
+    /// or [`None`] if the absolute position must be sent instead because the delta is too large
+    /// or the last absolute position is too old
+    fn relative_delta(&self, position: Vec3) -> Option<[i16; 3]> {
+        if self.ticks_since_absolute >= ABSOLUTE_SYNC_INTERVAL {
+            return None;
+        }
+
+        let delta = Self::encode(position) - self.sent?;
+        let delta = delta.to_array().map(i16::try_from);
+        match delta {
+            [Ok(x), Ok(y), Ok(z)] => Some([x, y, z]),
+            _ => None,
+        }
+    }
+
+    /// Records that the entity was moved to `position`, either with a relative movement packet
+    /// using the delta from [`PositionSync::relative_delta`] or with an absolute position
+    fn moved(&mut self, position: Vec3, absolute: bool) {
+        // The delta of a relative move is exact in these units, so the sent position is the same
+        // either way and rounding errors do not accumulate
+        self.sent = Some(Self::encode(position));
+        if absolute {
+            self.ticks_since_absolute = 0;
+        }
+    }
+
+    fn tick(&mut self) {
+        self.ticks_since_absolute = self.ticks_since_absolute.saturating_add(1);
+    }
+}
+
 fn entity_xp_sync(
     compose: Res<'_, Compose>,
     query: Query<'_, '_, (&ConnectionId, &Prev<Xp>, &Xp)>,
@@ -46,7 +110,9 @@ fn entity_xp_sync(
                 total_xp: VarInt::default(),
             };
 
-            compose.unicast(&packet, connection_id).unwrap();
+            compose
+                .unicast(&packet, connection_id)
+                .unwrap_or_disconnected();
         }
     }
 }
@@ -54,39 +120,56 @@ fn entity_xp_sync(
 fn entity_metadata_sync(
     compose: Res<'_, Compose>,
     mut query: Query<'_, '_, (Entity, &mut MetadataChanges)>,
+    ids: Res<'_, MinecraftIdRegistry>,
+    timings: Res<'_, TickTimings>,
 ) {
+    let _timing = timings.time(TimedSection::EntitySync);
     for (entity_id, mut metadata_changes) in &mut query {
         let metadata = get_and_clear_metadata(&mut metadata_changes);
 
         if let Some(view) = metadata {
             let pkt = play::EntityTrackerUpdateS2c {
-                entity_id: VarInt(entity_id.minecraft_id()),
+                entity_id: VarInt(ids.minecraft_id(entity_id)),
                 tracked_values: RawBytes(CowBytes::Borrowed(&view)),
             };
             compose
                 .broadcast_channel(&pkt, entity_id.into())
+                .batched(BatchOrder::Metadata)
                 .send()
                 .unwrap();
         }
     }
 }
 
+/// Broadcasts the animations pushed since the last run and clears them. See [`ActiveAnimation`].
 fn active_animation_sync(
     compose: Res<'_, Compose>,
-    mut query: Query<'_, '_, (Entity, &ConnectionId, &mut ActiveAnimation)>,
+    mut query: Query<
+        '_,
+        '_,
+        (Entity, Option<&ConnectionId>, &mut ActiveAnimation),
+        Changed<ActiveAnimation>,
+    >,
+    ids: Res<'_, MinecraftIdRegistry>,
 ) {
-    for (entity, &connection_id, mut animation) in &mut query {
-        let entity_id = VarInt(entity.minecraft_id());
+    for (entity, connection_id, mut animation) in &mut query {
+        if animation.is_empty() {
+            continue;
+        }
+
+        let entity_id = VarInt(ids.minecraft_id(entity));
 
         for pkt in animation.packets(entity_id) {
             compose
                 .broadcast_channel(&pkt, entity.into())
-                .exclude(Some(connection_id))
+                .exclude(connection_id.copied())
                 .send()
                 .unwrap();
         }
 
-        animation.clear();
+        // Clearing is not a change, so that animations pushed after this system are still sent
+        // in the next tick
+        animation.bypass_change_detection().clear();
     }
 }
 
@@ -108,12 +191,16 @@ fn sync_player_entity(
             &Pitch,
             Option<&mut PendingTeleportation>,
             &mut MovementTracking,
+            &mut PositionSync,
             &Flight,
         ),
     >,
     mut event_writer: MessageWriter<'_, HitGroundEvent>,
     commands: ParallelCommands<'_, '_>,
+    ids: Res<'_, MinecraftIdRegistry>,
+    timings: Res<'_, TickTimings>,
 ) {
+    let _timing = timings.time(TimedSection::EntitySync);
     let events = boxcar::Vec::new();
     query
         .par_iter_mut()
@@ -132,9 +219,10 @@ fn sync_player_entity(
                 pitch,
                 pending_teleport,
                 mut tracking,
+                mut sync,
                 flight,
             )| {
-                let entity_id = VarInt(entity.minecraft_id());
+                let entity_id = VarInt(ids.minecraft_id(entity));
 
                 if let Some(mut pending_teleport) = pending_teleport {
                     if pending_teleport.ttl == 0 {
@@ -149,8 +237,9 @@ fn sync_player_entity(
                     }
                 } else {
                     let position_delta = **position - tracking.last_tick_position;
-                    let needs_teleport = position_delta.abs().max_element() >= 8.0;
                     let changed_position = **position != tracking.last_tick_position;
+                    let relative_delta = sync.relative_delta(**position);
+                    let needs_teleport = changed_position && relative_delta.is_none();
 
                     let look_changed = (**yaw - ***prev_yaw).abs() >= 0.01
                         || (**pitch - ***prev_pitch).abs() >= 0.01;
@@ -162,10 +251,14 @@ fn sync_player_entity(
                         tracking.received_movement_packets = 1;
                     }
 
+                    // Movement packets merged on ingress still moved the player
+                    let movement_packets = f64::from(tracking.received_movement_packets)
+                        + f64::from(tracking.coalesced_movement_packets);
+
                     // Replace 100 by 300 if fall flying (aka elytra)
                     if f64::from(position_delta.length_squared())
                         - tracking.server_velocity.length_squared()
-                        > 100f64 * f64::from(tracking.received_movement_packets)
+                        > 100f64 * movement_packets
                     {
                         commands.command_scope(|mut commands| {
                             commands
@@ -173,9 +266,23 @@ fn sync_player_entity(
                                 .insert(PendingTeleportation::new(tracking.last_tick_position));
                         });
                         tracking.received_movement_packets = 0;
+                        tracking.coalesced_movement_packets = 0;
+                        tracking.coalesced_landing_y = None;
                         return;
                     }
 
+                    // Landings merged away on ingress are not seen below
+                    if let Some(landing_y) = tracking.coalesced_landing_y.take() {
+                        if !tracking.last_tick_flying && tracking.fall_start_y - landing_y > 3. {
+                            let event = HitGroundEvent {
+                                client: entity,
+                                fall_distance: tracking.fall_start_y - landing_y,
+                            };
+                            events.push(event);
+                        }
+                        tracking.fall_start_y = landing_y;
+                    }
+
                     let grounded = is_grounded(position, &blocks);
                     tracking.was_on_ground = grounded;
                     if grounded
@@ -194,11 +301,14 @@ fn sync_player_entity(
                         tracking.fall_start_y = position.y;
                     }
 
-                    if changed_position && !needs_teleport && look_changed {
+                    let relative_delta = relative_delta.filter(|_| changed_position);
+
+                    if let Some(delta) = relative_delta
+                        && look_changed
+                    {
                         let packet = play::RotateAndMoveRelativeS2c {
                             entity_id,
-                            #[allow(clippy::cast_possible_truncation)]
-                            delta: (position_delta * 4096.0).to_array().map(|x| x as i16),
+                            delta,
                             yaw: ByteAngle::from_degrees(**yaw),
                             pitch: ByteAngle::from_degrees(**pitch),
                             on_ground: grounded,
@@ -206,11 +316,10 @@ fn sync_player_entity(
 
                         bundle.add_packet(&packet).unwrap();
                     } else {
-                        if changed_position && !needs_teleport {
+                        if let Some(delta) = relative_delta {
                             let packet = play::MoveRelativeS2c {
                                 entity_id,
-                                #[allow(clippy::cast_possible_truncation)]
-                                delta: (position_delta * 4096.0).to_array().map(|x| x as i16),
+                                delta,
                                 on_ground: grounded,
                             };
 
@@ -247,20 +356,33 @@ fn sync_player_entity(
                         bundle.add_packet(&packet).unwrap();
                     }
 
+                    if changed_position {
+                        sync.moved(**position, needs_teleport);
+                    }
+                    sync.tick();
+
+                    bundle
+                        .broadcast_channel_batched(entity.into(), BatchOrder::Position)
+                        .unwrap();
+
                     if velocity.0 != Vec3::ZERO {
                         let packet = play::EntityVelocityUpdateS2c {
                             entity_id,
                             velocity: velocity.to_packet_units(),
                         };
 
-                        bundle.add_packet(&packet).unwrap();
+                        compose
+                            .broadcast_channel(&packet, entity.into())
+                            .batched(BatchOrder::Velocity)
+                            .send()
+                            .unwrap();
                         velocity.0 = Vec3::ZERO;
                     }
-
-                    bundle.broadcast_channel(entity.into()).unwrap();
                 }
 
                 tracking.received_movement_packets = 0;
+                tracking.coalesced_movement_packets = 0;
+                tracking.coalesced_landing_y = None;
                 tracking.last_tick_position = **position;
                 tracking.last_tick_flying = flight.is_flying;
 
@@ -300,8 +422,9 @@ fn sync_player_entity(
     event_writer.write_batch(events);
 }
 
+#[expect(clippy::too_many_arguments)]
 fn update_projectile_positions(
-    arrow_query: Query<'_, '_, (Entity, &Owner)>,
+    arrow_query: Query<'_, '_, (Entity, &Owner, Option<&TickRate>)>,
     mut query_set: ParamSet<
         '_,
         '_,
@@ -313,9 +436,15 @@ fn update_projectile_positions(
     mut projectile_block_writer: MessageWriter<'_, event::ProjectileBlockEvent>,
     mut projectile_entity_writer: MessageWriter<'_, event::ProjectileEntityEvent>,
     index: Res<'_, SpatialIndex>,
+    vanished: Query<'_, '_, (), With<Vanished>>,
     blocks: Res<'_, Blocks>,
+    tick: Res<'_, Tick>,
 ) {
-    for (arrow_entity, owner) in arrow_query.iter() {
+    for (arrow_entity, owner, tick_rate) in arrow_query.iter() {
+        if !should_tick(tick_rate, tick.0, arrow_entity) {
+            continue;
+        }
+
         let pv_query = query_set.p0();
         let (position, velocity) = match pv_query.get(arrow_entity) {
             Ok(data) => data,
@@ -336,7 +465,9 @@ fn update_projectile_positions(
 
         let ray = geometry::ray::Ray::new(center, velocity.0) * distance;
 
-        match get_first_collision(ray, &index, &blocks, query_set.p1(), Some(owner.entity)) {
+        // Projectiles pass through their owner and vanished players
+        let accept = |entity| entity != owner.entity && !vanished.contains(entity);
+        match get_first_collision(ray, &index, &blocks, query_set.p1(), accept) {
             Some(Either::Left(entity)) => {
                 // send event
                 projectile_entity_writer.write(event::ProjectileEntityEvent {
@@ -388,7 +519,7 @@ impl Plugin for EntityStateSyncPlugin {
                 entity_metadata_sync,
                 active_animation_sync,
                 sync_player_entity,
-                update_projectile_positions,
+                update_projectile_positions.in_set(EntitySimulation),
             ),
         );
 
@@ -398,3 +529,58 @@ impl Plugin for EntityStateSyncPlugin {
         track_prev::<Pitch>(app);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_move_is_absolute() {
+        let sync = PositionSync::default();
+        assert_eq!(sync.relative_delta(Vec3::ONE), None);
+    }
+
+    #[test]
+    fn relative_moves_do_not_drift() {
+        let mut sync = PositionSync::default();
+        sync.moved(Vec3::ZERO, true);
+
+        let mut client = I64Vec3::ZERO;
+        let mut position = Vec3::ZERO;
+        for _ in 0..ABSOLUTE_SYNC_INTERVAL - 1 {
+            position += Vec3::new(0.1, -0.03, 0.07);
+            let delta = sync.relative_delta(position).unwrap();
+            client += I64Vec3::from_array(delta.map(i64::from));
+            sync.moved(position, false);
+            sync.tick();
+        }
+
+        let error = (client.as_dvec3() / 4096.0 - position.as_dvec3()).abs();
+        assert!(error.max_element() <= 0.5 / 4096.0);
+    }
+
+    #[test]
+    fn large_moves_are_absolute() {
+        let mut sync = PositionSync::default();
+        sync.moved(Vec3::ZERO, true);
+
+        assert!(sync.relative_delta(Vec3::new(7.9, 0.0, -7.9)).is_some());
+        assert_eq!(sync.relative_delta(Vec3::new(8.0, 0.0, 0.0)), None);
+        assert_eq!(sync.relative_delta(Vec3::new(0.0, -8.1, 0.0)), None);
+    }
+
+    #[test]
+    fn absolute_position_is_resent_periodically() {
+        let mut sync = PositionSync::default();
+        sync.moved(Vec3::ZERO, true);
+
+        for _ in 0..ABSOLUTE_SYNC_INTERVAL {
+            assert!(sync.relative_delta(Vec3::X).is_some());
+            sync.tick();
+        }
+        assert_eq!(sync.relative_delta(Vec3::X), None);
+
+        sync.moved(Vec3::X, true);
+        assert!(sync.relative_delta(Vec3::X).is_some());
+    }
+}
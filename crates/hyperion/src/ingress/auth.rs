@@ -0,0 +1,214 @@
+//! How players are identified when they log in.
+
+use std::fmt::Write as _;
+
+use anyhow::{Context, bail};
+use bevy_ecs::{component::Component, resource::Resource};
+use md5::{Digest, Md5};
+use openssl::{
+    pkey::Private,
+    rsa::{Padding, Rsa},
+    sha::Sha1,
+};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "reflect")]
+use {bevy_ecs::reflect::ReflectResource, bevy_reflect::Reflect};
+
+/// Whether players are identified by their Mojang account or only by their username.
+///
+/// Permissions, player data, and other storage are keyed by the player's UUID, so switching modes
+/// on an existing server makes every player appear as a new player.
+///
+/// This is ignored when [`crate::ingress::forwarding::Forwarding`] is enabled, because the
+/// forwarding proxy decides the UUID instead.
+#[derive(
+    Resource,
+    Serialize,
+    Deserialize,
+    Copy,
+    Clone,
+    Debug,
+    Default,
+    PartialEq,
+    Eq
+)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Resource))]
+pub enum AuthMode {
+    /// Players use the UUID of their Mojang account, and their skins are fetched from Mojang.
+    ///
+    /// The connection is encrypted, and the Mojang session server confirms that the player owns
+    /// the account before they can join, like on a vanilla online-mode server.
+    #[default]
+    Online,
+    /// Players get the same UUID as on an offline-mode vanilla server, derived from their
+    /// username. Skins are not fetched because the player may not own the account.
+    Offline,
+}
+
+/// The key pair which clients encrypt their shared secret with in [`AuthMode::Online`]
+#[derive(Resource)]
+pub struct SessionKey {
+    private: Rsa<Private>,
+    /// The public key in the DER format, which is what clients expect
+    public: Box<[u8]>,
+}
+
+impl SessionKey {
+    /// Generates a new key pair. Vanilla servers use 1024-bit RSA keys.
+    pub fn generate() -> anyhow::Result<Self> {
+        let private = Rsa::generate(1024)?;
+        let public = private.public_key_to_der()?.into_boxed_slice();
+        Ok(Self { private, public })
+    }
+
+    #[must_use]
+    pub fn public_key(&self) -> &[u8] {
+        &self.public
+    }
+
+    /// Decrypts the encryption response of a client and returns the shared secret. The verify
+    /// token in the response must match the one sent in the encryption request.
+    pub fn decrypt_response(
+        &self,
+        shared_secret: &[u8],
+        verify_token: &[u8],
+        expected_token: &[u8],
+    ) -> anyhow::Result<[u8; 16]> {
+        if self.decrypt(verify_token)? != expected_token {
+            bail!("verify token does not match");
+        }
+
+        let shared_secret = self.decrypt(shared_secret)?;
+        shared_secret
+            .try_into()
+            .ok()
+            .context("shared secret is not 16 bytes long")
+    }
+
+    fn decrypt(&self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let mut decrypted = vec![0; self.private.size() as usize];
+        let len = self
+            .private
+            .private_decrypt(data, &mut decrypted, Padding::PKCS1)?;
+        decrypted.truncate(len);
+        Ok(decrypted)
+    }
+}
+
+/// A connection in [`AuthMode::Online`] which was sent an encryption request and has not
+/// answered it yet
+#[derive(Component, Debug)]
+pub struct AwaitingEncryption {
+    pub username: String,
+    pub verify_token: [u8; 4],
+}
+
+/// Returns the server hash which the client and the server send to the Mojang session server to
+/// prove that they agreed on the same shared secret. Hyperion sends an empty server ID, like
+/// vanilla servers do.
+#[must_use]
+pub fn server_hash(shared_secret: &[u8], public_key: &[u8]) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(shared_secret);
+    hasher.update(public_key);
+    signed_hex_digest(hasher.finish())
+}
+
+/// Formats a SHA-1 digest as a signed big-endian number in hexadecimal, which is how Minecraft
+/// formats server hashes
+fn signed_hex_digest(mut digest: [u8; 20]) -> String {
+    let negative = digest[0] & 0x80 != 0;
+    if negative {
+        // Negate the two's complement number
+        let mut carry = true;
+        for byte in digest.iter_mut().rev() {
+            let (value, overflow) = (!*byte).overflowing_add(u8::from(carry));
+            *byte = value;
+            carry = overflow;
+        }
+    }
+
+    let mut hex = String::with_capacity(41);
+    if negative {
+        hex.push('-');
+    }
+
+    let mut digits = String::with_capacity(40);
+    for byte in digest {
+        write!(digits, "{byte:02x}").unwrap();
+    }
+
+    match digits.trim_start_matches('0') {
+        "" => hex.push('0'),
+        digits => hex.push_str(digits),
+    }
+    hex
+}
+
+/// Returns the UUID that a vanilla offline-mode server assigns to `username`, which is the
+/// MD5-based version 3 UUID of `OfflinePlayer:<username>`
+#[must_use]
+pub fn offline_uuid(username: &str) -> uuid::Uuid {
+    let digest = Md5::new()
+        .chain_update("OfflinePlayer:")
+        .chain_update(username)
+        .finalize();
+
+    uuid::Builder::from_md5_bytes(digest.into()).into_uuid()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offline_uuid_matches_vanilla() {
+        assert_eq!(
+            offline_uuid("Notch"),
+            uuid::uuid!("b50ad385-829d-3141-a216-7e7d7539ba7f")
+        );
+        assert_eq!(
+            offline_uuid("jeb_"),
+            uuid::uuid!("a762f560-4fce-3236-812a-b80efff0b62b")
+        );
+    }
+
+    #[test]
+    fn offline_uuid_is_case_sensitive() {
+        assert_ne!(offline_uuid("notch"), offline_uuid("Notch"));
+    }
+
+    #[test]
+    fn hex_digests_match_vanilla() {
+        let digest = |name: &str| signed_hex_digest(openssl::sha::sha1(name.as_bytes()));
+
+        assert_eq!(digest("Notch"), "4ed1f46bbe04bc756bcb17c0c7ce3e4632f06a48");
+        assert_eq!(digest("jeb_"), "-7c9d5b0044c130109a5d7b5fb5c317c02b4e28c1");
+        assert_eq!(digest("simon"), "88e16a1019277b15d58faf0541e11910eb756f6");
+    }
+
+    #[test]
+    fn encryption_response_is_decrypted() {
+        let key = SessionKey::generate().unwrap();
+        let public = Rsa::public_key_from_der(key.public_key()).unwrap();
+        let encrypt = |data: &[u8]| {
+            let mut encrypted = vec![0; public.size() as usize];
+            public
+                .public_encrypt(data, &mut encrypted, Padding::PKCS1)
+                .unwrap();
+            encrypted
+        };
+
+        let secret = [3; 16];
+        let token = [1, 2, 3, 4];
+        assert_eq!(
+            key.decrypt_response(&encrypt(&secret), &encrypt(&token), &token)
+                .unwrap(),
+            secret
+        );
+        assert!(
+            key.decrypt_response(&encrypt(&secret), &encrypt(&[4, 3, 2, 1]), &token)
+                .is_err()
+        );
+    }
+}
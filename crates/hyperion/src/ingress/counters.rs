@@ -0,0 +1,327 @@
+//! Counting the play packets clients send by packet ID, to find clients which spam specific
+//! packets.
+//!
+//! Every play packet is counted in [`NetworkMetrics`], which exposes the totals of all
+//! connections. Keeping a history for every connection would cost memory for every player, so the
+//! packets of a single connection are only counted while it has [`InspectedPackets`], which is
+//! added by [`inspect_packets`] and removed again once nobody looked at the connection for
+//! [`INSPECTION_TICKS`].
+
+use std::{
+    collections::VecDeque,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use bevy_ecs::{
+    component::Component,
+    entity::Entity,
+    system::{Commands, Query, Res},
+    world::World,
+};
+#[cfg(feature = "reflect")]
+use {bevy_ecs::reflect::ReflectComponent, bevy_reflect::Reflect};
+
+#[cfg(doc)]
+use crate::net::metrics::NetworkMetrics;
+use crate::{TICKS_PER_SECOND, Tick};
+
+/// The number of packet IDs which are counted. Every play packet sent by clients has an ID below
+/// this.
+pub const PACKET_IDS: usize = 64;
+
+/// The number of seconds [`InspectedPackets`] keeps counts for
+pub const HISTORY_SECONDS: usize = 60;
+
+/// How long a connection is inspected for after [`inspect_packets`] was last called for it
+pub const INSPECTION_TICKS: i64 = 5 * 60 * TICKS_PER_SECOND;
+
+/// The name of the play packet with `id`, such as `KeepAlive`
+#[must_use]
+pub fn play_packet_name(id: i32) -> Option<&'static str> {
+    use valence_protocol::Packet as _;
+
+    hyperion_packet_macros::for_each_play_c2s_packet! {
+        match id {
+            #{
+                #valence_packet::ID => Some(stringify!(#packet_name)),
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The number of packets received with each packet ID
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PacketCounts([u32; PACKET_IDS]);
+
+impl Default for PacketCounts {
+    fn default() -> Self {
+        Self([0; PACKET_IDS])
+    }
+}
+
+impl PacketCounts {
+    /// Counts a packet with `id`. Packets with IDs outside of [`PACKET_IDS`] are not counted,
+    /// since they fail to decode anyways.
+    pub(crate) fn record(&mut self, id: i32) {
+        if let Some(count) = usize::try_from(id).ok().and_then(|id| self.0.get_mut(id)) {
+            *count = count.saturating_add(1);
+        }
+    }
+
+    fn add(&mut self, other: &Self) {
+        for (count, other) in self.0.iter_mut().zip(other.0) {
+            *count = count.saturating_add(other);
+        }
+    }
+
+    /// The number of packets received with `id`
+    #[must_use]
+    pub fn get(&self, id: i32) -> u32 {
+        usize::try_from(id)
+            .ok()
+            .and_then(|id| self.0.get(id))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(|&count| count == 0)
+    }
+
+    /// The packet IDs which were received at least once and their counts
+    pub fn iter(&self) -> impl Iterator<Item = (i32, u32)> {
+        (0..).zip(self.0).filter(|&(_, count)| count > 0)
+    }
+}
+
+/// The number of play packets received with each packet ID since the server started
+#[derive(Debug)]
+pub(crate) struct PacketTotals([AtomicU64; PACKET_IDS]);
+
+impl Default for PacketTotals {
+    fn default() -> Self {
+        Self(std::array::from_fn(|_| AtomicU64::new(0)))
+    }
+}
+
+impl PacketTotals {
+    pub(crate) fn add(&self, counts: &PacketCounts) {
+        for (total, count) in self.0.iter().zip(counts.0) {
+            if count > 0 {
+                total.fetch_add(u64::from(count), Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// The packet IDs which were received at least once and their totals
+    pub(crate) fn get(&self) -> Vec<(i32, u64)> {
+        (0..)
+            .zip(&self.0)
+            .map(|(id, total)| (id, total.load(Ordering::Relaxed)))
+            .filter(|&(_, total)| total > 0)
+            .collect()
+    }
+}
+
+/// How often a connection sent a packet, see [`InspectedPackets::top`]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PacketRate {
+    pub id: i32,
+    /// The name of the packet, see [`play_packet_name`]
+    pub name: Option<&'static str>,
+    /// The number of packets received over the counted seconds
+    pub total: u64,
+    /// The average number of packets received per second
+    pub per_second: f64,
+}
+
+#[expect(
+    clippy::cast_precision_loss,
+    reason = "the counts of a minute are far below 2^52"
+)]
+const fn to_f64(count: u64) -> f64 {
+    count as f64
+}
+
+/// The play packets of a connection counted by second. See the [module documentation](self).
+#[derive(Component, Clone, Debug)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+pub struct InspectedPackets {
+    /// The packets received in the current second
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    current: PacketCounts,
+    /// The packets received in each of the last [`HISTORY_SECONDS`] seconds, oldest first
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    history: VecDeque<PacketCounts>,
+    /// The tick the connection stops being inspected at
+    expires: i64,
+}
+
+impl InspectedPackets {
+    fn new(tick: i64) -> Self {
+        Self {
+            current: PacketCounts::default(),
+            history: VecDeque::with_capacity(HISTORY_SECONDS),
+            expires: tick + INSPECTION_TICKS,
+        }
+    }
+
+    pub(crate) fn record(&mut self, counts: &PacketCounts) {
+        self.current.add(counts);
+    }
+
+    fn end_second(&mut self) {
+        if self.history.len() == HISTORY_SECONDS {
+            self.history.pop_front();
+        }
+        self.history.push_back(std::mem::take(&mut self.current));
+    }
+
+    /// The packets received in the current second so far
+    #[must_use]
+    pub const fn this_second(&self) -> &PacketCounts {
+        &self.current
+    }
+
+    /// The number of complete seconds counted, up to [`HISTORY_SECONDS`]
+    #[must_use]
+    pub fn seconds(&self) -> usize {
+        self.history.len()
+    }
+
+    /// The packets received over the complete seconds counted, most frequent first
+    #[must_use]
+    pub fn top(&self) -> Vec<PacketRate> {
+        let mut totals = [0_u64; PACKET_IDS];
+        for counts in &self.history {
+            for (total, count) in totals.iter_mut().zip(counts.0) {
+                *total += u64::from(count);
+            }
+        }
+
+        let seconds = f64::from(u32::try_from(self.history.len().max(1)).unwrap_or(u32::MAX));
+
+        let mut rates = (0..)
+            .zip(totals)
+            .filter(|&(_, total)| total > 0)
+            .map(|(id, total)| PacketRate {
+                id,
+                name: play_packet_name(id),
+                total,
+                per_second: to_f64(total) / seconds,
+            })
+            .collect::<Vec<_>>();
+
+        rates.sort_by(|a, b| b.total.cmp(&a.total).then(a.id.cmp(&b.id)));
+        rates
+    }
+}
+
+/// Starts counting the packets of `player`, or keeps counting them for another
+/// [`INSPECTION_TICKS`] if they are counted already
+pub fn inspect_packets(player: Entity) -> impl FnOnce(&mut World) + Send + 'static {
+    move |world: &mut World| {
+        let tick = world.get_resource::<Tick>().map_or(0, |tick| tick.0);
+        let Ok(mut player) = world.get_entity_mut(player) else {
+            return;
+        };
+
+        if let Some(mut inspected) = player.get_mut::<InspectedPackets>() {
+            inspected.expires = tick + INSPECTION_TICKS;
+        } else {
+            player.insert(InspectedPackets::new(tick));
+        }
+    }
+}
+
+pub(crate) fn update_inspected_packets(
+    tick: Res<'_, Tick>,
+    mut query: Query<'_, '_, (Entity, &mut InspectedPackets)>,
+    mut commands: Commands<'_, '_>,
+) {
+    let end_of_second = tick.0 % TICKS_PER_SECOND == 0;
+
+    for (entity, mut inspected) in &mut query {
+        if tick.0 >= inspected.expires {
+            commands.entity(entity).remove::<InspectedPackets>();
+            continue;
+        }
+
+        if end_of_second {
+            inspected.end_second();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use valence_protocol::Packet as _;
+
+    use super::*;
+
+    #[test]
+    fn every_play_packet_is_counted() {
+        hyperion_packet_macros::for_each_play_c2s_packet! {
+            #{
+                let id = #valence_packet::ID;
+                assert!(usize::try_from(id).unwrap() < PACKET_IDS, "{id} is not counted");
+                assert_eq!(play_packet_name(id), Some(stringify!(#packet_name)));
+            }
+        }
+    }
+
+    #[test]
+    fn history_keeps_the_last_minute() {
+        let mut inspected = InspectedPackets::new(0);
+        let mut counts = PacketCounts::default();
+        counts.record(3);
+        counts.record(3);
+        counts.record(7);
+        // Out of range IDs are ignored
+        counts.record(-1);
+        counts.record(1000);
+        assert_eq!(counts.iter().collect::<Vec<_>>(), [(3, 2), (7, 1)]);
+
+        // The first second only has packet 7
+        let mut first = PacketCounts::default();
+        first.record(7);
+        inspected.record(&first);
+        inspected.end_second();
+
+        for _ in 0..HISTORY_SECONDS {
+            inspected.record(&counts);
+            inspected.end_second();
+        }
+
+        // The first second was dropped
+        assert_eq!(inspected.seconds(), HISTORY_SECONDS);
+        let top = inspected.top();
+        assert_eq!(top.len(), 2);
+        assert_eq!(top[0].id, 3);
+        assert_eq!(top[0].total, 120);
+        assert!((top[0].per_second - 2.0).abs() < f64::EPSILON);
+        assert_eq!(top[1].id, 7);
+        assert_eq!(top[1].total, 60);
+    }
+
+    #[test]
+    fn inspections_expire() {
+        let mut world = World::new();
+        world.insert_resource(Tick(0));
+        let player = world.spawn_empty().id();
+
+        inspect_packets(player)(&mut world);
+        world.insert_resource(Tick(INSPECTION_TICKS - 1));
+        inspect_packets(player)(&mut world);
+
+        world.insert_resource(Tick(INSPECTION_TICKS));
+        world.run_system_cached(update_inspected_packets).unwrap();
+        assert!(world.get::<InspectedPackets>(player).is_some());
+
+        world.insert_resource(Tick(2 * INSPECTION_TICKS - 1));
+        world.run_system_cached(update_inspected_packets).unwrap();
+        assert!(world.get::<InspectedPackets>(player).is_none());
+    }
+}
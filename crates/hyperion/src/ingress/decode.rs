@@ -6,19 +6,35 @@
               casing"
 )]
 
+use std::sync::atomic::Ordering;
+
 use bevy_app::{App, FixedUpdate, Plugin};
 use bevy_ecs::{
     batching::BatchingStrategy,
     entity::Entity,
+    schedule::IntoScheduleConfigs,
     system::{Query, Res},
 };
+use hyperion_utils::{Args, Locale, Translations, localization::DEFAULT_LANGUAGE};
 use paste::paste;
-use tracing::error;
+use tracing::{error, warn};
 use valence_protocol::Packet as _;
 
 use crate::{
-    net::{Compose, ConnectionId, PacketDecoder, decoder::BorrowedPacketFrame},
-    simulation::{packet::Packet, packet_state},
+    Tick,
+    ingress::{
+        counters::{self, InspectedPackets, PacketCounts},
+        limits::{BudgetOutcome, IngressBudget, IngressLimits},
+        movement::{Coalesce, MovementBurst},
+        pending::StateDeadline,
+    },
+    net::{
+        Compose, ConnectionId, PacketDecoder,
+        decoder::{BorrowedPacketFrame, DecodeLimitError, DecodeLimits},
+        metrics::NetworkMetrics,
+    },
+    simulation::{MovementTracking, packet::Packet, packet_state},
+    timings::{TickTimings, TimedSection},
 };
 
 mod __private {
@@ -99,24 +115,190 @@ mod may_transition {
     pub const play: bool = false;
 }
 
+/// Whether the packets in these states are counted by packet ID. See [`counters`].
+mod counted {
+    pub const handshake: bool = false;
+    pub const status: bool = false;
+    pub const login: bool = false;
+    pub const play: bool = true;
+}
+
+/// Merges movement packets into a [`MovementBurst`]. Only players send movement packets.
+mod coalesce {
+    use super::{BorrowedPacketFrame, Coalesce, IngressLimits, MovementBurst};
+
+    pub fn handshake(
+        _burst: &mut MovementBurst,
+        _frame: &BorrowedPacketFrame,
+        _limits: &IngressLimits,
+    ) -> anyhow::Result<Coalesce> {
+        Ok(Coalesce::Barrier)
+    }
+
+    pub fn status(
+        _burst: &mut MovementBurst,
+        _frame: &BorrowedPacketFrame,
+        _limits: &IngressLimits,
+    ) -> anyhow::Result<Coalesce> {
+        Ok(Coalesce::Barrier)
+    }
+
+    pub fn login(
+        _burst: &mut MovementBurst,
+        _frame: &BorrowedPacketFrame,
+        _limits: &IngressLimits,
+    ) -> anyhow::Result<Coalesce> {
+        Ok(Coalesce::Barrier)
+    }
+
+    pub fn play(
+        burst: &mut MovementBurst,
+        frame: &BorrowedPacketFrame,
+        limits: &IngressLimits,
+    ) -> anyhow::Result<Coalesce> {
+        burst.push_frame(frame, limits)
+    }
+}
+
+/// Writes the movement packet merged by a [`MovementBurst`] to the buffers
+mod flush {
+    use bevy_ecs::entity::Entity;
+
+    use super::{__private::PacketIdGenerator, MovementBurst, Packet, buffers};
+    use crate::{ingress::movement::Movement, net::ConnectionId};
+
+    pub fn handshake(
+        _burst: &mut MovementBurst,
+        _buffers: &buffers::handshake,
+        _sender: Entity,
+        _connection_id: ConnectionId,
+        _packet_id_generator: &PacketIdGenerator,
+    ) {
+    }
+
+    pub fn status(
+        _burst: &mut MovementBurst,
+        _buffers: &buffers::status,
+        _sender: Entity,
+        _connection_id: ConnectionId,
+        _packet_id_generator: &PacketIdGenerator,
+    ) {
+    }
+
+    pub fn login(
+        _burst: &mut MovementBurst,
+        _buffers: &buffers::login,
+        _sender: Entity,
+        _connection_id: ConnectionId,
+        _packet_id_generator: &PacketIdGenerator,
+    ) {
+    }
+
+    pub fn play(
+        burst: &mut MovementBurst,
+        buffers: &buffers::play,
+        sender: Entity,
+        connection_id: ConnectionId,
+        packet_id_generator: &PacketIdGenerator,
+    ) {
+        let Some(movement) = burst.take() else {
+            return;
+        };
+
+        // The merged packet is ordered after the packets before it, since it gets its ID now
+        let packet_id = packet_id_generator.next();
+        match movement {
+            Movement::Full(data) => {
+                buffers
+                    .Full
+                    .push(Packet::new(sender, connection_id, packet_id, data));
+            }
+            Movement::Position(data) => {
+                buffers.PositionAndOnGround.push(Packet::new(
+                    sender,
+                    connection_id,
+                    packet_id,
+                    data,
+                ));
+            }
+            Movement::Look(data) => {
+                buffers
+                    .LookAndOnGround
+                    .push(Packet::new(sender, connection_id, packet_id, data));
+            }
+            Movement::OnGround(data) => {
+                buffers
+                    .OnGroundOnly
+                    .push(Packet::new(sender, connection_id, packet_id, data));
+            }
+        }
+    }
+}
+
 fn try_next_frame(
-    compose: &Compose,
-    connection_id: ConnectionId,
+    metrics: &NetworkMetrics,
     decoder: &PacketDecoder,
+    limits: &DecodeLimits,
     decompressor: &mut libdeflater::Decompressor,
     receiver: &mut packet_channel::Receiver,
-) -> Option<BorrowedPacketFrame> {
-    let raw_packet = receiver.try_recv()?;
-    match decoder.try_next_packet(decompressor, raw_packet) {
-        Ok(packet) => Some(packet),
+) -> anyhow::Result<Option<BorrowedPacketFrame>> {
+    let Some(raw_packet) = receiver.try_recv() else {
+        return Ok(None);
+    };
+    metrics.packets_received.fetch_add(1, Ordering::Relaxed);
+    metrics
+        .bytes_received
+        .fetch_add(raw_packet.len() as u64, Ordering::Relaxed);
+
+    match decoder.try_next_packet(decompressor, limits, raw_packet) {
+        Ok(packet) => Ok(Some(packet)),
         Err(e) => {
-            error!("failed to decode packet: {e}");
-            compose.io_buf().shutdown(connection_id);
-            None
+            if e.is::<DecodeLimitError>() {
+                metrics.rejected_packets.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e)
         }
     }
 }
 
+/// Sends a disconnect packet for the given state and closes the stream. The proxy then reports the
+/// disconnect, which despawns the entity.
+pub(crate) mod disconnect {
+    use valence_protocol::packets::{login::LoginDisconnectS2c, play::DisconnectS2c};
+    use valence_text::IntoText;
+
+    use crate::net::{Compose, ConnectionId};
+
+    // Clients in these states cannot be sent a disconnect reason
+    pub fn handshake(compose: &Compose, connection_id: ConnectionId, _reason: &str) {
+        compose.io_buf().shutdown(connection_id);
+    }
+
+    pub fn status(compose: &Compose, connection_id: ConnectionId, _reason: &str) {
+        compose.io_buf().shutdown(connection_id);
+    }
+
+    pub fn login(compose: &Compose, connection_id: ConnectionId, reason: &str) {
+        let pkt = LoginDisconnectS2c {
+            reason: reason.into_cow_text(),
+        };
+        if let Err(e) = compose.unicast(&pkt, connection_id) {
+            tracing::error!("failed to send disconnect packet: {e}");
+        }
+        compose.io_buf().shutdown(connection_id);
+    }
+
+    pub fn play(compose: &Compose, connection_id: ConnectionId, reason: &str) {
+        let pkt = DisconnectS2c {
+            reason: reason.into_cow_text(),
+        };
+        if let Err(e) = compose.unicast(&pkt, connection_id) {
+            tracing::error!("failed to send disconnect packet: {e}");
+        }
+        compose.io_buf().shutdown(connection_id);
+    }
+}
+
 hyperion_packet_macros::for_each_state! {
     #{
         pub fn #state(
@@ -128,16 +310,29 @@ hyperion_packet_macros::for_each_state! {
                 &ConnectionId,
                 &PacketDecoder,
                 &mut packet_channel::Receiver,
+                &mut IngressBudget,
+                Option<&Locale>,
+                Option<&mut MovementTracking>,
+                Option<&mut StateDeadline>,
+                Option<&mut InspectedPackets>,
             ),
             paste! { bevy_ecs::query::With<packet_state::[< #state:camel >]> }
             >,
             compose: Res<'_, Compose>,
             packet_id_generator: Res<'_, __private::PacketIdGenerator>,
             decompressor: Res<'_, __private::Decompressor>,
+            limits: Res<'_, IngressLimits>,
+            tick: Res<'_, Tick>,
+            metrics: Res<'_, NetworkMetrics>,
+            timings: Res<'_, TickTimings>,
+            translations: Res<'_, Translations>,
             mut writers: writers::#state<'_>,
         ) {
+            let _timing = timings.time(TimedSection::Decode);
             let compose = &compose;
             let packet_id_generator = &packet_id_generator;
+            let limits = &*limits;
+            let metrics = &*metrics;
             let buffers = buffers::#state::default();
 
             // Fill buffers
@@ -145,23 +340,101 @@ hyperion_packet_macros::for_each_state! {
             query.par_iter_mut().batching_strategy(BatchingStrategy {
                 batch_size_limits: 1..128,
                 batches_per_thread: 1,
-            }).for_each(|(sender, &connection_id, decoder, receiver)| {
+            }).for_each(|(
+                sender,
+                &connection_id,
+                decoder,
+                receiver,
+                budget,
+                locale,
+                tracking,
+                deadline,
+                inspected,
+            )| {
                 let receiver = receiver.into_inner();
+                let budget = budget.into_inner();
                 let mut decompressor = decompressor.0.get_or_default().borrow_mut();
 
+                // Nothing more is decoded from connections which stayed in this state for too long
+                let timed_out = deadline.is_some_and(|mut deadline| deadline.expire(tick.0));
+                if timed_out {
+                    *receiver = packet_channel::Receiver::default();
+                    *budget = IngressBudget::default();
+                }
+
+                let mut invalid = false;
+                let was_on_ground = tracking.as_ref().is_some_and(|tracking| {
+                    tracking.was_on_ground
+                });
+                let mut burst = MovementBurst::new(was_on_ground);
+                let mut counts = PacketCounts::default();
+
                 loop {
-                    let Some(frame) = try_next_frame(
-                        compose,
-                        connection_id,
-                        decoder,
-                        &mut decompressor,
-                        receiver,
-                    ) else {
+                    let mut frame_error = None;
+                    let Some(frame) = budget.next_frame(limits, || {
+                        try_next_frame(
+                            metrics,
+                            decoder,
+                            &limits.decode,
+                            &mut decompressor,
+                            receiver,
+                        )
+                        .unwrap_or_else(|e| {
+                            frame_error = Some(e);
+                            None
+                        })
+                    }) else {
+                        if let Some(e) = frame_error {
+                            error!("failed to decode packet: {e}");
+                            invalid = true;
+                        }
                         break;
                     };
 
+                    let filters = compose.io_buf().filters();
+                    let frame = if filters.ingress_is_empty() {
+                        frame
+                    } else {
+                        match filters.filter_ingress(connection_id, stringify!(#state), frame) {
+                            Some(frame) => frame,
+                            None => continue,
+                        }
+                    };
+
                     let frame_id = frame.id;
 
+                    match coalesce::#state(&mut burst, &frame, limits) {
+                        Ok(Coalesce::Merged) => {
+                            if counted::#state {
+                                counts.record(frame_id);
+                            }
+                            continue;
+                        }
+                        Ok(Coalesce::Defer) => {
+                            budget.defer(frame);
+                            break;
+                        }
+                        Ok(Coalesce::Barrier) => {
+                            flush::#state(
+                                &mut burst,
+                                &buffers,
+                                sender,
+                                connection_id,
+                                packet_id_generator,
+                            );
+                        }
+                        Err(e) => {
+                            error!("error while decoding packet (id: {frame_id}): {e}");
+                            invalid = true;
+                            break;
+                        }
+                    }
+
+                    // Deferred packets are counted once they are decoded in a later tick
+                    if counted::#state {
+                        counts.record(frame_id);
+                    }
+
                     #for_each_packet! {
                         let result: anyhow::Result<()> = match frame_id {
                             #{
@@ -191,7 +464,7 @@ hyperion_packet_macros::for_each_state! {
                         // compile times by reducing code duplication from the expansion of the error!
                         // macro
                         error!("error while decoding packet (id: {frame_id}): {e}");
-                        compose.io_buf().shutdown(connection_id);
+                        invalid = true;
                         break;
                     }
 
@@ -202,6 +475,63 @@ hyperion_packet_macros::for_each_state! {
                         break;
                     }
                 }
+
+                flush::#state(&mut burst, &buffers, sender, connection_id, packet_id_generator);
+
+                if !counts.is_empty() {
+                    metrics.record_play_packets(&counts);
+                    if let Some(mut inspected) = inspected {
+                        inspected.record(&counts);
+                    }
+                }
+
+                if let Some(mut tracking) = tracking {
+                    if burst.coalesced() > 0 {
+                        tracking.coalesced_movement_packets = tracking
+                            .coalesced_movement_packets
+                            .saturating_add(burst.coalesced());
+                    }
+
+                    if let Some(landing_y) = burst.landing_y() {
+                        tracking.coalesced_landing_y = Some(
+                            tracking
+                                .coalesced_landing_y
+                                .map_or(landing_y, |lowest| lowest.min(landing_y)),
+                        );
+                    }
+                }
+
+                let kick_reason = if timed_out {
+                    metrics.state_timeouts.fetch_add(1, Ordering::Relaxed);
+                    Some("kick.timed_out")
+                } else if invalid {
+                    Some("kick.invalid_packet")
+                } else {
+                    match budget.end_tick(limits) {
+                        BudgetOutcome::WithinBudget => None,
+                        BudgetOutcome::Deferred => {
+                            metrics.deferrals.fetch_add(1, Ordering::Relaxed);
+                            None
+                        }
+                        BudgetOutcome::Kick => {
+                            metrics.flood_kicks.fetch_add(1, Ordering::Relaxed);
+                            warn!("kicking {connection_id:?} for sending too many packets");
+                            Some("kick.too_many_packets")
+                        }
+                    }
+                };
+
+                if let Some(key) = kick_reason {
+                    let locale = locale.map_or(DEFAULT_LANGUAGE, Locale::as_str);
+                    let reason = translations.translate(locale, key, &Args::new());
+                    disconnect::#state(compose, connection_id, &reason);
+
+                    // The entity is only despawned once the proxy reports the disconnect. Until
+                    // then, the decoder would keep failing on the rest of the stream, so the
+                    // remaining packets are dropped instead.
+                    *receiver = packet_channel::Receiver::default();
+                    *budget = IngressBudget::default();
+                }
             });
             scope.exit();
 
@@ -209,6 +539,10 @@ hyperion_packet_macros::for_each_state! {
             let scope = tracing::info_span!("write_events").entered();
             #for_each_packet! {
                 #{
+                    metrics.record_decoded(
+                        concat!(stringify!(#state), "::", stringify!(#packet_name)),
+                        buffers.#packet_name.count(),
+                    );
                     writers.#packet_name.write_batch(buffers.#packet_name);
                 }
             }
@@ -223,6 +557,11 @@ impl Plugin for DecodePlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(__private::PacketIdGenerator::default());
         app.insert_resource(__private::Decompressor::default());
+        app.init_resource::<IngressLimits>();
+        app.init_resource::<Tick>();
+        app.init_resource::<NetworkMetrics>();
+        app.init_resource::<TickTimings>();
+        app.init_resource::<Translations>();
         hyperion_packet_macros::for_each_state! {
             app.add_systems(
                 FixedUpdate, (
@@ -232,5 +571,257 @@ impl Plugin for DecodePlugin {
                 )
             );
         }
+        app.add_systems(FixedUpdate, counters::update_inspected_packets.after(play));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bevy_ecs::{message::Messages, system::RunSystemOnce, world::World};
+    use glam::DVec3;
+    use valence_protocol::{
+        CompressionThreshold, Encode, VarInt,
+        packets::play::{KeepAliveC2s, PositionAndOnGroundC2s},
+    };
+
+    use super::*;
+    use crate::{
+        Shared,
+        net::{IoBuf, ProxyId},
+        simulation::packet::{self, PacketPlugin},
+    };
+
+    fn send_keep_alive(sender: &mut packet_channel::Sender, id: i64) {
+        send_packet(sender, &KeepAliveC2s { id });
+    }
+
+    fn send_packet<P: valence_protocol::Packet + Encode>(
+        sender: &mut packet_channel::Sender,
+        packet: &P,
+    ) {
+        let mut data = Vec::new();
+        packet.encode_with_id(&mut data).unwrap();
+
+        let mut framed = Vec::new();
+        VarInt(i32::try_from(data.len()).unwrap())
+            .encode(&mut framed)
+            .unwrap();
+        framed.extend_from_slice(&data);
+        sender.send(&framed).unwrap();
+    }
+
+    fn app(egress_tx: tokio::sync::mpsc::UnboundedSender<bytes::Bytes>) -> App {
+        let mut io_buf = IoBuf::default();
+        io_buf.add_proxy(ProxyId::new(0), egress_tx.into());
+        let shared = Shared {
+            compression_threshold: CompressionThreshold(-1),
+            compression_level: libdeflater::CompressionLvl::default(),
+        };
+
+        let mut app = App::new();
+        app.add_plugins((PacketPlugin, DecodePlugin));
+        app.insert_resource(Compose::new(
+            libdeflater::CompressionLvl::default(),
+            Arc::new(shared),
+            io_buf,
+        ));
+        app
+    }
+
+    #[test]
+    fn invalid_packets_only_disconnect_the_sender() {
+        let (egress_tx, mut egress_rx) = tokio::sync::mpsc::unbounded_channel::<bytes::Bytes>();
+        let mut app = app(egress_tx);
+        let world = app.world_mut();
+
+        let mut connections = [0, 1].map(|stream| {
+            let (sender, receiver) = packet_channel::channel(4096);
+            let entity = world
+                .spawn((
+                    ConnectionId::new(stream, ProxyId::new(0)),
+                    packet_state::Play,
+                    PacketDecoder::default(),
+                    IngressBudget::default(),
+                    receiver,
+                ))
+                .id();
+            (entity, sender)
+        });
+        let [good, bad] = connections.each_ref().map(|(entity, _)| *entity);
+
+        send_keep_alive(&mut connections[0].1, 1);
+        send_keep_alive(&mut connections[0].1, 2);
+        send_keep_alive(&mut connections[1].1, 1);
+        // A packet ID that is not a valid VarInt
+        connections[1].1.send(&[3, 0xFF, 0xFF, 0xFF]).unwrap();
+        send_keep_alive(&mut connections[1].1, 2);
+
+        world.run_system_once(play).unwrap();
+
+        let received = |world: &mut World| {
+            world
+                .resource_mut::<Messages<packet::play::KeepAlive>>()
+                .drain()
+                .map(|packet| {
+                    let keep_alive: &KeepAliveC2s = &packet;
+                    (packet.sender(), keep_alive.id)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let mut keep_alives = received(world);
+        keep_alives.sort_by_key(|&(entity, id)| (entity != good, id));
+        assert_eq!(keep_alives, [(good, 1), (good, 2), (bad, 1)]);
+
+        // The disconnect packet and the shutdown were only sent for the invalid connection
+        assert_eq!(egress_rx.len(), 2);
+        egress_rx.try_recv().unwrap();
+        egress_rx.try_recv().unwrap();
+
+        // Later packets from the invalid connection are dropped until the proxy reports the
+        // disconnect
+        send_keep_alive(&mut connections[0].1, 3);
+        send_keep_alive(&mut connections[1].1, 3);
+        world.run_system_once(play).unwrap();
+
+        assert_eq!(received(world), [(good, 3)]);
+        assert!(egress_rx.is_empty());
+    }
+
+    #[test]
+    fn packets_are_counted_by_id() {
+        let (egress_tx, _egress_rx) = tokio::sync::mpsc::unbounded_channel::<bytes::Bytes>();
+        let mut app = app(egress_tx);
+        let world = app.world_mut();
+
+        let [(inspected, mut inspected_sender), (other, mut other_sender)] = [0, 1].map(|stream| {
+            let (sender, receiver) = packet_channel::channel(4096);
+            let entity = world
+                .spawn((
+                    ConnectionId::new(stream, ProxyId::new(0)),
+                    packet_state::Play,
+                    PacketDecoder::default(),
+                    IngressBudget::default(),
+                    receiver,
+                ))
+                .id();
+            (entity, sender)
+        });
+        counters::inspect_packets(inspected)(world);
+
+        send_keep_alive(&mut inspected_sender, 1);
+        send_keep_alive(&mut inspected_sender, 2);
+        send_keep_alive(&mut other_sender, 1);
+        world.run_system_once(play).unwrap();
+
+        let counts = world
+            .get::<InspectedPackets>(inspected)
+            .unwrap()
+            .this_second();
+        assert_eq!(counts.get(KeepAliveC2s::ID), 2);
+        assert!(world.get::<InspectedPackets>(other).is_none());
+
+        let metrics = world.resource::<NetworkMetrics>();
+        assert_eq!(metrics.play_packets(), [(KeepAliveC2s::ID, 3)]);
+    }
+
+    #[test]
+    fn movement_bursts_are_merged() {
+        let (egress_tx, _egress_rx) = tokio::sync::mpsc::unbounded_channel::<bytes::Bytes>();
+        let mut app = app(egress_tx);
+        let world = app.world_mut();
+
+        let (mut sender, receiver) = packet_channel::channel(4096);
+        let player = world
+            .spawn((
+                ConnectionId::new(0, ProxyId::new(0)),
+                packet_state::Play,
+                PacketDecoder::default(),
+                IngressBudget::default(),
+                receiver,
+                MovementTracking {
+                    fall_start_y: 80.0,
+                    ..MovementTracking::default()
+                },
+            ))
+            .id();
+
+        // The player falls from y = 80 to y = 60, lands and jumps again
+        let burst = (0..40)
+            .map(|i| (80.0 - f64::from(i) * 0.5, false))
+            .chain([(60.0, true)])
+            .chain((1..10).map(|i| (60.0 + f64::from(i) * 0.1, false)));
+        for (y, on_ground) in burst {
+            send_packet(&mut sender, &PositionAndOnGroundC2s {
+                position: DVec3::new(0.5, y, 0.5),
+                on_ground,
+            });
+        }
+
+        world.run_system_once(play).unwrap();
+
+        let moves: Vec<_> = world
+            .resource_mut::<Messages<packet::play::PositionAndOnGround>>()
+            .drain()
+            .collect();
+        assert_eq!(moves.len(), 1);
+        assert_eq!(moves[0].sender(), player);
+        assert!((moves[0].position.y - 60.9).abs() < 1e-9);
+        assert!(!moves[0].on_ground);
+
+        let tracking = world.get::<MovementTracking>(player).unwrap();
+        assert_eq!(tracking.coalesced_movement_packets, 49);
+        assert_eq!(tracking.coalesced_landing_y, Some(60.0));
+        let fall_distance = tracking.fall_start_y - tracking.coalesced_landing_y.unwrap();
+        assert!((fall_distance - 20.0).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn idle_connections_time_out() {
+        let (egress_tx, mut egress_rx) = tokio::sync::mpsc::unbounded_channel::<bytes::Bytes>();
+        let mut app = app(egress_tx);
+        let world = app.world_mut();
+        world.insert_resource(Tick(99));
+
+        let (_sender, receiver) = packet_channel::channel(4096);
+        let connection = world
+            .spawn((
+                ConnectionId::new(0, ProxyId::new(0)),
+                packet_state::Login,
+                PacketDecoder::default(),
+                IngressBudget::default(),
+                receiver,
+                StateDeadline(Some(100)),
+            ))
+            .id();
+
+        world.run_system_once(login).unwrap();
+        assert!(egress_rx.is_empty());
+
+        world.insert_resource(Tick(100));
+        world.run_system_once(login).unwrap();
+
+        // The disconnect packet and the shutdown
+        assert_eq!(egress_rx.len(), 2);
+        egress_rx.try_recv().unwrap();
+        egress_rx.try_recv().unwrap();
+        assert_eq!(
+            world
+                .resource::<NetworkMetrics>()
+                .state_timeouts
+                .load(Ordering::Relaxed),
+            1
+        );
+
+        // The connection is only disconnected once while the proxy reports the disconnect
+        world.insert_resource(Tick(101));
+        world.run_system_once(login).unwrap();
+        assert!(egress_rx.is_empty());
+        assert_eq!(
+            world.get::<StateDeadline>(connection),
+            Some(&StateDeadline(None))
+        );
     }
 }
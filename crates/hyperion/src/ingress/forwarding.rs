@@ -0,0 +1,313 @@
+//! Player info forwarding for servers running behind Velocity or BungeeCord.
+//!
+//! These proxies authenticate players themselves and connect to the server in offline mode, so the
+//! server must take the player's UUID, username, and skin from the proxy instead.
+
+use bevy_ecs::{component::Component, resource::Resource};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use valence_protocol::{Decode, VarInt};
+#[cfg(feature = "reflect")]
+use {
+    bevy_ecs::reflect::{ReflectComponent, ReflectResource},
+    bevy_reflect::Reflect,
+};
+
+use crate::simulation::skin::PlayerSkin;
+
+/// The modern forwarding version requested from Velocity. Later versions only add chat signing
+/// keys, which are not used.
+pub const VELOCITY_FORWARDING_VERSION: u8 = 1;
+
+/// The message ID of the `velocity:player_info` request. Each connection only has one request in
+/// flight, so this does not need to be unique.
+pub(crate) const VELOCITY_MESSAGE_ID: i32 = 0;
+
+/// How the server learns the identity of players connecting through a proxy in front of
+/// hyperion-proxy
+#[derive(Resource, Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Resource))]
+#[serde(tag = "mode")]
+pub enum Forwarding {
+    /// Players are authenticated from their login
+    #[default]
+    None,
+    /// BungeeCord's legacy forwarding, which appends the player info to the server address in
+    /// the handshake. This cannot be verified, so the server must not be reachable without going
+    /// through BungeeCord.
+    ///
+    /// The handshake limits the server address to 255 characters, so only forwarded info without
+    /// skin properties reliably fits.
+    BungeeCord,
+    /// Velocity's modern forwarding, which is signed with a secret shared with Velocity
+    Velocity { secret: String },
+}
+
+/// Player info forwarded by a proxy
+#[derive(Component, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+pub struct ForwardedPlayer {
+    /// The IP address of the client as seen by the proxy
+    pub address: String,
+    pub uuid: uuid::Uuid,
+    pub username: String,
+    /// The skin from the player's `textures` property, if the proxy sent one
+    pub skin: Option<PlayerSkin>,
+}
+
+/// Marks a connection that was sent a `velocity:player_info` request and has not answered yet
+#[derive(Component, Debug)]
+pub(crate) struct AwaitingForwarding;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ForwardingError {
+    #[error("the connection did not come from the forwarding proxy")]
+    Missing,
+    #[error("the player info signature does not match the forwarding secret")]
+    InvalidSignature,
+    #[error("unsupported forwarding version {0}")]
+    UnsupportedVersion(i32),
+    #[error("malformed player info: {0}")]
+    Malformed(&'static str),
+}
+
+impl ForwardedPlayer {
+    /// Parses the server address of a handshake forwarded by BungeeCord, which has the format
+    /// `host\0address\0uuid\0properties` where the properties are optional.
+    pub fn from_bungeecord(server_address: &str) -> Result<Self, ForwardingError> {
+        let mut parts = server_address.split('\0');
+        let (Some(_host), Some(address), Some(uuid)) = (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(ForwardingError::Missing);
+        };
+
+        let uuid = uuid::Uuid::try_parse(uuid).map_err(|_| ForwardingError::Malformed("uuid"))?;
+
+        let skin = match parts.next() {
+            Some(properties) => {
+                let properties: Vec<BungeeCordProperty> = serde_json::from_str(properties)
+                    .map_err(|_| ForwardingError::Malformed("properties"))?;
+                properties
+                    .into_iter()
+                    .find(|property| property.name == "textures")
+                    .map(|property| PlayerSkin {
+                        textures: property.value,
+                        signature: property.signature.unwrap_or_default(),
+                    })
+            }
+            None => None,
+        };
+
+        // BungeeCord does not forward the username, so it is taken from the login instead
+        Ok(Self {
+            address: address.to_owned(),
+            uuid,
+            username: String::new(),
+            skin,
+        })
+    }
+
+    /// Verifies and parses the response to a `velocity:player_info` login plugin request
+    pub fn from_velocity(secret: &[u8], data: &[u8]) -> Result<Self, ForwardingError> {
+        let (signature, mut body) = data
+            .split_at_checked(32)
+            .ok_or(ForwardingError::Malformed("missing signature"))?;
+
+        if !constant_time_eq(&hmac_sha256(secret, body), signature) {
+            return Err(ForwardingError::InvalidSignature);
+        }
+
+        let version = read_var_int(&mut body)?;
+        if version < i32::from(VELOCITY_FORWARDING_VERSION) {
+            return Err(ForwardingError::UnsupportedVersion(version));
+        }
+
+        let address = read_string(&mut body)?;
+
+        let uuid = body
+            .split_off(..16)
+            .ok_or(ForwardingError::Malformed("uuid"))?;
+        let uuid = uuid::Uuid::from_slice(uuid).map_err(|_| ForwardingError::Malformed("uuid"))?;
+
+        let username = read_string(&mut body)?;
+
+        let mut skin = None;
+        let property_count = read_var_int(&mut body)?;
+        for _ in 0..property_count {
+            let name = read_string(&mut body)?;
+            let value = read_string(&mut body)?;
+            let signature = match body.split_off_first() {
+                Some(0) => None,
+                Some(_) => Some(read_string(&mut body)?),
+                None => return Err(ForwardingError::Malformed("property")),
+            };
+
+            if name == "textures" {
+                skin = Some(PlayerSkin {
+                    textures: value,
+                    signature: signature.unwrap_or_default(),
+                });
+            }
+        }
+
+        // Later versions append more fields, which are ignored
+        Ok(Self {
+            address,
+            uuid,
+            username,
+            skin,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct BungeeCordProperty {
+    name: String,
+    value: String,
+    signature: Option<String>,
+}
+
+fn read_var_int(data: &mut &[u8]) -> Result<i32, ForwardingError> {
+    VarInt::decode(data)
+        .map(|VarInt(value)| value)
+        .map_err(|_| ForwardingError::Malformed("varint"))
+}
+
+fn read_string(data: &mut &[u8]) -> Result<String, ForwardingError> {
+    let len = usize::try_from(read_var_int(data)?)
+        .map_err(|_| ForwardingError::Malformed("string length"))?;
+    let bytes = data
+        .split_off(..len)
+        .ok_or(ForwardingError::Malformed("string"))?;
+    String::from_utf8(bytes.to_vec()).map_err(|_| ForwardingError::Malformed("string"))
+}
+
+/// Computes the HMAC-SHA256 of `data`, which Velocity uses to sign the player info
+fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    const BLOCK_SIZE: usize = 64;
+
+    let mut block = [0; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let inner = Sha256::new()
+        .chain_update(block.map(|b| b ^ 0x36))
+        .chain_update(data)
+        .finalize();
+
+    Sha256::new()
+        .chain_update(block.map(|b| b ^ 0x5c))
+        .chain_update(inner)
+        .finalize()
+        .into()
+}
+
+/// Compares the signatures without returning early, so the comparison does not reveal how many
+/// leading bytes of a forged signature are correct
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use valence_protocol::Encode;
+
+    use super::*;
+
+    const SECRET: &[u8] = b"forwarding-secret";
+    const UUID: uuid::Uuid = uuid::uuid!("069a79f4-44e9-4726-a5be-fca90e38aaf5");
+
+    fn write_string(buf: &mut Vec<u8>, s: &str) {
+        VarInt(i32::try_from(s.len()).unwrap())
+            .encode(&mut *buf)
+            .unwrap();
+        buf.extend_from_slice(s.as_bytes());
+    }
+
+    /// Builds a response to `velocity:player_info` like the one Velocity sends
+    fn velocity_payload(secret: &[u8]) -> Vec<u8> {
+        let mut body = Vec::new();
+        VarInt(1).encode(&mut body).unwrap();
+        write_string(&mut body, "127.0.0.1");
+        body.extend_from_slice(UUID.as_bytes());
+        write_string(&mut body, "Notch");
+        VarInt(1).encode(&mut body).unwrap();
+        write_string(&mut body, "textures");
+        write_string(&mut body, "dGV4dHVyZXM=");
+        body.push(1);
+        write_string(&mut body, "c2lnbmF0dXJl");
+
+        let mut payload = hmac_sha256(secret, &body).to_vec();
+        payload.extend_from_slice(&body);
+        payload
+    }
+
+    #[test]
+    fn hmac_matches_rfc_4231() {
+        let mac = hmac_sha256(b"Jefe", b"what do ya want for nothing?");
+        let expected = [
+            0x5b, 0xdc, 0xc1, 0x46, 0xbf, 0x60, 0x75, 0x4e, 0x6a, 0x04, 0x24, 0x26, 0x08, 0x95,
+            0x75, 0xc7, 0x5a, 0x00, 0x3f, 0x08, 0x9d, 0x27, 0x39, 0x83, 0x9d, 0xec, 0x58, 0xb9,
+            0x64, 0xec, 0x38, 0x43,
+        ];
+        assert_eq!(mac, expected);
+    }
+
+    #[test]
+    fn velocity_player_info_is_verified() {
+        let player = ForwardedPlayer::from_velocity(SECRET, &velocity_payload(SECRET)).unwrap();
+        assert_eq!(player, ForwardedPlayer {
+            address: "127.0.0.1".to_owned(),
+            uuid: UUID,
+            username: "Notch".to_owned(),
+            skin: Some(PlayerSkin {
+                textures: "dGV4dHVyZXM=".to_owned(),
+                signature: "c2lnbmF0dXJl".to_owned(),
+            }),
+        });
+
+        assert_eq!(
+            ForwardedPlayer::from_velocity(b"wrong-secret", &velocity_payload(SECRET)),
+            Err(ForwardingError::InvalidSignature)
+        );
+
+        let mut tampered = velocity_payload(SECRET);
+        *tampered.last_mut().unwrap() ^= 1;
+        assert_eq!(
+            ForwardedPlayer::from_velocity(SECRET, &tampered),
+            Err(ForwardingError::InvalidSignature)
+        );
+
+        assert_eq!(
+            ForwardedPlayer::from_velocity(SECRET, &[0; 8]),
+            Err(ForwardingError::Malformed("missing signature"))
+        );
+    }
+
+    #[test]
+    fn bungeecord_address_is_parsed() {
+        let player = ForwardedPlayer::from_bungeecord(
+            "play.example.com\u{0}10.0.0.5\u{0}069a79f444e94726a5befca90e38aaf5",
+        )
+        .unwrap();
+        assert_eq!(player.address, "10.0.0.5");
+        assert_eq!(player.uuid, UUID);
+        assert_eq!(player.skin, None);
+
+        let player = ForwardedPlayer::from_bungeecord(
+            "host\u{0}10.0.0.5\u{0}069a79f444e94726a5befca90e38aaf5\u{0}[{\"name\":\"textures\",\"\
+             value\":\"dGV4dHVyZXM=\",\"signature\":\"c2lnbmF0dXJl\"}]",
+        )
+        .unwrap();
+        assert_eq!(player.skin.unwrap().textures, "dGV4dHVyZXM=");
+
+        assert_eq!(
+            ForwardedPlayer::from_bungeecord("play.example.com"),
+            Err(ForwardingError::Missing)
+        );
+    }
+}
@@ -0,0 +1,442 @@
+//! Per-connection limits on the amount of packets decoded each tick.
+
+use bevy_ecs::{component::Component, resource::Resource};
+use valence_protocol::{
+    Packet as _,
+    packets::play::{
+        FullC2s, LookAndOnGroundC2s, OnGroundOnlyC2s, PositionAndOnGroundC2s, VehicleMoveC2s,
+    },
+};
+#[cfg(feature = "reflect")]
+use {
+    bevy_ecs::reflect::{ReflectComponent, ReflectResource},
+    bevy_reflect::Reflect,
+};
+
+use crate::net::decoder::{BorrowedPacketFrame, DecodeLimits};
+
+/// Limits on how much each connection may send per tick.
+///
+/// Once a connection has used its budget, its remaining packets stay queued until the next tick.
+/// Clients that send bursts of movement packets, such as after a lag spike, are only slowed down
+/// this way, unless their packets stay queued for more than
+/// [`IngressLimits::max_deferred_ticks`] ticks in a row or they keep sending more movement packets
+/// than [`IngressLimits::movement_rate`] allows. Exceeding the budget with any other packet counts
+/// as a violation, and connections with more than [`IngressLimits::max_violations`] outstanding
+/// violations are kicked.
+#[derive(Resource, Copy, Clone, Debug)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Resource))]
+pub struct IngressLimits {
+    /// The maximum number of packets decoded per connection per tick
+    pub max_packets_per_tick: usize,
+    /// The maximum number of bytes, after decompression, decoded per connection per tick
+    pub max_bytes_per_tick: usize,
+    /// The maximum number of movement packets handled per connection per tick. Consecutive
+    /// movement packets are merged into one before they count towards this limit.
+    pub max_movement_packets_per_tick: usize,
+    /// The number of movement packets per tick a connection may send on average. Vanilla clients
+    /// send at most one per tick, or two while riding a vehicle.
+    pub movement_rate: u32,
+    /// The number of movement packets a connection may send above [`IngressLimits::movement_rate`]
+    /// at once, such as after a lag spike. Every tick spent further above the rate counts as a
+    /// violation.
+    pub max_movement_burst: u32,
+    /// The number of violations after which a connection is kicked. Each tick spent within the
+    /// budget forgives one violation.
+    pub max_violations: u32,
+    /// The number of ticks in a row a connection may have packets queued for later ticks. Every
+    /// further tick counts as a violation, so a connection cannot grow its queue forever.
+    pub max_deferred_ticks: u32,
+    /// Limits on individual packets
+    pub decode: DecodeLimits,
+}
+
+impl Default for IngressLimits {
+    fn default() -> Self {
+        Self {
+            max_packets_per_tick: 128,
+            max_bytes_per_tick: 256 * 1024,
+            max_movement_packets_per_tick: 5,
+            movement_rate: 2,
+            // Ten seconds of packets from a lagging client
+            max_movement_burst: 200,
+            max_violations: 20,
+            max_deferred_ticks: 40,
+            decode: DecodeLimits::default(),
+        }
+    }
+}
+
+/// What happened to a connection's budget at the end of a tick
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum BudgetOutcome {
+    /// Every queued packet was decoded
+    WithinBudget,
+    /// The connection ran out of budget and its remaining packets were deferred
+    Deferred,
+    /// The connection exceeded its budget too often and should be kicked
+    Kick,
+}
+
+/// Which kind of packets did not fit into a tick's budget
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "reflect", derive(Reflect))]
+enum Exceeded {
+    #[default]
+    Nothing,
+    /// Only movement packets
+    Movement,
+    /// At least one packet which is not a movement packet
+    Other,
+}
+
+impl Exceeded {
+    const fn by(id: i32) -> Self {
+        if is_movement(id) {
+            Self::Movement
+        } else {
+            Self::Other
+        }
+    }
+}
+
+/// Tracks how much of its [`IngressLimits`] budget a connection has used
+#[derive(Component, Default)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+pub struct IngressBudget {
+    violations: u32,
+    packets: usize,
+    bytes: usize,
+    /// The packets which did not fit into this tick's budget
+    exceeded: Exceeded,
+    /// The number of ticks in a row which ended with a deferred packet
+    deferred_ticks: u32,
+    /// The number of movement packets decoded above [`IngressLimits::movement_rate`]
+    movement_debt: u32,
+    /// A packet that was received but not decoded because the budget was used up
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    deferred: Option<BorrowedPacketFrame>,
+}
+
+impl IngressBudget {
+    /// The number of violations that have not been forgiven yet
+    #[must_use]
+    pub const fn violations(&self) -> u32 {
+        self.violations
+    }
+
+    /// Returns the next frame to decode this tick, or [`None`] if there are no more packets or the
+    /// budget has been used up.
+    ///
+    /// The first packet of each tick is always allowed, so a single large packet cannot stall a
+    /// connection forever.
+    pub(crate) fn next_frame(
+        &mut self,
+        limits: &IngressLimits,
+        mut recv: impl FnMut() -> Option<BorrowedPacketFrame>,
+    ) -> Option<BorrowedPacketFrame> {
+        let exhausted = self.packets > 0
+            && (self.packets >= limits.max_packets_per_tick
+                || self.bytes >= limits.max_bytes_per_tick);
+
+        let frame = self.deferred.take().or_else(&mut recv)?;
+
+        if exhausted {
+            // The packet has already been taken from the receiver, so it is kept until the next
+            // tick to preserve the order of packets
+            self.exceed(frame.id);
+            self.deferred = Some(frame);
+            return None;
+        }
+
+        self.charge(&frame);
+        Some(frame)
+    }
+
+    fn charge(&mut self, frame: &BorrowedPacketFrame) {
+        self.packets += 1;
+        self.bytes += frame.body_len();
+        if is_movement(frame.id) {
+            self.movement_debt = self.movement_debt.saturating_add(1);
+        }
+    }
+
+    /// Keeps `frame`, which was returned by [`IngressBudget::next_frame`], until the next tick
+    /// even though the budget has not been used up, such as for movement packets once enough
+    /// movement has been handled this tick. The frame is only charged once it is decoded.
+    pub(crate) fn defer(&mut self, frame: BorrowedPacketFrame) {
+        self.packets = self.packets.saturating_sub(1);
+        self.bytes = self.bytes.saturating_sub(frame.body_len());
+        if is_movement(frame.id) {
+            self.movement_debt = self.movement_debt.saturating_sub(1);
+        }
+
+        self.exceed(frame.id);
+        self.deferred = Some(frame);
+    }
+
+    fn exceed(&mut self, id: i32) {
+        self.exceeded = self.exceeded.max(Exceeded::by(id));
+    }
+
+    /// Resets the budget for the next tick and returns what should happen to the connection
+    pub(crate) fn end_tick(&mut self, limits: &IngressLimits) -> BudgetOutcome {
+        self.packets = 0;
+        self.bytes = 0;
+
+        self.movement_debt = self.movement_debt.saturating_sub(limits.movement_rate);
+        let flooding = self.movement_debt > limits.max_movement_burst;
+
+        let exceeded = std::mem::take(&mut self.exceeded);
+        if exceeded == Exceeded::Nothing {
+            self.deferred_ticks = 0;
+        } else {
+            self.deferred_ticks = self.deferred_ticks.saturating_add(1);
+        }
+
+        let violation = flooding
+            || exceeded == Exceeded::Other
+            || self.deferred_ticks > limits.max_deferred_ticks;
+        if violation {
+            self.violations = self.violations.saturating_add(1);
+        } else if exceeded == Exceeded::Nothing {
+            self.violations = self.violations.saturating_sub(1);
+        }
+
+        if self.violations > limits.max_violations {
+            BudgetOutcome::Kick
+        } else if exceeded == Exceeded::Nothing {
+            BudgetOutcome::WithinBudget
+        } else {
+            BudgetOutcome::Deferred
+        }
+    }
+}
+
+/// Whether the play packet with this ID only updates the position or rotation of the player
+const fn is_movement(id: i32) -> bool {
+    matches!(
+        id,
+        FullC2s::ID
+            | PositionAndOnGroundC2s::ID
+            | LookAndOnGroundC2s::ID
+            | OnGroundOnlyC2s::ID
+            | VehicleMoveC2s::ID
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use itertools::Either;
+    use valence_protocol::packets::play::ChatMessageC2s;
+
+    use super::*;
+
+    fn frame(id: i32, len: usize) -> BorrowedPacketFrame {
+        BorrowedPacketFrame {
+            id,
+            body: Either::Left(Bytes::from(vec![0; len])),
+        }
+    }
+
+    /// Decodes packets from `queue` for one tick, returning the number of decoded packets
+    fn run_tick(
+        budget: &mut IngressBudget,
+        limits: &IngressLimits,
+        queue: &mut Vec<BorrowedPacketFrame>,
+    ) -> (usize, BudgetOutcome) {
+        let mut decoded = 0;
+        while budget
+            .next_frame(limits, || (!queue.is_empty()).then(|| queue.remove(0)))
+            .is_some()
+        {
+            decoded += 1;
+        }
+        (decoded, budget.end_tick(limits))
+    }
+
+    #[test]
+    fn movement_floods_are_deferred() {
+        let limits = IngressLimits {
+            max_packets_per_tick: 4,
+            max_violations: 1,
+            ..IngressLimits::default()
+        };
+        let mut budget = IngressBudget::default();
+        let mut queue: Vec<_> = (0..10)
+            .map(|_| frame(PositionAndOnGroundC2s::ID, 25))
+            .collect();
+
+        assert_eq!(
+            run_tick(&mut budget, &limits, &mut queue),
+            (4, BudgetOutcome::Deferred)
+        );
+        assert_eq!(
+            run_tick(&mut budget, &limits, &mut queue),
+            (4, BudgetOutcome::Deferred)
+        );
+        assert_eq!(
+            run_tick(&mut budget, &limits, &mut queue),
+            (2, BudgetOutcome::WithinBudget)
+        );
+        assert_eq!(budget.violations(), 0);
+    }
+
+    #[test]
+    fn sustained_movement_floods_are_kicked() {
+        let limits = IngressLimits {
+            max_packets_per_tick: 4,
+            max_violations: 2,
+            movement_rate: 1,
+            max_movement_burst: 10,
+            max_deferred_ticks: u32::MAX,
+            ..IngressLimits::default()
+        };
+        let mut budget = IngressBudget::default();
+        let mut queue: Vec<_> = (0..100)
+            .map(|_| frame(PositionAndOnGroundC2s::ID, 25))
+            .collect();
+
+        // Each tick adds 3 packets above the rate, so the burst is used up after 4 ticks
+        for _ in 0..3 {
+            assert_eq!(
+                run_tick(&mut budget, &limits, &mut queue),
+                (4, BudgetOutcome::Deferred)
+            );
+        }
+        assert_eq!(budget.violations(), 0);
+
+        for violations in 1..=2 {
+            assert_eq!(
+                run_tick(&mut budget, &limits, &mut queue),
+                (4, BudgetOutcome::Deferred)
+            );
+            assert_eq!(budget.violations(), violations);
+        }
+        assert_eq!(
+            run_tick(&mut budget, &limits, &mut queue),
+            (4, BudgetOutcome::Kick)
+        );
+    }
+
+    #[test]
+    fn deferred_frames_are_charged_once() {
+        let limits = IngressLimits {
+            max_packets_per_tick: 2,
+            movement_rate: 0,
+            ..IngressLimits::default()
+        };
+        let mut budget = IngressBudget::default();
+        let mut queue = vec![
+            frame(PositionAndOnGroundC2s::ID, 25),
+            frame(ChatMessageC2s::ID, 50),
+        ];
+
+        let deferred = budget
+            .next_frame(&limits, || Some(queue.remove(0)))
+            .unwrap();
+        budget.defer(deferred);
+        assert_eq!(
+            (budget.packets, budget.bytes, budget.movement_debt),
+            (0, 0, 0)
+        );
+        assert_eq!(budget.end_tick(&limits), BudgetOutcome::Deferred);
+
+        assert_eq!(
+            run_tick(&mut budget, &limits, &mut queue),
+            (2, BudgetOutcome::WithinBudget)
+        );
+        assert_eq!(budget.movement_debt, 1);
+    }
+
+    #[test]
+    fn chat_floods_are_kicked() {
+        let limits = IngressLimits {
+            max_packets_per_tick: 4,
+            max_violations: 2,
+            ..IngressLimits::default()
+        };
+        let mut budget = IngressBudget::default();
+        let mut queue: Vec<_> = (0..100).map(|_| frame(ChatMessageC2s::ID, 50)).collect();
+
+        assert_eq!(
+            run_tick(&mut budget, &limits, &mut queue),
+            (4, BudgetOutcome::Deferred)
+        );
+        assert_eq!(
+            run_tick(&mut budget, &limits, &mut queue),
+            (4, BudgetOutcome::Deferred)
+        );
+        assert_eq!(
+            run_tick(&mut budget, &limits, &mut queue),
+            (4, BudgetOutcome::Kick)
+        );
+    }
+
+    #[test]
+    fn chat_behind_movement_is_a_violation() {
+        let limits = IngressLimits {
+            max_packets_per_tick: 2,
+            ..IngressLimits::default()
+        };
+        let mut budget = IngressBudget::default();
+
+        budget.defer(frame(PositionAndOnGroundC2s::ID, 25));
+        budget.exceed(ChatMessageC2s::ID);
+        budget.exceed(PositionAndOnGroundC2s::ID);
+
+        assert_eq!(budget.end_tick(&limits), BudgetOutcome::Deferred);
+        assert_eq!(budget.violations(), 1);
+    }
+
+    #[test]
+    fn endless_deferral_is_kicked() {
+        let limits = IngressLimits {
+            max_packets_per_tick: 4,
+            max_violations: 2,
+            max_deferred_ticks: 3,
+            ..IngressLimits::default()
+        };
+        let mut budget = IngressBudget::default();
+        let mut queue: Vec<_> = (0..100)
+            .map(|_| frame(PositionAndOnGroundC2s::ID, 25))
+            .collect();
+
+        for _ in 0..5 {
+            assert_eq!(
+                run_tick(&mut budget, &limits, &mut queue),
+                (4, BudgetOutcome::Deferred)
+            );
+        }
+        assert_eq!(budget.violations(), 2);
+        assert_eq!(
+            run_tick(&mut budget, &limits, &mut queue),
+            (4, BudgetOutcome::Kick)
+        );
+    }
+
+    #[test]
+    fn byte_budget_allows_first_packet() {
+        let limits = IngressLimits {
+            max_bytes_per_tick: 100,
+            ..IngressLimits::default()
+        };
+        let mut budget = IngressBudget::default();
+        let mut queue = vec![
+            frame(ChatMessageC2s::ID, 1000),
+            frame(ChatMessageC2s::ID, 10),
+        ];
+
+        assert_eq!(
+            run_tick(&mut budget, &limits, &mut queue),
+            (1, BudgetOutcome::Deferred)
+        );
+        assert_eq!(budget.violations(), 1);
+        assert_eq!(
+            run_tick(&mut budget, &limits, &mut queue),
+            (1, BudgetOutcome::WithinBudget)
+        );
+        assert_eq!(budget.violations(), 0);
+    }
+}
@@ -1,26 +1,30 @@
 use std::borrow::Cow;
 
+use anyhow::Context;
 use bevy_app::{App, FixedUpdate, Plugin};
 use bevy_ecs::{
+    component::Component,
+    entity::Entity,
     lifecycle::Remove,
     message::MessageReader,
     name::Name,
     observer::On,
+    query::With,
     resource::Resource,
     schedule::IntoScheduleConfigs,
-    system::{Commands, Query, Res},
+    system::{Commands, Query, Res, SystemParam},
     world::World,
 };
 use colored::Colorize;
-use hyperion_utils::EntityExt;
+use hyperion_utils::{Args, Translations, localization::DEFAULT_LANGUAGE};
 use serde_json::json;
-use sha2::Digest;
 use tracing::{error, info, warn};
+use valence_bytes::CowBytes;
 use valence_protocol::{
-    VarInt,
+    Bounded, RawBytes, VarInt, ident,
     packets::{
         handshaking::handshake_c2s::HandshakeNextState,
-        login::{LoginCompressionS2c, LoginSuccessS2c},
+        login::{LoginCompressionS2c, LoginHelloS2c, LoginQueryRequestS2c, LoginSuccessS2c},
         play::{EntitiesDestroyS2c, PlayerRemoveS2c},
         status::{QueryPongS2c, QueryResponseS2c},
     },
@@ -29,28 +33,52 @@ use valence_protocol::{
 use {bevy_ecs::reflect::ReflectResource, bevy_reflect::Reflect};
 
 use crate::{
-    InitializePlayerPosition,
+    PlayerCount,
     command_channel::CommandChannel,
     egress::sync_chunks::ChunkSendQueue,
-    net::{Compose, MINECRAFT_VERSION, PROTOCOL_VERSION, PacketDecoder},
+    ingress::{
+        auth::{AuthMode, AwaitingEncryption, SessionKey, offline_uuid, server_hash},
+        forwarding::{AwaitingForwarding, ForwardedPlayer, Forwarding, ForwardingError},
+        virtual_host::{HandshakeInfo, HostAction, JoinTarget, VirtualHosts, normalize_hostname},
+    },
+    net::{
+        Compose, ConnectionId, MINECRAFT_VERSION, PROTOCOL_VERSION, PacketDecoder, SendResultExt,
+    },
     runtime::AsyncRuntime,
     simulation::{
         AiTargetable, ChunkPosition, ImmuneStatus, Pitch, Player, Uuid, Velocity, Xp, Yaw,
-        animation::ActiveAnimation, entity_kind::EntityKind, packet, packet_state,
+        animation::ActiveAnimation,
+        entity_kind::EntityKind,
+        join,
+        loading::{AwaitingWorld, EarlyJoins, MapLoadProgress, WorldReady},
+        minecraft_id::MinecraftIdRegistry,
+        packet, packet_state, session,
         skin::PlayerSkin,
+        world::WorldId,
     },
     storage::SkinHandler,
     util::mojang::MojangClient,
 };
 
+pub mod auth;
+pub mod counters;
 pub mod decode;
+pub mod forwarding;
+pub mod limits;
+mod movement;
+pub mod pending;
+pub mod virtual_host;
 
 pub fn process_handshake(
     mut packets: MessageReader<'_, '_, packet::handshake::Handshake>,
+    forwarding: Res<'_, Forwarding>,
+    virtual_hosts: Res<'_, VirtualHosts>,
+    compose: Res<'_, Compose>,
     mut commands: Commands<'_, '_>,
 ) {
     for packet in packets.read() {
         let mut entity = commands.entity(packet.sender());
+        let hostname = normalize_hostname(&packet.server_address);
 
         entity.remove::<packet_state::Handshake>();
         match packet.next_state {
@@ -58,9 +86,41 @@ pub fn process_handshake(
                 entity.insert(packet_state::Status);
             }
             HandshakeNextState::Login => {
+                match virtual_hosts.route(&hostname) {
+                    HostAction::Allow => {}
+                    HostAction::Reject { message } => {
+                        // The connection is left without a packet state, so its login is ignored
+                        info!(
+                            "rejecting {:?} connecting to {hostname:?}",
+                            packet.connection_id()
+                        );
+                        decode::disconnect::login(&compose, packet.connection_id(), message);
+                        continue;
+                    }
+                    HostAction::Target { target } => {
+                        entity.insert(JoinTarget(target.clone()));
+                    }
+                }
+
                 entity.insert(packet_state::Login);
+
+                if *forwarding == Forwarding::BungeeCord {
+                    // Connections without player info are rejected once they send their login
+                    match ForwardedPlayer::from_bungeecord(&packet.server_address) {
+                        Ok(player) => {
+                            entity.insert(player);
+                        }
+                        Err(e) => warn!("invalid BungeeCord forwarding: {e}"),
+                    }
+                }
             }
         }
+
+        entity.insert(HandshakeInfo {
+            hostname,
+            port: packet.server_port,
+            protocol_version: packet.protocol_version.0,
+        });
     }
 }
 
@@ -86,6 +146,7 @@ impl Default for ServerPingResponse {
 fn process_status_request(
     mut packets: MessageReader<'_, '_, packet::status::QueryRequest>,
     ping_response_data: Res<'_, ServerPingResponse>,
+    player_count: Res<'_, PlayerCount>,
     compose: Res<'_, Compose>,
 ) {
     for packet in packets.read() {
@@ -94,10 +155,7 @@ fn process_status_request(
         // let favicon = general_purpose::STANDARD.encode(img_bytes);
         // let favicon = format!("data:image/png;base64,{favicon}");
 
-        let online = compose
-            .global()
-            .player_count
-            .load(std::sync::atomic::Ordering::Relaxed);
+        let online = player_count.get();
 
         // https://wiki.vg/Server_List_Ping#Response
         let json = json!({
@@ -123,7 +181,7 @@ fn process_status_request(
         info!("sent query response: {packet:?}");
         compose
             .unicast_no_compression(&send, packet.connection_id())
-            .unwrap();
+            .unwrap_or_disconnected();
     }
 }
 
@@ -137,55 +195,99 @@ fn process_status_ping(
         info!("sent ping response: {send:?}");
         compose
             .unicast_no_compression(&send, packet.connection_id())
-            .unwrap();
+            .unwrap_or_disconnected();
     }
 }
-pub fn process_login_hello(
-    mut packets: MessageReader<'_, '_, packet::login::LoginHello>,
-    compose: Res<'_, Compose>,
-    runtime: Res<'_, AsyncRuntime>,
-    skins_collection: Res<'_, SkinHandler>,
-    mojang: Res<'_, MojangClient>,
-    command_channel: Res<'_, CommandChannel>,
-    mut commands: Commands<'_, '_>,
-    mut query: Query<'_, '_, &mut PacketDecoder>,
-) {
-    for packet in packets.read() {
-        let sender = packet.sender();
-        let mut decoder = query
+/// Everything needed to move a connection from the login state into the game
+#[derive(SystemParam)]
+pub struct LoginContext<'w, 's> {
+    compose: Res<'w, Compose>,
+    runtime: Res<'w, AsyncRuntime>,
+    skins: Res<'w, SkinHandler>,
+    mojang: Res<'w, MojangClient>,
+    command_channel: Res<'w, CommandChannel>,
+    translations: Res<'w, Translations>,
+    map_progress: Option<Res<'w, MapLoadProgress>>,
+    early_joins: Res<'w, EarlyJoins>,
+    commands: Commands<'w, 's>,
+    decoders: Query<'w, 's, &'static mut PacketDecoder>,
+}
+
+impl LoginContext<'_, '_> {
+    /// Disconnects a connection during login with the message `key`. Clients only send their
+    /// language after logging in, so the message is in [`DEFAULT_LANGUAGE`].
+    fn reject(&self, connection_id: ConnectionId, key: &str) {
+        let reason = self
+            .translations
+            .translate(DEFAULT_LANGUAGE, key, &Args::new());
+        decode::disconnect::login(&self.compose, connection_id, &reason);
+    }
+
+    /// Completes the login and spawns the player. If `skin` is [`None`], the skin of `uuid` is
+    /// fetched from Mojang.
+    ///
+    /// Before the world is ready, the player is handled according to [`EarlyJoins`] instead.
+    fn finish(
+        &mut self,
+        sender: Entity,
+        connection_id: ConnectionId,
+        uuid: uuid::Uuid,
+        username: &str,
+        skin: Option<PlayerSkin>,
+    ) {
+        if self
+            .map_progress
+            .as_ref()
+            .is_some_and(|progress| !progress.is_ready())
+        {
+            match *self.early_joins {
+                EarlyJoins::Disconnect => self.reject(connection_id, "login.still_loading"),
+                EarlyJoins::Hold => {
+                    info!("holding login of {username} until the world is ready");
+                    self.commands.entity(sender).insert(AwaitingWorld {
+                        connection_id,
+                        uuid,
+                        username: username.to_owned(),
+                        skin,
+                    });
+                }
+            }
+            return;
+        }
+
+        let mut decoder = self
+            .decoders
             .get_mut(sender)
             .expect("PacketDecoder must be available for player");
 
-        let username = &packet.username;
-        let profile_id = packet.profile_id;
-
         // Set compression
-        let global = compose.global();
+        let shared = self.compose.shared();
         let pkt = LoginCompressionS2c {
-            threshold: VarInt(global.shared.compression_threshold.0),
+            threshold: VarInt(shared.compression_threshold.0),
         };
-        compose
-            .unicast_no_compression(&pkt, packet.connection_id())
-            .unwrap();
-        decoder.set_compression(global.shared.compression_threshold);
+        self.compose
+            .unicast_no_compression(&pkt, connection_id)
+            .unwrap_or_disconnected();
+        decoder.set_compression(shared.compression_threshold);
 
-        let uuid = profile_id.unwrap_or_else(|| offline_uuid(username));
         let uuid_s = format!("{uuid:?}").dimmed();
         info!("Starting login: {sender:?} {username} {uuid_s}");
 
         let pkt = LoginSuccessS2c {
             uuid,
-            username: username.clone(),
+            username: Bounded(username.into()),
             properties: Cow::default(),
         };
 
-        compose.unicast(&pkt, packet.connection_id()).unwrap();
+        self.compose
+            .unicast(&pkt, connection_id)
+            .unwrap_or_disconnected();
 
-        let skin = if profile_id.is_some() {
-            let mojang = mojang.as_ref().clone();
-            let skins_collection = skins_collection.as_ref().clone();
-            let command_channel = command_channel.as_ref().clone();
-            runtime.spawn(async move {
+        let skin = if skin.is_none() {
+            let mojang = self.mojang.as_ref().clone();
+            let skins_collection = self.skins.as_ref().clone();
+            let command_channel = self.command_channel.as_ref().clone();
+            self.runtime.spawn(async move {
                 let skin = match PlayerSkin::from_uuid(uuid, &mojang, &skins_collection).await {
                     Ok(Some(skin)) => skin,
                     Err(e) => {
@@ -198,25 +300,31 @@ pub fn process_login_hello(
                     }
                 };
 
-                command_channel.push(move |world: &mut World| {
-                    let Ok(mut entity) = world.get_entity_mut(sender) else {
-                        warn!(
-                            "failed to get entity after skin has been fetched (likely because the \
-                             player has already left the server)"
-                        );
-                        return;
-                    };
-
-                    entity.insert(skin);
-                });
+                // The player joins without a skin if this is dropped
+                command_channel
+                    .send_priority(move |world: &mut World| {
+                        let Ok(mut entity) = world.get_entity_mut(sender) else {
+                            warn!(
+                                "failed to get entity after skin has been fetched (likely because \
+                                 the player has already left the server)"
+                            );
+                            return;
+                        };
+
+                        entity.insert(skin);
+                    })
+                    .await;
             });
             None
         } else {
-            Some(PlayerSkin::EMPTY)
+            skin
         };
 
-        let username = username.to_string();
-        commands.queue(move |world: &mut World| {
+        let username = username.to_owned();
+        self.commands.queue(move |world: &mut World| {
+            // A player who rejoins before their previous connection is gone replaces it
+            session::start_session(world, sender, uuid);
+
             let mut entity = world.entity_mut(sender);
 
             // TODO: The more specific components (such as ChunkSendQueue) should be added in a
@@ -228,16 +336,17 @@ pub fn process_login_hello(
                 AiTargetable,
                 ImmuneStatus::default(),
                 Uuid(uuid),
-                ChunkPosition::null(),
+                ChunkPosition::default(),
                 ChunkSendQueue::default(),
                 Yaw::default(),
                 Pitch::default(),
                 Velocity::default(),
                 Xp::default(),
                 EntityKind::Player,
+                WorldId::PRIMARY,
             ));
 
-            world.trigger(InitializePlayerPosition(sender));
+            join::prepare_player(world, sender);
 
             if let Some(skin) = skin {
                 let mut entity = world.entity_mut(sender);
@@ -247,20 +356,257 @@ pub fn process_login_hello(
     }
 }
 
-/// Get a [`uuid::Uuid`] based on the given user's name.
-fn offline_uuid(username: &str) -> uuid::Uuid {
-    let digest = sha2::Sha256::digest(username);
-    let digest: [u8; 32] = digest.into();
+pub fn process_login_hello(
+    mut packets: MessageReader<'_, '_, packet::login::LoginHello>,
+    forwarding: Res<'_, Forwarding>,
+    auth_mode: Res<'_, AuthMode>,
+    session_key: Res<'_, SessionKey>,
+    forwarded: Query<'_, '_, &ForwardedPlayer>,
+    mut login: LoginContext<'_, '_>,
+) {
+    for packet in packets.read() {
+        let sender = packet.sender();
+        let connection_id = packet.connection_id();
+        let username = &packet.username;
+
+        match &*forwarding {
+            Forwarding::None => match *auth_mode {
+                // The profile ID sent by the client is not trusted, the session server decides
+                // which account the player owns
+                AuthMode::Online => {
+                    let mut verify_token = [0; 4];
+                    openssl::rand::rand_bytes(&mut verify_token)
+                        .expect("failed to generate a verify token");
+
+                    let pkt = LoginHelloS2c {
+                        server_id: Bounded("".into()),
+                        public_key: session_key.public_key().into(),
+                        verify_token: verify_token.as_slice().into(),
+                    };
+                    login
+                        .compose
+                        .unicast_no_compression(&pkt, connection_id)
+                        .unwrap_or_disconnected();
+                    login.commands.entity(sender).insert(AwaitingEncryption {
+                        username: username.to_string(),
+                        verify_token,
+                    });
+                }
+                AuthMode::Offline => {
+                    let uuid = offline_uuid(username);
+                    login.finish(
+                        sender,
+                        connection_id,
+                        uuid,
+                        username,
+                        Some(PlayerSkin::EMPTY),
+                    );
+                }
+            },
+            Forwarding::BungeeCord => {
+                let Ok(player) = forwarded.get(sender) else {
+                    login.reject(connection_id, "login.proxy_only");
+                    continue;
+                };
 
-    // UUid expects 16 bytes
-    // todo: I have no idea which way we should go (be or le)
-    uuid::Uuid::from_slice(&digest[0..16]).unwrap()
+                let player = ForwardedPlayer {
+                    username: username.to_string(),
+                    ..player.clone()
+                };
+                let skin = Some(player.skin.clone().unwrap_or(PlayerSkin::EMPTY));
+                login.finish(sender, connection_id, player.uuid, username, skin);
+                login.commands.entity(sender).insert(player);
+            }
+            Forwarding::Velocity { .. } => {
+                let pkt = LoginQueryRequestS2c {
+                    message_id: VarInt(forwarding::VELOCITY_MESSAGE_ID),
+                    channel: ident!("velocity:player_info"),
+                    data: RawBytes::from(CowBytes::Borrowed(&[
+                        forwarding::VELOCITY_FORWARDING_VERSION,
+                    ]))
+                    .into(),
+                };
+                login
+                    .compose
+                    .unicast_no_compression(&pkt, connection_id)
+                    .unwrap_or_disconnected();
+                login.commands.entity(sender).insert(AwaitingForwarding);
+            }
+        }
+    }
+}
+
+fn process_velocity_forwarding(
+    mut packets: MessageReader<'_, '_, packet::login::LoginQueryResponse>,
+    forwarding: Res<'_, Forwarding>,
+    awaiting: Query<'_, '_, (), With<AwaitingForwarding>>,
+    mut login: LoginContext<'_, '_>,
+) {
+    let Forwarding::Velocity { secret } = &*forwarding else {
+        return;
+    };
+
+    for packet in packets.read() {
+        let sender = packet.sender();
+        let connection_id = packet.connection_id();
+        if packet.message_id.0 != forwarding::VELOCITY_MESSAGE_ID || !awaiting.contains(sender) {
+            continue;
+        }
+
+        login.commands.entity(sender).remove::<AwaitingForwarding>();
+
+        let result = match &packet.data {
+            Some(data) => ForwardedPlayer::from_velocity(secret.as_bytes(), data),
+            None => Err(ForwardingError::Missing),
+        };
+
+        let player = match result {
+            Ok(player) => player,
+            Err(e) => {
+                warn!("rejecting {connection_id:?}: {e}");
+                login.reject(connection_id, "login.verification_failed");
+                continue;
+            }
+        };
+
+        let skin = Some(player.skin.clone().unwrap_or(PlayerSkin::EMPTY));
+        login.finish(sender, connection_id, player.uuid, &player.username, skin);
+        login.commands.entity(sender).insert(player);
+    }
+}
+
+/// The answer of the Mojang session server for a connection in [`AuthMode::Online`]
+#[derive(Component, Debug)]
+enum SessionCheck {
+    Verified { uuid: uuid::Uuid, username: String },
+    Failed,
+}
+
+impl SessionCheck {
+    fn from_profile(profile: &serde_json::Value) -> anyhow::Result<Self> {
+        let uuid = profile
+            .get("id")
+            .and_then(serde_json::Value::as_str)
+            .context("no id in profile")?;
+        let username = profile
+            .get("name")
+            .and_then(serde_json::Value::as_str)
+            .context("no name in profile")?;
+
+        Ok(Self::Verified {
+            uuid: uuid::Uuid::parse_str(uuid)?,
+            username: username.to_owned(),
+        })
+    }
+}
+
+/// Enables encryption for online-mode connections which answered the encryption request, and asks
+/// the session server whether they own the account they log in with
+fn process_login_key(
+    mut packets: MessageReader<'_, '_, packet::login::LoginKey>,
+    session_key: Res<'_, SessionKey>,
+    awaiting: Query<'_, '_, &AwaitingEncryption>,
+    mut login: LoginContext<'_, '_>,
+) {
+    for packet in packets.read() {
+        let sender = packet.sender();
+        let connection_id = packet.connection_id();
+        let Ok(awaiting) = awaiting.get(sender) else {
+            continue;
+        };
+
+        login.commands.entity(sender).remove::<AwaitingEncryption>();
+
+        let shared_secret = match session_key.decrypt_response(
+            &packet.shared_secret,
+            &packet.verify_token,
+            &awaiting.verify_token,
+        ) {
+            Ok(shared_secret) => shared_secret,
+            Err(e) => {
+                warn!("rejecting {connection_id:?}: {e}");
+                login.reject(connection_id, "login.verification_failed");
+                continue;
+            }
+        };
+
+        // The client encrypts everything after its response, so the proxy has to as well
+        login
+            .compose
+            .io_buf()
+            .set_encryption(connection_id, shared_secret);
+
+        let server_hash = server_hash(&shared_secret, session_key.public_key());
+        let username = awaiting.username.clone();
+        let mojang = login.mojang.as_ref().clone();
+        let command_channel = login.command_channel.as_ref().clone();
+        login.runtime.spawn(async move {
+            let check = match mojang.has_joined(&username, &server_hash).await {
+                Ok(Some(profile)) => SessionCheck::from_profile(&profile).unwrap_or_else(|e| {
+                    error!("invalid session profile of {username}: {e}");
+                    SessionCheck::Failed
+                }),
+                Ok(None) => {
+                    warn!("{username} has not joined through the session server");
+                    SessionCheck::Failed
+                }
+                Err(e) => {
+                    error!("failed to verify the session of {username}: {e}");
+                    SessionCheck::Failed
+                }
+            };
+
+            // The login is stuck until the check arrives, so it must not be dropped
+            command_channel
+                .send_priority(move |world: &mut World| {
+                    if let Ok(mut entity) = world.get_entity_mut(sender) {
+                        entity.insert(check);
+                    }
+                })
+                .await;
+        });
+    }
+}
+
+/// Finishes the logins that the session server answered for
+fn finish_session_checks(
+    checks: Query<'_, '_, (Entity, &ConnectionId, &SessionCheck), With<packet_state::Login>>,
+    mut login: LoginContext<'_, '_>,
+) {
+    for (sender, &connection_id, check) in &checks {
+        login.commands.entity(sender).remove::<SessionCheck>();
+        match check {
+            SessionCheck::Verified { uuid, username } => {
+                login.finish(sender, connection_id, *uuid, username, None);
+            }
+            SessionCheck::Failed => login.reject(connection_id, "login.not_authenticated"),
+        }
+    }
+}
+
+/// Finishes the logins held by [`EarlyJoins::Hold`]
+fn finish_held_logins(
+    _: On<'_, '_, WorldReady>,
+    held: Query<'_, '_, (Entity, &AwaitingWorld)>,
+    mut login: LoginContext<'_, '_>,
+) {
+    for (sender, awaiting) in &held {
+        login.commands.entity(sender).remove::<AwaitingWorld>();
+        login.finish(
+            sender,
+            awaiting.connection_id,
+            awaiting.uuid,
+            &awaiting.username,
+            awaiting.skin.clone(),
+        );
+    }
 }
 
 fn remove_player_from_visibility(
     not_playing: On<'_, '_, Remove, packet_state::Play>,
     query: Query<'_, '_, &Uuid>,
     compose: Res<'_, Compose>,
+    ids: Res<'_, MinecraftIdRegistry>,
 ) {
     let uuid = match query.get(not_playing.entity) {
         Ok(uuid) => uuid,
@@ -271,14 +617,14 @@ fn remove_player_from_visibility(
     };
 
     let uuids = &[uuid.0];
-    let entity_ids = [VarInt(not_playing.entity.minecraft_id())];
+    let entity_ids = [VarInt(ids.minecraft_id(not_playing.entity))];
 
     // destroy
     let pkt = EntitiesDestroyS2c {
         entity_ids: Cow::Borrowed(&entity_ids),
     };
 
-    if let Err(e) = compose.broadcast(&pkt).send() {
+    if let Err(e) = compose.broadcast(&pkt).batched().send() {
         error!("failed to send player remove packet: {e}");
         return;
     }
@@ -287,7 +633,7 @@ fn remove_player_from_visibility(
         uuids: Cow::Borrowed(uuids),
     };
 
-    if let Err(e) = compose.broadcast(&pkt).send() {
+    if let Err(e) = compose.broadcast(&pkt).batched().send() {
         error!("failed to send player remove packet: {e}");
     }
 }
@@ -296,16 +642,28 @@ pub struct IngressPlugin;
 
 impl Plugin for IngressPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins(decode::DecodePlugin);
+        app.add_plugins((decode::DecodePlugin, pending::PendingConnectionsPlugin));
         app.add_systems(
             FixedUpdate,
             (
                 process_handshake.after(decode::handshake),
                 (process_status_request, process_status_ping).after(decode::status),
-                process_login_hello.after(decode::login),
+                (
+                    process_login_hello,
+                    process_velocity_forwarding,
+                    process_login_key,
+                    finish_session_checks,
+                )
+                    .after(decode::login),
             ),
         );
         app.add_observer(remove_player_from_visibility);
+        app.add_observer(finish_held_logins);
         app.init_resource::<ServerPingResponse>();
+        app.init_resource::<EarlyJoins>();
+        app.init_resource::<AuthMode>();
+        app.insert_resource(SessionKey::generate().expect("failed to generate the session key"));
+        app.init_resource::<Forwarding>();
+        app.init_resource::<VirtualHosts>();
     }
 }
@@ -0,0 +1,241 @@
+//! Coalescing bursts of movement packets. See [`MovementBurst`].
+
+use glam::DVec3;
+use valence_protocol::{
+    Packet as _,
+    packets::play::{FullC2s, LookAndOnGroundC2s, OnGroundOnlyC2s, PositionAndOnGroundC2s},
+};
+
+use crate::{ingress::limits::IngressLimits, net::decoder::BorrowedPacketFrame};
+
+/// What the decoder should do with a packet
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Coalesce {
+    /// The packet was a movement packet and has been merged into the burst
+    Merged,
+    /// The packet is not a movement packet. The burst before it must be written first, so that
+    /// the packet is handled at the position it was sent at.
+    Barrier,
+    /// The packet is a movement packet, but enough movement packets have been written this tick
+    Defer,
+}
+
+/// A movement packet which consecutive movement packets have been merged into
+#[derive(Debug)]
+pub(crate) enum Movement {
+    Full(FullC2s),
+    Position(PositionAndOnGroundC2s),
+    Look(LookAndOnGroundC2s),
+    OnGround(OnGroundOnlyC2s),
+}
+
+/// Merges consecutive movement packets of a connection within a tick into a single packet with
+/// the final position, rotation and on ground state.
+///
+/// Clients on unstable connections may send dozens of movement packets at once, which would
+/// otherwise each be validated and handled. The landings the client reported while the packets
+/// were merged are kept, so that fall damage is not lost when the final position is above them.
+#[derive(Debug, Default)]
+pub(crate) struct MovementBurst {
+    position: Option<DVec3>,
+    look: Option<(f32, f32)>,
+    on_ground: bool,
+    /// Whether a movement packet has been merged since the last one was written
+    pending: bool,
+    /// The on ground state of the previous movement packet
+    was_on_ground: bool,
+    /// The number of movement packets written this tick
+    written: usize,
+    /// The number of movement packets merged into another one this tick
+    coalesced: u16,
+    /// The height the client landed at in the pending movement packet, if it did
+    pending_landing_y: Option<f32>,
+    /// The lowest height the client landed at in a movement packet which was merged into another
+    /// one this tick
+    landing_y: Option<f32>,
+}
+
+impl MovementBurst {
+    /// Starts a tick for a player who was on the ground at the end of the last tick if
+    /// `was_on_ground`
+    pub(crate) fn new(was_on_ground: bool) -> Self {
+        Self {
+            was_on_ground,
+            ..Self::default()
+        }
+    }
+
+    /// Decodes `frame` and merges it into the burst if it is a movement packet
+    pub(crate) fn push_frame(
+        &mut self,
+        frame: &BorrowedPacketFrame,
+        limits: &IngressLimits,
+    ) -> anyhow::Result<Coalesce> {
+        let (position, look, on_ground) = match frame.id {
+            FullC2s::ID => {
+                let packet = frame.decode::<FullC2s>()?;
+                (
+                    Some(packet.position),
+                    Some((packet.yaw, packet.pitch)),
+                    packet.on_ground,
+                )
+            }
+            PositionAndOnGroundC2s::ID => {
+                let packet = frame.decode::<PositionAndOnGroundC2s>()?;
+                (Some(packet.position), None, packet.on_ground)
+            }
+            LookAndOnGroundC2s::ID => {
+                let packet = frame.decode::<LookAndOnGroundC2s>()?;
+                (None, Some((packet.yaw, packet.pitch)), packet.on_ground)
+            }
+            OnGroundOnlyC2s::ID => (None, None, frame.decode::<OnGroundOnlyC2s>()?.on_ground),
+            _ => return Ok(Coalesce::Barrier),
+        };
+
+        if !self.pending && self.written >= limits.max_movement_packets_per_tick {
+            return Ok(Coalesce::Defer);
+        }
+
+        self.push(position, look, on_ground);
+        Ok(Coalesce::Merged)
+    }
+
+    fn push(&mut self, position: Option<DVec3>, look: Option<(f32, f32)>, on_ground: bool) {
+        if self.pending {
+            self.coalesced = self.coalesced.saturating_add(1);
+
+            // The landing is merged away, so it would not be seen when the packet is handled
+            if let Some(y) = self.pending_landing_y.take() {
+                self.landing_y = Some(self.landing_y.map_or(y, |landing_y| landing_y.min(y)));
+            }
+        }
+
+        self.position = position.or(self.position);
+        self.look = look.or(self.look);
+        self.on_ground = on_ground;
+        self.pending = true;
+
+        #[expect(clippy::cast_possible_truncation)]
+        if !self.was_on_ground
+            && on_ground
+            && let Some(position) = self.position
+        {
+            self.pending_landing_y = Some(position.y as f32);
+        }
+
+        self.was_on_ground = on_ground;
+    }
+
+    /// Returns the packet the movement packets since the last call were merged into, if any
+    pub(crate) fn take(&mut self) -> Option<Movement> {
+        if !self.pending {
+            return None;
+        }
+
+        self.pending = false;
+        self.pending_landing_y = None;
+        self.written += 1;
+
+        let on_ground = self.on_ground;
+        let movement = match (self.position.take(), self.look.take()) {
+            (Some(position), Some((yaw, pitch))) => Movement::Full(FullC2s {
+                position,
+                yaw,
+                pitch,
+                on_ground,
+            }),
+            (Some(position), None) => Movement::Position(PositionAndOnGroundC2s {
+                position,
+                on_ground,
+            }),
+            (None, Some((yaw, pitch))) => Movement::Look(LookAndOnGroundC2s {
+                yaw,
+                pitch,
+                on_ground,
+            }),
+            (None, None) => Movement::OnGround(OnGroundOnlyC2s { on_ground }),
+        };
+
+        Some(movement)
+    }
+
+    /// The number of movement packets merged into another one this tick
+    pub(crate) const fn coalesced(&self) -> u16 {
+        self.coalesced
+    }
+
+    /// The lowest height the client landed at in a movement packet which was merged into another
+    /// one this tick
+    pub(crate) const fn landing_y(&self) -> Option<f32> {
+        self.landing_y
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(y: f64) -> Option<DVec3> {
+        Some(DVec3::new(0.0, y, 0.0))
+    }
+
+    #[test]
+    fn consecutive_packets_are_merged() {
+        let mut burst = MovementBurst::new(true);
+        burst.push(position(64.0), None, true);
+        burst.push(None, Some((90.0, 10.0)), true);
+        burst.push(position(65.0), None, false);
+
+        let Some(Movement::Full(packet)) = burst.take() else {
+            panic!("the packets should be merged into a full movement packet");
+        };
+        assert_eq!(packet.position, DVec3::new(0.0, 65.0, 0.0));
+        assert!((packet.yaw - 90.0).abs() < f32::EPSILON);
+        assert!(!packet.on_ground);
+        assert!(burst.take().is_none());
+        assert_eq!(burst.coalesced(), 2);
+        assert_eq!(burst.landing_y(), None);
+    }
+
+    #[test]
+    fn lowest_landing_is_kept() {
+        let mut burst = MovementBurst::new(false);
+        burst.push(position(70.0), None, true);
+        burst.push(position(71.0), None, false);
+        burst.push(position(62.0), None, true);
+        burst.push(position(62.0), None, true);
+        burst.push(position(63.0), None, false);
+
+        assert_eq!(burst.landing_y(), Some(62.0));
+    }
+
+    #[test]
+    fn written_landings_are_not_recorded() {
+        let mut burst = MovementBurst::new(false);
+        burst.push(position(70.0), None, false);
+        burst.push(position(62.0), None, true);
+        assert!(burst.take().is_some());
+
+        assert_eq!(burst.landing_y(), None);
+    }
+
+    #[test]
+    fn written_packets_are_limited() {
+        let limits = IngressLimits {
+            max_movement_packets_per_tick: 1,
+            ..IngressLimits::default()
+        };
+        let mut burst = MovementBurst::new(true);
+        burst.push(position(64.0), None, true);
+        assert!(burst.take().is_some());
+
+        let mut frame = Vec::new();
+        valence_protocol::Encode::encode(&OnGroundOnlyC2s { on_ground: true }, &mut frame).unwrap();
+        let frame = BorrowedPacketFrame {
+            id: OnGroundOnlyC2s::ID,
+            body: itertools::Either::Left(bytes::Bytes::from(frame)),
+        };
+
+        assert_eq!(burst.push_frame(&frame, &limits).unwrap(), Coalesce::Defer);
+    }
+}
@@ -0,0 +1,370 @@
+//! Limits on connections which have not entered the play state yet.
+//!
+//! Clients can open a connection and idle in the handshake, status or login state without ever
+//! joining, which holds a connection and an entity on the server. Connections get a
+//! [`StateDeadline`] when they enter one of these states and are disconnected by the decode
+//! systems once it passes. The number of such connections per IP address is limited as well.
+//!
+//! Behind Velocity or BungeeCord, every connection comes from the IP address of that proxy, so
+//! connections are counted towards the client IP address it forwards instead.
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::atomic::Ordering,
+};
+
+use bevy_app::{App, Plugin};
+use bevy_ecs::{
+    component::Component,
+    lifecycle::{Add, Remove},
+    observer::On,
+    query::Without,
+    resource::Resource,
+    system::{Commands, Query, Res, ResMut},
+};
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+#[cfg(feature = "reflect")]
+use {
+    bevy_ecs::reflect::{ReflectComponent, ReflectResource},
+    bevy_reflect::Reflect,
+};
+
+use crate::{
+    TICKS_PER_SECOND, Tick,
+    ingress::forwarding::{ForwardedPlayer, Forwarding},
+    net::{Compose, ConnectionId, metrics::NetworkMetrics},
+    simulation::packet_state,
+};
+
+/// How long connections may stay in each state before entering the play state, and how many of
+/// these connections each IP address may have
+#[derive(Resource, Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Resource))]
+#[serde(default)]
+pub struct PendingConnectionLimits {
+    pub handshake_timeout_secs: u64,
+    pub status_timeout_secs: u64,
+    /// This includes the time taken to authenticate the player with Mojang
+    pub login_timeout_secs: u64,
+    /// The maximum number of connections from one IP address which have not entered the play
+    /// state. Connections whose proxy did not report their IP address are not limited.
+    ///
+    /// With [`Forwarding`] enabled, connections are only counted once the IP address of the client
+    /// is forwarded.
+    pub max_per_ip: u32,
+}
+
+impl Default for PendingConnectionLimits {
+    fn default() -> Self {
+        Self {
+            handshake_timeout_secs: 5,
+            status_timeout_secs: 5,
+            login_timeout_secs: 10,
+            max_per_ip: 8,
+        }
+    }
+}
+
+pub(crate) fn secs_to_ticks(secs: u64) -> i64 {
+    i64::try_from(secs)
+        .unwrap_or(i64::MAX)
+        .saturating_mul(TICKS_PER_SECOND)
+}
+
+/// The IP address a connection was made from, as reported by its proxy
+#[derive(Component, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+pub struct ClientIp(pub IpAddr);
+
+/// The tick at which a connection which has not entered the play state is disconnected, or
+/// [`None`] once it has been disconnected
+#[derive(Component, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+pub struct StateDeadline(pub Option<i64>);
+
+impl StateDeadline {
+    /// Returns whether the deadline passed at `tick`. This only returns true once.
+    pub(crate) fn expire(&mut self, tick: i64) -> bool {
+        if self.0.is_some_and(|deadline| tick >= deadline) {
+            self.0 = None;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Counts a connection towards the limit of its IP address until it enters the play state or
+/// disconnects
+#[derive(Component, Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct PendingIp(IpAddr);
+
+/// The number of connections of each IP address which have not entered the play state
+#[derive(Resource, Default, Debug)]
+pub(crate) struct PendingConnections(FxHashMap<IpAddr, u32>);
+
+impl PendingConnections {
+    /// Counts a new connection from `ip`, returning [`None`] if `ip` already has `max`
+    /// connections
+    pub(crate) fn try_add(&mut self, ip: IpAddr, max: u32) -> Option<PendingIp> {
+        let count = self.0.entry(ip).or_default();
+        if *count >= max {
+            return None;
+        }
+
+        *count += 1;
+        Some(PendingIp(ip))
+    }
+
+    fn remove(&mut self, ip: IpAddr) {
+        if let Some(count) = self.0.get_mut(&ip) {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                self.0.remove(&ip);
+            }
+        }
+    }
+}
+
+/// Returns whether connections should be counted towards the IP address forwarded by a proxy in
+/// front of hyperion-proxy instead of the address they connected from
+pub(crate) fn counts_forwarded_ip(forwarding: Option<&Forwarding>) -> bool {
+    forwarding.is_some_and(|forwarding| *forwarding != Forwarding::None)
+}
+
+/// Parses the address forwarded by Velocity or BungeeCord, which may include a port
+fn parse_forwarded_ip(address: &str) -> Option<IpAddr> {
+    address.parse::<IpAddr>().ok().or_else(|| {
+        address
+            .parse::<SocketAddr>()
+            .ok()
+            .map(|address| address.ip())
+    })
+}
+
+/// Counts a connection through a forwarding proxy towards the limit of the client's IP address,
+/// and closes it if that IP address already has too many pending connections
+fn limit_forwarded_ip(
+    forwarded: On<'_, '_, Add, ForwardedPlayer>,
+    query: Query<
+        '_,
+        '_,
+        (&ForwardedPlayer, &ConnectionId),
+        (Without<packet_state::Play>, Without<PendingIp>),
+    >,
+    limits: Res<'_, PendingConnectionLimits>,
+    mut pending: ResMut<'_, PendingConnections>,
+    compose: Res<'_, Compose>,
+    metrics: Option<Res<'_, NetworkMetrics>>,
+    mut commands: Commands<'_, '_>,
+) {
+    let Ok((player, &connection_id)) = query.get(forwarded.entity) else {
+        return;
+    };
+
+    let Some(ip) = parse_forwarded_ip(&player.address) else {
+        warn!(
+            "not limiting {}, whose forwarded address {} is invalid",
+            player.username, player.address
+        );
+        return;
+    };
+
+    let mut entity = commands.entity(forwarded.entity);
+    entity.try_insert(ClientIp(ip));
+
+    if let Some(pending_ip) = pending.try_add(ip, limits.max_per_ip) {
+        entity.try_insert(pending_ip);
+        return;
+    }
+
+    warn!("refusing connection from {ip}, which has too many pending connections");
+    if let Some(metrics) = metrics {
+        metrics.ip_limit_rejections.fetch_add(1, Ordering::Relaxed);
+    }
+    // Packets which were already received are dropped
+    entity.try_insert(packet_channel::Receiver::default());
+    compose.io_buf().shutdown(connection_id);
+}
+
+/// A packet state which connections may only stay in for a limited time
+trait TimedState: Component {
+    fn timeout_secs(limits: &PendingConnectionLimits) -> u64;
+}
+
+impl TimedState for packet_state::Handshake {
+    fn timeout_secs(limits: &PendingConnectionLimits) -> u64 {
+        limits.handshake_timeout_secs
+    }
+}
+
+impl TimedState for packet_state::Status {
+    fn timeout_secs(limits: &PendingConnectionLimits) -> u64 {
+        limits.status_timeout_secs
+    }
+}
+
+impl TimedState for packet_state::Login {
+    fn timeout_secs(limits: &PendingConnectionLimits) -> u64 {
+        limits.login_timeout_secs
+    }
+}
+
+fn start_deadline<S: TimedState>(
+    entered: On<'_, '_, Add, S>,
+    tick: Res<'_, Tick>,
+    limits: Res<'_, PendingConnectionLimits>,
+    mut commands: Commands<'_, '_>,
+) {
+    let deadline = tick
+        .0
+        .saturating_add(secs_to_ticks(S::timeout_secs(&limits)));
+    commands
+        .entity(entered.entity)
+        .try_insert(StateDeadline(Some(deadline)));
+}
+
+fn clear_deadline(playing: On<'_, '_, Add, packet_state::Play>, mut commands: Commands<'_, '_>) {
+    commands
+        .entity(playing.entity)
+        .try_remove::<(StateDeadline, PendingIp)>();
+}
+
+fn release_pending_ip(
+    released: On<'_, '_, Remove, PendingIp>,
+    query: Query<'_, '_, &PendingIp>,
+    mut pending: ResMut<'_, PendingConnections>,
+) {
+    if let Ok(&PendingIp(ip)) = query.get(released.entity) {
+        pending.remove(ip);
+    }
+}
+
+pub struct PendingConnectionsPlugin;
+
+impl Plugin for PendingConnectionsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Tick>();
+        app.init_resource::<PendingConnectionLimits>();
+        app.init_resource::<PendingConnections>();
+        app.add_observer(start_deadline::<packet_state::Handshake>);
+        app.add_observer(start_deadline::<packet_state::Status>);
+        app.add_observer(start_deadline::<packet_state::Login>);
+        app.add_observer(clear_deadline);
+        app.add_observer(release_pending_ip);
+        app.add_observer(limit_forwarded_ip);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::Ipv4Addr, sync::Arc};
+
+    use super::*;
+    use crate::net::{IoBuf, ProxyId, Shared};
+
+    #[test]
+    fn pending_connections_are_limited_per_ip() {
+        let mut app = App::new();
+        app.add_plugins(PendingConnectionsPlugin);
+        let world = app.world_mut();
+
+        let ip = IpAddr::from(Ipv4Addr::new(10, 0, 0, 1));
+        let other = IpAddr::from(Ipv4Addr::new(10, 0, 0, 2));
+        let connect = |world: &mut bevy_ecs::world::World, ip| {
+            let pending = world.resource_mut::<PendingConnections>().try_add(ip, 2)?;
+            Some(world.spawn((packet_state::Handshake, pending)).id())
+        };
+
+        let first = connect(world, ip).unwrap();
+        let second = connect(world, ip).unwrap();
+        assert!(connect(world, ip).is_none());
+        assert!(connect(world, other).is_some());
+
+        // Joining and disconnecting both free up a slot
+        world.entity_mut(first).insert(packet_state::Play);
+        world.flush();
+        assert_eq!(world.resource::<PendingConnections>().0.get(&ip), Some(&1));
+
+        world.despawn(second);
+        assert_eq!(world.resource::<PendingConnections>().0.get(&ip), None);
+        assert!(connect(world, ip).is_some());
+    }
+
+    #[test]
+    fn forwarded_connections_are_limited_per_forwarded_ip() {
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut io_buf = IoBuf::default();
+        io_buf.add_proxy(ProxyId::new(0), tx.into());
+
+        let mut app = App::new();
+        app.add_plugins(PendingConnectionsPlugin);
+        app.insert_resource(PendingConnectionLimits {
+            max_per_ip: 1,
+            ..PendingConnectionLimits::default()
+        });
+        app.insert_resource(Compose::new(
+            libdeflater::CompressionLvl::default(),
+            Arc::new(Shared {
+                compression_threshold: valence_protocol::CompressionThreshold(-1),
+                compression_level: libdeflater::CompressionLvl::default(),
+            }),
+            io_buf,
+        ));
+        let world = app.world_mut();
+
+        let connect = |world: &mut bevy_ecs::world::World, stream, address: &str| {
+            let player = ForwardedPlayer {
+                address: address.to_owned(),
+                uuid: uuid::Uuid::nil(),
+                username: format!("player{stream}"),
+                skin: None,
+            };
+            let connection_id = ConnectionId::new(stream, ProxyId::new(0));
+            let entity = world
+                .spawn((packet_state::Login, connection_id, player))
+                .id();
+            world.flush();
+            world.get::<PendingIp>(entity).is_some()
+        };
+
+        assert!(connect(world, 1, "10.0.0.1"));
+        assert!(!connect(world, 2, "10.0.0.1:25565"));
+        assert!(connect(world, 3, "10.0.0.2"));
+
+        let ip = IpAddr::from(Ipv4Addr::new(10, 0, 0, 1));
+        assert_eq!(world.resource::<PendingConnections>().0.get(&ip), Some(&1));
+    }
+
+    #[test]
+    fn deadlines_depend_on_the_state() {
+        let mut app = App::new();
+        app.add_plugins(PendingConnectionsPlugin);
+        let world = app.world_mut();
+        world.insert_resource(Tick(100));
+
+        let connection = world.spawn(packet_state::Handshake).id();
+        world.flush();
+        let mut deadline = *world.get::<StateDeadline>(connection).unwrap();
+        assert_eq!(deadline, StateDeadline(Some(200)));
+        assert!(!deadline.expire(199));
+        assert!(deadline.expire(200));
+        assert!(!deadline.expire(201));
+
+        world
+            .entity_mut(connection)
+            .remove::<packet_state::Handshake>()
+            .insert(packet_state::Login);
+        world.flush();
+        assert_eq!(
+            world.get::<StateDeadline>(connection),
+            Some(&StateDeadline(Some(300)))
+        );
+
+        world.entity_mut(connection).insert(packet_state::Play);
+        world.flush();
+        assert!(world.get::<StateDeadline>(connection).is_none());
+    }
+}
@@ -0,0 +1,152 @@
+//! Routing connections by the hostname the client connected with, such as to send players
+//! connecting to `build.example.com` and `play.example.com` to different worlds or to reject
+//! connections to the IP address of the server.
+
+use std::collections::HashMap;
+
+use bevy_ecs::{component::Component, resource::Resource};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "reflect")]
+use {
+    bevy_ecs::reflect::{ReflectComponent, ReflectResource},
+    bevy_reflect::Reflect,
+};
+
+/// The server address and port from the handshake of a connection. This is inserted when the
+/// handshake is received and kept for the rest of the connection.
+#[derive(Component, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+pub struct HandshakeInfo {
+    /// The hostname the client connected with, see [`normalize_hostname`]
+    pub hostname: String,
+    pub port: u16,
+    pub protocol_version: i32,
+}
+
+/// The target chosen for a player by [`HostAction::Target`], which game code may use to choose
+/// where the player spawns
+#[derive(Component, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+pub struct JoinTarget(pub String);
+
+/// What happens to players logging in through a hostname
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect))]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum HostAction {
+    /// The player may join
+    #[default]
+    Allow,
+    /// The player is disconnected with `message`
+    Reject { message: String },
+    /// The player may join and is given a [`JoinTarget`]
+    Target { target: String },
+}
+
+/// The [`HostAction`] of each hostname. The hostnames of connections are normalized with
+/// [`normalize_hostname`] before they are looked up.
+///
+/// ```toml
+/// [virtual_hosts]
+/// default = { action = "reject", message = "Please connect through play.example.com" }
+///
+/// [virtual_hosts.hosts]
+/// "play.example.com" = { action = "allow" }
+/// "build.example.com" = { action = "target", target = "build" }
+/// ```
+#[derive(Resource, Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Resource))]
+#[serde(default)]
+pub struct VirtualHosts {
+    /// The action for hostnames which are not in `hosts`
+    pub default: HostAction,
+    pub hosts: HashMap<String, HostAction>,
+}
+
+impl VirtualHosts {
+    /// Normalizes the hostnames in `hosts` with [`normalize_hostname`], so that they match the
+    /// hostnames of connections no matter how they are written in the config
+    #[must_use]
+    pub fn normalized(self) -> Self {
+        Self {
+            default: self.default,
+            hosts: self
+                .hosts
+                .into_iter()
+                .map(|(hostname, action)| (normalize_hostname(&hostname), action))
+                .collect(),
+        }
+    }
+
+    /// The action for players connecting with `hostname`, which must be normalized
+    #[must_use]
+    pub fn route(&self, hostname: &str) -> &HostAction {
+        self.hosts.get(hostname).unwrap_or(&self.default)
+    }
+}
+
+/// Normalizes the server address of a handshake to the hostname the client connected with.
+///
+/// Anything after a null byte is removed, which includes the marker some modded clients append,
+/// such as `\0FML2\0`, and the player info appended by BungeeCord. The hostname is lowercased and
+/// a trailing dot is removed.
+#[must_use]
+pub fn normalize_hostname(server_address: &str) -> String {
+    let hostname = server_address.split('\0').next().unwrap_or_default().trim();
+
+    hostname
+        .strip_suffix('.')
+        .unwrap_or(hostname)
+        .to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hostnames_are_normalized() {
+        assert_eq!(normalize_hostname("Play.Example.COM"), "play.example.com");
+        assert_eq!(normalize_hostname("play.example.com."), "play.example.com");
+        assert_eq!(
+            normalize_hostname("play.example.com\0FML\0"),
+            "play.example.com"
+        );
+        assert_eq!(
+            normalize_hostname("Play.Example.com.\0FML3\0"),
+            "play.example.com"
+        );
+        assert_eq!(
+            normalize_hostname("play.example.com\x00127.0.0.1\x00069a79f444e94726a5befca90e38aaf5"),
+            "play.example.com"
+        );
+        assert_eq!(normalize_hostname("127.0.0.1"), "127.0.0.1");
+    }
+
+    #[test]
+    fn unknown_hosts_use_the_default() {
+        let hosts: VirtualHosts = toml::from_str(
+            r#"
+            default = { action = "reject", message = "Use play.example.com" }
+
+            [hosts]
+            "Play.Example.com." = { action = "allow" }
+            "build.example.com" = { action = "target", target = "build" }
+            "#,
+        )
+        .unwrap();
+        let hosts = hosts.normalized();
+
+        assert_eq!(hosts.route("play.example.com"), &HostAction::Allow);
+        assert_eq!(hosts.route("build.example.com"), &HostAction::Target {
+            target: "build".to_owned()
+        });
+        assert_eq!(hosts.route("127.0.0.1"), &HostAction::Reject {
+            message: "Use play.example.com".to_owned()
+        });
+        assert_eq!(
+            VirtualHosts::default().route("anything"),
+            &HostAction::Allow
+        );
+    }
+}
@@ -16,6 +16,13 @@ use egress::EgressPlugin;
 #[cfg(unix)]
 use libc::{RLIMIT_NOFILE, getrlimit, setrlimit};
 use libdeflater::CompressionLvl;
+use rustls::{
+    RootCertStore, SupportedCipherSuite, SupportedProtocolVersion,
+    crypto::CryptoProvider,
+    server::{
+        NoServerSessionStorage, ServerConfig, ServerSessionMemoryCache, WebPkiClientVerifier,
+    },
+};
 use rustls_pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject};
 use storage::{LocalDb, SkinHandler};
 use tracing::{info, warn};
@@ -29,21 +36,25 @@ use {
 mod common;
 pub use common::*;
 use hyperion_crafting::CraftingRegistry;
-use hyperion_utils::HyperionUtilsPlugin;
+use hyperion_utils::{HyperionUtilsPlugin, Translations};
 
 use crate::{
+    activity::{ActivityPlugin, ServerActivity},
     command_channel::{CommandChannel, CommandChannelPlugin},
-    ingress::IngressPlugin,
+    ingress::{IngressPlugin, auth::AuthMode},
     net::{Compose, ConnectionId, IoBuf, MAX_PACKET_SIZE, PacketDecoder, proxy::init_proxy_comms},
     runtime::AsyncRuntime,
     simulation::{IgnMap, SimPlugin, StreamLookup, blocks::Blocks},
     spatial::SpatialPlugin,
+    timings::TimingsPlugin,
     util::mojang::{ApiProvider, MojangClient},
 };
 
 pub mod egress;
 pub mod ingress;
 pub mod net;
+#[cfg(feature = "replay")]
+pub mod replay;
 pub mod simulation;
 pub mod spatial;
 pub mod storage;
@@ -56,12 +67,22 @@ pub const CHUNK_HEIGHT_SPAN: u32 = 384; // 512; // usually 384
 
 pub trait PacketBundle {
     fn encode_including_ids(self, w: impl Write) -> anyhow::Result<()>;
+
+    /// The ID of the packet if this is a single packet of the play state. This is used to look up
+    /// whether the packet is flushed immediately, see [`IoBuf::set_flush_immediate`].
+    fn play_packet_id(&self) -> Option<i32> {
+        None
+    }
 }
 
 impl<T: Packet + Encode> PacketBundle for &T {
     fn encode_including_ids(self, w: impl Write) -> anyhow::Result<()> {
         self.encode_with_id(w)
     }
+
+    fn play_packet_id(&self) -> Option<i32> {
+        matches!(T::STATE, valence_protocol::PacketState::Play).then_some(T::ID)
+    }
 }
 
 /// on macOS, the soft limit for the number of open file descriptors is often 256. This is far too low
@@ -106,6 +127,14 @@ pub fn adjust_file_descriptor_limits(recommended_min: u64) -> std::io::Result<()
     Ok(())
 }
 
+/// The number of TLS sessions that are remembered so that a reconnecting proxy can resume its
+/// session instead of doing a full handshake. This is the same as the default of rustls.
+pub const DEFAULT_TLS_SESSION_CACHE_SIZE: usize = 256;
+
+/// The certificates and TLS settings used for connections from proxies.
+///
+/// The cipher suites and protocol versions default to those of the crypto provider, and can be
+/// restricted with [`Crypto::with_cipher_suites`] and [`Crypto::with_protocol_versions`].
 #[derive(Resource)]
 #[cfg_attr(feature = "reflect", derive(Reflect), reflect(opaque))]
 pub struct Crypto {
@@ -117,6 +146,10 @@ pub struct Crypto {
 
     /// The game server's private key
     pub key: PrivateKeyDer<'static>,
+
+    cipher_suites: Option<Vec<SupportedCipherSuite>>,
+    protocol_versions: Option<Vec<&'static SupportedProtocolVersion>>,
+    session_cache_size: usize,
 }
 
 impl Crypto {
@@ -125,11 +158,92 @@ impl Crypto {
         cert_path: &Path,
         key_path: &Path,
     ) -> Result<Self, rustls_pki_types::pem::Error> {
-        Ok(Self {
-            root_ca_cert: CertificateDer::from_pem_file(root_ca_cert_path)?,
-            cert: CertificateDer::from_pem_file(cert_path)?,
-            key: PrivateKeyDer::from_pem_file(key_path)?,
-        })
+        Ok(Self::from_der(
+            CertificateDer::from_pem_file(root_ca_cert_path)?,
+            CertificateDer::from_pem_file(cert_path)?,
+            PrivateKeyDer::from_pem_file(key_path)?,
+        ))
+    }
+
+    #[must_use]
+    pub const fn from_der(
+        root_ca_cert: CertificateDer<'static>,
+        cert: CertificateDer<'static>,
+        key: PrivateKeyDer<'static>,
+    ) -> Self {
+        Self {
+            root_ca_cert,
+            cert,
+            key,
+            cipher_suites: None,
+            protocol_versions: None,
+            session_cache_size: DEFAULT_TLS_SESSION_CACHE_SIZE,
+        }
+    }
+
+    /// Only allow these cipher suites, in order of preference
+    #[must_use]
+    pub fn with_cipher_suites(mut self, cipher_suites: &[SupportedCipherSuite]) -> Self {
+        self.cipher_suites = Some(cipher_suites.to_vec());
+        self
+    }
+
+    /// Only allow these protocol versions, such as [`rustls::version::TLS13`]
+    #[must_use]
+    pub fn with_protocol_versions(
+        mut self,
+        protocol_versions: &[&'static SupportedProtocolVersion],
+    ) -> Self {
+        self.protocol_versions = Some(protocol_versions.to_vec());
+        self
+    }
+
+    /// Remember up to `size` sessions for resumption. A size of 0 disables session resumption.
+    /// This defaults to [`DEFAULT_TLS_SESSION_CACHE_SIZE`].
+    #[must_use]
+    pub const fn with_session_cache_size(mut self, size: usize) -> Self {
+        self.session_cache_size = size;
+        self
+    }
+
+    /// Creates the TLS configuration for accepting proxies, which must present a certificate
+    /// signed by the root certificate authority.
+    ///
+    /// Sessions are kept in memory, so proxies that reconnect can resume their session as long as
+    /// the server keeps running.
+    pub fn server_config(&self) -> anyhow::Result<ServerConfig> {
+        let mut provider = CryptoProvider::clone(ServerConfig::builder().crypto_provider());
+        if let Some(cipher_suites) = &self.cipher_suites {
+            provider.cipher_suites.clone_from(cipher_suites);
+        }
+        let provider = Arc::new(provider);
+
+        let root_cert_store = Arc::new(RootCertStore {
+            roots: vec![webpki::anchor_from_trusted_cert(&self.root_ca_cert)?.to_owned()],
+        });
+        let verifier =
+            WebPkiClientVerifier::builder_with_provider(root_cert_store, provider.clone())
+                .build()?;
+
+        let mut config = ServerConfig::builder_with_provider(provider)
+            .with_protocol_versions(
+                self.protocol_versions
+                    .as_deref()
+                    .unwrap_or(rustls::DEFAULT_VERSIONS),
+            )?
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(
+                vec![self.cert.clone(), self.root_ca_cert.clone()],
+                self.key.clone_key(),
+            )?;
+
+        config.session_storage = if self.session_cache_size == 0 {
+            Arc::new(NoServerSessionStorage {})
+        } else {
+            ServerSessionMemoryCache::new(self.session_cache_size)
+        };
+
+        Ok(config)
     }
 }
 
@@ -139,6 +253,9 @@ impl Clone for Crypto {
             root_ca_cert: self.root_ca_cert.clone(),
             cert: self.cert.clone(),
             key: self.key.clone_key(),
+            cipher_suites: self.cipher_suites.clone(),
+            protocol_versions: self.protocol_versions.clone(),
+            session_cache_size: self.session_cache_size,
         }
     }
 }
@@ -166,6 +283,10 @@ impl From<SocketAddr> for Endpoint {
     }
 }
 
+/// Triggered while a player joins so that observers can insert its [`simulation::Position`].
+///
+/// This is kept for compatibility. Prefer changing the spawn position in
+/// [`simulation::join::PlayerSpawning`], which is triggered right after this.
 #[derive(EntityEvent, Debug, Copy, Clone, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "reflect", derive(Reflect), reflect(Event))]
 pub struct InitializePlayerPosition(pub Entity);
@@ -215,50 +336,104 @@ impl Plugin for HyperionCore {
         });
 
         info!("starting hyperion");
-        let config = config::Config::load("run/config.toml").expect("failed to load config");
+        let config_path = Path::new("run/config.toml");
+        let config = config::Config::load(config_path).expect("failed to load config");
+        // An `AuthMode`, such as `Offline` for tests, may be inserted before this plugin
+        if !app.world().contains_resource::<AuthMode>() {
+            app.insert_resource(config.auth_mode);
+        }
+        app.insert_resource(config.forwarding.clone());
+        app.insert_resource(config.virtual_hosts.clone().normalized());
+        app.insert_resource(config.afk);
+        app.insert_resource(config.keep_alive);
+        app.insert_resource(config.idle);
+        app.insert_resource(config.early_joins);
+        app.insert_resource(config.map.clone());
+        app.insert_resource(config.corrupt_chunks);
+        app.insert_resource(config.pending_connections);
+        let connection_limits = config.connection_limits;
+
+        // A `GameRng` with a fixed seed, such as one for tests, may be inserted before this plugin
+        if !app.world().contains_resource::<GameRng>() {
+            let rng = config
+                .rng_seed
+                .map_or_else(GameRng::from_entropy, GameRng::new);
+            app.insert_resource(rng);
+        }
+        info!(
+            "gameplay rng seed: {}",
+            app.world().resource::<GameRng>().seed()
+        );
+
         app.insert_resource(config);
 
         let runtime = AsyncRuntime::new();
 
-        let db = LocalDb::new().expect("failed to load database");
+        // A database configured through `LocalDb::builder` may be inserted before this plugin
+        let db = match app.world_mut().remove_resource::<LocalDb>() {
+            Some(db) => db,
+            None => LocalDb::new().expect("failed to load database"),
+        };
         let skins = SkinHandler::new(&db).expect("failed to load skin handler");
 
         app.insert_resource(db);
         app.insert_resource(skins);
-        app.insert_resource(MojangClient::new(&runtime, ApiProvider::MAT_DOES_DEV));
+        // A stubbed client created with `MojangClient::stub` may be inserted before this plugin
+        if !app.world().contains_resource::<MojangClient>() {
+            app.insert_resource(MojangClient::new(&runtime, ApiProvider::MAT_DOES_DEV));
+        }
         app.insert_resource(Blocks::empty(&runtime));
 
-        let global = Global::new(shared.clone());
-
         app.add_plugins(CommandChannelPlugin);
+        app.add_plugins(config::ConfigReloadPlugin {
+            path: config_path.to_owned(),
+        });
 
+        let activity = ServerActivity::default();
         if let Some(address) = app.world().get_resource::<Endpoint>() {
             let crypto = app.world().resource::<Crypto>();
             let command_channel = app.world().resource::<CommandChannel>();
-            init_proxy_comms(&runtime, command_channel.clone(), address.0, crypto.clone());
+            init_proxy_comms(
+                &runtime,
+                command_channel.clone(),
+                activity.waker(),
+                address.0,
+                crypto.clone(),
+            );
         } else {
             warn!("Endpoint was not set while loading HyperionCore");
         }
 
+        let mut io_buf = IoBuf::default();
+        io_buf.set_limits(connection_limits);
         app.insert_resource(Compose::new(
             shared.compression_level,
-            global,
-            IoBuf::default(),
+            shared.clone(),
+            io_buf,
         ));
+        app.init_resource::<Tick>();
+        app.init_resource::<CombatConfig>();
+        app.init_resource::<TickDuration>();
+        app.init_resource::<PlayerCount>();
         app.insert_resource(runtime);
         app.insert_resource(CraftingRegistry::default());
         app.insert_resource(StreamLookup::default());
+        app.insert_resource(activity);
 
         app.add_plugins((
             bevy_time::TimePlugin,
             bevy_app::ScheduleRunnerPlugin::run_loop(Duration::from_millis(10)),
+            ActivityPlugin,
             IngressPlugin,
             EgressPlugin,
             SimPlugin,
             SpatialPlugin,
+            TimingsPlugin,
             HyperionUtilsPlugin,
         ));
 
+        translations::load(&mut app.world_mut().get_resource_or_init::<Translations>());
+
         app.insert_resource(IgnMap::default());
         // Minecraft is 20 TPS
         app.insert_resource(Time::<Fixed>::from_hz(20.0));
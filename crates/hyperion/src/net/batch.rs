@@ -0,0 +1,363 @@
+//! Egress messages that are collected during a tick and sent together. See [`BatchOrder`].
+
+use std::{cell::RefCell, sync::Mutex};
+
+use indexmap::IndexMap;
+use rustc_hash::{FxBuildHasher, FxHashSet};
+use thread_local::ThreadLocal;
+
+use crate::net::{
+    ConnectionId,
+    intermediate::{self, Exclude, IntermediateServerToProxyMessage},
+};
+
+/// Batched global broadcasts and multicasts are split into several messages once their data
+/// exceeds this length
+pub const MAX_BATCHED_BROADCAST_LEN: usize = 64 * 1024;
+
+/// Where packets that are broadcast to a channel with
+/// [`BroadcastChannel::batched`](crate::net::BroadcastChannel::batched) are placed in the batch
+/// of the channel.
+///
+/// Each channel receives one message per tick containing all of its batched packets, ordered by
+/// this and then by the order in which they were sent.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum BatchOrder {
+    Position,
+    Velocity,
+    Metadata,
+}
+
+impl BatchOrder {
+    const COUNT: usize = 3;
+}
+
+type FxIndexMap<K, V> = IndexMap<K, V, FxBuildHasher>;
+
+/// The batched messages of one thread. Keys are kept in the order they were first used in.
+#[derive(Default)]
+struct ThreadBatch {
+    /// The packets for each channel and excluded connection, grouped by [`BatchOrder`]
+    channels: FxIndexMap<(u32, Exclude), [Vec<u8>; BatchOrder::COUNT]>,
+    /// The data of global broadcasts for each excluded connection and world, split into
+    /// segments of at most [`MAX_BATCHED_BROADCAST_LEN`] bytes unless a single packet is longer
+    global: FxIndexMap<(Exclude, Option<u16>), Vec<Vec<u8>>>,
+    /// The data of filtered broadcasts for each set of recipients and world, split like `global`
+    multicast: FxIndexMap<(Box<[ConnectionId]>, Option<u16>), Vec<Vec<u8>>>,
+}
+
+#[derive(Default)]
+pub(crate) struct EgressBatch {
+    threads: ThreadLocal<RefCell<ThreadBatch>>,
+    /// Channels removed since the last flush, whose batched packets must not be sent
+    removed_channels: Mutex<FxHashSet<u32>>,
+}
+
+impl EgressBatch {
+    pub(crate) fn add_channel(
+        &self,
+        channel_id: u32,
+        exclude: Exclude,
+        order: BatchOrder,
+        data: &[u8],
+    ) {
+        let mut batch = self.threads.get_or_default().borrow_mut();
+        batch.channels.entry((channel_id, exclude)).or_default()[order as usize]
+            .extend_from_slice(data);
+    }
+
+    pub(crate) fn add_global(&self, exclude: Exclude, world: Option<u16>, data: &[u8]) {
+        let mut batch = self.threads.get_or_default().borrow_mut();
+        push_segment(batch.global.entry((exclude, world)).or_default(), data);
+    }
+
+    pub(crate) fn add_multicast(&self, streams: &[ConnectionId], world: Option<u16>, data: &[u8]) {
+        let mut batch = self.threads.get_or_default().borrow_mut();
+        push_segment(
+            batch.multicast.entry((streams.into(), world)).or_default(),
+            data,
+        );
+    }
+
+    /// Drops the batched packets of `channel`, which the proxy no longer knows about
+    pub(crate) fn remove_channel(&self, channel_id: u32) {
+        self.removed_channels.lock().unwrap().insert(channel_id);
+    }
+
+    /// Merges the batches of every thread and passes the resulting messages to `send`
+    pub(crate) fn flush(&mut self, mut send: impl FnMut(&IntermediateServerToProxyMessage<'_>)) {
+        let mut channels: FxIndexMap<_, [Vec<u8>; BatchOrder::COUNT]> = FxIndexMap::default();
+        let mut global: FxIndexMap<_, Vec<Vec<u8>>> = FxIndexMap::default();
+        let mut multicast: FxIndexMap<_, Vec<Vec<u8>>> = FxIndexMap::default();
+
+        for batch in self.threads.iter_mut() {
+            let batch = batch.get_mut();
+
+            for (key, packets) in batch.channels.drain(..) {
+                let merged = channels.entry(key).or_default();
+                for (merged, packets) in merged.iter_mut().zip(packets) {
+                    if merged.is_empty() {
+                        *merged = packets;
+                    } else {
+                        merged.extend_from_slice(&packets);
+                    }
+                }
+            }
+
+            for (key, segments) in batch.global.drain(..) {
+                global.entry(key).or_default().extend(segments);
+            }
+
+            for (key, segments) in batch.multicast.drain(..) {
+                multicast.entry(key).or_default().extend(segments);
+            }
+        }
+
+        let removed_channels = self.removed_channels.get_mut().unwrap();
+
+        for ((channel_id, exclude), packets) in channels {
+            if removed_channels.contains(&channel_id) {
+                continue;
+            }
+
+            let data = packets.concat();
+            send(&IntermediateServerToProxyMessage::BroadcastChannel(
+                intermediate::BroadcastChannel {
+                    channel_id,
+                    exclude,
+                    data: &data,
+                },
+            ));
+        }
+
+        removed_channels.clear();
+
+        for ((exclude, world), segments) in global {
+            for data in segments {
+                send(&IntermediateServerToProxyMessage::BroadcastGlobal(
+                    intermediate::BroadcastGlobal {
+                        exclude,
+                        world,
+                        data: &data,
+                    },
+                ));
+            }
+        }
+
+        for ((streams, world), segments) in multicast {
+            for data in segments {
+                send(&IntermediateServerToProxyMessage::Multicast(
+                    intermediate::Multicast {
+                        streams: &streams,
+                        world,
+                        data: &data,
+                    },
+                ));
+            }
+        }
+    }
+}
+
+/// Appends `data` to the last segment, or starts a new one if that would exceed
+/// [`MAX_BATCHED_BROADCAST_LEN`]
+fn push_segment(segments: &mut Vec<Vec<u8>>, data: &[u8]) {
+    match segments.last_mut() {
+        Some(segment) if segment.len() + data.len() <= MAX_BATCHED_BROADCAST_LEN => {
+            segment.extend_from_slice(data);
+        }
+        _ => segments.push(data.to_vec()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use valence_protocol::{ByteAngle, VarInt, packets::play};
+
+    use super::*;
+    use crate::{
+        Shared,
+        net::{ChannelId, Compose, ConnectionId, IoBuf, ProxyId},
+        simulation::Velocity,
+    };
+
+    fn flush(batch: &mut EgressBatch) -> Vec<(Option<u32>, Exclude, Vec<u8>)> {
+        let mut messages = Vec::new();
+        batch.flush(|message| match message {
+            IntermediateServerToProxyMessage::BroadcastChannel(message) => {
+                messages.push((
+                    Some(message.channel_id),
+                    message.exclude.clone(),
+                    message.data.to_vec(),
+                ));
+            }
+            IntermediateServerToProxyMessage::BroadcastGlobal(message) => {
+                messages.push((None, message.exclude.clone(), message.data.to_vec()));
+            }
+            _ => unreachable!(),
+        });
+        messages
+    }
+
+    #[test]
+    fn channel_packets_are_ordered_and_not_merged_across_excludes() {
+        let mut batch = EgressBatch::default();
+        let exclude = Exclude::One(ConnectionId::new(1, ProxyId::new(0)));
+
+        batch.add_channel(7, Exclude::None, BatchOrder::Metadata, b"m");
+        batch.add_channel(7, Exclude::None, BatchOrder::Velocity, b"v");
+        batch.add_channel(7, Exclude::None, BatchOrder::Position, b"p1");
+        batch.add_channel(7, exclude.clone(), BatchOrder::Position, b"x");
+        batch.add_channel(7, Exclude::None, BatchOrder::Position, b"p2");
+        batch.add_channel(8, Exclude::None, BatchOrder::Position, b"q");
+
+        assert_eq!(flush(&mut batch), [
+            (Some(7), Exclude::None, b"p1p2vm".to_vec()),
+            (Some(7), exclude, b"x".to_vec()),
+            (Some(8), Exclude::None, b"q".to_vec()),
+        ]);
+        assert!(flush(&mut batch).is_empty());
+    }
+
+    #[test]
+    fn removed_channels_are_skipped() {
+        let mut batch = EgressBatch::default();
+        batch.add_channel(7, Exclude::None, BatchOrder::Position, b"p");
+        batch.remove_channel(7);
+        assert!(flush(&mut batch).is_empty());
+
+        batch.add_channel(7, Exclude::None, BatchOrder::Position, b"p");
+        assert_eq!(flush(&mut batch).len(), 1);
+    }
+
+    #[test]
+    fn global_broadcasts_are_split_at_the_threshold() {
+        let mut batch = EgressBatch::default();
+        let packet = vec![0; MAX_BATCHED_BROADCAST_LEN / 4 + 1];
+
+        for _ in 0..6 {
+            batch.add_global(Exclude::None, None, &packet);
+        }
+        batch.add_global(Exclude::None, Some(1), b"world");
+
+        let messages = flush(&mut batch);
+        let lens: Vec<_> = messages.iter().map(|(_, _, data)| data.len()).collect();
+        assert_eq!(lens, [packet.len() * 3, packet.len() * 3, 5]);
+    }
+
+    #[test]
+    fn multicasts_are_merged_per_recipients_and_world() {
+        let mut batch = EgressBatch::default();
+        let [a, b] = [1, 2].map(|stream| ConnectionId::new(stream, ProxyId::new(0)));
+
+        batch.add_multicast(&[a, b], None, b"1");
+        batch.add_multicast(&[a], None, b"2");
+        batch.add_multicast(&[a, b], None, b"3");
+        batch.add_multicast(&[a, b], Some(1), b"4");
+
+        let mut messages = Vec::new();
+        batch.flush(|message| match message {
+            IntermediateServerToProxyMessage::Multicast(message) => {
+                messages.push((
+                    message.streams.to_vec(),
+                    message.world,
+                    message.data.to_vec(),
+                ));
+            }
+            _ => unreachable!(),
+        });
+
+        assert_eq!(messages, [
+            (vec![a, b], None, b"13".to_vec()),
+            (vec![a], None, b"2".to_vec()),
+            (vec![a, b], Some(1), b"4".to_vec()),
+        ]);
+    }
+
+    /// Sends a movement, velocity, and metadata packet for each of `entities` and returns the
+    /// number of writes to the proxy and the number of proxy messages in them
+    fn send_movement(entities: u32, batched: bool) -> (usize, usize) {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut io_buf = IoBuf::default();
+        io_buf.add_proxy(ProxyId::new(0), tx.into());
+        let mut compose = Compose::new(
+            libdeflater::CompressionLvl::default(),
+            Arc::new(Shared {
+                compression_threshold: valence_protocol::CompressionThreshold(-1),
+                compression_level: libdeflater::CompressionLvl::default(),
+            }),
+            io_buf,
+        );
+
+        for id in 0..entities {
+            let channel = ChannelId::new(id);
+            let entity_id = VarInt(i32::try_from(id).unwrap());
+            let movement = play::RotateS2c {
+                entity_id,
+                yaw: ByteAngle(0),
+                pitch: ByteAngle(0),
+                on_ground: true,
+            };
+            let velocity = play::EntityVelocityUpdateS2c {
+                entity_id,
+                velocity: Velocity::new(0.0, 1.0, 0.0).to_packet_units(),
+            };
+            let metadata = play::EntitySetHeadYawS2c {
+                entity_id,
+                head_yaw: ByteAngle(0),
+            };
+
+            if batched {
+                compose
+                    .broadcast_channel(&movement, channel)
+                    .batched(BatchOrder::Position)
+                    .send()
+                    .unwrap();
+                compose
+                    .broadcast_channel(&velocity, channel)
+                    .batched(BatchOrder::Velocity)
+                    .send()
+                    .unwrap();
+                compose
+                    .broadcast_channel(&metadata, channel)
+                    .batched(BatchOrder::Metadata)
+                    .send()
+                    .unwrap();
+            } else {
+                compose
+                    .broadcast_channel(&movement, channel)
+                    .send()
+                    .unwrap();
+                compose
+                    .broadcast_channel(&velocity, channel)
+                    .send()
+                    .unwrap();
+                compose
+                    .broadcast_channel(&metadata, channel)
+                    .send()
+                    .unwrap();
+            }
+        }
+
+        compose.io_buf_mut().flush_batch();
+
+        let mut writes = 0;
+        let mut messages = 0;
+        while let Ok(bytes) = rx.try_recv() {
+            writes += 1;
+            let mut rest = &bytes[..];
+            while let Some((len, body)) = rest.split_first_chunk::<8>() {
+                rest = &body[usize::try_from(u64::from_be_bytes(*len)).unwrap()..];
+                messages += 1;
+            }
+        }
+        (writes, messages)
+    }
+
+    #[test]
+    fn batching_reduces_proxy_messages_at_1k_moving_entities() {
+        assert_eq!(send_movement(1000, false), (3000, 3000));
+        assert_eq!(send_movement(1000, true), (1, 1000));
+    }
+}
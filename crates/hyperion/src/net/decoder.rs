@@ -3,6 +3,7 @@ use bevy_ecs::component::Component;
 use bytes::{Bytes, BytesMut};
 use itertools::Either;
 use packet_channel::RawPacket;
+use thiserror::Error;
 use valence_protocol::{
     CompressionThreshold, Decode, DecodeBytes, MAX_PACKET_SIZE, Packet, VarInt,
 };
@@ -17,6 +18,64 @@ pub struct PacketDecoder {
     threshold: CompressionThreshold,
 }
 
+/// Limits checked against every incoming packet before any memory is allocated for it
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect))]
+pub struct DecodeLimits {
+    /// The maximum size of a packet after decompression
+    pub max_uncompressed_size: usize,
+    /// The maximum ratio between the decompressed and the compressed size of a packet. Legitimate
+    /// packets rarely exceed 10:1, while zip bombs are far above it.
+    pub max_decompression_ratio: usize,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self {
+            max_uncompressed_size: crate::net::MAX_PACKET_SIZE,
+            max_decompression_ratio: 128,
+        }
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum DecodeLimitError {
+    #[error("packet of {size} bytes exceeds the maximum size of {max} bytes")]
+    TooLarge { size: usize, max: usize },
+    #[error(
+        "packet would decompress from {compressed} to {decompressed} bytes, exceeding the maximum \
+         ratio of {max_ratio}"
+    )]
+    RatioExceeded {
+        compressed: usize,
+        decompressed: usize,
+        max_ratio: usize,
+    },
+}
+
+impl DecodeLimits {
+    fn check_size(&self, size: usize) -> Result<(), DecodeLimitError> {
+        if size > self.max_uncompressed_size {
+            return Err(DecodeLimitError::TooLarge {
+                size,
+                max: self.max_uncompressed_size,
+            });
+        }
+        Ok(())
+    }
+
+    fn check_ratio(&self, compressed: usize, decompressed: usize) -> Result<(), DecodeLimitError> {
+        if decompressed > compressed.saturating_mul(self.max_decompression_ratio) {
+            return Err(DecodeLimitError::RatioExceeded {
+                compressed,
+                decompressed,
+                max_ratio: self.max_decompression_ratio,
+            });
+        }
+        Ok(())
+    }
+}
+
 #[derive(Clone)]
 pub struct BorrowedPacketFrame {
     /// The ID of the decoded packet.
@@ -28,6 +87,15 @@ pub struct BorrowedPacketFrame {
 }
 
 impl BorrowedPacketFrame {
+    /// The length of the packet body in bytes, after decompression
+    #[must_use]
+    pub fn body_len(&self) -> usize {
+        match &self.body {
+            Either::Left(bytes) => bytes.len(),
+            Either::Right(packet) => packet.len(),
+        }
+    }
+
     /// Attempts to decode this packet as type `P`. An error is returned if the
     /// packet ID does not match, the body of the packet failed to decode, or
     /// some input was missed.
@@ -76,11 +144,17 @@ impl BorrowedPacketFrame {
 }
 
 impl PacketDecoder {
+    /// Decodes the frame of a packet received from the proxy.
+    ///
+    /// Packets violating `limits` are rejected with a [`DecodeLimitError`].
     pub fn try_next_packet(
         &self,
         decompressor: &mut libdeflater::Decompressor,
+        limits: &DecodeLimits,
         mut raw_packet: RawPacket,
     ) -> anyhow::Result<BorrowedPacketFrame> {
+        limits.check_size(raw_packet.len())?;
+
         let mut raw_packet_slice: &[u8] = &raw_packet;
         let mut data;
 
@@ -102,22 +176,25 @@ impl PacketDecoder {
                     self.threshold.0
                 );
 
+                let data_len = usize::try_from(data_len)?;
+                limits.check_size(data_len)?;
+                limits.check_ratio(raw_packet_slice.len(), data_len)?;
+
                 // todo(perf): find a decompression library which accepts &[MaybeUninit<u8>] to
                 // avoid cost of initializing the data
-                let mut decompression_buf = BytesMut::zeroed(usize::try_from(data_len)?);
+                let mut decompression_buf = BytesMut::zeroed(data_len);
 
                 let written_len =
                     decompressor.zlib_decompress(raw_packet_slice, &mut decompression_buf)?;
 
-                debug_assert_eq!(
-                    written_len, data_len as usize,
-                    "{written_len} != {data_len}"
+                ensure!(
+                    written_len == data_len,
+                    "packet decompressed to {written_len} bytes instead of the declared length of \
+                     {data_len}"
                 );
 
                 data = Either::Left(decompression_buf.freeze());
             } else {
-                debug_assert_eq!(data_len, 0, "{data_len} != 0");
-
                 ensure!(
                     raw_packet_slice.len() <= self.threshold.0 as usize,
                     "uncompressed packet length of {} exceeds compression threshold of {}",
@@ -166,3 +243,116 @@ impl PacketDecoder {
         self.threshold = threshold;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use valence_protocol::Encode;
+
+    use super::*;
+
+    fn raw_packet(data: &[u8]) -> RawPacket {
+        let (mut sender, mut receiver) = packet_channel::channel(1 << 16);
+        let mut framed = Vec::new();
+        VarInt(i32::try_from(data.len()).unwrap())
+            .encode(&mut framed)
+            .unwrap();
+        framed.extend_from_slice(data);
+        sender.send(&framed).unwrap();
+        receiver.try_recv().unwrap()
+    }
+
+    /// Builds a compressed packet with the given body, which includes the packet ID
+    fn compressed_packet(body: &[u8]) -> RawPacket {
+        let mut compressor = libdeflater::Compressor::new(libdeflater::CompressionLvl::default());
+        let mut compressed = vec![0; compressor.zlib_compress_bound(body.len())];
+        let len = compressor.zlib_compress(body, &mut compressed).unwrap();
+        compressed.truncate(len);
+
+        let mut data = Vec::new();
+        VarInt(i32::try_from(body.len()).unwrap())
+            .encode(&mut data)
+            .unwrap();
+        data.extend_from_slice(&compressed);
+        raw_packet(&data)
+    }
+
+    fn limit_error(result: anyhow::Result<BorrowedPacketFrame>) -> DecodeLimitError {
+        result.err().unwrap().downcast().unwrap()
+    }
+
+    #[test]
+    fn oversized_packets_are_rejected() {
+        let decoder = PacketDecoder::default();
+        let mut decompressor = libdeflater::Decompressor::new();
+        let limits = DecodeLimits {
+            max_uncompressed_size: 100,
+            ..DecodeLimits::default()
+        };
+
+        let frame = decoder
+            .try_next_packet(&mut decompressor, &limits, raw_packet(&[5; 100]))
+            .unwrap();
+        assert_eq!(frame.id, 5);
+        assert_eq!(frame.body_len(), 99);
+
+        assert_eq!(
+            limit_error(decoder.try_next_packet(&mut decompressor, &limits, raw_packet(&[5; 101]))),
+            DecodeLimitError::TooLarge {
+                size: 101,
+                max: 100
+            }
+        );
+    }
+
+    #[test]
+    fn compressed_packets_are_checked_before_decompressing() {
+        let mut decoder = PacketDecoder::default();
+        decoder.set_compression(CompressionThreshold(256));
+        let mut decompressor = libdeflater::Decompressor::new();
+        let limits = DecodeLimits::default();
+
+        // Bytes from a simple LCG barely compress, like most real packets
+        let mut state = 1_u32;
+        let noise: Vec<u8> = (0..1000)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                state.to_be_bytes()[0] & 0x7F
+            })
+            .collect();
+        let frame = decoder
+            .try_next_packet(&mut decompressor, &limits, compressed_packet(&noise))
+            .unwrap();
+        assert_eq!(frame.id, i32::from(noise[0]));
+        assert_eq!(frame.body_len(), 999);
+
+        let bomb = vec![0; 1_000_000];
+        assert!(matches!(
+            limit_error(decoder.try_next_packet(
+                &mut decompressor,
+                &limits,
+                compressed_packet(&bomb)
+            )),
+            DecodeLimitError::RatioExceeded {
+                decompressed: 1_000_000,
+                ..
+            }
+        ));
+
+        let limits = DecodeLimits {
+            max_uncompressed_size: 500,
+            ..DecodeLimits::default()
+        };
+        let zeros = vec![0; 1000];
+        assert_eq!(
+            limit_error(decoder.try_next_packet(
+                &mut decompressor,
+                &limits,
+                compressed_packet(&zeros)
+            )),
+            DecodeLimitError::TooLarge {
+                size: 1000,
+                max: 500
+            }
+        );
+    }
+}
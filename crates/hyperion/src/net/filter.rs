@@ -0,0 +1,477 @@
+//! Hooks which inspect, change or drop packets right before they are sent to the proxies and
+//! right after they are received, such as for logging every packet of a connection to a file.
+//!
+//! Filters are registered with [`Compose::register_egress_filter`] and
+//! [`Compose::register_ingress_filter`]. They run in ascending order of priority, and filters
+//! with the same priority run in the order they were registered. Packets are not decoded or copied
+//! while no filters are registered.
+//!
+//! Ingress filters run in parallel for different connections, so filters keeping state need to
+//! synchronize it themselves.
+//!
+//! [`Compose::register_egress_filter`]: crate::net::Compose::register_egress_filter
+//! [`Compose::register_ingress_filter`]: crate::net::Compose::register_ingress_filter
+
+use std::{borrow::Cow, cell::RefCell, io::Write};
+
+use anyhow::{Context, ensure};
+use bytes::Bytes;
+use itertools::Either;
+use libdeflater::CompressionLvl;
+use thread_local::ThreadLocal;
+use tracing::warn;
+use valence_protocol::{
+    CompressionThreshold, Decode, Encode, Packet as _, VarInt, packets::play::BundleSplitterS2c,
+};
+
+use crate::{
+    PacketBundle, Scratch,
+    net::{
+        ChannelId, ConnectionId, MAX_PACKET_SIZE, decoder::BorrowedPacketFrame,
+        encoder::PacketEncoder,
+    },
+};
+
+/// Whether a packet is passed on after a filter ran
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum FilterResult {
+    /// The packet is passed to the next filter, and then sent or handled
+    Continue,
+    /// The packet is neither sent nor handled, and the remaining filters do not see it
+    Drop,
+}
+
+/// The direction a packet travels in
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum PacketDirection {
+    /// Sent by the server
+    Clientbound,
+    /// Received from a client
+    Serverbound,
+}
+
+/// The connections a packet is sent to or received from
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[expect(
+    variant_size_differences,
+    reason = "the target is small and only copied once per packet"
+)]
+pub enum PacketTarget {
+    Connection(ConnectionId),
+    /// The connections receiving a broadcast. Unless the broadcast is filtered, they are only
+    /// known to the proxies.
+    Broadcast,
+    /// The connections subscribed to a channel
+    Channel(ChannelId),
+}
+
+/// A packet seen by a filter
+pub struct PacketContext<'a> {
+    direction: PacketDirection,
+    target: PacketTarget,
+    state: Option<&'static str>,
+    id: i32,
+    body: Cow<'a, [u8]>,
+}
+
+impl PacketContext<'_> {
+    #[must_use]
+    pub const fn direction(&self) -> PacketDirection {
+        self.direction
+    }
+
+    #[must_use]
+    pub const fn target(&self) -> PacketTarget {
+        self.target
+    }
+
+    /// The connection the packet is sent to or received from, if it concerns a single connection
+    #[must_use]
+    pub const fn connection(&self) -> Option<ConnectionId> {
+        match self.target {
+            PacketTarget::Connection(connection) => Some(connection),
+            PacketTarget::Broadcast | PacketTarget::Channel(_) => None,
+        }
+    }
+
+    /// The name of the packet state of the connection, such as `play`. This is only known for
+    /// received packets.
+    #[must_use]
+    pub const fn state(&self) -> Option<&'static str> {
+        self.state
+    }
+
+    /// The ID of the packet
+    #[must_use]
+    pub const fn id(&self) -> i32 {
+        self.id
+    }
+
+    /// The uncompressed contents of the packet after the ID
+    #[must_use]
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// The uncompressed contents of the packet after the ID. Changed packets are compressed again
+    /// before they are sent.
+    pub fn body_mut(&mut self) -> &mut Vec<u8> {
+        self.body.to_mut()
+    }
+
+    const fn is_modified(&self) -> bool {
+        matches!(self.body, Cow::Owned(_))
+    }
+}
+
+type Filter = Box<dyn Fn(&mut PacketContext<'_>) -> FilterResult + Send + Sync>;
+
+/// Filters ordered by priority
+#[derive(Default)]
+struct FilterList(Vec<(i32, Filter)>);
+
+impl FilterList {
+    fn insert(&mut self, priority: i32, filter: Filter) {
+        // Filters with the same priority keep the order they were registered in
+        let index = self.0.partition_point(|&(other, _)| other <= priority);
+        self.0.insert(index, (priority, filter));
+    }
+
+    fn run(&self, context: &mut PacketContext<'_>) -> FilterResult {
+        for (_, filter) in &self.0 {
+            if filter(context) == FilterResult::Drop {
+                return FilterResult::Drop;
+            }
+        }
+        FilterResult::Continue
+    }
+}
+
+/// The filters registered with [`Compose`](crate::net::Compose)
+pub(crate) struct PacketFilters {
+    egress: FilterList,
+    ingress: FilterList,
+    /// The compression of the packets sent to the proxies
+    threshold: CompressionThreshold,
+    compression_lvl: CompressionLvl,
+    compressor: ThreadLocal<RefCell<libdeflater::Compressor>>,
+    decompressor: ThreadLocal<RefCell<libdeflater::Decompressor>>,
+    scratch: ThreadLocal<RefCell<Scratch>>,
+}
+
+impl Default for PacketFilters {
+    fn default() -> Self {
+        Self {
+            egress: FilterList::default(),
+            ingress: FilterList::default(),
+            threshold: CompressionThreshold::default(),
+            compression_lvl: CompressionLvl::default(),
+            compressor: ThreadLocal::new(),
+            decompressor: ThreadLocal::new(),
+            scratch: ThreadLocal::new(),
+        }
+    }
+}
+
+/// Whether a clientbound packet is a [`BundleSplitterS2c`]. No other packet has an empty body and
+/// the same ID in any state.
+fn is_bundle_delimiter(id: i32, body: &[u8]) -> bool {
+    id == BundleSplitterS2c::ID && body.is_empty()
+}
+
+/// A packet which is encoded from its ID and body
+pub(crate) struct FilteredPacket<'a> {
+    pub(crate) id: i32,
+    pub(crate) body: &'a [u8],
+}
+
+impl PacketBundle for FilteredPacket<'_> {
+    fn encode_including_ids(self, mut w: impl Write) -> anyhow::Result<()> {
+        VarInt(self.id).encode(&mut w)?;
+        w.write_all(self.body)?;
+        Ok(())
+    }
+}
+
+impl PacketFilters {
+    pub(crate) const fn set_compression(
+        &mut self,
+        threshold: CompressionThreshold,
+        compression_lvl: CompressionLvl,
+    ) {
+        self.threshold = threshold;
+        self.compression_lvl = compression_lvl;
+    }
+
+    pub(crate) fn register_egress(&mut self, priority: i32, filter: Filter) {
+        self.egress.insert(priority, filter);
+    }
+
+    pub(crate) fn register_ingress(&mut self, priority: i32, filter: Filter) {
+        self.ingress.insert(priority, filter);
+    }
+
+    #[must_use]
+    pub(crate) fn egress_is_empty(&self) -> bool {
+        self.egress.0.is_empty()
+    }
+
+    #[must_use]
+    pub(crate) fn ingress_is_empty(&self) -> bool {
+        self.ingress.0.is_empty()
+    }
+
+    /// Runs the egress filters over the packets in `data`, which are sent to `target`. Returns
+    /// the packets to send instead, or `None` if no packet was changed or dropped.
+    pub(crate) fn filter_egress(&self, target: PacketTarget, data: &[u8]) -> Option<Vec<u8>> {
+        let mut filtered = Vec::new();
+        let mut changed = false;
+        let mut rest = data;
+
+        while !rest.is_empty() {
+            let (frame_len, id, body) = match self.decode_frame(rest) {
+                Ok(frame) => frame,
+                Err(e) => {
+                    warn!("egress filters skipped packets which failed to decode: {e}");
+                    filtered.extend_from_slice(rest);
+                    break;
+                }
+            };
+
+            // Filters never see bundle delimiters, so dropping a packet cannot break up a bundle
+            if is_bundle_delimiter(id, &body) {
+                filtered.extend_from_slice(&rest[..frame_len]);
+                rest = &rest[frame_len..];
+                continue;
+            }
+
+            let mut context = PacketContext {
+                direction: PacketDirection::Clientbound,
+                target,
+                state: None,
+                id,
+                body,
+            };
+
+            let result = self.egress.run(&mut context);
+
+            match result {
+                FilterResult::Drop => changed = true,
+                FilterResult::Continue if context.is_modified() => {
+                    changed = true;
+                    if let Err(e) = self.encode(context.id, context.body(), &mut filtered) {
+                        warn!("dropped packet {id} changed by an egress filter: {e}");
+                    }
+                }
+                FilterResult::Continue => filtered.extend_from_slice(&rest[..frame_len]),
+            }
+
+            rest = &rest[frame_len..];
+        }
+
+        changed.then_some(filtered)
+    }
+
+    /// Runs the ingress filters over `frame`, which was received from `connection` in `state`.
+    /// Returns `None` if the packet was dropped.
+    pub(crate) fn filter_ingress(
+        &self,
+        connection: ConnectionId,
+        state: &'static str,
+        frame: BorrowedPacketFrame,
+    ) -> Option<BorrowedPacketFrame> {
+        let body = match &frame.body {
+            Either::Left(bytes) => &bytes[..],
+            Either::Right(packet) => &packet[..],
+        };
+
+        let mut context = PacketContext {
+            direction: PacketDirection::Serverbound,
+            target: PacketTarget::Connection(connection),
+            state: Some(state),
+            id: frame.id,
+            body: Cow::Borrowed(body),
+        };
+
+        if self.ingress.run(&mut context) == FilterResult::Drop {
+            return None;
+        }
+
+        match context.body {
+            Cow::Borrowed(_) => Some(frame),
+            Cow::Owned(body) => Some(BorrowedPacketFrame {
+                id: frame.id,
+                body: Either::Left(Bytes::from(body)),
+            }),
+        }
+    }
+
+    /// Decodes the first packet of `data`, returning the length of its frame, its ID and its body
+    fn decode_frame<'a>(&self, data: &'a [u8]) -> anyhow::Result<(usize, i32, Cow<'a, [u8]>)> {
+        let mut rest = data;
+        let packet_len = usize::try_from(VarInt::decode(&mut rest)?.0)?;
+        ensure!(packet_len <= rest.len(), "packet is cut off");
+
+        let frame_len = data.len() - rest.len() + packet_len;
+        let mut payload = &rest[..packet_len];
+
+        let data_len = if self.threshold.0 >= 0 {
+            usize::try_from(VarInt::decode(&mut payload)?.0)?
+        } else {
+            0
+        };
+
+        if data_len == 0 {
+            let id = VarInt::decode(&mut payload)
+                .context("failed to decode packet ID")?
+                .0;
+            return Ok((frame_len, id, Cow::Borrowed(payload)));
+        }
+
+        ensure!(
+            data_len <= MAX_PACKET_SIZE,
+            "decompressed packet length of {data_len} is out of bounds"
+        );
+
+        let mut decompressed = vec![0; data_len];
+        self.decompressor
+            .get_or_default()
+            .borrow_mut()
+            .zlib_decompress(payload, &mut decompressed)?;
+
+        let mut body = decompressed.as_slice();
+        let id = VarInt::decode(&mut body)
+            .context("failed to decode packet ID")?
+            .0;
+        let id_len = decompressed.len() - body.len();
+        decompressed.drain(..id_len);
+
+        Ok((frame_len, id, Cow::Owned(decompressed)))
+    }
+
+    fn encode(&self, id: i32, body: &[u8], out: &mut Vec<u8>) -> anyhow::Result<()> {
+        let mut compressor = self
+            .compressor
+            .get_or(|| RefCell::new(libdeflater::Compressor::new(self.compression_lvl)))
+            .borrow_mut();
+        let mut scratch = self.scratch.get_or_default().borrow_mut();
+
+        PacketEncoder::new(self.threshold).append_packet(
+            FilteredPacket { id, body },
+            out,
+            &mut *scratch,
+            &mut compressor,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::net::ProxyId;
+
+    fn encode_all(filters: &PacketFilters, packets: &[(i32, &[u8])]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for &(id, body) in packets {
+            filters.encode(id, body, &mut data).unwrap();
+        }
+        data
+    }
+
+    #[test]
+    fn filters_run_by_priority_and_can_drop_and_change_packets() {
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let mut filters = PacketFilters::default();
+        filters.set_compression(CompressionThreshold(4), CompressionLvl::default());
+
+        for (priority, name) in [(10, "late"), (-5, "early"), (10, "later")] {
+            let order = order.clone();
+            filters.register_egress(
+                priority,
+                Box::new(move |context: &mut PacketContext<'_>| {
+                    order.lock().unwrap().push((name, context.id()));
+                    match (name, context.id()) {
+                        ("early", 1) => FilterResult::Drop,
+                        ("late", 2) => {
+                            context.body_mut().push(9);
+                            FilterResult::Continue
+                        }
+                        _ => FilterResult::Continue,
+                    }
+                }),
+            );
+        }
+
+        let target = PacketTarget::Connection(ConnectionId::new(1, ProxyId::new(0)));
+        let long_body = [7; 16];
+        let data = encode_all(&filters, &[(1, &[1]), (2, &long_body), (3, &[3])]);
+
+        let filtered = filters.filter_egress(target, &data).unwrap();
+
+        let mut expected_body = long_body.to_vec();
+        expected_body.push(9);
+        assert_eq!(
+            filtered,
+            encode_all(&filters, &[(2, &expected_body), (3, &[3])])
+        );
+        assert_eq!(*order.lock().unwrap(), [
+            ("early", 1),
+            ("early", 2),
+            ("late", 2),
+            ("later", 2),
+            ("early", 3),
+            ("late", 3),
+            ("later", 3),
+        ]);
+
+        // Bundle delimiters are kept without running the filters
+        order.lock().unwrap().clear();
+        let bundle = encode_all(&filters, &[(0, &[]), (1, &[1]), (0, &[])]);
+        assert_eq!(
+            filters.filter_egress(target, &bundle).unwrap(),
+            encode_all(&filters, &[(0, &[]), (0, &[])])
+        );
+        assert_eq!(*order.lock().unwrap(), [("early", 1)]);
+
+        // Packets which are passed on unchanged are not encoded again
+        let unchanged = encode_all(&filters, &[(3, &[3]), (4, &[4])]);
+        assert_eq!(filters.filter_egress(target, &unchanged), None);
+    }
+
+    #[test]
+    fn ingress_filters_see_the_connection_and_state() {
+        let connection = ConnectionId::new(1, ProxyId::new(0));
+        let mut filters = PacketFilters::default();
+        filters.register_ingress(
+            0,
+            Box::new(move |context: &mut PacketContext<'_>| {
+                assert_eq!(context.connection(), Some(connection));
+                assert_eq!(context.state(), Some("play"));
+                if context.id() == 1 {
+                    return FilterResult::Drop;
+                }
+                context.body_mut().reverse();
+                FilterResult::Continue
+            }),
+        );
+
+        let frame = |id| BorrowedPacketFrame {
+            id,
+            body: Either::Left(Bytes::from_static(&[1, 2, 3])),
+        };
+
+        assert!(
+            filters
+                .filter_ingress(connection, "play", frame(1))
+                .is_none()
+        );
+
+        let frame = filters
+            .filter_ingress(connection, "play", frame(2))
+            .unwrap();
+        assert_eq!(frame.id, 2);
+        assert!(matches!(frame.body, Either::Left(body) if body[..] == [3, 2, 1]));
+    }
+}
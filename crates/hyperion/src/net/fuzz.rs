@@ -0,0 +1,156 @@
+//! Entry points for fuzzing the parsers of untrusted input.
+//!
+//! Every function accepts arbitrary bytes and must never panic, no matter the input. Malformed
+//! input is rejected the way the server would reject it, after which the remaining input is
+//! ignored. The functions are meant to be called by fuzz targets such as those of `cargo fuzz`, and
+//! by the property tests in `tests/fuzz.rs`, which run a bounded number of iterations on every test
+//! run. More iterations can be run by setting `PROPTEST_CASES`.
+//!
+//! `tests/data/fuzz` contains a corpus of packets from `tests/data/place_block.capture`, framed
+//! the way a client frames them.
+
+use std::convert::Infallible;
+
+use hyperion_proto::ArchivedProxyToServerMessage;
+use rkyv::util::AlignedVec;
+use rustc_hash::FxHashMap;
+use valence_protocol::CompressionThreshold;
+
+use crate::net::{
+    PacketDecoder,
+    decoder::DecodeLimits,
+    proxy::{DEFAULT_FRAGMENT_SIZE, access_proxy_message, proxy_message_len},
+};
+
+/// The packets of a connection which are decoded as they arrive
+struct Connection {
+    sender: packet_channel::Sender,
+    receiver: packet_channel::Receiver,
+    decoder: PacketDecoder,
+    decompressor: libdeflater::Decompressor,
+    /// Whether the connection would have been disconnected because of a malformed packet
+    closed: bool,
+}
+
+impl Connection {
+    fn new(threshold: CompressionThreshold) -> Self {
+        let (sender, receiver) = packet_channel::channel(DEFAULT_FRAGMENT_SIZE);
+        let mut decoder = PacketDecoder::default();
+        decoder.set_compression(threshold);
+
+        Self {
+            sender,
+            receiver,
+            decoder,
+            decompressor: libdeflater::Decompressor::new(),
+            closed: false,
+        }
+    }
+
+    /// Receives `data` from the connection and returns the number of packets decoded from it
+    fn receive(&mut self, data: &[u8]) -> usize {
+        if self.closed {
+            return 0;
+        }
+
+        if self.sender.send(data).is_err() {
+            self.closed = true;
+        }
+
+        let limits = DecodeLimits::default();
+        let mut decoded = 0;
+
+        while let Some(raw_packet) = self.receiver.try_recv() {
+            if self
+                .decoder
+                .try_next_packet(&mut self.decompressor, &limits, raw_packet)
+                .is_err()
+            {
+                self.closed = true;
+                break;
+            }
+            decoded += 1;
+        }
+
+        decoded
+    }
+}
+
+/// Feeds `data` into a fresh [`PacketDecoder`] with the compression `threshold`, split into
+/// separate reads at every index in `splits`. Indices past the end of `data` are clamped.
+///
+/// Returns the number of packets decoded before the end of `data` or the first malformed packet.
+#[must_use]
+pub fn decode_packets(data: &[u8], splits: &[usize], threshold: CompressionThreshold) -> usize {
+    let mut splits: Vec<usize> = splits.iter().map(|&split| split.min(data.len())).collect();
+    splits.sort_unstable();
+
+    let mut connection = Connection::new(threshold);
+    let mut decoded = 0;
+    let mut start = 0;
+
+    for end in splits.into_iter().chain([data.len()]) {
+        decoded += connection.receive(&data[start..end]);
+        start = end;
+    }
+
+    decoded
+}
+
+/// Handles `data` as the messages a proxy sent to the server, each prefixed by its length as a
+/// big-endian `u64`. The packets of every connection are decoded by a [`PacketDecoder`] without
+/// compression.
+///
+/// Returns the number of messages handled before the end of `data` or the first malformed
+/// message, which would close the connection to the proxy.
+#[must_use]
+pub fn decode_proxy_messages(mut data: &[u8]) -> usize {
+    let mut connections: FxHashMap<u64, Connection> = FxHashMap::default();
+    let mut buffer = AlignedVec::<16>::new();
+    let mut handled = 0;
+
+    while let Some((prefix, rest)) = data.split_first_chunk::<8>() {
+        let Ok(len) = proxy_message_len(*prefix) else {
+            break;
+        };
+        let Some((message, rest)) = rest.split_at_checked(len) else {
+            break;
+        };
+        data = rest;
+
+        // The reader of the proxy connection aligns every message the same way
+        buffer.clear();
+        buffer.extend_from_slice(message);
+
+        let Ok(message) = access_proxy_message(&buffer) else {
+            break;
+        };
+
+        match message {
+            ArchivedProxyToServerMessage::PlayerConnect(message) => {
+                let Ok(stream) = rkyv::deserialize::<u64, Infallible>(&message.stream);
+                connections.insert(stream, Connection::new(CompressionThreshold::default()));
+            }
+            ArchivedProxyToServerMessage::PlayerDisconnect(message) => {
+                let Ok(stream) = rkyv::deserialize::<u64, Infallible>(&message.stream);
+                connections.remove(&stream);
+            }
+            ArchivedProxyToServerMessage::PlayerPackets(message) => {
+                let Ok(stream) = rkyv::deserialize::<u64, Infallible>(&message.stream);
+                if let Some(connection) = connections.get_mut(&stream) {
+                    connection.receive(&message.data);
+                }
+            }
+            ArchivedProxyToServerMessage::RequestSubscribeChannelPackets(message) => {
+                let _channels =
+                    rkyv::deserialize::<Box<[u32]>, rkyv::rancor::Error>(&message.channels);
+            }
+            // Only contains integers, which were already validated when accessing the message
+            ArchivedProxyToServerMessage::StreamBacklog(_) => {}
+        }
+
+        handled += 1;
+    }
+
+    handled
+}
@@ -1,6 +1,76 @@
-use hyperion_proto::{ChunkPosition, ServerToProxyMessage, UpdateChannelPosition};
+use hyperion_proto::{ChunkPosition, Features, ServerToProxyMessage, UpdateChannelPosition};
 
-use crate::net::{ConnectionId, ProxyId};
+use crate::net::{ChannelId, ConnectionId, ProxyId, filter::PacketTarget};
+
+/// The connections that do not receive a broadcast
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Exclude {
+    #[default]
+    None,
+    One(ConnectionId),
+    /// Always contains more than one connection
+    Many(Box<[ConnectionId]>),
+}
+
+impl Exclude {
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        matches!(self, Self::None)
+    }
+
+    #[must_use]
+    pub fn as_slice(&self) -> &[ConnectionId] {
+        match self {
+            Self::None => &[],
+            Self::One(connection) => std::slice::from_ref(connection),
+            Self::Many(connections) => connections,
+        }
+    }
+
+    /// Whether a connection of `proxy_id` is excluded
+    #[must_use]
+    pub fn contains_proxy(&self, proxy_id: ProxyId) -> bool {
+        self.as_slice()
+            .iter()
+            .any(|connection| connection.proxy_id() == proxy_id)
+    }
+
+    /// The excluded connections of `proxy_id`
+    fn for_proxy(&self, proxy_id: ProxyId) -> hyperion_proto::Exclude {
+        let mut streams = self
+            .as_slice()
+            .iter()
+            .filter(|connection| connection.proxy_id() == proxy_id)
+            .map(|connection| connection.inner());
+
+        hyperion_proto::Exclude {
+            first: streams.next().unwrap_or_default(),
+            rest: streams.collect(),
+        }
+    }
+}
+
+impl From<Option<ConnectionId>> for Exclude {
+    fn from(connection: Option<ConnectionId>) -> Self {
+        connection.map_or(Self::None, Self::One)
+    }
+}
+
+impl From<ConnectionId> for Exclude {
+    fn from(connection: ConnectionId) -> Self {
+        Self::One(connection)
+    }
+}
+
+impl From<&[ConnectionId]> for Exclude {
+    fn from(connections: &[ConnectionId]) -> Self {
+        match connections {
+            [] => Self::None,
+            &[connection] => Self::One(connection),
+            connections => Self::Many(connections.into()),
+        }
+    }
+}
 
 #[derive(Clone, PartialEq)]
 pub struct UpdatePlayerPositions {
@@ -40,7 +110,8 @@ pub struct SetReceiveBroadcasts {
 
 #[derive(Clone, PartialEq, Eq)]
 pub struct BroadcastGlobal<'a> {
-    pub exclude: Option<ConnectionId>,
+    pub exclude: Exclude,
+    pub world: Option<u16>,
 
     pub data: &'a [u8],
 }
@@ -48,7 +119,7 @@ pub struct BroadcastGlobal<'a> {
 #[derive(Clone, PartialEq)]
 pub struct BroadcastLocal<'a> {
     pub center: ChunkPosition,
-    pub exclude: Option<ConnectionId>,
+    pub exclude: Exclude,
 
     pub data: &'a [u8],
 }
@@ -56,7 +127,7 @@ pub struct BroadcastLocal<'a> {
 #[derive(Clone, PartialEq, Eq)]
 pub struct BroadcastChannel<'a> {
     pub channel_id: u32,
-    pub exclude: Option<ConnectionId>,
+    pub exclude: Exclude,
 
     pub data: &'a [u8],
 }
@@ -68,11 +139,36 @@ pub struct Unicast<'a> {
     pub data: &'a [u8],
 }
 
+#[derive(Clone, PartialEq, Eq)]
+pub struct Multicast<'a> {
+    pub streams: &'a [ConnectionId],
+    pub world: Option<u16>,
+
+    pub data: &'a [u8],
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct Shutdown {
     pub stream: ConnectionId,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ResubscribeChannels {
+    pub stream: ConnectionId,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SetSeeHiddenChannels {
+    pub stream: ConnectionId,
+    pub see: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SetEncryption {
+    pub stream: ConnectionId,
+    pub key: [u8; 16],
+}
+
 #[derive(Clone, PartialEq)]
 pub enum IntermediateServerToProxyMessage<'a> {
     UpdatePlayerPositions(UpdatePlayerPositions),
@@ -84,24 +180,66 @@ pub enum IntermediateServerToProxyMessage<'a> {
     BroadcastLocal(BroadcastLocal<'a>),
     BroadcastChannel(BroadcastChannel<'a>),
     Unicast(Unicast<'a>),
+    Multicast(Multicast<'a>),
     SetReceiveBroadcasts(SetReceiveBroadcasts),
     Shutdown(Shutdown),
+    ResubscribeChannels(ResubscribeChannels),
+    SetSeeHiddenChannels(SetSeeHiddenChannels),
+    SetEncryption(SetEncryption),
 }
 
 impl IntermediateServerToProxyMessage<'_> {
     /// Whether the result of [`IntermediateServerToProxyMessage::transform_for_proxy`] will be
     /// affected by the proxy id provided
     #[must_use]
-    pub const fn affected_by_proxy(&self) -> bool {
+    pub fn affected_by_proxy(&self) -> bool {
+        match self {
+            Self::UpdatePlayerPositions(_)
+            | Self::Unicast(_)
+            | Self::Multicast(_)
+            | Self::SetReceiveBroadcasts(_)
+            | Self::Shutdown(_)
+            | Self::ResubscribeChannels(_)
+            | Self::SetSeeHiddenChannels(_)
+            | Self::SetEncryption(_) => true,
+            Self::SubscribeChannelPackets(message) => message.exclude.is_some(),
+            Self::BroadcastGlobal(BroadcastGlobal { exclude, .. })
+            | Self::BroadcastLocal(BroadcastLocal { exclude, .. })
+            | Self::BroadcastChannel(BroadcastChannel { exclude, .. }) => !exclude.is_empty(),
+            Self::AddChannel(_) | Self::UpdateChannelPositions(_) | Self::RemoveChannel(_) => false,
+        }
+    }
+
+    /// The features a proxy must support to be sent this message
+    #[must_use]
+    pub const fn required_features(&self) -> Features {
+        match self {
+            Self::ResubscribeChannels(_) => Features::RESUBSCRIBE_CHANNELS,
+            _ => Features::NONE,
+        }
+    }
+
+    /// Whether [`IntermediateServerToProxyMessage::transform_for_proxy`] returns a different
+    /// result for `proxy_id` than for the proxies without excluded connections
+    #[must_use]
+    pub fn specialized_for(&self, proxy_id: ProxyId) -> bool {
         match self {
             Self::UpdatePlayerPositions(_)
-            | Self::SubscribeChannelPackets(_)
-            | Self::BroadcastGlobal(_)
-            | Self::BroadcastLocal(_)
-            | Self::BroadcastChannel(_)
             | Self::Unicast(_)
+            | Self::Multicast(_)
             | Self::SetReceiveBroadcasts(_)
-            | Self::Shutdown(_) => true,
+            | Self::Shutdown(_)
+            | Self::ResubscribeChannels(_)
+            | Self::SetSeeHiddenChannels(_)
+            | Self::SetEncryption(_) => true,
+            Self::SubscribeChannelPackets(message) => message
+                .exclude
+                .is_some_and(|exclude| exclude.proxy_id() == proxy_id),
+            Self::BroadcastGlobal(BroadcastGlobal { exclude, .. })
+            | Self::BroadcastLocal(BroadcastLocal { exclude, .. })
+            | Self::BroadcastChannel(BroadcastChannel { exclude, .. }) => {
+                exclude.contains_proxy(proxy_id)
+            }
             Self::AddChannel(_) | Self::UpdateChannelPositions(_) | Self::RemoveChannel(_) => false,
         }
     }
@@ -114,16 +252,18 @@ impl IntermediateServerToProxyMessage<'_> {
             |id: ConnectionId| (id.proxy_id() == proxy_id).then(|| id.inner());
         match self {
             Self::UpdatePlayerPositions(message) => {
+                // The proxy only needs the positions of its own players
+                let (stream, positions) = message
+                    .stream
+                    .iter()
+                    .zip(&message.positions)
+                    .filter_map(|(&stream, &position)| {
+                        Some((filter_map_connection_id(stream)?, position))
+                    })
+                    .unzip();
+
                 Some(ServerToProxyMessage::UpdatePlayerPositions(
-                    hyperion_proto::UpdatePlayerPositions {
-                        stream: message
-                            .stream
-                            .iter()
-                            .copied()
-                            .filter_map(filter_map_connection_id)
-                            .collect::<Vec<_>>(),
-                        positions: message.positions.clone(),
-                    },
+                    hyperion_proto::UpdatePlayerPositions { stream, positions },
                 ))
             }
             Self::AddChannel(message) => Some(ServerToProxyMessage::AddChannel(
@@ -158,30 +298,22 @@ impl IntermediateServerToProxyMessage<'_> {
             }
             Self::BroadcastGlobal(message) => Some(ServerToProxyMessage::BroadcastGlobal(
                 hyperion_proto::BroadcastGlobal {
-                    exclude: message
-                        .exclude
-                        .and_then(filter_map_connection_id)
-                        .unwrap_or_default(),
+                    exclude: message.exclude.for_proxy(proxy_id),
+                    world: message.world,
                     data: message.data,
                 },
             )),
             Self::BroadcastLocal(message) => Some(ServerToProxyMessage::BroadcastLocal(
                 hyperion_proto::BroadcastLocal {
                     center: message.center,
-                    exclude: message
-                        .exclude
-                        .and_then(filter_map_connection_id)
-                        .unwrap_or_default(),
+                    exclude: message.exclude.for_proxy(proxy_id),
                     data: message.data,
                 },
             )),
             Self::BroadcastChannel(message) => Some(ServerToProxyMessage::BroadcastChannel(
                 hyperion_proto::BroadcastChannel {
                     channel_id: message.channel_id,
-                    exclude: message
-                        .exclude
-                        .and_then(filter_map_connection_id)
-                        .unwrap_or_default(),
+                    exclude: message.exclude.for_proxy(proxy_id),
                     data: message.data,
                 },
             )),
@@ -191,16 +323,348 @@ impl IntermediateServerToProxyMessage<'_> {
                     data: message.data,
                 }))
             }
+            Self::Multicast(message) => {
+                // The proxy only needs its own streams and is skipped if it has none
+                let streams: Vec<_> = message
+                    .streams
+                    .iter()
+                    .filter_map(|&stream| filter_map_connection_id(stream))
+                    .collect();
+
+                (!streams.is_empty()).then(|| {
+                    ServerToProxyMessage::Multicast(hyperion_proto::Multicast {
+                        streams,
+                        world: message.world,
+                        data: message.data,
+                    })
+                })
+            }
             Self::SetReceiveBroadcasts(message) => Some(
                 ServerToProxyMessage::SetReceiveBroadcasts(hyperion_proto::SetReceiveBroadcasts {
                     stream: filter_map_connection_id(message.stream)?,
                 }),
             ),
-            Self::Shutdown(message) => Some(ServerToProxyMessage::SetReceiveBroadcasts(
-                hyperion_proto::SetReceiveBroadcasts {
+            Self::Shutdown(message) => {
+                Some(ServerToProxyMessage::Shutdown(hyperion_proto::Shutdown {
+                    stream: filter_map_connection_id(message.stream)?,
+                }))
+            }
+            Self::ResubscribeChannels(message) => Some(ServerToProxyMessage::ResubscribeChannels(
+                hyperion_proto::ResubscribeChannels {
                     stream: filter_map_connection_id(message.stream)?,
                 },
             )),
+            Self::SetSeeHiddenChannels(message) => Some(
+                ServerToProxyMessage::SetSeeHiddenChannels(hyperion_proto::SetSeeHiddenChannels {
+                    stream: filter_map_connection_id(message.stream)?,
+                    see: message.see,
+                }),
+            ),
+            Self::SetEncryption(message) => Some(ServerToProxyMessage::SetEncryption(
+                hyperion_proto::SetEncryption {
+                    stream: filter_map_connection_id(message.stream)?,
+                    key: message.key,
+                },
+            )),
         }
     }
 }
+
+impl<'a> IntermediateServerToProxyMessage<'a> {
+    /// The encoded packets sent to clients with this message and who receives them, if the message
+    /// carries packets
+    #[must_use]
+    pub fn packets(&self) -> Option<(PacketTarget, &'a [u8])> {
+        match self {
+            Self::AddChannel(message) => Some((
+                PacketTarget::Channel(ChannelId::new(message.channel_id)),
+                message.unsubscribe_packets,
+            )),
+            Self::SubscribeChannelPackets(message) => Some((
+                PacketTarget::Channel(ChannelId::new(message.channel_id)),
+                message.data,
+            )),
+            Self::BroadcastGlobal(BroadcastGlobal { data, .. })
+            | Self::BroadcastLocal(BroadcastLocal { data, .. })
+            | Self::Multicast(Multicast { data, .. }) => Some((PacketTarget::Broadcast, data)),
+            Self::BroadcastChannel(message) => Some((
+                PacketTarget::Channel(ChannelId::new(message.channel_id)),
+                message.data,
+            )),
+            Self::Unicast(message) => {
+                Some((PacketTarget::Connection(message.stream), message.data))
+            }
+            Self::UpdatePlayerPositions(_)
+            | Self::UpdateChannelPositions(_)
+            | Self::RemoveChannel(_)
+            | Self::SetReceiveBroadcasts(_)
+            | Self::Shutdown(_)
+            | Self::ResubscribeChannels(_)
+            | Self::SetSeeHiddenChannels(_)
+            | Self::SetEncryption(_) => None,
+        }
+    }
+
+    /// Returns this message with its packets replaced by `data`. Messages without packets are
+    /// returned unchanged.
+    #[must_use]
+    pub fn with_packets<'b>(&self, data: &'b [u8]) -> IntermediateServerToProxyMessage<'b>
+    where
+        'a: 'b,
+    {
+        match self {
+            Self::AddChannel(message) => IntermediateServerToProxyMessage::AddChannel(AddChannel {
+                channel_id: message.channel_id,
+                unsubscribe_packets: data,
+            }),
+            Self::SubscribeChannelPackets(message) => {
+                IntermediateServerToProxyMessage::SubscribeChannelPackets(SubscribeChannelPackets {
+                    channel_id: message.channel_id,
+                    exclude: message.exclude,
+                    data,
+                })
+            }
+            Self::BroadcastGlobal(message) => {
+                IntermediateServerToProxyMessage::BroadcastGlobal(BroadcastGlobal {
+                    exclude: message.exclude.clone(),
+                    world: message.world,
+                    data,
+                })
+            }
+            Self::BroadcastLocal(message) => {
+                IntermediateServerToProxyMessage::BroadcastLocal(BroadcastLocal {
+                    center: message.center,
+                    exclude: message.exclude.clone(),
+                    data,
+                })
+            }
+            Self::BroadcastChannel(message) => {
+                IntermediateServerToProxyMessage::BroadcastChannel(BroadcastChannel {
+                    channel_id: message.channel_id,
+                    exclude: message.exclude.clone(),
+                    data,
+                })
+            }
+            Self::Unicast(message) => IntermediateServerToProxyMessage::Unicast(Unicast {
+                stream: message.stream,
+                data,
+            }),
+            Self::Multicast(message) => IntermediateServerToProxyMessage::Multicast(Multicast {
+                streams: message.streams,
+                world: message.world,
+                data,
+            }),
+            message => message.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transform_for_proxy_keeps_the_variant() {
+        let proxy_id = ProxyId::new(0);
+        let stream = ConnectionId::new(1, proxy_id);
+        let center = ChunkPosition::new(0, 0);
+        let updates = [UpdateChannelPosition {
+            channel_id: 1,
+            position: center,
+            radius: 1,
+            hidden: false,
+        }];
+
+        let messages = [
+            IntermediateServerToProxyMessage::UpdatePlayerPositions(UpdatePlayerPositions {
+                stream: vec![stream],
+                positions: vec![center],
+            }),
+            IntermediateServerToProxyMessage::AddChannel(AddChannel {
+                channel_id: 1,
+                unsubscribe_packets: b"unsubscribe",
+            }),
+            IntermediateServerToProxyMessage::UpdateChannelPositions(UpdateChannelPositions {
+                updates: &updates,
+            }),
+            IntermediateServerToProxyMessage::RemoveChannel(RemoveChannel { channel_id: 1 }),
+            IntermediateServerToProxyMessage::SubscribeChannelPackets(SubscribeChannelPackets {
+                channel_id: 1,
+                exclude: Some(stream),
+                data: b"data",
+            }),
+            IntermediateServerToProxyMessage::BroadcastGlobal(BroadcastGlobal {
+                exclude: Exclude::None,
+                world: None,
+                data: b"data",
+            }),
+            IntermediateServerToProxyMessage::BroadcastLocal(BroadcastLocal {
+                center,
+                exclude: Exclude::None,
+                data: b"data",
+            }),
+            IntermediateServerToProxyMessage::BroadcastChannel(BroadcastChannel {
+                channel_id: 1,
+                exclude: Exclude::None,
+                data: b"data",
+            }),
+            IntermediateServerToProxyMessage::Unicast(Unicast {
+                stream,
+                data: b"data",
+            }),
+            IntermediateServerToProxyMessage::Multicast(Multicast {
+                streams: &[stream],
+                world: None,
+                data: b"data",
+            }),
+            IntermediateServerToProxyMessage::SetReceiveBroadcasts(SetReceiveBroadcasts { stream }),
+            IntermediateServerToProxyMessage::Shutdown(Shutdown { stream }),
+            IntermediateServerToProxyMessage::ResubscribeChannels(ResubscribeChannels { stream }),
+            IntermediateServerToProxyMessage::SetSeeHiddenChannels(SetSeeHiddenChannels {
+                stream,
+                see: true,
+            }),
+            IntermediateServerToProxyMessage::SetEncryption(SetEncryption {
+                stream,
+                key: [1; 16],
+            }),
+        ];
+
+        for (i, message) in messages.iter().enumerate() {
+            let transformed = message.transform_for_proxy(proxy_id).unwrap();
+
+            assert!(
+                matches!(
+                    (message, transformed),
+                    (
+                        IntermediateServerToProxyMessage::UpdatePlayerPositions(_),
+                        ServerToProxyMessage::UpdatePlayerPositions(_)
+                    ) | (
+                        IntermediateServerToProxyMessage::AddChannel(_),
+                        ServerToProxyMessage::AddChannel(_)
+                    ) | (
+                        IntermediateServerToProxyMessage::UpdateChannelPositions(_),
+                        ServerToProxyMessage::UpdateChannelPositions(_)
+                    ) | (
+                        IntermediateServerToProxyMessage::RemoveChannel(_),
+                        ServerToProxyMessage::RemoveChannel(_)
+                    ) | (
+                        IntermediateServerToProxyMessage::SubscribeChannelPackets(_),
+                        ServerToProxyMessage::SubscribeChannelPackets(_)
+                    ) | (
+                        IntermediateServerToProxyMessage::BroadcastGlobal(_),
+                        ServerToProxyMessage::BroadcastGlobal(_)
+                    ) | (
+                        IntermediateServerToProxyMessage::BroadcastLocal(_),
+                        ServerToProxyMessage::BroadcastLocal(_)
+                    ) | (
+                        IntermediateServerToProxyMessage::BroadcastChannel(_),
+                        ServerToProxyMessage::BroadcastChannel(_)
+                    ) | (
+                        IntermediateServerToProxyMessage::Unicast(_),
+                        ServerToProxyMessage::Unicast(_)
+                    ) | (
+                        IntermediateServerToProxyMessage::Multicast(_),
+                        ServerToProxyMessage::Multicast(_)
+                    ) | (
+                        IntermediateServerToProxyMessage::SetReceiveBroadcasts(_),
+                        ServerToProxyMessage::SetReceiveBroadcasts(_)
+                    ) | (
+                        IntermediateServerToProxyMessage::Shutdown(_),
+                        ServerToProxyMessage::Shutdown(_)
+                    ) | (
+                        IntermediateServerToProxyMessage::ResubscribeChannels(_),
+                        ServerToProxyMessage::ResubscribeChannels(_)
+                    ) | (
+                        IntermediateServerToProxyMessage::SetSeeHiddenChannels(_),
+                        ServerToProxyMessage::SetSeeHiddenChannels(_)
+                    ) | (
+                        IntermediateServerToProxyMessage::SetEncryption(_),
+                        ServerToProxyMessage::SetEncryption(_)
+                    )
+                ),
+                "message {i} was transformed into a different variant"
+            );
+        }
+    }
+
+    #[test]
+    fn messages_for_other_proxies_are_skipped() {
+        let stream = ConnectionId::new(1, ProxyId::new(0));
+        let other_proxy = ProxyId::new(1);
+
+        let message = IntermediateServerToProxyMessage::Shutdown(Shutdown { stream });
+        assert!(message.transform_for_proxy(other_proxy).is_none());
+
+        let message = IntermediateServerToProxyMessage::Unicast(Unicast {
+            stream,
+            data: b"data",
+        });
+        assert!(message.transform_for_proxy(other_proxy).is_none());
+    }
+
+    #[test]
+    fn excluded_connections_are_filtered_per_proxy() {
+        let [a, b] = [1, 2].map(|stream| ConnectionId::new(stream, ProxyId::new(0)));
+        let c = ConnectionId::new(3, ProxyId::new(1));
+
+        let message = IntermediateServerToProxyMessage::BroadcastGlobal(BroadcastGlobal {
+            exclude: Exclude::from(&[a, c, b][..]),
+            world: None,
+            data: b"data",
+        });
+
+        let exclude = |proxy_id| match message.transform_for_proxy(ProxyId::new(proxy_id)) {
+            Some(ServerToProxyMessage::BroadcastGlobal(message)) => message.exclude,
+            _ => unreachable!(),
+        };
+
+        assert_eq!(exclude(0), hyperion_proto::Exclude {
+            first: 1,
+            rest: vec![2]
+        });
+        assert_eq!(exclude(1), hyperion_proto::Exclude {
+            first: 3,
+            rest: Vec::new()
+        });
+        assert_eq!(exclude(2), hyperion_proto::Exclude::default());
+
+        assert!(message.specialized_for(ProxyId::new(0)));
+        assert!(message.specialized_for(ProxyId::new(1)));
+        assert!(!message.specialized_for(ProxyId::new(2)));
+    }
+
+    #[test]
+    fn multicasts_only_carry_the_streams_of_each_proxy() {
+        let [a, b] = [1, 2].map(|stream| ConnectionId::new(stream, ProxyId::new(0)));
+        let c = ConnectionId::new(3, ProxyId::new(1));
+
+        let message = IntermediateServerToProxyMessage::Multicast(Multicast {
+            streams: &[a, c, b],
+            world: Some(1),
+            data: b"data",
+        });
+
+        let streams = |proxy_id| match message.transform_for_proxy(ProxyId::new(proxy_id)) {
+            Some(ServerToProxyMessage::Multicast(message)) => {
+                assert_eq!(message.world, Some(1));
+                Some(message.streams)
+            }
+            None => None,
+            _ => unreachable!(),
+        };
+
+        assert_eq!(streams(0), Some(vec![1, 2]));
+        assert_eq!(streams(1), Some(vec![3]));
+        assert_eq!(streams(2), None);
+    }
+
+    #[test]
+    fn exclude_keeps_a_single_connection_inline() {
+        let stream = ConnectionId::new(1, ProxyId::new(0));
+
+        assert_eq!(Exclude::from(&[][..]), Exclude::None);
+        assert_eq!(Exclude::from(&[stream][..]), Exclude::One(stream));
+        assert_eq!(Exclude::from(Some(stream)), Exclude::One(stream));
+    }
+}
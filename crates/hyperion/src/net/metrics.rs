@@ -0,0 +1,125 @@
+//! Counters describing network traffic.
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use bevy_ecs::resource::Resource;
+#[cfg(feature = "reflect")]
+use {bevy_ecs::reflect::ReflectResource, bevy_reflect::Reflect};
+
+use crate::{
+    ingress::counters::{PacketCounts, PacketTotals},
+    net::ConnectionId,
+};
+
+/// Running totals of network events since the server started.
+///
+/// The counters are atomic because packets are decoded in parallel. They are only ever
+/// incremented, so readers should compare two samples to get a rate.
+#[derive(Resource, Default, Debug)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Resource))]
+pub struct NetworkMetrics {
+    /// The number of packets received from clients
+    pub packets_received: AtomicU64,
+    /// The number of bytes received from clients, before decompression
+    pub bytes_received: AtomicU64,
+    /// The number of bytes sent to proxies as of the end of the last tick
+    pub bytes_sent: AtomicU64,
+    /// The number of packets rejected for exceeding the size or decompression ratio limits
+    pub rejected_packets: AtomicU64,
+    /// The number of times a connection's remaining packets were deferred to the next tick
+    /// because it exceeded its per-tick budget
+    pub deferrals: AtomicU64,
+    /// The number of connections kicked for repeatedly exceeding their per-tick budget
+    pub flood_kicks: AtomicU64,
+    /// The number of connections kicked for not keeping up with the data sent to them
+    pub slow_kicks: AtomicU64,
+    /// The number of connections disconnected for staying in the handshake, status or login state
+    /// for too long
+    pub state_timeouts: AtomicU64,
+    /// The number of connections refused because their IP address already had too many
+    /// connections which have not entered the play state
+    pub ip_limit_rejections: AtomicU64,
+    /// The number of TLS handshakes with proxies
+    pub proxy_handshakes: AtomicU64,
+    /// The number of TLS handshakes with proxies that resumed an earlier session
+    pub resumed_proxy_handshakes: AtomicU64,
+    /// The duration of the last TLS handshake with a proxy in microseconds
+    pub last_proxy_handshake_micros: AtomicU64,
+    /// The number of decoded packets of each type, keyed by `state::Packet`
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    decoded: Mutex<BTreeMap<&'static str, u64>>,
+    /// The number of play packets received with each packet ID, including packets which were
+    /// merged or failed to decode
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    play_packets: PacketTotals,
+    /// The current backlog of each connection with a backlog, as reported by its proxy
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    backlogs: Mutex<Vec<(ConnectionId, u64)>>,
+}
+
+impl NetworkMetrics {
+    pub(crate) fn record_decoded(&self, packet: &'static str, count: usize) {
+        if count == 0 {
+            return;
+        }
+
+        *self.decoded.lock().unwrap().entry(packet).or_default() += count as u64;
+    }
+
+    /// Returns the number of decoded packets of each type that has been received at least once
+    #[must_use]
+    pub fn decoded_packets(&self) -> Vec<(&'static str, u64)> {
+        self.decoded
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(&packet, &count)| (packet, count))
+            .collect()
+    }
+
+    pub(crate) fn record_play_packets(&self, counts: &PacketCounts) {
+        self.play_packets.add(counts);
+    }
+
+    /// Returns the number of play packets received with each packet ID that has been received
+    /// at least once
+    #[must_use]
+    pub fn play_packets(&self) -> Vec<(i32, u64)> {
+        self.play_packets.get()
+    }
+
+    pub(crate) fn set_bytes_sent(&self, bytes: u64) {
+        self.bytes_sent.store(bytes, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_proxy_handshake(&self, duration: Duration, resumed: bool) {
+        self.proxy_handshakes.fetch_add(1, Ordering::Relaxed);
+        if resumed {
+            self.resumed_proxy_handshakes
+                .fetch_add(1, Ordering::Relaxed);
+        }
+        self.last_proxy_handshake_micros.store(
+            u64::try_from(duration.as_micros()).unwrap_or(u64::MAX),
+            Ordering::Relaxed,
+        );
+    }
+
+    /// Returns the number of bytes the proxy has queued for each connection with a backlog,
+    /// largest first
+    #[must_use]
+    pub fn backlogs(&self) -> Vec<(ConnectionId, u64)> {
+        self.backlogs.lock().unwrap().clone()
+    }
+
+    pub(crate) fn set_backlogs(&self, mut backlogs: Vec<(ConnectionId, u64)>) {
+        backlogs.sort_unstable_by(|(_, a), (_, b)| b.cmp(a));
+        *self.backlogs.lock().unwrap() = backlogs;
+    }
+}
@@ -1,8 +1,13 @@
 //! All the networking related code.
 
 use std::{
+    any::TypeId,
     cell::{Cell, RefCell},
     fmt::Debug,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
 };
 
 use bevy_ecs::{component::Component, entity::Entity, resource::Resource};
@@ -10,12 +15,14 @@ use byteorder::WriteBytesExt;
 use bytes::{Bytes, BytesMut};
 pub use decoder::PacketDecoder;
 use glam::I16Vec2;
-use hyperion_proto::{ChunkPosition, ServerToProxyMessage};
+use hyperion_proto::{ChunkPosition, Features, ServerToProxyMessage};
 use hyperion_utils::EntityExt;
 use libdeflater::CompressionLvl;
-use rustc_hash::FxHashMap;
+use rustc_hash::{FxBuildHasher, FxHashMap, FxHashSet};
+use serde::{Deserialize, Serialize};
 use thread_local::ThreadLocal;
-use tracing::error;
+use tracing::{error, warn};
+use valence_protocol::{Decode, Packet, PacketState, VarInt, packets::play};
 #[cfg(feature = "reflect")]
 use {
     bevy_ecs::reflect::{ReflectComponent, ReflectResource},
@@ -23,20 +30,28 @@ use {
 };
 
 use crate::{
-    Global, PacketBundle, Scratch,
+    PacketBundle, Scratch, Shared,
     net::{
+        batch::{BatchOrder, EgressBatch},
         encoder::{PacketEncoder, append_packet_without_compression},
-        intermediate::IntermediateServerToProxyMessage,
+        filter::{FilterResult, PacketContext, PacketFilters},
+        intermediate::{Exclude, IntermediateServerToProxyMessage},
+        recipients::{RecipientFilter, Recipients},
     },
-    simulation::EgressComm,
+    simulation::{EgressComm, world::WorldId},
 };
 
 pub mod agnostic;
+pub mod batch;
 pub mod decoder;
 pub mod encoder;
+pub mod filter;
+pub mod fuzz;
 pub mod intermediate;
+pub mod metrics;
 pub mod packets;
 pub mod proxy;
+pub mod recipients;
 
 /// The Minecraft protocol version this library currently targets.
 pub const PROTOCOL_VERSION: i32 = 763;
@@ -44,10 +59,109 @@ pub const PROTOCOL_VERSION: i32 = 763;
 /// The maximum number of bytes that can be sent in a single packet.
 pub const MAX_PACKET_SIZE: usize = valence_protocol::MAX_PACKET_SIZE as usize;
 
+/// The number of [`DataBundle`] buffers kept for reuse on each thread. Threads rarely build more
+/// bundles at once than this.
+const MAX_POOLED_BUNDLE_BUFFERS: usize = 4;
+
+/// [`DataBundle`] buffers that grew beyond this, such as those of chunk bundles, are freed instead
+/// of kept for reuse
+const MAX_POOLED_BUNDLE_CAPACITY: usize = 4 * MAX_PACKET_SIZE;
+
+/// The maximum number of packets the client accepts between two bundle delimiters. Larger bundles
+/// are split into several bundles by [`DataBundle::add_bundle`].
+pub const MAX_BUNDLE_PACKETS: usize = 4096;
+
 /// The stringified name of the Minecraft version this library currently
 /// targets.
 pub const MINECRAFT_VERSION: &str = "1.20.1";
 
+/// Why a packet could not be sent
+#[derive(Debug, thiserror::Error)]
+pub enum SendError {
+    /// The connection already has as much pending data as [`ConnectionLimits::max_pending_bytes`]
+    /// allows. Sending can be retried after the next flush.
+    #[error("the output buffer of the connection is full, {needed} more bytes are needed")]
+    BufferFull {
+        /// How many more bytes the buffer would need to fit the packet
+        needed: usize,
+    },
+    /// The proxy of the connection is not connected
+    #[error("the connection is disconnected")]
+    Disconnected,
+    /// The packet could not be encoded
+    #[error(transparent)]
+    Encode(#[from] anyhow::Error),
+    /// The [`RecipientFilter`] of a [`Broadcast::filter`] was not registered with
+    /// [`register_recipient_filter`](recipients::register_recipient_filter)
+    #[error("the recipient filter {0} is not registered")]
+    UnregisteredFilter(&'static str),
+}
+
+/// The result of sending a packet
+pub type SendResult = Result<(), SendError>;
+
+/// Extension methods for [`SendResult`]
+pub trait SendResultExt {
+    /// Panics if the packet could not be sent, unless the connection is disconnected. Packets
+    /// for a disconnected connection can be dropped because the connection is removed separately.
+    #[track_caller]
+    fn unwrap_or_disconnected(self);
+}
+
+impl SendResultExt for SendResult {
+    fn unwrap_or_disconnected(self) {
+        match self {
+            Ok(()) | Err(SendError::Disconnected) => {}
+            Err(e) => panic!("failed to send packet: {e}"),
+        }
+    }
+}
+
+/// Limits on the data queued for a single connection
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect))]
+#[serde(default)]
+pub struct ConnectionLimits {
+    /// The maximum number of bytes sent to a connection between two flushes by
+    /// [`Compose::try_unicast`]. Other sends are never refused but still count towards it.
+    pub max_pending_bytes: usize,
+    /// Connections whose proxy has more bytes than this queued for them are not sent low
+    /// priority data, such as chunks and [`Broadcast::low_priority`] broadcasts
+    pub soft_backlog_bytes: u64,
+    /// Connections whose proxy has more bytes than this queued for them for
+    /// [`ConnectionLimits::hard_backlog_secs`] are disconnected
+    pub hard_backlog_bytes: u64,
+    pub hard_backlog_secs: u64,
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        Self {
+            max_pending_bytes: 4 * 1024 * 1024,
+            soft_backlog_bytes: 2 * 1024 * 1024,
+            hard_backlog_bytes: 16 * 1024 * 1024,
+            hard_backlog_secs: 10,
+        }
+    }
+}
+
+/// The IDs of the play packets which are written to the proxy as soon as they are sent instead of
+/// at the end of the tick. See [`IoBuf::set_flush_immediate`].
+struct FlushImmediate(FxHashSet<i32>);
+
+impl Default for FlushImmediate {
+    fn default() -> Self {
+        // Packets that the client waits for, or whose delay the player notices in combat
+        Self(FxHashSet::from_iter([
+            play::PlayerPositionLookS2c::ID,
+            play::PlayerActionResponseS2c::ID,
+            play::EntityDamageS2c::ID,
+            play::DamageTiltS2c::ID,
+            play::KeepAliveS2c::ID,
+        ]))
+    }
+}
+
 /// A unique identifier for a proxy to game server connection
 #[derive(Component, Copy, Clone, Debug, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
@@ -170,6 +284,38 @@ impl From<Entity> for ChannelId {
     }
 }
 
+/// The entity of every [`Channel`] by its [`ChannelId`].
+///
+/// Channel ids are entity indices, which are reused once an entity is despawned. Channel ids
+/// from the proxy are resolved through this instead of the entity index, so an id which is
+/// stale by the time it arrives does not resolve to whichever entity reused the index.
+#[derive(Resource, Default, Debug)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Resource))]
+pub struct Channels {
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    entities: FxHashMap<u32, Entity>,
+}
+
+impl Channels {
+    /// Returns the channel entity with the id `channel`, if it has not been despawned
+    #[must_use]
+    pub fn get(&self, channel: ChannelId) -> Option<Entity> {
+        self.entities.get(&channel.inner()).copied()
+    }
+
+    pub(crate) fn insert(&mut self, entity: Entity) {
+        self.entities
+            .insert(ChannelId::from(entity).inner(), entity);
+    }
+
+    pub(crate) fn remove(&mut self, entity: Entity) {
+        let id = ChannelId::from(entity).inner();
+        if self.entities.get(&id) == Some(&entity) {
+            self.entities.remove(&id);
+        }
+    }
+}
+
 /// A singleton that can be used to compose and encode packets.
 #[derive(Resource)]
 #[cfg_attr(feature = "reflect", derive(Reflect), reflect(Resource))]
@@ -180,91 +326,286 @@ pub struct Compose {
     compressor: ThreadLocal<RefCell<libdeflater::Compressor>>,
     #[cfg_attr(feature = "reflect", reflect(ignore))]
     scratch: ThreadLocal<RefCell<Scratch>>,
-    global: Global,
+    #[cfg_attr(
+        feature = "reflect",
+        reflect(ignore, default = "crate::common::dummy_reflect_shared")
+    )]
+    shared: Arc<Shared>,
     io_buf: IoBuf,
 }
 
+/// Packets which are encoded into one buffer and sent together.
+///
+/// The buffer is taken from a per-thread pool and returned to it when the bundle is dropped, so
+/// building bundles does not allocate once the pool is warm.
 #[must_use]
 pub struct DataBundle<'a> {
     compose: &'a Compose,
-    data: BytesMut,
+    data: Vec<u8>,
+    /// Whether a packet of the bundle is flushed immediately, see [`IoBuf::set_flush_immediate`]
+    immediate: bool,
+    /// The number of packets in the open bundle of [`DataBundle::add_bundle`], if there is one
+    bundled: Option<usize>,
 }
 
 impl<'a> DataBundle<'a> {
     pub fn new(compose: &'a Compose) -> Self {
         Self {
             compose,
-            data: BytesMut::new(),
+            data: compose.io_buf.take_bundle_buffer(),
+            immediate: false,
+            bundled: None,
         }
     }
 
     pub fn add_packet(&mut self, pkt: impl PacketBundle) -> anyhow::Result<()> {
-        let data = self.compose.io_buf.encode_packet(pkt, self.compose)?;
-        // todo: test to see if this ever actually unsplits
-        self.data.unsplit(data);
-        Ok(())
+        self.immediate |= self.compose.io_buf.is_flush_immediate(&pkt);
+        self.make_room_in_bundle();
+        self.compose
+            .io_buf
+            .encode_packet_into(pkt, self.compose, &mut self.data)
     }
 
+    /// Adds packets which are encoded already. Inside of [`DataBundle::add_bundle`], each packet
+    /// of `raw` counts towards [`MAX_BUNDLE_PACKETS`].
     pub fn add_raw(&mut self, raw: &[u8]) {
-        self.data.extend_from_slice(raw);
+        if self.bundled.is_none() {
+            self.data.extend_from_slice(raw);
+            return;
+        }
+
+        let mut rest = raw;
+        while !rest.is_empty() {
+            // Data which is not made of whole packets is kept together
+            let frame_len = frame_len(rest).unwrap_or(rest.len());
+            self.make_room_in_bundle();
+            self.data.extend_from_slice(&rest[..frame_len]);
+            rest = &rest[frame_len..];
+        }
     }
 
-    pub fn unicast(&self, stream: ConnectionId) -> anyhow::Result<()> {
+    /// Adds the packets added by `f` as a bundle, which the client applies together in a single
+    /// tick. This keeps it from showing an entity before its equipment and metadata arrived, for
+    /// example.
+    ///
+    /// Bundles with more than [`MAX_BUNDLE_PACKETS`] packets are split into several bundles with
+    /// a warning. Calling this while a bundle is open adds the packets to the open bundle, since
+    /// bundles cannot be nested. If `f` fails, none of its packets are added.
+    ///
+    /// A bundle is never split up on the way to the client: the proxies forward the data of each
+    /// send as a whole and in order, and batching only ever joins whole sends.
+    pub fn add_bundle(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        if self.bundled.is_some() {
+            return f(self);
+        }
+
+        let start = self.data.len();
+        self.add_bundle_delimiter();
+        self.bundled = Some(0);
+
+        let result = f(self);
+        let packets = self.bundled.take();
+
+        if result.is_err() || packets == Some(0) {
+            self.data.truncate(start);
+            return result;
+        }
+
+        self.add_bundle_delimiter();
+        Ok(())
+    }
+
+    fn add_bundle_delimiter(&mut self) {
+        self.compose
+            .io_buf
+            .encode_packet_into(&play::BundleSplitterS2c, self.compose, &mut self.data)
+            .expect("the bundle delimiter is empty, so it is always encodable");
+    }
+
+    /// Counts a packet which is about to be added to the open bundle, and starts a new bundle if
+    /// the open one is full
+    fn make_room_in_bundle(&mut self) {
+        let Some(packets) = self.bundled else {
+            return;
+        };
+
+        if packets < MAX_BUNDLE_PACKETS {
+            self.bundled = Some(packets + 1);
+            return;
+        }
+
+        warn!("splitting a bundle with more than {MAX_BUNDLE_PACKETS} packets");
+        self.add_bundle_delimiter();
+        self.add_bundle_delimiter();
+        self.bundled = Some(1);
+    }
+
+    /// The encoded packets of the bundle
+    #[cfg(test)]
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn unicast(&self, stream: ConnectionId) -> SendResult {
         if self.data.is_empty() {
             return Ok(());
         }
 
-        self.compose.io_buf.unicast_raw(&self.data, stream);
+        self.compose.io_buf.unicast_raw(&self.data, stream)?;
+        self.flush(Some(stream));
+        Ok(())
+    }
+
+    /// Like [`DataBundle::unicast`], but fails with [`SendError::BufferFull`] instead of exceeding
+    /// [`ConnectionLimits::max_pending_bytes`]. See [`Compose::try_unicast`].
+    pub fn try_unicast(&self, stream: ConnectionId) -> SendResult {
+        if self.data.is_empty() {
+            return Ok(());
+        }
+
+        self.compose.io_buf.try_unicast_raw(&self.data, stream)?;
+        self.flush(Some(stream));
         Ok(())
     }
 
     // todo: use builder pattern for excluding
-    pub fn broadcast_local(&self, center: I16Vec2) -> anyhow::Result<()> {
+    pub fn broadcast_local(&self, center: I16Vec2) -> SendResult {
         if self.data.is_empty() {
             return Ok(());
         }
 
         self.compose
             .io_buf
-            .broadcast_local_raw(&self.data, center, None);
+            .broadcast_local_raw(&self.data, center, Exclude::None);
+        self.flush(None);
         Ok(())
     }
 
     // todo: use builder pattern for excluding
-    pub fn broadcast_channel(&self, channel: ChannelId) -> anyhow::Result<()> {
+    pub fn broadcast_channel(&self, channel: ChannelId) -> SendResult {
         if self.data.is_empty() {
             return Ok(());
         }
 
         self.compose
             .io_buf
-            .broadcast_channel_raw(&self.data, channel, None);
+            .broadcast_channel_raw(&self.data, channel, Exclude::None);
+        self.flush(None);
 
         Ok(())
     }
+
+    /// Like [`DataBundle::broadcast_channel`], but batched. See [`BatchOrder`].
+    pub fn broadcast_channel_batched(&self, channel: ChannelId, order: BatchOrder) -> SendResult {
+        if self.data.is_empty() {
+            return Ok(());
+        }
+
+        self.compose
+            .io_buf
+            .batch
+            .add_channel(channel.inner(), Exclude::None, order, &self.data);
+
+        Ok(())
+    }
+
+    /// Wakes the task writing to the proxy of `stream`, or to every proxy for broadcasts, if the
+    /// bundle contains a packet which is flushed immediately
+    fn flush(&self, stream: Option<ConnectionId>) {
+        if !self.immediate {
+            return;
+        }
+
+        match stream {
+            Some(stream) => self.compose.io_buf.flush_proxy(stream.proxy_id()),
+            None => self.compose.io_buf.flush_proxies(),
+        }
+    }
+
+    /// Sends the bundle to the connections which are waiting to subscribe to `channel`
+    pub(crate) fn send_subscribe_channel_packets(
+        &self,
+        channel: ChannelId,
+        exclude: Option<ConnectionId>,
+    ) {
+        self.compose
+            .io_buf
+            .send_subscribe_channel_packets(channel, &self.data, exclude);
+    }
+}
+
+/// The length of the first packet frame of `data`, including its length prefix
+fn frame_len(data: &[u8]) -> anyhow::Result<usize> {
+    let mut rest = data;
+    let packet_len = usize::try_from(VarInt::decode(&mut rest)?.0)?;
+    let frame_len = data.len() - rest.len() + packet_len;
+    anyhow::ensure!(frame_len <= data.len(), "packet is cut off");
+    Ok(frame_len)
+}
+
+impl Drop for DataBundle<'_> {
+    fn drop(&mut self) {
+        self.compose
+            .io_buf
+            .return_bundle_buffer(std::mem::take(&mut self.data));
+    }
 }
 
 impl Compose {
     #[must_use]
-    pub const fn new(compression_lvl: CompressionLvl, global: Global, io_buf: IoBuf) -> Self {
+    pub const fn new(compression_lvl: CompressionLvl, shared: Arc<Shared>, io_buf: IoBuf) -> Self {
         Self {
             compression_lvl,
             compressor: ThreadLocal::new(),
             scratch: ThreadLocal::new(),
-            global,
+            shared,
             io_buf,
         }
     }
 
+    /// Data shared between the IO thread and the ECS framework.
     #[must_use]
-    #[expect(missing_docs)]
-    pub const fn global(&self) -> &Global {
-        &self.global
+    pub fn shared(&self) -> &Shared {
+        &self.shared
     }
 
-    #[expect(missing_docs)]
-    pub const fn global_mut(&mut self) -> &mut Global {
-        &mut self.global
+    /// Registers `filter` to run over every packet right before it is sent to the proxies. See
+    /// [the filter module](crate::net::filter) for the order filters run in.
+    ///
+    /// Broadcasts are filtered once for all connections receiving them. The delimiters of
+    /// [bundles](DataBundle::add_bundle) are passed on without running the filters.
+    pub fn register_egress_filter(
+        &mut self,
+        priority: i32,
+        filter: impl Fn(&mut PacketContext<'_>) -> FilterResult + Send + Sync + 'static,
+    ) {
+        self.update_filter_compression();
+        self.io_buf
+            .filters
+            .register_egress(priority, Box::new(filter));
+    }
+
+    /// Registers `filter` to run over every packet right after it is received and decompressed,
+    /// before it is decoded. See [the filter module](crate::net::filter) for the order filters run
+    /// in.
+    pub fn register_ingress_filter(
+        &mut self,
+        priority: i32,
+        filter: impl Fn(&mut PacketContext<'_>) -> FilterResult + Send + Sync + 'static,
+    ) {
+        self.update_filter_compression();
+        self.io_buf
+            .filters
+            .register_ingress(priority, Box::new(filter));
+    }
+
+    fn update_filter_compression(&mut self) {
+        self.io_buf
+            .filters
+            .set_compression(self.shared.compression_threshold, self.compression_lvl);
     }
 
     /// Broadcast globally to all players
@@ -277,7 +618,11 @@ impl Compose {
         Broadcast {
             packet,
             compose: self,
-            exclude: None,
+            exclude: Exclude::None,
+            world: None,
+            batched: false,
+            low_priority: false,
+            filter: None,
         }
     }
 
@@ -302,11 +647,9 @@ impl Compose {
         BroadcastLocal {
             packet,
             compose: self,
-            exclude: None,
-            center: ChunkPosition {
-                x: center.x,
-                z: center.y,
-            },
+            exclude: Exclude::None,
+            center: ChunkPosition::new(center.x, center.y),
+            low_priority: false,
         }
     }
 
@@ -322,13 +665,14 @@ impl Compose {
         BroadcastChannel {
             packet,
             compose: self,
-            exclude: None,
+            exclude: Exclude::None,
             channel,
+            batch: None,
         }
     }
 
     /// Send a packet to a single player.
-    pub fn unicast<P>(&self, packet: P, stream_id: ConnectionId) -> anyhow::Result<()>
+    pub fn unicast<P>(&self, packet: P, stream_id: ConnectionId) -> SendResult
     where
         P: PacketBundle,
     {
@@ -339,16 +683,45 @@ impl Compose {
             // todo: Should we have this true by default, or is there a better way?
             // Or a better word for no_compress, or should we just use negative field names?
             compress: true,
+            capped: false,
         }
         .send()
     }
 
-    /// Send a packet to a single player without compression.
-    pub fn unicast_no_compression<P>(
+    /// Sends the packets added by `f` to a single player as a bundle, which the client applies
+    /// together in a single tick. See [`DataBundle::add_bundle`].
+    pub fn bundle(
         &self,
-        packet: &P,
         stream_id: ConnectionId,
-    ) -> anyhow::Result<()>
+        f: impl FnOnce(&mut DataBundle<'_>) -> anyhow::Result<()>,
+    ) -> SendResult {
+        let mut bundle = DataBundle::new(self);
+        bundle.add_bundle(f)?;
+        bundle.unicast(stream_id)
+    }
+
+    /// Send a packet to a single player unless that would queue more than
+    /// [`ConnectionLimits::max_pending_bytes`] for them since the last flush, in which case
+    /// [`SendError::BufferFull`] is returned and nothing is queued.
+    ///
+    /// This should be used for large packets that can be sent later, so that a stalled client
+    /// cannot make the server queue an unbounded amount of data for it.
+    pub fn try_unicast<P>(&self, packet: P, stream_id: ConnectionId) -> SendResult
+    where
+        P: PacketBundle,
+    {
+        Unicast {
+            packet,
+            stream_id,
+            compose: self,
+            compress: true,
+            capped: true,
+        }
+        .send()
+    }
+
+    /// Send a packet to a single player without compression.
+    pub fn unicast_no_compression<P>(&self, packet: &P, stream_id: ConnectionId) -> SendResult
     where
         P: valence_protocol::Packet + valence_protocol::Encode,
     {
@@ -357,6 +730,7 @@ impl Compose {
             stream_id,
             compose: self,
             compress: false,
+            capped: false,
         }
         .send()
     }
@@ -364,7 +738,7 @@ impl Compose {
     #[must_use]
     #[allow(clippy::missing_const_for_fn, reason = "this is a false positive")]
     pub(crate) fn encoder(&self) -> PacketEncoder {
-        let threshold = self.global.shared.compression_threshold;
+        let threshold = self.shared.compression_threshold;
         PacketEncoder::new(threshold)
     }
 
@@ -388,12 +762,36 @@ impl Compose {
 pub struct IoBuf {
     // system_on: ThreadLocal<Cell<u32>>,
     // broadcast_buffer: ThreadLocal<RefCell<BytesMut>>,
+    /// The buffer each thread encodes single packets into before sending them
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    encode_buffer: ThreadLocal<RefCell<Vec<u8>>>,
+    /// Cleared buffers of dropped [`DataBundle`]s, which keep their capacity
     #[cfg_attr(feature = "reflect", reflect(ignore))]
-    temp_buffer: ThreadLocal<RefCell<BytesMut>>,
+    bundle_buffers: ThreadLocal<RefCell<Vec<Vec<u8>>>>,
     #[cfg_attr(feature = "reflect", reflect(ignore))]
     idx: ThreadLocal<Cell<u16>>,
     #[cfg_attr(feature = "reflect", reflect(ignore))]
     egress_comms: FxHashMap<ProxyId, EgressComm>,
+    /// Messages that are sent together when the batch is flushed
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    batch: EgressBatch,
+    /// The number of bytes sent to all proxies
+    bytes_sent: AtomicU64,
+    /// The number of bytes unicast to each connection since the last flush. Each connection has
+    /// its own counter, so threads unicasting to different connections do not contend.
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    pending: papaya::HashMap<ConnectionId, AtomicUsize, FxBuildHasher>,
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    limits: ConnectionLimits,
+    /// Connections with a backlog above [`ConnectionLimits::soft_backlog_bytes`]
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    congested: FxHashSet<ConnectionId>,
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    filters: PacketFilters,
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    flush_immediate: FlushImmediate,
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    recipients: Recipients,
 }
 
 impl IoBuf {
@@ -412,9 +810,175 @@ impl IoBuf {
         }
     }
 
+    /// Adds a proxy which receives every message sent to it through `tx`, for benchmarks outside
+    /// of this crate
+    #[cfg(feature = "test-util")]
+    pub fn add_test_proxy(
+        &mut self,
+        proxy_id: ProxyId,
+        tx: tokio::sync::mpsc::UnboundedSender<Bytes>,
+    ) {
+        self.add_proxy(proxy_id, tx.into());
+    }
+
     pub(crate) fn remove_proxy(&mut self, proxy_id: ProxyId) -> Option<EgressComm> {
         self.egress_comms.remove(&proxy_id)
     }
+
+    /// The total number of bytes sent to proxies, including the framing of proxy messages
+    #[must_use]
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent.load(Ordering::Relaxed)
+    }
+
+    #[must_use]
+    pub const fn limits(&self) -> ConnectionLimits {
+        self.limits
+    }
+
+    pub const fn set_limits(&mut self, limits: ConnectionLimits) {
+        self.limits = limits;
+    }
+
+    /// Whether the backlog of `stream` is above [`ConnectionLimits::soft_backlog_bytes`], in which
+    /// case it should not be sent low priority data
+    #[must_use]
+    pub fn is_congested(&self, stream: ConnectionId) -> bool {
+        self.congested.contains(&stream)
+    }
+
+    /// The cached members of every [`RecipientFilter`]
+    #[must_use]
+    pub const fn recipients(&self) -> &Recipients {
+        &self.recipients
+    }
+
+    pub(crate) fn set_congested(&mut self, congested: FxHashSet<ConnectionId>) {
+        self.congested = congested;
+    }
+
+    /// Adds every congested connection to `exclude`
+    fn exclude_congested(&self, exclude: Exclude) -> Exclude {
+        if self.congested.is_empty() {
+            return exclude;
+        }
+
+        let mut connections = exclude.as_slice().to_vec();
+        connections.extend(
+            self.congested
+                .iter()
+                .filter(|connection| !exclude.as_slice().contains(connection)),
+        );
+        Exclude::from(&connections[..])
+    }
+
+    /// The number of bytes unicast to `stream` since the last flush
+    #[must_use]
+    pub fn pending_bytes(&self, stream: ConnectionId) -> usize {
+        self.pending
+            .pin()
+            .get(&stream)
+            .map_or(0, |pending| pending.load(Ordering::Relaxed))
+    }
+
+    /// Sets whether packets of type `P` are written to the proxy as soon as they are sent.
+    ///
+    /// Everything sent to a proxy is written to it in a single write at the end of the tick by
+    /// [`IoBuf::flush_batch`]. Sending one of these packets without [`Broadcast::batched`] wakes
+    /// the task writing to its proxy instead, which then writes everything sent to the proxy so
+    /// far. A packet that is flushed immediately therefore never overtakes packets sent before it,
+    /// and always arrives before the batch of its tick.
+    ///
+    /// By default, this is enabled for teleports, block acknowledgments, damage and keep alives.
+    pub fn set_flush_immediate<P: Packet>(&mut self, immediate: bool) {
+        debug_assert!(matches!(P::STATE, PacketState::Play));
+
+        if immediate {
+            self.flush_immediate.0.insert(P::ID);
+        } else {
+            self.flush_immediate.0.remove(&P::ID);
+        }
+    }
+
+    fn is_flush_immediate(&self, packet: &impl PacketBundle) -> bool {
+        packet
+            .play_packet_id()
+            .is_some_and(|id| self.flush_immediate.0.contains(&id))
+    }
+
+    /// Wakes the task writing to the proxy with `proxy_id`
+    fn flush_proxy(&self, proxy_id: ProxyId) {
+        if let Some(egress_comm) = self.egress_comms.get(&proxy_id) {
+            egress_comm.flush.notify_one();
+        }
+    }
+
+    /// Wakes the tasks writing to every proxy
+    fn flush_proxies(&self) {
+        for egress_comm in self.egress_comms.values() {
+            egress_comm.flush.notify_one();
+        }
+    }
+
+    /// Sends every batched message and lets every proxy write what was sent to it during the tick.
+    /// Each proxy receives all of its batched messages in a single message.
+    pub(crate) fn flush_batch(&mut self) {
+        let Self {
+            batch,
+            egress_comms,
+            bytes_sent,
+            pending,
+            filters,
+            ..
+        } = self;
+
+        pending.pin().clear();
+
+        let mut buffers: FxHashMap<ProxyId, Vec<u8>> = egress_comms
+            .keys()
+            .map(|&proxy_id| (proxy_id, Vec::new()))
+            .collect();
+
+        let proxies: Vec<_> = egress_comms
+            .iter()
+            .map(|(&proxy_id, egress_comm)| (proxy_id, egress_comm.features))
+            .collect();
+        batch.flush(|message| {
+            Self::with_filtered(filters, message, |message| {
+                Self::encode_for_proxies(message, proxies.iter().copied(), |proxy_id, encoded| {
+                    buffers
+                        .get_mut(&proxy_id)
+                        .unwrap()
+                        .extend_from_slice(encoded);
+                });
+            });
+        });
+
+        for (proxy_id, buffer) in buffers {
+            if buffer.is_empty() {
+                continue;
+            }
+
+            bytes_sent.fetch_add(buffer.len() as u64, Ordering::Relaxed);
+            // A proxy that disconnected is removed once the proxy task notices
+            if egress_comms[&proxy_id]
+                .tx
+                .send(Bytes::from(buffer))
+                .is_err()
+            {
+                warn!("dropped a batch for {proxy_id:?} because its connection is closed");
+            }
+        }
+
+        self.flush_proxies();
+    }
+
+    /// Returns each proxy and whether its connection is still open
+    pub fn proxies(&self) -> impl Iterator<Item = (ProxyId, bool)> + '_ {
+        self.egress_comms
+            .iter()
+            .map(|(&proxy_id, egress_comm)| (proxy_id, !egress_comm.tx.is_closed()))
+    }
 }
 
 /// A broadcast builder
@@ -422,7 +986,12 @@ impl IoBuf {
 pub struct Broadcast<'a, P> {
     packet: P,
     compose: &'a Compose,
-    exclude: Option<ConnectionId>,
+    exclude: Exclude,
+    world: Option<WorldId>,
+    batched: bool,
+    low_priority: bool,
+    /// The [`RecipientFilter`] and its name
+    filter: Option<(TypeId, &'static str)>,
 }
 
 /// A unicast builder
@@ -432,45 +1001,154 @@ struct Unicast<'a, P> {
     stream_id: ConnectionId,
     compose: &'a Compose,
     compress: bool,
+    /// Whether [`ConnectionLimits::max_pending_bytes`] is enforced
+    capped: bool,
 }
 
 impl<P> Unicast<'_, P>
 where
     P: PacketBundle,
 {
-    fn send(self) -> anyhow::Result<()> {
-        self.compose.io_buf.unicast_private(
-            self.packet,
-            self.stream_id,
-            self.compose,
-            self.compress,
-        )
+    fn send(self) -> SendResult {
+        let io_buf = &self.compose.io_buf;
+        let send = |bytes: &[u8]| {
+            if self.capped {
+                io_buf.try_unicast_raw(bytes, self.stream_id)
+            } else {
+                io_buf.unicast_raw(bytes, self.stream_id)
+            }
+        };
+
+        let immediate = io_buf.is_flush_immediate(&self.packet);
+
+        if self.compress {
+            io_buf.with_encoded_packet(self.packet, self.compose, send)??;
+        } else {
+            io_buf.with_encoded_packet_no_compression(self.packet, send)??;
+        }
+
+        if immediate {
+            io_buf.flush_proxy(self.stream_id.proxy_id());
+        }
+
+        Ok(())
     }
 }
 
 impl<P> Broadcast<'_, P> {
     /// Send the packet to all players.
-    pub fn send(self) -> anyhow::Result<()>
+    pub fn send(self) -> SendResult
     where
         P: PacketBundle,
     {
-        let bytes = self
-            .compose
-            .io_buf
-            .encode_packet(self.packet, self.compose)?;
+        let io_buf = &self.compose.io_buf;
+        let exclude = if self.low_priority {
+            io_buf.exclude_congested(self.exclude)
+        } else {
+            self.exclude
+        };
+
+        if let Some((filter, name)) = self.filter {
+            let play_only = self.packet.play_packet_id().is_some();
+            let Some(recipients) = io_buf.recipients.get_by_id(filter, play_only) else {
+                return Err(SendError::UnregisteredFilter(name));
+            };
+
+            let streams: Vec<_> = recipients
+                .iter()
+                .filter(|stream| !exclude.as_slice().contains(stream))
+                .copied()
+                .collect();
+            if streams.is_empty() {
+                return Ok(());
+            }
+
+            let world = self.world.map(WorldId::inner);
+            let immediate = !self.batched && io_buf.is_flush_immediate(&self.packet);
+            io_buf.with_encoded_packet(self.packet, self.compose, |bytes| {
+                if self.batched {
+                    io_buf.batch.add_multicast(&streams, world, bytes);
+                } else {
+                    io_buf.multicast_raw(bytes, &streams, world);
+                }
+            })?;
+
+            if immediate {
+                io_buf.flush_proxies();
+            }
+
+            return Ok(());
+        }
+
+        let immediate = !self.batched && io_buf.is_flush_immediate(&self.packet);
+
+        io_buf.with_encoded_packet(self.packet, self.compose, |bytes| {
+            if self.batched {
+                io_buf
+                    .batch
+                    .add_global(exclude, self.world.map(WorldId::inner), bytes);
+            } else {
+                io_buf.broadcast_raw_in_world(bytes, exclude, self.world);
+            }
+        })?;
 
-        self.compose.io_buf.broadcast_raw(&bytes, self.exclude);
+        if immediate {
+            io_buf.flush_proxies();
+        }
 
         Ok(())
     }
 
     /// Exclude a certain player from the broadcast. This can only be called once.
     pub fn exclude(self, exclude: impl Into<Option<ConnectionId>>) -> Self {
-        let exclude = exclude.into();
-        Broadcast {
-            packet: self.packet,
-            compose: self.compose,
-            exclude,
+        let exclude = Exclude::from(exclude.into());
+        Self { exclude, ..self }
+    }
+
+    /// Exclude several players from the broadcast. This replaces any player excluded before.
+    pub fn exclude_many(self, exclude: &[ConnectionId]) -> Self {
+        let exclude = Exclude::from(exclude);
+        Self { exclude, ..self }
+    }
+
+    /// Only send the packet to players in the given world.
+    pub fn world(self, world: WorldId) -> Self {
+        Self {
+            world: Some(world),
+            ..self
+        }
+    }
+
+    /// Only send the packet to the players matching `F`, such as `With<InArena>`. The players are
+    /// looked up in the cache of [`Recipients`], so `F` must be registered with
+    /// [`register_recipient_filter`](recipients::register_recipient_filter).
+    ///
+    /// Packets of the play state are only sent to players in
+    /// [`packet_state::Play`](crate::simulation::packet_state::Play). The packet is sent to the
+    /// proxies once, together with the connections of the players that receive it.
+    pub fn filter<F: RecipientFilter>(self) -> Self {
+        Self {
+            filter: Some((TypeId::of::<F>(), std::any::type_name::<F>())),
+            ..self
+        }
+    }
+
+    /// Send the packet at the end of the tick, merged with other batched broadcasts that have the
+    /// same exclusion and world, or the same recipients for a [`Broadcast::filter`]. The packet may
+    /// be reordered with packets that are not part of the same batch.
+    pub fn batched(self) -> Self {
+        Self {
+            batched: true,
+            ..self
+        }
+    }
+
+    /// Do not send the packet to players that are not keeping up with the data sent to them. This
+    /// should be used for packets that are only cosmetic, such as particles and sounds.
+    pub fn low_priority(self) -> Self {
+        Self {
+            low_priority: true,
+            ..self
         }
     }
 }
@@ -481,35 +1159,60 @@ pub struct BroadcastLocal<'a, P> {
     packet: P,
     compose: &'a Compose,
     center: ChunkPosition,
-    exclude: Option<ConnectionId>,
+    exclude: Exclude,
+    low_priority: bool,
 }
 
 impl<P> BroadcastLocal<'_, P> {
     /// Send the packet
-    pub fn send(self) -> anyhow::Result<()>
+    pub fn send(self) -> SendResult
     where
         P: PacketBundle,
     {
-        let bytes = self
-            .compose
-            .io_buf
-            .encode_packet(self.packet, self.compose)?;
+        let io_buf = &self.compose.io_buf;
+        let exclude = if self.low_priority {
+            io_buf.exclude_congested(self.exclude)
+        } else {
+            self.exclude
+        };
 
-        self.compose
-            .io_buf
-            .broadcast_local_raw(&bytes, self.center, self.exclude);
+        let immediate = io_buf.is_flush_immediate(&self.packet);
+
+        io_buf.with_encoded_packet(self.packet, self.compose, |bytes| {
+            io_buf.broadcast_local_raw(bytes, self.center, exclude);
+        })?;
+
+        if immediate {
+            io_buf.flush_proxies();
+        }
 
         Ok(())
     }
 
     /// Exclude a certain player from the broadcast. This can only be called once.
     pub fn exclude(self, exclude: impl Into<Option<ConnectionId>>) -> Self {
-        let exclude = exclude.into();
-        BroadcastLocal {
-            packet: self.packet,
-            compose: self.compose,
-            center: self.center,
-            exclude,
+        let exclude = Exclude::from(exclude.into());
+        Self { exclude, ..self }
+    }
+
+    /// Exclude several players from the broadcast. This replaces any player excluded before.
+    pub fn exclude_many(self, exclude: &[ConnectionId]) -> Self {
+        let exclude = Exclude::from(exclude);
+        Self { exclude, ..self }
+    }
+
+    /// Sets the world that `center` is in. This defaults to [`WorldId::PRIMARY`].
+    pub fn world(mut self, world: WorldId) -> Self {
+        self.center = self.center.with_world(world.inner());
+        self
+    }
+
+    /// Do not send the packet to players that are not keeping up with the data sent to them. See
+    /// [`Broadcast::low_priority`].
+    pub fn low_priority(self) -> Self {
+        Self {
+            low_priority: true,
+            ..self
         }
     }
 }
@@ -519,87 +1222,157 @@ impl<P> BroadcastLocal<'_, P> {
 pub struct BroadcastChannel<'a, P> {
     packet: P,
     compose: &'a Compose,
-    exclude: Option<ConnectionId>,
+    exclude: Exclude,
     channel: ChannelId,
+    batch: Option<BatchOrder>,
 }
 
 impl<P> BroadcastChannel<'_, P> {
     /// Send the packet
-    pub fn send(self) -> anyhow::Result<()>
+    pub fn send(self) -> SendResult
     where
         P: PacketBundle,
     {
-        let bytes = self
-            .compose
-            .io_buf
-            .encode_packet(self.packet, self.compose)?;
+        let io_buf = &self.compose.io_buf;
+        let immediate = self.batch.is_none() && io_buf.is_flush_immediate(&self.packet);
+
+        io_buf.with_encoded_packet(self.packet, self.compose, |bytes| match self.batch {
+            Some(order) => {
+                io_buf
+                    .batch
+                    .add_channel(self.channel.inner(), self.exclude, order, bytes);
+            }
+            None => io_buf.broadcast_channel_raw(bytes, self.channel, self.exclude),
+        })?;
 
-        self.compose
-            .io_buf
-            .broadcast_channel_raw(&bytes, self.channel, self.exclude);
+        if immediate {
+            io_buf.flush_proxies();
+        }
 
         Ok(())
     }
 
     /// Exclude a certain player from the broadcast. This can only be called once.
     pub fn exclude(self, exclude: impl Into<Option<ConnectionId>>) -> Self {
-        let exclude = exclude.into();
+        let exclude = Exclude::from(exclude.into());
         Self { exclude, ..self }
     }
+
+    /// Exclude several players from the broadcast. This replaces any player excluded before.
+    pub fn exclude_many(self, exclude: &[ConnectionId]) -> Self {
+        let exclude = Exclude::from(exclude);
+        Self { exclude, ..self }
+    }
+
+    /// Send the packet at the end of the tick, together with every other batched packet of the
+    /// channel. See [`BatchOrder`].
+    pub fn batched(self, order: BatchOrder) -> Self {
+        Self {
+            batch: Some(order),
+            ..self
+        }
+    }
 }
 
 impl IoBuf {
+    /// Encodes `packet` into an owned buffer. Sending packets through [`Compose`] or a
+    /// [`DataBundle`] avoids this allocation.
     pub fn encode_packet<P>(&self, packet: P, compose: &Compose) -> anyhow::Result<BytesMut>
     where
         P: PacketBundle,
     {
-        let temp_buffer = self.temp_buffer.get_or_default();
-        let temp_buffer = &mut *temp_buffer.borrow_mut();
+        self.with_encoded_packet(packet, compose, |bytes| BytesMut::from(bytes))
+    }
+
+    pub fn encode_packet_no_compression<P>(&self, packet: P) -> anyhow::Result<BytesMut>
+    where
+        P: PacketBundle,
+    {
+        self.with_encoded_packet_no_compression(packet, |bytes| BytesMut::from(bytes))
+    }
 
+    /// Appends the encoded `packet` to `out`. The encoder reserves [`MAX_PACKET_SIZE`] bytes in
+    /// `out` to write into, so reusing `out` for several packets avoids reallocating it.
+    pub fn encode_packet_into<P>(
+        &self,
+        packet: P,
+        compose: &Compose,
+        out: &mut Vec<u8>,
+    ) -> anyhow::Result<()>
+    where
+        P: PacketBundle,
+    {
         let compressor = compose.compressor();
         let mut compressor = compressor.borrow_mut();
 
         let scratch = compose.scratch();
         let mut scratch = scratch.borrow_mut();
 
-        let result =
-            compose
-                .encoder()
-                .append_packet(packet, temp_buffer, &mut *scratch, &mut compressor)?;
-
-        Ok(result)
+        compose
+            .encoder()
+            .append_packet(packet, out, &mut *scratch, &mut compressor)
     }
 
-    pub fn encode_packet_no_compression<P>(&self, packet: P) -> anyhow::Result<BytesMut>
+    /// Encodes `packet` into the buffer of the current thread and passes the encoded bytes to `f`
+    pub fn with_encoded_packet<P, R>(
+        &self,
+        packet: P,
+        compose: &Compose,
+        f: impl FnOnce(&[u8]) -> R,
+    ) -> anyhow::Result<R>
     where
         P: PacketBundle,
     {
-        let temp_buffer = self.temp_buffer.get_or_default();
-        let temp_buffer = &mut *temp_buffer.borrow_mut();
-
-        let result = append_packet_without_compression(packet, temp_buffer)?;
-
-        Ok(result)
+        self.with_encode_buffer(|buffer| {
+            self.encode_packet_into(packet, compose, buffer)?;
+            Ok(f(buffer))
+        })
     }
 
-    fn unicast_private<P>(
+    /// Like [`IoBuf::with_encoded_packet`], but never compresses the packet
+    pub fn with_encoded_packet_no_compression<P, R>(
         &self,
         packet: P,
-        id: ConnectionId,
-        compose: &Compose,
-        compress: bool,
-    ) -> anyhow::Result<()>
+        f: impl FnOnce(&[u8]) -> R,
+    ) -> anyhow::Result<R>
     where
         P: PacketBundle,
     {
-        let bytes = if compress {
-            self.encode_packet(packet, compose)?
-        } else {
-            self.encode_packet_no_compression(packet)?
+        self.with_encode_buffer(|buffer| {
+            append_packet_without_compression(packet, buffer)?;
+            Ok(f(buffer))
+        })
+    }
+
+    /// Runs `f` with the empty encode buffer of the current thread. A temporary buffer is used if
+    /// `f` is called while the buffer is already in use, such as when `f` encodes another packet.
+    fn with_encode_buffer<R>(&self, f: impl FnOnce(&mut Vec<u8>) -> R) -> R {
+        let Ok(mut buffer) = self.encode_buffer.get_or_default().try_borrow_mut() else {
+            return f(&mut Vec::new());
         };
 
-        self.unicast_raw(&bytes, id);
-        Ok(())
+        buffer.clear();
+        f(&mut buffer)
+    }
+
+    fn take_bundle_buffer(&self) -> Vec<u8> {
+        self.bundle_buffers
+            .get_or_default()
+            .borrow_mut()
+            .pop()
+            .unwrap_or_default()
+    }
+
+    fn return_bundle_buffer(&self, mut buffer: Vec<u8>) {
+        if buffer.capacity() == 0 || buffer.capacity() > MAX_POOLED_BUNDLE_CAPACITY {
+            return;
+        }
+
+        let mut buffers = self.bundle_buffers.get_or_default().borrow_mut();
+        if buffers.len() < MAX_POOLED_BUNDLE_BUFFERS {
+            buffer.clear();
+            buffers.push(buffer);
+        }
     }
 
     pub(crate) fn encode_proxy_message(message: &ServerToProxyMessage<'_>) -> Bytes {
@@ -615,39 +1388,91 @@ impl IoBuf {
         Bytes::from_owner(buffer)
     }
 
-    pub(crate) fn add_proxy_message(&self, message: &IntermediateServerToProxyMessage<'_>) {
-        if message.affected_by_proxy() {
-            // Encode the message for each proxy before sending it
-            for (&proxy_id, egress_comm) in &self.egress_comms {
-                let Some(message) = message.transform_for_proxy(proxy_id) else {
-                    continue;
-                };
-
-                egress_comm
-                    .tx
-                    .send(Self::encode_proxy_message(&message))
-                    .unwrap();
+    /// Encodes `message` for each of `proxies` which supports the features the message requires
+    /// and passes the encoded message to `send`.
+    ///
+    /// Proxies that receive an identical message share a single encoding, so a broadcast is only
+    /// encoded once, plus once for each proxy with an excluded connection.
+    fn encode_for_proxies(
+        message: &IntermediateServerToProxyMessage<'_>,
+        proxies: impl IntoIterator<Item = (ProxyId, Features)>,
+        mut send: impl FnMut(ProxyId, &Bytes),
+    ) {
+        let required = message.required_features();
+        let encode = |proxy_id| {
+            message
+                .transform_for_proxy(proxy_id)
+                .map(|message| Self::encode_proxy_message(&message))
+        };
+        let mut shared: Option<Option<Bytes>> = None;
+
+        for (proxy_id, features) in proxies {
+            if !features.contains(required) {
+                continue;
             }
-        } else {
-            // Encode the message once and then send it to each proxy. This uses a placeholder
-            // proxy id.
-            let Some(message) = message.transform_for_proxy(ProxyId::new(0)) else {
-                return;
+
+            let buffer = if message.specialized_for(proxy_id) {
+                encode(proxy_id)
+            } else {
+                shared.get_or_insert_with(|| encode(proxy_id)).clone()
             };
 
-            let buffer = Self::encode_proxy_message(&message);
-            for egress_comm in self.egress_comms.values() {
-                egress_comm.tx.send(buffer.clone()).unwrap();
+            if let Some(buffer) = buffer {
+                send(proxy_id, &buffer);
             }
         }
     }
 
-    fn broadcast_local_raw(
-        &self,
-        data: &[u8],
-        center: impl Into<ChunkPosition>,
-        exclude: Option<ConnectionId>,
+    /// Passes `message` to `f` after running the egress filters over its packets
+    fn with_filtered(
+        filters: &PacketFilters,
+        message: &IntermediateServerToProxyMessage<'_>,
+        f: impl FnOnce(&IntermediateServerToProxyMessage<'_>),
     ) {
+        if filters.egress_is_empty() {
+            return f(message);
+        }
+
+        let filtered = message
+            .packets()
+            .and_then(|(target, data)| filters.filter_egress(target, data));
+
+        match filtered {
+            Some(data) => f(&message.with_packets(&data)),
+            None => f(message),
+        }
+    }
+
+    pub(crate) fn add_proxy_message(&self, message: &IntermediateServerToProxyMessage<'_>) {
+        Self::with_filtered(&self.filters, message, |message| {
+            Self::encode_for_proxies(
+                message,
+                self.egress_comms
+                    .iter()
+                    .map(|(&proxy_id, egress_comm)| (proxy_id, egress_comm.features)),
+                |proxy_id, buffer| {
+                    self.bytes_sent
+                        .fetch_add(buffer.len() as u64, Ordering::Relaxed);
+                    // A proxy that disconnected is removed once the proxy task notices
+                    if self.egress_comms[&proxy_id]
+                        .tx
+                        .send(buffer.clone())
+                        .is_err()
+                    {
+                        warn!(
+                            "dropped a message for {proxy_id:?} because its connection is closed"
+                        );
+                    }
+                },
+            );
+        });
+    }
+
+    pub(crate) const fn filters(&self) -> &PacketFilters {
+        &self.filters
+    }
+
+    fn broadcast_local_raw(&self, data: &[u8], center: impl Into<ChunkPosition>, exclude: Exclude) {
         let center = center.into();
 
         self.add_proxy_message(&IntermediateServerToProxyMessage::BroadcastLocal(
@@ -659,12 +1484,7 @@ impl IoBuf {
         ));
     }
 
-    fn broadcast_channel_raw(
-        &self,
-        data: &[u8],
-        channel: ChannelId,
-        exclude: Option<ConnectionId>,
-    ) {
+    fn broadcast_channel_raw(&self, data: &[u8], channel: ChannelId, exclude: Exclude) {
         self.add_proxy_message(&IntermediateServerToProxyMessage::BroadcastChannel(
             intermediate::BroadcastChannel {
                 channel_id: channel.inner(),
@@ -674,13 +1494,77 @@ impl IoBuf {
         ));
     }
 
-    pub(crate) fn broadcast_raw(&self, data: &[u8], exclude: Option<ConnectionId>) {
+    pub(crate) fn broadcast_raw(&self, data: &[u8], exclude: Exclude) {
+        self.broadcast_raw_in_world(data, exclude, None);
+    }
+
+    pub(crate) fn broadcast_raw_in_world(
+        &self,
+        data: &[u8],
+        exclude: Exclude,
+        world: Option<WorldId>,
+    ) {
         self.add_proxy_message(&IntermediateServerToProxyMessage::BroadcastGlobal(
-            intermediate::BroadcastGlobal { exclude, data },
+            intermediate::BroadcastGlobal {
+                exclude,
+                world: world.map(WorldId::inner),
+                data,
+            },
         ));
     }
 
-    pub(crate) fn unicast_raw(&self, data: &[u8], stream: ConnectionId) {
+    /// Sends `data` to every connection in `streams` whose player is in `world`, if it is set
+    fn multicast_raw(&self, data: &[u8], streams: &[ConnectionId], world: Option<u16>) {
+        self.add_proxy_message(&IntermediateServerToProxyMessage::Multicast(
+            intermediate::Multicast {
+                streams,
+                world,
+                data,
+            },
+        ));
+    }
+
+    pub(crate) fn unicast_raw(&self, data: &[u8], stream: ConnectionId) -> SendResult {
+        self.check_connected(stream)?;
+        self.pending
+            .pin()
+            .get_or_insert_with(stream, AtomicUsize::default)
+            .fetch_add(data.len(), Ordering::Relaxed);
+        self.send_unicast(data, stream);
+        Ok(())
+    }
+
+    /// Like [`IoBuf::unicast_raw`], but fails instead of exceeding
+    /// [`ConnectionLimits::max_pending_bytes`]
+    pub(crate) fn try_unicast_raw(&self, data: &[u8], stream: ConnectionId) -> SendResult {
+        self.check_connected(stream)?;
+
+        let max = self.limits.max_pending_bytes;
+        let reserved = self
+            .pending
+            .pin()
+            .get_or_insert_with(stream, AtomicUsize::default)
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |pending| {
+                Some(pending + data.len()).filter(|&total| total <= max)
+            });
+        if let Err(pending) = reserved {
+            return Err(SendError::BufferFull {
+                needed: pending + data.len() - max,
+            });
+        }
+
+        self.send_unicast(data, stream);
+        Ok(())
+    }
+
+    fn check_connected(&self, stream: ConnectionId) -> SendResult {
+        match self.egress_comms.get(&stream.proxy_id()) {
+            Some(egress_comm) if !egress_comm.tx.is_closed() => Ok(()),
+            _ => Err(SendError::Disconnected),
+        }
+    }
+
+    fn send_unicast(&self, data: &[u8], stream: ConnectionId) {
         self.add_proxy_message(&IntermediateServerToProxyMessage::Unicast(
             intermediate::Unicast { stream, data },
         ));
@@ -717,6 +1601,7 @@ impl IoBuf {
     }
 
     pub(crate) fn remove_channel(&self, channel: ChannelId) {
+        self.batch.remove_channel(channel.inner());
         self.add_proxy_message(&IntermediateServerToProxyMessage::RemoveChannel(
             intermediate::RemoveChannel {
                 channel_id: channel.inner(),
@@ -729,4 +1614,352 @@ impl IoBuf {
             intermediate::Shutdown { stream },
         ));
     }
+
+    /// Despawns every channel for `stream` and spawns the channels in range again, such as when
+    /// the client may have missed some of them
+    pub fn resubscribe_channels(&self, stream: ConnectionId) {
+        self.add_proxy_message(&IntermediateServerToProxyMessage::ResubscribeChannels(
+            intermediate::ResubscribeChannels { stream },
+        ));
+    }
+
+    /// Sets whether `stream` is subscribed to hidden channels, such as the ones of vanished
+    /// players
+    pub fn set_see_hidden_channels(&self, stream: ConnectionId, see: bool) {
+        self.add_proxy_message(&IntermediateServerToProxyMessage::SetSeeHiddenChannels(
+            intermediate::SetSeeHiddenChannels { stream, see },
+        ));
+    }
+
+    /// Makes the proxy encrypt and decrypt all further data of `stream` with `key`. This must be
+    /// sent before any packet which the client expects to be encrypted.
+    pub(crate) fn set_encryption(&self, stream: ConnectionId, key: [u8; 16]) {
+        self.add_proxy_message(&IntermediateServerToProxyMessage::SetEncryption(
+            intermediate::SetEncryption { stream, key },
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use valence_protocol::{VarInt, packets::play};
+
+    use super::*;
+
+    /// Broadcasts `data` to 3 proxies and returns what each proxy received, in proxy id order
+    fn broadcast_to_3_proxies(data: &[u8], exclude: Exclude) -> Vec<Bytes> {
+        let mut io_buf = IoBuf::default();
+        let mut receivers = Vec::new();
+        for proxy_id in 0..3 {
+            let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+            io_buf.add_proxy(ProxyId::new(proxy_id), tx.into());
+            receivers.push(rx);
+        }
+
+        io_buf.broadcast_raw(data, exclude);
+
+        receivers
+            .iter_mut()
+            .map(|rx| {
+                let bytes = rx.try_recv().unwrap();
+                assert!(rx.try_recv().is_err());
+                bytes
+            })
+            .collect()
+    }
+
+    /// Returns the number of distinct buffers in `messages`
+    fn copies(messages: &[Bytes]) -> usize {
+        let mut pointers: Vec<_> = messages.iter().map(|bytes| bytes.as_ptr()).collect();
+        pointers.sort_unstable();
+        pointers.dedup();
+        pointers.len()
+    }
+
+    #[test]
+    fn broadcast_is_encoded_once_for_every_proxy() {
+        let data = vec![7; 1024 * 1024];
+        let messages = broadcast_to_3_proxies(&data, Exclude::None);
+
+        assert_eq!(copies(&messages), 1);
+        assert!(messages[0].len() > data.len());
+    }
+
+    #[test]
+    fn broadcast_with_exclude_is_specialized_for_its_proxy() {
+        let data = vec![7; 1024];
+        let exclude = ConnectionId::new(5, ProxyId::new(1));
+        let messages = broadcast_to_3_proxies(&data, Exclude::One(exclude));
+
+        assert_eq!(copies(&messages), 2);
+        assert_eq!(messages[0].as_ptr(), messages[2].as_ptr());
+        assert_ne!(messages[0], messages[1]);
+
+        let expected = |proxy_id| {
+            let message =
+                IntermediateServerToProxyMessage::BroadcastGlobal(intermediate::BroadcastGlobal {
+                    exclude: Exclude::One(exclude),
+                    world: None,
+                    data: &data,
+                });
+            IoBuf::encode_proxy_message(
+                &message.transform_for_proxy(ProxyId::new(proxy_id)).unwrap(),
+            )
+        };
+        assert_eq!(messages[0], expected(0));
+        assert_eq!(messages[1], expected(1));
+    }
+
+    #[test]
+    fn broadcast_excluding_several_connections_is_specialized_for_each_of_their_proxies() {
+        let data = vec![7; 1024];
+        let exclude = [
+            ConnectionId::new(5, ProxyId::new(0)),
+            ConnectionId::new(6, ProxyId::new(0)),
+            ConnectionId::new(5, ProxyId::new(2)),
+        ];
+        let messages = broadcast_to_3_proxies(&data, Exclude::from(&exclude[..]));
+
+        assert_eq!(copies(&messages), 3);
+        assert_ne!(messages[0], messages[1]);
+        assert_ne!(messages[2], messages[1]);
+    }
+
+    #[test]
+    fn capped_connection_is_paused_until_the_next_flush() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut io_buf = IoBuf::default();
+        io_buf.set_limits(ConnectionLimits {
+            max_pending_bytes: 100,
+            ..ConnectionLimits::default()
+        });
+        io_buf.add_proxy(ProxyId::new(0), tx.into());
+        let stream = ConnectionId::new(1, ProxyId::new(0));
+
+        io_buf.try_unicast_raw(&[0; 60], stream).unwrap();
+        assert!(matches!(
+            io_buf.try_unicast_raw(&[0; 60], stream),
+            Err(SendError::BufferFull { needed: 20 })
+        ));
+        // Other connections have their own budget
+        io_buf
+            .try_unicast_raw(&[0; 60], ConnectionId::new(2, ProxyId::new(0)))
+            .unwrap();
+        // Uncapped sends are never refused but count towards the cap
+        io_buf.unicast_raw(&[0; 60], stream).unwrap();
+        assert_eq!(io_buf.pending_bytes(stream), 120);
+        assert_eq!(std::iter::from_fn(|| rx.try_recv().ok()).count(), 3);
+
+        io_buf.flush_batch();
+        assert_eq!(io_buf.pending_bytes(stream), 0);
+        io_buf.try_unicast_raw(&[0; 60], stream).unwrap();
+    }
+
+    #[test]
+    fn concurrent_capped_unicasts_do_not_exceed_the_cap() {
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut io_buf = IoBuf::default();
+        io_buf.set_limits(ConnectionLimits {
+            max_pending_bytes: 100,
+            ..ConnectionLimits::default()
+        });
+        io_buf.add_proxy(ProxyId::new(0), tx.into());
+        let stream = ConnectionId::new(1, ProxyId::new(0));
+
+        let io_buf = &io_buf;
+        let sent = std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..8)
+                .map(|_| scope.spawn(move || io_buf.try_unicast_raw(&[0; 60], stream).is_ok()))
+                .collect();
+            threads
+                .into_iter()
+                .map(|thread| thread.join().unwrap())
+                .filter(|&sent| sent)
+                .count()
+        });
+
+        assert_eq!(sent, 1);
+        assert_eq!(io_buf.pending_bytes(stream), 60);
+    }
+
+    #[test]
+    fn messages_are_only_sent_to_proxies_with_their_features() {
+        let mut io_buf = IoBuf::default();
+        let (tx, mut old_rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut old = EgressComm::from(tx);
+        old.features = Features::BACKLOG_REPORTS;
+        io_buf.add_proxy(ProxyId::new(0), old);
+        let (tx, mut new_rx) = tokio::sync::mpsc::unbounded_channel();
+        io_buf.add_proxy(ProxyId::new(1), tx.into());
+
+        io_buf.resubscribe_channels(ConnectionId::new(1, ProxyId::new(0)));
+        io_buf.resubscribe_channels(ConnectionId::new(1, ProxyId::new(1)));
+        assert!(old_rx.try_recv().is_err());
+        assert!(new_rx.try_recv().is_ok());
+
+        io_buf.shutdown(ConnectionId::new(1, ProxyId::new(0)));
+        assert!(old_rx.try_recv().is_ok());
+    }
+
+    #[test]
+    fn unicast_to_a_missing_proxy_is_disconnected() {
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut io_buf = IoBuf::default();
+        io_buf.add_proxy(ProxyId::new(0), tx.into());
+
+        let missing = ConnectionId::new(1, ProxyId::new(1));
+        assert!(matches!(
+            io_buf.unicast_raw(&[0; 4], missing),
+            Err(SendError::Disconnected)
+        ));
+
+        let closed = ConnectionId::new(1, ProxyId::new(0));
+        drop(rx);
+        assert!(matches!(
+            io_buf.try_unicast_raw(&[0; 4], closed),
+            Err(SendError::Disconnected)
+        ));
+        assert_eq!(io_buf.pending_bytes(closed), 0);
+    }
+
+    #[test]
+    fn low_priority_broadcasts_exclude_congested_connections() {
+        let slow = ConnectionId::new(1, ProxyId::new(0));
+        let other = ConnectionId::new(2, ProxyId::new(0));
+        let mut io_buf = IoBuf::default();
+        assert_eq!(io_buf.exclude_congested(Exclude::None), Exclude::None);
+
+        io_buf.set_congested(std::iter::once(slow).collect());
+        assert!(io_buf.is_congested(slow));
+        assert_eq!(io_buf.exclude_congested(Exclude::None), Exclude::One(slow));
+        assert_eq!(
+            io_buf.exclude_congested(Exclude::One(slow)),
+            Exclude::One(slow)
+        );
+        assert_eq!(
+            io_buf.exclude_congested(Exclude::One(other)),
+            Exclude::from(&[other, slow][..])
+        );
+    }
+
+    #[test]
+    fn bundle_buffers_are_reused() {
+        let compose = Compose::new(
+            CompressionLvl::default(),
+            Arc::new(Shared {
+                compression_threshold: valence_protocol::CompressionThreshold(-1),
+                compression_level: CompressionLvl::default(),
+            }),
+            IoBuf::default(),
+        );
+        let packet = play::EntitiesDestroyS2c {
+            entity_ids: Cow::Borrowed(&[VarInt(1)]),
+        };
+
+        let mut bundle = DataBundle::new(&compose);
+        bundle.add_packet(&packet).unwrap();
+        bundle.add_packet(&packet).unwrap();
+        let encoded = compose.io_buf().encode_packet(&packet, &compose).unwrap();
+        assert_eq!(bundle.data, [&encoded[..], &encoded[..]].concat());
+        let buffer = bundle.data.as_ptr();
+        drop(bundle);
+
+        let bundle = DataBundle::new(&compose);
+        assert!(bundle.data.is_empty());
+        assert_eq!(bundle.data.as_ptr(), buffer);
+    }
+
+    #[test]
+    fn bundles_are_delimited_and_split_when_full() {
+        let compose = Compose::new(
+            CompressionLvl::default(),
+            Arc::new(Shared {
+                compression_threshold: valence_protocol::CompressionThreshold(-1),
+                compression_level: CompressionLvl::default(),
+            }),
+            IoBuf::default(),
+        );
+        let packet = play::EntitiesDestroyS2c {
+            entity_ids: Cow::Borrowed(&[VarInt(1)]),
+        };
+        let io_buf = compose.io_buf();
+        let encoded = io_buf.encode_packet(&packet, &compose).unwrap();
+        let delimiter = io_buf
+            .encode_packet(&play::BundleSplitterS2c, &compose)
+            .unwrap();
+
+        let mut bundle = DataBundle::new(&compose);
+        bundle
+            .add_bundle(|bundle| {
+                bundle.add_packet(&packet)?;
+                // Nested bundles are part of the open bundle
+                bundle.add_bundle(|bundle| {
+                    bundle.add_raw(&[&encoded[..], &encoded[..]].concat());
+                    Ok(())
+                })
+            })
+            .unwrap();
+        // Empty and failed bundles are left out
+        bundle.add_bundle(|_| Ok(())).unwrap();
+        bundle
+            .add_bundle(|bundle| {
+                bundle.add_packet(&packet)?;
+                anyhow::bail!("failed")
+            })
+            .unwrap_err();
+
+        let expected = [
+            &delimiter[..],
+            &encoded[..],
+            &encoded[..],
+            &encoded[..],
+            &delimiter[..],
+        ]
+        .concat();
+        assert_eq!(bundle.as_bytes(), expected);
+
+        let mut bundle = DataBundle::new(&compose);
+        bundle
+            .add_bundle(|bundle| {
+                for _ in 0..=MAX_BUNDLE_PACKETS {
+                    bundle.add_packet(&packet)?;
+                }
+                Ok(())
+            })
+            .unwrap();
+
+        let full = [
+            &delimiter[..],
+            &encoded.repeat(MAX_BUNDLE_PACKETS)[..],
+            &delimiter[..],
+        ]
+        .concat();
+        let rest = [&delimiter[..], &encoded[..], &delimiter[..]].concat();
+        assert_eq!(bundle.as_bytes(), [full, rest].concat());
+    }
+
+    #[test]
+    fn stale_channel_ids_do_not_resolve_after_index_reuse() {
+        let mut world = bevy_ecs::world::World::new();
+        let mut channels = Channels::default();
+
+        let player = world.spawn_empty().id();
+        let stale = ChannelId::from(player);
+        channels.insert(player);
+        assert_eq!(channels.get(stale), Some(player));
+
+        world.despawn(player);
+        channels.remove(player);
+
+        let arrow = world.spawn_empty().id();
+        assert_eq!(arrow.index(), player.index());
+        assert_eq!(channels.get(stale), None);
+
+        // A channel reusing the index is not removed by a late removal of the old channel
+        channels.insert(arrow);
+        channels.remove(player);
+        assert_eq!(channels.get(stale), Some(arrow));
+    }
 }
@@ -2,12 +2,13 @@ use std::{borrow::Cow, io::Write};
 
 use uuid::Uuid;
 use valence_protocol::{
-    Decode, DecodeBytesAuto, Encode, ItemStack, Packet, VarInt,
+    Decode, DecodeBytesAuto, Encode, Ident, ItemStack, Packet, VarInt,
     packets::play::{
         boss_bar_s2c::{BossBarColor, BossBarDivision, BossBarFlags},
         entity_equipment_update_s2c::EquipmentEntry,
     },
 };
+use valence_text::Text;
 
 #[derive(Clone, PartialEq, Debug, Packet, DecodeBytesAuto)]
 pub struct EntityEquipmentUpdateS2c<'a> {
@@ -79,3 +80,228 @@ pub enum BossBarAction<'a> {
     UpdateStyle(BossBarColor, BossBarDivision),
     UpdateFlags(BossBarFlags),
 }
+
+/// Adds, removes, and updates the progress of advancements.
+#[derive(Clone, Debug, Encode, Packet)]
+pub struct AdvancementUpdateS2c<'a> {
+    /// Removes all advancements known by the client before applying this packet
+    pub reset: bool,
+    pub added: Vec<(Ident<Cow<'a, str>>, Advancement<'a>)>,
+    pub removed: Vec<Ident<Cow<'a, str>>>,
+    /// The progress of each criterion of an advancement. The value is the time the criterion was
+    /// achieved in milliseconds since the unix epoch, if it was achieved.
+    pub progress: Vec<(Ident<Cow<'a, str>>, Vec<(Cow<'a, str>, Option<i64>)>)>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Advancement<'a> {
+    pub parent: Option<Ident<Cow<'a, str>>>,
+    pub display: Option<AdvancementDisplay<'a>>,
+    pub criteria: Vec<Cow<'a, str>>,
+    /// Every inner list must have at least one achieved criterion for the advancement to be
+    /// complete
+    pub requirements: Vec<Vec<Cow<'a, str>>>,
+    pub sends_telemetry_data: bool,
+}
+
+impl Encode for Advancement<'_> {
+    fn encode(&self, mut w: impl Write) -> anyhow::Result<()> {
+        self.parent.encode(&mut w)?;
+        self.display.encode(&mut w)?;
+
+        // Criteria are a map from the criterion name to an empty value
+        VarInt(i32::try_from(self.criteria.len())?).encode(&mut w)?;
+        for criterion in &self.criteria {
+            criterion.encode(&mut w)?;
+        }
+
+        self.requirements.encode(&mut w)?;
+        self.sends_telemetry_data.encode(&mut w)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum FrameType {
+    #[default]
+    Task,
+    Challenge,
+    Goal,
+}
+
+impl Encode for FrameType {
+    fn encode(&self, w: impl Write) -> anyhow::Result<()> {
+        let id = match self {
+            Self::Task => 0,
+            Self::Challenge => 1,
+            Self::Goal => 2,
+        };
+
+        VarInt(id).encode(w)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AdvancementDisplay<'a> {
+    pub title: Cow<'a, Text>,
+    pub description: Cow<'a, Text>,
+    pub icon: Cow<'a, ItemStack>,
+    pub frame: FrameType,
+    /// The background of the advancement tab. This is only used by root advancements.
+    pub background: Option<Ident<Cow<'a, str>>>,
+    pub show_toast: bool,
+    pub hidden: bool,
+    pub x: f32,
+    pub y: f32,
+}
+
+impl AdvancementDisplay<'_> {
+    const HAS_BACKGROUND: i32 = 0x01;
+    const HIDDEN: i32 = 0x04;
+    const SHOW_TOAST: i32 = 0x02;
+
+    const fn flags(&self) -> i32 {
+        let mut flags = 0;
+        if self.background.is_some() {
+            flags |= Self::HAS_BACKGROUND;
+        }
+        if self.show_toast {
+            flags |= Self::SHOW_TOAST;
+        }
+        if self.hidden {
+            flags |= Self::HIDDEN;
+        }
+        flags
+    }
+}
+
+impl Encode for AdvancementDisplay<'_> {
+    fn encode(&self, mut w: impl Write) -> anyhow::Result<()> {
+        self.title.encode(&mut w)?;
+        self.description.encode(&mut w)?;
+        self.icon.encode(&mut w)?;
+        self.frame.encode(&mut w)?;
+        self.flags().encode(&mut w)?;
+
+        // The background is not prefixed by a boolean; its presence is part of the flags
+        if let Some(background) = &self.background {
+            background.encode(&mut w)?;
+        }
+
+        self.x.encode(&mut w)?;
+        self.y.encode(&mut w)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use valence_protocol::ItemKind;
+    use valence_text::IntoText;
+
+    use super::*;
+
+    fn ident(s: &'static str) -> Ident<Cow<'static, str>> {
+        Ident::new(Cow::Borrowed(s)).unwrap()
+    }
+
+    fn display(background: Option<Ident<Cow<'static, str>>>) -> AdvancementDisplay<'static> {
+        AdvancementDisplay {
+            title: Cow::Owned("Title".into_text()),
+            description: Cow::Owned("Description".into_text()),
+            icon: Cow::Owned(ItemStack::new(ItemKind::Diamond, 1, None)),
+            frame: FrameType::Challenge,
+            background,
+            show_toast: true,
+            hidden: false,
+            x: 1.0,
+            y: 2.0,
+        }
+    }
+
+    fn encode(value: &impl Encode) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        value.encode(&mut bytes).unwrap();
+        bytes
+    }
+
+    /// The fields of a display up to and including the frame
+    fn display_prefix(display: &AdvancementDisplay<'_>) -> Vec<u8> {
+        let mut bytes = encode(&display.title);
+        bytes.extend(encode(&display.description));
+        bytes.extend(encode(&display.icon));
+        bytes.extend(encode(&VarInt(1)));
+        bytes
+    }
+
+    #[test]
+    fn display_without_background() {
+        let display = display(None);
+
+        let mut expected = display_prefix(&display);
+        expected.extend(0x02_i32.to_be_bytes());
+        expected.extend(1.0_f32.to_be_bytes());
+        expected.extend(2.0_f32.to_be_bytes());
+
+        assert_eq!(encode(&display), expected);
+    }
+
+    #[test]
+    fn display_with_background() {
+        let background = ident("minecraft:textures/block/stone.png");
+        let display = display(Some(background.clone()));
+
+        let mut expected = display_prefix(&display);
+        expected.extend(0x03_i32.to_be_bytes());
+        expected.extend(encode(&background));
+        expected.extend(1.0_f32.to_be_bytes());
+        expected.extend(2.0_f32.to_be_bytes());
+
+        assert_eq!(encode(&display), expected);
+    }
+
+    #[test]
+    fn advancement_encoding() {
+        let advancement = Advancement {
+            parent: Some(ident("hyperion:root")),
+            display: None,
+            criteria: vec![Cow::Borrowed("a"), Cow::Borrowed("b")],
+            requirements: vec![vec![Cow::Borrowed("a")], vec![Cow::Borrowed("b")]],
+            sends_telemetry_data: false,
+        };
+
+        let mut expected = vec![1];
+        expected.extend(encode(&ident("hyperion:root")));
+        // No display, then two criteria names without values
+        expected.extend([0, 2, 1, b'a', 1, b'b']);
+        // Two requirement lists with one criterion each
+        expected.extend([2, 1, 1, b'a', 1, 1, b'b']);
+        // No telemetry
+        expected.push(0);
+
+        assert_eq!(encode(&advancement), expected);
+    }
+
+    #[test]
+    fn progress_encoding() {
+        let pkt = AdvancementUpdateS2c {
+            reset: false,
+            added: Vec::new(),
+            removed: vec![ident("hyperion:toast")],
+            progress: vec![(ident("hyperion:a"), vec![
+                (Cow::Borrowed("done"), Some(5)),
+                (Cow::Borrowed("x"), None),
+            ])],
+        };
+
+        let mut expected = vec![0, 0, 1];
+        expected.extend(encode(&ident("hyperion:toast")));
+        expected.push(1);
+        expected.extend(encode(&ident("hyperion:a")));
+        expected.extend([2, 4]);
+        expected.extend(b"done");
+        expected.push(1);
+        expected.extend(5_i64.to_be_bytes());
+        expected.extend([1, b'x', 0]);
+
+        assert_eq!(encode(&pkt), expected);
+    }
+}
@@ -1,37 +1,58 @@
 //! Communication to a proxy which forwards packets to the players.
 
 use std::{
-    net::SocketAddr,
+    borrow::Cow,
+    net::{IpAddr, SocketAddr},
     process::Command,
     sync::{
         Arc,
         atomic::{AtomicU64, Ordering},
     },
+    time::Instant,
 };
 
+use anyhow::ensure;
 use bevy_ecs::{entity::Entity, message::Messages, query::With, world::World};
-use hyperion_proto::ArchivedProxyToServerMessage;
-use hyperion_utils::EntityExt;
+use bytes::Bytes;
+use hyperion_proto::{ArchivedProxyToServerMessage, Features, Hello, Side};
+use rkyv::util::AlignedVec;
 use rustc_hash::FxHashMap;
-use rustls::{
-    RootCertStore,
-    server::{ServerConfig, WebPkiClientVerifier},
+use rustls::HandshakeKind;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    sync::Notify,
 };
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
 use tokio_rustls::TlsAcceptor;
-use tracing::{error, info, warn};
+use tracing::{debug, error, info, warn};
 use valence_protocol::{VarInt, packets::play};
 
 use crate::{
-    ConnectionId, Crypto, PacketDecoder,
+    ConnectionId, Crypto, PacketDecoder, PlayerCount, ProxyPlayerCounts,
+    activity::IdleWaker,
     command_channel::CommandChannel,
-    net::{Channel, ChannelId, Compose, IoBuf, ProxyId},
+    egress::backlog::Backlog,
+    ingress::{
+        forwarding::Forwarding,
+        limits::IngressBudget,
+        pending::{self, ClientIp, PendingConnectionLimits, PendingConnections},
+    },
+    net::{
+        Channel, ChannelId, Channels, Compose, IoBuf, MAX_PACKET_SIZE, ProxyId,
+        metrics::NetworkMetrics,
+    },
     runtime::AsyncRuntime,
-    simulation::{EgressComm, RequestSubscribeChannelPackets, StreamLookup, packet_state},
+    simulation::{
+        EgressComm, RequestSubscribeChannelPackets, StreamLookup,
+        minecraft_id::MinecraftIdRegistry, packet_state,
+    },
 };
 
 // TODO: Determine a better default
-const DEFAULT_FRAGMENT_SIZE: usize = 4096;
+pub(crate) const DEFAULT_FRAGMENT_SIZE: usize = 4096;
+
+/// The maximum size of a message received from a proxy. The proxy forwards at most a few reads
+/// from a player socket per message, so this is far above any legitimate message.
+pub const MAX_PROXY_MESSAGE_SIZE: usize = 8 * MAX_PACKET_SIZE;
 
 fn get_pid_from_port(port: u16) -> Result<Option<u32>, std::io::Error> {
     let output = if cfg!(target_os = "windows") {
@@ -52,9 +73,110 @@ fn get_pid_from_port(port: u16) -> Result<Option<u32>, std::io::Error> {
     Ok(pid)
 }
 
+/// Spawns the entity of a connection which has just connected from `ip`, whose packets are
+/// received from `receiver`.
+///
+/// If `ip` already has as many connections which have not entered the play state as
+/// [`PendingConnectionLimits::max_per_ip`] allows, the connection is closed and its packets are
+/// dropped. The entity is still spawned so that it is despawned like any other connection once
+/// the proxy reports the disconnect. With player info forwarding, `ip` is the address of the
+/// forwarding proxy, so the connection is only counted once the client's address is forwarded.
+pub(crate) fn spawn_connection(
+    world: &mut World,
+    connection_id: ConnectionId,
+    ip: Option<IpAddr>,
+    receiver: packet_channel::Receiver,
+) -> Entity {
+    let max_per_ip = world
+        .get_resource::<PendingConnectionLimits>()
+        .copied()
+        .unwrap_or_default()
+        .max_per_ip;
+    let forwarded = pending::counts_forwarded_ip(world.get_resource::<Forwarding>());
+    let pending = ip.filter(|_| !forwarded).map(|ip| {
+        world
+            .get_resource_or_init::<PendingConnections>()
+            .try_add(ip, max_per_ip)
+    });
+    let rejected = matches!(pending, Some(None));
+    let pending = pending.flatten();
+
+    let player = world
+        .spawn((
+            connection_id,
+            packet_state::Handshake,
+            PacketDecoder::default(),
+            IngressBudget::default(),
+            Backlog::default(),
+            if rejected {
+                packet_channel::Receiver::default()
+            } else {
+                receiver
+            },
+        ))
+        .id();
+
+    if let Some(ip) = ip {
+        world.entity_mut(player).insert(ClientIp(ip));
+    }
+
+    if let Some(pending) = pending {
+        world.entity_mut(player).insert(pending);
+    }
+
+    world
+        .get_resource_mut::<StreamLookup>()
+        .expect("StreamLookup resource should exist")
+        .insert(connection_id.inner(), player);
+
+    if rejected {
+        warn!("refusing connection from {ip:?}, which has too many pending connections");
+        if let Some(metrics) = world.get_resource::<NetworkMetrics>() {
+            metrics.ip_limit_rejections.fetch_add(1, Ordering::Relaxed);
+        }
+        world.resource::<Compose>().io_buf().shutdown(connection_id);
+    }
+
+    player
+}
+
+/// Removes the players of `proxy_id` from the player counts after the proxy disconnected and its
+/// players were despawned. Only players which were somehow still counted are subtracted, so the
+/// players of other proxies are never affected.
+fn forget_proxy_players(world: &mut World, proxy_id: ProxyId) {
+    let Some(mut counts) = world.get_resource_mut::<ProxyPlayerCounts>() else {
+        return;
+    };
+
+    let remaining = counts.remove_proxy(proxy_id);
+    if remaining == 0 {
+        return;
+    }
+
+    warn!("{remaining} players of proxy {proxy_id:?} were still counted after it disconnected");
+    if let Some(count) = world.get_resource::<PlayerCount>() {
+        count.0.fetch_sub(remaining, Ordering::Relaxed);
+    }
+}
+
+/// Despawns the entity of the connection with the stream ID `stream` after it disconnected
+pub(crate) fn despawn_connection(world: &mut World, stream: u64) {
+    let Some(player) = world
+        .get_resource_mut::<StreamLookup>()
+        .expect("StreamLookup resource should exist")
+        .remove(&stream)
+    else {
+        // The player joined again and their new session already despawned this connection
+        return;
+    };
+
+    world.despawn(player);
+}
+
 async fn handle_proxy_messages(
     read: impl AsyncRead + Unpin,
     command_channel: CommandChannel,
+    waker: IdleWaker,
     proxy_id: ProxyId,
 ) {
     let mut reader = ProxyReader::new(read);
@@ -82,12 +204,20 @@ async fn handle_proxy_messages(
             }
         };
 
-        let result = unsafe { rkyv::access_unchecked::<ArchivedProxyToServerMessage<'_>>(buffer) };
+        let result = match access_proxy_message(buffer) {
+            Ok(result) => result,
+            Err(e) => {
+                error!("closing proxy connection due to an invalid message: {e}");
+                break;
+            }
+        };
 
         match result {
             ArchivedProxyToServerMessage::PlayerConnect(message) => {
                 let Ok(stream) =
                     rkyv::deserialize::<u64, std::convert::Infallible>(&message.stream);
+                let Ok(ip) =
+                    rkyv::deserialize::<Option<IpAddr>, std::convert::Infallible>(&message.ip);
 
                 let (sender, receiver) = packet_channel::channel(DEFAULT_FRAGMENT_SIZE);
                 if player_packet_sender.insert(stream, sender).is_some() {
@@ -97,20 +227,12 @@ async fn handle_proxy_messages(
                     );
                 }
 
-                command_channel.push(move |world: &mut World| {
-                    let player = world
-                        .spawn((
-                            ConnectionId::new(stream, proxy_id),
-                            packet_state::Handshake,
-                            PacketDecoder::default(),
-                            receiver,
-                        ))
-                        .id();
-                    world
-                        .get_resource_mut::<StreamLookup>()
-                        .expect("StreamLookup resource should exist")
-                        .insert(stream, player);
-                });
+                command_channel
+                    .send_priority(move |world: &mut World| {
+                        spawn_connection(world, ConnectionId::new(stream, proxy_id), ip, receiver);
+                    })
+                    .await;
+                waker.wake();
             }
             ArchivedProxyToServerMessage::PlayerDisconnect(message) => {
                 let Ok(stream) =
@@ -122,15 +244,11 @@ async fn handle_proxy_messages(
                     );
                 }
 
-                command_channel.push(move |world: &mut World| {
-                    let player = world
-                        .get_resource_mut::<StreamLookup>()
-                        .expect("StreamLookup resource should exist")
-                        .remove(&stream)
-                        .expect("player from PlayerDisconnect must exist in the stream lookup map");
-
-                    world.despawn(player);
-                });
+                command_channel
+                    .send_priority(move |world: &mut World| {
+                        despawn_connection(world, stream);
+                    })
+                    .await;
             }
             ArchivedProxyToServerMessage::PlayerPackets(message) => {
                 let Ok(stream) =
@@ -157,14 +275,16 @@ async fn handle_proxy_messages(
                         SendError::AlreadyClosed => false,
                     };
                     if needs_shutdown {
-                        command_channel.push(move |world: &mut World| {
-                            let compose = world
-                                .get_resource::<Compose>()
-                                .expect("Compose resource should exist");
-                            compose
-                                .io_buf()
-                                .shutdown(ConnectionId::new(stream, proxy_id));
-                        });
+                        command_channel
+                            .send(move |world: &mut World| {
+                                let compose = world
+                                    .get_resource::<Compose>()
+                                    .expect("Compose resource should exist");
+                                compose
+                                    .io_buf()
+                                    .shutdown(ConnectionId::new(stream, proxy_id));
+                            })
+                            .await;
                     }
                 }
             }
@@ -181,44 +301,76 @@ async fn handle_proxy_messages(
                         }
                     };
 
-                command_channel.push(move |world: &mut World| {
-                    // TODO: Is it possible to avoid this second allocation?
-                    let channels = channels
-                        .into_iter()
-                        .filter_map(|channel_id| match Entity::from_id(channel_id, world) {
-                            Ok(channel) => Some(RequestSubscribeChannelPackets(channel)),
-                            Err(e) => {
-                                error!(
-                                    "RequestSubscribeChannelPackets: channel id is invalid: {e}"
-                                );
-                                None
-                            }
-                        })
-                        .collect::<Vec<_>>();
+                command_channel
+                    .send(move |world: &mut World| {
+                        // TODO: Is it possible to avoid this second allocation?
+                        let registered = world.resource::<Channels>();
+                        let channels = channels
+                            .into_iter()
+                            .filter_map(|channel_id| {
+                                let channel = registered.get(ChannelId::new(channel_id));
+                                // The channel may have been despawned since the proxy sent this
+                                if channel.is_none() {
+                                    debug!(
+                                        "RequestSubscribeChannelPackets: channel {channel_id} \
+                                         does not exist"
+                                    );
+                                }
+                                channel.map(RequestSubscribeChannelPackets)
+                            })
+                            .collect::<Vec<_>>();
+
+                        let mut messages =
+                            world.resource_mut::<Messages<RequestSubscribeChannelPackets>>();
+                        messages.write_batch(channels);
+                    })
+                    .await;
+            }
+            ArchivedProxyToServerMessage::StreamBacklog(message) => {
+                let Ok(stream) =
+                    rkyv::deserialize::<u64, std::convert::Infallible>(&message.stream);
+                let Ok(bytes) = rkyv::deserialize::<u64, std::convert::Infallible>(&message.bytes);
 
-                    let mut messages =
-                        world.resource_mut::<Messages<RequestSubscribeChannelPackets>>();
-                    messages.write_batch(channels);
-                });
+                command_channel
+                    .send(move |world: &mut World| {
+                        // The player may have disconnected since the backlog was reported
+                        let Some(&player) = world.resource::<StreamLookup>().get(&stream) else {
+                            return;
+                        };
+
+                        if let Some(mut backlog) = world.get_mut::<Backlog>(player) {
+                            backlog.report(bytes);
+                        }
+                    })
+                    .await;
             }
         }
     }
 
     // Disconnect all players that were connected through this proxy
-    command_channel.push(move |world: &mut World| {
-        let mut query = world.query::<(Entity, &ConnectionId)>();
-        let players_to_remove = query
-            .iter(world)
-            .filter(|(_, connection_id)| connection_id.proxy_id() == proxy_id)
-            .map(|(entity, _)| entity)
-            .collect::<Vec<_>>();
-        for player in players_to_remove {
-            world.despawn(player);
-        }
-    });
+    command_channel
+        .send_priority(move |world: &mut World| {
+            let mut query = world.query::<(Entity, &ConnectionId)>();
+            let players_to_remove = query
+                .iter(world)
+                .filter(|(_, connection_id)| connection_id.proxy_id() == proxy_id)
+                .map(|(entity, _)| entity)
+                .collect::<Vec<_>>();
+            for player in players_to_remove {
+                world.despawn(player);
+            }
+
+            forget_proxy_players(world, proxy_id);
+        })
+        .await;
 }
 
-async fn inner(socket: SocketAddr, crypto: Crypto, command_channel: CommandChannel) {
+async fn inner(
+    socket: SocketAddr,
+    crypto: Crypto,
+    command_channel: CommandChannel,
+    waker: IdleWaker,
+) {
     let listener = match tokio::net::TcpListener::bind(socket).await {
         Ok(listener) => listener,
         Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => {
@@ -246,22 +398,9 @@ async fn inner(socket: SocketAddr, crypto: Crypto, command_channel: CommandChann
         Err(e) => panic!("Failed to bind to address {socket}: {e}"),
     };
 
-    let root_cert_store = Arc::new(RootCertStore {
-        roots: vec![
-            webpki::anchor_from_trusted_cert(&crypto.root_ca_cert)
-                .unwrap()
-                .to_owned(),
-        ],
-    });
-
-    let config = ServerConfig::builder()
-        .with_client_cert_verifier(
-            WebPkiClientVerifier::builder(root_cert_store)
-                .build()
-                .unwrap(),
-        )
-        .with_single_cert(vec![crypto.cert, crypto.root_ca_cert], crypto.key)
-        .unwrap();
+    let config = crypto
+        .server_config()
+        .expect("failed to create tls server config");
 
     let acceptor = TlsAcceptor::from(Arc::new(config));
 
@@ -287,6 +426,7 @@ async fn inner(socket: SocketAddr, crypto: Crypto, command_channel: CommandChann
                 let stream = acceptor.accept(socket);
 
                 tokio::spawn(async move {
+                    let handshake_start = Instant::now();
                     let stream = match stream.await {
                         Ok(stream) => stream,
                         Err(e) => {
@@ -298,74 +438,107 @@ async fn inner(socket: SocketAddr, crypto: Crypto, command_channel: CommandChann
                         }
                     };
 
-                    info!("Proxy connection established on {addr}");
+                    let handshake = handshake_start.elapsed();
+                    let resumed =
+                        stream.get_ref().1.handshake_kind() == Some(HandshakeKind::Resumed);
+                    command_channel
+                        .send_priority(move |world: &mut World| {
+                            world
+                                .resource::<NetworkMetrics>()
+                                .record_proxy_handshake(handshake, resumed);
+                        })
+                        .await;
+
+                    info!(
+                        "Proxy connection established on {addr} after a {}handshake of \
+                         {handshake:?}",
+                        if resumed { "resumed " } else { "" }
+                    );
+
+                    let mut stream = stream;
+                    let features = match exchange_hello(&mut stream).await {
+                        Ok(features) => features,
+                        Err(e) => {
+                            error!("refusing proxy connection from {addr}: {e}");
+                            return;
+                        }
+                    };
 
                     let (read, mut write) = tokio::io::split(stream);
 
                     let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
-                    let egress_comm = EgressComm::from(tx.clone());
+                    let mut egress_comm = EgressComm::from(tx.clone());
+                    egress_comm.features = features;
+                    let flush = egress_comm.flush.clone();
                     let proxy_id = ProxyId::new(next_proxy_id.fetch_add(1, Ordering::Relaxed));
 
-                    command_channel.push(move |world: &mut World| {
-                        let mut compose = world.resource_mut::<Compose>();
-                        compose.io_buf_mut().add_proxy(proxy_id, egress_comm);
-                    });
+                    command_channel
+                        .send_priority(move |world: &mut World| {
+                            let mut compose = world.resource_mut::<Compose>();
+                            compose.io_buf_mut().add_proxy(proxy_id, egress_comm);
+                        })
+                        .await;
 
                     let command_channel_clone = command_channel.clone();
                     tokio::spawn(async move {
-                        // Send the bytes from the channel to the proxy
-                        while let Some(bytes) = rx.recv().await {
-                            if write.write_all(&bytes).await.is_err() {
-                                error!("error writing to proxy");
-                                break;
-                            }
+                        if let Err(e) = write_to_proxy(&mut rx, &flush, &mut write).await {
+                            error!("error writing to proxy: {e}");
                         }
 
                         warn!("proxy shut down");
 
-                        command_channel_clone.push(move |world: &mut World| {
-                            // Remove this channel from the compose egress comms list
-                            let mut compose = world.resource_mut::<Compose>();
-                            let removed = compose.io_buf_mut().remove_proxy(proxy_id).is_some();
-                            if !removed {
-                                error!("failed to remove proxy from compose egress comms");
-                            }
-
-                            // Explicitly close this receiver. This ensures that the channel isn't
-                            // closed before this, which would lead to an error on the sender side
-                            // of Compose.
-                            rx.close();
-                        });
+                        command_channel_clone
+                            .send_priority(move |world: &mut World| {
+                                // Remove this channel from the compose egress comms list
+                                let mut compose = world.resource_mut::<Compose>();
+                                let removed = compose.io_buf_mut().remove_proxy(proxy_id).is_some();
+                                if !removed {
+                                    error!("failed to remove proxy from compose egress comms");
+                                }
+
+                                // Explicitly close this receiver. This ensures that the channel
+                                // isn't closed before this, which would lead to an error on the
+                                // sender side of Compose.
+                                rx.close();
+                            })
+                            .await;
                     });
 
-                    command_channel.push(move |world: &mut World| {
-                        // Let the proxy know about all packet channels that exist at the moment
-
-                        let mut query = world.query_filtered::<Entity, With<Channel>>();
-                        let compose = world.resource::<Compose>();
-                        for channel in query.iter(world) {
-                            let packet = play::EntitiesDestroyS2c {
-                                entity_ids: vec![VarInt(channel.minecraft_id())].into(),
-                            };
-
-                            let packet_buf =
-                                compose.io_buf().encode_packet(&packet, compose).unwrap();
-
-                            tx.send(IoBuf::encode_proxy_message(
-                                &hyperion_proto::ServerToProxyMessage::AddChannel(
-                                    hyperion_proto::AddChannel {
-                                        channel_id: ChannelId::from(channel).inner(),
-                                        unsubscribe_packets: &packet_buf,
-                                    },
-                                ),
-                            ))
-                            .unwrap();
-                        }
-                    });
+                    command_channel
+                        .send_priority(move |world: &mut World| {
+                            // Let the proxy know about all packet channels that exist at the moment
+
+                            let mut query = world.query_filtered::<Entity, With<Channel>>();
+                            let compose = world.resource::<Compose>();
+                            let ids = world.resource::<MinecraftIdRegistry>();
+                            for channel in query.iter(world) {
+                                let packet = play::EntitiesDestroyS2c {
+                                    entity_ids: Cow::Borrowed(&[VarInt(ids.minecraft_id(channel))]),
+                                };
+
+                                let message = compose
+                                    .io_buf()
+                                    .with_encoded_packet(&packet, compose, |unsubscribe_packets| {
+                                        IoBuf::encode_proxy_message(
+                                            &hyperion_proto::ServerToProxyMessage::AddChannel(
+                                                hyperion_proto::AddChannel {
+                                                    channel_id: ChannelId::from(channel).inner(),
+                                                    unsubscribe_packets,
+                                                },
+                                            ),
+                                        )
+                                    })
+                                    .unwrap();
+
+                                tx.send(message).unwrap();
+                            }
+                        })
+                        .await;
 
                     tokio::spawn(handle_proxy_messages(
                         read,
                         command_channel.clone(),
+                        waker.clone(),
                         proxy_id,
                     ));
                 });
@@ -374,20 +547,110 @@ async fn inner(socket: SocketAddr, crypto: Crypto, command_channel: CommandChann
     );
 }
 
+/// Sends the [`Hello`] of this server to a newly connected proxy and checks the hello the proxy
+/// sends, returning the features both support.
+///
+/// Both hellos are framed like every other message, so the proxy can tell that it connected to a
+/// server that speaks a different protocol.
+async fn exchange_hello(
+    stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+) -> anyhow::Result<Features> {
+    let local = Hello::current();
+    let encoded = local.encode();
+    stream
+        .write_all(&u64::try_from(encoded.len())?.to_be_bytes())
+        .await?;
+    stream.write_all(&encoded).await?;
+    stream.flush().await?;
+
+    let mut len = [0u8; 8];
+    stream.read_exact(&mut len).await?;
+    let mut remote = vec![0; proxy_message_len(len)?];
+    stream.read_exact(&mut remote).await?;
+    let remote = Hello::decode(&remote)?;
+
+    let features = local.negotiate(Side::Server, &remote)?;
+    info!(
+        "proxy speaks proxy-proto {} ({}), negotiated features {:#x}",
+        remote.protocol_version,
+        remote.crate_version,
+        features.bits()
+    );
+    Ok(features)
+}
+
+/// Writes the messages received from `rx` to the proxy. The messages are collected until `flush`
+/// is notified, which happens at the end of every tick and after sending a packet which is flushed
+/// immediately. Everything received so far is then written in order in a single write.
+///
+/// Returns once every sender of `rx` has been dropped.
+pub(crate) async fn write_to_proxy(
+    rx: &mut tokio::sync::mpsc::UnboundedReceiver<Bytes>,
+    flush: &Notify,
+    write: &mut (impl AsyncWrite + Unpin),
+) -> std::io::Result<()> {
+    let mut buffer = Vec::new();
+
+    loop {
+        tokio::select! {
+            () = flush.notified() => {}
+            bytes = rx.recv() => match bytes {
+                Some(bytes) => {
+                    buffer.extend_from_slice(&bytes);
+                    continue;
+                }
+                None => return write.write_all(&buffer).await,
+            },
+        }
+
+        // A flushed message may still be in the channel if the notification won the race
+        while let Ok(bytes) = rx.try_recv() {
+            buffer.extend_from_slice(&bytes);
+        }
+
+        if buffer.is_empty() {
+            continue;
+        }
+
+        write.write_all(&buffer).await?;
+        buffer.clear();
+    }
+}
+
 /// Initializes proxy communications.
 pub fn init_proxy_comms(
     runtime: &AsyncRuntime,
     command_channel: CommandChannel,
+    waker: IdleWaker,
     socket: SocketAddr,
     crypto: Crypto,
 ) {
-    runtime.spawn(inner(socket, crypto, command_channel));
+    runtime.spawn(inner(socket, crypto, command_channel, waker));
+}
+
+/// Validates a message received from a proxy, which must be aligned to 16 bytes
+pub(crate) fn access_proxy_message(
+    buffer: &[u8],
+) -> Result<&ArchivedProxyToServerMessage<'_>, rkyv::rancor::Error> {
+    rkyv::access::<ArchivedProxyToServerMessage<'_>, rkyv::rancor::Error>(buffer)
+}
+
+/// Decodes the big-endian length prefix of a message received from a proxy, rejecting lengths
+/// above [`MAX_PROXY_MESSAGE_SIZE`] before any memory is allocated for the message
+pub(crate) fn proxy_message_len(prefix: [u8; 8]) -> anyhow::Result<usize> {
+    let len = u64::from_be_bytes(prefix);
+    let len = usize::try_from(len)?;
+    ensure!(
+        len <= MAX_PROXY_MESSAGE_SIZE,
+        "proxy message of {len} bytes exceeds the maximum size of {MAX_PROXY_MESSAGE_SIZE} bytes"
+    );
+    Ok(len)
 }
 
 #[derive(Debug)]
 struct ProxyReader<R> {
     server_read: R,
-    buffer: Vec<u8>,
+    buffer: AlignedVec<16>,
 }
 
 impl<R> ProxyReader<R>
@@ -395,9 +658,11 @@ where
     R: AsyncRead + Unpin,
 {
     pub fn new(server_read: R) -> Self {
+        let mut buffer = AlignedVec::new();
+        buffer.resize(1024 * 1024, 0);
         Self {
             server_read,
-            buffer: vec![0; 1024 * 1024],
+            buffer,
         }
     }
 
@@ -405,8 +670,7 @@ where
     pub async fn next_server_packet_buffer(&mut self) -> anyhow::Result<&[u8]> {
         let mut len = [0u8; 8];
         self.server_read.read_exact(&mut len).await?;
-        let len = u64::from_be_bytes(len);
-        let len = usize::try_from(len)?;
+        let len = proxy_message_len(len)?;
 
         if len > self.buffer.len() {
             self.buffer.resize(len, 0);
@@ -419,3 +683,171 @@ where
         Ok(buffer)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::io::DuplexStream;
+    use valence_generated::block::BlockState;
+    use valence_protocol::BlockPos;
+
+    use super::*;
+    use crate::Shared;
+
+    fn compose_with_proxy(egress_comm: EgressComm) -> Compose {
+        let mut io_buf = IoBuf::default();
+        io_buf.add_proxy(ProxyId::new(0), egress_comm);
+
+        Compose::new(
+            libdeflater::CompressionLvl::default(),
+            Arc::new(Shared {
+                compression_threshold: valence_protocol::CompressionThreshold(-1),
+                compression_level: libdeflater::CompressionLvl::default(),
+            }),
+            io_buf,
+        )
+    }
+
+    fn block_update(y: i32) -> play::BlockUpdateS2c {
+        play::BlockUpdateS2c {
+            position: BlockPos::new(0, y, 0),
+            block_id: BlockState::STONE,
+        }
+    }
+
+    fn ack(sequence: i32) -> play::PlayerActionResponseS2c {
+        play::PlayerActionResponseS2c {
+            sequence: VarInt(sequence),
+        }
+    }
+
+    /// Reads the next `n` messages the proxy received, in the order they arrived
+    async fn read_messages(proxy: &mut DuplexStream, n: usize) -> Vec<Vec<u8>> {
+        let mut messages = Vec::new();
+
+        for _ in 0..n {
+            let mut len = [0; 8];
+            proxy.read_exact(&mut len).await.unwrap();
+
+            let mut message = len.to_vec();
+            message.resize(len.len() + proxy_message_len(len).unwrap(), 0);
+            proxy.read_exact(&mut message[len.len()..]).await.unwrap();
+            messages.push(message);
+        }
+
+        messages
+    }
+
+    async fn assert_nothing_arrives(proxy: &mut DuplexStream) {
+        let mut byte = [0];
+        let read = tokio::time::timeout(Duration::from_millis(50), proxy.read(&mut byte)).await;
+        assert!(
+            read.is_err(),
+            "the proxy received data that was not flushed"
+        );
+    }
+
+    #[test]
+    fn immediate_packets_keep_their_order_with_the_batch() {
+        let runtime = AsyncRuntime::new();
+        let stream = ConnectionId::new(1, ProxyId::new(0));
+
+        // The messages in the order the proxy should receive them
+        let (tx, mut expected_rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut expected_compose = compose_with_proxy(tx.into());
+        expected_compose.unicast(&block_update(0), stream).unwrap();
+        expected_compose.unicast(&ack(1), stream).unwrap();
+        expected_compose.unicast(&ack(2), stream).unwrap();
+        expected_compose
+            .broadcast(&block_update(1))
+            .batched()
+            .send()
+            .unwrap();
+        expected_compose.io_buf_mut().flush_batch();
+        expected_compose.unicast(&ack(3), stream).unwrap();
+        let expected: Vec<_> = std::iter::from_fn(|| expected_rx.try_recv().ok())
+            .map(|bytes| bytes.to_vec())
+            .collect();
+        assert_eq!(expected.len(), 5);
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let egress_comm = EgressComm::from(tx);
+        let flush = egress_comm.flush.clone();
+        let mut compose = compose_with_proxy(egress_comm);
+
+        let (mut proxy, mut server) = tokio::io::duplex(1 << 16);
+        runtime.spawn(async move {
+            write_to_proxy(&mut rx, &flush, &mut server).await.unwrap();
+        });
+
+        runtime.block_on(async {
+            // Bulk packets wait for the end of the tick
+            compose.unicast(&block_update(0), stream).unwrap();
+            assert_nothing_arrives(&mut proxy).await;
+
+            // An immediate packet is written together with the packets sent before it
+            compose.unicast(&ack(1), stream).unwrap();
+            assert_eq!(read_messages(&mut proxy, 2).await, expected[..2]);
+
+            // The batch of the tick is still sent at the end of the tick
+            compose
+                .broadcast(&block_update(1))
+                .batched()
+                .send()
+                .unwrap();
+            compose.unicast(&ack(2), stream).unwrap();
+            assert_eq!(read_messages(&mut proxy, 1).await, expected[2..3]);
+            assert_nothing_arrives(&mut proxy).await;
+
+            // Immediate packets sent after the end of the tick arrive after its batch
+            compose.io_buf_mut().flush_batch();
+            compose.unicast(&ack(3), stream).unwrap();
+            assert_eq!(read_messages(&mut proxy, 2).await, expected[3..]);
+
+            compose.unicast(&block_update(2), stream).unwrap();
+            assert_nothing_arrives(&mut proxy).await;
+        });
+    }
+
+    /// Sends `hello` from the proxy side of `proxy` as a framed message
+    async fn send_hello(proxy: &mut DuplexStream, hello: &Hello) {
+        let encoded = hello.encode();
+        proxy
+            .write_all(&u64::try_from(encoded.len()).unwrap().to_be_bytes())
+            .await
+            .unwrap();
+        proxy.write_all(&encoded).await.unwrap();
+    }
+
+    #[test]
+    fn proxies_with_other_protocol_versions_are_refused() {
+        let runtime = AsyncRuntime::new();
+
+        runtime.block_on(async {
+            let (mut proxy, mut server) = tokio::io::duplex(1 << 16);
+            send_hello(&mut proxy, &Hello {
+                features: Features::BACKLOG_REPORTS,
+                ..Hello::current()
+            })
+            .await;
+            assert_eq!(
+                exchange_hello(&mut server).await.unwrap(),
+                Features::BACKLOG_REPORTS
+            );
+
+            // The server sent its own hello first
+            let [hello] = read_messages(&mut proxy, 1).await.try_into().unwrap();
+            assert_eq!(Hello::decode(&hello[8..]).unwrap(), Hello::current());
+
+            let (mut proxy, mut server) = tokio::io::duplex(1 << 16);
+            send_hello(&mut proxy, &Hello {
+                protocol_version: hyperion_proto::PROTOCOL_VERSION + 1,
+                ..Hello::current()
+            })
+            .await;
+            let error = exchange_hello(&mut server).await.unwrap_err();
+            assert!(error.to_string().contains("proxy protocol mismatch"));
+        });
+    }
+}
@@ -0,0 +1,372 @@
+//! Cached sets of connections which packets can be sent to, see [`Broadcast::filter`].
+//!
+//! Collecting the [`ConnectionId`] of every player with a marker component for each packet would
+//! query the world over and over, so the members of each [`RecipientFilter`] are kept in
+//! [`Recipients`] instead. Observers on the marker component add and remove members as soon as
+//! the component is added or removed, and the list of connections sent to is cached until the
+//! members change.
+//!
+//! Packets of the play state are only sent to members in [`packet_state::Play`], since clients
+//! which are still joining cannot decode them.
+
+use std::{
+    any::TypeId,
+    sync::{Arc, RwLock},
+};
+
+use bevy_app::App;
+use bevy_ecs::{
+    component::Component,
+    entity::Entity,
+    lifecycle::{Add, Remove},
+    observer::On,
+    query::With,
+    system::{Query, Res},
+};
+use rustc_hash::{FxHashMap, FxHashSet};
+
+#[cfg(doc)]
+use crate::net::Broadcast;
+use crate::{
+    net::{Compose, ConnectionId},
+    simulation::packet_state,
+};
+
+/// A set of players which packets can be sent to with [`Broadcast::filter`]. This is implemented
+/// for [`With`] of any component, so `With<InArena>` is every player with an `InArena` marker.
+///
+/// Filters must be registered with [`register_recipient_filter`] before players join.
+pub trait RecipientFilter: 'static {
+    /// Adds the observers which keep the members of this filter up to date
+    fn observe(app: &mut App);
+}
+
+impl<C: Component> RecipientFilter for With<C> {
+    fn observe(app: &mut App) {
+        app.add_observer(add_member::<C>);
+        app.add_observer(remove_member::<C>);
+    }
+}
+
+#[derive(Default)]
+struct RecipientSet {
+    /// The connection of every player matching the filter
+    members: FxHashMap<Entity, ConnectionId>,
+    /// The connections of the members in the play state, if they did not change since they
+    /// were last collected
+    playing: Option<Arc<[ConnectionId]>>,
+    /// The connections of all members, if they did not change since they were last collected
+    all: Option<Arc<[ConnectionId]>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    sets: FxHashMap<TypeId, RecipientSet>,
+    /// The players in the play state
+    playing: FxHashSet<Entity>,
+}
+
+/// The members of every registered [`RecipientFilter`]. See the [module documentation](self).
+#[derive(Default)]
+pub struct Recipients {
+    inner: RwLock<Inner>,
+}
+
+impl Recipients {
+    /// Starts tracking the members of `filter`. Returns whether it was not tracked already.
+    fn register(&self, filter: TypeId) -> bool {
+        let mut inner = self.inner.write().unwrap();
+        if inner.sets.contains_key(&filter) {
+            return false;
+        }
+        inner.sets.insert(filter, RecipientSet::default());
+        true
+    }
+
+    fn insert(&self, filter: TypeId, player: Entity, connection: ConnectionId) {
+        let mut inner = self.inner.write().unwrap();
+        let Some(set) = inner.sets.get_mut(&filter) else {
+            return;
+        };
+
+        set.members.insert(player, connection);
+        set.playing = None;
+        set.all = None;
+    }
+
+    fn remove(&self, filter: TypeId, player: Entity) {
+        let mut inner = self.inner.write().unwrap();
+        let Some(set) = inner.sets.get_mut(&filter) else {
+            return;
+        };
+
+        if set.members.remove(&player).is_some() {
+            set.playing = None;
+            set.all = None;
+        }
+    }
+
+    fn set_playing(&self, player: Entity, playing: bool) {
+        let mut inner = self.inner.write().unwrap();
+        let changed = if playing {
+            inner.playing.insert(player)
+        } else {
+            inner.playing.remove(&player)
+        };
+
+        if !changed {
+            return;
+        }
+
+        for set in inner.sets.values_mut() {
+            if set.members.contains_key(&player) {
+                set.playing = None;
+            }
+        }
+    }
+
+    /// The connections of the members of `F`, or [`None`] if `F` is not registered. If
+    /// `play_only` is set, only the members in the play state are returned.
+    #[must_use]
+    pub fn get<F: RecipientFilter>(&self, play_only: bool) -> Option<Arc<[ConnectionId]>> {
+        self.get_by_id(TypeId::of::<F>(), play_only)
+    }
+
+    pub(crate) fn get_by_id(&self, filter: TypeId, play_only: bool) -> Option<Arc<[ConnectionId]>> {
+        {
+            let inner = self.inner.read().unwrap();
+            let set = inner.sets.get(&filter)?;
+            let cached = if play_only { &set.playing } else { &set.all };
+            if let Some(cached) = cached {
+                return Some(cached.clone());
+            }
+        }
+
+        let mut inner = self.inner.write().unwrap();
+        let Inner { sets, playing } = &mut *inner;
+        let RecipientSet {
+            members,
+            playing: cached_playing,
+            all,
+        } = sets.get_mut(&filter)?;
+
+        let cached = if play_only { cached_playing } else { all };
+        let connections = cached.get_or_insert_with(|| {
+            members
+                .iter()
+                .filter(|(player, _)| !play_only || playing.contains(player))
+                .map(|(_, &connection)| connection)
+                .collect()
+        });
+
+        Some(connections.clone())
+    }
+}
+
+/// Lets packets be sent to the players matching `F` with [`Broadcast::filter`]. Registering a
+/// filter more than once has no effect.
+///
+/// Players which matched `F` before it was registered are not members, so filters should be
+/// registered while building the app, after [`HyperionCore`](crate::HyperionCore).
+pub fn register_recipient_filter<F: RecipientFilter>(app: &mut App) {
+    let recipients = app.world().resource::<Compose>().io_buf().recipients();
+    if recipients.register(TypeId::of::<F>()) {
+        F::observe(app);
+    }
+}
+
+fn add_member<C: Component>(
+    added: On<'_, '_, Add, C>,
+    connections: Query<'_, '_, &ConnectionId>,
+    compose: Res<'_, Compose>,
+) {
+    // Only players can receive packets
+    let Ok(&connection) = connections.get(added.entity) else {
+        return;
+    };
+
+    compose
+        .io_buf()
+        .recipients()
+        .insert(TypeId::of::<With<C>>(), added.entity, connection);
+}
+
+fn remove_member<C: Component>(removed: On<'_, '_, Remove, C>, compose: Res<'_, Compose>) {
+    compose
+        .io_buf()
+        .recipients()
+        .remove(TypeId::of::<With<C>>(), removed.entity);
+}
+
+pub(crate) fn enter_play(added: On<'_, '_, Add, packet_state::Play>, compose: Res<'_, Compose>) {
+    compose
+        .io_buf()
+        .recipients()
+        .set_playing(added.entity, true);
+}
+
+pub(crate) fn leave_play(
+    removed: On<'_, '_, Remove, packet_state::Play>,
+    compose: Res<'_, Compose>,
+) {
+    compose
+        .io_buf()
+        .recipients()
+        .set_playing(removed.entity, false);
+}
+
+#[cfg(test)]
+mod tests {
+    use hyperion_proto::ArchivedServerToProxyMessage;
+    use libdeflater::CompressionLvl;
+    use rkyv::util::AlignedVec;
+    use valence_protocol::{VarInt, packets::play::PlayerActionResponseS2c};
+
+    use super::*;
+    use crate::{
+        Shared,
+        net::{IoBuf, ProxyId},
+    };
+
+    #[derive(Component)]
+    struct InArena;
+
+    fn app() -> App {
+        app_with(IoBuf::default())
+    }
+
+    fn app_with(io_buf: IoBuf) -> App {
+        let mut app = App::new();
+        app.insert_resource(Compose::new(
+            CompressionLvl::default(),
+            Arc::new(Shared {
+                compression_threshold: valence_protocol::CompressionThreshold(-1),
+                compression_level: CompressionLvl::default(),
+            }),
+            io_buf,
+        ));
+        app.add_observer(enter_play);
+        app.add_observer(leave_play);
+        register_recipient_filter::<With<InArena>>(&mut app);
+        app
+    }
+
+    fn recipients(app: &App, play_only: bool) -> Vec<ConnectionId> {
+        let compose = app.world().resource::<Compose>();
+        let mut recipients = compose
+            .io_buf()
+            .recipients()
+            .get::<With<InArena>>(play_only)
+            .unwrap()
+            .to_vec();
+        recipients.sort_by_key(|connection| connection.inner());
+        recipients
+    }
+
+    #[test]
+    fn toggling_a_marker_updates_the_recipients() {
+        let mut app = app();
+        let [a, b] = [1, 2].map(|stream| ConnectionId::new(stream, ProxyId::new(0)));
+        let player_a = app.world_mut().spawn((a, packet_state::Play)).id();
+        let player_b = app.world_mut().spawn((b, packet_state::Play)).id();
+
+        assert!(recipients(&app, true).is_empty());
+
+        app.world_mut().entity_mut(player_a).insert(InArena);
+        assert_eq!(recipients(&app, true), [a]);
+
+        app.world_mut().entity_mut(player_b).insert(InArena);
+        assert_eq!(recipients(&app, true), [a, b]);
+
+        app.world_mut().entity_mut(player_a).remove::<InArena>();
+        assert_eq!(recipients(&app, true), [b]);
+
+        app.world_mut().entity_mut(player_b).despawn();
+        assert!(recipients(&app, true).is_empty());
+    }
+
+    #[test]
+    fn play_packets_only_go_to_playing_members() {
+        let mut app = app();
+        let connection = ConnectionId::new(1, ProxyId::new(0));
+        let player = app.world_mut().spawn((connection, InArena)).id();
+
+        assert!(recipients(&app, true).is_empty());
+        assert_eq!(recipients(&app, false), [connection]);
+
+        app.world_mut()
+            .entity_mut(player)
+            .insert(packet_state::Play);
+        assert_eq!(recipients(&app, true), [connection]);
+    }
+
+    #[test]
+    fn unregistered_filters_have_no_recipients() {
+        #[derive(Component)]
+        struct Unregistered;
+
+        let app = app();
+        let compose = app.world().resource::<Compose>();
+        assert!(
+            compose
+                .io_buf()
+                .recipients()
+                .get::<With<Unregistered>>(true)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn filtered_broadcasts_are_sent_once_to_the_members() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut io_buf = IoBuf::default();
+        io_buf.add_proxy(ProxyId::new(0), tx.into());
+        let mut app = app_with(io_buf);
+
+        let [a, b, c, d] = [1, 2, 3, 4].map(|stream| ConnectionId::new(stream, ProxyId::new(0)));
+        for connection in [a, b, c] {
+            app.world_mut()
+                .spawn((connection, packet_state::Play, InArena));
+        }
+        app.world_mut().spawn((d, packet_state::Play));
+
+        let packet = PlayerActionResponseS2c {
+            sequence: VarInt(0),
+        };
+        app.world()
+            .resource::<Compose>()
+            .broadcast(&packet)
+            .filter::<With<InArena>>()
+            .exclude(b)
+            .send()
+            .unwrap();
+
+        let mut multicasts = Vec::new();
+        while let Ok(bytes) = rx.try_recv() {
+            let (len, message) = bytes.split_first_chunk::<8>().unwrap();
+            assert_eq!(
+                usize::try_from(u64::from_be_bytes(*len)).unwrap(),
+                message.len()
+            );
+
+            let mut aligned = AlignedVec::<16>::new();
+            aligned.extend_from_slice(message);
+            // SAFETY: the message was encoded by the server
+            let message =
+                unsafe { rkyv::access_unchecked::<ArchivedServerToProxyMessage<'_>>(&aligned) };
+            let ArchivedServerToProxyMessage::Multicast(multicast) = message else {
+                panic!("a filtered broadcast should be sent as a multicast");
+            };
+
+            let mut streams: Vec<u64> = multicast
+                .streams
+                .iter()
+                .map(|&stream| stream.into())
+                .collect();
+            streams.sort_unstable();
+            multicasts.push(streams);
+        }
+
+        assert_eq!(multicasts, [vec![a.inner(), c.inner()]]);
+    }
+}
@@ -0,0 +1,279 @@
+//! The file format of captures.
+//!
+//! A capture starts with [`MAGIC`], the format version as a little-endian `u16` and the seed of
+//! the [`GameRng`](crate::GameRng) as a little-endian `u64`, followed by the records until the end
+//! of the file. Captures of version 1 have no seed. Every record starts with its tick and stream as
+//! little-endian `u64`s and a tag byte. Packet records continue with the packet ID as a
+//! little-endian `i32`, the length of the body as a little-endian `u32` and the body itself.
+
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, ErrorKind, Read, Write},
+    path::Path,
+};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use thiserror::Error;
+
+use crate::net::MAX_PACKET_SIZE;
+
+/// The bytes every capture starts with
+pub const MAGIC: &[u8; 8] = b"HYPRCAPT";
+
+/// The version of the format written by [`CaptureWriter`]
+pub const VERSION: u16 = 2;
+
+const TAG_CONNECT: u8 = 0;
+const TAG_DISCONNECT: u8 = 1;
+const TAG_PACKET: u8 = 2;
+
+#[derive(Debug, Error)]
+pub enum CaptureError {
+    #[error("failed to access capture: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("file is not a capture")]
+    InvalidMagic,
+    #[error("capture version {0} is not supported")]
+    UnsupportedVersion(u16),
+    #[error("capture contains a record with the unknown tag {0}")]
+    UnknownTag(u8),
+    #[error("capture contains a packet body of {0} bytes, which is too large")]
+    TooLarge(u32),
+    #[error("capture records are not ordered by tick")]
+    Unordered,
+}
+
+/// What happened to a connection
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RecordKind {
+    /// The connection connected to the server
+    Connect,
+    /// The connection disconnected from the server
+    Disconnect,
+    /// The connection sent a packet
+    Packet {
+        id: i32,
+        /// The uncompressed contents of the packet after the ID
+        body: Vec<u8>,
+    },
+}
+
+/// Something that happened to a connection during a tick
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    /// The number of ticks since the capture started
+    pub tick: u64,
+    /// The stream ID of the connection
+    pub stream: u64,
+    pub kind: RecordKind,
+}
+
+/// The ingress of every connection recorded while a server was running
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Capture {
+    /// The seed of the [`GameRng`](crate::GameRng) of the recorded server, or 0 for captures of
+    /// version 1
+    pub seed: u64,
+    /// The records in the order they happened
+    pub records: Vec<Record>,
+}
+
+impl Capture {
+    /// Loads the capture at `path`
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, CaptureError> {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+
+    /// Saves the capture to `path`, replacing the file if it exists
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), CaptureError> {
+        let mut writer = CaptureWriter::new(BufWriter::new(File::create(path)?), self.seed)?;
+        for record in &self.records {
+            writer.append(record)?;
+        }
+        writer.into_inner().flush()?;
+        Ok(())
+    }
+
+    pub fn read(mut reader: impl Read) -> Result<Self, CaptureError> {
+        let mut magic = [0; MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(CaptureError::InvalidMagic);
+        }
+
+        let seed = match reader.read_u16::<LittleEndian>()? {
+            1 => 0,
+            VERSION => reader.read_u64::<LittleEndian>()?,
+            version => return Err(CaptureError::UnsupportedVersion(version)),
+        };
+
+        let mut records = Vec::new();
+
+        loop {
+            let tick = match reader.read_u64::<LittleEndian>() {
+                Ok(tick) => tick,
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.into()),
+            };
+
+            if records.last().is_some_and(|last: &Record| last.tick > tick) {
+                return Err(CaptureError::Unordered);
+            }
+
+            let stream = reader.read_u64::<LittleEndian>()?;
+
+            let kind = match reader.read_u8()? {
+                TAG_CONNECT => RecordKind::Connect,
+                TAG_DISCONNECT => RecordKind::Disconnect,
+                TAG_PACKET => {
+                    let id = reader.read_i32::<LittleEndian>()?;
+                    let len = reader.read_u32::<LittleEndian>()?;
+                    if len as usize > MAX_PACKET_SIZE {
+                        return Err(CaptureError::TooLarge(len));
+                    }
+
+                    let mut body = vec![0; len as usize];
+                    reader.read_exact(&mut body)?;
+                    RecordKind::Packet { id, body }
+                }
+                tag => return Err(CaptureError::UnknownTag(tag)),
+            };
+
+            records.push(Record { tick, stream, kind });
+        }
+
+        Ok(Self { seed, records })
+    }
+}
+
+/// Appends records to a capture as they happen
+#[derive(Debug)]
+pub struct CaptureWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> CaptureWriter<W> {
+    /// Writes the header of a capture recorded with the [`GameRng`](crate::GameRng) seed `seed`
+    /// to `inner`
+    pub fn new(mut inner: W, seed: u64) -> Result<Self, CaptureError> {
+        inner.write_all(MAGIC)?;
+        inner.write_u16::<LittleEndian>(VERSION)?;
+        inner.write_u64::<LittleEndian>(seed)?;
+        Ok(Self { inner })
+    }
+
+    pub fn append(&mut self, record: &Record) -> Result<(), CaptureError> {
+        match &record.kind {
+            RecordKind::Connect => self.append_event(record.tick, record.stream, TAG_CONNECT),
+            RecordKind::Disconnect => self.append_event(record.tick, record.stream, TAG_DISCONNECT),
+            RecordKind::Packet { id, body } => {
+                self.append_packet(record.tick, record.stream, *id, body)
+            }
+        }
+    }
+
+    /// Appends a packet without copying its body into a [`Record`] first
+    pub fn append_packet(
+        &mut self,
+        tick: u64,
+        stream: u64,
+        id: i32,
+        body: &[u8],
+    ) -> Result<(), CaptureError> {
+        let len = u32::try_from(body.len()).map_err(|_| CaptureError::TooLarge(u32::MAX))?;
+        if body.len() > MAX_PACKET_SIZE {
+            return Err(CaptureError::TooLarge(len));
+        }
+
+        self.append_event(tick, stream, TAG_PACKET)?;
+        self.inner.write_i32::<LittleEndian>(id)?;
+        self.inner.write_u32::<LittleEndian>(len)?;
+        self.inner.write_all(body)?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), CaptureError> {
+        self.inner.flush()?;
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    fn append_event(&mut self, tick: u64, stream: u64, tag: u8) -> Result<(), CaptureError> {
+        self.inner.write_u64::<LittleEndian>(tick)?;
+        self.inner.write_u64::<LittleEndian>(stream)?;
+        self.inner.write_u8(tag)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn captures_survive_a_round_trip() {
+        let capture = Capture {
+            seed: 0xDEAD_BEEF,
+            records: vec![
+                Record {
+                    tick: 0,
+                    stream: 3,
+                    kind: RecordKind::Connect,
+                },
+                Record {
+                    tick: 2,
+                    stream: 3,
+                    kind: RecordKind::Packet {
+                        id: 0x14,
+                        body: vec![1, 2, 3],
+                    },
+                },
+                Record {
+                    tick: 7,
+                    stream: 3,
+                    kind: RecordKind::Disconnect,
+                },
+            ],
+        };
+
+        let mut writer = CaptureWriter::new(Vec::new(), capture.seed).unwrap();
+        for record in &capture.records {
+            writer.append(record).unwrap();
+        }
+        let bytes = writer.into_inner();
+
+        assert_eq!(Capture::read(bytes.as_slice()).unwrap(), capture);
+
+        // A capture cut off in the middle of a record is rejected
+        let cut = &bytes[..bytes.len() - 4];
+        assert!(matches!(Capture::read(cut), Err(CaptureError::Io(_))));
+
+        assert!(matches!(
+            Capture::read(&b"NOTACAPTURE"[..]),
+            Err(CaptureError::InvalidMagic)
+        ));
+
+        // Captures of version 1 have no seed
+        let mut version_1 = MAGIC.to_vec();
+        version_1.extend_from_slice(&1_u16.to_le_bytes());
+        assert_eq!(
+            Capture::read(version_1.as_slice()).unwrap(),
+            Capture::default()
+        );
+    }
+
+    #[test]
+    fn records_must_be_ordered_by_tick() {
+        let mut writer = CaptureWriter::new(Vec::new(), 0).unwrap();
+        writer.append_packet(5, 1, 0, &[]).unwrap();
+        writer.append_packet(4, 1, 0, &[]).unwrap();
+
+        assert!(matches!(
+            Capture::read(writer.into_inner().as_slice()),
+            Err(CaptureError::Unordered)
+        ));
+    }
+}
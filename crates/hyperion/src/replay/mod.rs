@@ -0,0 +1,17 @@
+//! Recording the ingress of a server and replaying it into a headless server, such as to test
+//! for regressions without connecting a real client.
+//!
+//! [`RecordPlugin`] writes every packet received from every connection to a [`Capture`] file.
+//! [`Playback`] replays such a capture into an [`App`](bevy_app::App) with
+//! [`HyperionCore`](crate::HyperionCore) on a virtual clock, after which the world and the packets
+//! the server sent can be inspected.
+//!
+//! This module is only available with the `replay` feature.
+
+pub mod capture;
+pub mod playback;
+pub mod record;
+
+pub use capture::{Capture, CaptureError, Record, RecordKind};
+pub use playback::{EgressPacket, PLAYBACK_PROXY, Playback, headless_app};
+pub use record::{RecordPlugin, Recorder};
@@ -0,0 +1,301 @@
+//! Replaying a capture into a headless server. See [`Playback`].
+
+use std::{
+    collections::VecDeque,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use bevy_app::{App, FixedFirst, FixedLast, PluginsState};
+use bevy_ecs::{resource::Resource, system::Res, world::World};
+use bevy_time::{Fixed, Time, TimeUpdateStrategy};
+use bytes::Bytes;
+use rustc_hash::FxHashMap;
+use tracing::warn;
+use valence_protocol::{CompressionThreshold, DecodeBytes, Packet};
+
+use crate::{
+    GameRng, HyperionCore, Tick,
+    ingress::auth::AuthMode,
+    net::{
+        Compose, ConnectionId, PacketDecoder, ProxyId,
+        encoder::PacketEncoder,
+        filter::{FilterResult, FilteredPacket, PacketContext, PacketTarget},
+        proxy::{DEFAULT_FRAGMENT_SIZE, despawn_connection, spawn_connection},
+    },
+    replay::capture::{Capture, Record, RecordKind},
+    simulation::StreamLookup,
+    util::mojang::{MojangClient, StubProfiles},
+};
+
+/// The proxy every replayed connection is connected through
+pub const PLAYBACK_PROXY: ProxyId = ProxyId::new(0);
+
+/// A packet the server sent during playback
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EgressPacket {
+    /// The number of ticks since playback started
+    pub tick: u64,
+    pub target: PacketTarget,
+    pub id: i32,
+    /// The uncompressed contents of the packet after the ID
+    pub body: Vec<u8>,
+}
+
+impl EgressPacket {
+    /// Whether this packet was sent to `connection`, either directly or by a broadcast
+    #[must_use]
+    pub fn is_sent_to(&self, connection: ConnectionId) -> bool {
+        match self.target {
+            PacketTarget::Connection(target) => target == connection,
+            PacketTarget::Broadcast | PacketTarget::Channel(_) => true,
+        }
+    }
+
+    /// Decodes the packet as `P`, or returns `None` if it is a different packet
+    #[must_use]
+    pub fn decode<P: Packet + DecodeBytes>(&self) -> Option<anyhow::Result<P>> {
+        (self.id == P::ID).then(|| P::decode_bytes(&mut Bytes::copy_from_slice(&self.body)))
+    }
+}
+
+/// Creates an [`App`] with [`HyperionCore`] which never sends requests to the Mojang API.
+/// Profiles are looked up in `profiles` instead. Replayed clients cannot answer encryption
+/// requests, so players log in with [`AuthMode::Offline`].
+///
+/// Resources of the app, such as the [`Blocks`](crate::simulation::blocks::Blocks), can be
+/// changed before the app is passed to [`Playback::new`].
+#[must_use]
+pub fn headless_app(profiles: StubProfiles) -> App {
+    let mut app = App::new();
+    app.insert_resource(MojangClient::stub(profiles));
+    app.insert_resource(AuthMode::Offline);
+    app.add_plugins(HyperionCore);
+    app
+}
+
+/// The records which have not been replayed yet
+#[derive(Resource)]
+struct PlaybackQueue {
+    records: VecDeque<Record>,
+    senders: FxHashMap<u64, packet_channel::Sender>,
+    /// The number of ticks since playback started
+    tick: Arc<AtomicU64>,
+}
+
+/// Replays a [`Capture`] into an [`App`] with [`HyperionCore`] as fast as the server can tick.
+///
+/// The app runs on a virtual clock which advances [`Time<Fixed>`] by exactly one timestep per
+/// tick, so logic depending on the tick, such as cooldowns and keep alives, behaves the same no
+/// matter how long a tick takes. The [`GameRng`] is seeded with the seed of the capture. Every
+/// record is replayed at the start of the tick it was recorded in, relative to the start of the
+/// capture.
+///
+/// The connections are connected through [`PLAYBACK_PROXY`], which discards the data sent to it.
+/// The packets the server sent can be inspected with [`Playback::egress`].
+pub struct Playback {
+    app: App,
+    tick: Arc<AtomicU64>,
+    egress: Arc<Mutex<Vec<EgressPacket>>>,
+    proxy: tokio::sync::mpsc::UnboundedReceiver<Bytes>,
+}
+
+impl Playback {
+    /// Prepares `app` for replaying `capture`. The app must already contain [`HyperionCore`],
+    /// such as one created by [`headless_app`].
+    #[must_use]
+    pub fn new(mut app: App, capture: Capture) -> Self {
+        if app.plugins_state() != PluginsState::Cleaned {
+            app.finish();
+            app.cleanup();
+        }
+
+        let timestep = app.world().resource::<Time<Fixed>>().timestep();
+        app.insert_resource(TimeUpdateStrategy::ManualDuration(timestep));
+
+        // Drawing the same random numbers as the recorded server keeps playback bit-identical
+        app.insert_resource(GameRng::new(capture.seed));
+
+        let tick = Arc::new(AtomicU64::new(0));
+        let egress = Arc::new(Mutex::new(Vec::new()));
+        let (tx, proxy) = tokio::sync::mpsc::unbounded_channel();
+
+        {
+            let mut compose = app.world_mut().resource_mut::<Compose>();
+            compose.io_buf_mut().add_proxy(PLAYBACK_PROXY, tx.into());
+
+            let tick = tick.clone();
+            let egress = egress.clone();
+            // The egress is recorded last so that it contains the packets as they were sent
+            compose.register_egress_filter(i32::MAX, move |context: &mut PacketContext<'_>| {
+                egress.lock().unwrap().push(EgressPacket {
+                    tick: tick.load(Ordering::Relaxed),
+                    target: context.target(),
+                    id: context.id(),
+                    body: context.body().to_vec(),
+                });
+                FilterResult::Continue
+            });
+        }
+
+        app.insert_resource(PlaybackQueue {
+            records: capture.records.into(),
+            senders: FxHashMap::default(),
+            tick: tick.clone(),
+        });
+        app.add_systems(FixedFirst, replay_records);
+        app.add_systems(FixedLast, finish_replayed_tick);
+
+        Self {
+            app,
+            tick,
+            egress,
+            proxy,
+        }
+    }
+
+    #[must_use]
+    pub const fn app(&self) -> &App {
+        &self.app
+    }
+
+    pub const fn app_mut(&mut self) -> &mut App {
+        &mut self.app
+    }
+
+    #[must_use]
+    pub fn world(&self) -> &World {
+        self.app.world()
+    }
+
+    pub fn world_mut(&mut self) -> &mut World {
+        self.app.world_mut()
+    }
+
+    /// The number of ticks since playback started
+    #[must_use]
+    pub fn tick(&self) -> u64 {
+        self.tick.load(Ordering::Relaxed)
+    }
+
+    /// Whether every record of the capture has been replayed
+    #[must_use]
+    pub fn is_finished(&self) -> bool {
+        self.world().resource::<PlaybackQueue>().records.is_empty()
+    }
+
+    /// Runs the app until the server has ticked once
+    pub fn step(&mut self) {
+        let tick = self.world().resource::<Tick>().0;
+
+        // The virtual clock only starts on the first update, so a tick may take more than one
+        // update
+        while self.world().resource::<Tick>().0 == tick {
+            self.app.update();
+
+            // The data sent to the proxy is only drained so that it does not pile up
+            while self.proxy.try_recv().is_ok() {}
+        }
+    }
+
+    /// Runs the app for `ticks` ticks
+    pub fn run_for(&mut self, ticks: u64) {
+        for _ in 0..ticks {
+            self.step();
+        }
+    }
+
+    /// Runs the app until every record has been replayed, and then for another `extra_ticks`
+    /// ticks so that the server can react to the last packets
+    pub fn run_to_end(&mut self, extra_ticks: u64) {
+        while !self.is_finished() {
+            self.step();
+        }
+        self.run_for(extra_ticks);
+    }
+
+    /// The packets the server has sent since playback started
+    #[must_use]
+    pub fn egress(&self) -> Vec<EgressPacket> {
+        self.egress.lock().unwrap().clone()
+    }
+
+    /// The connection that the stream `stream` of the capture was replayed as
+    #[must_use]
+    pub const fn connection(stream: u64) -> ConnectionId {
+        ConnectionId::new(stream, PLAYBACK_PROXY)
+    }
+}
+
+fn replay_records(world: &mut World) {
+    world.resource_scope::<PlaybackQueue, _>(|world, mut queue| {
+        let tick = queue.tick.load(Ordering::Relaxed);
+
+        while queue
+            .records
+            .front()
+            .is_some_and(|record| record.tick <= tick)
+        {
+            let record = queue.records.pop_front().unwrap();
+            replay(world, &mut queue, record);
+        }
+    });
+}
+
+fn replay(world: &mut World, queue: &mut PlaybackQueue, record: Record) {
+    let Record { stream, kind, .. } = record;
+
+    match kind {
+        RecordKind::Connect => {
+            let (sender, receiver) = packet_channel::channel(DEFAULT_FRAGMENT_SIZE);
+            if queue.senders.insert(stream, sender).is_some() {
+                warn!("replayed connection of stream {stream} which is already connected");
+            }
+            spawn_connection(world, Playback::connection(stream), None, receiver);
+        }
+        RecordKind::Disconnect => {
+            if queue.senders.remove(&stream).is_none() {
+                warn!("replayed disconnect of stream {stream} which is not connected");
+                return;
+            }
+            despawn_connection(world, stream);
+        }
+        RecordKind::Packet { id, body } => {
+            let Some(sender) = queue.senders.get_mut(&stream) else {
+                warn!("replayed packet {id} of stream {stream} which is not connected");
+                return;
+            };
+
+            // The packet is framed the way the client would frame it at this moment
+            let threshold = world
+                .resource::<StreamLookup>()
+                .get(&stream)
+                .and_then(|&player| world.get::<PacketDecoder>(player))
+                .map_or(CompressionThreshold::default(), PacketDecoder::compression);
+
+            let compose = world.resource::<Compose>();
+            let mut frame = Vec::new();
+            let result = PacketEncoder::new(threshold).append_packet(
+                FilteredPacket { id, body: &body },
+                &mut frame,
+                &mut *compose.scratch().borrow_mut(),
+                &mut compose.compressor().borrow_mut(),
+            );
+
+            if let Err(e) = result {
+                warn!("failed to encode replayed packet {id} of stream {stream}: {e}");
+                return;
+            }
+
+            if let Err(e) = sender.send(&frame) {
+                warn!("failed to replay packet {id} of stream {stream}: {e:?}");
+            }
+        }
+    }
+}
+
+fn finish_replayed_tick(queue: Res<'_, PlaybackQueue>) {
+    queue.tick.fetch_add(1, Ordering::Relaxed);
+}
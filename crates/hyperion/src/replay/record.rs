@@ -0,0 +1,142 @@
+//! Recording the ingress of every connection into a capture. See [`RecordPlugin`].
+
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::PathBuf,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use bevy_app::{App, FixedLast, Plugin};
+use bevy_ecs::{
+    lifecycle::{Add, Remove},
+    observer::On,
+    resource::Resource,
+    system::{Query, Res},
+};
+use tracing::{info, warn};
+
+use crate::{
+    GameRng,
+    net::{
+        Compose, ConnectionId,
+        filter::{FilterResult, PacketContext},
+    },
+    replay::capture::{CaptureError, CaptureWriter, Record, RecordKind},
+};
+
+/// Records every packet received from every connection to the capture at `path`, which can be
+/// replayed with [`Playback`](crate::replay::playback::Playback).
+///
+/// Packets are recorded by an ingress filter with the lowest priority, so the capture contains
+/// them as they were received before other filters could change them. This plugin must be added
+/// after [`HyperionCore`](crate::HyperionCore).
+pub struct RecordPlugin {
+    pub path: PathBuf,
+}
+
+/// The capture being written by [`RecordPlugin`]
+#[derive(Resource, Clone)]
+pub struct Recorder {
+    writer: Arc<Mutex<CaptureWriter<BufWriter<File>>>>,
+    /// The number of ticks since recording started
+    tick: Arc<AtomicU64>,
+}
+
+impl Recorder {
+    fn append(&self, record: &Record) {
+        if let Err(e) = self.writer.lock().unwrap().append(record) {
+            warn!("failed to record {:?}: {e}", record.kind);
+        }
+    }
+
+    fn flush(&self) -> Result<(), CaptureError> {
+        self.writer.lock().unwrap().flush()
+    }
+}
+
+impl Plugin for RecordPlugin {
+    fn build(&self, app: &mut App) {
+        let file = File::create(&self.path).expect("failed to create capture file");
+        let seed = app.world().resource::<GameRng>().seed();
+        let writer =
+            CaptureWriter::new(BufWriter::new(file), seed).expect("failed to write capture");
+
+        let recorder = Recorder {
+            writer: Arc::new(Mutex::new(writer)),
+            tick: Arc::new(AtomicU64::new(0)),
+        };
+
+        let filter_recorder = recorder.clone();
+        app.world_mut()
+            .resource_mut::<Compose>()
+            .register_ingress_filter(i32::MIN, move |context: &mut PacketContext<'_>| {
+                if let Some(connection) = context.connection() {
+                    let tick = filter_recorder.tick.load(Ordering::Relaxed);
+                    let result = filter_recorder.writer.lock().unwrap().append_packet(
+                        tick,
+                        connection.inner(),
+                        context.id(),
+                        context.body(),
+                    );
+
+                    if let Err(e) = result {
+                        warn!("failed to record packet {}: {e}", context.id());
+                    }
+                }
+
+                FilterResult::Continue
+            });
+
+        info!("recording ingress to {}", self.path.display());
+
+        app.insert_resource(recorder);
+        app.add_systems(FixedLast, finish_recorded_tick);
+        app.add_observer(record_connect);
+        app.add_observer(record_disconnect);
+    }
+}
+
+fn finish_recorded_tick(recorder: Res<'_, Recorder>) {
+    // Flushing every tick keeps the capture usable if the server is killed
+    if let Err(e) = recorder.flush() {
+        warn!("failed to flush capture: {e}");
+    }
+
+    recorder.tick.fetch_add(1, Ordering::Relaxed);
+}
+
+fn record_connect(
+    connected: On<'_, '_, Add, ConnectionId>,
+    query: Query<'_, '_, &ConnectionId>,
+    recorder: Res<'_, Recorder>,
+) {
+    let Ok(connection_id) = query.get(connected.entity) else {
+        return;
+    };
+
+    recorder.append(&Record {
+        tick: recorder.tick.load(Ordering::Relaxed),
+        stream: connection_id.inner(),
+        kind: RecordKind::Connect,
+    });
+}
+
+fn record_disconnect(
+    disconnected: On<'_, '_, Remove, ConnectionId>,
+    query: Query<'_, '_, &ConnectionId>,
+    recorder: Res<'_, Recorder>,
+) {
+    let Ok(connection_id) = query.get(disconnected.entity) else {
+        return;
+    };
+
+    recorder.append(&Record {
+        tick: recorder.tick.load(Ordering::Relaxed),
+        stream: connection_id.inner(),
+        kind: RecordKind::Disconnect,
+    });
+}
@@ -0,0 +1,591 @@
+//! Advancements and toast notifications.
+//!
+//! [`send_toast`] shows a one-off popup such as "Challenge Complete!" without touching the
+//! advancement screen of the player. Games which want a real advancement tab can define it in the
+//! [`AdvancementTree`] resource and grant advancements with [`grant`]. Progress on the tree is
+//! saved in the [`LocalDb`] by [`AdvancementPlugin`].
+
+use std::{
+    borrow::Cow,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bevy_app::{App, Plugin};
+use bevy_ecs::{
+    component::Component,
+    entity::Entity,
+    lifecycle::Add,
+    observer::On,
+    resource::Resource,
+    system::{Commands, Query, Res},
+    world::World,
+};
+use indexmap::IndexMap;
+use rkyv::util::AlignedVec;
+use rustc_hash::FxHashMap;
+use thiserror::Error;
+use tracing::error;
+use valence_protocol::{Ident, ItemStack};
+use valence_text::{IntoText, Text};
+#[cfg(feature = "reflect")]
+use {
+    bevy_ecs::reflect::{ReflectComponent, ReflectResource},
+    bevy_reflect::Reflect,
+};
+
+pub use crate::net::packets::FrameType;
+use crate::{
+    net::{
+        Compose, ConnectionId, DataBundle,
+        packets::{Advancement, AdvancementDisplay, AdvancementUpdateS2c},
+    },
+    simulation::{Uuid, packet_state},
+    storage::{AdvancementHandler, LocalDb},
+};
+
+/// The advancement used to display toasts. It is removed immediately after being granted.
+const TOAST_ID: &str = "hyperion:toast";
+
+/// The only criterion of toasts and of advancements created with [`AdvancementDefinition::new`]
+const DEFAULT_CRITERION: &str = "done";
+
+#[derive(Debug, Error)]
+pub enum AdvancementError {
+    #[error("advancement {0} already exists")]
+    Duplicate(String),
+    #[error("advancement {0} does not exist")]
+    Unknown(String),
+    #[error("advancement {0} has no criteria")]
+    NoCriteria(String),
+    #[error("advancement {advancement} does not have the criterion {criterion}")]
+    UnknownCriterion {
+        advancement: String,
+        criterion: String,
+    },
+    #[error("entity {0} is not a connected player")]
+    NotAPlayer(Entity),
+}
+
+/// A popup notification shown in the top right corner of the screen.
+#[derive(Clone, Debug)]
+pub struct Toast {
+    pub icon: ItemStack,
+    pub title: Text,
+    /// Decides the header of the toast, such as "Challenge Complete!" for
+    /// [`FrameType::Challenge`]
+    pub frame: FrameType,
+}
+
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .and_then(|duration| i64::try_from(duration.as_millis()).ok())
+        .unwrap_or_default()
+}
+
+fn toast_id() -> Ident<Cow<'static, str>> {
+    Ident::new(Cow::Borrowed(TOAST_ID)).expect("the toast id is a valid identifier")
+}
+
+/// Shows a toast to a player.
+///
+/// The toast is shown by adding a hidden advancement which is already complete and removing it
+/// again in the same bundle, so the advancement screen of the player is not affected.
+pub fn send_toast(
+    compose: &Compose,
+    connection_id: ConnectionId,
+    toast: &Toast,
+) -> anyhow::Result<()> {
+    let add = AdvancementUpdateS2c {
+        reset: false,
+        added: vec![(toast_id(), Advancement {
+            parent: None,
+            display: Some(AdvancementDisplay {
+                title: Cow::Borrowed(&toast.title),
+                description: Cow::Owned(Text::default()),
+                icon: Cow::Borrowed(&toast.icon),
+                frame: toast.frame,
+                background: None,
+                show_toast: true,
+                hidden: true,
+                x: 0.0,
+                y: 0.0,
+            }),
+            criteria: vec![Cow::Borrowed(DEFAULT_CRITERION)],
+            requirements: vec![vec![Cow::Borrowed(DEFAULT_CRITERION)]],
+            sends_telemetry_data: false,
+        })],
+        removed: Vec::new(),
+        progress: vec![(toast_id(), vec![(
+            Cow::Borrowed(DEFAULT_CRITERION),
+            Some(now_millis()),
+        )])],
+    };
+
+    let remove = AdvancementUpdateS2c {
+        reset: false,
+        added: Vec::new(),
+        removed: vec![toast_id()],
+        progress: Vec::new(),
+    };
+
+    let mut bundle = DataBundle::new(compose);
+    bundle.add_packet(&add)?;
+    bundle.add_packet(&remove)?;
+    Ok(bundle.unicast(connection_id)?)
+}
+
+/// An advancement in the [`AdvancementTree`].
+#[derive(Clone, Debug)]
+pub struct AdvancementDefinition {
+    pub id: Ident<Cow<'static, str>>,
+    /// The parent advancement. Advancements without a parent are the root of a new tab.
+    pub parent: Option<Ident<Cow<'static, str>>>,
+    pub title: Text,
+    pub description: Text,
+    pub icon: ItemStack,
+    pub frame: FrameType,
+    /// The background texture of the tab, such as
+    /// `minecraft:textures/gui/advancements/backgrounds/stone.png`. This is only used by root
+    /// advancements.
+    pub background: Option<Ident<Cow<'static, str>>>,
+    pub show_toast: bool,
+    pub hidden: bool,
+    /// The position in the tab
+    pub x: f32,
+    pub y: f32,
+    /// Every criterion needs to be granted for the advancement to be complete
+    pub criteria: Vec<String>,
+}
+
+impl AdvancementDefinition {
+    /// Creates a task advancement with a single criterion at the origin of its tab.
+    #[must_use]
+    pub fn new(
+        id: Ident<Cow<'static, str>>,
+        title: impl IntoText<'static>,
+        description: impl IntoText<'static>,
+        icon: ItemStack,
+    ) -> Self {
+        Self {
+            id,
+            parent: None,
+            title: title.into_text(),
+            description: description.into_text(),
+            icon,
+            frame: FrameType::Task,
+            background: None,
+            show_toast: true,
+            hidden: false,
+            x: 0.0,
+            y: 0.0,
+            criteria: vec![DEFAULT_CRITERION.to_owned()],
+        }
+    }
+
+    fn to_advancement(&self) -> Advancement<'_> {
+        Advancement {
+            parent: self.parent.clone(),
+            display: Some(AdvancementDisplay {
+                title: Cow::Borrowed(&self.title),
+                description: Cow::Borrowed(&self.description),
+                icon: Cow::Borrowed(&self.icon),
+                frame: self.frame,
+                background: self.background.clone(),
+                show_toast: self.show_toast,
+                hidden: self.hidden,
+                x: self.x,
+                y: self.y,
+            }),
+            criteria: self
+                .criteria
+                .iter()
+                .map(|criterion| Cow::Borrowed(criterion.as_str()))
+                .collect(),
+            requirements: self
+                .criteria
+                .iter()
+                .map(|criterion| vec![Cow::Borrowed(criterion.as_str())])
+                .collect(),
+            sends_telemetry_data: false,
+        }
+    }
+}
+
+/// The advancements sent to every player. This should be filled before players join; players who
+/// are already connected do not receive changes.
+#[derive(Resource, Default, Debug)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Resource))]
+pub struct AdvancementTree {
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    advancements: IndexMap<String, AdvancementDefinition>,
+}
+
+impl AdvancementTree {
+    /// Adds an advancement. The parent must be added first.
+    pub fn insert(&mut self, advancement: AdvancementDefinition) -> Result<(), AdvancementError> {
+        let id = advancement.id.as_str().to_owned();
+
+        if self.advancements.contains_key(&id) {
+            return Err(AdvancementError::Duplicate(id));
+        }
+
+        if let Some(parent) = &advancement.parent
+            && !self.advancements.contains_key(parent.as_str())
+        {
+            return Err(AdvancementError::Unknown(parent.as_str().to_owned()));
+        }
+
+        if advancement.criteria.is_empty() {
+            return Err(AdvancementError::NoCriteria(id));
+        }
+
+        self.advancements.insert(id, advancement);
+        Ok(())
+    }
+
+    #[must_use]
+    pub fn get(&self, id: &str) -> Option<&AdvancementDefinition> {
+        self.advancements.get(id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &AdvancementDefinition> {
+        self.advancements.values()
+    }
+
+    /// Creates the packet replacing all advancements of a player with this tree
+    #[must_use]
+    pub fn to_packet<'a>(&'a self, progress: &'a AdvancementProgress) -> AdvancementUpdateS2c<'a> {
+        AdvancementUpdateS2c {
+            reset: true,
+            added: self
+                .iter()
+                .map(|advancement| (advancement.id.clone(), advancement.to_advancement()))
+                .collect(),
+            removed: Vec::new(),
+            progress: self
+                .iter()
+                .map(|advancement| (advancement.id.clone(), progress.criteria_of(advancement)))
+                .collect(),
+        }
+    }
+}
+
+/// The criteria a player has been granted, with the time they were granted at in milliseconds
+/// since the unix epoch.
+#[derive(Component, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+pub struct AdvancementProgress {
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    granted: FxHashMap<String, FxHashMap<String, i64>>,
+}
+
+/// Encoded form of [`AdvancementProgress`]
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+struct SavedCriterion {
+    advancement: String,
+    criterion: String,
+    granted_at: i64,
+}
+
+impl AdvancementProgress {
+    /// Returns the time the criterion was granted at, if it was granted
+    #[must_use]
+    pub fn granted_at(&self, advancement: &str, criterion: &str) -> Option<i64> {
+        self.granted.get(advancement)?.get(criterion).copied()
+    }
+
+    /// Returns whether every criterion of the advancement has been granted
+    #[must_use]
+    pub fn is_complete(&self, advancement: &AdvancementDefinition) -> bool {
+        advancement.criteria.iter().all(|criterion| {
+            self.granted_at(advancement.id.as_str(), criterion)
+                .is_some()
+        })
+    }
+
+    /// Returns whether the criterion was newly granted
+    fn grant(&mut self, advancement: &str, criterion: &str, now: i64) -> bool {
+        let criteria = self.granted.entry(advancement.to_owned()).or_default();
+
+        if criteria.contains_key(criterion) {
+            return false;
+        }
+
+        criteria.insert(criterion.to_owned(), now);
+        true
+    }
+
+    fn criteria_of<'a>(
+        &'a self,
+        advancement: &'a AdvancementDefinition,
+    ) -> Vec<(Cow<'a, str>, Option<i64>)> {
+        advancement
+            .criteria
+            .iter()
+            .map(|criterion| {
+                (
+                    Cow::Borrowed(criterion.as_str()),
+                    self.granted_at(advancement.id.as_str(), criterion),
+                )
+            })
+            .collect()
+    }
+
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let saved = self
+            .granted
+            .iter()
+            .flat_map(|(advancement, criteria)| {
+                criteria
+                    .iter()
+                    .map(|(criterion, &granted_at)| SavedCriterion {
+                        advancement: advancement.clone(),
+                        criterion: criterion.clone(),
+                        granted_at,
+                    })
+            })
+            .collect::<Vec<_>>();
+
+        rkyv::to_bytes::<rkyv::rancor::Error>(&saved)
+            .unwrap()
+            .to_vec()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, rkyv::rancor::Error> {
+        // Database entries are not guaranteed to be aligned
+        let mut aligned = AlignedVec::<16>::with_capacity(bytes.len());
+        aligned.extend_from_slice(bytes);
+
+        let saved = rkyv::from_bytes::<Vec<SavedCriterion>, rkyv::rancor::Error>(&aligned)?;
+
+        let mut progress = Self::default();
+        for criterion in saved {
+            progress
+                .granted
+                .entry(criterion.advancement)
+                .or_default()
+                .insert(criterion.criterion, criterion.granted_at);
+        }
+
+        Ok(progress)
+    }
+}
+
+/// Grants every criterion of an advancement to a player. Returns whether anything was newly
+/// granted. This can be called from a system through `commands.queue`.
+pub fn grant(world: &mut World, player: Entity, id: &str) -> Result<bool, AdvancementError> {
+    let criteria = world
+        .resource::<AdvancementTree>()
+        .get(id)
+        .ok_or_else(|| AdvancementError::Unknown(id.to_owned()))?
+        .criteria
+        .clone();
+
+    grant_criteria(world, player, id, &criteria)
+}
+
+/// Grants one criterion of an advancement to a player. Returns whether the criterion was newly
+/// granted.
+pub fn grant_criterion(
+    world: &mut World,
+    player: Entity,
+    id: &str,
+    criterion: &str,
+) -> Result<bool, AdvancementError> {
+    let advancement = world
+        .resource::<AdvancementTree>()
+        .get(id)
+        .ok_or_else(|| AdvancementError::Unknown(id.to_owned()))?;
+
+    if !advancement.criteria.iter().any(|c| c == criterion) {
+        return Err(AdvancementError::UnknownCriterion {
+            advancement: id.to_owned(),
+            criterion: criterion.to_owned(),
+        });
+    }
+
+    grant_criteria(world, player, id, &[criterion.to_owned()])
+}
+
+fn grant_criteria(
+    world: &mut World,
+    player: Entity,
+    id: &str,
+    criteria: &[String],
+) -> Result<bool, AdvancementError> {
+    let (Some(&connection_id), Some(&uuid)) =
+        (world.get::<ConnectionId>(player), world.get::<Uuid>(player))
+    else {
+        return Err(AdvancementError::NotAPlayer(player));
+    };
+
+    let Some(mut progress) = world.get_mut::<AdvancementProgress>(player) else {
+        return Err(AdvancementError::NotAPlayer(player));
+    };
+
+    let now = now_millis();
+    let mut changed = false;
+    for criterion in criteria {
+        changed |= progress.grant(id, criterion, now);
+    }
+
+    if !changed {
+        return Ok(false);
+    }
+
+    let progress = progress.clone();
+    let tree = world.resource::<AdvancementTree>();
+    let advancement = tree
+        .get(id)
+        .ok_or_else(|| AdvancementError::Unknown(id.to_owned()))?;
+
+    let pkt = AdvancementUpdateS2c {
+        reset: false,
+        added: Vec::new(),
+        removed: Vec::new(),
+        progress: vec![(advancement.id.clone(), progress.criteria_of(advancement))],
+    };
+
+    if let Err(e) = world.resource::<Compose>().unicast(&pkt, connection_id) {
+        error!("failed to send advancement progress: {e}");
+    }
+
+    if let Err(e) = world
+        .resource::<AdvancementHandler>()
+        .insert(uuid.0, &progress)
+    {
+        error!("failed to save advancement progress of {}: {e}", uuid.0);
+    }
+
+    Ok(true)
+}
+
+fn send_advancements(
+    now_playing: On<'_, '_, Add, packet_state::Play>,
+    compose: Res<'_, Compose>,
+    tree: Res<'_, AdvancementTree>,
+    handler: Res<'_, AdvancementHandler>,
+    query: Query<'_, '_, (&ConnectionId, &Uuid)>,
+    mut commands: Commands<'_, '_>,
+) {
+    let entity = now_playing.entity;
+    let Ok((&connection_id, uuid)) = query.get(entity) else {
+        error!("failed to send advancements: missing ConnectionId or Uuid component");
+        return;
+    };
+
+    let progress = match handler.find(uuid.0) {
+        Ok(progress) => progress.unwrap_or_default(),
+        Err(e) => {
+            error!("failed to load advancement progress of {}: {e}", uuid.0);
+            AdvancementProgress::default()
+        }
+    };
+
+    if let Err(e) = compose.unicast(&tree.to_packet(&progress), connection_id) {
+        error!("failed to send advancements: {e}");
+    }
+
+    commands.entity(entity).insert(progress);
+}
+
+/// Sends the [`AdvancementTree`] to players and saves their progress. This must be added after
+/// [`crate::HyperionCore`].
+pub struct AdvancementPlugin;
+
+impl Plugin for AdvancementPlugin {
+    fn build(&self, app: &mut App) {
+        let db = app.world().resource::<LocalDb>();
+        let handler = AdvancementHandler::new(db).expect("failed to load advancement handler");
+        app.insert_resource(handler);
+
+        app.init_resource::<AdvancementTree>();
+        app.add_observer(send_advancements);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use valence_protocol::ItemKind;
+
+    use super::*;
+
+    fn ident(s: &'static str) -> Ident<Cow<'static, str>> {
+        Ident::new(Cow::Borrowed(s)).unwrap()
+    }
+
+    fn definition(id: &'static str) -> AdvancementDefinition {
+        AdvancementDefinition::new(
+            ident(id),
+            "Title",
+            "Description",
+            ItemStack::new(ItemKind::Diamond, 1, None),
+        )
+    }
+
+    #[test]
+    fn tree_validation() {
+        let mut tree = AdvancementTree::default();
+
+        let mut child = definition("test:child");
+        child.parent = Some(ident("test:root"));
+
+        assert!(matches!(
+            tree.insert(child.clone()),
+            Err(AdvancementError::Unknown(_))
+        ));
+
+        tree.insert(definition("test:root")).unwrap();
+        tree.insert(child).unwrap();
+
+        assert!(matches!(
+            tree.insert(definition("test:root")),
+            Err(AdvancementError::Duplicate(_))
+        ));
+
+        let mut empty = definition("test:empty");
+        empty.criteria.clear();
+        assert!(matches!(
+            tree.insert(empty),
+            Err(AdvancementError::NoCriteria(_))
+        ));
+    }
+
+    #[test]
+    fn progress_round_trip() {
+        let mut root = definition("test:root");
+        root.criteria = vec!["a".to_owned(), "b".to_owned()];
+
+        let mut progress = AdvancementProgress::default();
+        assert!(progress.grant("test:root", "a", 10));
+        assert!(!progress.grant("test:root", "a", 20));
+        assert!(!progress.is_complete(&root));
+
+        assert!(progress.grant("test:root", "b", 30));
+        assert!(progress.is_complete(&root));
+        assert_eq!(progress.granted_at("test:root", "a"), Some(10));
+
+        let decoded = AdvancementProgress::from_bytes(&progress.to_bytes()).unwrap();
+        assert_eq!(decoded, progress);
+    }
+
+    #[test]
+    fn tree_packet_contains_progress() {
+        let mut tree = AdvancementTree::default();
+        tree.insert(definition("test:root")).unwrap();
+
+        let mut progress = AdvancementProgress::default();
+        progress.grant("test:root", DEFAULT_CRITERION, 5);
+
+        let pkt = tree.to_packet(&progress);
+        assert!(pkt.reset);
+        assert_eq!(pkt.added.len(), 1);
+        assert_eq!(pkt.progress[0].1, [(
+            Cow::Borrowed(DEFAULT_CRITERION),
+            Some(5)
+        )]);
+    }
+}
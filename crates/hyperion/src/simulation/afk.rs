@@ -0,0 +1,358 @@
+//! Detecting players who are connected but idle. See [`AfkStatus`].
+//!
+//! A player is idle until they send meaningful input: moving or looking around, chatting, running
+//! commands, interacting, or using their inventory. Movement while a teleport is pending or shortly
+//! after the player was knocked back is caused by the server rather than the player, so it does
+//! not count.
+
+use bevy_app::{App, FixedUpdate, Plugin};
+use bevy_ecs::{
+    component::Component,
+    entity::Entity,
+    lifecycle::Add,
+    message::{MessageReader, MessageWriter},
+    observer::On,
+    query::{Changed, Has},
+    resource::Resource,
+    schedule::IntoScheduleConfigs,
+    system::{Commands, Query, Res},
+};
+use glam::Vec3;
+use hyperion_utils::{Args, Locale, Translations, localization::DEFAULT_LANGUAGE};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+#[cfg(feature = "reflect")]
+use {
+    bevy_ecs::reflect::{ReflectComponent, ReflectResource},
+    bevy_reflect::Reflect,
+};
+
+use crate::{
+    Tick, ingress,
+    ingress::decode::disconnect,
+    net::{Compose, ConnectionId},
+    simulation::{PendingTeleportation, Velocity, event, packet::play, packet_state},
+};
+
+/// How far a player has to move in one packet for it to count as input
+pub const MOVEMENT_EPSILON: f32 = 0.1;
+
+/// How many degrees a player has to turn in one packet for it to count as input
+pub const ROTATION_EPSILON: f32 = 1.0;
+
+/// How long movement is ignored after a player was knocked back, in ticks
+pub const KNOCKBACK_GRACE_TICKS: i64 = 20;
+
+/// When idle players are marked as AFK and kicked
+#[derive(Resource, Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Resource))]
+#[serde(default)]
+pub struct AfkConfig {
+    /// How long a player has to be idle to be marked as AFK
+    pub afk_after_secs: u64,
+    /// How long a player has to be idle to be kicked. Idle players are never kicked if this is
+    /// not set.
+    pub kick_after_secs: Option<u64>,
+}
+
+impl Default for AfkConfig {
+    fn default() -> Self {
+        Self {
+            afk_after_secs: 5 * 60,
+            kick_after_secs: None,
+        }
+    }
+}
+
+impl AfkConfig {
+    fn afk_after_ticks(&self) -> i64 {
+        secs_to_ticks(self.afk_after_secs)
+    }
+
+    fn kick_after_ticks(&self) -> Option<i64> {
+        self.kick_after_secs.map(secs_to_ticks)
+    }
+}
+
+fn secs_to_ticks(secs: u64) -> i64 {
+    i64::try_from(secs).unwrap_or(i64::MAX).saturating_mul(20)
+}
+
+/// Whether a player is AFK. Every player in the play state has this component.
+#[derive(Component, Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+pub struct AfkStatus {
+    /// The tick the player was marked as AFK, or [`None`] if the player is not AFK
+    pub afk_since: Option<i64>,
+    /// The tick of the last meaningful input of the player
+    last_input: i64,
+    /// Movement before this tick is ignored because the player was knocked back
+    pushed_until: i64,
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    last_position: Option<Vec3>,
+    last_rotation: Option<(f32, f32)>,
+}
+
+/// What changed after checking an [`AfkStatus`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Transition {
+    None,
+    WentAfk,
+    Kick,
+}
+
+impl AfkStatus {
+    const fn new(tick: i64) -> Self {
+        Self {
+            afk_since: None,
+            last_input: tick,
+            pushed_until: tick,
+            last_position: None,
+            last_rotation: None,
+        }
+    }
+
+    /// The tick of the last meaningful input of the player
+    #[must_use]
+    pub const fn last_input(&self) -> i64 {
+        self.last_input
+    }
+
+    #[must_use]
+    pub const fn is_afk(&self) -> bool {
+        self.afk_since.is_some()
+    }
+
+    /// Records input at `tick`. Returns the tick the player went AFK at if the player is no longer
+    /// AFK because of it.
+    fn input(&mut self, tick: i64) -> Option<i64> {
+        self.last_input = tick;
+        self.afk_since.take()
+    }
+
+    /// Returns whether a movement to `position` is input of the player
+    fn moved(&mut self, position: Vec3, tick: i64, teleporting: bool) -> bool {
+        let previous = self.last_position.replace(position);
+
+        if teleporting || tick < self.pushed_until {
+            return false;
+        }
+
+        previous.is_some_and(|previous| previous.distance(position) > MOVEMENT_EPSILON)
+    }
+
+    /// Returns whether turning to `yaw` and `pitch` is input of the player
+    fn turned(&mut self, yaw: f32, pitch: f32) -> bool {
+        let previous = self.last_rotation.replace((yaw, pitch));
+
+        previous.is_some_and(|(previous_yaw, previous_pitch)| {
+            (previous_yaw - yaw).abs() > ROTATION_EPSILON
+                || (previous_pitch - pitch).abs() > ROTATION_EPSILON
+        })
+    }
+
+    fn check(&mut self, tick: i64, config: &AfkConfig) -> Transition {
+        let idle = tick - self.last_input;
+
+        if let Some(kick_after) = config.kick_after_ticks()
+            && idle >= kick_after
+        {
+            return Transition::Kick;
+        }
+
+        if self.afk_since.is_none() && idle >= config.afk_after_ticks() {
+            self.afk_since = Some(tick);
+            return Transition::WentAfk;
+        }
+
+        Transition::None
+    }
+}
+
+fn initialize_afk_status(
+    now_playing: On<'_, '_, Add, packet_state::Play>,
+    tick: Res<'_, Tick>,
+    mut commands: Commands<'_, '_>,
+) {
+    commands
+        .entity(now_playing.entity)
+        .insert(AfkStatus::new(tick.0));
+}
+
+fn track_knockback(
+    tick: Res<'_, Tick>,
+    mut query: Query<'_, '_, &mut AfkStatus, Changed<Velocity>>,
+) {
+    for mut status in &mut query {
+        status.pushed_until = tick.0 + KNOCKBACK_GRACE_TICKS;
+    }
+}
+
+fn record_input(
+    tick: Res<'_, Tick>,
+    mut full: MessageReader<'_, '_, play::Full>,
+    mut position: MessageReader<'_, '_, play::PositionAndOnGround>,
+    mut look: MessageReader<'_, '_, play::LookAndOnGround>,
+    mut chat: MessageReader<'_, '_, play::ChatMessage>,
+    mut command: MessageReader<'_, '_, play::CommandExecution>,
+    mut action: MessageReader<'_, '_, play::PlayerAction>,
+    mut interact_block: MessageReader<'_, '_, play::PlayerInteractBlock>,
+    mut interact_entity: MessageReader<'_, '_, play::PlayerInteractEntity>,
+    mut interact_item: MessageReader<'_, '_, play::PlayerInteractItem>,
+    mut click_slot: MessageReader<'_, '_, play::ClickSlot>,
+    mut selected_slot: MessageReader<'_, '_, play::UpdateSelectedSlot>,
+    mut query: Query<'_, '_, (&mut AfkStatus, Has<PendingTeleportation>)>,
+    mut returned: MessageWriter<'_, event::PlayerReturned>,
+) {
+    let tick = tick.0;
+    let mut inputs = Vec::new();
+
+    for packet in full.read() {
+        if let Ok((mut status, teleporting)) = query.get_mut(packet.sender()) {
+            let moved = status.moved(packet.position.as_vec3(), tick, teleporting);
+            let turned = status.turned(packet.yaw, packet.pitch);
+            if moved || turned {
+                inputs.push(packet.sender());
+            }
+        }
+    }
+
+    for packet in position.read() {
+        if let Ok((mut status, teleporting)) = query.get_mut(packet.sender())
+            && status.moved(packet.position.as_vec3(), tick, teleporting)
+        {
+            inputs.push(packet.sender());
+        }
+    }
+
+    for packet in look.read() {
+        if let Ok((mut status, _)) = query.get_mut(packet.sender())
+            && status.turned(packet.yaw, packet.pitch)
+        {
+            inputs.push(packet.sender());
+        }
+    }
+
+    inputs.extend(
+        chat.read()
+            .map(|packet| packet.sender())
+            .chain(command.read().map(|packet| packet.sender()))
+            .chain(action.read().map(|packet| packet.sender()))
+            .chain(interact_block.read().map(|packet| packet.sender()))
+            .chain(interact_entity.read().map(|packet| packet.sender()))
+            .chain(interact_item.read().map(|packet| packet.sender()))
+            .chain(click_slot.read().map(|packet| packet.sender()))
+            .chain(selected_slot.read().map(|packet| packet.sender())),
+    );
+
+    for player in inputs {
+        let Ok((mut status, _)) = query.get_mut(player) else {
+            continue;
+        };
+
+        if let Some(afk_since) = status.input(tick) {
+            returned.write(event::PlayerReturned {
+                player,
+                afk_ticks: tick - afk_since,
+            });
+        }
+    }
+}
+
+fn update_afk_status(
+    tick: Res<'_, Tick>,
+    config: Res<'_, AfkConfig>,
+    compose: Res<'_, Compose>,
+    translations: Res<'_, Translations>,
+    mut query: Query<'_, '_, (Entity, &mut AfkStatus, &ConnectionId, Option<&Locale>)>,
+    mut went_afk: MessageWriter<'_, event::PlayerWentAfk>,
+) {
+    for (player, mut status, &connection_id, locale) in &mut query {
+        match status.check(tick.0, &config) {
+            Transition::None => {}
+            Transition::WentAfk => {
+                went_afk.write(event::PlayerWentAfk { player });
+            }
+            Transition::Kick => {
+                info!("kicking {connection_id:?} for being idle");
+                let locale = locale.map_or(DEFAULT_LANGUAGE, Locale::as_str);
+                let reason = translations.translate(locale, "kick.afk", &Args::new());
+                disconnect::play(&compose, connection_id, &reason);
+
+                // The player is despawned once the proxy reports the disconnect, so this prevents
+                // kicking the player again until then
+                status.last_input = tick.0;
+            }
+        }
+    }
+}
+
+/// Tracks the [`AfkStatus`] of players and kicks idle players according to the [`AfkConfig`]
+pub struct AfkPlugin;
+
+impl Plugin for AfkPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<AfkConfig>();
+        app.add_observer(initialize_afk_status);
+        app.add_systems(
+            FixedUpdate,
+            (track_knockback, record_input, update_afk_status)
+                .chain()
+                .after(ingress::decode::play),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: AfkConfig = AfkConfig {
+        afk_after_secs: 10,
+        kick_after_secs: Some(20),
+    };
+
+    #[test]
+    fn idle_players_go_afk_and_are_kicked() {
+        let mut status = AfkStatus::new(0);
+
+        assert_eq!(status.check(199, &CONFIG), Transition::None);
+        assert_eq!(status.check(200, &CONFIG), Transition::WentAfk);
+        assert_eq!(status.afk_since, Some(200));
+        assert_eq!(status.check(201, &CONFIG), Transition::None);
+
+        assert_eq!(status.input(250), Some(200));
+        assert!(!status.is_afk());
+        assert_eq!(status.check(449, &CONFIG), Transition::None);
+        assert_eq!(status.check(450, &CONFIG), Transition::WentAfk);
+        assert_eq!(status.check(650, &CONFIG), Transition::Kick);
+
+        let config = AfkConfig::default();
+        let mut status = AfkStatus::new(0);
+        assert_eq!(status.check(i64::MAX / 2, &config), Transition::WentAfk);
+        assert_eq!(status.check(i64::MAX / 2, &config), Transition::None);
+    }
+
+    #[test]
+    fn only_movement_of_the_player_counts() {
+        let mut status = AfkStatus::new(0);
+
+        // The first position is only the baseline
+        assert!(!status.moved(Vec3::ZERO, 1, false));
+        assert!(!status.moved(Vec3::new(0.05, 0.0, 0.0), 2, false));
+        assert!(status.moved(Vec3::new(1.0, 0.0, 0.0), 3, false));
+
+        // Teleports
+        assert!(!status.moved(Vec3::new(100.0, 0.0, 0.0), 4, true));
+        assert!(!status.moved(Vec3::new(100.0, 0.0, 0.0), 5, false));
+
+        // Knockback
+        status.pushed_until = 10;
+        assert!(!status.moved(Vec3::new(103.0, 1.0, 0.0), 9, false));
+        assert!(status.moved(Vec3::new(105.0, 0.0, 0.0), 10, false));
+
+        assert!(!status.turned(90.0, 0.0));
+        assert!(!status.turned(90.5, 0.0));
+        assert!(status.turned(90.5, 10.0));
+    }
+}
@@ -15,6 +15,13 @@ pub enum Kind {
     MagicCritical = 5,
 }
 
+/// The animations an entity plays this tick. Game code only needs to [`push`](Self::push)
+/// animations.
+///
+/// The animations are broadcast to the players subscribed to the channel of the entity, except
+/// for the animating player, and cleared in `FixedPostUpdate`. Animations pushed after that, such
+/// as by observers of later `FixedPostUpdate` systems, are sent in the next tick instead of being
+/// lost.
 #[derive(Component)]
 #[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
 pub struct ActiveAnimation {
@@ -40,6 +47,11 @@ impl ActiveAnimation {
         })
     }
 
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.kind.is_empty()
+    }
+
     pub fn push(&mut self, kind: Kind) {
         self.kind.insert(kind);
     }
@@ -48,3 +60,27 @@ impl ActiveAnimation {
         self.kind.clear();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pushed_animations_are_sent_once() {
+        let mut animation = ActiveAnimation::NONE;
+        assert!(animation.is_empty());
+
+        animation.push(Kind::SwingMainArm);
+        animation.push(Kind::Critical);
+        animation.push(Kind::SwingMainArm);
+
+        let animations: Vec<_> = animation
+            .packets(VarInt(1))
+            .map(|pkt| pkt.animation)
+            .collect();
+        assert_eq!(animations, [Kind::SwingMainArm as u8, Kind::Critical as u8]);
+
+        animation.clear();
+        assert!(animation.is_empty());
+    }
+}
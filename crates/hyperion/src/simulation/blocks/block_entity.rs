@@ -0,0 +1,594 @@
+//! Typed data of the block entities in a chunk, such as the text of signs and the contents of
+//! chests. See [`BlockEntities`].
+
+use std::collections::BTreeMap;
+
+use glam::UVec3;
+use thiserror::Error;
+use tracing::warn;
+use valence_generated::item::ItemKind;
+use valence_nbt::{Compound, List, Value, compound};
+use valence_protocol::ItemStack;
+
+#[derive(Debug, Error)]
+pub enum BlockEntityError {
+    #[error("missing or invalid field \"{0}\"")]
+    InvalidField(&'static str),
+    #[error("unknown item name of \"{0}\"")]
+    UnknownItem(String),
+}
+
+/// A block entity as it is stored in a chunk
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockEntity {
+    /// The resource identifier of the kind of block entity, such as `minecraft:chest`
+    pub id: String,
+    pub data: BlockEntityData,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum BlockEntityData {
+    /// A sign or hanging sign
+    Sign(Box<SignData>),
+    /// A chest or trapped chest
+    Chest(ChestData),
+    Spawner(SpawnerData),
+    /// A block entity whose kind is not modelled, such as a furnace. Its NBT is kept as it was
+    /// saved so that it is not lost.
+    Opaque(Compound),
+}
+
+impl BlockEntity {
+    /// Parses the NBT of a block entity without its `id`, `x`, `y` and `z` fields.
+    ///
+    /// Kinds which are not modelled, and block entities whose NBT cannot be parsed, are kept as
+    /// [`BlockEntityData::Opaque`].
+    #[must_use]
+    pub fn from_nbt(id: String, nbt: Compound) -> Self {
+        let parsed = match id.strip_prefix("minecraft:").unwrap_or(&id) {
+            "sign" | "hanging_sign" => {
+                SignData::from_nbt(&nbt).map(|sign| BlockEntityData::Sign(Box::new(sign)))
+            }
+            "chest" | "trapped_chest" => ChestData::from_nbt(&nbt).map(BlockEntityData::Chest),
+            "mob_spawner" => SpawnerData::from_nbt(&nbt).map(BlockEntityData::Spawner),
+            _ => Ok(BlockEntityData::Opaque(nbt)),
+        };
+
+        let data = match parsed {
+            Ok(data) => data,
+            Err(e) => {
+                warn!("keeping {id} block entity as opaque nbt: {e}");
+                BlockEntityData::Opaque(nbt)
+            }
+        };
+
+        Self { id, data }
+    }
+
+    /// The NBT of the block entity without its `id`, `x`, `y` and `z` fields. This is also what
+    /// is sent to clients in the chunk data packet.
+    #[must_use]
+    pub fn to_nbt(&self) -> Compound {
+        match &self.data {
+            BlockEntityData::Sign(sign) => sign.to_nbt(),
+            BlockEntityData::Chest(chest) => chest.to_nbt(),
+            BlockEntityData::Spawner(spawner) => spawner.to_nbt(),
+            BlockEntityData::Opaque(nbt) => nbt.clone(),
+        }
+    }
+}
+
+/// The text on one side of a sign
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SignText {
+    /// The four lines as JSON text components
+    pub messages: [String; 4],
+    /// The name of the dye color of the text, such as `black`
+    pub color: String,
+    pub glowing: bool,
+}
+
+impl Default for SignText {
+    fn default() -> Self {
+        Self {
+            messages: std::array::from_fn(|_| String::from("\"\"")),
+            color: String::from("black"),
+            glowing: false,
+        }
+    }
+}
+
+impl SignText {
+    fn from_nbt(nbt: &Compound, field: &'static str) -> Result<Self, BlockEntityError> {
+        let Some(Value::Compound(text)) = nbt.get(field) else {
+            return Err(BlockEntityError::InvalidField(field));
+        };
+
+        let Some(Value::List(List::String(messages))) = text.get("messages") else {
+            return Err(BlockEntityError::InvalidField("messages"));
+        };
+
+        let Ok(messages) = <[String; 4]>::try_from(messages.clone()) else {
+            return Err(BlockEntityError::InvalidField("messages"));
+        };
+
+        let color = match text.get("color") {
+            Some(Value::String(color)) => color.clone(),
+            None => String::from("black"),
+            Some(_) => return Err(BlockEntityError::InvalidField("color")),
+        };
+
+        Ok(Self {
+            messages,
+            color,
+            glowing: read_bool(text, "has_glowing_text")?,
+        })
+    }
+
+    fn to_nbt(&self) -> Compound {
+        compound! {
+            "messages" => List::String(self.messages.to_vec()),
+            "color" => self.color.clone(),
+            "has_glowing_text" => i8::from(self.glowing),
+        }
+    }
+}
+
+/// The data of a sign or hanging sign
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SignData {
+    pub front: SignText,
+    pub back: SignText,
+    /// Waxed signs cannot be edited by players
+    pub waxed: bool,
+    /// Fields which are not modelled
+    pub extra: Compound,
+}
+
+impl SignData {
+    const FIELDS: [&str; 3] = ["front_text", "back_text", "is_waxed"];
+
+    pub fn from_nbt(nbt: &Compound) -> Result<Self, BlockEntityError> {
+        Ok(Self {
+            front: SignText::from_nbt(nbt, "front_text")?,
+            back: SignText::from_nbt(nbt, "back_text")?,
+            waxed: read_bool(nbt, "is_waxed")?,
+            extra: without(nbt, &Self::FIELDS),
+        })
+    }
+
+    #[must_use]
+    pub fn to_nbt(&self) -> Compound {
+        let mut nbt = self.extra.clone();
+        nbt.insert("front_text", self.front.to_nbt());
+        nbt.insert("back_text", self.back.to_nbt());
+        nbt.insert("is_waxed", i8::from(self.waxed));
+        nbt
+    }
+}
+
+/// The data of a chest or trapped chest
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChestData {
+    /// The title of the chest as a JSON text component
+    pub custom_name: Option<String>,
+    /// The items by their slot, from `0` in the top left to `26` in the bottom right
+    pub items: BTreeMap<u8, ItemStack>,
+    /// Fields which are not modelled, such as `Lock` and `LootTable`
+    pub extra: Compound,
+}
+
+impl ChestData {
+    const FIELDS: [&str; 2] = ["CustomName", "Items"];
+
+    pub fn from_nbt(nbt: &Compound) -> Result<Self, BlockEntityError> {
+        let custom_name = match nbt.get("CustomName") {
+            Some(Value::String(name)) => Some(name.clone()),
+            None => None,
+            Some(_) => return Err(BlockEntityError::InvalidField("CustomName")),
+        };
+
+        let items = match nbt.get("Items") {
+            Some(Value::List(List::Compound(items))) => {
+                items.iter().map(item_from_nbt).collect::<Result<_, _>>()?
+            }
+            Some(Value::List(List::End)) | None => BTreeMap::new(),
+            Some(_) => return Err(BlockEntityError::InvalidField("Items")),
+        };
+
+        Ok(Self {
+            custom_name,
+            items,
+            extra: without(nbt, &Self::FIELDS),
+        })
+    }
+
+    #[must_use]
+    pub fn to_nbt(&self) -> Compound {
+        let mut nbt = self.extra.clone();
+
+        if let Some(name) = &self.custom_name {
+            nbt.insert("CustomName", name.clone());
+        }
+
+        let items = self
+            .items
+            .iter()
+            .filter(|(_, stack)| !stack.is_empty())
+            .map(|(&slot, stack)| item_to_nbt(slot, stack))
+            .collect();
+        nbt.insert("Items", List::Compound(items));
+
+        nbt
+    }
+}
+
+/// The data of a monster spawner
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpawnerData {
+    /// The kind of entity that is spawned next, such as `minecraft:zombie`
+    pub entity: Option<String>,
+    /// Ticks until the next spawn
+    pub delay: i16,
+    pub min_spawn_delay: i16,
+    pub max_spawn_delay: i16,
+    pub spawn_count: i16,
+    pub max_nearby_entities: i16,
+    pub required_player_range: i16,
+    pub spawn_range: i16,
+    /// Fields which are not modelled, such as `SpawnPotentials`. This also contains the
+    /// `SpawnData` as it was saved, whose entity is replaced by [`SpawnerData::entity`].
+    pub extra: Compound,
+}
+
+impl Default for SpawnerData {
+    fn default() -> Self {
+        Self {
+            entity: None,
+            delay: 20,
+            min_spawn_delay: 200,
+            max_spawn_delay: 800,
+            spawn_count: 4,
+            max_nearby_entities: 6,
+            required_player_range: 16,
+            spawn_range: 4,
+            extra: Compound::new(),
+        }
+    }
+}
+
+impl SpawnerData {
+    const FIELDS: [&str; 7] = [
+        "Delay",
+        "MinSpawnDelay",
+        "MaxSpawnDelay",
+        "SpawnCount",
+        "MaxNearbyEntities",
+        "RequiredPlayerRange",
+        "SpawnRange",
+    ];
+
+    pub fn from_nbt(nbt: &Compound) -> Result<Self, BlockEntityError> {
+        let default = Self::default();
+
+        let entity = match nbt.get("SpawnData") {
+            Some(Value::Compound(spawn_data)) => match spawn_data.get("entity") {
+                Some(Value::Compound(entity)) => match entity.get("id") {
+                    Some(Value::String(id)) => Some(id.clone()),
+                    None => None,
+                    Some(_) => return Err(BlockEntityError::InvalidField("id")),
+                },
+                None => None,
+                Some(_) => return Err(BlockEntityError::InvalidField("entity")),
+            },
+            None => None,
+            Some(_) => return Err(BlockEntityError::InvalidField("SpawnData")),
+        };
+
+        Ok(Self {
+            entity,
+            delay: read_short(nbt, "Delay", default.delay)?,
+            min_spawn_delay: read_short(nbt, "MinSpawnDelay", default.min_spawn_delay)?,
+            max_spawn_delay: read_short(nbt, "MaxSpawnDelay", default.max_spawn_delay)?,
+            spawn_count: read_short(nbt, "SpawnCount", default.spawn_count)?,
+            max_nearby_entities: read_short(nbt, "MaxNearbyEntities", default.max_nearby_entities)?,
+            required_player_range: read_short(
+                nbt,
+                "RequiredPlayerRange",
+                default.required_player_range,
+            )?,
+            spawn_range: read_short(nbt, "SpawnRange", default.spawn_range)?,
+            extra: without(nbt, &Self::FIELDS),
+        })
+    }
+
+    #[must_use]
+    pub fn to_nbt(&self) -> Compound {
+        let mut nbt = self.extra.clone();
+
+        match &self.entity {
+            Some(id) => {
+                let mut spawn_data = match nbt.remove("SpawnData") {
+                    Some(Value::Compound(spawn_data)) => spawn_data,
+                    _ => Compound::new(),
+                };
+                let mut entity = match spawn_data.remove("entity") {
+                    Some(Value::Compound(entity)) => entity,
+                    _ => Compound::new(),
+                };
+                entity.insert("id", id.clone());
+                spawn_data.insert("entity", entity);
+                nbt.insert("SpawnData", spawn_data);
+            }
+            None => {
+                nbt.remove("SpawnData");
+            }
+        }
+
+        nbt.insert("Delay", self.delay);
+        nbt.insert("MinSpawnDelay", self.min_spawn_delay);
+        nbt.insert("MaxSpawnDelay", self.max_spawn_delay);
+        nbt.insert("SpawnCount", self.spawn_count);
+        nbt.insert("MaxNearbyEntities", self.max_nearby_entities);
+        nbt.insert("RequiredPlayerRange", self.required_player_range);
+        nbt.insert("SpawnRange", self.spawn_range);
+        nbt
+    }
+}
+
+/// The block entities of a chunk by their position relative to the chunk
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BlockEntities {
+    /// Indexed by `x + z * 16 + y * 256`, where `y` starts at the bottom of the chunk
+    entries: BTreeMap<u32, BlockEntity>,
+}
+
+impl BlockEntities {
+    const fn index(x: u32, y: u32, z: u32) -> u32 {
+        x + z * 16 + y * 16 * 16
+    }
+
+    #[must_use]
+    pub fn get(&self, x: u32, y: u32, z: u32) -> Option<&BlockEntity> {
+        self.entries.get(&Self::index(x, y, z))
+    }
+
+    pub fn get_mut(&mut self, x: u32, y: u32, z: u32) -> Option<&mut BlockEntity> {
+        self.entries.get_mut(&Self::index(x, y, z))
+    }
+
+    /// Returns the block entity which was previously at the position
+    pub fn insert(
+        &mut self,
+        x: u32,
+        y: u32,
+        z: u32,
+        block_entity: BlockEntity,
+    ) -> Option<BlockEntity> {
+        self.entries.insert(Self::index(x, y, z), block_entity)
+    }
+
+    /// Returns the block entity at the position, first inserting the one returned by `default` if
+    /// there is none
+    pub fn get_or_insert_with(
+        &mut self,
+        x: u32,
+        y: u32,
+        z: u32,
+        default: impl FnOnce() -> BlockEntity,
+    ) -> &mut BlockEntity {
+        self.entries
+            .entry(Self::index(x, y, z))
+            .or_insert_with(default)
+    }
+
+    pub fn remove(&mut self, x: u32, y: u32, z: u32) -> Option<BlockEntity> {
+        self.entries.remove(&Self::index(x, y, z))
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Iterates over the block entities and their positions relative to the chunk
+    pub fn iter(&self) -> impl Iterator<Item = (UVec3, &BlockEntity)> + '_ {
+        self.entries.iter().map(|(&idx, block_entity)| {
+            let position = UVec3::new(idx % 16, idx / 16 / 16, (idx / 16) % 16);
+            (position, block_entity)
+        })
+    }
+}
+
+/// Clones `nbt` without the `fields` which are modelled
+fn without(nbt: &Compound, fields: &[&str]) -> Compound {
+    let mut extra = nbt.clone();
+    for field in fields {
+        extra.remove(*field);
+    }
+    extra
+}
+
+fn read_bool(nbt: &Compound, field: &'static str) -> Result<bool, BlockEntityError> {
+    match nbt.get(field) {
+        Some(&Value::Byte(value)) => Ok(value != 0),
+        None => Ok(false),
+        Some(_) => Err(BlockEntityError::InvalidField(field)),
+    }
+}
+
+fn read_short(nbt: &Compound, field: &'static str, default: i16) -> Result<i16, BlockEntityError> {
+    match nbt.get(field) {
+        Some(&Value::Short(value)) => Ok(value),
+        None => Ok(default),
+        Some(_) => Err(BlockEntityError::InvalidField(field)),
+    }
+}
+
+fn item_from_nbt(nbt: &Compound) -> Result<(u8, ItemStack), BlockEntityError> {
+    let Some(&Value::Byte(slot)) = nbt.get("Slot") else {
+        return Err(BlockEntityError::InvalidField("Slot"));
+    };
+
+    let Ok(slot) = u8::try_from(slot) else {
+        return Err(BlockEntityError::InvalidField("Slot"));
+    };
+
+    let Some(Value::String(id)) = nbt.get("id") else {
+        return Err(BlockEntityError::InvalidField("id"));
+    };
+
+    let Some(item) = ItemKind::from_str(id.strip_prefix("minecraft:").unwrap_or(id)) else {
+        return Err(BlockEntityError::UnknownItem(id.clone()));
+    };
+
+    let Some(&Value::Byte(count)) = nbt.get("Count") else {
+        return Err(BlockEntityError::InvalidField("Count"));
+    };
+
+    let tag = match nbt.get("tag") {
+        Some(Value::Compound(tag)) => Some(tag.clone()),
+        None => None,
+        Some(_) => return Err(BlockEntityError::InvalidField("tag")),
+    };
+
+    Ok((slot, ItemStack::new(item, count, tag)))
+}
+
+fn item_to_nbt(slot: u8, stack: &ItemStack) -> Compound {
+    let mut nbt = compound! {
+        "Slot" => slot.cast_signed(),
+        "id" => format!("minecraft:{}", stack.item.to_str()),
+        "Count" => stack.count,
+    };
+
+    if let Some(tag) = &stack.nbt {
+        nbt.insert("tag", tag.clone());
+    }
+
+    nbt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sign_text(line: &str) -> Compound {
+        compound! {
+            "messages" => List::String(vec![
+                format!("{{\"text\":\"{line}\"}}"),
+                String::from("\"\""),
+                String::from("\"\""),
+                String::from("\"\""),
+            ]),
+            "color" => "black",
+            "has_glowing_text" => 1_i8,
+        }
+    }
+
+    #[test]
+    fn sign_round_trip() {
+        let nbt = compound! {
+            "front_text" => sign_text("hello"),
+            "back_text" => sign_text("world"),
+            "is_waxed" => 0_i8,
+        };
+
+        let block_entity = BlockEntity::from_nbt(String::from("minecraft:sign"), nbt.clone());
+        let BlockEntityData::Sign(sign) = &block_entity.data else {
+            panic!("expected a sign, got {block_entity:?}");
+        };
+
+        assert_eq!(sign.front.messages[0], "{\"text\":\"hello\"}");
+        assert_eq!(sign.back.messages[0], "{\"text\":\"world\"}");
+        assert!(sign.front.glowing);
+        assert!(!sign.waxed);
+        assert_eq!(block_entity.to_nbt(), nbt);
+    }
+
+    #[test]
+    fn chest_round_trip() {
+        let nbt = compound! {
+            "CustomName" => "{\"text\":\"Loot\"}",
+            "Lock" => "key",
+            "Items" => List::Compound(vec![
+                compound! {
+                    "Slot" => 3_i8,
+                    "id" => "minecraft:diamond",
+                    "Count" => 5_i8,
+                },
+                compound! {
+                    "Slot" => 26_i8,
+                    "id" => "minecraft:stone",
+                    "Count" => 64_i8,
+                    "tag" => compound! { "Damage" => 0_i32 },
+                },
+            ]),
+        };
+
+        let block_entity = BlockEntity::from_nbt(String::from("minecraft:chest"), nbt.clone());
+        let BlockEntityData::Chest(chest) = &block_entity.data else {
+            panic!("expected a chest, got {block_entity:?}");
+        };
+
+        assert_eq!(chest.custom_name.as_deref(), Some("{\"text\":\"Loot\"}"));
+        assert_eq!(chest.items[&3], ItemStack::new(ItemKind::Diamond, 5, None));
+        assert_eq!(chest.items[&26].item, ItemKind::Stone);
+        assert_eq!(chest.extra, compound! { "Lock" => "key" });
+        assert_eq!(block_entity.to_nbt(), nbt);
+    }
+
+    #[test]
+    fn spawner_round_trip() {
+        let nbt = compound! {
+            "SpawnData" => compound! {
+                "entity" => compound! { "id" => "minecraft:zombie" },
+            },
+            "SpawnPotentials" => List::End,
+            "Delay" => 20_i16,
+            "MinSpawnDelay" => 200_i16,
+            "MaxSpawnDelay" => 800_i16,
+            "SpawnCount" => 4_i16,
+            "MaxNearbyEntities" => 6_i16,
+            "RequiredPlayerRange" => 16_i16,
+            "SpawnRange" => 4_i16,
+        };
+
+        let block_entity =
+            BlockEntity::from_nbt(String::from("minecraft:mob_spawner"), nbt.clone());
+        let BlockEntityData::Spawner(spawner) = &block_entity.data else {
+            panic!("expected a spawner, got {block_entity:?}");
+        };
+
+        assert_eq!(spawner.entity.as_deref(), Some("minecraft:zombie"));
+        assert_eq!(block_entity.to_nbt(), nbt);
+    }
+
+    #[test]
+    fn unknown_and_invalid_block_entities_are_kept() {
+        let furnace = compound! { "BurnTime" => 100_i16, "Items" => List::End };
+        let block_entity =
+            BlockEntity::from_nbt(String::from("minecraft:furnace"), furnace.clone());
+        assert_eq!(block_entity.data, BlockEntityData::Opaque(furnace.clone()));
+        assert_eq!(block_entity.to_nbt(), furnace);
+
+        let chest = compound! {
+            "Items" => List::Compound(vec![compound! {
+                "Slot" => 0_i8,
+                "id" => "mymod:gadget",
+                "Count" => 1_i8,
+            }]),
+        };
+        let block_entity = BlockEntity::from_nbt(String::from("minecraft:chest"), chest.clone());
+        assert_eq!(block_entity.data, BlockEntityData::Opaque(chest));
+    }
+}
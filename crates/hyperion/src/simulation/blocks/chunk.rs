@@ -1,11 +1,15 @@
-use std::fmt::Debug;
+use std::{fmt::Debug, sync::Arc};
 
 use bytes::Bytes;
-use glam::{IVec2, IVec3};
+use glam::{I16Vec2, IVec2, IVec3};
 use valence_generated::block::BlockState;
 use valence_server::layer::chunk::Chunk;
 
-use super::loader::parse::ColumnData;
+use super::{
+    generator::WorldGenerator,
+    loader::{encode_column, generate_column, parse::ColumnData},
+    saved_entity::SavedEntity,
+};
 use crate::simulation::blocks::loader::parse::section::Section;
 
 pub const START_Y: i16 = -64;
@@ -64,9 +68,19 @@ pub struct Column {
 
     /// The actual chunk data that is "uncompressed". It uses a palette to store the actual data. This is usually used
     /// for obtaining the actual data from the chunk such as getting the block state of a block at a given position.
-    pub data: ColumnData,
+    ///
+    /// The data is shared with the chunk data packets being encoded, so it is only copied if the
+    /// chunk is changed while a packet is encoded. Use [`Column::data_mut`] to change it.
+    pub data: Arc<ColumnData>,
 
     pub position: IVec2,
+
+    /// The entities saved in the chunk which have not been spawned yet. See
+    /// [`Blocks::take_loaded_entities`](super::Blocks::take_loaded_entities).
+    pub entities: Vec<SavedEntity>,
+
+    /// Incremented whenever the changes to the chunk are sent to players. See [`Column::epoch`].
+    epoch: u64,
 }
 
 fn y_index(y: i16) -> u16 {
@@ -74,14 +88,50 @@ fn y_index(y: i16) -> u16 {
 }
 
 impl Column {
-    pub const fn new(base_packet_bytes: Bytes, data: ColumnData, position: IVec2) -> Self {
+    pub fn new(base_packet_bytes: Bytes, data: ColumnData, position: IVec2) -> Self {
         Self {
             base_packet_bytes,
-            data,
+            data: Arc::new(data),
             position,
+            entities: Vec::new(),
+            epoch: 0,
         }
     }
 
+    /// The data of the chunk for changing it, see [`Column::data`]
+    pub fn data_mut(&mut self) -> &mut ColumnData {
+        Arc::make_mut(&mut self.data)
+    }
+
+    /// Generates the column at `position` with `generator`
+    #[must_use]
+    pub fn generate(position: I16Vec2, generator: &dyn WorldGenerator) -> Self {
+        generate_column(position, generator)
+    }
+
+    /// How many times changes to the chunk have been sent to players since it was loaded.
+    ///
+    /// [`Column::base_packet_bytes`] is only up to date in epoch `0`. In later epochs, a chunk
+    /// data packet encoded during the epoch is up to date as well, since any change made after
+    /// encoding it is sent to players at the end of the epoch.
+    #[must_use]
+    pub const fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Starts a new [`Column::epoch`], after which previously encoded packets of the chunk are
+    /// out of date
+    pub const fn mark_dirty(&mut self) {
+        self.epoch += 1;
+    }
+
+    /// Encodes the chunk data packet of the current state of the chunk. Use
+    /// [`ChunkPacketCache`](super::packet_cache::ChunkPacketCache) to avoid encoding a chunk more
+    /// than once.
+    pub fn encode_packet(&self) -> anyhow::Result<Bytes> {
+        encode_column(&self.data, self.position)
+    }
+
     pub fn sections(&self) -> impl Iterator<Item = (IVec3, &Section)> + '_ {
         let column_start_position = IVec3::new(
             self.position.x << 4,
@@ -143,7 +193,7 @@ impl Column {
 
     #[expect(unused, reason = "might be useful in the future")]
     fn set_block_internal(&mut self, x: u8, y: u16, z: u8, state: BlockState) {
-        self.data
+        self.data_mut()
             .set_block(u32::from(x), u32::from(y), u32::from(z), state);
     }
 
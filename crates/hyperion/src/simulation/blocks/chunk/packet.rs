@@ -93,10 +93,21 @@ impl PacketBundle for DeltaPacket<'_> {
 }
 
 impl Column {
+    /// Returns the packets of the changes made since the last call. The changes start a new
+    /// [`Column::epoch`], since they are sent to players once the packets are.
     pub fn delta_drain_packets(&mut self) -> impl Iterator<Item = DeltaDrainPacket<'_>> + '_ {
         let IVec2 { x, y: z } = self.position;
 
-        self.data
+        if self
+            .data
+            .sections
+            .iter()
+            .any(|section| !section.changed_since_last_tick.is_empty())
+        {
+            self.mark_dirty();
+        }
+
+        self.data_mut()
             .sections
             .iter_mut()
             .enumerate()
@@ -0,0 +1,221 @@
+//! The collision shapes of block states, which are the boxes entities cannot move into.
+//!
+//! The shapes are baked into `valence_generated` from vanilla data when it is built, so nothing is
+//! parsed at runtime. [`CollisionShapes`] flattens them into a table of [`Aabb`]s indexed by the
+//! raw block state once, so the physics, raycasts, and placement checks do not convert shapes on
+//! every lookup.
+//!
+//! Shapes are relative to the minimum corner of their block and may extend above it, such as
+//! fences and walls, which are 1.5 blocks tall so that entities cannot jump over them.
+
+use std::{ops::ControlFlow, sync::LazyLock};
+
+use geometry::aabb::Aabb;
+use glam::{IVec3, Vec3};
+use valence_generated::block::BlockState;
+
+use super::Blocks;
+
+static COLLISION_SHAPES: LazyLock<CollisionShapes> = LazyLock::new(CollisionShapes::build);
+
+/// The collision shapes of every block state. See the [module documentation](self).
+pub struct CollisionShapes {
+    shapes: Vec<Aabb>,
+    /// The range of `shapes` belonging to each raw block state
+    ranges: Vec<(u32, u32)>,
+    /// How far the tallest shape extends above its block
+    max_overhang: f32,
+}
+
+impl CollisionShapes {
+    fn build() -> Self {
+        let mut shapes = Vec::new();
+        let mut ranges = Vec::with_capacity(usize::from(BlockState::max_raw()) + 1);
+
+        for raw in 0..=BlockState::max_raw() {
+            let start = u32::try_from(shapes.len()).unwrap();
+            if let Some(state) = BlockState::from_raw(raw) {
+                shapes.extend(
+                    state
+                        .collision_shapes()
+                        .map(|shape| Aabb::new(shape.min().as_vec3(), shape.max().as_vec3())),
+                );
+            }
+            let end = u32::try_from(shapes.len()).unwrap();
+            ranges.push((start, end));
+        }
+
+        let max_overhang = shapes
+            .iter()
+            .map(|shape| shape.max.y - 1.0)
+            .fold(0.0, f32::max);
+
+        Self {
+            shapes,
+            ranges,
+            max_overhang,
+        }
+    }
+
+    /// The shared registry, which is built on first use
+    #[must_use]
+    pub fn get() -> &'static Self {
+        &COLLISION_SHAPES
+    }
+
+    /// The collision shapes of `state` relative to its block. Blocks without collision, such as
+    /// air, flowers, and open fence gates, have none.
+    #[must_use]
+    pub fn shapes_for(&self, state: BlockState) -> &[Aabb] {
+        let (start, end) = self.ranges[usize::from(state.to_raw())];
+        &self.shapes[start as usize..end as usize]
+    }
+
+    /// Whether `state` placed at `block_pos` overlaps `entity`. Touching a shape does not count as
+    /// overlapping it, so an entity standing on a block does not collide with it.
+    #[must_use]
+    pub fn collides(&self, state: BlockState, block_pos: IVec3, entity: &Aabb) -> bool {
+        let origin = block_pos.as_vec3();
+        self.shapes_for(state)
+            .iter()
+            .any(|shape| Aabb::overlap(&shape.move_by(origin), entity).is_some())
+    }
+
+    /// How many blocks the tallest shape extends above its block, which is `0.5` for fences and
+    /// walls. Collision checks have to include the blocks this far below an entity.
+    #[must_use]
+    pub const fn max_overhang(&self) -> f32 {
+        self.max_overhang
+    }
+}
+
+/// The collision shapes of `state`. See [`CollisionShapes::shapes_for`].
+#[must_use]
+pub fn shapes_for(state: BlockState) -> &'static [Aabb] {
+    CollisionShapes::get().shapes_for(state)
+}
+
+/// Whether `state` at `block_pos` overlaps `entity`. See [`CollisionShapes::collides`].
+#[must_use]
+pub fn collides(state: BlockState, block_pos: IVec3, entity: &Aabb) -> bool {
+    CollisionShapes::get().collides(state, block_pos, entity)
+}
+
+impl Blocks {
+    /// Whether `aabb` overlaps the collision shape of any loaded block, including the blocks
+    /// below it which extend into it, such as fences.
+    #[must_use]
+    pub fn collides(&self, aabb: &Aabb) -> bool {
+        let shapes = CollisionShapes::get();
+
+        let min = (aabb.min - Vec3::new(0.0, shapes.max_overhang(), 0.0))
+            .floor()
+            .as_ivec3();
+        let max = aabb.max.floor().as_ivec3();
+
+        let result = self.get_blocks(min, max, |position, block| {
+            if shapes.collides(block, position, aabb) {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        });
+
+        result.is_break()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use valence_generated::block::{PropName, PropValue};
+
+    use super::*;
+    use crate::simulation::{
+        EntitySize,
+        handlers::{has_block_collision, is_grounded},
+    };
+
+    /// Stone below `y = 0` with `state` at `position`
+    fn with_block(position: IVec3, state: BlockState) -> Blocks {
+        let mut blocks = Blocks::flat(0, BlockState::STONE);
+        blocks.set_block(position, state).unwrap();
+        blocks
+    }
+
+    fn slab(kind: PropValue) -> BlockState {
+        BlockState::OAK_SLAB.set(PropName::Type, kind)
+    }
+
+    #[test]
+    fn shapes_are_not_only_full_cubes() {
+        assert!(shapes_for(BlockState::AIR).is_empty());
+        assert_eq!(shapes_for(BlockState::STONE), [Aabb::new(
+            Vec3::ZERO,
+            Vec3::ONE
+        )]);
+
+        let carpet = shapes_for(BlockState::WHITE_CARPET);
+        assert_eq!(carpet.len(), 1);
+        assert!((carpet[0].max.y - 1.0 / 16.0).abs() < 1e-6);
+
+        let fence = shapes_for(BlockState::OAK_FENCE);
+        assert!(fence.iter().any(|shape| (shape.max.y - 1.5).abs() < 1e-6));
+        assert!((CollisionShapes::get().max_overhang() - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn players_stand_on_top_slabs() {
+        let blocks = with_block(IVec3::new(1, 0, 1), slab(PropValue::Top));
+        let size = EntitySize::default();
+
+        let on_slab = Vec3::new(1.5, 1.0, 1.5);
+        assert!(is_grounded(&on_slab, &blocks));
+        assert!(!has_block_collision(&on_slab, size, &blocks));
+
+        let above_slab = Vec3::new(1.5, 1.2, 1.5);
+        assert!(!is_grounded(&above_slab, &blocks));
+    }
+
+    #[test]
+    fn top_slabs_are_solid_from_below() {
+        let blocks = with_block(IVec3::new(1, 2, 1), slab(PropValue::Top));
+        let size = EntitySize::default();
+
+        // The head of a player jumping up reaches into the top half of the block
+        assert!(!has_block_collision(
+            &Vec3::new(1.5, 0.0, 1.5),
+            size,
+            &blocks
+        ));
+        assert!(has_block_collision(
+            &Vec3::new(1.5, 0.8, 1.5),
+            size,
+            &blocks
+        ));
+    }
+
+    #[test]
+    fn players_walk_under_bottom_slab_overhangs() {
+        let size = EntitySize::default();
+        let under_overhang = Vec3::new(1.5, 0.0, 1.5);
+
+        // A bottom slab two blocks up leaves room for a player
+        let blocks = with_block(IVec3::new(1, 2, 1), slab(PropValue::Bottom));
+        assert!(!has_block_collision(&under_overhang, size, &blocks));
+
+        // A top slab one block up does not
+        let blocks = with_block(IVec3::new(1, 1, 1), slab(PropValue::Top));
+        assert!(has_block_collision(&under_overhang, size, &blocks));
+    }
+
+    #[test]
+    fn fences_block_entities_above_them() {
+        let blocks = with_block(IVec3::new(0, 0, 0), BlockState::OAK_FENCE);
+
+        let entity = Aabb::new(Vec3::new(0.3, 1.2, 0.3), Vec3::new(0.7, 3.0, 0.7));
+        assert!(blocks.collides(&entity));
+
+        let entity = entity.move_by(Vec3::new(0.0, 0.4, 0.0));
+        assert!(!blocks.collides(&entity));
+    }
+}
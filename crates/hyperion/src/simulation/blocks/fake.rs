@@ -0,0 +1,213 @@
+//! Blocks which only a single player sees, such as a structure previewed before placing it.
+//!
+//! The overrides in [`FakeBlocks`] are sent to the player as block updates whenever the real
+//! blocks are sent, right after the chunk or the changes to it. Digging a fake block does not
+//! affect the real block and is answered with the fake block again.
+
+use bevy_ecs::{component::Component, entity::Entity, world::World};
+use glam::{I16Vec2, IVec3};
+use rustc_hash::{FxHashMap, FxHashSet};
+use valence_generated::block::BlockState;
+use valence_protocol::{BlockPos, packets::play};
+#[cfg(feature = "reflect")]
+use {bevy_ecs::reflect::ReflectComponent, bevy_reflect::Reflect};
+
+use crate::{net::DataBundle, simulation::blocks::Blocks};
+
+/// Blocks shown to a player in place of the real blocks of its world. The overrides are removed
+/// when the player is transferred to another world.
+#[derive(Component, Default, Debug)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+pub struct FakeBlocks {
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    chunks: FxHashMap<I16Vec2, FxHashMap<IVec3, BlockState>>,
+    /// Positions whose fake block was set or cleared since the player was last sent its fake
+    /// blocks
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    changed: FxHashSet<IVec3>,
+}
+
+const fn chunk_of(position: IVec3) -> I16Vec2 {
+    #[expect(
+        clippy::cast_possible_truncation,
+        reason = "chunk positions of valid blocks fit in an i16"
+    )]
+    let (x, z) = ((position.x >> 4) as i16, (position.z >> 4) as i16);
+    I16Vec2::new(x, z)
+}
+
+impl FakeBlocks {
+    /// Returns the fake block at `position`, if there is one
+    #[must_use]
+    pub fn get(&self, position: IVec3) -> Option<BlockState> {
+        self.chunks
+            .get(&chunk_of(position))?
+            .get(&position)
+            .copied()
+    }
+
+    pub fn set(&mut self, position: IVec3, state: BlockState) {
+        let previous = self
+            .chunks
+            .entry(chunk_of(position))
+            .or_default()
+            .insert(position, state);
+
+        if previous != Some(state) {
+            self.changed.insert(position);
+        }
+    }
+
+    /// Removes the fake blocks from `min` to `max`, inclusive. The player is sent the real blocks
+    /// again.
+    pub fn clear(&mut self, min: IVec3, max: IVec3) {
+        let (min, max) = (min.min(max), min.max(max));
+
+        self.chunks.retain(|_, blocks| {
+            blocks.retain(|&position, _| {
+                let inside = position.cmpge(min).all() && position.cmple(max).all();
+                if inside {
+                    self.changed.insert(position);
+                }
+                !inside
+            });
+            !blocks.is_empty()
+        });
+    }
+
+    /// Whether the player has no fake blocks
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// Returns the fake blocks in the chunk at `chunk`
+    pub fn in_chunk(&self, chunk: I16Vec2) -> impl Iterator<Item = (IVec3, BlockState)> + '_ {
+        self.chunks
+            .get(&chunk)
+            .into_iter()
+            .flatten()
+            .map(|(&position, &state)| (position, state))
+    }
+
+    /// Adds the block updates of the fake blocks in `chunk` to `bundle`
+    pub(crate) fn add_chunk_updates(&self, bundle: &mut DataBundle<'_>, chunk: I16Vec2) {
+        for (position, state) in self.in_chunk(chunk) {
+            bundle.add_packet(&block_update(position, state)).unwrap();
+        }
+    }
+
+    /// Adds the block updates of the fake blocks which changed since the last call, and of the
+    /// fake blocks in `changed_chunks` whose real blocks were just sent, to `bundle`. Cleared fake
+    /// blocks are replaced with the real block from `blocks`.
+    pub(crate) fn add_updates(
+        &mut self,
+        bundle: &mut DataBundle<'_>,
+        blocks: &Blocks,
+        changed_chunks: &FxHashSet<I16Vec2>,
+    ) {
+        for position in std::mem::take(&mut self.changed) {
+            let fake = self.get(position);
+
+            if fake.is_some() && changed_chunks.contains(&chunk_of(position)) {
+                // Sent below with the other fake blocks of the chunk
+                continue;
+            }
+
+            // Chunks which are not loaded have not been sent, and the fake block is sent with
+            // the chunk
+            let Some(state) = fake.or_else(|| blocks.get_block(position)) else {
+                continue;
+            };
+
+            bundle.add_packet(&block_update(position, state)).unwrap();
+        }
+
+        for &chunk in changed_chunks {
+            self.add_chunk_updates(bundle, chunk);
+        }
+    }
+}
+
+fn block_update(position: IVec3, state: BlockState) -> play::BlockUpdateS2c {
+    play::BlockUpdateS2c {
+        position: BlockPos::new(position.x, position.y, position.z),
+        block_id: state,
+    }
+}
+
+/// Returns a command showing `state` at `position` to `player` only
+pub fn set_fake_block(
+    player: Entity,
+    position: IVec3,
+    state: BlockState,
+) -> impl FnOnce(&mut World) + Send + 'static {
+    move |world: &mut World| {
+        let Ok(mut entity) = world.get_entity_mut(player) else {
+            return;
+        };
+
+        if let Some(mut fake_blocks) = entity.get_mut::<FakeBlocks>() {
+            fake_blocks.set(position, state);
+        } else {
+            let mut fake_blocks = FakeBlocks::default();
+            fake_blocks.set(position, state);
+            entity.insert(fake_blocks);
+        }
+    }
+}
+
+/// Returns a command removing the fake blocks of `player` from `min` to `max`, inclusive
+pub fn clear_fake_blocks(
+    player: Entity,
+    min: IVec3,
+    max: IVec3,
+) -> impl FnOnce(&mut World) + Send + 'static {
+    move |world: &mut World| {
+        if let Some(mut fake_blocks) = world.get_mut::<FakeBlocks>(player) {
+            fake_blocks.clear(min, max);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fake_blocks_are_tracked_per_chunk() {
+        let mut fake_blocks = FakeBlocks::default();
+        fake_blocks.set(IVec3::new(1, 64, 1), BlockState::RED_BED);
+        fake_blocks.set(IVec3::new(-1, 64, 1), BlockState::STONE);
+
+        assert_eq!(
+            fake_blocks.get(IVec3::new(1, 64, 1)),
+            Some(BlockState::RED_BED)
+        );
+        assert_eq!(fake_blocks.get(IVec3::new(1, 65, 1)), None);
+        assert_eq!(fake_blocks.in_chunk(I16Vec2::new(-1, 0)).count(), 1);
+        assert_eq!(fake_blocks.changed.len(), 2);
+    }
+
+    #[test]
+    fn clearing_only_removes_the_region() {
+        let mut fake_blocks = FakeBlocks::default();
+        fake_blocks.set(IVec3::new(0, 64, 0), BlockState::STONE);
+        fake_blocks.set(IVec3::new(40, 64, 0), BlockState::STONE);
+        fake_blocks.changed.clear();
+
+        fake_blocks.clear(IVec3::new(10, 70, 10), IVec3::new(-10, 60, -10));
+
+        assert_eq!(fake_blocks.get(IVec3::new(0, 64, 0)), None);
+        assert_eq!(
+            fake_blocks.get(IVec3::new(40, 64, 0)),
+            Some(BlockState::STONE)
+        );
+        assert_eq!(fake_blocks.changed.iter().copied().collect::<Vec<_>>(), [
+            IVec3::new(0, 64, 0)
+        ]);
+
+        fake_blocks.clear(IVec3::splat(-100), IVec3::splat(100));
+        assert!(fake_blocks.is_empty());
+    }
+}
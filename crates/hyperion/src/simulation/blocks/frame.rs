@@ -49,7 +49,7 @@ impl Blocks {
 
                 self.should_update.insert(idx as u32);
 
-                let chunk = &mut loaded_chunk.data;
+                let chunk = loaded_chunk.data_mut();
 
                 for section_y in start_chunk.y..=end_chunk.y {
                     let section_idx = (section_y - (START_Y / 16)) as usize;
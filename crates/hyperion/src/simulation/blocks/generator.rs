@@ -0,0 +1,382 @@
+//! Procedural generation of chunks that do not exist in an Anvil save.
+//!
+//! A [`WorldGenerator`] is used by [`Blocks`](super::Blocks) whenever a requested chunk is not
+//! present on disk. Generation runs on the rayon thread pool, so generators must be cheap to share
+//! between threads.
+
+use glam::{I16Vec2, IVec2};
+use valence_generated::block::BlockState;
+
+use super::Section;
+use crate::CHUNK_HEIGHT_SPAN;
+
+const SECTION_COUNT: u32 = CHUNK_HEIGHT_SPAN / 16;
+
+/// Generates the blocks of chunk sections on demand.
+pub trait WorldGenerator: Send + Sync + 'static {
+    /// Generates the section at `section_y` of the chunk at `chunk_pos`.
+    ///
+    /// `section_y` counts from the bottom of the world, so `0` is the section containing the
+    /// lowest 16 block layers.
+    fn generate_section(&self, chunk_pos: I16Vec2, section_y: u32) -> Section;
+}
+
+/// Generates nothing but air.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct VoidGenerator;
+
+impl WorldGenerator for VoidGenerator {
+    fn generate_section(&self, _chunk_pos: I16Vec2, _section_y: u32) -> Section {
+        Section::empty_sky()
+    }
+}
+
+/// Generates identical layers of blocks in every chunk, similar to a vanilla superflat world.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlatGenerator {
+    /// The layers from the bottom of the world upwards, as a block and the number of layers it
+    /// occupies. Everything above the last layer is air.
+    pub layers: Vec<(BlockState, u8)>,
+}
+
+impl FlatGenerator {
+    #[must_use]
+    pub const fn new(layers: Vec<(BlockState, u8)>) -> Self {
+        Self { layers }
+    }
+
+    /// Returns the block at `y`, where `0` is the bottom of the world.
+    #[must_use]
+    pub fn block_at(&self, y: u32) -> BlockState {
+        let mut top = 0_u32;
+        for &(block, count) in &self.layers {
+            top += u32::from(count);
+            if y < top {
+                return block;
+            }
+        }
+        BlockState::AIR
+    }
+}
+
+impl Default for FlatGenerator {
+    /// One layer of bedrock, two layers of dirt, and one layer of grass
+    fn default() -> Self {
+        Self::new(vec![
+            (BlockState::BEDROCK, 1),
+            (BlockState::DIRT, 2),
+            (BlockState::GRASS_BLOCK, 1),
+        ])
+    }
+}
+
+impl WorldGenerator for FlatGenerator {
+    fn generate_section(&self, _chunk_pos: I16Vec2, section_y: u32) -> Section {
+        let base_y = section_y * 16;
+        let mut layers = [BlockState::AIR; 16];
+        for (dy, layer) in (0..16).zip(&mut layers) {
+            *layer = self.block_at(base_y + dy);
+        }
+
+        let mut section = Section::empty_sky();
+
+        if layers.iter().all(|&block| block == layers[0]) {
+            section.block_states.fill(layers[0].to_raw());
+            return section;
+        }
+
+        for (dy, &block) in (0_u16..).zip(&layers) {
+            if block == BlockState::AIR {
+                continue;
+            }
+            for xz in 0..256 {
+                section.set(dy * 256 + xz, block);
+            }
+        }
+
+        section
+    }
+}
+
+/// Generates a magenta and black checkerboard from the bottom of the world up to sea level, like
+/// the missing texture, so that chunks which could not be loaded stand out.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct ErrorPatternGenerator;
+
+impl ErrorPatternGenerator {
+    /// The number of block layers which are filled, which reaches up to sea level
+    pub const HEIGHT: u32 = 128;
+}
+
+impl WorldGenerator for ErrorPatternGenerator {
+    fn generate_section(&self, _chunk_pos: I16Vec2, section_y: u32) -> Section {
+        let mut section = Section::empty_sky();
+        let base_y = section_y * 16;
+
+        for y in (0..16_u16).take_while(|&y| base_y + u32::from(y) < Self::HEIGHT) {
+            for xz in 0..256_u16 {
+                let (x, z) = (xz % 16, xz / 16);
+                let block = if (x + y + z) % 2 == 0 {
+                    BlockState::MAGENTA_CONCRETE
+                } else {
+                    BlockState::BLACK_CONCRETE
+                };
+                section.set(y * 256 + xz, block);
+            }
+        }
+
+        section
+    }
+}
+
+/// A superflat world with gentle hills from seeded Perlin noise.
+#[derive(Debug, Clone)]
+pub struct SuperflatPlusGenerator {
+    noise: Perlin,
+    /// Height of the terrain surface without any noise, where `0` is the bottom of the world
+    pub base_height: u32,
+    /// Maximum height difference from `base_height` in blocks
+    pub amplitude: f32,
+    /// Horizontal size of a hill in blocks
+    pub scale: f32,
+    pub surface: BlockState,
+    pub subsurface: BlockState,
+    pub subsurface_depth: u32,
+    pub stone: BlockState,
+}
+
+impl SuperflatPlusGenerator {
+    #[must_use]
+    pub fn new(seed: u64) -> Self {
+        Self {
+            noise: Perlin::new(seed),
+            base_height: 128,
+            amplitude: 8.0,
+            scale: 64.0,
+            surface: BlockState::GRASS_BLOCK,
+            subsurface: BlockState::DIRT,
+            subsurface_depth: 3,
+            stone: BlockState::STONE,
+        }
+    }
+
+    /// Returns the y coordinate of the surface block in the column at the given block position,
+    /// where `0` is the bottom of the world.
+    #[must_use]
+    #[expect(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        reason = "coordinates and heights are small and the height is clamped within the world"
+    )]
+    pub fn surface_height(&self, x: i32, z: i32) -> u32 {
+        let noise = self.noise.get(x as f32 / self.scale, z as f32 / self.scale);
+        let height = noise.mul_add(self.amplitude, self.base_height as f32);
+
+        height.round().clamp(1.0, (CHUNK_HEIGHT_SPAN - 1) as f32) as u32
+    }
+
+    fn block_at(&self, y: u32, surface: u32) -> BlockState {
+        if y == 0 {
+            BlockState::BEDROCK
+        } else if y == surface {
+            self.surface
+        } else if y > surface {
+            BlockState::AIR
+        } else if y + self.subsurface_depth >= surface {
+            self.subsurface
+        } else {
+            self.stone
+        }
+    }
+}
+
+impl WorldGenerator for SuperflatPlusGenerator {
+    fn generate_section(&self, chunk_pos: I16Vec2, section_y: u32) -> Section {
+        debug_assert!(section_y < SECTION_COUNT);
+
+        let mut section = Section::empty_sky();
+        let base_y = section_y * 16;
+        let origin = IVec2::new(i32::from(chunk_pos.x), i32::from(chunk_pos.y)) * 16;
+
+        for z in 0..16_u16 {
+            for x in 0..16_u16 {
+                let surface = self.surface_height(origin.x + i32::from(x), origin.y + i32::from(z));
+
+                for y in 0..16_u16 {
+                    let block = self.block_at(base_y + u32::from(y), surface);
+                    if block != BlockState::AIR {
+                        section.set(x + z * 16 + y * 256, block);
+                    }
+                }
+            }
+        }
+
+        section
+    }
+}
+
+/// Two dimensional Perlin noise with a seeded permutation table.
+#[derive(Debug, Clone)]
+struct Perlin {
+    permutation: Box<[u8; 512]>,
+}
+
+impl Perlin {
+    fn new(seed: u64) -> Self {
+        let mut rng = fastrand::Rng::with_seed(seed);
+
+        let mut table: [u8; 256] = std::array::from_fn(|i| u8::try_from(i).unwrap());
+        rng.shuffle(&mut table);
+
+        let permutation = Box::new(std::array::from_fn(|i| table[i % 256]));
+        Self { permutation }
+    }
+
+    /// Returns noise in the range `-1.0..=1.0`
+    #[expect(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        reason = "only the lowest 8 bits of the lattice coordinates are used"
+    )]
+    fn get(&self, x: f32, z: f32) -> f32 {
+        let x0 = x.floor();
+        let z0 = z.floor();
+
+        let xi = (x0 as i32 & 255) as usize;
+        let zi = (z0 as i32 & 255) as usize;
+
+        let xf = x - x0;
+        let zf = z - z0;
+
+        let u = fade(xf);
+        let v = fade(zf);
+
+        let p = &self.permutation;
+        let hash = |x: usize, z: usize| p[usize::from(p[x]) + z];
+
+        let x1 = lerp(
+            u,
+            gradient(hash(xi, zi), xf, zf),
+            gradient(hash(xi + 1, zi), xf - 1.0, zf),
+        );
+        let x2 = lerp(
+            u,
+            gradient(hash(xi, zi + 1), xf, zf - 1.0),
+            gradient(hash(xi + 1, zi + 1), xf - 1.0, zf - 1.0),
+        );
+
+        // The maximum magnitude of 2D Perlin noise with these gradients is sqrt(0.5)
+        (lerp(v, x1, x2) * std::f32::consts::SQRT_2).clamp(-1.0, 1.0)
+    }
+}
+
+fn fade(t: f32) -> f32 {
+    t * t * t * t.mul_add(t.mul_add(6.0, -15.0), 10.0)
+}
+
+fn lerp(t: f32, a: f32, b: f32) -> f32 {
+    t.mul_add(b - a, a)
+}
+
+fn gradient(hash: u8, x: f32, z: f32) -> f32 {
+    match hash & 7 {
+        0 => x + z,
+        1 => x - z,
+        2 => -x + z,
+        3 => -x - z,
+        4 => x,
+        5 => -x,
+        6 => z,
+        _ => -z,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn void_generator_is_empty() {
+        let section = VoidGenerator.generate_section(I16Vec2::ZERO, 4);
+        assert_eq!(section.block_states.unique_count(), 1);
+        assert_eq!(section.block_states.get(0), BlockState::AIR.to_raw());
+        assert!(section.sky_light.is_some());
+    }
+
+    #[test]
+    fn flat_generator_layers() {
+        let generator = FlatGenerator::default();
+        assert_eq!(generator.block_at(0), BlockState::BEDROCK);
+        assert_eq!(generator.block_at(1), BlockState::DIRT);
+        assert_eq!(generator.block_at(2), BlockState::DIRT);
+        assert_eq!(generator.block_at(3), BlockState::GRASS_BLOCK);
+        assert_eq!(generator.block_at(4), BlockState::AIR);
+
+        let section = generator.generate_section(I16Vec2::new(3, -7), 0);
+        assert_eq!(section.block_states.get(0), BlockState::BEDROCK.to_raw());
+        assert_eq!(
+            section.block_states.get(3 * 256 + 17),
+            BlockState::GRASS_BLOCK.to_raw()
+        );
+        assert_eq!(section.block_states.get(4 * 256), BlockState::AIR.to_raw());
+
+        let above = generator.generate_section(I16Vec2::ZERO, 1);
+        assert_eq!(above.block_states.unique_count(), 1);
+    }
+
+    #[test]
+    fn flat_generator_uniform_section() {
+        let generator = FlatGenerator::new(vec![(BlockState::STONE, 32)]);
+        let section = generator.generate_section(I16Vec2::ZERO, 1);
+        assert_eq!(section.block_states.unique_count(), 1);
+        assert_eq!(section.block_states.get(4095), BlockState::STONE.to_raw());
+    }
+
+    #[test]
+    fn error_pattern_reaches_sea_level() {
+        let bottom = ErrorPatternGenerator.generate_section(I16Vec2::ZERO, 0);
+        assert_eq!(
+            bottom.block_states.get(0),
+            BlockState::MAGENTA_CONCRETE.to_raw()
+        );
+        assert_eq!(
+            bottom.block_states.get(1),
+            BlockState::BLACK_CONCRETE.to_raw()
+        );
+        assert_eq!(
+            bottom.block_states.get(256),
+            BlockState::BLACK_CONCRETE.to_raw()
+        );
+
+        let last = ErrorPatternGenerator::HEIGHT / 16;
+        let top = ErrorPatternGenerator.generate_section(I16Vec2::ZERO, last - 1);
+        assert_eq!(top.block_states.unique_count(), 2);
+        let above = ErrorPatternGenerator.generate_section(I16Vec2::ZERO, last);
+        assert_eq!(above.block_states.get(0), BlockState::AIR.to_raw());
+    }
+
+    #[test]
+    fn superflat_plus_is_deterministic() {
+        let a = SuperflatPlusGenerator::new(42);
+        let b = SuperflatPlusGenerator::new(42);
+
+        for (x, z) in [(0, 0), (17, -5), (-300, 1200)] {
+            assert_eq!(a.surface_height(x, z), b.surface_height(x, z));
+        }
+    }
+
+    #[test]
+    fn superflat_plus_hills_are_gentle() {
+        let generator = SuperflatPlusGenerator::new(7);
+
+        for x in -64..64 {
+            let height = generator.surface_height(x, x * 3);
+            let difference = height.abs_diff(generator.base_height);
+            assert!(
+                difference <= 8,
+                "height {height} is too far from the base height"
+            );
+        }
+    }
+}
@@ -0,0 +1,187 @@
+//! Worlds which are built in memory instead of being loaded, so that tests of logic depending on
+//! blocks need neither an Anvil save nor an [`AsyncRuntime`](crate::runtime::AsyncRuntime).
+
+use glam::{I16Vec2, IVec3};
+use valence_generated::block::BlockState;
+
+use super::{
+    Blocks,
+    block_entity::BlockEntities,
+    chunk::{Column, START_Y},
+    loader::{ChunkLoaderHandle, encode_column, parse::ColumnData},
+};
+use crate::{CHUNK_HEIGHT_SPAN, simulation::blocks::Section};
+
+/// An area of chunks from `min` to `max` (inclusive)
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ChunkBounds {
+    pub min: I16Vec2,
+    pub max: I16Vec2,
+}
+
+impl ChunkBounds {
+    #[must_use]
+    pub fn new(a: I16Vec2, b: I16Vec2) -> Self {
+        Self {
+            min: a.min(b),
+            max: a.max(b),
+        }
+    }
+
+    /// The `radius` chunks in every direction of the chunk at the origin, so `0` is only that
+    /// chunk
+    #[must_use]
+    pub fn around_origin(radius: i16) -> Self {
+        Self::new(I16Vec2::splat(-radius), I16Vec2::splat(radius))
+    }
+
+    /// The positions of the chunks in the area
+    pub fn chunks(self) -> impl Iterator<Item = I16Vec2> {
+        (self.min.x..=self.max.x)
+            .flat_map(move |x| (self.min.y..=self.max.y).map(move |z| I16Vec2::new(x, z)))
+    }
+}
+
+impl Default for ChunkBounds {
+    /// The chunks within 2 chunks of the origin
+    fn default() -> Self {
+        Self::around_origin(2)
+    }
+}
+
+/// Builds the column at `position` from the block at each position in the world
+fn build_column(position: I16Vec2, f: &impl Fn(IVec3) -> BlockState) -> Column {
+    let start = position.as_ivec2() << 4;
+
+    let sections = (0..CHUNK_HEIGHT_SPAN / 16)
+        .map(|section_y| {
+            let start_y = i32::from(START_Y) + i32::try_from(section_y * 16).unwrap();
+            let mut section = Section::empty_sky();
+
+            for idx in 0..4096_u16 {
+                let offset = Section::idx_to_xyz(usize::from(idx));
+                let block = f(IVec3::new(
+                    start.x + offset.x,
+                    start_y + offset.y,
+                    start.y + offset.z,
+                ));
+
+                if block != BlockState::AIR {
+                    section.set(idx, block);
+                }
+            }
+
+            section
+        })
+        .collect();
+
+    let data = ColumnData {
+        sections,
+        block_entities: BlockEntities::default(),
+    };
+
+    let bytes = encode_column(&data, position.as_ivec2()).unwrap();
+    Column::new(bytes, data, position.as_ivec2())
+}
+
+impl Blocks {
+    /// Creates a world in which the chunks within `bounds` are loaded, with the block returned by
+    /// `f` at each position. Chunks outside of `bounds` are never loaded.
+    ///
+    /// The chunks are the same as chunks loaded from a save, so changes are tracked and sent to
+    /// players as usual.
+    #[must_use]
+    pub fn from_fn(bounds: ChunkBounds, f: impl Fn(IVec3) -> BlockState) -> Self {
+        let mut blocks = Self::from(ChunkLoaderHandle::detached());
+
+        for position in bounds.chunks() {
+            blocks
+                .chunk_cache
+                .insert(position, build_column(position, &f));
+        }
+
+        blocks
+    }
+
+    /// Creates a world of `state` below `height` and air above it within the
+    /// [default bounds](ChunkBounds::default). See [`Blocks::from_fn`].
+    #[must_use]
+    pub fn flat(height: i32, state: BlockState) -> Self {
+        Self::from_fn(ChunkBounds::default(), |position| {
+            if position.y < height {
+                state
+            } else {
+                BlockState::AIR
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use geometry::ray::Ray;
+    use glam::Vec3;
+
+    use super::*;
+
+    #[test]
+    fn blocks_are_built_from_the_function() {
+        let blocks = Blocks::from_fn(
+            ChunkBounds::new(I16Vec2::new(-1, 0), I16Vec2::new(0, 1)),
+            |position| {
+                if position == IVec3::new(-3, 100, 20) {
+                    BlockState::GOLD_BLOCK
+                } else if position.y < -60 {
+                    BlockState::STONE
+                } else {
+                    BlockState::AIR
+                }
+            },
+        );
+
+        assert_eq!(blocks.loaded_chunk_count(), 4);
+        assert_eq!(
+            blocks.get_block(IVec3::new(-3, 100, 20)),
+            Some(BlockState::GOLD_BLOCK)
+        );
+        assert_eq!(
+            blocks.get_block(IVec3::new(-16, -61, 31)),
+            Some(BlockState::STONE)
+        );
+        assert_eq!(
+            blocks.get_block(IVec3::new(15, -60, 0)),
+            Some(BlockState::AIR)
+        );
+
+        // Outside of the bounds
+        assert_eq!(blocks.get_block(IVec3::new(16, -61, 0)), None);
+        assert_eq!(blocks.get_block(IVec3::new(0, -61, -1)), None);
+    }
+
+    #[test]
+    fn rays_hit_the_ground() {
+        let blocks = Blocks::flat(0, BlockState::STONE);
+
+        let ray = Ray::new(Vec3::new(0.5, 10.0, 0.5), Vec3::NEG_Y);
+        let collision = blocks.first_collision(ray).unwrap();
+
+        assert_eq!(collision.location, IVec3::new(0, -1, 0));
+        assert_eq!(collision.block, BlockState::STONE);
+        assert!((collision.distance - 10.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn changes_are_tracked() {
+        let mut blocks = Blocks::flat(0, BlockState::STONE);
+
+        let old = blocks.set_block(IVec3::new(1, 0, 1), BlockState::STONE);
+        assert_eq!(old.unwrap(), BlockState::AIR);
+        let old = blocks.set_block(IVec3::new(-20, -1, 1), BlockState::STONE);
+        assert_eq!(old.unwrap(), BlockState::STONE);
+
+        // Only the chunk in which a block changed is sent again
+        let mut updated = Vec::new();
+        blocks.for_each_to_update(|column| updated.push(column.position));
+        assert_eq!(updated, [glam::IVec2::ZERO]);
+    }
+}
@@ -1,8 +1,16 @@
-use std::{borrow::Cow, cell::RefCell, io::Write, sync::Arc};
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    io::Write,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+};
 
-use anyhow::{Context, bail};
-use bytes::BytesMut;
-use glam::{I16Vec2, IVec2};
+use anyhow::Context;
+use bytes::{Bytes, BytesMut};
+use glam::{I16Vec2, IVec2, UVec3};
 use itertools::Itertools;
 use libdeflater::{CompressionLvl, Compressor};
 use parse::ColumnData;
@@ -19,12 +27,20 @@ use valence_server::layer::chunk::{BiomeContainer, Chunk, bit_width};
 
 pub mod parse;
 
-use super::{chunk::Column, shared::WorldShared};
+use super::{
+    CorruptChunkFallback,
+    block_entity::BlockEntities,
+    chunk::Column,
+    generator::{ErrorPatternGenerator, WorldGenerator},
+    region::ChunkLoadError,
+    saved_entity::{SavedEntity, parse_entities},
+    shared::WorldShared,
+};
 use crate::{
     CHUNK_HEIGHT_SPAN, Scratch,
     net::encoder::PacketEncoder,
     runtime::AsyncRuntime,
-    simulation::{blocks::loader::parse::section::Section, util::heightmap},
+    simulation::{blocks::loader::parse::section::Section, util::heightmap_from_fn},
     storage::BitStorage,
 };
 
@@ -57,37 +73,71 @@ struct ChunkLoader {
     rx_load_chunk_requests: tokio::sync::mpsc::UnboundedReceiver<Message>,
     received_request: FxHashSet<I16Vec2>,
     shared: Arc<WorldShared>,
+    generator: Arc<dyn WorldGenerator>,
+    fallback: CorruptChunkFallback,
+    corrupt_chunks: Arc<AtomicU64>,
     runtime: AsyncRuntime,
 }
 
 pub struct ChunkLoaderHandle {
-    tx_load_chunk_requests: tokio::sync::mpsc::UnboundedSender<Message>,
+    /// `None` if there is no loader, in which case requested chunks are never loaded
+    tx_load_chunk_requests: Option<tokio::sync::mpsc::UnboundedSender<Message>>,
+    /// The number of corrupt chunks which the loader replaced by the [`CorruptChunkFallback`]
+    corrupt_chunks: Arc<AtomicU64>,
 }
 
 impl ChunkLoaderHandle {
-    pub const fn new(tx_load_chunk_requests: tokio::sync::mpsc::UnboundedSender<Message>) -> Self {
+    pub fn new(tx_load_chunk_requests: tokio::sync::mpsc::UnboundedSender<Message>) -> Self {
         Self {
-            tx_load_chunk_requests,
+            tx_load_chunk_requests: Some(tx_load_chunk_requests),
+            corrupt_chunks: Arc::default(),
         }
     }
 
+    /// A handle without a loader, for worlds which only consist of the chunks inserted into them
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn detached() -> Self {
+        Self {
+            tx_load_chunk_requests: None,
+            corrupt_chunks: Arc::default(),
+        }
+    }
+
+    pub fn corrupt_chunks(&self) -> u64 {
+        self.corrupt_chunks.load(Ordering::Relaxed)
+    }
+
     pub fn send(&self, position: I16Vec2, tx: tokio::sync::mpsc::UnboundedSender<Column>) {
-        self.tx_load_chunk_requests
+        let Some(tx_load_chunk_requests) = &self.tx_load_chunk_requests else {
+            return;
+        };
+
+        tx_load_chunk_requests
             .send(Message { position, tx })
             .unwrap();
     }
 }
 
-pub fn launch_loader(shared: Arc<WorldShared>, runtime: &AsyncRuntime) -> ChunkLoaderHandle {
+pub fn launch_loader(
+    shared: Arc<WorldShared>,
+    generator: Arc<dyn WorldGenerator>,
+    fallback: CorruptChunkFallback,
+    runtime: &AsyncRuntime,
+) -> ChunkLoaderHandle {
     let (tx_load_chunk_requests, rx_load_chunk_requests) = tokio::sync::mpsc::unbounded_channel();
+    let handle = ChunkLoaderHandle::new(tx_load_chunk_requests);
 
     runtime.spawn({
         let runtime = runtime.clone();
+        let corrupt_chunks = handle.corrupt_chunks.clone();
         async move {
             ChunkLoader {
                 rx_load_chunk_requests,
                 received_request: FxHashSet::default(),
                 shared,
+                generator,
+                fallback,
+                corrupt_chunks,
                 runtime,
             }
             .run()
@@ -95,9 +145,7 @@ pub fn launch_loader(shared: Arc<WorldShared>, runtime: &AsyncRuntime) -> ChunkL
         }
     });
 
-    ChunkLoaderHandle {
-        tx_load_chunk_requests,
-    }
+    handle
 }
 
 pub fn launch_empty_loader(runtime: &AsyncRuntime) -> ChunkLoaderHandle {
@@ -114,6 +162,37 @@ pub fn launch_empty_loader(runtime: &AsyncRuntime) -> ChunkLoaderHandle {
     ChunkLoaderHandle::new(tx_loaded_chunks)
 }
 
+pub fn launch_generator_loader(
+    generator: Arc<dyn WorldGenerator>,
+    runtime: &AsyncRuntime,
+) -> ChunkLoaderHandle {
+    let (tx_loaded_chunks, mut rx_loaded_chunks) =
+        tokio::sync::mpsc::unbounded_channel::<Message>();
+
+    runtime.spawn(async move {
+        let mut received_request = FxHashSet::default();
+        while let Some(msg) = rx_loaded_chunks.recv().await {
+            if received_request.insert(msg.position) {
+                spawn_generate_column(msg.position, generator.clone(), msg.tx);
+            }
+        }
+    });
+
+    ChunkLoaderHandle::new(tx_loaded_chunks)
+}
+
+/// Generates a column on the rayon thread pool and sends it to `tx` once it is done.
+fn spawn_generate_column(
+    position: I16Vec2,
+    generator: Arc<dyn WorldGenerator>,
+    tx: tokio::sync::mpsc::UnboundedSender<Column>,
+) {
+    rayon::spawn(move || {
+        let column = generate_column(position, &*generator);
+        tx.send(column).unwrap();
+    });
+}
+
 impl ChunkLoader {
     async fn run(mut self) {
         while let Some(message) = self.rx_load_chunk_requests.recv().await {
@@ -133,10 +212,17 @@ impl ChunkLoader {
 
         let tx_load_chunks = message.tx;
         let shared = self.shared.clone();
+        let generator = self.generator.clone();
+        let fallback = self.fallback;
+        let corrupt_chunks = self.corrupt_chunks.clone();
 
         self.runtime.spawn(async move {
             let loaded_chunk = match load_chunk(position, &shared).await {
-                Ok(loaded_chunk) => {
+                Ok(None) => {
+                    spawn_generate_column(position, generator, tx_load_chunks);
+                    return;
+                }
+                Ok(Some(loaded_chunk)) => {
                     let chunk_height = loaded_chunk.data.height();
                     if chunk_height == CHUNK_HEIGHT_SPAN {
                         loaded_chunk
@@ -151,8 +237,9 @@ impl ChunkLoader {
                     }
                 }
                 Err(err) => {
-                    warn!("failed to load chunk {position:?}: {err}");
-                    empty_column(position)
+                    warn!("chunk {position} is corrupt, loading {fallback:?} instead: {err}");
+                    corrupt_chunks.fetch_add(1, Ordering::Relaxed);
+                    fallback_column(position, fallback)
                 }
             };
 
@@ -171,6 +258,25 @@ impl ChunkLoader {
     }
 }
 
+pub(super) fn generate_column(position: I16Vec2, generator: &dyn WorldGenerator) -> Column {
+    let sections = (0..CHUNK_HEIGHT_SPAN / 16)
+        .map(|section_y| generator.generate_section(position, section_y))
+        .collect();
+
+    let column = ColumnData {
+        sections,
+        block_entities: BlockEntities::default(),
+    };
+
+    let bytes = STATE.with_borrow_mut(|state| {
+        encode_chunk_packet(&column, position.as_ivec2(), state)
+            .unwrap()
+            .unwrap()
+    });
+
+    Column::new(bytes.freeze(), column, position.as_ivec2())
+}
+
 fn empty_column(position: I16Vec2) -> Column {
     // height: 24
     let unloaded = ColumnData::new_with(CHUNK_HEIGHT_SPAN, Section::empty_sky);
@@ -187,7 +293,19 @@ fn empty_column(position: I16Vec2) -> Column {
     Column::new(bytes.freeze(), unloaded, position)
 }
 
-async fn load_chunk(position: I16Vec2, shared: &WorldShared) -> anyhow::Result<Column> {
+/// The column which is loaded instead of a corrupt one
+fn fallback_column(position: I16Vec2, fallback: CorruptChunkFallback) -> Column {
+    match fallback {
+        CorruptChunkFallback::Air => empty_column(position),
+        CorruptChunkFallback::ErrorPattern => generate_column(position, &ErrorPatternGenerator),
+    }
+}
+
+/// Loads a column from the Anvil save. Returns `None` if the column is not in the save.
+async fn load_chunk(
+    position: I16Vec2,
+    shared: &WorldShared,
+) -> Result<Option<Column>, ChunkLoadError> {
     let x = position.x;
     let y = position.y;
 
@@ -195,37 +313,79 @@ async fn load_chunk(position: I16Vec2, shared: &WorldShared) -> anyhow::Result<C
     let mut decompress_buf = vec![0; 1024 * 1024];
 
     // https://rust-lang.github.io/rust-clippy/master/index.html#/large_futures
-    let Ok(region) = shared.regions.get_region_from_chunk(x, y).await else {
-        // most likely the file representing the region does not exist so we will just return en empty chunk
-        warn!("region file for {position} does not exist; returning empty chunk");
-        return Ok(empty_column(position));
+    let region = match shared.regions.get_region_from_chunk(x, y).await {
+        Ok(region) => region,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            trace!("region file for {position} does not exist; generating chunk");
+            return Ok(None);
+        }
+        Err(err) => return Err(err.into()),
     };
 
     let raw_chunk = {
         // todo: note that this is likely blocking to tokio
         let x = i32::from(x);
         let y = i32::from(y);
-        region
-            .get_chunk(x, y, &mut decompress_buf, shared.regions.root())?
-            .context("no chunk found")?
+        let Some(raw_chunk) = region.get_chunk(x, y, &mut decompress_buf, shared.regions.root())?
+        else {
+            return Ok(None);
+        };
+        raw_chunk
     };
 
-    let chunk = match parse::parse_chunk(raw_chunk.data, &shared.biome_to_id) {
-        Ok(chunk) => chunk,
+    let chunk = parse::parse_chunk(raw_chunk.data, &shared.biome_to_id)?;
+
+    let entities = match load_entities(position, shared, &mut decompress_buf).await {
+        Ok(entities) => entities,
         Err(err) => {
-            bail!("failed to parse chunk {position}: {err}");
+            warn!("failed to load the entities of chunk {position}: {err}");
+            Vec::new()
         }
     };
 
     STATE.with_borrow_mut(|state| {
         let position = position.as_ivec2();
-        let Ok(Some(bytes)) = encode_chunk_packet(&chunk, position, state) else {
-            bail!("failed to encode chunk {position:?}");
-        };
+        let bytes = encode_chunk_packet(&chunk, position, state)
+            .map_err(|err| ChunkLoadError::Encode(err.into()))?
+            .ok_or_else(|| ChunkLoadError::Encode("no packet was encoded".into()))?;
 
-        let loaded_chunk = Column::new(bytes.freeze(), chunk, position);
+        let mut loaded_chunk = Column::new(bytes.freeze(), chunk, position);
+        loaded_chunk.entities = entities;
 
-        Ok(loaded_chunk)
+        Ok(Some(loaded_chunk))
+    })
+}
+
+/// Loads the entities of a column from the `entities` region files of the save, which are empty
+/// if the save has no entities directory or the column has no entities.
+async fn load_entities(
+    position: I16Vec2,
+    shared: &WorldShared,
+    decompress_buf: &mut Vec<u8>,
+) -> Result<Vec<SavedEntity>, ChunkLoadError> {
+    let Some(regions) = &shared.entities else {
+        return Ok(Vec::new());
+    };
+
+    let Ok(region) = regions.get_region_from_chunk(position.x, position.y).await else {
+        return Ok(Vec::new());
+    };
+
+    let x = i32::from(position.x);
+    let z = i32::from(position.y);
+    let Some(raw_chunk) = region.get_chunk(x, z, decompress_buf, regions.root())? else {
+        return Ok(Vec::new());
+    };
+
+    Ok(parse_entities(raw_chunk.data))
+}
+
+/// Encodes the chunk data packet of `chunk` with the encoder state of the current thread
+pub(super) fn encode_column(chunk: &ColumnData, position: IVec2) -> anyhow::Result<Bytes> {
+    STATE.with_borrow_mut(|state| {
+        let bytes = encode_chunk_packet(chunk, position, state)?
+            .with_context(|| format!("failed to encode chunk {position:?}"))?;
+        Ok(bytes.freeze())
     })
 }
 
@@ -239,7 +399,7 @@ fn encode_chunk_packet(
     let section_count = CHUNK_HEIGHT_SPAN as usize / 16_usize;
     let dimension_height = CHUNK_HEIGHT_SPAN;
 
-    let map = heightmap(dimension_height, dimension_height - 3);
+    let map = heightmap_from_fn(dimension_height, |x, z| motion_blocking_height(chunk, x, z));
     let map = map.into_iter().map(i64::try_from).try_collect()?;
 
     // convert section_count + 2 0b1s into `u64` array
@@ -304,16 +464,14 @@ fn encode_chunk_packet(
     let block_entities = chunk
         .block_entities
         .iter()
-        .map(|(idx, data)| {
+        .filter_map(|(position, block_entity)| {
             pub const START_Y: i16 = -64;
 
-            // Decode the coordinates relative to the chunk
-            let x = idx % 16;
-            let y = idx / 16 / 16;
-            let z = (idx / 16) % 16;
+            let UVec3 { x, y, z } = position;
 
-            // Get the block entity kind
-            let kind = chunk.block_state(x, y, z).block_entity_kind().unwrap();
+            // Block entities whose block has been replaced are not sent, since the client could
+            // not tell which kind they are
+            let kind = chunk.block_state(x, y, z).block_entity_kind()?;
 
             let absolute_y = i16::try_from(y).unwrap() + START_Y;
 
@@ -321,12 +479,12 @@ fn encode_chunk_packet(
             #[expect(clippy::cast_possible_truncation, reason = "this cannot truncate")]
             let packed_xz = ((x * 16) | z) as i8;
 
-            ChunkDataBlockEntity {
+            Some(ChunkDataBlockEntity {
                 packed_xz,
                 y: absolute_y,
                 kind,
-                data: Cow::Borrowed(data),
-            }
+                data: Cow::Owned(block_entity.to_nbt()),
+            })
         })
         .collect::<Vec<_>>();
 
@@ -359,6 +517,29 @@ fn encode_chunk_packet(
     Ok(Some(result))
 }
 
+/// Returns one more than the y of the highest non-air block in the column, or `0` if the column
+/// only contains air.
+fn motion_blocking_height(chunk: &ColumnData, x: u32, z: u32) -> u32 {
+    let air = BlockState::AIR.to_raw();
+    let column_idx = (x + z * 16) as usize;
+
+    for (section_y, section) in chunk.sections.iter().enumerate().rev() {
+        if let hyperion_palette::PalettedContainer::Single(block) = section.block_states
+            && block == air
+        {
+            continue;
+        }
+
+        for y in (0..16).rev() {
+            if section.block_states.get(column_idx + y * 256) != air {
+                return u32::try_from(section_y * 16 + y + 1).unwrap();
+            }
+        }
+    }
+
+    0
+}
+
 fn write_block_states(
     states: &hyperion_palette::PalettedContainer,
     writer: &mut impl Write,
@@ -2,7 +2,6 @@ use std::collections::BTreeMap;
 
 use thiserror::Error;
 use tracing::warn;
-use valence_anvil::RegionError;
 use valence_bytes::Utf8Bytes;
 use valence_generated::block::{BlockKind, BlockState, PropName, PropValue};
 use valence_nbt::{Compound, List, Value};
@@ -10,13 +9,14 @@ use valence_protocol::Ident;
 use valence_registry::biome::BiomeId;
 use valence_server::layer::chunk::{Chunk, check_biome_oob, check_block_oob, check_section_oob};
 
-use crate::simulation::blocks::loader::parse::section::Section;
+use crate::simulation::blocks::{
+    block_entity::{BlockEntities, BlockEntity, BlockEntityData},
+    loader::parse::section::Section,
+};
 
 #[derive(Debug, Error)]
 #[non_exhaustive]
 pub enum ParseChunkError {
-    #[error("region error: {0}")]
-    Region(#[from] RegionError),
     #[error("missing chunk sections")]
     MissingSections,
     #[error("missing chunk section Y")]
@@ -82,21 +82,21 @@ pub mod section;
 #[derive(Clone, Default, Debug)]
 pub struct ColumnData {
     pub sections: Vec<Section>,
-    pub block_entities: BTreeMap<u32, Compound>,
+    pub block_entities: BlockEntities,
 }
 
 impl ColumnData {
     pub fn new(height: u32) -> Self {
         Self {
             sections: vec![Section::default(); height as usize / 16],
-            block_entities: BTreeMap::new(),
+            block_entities: BlockEntities::default(),
         }
     }
 
     pub fn new_with(height: u32, f: impl Fn() -> Section) -> Self {
         Self {
             sections: vec![f(); height as usize / 16],
-            block_entities: BTreeMap::new(),
+            block_entities: BlockEntities::default(),
         }
     }
 
@@ -153,20 +153,29 @@ impl Chunk for ColumnData {
             .fill(block.to_raw());
     }
 
+    /// Returns the block entity if it is [`BlockEntityData::Opaque`]. Use
+    /// [`ColumnData::block_entities`] to access the typed block entities.
     fn block_entity(&self, x: u32, y: u32, z: u32) -> Option<&Compound> {
         check_block_oob(self, x, y, z);
 
-        let idx = x + z * 16 + y * 16 * 16;
-        self.block_entities.get(&idx)
+        match &self.block_entities.get(x, y, z)?.data {
+            BlockEntityData::Opaque(nbt) => Some(nbt),
+            _ => None,
+        }
     }
 
+    /// Returns the block entity if it is [`BlockEntityData::Opaque`]. Use
+    /// [`ColumnData::block_entities`] to access the typed block entities.
     fn block_entity_mut(&mut self, x: u32, y: u32, z: u32) -> Option<&mut Compound> {
         check_block_oob(self, x, y, z);
 
-        let idx = x + z * 16 + y * 16 * 16;
-        self.block_entities.get_mut(&idx)
+        match &mut self.block_entities.get_mut(x, y, z)?.data {
+            BlockEntityData::Opaque(nbt) => Some(nbt),
+            _ => None,
+        }
     }
 
+    /// Sets the block entity from its NBT, which should contain its `id`
     fn set_block_entity(
         &mut self,
         x: u32,
@@ -176,12 +185,19 @@ impl Chunk for ColumnData {
     ) -> Option<Compound> {
         check_block_oob(self, x, y, z);
 
-        let idx = x + z * 16 + y * 16 * 16;
+        let old = match block_entity {
+            Some(mut nbt) => {
+                let id = match nbt.remove("id") {
+                    Some(Value::String(id)) => id,
+                    _ => String::new(),
+                };
+                self.block_entities
+                    .insert(x, y, z, BlockEntity::from_nbt(id, nbt))
+            }
+            None => self.block_entities.remove(x, y, z),
+        };
 
-        match block_entity {
-            Some(be) => self.block_entities.insert(idx, be),
-            None => self.block_entities.remove(&idx),
-        }
+        old.map(|old| old.to_nbt())
     }
 
     fn clear_block_entities(&mut self) {
@@ -468,7 +484,7 @@ pub fn parse_chunk(
                 return Err(ParseChunkError::MissingBlockEntityIdent);
             };
 
-            if let Err(e) = Ident::new(ident) {
+            if let Err(e) = Ident::new(ident.clone()) {
                 return Err(ParseChunkError::InvalidBlockEntityName(e.0));
             }
 
@@ -498,7 +514,9 @@ pub fn parse_chunk(
 
             comp.remove("keepPacked");
 
-            chunk.set_block_entity(x, y, z, Some(comp));
+            chunk
+                .block_entities
+                .insert(x, y, z, BlockEntity::from_nbt(ident, comp));
         }
     }
 
@@ -34,11 +34,22 @@ impl RegionManager {
 
         ensure!(root.exists(), "{} directory does not exist", root.display());
 
+        Ok(Self::spawn(runtime, root))
+    }
+
+    /// Manages the region files in the `entities` directory of the save, which only exists in
+    /// saves from 1.17 onwards
+    pub fn entities(runtime: &Runtime, save: &Path) -> Option<Self> {
+        let root = save.join("entities");
+        root.exists().then(|| Self::spawn(runtime, root))
+    }
+
+    fn spawn(runtime: &Runtime, root: PathBuf) -> Self {
         let (sender, receiver) = mpsc::channel(100);
 
         runtime.spawn(RegionManagerTask::new(root.clone(), receiver).run());
 
-        Ok(Self { root, sender })
+        Self { root, sender }
     }
 
     #[allow(clippy::missing_const_for_fn, reason = "this is a false positive")]
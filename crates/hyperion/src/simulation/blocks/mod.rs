@@ -4,37 +4,62 @@ use std::{future::Future, ops::Try, path::Path, pin::Pin, sync::Arc};
 
 use anyhow::Context;
 use bevy_ecs::{entity::Entity, resource::Resource};
+use block_entity::BlockEntity;
 use bytes::Bytes;
 use chunk::Column;
-use geometry::{aabb::Aabb, ray::Ray};
+use geometry::ray::Ray;
 use glam::{I16Vec2, IVec2, IVec3, Vec3};
 use indexmap::IndexMap;
 use loader::{ChunkLoaderHandle, launch_loader};
+use packet_cache::{ChunkPacketCache, ChunkPacketCacheStats};
 use rayon::iter::ParallelIterator;
 use roaring::RoaringBitmap;
-use rustc_hash::FxBuildHasher;
+use rustc_hash::{FxBuildHasher, FxHashSet};
+use saved_entity::SavedEntity;
+use serde::{Deserialize, Serialize};
 use shared::WorldShared;
 use tracing::error;
 use valence_generated::block::BlockState;
 use valence_server::layer::chunk::Chunk;
+#[cfg(feature = "reflect")]
+use {bevy_ecs::reflect::ReflectResource, bevy_reflect::Reflect};
 
 use crate::{
     CHUNK_HEIGHT_SPAN,
     runtime::AsyncRuntime,
     simulation::{
-        blocks::loader::{launch_empty_loader, parse::section::Section},
+        blocks::{
+            generator::{VoidGenerator, WorldGenerator},
+            loader::{launch_empty_loader, launch_generator_loader},
+        },
         util::generate_biome_registry,
     },
 };
 
+pub mod block_entity;
 pub mod chunk;
+pub mod collision;
+pub mod fake;
+pub mod generator;
+#[cfg(any(test, feature = "test-util"))]
+mod in_memory;
 
 mod loader;
 mod manager;
 
 pub mod frame;
+pub mod packet_cache;
 mod region;
+pub mod saved_entity;
+pub mod scheduled;
+pub mod schematic;
 mod shared;
+pub mod snapshot;
+
+#[cfg(any(test, feature = "test-util"))]
+pub use in_memory::ChunkBounds;
+pub use loader::parse::section::Section;
+pub use region::ChunkLoadError;
 
 pub enum GetChunk<'a> {
     Loaded(&'a Column),
@@ -60,6 +85,28 @@ pub enum TrySetBlockDeltaError {
     ChunkNotLoaded,
 }
 
+/// What is loaded instead of chunks of an Anvil save which are corrupt, see [`ChunkLoadError`]
+#[derive(
+    Resource,
+    Serialize,
+    Deserialize,
+    Copy,
+    Clone,
+    Debug,
+    Default,
+    PartialEq,
+    Eq
+)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Resource))]
+pub enum CorruptChunkFallback {
+    /// An empty chunk
+    #[default]
+    Air,
+    /// The pattern of the [`ErrorPatternGenerator`](generator::ErrorPatternGenerator), so that
+    /// map makers notice the chunk
+    ErrorPattern,
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct RayCollision {
     pub distance: f32,
@@ -75,11 +122,19 @@ pub struct Blocks {
     /// Map to a Chunk by Entity ID
     chunk_cache: IndexMap<I16Vec2, Column, FxBuildHasher>,
     should_update: RoaringBitmap,
+    /// Chunks which are sent to players again as a whole. See [`Blocks::resend_chunk`].
+    resent_chunks: FxHashSet<I16Vec2>,
 
     loader_handle: ChunkLoaderHandle,
 
     tx_loaded_chunks: tokio::sync::mpsc::UnboundedSender<Column>,
     rx_loaded_chunks: tokio::sync::mpsc::UnboundedReceiver<Column>,
+    packet_cache: ChunkPacketCache,
+    /// Entities of loaded chunks which have not been spawned yet
+    loaded_entities: Vec<SavedEntity>,
+    /// Sequences which are acknowledged together with the
+    /// [`ConfirmBlockSequences`](crate::simulation::ConfirmBlockSequences) of each player. The
+    /// interaction handlers already acknowledge every sequence, so this is rarely needed.
     pub to_confirm: Vec<EntityAndSequence>,
 }
 
@@ -89,9 +144,12 @@ impl From<ChunkLoaderHandle> for Blocks {
         Self {
             chunk_cache: IndexMap::default(),
             should_update: RoaringBitmap::default(),
+            resent_chunks: FxHashSet::default(),
             loader_handle,
             tx_loaded_chunks,
             rx_loaded_chunks,
+            packet_cache: ChunkPacketCache::default(),
+            loaded_entities: Vec::new(),
             to_confirm: vec![],
         }
     }
@@ -99,13 +157,33 @@ impl From<ChunkLoaderHandle> for Blocks {
 
 impl Blocks {
     pub fn new(runtime: &AsyncRuntime, path: &Path) -> anyhow::Result<Self> {
+        Self::with_generator(runtime, path, VoidGenerator)
+    }
+
+    /// Loads chunks from the Anvil save at `path`, using `generator` for chunks which are not in
+    /// the save.
+    pub fn with_generator(
+        runtime: &AsyncRuntime,
+        path: &Path,
+        generator: impl WorldGenerator,
+    ) -> anyhow::Result<Self> {
+        Self::with_fallback(runtime, path, generator, CorruptChunkFallback::default())
+    }
+
+    /// Like [`Blocks::with_generator`], but loads `fallback` instead of chunks which are corrupt
+    pub fn with_fallback(
+        runtime: &AsyncRuntime,
+        path: &Path,
+        generator: impl WorldGenerator,
+        fallback: CorruptChunkFallback,
+    ) -> anyhow::Result<Self> {
         let biome_registry =
             generate_biome_registry().context("failed to generate biome registry")?;
 
         let shared = WorldShared::new(&biome_registry, runtime, path)?;
         let shared = Arc::new(shared);
 
-        let loader_handle = launch_loader(shared, runtime);
+        let loader_handle = launch_loader(shared, Arc::new(generator), fallback, runtime);
 
         let result = Self::from(loader_handle);
 
@@ -118,6 +196,19 @@ impl Blocks {
         Self::from(loader_handle)
     }
 
+    /// Generates every chunk on demand without an Anvil save.
+    #[must_use]
+    pub fn generated(runtime: &AsyncRuntime, generator: impl WorldGenerator) -> Self {
+        let loader_handle = launch_generator_loader(Arc::new(generator), runtime);
+        Self::from(loader_handle)
+    }
+
+    /// The number of chunks currently loaded in memory
+    #[must_use]
+    pub fn loaded_chunk_count(&self) -> usize {
+        self.chunk_cache.len()
+    }
+
     #[must_use]
     pub fn first_collision(&self, ray: Ray) -> Option<RayCollision> {
         // Define bounds for the voxel traversal
@@ -130,10 +221,9 @@ impl Blocks {
                 let origin = cell.as_vec3();
 
                 // Check collision with block shapes
-                let collision = block
-                    .collision_shapes()
-                    .map(|shape| Aabb::new(shape.min().as_vec3(), shape.max().as_vec3()))
-                    .map(|shape| shape + origin)
+                let collision = collision::shapes_for(block)
+                    .iter()
+                    .map(|&shape| shape + origin)
                     .filter_map(|shape| shape.intersect_ray(&ray))
                     .min();
 
@@ -196,6 +286,24 @@ impl Blocks {
         self.should_update.clear();
     }
 
+    /// Sends the whole chunk again to the players who can see it, instead of sending the changes
+    /// made to it. This is used after changing blocks with [`Section::set`], which does not
+    /// record the changes, such as when restoring a large [`snapshot::RegionSnapshot`].
+    pub fn resend_chunk(&mut self, position: I16Vec2) {
+        let Some(column) = self.chunk_cache.get_mut(&position) else {
+            return;
+        };
+
+        // Packets encoded before the blocks were changed are out of date
+        column.mark_dirty();
+        self.resent_chunks.insert(position);
+    }
+
+    /// Returns the chunks passed to [`Blocks::resend_chunk`] since the last call
+    pub fn take_resent_chunks(&mut self) -> FxHashSet<I16Vec2> {
+        std::mem::take(&mut self.resent_chunks)
+    }
+
     pub const fn cache_mut(&mut self) -> &mut IndexMap<I16Vec2, Column, FxBuildHasher> {
         &mut self.chunk_cache
     }
@@ -238,14 +346,50 @@ impl Blocks {
     }
 
     pub fn load_pending(&mut self) {
-        while let Ok(chunk) = self.rx_loaded_chunks.try_recv() {
+        while let Ok(mut chunk) = self.rx_loaded_chunks.try_recv() {
             let position = chunk.position;
             let position = position.as_i16vec2();
 
+            self.loaded_entities.append(&mut chunk.entities);
             self.chunk_cache.insert(position, chunk);
         }
     }
 
+    /// Makes the chunk packets which finished encoding available to [`Self::chunk_packet`]
+    pub fn receive_encoded_packets(&mut self) {
+        self.packet_cache.receive_encoded();
+    }
+
+    /// Returns the saved entities of the chunks loaded since the last call, which should be
+    /// spawned with [`SavedEntity::spawn`]
+    pub fn take_loaded_entities(&mut self) -> Vec<SavedEntity> {
+        std::mem::take(&mut self.loaded_entities)
+    }
+
+    /// Returns the up to date chunk data packet of `column`, or `None` while it is being encoded.
+    /// See [`ChunkPacketCache`].
+    #[must_use]
+    pub fn chunk_packet(&self, column: &Column) -> Option<Bytes> {
+        self.packet_cache.get(column)
+    }
+
+    #[must_use]
+    pub fn packet_cache_stats(&self) -> ChunkPacketCacheStats {
+        self.packet_cache.stats()
+    }
+
+    /// The number of chunks of the Anvil save which were replaced by the [`CorruptChunkFallback`]
+    /// because they could not be loaded
+    #[must_use]
+    pub fn corrupt_chunk_count(&self) -> u64 {
+        self.loader_handle.corrupt_chunks()
+    }
+
+    /// Replaces the cache of chunk data packets, such as to change its capacity
+    pub fn set_packet_cache(&mut self, packet_cache: ChunkPacketCache) {
+        self.packet_cache = packet_cache;
+    }
+
     /// Returns the unloaded chunk if it is loaded, otherwise `None`.
     // todo: return type: what do you think about the type right here?
     // This seems really complicated.
@@ -390,7 +534,81 @@ impl Blocks {
         Some(chunk.block_state(x, y, z))
     }
 
-    /// Returns the old block state
+    /// Returns the block entity at `position`, or `None` if there is none or its chunk is not
+    /// loaded
+    #[must_use]
+    pub fn block_entity(&self, position: IVec3) -> Option<&BlockEntity> {
+        let (chunk_pos, x, y, z) = Self::chunk_local(position)?;
+        let chunk = self.get_loaded_chunk(chunk_pos)?;
+        chunk.data.block_entities.get(x, y, z)
+    }
+
+    /// Sets or removes the block entity at `position` and sends its chunk to players again.
+    /// Returns the previous block entity.
+    pub fn set_block_entity(
+        &mut self,
+        position: IVec3,
+        block_entity: Option<BlockEntity>,
+    ) -> Result<Option<BlockEntity>, TrySetBlockDeltaError> {
+        let (chunk_pos, x, y, z) =
+            Self::chunk_local(position).ok_or(TrySetBlockDeltaError::OutOfBounds)?;
+
+        let chunk = self
+            .get_loaded_chunk_mut(chunk_pos)
+            .ok_or(TrySetBlockDeltaError::ChunkNotLoaded)?;
+
+        let block_entities = &mut chunk.data_mut().block_entities;
+        let old = match block_entity {
+            Some(block_entity) => block_entities.insert(x, y, z, block_entity),
+            None => block_entities.remove(x, y, z),
+        };
+
+        self.resend_chunk(chunk_pos);
+
+        Ok(old)
+    }
+
+    /// Returns the block entity at `position`, first inserting the one returned by `default` if
+    /// there is none.
+    ///
+    /// Unlike [`Blocks::set_block_entity`], this does not send the chunk again, so changes have to
+    /// be sent to the players who can see it, such as with a `BlockEntityUpdateS2c` packet.
+    pub fn block_entity_or_insert_with(
+        &mut self,
+        position: IVec3,
+        default: impl FnOnce() -> BlockEntity,
+    ) -> Result<&mut BlockEntity, TrySetBlockDeltaError> {
+        let (chunk_pos, x, y, z) =
+            Self::chunk_local(position).ok_or(TrySetBlockDeltaError::OutOfBounds)?;
+
+        let chunk = self
+            .get_loaded_chunk_mut(chunk_pos)
+            .ok_or(TrySetBlockDeltaError::ChunkNotLoaded)?;
+
+        // Packets encoded before the block entity is changed are out of date
+        chunk.mark_dirty();
+
+        Ok(chunk
+            .data_mut()
+            .block_entities
+            .get_or_insert_with(x, y, z, default))
+    }
+
+    /// Splits `position` into the position of its chunk and its position relative to the chunk,
+    /// or returns `None` if it is outside of the height of the world
+    fn chunk_local(position: IVec3) -> Option<(I16Vec2, u32, u32, u32)> {
+        const START_Y: i32 = -64;
+
+        let chunk_pos = IVec2::new(position.x, position.z) >> 4;
+        let x = (position.x & 15).cast_unsigned();
+        let y = u32::try_from(position.y - START_Y).ok()?;
+        let z = (position.z & 15).cast_unsigned();
+
+        (y < CHUNK_HEIGHT_SPAN).then_some((chunk_pos.as_i16vec2(), x, y, z))
+    }
+
+    /// Returns the old block state. The block entity at `position` is removed if the kind of block
+    /// changes, like the block entity of a sign which is broken.
     pub fn set_block(
         &mut self,
         position: IVec3,
@@ -417,7 +635,12 @@ impl Blocks {
         let y = u32::try_from(position.y - START_Y).unwrap();
         let z = u32::try_from(position.z - chunk_start_block[1]).unwrap();
 
-        let old_state = chunk.data.set_delta(x, y, z, state);
+        let data = chunk.data_mut();
+        let old_state = data.set_delta(x, y, z, state);
+
+        if old_state.to_kind() != state.to_kind() {
+            data.block_entities.remove(x, y, z);
+        }
 
         if old_state != state {
             self.should_update.insert(u32::try_from(chunk_idx).unwrap());
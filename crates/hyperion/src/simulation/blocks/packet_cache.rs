@@ -0,0 +1,364 @@
+//! Caching of the chunk data packets of modified chunks. See [`ChunkPacketCache`].
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use bytes::Bytes;
+use glam::I16Vec2;
+use rustc_hash::FxHashMap;
+use tracing::{error, warn};
+
+use super::{chunk::Column, loader::encode_column};
+
+/// The default number of bytes of encoded packets a [`ChunkPacketCache`] keeps
+pub const DEFAULT_CHUNK_PACKET_CACHE_BYTES: usize = 64 * 1024 * 1024;
+
+/// Counters of a [`ChunkPacketCache`]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChunkPacketCacheStats {
+    /// Lookups which were answered with an up to date packet
+    pub hits: u64,
+    /// Lookups which had to encode the chunk again
+    pub misses: u64,
+    /// The number of bytes of the cached packets
+    pub bytes: usize,
+}
+
+impl ChunkPacketCacheStats {
+    /// The fraction of lookups which were hits, or `1.0` if there were none
+    #[must_use]
+    #[expect(
+        clippy::cast_precision_loss,
+        reason = "both counters stay far below 2^52"
+    )]
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            return 1.0;
+        }
+
+        self.hits as f64 / lookups as f64
+    }
+
+    /// Adds the counters of `other` to these
+    pub const fn add(&mut self, other: Self) {
+        self.hits += other.hits;
+        self.misses += other.misses;
+        self.bytes += other.bytes;
+    }
+}
+
+struct Entry {
+    epoch: u64,
+    bytes: Bytes,
+    last_used: u64,
+}
+
+/// A packet encoded on the rayon thread pool
+struct Encoded {
+    position: I16Vec2,
+    epoch: u64,
+    result: anyhow::Result<Bytes>,
+}
+
+#[derive(Default)]
+struct State {
+    entries: FxHashMap<I16Vec2, Entry>,
+    /// The cached chunks by when they were last used, which is unique for each entry
+    by_last_used: BTreeMap<u64, I16Vec2>,
+    /// The epoch each chunk is being encoded at
+    encoding: FxHashMap<I16Vec2, u64>,
+    /// The epoch each chunk failed to be encoded at. These are not encoded again until the chunk
+    /// is changed.
+    failed: FxHashMap<I16Vec2, u64>,
+    bytes: usize,
+    /// Incremented on every lookup to order entries by when they were last used
+    clock: u64,
+}
+
+impl State {
+    /// Marks the entry of `position` as the most recently used one
+    fn touch(&mut self, position: I16Vec2) {
+        self.clock += 1;
+        let now = self.clock;
+
+        let Some(entry) = self.entries.get_mut(&position) else {
+            return;
+        };
+        self.by_last_used.remove(&entry.last_used);
+        entry.last_used = now;
+        self.by_last_used.insert(now, position);
+    }
+
+    fn insert(&mut self, position: I16Vec2, epoch: u64, bytes: Bytes) {
+        self.clock += 1;
+        self.bytes += bytes.len();
+        let entry = Entry {
+            epoch,
+            bytes,
+            last_used: self.clock,
+        };
+        self.by_last_used.insert(self.clock, position);
+
+        if let Some(old) = self.entries.insert(position, entry) {
+            self.by_last_used.remove(&old.last_used);
+            self.bytes -= old.bytes.len();
+        }
+    }
+
+    /// Evicts the least recently used entries until at most `capacity` bytes are cached
+    fn evict(&mut self, capacity: usize) {
+        while self.bytes > capacity {
+            let Some((_, oldest)) = self.by_last_used.pop_first() else {
+                return;
+            };
+
+            let entry = self.entries.remove(&oldest).unwrap();
+            self.bytes -= entry.bytes.len();
+        }
+    }
+}
+
+/// The chunk data packets of chunks which were modified since they were loaded.
+///
+/// Encoding a chunk is expensive, but the packet is the same for every player, so each chunk is
+/// encoded at most once per [`Column::epoch`]. Unmodified chunks are sent with the packet encoded
+/// when they were loaded, so only modified chunks take up space in the cache. The least recently
+/// used packets are evicted once the cache holds more than its capacity.
+///
+/// Chunks which fail to be encoded are not encoded again until they are changed, and are not sent
+/// until then.
+pub struct ChunkPacketCache {
+    state: Mutex<State>,
+    capacity: usize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    tx_encoded: tokio::sync::mpsc::UnboundedSender<Encoded>,
+    rx_encoded: tokio::sync::mpsc::UnboundedReceiver<Encoded>,
+}
+
+impl Default for ChunkPacketCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_CHUNK_PACKET_CACHE_BYTES)
+    }
+}
+
+impl ChunkPacketCache {
+    /// Creates a cache which keeps up to `capacity` bytes of packets
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        let (tx_encoded, rx_encoded) = tokio::sync::mpsc::unbounded_channel();
+        Self {
+            state: Mutex::default(),
+            capacity,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            tx_encoded,
+            rx_encoded,
+        }
+    }
+
+    /// Returns the up to date packet of `column`.
+    ///
+    /// If the cached packet is out of date, `None` is returned and the column is encoded on the
+    /// rayon thread pool. The packet is available once [`ChunkPacketCache::receive_encoded`] has
+    /// received it.
+    pub fn get(&self, column: &Column) -> Option<Bytes> {
+        let epoch = column.epoch();
+        if epoch == 0 {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Some(column.bytes());
+        }
+
+        let position = column.position.as_i16vec2();
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(entry) = state.entries.get(&position)
+            && entry.epoch == epoch
+        {
+            let bytes = entry.bytes.clone();
+            state.touch(position);
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Some(bytes);
+        }
+
+        if state.encoding.get(&position) == Some(&epoch)
+            || state.failed.get(&position) == Some(&epoch)
+        {
+            return None;
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        state.encoding.insert(position, epoch);
+        state.failed.remove(&position);
+        drop(state);
+
+        // Only the reference count is changed on the tick thread, the data is copied if the chunk
+        // is changed before the packet is encoded
+        let data = Arc::clone(&column.data);
+        let column_position = column.position;
+        let tx = self.tx_encoded.clone();
+        rayon::spawn(move || {
+            let result = encode_column(&data, column_position);
+            let encoded = Encoded {
+                position,
+                epoch,
+                result,
+            };
+            if tx.send(encoded).is_err() {
+                warn!("dropped the packet of chunk {position} because its cache was dropped");
+            }
+        });
+
+        None
+    }
+
+    /// Stores the packets which were encoded since the last call
+    pub fn receive_encoded(&mut self) {
+        let state = self.state.get_mut().unwrap();
+
+        while let Ok(encoded) = self.rx_encoded.try_recv() {
+            if state.encoding.get(&encoded.position) == Some(&encoded.epoch) {
+                state.encoding.remove(&encoded.position);
+            }
+
+            let bytes = match encoded.result {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    error!("failed to encode chunk {}: {e}", encoded.position);
+                    state.failed.insert(encoded.position, encoded.epoch);
+                    continue;
+                }
+            };
+
+            // A chunk that was modified again while it was encoded has a newer packet already
+            if state
+                .entries
+                .get(&encoded.position)
+                .is_some_and(|entry| entry.epoch > encoded.epoch)
+            {
+                continue;
+            }
+
+            state.insert(encoded.position, encoded.epoch, bytes);
+        }
+
+        state.evict(self.capacity);
+    }
+
+    /// Waits until every chunk that is being encoded has been received
+    pub fn wait_for_encoded(&mut self) {
+        while !self.state.get_mut().unwrap().encoding.is_empty() {
+            self.receive_encoded();
+            std::thread::yield_now();
+        }
+    }
+
+    #[must_use]
+    pub fn stats(&self) -> ChunkPacketCacheStats {
+        ChunkPacketCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            bytes: self.state.lock().unwrap().bytes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::blocks::generator::{FlatGenerator, VoidGenerator};
+
+    fn modified_column(position: I16Vec2) -> Column {
+        let mut column = Column::generate(position, &VoidGenerator);
+        column.mark_dirty();
+        column
+    }
+
+    #[test]
+    fn packets_are_encoded_once_per_epoch() {
+        let mut cache = ChunkPacketCache::default();
+        let mut column = modified_column(I16Vec2::ZERO);
+
+        assert!(cache.get(&column).is_none());
+        // The chunk is already being encoded
+        assert!(cache.get(&column).is_none());
+        cache.wait_for_encoded();
+
+        let packet = cache.get(&column).unwrap();
+        assert_eq!(packet, column.encode_packet().unwrap());
+        assert_eq!(cache.get(&column).unwrap().as_ptr(), packet.as_ptr());
+
+        column.mark_dirty();
+        assert!(cache.get(&column).is_none());
+        cache.wait_for_encoded();
+        assert!(cache.get(&column).is_some());
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (3, 2));
+        assert_eq!(stats.bytes, packet.len());
+    }
+
+    #[test]
+    fn unmodified_chunks_use_the_packet_from_loading() {
+        let cache = ChunkPacketCache::default();
+        let column = Column::generate(I16Vec2::ZERO, &FlatGenerator::default());
+
+        assert_eq!(cache.get(&column).unwrap(), column.bytes());
+        assert_eq!(cache.stats().bytes, 0);
+    }
+
+    #[test]
+    fn failed_chunks_are_not_encoded_again_until_changed() {
+        let mut cache = ChunkPacketCache::default();
+        let mut column = modified_column(I16Vec2::ZERO);
+
+        cache
+            .state
+            .get_mut()
+            .unwrap()
+            .failed
+            .insert(I16Vec2::ZERO, column.epoch());
+        assert!(cache.get(&column).is_none());
+        assert_eq!(cache.stats().misses, 0);
+
+        column.mark_dirty();
+        assert!(cache.get(&column).is_none());
+        cache.wait_for_encoded();
+        assert!(cache.get(&column).is_some());
+        assert!(cache.state.get_mut().unwrap().failed.is_empty());
+    }
+
+    #[test]
+    fn least_recently_used_packets_are_evicted() {
+        let packet_len = modified_column(I16Vec2::ZERO)
+            .encode_packet()
+            .unwrap()
+            .len();
+        // Room for two packets, whose lengths may differ slightly
+        let mut cache = ChunkPacketCache::new(packet_len * 5 / 2);
+        let columns: Vec<_> = (0..3)
+            .map(|x| modified_column(I16Vec2::new(x, 0)))
+            .collect();
+
+        for column in &columns[..2] {
+            assert!(cache.get(column).is_none());
+        }
+        cache.wait_for_encoded();
+        // The first chunk becomes the most recently used one
+        assert!(cache.get(&columns[0]).is_some());
+
+        assert!(cache.get(&columns[2]).is_none());
+        cache.wait_for_encoded();
+
+        assert!(cache.get(&columns[0]).is_some());
+        assert!(cache.get(&columns[2]).is_some());
+        assert!(cache.get(&columns[1]).is_none());
+        assert!(cache.stats().bytes <= packet_len * 5 / 2);
+    }
+}
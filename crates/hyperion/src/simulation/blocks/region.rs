@@ -6,10 +6,41 @@ use std::{
 
 use bitfield_struct::bitfield;
 use flate2::bufread::{GzDecoder, ZlibDecoder};
+use thiserror::Error;
 use tokio::fs::File;
-use valence_anvil::{Compression, RawChunk, RegionError};
+use valence_anvil::RawChunk;
 use valence_nbt::binary::FromModifiedUtf8;
 
+use super::loader::parse::ParseChunkError;
+
+/// Why a chunk of an Anvil save could not be loaded
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum ChunkLoadError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("the region header is missing")]
+    MissingHeader,
+    #[error("the chunk points into the region header")]
+    InvalidSectorOffset,
+    #[error("the chunk stream is truncated: expected {expected} bytes, but only {available} exist")]
+    Truncated { expected: usize, available: usize },
+    #[error("the chunk stream is empty")]
+    MissingStream,
+    #[error("unknown compression scheme {0}")]
+    CompressionScheme(u8),
+    #[error("failed to decompress chunk: {0}")]
+    Decompress(#[source] std::io::Error),
+    #[error("invalid NBT: {0}")]
+    Nbt(#[from] valence_nbt::Error),
+    #[error("trailing data after the NBT")]
+    TrailingNbtData,
+    #[error("{0}")]
+    Parse(#[from] ParseChunkError),
+    #[error("failed to encode chunk: {0}")]
+    Encode(#[source] Box<dyn std::error::Error + Send + Sync>),
+}
+
 #[bitfield(u32)]
 struct Location {
     count: u8,
@@ -39,14 +70,11 @@ pub struct Region {
 const SECTOR_SIZE: usize = 4096;
 
 impl Region {
-    pub fn open(file: &File) -> Result<Self, RegionError> {
+    pub fn open(file: &File) -> Result<Self, ChunkLoadError> {
         let mmap = unsafe { MmapOptions::new().map(file)? };
 
         let Some(header) = &mmap.get(..SECTOR_SIZE * 2) else {
-            return Err(RegionError::Io(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "region header is not present",
-            )));
+            return Err(ChunkLoadError::MissingHeader);
         };
 
         let locations = std::array::from_fn(|i| {
@@ -99,7 +127,7 @@ impl Region {
         pos_z: i32,
         decompress_buf: &mut Vec<u8>,
         region_root: &Path,
-    ) -> Result<Option<RawChunk<S>>, RegionError>
+    ) -> Result<Option<RawChunk<S>>, ChunkLoadError>
     where
         S: for<'a> FromModifiedUtf8<'a> + Hash + Ord,
     {
@@ -118,69 +146,76 @@ impl Region {
         // If the sector offset was <2, then the chunk data would be inside the region
         // header. That doesn't make any sense.
         if sector_offset < 2 {
-            return Err(RegionError::InvalidChunkSectorOffset);
-        }
-
-        let chunk_start = sector_offset * SECTOR_SIZE as u64;
-        let chunk_end = chunk_start + (sector_count * SECTOR_SIZE) as u64;
-
-        if usize::try_from(chunk_end).unwrap() > self.mmap.len() {
-            return Err(RegionError::InvalidChunkSize);
+            return Err(ChunkLoadError::InvalidSectorOffset);
         }
 
-        let chunk_data =
-            &self.mmap[usize::try_from(chunk_start).unwrap()..usize::try_from(chunk_end).unwrap()];
-
-        let exact_chunk_size = u32::from_be_bytes(chunk_data[..4].try_into().unwrap()) as usize;
-        if exact_chunk_size == 0 {
-            return Err(RegionError::MissingChunkStream);
-        }
+        let chunk_start = usize::try_from(sector_offset).unwrap_or(usize::MAX) * SECTOR_SIZE;
+        let chunk_end = chunk_start
+            .saturating_add(sector_count * SECTOR_SIZE)
+            .min(self.mmap.len());
+        let chunk_data = self.mmap.get(chunk_start..chunk_end).unwrap_or_default();
+
+        let Some((exact_chunk_size, chunk_data)) = chunk_data.split_first_chunk::<4>() else {
+            return Err(ChunkLoadError::Truncated {
+                expected: 4,
+                available: chunk_data.len(),
+            });
+        };
 
-        // size of this chunk in sectors must always be >= the exact size.
-        if sector_count * SECTOR_SIZE < exact_chunk_size {
-            return Err(RegionError::InvalidChunkSize);
-        }
+        // The size includes the compression byte, and the sectors of the chunk must contain it
+        let exact_chunk_size =
+            usize::try_from(u32::from_be_bytes(*exact_chunk_size)).unwrap_or(usize::MAX);
+        let Some(stream) = chunk_data.get(..exact_chunk_size) else {
+            return Err(ChunkLoadError::Truncated {
+                expected: exact_chunk_size,
+                available: chunk_data.len(),
+            });
+        };
 
-        let compression = chunk_data[4];
+        let Some((&compression, stream)) = stream.split_first() else {
+            return Err(ChunkLoadError::MissingStream);
+        };
 
-        let data_buf = if Self::is_external_stream_chunk(compression) {
+        let external_data;
+        let r: &[u8] = if Self::is_external_stream_chunk(compression) {
             let external_file =
                 std::fs::File::open(Self::external_chunk_file(pos_x, pos_z, region_root))?;
             let external_mmap = unsafe { MmapOptions::new().map(&external_file)? };
-            external_mmap.to_vec().into_boxed_slice()
+            external_data = external_mmap.to_vec();
+            &external_data
         } else {
-            chunk_data[5..exact_chunk_size].to_vec().into_boxed_slice()
+            stream
         };
 
-        let r: &[u8] = data_buf.as_ref();
-
         decompress_buf.clear();
 
         // What compression does the chunk use?
-        let mut nbt_slice = match compression_from_u8(compression) {
-            Some(Compression::Gzip) => {
+        let mut nbt_slice = match Self::external_chunk_version(compression) {
+            1 => {
                 let mut z = GzDecoder::new(r);
-                z.read_to_end(decompress_buf)?;
+                z.read_to_end(decompress_buf)
+                    .map_err(ChunkLoadError::Decompress)?;
                 decompress_buf.as_slice()
             }
-            Some(Compression::Zlib) => {
+            2 => {
                 let mut z = ZlibDecoder::new(r);
-                z.read_to_end(decompress_buf)?;
+                z.read_to_end(decompress_buf)
+                    .map_err(ChunkLoadError::Decompress)?;
                 decompress_buf.as_slice()
             }
             // Uncompressed
-            Some(Compression::None) => r,
-            // Unknown
-            None => return Err(RegionError::InvalidCompressionScheme(compression)),
-            Some(_) => {
-                panic!("what???????");
+            3 => r,
+            4 => {
+                decompress_lz4(r, decompress_buf)?;
+                decompress_buf.as_slice()
             }
+            scheme => return Err(ChunkLoadError::CompressionScheme(scheme)),
         };
 
         let (data, _) = valence_nbt::from_binary(&mut nbt_slice)?;
 
         if !nbt_slice.is_empty() {
-            return Err(RegionError::TrailingNbtData);
+            return Err(ChunkLoadError::TrailingNbtData);
         }
 
         Ok(Some(RawChunk { data, timestamp }))
@@ -190,7 +225,7 @@ impl Region {
     //     &self,
     //     region_x: i32,
     //     region_z: i32,
-    // ) -> Vec<Result<(i32, i32), RegionError>> {
+    // ) -> Vec<Result<(i32, i32), ChunkLoadError>> {
     //     self.locations
     //         .iter()
     //         .enumerate()
@@ -218,7 +253,7 @@ impl Region {
     //     pos_x: i32,
     //     pos_z: i32,
     //     region_root: &Path,
-    // ) -> Result<(), RegionError> {
+    // ) -> Result<(), ChunkLoadError> {
     //     match std::fs::remove_file(Self::external_chunk_file(pos_x, pos_z, region_root)) {
     //         Ok(()) => Ok(()),
     //         Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
@@ -250,17 +285,213 @@ impl Region {
         (stream_version & 0x80) != 0
     }
 
-    #[expect(unused, reason = "todo")]
+    /// The compression scheme of a chunk, without the flag for chunks stored in external files
     const fn external_chunk_version(stream_version: u8) -> u8 {
         stream_version & !0x80
     }
 }
 
-const fn compression_from_u8(compression: u8) -> Option<Compression> {
-    match compression {
-        1 => Some(Compression::Gzip),
-        2 => Some(Compression::Zlib),
-        3 => Some(Compression::None),
-        _ => None,
+/// The magic bytes at the start of each block of an LZ4 stream
+const LZ4_MAGIC: &[u8; 8] = b"LZ4Block";
+/// The compression method of an LZ4 block which is stored as is
+const LZ4_RAW: u8 = 0x10;
+/// The compression method of an LZ4 block which is compressed with LZ4
+const LZ4_COMPRESSED: u8 = 0x20;
+/// How many times larger than its compressed block the data of an LZ4 block can be at most
+const LZ4_MAX_RATIO: usize = 255;
+/// The largest chunk which is decompressed from an LZ4 stream, so that corrupt lengths cannot
+/// allocate arbitrary amounts of memory
+const LZ4_MAX_CHUNK_LEN: usize = 32 * 1024 * 1024;
+
+/// Decompresses the LZ4 stream of a chunk into `out`. Vanilla writes these streams with the block
+/// format of `lz4-java`: every block has a header with the magic bytes, the compression method,
+/// the compressed and decompressed length and a checksum, and a block with a decompressed length
+/// of 0 ends the stream.
+///
+/// The checksums are not verified because corrupt data is caught when parsing the NBT.
+fn decompress_lz4(mut stream: &[u8], out: &mut Vec<u8>) -> Result<(), ChunkLoadError> {
+    let invalid = |message: &str| {
+        ChunkLoadError::Decompress(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("invalid LZ4 stream: {message}"),
+        ))
+    };
+    let length = |bytes: [u8; 4]| usize::try_from(u32::from_le_bytes(bytes)).unwrap_or(usize::MAX);
+    let chunk_start = out.len();
+
+    loop {
+        let Some((header, rest)) = stream.split_first_chunk::<21>() else {
+            return Err(invalid("truncated block header"));
+        };
+        if header[..8] != LZ4_MAGIC[..] {
+            return Err(invalid("missing magic bytes"));
+        }
+
+        let method = header[8] & 0xF0;
+        let compressed_len = length([header[9], header[10], header[11], header[12]]);
+        let decompressed_len = length([header[13], header[14], header[15], header[16]]);
+
+        let Some(block) = rest.get(..compressed_len) else {
+            return Err(invalid("truncated block"));
+        };
+        stream = &rest[compressed_len..];
+
+        if decompressed_len == 0 {
+            return Ok(());
+        }
+
+        // Checked before the block is allocated
+        if decompressed_len > compressed_len.saturating_mul(LZ4_MAX_RATIO) {
+            return Err(invalid("block is larger than its compressed data allows"));
+        }
+        if out.len() - chunk_start + decompressed_len > LZ4_MAX_CHUNK_LEN {
+            return Err(invalid("chunk is too large"));
+        }
+
+        match method {
+            LZ4_RAW if compressed_len == decompressed_len => out.extend_from_slice(block),
+            LZ4_COMPRESSED => {
+                let start = out.len();
+                out.resize(start + decompressed_len, 0);
+                let len = lz4_flex::block::decompress_into(block, &mut out[start..])
+                    .map_err(|e| invalid(&e.to_string()))?;
+                if len != decompressed_len {
+                    return Err(invalid("block has the wrong length"));
+                }
+            }
+            _ => return Err(invalid("unknown block method")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{Compression, write::ZlibEncoder};
+    use valence_nbt::{Compound, compound};
+
+    use super::*;
+
+    /// The NBT of a chunk with its position
+    fn chunk_nbt(x: i32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        valence_nbt::to_binary(&compound! { "xPos" => x }, &mut bytes, "").unwrap();
+        bytes
+    }
+
+    /// An LZ4 stream with the given blocks, each of which is given as the compression method, the
+    /// block and its decompressed length
+    fn lz4_blocks(blocks: &[(u8, &[u8], usize)]) -> Vec<u8> {
+        let mut stream = Vec::new();
+        for &(method, block, len) in blocks {
+            stream.extend_from_slice(LZ4_MAGIC);
+            stream.push(method);
+            stream.extend_from_slice(&u32::try_from(block.len()).unwrap().to_le_bytes());
+            stream.extend_from_slice(&u32::try_from(len).unwrap().to_le_bytes());
+            // The checksum is not verified
+            stream.extend_from_slice(&[0; 4]);
+            stream.extend_from_slice(block);
+        }
+        stream
+    }
+
+    /// An LZ4 stream as written by vanilla, with one compressed block
+    fn lz4_stream(data: &[u8]) -> Vec<u8> {
+        let compressed = lz4_flex::block::compress(data);
+        lz4_blocks(&[
+            (LZ4_COMPRESSED, compressed.as_slice(), data.len()),
+            (LZ4_RAW, &[][..], 0),
+        ])
+    }
+
+    /// Writes a region file with one chunk stream per entry in sector 2 onwards. Each stream is
+    /// given as the compression scheme, the data, and the length written before it.
+    fn write_region(path: &Path, chunks: &[(u8, Vec<u8>, u32)]) {
+        let mut header = vec![0; SECTOR_SIZE * 2];
+        let mut sectors = Vec::new();
+
+        for (index, (scheme, data, length)) in chunks.iter().enumerate() {
+            let offset = 2 + u32::try_from(index).unwrap();
+            let location = (offset << 8) | 1;
+            header[index * 4..index * 4 + 4].copy_from_slice(&location.to_be_bytes());
+
+            let mut sector = Vec::with_capacity(SECTOR_SIZE);
+            sector.extend_from_slice(&length.to_be_bytes());
+            sector.push(*scheme);
+            sector.extend_from_slice(data);
+            sector.resize(SECTOR_SIZE, 0);
+            sectors.extend_from_slice(&sector);
+        }
+
+        let mut file = std::fs::File::create(path).unwrap();
+        file.write_all(&header).unwrap();
+        file.write_all(&sectors).unwrap();
+    }
+
+    #[test]
+    fn loads_the_rest_of_a_region_with_corrupt_chunks() {
+        let dir = std::env::temp_dir().join(format!("hyperion-region-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("r.0.0.mca");
+
+        let stream_len = |data: &[u8]| u32::try_from(data.len() + 1).unwrap();
+        let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+        zlib.write_all(&chunk_nbt(0)).unwrap();
+        let zlib = zlib.finish().unwrap();
+        let lz4 = lz4_stream(&chunk_nbt(3));
+        let uncompressed = chunk_nbt(4);
+
+        write_region(&path, &[
+            (2, zlib.clone(), stream_len(&zlib)),
+            // The stream claims to be longer than its sector
+            (2, zlib.clone(), u32::try_from(SECTOR_SIZE * 2).unwrap()),
+            (42, zlib.clone(), stream_len(&zlib)),
+            (4, lz4.clone(), stream_len(&lz4)),
+            (3, uncompressed.clone(), stream_len(&uncompressed)),
+        ]);
+
+        let file = File::from_std(std::fs::File::open(&path).unwrap());
+        let region = Region::open(&file).unwrap();
+        let mut buf = Vec::new();
+        let mut get = |x| region.get_chunk::<String>(x, 0, &mut buf, &dir);
+
+        let x_pos = |chunk: Option<RawChunk<String>>| -> Compound<String> { chunk.unwrap().data };
+        assert_eq!(x_pos(get(0).unwrap()), compound! { "xPos" => 0 });
+        assert!(matches!(
+            get(1),
+            Err(ChunkLoadError::Truncated { available, .. }) if available == SECTOR_SIZE - 4
+        ));
+        assert!(matches!(get(2), Err(ChunkLoadError::CompressionScheme(42))));
+        assert_eq!(x_pos(get(3).unwrap()), compound! { "xPos" => 3 });
+        assert_eq!(x_pos(get(4).unwrap()), compound! { "xPos" => 4 });
+        assert!(get(5).unwrap().is_none());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_lz4_lengths_before_allocating() {
+        let mut out = Vec::new();
+
+        // No block decompresses to more than 255 times its size
+        let stream = lz4_blocks(&[
+            (LZ4_COMPRESSED, &[0; 4][..], 4 * 256),
+            (LZ4_RAW, &[][..], 0),
+        ]);
+        assert!(decompress_lz4(&stream, &mut out).is_err());
+        assert_eq!(out.capacity(), 0);
+
+        // Nor is a chunk larger than the limit, even across blocks
+        let block = vec![0; LZ4_MAX_CHUNK_LEN / LZ4_MAX_RATIO + 1];
+        let half = LZ4_MAX_CHUNK_LEN / 2 + 1;
+        let compressed = lz4_flex::block::compress(&vec![0; half]);
+        let stream = lz4_blocks(&[
+            (LZ4_COMPRESSED, compressed.as_slice(), half),
+            (LZ4_COMPRESSED, block.as_slice(), half),
+            (LZ4_RAW, &[][..], 0),
+        ]);
+        assert!(decompress_lz4(&stream, &mut out).is_err());
+        assert_eq!(out.len(), half);
     }
 }
@@ -0,0 +1,380 @@
+//! Entities stored in the `entities` region files of an Anvil save, which hold the entities of a
+//! chunk separately from its blocks since 1.17. See [`SavedEntity`].
+
+use bevy_ecs::{component::Component, system::Commands};
+use glam::Vec3;
+use thiserror::Error;
+use tracing::{trace, warn};
+use valence_generated::item::ItemKind;
+use valence_nbt::{Compound, List, Value};
+use valence_protocol::{ItemStack, VarInt};
+
+use crate::{
+    net::Channel,
+    simulation::{
+        ObjectData, Pitch, Position, Uuid, Velocity, Yaw,
+        entity_kind::EntityKind,
+        metadata::{
+            armor_stand::ArmorStandFlags,
+            item_frame::{FramedItem, ItemRotation},
+            painting::{PaintingVariant, PaintingVariantId},
+        },
+        world::WorldId,
+    },
+};
+
+#[derive(Debug, Error)]
+pub enum SavedEntityError {
+    #[error("missing or invalid field \"{0}\"")]
+    InvalidField(&'static str),
+}
+
+/// The NBT an entity was loaded with from the save. It is kept so that saving the entity again
+/// does not lose the fields which are not modelled.
+#[derive(Component, Clone, Debug, PartialEq)]
+pub struct SavedNbt(pub Compound);
+
+/// The data of a [`SavedEntity`] which depends on its kind
+#[derive(Clone, Debug, PartialEq)]
+#[expect(
+    variant_size_differences,
+    reason = "saved entities only exist between loading their chunk and spawning them"
+)]
+pub enum SavedEntityData {
+    /// An item frame or glow item frame
+    ItemFrame {
+        item: ItemStack,
+        /// The rotation of the item in 45 degree steps
+        rotation: i32,
+    },
+    ArmorStand {
+        /// See [`ArmorStandFlags`]
+        flags: u8,
+    },
+    Painting {
+        variant: PaintingVariantId,
+    },
+}
+
+/// An entity loaded from the save which is spawned once its chunk is loaded.
+///
+/// Only item frames, armor stands and paintings are supported. Other entities in the save are
+/// skipped.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SavedEntity {
+    pub kind: EntityKind,
+    pub uuid: uuid::Uuid,
+    /// The position of the entity. Item frames and paintings are positioned at the block they
+    /// hang in.
+    pub position: Vec3,
+    pub yaw: f32,
+    pub pitch: f32,
+    /// The `data` field of the spawn packet, which is the facing direction of item frames and
+    /// paintings
+    pub object_data: i32,
+    pub data: SavedEntityData,
+    pub nbt: Compound,
+}
+
+impl SavedEntity {
+    /// Parses a saved entity, returning `None` if its kind is not supported
+    pub fn from_nbt(nbt: Compound) -> Result<Option<Self>, SavedEntityError> {
+        let Some(Value::String(id)) = nbt.get("id") else {
+            return Err(SavedEntityError::InvalidField("id"));
+        };
+
+        let kind = match id.strip_prefix("minecraft:").unwrap_or(id) {
+            "item_frame" => EntityKind::ItemFrame,
+            "glow_item_frame" => EntityKind::GlowItemFrame,
+            "armor_stand" => EntityKind::ArmorStand,
+            "painting" => EntityKind::Painting,
+            _ => return Ok(None),
+        };
+
+        let uuid = read_uuid(&nbt)?;
+        let (yaw, pitch) = read_rotation(&nbt)?;
+
+        let (position, object_data, data) = match kind {
+            EntityKind::ItemFrame | EntityKind::GlowItemFrame => {
+                let item = match nbt.get("Item") {
+                    Some(Value::Compound(item)) => read_item(item)?,
+                    None => ItemStack::EMPTY,
+                    Some(_) => return Err(SavedEntityError::InvalidField("Item")),
+                };
+
+                let data = SavedEntityData::ItemFrame {
+                    item,
+                    rotation: i32::from(read_byte(&nbt, "ItemRotation")?),
+                };
+
+                // Item frames save their facing as a 3D direction
+                let facing = i32::from(read_byte(&nbt, "Facing")?);
+                (read_tile(&nbt)?, facing, data)
+            }
+            EntityKind::Painting => {
+                let variant = match nbt.get("variant") {
+                    Some(Value::String(variant)) => PaintingVariantId::from_name(variant)
+                        .unwrap_or_else(|| {
+                            warn!("unknown painting variant {variant}, using the default");
+                            PaintingVariantId::default()
+                        }),
+                    None => PaintingVariantId::default(),
+                    Some(_) => return Err(SavedEntityError::InvalidField("variant")),
+                };
+
+                // Paintings save their facing as a 2D direction, but the spawn packet expects a
+                // 3D one
+                let facing = match read_byte(&nbt, "facing")? {
+                    0 => 3, // south
+                    1 => 4, // west
+                    2 => 2, // north
+                    3 => 5, // east
+                    _ => return Err(SavedEntityError::InvalidField("facing")),
+                };
+
+                (read_tile(&nbt)?, facing, SavedEntityData::Painting {
+                    variant,
+                })
+            }
+            EntityKind::ArmorStand => {
+                let mut flags = 0;
+                for (field, flag) in [
+                    ("Small", ArmorStandFlags::SMALL),
+                    ("ShowArms", ArmorStandFlags::ARMS),
+                    ("NoBasePlate", ArmorStandFlags::NO_BASE_PLATE),
+                    ("Marker", ArmorStandFlags::MARKER),
+                ] {
+                    if read_byte(&nbt, field)? != 0 {
+                        flags |= flag;
+                    }
+                }
+
+                (read_pos(&nbt)?, 0, SavedEntityData::ArmorStand { flags })
+            }
+            _ => unreachable!("unsupported kinds are skipped above"),
+        };
+
+        Ok(Some(Self {
+            kind,
+            uuid,
+            position,
+            yaw,
+            pitch,
+            object_data,
+            data,
+            nbt,
+        }))
+    }
+
+    /// Spawns the entity in `world`
+    pub fn spawn(self, commands: &mut Commands<'_, '_>, world: WorldId) {
+        let mut entity = commands.spawn((
+            self.kind,
+            Uuid(self.uuid),
+            Position::from(self.position),
+            Velocity::default(),
+            Yaw::new(self.yaw),
+            Pitch::new(self.pitch),
+            ObjectData(self.object_data),
+            world,
+            SavedNbt(self.nbt),
+            Channel,
+        ));
+
+        // The metadata is inserted separately, since inserting the EntityKind resets it to its
+        // defaults
+        match self.data {
+            SavedEntityData::ItemFrame { item, rotation } => {
+                entity.insert((FramedItem::new(item), ItemRotation::new(VarInt(rotation))));
+            }
+            SavedEntityData::ArmorStand { flags } => {
+                entity.insert(ArmorStandFlags::new(flags));
+            }
+            SavedEntityData::Painting { variant } => {
+                entity.insert(PaintingVariant::new(variant));
+            }
+        }
+    }
+}
+
+/// Parses the entities of a chunk from the `entities` region files, skipping unsupported and
+/// invalid ones
+pub fn parse_entities(mut nbt: Compound) -> Vec<SavedEntity> {
+    let Some(Value::List(List::Compound(entities))) = nbt.remove("Entities") else {
+        return Vec::new();
+    };
+
+    entities
+        .into_iter()
+        .filter_map(|entity| match SavedEntity::from_nbt(entity) {
+            Ok(Some(entity)) => Some(entity),
+            Ok(None) => None,
+            Err(e) => {
+                trace!("skipping saved entity: {e}");
+                None
+            }
+        })
+        .collect()
+}
+
+fn read_byte(nbt: &Compound, field: &'static str) -> Result<i8, SavedEntityError> {
+    match nbt.get(field) {
+        Some(&Value::Byte(value)) => Ok(value),
+        None => Ok(0),
+        Some(_) => Err(SavedEntityError::InvalidField(field)),
+    }
+}
+
+fn read_uuid(nbt: &Compound) -> Result<uuid::Uuid, SavedEntityError> {
+    let Some(Value::IntArray(uuid)) = nbt.get("UUID") else {
+        return Err(SavedEntityError::InvalidField("UUID"));
+    };
+
+    let &[a, b, c, d] = uuid.as_slice() else {
+        return Err(SavedEntityError::InvalidField("UUID"));
+    };
+
+    let bits = [a, b, c, d].into_iter().fold(0_u128, |bits, part| {
+        (bits << 32) | u128::from(part.cast_unsigned())
+    });
+
+    Ok(uuid::Uuid::from_u128(bits))
+}
+
+fn read_rotation(nbt: &Compound) -> Result<(f32, f32), SavedEntityError> {
+    match nbt.get("Rotation") {
+        Some(Value::List(List::Float(rotation))) => match rotation.as_slice() {
+            &[yaw, pitch] => Ok((yaw, pitch)),
+            _ => Err(SavedEntityError::InvalidField("Rotation")),
+        },
+        None => Ok((0.0, 0.0)),
+        Some(_) => Err(SavedEntityError::InvalidField("Rotation")),
+    }
+}
+
+#[expect(
+    clippy::cast_possible_truncation,
+    reason = "positions are stored as f32 in Position"
+)]
+fn read_pos(nbt: &Compound) -> Result<Vec3, SavedEntityError> {
+    let Some(Value::List(List::Double(pos))) = nbt.get("Pos") else {
+        return Err(SavedEntityError::InvalidField("Pos"));
+    };
+
+    let &[x, y, z] = pos.as_slice() else {
+        return Err(SavedEntityError::InvalidField("Pos"));
+    };
+
+    Ok(Vec3::new(x as f32, y as f32, z as f32))
+}
+
+/// Reads the position of the block a hanging entity is attached to
+#[expect(clippy::cast_precision_loss, reason = "block positions are small")]
+fn read_tile(nbt: &Compound) -> Result<Vec3, SavedEntityError> {
+    let mut tile = [0.0; 3];
+    for (value, field) in tile.iter_mut().zip(["TileX", "TileY", "TileZ"]) {
+        let Some(&Value::Int(coordinate)) = nbt.get(field) else {
+            return Err(SavedEntityError::InvalidField(field));
+        };
+        *value = coordinate as f32;
+    }
+
+    Ok(Vec3::from_array(tile))
+}
+
+fn read_item(nbt: &Compound) -> Result<ItemStack, SavedEntityError> {
+    let Some(Value::String(id)) = nbt.get("id") else {
+        return Err(SavedEntityError::InvalidField("id"));
+    };
+
+    let Some(item) = ItemKind::from_str(id.strip_prefix("minecraft:").unwrap_or(id)) else {
+        return Err(SavedEntityError::InvalidField("id"));
+    };
+
+    let count = read_byte(nbt, "Count")?;
+
+    let tag = match nbt.get("tag") {
+        Some(Value::Compound(tag)) => Some(tag.clone()),
+        None => None,
+        Some(_) => return Err(SavedEntityError::InvalidField("tag")),
+    };
+
+    Ok(ItemStack::new(item, count, tag))
+}
+
+#[cfg(test)]
+mod tests {
+    use valence_nbt::compound;
+
+    use super::*;
+
+    #[test]
+    fn parses_supported_entities() {
+        let nbt = compound! {
+            "Entities" => List::Compound(vec![
+                compound! {
+                    "id" => "minecraft:item_frame",
+                    "UUID" => vec![1, 2, 3, -4],
+                    "Pos" => List::Double(vec![10.5, 64.5, -3.03]),
+                    "Rotation" => List::Float(vec![180.0, 0.0]),
+                    "TileX" => 10,
+                    "TileY" => 64,
+                    "TileZ" => -4,
+                    "Facing" => 3_i8,
+                    "ItemRotation" => 2_i8,
+                    "Item" => compound! { "id" => "minecraft:diamond", "Count" => 1_i8 },
+                },
+                compound! {
+                    "id" => "minecraft:painting",
+                    "UUID" => vec![0, 0, 0, 1],
+                    "TileX" => 0,
+                    "TileY" => 70,
+                    "TileZ" => 0,
+                    "facing" => 1_i8,
+                    "variant" => "minecraft:aztec",
+                },
+                compound! {
+                    "id" => "minecraft:armor_stand",
+                    "UUID" => vec![0, 0, 0, 2],
+                    "Pos" => List::Double(vec![1.5, 65.0, 1.5]),
+                    "ShowArms" => 1_i8,
+                    "Small" => 1_i8,
+                },
+                compound! {
+                    "id" => "minecraft:zombie",
+                    "UUID" => vec![0, 0, 0, 3],
+                    "Pos" => List::Double(vec![0.0, 64.0, 0.0]),
+                },
+            ]),
+        };
+
+        let entities = parse_entities(nbt);
+        assert_eq!(entities.len(), 3);
+
+        let frame = &entities[0];
+        assert_eq!(frame.kind, EntityKind::ItemFrame);
+        assert_eq!(
+            frame.uuid,
+            uuid::Uuid::from_u128(0x0000_0001_0000_0002_0000_0003_ffff_fffc)
+        );
+        assert_eq!(frame.position, Vec3::new(10.0, 64.0, -4.0));
+        assert_eq!(frame.object_data, 3);
+        assert_eq!(frame.data, SavedEntityData::ItemFrame {
+            item: ItemStack::new(ItemKind::Diamond, 1, None),
+            rotation: 2,
+        });
+
+        let painting = &entities[1];
+        assert_eq!(painting.kind, EntityKind::Painting);
+        assert_eq!(painting.object_data, 4);
+        assert_eq!(painting.data, SavedEntityData::Painting {
+            variant: PaintingVariantId(VarInt(1)),
+        });
+
+        let armor_stand = &entities[2];
+        assert_eq!(armor_stand.position, Vec3::new(1.5, 65.0, 1.5));
+        assert_eq!(armor_stand.data, SavedEntityData::ArmorStand {
+            flags: ArmorStandFlags::SMALL | ArmorStandFlags::ARMS,
+        });
+    }
+}
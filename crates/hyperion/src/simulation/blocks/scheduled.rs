@@ -0,0 +1,105 @@
+//! Block updates which happen after a delay, such as a button being released. See
+//! [`ScheduledBlockUpdates`].
+
+use std::collections::BTreeMap;
+
+use bevy_app::{App, FixedPreUpdate, Plugin};
+use bevy_ecs::{
+    message::MessageWriter,
+    resource::Resource,
+    schedule::IntoScheduleConfigs,
+    system::{Res, ResMut},
+};
+use glam::IVec3;
+#[cfg(feature = "reflect")]
+use {bevy_ecs::reflect::ReflectResource, bevy_reflect::Reflect};
+
+use crate::{
+    Tick,
+    simulation::{event, loading::world_ready, world::WorldId},
+};
+
+/// The queue of scheduled block updates. Each update is sent as an
+/// [`event::ScheduledBlockUpdate`] at the start of the tick it is scheduled for. The block is not
+/// checked beforehand, so systems reacting to the update have to check that the block is still
+/// the one they expect.
+#[derive(Resource, Default, Debug)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Resource))]
+pub struct ScheduledBlockUpdates {
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    queue: BTreeMap<i64, Vec<(WorldId, IVec3)>>,
+}
+
+impl ScheduledBlockUpdates {
+    /// Schedules an update of the block at `position` in `world` for the tick `tick`. Updates
+    /// scheduled for a tick which has already passed happen in the next tick.
+    pub fn schedule(&mut self, world: WorldId, position: IVec3, tick: i64) {
+        self.queue.entry(tick).or_default().push((world, position));
+    }
+
+    /// Removes the updates which are due at `tick`, in the order they were scheduled
+    pub fn take_due(&mut self, tick: i64) -> impl Iterator<Item = (WorldId, IVec3)> {
+        let later = self.queue.split_off(&(tick + 1));
+        std::mem::replace(&mut self.queue, later)
+            .into_values()
+            .flatten()
+    }
+
+    /// The number of updates which have been scheduled but have not happened yet
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.queue.values().map(Vec::len).sum()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+fn send_scheduled_updates(
+    mut updates: ResMut<'_, ScheduledBlockUpdates>,
+    tick: Res<'_, Tick>,
+    mut writer: MessageWriter<'_, event::ScheduledBlockUpdate>,
+) {
+    for (world, position) in updates.take_due(tick.0) {
+        writer.write(event::ScheduledBlockUpdate { world, position });
+    }
+}
+
+pub struct ScheduledUpdatePlugin;
+
+impl Plugin for ScheduledUpdatePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ScheduledBlockUpdates>();
+        // Updates which are due while the world is still loading happen once it is ready
+        app.add_systems(FixedPreUpdate, send_scheduled_updates.run_if(world_ready));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn updates_happen_when_due() {
+        let mut updates = ScheduledBlockUpdates::default();
+        let a = IVec3::new(1, 2, 3);
+        let b = IVec3::new(4, 5, 6);
+
+        updates.schedule(WorldId::PRIMARY, a, 10);
+        updates.schedule(WorldId::PRIMARY, b, 5);
+        assert_eq!(updates.len(), 2);
+
+        assert_eq!(updates.take_due(4).count(), 0);
+        assert_eq!(updates.take_due(7).collect::<Vec<_>>(), [(
+            WorldId::PRIMARY,
+            b
+        )]);
+        assert_eq!(updates.take_due(12).collect::<Vec<_>>(), [(
+            WorldId::PRIMARY,
+            a
+        )]);
+        assert!(updates.is_empty());
+    }
+}
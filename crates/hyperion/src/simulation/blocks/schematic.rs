@@ -0,0 +1,719 @@
+//! Loading and pasting of [Sponge schematics](https://github.com/SpongePowered/Schematic-Specification)
+//! (`.schem` files), which is the format used by WorldEdit.
+//!
+//! Versions 2 and 3 of the format are supported. Block entities are currently ignored.
+
+use std::{collections::VecDeque, io::Read, path::Path, sync::Arc};
+
+use bevy_app::{App, FixedUpdate, Plugin};
+use bevy_ecs::{resource::Resource, system::ResMut};
+use glam::{I16Vec2, IVec2, IVec3};
+use rustc_hash::FxHashMap;
+use thiserror::Error;
+use valence_generated::block::{BlockKind, BlockState, PropName, PropValue};
+use valence_nbt::{Compound, List, Value};
+#[cfg(feature = "reflect")]
+use {bevy_ecs::reflect::ReflectResource, bevy_reflect::Reflect};
+
+use crate::{
+    CHUNK_HEIGHT_SPAN,
+    simulation::blocks::{Blocks, TrySetBlockDeltaError},
+};
+
+const START_Y: i32 = -64;
+
+/// The largest number of blocks a schematic may contain, which keeps a small malicious file from
+/// allocating gigabytes of memory
+const MAX_VOLUME: u64 = 1 << 26;
+
+#[derive(Debug, Error)]
+pub enum SchematicError {
+    #[error("failed to read schematic: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("failed to parse schematic nbt: {0}")]
+    Nbt(#[from] valence_nbt::Error),
+    #[error("unsupported schematic version {0}")]
+    UnsupportedVersion(i32),
+    #[error("missing or invalid field \"{0}\"")]
+    InvalidField(&'static str),
+    #[error("unknown block name of \"{0}\"")]
+    UnknownBlockName(String),
+    #[error("invalid block property \"{0}\"")]
+    InvalidProperty(String),
+    #[error("invalid palette index {0}")]
+    BadPaletteIndex(i32),
+    #[error("expected {expected} blocks but the block data contains {actual}")]
+    BadBlockCount { expected: usize, actual: usize },
+    #[error("the schematic contains {volume} blocks, which is more than the maximum of {max}")]
+    TooLarge { volume: u64, max: u64 },
+    #[error("expected {expected} blocks but the block data only has {bytes} bytes")]
+    TruncatedBlockData { expected: usize, bytes: usize },
+    #[error("malformed varint in block data")]
+    BadVarInt,
+}
+
+/// A rotation around the y axis, clockwise when looking down.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Rotation90 {
+    #[default]
+    None,
+    Clockwise90,
+    Clockwise180,
+    Clockwise270,
+}
+
+impl Rotation90 {
+    #[must_use]
+    pub const fn rotate(self, position: IVec3) -> IVec3 {
+        let IVec3 { x, y, z } = position;
+        match self {
+            Self::None => position,
+            Self::Clockwise90 => IVec3::new(-z, y, x),
+            Self::Clockwise180 => IVec3::new(-x, y, -z),
+            Self::Clockwise270 => IVec3::new(z, y, -x),
+        }
+    }
+}
+
+/// Options for [`Schematic::paste`].
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct PasteOptions {
+    /// Keeps the existing blocks where the schematic contains air
+    pub ignore_air: bool,
+    /// Rotates the positions of the schematic around the paste origin. Block properties such as
+    /// `facing` are not rotated.
+    pub rotation: Rotation90,
+}
+
+/// A cuboid of blocks loaded from a schematic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schematic {
+    /// Width (x), height (y), and length (z)
+    size: IVec3,
+    offset: IVec3,
+    /// Indexed by `x + z * width + y * width * length`
+    blocks: Vec<BlockState>,
+}
+
+impl Schematic {
+    /// Loads a gzip compressed or uncompressed schematic file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SchematicError> {
+        let bytes = std::fs::read(path)?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// Parses a gzip compressed or uncompressed schematic.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SchematicError> {
+        const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+        let decompressed;
+        let mut nbt: &[u8] = if bytes.starts_with(&GZIP_MAGIC) {
+            let mut buf = Vec::new();
+            flate2::read::GzDecoder::new(bytes).read_to_end(&mut buf)?;
+            decompressed = buf;
+            &decompressed
+        } else {
+            bytes
+        };
+
+        let (root, _) = valence_nbt::from_binary(&mut nbt)?;
+        Self::from_nbt(root)
+    }
+
+    pub fn from_nbt(mut root: Compound) -> Result<Self, SchematicError> {
+        // Version 3 nests everything in a `Schematic` compound
+        if let Some(Value::Compound(inner)) = root.remove("Schematic") {
+            root = inner;
+        }
+
+        let Some(&Value::Int(version)) = root.get("Version") else {
+            return Err(SchematicError::InvalidField("Version"));
+        };
+
+        let size = IVec3::new(
+            read_dimension(&root, "Width")?,
+            read_dimension(&root, "Height")?,
+            read_dimension(&root, "Length")?,
+        );
+
+        let offset = match root.get("Offset") {
+            Some(Value::IntArray(offset)) => match offset.as_slice() {
+                &[x, y, z] => IVec3::new(x, y, z),
+                _ => return Err(SchematicError::InvalidField("Offset")),
+            },
+            None => IVec3::ZERO,
+            Some(_) => return Err(SchematicError::InvalidField("Offset")),
+        };
+
+        let (palette, data) = match version {
+            2 => (root.remove("Palette"), root.remove("BlockData")),
+            3 => {
+                let Some(Value::Compound(mut blocks)) = root.remove("Blocks") else {
+                    return Err(SchematicError::InvalidField("Blocks"));
+                };
+                (blocks.remove("Palette"), blocks.remove("Data"))
+            }
+            version => return Err(SchematicError::UnsupportedVersion(version)),
+        };
+
+        let Some(Value::Compound(palette)) = palette else {
+            return Err(SchematicError::InvalidField("Palette"));
+        };
+
+        let Some(Value::ByteArray(data)) = data else {
+            return Err(SchematicError::InvalidField("BlockData"));
+        };
+
+        let palette = read_palette(palette)?;
+
+        let volume = size
+            .to_array()
+            .into_iter()
+            .try_fold(1_u64, |volume, dimension| {
+                volume.checked_mul(u64::from(dimension.cast_unsigned()))
+            })
+            .unwrap_or(u64::MAX);
+        if volume > MAX_VOLUME {
+            return Err(SchematicError::TooLarge {
+                volume,
+                max: MAX_VOLUME,
+            });
+        }
+        let volume = usize::try_from(volume).map_err(|_| SchematicError::InvalidField("Width"))?;
+
+        // Every block takes at least one byte
+        if data.len() < volume {
+            return Err(SchematicError::TruncatedBlockData {
+                expected: volume,
+                bytes: data.len(),
+            });
+        }
+
+        let mut blocks = Vec::with_capacity(volume);
+
+        let mut bytes = data.iter().map(|&byte| byte.cast_unsigned());
+        while let Some(index) = read_varint(&mut bytes)? {
+            let Some(&block) = usize::try_from(index)
+                .ok()
+                .and_then(|index| palette.get(index))
+            else {
+                return Err(SchematicError::BadPaletteIndex(index));
+            };
+            blocks.push(block);
+        }
+
+        if blocks.len() != volume {
+            return Err(SchematicError::BadBlockCount {
+                expected: volume,
+                actual: blocks.len(),
+            });
+        }
+
+        Ok(Self {
+            size,
+            offset,
+            blocks,
+        })
+    }
+
+    /// Serializes the schematic in the version 3 format.
+    #[must_use]
+    pub fn to_nbt(&self) -> Compound {
+        let mut palette = FxHashMap::<BlockState, i32>::default();
+        let mut data = Vec::with_capacity(self.blocks.len());
+
+        for block in &self.blocks {
+            let next_id = i32::try_from(palette.len()).unwrap();
+            let id = *palette.entry(*block).or_insert(next_id);
+            write_varint(id, &mut data);
+        }
+
+        let palette = palette
+            .into_iter()
+            .map(|(block, id)| (block_state_name(block), Value::Int(id)))
+            .collect::<Compound>();
+
+        let mut blocks = Compound::new();
+        blocks.insert("Palette", palette);
+        blocks.insert("Data", Value::ByteArray(data));
+        blocks.insert("BlockEntities", List::Compound(Vec::new()));
+
+        let mut schematic = Compound::new();
+        schematic.insert("Version", 3);
+        schematic.insert("Width", dimension_to_nbt(self.size.x));
+        schematic.insert("Height", dimension_to_nbt(self.size.y));
+        schematic.insert("Length", dimension_to_nbt(self.size.z));
+        schematic.insert("Offset", Value::IntArray(self.offset.to_array().to_vec()));
+        schematic.insert("Blocks", blocks);
+
+        let mut root = Compound::new();
+        root.insert("Schematic", schematic);
+        root
+    }
+
+    /// Width (x), height (y), and length (z) of the schematic
+    #[must_use]
+    pub const fn size(&self) -> IVec3 {
+        self.size
+    }
+
+    #[must_use]
+    pub const fn offset(&self) -> IVec3 {
+        self.offset
+    }
+
+    /// Returns the block at a position relative to the minimum corner of the schematic.
+    #[must_use]
+    pub fn block(&self, position: IVec3) -> Option<BlockState> {
+        if position.cmplt(IVec3::ZERO).any() || position.cmpge(self.size).any() {
+            return None;
+        }
+
+        let index = position.x + position.z * self.size.x + position.y * self.size.x * self.size.z;
+        self.blocks.get(usize::try_from(index).ok()?).copied()
+    }
+
+    /// Pastes the whole schematic at once. Every chunk covered by the schematic must be loaded.
+    ///
+    /// Use [`PendingPastes`] instead for large schematics.
+    pub fn paste(
+        &self,
+        blocks: &mut Blocks,
+        origin: IVec3,
+        options: PasteOptions,
+    ) -> Result<(), TrySetBlockDeltaError> {
+        let mut cursor = 0;
+        match paste_blocks(self, &mut cursor, blocks, origin, options, usize::MAX) {
+            PasteProgress::Done => Ok(()),
+            PasteProgress::Pending => Err(TrySetBlockDeltaError::ChunkNotLoaded),
+        }
+    }
+
+    fn position_of(&self, index: usize) -> IVec3 {
+        let index = i32::try_from(index).unwrap();
+        let layer = self.size.x * self.size.z;
+        IVec3::new(
+            index % self.size.x,
+            index / layer,
+            (index / self.size.x) % self.size.z,
+        )
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PasteProgress {
+    Done,
+    /// The paste ran out of budget or is waiting for a chunk to load
+    Pending,
+}
+
+/// Places blocks starting at `cursor` until `budget` blocks have been visited. The cursor is
+/// advanced past every block that was handled.
+fn paste_blocks(
+    schematic: &Schematic,
+    cursor: &mut usize,
+    blocks: &mut Blocks,
+    origin: IVec3,
+    options: PasteOptions,
+    budget: usize,
+) -> PasteProgress {
+    let max_y = START_Y + i32::try_from(CHUNK_HEIGHT_SPAN).unwrap();
+    let end = cursor.saturating_add(budget).min(schematic.blocks.len());
+
+    while *cursor < end {
+        let block = schematic.blocks[*cursor];
+
+        if options.ignore_air && block.is_air() {
+            *cursor += 1;
+            continue;
+        }
+
+        let relative = schematic.position_of(*cursor) + schematic.offset;
+        let position = origin + options.rotation.rotate(relative);
+
+        if !(START_Y..max_y).contains(&position.y) {
+            *cursor += 1;
+            continue;
+        }
+
+        match blocks.set_block(position, block) {
+            Ok(_) | Err(TrySetBlockDeltaError::OutOfBounds) => *cursor += 1,
+            Err(TrySetBlockDeltaError::ChunkNotLoaded) => {
+                let chunk = (IVec2::new(position.x, position.z) >> 4).as_i16vec2();
+                request_chunk(blocks, chunk);
+                return PasteProgress::Pending;
+            }
+        }
+    }
+
+    if *cursor == schematic.blocks.len() {
+        PasteProgress::Done
+    } else {
+        PasteProgress::Pending
+    }
+}
+
+pub(super) fn request_chunk(blocks: &Blocks, chunk: I16Vec2) {
+    // The chunk is sent to `Blocks` once it is loaded, so the result does not need to be used
+    drop(blocks.get_cached_or_load(chunk));
+}
+
+/// A schematic which is pasted over multiple ticks.
+#[derive(Debug, Clone)]
+pub struct PasteJob {
+    schematic: Arc<Schematic>,
+    origin: IVec3,
+    options: PasteOptions,
+    cursor: usize,
+}
+
+impl PasteJob {
+    #[must_use]
+    pub const fn new(schematic: Arc<Schematic>, origin: IVec3, options: PasteOptions) -> Self {
+        Self {
+            schematic,
+            origin,
+            options,
+            cursor: 0,
+        }
+    }
+
+    /// Visits at most `budget` blocks of the schematic.
+    pub fn step(&mut self, blocks: &mut Blocks, budget: usize) -> PasteProgress {
+        paste_blocks(
+            &self.schematic,
+            &mut self.cursor,
+            blocks,
+            self.origin,
+            self.options,
+            budget,
+        )
+    }
+
+    /// Number of blocks of the schematic that have been handled
+    #[must_use]
+    pub const fn progress(&self) -> usize {
+        self.cursor
+    }
+}
+
+/// Schematics which are pasted into [`Blocks`] a limited number of blocks per tick.
+#[derive(Resource, Debug)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Resource))]
+pub struct PendingPastes {
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    jobs: VecDeque<PasteJob>,
+    /// Maximum number of blocks visited per tick across all pastes
+    pub blocks_per_tick: usize,
+}
+
+impl Default for PendingPastes {
+    fn default() -> Self {
+        Self {
+            jobs: VecDeque::new(),
+            blocks_per_tick: 65_536,
+        }
+    }
+}
+
+impl PendingPastes {
+    pub fn push(&mut self, schematic: Arc<Schematic>, origin: IVec3, options: PasteOptions) {
+        self.jobs
+            .push_back(PasteJob::new(schematic, origin, options));
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.jobs.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+}
+
+fn run_pending_pastes(mut pending: ResMut<'_, PendingPastes>, mut blocks: ResMut<'_, Blocks>) {
+    let mut budget = pending.blocks_per_tick;
+
+    while budget > 0 {
+        let Some(job) = pending.jobs.front_mut() else {
+            return;
+        };
+
+        let before = job.cursor;
+        let progress = job.step(&mut blocks, budget);
+        budget = budget.saturating_sub(job.cursor - before);
+
+        match progress {
+            PasteProgress::Done => {
+                pending.jobs.pop_front();
+            }
+            // Either out of budget or waiting for a chunk, so try again next tick
+            PasteProgress::Pending => return,
+        }
+    }
+}
+
+pub struct SchematicPlugin;
+
+impl Plugin for SchematicPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PendingPastes>();
+        app.add_systems(FixedUpdate, run_pending_pastes);
+    }
+}
+
+fn read_dimension(root: &Compound, name: &'static str) -> Result<i32, SchematicError> {
+    // Dimensions are unsigned shorts
+    match root.get(name) {
+        Some(&Value::Short(value)) => Ok(i32::from(value.cast_unsigned())),
+        _ => Err(SchematicError::InvalidField(name)),
+    }
+}
+
+#[expect(clippy::cast_possible_truncation, reason = "dimensions fit in a u16")]
+fn dimension_to_nbt(value: i32) -> Value {
+    Value::Short((value as u16).cast_signed())
+}
+
+fn read_palette(palette: Compound) -> Result<Vec<BlockState>, SchematicError> {
+    let mut blocks = vec![BlockState::AIR; palette.len()];
+
+    for (name, id) in palette {
+        let Value::Int(id) = id else {
+            return Err(SchematicError::InvalidField("Palette"));
+        };
+
+        let Some(slot) = usize::try_from(id).ok().and_then(|id| blocks.get_mut(id)) else {
+            return Err(SchematicError::BadPaletteIndex(id));
+        };
+
+        *slot = parse_block_state(&name)?;
+    }
+
+    Ok(blocks)
+}
+
+/// Parses a block state such as `minecraft:oak_stairs[facing=east,half=bottom]`.
+fn parse_block_state(name: &str) -> Result<BlockState, SchematicError> {
+    let (kind, properties) = match name.split_once('[') {
+        Some((kind, properties)) => (kind, properties.strip_suffix(']')),
+        None => (name, None),
+    };
+
+    let path = kind.rsplit_once(':').map_or(kind, |(_, path)| path);
+    let Some(kind) = BlockKind::from_str(path) else {
+        return Err(SchematicError::UnknownBlockName(name.to_owned()));
+    };
+
+    let mut state = kind.to_state();
+
+    for property in properties.into_iter().flat_map(|p| p.split(',')) {
+        let parsed = property
+            .split_once('=')
+            .and_then(|(key, value)| Some((PropName::from_str(key)?, PropValue::from_str(value)?)));
+
+        let Some((key, value)) = parsed else {
+            return Err(SchematicError::InvalidProperty(property.to_owned()));
+        };
+
+        state = state.set(key, value);
+    }
+
+    Ok(state)
+}
+
+fn block_state_name(block: BlockState) -> String {
+    let kind = block.to_kind();
+    let mut name = format!("minecraft:{}", kind.to_str());
+
+    let properties = kind
+        .props()
+        .iter()
+        .filter_map(|&prop| Some(format!("{}={}", prop.to_str(), block.get(prop)?.to_str())))
+        .collect::<Vec<_>>();
+
+    if !properties.is_empty() {
+        name.push('[');
+        name.push_str(&properties.join(","));
+        name.push(']');
+    }
+
+    name
+}
+
+fn read_varint(bytes: &mut impl Iterator<Item = u8>) -> Result<Option<i32>, SchematicError> {
+    let mut value = 0_i32;
+
+    for i in 0..5 {
+        let Some(byte) = bytes.next() else {
+            return if i == 0 {
+                Ok(None)
+            } else {
+                Err(SchematicError::BadVarInt)
+            };
+        };
+
+        value |= i32::from(byte & 0x7f) << (i * 7);
+
+        if byte & 0x80 == 0 {
+            return Ok(Some(value));
+        }
+    }
+
+    Err(SchematicError::BadVarInt)
+}
+
+#[expect(
+    clippy::cast_possible_truncation,
+    reason = "only the lowest 7 bits are kept"
+)]
+fn write_varint(value: i32, out: &mut Vec<i8>) {
+    let mut value = value.cast_unsigned();
+
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+
+        if value == 0 {
+            out.push(byte.cast_signed());
+            return;
+        }
+
+        out.push((byte | 0x80).cast_signed());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &[u8] = include_bytes!("data/small.schem");
+
+    fn stairs() -> BlockState {
+        BlockState::OAK_STAIRS
+            .set(PropName::Facing, PropValue::East)
+            .set(PropName::Half, PropValue::Bottom)
+            .set(PropName::Shape, PropValue::Straight)
+            .set(PropName::Waterlogged, PropValue::False)
+    }
+
+    #[test]
+    fn load_fixture() {
+        let schematic = Schematic::from_bytes(FIXTURE).unwrap();
+
+        assert_eq!(schematic.size(), IVec3::new(3, 2, 2));
+        assert_eq!(schematic.offset(), IVec3::new(-1, 0, -1));
+
+        for x in 0..3 {
+            for z in 0..2 {
+                assert_eq!(
+                    schematic.block(IVec3::new(x, 0, z)),
+                    Some(BlockState::STONE)
+                );
+            }
+        }
+
+        assert_eq!(schematic.block(IVec3::new(0, 1, 0)), Some(stairs()));
+        assert_eq!(
+            schematic
+                .block(IVec3::new(2, 1, 1))
+                .map(BlockState::to_kind),
+            Some(BlockKind::Chest)
+        );
+        assert_eq!(schematic.block(IVec3::new(1, 1, 0)), Some(BlockState::AIR));
+        assert_eq!(schematic.block(IVec3::new(3, 0, 0)), None);
+    }
+
+    #[test]
+    fn round_trip() {
+        let schematic = Schematic::from_bytes(FIXTURE).unwrap();
+
+        let mut bytes = Vec::new();
+        valence_nbt::to_binary(&schematic.to_nbt(), &mut bytes, "").unwrap();
+
+        let reloaded = Schematic::from_bytes(&bytes).unwrap();
+        assert_eq!(schematic, reloaded);
+    }
+
+    fn schematic_nbt(size: [i16; 3], data: Vec<i8>) -> Compound {
+        let mut palette = Compound::new();
+        palette.insert("minecraft:stone", Value::Int(0));
+
+        let mut root = Compound::new();
+        root.insert("Version", Value::Int(2));
+        root.insert("Width", Value::Short(size[0]));
+        root.insert("Height", Value::Short(size[1]));
+        root.insert("Length", Value::Short(size[2]));
+        root.insert("Palette", Value::Compound(palette));
+        root.insert("BlockData", Value::ByteArray(data));
+        root
+    }
+
+    #[test]
+    fn huge_schematics_are_rejected_before_allocating() {
+        assert!(matches!(
+            Schematic::from_nbt(schematic_nbt([-1, -1, -1], vec![0; 8])),
+            Err(SchematicError::TooLarge { .. })
+        ));
+        assert!(matches!(
+            Schematic::from_nbt(schematic_nbt([256, 256, 256], vec![0; 8])),
+            Err(SchematicError::TruncatedBlockData {
+                expected: 16_777_216,
+                bytes: 8,
+            })
+        ));
+        assert_eq!(
+            Schematic::from_nbt(schematic_nbt([2, 2, 2], vec![0; 8]))
+                .unwrap()
+                .block(IVec3::ONE),
+            Some(BlockState::STONE)
+        );
+    }
+
+    #[test]
+    fn block_state_names() {
+        for block in [BlockState::STONE, stairs(), BlockState::AIR] {
+            assert_eq!(parse_block_state(&block_state_name(block)).unwrap(), block);
+        }
+
+        assert!(matches!(
+            parse_block_state("minecraft:not_a_block"),
+            Err(SchematicError::UnknownBlockName(_))
+        ));
+        assert!(matches!(
+            parse_block_state("minecraft:stone[bogus=1]"),
+            Err(SchematicError::InvalidProperty(_))
+        ));
+    }
+
+    #[test]
+    fn varint_round_trip() {
+        for value in [0, 1, 127, 128, 300, 65_535, i32::MAX] {
+            let mut bytes = Vec::new();
+            write_varint(value, &mut bytes);
+            let mut iter = bytes.iter().map(|&byte| byte.cast_unsigned());
+            assert_eq!(read_varint(&mut iter).unwrap(), Some(value));
+            assert_eq!(read_varint(&mut iter).unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn rotation() {
+        let position = IVec3::new(1, 2, 3);
+        assert_eq!(Rotation90::None.rotate(position), position);
+        assert_eq!(
+            Rotation90::Clockwise90.rotate(position),
+            IVec3::new(-3, 2, 1)
+        );
+        assert_eq!(
+            Rotation90::Clockwise180.rotate(position),
+            IVec3::new(-1, 2, -3)
+        );
+        assert_eq!(
+            Rotation90::Clockwise270.rotate(position),
+            IVec3::new(3, 2, -1)
+        );
+    }
+}
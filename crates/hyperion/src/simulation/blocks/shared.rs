@@ -10,6 +10,8 @@ use super::manager::RegionManager;
 /// Inner state of the [`MinecraftWorld`] component.
 pub struct WorldShared {
     pub regions: RegionManager,
+    /// The region files of the entities, which is `None` for saves from before 1.17
+    pub entities: Option<RegionManager>,
     pub biome_to_id: BTreeMap<Ident, BiomeId>,
 }
 
@@ -20,11 +22,13 @@ impl WorldShared {
         path: &Path,
     ) -> anyhow::Result<Self> {
         let regions = RegionManager::new(runtime, path).context("failed to get anvil data")?;
+        let entities = RegionManager::entities(runtime, path);
 
         let biome_to_id = biomes.iter().map(|(id, name, _)| (name, id)).collect();
 
         Ok(Self {
             regions,
+            entities,
             biome_to_id,
         })
     }
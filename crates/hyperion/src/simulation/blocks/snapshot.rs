@@ -0,0 +1,836 @@
+//! Capturing regions of blocks and restoring them later, such as to reset an arena between
+//! rounds. See [`RegionSnapshot`].
+
+use std::{collections::VecDeque, path::Path, sync::Arc};
+
+use bevy_app::{App, FixedUpdate, Plugin};
+use bevy_ecs::{
+    entity::Entity,
+    query::{With, Without},
+    resource::Resource,
+    system::{Commands, Query, ResMut},
+};
+use glam::{I16Vec2, IVec2, IVec3};
+use rkyv::util::AlignedVec;
+use rustc_hash::FxHashMap;
+use thiserror::Error;
+use tracing::warn;
+use valence_generated::block::BlockState;
+#[cfg(feature = "reflect")]
+use {bevy_ecs::reflect::ReflectResource, bevy_reflect::Reflect};
+
+use crate::{
+    CHUNK_HEIGHT_SPAN,
+    net::ConnectionId,
+    simulation::{
+        EntityKind, Position,
+        blocks::{
+            Blocks,
+            schematic::{PasteProgress, request_chunk},
+        },
+        world::{WorldBlocksMut, WorldId},
+    },
+    storage::{BitStorage, BitStorageError},
+};
+
+const MIN_Y: i32 = -64;
+#[expect(
+    clippy::cast_possible_wrap,
+    reason = "the height of the world fits in an i32"
+)]
+const MAX_Y: i32 = MIN_Y + CHUNK_HEIGHT_SPAN as i32 - 1;
+
+/// The horizontal coordinates of blocks whose chunk positions fit in an [`I16Vec2`]
+const MIN_HORIZONTAL: i32 = -(1 << 19);
+const MAX_HORIZONTAL: i32 = (1 << 19) - 1;
+
+/// The maximum number of blocks in a snapshot, which is about 2700 chunks of full height. With
+/// the largest possible palette, the indices of this many blocks take up 512 MiB.
+pub const MAX_SNAPSHOT_BLOCKS: u64 = 1 << 28;
+
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    #[error("failed to access snapshot file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("failed to decode snapshot: {0}")]
+    Decode(#[from] rkyv::rancor::Error),
+    #[error("snapshot block data is invalid: {0}")]
+    Storage(#[from] BitStorageError),
+    #[error("snapshot contains invalid block state {0}")]
+    InvalidBlockState(u16),
+    #[error("snapshot refers to palette entry {0}, which does not exist")]
+    InvalidPaletteIndex(u64),
+    #[error("snapshot region from {min} to {max} is empty")]
+    EmptyRegion { min: IVec3, max: IVec3 },
+    #[error("snapshot region from {min} to {max} is outside of the world")]
+    OutOfBounds { min: IVec3, max: IVec3 },
+    #[error("snapshot region contains {0} blocks, more than `MAX_SNAPSHOT_BLOCKS`")]
+    TooLarge(u64),
+    #[error("chunk {0} is not loaded")]
+    ChunkNotLoaded(I16Vec2),
+}
+
+/// The block states of a box of blocks at the time it was captured with
+/// [`Blocks::snapshot_region`].
+///
+/// Like chunk sections, the blocks are stored as indices into a palette of the distinct block
+/// states in the region, so a region of mostly a few kinds of blocks takes up a few bits per
+/// block. Snapshots can be saved to disk and restored with [`PendingRestores`] or
+/// [`Blocks::restore`].
+///
+/// [`PendingRestores`] restores a snapshot into the world it was taken in. World ids are not saved,
+/// so snapshots which are loaded from disk belong to the primary world until they are moved with
+/// [`RegionSnapshot::in_world`].
+#[derive(Debug, Clone)]
+pub struct RegionSnapshot {
+    world: WorldId,
+    min: IVec3,
+    max: IVec3,
+    palette: Vec<BlockState>,
+    /// Indices into `palette` in yzx order, relative to `min`
+    indices: BitStorage,
+}
+
+/// Encoded form of [`RegionSnapshot`]
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+struct SavedSnapshot {
+    min: [i32; 3],
+    max: [i32; 3],
+    palette: Vec<u16>,
+    indices: Vec<u64>,
+}
+
+/// Checks that the box from `min` to `max` (inclusive) is inside of the world and small enough to
+/// be captured
+fn check_region(min: IVec3, max: IVec3) -> Result<(), SnapshotError> {
+    if min.cmpgt(max).any() {
+        return Err(SnapshotError::EmptyRegion { min, max });
+    }
+
+    let lowest = IVec3::new(MIN_HORIZONTAL, MIN_Y, MIN_HORIZONTAL);
+    let highest = IVec3::new(MAX_HORIZONTAL, MAX_Y, MAX_HORIZONTAL);
+    if min.cmplt(lowest).any() || max.cmpgt(highest).any() {
+        return Err(SnapshotError::OutOfBounds { min, max });
+    }
+
+    // Both corners are in bounds, so the sizes are positive and fit in an i32
+    let size = (max - min + IVec3::ONE).as_i64vec3();
+    #[expect(clippy::cast_sign_loss, reason = "the size is positive")]
+    let blocks = size.x.saturating_mul(size.y).saturating_mul(size.z) as u64;
+    if blocks > MAX_SNAPSHOT_BLOCKS {
+        return Err(SnapshotError::TooLarge(blocks));
+    }
+
+    Ok(())
+}
+
+/// The number of bits needed to store an index into a palette of `len` entries
+fn bits_for(len: usize) -> usize {
+    if len <= 1 {
+        0
+    } else {
+        (usize::BITS - (len - 1).leading_zeros()) as usize
+    }
+}
+
+fn new_storage(bits: usize, size: usize, data: Vec<u64>) -> Result<BitStorage, BitStorageError> {
+    if bits == 0 {
+        // Every block is the only entry of the palette
+        return BitStorage::new(0, size, Some(Vec::new()));
+    }
+
+    BitStorage::new(bits, size, Some(data))
+}
+
+/// The chunks covering the blocks from `min` to `max` (inclusive)
+fn chunks_between(min: IVec3, max: IVec3) -> impl Iterator<Item = I16Vec2> {
+    let min_chunk = IVec2::new(min.x, min.z) >> 4;
+    let max_chunk = IVec2::new(max.x, max.z) >> 4;
+
+    (min_chunk.y..=max_chunk.y)
+        .flat_map(move |z| (min_chunk.x..=max_chunk.x).map(move |x| IVec2::new(x, z).as_i16vec2()))
+}
+
+/// The part of the box from `min` to `max` (inclusive) inside `chunk`
+fn chunk_bounds(min: IVec3, max: IVec3, chunk: I16Vec2) -> (IVec3, IVec3) {
+    let start = IVec3::new(i32::from(chunk.x) << 4, MIN_Y, i32::from(chunk.y) << 4);
+    let end = start + IVec3::new(15, MAX_Y - MIN_Y, 15);
+
+    (min.max(start), max.min(end))
+}
+
+/// Returns the index of the block at `position` in a chunk section
+#[expect(
+    clippy::cast_sign_loss,
+    clippy::cast_possible_truncation,
+    reason = "the coordinates are masked"
+)]
+const fn section_index(position: IVec3) -> u16 {
+    (((position.y & 15) << 8) | ((position.z & 15) << 4) | (position.x & 15)) as u16
+}
+
+#[expect(
+    clippy::cast_sign_loss,
+    reason = "the position is above the bottom of the world"
+)]
+const fn section_of(position: IVec3) -> usize {
+    ((position.y - MIN_Y) >> 4) as usize
+}
+
+impl RegionSnapshot {
+    /// The world the snapshot is restored into by [`PendingRestores`]
+    #[must_use]
+    pub const fn world(&self) -> WorldId {
+        self.world
+    }
+
+    /// Moves the snapshot to `world`, such as after taking it from the [`Blocks`] of a secondary
+    /// world
+    #[must_use]
+    pub const fn in_world(mut self, world: WorldId) -> Self {
+        self.world = world;
+        self
+    }
+
+    /// The lowest corner of the region
+    #[must_use]
+    pub const fn min(&self) -> IVec3 {
+        self.min
+    }
+
+    /// The highest corner of the region (inclusive)
+    #[must_use]
+    pub const fn max(&self) -> IVec3 {
+        self.max
+    }
+
+    #[must_use]
+    pub const fn size(&self) -> IVec3 {
+        IVec3::new(
+            self.max.x - self.min.x + 1,
+            self.max.y - self.min.y + 1,
+            self.max.z - self.min.z + 1,
+        )
+    }
+
+    /// The number of blocks in the region
+    #[must_use]
+    #[expect(clippy::cast_sign_loss, reason = "the size is positive")]
+    pub const fn len(&self) -> usize {
+        let size = self.size();
+        size.x as usize * size.y as usize * size.z as usize
+    }
+
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The distinct block states in the region
+    #[must_use]
+    pub fn palette(&self) -> &[BlockState] {
+        &self.palette
+    }
+
+    #[must_use]
+    pub fn contains(&self, position: IVec3) -> bool {
+        position.cmpge(self.min).all() && position.cmple(self.max).all()
+    }
+
+    #[expect(clippy::cast_sign_loss, reason = "the position is inside the region")]
+    fn index(&self, position: IVec3) -> usize {
+        let relative = position - self.min;
+        let size = self.size();
+        ((relative.y * size.z + relative.z) * size.x + relative.x) as usize
+    }
+
+    /// Returns the captured block at `position`, or `None` if it is outside of the region
+    #[must_use]
+    pub fn block(&self, position: IVec3) -> Option<BlockState> {
+        if !self.contains(position) {
+            return None;
+        }
+
+        let index = self.indices.get(self.index(position));
+        Some(self.palette[usize::try_from(index).unwrap()])
+    }
+
+    /// Reads a snapshot which was written with [`RegionSnapshot::save`]
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SnapshotError> {
+        let bytes = std::fs::read(path)?;
+        Self::from_bytes(&bytes)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SnapshotError> {
+        std::fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        // Files are not guaranteed to be read into aligned memory
+        let mut aligned = AlignedVec::<16>::with_capacity(bytes.len());
+        aligned.extend_from_slice(bytes);
+
+        let saved = rkyv::from_bytes::<SavedSnapshot, rkyv::rancor::Error>(&aligned)?;
+
+        let min = IVec3::from_array(saved.min);
+        let max = IVec3::from_array(saved.max);
+        check_region(min, max)?;
+        if saved.palette.is_empty() {
+            return Err(SnapshotError::EmptyRegion { min, max });
+        }
+
+        let palette = saved
+            .palette
+            .iter()
+            .map(|&raw| BlockState::from_raw(raw).ok_or(SnapshotError::InvalidBlockState(raw)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut snapshot = Self {
+            world: WorldId::PRIMARY,
+            min,
+            max,
+            palette,
+            indices: BitStorage::default(),
+        };
+
+        let bits = bits_for(snapshot.palette.len());
+        snapshot.indices = new_storage(bits, snapshot.len(), saved.indices)?;
+
+        let palette_len = snapshot.palette.len() as u64;
+        if let Some(index) = (0..snapshot.len())
+            .map(|i| snapshot.indices.get(i))
+            .find(|&index| index >= palette_len)
+        {
+            return Err(SnapshotError::InvalidPaletteIndex(index));
+        }
+
+        Ok(snapshot)
+    }
+
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let saved = SavedSnapshot {
+            min: self.min.to_array(),
+            max: self.max.to_array(),
+            palette: self.palette.iter().map(|block| block.to_raw()).collect(),
+            indices: self.indices.data().to_vec(),
+        };
+
+        rkyv::to_bytes::<rkyv::rancor::Error>(&saved)
+            .unwrap()
+            .to_vec()
+    }
+}
+
+impl Blocks {
+    /// Captures the blocks in the box from `min` to `max` (inclusive). The box is cut off at the
+    /// bottom and top of the world.
+    ///
+    /// Every chunk covered by the box must be loaded. Chunks which are not loaded are requested,
+    /// so the snapshot can be taken once they are.
+    ///
+    /// The snapshot belongs to the primary world. Use [`RegionSnapshot::in_world`] when these are
+    /// the blocks of a secondary world.
+    pub fn snapshot_region(&self, min: IVec3, max: IVec3) -> Result<RegionSnapshot, SnapshotError> {
+        let (min, max) = (min.min(max), min.max(max));
+        let min = min.with_y(min.y.max(MIN_Y));
+        let max = max.with_y(max.y.min(MAX_Y));
+        check_region(min, max)?;
+
+        let mut snapshot = RegionSnapshot {
+            world: WorldId::PRIMARY,
+            min,
+            max,
+            palette: Vec::new(),
+            indices: BitStorage::default(),
+        };
+
+        if let Some(chunk) =
+            chunks_between(min, max).find(|&chunk| self.get_loaded_chunk(chunk).is_none())
+        {
+            request_chunk(self, chunk);
+            return Err(SnapshotError::ChunkNotLoaded(chunk));
+        }
+
+        // The palette is collected first, so that the indices can be written straight into
+        // storage with the number of bits the palette needs
+        let mut palette_indices = FxHashMap::<u16, u64>::default();
+        for_each_block(self, min, max, |_, raw| {
+            let next = snapshot.palette.len() as u64;
+            palette_indices.entry(raw).or_insert_with(|| {
+                snapshot
+                    .palette
+                    .push(BlockState::from_raw(raw).unwrap_or(BlockState::AIR));
+                next
+            });
+        });
+
+        let bits = bits_for(snapshot.palette.len());
+        if bits == 0 {
+            snapshot.indices = new_storage(0, snapshot.len(), Vec::new())?;
+            return Ok(snapshot);
+        }
+
+        let mut storage = BitStorage::new(bits, snapshot.len(), None)?;
+        for_each_block(self, min, max, |position, raw| {
+            storage.set(snapshot.index(position), palette_indices[&raw]);
+        });
+
+        snapshot.indices = storage;
+        Ok(snapshot)
+    }
+
+    /// Restores the whole snapshot at once. Every chunk covered by the snapshot must be loaded,
+    /// otherwise nothing is restored and the first chunk which is not loaded is requested.
+    ///
+    /// Use [`PendingRestores`] instead for large regions.
+    pub fn restore(&mut self, snapshot: &RegionSnapshot) -> Result<(), SnapshotError> {
+        if let Some(chunk) = chunks_between(snapshot.min, snapshot.max)
+            .find(|&chunk| self.get_loaded_chunk(chunk).is_none())
+        {
+            request_chunk(self, chunk);
+            return Err(SnapshotError::ChunkNotLoaded(chunk));
+        }
+
+        let progress = restore_blocks(snapshot, &mut RestoreProgress::default(), self, usize::MAX);
+        debug_assert_eq!(progress, PasteProgress::Done);
+        Ok(())
+    }
+}
+
+/// Calls `f` with the position and raw block state of every block from `min` to `max`
+/// (inclusive). Every chunk covered by the box must be loaded.
+fn for_each_block(blocks: &Blocks, min: IVec3, max: IVec3, mut f: impl FnMut(IVec3, u16)) {
+    for chunk in chunks_between(min, max) {
+        let column = blocks.get_loaded_chunk(chunk).unwrap();
+        let (start, end) = chunk_bounds(min, max, chunk);
+
+        for y in start.y..=end.y {
+            let section = &column.data.sections[section_of(IVec3::new(0, y, 0))];
+
+            for z in start.z..=end.z {
+                for x in start.x..=end.x {
+                    let position = IVec3::new(x, y, z);
+                    let raw = section
+                        .block_states
+                        .get(usize::from(section_index(position)));
+                    f(position, raw);
+                }
+            }
+        }
+    }
+}
+
+/// How far a restore has gotten
+#[derive(Debug, Copy, Clone, Default)]
+struct RestoreProgress {
+    /// The index of the chunk being restored in [`chunks_between`]
+    column: usize,
+    /// The number of blocks of the chunk which have been restored
+    cursor: usize,
+    /// The total number of blocks which have been restored
+    blocks: usize,
+}
+
+/// Restores blocks until `budget` blocks have been visited.
+///
+/// Blocks are written without recording them as changes, since resending every changed chunk is
+/// much cheaper for clients than millions of single block updates. See [`Blocks::resend_chunk`].
+/// The blocks of a chunk are written a row at a time, so the column data and section are only
+/// looked up once per row.
+#[expect(clippy::cast_sign_loss, reason = "the bounds are not empty")]
+fn restore_blocks(
+    snapshot: &RegionSnapshot,
+    progress: &mut RestoreProgress,
+    blocks: &mut Blocks,
+    budget: usize,
+) -> PasteProgress {
+    let mut remaining = budget;
+
+    for chunk in chunks_between(snapshot.min, snapshot.max).skip(progress.column) {
+        if remaining == 0 {
+            return PasteProgress::Pending;
+        }
+
+        let Some(column) = blocks.get_loaded_chunk_mut(chunk) else {
+            request_chunk(blocks, chunk);
+            return PasteProgress::Pending;
+        };
+
+        let (start, end) = chunk_bounds(snapshot.min, snapshot.max, chunk);
+        let size = end - start + IVec3::ONE;
+        let total = size.x as usize * size.y as usize * size.z as usize;
+        let until = total.min(progress.cursor.saturating_add(remaining));
+        let data = column.data_mut();
+        let layer = size.x * size.z;
+        let mut changed = false;
+        let mut i = progress.cursor;
+
+        while i < until {
+            let row = i32::try_from(i).unwrap();
+            let position = start + IVec3::new(row % size.x, row / layer, (row / size.x) % size.z);
+            let len = (size.x - position.x + start.x).min(i32::try_from(until - i).unwrap());
+
+            let section = &mut data.sections[section_of(position)];
+            let first = snapshot.index(position);
+            for x in 0..len {
+                let index = snapshot.indices.get(first + x as usize);
+                let block = snapshot.palette[usize::try_from(index).unwrap()];
+                let position = position.with_x(position.x + x);
+                changed |= section.set(section_index(position), block) != block;
+            }
+
+            i += len as usize;
+        }
+
+        remaining -= until - progress.cursor;
+        progress.blocks += until - progress.cursor;
+        progress.cursor = until;
+
+        if changed {
+            blocks.resend_chunk(chunk);
+        }
+
+        if progress.cursor < total {
+            return PasteProgress::Pending;
+        }
+
+        progress.column += 1;
+        progress.cursor = 0;
+    }
+
+    PasteProgress::Done
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct RestoreOptions {
+    /// Whether entities in the region which are not players, such as dropped items and
+    /// projectiles, are despawned once the restore is done
+    pub despawn_entities: bool,
+}
+
+/// A snapshot which is restored over multiple ticks.
+#[derive(Debug, Clone)]
+pub struct RestoreJob {
+    snapshot: Arc<RegionSnapshot>,
+    options: RestoreOptions,
+    progress: RestoreProgress,
+}
+
+impl RestoreJob {
+    #[must_use]
+    pub fn new(snapshot: Arc<RegionSnapshot>, options: RestoreOptions) -> Self {
+        Self {
+            snapshot,
+            options,
+            progress: RestoreProgress::default(),
+        }
+    }
+
+    /// Restores at most `budget` blocks of the snapshot.
+    pub fn step(&mut self, blocks: &mut Blocks, budget: usize) -> PasteProgress {
+        restore_blocks(&self.snapshot, &mut self.progress, blocks, budget)
+    }
+
+    /// Number of blocks of the snapshot that have been restored
+    #[must_use]
+    pub const fn progress(&self) -> usize {
+        self.progress.blocks
+    }
+}
+
+/// Snapshots which are restored into [`Blocks`] a limited number of blocks per tick.
+#[derive(Resource, Debug)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Resource))]
+pub struct PendingRestores {
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    jobs: VecDeque<RestoreJob>,
+    /// Maximum number of blocks restored per tick across all restores
+    pub blocks_per_tick: usize,
+}
+
+impl Default for PendingRestores {
+    fn default() -> Self {
+        Self {
+            jobs: VecDeque::new(),
+            blocks_per_tick: 262_144,
+        }
+    }
+}
+
+impl PendingRestores {
+    pub fn push(&mut self, snapshot: Arc<RegionSnapshot>, options: RestoreOptions) {
+        self.jobs.push_back(RestoreJob::new(snapshot, options));
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.jobs.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+}
+
+type Despawnable = (With<EntityKind>, Without<ConnectionId>);
+
+fn run_pending_restores(
+    mut pending: ResMut<'_, PendingRestores>,
+    mut worlds: WorldBlocksMut<'_>,
+    entities: Query<'_, '_, (Entity, &Position, Option<&WorldId>), Despawnable>,
+    mut commands: Commands<'_, '_>,
+) {
+    let mut budget = pending.blocks_per_tick;
+
+    while budget > 0 {
+        let Some(job) = pending.jobs.front_mut() else {
+            return;
+        };
+
+        let world = job.snapshot.world();
+        let Some(blocks) = worlds.get_mut(Some(&world)) else {
+            warn!("dropped the restore of a snapshot into world {world:?}, which no longer exists");
+            pending.jobs.pop_front();
+            continue;
+        };
+
+        let before = job.progress();
+        let progress = job.step(blocks, budget);
+        budget = budget.saturating_sub(job.progress() - before);
+
+        match progress {
+            PasteProgress::Done => {
+                let job = pending.jobs.pop_front().unwrap();
+                if job.options.despawn_entities {
+                    despawn_entities_in(&job.snapshot, &entities, &mut commands);
+                }
+            }
+            // Either out of budget or waiting for a chunk, so try again next tick
+            PasteProgress::Pending => return,
+        }
+    }
+}
+
+fn despawn_entities_in(
+    snapshot: &RegionSnapshot,
+    entities: &Query<'_, '_, (Entity, &Position, Option<&WorldId>), Despawnable>,
+    commands: &mut Commands<'_, '_>,
+) {
+    for (entity, position, world) in entities {
+        if world.copied().unwrap_or_default() != snapshot.world() {
+            continue;
+        }
+
+        if snapshot.contains(position.floor().as_ivec3()) {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+pub struct RestorePlugin;
+
+impl Plugin for RestorePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PendingRestores>();
+        app.add_systems(FixedUpdate, run_pending_restores);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::{
+        blocks::{ChunkBounds, generator::FlatGenerator},
+        world::Worlds,
+    };
+
+    /// The chunks `(-1, 0)` and `(0, 0)` of a default flat world
+    fn loaded_blocks() -> Blocks {
+        let flat = FlatGenerator::default();
+        let bounds = ChunkBounds::new(I16Vec2::new(-1, 0), I16Vec2::new(0, 0));
+        Blocks::from_fn(bounds, |position| {
+            flat.block_at(u32::try_from(position.y + 64).unwrap())
+        })
+    }
+
+    #[test]
+    fn palette_bits() {
+        assert_eq!(bits_for(1), 0);
+        assert_eq!(bits_for(2), 1);
+        assert_eq!(bits_for(3), 2);
+        assert_eq!(bits_for(4), 2);
+        assert_eq!(bits_for(5), 3);
+    }
+
+    #[test]
+    fn restore_undoes_changes() {
+        let mut blocks = loaded_blocks();
+        let min = IVec3::new(-4, -64, 2);
+        let max = IVec3::new(3, -58, 9);
+
+        let snapshot = blocks.snapshot_region(min, max).unwrap();
+        assert_eq!(snapshot.len(), 8 * 7 * 8);
+        assert_eq!(snapshot.palette(), [
+            BlockState::BEDROCK,
+            BlockState::DIRT,
+            BlockState::GRASS_BLOCK,
+            BlockState::AIR
+        ]);
+
+        let changed = IVec3::new(-1, -61, 5);
+        blocks.set_block(changed, BlockState::STONE).unwrap();
+        blocks.set_block(max, BlockState::STONE).unwrap();
+        blocks.clear_should_update();
+
+        let mut job = RestoreJob::new(Arc::new(snapshot), RestoreOptions::default());
+        assert_eq!(job.step(&mut blocks, 100), PasteProgress::Pending);
+        assert_eq!(job.progress(), 100);
+        while job.step(&mut blocks, 100) == PasteProgress::Pending {}
+        assert_eq!(job.progress(), 8 * 7 * 8);
+
+        assert_eq!(blocks.get_block(changed), Some(BlockState::GRASS_BLOCK));
+        assert_eq!(blocks.get_block(max), Some(BlockState::AIR));
+
+        // The chunks are sent again instead of sending the changes
+        let resent = blocks.take_resent_chunks();
+        assert_eq!(resent.len(), 2);
+        blocks.for_each_to_update(|_| panic!("restored blocks are not sent as changes"));
+    }
+
+    #[test]
+    fn restore_writes_nothing_unless_every_chunk_is_loaded() {
+        let snapshot = loaded_blocks()
+            .snapshot_region(IVec3::new(-4, -64, 2), IVec3::new(3, -58, 9))
+            .unwrap();
+
+        // Only the first of the two chunks of the snapshot is loaded
+        let bounds = ChunkBounds::new(I16Vec2::new(-1, 0), I16Vec2::new(-1, 0));
+        let mut blocks = Blocks::from_fn(bounds, |_| BlockState::STONE);
+
+        assert!(matches!(
+            blocks.restore(&snapshot),
+            Err(SnapshotError::ChunkNotLoaded(chunk)) if chunk == I16Vec2::new(0, 0)
+        ));
+        assert_eq!(
+            blocks.get_block(IVec3::new(-1, -61, 5)),
+            Some(BlockState::STONE)
+        );
+        assert!(blocks.take_resent_chunks().is_empty());
+    }
+
+    #[test]
+    fn snapshots_need_loaded_chunks() {
+        let blocks = loaded_blocks();
+
+        let result = blocks.snapshot_region(IVec3::new(0, 0, 0), IVec3::new(16, 0, 0));
+        assert!(matches!(
+            result,
+            Err(SnapshotError::ChunkNotLoaded(chunk)) if chunk == I16Vec2::new(1, 0)
+        ));
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut blocks = loaded_blocks();
+        blocks
+            .set_block(IVec3::new(0, -60, 0), BlockState::STONE)
+            .unwrap();
+
+        let snapshot = blocks
+            .snapshot_region(IVec3::new(-2, -64, 0), IVec3::new(2, -59, 2))
+            .unwrap();
+        let decoded = RegionSnapshot::from_bytes(&snapshot.to_bytes()).unwrap();
+
+        assert_eq!(decoded.min(), snapshot.min());
+        assert_eq!(decoded.max(), snapshot.max());
+        assert_eq!(decoded.palette(), snapshot.palette());
+        for i in 0..snapshot.len() {
+            assert_eq!(decoded.indices.get(i), snapshot.indices.get(i));
+        }
+        assert_eq!(
+            decoded.block(IVec3::new(0, -60, 0)),
+            Some(BlockState::STONE)
+        );
+    }
+
+    #[test]
+    fn invalid_extents_are_rejected() {
+        let saved = |min: [i32; 3], max: [i32; 3]| {
+            let saved = SavedSnapshot {
+                min,
+                max,
+                palette: vec![BlockState::AIR.to_raw()],
+                indices: Vec::new(),
+            };
+            rkyv::to_bytes::<rkyv::rancor::Error>(&saved)
+                .unwrap()
+                .to_vec()
+        };
+
+        assert!(matches!(
+            RegionSnapshot::from_bytes(&saved([0, 0, 0], [-1, 0, 0])),
+            Err(SnapshotError::EmptyRegion { .. })
+        ));
+        assert!(matches!(
+            RegionSnapshot::from_bytes(&saved([0, -65, 0], [0, 0, 0])),
+            Err(SnapshotError::OutOfBounds { .. })
+        ));
+        assert!(matches!(
+            RegionSnapshot::from_bytes(&saved([i32::MIN, 0, 0], [i32::MAX, 0, 0])),
+            Err(SnapshotError::OutOfBounds { .. })
+        ));
+        assert!(matches!(
+            RegionSnapshot::from_bytes(&saved([0, -64, 0], [99_999, 319, 99_999])),
+            Err(SnapshotError::TooLarge(_))
+        ));
+    }
+
+    #[test]
+    fn restore_into_snapshot_world() {
+        let mut app = App::new();
+        app.add_plugins(RestorePlugin);
+        app.insert_resource(loaded_blocks());
+        let mut worlds = Worlds::default();
+        let secondary = worlds.insert(loaded_blocks());
+        app.insert_resource(worlds);
+
+        let position = IVec3::new(0, -61, 0);
+        let blocks = app.world().resource::<Worlds>().get(secondary).unwrap();
+        let snapshot = blocks
+            .snapshot_region(position, position)
+            .unwrap()
+            .in_world(secondary);
+
+        let mut worlds = app.world_mut().resource_mut::<Worlds>();
+        let blocks = worlds.get_mut(secondary).unwrap();
+        blocks.set_block(position, BlockState::STONE).unwrap();
+
+        app.world_mut()
+            .resource_mut::<PendingRestores>()
+            .push(Arc::new(snapshot), RestoreOptions::default());
+        app.world_mut().run_schedule(FixedUpdate);
+
+        let worlds = app.world().resource::<Worlds>();
+        assert_eq!(
+            worlds.get(secondary).unwrap().get_block(position),
+            Some(BlockState::GRASS_BLOCK)
+        );
+        assert!(app.world().resource::<PendingRestores>().is_empty());
+    }
+
+    #[test]
+    fn single_block_kind() {
+        let blocks = loaded_blocks();
+
+        let snapshot = blocks
+            .snapshot_region(IVec3::new(0, 0, 0), IVec3::new(15, 15, 15))
+            .unwrap();
+        assert_eq!(snapshot.palette(), [BlockState::AIR]);
+
+        let decoded = RegionSnapshot::from_bytes(&snapshot.to_bytes()).unwrap();
+        assert_eq!(decoded.block(IVec3::new(5, 5, 5)), Some(BlockState::AIR));
+    }
+}
@@ -19,6 +19,8 @@ use {
 pub struct Command {
     #[cfg_attr(feature = "reflect", reflect(remote = crate::reflect::NodeDataRemote))]
     data: NodeData,
+    /// Whether the command may end at this node
+    executable: bool,
     #[cfg_attr(
         feature = "reflect",
         reflect(ignore, default = "crate::reflect::command_permission_default")
@@ -41,6 +43,7 @@ impl std::ops::Deref for RootCommand {
 impl Command {
     pub const ROOT: Self = Self {
         data: NodeData::Root,
+        executable: false,
         has_permission: |_: _, _: _| true,
     };
 
@@ -52,6 +55,7 @@ impl Command {
         let name = name.into();
         Self {
             data: NodeData::Literal { name },
+            executable: true,
             has_permission,
         }
     }
@@ -65,9 +69,19 @@ impl Command {
                 parser,
                 suggestion: Some(Suggestion::AskServer),
             },
+            executable: true,
             has_permission: |_: _, _: _| true,
         }
     }
+
+    /// Sets whether the command may end at this node, which is the case by default. Nodes
+    /// followed by a required argument should not be executable, so that the client rejects
+    /// commands missing the argument like the server does.
+    #[must_use]
+    pub const fn executable(mut self, executable: bool) -> Self {
+        self.executable = executable;
+        self
+    }
 }
 
 // we want a get command packet
@@ -132,7 +146,7 @@ pub fn get_command_packet(
 
             commands.push(Node {
                 data: command.data.clone(),
-                executable: true,
+                executable: command.executable,
                 children: Vec::new(),
                 redirect_node: None,
             });
@@ -196,6 +210,7 @@ mod tests {
                 data: NodeData::Literal {
                     name: "test".into(),
                 },
+                executable: true,
                 has_permission: |_: _, _: _| true,
             },
             ChildOf(root_command),
@@ -225,6 +240,7 @@ mod tests {
                     data: NodeData::Literal {
                         name: "parent".into(),
                     },
+                    executable: false,
                     has_permission: |_: _, _: _| true,
                 },
                 ChildOf(root_command),
@@ -236,6 +252,7 @@ mod tests {
                 data: NodeData::Literal {
                     name: "child".into(),
                 },
+                executable: true,
                 has_permission: |_: _, _: _| true,
             },
             ChildOf(parent),
@@ -253,6 +270,8 @@ mod tests {
         assert_eq!(packet.commands[2].data, NodeData::Literal {
             name: "child".into(),
         });
+        assert!(!packet.commands[1].executable);
+        assert!(packet.commands[2].executable);
     }
 
     #[test]
@@ -271,6 +290,7 @@ mod tests {
                         data: NodeData::Literal {
                             name: format!("command_{i}").into(),
                         },
+                        executable: true,
                         has_permission: |_: _, _: _| true,
                     },
                     ChildOf(parent),
@@ -0,0 +1,30 @@
+//! Pruning expired [`Cooldowns`] so that they do not grow on long-lived entities. This is
+//! optional: cooldowns work without it, but every key ever used stays in the component.
+
+use bevy_app::{App, FixedLast, Plugin};
+use bevy_ecs::system::{Query, Res};
+use hyperion_utils::cooldown::Cooldowns;
+
+use crate::Tick;
+
+/// How often expired cooldowns are removed, in ticks
+pub const PRUNE_INTERVAL_TICKS: i64 = 60 * 20;
+
+fn prune_cooldowns(tick: Res<'_, Tick>, mut query: Query<'_, '_, &mut Cooldowns>) {
+    if tick.0 % PRUNE_INTERVAL_TICKS != 0 {
+        return;
+    }
+
+    for mut cooldowns in &mut query {
+        cooldowns.prune(tick.0);
+    }
+}
+
+/// Removes the expired entries of every [`Cooldowns`] component every [`PRUNE_INTERVAL_TICKS`]
+pub struct CooldownPrunePlugin;
+
+impl Plugin for CooldownPrunePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(FixedLast, prune_cooldowns);
+    }
+}
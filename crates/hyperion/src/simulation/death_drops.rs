@@ -0,0 +1,328 @@
+//! Dropping the inventory and experience of players when they die. See [`DropsInventoryOnDeath`].
+//!
+//! Item pickup is not implemented yet, so [`DroppedItemOwner`] only records who may pick up a
+//! dropped item for game code which implements it.
+
+use std::f32::consts::TAU;
+
+use bevy_app::{App, FixedPostUpdate, Plugin};
+use bevy_ecs::{
+    component::Component,
+    entity::Entity,
+    event::EntityEvent,
+    query::With,
+    resource::Resource,
+    system::{Commands, Query, Res},
+    world::World,
+};
+use glam::Vec3;
+use hyperion_inventory::PlayerInventory;
+use hyperion_utils::Prev;
+use valence_server::ItemStack;
+#[cfg(feature = "reflect")]
+use {
+    bevy_ecs::reflect::{ReflectComponent, ReflectResource},
+    bevy_reflect::Reflect,
+};
+
+use crate::{
+    GameRng, Tick,
+    net::Channel,
+    simulation::{
+        Pitch, Position, Uuid, Velocity, Xp, Yaw, entity_kind::EntityKind, inventory::spawn_item,
+        metadata::living_entity::Health, world::WorldId,
+    },
+};
+
+/// The experience orb sizes the client knows, from the largest to the smallest
+const ORB_SIZES: [u16; 11] = [2477, 1237, 617, 307, 149, 73, 37, 17, 7, 3, 1];
+
+/// Players with this component drop their inventory and some of their experience when they die
+#[derive(Component, Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+pub struct DropsInventoryOnDeath;
+
+/// How the items and experience dropped on death behave
+#[derive(Resource, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Resource))]
+pub struct DeathDropConfig {
+    /// How many ticks only the player who died may pick up their items
+    pub owner_only_ticks: i64,
+    /// How many ticks dropped items and experience stay before they despawn
+    pub despawn_ticks: i64,
+    /// How much experience is dropped per level of the player
+    pub xp_per_level: u16,
+    /// The maximum amount of experience dropped
+    pub max_xp: u16,
+}
+
+impl Default for DeathDropConfig {
+    fn default() -> Self {
+        Self {
+            owner_only_ticks: 10 * 20,
+            despawn_ticks: 5 * 60 * 20,
+            xp_per_level: 7,
+            max_xp: 100,
+        }
+    }
+}
+
+/// Triggered before a player with [`DropsInventoryOnDeath`] drops their items. Observers may set
+/// `cancelled` to keep the inventory and experience of the player, such as for kits.
+#[derive(EntityEvent, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DroppingDeathItems {
+    pub entity: Entity,
+    pub cancelled: bool,
+}
+
+/// The player who dropped an item on death and until which tick only they may pick it up
+#[derive(Component, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+pub struct DroppedItemOwner {
+    pub owner: Uuid,
+    pub exclusive_until: i64,
+}
+
+impl DroppedItemOwner {
+    /// Whether the player with `uuid` may pick up the item at `tick`
+    #[must_use]
+    pub fn may_pick_up(&self, uuid: Uuid, tick: i64) -> bool {
+        tick >= self.exclusive_until || self.owner == uuid
+    }
+}
+
+/// The amount of experience an experience orb is worth
+#[derive(Component, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+pub struct ExperienceOrbValue(pub u16);
+
+/// The entity is despawned once this tick is reached
+#[derive(Component, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+pub struct DespawnAt(pub i64);
+
+/// The amount of experience a player with `xp` drops on death
+fn dropped_xp(xp: Xp, config: &DeathDropConfig) -> u16 {
+    u16::from(xp.get_visual().level)
+        .saturating_mul(config.xp_per_level)
+        .min(config.max_xp)
+}
+
+/// Splits `xp` into orbs the way vanilla does
+fn split_into_orbs(mut xp: u16) -> Vec<u16> {
+    let mut orbs = Vec::new();
+
+    while xp > 0 {
+        let size = ORB_SIZES.into_iter().find(|&size| size <= xp).unwrap_or(1);
+        orbs.push(size);
+        xp -= size;
+    }
+
+    orbs
+}
+
+/// A small random velocity which scatters a dropped item around the position of the player
+fn scatter(rng: &mut fastrand::Rng) -> Vec3 {
+    let speed = rng.f32() * 0.5;
+    let angle = rng.f32() * TAU;
+    Vec3::new(-angle.sin() * speed, 0.2, angle.cos() * speed)
+}
+
+fn drop_on_death(
+    query: Query<'_, '_, (Entity, &Health, &Prev<Health>), With<DropsInventoryOnDeath>>,
+    mut commands: Commands<'_, '_>,
+) {
+    for (player, health, prev) in &query {
+        if !prev.is_dead() && health.is_dead() {
+            commands.queue(move |world: &mut World| drop_death_items(world, player));
+        }
+    }
+}
+
+/// Drops the inventory and experience of `player` unless an observer of [`DroppingDeathItems`]
+/// cancels it
+fn drop_death_items(world: &mut World, player: Entity) {
+    let mut dropping = DroppingDeathItems {
+        entity: player,
+        cancelled: false,
+    };
+    world.trigger_ref(&mut dropping);
+    world.flush();
+
+    if dropping.cancelled {
+        return;
+    }
+
+    let tick = world.resource::<Tick>().0;
+    let config = *world.resource::<DeathDropConfig>();
+    let mut rng = world
+        .resource::<GameRng>()
+        .fork_keyed("death_drops", (player, tick));
+
+    let Ok(mut entity) = world.get_entity_mut(player) else {
+        return;
+    };
+
+    let Some(&position) = entity.get::<Position>() else {
+        return;
+    };
+    let world_id = entity.get::<WorldId>().copied().unwrap_or(WorldId::PRIMARY);
+    let owner = entity.get::<Uuid>().copied();
+
+    let stacks: Vec<ItemStack> = entity
+        .get_mut::<PlayerInventory>()
+        .map(|mut inventory| {
+            let stacks = inventory.items().map(|(_, stack)| stack.clone()).collect();
+            // Records the slot changes, which sends them to the client
+            inventory.clear(|_| true);
+            stacks
+        })
+        .unwrap_or_default();
+
+    let xp = entity.get_mut::<Xp>().map_or(0, |mut xp| {
+        let dropped = dropped_xp(*xp, &config);
+        xp.amount = 0;
+        dropped
+    });
+
+    let despawn_at = DespawnAt(tick + config.despawn_ticks);
+
+    for stack in stacks {
+        let item = spawn_item(world, stack, *position, world_id);
+        let mut item = world.entity_mut(item);
+        item.insert((Velocity(scatter(&mut rng)), despawn_at));
+
+        if let Some(owner) = owner {
+            item.insert(DroppedItemOwner {
+                owner,
+                exclusive_until: tick + config.owner_only_ticks,
+            });
+        }
+    }
+
+    for value in split_into_orbs(xp) {
+        world.spawn((
+            EntityKind::ExperienceOrb,
+            Uuid::from_rng(&mut rng),
+            position,
+            Velocity(scatter(&mut rng)),
+            Yaw::default(),
+            Pitch::default(),
+            world_id,
+            Channel,
+            ExperienceOrbValue(value),
+            despawn_at,
+        ));
+    }
+}
+
+fn despawn_expired(
+    tick: Res<'_, Tick>,
+    query: Query<'_, '_, (Entity, &DespawnAt)>,
+    mut commands: Commands<'_, '_>,
+) {
+    for (entity, despawn_at) in &query {
+        if tick.0 >= despawn_at.0 {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+pub struct DeathDropsPlugin;
+
+impl Plugin for DeathDropsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<DeathDropConfig>();
+        app.add_systems(FixedPostUpdate, (drop_on_death, despawn_expired));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_ecs::observer::On;
+    use valence_generated::item::ItemKind;
+
+    use super::*;
+
+    fn world_with_player() -> (World, Entity) {
+        let mut world = World::new();
+        world.insert_resource(Tick(100));
+        world.insert_resource(GameRng::new(1));
+        world.init_resource::<DeathDropConfig>();
+
+        let mut inventory = PlayerInventory::default();
+        inventory
+            .set_hotbar(0, ItemStack::new(ItemKind::Stone, 64, None))
+            .unwrap();
+        inventory.set_helmet(ItemStack::new(ItemKind::IronHelmet, 1, None));
+
+        let player = world
+            .spawn((
+                Position::new(1.0, 64.0, 2.0),
+                Uuid::new_v4(),
+                Xp { amount: 30 },
+                inventory,
+            ))
+            .id();
+
+        (world, player)
+    }
+
+    #[test]
+    fn inventory_and_xp_are_dropped() {
+        let (mut world, player) = world_with_player();
+        drop_death_items(&mut world, player);
+
+        let owner = *world.get::<Uuid>(player).unwrap();
+        let mut owners = world.query::<&DroppedItemOwner>();
+        let owners: Vec<_> = owners.iter(&world).copied().collect();
+        assert_eq!(owners.len(), 2);
+        assert!(owners[0].may_pick_up(owner, 100));
+        assert!(!owners[0].may_pick_up(Uuid::new_v4(), 299));
+        assert!(owners[0].may_pick_up(Uuid::new_v4(), 300));
+
+        // Level 3 drops 21 experience
+        let mut orbs = world.query::<&ExperienceOrbValue>();
+        let mut orbs: Vec<u16> = orbs.iter(&world).map(|orb| orb.0).collect();
+        orbs.sort_unstable();
+        assert_eq!(orbs, [1, 3, 17]);
+
+        assert_eq!(
+            world
+                .get::<PlayerInventory>(player)
+                .unwrap()
+                .items()
+                .count(),
+            0
+        );
+        assert_eq!(world.get::<Xp>(player).unwrap().amount, 0);
+    }
+
+    #[test]
+    fn drops_can_be_cancelled() {
+        let (mut world, player) = world_with_player();
+        world.add_observer(|mut dropping: On<'_, '_, DroppingDeathItems>| {
+            dropping.cancelled = true;
+        });
+
+        drop_death_items(&mut world, player);
+
+        assert_eq!(world.query::<&DespawnAt>().iter(&world).count(), 0);
+        assert_eq!(
+            world
+                .get::<PlayerInventory>(player)
+                .unwrap()
+                .items()
+                .count(),
+            2
+        );
+        assert_eq!(world.get::<Xp>(player).unwrap().amount, 30);
+    }
+
+    #[test]
+    fn xp_is_split_into_orbs() {
+        assert!(split_into_orbs(0).is_empty());
+        assert_eq!(split_into_orbs(100), [73, 17, 7, 3]);
+        assert_eq!(split_into_orbs(2500).iter().sum::<u16>(), 2500);
+    }
+}
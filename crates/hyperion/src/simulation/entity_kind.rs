@@ -132,3 +132,60 @@ pub enum EntityKind {
     FishingBobber = 123,
     Gui = 124,
 }
+
+impl EntityKind {
+    /// The [`TrackingRange`] of entities of this kind without one, based on the vanilla client
+    /// tracking ranges
+    #[must_use]
+    pub const fn default_tracking_range(self) -> TrackingRange {
+        let chunks: f32 = match self {
+            Self::Player => 16.0,
+            Self::EnderDragon | Self::Ghast | Self::Wither => 10.0,
+            Self::Item | Self::ExperienceOrb | Self::ItemFrame | Self::GlowItemFrame => 6.0,
+            Self::Arrow
+            | Self::SpectralArrow
+            | Self::Trident
+            | Self::Snowball
+            | Self::Egg
+            | Self::EnderPearl
+            | Self::Potion
+            | Self::ExperienceBottle
+            | Self::Fireball
+            | Self::SmallFireball
+            | Self::DragonFireball
+            | Self::WitherSkull
+            | Self::ShulkerBullet
+            | Self::LlamaSpit
+            | Self::FireworkRocket
+            | Self::FishingBobber
+            | Self::EvokerFangs => 4.0,
+            _ => 8.0,
+        };
+        TrackingRange(chunks * 16.0)
+    }
+}
+
+/// The distance in blocks within which players receive the spawn and updates of an entity.
+///
+/// Entities without this component use [`EntityKind::default_tracking_range`]. Players are
+/// subscribed to an entity's channel while they are within this range of it, measured per chunk
+/// along each axis, and receive the entity's unsubscribe packets once they leave it.
+#[derive(Component, Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+pub struct TrackingRange(pub f32);
+
+impl TrackingRange {
+    /// Returns the range in whole chunks, rounded up and at most `max`, which is usually the view
+    /// distance since players cannot see entities beyond it anyway
+    #[must_use]
+    #[expect(clippy::cast_possible_truncation)]
+    pub fn chunks(self, max: i16) -> i16 {
+        (self.0 / 16.0).ceil().clamp(0.0, f32::from(max.max(0))) as i16
+    }
+}
+
+impl From<EntityKind> for TrackingRange {
+    fn from(kind: EntityKind) -> Self {
+        kind.default_tracking_range()
+    }
+}
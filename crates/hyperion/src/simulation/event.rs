@@ -1,5 +1,6 @@
 use bevy_ecs::{entity::Entity, message::Message};
 use glam::{IVec3, Vec3};
+use hyperion_inventory::SlotChangeCause;
 use valence_generated::block::BlockState;
 use valence_protocol::{
     Hand, Ident, ItemStack,
@@ -10,7 +11,7 @@ use valence_protocol::{
 };
 
 use super::blocks::RayCollision;
-use crate::simulation::skin::PlayerSkin;
+use crate::simulation::{skin::PlayerSkin, world::WorldId};
 
 // TODO: Check that all of these events are needed
 
@@ -55,6 +56,8 @@ pub struct AttackEntity {
 
 #[derive(Message, Copy, Clone, Debug, PartialEq, Eq)]
 pub struct StartDestroyBlock {
+    /// The world of the block, which is the world the player was in
+    pub world: WorldId,
     pub position: IVec3,
     pub from: Entity,
     pub sequence: i32,
@@ -62,13 +65,20 @@ pub struct StartDestroyBlock {
 
 #[derive(Message, Copy, Clone, Debug, PartialEq, Eq)]
 pub struct DestroyBlock {
+    /// The world of the block, which is the world the player was in
+    pub world: WorldId,
     pub position: IVec3,
+    /// The block before it was destroyed. Systems reading this message may run after the block
+    /// was already replaced.
+    pub block: BlockState,
     pub from: Entity,
     pub sequence: i32,
 }
 
 #[derive(Message, Copy, Clone, Debug, PartialEq, Eq)]
 pub struct PlaceBlock {
+    /// The world of the block, which is the world the player was in
+    pub world: WorldId,
     pub position: IVec3,
     pub block: BlockState,
     pub from: Entity,
@@ -77,6 +87,8 @@ pub struct PlaceBlock {
 
 #[derive(Message, Copy, Clone, Debug, PartialEq, Eq)]
 pub struct ToggleDoor {
+    /// The world of the block, which is the world the player was in
+    pub world: WorldId,
     pub position: IVec3,
     pub from: Entity,
     pub sequence: i32,
@@ -90,6 +102,18 @@ pub struct SwingArm {
 #[derive(Message, Copy, Clone, Debug)]
 pub struct ReleaseUseItem {
     pub from: Entity,
+    /// How many ticks the item was used for, or 0 if the player was not using an item. See
+    /// [`UsingItem`](crate::simulation::item_use::UsingItem).
+    pub use_ticks: u32,
+}
+
+/// Sent when a player finished eating or drinking an item. One item has already been removed
+/// from the stack.
+#[derive(Message, Clone, Debug)]
+pub struct FoodConsumed {
+    pub player: Entity,
+    /// The item as it was when the player started eating it
+    pub item: ItemStack,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
@@ -167,9 +191,87 @@ pub struct HitGroundEvent {
     pub fall_distance: f32,
 }
 
+/// Sent when a player without food takes starvation damage. The damage has not been applied.
+/// See [`Hunger`](crate::simulation::hunger::Hunger).
+#[derive(Message, Clone, Debug)]
+pub struct StarvationEvent {
+    pub client: Entity,
+    pub damage: f32,
+}
+
 #[derive(Message, Clone, Debug)]
 pub struct InteractEvent {
     pub client: Entity,
     pub hand: Hand,
     pub sequence: i32,
 }
+
+/// Opens the sign editor for `player`, who may then change the text on one side of the sign at
+/// `position`. This is sent automatically after a player places a sign. See
+/// [`EditingSign`](crate::simulation::sign::EditingSign).
+#[derive(Message, Copy, Clone, Debug, PartialEq, Eq)]
+pub struct OpenSignEditor {
+    pub player: Entity,
+    pub position: IVec3,
+    /// Whether the front or the back of the sign is edited
+    pub front: bool,
+}
+
+/// Sent after a player changed the text of a sign. The text has already been stored.
+#[derive(Message, Clone, Debug, PartialEq, Eq)]
+pub struct SignChanged {
+    pub player: Entity,
+    pub position: IVec3,
+    /// The plain text of the four lines, which has been sanitized with
+    /// [`sanitize_sign_line`](crate::simulation::sign::sanitize_sign_line)
+    pub lines: [String; 4],
+}
+
+/// Sent when a player clicks a block which does something when used, such as a lever or a button.
+/// See [`redstone`](crate::simulation::redstone).
+#[derive(Message, Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ActivateBlock {
+    /// The world of the block, which is the world the player was in
+    pub world: WorldId,
+    pub position: IVec3,
+    pub from: Entity,
+    pub sequence: i32,
+}
+
+/// Sent at the start of the tick a block update was scheduled for with
+/// [`ScheduledBlockUpdates`](crate::simulation::blocks::scheduled::ScheduledBlockUpdates)
+#[derive(Message, Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ScheduledBlockUpdate {
+    pub world: WorldId,
+    pub position: IVec3,
+}
+
+/// Sent after a tick for every slot of an inventory which changed during the tick. All changes of a
+/// tick are sent together, after the changes made by game code during `FixedUpdate`.
+#[derive(Message, Clone, Debug, PartialEq)]
+pub struct SlotChanged {
+    /// The entity with the inventory, which is the player unless it is an inventory the player
+    /// opened
+    pub player: Entity,
+    pub slot_index: u16,
+    /// The stack before the first change in the tick
+    pub old: ItemStack,
+    pub new: ItemStack,
+    /// The cause of the first change in the tick
+    pub cause: SlotChangeCause,
+}
+
+/// Sent when a player has been idle for long enough to be marked as AFK. See
+/// [`AfkStatus`](crate::simulation::afk::AfkStatus).
+#[derive(Message, Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PlayerWentAfk {
+    pub player: Entity,
+}
+
+/// Sent when a player who was AFK sends input again
+#[derive(Message, Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PlayerReturned {
+    pub player: Entity,
+    /// How many ticks the player was marked as AFK for
+    pub afk_ticks: i64,
+}
@@ -0,0 +1,341 @@
+//! The game rules which the client needs to know about, see [`GameRules`], and the respawn flow
+//! which depends on them.
+//!
+//! When a player dies, they either respawn immediately if [`GameRules::immediate_respawn`] is set,
+//! or once they press the respawn button on the death screen. Either way, [`PlayerRespawn`] is
+//! triggered exactly once per death, so game code only observes a single event to choose where
+//! the player respawns.
+//!
+//! While [`GameRules::reduced_debug_info`] is set, the spawn position sent to players is the
+//! origin instead of where they are, so that compasses do not reveal the coordinates which the
+//! debug screen hides.
+
+use bevy_app::{App, FixedPostUpdate, FixedUpdate, Plugin};
+use bevy_ecs::{
+    component::Component,
+    entity::Entity,
+    event::EntityEvent,
+    message::MessageReader,
+    query::{With, Without},
+    resource::Resource,
+    schedule::IntoScheduleConfigs,
+    system::{Commands, Local, Query, Res},
+};
+use glam::Vec3;
+use hyperion_utils::Prev;
+use tracing::debug;
+use valence_protocol::{
+    BlockPos,
+    packets::play::{
+        EntityStatusS2c, GameStateChangeS2c, PlayerSpawnPositionS2c,
+        client_status_c2s::ClientStatusC2s, game_state_change_s2c::GameEventKind,
+    },
+};
+#[cfg(feature = "reflect")]
+use {
+    bevy_ecs::reflect::{ReflectComponent, ReflectEvent, ReflectResource},
+    bevy_reflect::Reflect,
+};
+
+use crate::{
+    ingress,
+    net::{Compose, ConnectionId, SendResultExt},
+    simulation::{
+        Position, Yaw, metadata::living_entity::Health, minecraft_id::MinecraftIdRegistry,
+        packet::play, packet_state,
+    },
+};
+
+/// The entity status which enables the reduced debug screen of the player it is sent to
+const ENABLE_REDUCED_DEBUG_INFO: u8 = 22;
+
+/// The entity status which disables the reduced debug screen of the player it is sent to
+const DISABLE_REDUCED_DEBUG_INFO: u8 = 23;
+
+/// The game rules which affect clients. They are sent to players when they join and again
+/// whenever the resource changes.
+#[derive(Resource, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Resource))]
+pub struct GameRules {
+    /// `doImmediateRespawn`: players respawn as soon as they die instead of seeing the death
+    /// screen
+    pub immediate_respawn: bool,
+    /// `reducedDebugInfo`: the debug screen of clients hides coordinates and other details. The
+    /// spawn position sent to players is hidden as well, see the
+    /// [module documentation](self).
+    pub reduced_debug_info: bool,
+    /// `showDeathMessages`: deaths are announced with a message. Hyperion does not send death
+    /// messages by itself, so this is for game code which does.
+    pub show_death_messages: bool,
+}
+
+impl Default for GameRules {
+    /// The defaults of vanilla, except that players respawn immediately because Hyperion
+    /// disables the death screen unless a game enables it
+    fn default() -> Self {
+        Self {
+            immediate_respawn: true,
+            reduced_debug_info: false,
+            show_death_messages: true,
+        }
+    }
+}
+
+impl GameRules {
+    /// The packet telling clients whether to show the death screen
+    #[must_use]
+    pub fn respawn_screen_packet(&self) -> GameStateChangeS2c {
+        GameStateChangeS2c {
+            kind: GameEventKind::EnableRespawnScreen,
+            value: if self.immediate_respawn { 1.0 } else { 0.0 },
+        }
+    }
+
+    /// The packet telling the player with `entity_id` whether to reduce their debug screen
+    #[must_use]
+    pub const fn debug_info_packet(&self, entity_id: i32) -> EntityStatusS2c {
+        EntityStatusS2c {
+            entity_id,
+            entity_status: if self.reduced_debug_info {
+                ENABLE_REDUCED_DEBUG_INFO
+            } else {
+                DISABLE_REDUCED_DEBUG_INFO
+            },
+        }
+    }
+
+    /// The packet telling a player at `position` where the spawn is, which compasses point to
+    #[must_use]
+    pub fn spawn_position_packet(&self, position: Vec3, angle: f32) -> PlayerSpawnPositionS2c {
+        let position = if self.reduced_debug_info {
+            BlockPos::new(0, 0, 0)
+        } else {
+            position.as_dvec3().into()
+        };
+
+        PlayerSpawnPositionS2c { position, angle }
+    }
+}
+
+/// Marks a dead player who has not respawned yet because they still see the death screen
+#[derive(Component, Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+pub struct AwaitingRespawn;
+
+/// Triggered when a dead player respawns. Observers choose where the player respawns and restore
+/// their state. See the [module documentation](self).
+#[derive(EntityEvent, Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Event))]
+pub struct PlayerRespawn {
+    pub entity: Entity,
+}
+
+fn sync_game_rules(
+    rules: Res<'_, GameRules>,
+    mut sent: Local<'_, Option<GameRules>>,
+    compose: Res<'_, Compose>,
+    ids: Res<'_, MinecraftIdRegistry>,
+    players: Query<'_, '_, (Entity, &ConnectionId, &Position, &Yaw), With<packet_state::Play>>,
+) {
+    // Players who join receive the current rules with their join packet
+    let Some(previous) = sent.replace(*rules) else {
+        return;
+    };
+
+    if previous.immediate_respawn != rules.immediate_respawn {
+        compose
+            .broadcast(&rules.respawn_screen_packet())
+            .send()
+            .unwrap();
+    }
+
+    if previous.reduced_debug_info != rules.reduced_debug_info {
+        for (entity, &connection_id, position, yaw) in &players {
+            compose
+                .unicast(
+                    &rules.debug_info_packet(ids.minecraft_id(entity)),
+                    connection_id,
+                )
+                .unwrap_or_disconnected();
+            compose
+                .unicast(
+                    &rules.spawn_position_packet(**position, **yaw),
+                    connection_id,
+                )
+                .unwrap_or_disconnected();
+        }
+    }
+}
+
+fn detect_deaths(
+    rules: Res<'_, GameRules>,
+    query: Query<
+        '_,
+        '_,
+        (Entity, &Health, &Prev<Health>),
+        (
+            With<ConnectionId>,
+            With<packet_state::Play>,
+            Without<AwaitingRespawn>,
+        ),
+    >,
+    mut commands: Commands<'_, '_>,
+) {
+    for (entity, health, prev) in &query {
+        if prev.is_dead() || !health.is_dead() {
+            continue;
+        }
+
+        if rules.immediate_respawn {
+            commands.trigger(PlayerRespawn { entity });
+        } else {
+            commands.entity(entity).insert(AwaitingRespawn);
+        }
+    }
+}
+
+fn receive_respawn_requests(
+    mut packets: MessageReader<'_, '_, play::ClientStatus>,
+    query: Query<'_, '_, (), With<AwaitingRespawn>>,
+    mut commands: Commands<'_, '_>,
+) {
+    for packet in packets.read() {
+        if !matches!(**packet, ClientStatusC2s::PerformRespawn) {
+            continue;
+        }
+
+        let entity = packet.sender();
+
+        // Clients also request a respawn after an immediate respawn, which already happened
+        if !query.contains(entity) {
+            debug!("ignoring respawn request of {entity:?}, which is not awaiting a respawn");
+            continue;
+        }
+
+        commands.entity(entity).remove::<AwaitingRespawn>();
+        commands.trigger(PlayerRespawn { entity });
+    }
+}
+
+/// Sends the [`GameRules`] to players and triggers [`PlayerRespawn`]
+pub struct GameRulesPlugin;
+
+impl Plugin for GameRulesPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GameRules>();
+        app.add_systems(
+            FixedUpdate,
+            (sync_game_rules, receive_respawn_requests).after(ingress::decode::play),
+        );
+        app.add_systems(FixedPostUpdate, detect_deaths);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_ecs::{observer::On, system::ResMut, world::World};
+    use hyperion_utils::track_prev;
+
+    use super::*;
+    use crate::net::ProxyId;
+
+    #[derive(Resource, Default)]
+    struct Respawned(Vec<Entity>);
+
+    fn app(rules: GameRules) -> (App, Entity) {
+        let mut app = App::new();
+        track_prev::<Health>(&mut app);
+
+        let world = app.world_mut();
+        world.insert_resource(rules);
+        world.init_resource::<Respawned>();
+        world.add_observer(
+            |respawn: On<'_, '_, PlayerRespawn>, mut respawned: ResMut<'_, Respawned>| {
+                respawned.0.push(respawn.entity);
+            },
+        );
+
+        let player = world
+            .spawn((
+                ConnectionId::new(1, ProxyId::new(0)),
+                packet_state::Play,
+                Health::default(),
+            ))
+            .id();
+        world.flush();
+        (app, player)
+    }
+
+    fn kill(world: &mut World, player: Entity) {
+        world.get_mut::<Health>(player).unwrap().damage(20.0);
+        world.run_system_cached(detect_deaths).unwrap();
+        world.get_mut::<Prev<Health>>(player).unwrap().damage(20.0);
+        // Staying dead does not count as dying again
+        world.run_system_cached(detect_deaths).unwrap();
+    }
+
+    #[test]
+    fn immediate_respawns_do_not_wait() {
+        let (mut app, player) = app(GameRules {
+            immediate_respawn: true,
+            ..GameRules::default()
+        });
+        let world = app.world_mut();
+
+        kill(world, player);
+
+        assert_eq!(world.resource::<Respawned>().0, [player]);
+        assert!(world.get::<AwaitingRespawn>(player).is_none());
+    }
+
+    #[test]
+    fn players_wait_on_the_death_screen() {
+        let (mut app, player) = app(GameRules {
+            immediate_respawn: false,
+            ..GameRules::default()
+        });
+        let world = app.world_mut();
+
+        kill(world, player);
+
+        assert!(world.resource::<Respawned>().0.is_empty());
+        assert!(world.get::<AwaitingRespawn>(player).is_some());
+    }
+
+    #[test]
+    fn packets_match_the_rules() {
+        let rules = GameRules {
+            immediate_respawn: true,
+            reduced_debug_info: true,
+            show_death_messages: false,
+        };
+
+        let packet = rules.respawn_screen_packet();
+        assert!(matches!(packet.kind, GameEventKind::EnableRespawnScreen));
+        assert!((packet.value - 1.0).abs() < f32::EPSILON);
+        assert_eq!(rules.debug_info_packet(5).entity_status, 22);
+
+        let rules = GameRules {
+            immediate_respawn: false,
+            ..GameRules::default()
+        };
+        assert!(rules.respawn_screen_packet().value.abs() < f32::EPSILON);
+        assert_eq!(rules.debug_info_packet(5).entity_status, 23);
+    }
+
+    #[test]
+    fn spawn_position_is_hidden_with_reduced_debug_info() {
+        let position = Vec3::new(12.5, 70.0, -3.5);
+
+        let packet = GameRules::default().spawn_position_packet(position, 90.0);
+        assert_eq!(packet.position, BlockPos::new(12, 70, -4));
+        assert!((packet.angle - 90.0).abs() < f32::EPSILON);
+
+        let rules = GameRules {
+            reduced_debug_info: true,
+            ..GameRules::default()
+        };
+        let packet = rules.spawn_position_packet(position, 90.0);
+        assert_eq!(packet.position, BlockPos::new(0, 0, 0));
+    }
+}
@@ -8,33 +8,40 @@ use bevy_ecs::{
 };
 use glam::{DVec3, IVec3, Vec3};
 use hyperion_inventory::PlayerInventory;
-use hyperion_utils::next_lowest;
+use hyperion_utils::{Args, Locale, Localizer, next_lowest};
 use tracing::{error, warn};
 use valence_generated::{
     block::{BlockKind, BlockState, PropName},
     item::ItemKind,
 };
 use valence_protocol::{
-    Hand, VarInt,
+    GameMode, Hand, VarInt,
     packets::play::{
-        GameMessageS2c, OpenWrittenBookS2c, UpdatePlayerAbilitiesC2s,
+        BlockUpdateS2c, GameMessageS2c, OpenWrittenBookS2c, UpdatePlayerAbilitiesC2s,
         client_command_c2s::ClientCommand, player_action_c2s::PlayerAction,
     },
 };
 use valence_text::IntoText;
 
 use crate::{
-    ingress,
-    net::{Compose, ConnectionId},
+    Tick, ingress,
+    net::{Compose, ConnectionId, SendResultExt},
     simulation::{
         Aabb, ConfirmBlockSequences, EntitySize, Flight, MovementTracking, PendingTeleportation,
         Pitch, Position, Yaw, aabb,
         animation::{self, ActiveAnimation},
-        block_bounds,
-        blocks::Blocks,
+        blocks::{Blocks, collision, fake::FakeBlocks},
         event,
+        hunger::{self, Hunger, HungerConfig},
+        inventory::{self, CreativeItemLimits, validate_creative_item},
+        item_use::UsingItem,
+        join::PlayerGameMode,
         metadata::{entity::Pose, living_entity::HandStates},
         packet::{OrderedPacketRef, play},
+        redstone,
+        region::{ProtectedRegions, RegionFlag, RegionGroup, send_denied_message},
+        statistics::{CustomStatistic, Statistics},
+        world::{WorldBlocks, WorldId},
     },
 };
 
@@ -54,14 +61,27 @@ fn position_and_look_updates(
         '_,
         '_,
         (
-            Query<'_, '_, (&EntitySize, &mut MovementTracking, &mut Position, &Yaw)>,
+            Query<
+                '_,
+                '_,
+                (
+                    &EntitySize,
+                    &mut MovementTracking,
+                    &mut Position,
+                    &Yaw,
+                    Option<&WorldId>,
+                    Option<&mut Statistics>,
+                    Option<&mut Hunger>,
+                ),
+            >,
             Query<'_, '_, (&mut Yaw, &mut Pitch)>,
             Query<'_, '_, &mut Position>,
         ),
     >,
     teleport_query: Query<'_, '_, &PendingTeleportation>,
-    blocks: Res<'_, Blocks>,
+    blocks: WorldBlocks<'_>,
     compose: Res<'_, Compose>,
+    localizer: Localizer<'_, '_>,
     mut commands: Commands<'_, '_>,
 ) {
     let mut full_reader = full_reader.read().map(OrderedPacketRef::from).peekable();
@@ -74,7 +94,6 @@ fn position_and_look_updates(
         .read()
         .map(OrderedPacketRef::from)
         .peekable();
-    let blocks = blocks.into_inner();
     let compose = compose.into_inner();
 
     loop {
@@ -87,8 +106,9 @@ fn position_and_look_updates(
                     packet.sender(),
                     packet.connection_id(),
                     queries.p0(),
-                    blocks,
+                    &blocks,
                     compose,
+                    &localizer,
                     &mut commands,
                     packet.position.as_vec3(),
                     packet.on_ground,
@@ -111,8 +131,9 @@ fn position_and_look_updates(
                     packet.sender(),
                     packet.connection_id(),
                     queries.p0(),
-                    blocks,
+                    &blocks,
                     compose,
+                    &localizer,
                     &mut commands,
                     packet.position.as_vec3(),
                     packet.on_ground,
@@ -194,26 +215,44 @@ fn position_and_look_updates(
 fn change_position_or_correct_client(
     client: Entity,
     connection_id: ConnectionId,
-    mut query: Query<'_, '_, (&EntitySize, &mut MovementTracking, &mut Position, &Yaw)>,
-    blocks: &Blocks,
+    mut query: Query<
+        '_,
+        '_,
+        (
+            &EntitySize,
+            &mut MovementTracking,
+            &mut Position,
+            &Yaw,
+            Option<&WorldId>,
+            Option<&mut Statistics>,
+            Option<&mut Hunger>,
+        ),
+    >,
+    blocks: &WorldBlocks<'_>,
     compose: &Compose,
+    localizer: &Localizer<'_, '_>,
     commands: &mut Commands<'_, '_>,
     proposed: Vec3,
     on_ground: bool,
 ) {
-    let (&size, mut tracking, mut pose, yaw) = match query.get_mut(client) {
-        Ok(data) => data,
-        Err(e) => {
-            error!("change_position_or_correct_client failed: query failed: {e}");
-            return;
-        }
+    let (&size, mut tracking, mut pose, yaw, world, statistics, hunger) =
+        match query.get_mut(client) {
+            Ok(data) => data,
+            Err(e) => {
+                error!("change_position_or_correct_client failed: query failed: {e}");
+                return;
+            }
+        };
+
+    let Some(blocks) = blocks.get(world) else {
+        error!("change_position_or_correct_client failed: world {world:?} does not exist");
+        return;
     };
 
-    if let Err(e) = try_change_position(proposed, &pose, size, blocks) {
+    if let Err(key) = try_change_position(proposed, &pose, size, blocks) {
         // Send error message to player
-        let msg = format!("§c{e}");
         let pkt = GameMessageS2c {
-            chat: msg.into_cow_text(),
+            chat: localizer.tr(client, key, &Args::new()).into_cow_text(),
             overlay: false,
         };
 
@@ -232,6 +271,14 @@ fn change_position_or_correct_client(
     if y_delta > 0. && tracking.was_on_ground && !on_ground {
         tracking.server_velocity.y = 0.419_999_986_886_978_15;
 
+        if let Some(mut statistics) = statistics {
+            statistics.increment(CustomStatistic::Jump);
+        }
+
+        if let Some(mut hunger) = hunger {
+            hunger::exhaust_jump(&mut hunger, tracking.sprinting);
+        }
+
         if tracking.sprinting {
             let smth = yaw.yaw * 0.017_453_292;
             tracking.server_velocity += DVec3::new(
@@ -258,60 +305,36 @@ fn change_position_or_correct_client(
 /// ```
 /// Only denies movement if starting outside a block and moving into a block.
 /// This prevents players from glitching into blocks while allowing them to move out.
+///
+/// The error is the translation key of the message shown to the player.
 fn try_change_position(
     proposed: Vec3,
     position: &Position,
     size: EntitySize,
     blocks: &Blocks,
-) -> anyhow::Result<()> {
+) -> Result<(), &'static str> {
     // Only check collision if we're starting outside a block
     if !has_block_collision(position, size, blocks) && has_block_collision(&proposed, size, blocks)
     {
-        return Err(anyhow::anyhow!("Cannot move into solid blocks"));
+        return Err("movement.into_solid_blocks");
     }
 
     Ok(())
 }
 
+/// Whether the feet of an entity at `position` rest on the collision shape of a block
 #[must_use]
-#[allow(clippy::cast_possible_truncation)]
 pub fn is_grounded(position: &Vec3, blocks: &Blocks) -> bool {
-    // Calculate the block position by flooring the x and z coordinates
-    let block_x = position.x as i32;
-    let block_y = (position.y.ceil() - 1.0) as i32; // Check the block directly below
-    let block_z = position.z as i32;
-
-    // Check if the block at the calculated position is not air
-    let is_air = blocks
-        .get_block(IVec3::new(block_x, block_y, block_z))
-        .is_none_or(BlockState::is_air);
+    let feet = Aabb::new(
+        *position - Vec3::new(0.001, 0.01, 0.001),
+        *position + Vec3::new(0.001, 0.0, 0.001),
+    );
 
-    !is_air
+    blocks.collides(&feet)
 }
 
-fn has_block_collision(position: &Vec3, size: EntitySize, blocks: &Blocks) -> bool {
-    use std::ops::ControlFlow;
-
-    let (min, max) = block_bounds(*position, size);
-    let shrunk = aabb(*position, size).shrink(0.01);
-
-    let res = blocks.get_blocks(min, max, |pos, block| {
-        #[expect(clippy::cast_precision_loss)]
-        let pos = Vec3::new(pos.x as f32, pos.y as f32, pos.z as f32);
-
-        for aabb in block.collision_shapes() {
-            let aabb = Aabb::new(aabb.min().as_vec3(), aabb.max().as_vec3());
-            let aabb = aabb.move_by(pos);
-
-            if shrunk.collides(&aabb) {
-                return ControlFlow::Break(false);
-            }
-        }
-
-        ControlFlow::Continue(())
-    });
-
-    res.is_break()
+pub(crate) fn has_block_collision(position: &Vec3, size: EntitySize, blocks: &Blocks) -> bool {
+    blocks.collides(&aabb(*position, size).shrink(0.01))
 }
 
 fn hand_swing(
@@ -344,15 +367,85 @@ fn player_action(
     mut start_destroy_writer: MessageWriter<'_, event::StartDestroyBlock>,
     mut stop_destroy_writer: MessageWriter<'_, event::DestroyBlock>,
     mut release_writer: MessageWriter<'_, event::ReleaseUseItem>,
+    using_query: Query<'_, '_, &UsingItem>,
+    fake_query: Query<'_, '_, &FakeBlocks>,
+    mut confirm_query: Query<'_, '_, &mut ConfirmBlockSequences>,
+    region_query: Query<'_, '_, (Option<&RegionGroup>, Option<&WorldId>)>,
+    regions: Res<'_, ProtectedRegions>,
+    blocks: WorldBlocks<'_>,
+    tick: Res<'_, Tick>,
+    compose: Res<'_, Compose>,
+    localizer: Localizer<'_, '_>,
     mut commands: Commands<'_, '_>,
 ) {
     for packet in packets.read() {
         let sequence = packet.sequence.0;
         let position = IVec3::new(packet.position.x, packet.position.y, packet.position.z);
 
+        let is_digging = matches!(
+            packet.action,
+            PlayerAction::StartDestroyBlock
+                | PlayerAction::StopDestroyBlock
+                | PlayerAction::AbortDestroyBlock
+        );
+
+        if is_digging && let Ok(mut sequences) = confirm_query.get_mut(packet.sender()) {
+            sequences.push(sequence);
+        }
+
+        let fake_block = fake_query
+            .get(packet.sender())
+            .ok()
+            .and_then(|fake_blocks| fake_blocks.get(position));
+
+        // Digging a fake block leaves the real block alone, so the client is told that the
+        // fake block is still there
+        if let Some(fake_block) = fake_block
+            && is_digging
+        {
+            let pkt = BlockUpdateS2c {
+                position: packet.position,
+                block_id: fake_block,
+            };
+
+            compose
+                .unicast(&pkt, packet.connection_id())
+                .unwrap_or_disconnected();
+            continue;
+        }
+
+        let is_breaking = matches!(
+            packet.action,
+            PlayerAction::StartDestroyBlock | PlayerAction::StopDestroyBlock
+        );
+
+        let (group, world) = region_query.get(packet.sender()).unwrap_or_default();
+        let world_id = world.copied().unwrap_or_default();
+
+        if is_breaking {
+            let group = group.copied().unwrap_or_default();
+
+            if !regions.allows(RegionFlag::Build, group, world_id, position) {
+                // The sequence was pushed above, so the client undoes the change once it is
+                // acknowledged. Survival players start and stop breaking each block, so they are
+                // only told once.
+                if matches!(packet.action, PlayerAction::StartDestroyBlock) {
+                    send_denied_message(
+                        &compose,
+                        &localizer,
+                        packet.sender(),
+                        packet.connection_id(),
+                        RegionFlag::Build.denied_message(),
+                    );
+                }
+                continue;
+            }
+        }
+
         match packet.action {
             PlayerAction::StartDestroyBlock => {
                 let event = event::StartDestroyBlock {
+                    world: world_id,
                     position,
                     from: packet.sender(),
                     sequence,
@@ -360,8 +453,15 @@ fn player_action(
                 start_destroy_writer.write(event);
             }
             PlayerAction::StopDestroyBlock => {
+                let block = blocks
+                    .get(world)
+                    .and_then(|blocks| blocks.get_block(position))
+                    .unwrap_or(BlockState::AIR);
+
                 let event = event::DestroyBlock {
+                    world: world_id,
                     position,
+                    block,
                     from: packet.sender(),
                     sequence,
                 };
@@ -369,14 +469,24 @@ fn player_action(
                 stop_destroy_writer.write(event);
             }
             PlayerAction::ReleaseUseItem => {
+                let use_ticks = using_query
+                    .get(packet.sender())
+                    .map_or(0, |using| using.ticks(tick.0));
+
                 let event = event::ReleaseUseItem {
                     from: packet.sender(),
+                    use_ticks,
                 };
 
-                commands.entity(packet.sender()).insert(HandStates::new(0));
+                commands
+                    .entity(packet.sender())
+                    .remove::<UsingItem>()
+                    .insert(HandStates::new(0));
 
                 release_writer.write(event);
             }
+            // Handled by the inventory plugin
+            PlayerAction::SwapItemWithOffhand => {}
             action => error!("failed to handle player action: unimplemented {action:?}"),
         }
 
@@ -387,10 +497,20 @@ fn player_action(
 // for sneaking/crouching/etc
 fn client_command(
     mut packets: MessageReader<'_, '_, play::ClientCommand>,
-    mut query: Query<'_, '_, (&mut Pose, &mut EntitySize, &mut MovementTracking)>,
+    mut query: Query<
+        '_,
+        '_,
+        (
+            &mut Pose,
+            &mut EntitySize,
+            &mut MovementTracking,
+            Option<&Hunger>,
+        ),
+    >,
+    hunger_config: Res<'_, HungerConfig>,
 ) {
     for packet in packets.read() {
-        let (mut pose, mut size, mut tracking) = match query.get_mut(packet.sender()) {
+        let (mut pose, mut size, mut tracking, hunger) = match query.get_mut(packet.sender()) {
             Ok(data) => data,
             Err(e) => {
                 error!("failed to handle client command: query failed: {e}");
@@ -408,6 +528,11 @@ fn client_command(
                 size.height = 1.8;
             }
             ClientCommand::StartSprinting => {
+                // Players without enough food cannot sprint
+                if hunger_config.enabled && hunger.is_some_and(|hunger| !hunger.can_sprint()) {
+                    continue;
+                }
+
                 tracking.sprinting = true;
             }
             ClientCommand::StopSprinting => {
@@ -432,26 +557,28 @@ fn client_command(
 fn player_interact_item(
     mut packets: MessageReader<'_, '_, play::PlayerInteractItem>,
     compose: Res<'_, Compose>,
-    query: Query<'_, '_, &PlayerInventory>,
+    mut query: Query<'_, '_, (&PlayerInventory, &mut ConfirmBlockSequences)>,
     mut interact_event_writer: MessageWriter<'_, event::InteractEvent>,
     mut item_interact_writer: MessageWriter<'_, event::ItemInteract>,
 ) {
     for packet in packets.read() {
-        let inventory = match query.get(packet.sender()) {
-            Ok(inventory) => inventory,
+        let (inventory, mut confirm_block_sequences) = match query.get_mut(packet.sender()) {
+            Ok(data) => data,
             Err(e) => {
                 error!("failed to process player interact item: query failed: {e}");
                 continue;
             }
         };
 
+        confirm_block_sequences.push(packet.sequence.0);
+
         let event = event::InteractEvent {
             client: packet.sender(),
             hand: packet.hand,
             sequence: packet.sequence.0,
         };
 
-        let cursor = &inventory.get_cursor().stack;
+        let cursor = &inventory.held(packet.hand).stack;
 
         if !cursor.is_empty() {
             let event = event::ItemInteract {
@@ -465,7 +592,7 @@ fn player_interact_item(
                         &OpenWrittenBookS2c { hand: packet.hand },
                         packet.connection_id(),
                     )
-                    .unwrap();
+                    .unwrap_or_disconnected();
             }
             item_interact_writer.write(event);
         }
@@ -481,14 +608,20 @@ fn player_interact_block(
         '_,
         (
             &mut ConfirmBlockSequences,
-            &PlayerInventory,
+            &mut PlayerInventory,
             &Position,
             &EntitySize,
+            Option<&WorldId>,
+            Option<&RegionGroup>,
         ),
     >,
-    blocks: Res<'_, Blocks>,
+    blocks: WorldBlocks<'_>,
+    regions: Res<'_, ProtectedRegions>,
+    compose: Res<'_, Compose>,
+    localizer: Localizer<'_, '_>,
     mut toggle_door_writer: MessageWriter<'_, event::ToggleDoor>,
     mut place_block_writer: MessageWriter<'_, event::PlaceBlock>,
+    mut activate_block_writer: MessageWriter<'_, event::ActivateBlock>,
 ) {
     for packet in packets.read() {
         // PlayerInteractBlock contains:
@@ -499,7 +632,7 @@ fn player_interact_block(
         // - inside_block: bool (whether the player's head is inside a block)
         // - sequence: VarInt (sequence number for this interaction)
 
-        let (mut confirm_block_sequences, inventory, client_position, size) =
+        let (mut confirm_block_sequences, mut inventory, client_position, size, world, group) =
             match query.get_mut(packet.sender()) {
                 Ok(data) => data,
                 Err(e) => {
@@ -517,16 +650,54 @@ fn player_interact_block(
             interacted_block_pos.z,
         );
 
-        let Some(interacted_block) = blocks.get_block(interacted_block_pos_vec) else {
+        let Some(interacted_block) = blocks
+            .get(world)
+            .and_then(|blocks| blocks.get_block(interacted_block_pos_vec))
+        else {
             continue;
         };
 
-        if interacted_block.get(PropName::Open).is_some() {
+        let group = group.copied().unwrap_or_default();
+        let world_id = world.copied().unwrap_or_default();
+        // The sequence was pushed above, so the client undoes a denied change once it is
+        // acknowledged
+        let deny = |flag: RegionFlag| {
+            send_denied_message(
+                &compose,
+                &localizer,
+                packet.sender(),
+                packet.connection_id(),
+                flag.denied_message(),
+            );
+        };
+
+        let is_door = interacted_block.get(PropName::Open).is_some();
+        if (is_door || redstone::is_activatable(interacted_block))
+            && !regions.allows(
+                RegionFlag::Interact,
+                group,
+                world_id,
+                interacted_block_pos_vec,
+            )
+        {
+            deny(RegionFlag::Interact);
+            continue;
+        }
+
+        if is_door {
             // Toggle the open state of a door
             // todo: place block instead of toggling door if the player is crouching and holding a
             // block
 
             toggle_door_writer.write(event::ToggleDoor {
+                world: world_id,
+                position: interacted_block_pos_vec,
+                from: packet.sender(),
+                sequence: packet.sequence.0,
+            });
+        } else if redstone::is_activatable(interacted_block) {
+            activate_block_writer.write(event::ActivateBlock {
+                world: world_id,
                 position: interacted_block_pos_vec,
                 from: packet.sender(),
                 sequence: packet.sequence.0,
@@ -534,7 +705,7 @@ fn player_interact_block(
         } else {
             // Attempt to place a block
 
-            let held = &inventory.get_cursor().stack;
+            let held = &inventory.held(Hand::Main).stack;
 
             if held.is_empty() {
                 continue;
@@ -552,23 +723,22 @@ fn player_interact_block(
             let position = interacted_block_pos.get_in_direction(packet.face);
             let position = IVec3::new(position.x, position.y, position.z);
 
-            let position_dvec3 = position.as_vec3();
-
             // todo(hack): technically players can do some crazy position stuff to abuse this probably
             let player_aabb = aabb(**client_position, *size);
 
-            let collides_player = block_state
-                .collision_shapes()
-                .map(|aabb| {
-                    Aabb::new(aabb.min().as_vec3(), aabb.max().as_vec3()).move_by(position_dvec3)
-                })
-                .any(|block_aabb| Aabb::overlap(&block_aabb, &player_aabb).is_some());
+            if collision::collides(block_state, position, &player_aabb) {
+                continue;
+            }
 
-            if collides_player {
+            if !regions.allows(RegionFlag::Build, group, world_id, position) {
+                deny(RegionFlag::Build);
+                // Survival clients already took the block out of the held stack
+                inventory.held_mut(packet.hand).changed = true;
                 continue;
             }
 
             place_block_writer.write(event::PlaceBlock {
+                world: world_id,
                 position,
                 from: packet.sender(),
                 sequence: packet.sequence.0,
@@ -580,30 +750,91 @@ fn player_interact_block(
 
 fn creative_inventory_action(
     mut packets: MessageReader<'_, '_, play::CreativeInventoryAction>,
-    mut query: Query<'_, '_, &mut PlayerInventory>,
+    mut query: Query<
+        '_,
+        '_,
+        (
+            &mut PlayerInventory,
+            &Position,
+            Option<&PlayerGameMode>,
+            Option<&WorldId>,
+        ),
+    >,
+    limits: Res<'_, CreativeItemLimits>,
+    mut commands: Commands<'_, '_>,
 ) {
     for packet in packets.read() {
-        // TODO: Verify that the player is in creative mode
+        let player = packet.sender();
+        let (mut inventory, position, game_mode, world) = match query.get_mut(player) {
+            Ok(data) => data,
+            Err(e) => {
+                error!("failed to handle creative inventory action: query failed: {e}");
+                continue;
+            }
+        };
 
-        let Ok(slot) = u16::try_from(packet.slot) else {
-            warn!("invalid slot {}", packet.slot);
-            continue;
+        let stack = &packet.clicked_item;
+        let valid = if game_mode.is_none_or(|game_mode| game_mode.0 != GameMode::Creative) {
+            warn!("{player} sent a creative inventory action while not in creative mode");
+            false
+        } else if let Err(e) = validate_creative_item(stack, &limits) {
+            warn!("{player} sent an invalid creative item: {e}");
+            false
+        } else {
+            true
         };
 
-        let mut inventory = match query.get_mut(packet.sender()) {
-            Ok(inventory) => inventory,
-            Err(e) => {
-                error!("failed to handle creative inventory action: query failed: {e}");
+        // Slot -1 drops the item
+        if packet.slot == -1 {
+            if valid && !stack.is_empty() {
+                let stack = stack.clone();
+                let position = **position;
+                let world_id = world.copied().unwrap_or(WorldId::PRIMARY);
+
+                commands.queue(move |world: &mut World| {
+                    inventory::spawn_item(world, stack, position, world_id);
+                });
+            }
+            continue;
+        }
+
+        let slot = match u16::try_from(packet.slot) {
+            Ok(slot @ 1..=PlayerInventory::OFFHAND_SLOT) => slot,
+            _ => {
+                warn!(
+                    "{player} sent a creative inventory action for invalid slot {}",
+                    packet.slot
+                );
                 continue;
             }
         };
 
-        if let Err(e) = inventory.set(slot, packet.clicked_item.clone()) {
+        if !valid {
+            // Marking the slot as changed sends it again, which undoes the change on the client
+            inventory
+                .get_mut(slot)
+                .expect("slot index is in bounds")
+                .changed = true;
+            continue;
+        }
+
+        if let Err(e) = inventory.by_player().set(slot, stack.clone()) {
             error!("failed to handle creative inventory action: inventory set failed: {e}");
         }
     }
 }
 
+fn client_settings(
+    mut packets: MessageReader<'_, '_, play::ClientSettings>,
+    mut commands: Commands<'_, '_>,
+) {
+    for packet in packets.read() {
+        commands
+            .entity(packet.sender())
+            .insert(Locale::new(packet.locale));
+    }
+}
+
 fn player_abilities(
     mut packets: MessageReader<'_, '_, play::UpdatePlayerAbilities>,
     mut query: Query<'_, '_, &mut Flight>,
@@ -638,6 +869,7 @@ impl Plugin for HandlersPlugin {
                 player_interact_item,
                 player_interact_block,
                 creative_inventory_action,
+                client_settings,
                 player_abilities,
             )
                 .after(ingress::decode::play),
@@ -0,0 +1,197 @@
+//! Telling apart players who join for the first time, for welcome flows such as tutorials or
+//! starter items.
+//!
+//! [`PlayerHistoryPlugin`] records every join in the [`PlayerHistory`]. Players who have never
+//! joined before get [`FirstJoin`] when they are authenticated, so observers of the later
+//! [join phases](crate::simulation::join) can already see it.
+//!
+//! [`PlayerFirstJoined`] is triggered once such a player is in the play state and their first join
+//! has been committed to the database. If the server crashes before the commit, the next join is
+//! a first join again, so the event is never triggered twice for the same player and can be used
+//! to grant starter items.
+
+use std::time::SystemTime;
+
+use bevy_app::{App, FixedUpdate, Plugin};
+use bevy_ecs::{
+    component::Component,
+    entity::Entity,
+    event::EntityEvent,
+    lifecycle::Remove,
+    name::Name,
+    observer::On,
+    query::With,
+    schedule::IntoScheduleConfigs,
+    system::{Commands, Query, Res},
+};
+use tracing::error;
+#[cfg(feature = "reflect")]
+use {
+    bevy_ecs::reflect::{ReflectComponent, ReflectEvent},
+    bevy_reflect::Reflect,
+};
+
+use crate::{
+    AsyncRuntime,
+    simulation::{Uuid, join::PlayerAuthenticated, packet_state},
+    storage::{LocalDb, PlayerHistory},
+};
+
+/// Marks a player who joined for the first time. The marker stays for the whole session.
+#[derive(Component, Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+pub struct FirstJoin;
+
+/// Marks a player with [`FirstJoin`] for whom [`PlayerFirstJoined`] has not been triggered yet
+#[derive(Component, Copy, Clone, Debug, Default, PartialEq, Eq)]
+struct AwaitingWelcome;
+
+/// Triggered for players with [`FirstJoin`] once they are in the play state and their first join
+/// has been committed, which is usually a tick after
+/// [`PlayerJoined`](crate::simulation::join::PlayerJoined)
+#[derive(EntityEvent, Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Event))]
+pub struct PlayerFirstJoined {
+    pub entity: Entity,
+}
+
+fn record_join(
+    authenticated: On<'_, '_, PlayerAuthenticated>,
+    history: Res<'_, PlayerHistory>,
+    query: Query<'_, '_, (&Uuid, &Name)>,
+    mut commands: Commands<'_, '_>,
+) {
+    let Ok((uuid, name)) = query.get(authenticated.entity) else {
+        return;
+    };
+
+    match history.record_join(uuid.0, name.as_str(), SystemTime::now()) {
+        Ok(record) if record.is_first_join() => {
+            commands
+                .entity(authenticated.entity)
+                .insert((FirstJoin, AwaitingWelcome));
+        }
+        Ok(_) => {}
+        Err(e) => error!("failed to record join of player {}: {e}", uuid.0),
+    }
+}
+
+fn welcome(
+    history: Res<'_, PlayerHistory>,
+    query: Query<'_, '_, (Entity, &Uuid), (With<AwaitingWelcome>, With<packet_state::Play>)>,
+    mut commands: Commands<'_, '_>,
+) {
+    for (entity, uuid) in &query {
+        if !history.is_committed(uuid.0) {
+            continue;
+        }
+
+        commands.entity(entity).remove::<AwaitingWelcome>();
+        commands.trigger(PlayerFirstJoined { entity });
+    }
+}
+
+fn record_leave(
+    removed: On<'_, '_, Remove, packet_state::Play>,
+    history: Res<'_, PlayerHistory>,
+    query: Query<'_, '_, &Uuid>,
+) {
+    let Ok(uuid) = query.get(removed.entity) else {
+        return;
+    };
+
+    if let Err(e) = history.record_seen(uuid.0, SystemTime::now()) {
+        error!("failed to record leave of player {}: {e}", uuid.0);
+    }
+}
+
+fn commit_history(history: Res<'_, PlayerHistory>, runtime: Res<'_, AsyncRuntime>) {
+    history.commit_in_background(&runtime);
+}
+
+/// Adds the [`PlayerHistory`] resource and keeps it up to date. This must be added after
+/// [`crate::HyperionCore`].
+pub struct PlayerHistoryPlugin;
+
+impl Plugin for PlayerHistoryPlugin {
+    fn build(&self, app: &mut App) {
+        let db = app.world().resource::<LocalDb>();
+        let history = PlayerHistory::new(db).expect("failed to load player history");
+        app.insert_resource(history);
+
+        app.add_observer(record_join);
+        app.add_observer(record_leave);
+        app.add_systems(FixedUpdate, (commit_history, welcome).chain());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_ecs::{
+        resource::Resource,
+        system::{ResMut, RunSystemOnce},
+        world::World,
+    };
+
+    use super::*;
+    use crate::simulation::join::{enter_play, prepare_player};
+
+    #[derive(Resource, Default)]
+    struct Welcomed(Vec<Entity>);
+
+    #[test]
+    fn only_the_first_join_is_welcomed() {
+        let path = std::env::temp_dir().join(format!("hyperion-history-{}", fastrand::u64(..)));
+        let db = LocalDb::builder().path(&path).build().unwrap();
+
+        let mut world = World::new();
+        world.insert_resource(PlayerHistory::new(&db).unwrap());
+        world.init_resource::<Welcomed>();
+        world.add_observer(record_join);
+        world.add_observer(
+            |joined: On<'_, '_, PlayerFirstJoined>, mut welcomed: ResMut<'_, Welcomed>| {
+                welcomed.0.push(joined.entity);
+            },
+        );
+
+        let join = |world: &mut World| {
+            let player = world
+                .spawn((Uuid(uuid::Uuid::from_u128(1)), Name::new("alice")))
+                .id();
+            prepare_player(world, player);
+            enter_play(&mut world.commands(), player, ());
+            world.flush();
+            player
+        };
+
+        let first = join(&mut world);
+        assert!(world.get::<FirstJoin>(first).is_some());
+
+        // The event waits for the first join to be committed
+        world.run_system_once(welcome).unwrap();
+        assert!(world.resource::<Welcomed>().0.is_empty());
+
+        world.resource::<PlayerHistory>().commit().unwrap();
+        world.run_system_once(welcome).unwrap();
+        world.run_system_once(welcome).unwrap();
+        assert_eq!(world.resource::<Welcomed>().0, [first]);
+        world.despawn(first);
+
+        let second = join(&mut world);
+        assert!(world.get::<FirstJoin>(second).is_none());
+        world.resource::<PlayerHistory>().commit().unwrap();
+        world.run_system_once(welcome).unwrap();
+        assert_eq!(world.resource::<Welcomed>().0, [first]);
+
+        let record = world
+            .resource::<PlayerHistory>()
+            .find(uuid::Uuid::from_u128(1))
+            .unwrap()
+            .unwrap();
+        assert_eq!(record.joins, 2);
+
+        drop(world);
+        drop(db);
+        std::fs::remove_dir_all(path).unwrap();
+    }
+}
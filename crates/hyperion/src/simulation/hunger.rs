@@ -0,0 +1,404 @@
+//! Food level, saturation and exhaustion of players, following the rules of vanilla. See
+//! [`Hunger`].
+//!
+//! Sprinting, jumping and attacking add exhaustion. Every 4.0 exhaustion removes one point of
+//! saturation, or one point of food once there is no saturation left. Players with
+//! [`Hunger::can_sprint`] being false cannot sprint, and players without food take starvation
+//! damage through [`event::StarvationEvent`]. Eating restores the values of [`food_value`].
+//!
+//! The whole system can be turned off through [`HungerConfig`], which keeps every player at full
+//! food.
+
+use bevy_app::{App, FixedPostUpdate, FixedUpdate, Plugin};
+use bevy_ecs::{
+    component::Component,
+    entity::Entity,
+    lifecycle::Add,
+    message::{MessageReader, MessageWriter},
+    observer::On,
+    query::Without,
+    resource::Resource,
+    schedule::IntoScheduleConfigs,
+    system::{Commands, Query, Res},
+};
+use glam::Vec2;
+use hyperion_utils::{Prev, track_prev};
+use tracing::error;
+use valence_generated::item::ItemKind;
+use valence_protocol::{VarInt, packets::play::HealthUpdateS2c};
+#[cfg(feature = "reflect")]
+use {
+    bevy_ecs::reflect::{ReflectComponent, ReflectResource},
+    bevy_reflect::Reflect,
+};
+
+use crate::{
+    Tick, ingress,
+    net::{Compose, ConnectionId, SendResultExt},
+    simulation::{
+        MovementTracking, PendingTeleportation, Position, event, metadata::living_entity::Health,
+        packet_state,
+    },
+};
+
+/// The highest food level
+pub const MAX_FOOD: u8 = 20;
+
+/// The exhaustion which removes one point of saturation or food
+pub const EXHAUSTION_PER_POINT: f32 = 4.0;
+
+/// Exhaustion is capped at this value
+const MAX_EXHAUSTION: f32 = 40.0;
+
+/// Exhaustion added per block sprinted
+const SPRINT_EXHAUSTION: f32 = 0.1;
+
+/// Exhaustion added per jump
+const JUMP_EXHAUSTION: f32 = 0.05;
+
+/// Exhaustion added per jump while sprinting
+const SPRINT_JUMP_EXHAUSTION: f32 = 0.2;
+
+/// Exhaustion added per attack
+const ATTACK_EXHAUSTION: f32 = 0.1;
+
+/// How often players without food take starvation damage
+const STARVATION_INTERVAL: i64 = 80;
+
+/// Movement of more than this many blocks in one tick is a teleport rather than sprinting
+const MAX_SPRINT_DISTANCE: f32 = 8.0;
+
+/// Configuration of the hunger system
+#[derive(Resource, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Resource))]
+pub struct HungerConfig {
+    /// Whether food is drained and restored. Minigames which do not want hunger can turn this
+    /// off, which keeps every player at full food.
+    pub enabled: bool,
+}
+
+impl Default for HungerConfig {
+    fn default() -> Self {
+        Self { enabled: true }
+    }
+}
+
+/// The food level of a player
+#[derive(Component, Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+pub struct Hunger {
+    /// From 0 to [`MAX_FOOD`]
+    pub food: u8,
+    /// From 0 to `food`. This is drained before food.
+    pub saturation: f32,
+    /// Drains saturation or food once it reaches [`EXHAUSTION_PER_POINT`]
+    pub exhaustion: f32,
+}
+
+impl Default for Hunger {
+    fn default() -> Self {
+        Self {
+            food: MAX_FOOD,
+            saturation: 5.0,
+            exhaustion: 0.0,
+        }
+    }
+}
+
+impl Hunger {
+    pub const fn add_exhaustion(&mut self, exhaustion: f32) {
+        self.exhaustion = (self.exhaustion + exhaustion).min(MAX_EXHAUSTION);
+    }
+
+    /// Restores `food` points of food and `food * saturation_modifier * 2` points of saturation,
+    /// like eating an item with these values. See [`food_value`].
+    pub fn eat(&mut self, food: u8, saturation_modifier: f32) {
+        self.food = self.food.saturating_add(food).min(MAX_FOOD);
+        self.saturation = (f32::from(food) * saturation_modifier)
+            .mul_add(2.0, self.saturation)
+            .min(f32::from(self.food));
+    }
+
+    /// Removes the points of saturation or food paid for by the exhaustion
+    pub fn drain(&mut self) {
+        while self.exhaustion >= EXHAUSTION_PER_POINT {
+            self.exhaustion -= EXHAUSTION_PER_POINT;
+
+            if self.saturation > 0.0 {
+                self.saturation = (self.saturation - 1.0).max(0.0);
+            } else {
+                self.food = self.food.saturating_sub(1);
+            }
+        }
+    }
+
+    #[must_use]
+    pub const fn is_full(&self) -> bool {
+        self.food >= MAX_FOOD
+    }
+
+    /// Whether the player has enough food to sprint
+    #[must_use]
+    pub const fn can_sprint(&self) -> bool {
+        self.food > 6
+    }
+
+    /// Whether the player has enough food to regenerate health
+    #[must_use]
+    pub const fn can_regenerate(&self) -> bool {
+        self.food >= 18
+    }
+
+    #[must_use]
+    pub const fn is_starving(&self) -> bool {
+        self.food == 0
+    }
+}
+
+/// The food points and saturation modifier eating `item` restores, or `None` if it is not food.
+/// See [`Hunger::eat`].
+#[must_use]
+pub const fn food_value(item: ItemKind) -> Option<(u8, f32)> {
+    let value = match item {
+        ItemKind::Apple | ItemKind::ChorusFruit => (4, 0.3),
+        ItemKind::BakedPotato | ItemKind::Bread | ItemKind::CookedCod | ItemKind::CookedRabbit => {
+            (5, 0.6)
+        }
+        ItemKind::Beef | ItemKind::Porkchop | ItemKind::Rabbit => (3, 0.3),
+        ItemKind::Beetroot => (1, 0.6),
+        ItemKind::BeetrootSoup
+        | ItemKind::CookedChicken
+        | ItemKind::MushroomStew
+        | ItemKind::SuspiciousStew => (6, 0.6),
+        ItemKind::Carrot => (3, 0.6),
+        ItemKind::Chicken | ItemKind::MelonSlice | ItemKind::Mutton | ItemKind::PoisonousPotato => {
+            (2, 0.3)
+        }
+        ItemKind::Cod
+        | ItemKind::Cookie
+        | ItemKind::GlowBerries
+        | ItemKind::Salmon
+        | ItemKind::SweetBerries => (2, 0.1),
+        ItemKind::CookedBeef | ItemKind::CookedPorkchop => (8, 0.8),
+        ItemKind::CookedMutton | ItemKind::CookedSalmon => (6, 0.8),
+        ItemKind::DriedKelp | ItemKind::Potato => (1, 0.3),
+        ItemKind::EnchantedGoldenApple | ItemKind::GoldenApple => (4, 1.2),
+        ItemKind::GoldenCarrot => (6, 1.2),
+        ItemKind::HoneyBottle => (6, 0.1),
+        ItemKind::Pufferfish | ItemKind::TropicalFish => (1, 0.1),
+        ItemKind::PumpkinPie => (8, 0.3),
+        ItemKind::RabbitStew => (10, 0.6),
+        ItemKind::RottenFlesh => (4, 0.1),
+        ItemKind::SpiderEye => (2, 0.8),
+        _ => return None,
+    };
+
+    Some(value)
+}
+
+/// Whether `item` can be eaten at full food
+#[must_use]
+pub const fn is_always_edible(item: ItemKind) -> bool {
+    matches!(
+        item,
+        ItemKind::ChorusFruit
+            | ItemKind::EnchantedGoldenApple
+            | ItemKind::GoldenApple
+            | ItemKind::SuspiciousStew
+    )
+}
+
+fn hunger_enabled(config: Res<'_, HungerConfig>) -> bool {
+    config.enabled
+}
+
+fn initialize_player(
+    now_playing: On<'_, '_, Add, packet_state::Play>,
+    mut commands: Commands<'_, '_>,
+) {
+    commands
+        .entity(now_playing.entity)
+        .insert(Hunger::default());
+}
+
+fn restore_food(
+    mut events: MessageReader<'_, '_, event::FoodConsumed>,
+    mut query: Query<'_, '_, &mut Hunger>,
+) {
+    for event in events.read() {
+        let Some((food, saturation_modifier)) = food_value(event.item.item) else {
+            continue;
+        };
+
+        let mut hunger = match query.get_mut(event.player) {
+            Ok(hunger) => hunger,
+            Err(e) => {
+                error!("failed to restore food: query failed: {e}");
+                continue;
+            }
+        };
+
+        hunger.eat(food, saturation_modifier);
+    }
+}
+
+fn exhaust_attackers(
+    mut events: MessageReader<'_, '_, event::AttackEntity>,
+    mut query: Query<'_, '_, &mut Hunger>,
+) {
+    for event in events.read() {
+        if let Ok(mut hunger) = query.get_mut(event.origin) {
+            hunger.add_exhaustion(ATTACK_EXHAUSTION);
+        }
+    }
+}
+
+fn drain_food(
+    mut query: Query<'_, '_, (Entity, &mut Hunger, &mut MovementTracking)>,
+    tick: Res<'_, Tick>,
+    mut writer: MessageWriter<'_, event::StarvationEvent>,
+) {
+    let starvation_tick = tick.0 % STARVATION_INTERVAL == 0;
+
+    for (entity, mut hunger, mut tracking) in &mut query {
+        if hunger.exhaustion >= EXHAUSTION_PER_POINT {
+            hunger.drain();
+        }
+
+        if tracking.sprinting && !hunger.can_sprint() {
+            tracking.sprinting = false;
+        }
+
+        if starvation_tick && hunger.is_starving() {
+            writer.write(event::StarvationEvent {
+                client: entity,
+                damage: 1.0,
+            });
+        }
+    }
+}
+
+fn exhaust_sprinters(
+    mut query: Query<
+        '_,
+        '_,
+        (&mut Hunger, &Position, &Prev<Position>, &MovementTracking),
+        Without<PendingTeleportation>,
+    >,
+) {
+    for (mut hunger, position, prev, tracking) in &mut query {
+        if !tracking.sprinting {
+            continue;
+        }
+
+        let delta = **position - ***prev;
+        if delta.abs().max_element() >= MAX_SPRINT_DISTANCE {
+            continue;
+        }
+
+        let horizontal = Vec2::new(delta.x, delta.z).length();
+        hunger.add_exhaustion(horizontal * SPRINT_EXHAUSTION);
+    }
+}
+
+fn sync_hunger(
+    query: Query<'_, '_, (&Hunger, &Prev<Hunger>, &Health, &ConnectionId)>,
+    compose: Res<'_, Compose>,
+) {
+    for (hunger, prev, health, &connection_id) in &query {
+        if hunger.food == prev.food && hunger.saturation.to_bits() == prev.saturation.to_bits() {
+            continue;
+        }
+
+        let pkt = HealthUpdateS2c {
+            health: **health,
+            food: VarInt(i32::from(hunger.food)),
+            food_saturation: hunger.saturation,
+        };
+
+        compose
+            .unicast(&pkt, connection_id)
+            .unwrap_or_disconnected();
+    }
+}
+
+/// Adds the exhaustion of a jump to `hunger`
+pub(crate) const fn exhaust_jump(hunger: &mut Hunger, sprinting: bool) {
+    hunger.add_exhaustion(if sprinting {
+        SPRINT_JUMP_EXHAUSTION
+    } else {
+        JUMP_EXHAUSTION
+    });
+}
+
+pub struct HungerPlugin;
+
+impl Plugin for HungerPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<HungerConfig>();
+        app.add_observer(initialize_player);
+        app.add_systems(
+            FixedUpdate,
+            (restore_food, exhaust_attackers, drain_food)
+                .chain()
+                .after(ingress::decode::play)
+                .run_if(hunger_enabled),
+        );
+        app.add_systems(
+            FixedPostUpdate,
+            (exhaust_sprinters.run_if(hunger_enabled), sync_hunger),
+        );
+
+        track_prev::<Hunger>(app);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exhaustion_drains_saturation_before_food() {
+        let mut hunger = Hunger {
+            food: 20,
+            saturation: 1.5,
+            exhaustion: 0.0,
+        };
+
+        hunger.add_exhaustion(EXHAUSTION_PER_POINT * 3.0 + 1.0);
+        hunger.drain();
+
+        assert_eq!(hunger.food, 19);
+        assert!(hunger.saturation.abs() < f32::EPSILON);
+        assert!((hunger.exhaustion - 1.0).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn eating_is_capped() {
+        let mut hunger = Hunger {
+            food: 17,
+            saturation: 0.0,
+            exhaustion: 0.0,
+        };
+
+        let (food, saturation_modifier) = food_value(ItemKind::CookedBeef).unwrap();
+        hunger.eat(food, saturation_modifier);
+
+        assert_eq!(hunger.food, MAX_FOOD);
+        assert!((hunger.saturation - 12.8).abs() < 1e-4);
+
+        hunger.eat(food, saturation_modifier);
+        assert!((hunger.saturation - 20.0).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn low_food_disables_sprinting() {
+        let mut hunger = Hunger::default();
+        assert!(hunger.can_sprint() && hunger.can_regenerate());
+
+        hunger.food = 6;
+        assert!(!hunger.can_sprint());
+        assert!(!hunger.can_regenerate());
+        assert!(!hunger.is_starving());
+    }
+}
@@ -0,0 +1,143 @@
+//! [`IgnMap`], the lookup from player names to entities.
+
+use bevy_ecs::{entity::Entity, resource::Resource};
+use rustc_hash::FxHashMap;
+#[cfg(feature = "reflect")]
+use {bevy_ecs::reflect::ReflectResource, bevy_reflect::Reflect};
+
+/// The players in the play state, by name.
+///
+/// Names are looked up case-insensitively with [`IgnMap::get_ignore_case`], so `/msg Notch` finds
+/// `notch`. Names which only differ by case belong to different accounts, so they are stored
+/// separately and [`IgnMap::get`] and [`IgnMap::insert`] compare the exact name.
+#[derive(Resource, Debug, Default)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Resource))]
+pub struct IgnMap {
+    /// The players with each lowercase name, along with their exact name
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    players: FxHashMap<String, Vec<(String, Entity)>>,
+    len: usize,
+}
+
+impl IgnMap {
+    /// Returns the player named `name`, preferring an exact match over a player whose name only
+    /// differs by case
+    #[must_use]
+    pub fn get_ignore_case(&self, name: &str) -> Option<Entity> {
+        let players = self.players.get(&name.to_ascii_lowercase())?;
+        players
+            .iter()
+            .find(|(exact, _)| exact == name)
+            .or_else(|| players.first())
+            .map(|&(_, entity)| entity)
+    }
+
+    /// Returns the player named exactly `name`
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<Entity> {
+        self.players
+            .get(&name.to_ascii_lowercase())?
+            .iter()
+            .find(|(exact, _)| exact == name)
+            .map(|&(_, entity)| entity)
+    }
+
+    /// Adds a player, returning the player that previously had exactly the same name
+    pub fn insert(&mut self, name: &str, entity: Entity) -> Option<Entity> {
+        let players = self.players.entry(name.to_ascii_lowercase()).or_default();
+
+        if let Some((_, previous)) = players.iter_mut().find(|(exact, _)| exact == name) {
+            return Some(std::mem::replace(previous, entity));
+        }
+
+        players.push((name.to_owned(), entity));
+        self.len += 1;
+        None
+    }
+
+    /// Removes the player named exactly `name`, returning its entity
+    pub fn remove(&mut self, name: &str) -> Option<Entity> {
+        let key = name.to_ascii_lowercase();
+        let players = self.players.get_mut(&key)?;
+        let index = players.iter().position(|(exact, _)| exact == name)?;
+        let (_, entity) = players.swap_remove(index);
+
+        if players.is_empty() {
+            self.players.remove(&key);
+        }
+
+        self.len -= 1;
+        Some(entity)
+    }
+
+    /// Returns the number of players
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the exact name and entity of every player
+    pub fn iter(&self) -> impl Iterator<Item = (&str, Entity)> {
+        self.players
+            .values()
+            .flatten()
+            .map(|(name, entity)| (name.as_str(), *entity))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_ecs::world::World;
+
+    use super::*;
+
+    #[test]
+    fn lookup_ignores_case() {
+        let mut map = IgnMap::default();
+        let notch = World::new().spawn_empty().id();
+        assert_eq!(map.insert("notch", notch), None);
+
+        assert_eq!(map.get_ignore_case("Notch"), Some(notch));
+        assert_eq!(map.get_ignore_case("NOTCH"), Some(notch));
+        assert_eq!(map.get("Notch"), None);
+        assert_eq!(map.get("notch"), Some(notch));
+        assert_eq!(map.get_ignore_case("jeb_"), None);
+    }
+
+    #[test]
+    fn names_differing_by_case_are_different_players() {
+        let mut map = IgnMap::default();
+        let mut world = World::new();
+        let [lower, upper] = std::array::from_fn(|_| world.spawn_empty().id());
+
+        assert_eq!(map.insert("notch", lower), None);
+        assert_eq!(map.insert("Notch", upper), None);
+        assert_eq!(map.len(), 2);
+
+        assert_eq!(map.get_ignore_case("notch"), Some(lower));
+        assert_eq!(map.get_ignore_case("Notch"), Some(upper));
+
+        assert_eq!(map.remove("Notch"), Some(upper));
+        assert_eq!(map.get_ignore_case("Notch"), Some(lower));
+        assert_eq!(map.remove("Notch"), None);
+        assert_eq!(map.remove("notch"), Some(lower));
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn same_name_replaces_the_previous_player() {
+        let mut map = IgnMap::default();
+        let mut world = World::new();
+        let [first, second] = std::array::from_fn(|_| world.spawn_empty().id());
+
+        assert_eq!(map.insert("notch", first), None);
+        assert_eq!(map.insert("notch", second), Some(first));
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.iter().collect::<Vec<_>>(), [("notch", second)]);
+    }
+}
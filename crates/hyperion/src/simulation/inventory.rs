@@ -1,41 +1,54 @@
 use std::borrow::Cow;
 
-use bevy_app::{App, FixedUpdate, Plugin};
+use bevy_app::{App, FixedPostUpdate, FixedUpdate, Plugin};
 use bevy_ecs::{
     entity::Entity,
     lifecycle::{Add, Insert, Remove},
     message::{MessageReader, MessageWriter},
     observer::On,
+    query::Changed,
+    resource::Resource,
     schedule::IntoScheduleConfigs,
     system::{Commands, Query, Res},
+    world::{EntityWorldMut, World},
 };
+use glam::Vec3;
 use hyperion_inventory::{
-    CursorItem, Inventory, InventoryState, ItemKindExt, ItemSlot, OpenInventory, PlayerInventory,
+    CursorItem, GiveResult, Inventory, InventoryAccessError, InventoryState, ItemKindExt, ItemSlot,
+    OpenInventory, PlayerInventory,
 };
-use hyperion_utils::EntityExt;
+use thiserror::Error;
 use tracing::error;
+use valence_generated::item::ItemKind;
 use valence_protocol::{
     VarInt,
     packets::play::{
         self,
         click_slot_c2s::{ClickMode, SlotChange},
         entity_equipment_update_s2c::EquipmentEntry,
+        player_action_c2s::PlayerAction,
     },
 };
 use valence_server::ItemStack;
 use valence_text::IntoText;
+#[cfg(feature = "reflect")]
+use {bevy_ecs::reflect::ReflectResource, bevy_reflect::Reflect};
 
 use super::event;
 use crate::{
-    ingress,
-    net::{Compose, ConnectionId, DataBundle},
-    simulation::{packet, packet_state},
+    GameRng, Tick, ingress,
+    net::{Channel, Compose, ConnectionId, DataBundle, SendResultExt},
+    simulation::{
+        Pitch, Position, Uuid, Velocity, Yaw, entity_kind::EntityKind, metadata,
+        minecraft_id::MinecraftIdRegistry, packet, packet_state, world::WorldId,
+    },
 };
 
 pub struct InventoryPlugin;
 
 impl Plugin for InventoryPlugin {
     fn build(&self, app: &mut App) {
+        app.init_resource::<CreativeItemLimits>();
         app.add_observer(initialize_inventory_state);
         app.add_observer(on_inventory_open);
         app.add_observer(on_inventory_close);
@@ -46,14 +59,185 @@ impl Plugin for InventoryPlugin {
                     handle_close_window,
                     handle_update_selected_slot,
                     handle_click_slot,
+                    handle_swap_offhand,
                 )
                     .after(ingress::decode::play),
                 update_player_inventory
                     .after(handle_close_window)
                     .after(handle_update_selected_slot)
-                    .after(handle_click_slot),
+                    .after(handle_click_slot)
+                    .after(handle_swap_offhand),
             ),
         );
+        app.add_systems(FixedPostUpdate, send_slot_changes);
+    }
+}
+
+/// Limits of the items which players in creative mode may put into their inventory
+#[derive(Resource, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Resource))]
+pub struct CreativeItemLimits {
+    /// The maximum size of the NBT of an item in bytes. Items with large NBT would otherwise be
+    /// stored and sent to every player who can see them.
+    pub max_nbt_bytes: usize,
+}
+
+impl Default for CreativeItemLimits {
+    fn default() -> Self {
+        Self {
+            max_nbt_bytes: 16 * 1024,
+        }
+    }
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum InvalidCreativeItem {
+    #[error("invalid count {count} of {item:?}")]
+    InvalidCount { item: ItemKind, count: i8 },
+    #[error("item NBT is {size} bytes, but at most {max} bytes are allowed")]
+    NbtTooLarge { size: usize, max: usize },
+}
+
+/// Checks whether a player in creative mode may create `stack`. Unknown item ids are already
+/// rejected when the packet is decoded.
+pub fn validate_creative_item(
+    stack: &ItemStack,
+    limits: &CreativeItemLimits,
+) -> Result<(), InvalidCreativeItem> {
+    // Empty stacks clear the slot
+    if stack.item == ItemKind::Air {
+        return Ok(());
+    }
+
+    if !(1..=stack.item.max_stack()).contains(&stack.count) {
+        return Err(InvalidCreativeItem::InvalidCount {
+            item: stack.item,
+            count: stack.count,
+        });
+    }
+
+    if let Some(nbt) = &stack.nbt {
+        let mut bytes = Vec::new();
+        valence_nbt::to_binary(nbt, &mut bytes, "").unwrap();
+
+        if bytes.len() > limits.max_nbt_bytes {
+            return Err(InvalidCreativeItem::NbtTooLarge {
+                size: bytes.len(),
+                max: limits.max_nbt_bytes,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Spawns a dropped item entity with `stack` at `position` in `world`.
+///
+/// Players cannot pick the item up again yet, since item pickup is not implemented. The UUID of the
+/// item is drawn from the [`GameRng`].
+pub fn spawn_item(
+    world: &mut World,
+    stack: ItemStack,
+    position: Vec3,
+    world_id: WorldId,
+) -> Entity {
+    let uuid = Uuid::from_rng(world.resource_mut::<GameRng>().rng());
+    let mut entity = world.spawn((
+        EntityKind::Item,
+        uuid,
+        Position::from(position),
+        Velocity::default(),
+        Yaw::default(),
+        Pitch::default(),
+        world_id,
+        Channel,
+    ));
+
+    // The item is inserted separately, since inserting the EntityKind resets the metadata to its
+    // defaults
+    entity.insert(metadata::item::Item::new(stack));
+    entity.id()
+}
+
+/// Gives `stack` to `player` with [`PlayerInventory::give`] and drops the items which do not fit
+/// into the inventory at the feet of the player.
+///
+/// Returns [`None`] if `player` has no inventory.
+pub fn give_or_drop(player: &mut EntityWorldMut<'_>, stack: ItemStack) -> Option<GiveResult> {
+    let position = **player.get::<Position>()?;
+    let world_id = player.get::<WorldId>().copied().unwrap_or(WorldId::PRIMARY);
+
+    let result = player.get_mut::<PlayerInventory>()?.give(stack);
+
+    if let Some(remainder) = &result.remainder {
+        let remainder = remainder.clone();
+        player.world_scope(|world| spawn_item(world, remainder, position, world_id));
+    }
+
+    Some(result)
+}
+
+#[derive(Error, Debug)]
+pub enum SelectHotbarError {
+    #[error("the player has no inventory")]
+    NoInventory,
+    #[error(transparent)]
+    InvalidSlot(#[from] InventoryAccessError),
+}
+
+/// Selects the hotbar slot `index` of `player` with
+/// [`PlayerInventory::set_selected_hotbar_index`], tells the client about it and writes an
+/// [`event::UpdateSelectedSlotEvent`] like a selection by the player would.
+pub fn set_selected_hotbar_index(
+    player: &mut EntityWorldMut<'_>,
+    index: u8,
+) -> Result<(), SelectHotbarError> {
+    let client = player.id();
+    player
+        .get_mut::<PlayerInventory>()
+        .ok_or(SelectHotbarError::NoInventory)?
+        .set_selected_hotbar_index(index)?;
+
+    let connection_id = player.get::<ConnectionId>().copied();
+    player.world_scope(|world| {
+        if let Some(connection_id) = connection_id {
+            let packet = play::UpdateSelectedSlotS2c { slot: index };
+            world
+                .resource::<Compose>()
+                .unicast(&packet, connection_id)
+                .unwrap_or_disconnected();
+        }
+
+        world.write_message(event::UpdateSelectedSlotEvent {
+            client,
+            slot: index,
+        });
+    });
+
+    Ok(())
+}
+
+fn send_slot_changes(
+    mut query: Query<'_, '_, (Entity, &mut Inventory), Changed<Inventory>>,
+    mut writer: MessageWriter<'_, event::SlotChanged>,
+) {
+    for (entity, mut inventory) in &mut query {
+        if !inventory.has_changes() {
+            continue;
+        }
+
+        let changes = inventory
+            .bypass_change_detection()
+            .take_changes()
+            .map(|change| event::SlotChanged {
+                player: entity,
+                slot_index: change.index,
+                old: change.old,
+                new: change.new,
+                cause: change.cause,
+            });
+
+        writer.write_batch(changes);
     }
 }
 
@@ -107,7 +291,7 @@ fn on_inventory_open(
         window_title: inventory.title().to_string().into_cow_text(),
     });
 
-    compose.unicast(packet, stream_id).unwrap();
+    compose.unicast(packet, stream_id).unwrap_or_disconnected();
 
     let packet = &(play::InventoryS2c {
         window_id: inv_state.window_id(),
@@ -122,7 +306,7 @@ fn on_inventory_open(
         carried_item: Cow::Borrowed(&cursor_item.0),
     });
 
-    compose.unicast(packet, stream_id).unwrap();
+    compose.unicast(packet, stream_id).unwrap_or_disconnected();
 }
 
 fn on_inventory_close(
@@ -144,7 +328,7 @@ fn on_inventory_close(
         window_id: inv_state.window_id(),
     });
 
-    compose.unicast(packet, stream_id).unwrap();
+    compose.unicast(packet, stream_id).unwrap_or_disconnected();
 }
 
 fn update_player_inventory(
@@ -161,6 +345,7 @@ fn update_player_inventory(
         ),
     >,
     mut inventory_query: Query<'_, '_, &mut Inventory>,
+    ids: Res<'_, MinecraftIdRegistry>,
 ) {
     for (entity, inv_state, cursor_item, open_inventory, &stream_id) in player_query {
         let mut inventory;
@@ -189,7 +374,7 @@ fn update_player_inventory(
 
         let mut equipment_changes: Vec<EquipmentEntry> = Vec::new();
         let hand_slot = inventory.get_cursor_index();
-        for (idx, slot) in inventory.slots_mut().iter_mut().enumerate() {
+        for (idx, slot) in inventory.slots().iter().enumerate() {
             if slot.changed {
                 if idx == usize::from(hand_slot) {
                     equipment_changes.push(EquipmentEntry {
@@ -223,7 +408,7 @@ fn update_player_inventory(
 
         if !equipment_changes.is_empty() {
             let packet = &(play::EntityEquipmentUpdateS2c {
-                entity_id: VarInt(entity.minecraft_id()),
+                entity_id: VarInt(ids.minecraft_id(entity)),
                 equipment: equipment_changes,
             });
 
@@ -235,39 +420,49 @@ fn update_player_inventory(
         }
 
         if let Some(mut open_inv) = open_inv {
-            update_player_inventory_inner(
+            let sent = update_player_inventory_inner(
                 &compose,
                 stream_id,
                 inv_state,
                 cursor_item,
                 open_inv
-                    .slots_mut()
-                    .iter_mut()
-                    .chain(inventory.slots_inventory_mut().iter_mut()),
+                    .slots()
+                    .iter()
+                    .chain(inventory.slots_inventory().iter()),
             );
+
+            if sent {
+                open_inv.clear_changed();
+                inventory.clear_changed_inventory();
+            }
         } else {
-            update_player_inventory_inner(
+            let sent = update_player_inventory_inner(
                 &compose,
                 stream_id,
                 inv_state,
                 cursor_item,
-                inventory.slots_mut().iter_mut(),
+                inventory.slots().iter(),
             );
+
+            if sent {
+                inventory.clear_changed();
+            }
         }
     }
 }
 
+/// Sends the slots which changed to the client. Returns whether any slot was sent.
 fn update_player_inventory_inner<'a>(
     compose: &Compose,
     stream_id: ConnectionId,
     inv_state: &InventoryState,
     cursor_item: &CursorItem,
-    inventories_mut: impl Iterator<Item = &'a mut ItemSlot>,
-) {
+    slots: impl Iterator<Item = &'a ItemSlot>,
+) -> bool {
     let mut bundle = DataBundle::new(compose);
     let mut changed_slots = false;
     let window_id = i8::try_from(inv_state.window_id()).unwrap();
-    for (idx, slot) in inventories_mut.enumerate() {
+    for (idx, slot) in slots.enumerate() {
         if slot.changed {
             let idx = i16::try_from(idx).unwrap();
             let packet = &(play::ScreenHandlerSlotUpdateS2c {
@@ -278,13 +473,12 @@ fn update_player_inventory_inner<'a>(
             });
 
             bundle.add_packet(packet).unwrap();
-            slot.changed = false;
             changed_slots = true;
         }
     }
 
     if changed_slots {
-        bundle.unicast(stream_id).unwrap();
+        bundle.unicast(stream_id).unwrap_or_disconnected();
 
         let packet = &(play::ScreenHandlerSlotUpdateS2c {
             window_id: -1,
@@ -293,8 +487,10 @@ fn update_player_inventory_inner<'a>(
             slot_data: Cow::Borrowed(&cursor_item.0),
         });
 
-        compose.unicast(packet, stream_id).unwrap();
+        compose.unicast(packet, stream_id).unwrap_or_disconnected();
     }
+
+    changed_slots
 }
 
 fn handle_close_window(
@@ -324,7 +520,7 @@ fn handle_update_selected_slot(
             continue;
         };
 
-        if inventory.set_cursor(u16::from(slot)).is_err() {
+        if inventory.set_selected_hotbar_index(slot).is_err() {
             continue;
         }
 
@@ -337,10 +533,38 @@ fn handle_update_selected_slot(
     }
 }
 
+/// Swaps the selected hotbar slot with the offhand when the player presses the swap key. The
+/// client already swapped the items, but both slots are sent again in case the client and server
+/// inventories differed.
+fn handle_swap_offhand(
+    mut packets: MessageReader<'_, '_, packet::play::PlayerAction>,
+    mut query: Query<'_, '_, &mut PlayerInventory>,
+) {
+    for packet in packets.read() {
+        if !matches!(packet.action, PlayerAction::SwapItemWithOffhand) {
+            continue;
+        }
+
+        let mut inventory = match query.get_mut(packet.sender()) {
+            Ok(inventory) => inventory,
+            Err(e) => {
+                error!("failed to swap offhand: query failed: {e}");
+                continue;
+            }
+        };
+
+        let hand_slot = inventory.get_cursor_index();
+        inventory
+            .by_player()
+            .swap_slot(hand_slot, PlayerInventory::OFFHAND_SLOT);
+    }
+}
+
 #[expect(clippy::too_many_arguments)]
 fn handle_click_slot_inner<'a>(
     packet: &packet::play::ClickSlot,
     compose: &Compose,
+    tick: i64,
     event_writer: &mut MessageWriter<'_, event::DropItemStackEvent>,
     inv_state: &mut InventoryState,
     player_inventory: &'a mut PlayerInventory,
@@ -415,7 +639,7 @@ fn handle_click_slot_inner<'a>(
                 0 => {
                     handle_left_click_slot(
                         packet,
-                        compose,
+                        tick,
                         event_writer,
                         &mut inventories_mut,
                         inv_state,
@@ -509,8 +733,8 @@ fn handle_click_slot_inner<'a>(
     }
 
     if has_changed {
-        inv_state.set_last_button(0, compose.global().tick);
-        inv_state.set_last_mode(ClickMode::Click, compose.global().tick);
+        inv_state.set_last_button(0, tick);
+        inv_state.set_last_mode(ClickMode::Click, tick);
     }
 }
 
@@ -527,6 +751,7 @@ fn handle_click_slot(
         ),
     >,
     compose: Res<'_, Compose>,
+    tick: Res<'_, Tick>,
     mut inventory_query: Query<'_, '_, &mut Inventory>,
     mut event_writer: MessageWriter<'_, event::DropItemStackEvent>,
 ) {
@@ -569,7 +794,10 @@ fn handle_click_slot(
         // First of we need to check if the player has the inventory open
         // Then we need to check if that inventory is readonly
         // If so then we need to resync the inventory with the client to make sure the client is in sync with the server
+        let mut player_inventory = player_inventory.by_player();
+
         if let Some(mut open_inv) = open_inv {
+            let mut open_inv = open_inv.by_player();
             let readonly = open_inv.readonly();
             let open_inv_size = open_inv.size();
             let player_only = false;
@@ -579,6 +807,7 @@ fn handle_click_slot(
             handle_click_slot_inner(
                 packet,
                 compose,
+                tick.0,
                 &mut event_writer,
                 &mut inv_state,
                 &mut player_inventory,
@@ -597,6 +826,7 @@ fn handle_click_slot(
             handle_click_slot_inner(
                 packet,
                 compose,
+                tick.0,
                 &mut event_writer,
                 &mut inv_state,
                 &mut player_inventory,
@@ -612,7 +842,7 @@ fn handle_click_slot(
 
 fn handle_left_click_slot(
     packet: &packet::play::ClickSlot,
-    compose: &Compose,
+    tick: i64,
     event_writer: &mut MessageWriter<'_, event::DropItemStackEvent>,
     inventories_mut: &mut Vec<&mut ItemSlot>,
     inv_state: &mut InventoryState,
@@ -662,7 +892,7 @@ fn handle_left_click_slot(
         slot.stack = cursor;
         slot.changed = true;
         cursor_item.0 = ItemStack::EMPTY;
-        inv_state.set_last_stack_clicked(ItemStack::EMPTY, compose.global().tick);
+        inv_state.set_last_stack_clicked(ItemStack::EMPTY, tick);
     } else if slot.stack.item == cursor.item {
         let count = slot.stack.count.saturating_add(cursor.count);
         let max = slot.stack.item.max_stack();
@@ -677,13 +907,13 @@ fn handle_left_click_slot(
         }
 
         slot.changed = true;
-        inv_state.set_last_stack_clicked(slot.stack.clone(), compose.global().tick);
+        inv_state.set_last_stack_clicked(slot.stack.clone(), tick);
     } else {
         let old_slot_stack = slot.stack.clone();
         slot.stack = cursor;
         slot.changed = true;
         cursor_item.0 = old_slot_stack.clone();
-        inv_state.set_last_stack_clicked(old_slot_stack, compose.global().tick);
+        inv_state.set_last_stack_clicked(old_slot_stack, tick);
     }
 }
 
@@ -1250,7 +1480,7 @@ fn resync_inventory(
         carried_item: Cow::Borrowed(&cursor_item.0),
     });
 
-    compose.unicast(packet, stream_id).unwrap();
+    compose.unicast(packet, stream_id).unwrap_or_disconnected();
 
     let packet = &(play::ScreenHandlerSlotUpdateS2c {
         window_id: -1,
@@ -1259,5 +1489,151 @@ fn resync_inventory(
         slot_data: Cow::Borrowed(&cursor_item.0),
     });
 
-    compose.unicast(packet, stream_id).unwrap();
+    compose.unicast(packet, stream_id).unwrap_or_disconnected();
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_ecs::{message::Messages, system::RunSystemOnce};
+    use valence_protocol::nbt::{Compound, Value};
+
+    use super::*;
+
+    #[test]
+    fn item_uuids_come_from_the_game_rng() {
+        let spawn = || {
+            let mut world = World::new();
+            world.insert_resource(GameRng::new(7));
+            let stack = ItemStack::new(ItemKind::Stone, 1, None);
+            let item = spawn_item(&mut world, stack, Vec3::ZERO, WorldId::PRIMARY);
+            *world.get::<Uuid>(item).unwrap()
+        };
+
+        assert_eq!(spawn().0, spawn().0);
+    }
+
+    #[test]
+    fn swapping_with_the_offhand_swaps_the_selected_slot() {
+        use valence_protocol::{BlockPos, Direction, Hand, VarInt, packets::play::PlayerActionC2s};
+
+        let mut world = World::new();
+        world.init_resource::<Messages<packet::play::PlayerAction>>();
+
+        let mut inventory = PlayerInventory::default();
+        inventory.set_selected_hotbar_index(2).unwrap();
+        inventory.held_mut(Hand::Main).stack = ItemStack::new(ItemKind::Stone, 3, None);
+        inventory
+            .set(
+                PlayerInventory::OFFHAND_SLOT,
+                ItemStack::new(ItemKind::Torch, 5, None),
+            )
+            .unwrap();
+        let player = world.spawn(inventory).id();
+
+        let connection_id = ConnectionId::new(1, crate::net::ProxyId::new(0));
+        world.write_message(packet::play::PlayerAction::new(
+            player,
+            connection_id,
+            0,
+            PlayerActionC2s {
+                action: PlayerAction::SwapItemWithOffhand,
+                position: BlockPos::new(0, 0, 0),
+                direction: Direction::Down,
+                sequence: VarInt(0),
+            },
+        ));
+        world.run_system_once(handle_swap_offhand).unwrap();
+
+        let inventory = world.get::<PlayerInventory>(player).unwrap();
+        assert_eq!(inventory.held(Hand::Main).stack.item, ItemKind::Torch);
+        assert_eq!(inventory.held(Hand::Main).stack.count, 5);
+        assert_eq!(inventory.get_offhand().stack.item, ItemKind::Stone);
+        assert_eq!(inventory.get_offhand().stack.count, 3);
+    }
+
+    #[test]
+    fn selecting_a_hotbar_slot_writes_an_event() {
+        let mut world = World::new();
+        world.init_resource::<Messages<event::UpdateSelectedSlotEvent>>();
+        let player = world.spawn(PlayerInventory::default()).id();
+
+        set_selected_hotbar_index(&mut world.entity_mut(player), 3).unwrap();
+        assert!(matches!(
+            set_selected_hotbar_index(&mut world.entity_mut(player), 9),
+            Err(SelectHotbarError::InvalidSlot(_))
+        ));
+
+        let inventory = world.get::<PlayerInventory>(player).unwrap();
+        assert_eq!(inventory.selected_hotbar_index(), 3);
+
+        let events: Vec<_> = world
+            .resource_mut::<Messages<event::UpdateSelectedSlotEvent>>()
+            .drain()
+            .map(|event| (event.client, event.slot))
+            .collect();
+        assert_eq!(events, [(player, 3)]);
+
+        let no_inventory = world.spawn_empty().id();
+        assert!(matches!(
+            set_selected_hotbar_index(&mut world.entity_mut(no_inventory), 0),
+            Err(SelectHotbarError::NoInventory)
+        ));
+    }
+
+    #[test]
+    fn creative_items_are_validated() {
+        let limits = CreativeItemLimits::default();
+
+        assert_eq!(validate_creative_item(&ItemStack::EMPTY, &limits), Ok(()));
+        assert_eq!(
+            validate_creative_item(&ItemStack::new(ItemKind::Stone, 64, None), &limits),
+            Ok(())
+        );
+        assert_eq!(
+            validate_creative_item(&ItemStack::new(ItemKind::Stone, 65, None), &limits),
+            Err(InvalidCreativeItem::InvalidCount {
+                item: ItemKind::Stone,
+                count: 65
+            })
+        );
+        assert!(
+            validate_creative_item(&ItemStack::new(ItemKind::Stone, -1, None), &limits).is_err()
+        );
+    }
+
+    #[test]
+    fn nbt_above_the_limit_is_rejected() {
+        // The root compound takes 3 bytes, the string entry takes 6 bytes besides the string
+        // itself, and the end tag takes 1 byte
+        let nbt_with_len = |len: usize| {
+            let mut nbt = Compound::new();
+            nbt.insert("a", Value::String("x".repeat(len)));
+            nbt
+        };
+
+        let limits = CreativeItemLimits {
+            max_nbt_bytes: 1024,
+        };
+        let overhead = 10;
+
+        let at_limit = ItemStack::new(
+            ItemKind::Stone,
+            1,
+            Some(nbt_with_len(limits.max_nbt_bytes - overhead)),
+        );
+        assert_eq!(validate_creative_item(&at_limit, &limits), Ok(()));
+
+        let above_limit = ItemStack::new(
+            ItemKind::Stone,
+            1,
+            Some(nbt_with_len(limits.max_nbt_bytes - overhead + 1)),
+        );
+        assert_eq!(
+            validate_creative_item(&above_limit, &limits),
+            Err(InvalidCreativeItem::NbtTooLarge {
+                size: limits.max_nbt_bytes + 1,
+                max: limits.max_nbt_bytes
+            })
+        );
+    }
 }
@@ -0,0 +1,455 @@
+//! Tracking of items which are used over time, such as charging a bow or eating food. See
+//! [`UsingItem`].
+
+use std::borrow::Cow;
+
+use bevy_app::{App, FixedUpdate, Plugin};
+use bevy_ecs::{
+    component::Component,
+    entity::Entity,
+    message::{MessageReader, MessageWriter},
+    schedule::IntoScheduleConfigs,
+    system::{Commands, Local, Query, Res},
+};
+use hyperion_inventory::PlayerInventory;
+use tracing::error;
+use valence_generated::item::ItemKind;
+use valence_protocol::{Hand, ItemStack, Particle, ident, packets::play::ParticleS2c};
+#[cfg(feature = "reflect")]
+use {bevy_ecs::reflect::ReflectComponent, bevy_reflect::Reflect};
+
+use crate::{
+    GameRng, Tick, ingress,
+    net::{Compose, ConnectionId, agnostic},
+    simulation::{
+        Position, event,
+        hunger::{self, Hunger},
+        metadata::living_entity::{HandStates, Health},
+    },
+};
+
+/// How many ticks a bow has to be charged for to shoot with full power
+pub const BOW_FULL_CHARGE_TICKS: u32 = 20;
+
+/// How an item is used while the use button is held
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ItemUse {
+    /// Charged until released, such as a bow
+    Charge,
+    /// Eaten once held for the given number of ticks
+    Eat { ticks: u32 },
+    /// Drunk once held for the given number of ticks
+    Drink { ticks: u32 },
+}
+
+impl ItemUse {
+    /// The number of ticks after which the item is consumed, or `None` if it is used until it is
+    /// released
+    #[must_use]
+    pub const fn duration(self) -> Option<u32> {
+        match self {
+            Self::Charge => None,
+            Self::Eat { ticks } | Self::Drink { ticks } => Some(ticks),
+        }
+    }
+}
+
+/// How `item` is used over time, or `None` if using it has an immediate effect or none at all
+#[must_use]
+pub const fn item_use(item: ItemKind) -> Option<ItemUse> {
+    match item {
+        ItemKind::Bow | ItemKind::Crossbow | ItemKind::Trident => Some(ItemUse::Charge),
+        ItemKind::DriedKelp => Some(ItemUse::Eat { ticks: 16 }),
+        ItemKind::HoneyBottle => Some(ItemUse::Drink { ticks: 40 }),
+        ItemKind::Apple
+        | ItemKind::BakedPotato
+        | ItemKind::Beef
+        | ItemKind::Beetroot
+        | ItemKind::BeetrootSoup
+        | ItemKind::Bread
+        | ItemKind::Carrot
+        | ItemKind::Chicken
+        | ItemKind::ChorusFruit
+        | ItemKind::Cod
+        | ItemKind::CookedBeef
+        | ItemKind::CookedChicken
+        | ItemKind::CookedCod
+        | ItemKind::CookedMutton
+        | ItemKind::CookedPorkchop
+        | ItemKind::CookedRabbit
+        | ItemKind::CookedSalmon
+        | ItemKind::Cookie
+        | ItemKind::EnchantedGoldenApple
+        | ItemKind::GlowBerries
+        | ItemKind::GoldenApple
+        | ItemKind::GoldenCarrot
+        | ItemKind::MelonSlice
+        | ItemKind::MushroomStew
+        | ItemKind::Mutton
+        | ItemKind::PoisonousPotato
+        | ItemKind::Porkchop
+        | ItemKind::Potato
+        | ItemKind::Pufferfish
+        | ItemKind::PumpkinPie
+        | ItemKind::Rabbit
+        | ItemKind::RabbitStew
+        | ItemKind::RottenFlesh
+        | ItemKind::Salmon
+        | ItemKind::SpiderEye
+        | ItemKind::SuspiciousStew
+        | ItemKind::SweetBerries
+        | ItemKind::TropicalFish => Some(ItemUse::Eat { ticks: 32 }),
+        _ => None,
+    }
+}
+
+/// The power of an arrow shot from a bow which was charged for `use_ticks`, from `0.0` to `1.0`.
+/// The velocity of the arrow is this multiplied by 3 blocks per tick.
+#[must_use]
+#[expect(
+    clippy::cast_precision_loss,
+    reason = "the charge is capped long before precision is lost"
+)]
+pub fn bow_power(use_ticks: u32) -> f32 {
+    let charge = use_ticks.min(BOW_FULL_CHARGE_TICKS) as f32 / BOW_FULL_CHARGE_TICKS as f32;
+    charge.mul_add(charge, charge * 2.0) / 3.0
+}
+
+/// Present on a player while it holds down the use button with an item which is charged or
+/// consumed over time. See [`item_use`].
+///
+/// The use ends when the player releases the button, which is reported through
+/// [`event::ReleaseUseItem`], when it is cancelled by switching the held item or taking damage,
+/// or when a consumable item is finished, which is reported through [`event::FoodConsumed`].
+#[derive(Component, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+pub struct UsingItem {
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    pub hand: Hand,
+    /// The [`Tick`] the use started at
+    pub start_tick: i64,
+    /// The item as it was when the use started
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    pub item: ItemStack,
+    /// The health of the player when it was last checked, to notice damage
+    health: f32,
+}
+
+impl UsingItem {
+    #[must_use]
+    pub const fn new(hand: Hand, start_tick: i64, item: ItemStack) -> Self {
+        Self {
+            hand,
+            start_tick,
+            item,
+            health: f32::INFINITY,
+        }
+    }
+
+    /// The number of ticks the item has been used for at tick `now`
+    #[must_use]
+    pub fn ticks(&self, now: i64) -> u32 {
+        u32::try_from(now - self.start_tick).unwrap_or(0)
+    }
+
+    /// How the item is used
+    #[must_use]
+    pub const fn item_use(&self) -> Option<ItemUse> {
+        item_use(self.item.item)
+    }
+
+    /// The [`HandStates`] which show this use to other players
+    #[must_use]
+    pub const fn hand_states(&self) -> HandStates {
+        match self.hand {
+            Hand::Main => HandStates::new(0b01),
+            Hand::Off => HandStates::new(0b11),
+        }
+    }
+}
+
+pub(crate) const fn hand_slot(inventory: &PlayerInventory, hand: Hand) -> u16 {
+    match hand {
+        Hand::Main => inventory.get_cursor_index(),
+        Hand::Off => PlayerInventory::OFFHAND_SLOT,
+    }
+}
+
+pub(crate) fn held_item(inventory: &PlayerInventory, hand: Hand) -> &ItemStack {
+    &inventory.held(hand).stack
+}
+
+fn start_using_item(
+    mut events: MessageReader<'_, '_, event::InteractEvent>,
+    query: Query<'_, '_, (&PlayerInventory, Option<&Hunger>)>,
+    tick: Res<'_, Tick>,
+    mut commands: Commands<'_, '_>,
+) {
+    for event in events.read() {
+        let (inventory, hunger) = match query.get(event.client) {
+            Ok(data) => data,
+            Err(e) => {
+                error!("failed to start using item: query failed: {e}");
+                continue;
+            }
+        };
+
+        // The client tries the off hand after the main hand, which takes precedence
+        if event.hand == Hand::Off && item_use(held_item(inventory, Hand::Main).item).is_some() {
+            continue;
+        }
+
+        let item = held_item(inventory, event.hand);
+        let Some(kind) = item_use(item.item) else {
+            continue;
+        };
+
+        // Like the client, only let full players eat items which are always edible
+        if kind != ItemUse::Charge
+            && hunger.is_some_and(Hunger::is_full)
+            && !hunger::is_always_edible(item.item)
+        {
+            continue;
+        }
+
+        let using = UsingItem::new(event.hand, tick.0, item.clone());
+        commands
+            .entity(event.client)
+            .insert((using.hand_states(), using));
+    }
+}
+
+/// Cancels uses whose item is no longer held or whose player took damage
+fn cancel_using_item(
+    mut slot_events: MessageReader<'_, '_, event::UpdateSelectedSlotEvent>,
+    mut query: Query<'_, '_, (Entity, &mut UsingItem, &PlayerInventory, Option<&Health>)>,
+    mut commands: Commands<'_, '_>,
+) {
+    for event in slot_events.read() {
+        if let Ok((_, using, ..)) = query.get(event.client)
+            && using.hand == Hand::Main
+        {
+            commands
+                .entity(event.client)
+                .remove::<UsingItem>()
+                .insert(HandStates::new(0));
+        }
+    }
+
+    for (entity, mut using, inventory, health) in &mut query {
+        let held = held_item(inventory, using.hand);
+        let damaged = health.is_some_and(|health| **health < using.health);
+
+        if held.item != using.item.item || held.is_empty() || damaged {
+            commands
+                .entity(entity)
+                .remove::<UsingItem>()
+                .insert(HandStates::new(0));
+            continue;
+        }
+
+        if let Some(health) = health {
+            using.health = **health;
+        }
+    }
+}
+
+/// Consumes the items which have been eaten or drunk for their whole duration
+fn finish_using_item(
+    mut query: Query<'_, '_, (Entity, &UsingItem, &mut PlayerInventory)>,
+    tick: Res<'_, Tick>,
+    mut writer: MessageWriter<'_, event::FoodConsumed>,
+    mut commands: Commands<'_, '_>,
+) {
+    for (entity, using, mut inventory) in &mut query {
+        let Some(duration) = using.item_use().and_then(ItemUse::duration) else {
+            continue;
+        };
+
+        if using.ticks(tick.0) < duration {
+            continue;
+        }
+
+        let slot = hand_slot(&inventory, using.hand);
+        let held = held_item(&inventory, using.hand);
+        let remaining = if held.count > 1 {
+            ItemStack::new(held.item, held.count - 1, held.nbt.clone())
+        } else {
+            ItemStack::EMPTY
+        };
+
+        if let Err(e) = inventory.set(slot, remaining) {
+            error!("failed to consume item: {e}");
+        }
+
+        commands
+            .entity(entity)
+            .remove::<UsingItem>()
+            .insert(HandStates::new(0));
+
+        writer.write(event::FoodConsumed {
+            player: entity,
+            item: using.item.clone(),
+        });
+    }
+}
+
+/// Plays the sound and particles of eating and drinking every 4 ticks, like vanilla
+fn broadcast_using_effects(
+    query: Query<'_, '_, (&UsingItem, &Position, &ConnectionId)>,
+    tick: Res<'_, Tick>,
+    compose: Res<'_, Compose>,
+    game_rng: Res<'_, GameRng>,
+    mut rng: Local<'_, Option<fastrand::Rng>>,
+) {
+    let rng = rng.get_or_insert_with(|| game_rng.fork("using_effects"));
+
+    for (using, position, &connection_id) in &query {
+        let Some(duration) = using.item_use().and_then(ItemUse::duration) else {
+            continue;
+        };
+
+        let ticks = using.ticks(tick.0);
+        if ticks < 7 || ticks >= duration || !(duration - ticks).is_multiple_of(4) {
+            continue;
+        }
+
+        let chunk = position.to_chunk();
+        let sound_name = match using.item_use() {
+            Some(ItemUse::Drink { .. }) => ident!("minecraft:entity.generic.drink"),
+            _ => ident!("minecraft:entity.generic.eat"),
+        };
+
+        let sound = agnostic::sound(sound_name, **position)
+            .volume(rng.f32().mul_add(0.5, 0.5))
+            .pitch((rng.f32() - rng.f32()).mul_add(0.2, 1.0))
+            .seed(rng.i64(..))
+            .build();
+
+        compose
+            .broadcast_local(&sound, chunk)
+            .exclude(connection_id)
+            .low_priority()
+            .send()
+            .unwrap();
+
+        if matches!(using.item_use(), Some(ItemUse::Drink { .. })) {
+            continue;
+        }
+
+        let particles = ParticleS2c {
+            particle: Cow::Owned(Particle::Item(using.item.clone())),
+            long_distance: false,
+            position: position.as_dvec3() + glam::DVec3::new(0.0, 1.5, 0.0),
+            max_speed: 0.05,
+            count: 5,
+            offset: glam::Vec3::new(0.1, 0.1, 0.1),
+        };
+
+        compose
+            .broadcast_local(&particles, chunk)
+            .exclude(connection_id)
+            .low_priority()
+            .send()
+            .unwrap();
+    }
+}
+
+pub struct ItemUsePlugin;
+
+impl Plugin for ItemUsePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            FixedUpdate,
+            (
+                start_using_item,
+                cancel_using_item,
+                finish_using_item,
+                broadcast_using_effects,
+            )
+                .chain()
+                .after(ingress::decode::play),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_ecs::{message::Messages, system::RunSystemOnce, world::World};
+
+    use super::*;
+
+    fn eating_player(world: &mut World, count: i8) -> Entity {
+        let mut inventory = PlayerInventory::default();
+        let slot = inventory.get_cursor_index();
+        inventory
+            .set(slot, ItemStack::new(ItemKind::Bread, count, None))
+            .unwrap();
+
+        world
+            .spawn((
+                inventory,
+                UsingItem::new(Hand::Main, 0, ItemStack::new(ItemKind::Bread, count, None)),
+            ))
+            .id()
+    }
+
+    fn consumed(world: &World) -> usize {
+        world.resource::<Messages<event::FoodConsumed>>().len()
+    }
+
+    #[test]
+    fn classifies_items() {
+        assert_eq!(item_use(ItemKind::Bow), Some(ItemUse::Charge));
+        assert_eq!(item_use(ItemKind::Bread), Some(ItemUse::Eat { ticks: 32 }));
+        assert_eq!(
+            item_use(ItemKind::DriedKelp),
+            Some(ItemUse::Eat { ticks: 16 })
+        );
+        assert_eq!(item_use(ItemKind::Stone), None);
+    }
+
+    #[test]
+    fn bow_power_is_capped() {
+        assert!(bow_power(0).abs() < f32::EPSILON);
+        assert!(bow_power(10) < 1.0);
+        assert!((bow_power(BOW_FULL_CHARGE_TICKS) - 1.0).abs() < f32::EPSILON);
+        assert!((bow_power(100) - 1.0).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn food_is_consumed_after_its_duration() {
+        let mut world = World::new();
+        world.init_resource::<Messages<event::FoodConsumed>>();
+        let player = eating_player(&mut world, 2);
+
+        world.insert_resource(Tick(31));
+        world.run_system_once(finish_using_item).unwrap();
+        assert!(world.get::<UsingItem>(player).is_some());
+        assert_eq!(consumed(&world), 0);
+
+        world.insert_resource(Tick(32));
+        world.run_system_once(finish_using_item).unwrap();
+        assert!(world.get::<UsingItem>(player).is_none());
+        assert_eq!(consumed(&world), 1);
+
+        let inventory = world.get::<PlayerInventory>(player).unwrap();
+        assert_eq!(inventory.held(Hand::Main).stack.count, 1);
+    }
+
+    #[test]
+    fn damage_cancels_the_use() {
+        let mut world = World::new();
+        let player = eating_player(&mut world, 1);
+        world.entity_mut(player).insert(Health::new(20.0));
+        world.init_resource::<Messages<event::UpdateSelectedSlotEvent>>();
+
+        world.run_system_once(cancel_using_item).unwrap();
+        assert!(world.get::<UsingItem>(player).is_some());
+
+        **world.get_mut::<Health>(player).unwrap() = 15.0;
+        world.run_system_once(cancel_using_item).unwrap();
+        assert!(world.get::<UsingItem>(player).is_none());
+        assert_eq!(world.get::<HandStates>(player), Some(&HandStates::new(0)));
+    }
+}
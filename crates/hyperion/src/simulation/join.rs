@@ -0,0 +1,415 @@
+//! The phases a player goes through while joining, as [`EntityEvent`]s which game code can
+//! observe.
+//!
+//! The events are triggered in this order, once per player:
+//!
+//! 1. [`PlayerAuthenticated`]: the login succeeded and the player components listed on
+//!    [`packet_state::Play`] except [`Position`] and the skin have been inserted. Players who
+//!    connected through a hostname with a
+//!    [`HostAction::Target`](crate::ingress::virtual_host::HostAction::Target) have a
+//!    [`JoinTarget`](crate::ingress::virtual_host::JoinTarget).
+//! 2. [`PlayerConfiguring`]: for game code to prepare the player, such as by assigning it to a
+//!    team. Components inserted by observers of the earlier phases are available.
+//! 3. [`InitializePlayerPosition`]: kept for compatibility, observers may insert a [`Position`].
+//! 4. [`PlayerSpawning`]: for game code to choose where and how the player spawns. The
+//!    parameters are then inserted as [`Position`] and [`PlayerGameMode`].
+//! 5. [`BroadcastsEnabled`]: the player is in the play state and the packets it needs to join
+//!    have been queued, so the proxy starts to send it broadcasts. Observers of `Add`
+//!    [`packet_state::Play`] have already run.
+//! 6. [`PlayerJoined`]: the player has been sent the world and receives broadcasts.
+//!
+//! Commands queued by the observers of a phase are applied before the next phase starts, so
+//! the order holds no matter which plugins register observers or in which order they do.
+
+use bevy_ecs::{
+    bundle::Bundle, component::Component, entity::Entity, event::EntityEvent, system::Commands,
+    world::World,
+};
+use glam::Vec3;
+use valence_protocol::GameMode;
+#[cfg(feature = "reflect")]
+use {
+    bevy_ecs::reflect::{ReflectComponent, ReflectEvent},
+    bevy_reflect::Reflect,
+};
+
+use crate::{
+    InitializePlayerPosition,
+    ingress::virtual_host::HandshakeInfo,
+    net::{Compose, ConnectionId},
+    simulation::{Position, packet_state},
+};
+
+/// Where players spawn if nothing else chose a position for them
+pub const DEFAULT_SPAWN_POSITION: Vec3 = Vec3::new(0.0, 120.0, 0.0);
+
+/// Triggered once a player has logged in. See the [module documentation](self).
+#[derive(EntityEvent, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Event))]
+pub struct PlayerAuthenticated {
+    pub entity: Entity,
+    /// The hostname and port the player connected with, which is also the [`HandshakeInfo`]
+    /// component of the player
+    pub handshake: Option<HandshakeInfo>,
+}
+
+/// Triggered before the spawn of a player is chosen. See the [module documentation](self).
+#[derive(EntityEvent, Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Event))]
+pub struct PlayerConfiguring {
+    pub entity: Entity,
+}
+
+/// Triggered to choose the spawn of a player. Observers may change the fields through
+/// [`On`](bevy_ecs::observer::On), which the later observers and the server see. See the
+/// [module documentation](self).
+#[derive(EntityEvent, Debug, Copy, Clone, PartialEq)]
+pub struct PlayerSpawning {
+    pub entity: Entity,
+    /// The [`Position`] inserted by observers of [`InitializePlayerPosition`], or
+    /// [`DEFAULT_SPAWN_POSITION`]
+    pub spawn_position: Vec3,
+    pub game_mode: GameMode,
+}
+
+/// Triggered once the proxy sends broadcasts to a player. See the [module documentation](self).
+///
+/// Until then the proxy drops broadcasts for the player, since a client which has not received
+/// its join packets yet disconnects when it receives play packets.
+#[derive(EntityEvent, Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Event))]
+pub struct BroadcastsEnabled {
+    pub entity: Entity,
+}
+
+/// Triggered once a player is in the play state. See the [module documentation](self).
+#[derive(EntityEvent, Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Event))]
+pub struct PlayerJoined {
+    pub entity: Entity,
+}
+
+/// The game mode a player was sent when joining, as chosen in [`PlayerSpawning`]
+#[derive(Component, Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+pub struct PlayerGameMode(#[cfg_attr(feature = "reflect", reflect(ignore))] pub GameMode);
+
+/// Runs the phases up to and including [`PlayerSpawning`] for `player`. The player components
+/// must have been inserted already.
+pub(crate) fn prepare_player(world: &mut World, player: Entity) {
+    let handshake = world.get::<HandshakeInfo>(player).cloned();
+    world.trigger(PlayerAuthenticated {
+        entity: player,
+        handshake,
+    });
+    world.flush();
+
+    world.trigger(PlayerConfiguring { entity: player });
+    world.flush();
+
+    world.trigger(InitializePlayerPosition(player));
+    world.flush();
+
+    let spawn_position = world
+        .get::<Position>(player)
+        .map_or(DEFAULT_SPAWN_POSITION, |position| **position);
+
+    let mut spawning = PlayerSpawning {
+        entity: player,
+        spawn_position,
+        game_mode: GameMode::Survival,
+    };
+    world.trigger_ref(&mut spawning);
+    world.flush();
+
+    // The player may have disconnected in the meantime
+    let Ok(mut entity) = world.get_entity_mut(player) else {
+        return;
+    };
+
+    entity.insert((
+        Position::from(spawning.spawn_position),
+        PlayerGameMode(spawning.game_mode),
+    ));
+}
+
+/// Puts `player` into the play state together with `bundle`, enables broadcasts for it and
+/// triggers [`PlayerJoined`] afterwards. The packets the player needs to join must have been
+/// queued already.
+pub(crate) fn enter_play(commands: &mut Commands<'_, '_>, player: Entity, bundle: impl Bundle) {
+    commands.entity(player).insert((bundle, packet_state::Play));
+    commands.queue(move |world: &mut World| enable_broadcasts(world, player));
+    commands.trigger(PlayerJoined { entity: player });
+}
+
+/// Tells the proxy to send broadcasts to `player` and triggers [`BroadcastsEnabled`]
+fn enable_broadcasts(world: &mut World, player: Entity) {
+    // The player may have disconnected in the meantime
+    let Some(&connection_id) = world.get::<ConnectionId>(player) else {
+        return;
+    };
+
+    if let Some(compose) = world.get_resource::<Compose>() {
+        compose.io_buf().set_receive_broadcasts(connection_id);
+    }
+
+    world.trigger(BroadcastsEnabled { entity: player });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bevy_app::{App, Plugin};
+    use bevy_ecs::{
+        lifecycle::Add,
+        observer::On,
+        resource::Resource,
+        system::{Query, ResMut},
+    };
+    use bytes::Bytes;
+    use hyperion_proto::ArchivedServerToProxyMessage;
+    use rkyv::util::AlignedVec;
+    use rustc_hash::{FxHashMap, FxHashSet};
+    use tokio::sync::mpsc::UnboundedReceiver;
+    use valence_protocol::{VarInt, packets::play::PlayerActionResponseS2c};
+
+    use super::*;
+    use crate::{
+        Shared,
+        net::{IoBuf, ProxyId},
+    };
+
+    #[derive(Resource, Default)]
+    struct Phases(Vec<&'static str>);
+
+    #[derive(Component)]
+    struct Team(u8);
+
+    /// Registers the observers of the later phases first
+    struct SpawnPlugin;
+
+    impl Plugin for SpawnPlugin {
+        fn build(&self, app: &mut App) {
+            app.add_observer(
+                |_: On<'_, '_, PlayerJoined>, mut phases: ResMut<'_, Phases>| {
+                    phases.0.push("joined");
+                },
+            );
+            app.add_observer(
+                |_: On<'_, '_, BroadcastsEnabled>, mut phases: ResMut<'_, Phases>| {
+                    phases.0.push("broadcasts");
+                },
+            );
+            app.add_observer(
+                |mut spawning: On<'_, '_, PlayerSpawning>,
+                 teams: Query<'_, '_, &Team>,
+                 mut phases: ResMut<'_, Phases>| {
+                    phases.0.push("spawning");
+                    let team = teams.get(spawning.entity).unwrap();
+                    spawning.spawn_position.x = f32::from(team.0) * 100.0;
+                    spawning.game_mode = GameMode::Adventure;
+                },
+            );
+            app.add_observer(
+                |init: On<'_, '_, InitializePlayerPosition>,
+                 mut phases: ResMut<'_, Phases>,
+                 mut commands: Commands<'_, '_>| {
+                    phases.0.push("initialize position");
+                    commands
+                        .entity(init.event_target())
+                        .insert(Position::new(0.0, 64.0, 5.0));
+                },
+            );
+        }
+    }
+
+    struct TeamPlugin;
+
+    impl Plugin for TeamPlugin {
+        fn build(&self, app: &mut App) {
+            app.add_observer(
+                |_: On<'_, '_, Add, packet_state::Play>, mut phases: ResMut<'_, Phases>| {
+                    phases.0.push("play");
+                },
+            );
+            app.add_observer(
+                |configuring: On<'_, '_, PlayerConfiguring>,
+                 mut phases: ResMut<'_, Phases>,
+                 mut commands: Commands<'_, '_>| {
+                    phases.0.push("configuring");
+                    commands.entity(configuring.entity).insert(Team(2));
+                },
+            );
+            app.add_observer(
+                |_: On<'_, '_, PlayerAuthenticated>, mut phases: ResMut<'_, Phases>| {
+                    phases.0.push("authenticated");
+                },
+            );
+        }
+    }
+
+    #[test]
+    fn phases_run_in_order() {
+        let mut app = App::new();
+        app.init_resource::<Phases>();
+        app.add_plugins((SpawnPlugin, TeamPlugin));
+        let world = app.world_mut();
+        let player = world.spawn(ConnectionId::new(1, ProxyId::new(0))).id();
+
+        prepare_player(world, player);
+
+        assert_eq!(
+            world.get::<Position>(player),
+            Some(&Position::new(200.0, 64.0, 5.0))
+        );
+        assert_eq!(
+            world.get::<PlayerGameMode>(player),
+            Some(&PlayerGameMode(GameMode::Adventure))
+        );
+
+        enter_play(&mut world.commands(), player, ());
+        world.flush();
+
+        assert_eq!(world.resource::<Phases>().0, [
+            "authenticated",
+            "configuring",
+            "initialize position",
+            "spawning",
+            "play",
+            "broadcasts",
+            "joined"
+        ]);
+    }
+
+    /// Delivers the messages of the server to the streams like the proxy does
+    #[derive(Default)]
+    struct SyntheticProxy {
+        receives_broadcasts: FxHashSet<u64>,
+        /// What each stream received, in order
+        received: FxHashMap<u64, Vec<&'static str>>,
+    }
+
+    impl SyntheticProxy {
+        fn receive(&mut self, rx: &mut UnboundedReceiver<Bytes>) {
+            while let Ok(bytes) = rx.try_recv() {
+                let mut rest = &bytes[..];
+                while let Some((len, body)) = rest.split_first_chunk::<8>() {
+                    let len = usize::try_from(u64::from_be_bytes(*len)).unwrap();
+                    let (message, next) = body.split_at(len);
+                    rest = next;
+
+                    let mut aligned = AlignedVec::<16>::new();
+                    aligned.extend_from_slice(message);
+                    // SAFETY: the message was encoded by the server
+                    let message = unsafe {
+                        rkyv::access_unchecked::<ArchivedServerToProxyMessage<'_>>(&aligned)
+                    };
+                    self.deliver(message);
+                }
+            }
+        }
+
+        fn deliver(&mut self, message: &ArchivedServerToProxyMessage<'_>) {
+            match message {
+                ArchivedServerToProxyMessage::Unicast(unicast) => {
+                    let stream = u64::from(unicast.stream);
+                    self.received.entry(stream).or_default().push("unicast");
+                }
+                ArchivedServerToProxyMessage::SetReceiveBroadcasts(enabled) => {
+                    self.receives_broadcasts.insert(u64::from(enabled.stream));
+                }
+                ArchivedServerToProxyMessage::BroadcastGlobal(broadcast) => {
+                    for &stream in &self.receives_broadcasts {
+                        if !broadcast.exclude.contains(stream) {
+                            self.received.entry(stream).or_default().push("broadcast");
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        fn received(&self, stream: ConnectionId) -> &[&'static str] {
+            self.received
+                .get(&stream.inner())
+                .map_or(&[], Vec::as_slice)
+        }
+    }
+
+    #[test]
+    fn broadcasts_only_arrive_after_entering_play() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut io_buf = IoBuf::default();
+        io_buf.add_proxy(ProxyId::new(0), tx.into());
+
+        let mut world = World::new();
+        world.insert_resource(Compose::new(
+            libdeflater::CompressionLvl::default(),
+            Arc::new(Shared {
+                compression_threshold: valence_protocol::CompressionThreshold(-1),
+                compression_level: libdeflater::CompressionLvl::default(),
+            }),
+            io_buf,
+        ));
+        let mut proxy = SyntheticProxy::default();
+
+        let broadcast = |world: &World| {
+            let packet = PlayerActionResponseS2c {
+                sequence: VarInt(0),
+            };
+            world
+                .resource::<Compose>()
+                .broadcast(&packet)
+                .send()
+                .unwrap();
+        };
+
+        let playing = ConnectionId::new(1, ProxyId::new(0));
+        let entity = world.spawn(playing).id();
+        enter_play(&mut world.commands(), entity, ());
+        world.flush();
+
+        // The player is still joining, so it only receives the packets sent to it
+        let joining = ConnectionId::new(2, ProxyId::new(0));
+        let player = world.spawn(joining).id();
+        broadcast(&world);
+        let join_packet = PlayerActionResponseS2c {
+            sequence: VarInt(1),
+        };
+        world
+            .resource::<Compose>()
+            .unicast(&join_packet, joining)
+            .unwrap();
+        broadcast(&world);
+
+        proxy.receive(&mut rx);
+        assert_eq!(proxy.received(playing), ["broadcast", "broadcast"]);
+        assert_eq!(proxy.received(joining), ["unicast"]);
+
+        enter_play(&mut world.commands(), player, ());
+        world.flush();
+        broadcast(&world);
+
+        proxy.receive(&mut rx);
+        assert_eq!(proxy.received(playing), ["broadcast"; 3]);
+        assert_eq!(proxy.received(joining), ["unicast", "broadcast"]);
+    }
+
+    #[test]
+    fn players_spawn_at_the_default_position() {
+        let mut world = World::new();
+        let player = world.spawn_empty().id();
+
+        prepare_player(&mut world, player);
+
+        assert_eq!(
+            world.get::<Position>(player),
+            Some(&Position::from(DEFAULT_SPAWN_POSITION))
+        );
+        assert_eq!(
+            world.get::<PlayerGameMode>(player),
+            Some(&PlayerGameMode(GameMode::Survival))
+        );
+    }
+}
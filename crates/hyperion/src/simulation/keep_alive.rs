@@ -0,0 +1,328 @@
+//! Detecting dead connections with keep alives. See [`KeepAliveConfig`].
+//!
+//! The first keep alive is sent as soon as a player enters the play state, so a connection which
+//! dies while joining is noticed within one timeout. Afterwards, a new keep alive is sent once the
+//! previous one was answered and the interval passed. Players who do not answer within the timeout
+//! are kicked.
+
+use bevy_app::{App, FixedUpdate, Plugin};
+use bevy_ecs::{
+    component::Component,
+    lifecycle::Add,
+    message::MessageReader,
+    observer::On,
+    schedule::IntoScheduleConfigs,
+    system::{Commands, Query, Res},
+};
+use hyperion_utils::{Args, Locale, Translations, localization::DEFAULT_LANGUAGE};
+use tracing::{debug, info};
+use valence_protocol::packets::play::KeepAliveS2c;
+#[cfg(feature = "reflect")]
+use {bevy_ecs::reflect::ReflectComponent, bevy_reflect::Reflect};
+
+use crate::{
+    KeepAliveConfig, Tick, ingress,
+    ingress::decode::disconnect,
+    net::{Compose, ConnectionId, SendResultExt},
+    simulation::{packet::play, packet_state},
+};
+
+/// The keep alives of a player. Every player in the play state has this component.
+#[derive(Component, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+pub struct KeepAliveStatus {
+    /// The id of the keep alive the player has not answered yet
+    pending: Option<i64>,
+    /// The tick the last keep alive was sent at
+    last_sent: i64,
+    /// The tick the last keep alive was answered at, or the player entered the play state at
+    last_answered: i64,
+    /// The tick the player is kicked at unless they answer the pending keep alive
+    deadline: i64,
+    /// The number of ticks it took the player to answer the last answered keep alive
+    ping_ticks: Option<i64>,
+}
+
+/// What to do after checking a [`KeepAliveStatus`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Action {
+    None,
+    /// Send a keep alive with this id
+    Send(i64),
+    Kick,
+}
+
+impl KeepAliveStatus {
+    fn new(tick: i64, config: &KeepAliveConfig) -> Self {
+        Self {
+            pending: None,
+            last_sent: tick,
+            last_answered: tick,
+            deadline: tick + config.timeout_ticks(),
+            ping_ticks: None,
+        }
+    }
+
+    /// The tick the last keep alive was answered at
+    #[must_use]
+    pub const fn last_answered(&self) -> i64 {
+        self.last_answered
+    }
+
+    /// The round trip time of the last answered keep alive in milliseconds, or [`None`] if the
+    /// player has not answered one yet. Keep alives are only checked once per tick, so this is a
+    /// multiple of 50 ms.
+    #[must_use]
+    pub const fn ping_ms(&self) -> Option<i64> {
+        match self.ping_ticks {
+            Some(ticks) => Some(ticks.saturating_mul(50)),
+            None => None,
+        }
+    }
+
+    /// Whether the player has not answered the last keep alive yet
+    #[must_use]
+    pub const fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Starts waiting for an answer to a keep alive sent at `tick`. Returns the id of the keep
+    /// alive, which is `tick` since only one is sent at a time.
+    const fn send(&mut self, tick: i64) -> i64 {
+        self.pending = Some(tick);
+        self.last_sent = tick;
+        tick
+    }
+
+    /// Records an answer with `id` at `tick`. Returns whether it answered the pending keep alive.
+    fn answer(&mut self, id: i64, tick: i64, config: &KeepAliveConfig) -> bool {
+        if self.pending != Some(id) {
+            return false;
+        }
+
+        self.pending = None;
+        // The id of a keep alive is the tick it was sent at
+        self.ping_ticks = Some(tick - id);
+        self.last_answered = tick;
+        self.deadline = tick + config.timeout_ticks();
+        true
+    }
+
+    /// Applies a changed `config`. A longer timeout moves the deadline back, while a shorter one
+    /// only applies from the next answer on, so the player keeps the time the previous timeout
+    /// gave them.
+    fn reconfigure(&mut self, config: &KeepAliveConfig) {
+        self.deadline = self
+            .deadline
+            .max(self.last_answered + config.timeout_ticks());
+    }
+
+    fn check(&mut self, tick: i64, config: &KeepAliveConfig) -> Action {
+        if tick >= self.deadline {
+            return Action::Kick;
+        }
+
+        if self.pending.is_none() && tick - self.last_sent >= config.interval_ticks() {
+            return Action::Send(self.send(tick));
+        }
+
+        Action::None
+    }
+}
+
+fn send_keep_alive(compose: &Compose, connection_id: ConnectionId, id: i64) {
+    compose
+        .unicast(&KeepAliveS2c { id }, connection_id)
+        .unwrap_or_disconnected();
+}
+
+fn initialize_keep_alive_status(
+    now_playing: On<'_, '_, Add, packet_state::Play>,
+    tick: Res<'_, Tick>,
+    config: Res<'_, KeepAliveConfig>,
+    compose: Res<'_, Compose>,
+    query: Query<'_, '_, &ConnectionId>,
+    mut commands: Commands<'_, '_>,
+) {
+    let Ok(&connection_id) = query.get(now_playing.entity) else {
+        return;
+    };
+
+    let mut status = KeepAliveStatus::new(tick.0, &config);
+    let id = status.send(tick.0);
+    send_keep_alive(&compose, connection_id, id);
+
+    commands.entity(now_playing.entity).insert(status);
+}
+
+fn receive_keep_alives(
+    tick: Res<'_, Tick>,
+    config: Res<'_, KeepAliveConfig>,
+    mut packets: MessageReader<'_, '_, play::KeepAlive>,
+    mut query: Query<'_, '_, &mut KeepAliveStatus>,
+) {
+    for packet in packets.read() {
+        let Ok(mut status) = query.get_mut(packet.sender()) else {
+            continue;
+        };
+
+        if !status.answer(packet.id, tick.0, &config) {
+            debug!(
+                "ignoring unexpected keep alive {} of {:?}",
+                packet.id,
+                packet.sender()
+            );
+        }
+    }
+}
+
+fn update_keep_alives(
+    tick: Res<'_, Tick>,
+    config: Res<'_, KeepAliveConfig>,
+    compose: Res<'_, Compose>,
+    translations: Res<'_, Translations>,
+    mut query: Query<'_, '_, (&mut KeepAliveStatus, &ConnectionId, Option<&Locale>)>,
+) {
+    let reconfigured = config.is_changed();
+
+    for (mut status, &connection_id, locale) in &mut query {
+        if reconfigured {
+            status.reconfigure(&config);
+        }
+
+        match status.check(tick.0, &config) {
+            Action::None => {}
+            Action::Send(id) => send_keep_alive(&compose, connection_id, id),
+            Action::Kick => {
+                info!("kicking {connection_id:?} for not answering keep alives");
+                let locale = locale.map_or(DEFAULT_LANGUAGE, Locale::as_str);
+                let reason = translations.translate(locale, "kick.timed_out", &Args::new());
+                disconnect::play(&compose, connection_id, &reason);
+
+                // The player is despawned once the proxy reports the disconnect, so this prevents
+                // kicking the player again until then
+                status.deadline = tick.0 + config.timeout_ticks();
+            }
+        }
+    }
+}
+
+/// Sends keep alives to players and kicks players who do not answer them according to the
+/// [`KeepAliveConfig`]
+pub struct KeepAlivePlugin;
+
+impl Plugin for KeepAlivePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<KeepAliveConfig>();
+        app.add_observer(initialize_keep_alive_status);
+        app.add_systems(
+            FixedUpdate,
+            (receive_keep_alives, update_keep_alives)
+                .chain()
+                .after(ingress::decode::play),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::KeepAliveConfigError;
+
+    const CONFIG: KeepAliveConfig = KeepAliveConfig {
+        interval_secs: 10,
+        timeout_secs: 20,
+    };
+
+    #[test]
+    fn keep_alives_are_sent_after_the_interval() {
+        let mut status = KeepAliveStatus::new(0, &CONFIG);
+        assert_eq!(status.send(0), 0);
+
+        // Nothing else is sent while a keep alive is pending
+        assert_eq!(status.check(300, &CONFIG), Action::None);
+        assert!(!status.answer(1, 300, &CONFIG));
+        assert!(status.answer(0, 300, &CONFIG));
+        assert!(!status.is_pending());
+        assert_eq!(status.check(301, &CONFIG), Action::Send(301));
+
+        assert!(status.answer(301, 310, &CONFIG));
+        assert_eq!(status.last_answered(), 310);
+        assert_eq!(status.check(500, &CONFIG), Action::None);
+        assert_eq!(status.check(501, &CONFIG), Action::Send(501));
+        assert!(status.is_pending());
+    }
+
+    #[test]
+    fn ping_is_the_time_to_answer_the_last_keep_alive() {
+        let mut status = KeepAliveStatus::new(0, &CONFIG);
+        assert_eq!(status.ping_ms(), None);
+
+        status.send(0);
+        assert_eq!(status.ping_ms(), None);
+        assert!(status.answer(0, 3, &CONFIG));
+        assert_eq!(status.ping_ms(), Some(150));
+
+        status.send(200);
+        assert_eq!(status.ping_ms(), Some(150));
+        assert!(status.answer(200, 200, &CONFIG));
+        assert_eq!(status.ping_ms(), Some(0));
+    }
+
+    #[test]
+    fn players_who_do_not_answer_are_kicked() {
+        let mut status = KeepAliveStatus::new(0, &CONFIG);
+        status.send(0);
+
+        assert_eq!(status.check(399, &CONFIG), Action::None);
+        assert_eq!(status.check(400, &CONFIG), Action::Kick);
+    }
+
+    #[test]
+    fn reducing_the_timeout_keeps_the_previous_deadline() {
+        let mut status = KeepAliveStatus::new(0, &CONFIG);
+        status.send(0);
+
+        let shorter = KeepAliveConfig {
+            interval_secs: 2,
+            timeout_secs: 5,
+        };
+        status.reconfigure(&shorter);
+        assert_eq!(status.check(300, &shorter), Action::None);
+        let mut unanswered = status;
+        assert_eq!(unanswered.check(400, &shorter), Action::Kick);
+
+        // The shorter timeout applies once the keep alive is answered
+        assert!(status.answer(0, 350, &shorter));
+        assert_eq!(status.check(390, &shorter), Action::Send(390));
+        assert_eq!(status.check(450, &shorter), Action::Kick);
+
+        let longer = KeepAliveConfig {
+            interval_secs: 10,
+            timeout_secs: 60,
+        };
+        let mut status = KeepAliveStatus::new(0, &CONFIG);
+        status.send(0);
+        status.reconfigure(&longer);
+        assert_eq!(status.check(1199, &longer), Action::None);
+        assert_eq!(status.check(1200, &longer), Action::Kick);
+    }
+
+    #[test]
+    fn interval_must_be_shorter_than_timeout() {
+        assert_eq!(CONFIG.validate(), Ok(()));
+        assert_eq!(KeepAliveConfig::default().validate(), Ok(()));
+
+        let config = KeepAliveConfig {
+            interval_secs: 20,
+            timeout_secs: 20,
+        };
+        assert_eq!(
+            config.validate(),
+            Err(KeepAliveConfigError {
+                interval_secs: 20,
+                timeout_secs: 20,
+            })
+        );
+    }
+}
@@ -0,0 +1,179 @@
+//! Loading the primary world in the background.
+//!
+//! Plugins which load the [`Blocks`] of the primary world after the server started, such as the
+//! map of `hyperion-genmap`, insert a [`MapLoadProgress`] while loading and call
+//! [`finish_loading`] once they are done, which triggers [`WorldReady`]. Systems which need the
+//! world can use the [`world_ready`] run condition, which scheduled block updates and the
+//! [`EntitySimulation`](crate::activity::EntitySimulation) set already use. Without a
+//! [`MapLoadProgress`], the world is always ready. Such plugins load the map of the [`MapSource`]
+//! of the configuration.
+//!
+//! Players who log in before the world is ready are handled according to [`EarlyJoins`].
+
+use std::time::Duration;
+
+use bevy_ecs::{component::Component, event::Event, resource::Resource, system::Res, world::World};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+#[cfg(feature = "reflect")]
+use {
+    bevy_ecs::reflect::{ReflectEvent, ReflectResource},
+    bevy_reflect::Reflect,
+};
+
+use crate::{
+    net::ConnectionId,
+    simulation::{blocks::Blocks, skin::PlayerSkin},
+};
+
+/// What the loading of the primary world is currently doing
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "reflect", derive(Reflect))]
+pub enum MapLoadStage {
+    #[default]
+    Downloading,
+    /// Comparing the checksum of the download to the expected one
+    Verifying,
+    Extracting,
+    Ready,
+}
+
+/// The progress of loading the primary world, see the [module documentation](self)
+#[derive(Resource, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Resource))]
+pub struct MapLoadProgress {
+    pub stage: MapLoadStage,
+    pub downloaded_bytes: u64,
+    /// The size of the download, if it is known
+    pub total_bytes: Option<u64>,
+    /// The estimated time until the download finishes
+    pub eta: Option<Duration>,
+}
+
+impl MapLoadProgress {
+    #[must_use]
+    pub fn is_ready(&self) -> bool {
+        self.stage == MapLoadStage::Ready
+    }
+
+    /// How much of the download finished, from 0 to 100
+    #[must_use]
+    #[expect(
+        clippy::cast_precision_loss,
+        reason = "the percentage does not need to be exact"
+    )]
+    pub fn percent(&self) -> Option<f64> {
+        let total = self.total_bytes.filter(|&total| total != 0)?;
+        let downloaded = self.downloaded_bytes.min(total);
+        Some(downloaded as f64 / total as f64 * 100.0)
+    }
+}
+
+/// Triggered once the primary world finished loading, see the [module documentation](self)
+#[derive(Event, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Event))]
+pub struct WorldReady;
+
+/// A run condition for systems which need the primary world to be loaded
+#[must_use]
+pub fn world_ready(progress: Option<Res<'_, MapLoadProgress>>) -> bool {
+    progress.is_none_or(|progress| progress.is_ready())
+}
+
+/// Replaces the primary world with `blocks` and triggers [`WorldReady`]
+pub fn finish_loading(world: &mut World, blocks: Blocks) {
+    world.insert_resource(blocks);
+
+    let mut progress = world.get_resource_or_init::<MapLoadProgress>();
+    progress.stage = MapLoadStage::Ready;
+    progress.eta = None;
+
+    info!("world is ready");
+    world.trigger(WorldReady);
+    world.flush();
+}
+
+/// Where the map of the primary world is loaded from, see the [module documentation](self)
+#[derive(Resource, Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Resource))]
+pub struct MapSource {
+    /// The HTTP(S) URL, `file://` URL or path of the save. The plugin loads its own map if this
+    /// is not set.
+    pub url: Option<String>,
+    /// The SHA-256 checksum of the archive of the save in hex, which is required for HTTP(S) URLs
+    pub sha256: Option<String>,
+}
+
+/// What happens to players who log in before the world is ready
+#[derive(
+    Resource,
+    Serialize,
+    Deserialize,
+    Copy,
+    Clone,
+    Debug,
+    Default,
+    PartialEq,
+    Eq
+)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Resource))]
+pub enum EarlyJoins {
+    /// Players are disconnected with a message that the server is still loading
+    #[default]
+    Disconnect,
+    /// Players stay in the login state until the world is ready. They are still disconnected once
+    /// the [login timeout](crate::ingress::pending::PendingConnectionLimits::login_timeout_secs)
+    /// passes, so this is only useful if the world loads quickly, such as from the cache.
+    Hold,
+}
+
+/// Marks a connection whose login is finished once the world is ready, see [`EarlyJoins::Hold`]
+#[derive(Component, Clone, Debug)]
+pub(crate) struct AwaitingWorld {
+    pub connection_id: ConnectionId,
+    pub uuid: uuid::Uuid,
+    pub username: String,
+    pub skin: Option<PlayerSkin>,
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_ecs::{
+        observer::On,
+        resource::Resource,
+        system::{ResMut, RunSystemOnce},
+    };
+
+    use super::*;
+    use crate::runtime::AsyncRuntime;
+
+    #[derive(Resource, Default)]
+    struct Triggered(u32);
+
+    #[test]
+    fn finishing_makes_the_world_ready() {
+        let runtime = AsyncRuntime::new();
+        let mut world = World::new();
+        world.init_resource::<Triggered>();
+        world.add_observer(
+            |_: On<'_, '_, WorldReady>, mut triggered: ResMut<'_, Triggered>| {
+                triggered.0 += 1;
+            },
+        );
+
+        assert!(world.run_system_once(world_ready).unwrap());
+
+        world.insert_resource(MapLoadProgress {
+            downloaded_bytes: 300,
+            total_bytes: Some(1200),
+            ..MapLoadProgress::default()
+        });
+        assert_eq!(world.resource::<MapLoadProgress>().percent(), Some(25.0));
+        assert!(!world.run_system_once(world_ready).unwrap());
+
+        finish_loading(&mut world, Blocks::empty(&runtime));
+        assert!(world.run_system_once(world_ready).unwrap());
+        assert!(world.contains_resource::<Blocks>());
+        assert_eq!(world.resource::<Triggered>().0, 1);
+    }
+}
@@ -0,0 +1,175 @@
+//! The maximum health of living entities, see [`MaxHealth`].
+//!
+//! Entities without a [`MaxHealth`] have the vanilla maximum of [`FULL_HEALTH`]. Whenever the
+//! maximum of an entity is inserted or changed, its [`Health`] is lowered to the new maximum if
+//! needed, and players are sent the new maximum so that their client shows the right number of
+//! hearts.
+
+use bevy_app::{App, FixedPostUpdate, Plugin};
+use bevy_ecs::{
+    component::Component,
+    entity::Entity,
+    query::{Added, Changed, Has, Or},
+    system::{Query, Res},
+};
+use valence_protocol::{
+    VarInt, ident,
+    packets::play::{self, entity_attributes_s2c::AttributeProperty},
+};
+#[cfg(feature = "reflect")]
+use {
+    bevy_ecs::reflect::ReflectComponent,
+    bevy_reflect::{Reflect, std_traits::ReflectDefault},
+};
+
+use crate::{
+    net::{Compose, ConnectionId, SendResultExt},
+    simulation::{
+        FULL_HEALTH, metadata::living_entity::Health, minecraft_id::MinecraftIdRegistry,
+        packet_state,
+    },
+};
+
+/// The health which an entity can heal up to, see the [module documentation](self)
+#[derive(Component, Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component, Default))]
+pub struct MaxHealth(pub f32);
+
+impl Default for MaxHealth {
+    fn default() -> Self {
+        Self(FULL_HEALTH)
+    }
+}
+
+impl std::ops::Deref for MaxHealth {
+    type Target = f32;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// Lowers the health of entities whose maximum changed and sends the maximum to players. Players
+/// who start playing with a [`MaxHealth`] are sent it as well.
+fn apply_max_health(
+    query: Query<
+        '_,
+        '_,
+        (
+            Entity,
+            &MaxHealth,
+            Option<&mut Health>,
+            Option<&ConnectionId>,
+            Has<packet_state::Play>,
+        ),
+        Or<(Changed<MaxHealth>, Added<packet_state::Play>)>,
+    >,
+    compose: Res<'_, Compose>,
+    ids: Res<'_, MinecraftIdRegistry>,
+) {
+    for (entity, &max_health, health, connection_id, playing) in query {
+        if let Some(mut health) = health
+            && **health > *max_health
+        {
+            **health = *max_health;
+        }
+
+        let Some(&connection_id) = connection_id.filter(|_| playing) else {
+            continue;
+        };
+
+        let pkt = play::EntityAttributesS2c {
+            entity_id: VarInt(ids.minecraft_id(entity)),
+            properties: vec![AttributeProperty {
+                key: ident!("minecraft:generic.max_health").into(),
+                value: f64::from(*max_health),
+                modifiers: Vec::new(),
+            }],
+        };
+
+        compose
+            .unicast(&pkt, connection_id)
+            .unwrap_or_disconnected();
+    }
+}
+
+pub struct MaxHealthPlugin;
+
+impl Plugin for MaxHealthPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(FixedPostUpdate, apply_max_health);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bevy_ecs::{system::RunSystemOnce, world::World};
+
+    use super::*;
+    use crate::{
+        Shared,
+        net::{IoBuf, ProxyId},
+    };
+
+    fn world() -> World {
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut io_buf = IoBuf::default();
+        io_buf.add_proxy(ProxyId::new(0), tx.into());
+
+        let mut world = World::new();
+        world.insert_resource(Compose::new(
+            libdeflater::CompressionLvl::default(),
+            Arc::new(Shared {
+                compression_threshold: valence_protocol::CompressionThreshold(-1),
+                compression_level: libdeflater::CompressionLvl::default(),
+            }),
+            io_buf,
+        ));
+        world.init_resource::<MinecraftIdRegistry>();
+        world
+    }
+
+    #[test]
+    fn npcs_heal_up_to_their_own_maximum() {
+        let mut world = world();
+        let npc = world.spawn((Health::new(100.0), MaxHealth(100.0))).id();
+        world.run_system_once(apply_max_health).unwrap();
+
+        let mut health = world.get_mut::<Health>(npc).unwrap();
+        health.damage(30.0);
+        assert_eq!(*health, Health::new(70.0));
+
+        health.heal(50.0, MaxHealth(100.0));
+        assert_eq!(*health, Health::new(100.0));
+
+        health.damage(150.0);
+        assert!(health.is_dead());
+    }
+
+    #[test]
+    fn boosted_players_keep_their_health() {
+        let mut world = world();
+        let player = world
+            .spawn((
+                ConnectionId::new(1, ProxyId::new(0)),
+                packet_state::Play,
+                Health::default(),
+            ))
+            .id();
+
+        world.entity_mut(player).insert(MaxHealth(40.0));
+        world.run_system_once(apply_max_health).unwrap();
+        assert_eq!(world.get::<Health>(player), Some(&Health::new(20.0)));
+
+        let mut health = world.get_mut::<Health>(player).unwrap();
+        health.heal(30.0, MaxHealth(40.0));
+        assert_eq!(*health, Health::new(40.0));
+
+        // Lowering the maximum lowers the health as well
+        world.get_mut::<MaxHealth>(player).unwrap().0 = 10.0;
+        world.run_system_once(apply_max_health).unwrap();
+        assert_eq!(world.get::<Health>(player), Some(&Health::new(10.0)));
+    }
+}
@@ -0,0 +1,30 @@
+// Extends LivingEntity.
+//
+// Index	Type	Meaning	Default
+// 15	Byte (0)	Flags	0
+// Bit mask	Meaning
+// 0x01	Is small
+// 0x04	Has arms
+// 0x08	Has no base plate
+// 0x10	Is marker
+// 16..=21	Rotations (9)	Head, body, left arm, right arm, left leg and right leg rotations
+
+use super::Metadata;
+use crate::define_and_register_components;
+
+define_and_register_components! {
+    15, ArmorStandFlags -> u8,
+}
+
+impl ArmorStandFlags {
+    pub const ARMS: u8 = 0x04;
+    pub const MARKER: u8 = 0x10;
+    pub const NO_BASE_PLATE: u8 = 0x08;
+    pub const SMALL: u8 = 0x01;
+}
+
+impl Default for ArmorStandFlags {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
@@ -0,0 +1,39 @@
+// Extends Entity.
+//
+// Index	Type	Meaning	Default
+// 8	VarInt (1)	Time since last hit	0
+// 9	VarInt (1)	Forward direction	1
+// 10	Float (3)	Damage taken	0.0
+// 11	VarInt (1)	Type (oak, spruce, birch, jungle, acacia, cherry, dark oak, mangrove, bamboo)	0 (oak)
+// 12	Boolean (8)	Is left paddle turning	false
+// 13	Boolean (8)	Is right paddle turning	false
+// 14	VarInt (1)	Splash timer	0
+
+use valence_protocol::VarInt;
+
+use super::Metadata;
+use crate::define_and_register_components;
+
+define_and_register_components! {
+    11, BoatType -> VarInt,
+    12, LeftPaddleTurning -> bool,
+    13, RightPaddleTurning -> bool,
+}
+
+impl Default for BoatType {
+    fn default() -> Self {
+        Self::new(VarInt(0))
+    }
+}
+
+impl Default for LeftPaddleTurning {
+    fn default() -> Self {
+        Self::new(false)
+    }
+}
+
+impl Default for RightPaddleTurning {
+    fn default() -> Self {
+        Self::new(false)
+    }
+}
@@ -0,0 +1,27 @@
+// Extends Entity. Used by item frames and glow item frames.
+//
+// Index	Type	Meaning	Default
+// 8	Slot (7)	Item	Empty
+// 9	VarInt (1)	Rotation, in 45 degree steps	0
+
+use valence_protocol::{ItemStack, VarInt};
+
+use super::Metadata;
+use crate::define_and_register_components;
+
+define_and_register_components! {
+    8, FramedItem -> ItemStack,
+    9, ItemRotation -> VarInt,
+}
+
+impl Default for FramedItem {
+    fn default() -> Self {
+        Self::new(ItemStack::EMPTY)
+    }
+}
+
+impl Default for ItemRotation {
+    fn default() -> Self {
+        Self::new(VarInt(0))
+    }
+}
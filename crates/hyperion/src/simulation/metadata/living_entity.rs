@@ -15,7 +15,10 @@ use std::fmt::Display;
 use valence_protocol::VarInt;
 
 use super::Metadata;
-use crate::define_and_register_components;
+use crate::{
+    define_and_register_components,
+    simulation::{FULL_HEALTH, max_health::MaxHealth},
+};
 
 // Example usage:
 define_and_register_components! {
@@ -70,7 +73,7 @@ impl Default for ArrowsInEntity {
 
 impl Default for Health {
     fn default() -> Self {
-        Self::new(20.0)
+        Self::new(FULL_HEALTH)
     }
 }
 
@@ -80,16 +83,13 @@ impl Health {
         self.value <= 0.0
     }
 
-    pub fn damage(&mut self, damage: f32) {
-        self.update(self.value - damage);
+    pub const fn damage(&mut self, damage: f32) {
+        self.value = (self.value - damage).max(0.0);
     }
 
-    const fn update(&mut self, value: f32) {
-        self.value = value.clamp(0.0, 20.0);
-    }
-
-    pub fn heal(&mut self, heal: f32) {
-        self.update(self.value + heal);
+    /// Heals by `heal`, but not above `max`
+    pub const fn heal(&mut self, heal: f32, max: MaxHealth) {
+        self.value = (self.value + heal).min(max.0).max(0.0);
     }
 }
 
@@ -1,10 +1,12 @@
-use std::fmt::Debug;
+use std::{any::TypeId, fmt::Debug};
 
 use bevy_app::{App, FixedPostUpdate, Plugin};
 use bevy_ecs::{
     component::Component,
     lifecycle::Insert,
     observer::On,
+    query::Has,
+    resource::Resource,
     system::{Commands, Query},
     world::EntityRef,
 };
@@ -14,20 +16,117 @@ use valence_protocol::{Encode, VarInt};
 #[cfg(feature = "reflect")]
 use {bevy_ecs::reflect::ReflectComponent, bevy_reflect::Reflect};
 
-use crate::simulation::metadata::entity::{EntityFlags, Pose};
+use crate::{
+    net::ConnectionId,
+    simulation::metadata::{
+        entity::{EntityFlags, Pose},
+        player::DisplayedSkinParts,
+    },
+};
 
+pub mod armor_stand;
 pub mod block_display;
+pub mod boat;
 pub mod display;
 pub mod entity;
 pub mod item;
+pub mod item_frame;
 pub mod living_entity;
+pub mod painting;
 pub mod player;
 
-/// Set up a system to track metadata changes
-fn component_and_track<T>(app: &mut App)
+/// The entity kinds a metadata component is sent for
+#[derive(Copy, Clone, Debug)]
+pub enum MetadataKinds {
+    /// Every entity kind, for metadata of the base entity class
+    All,
+    Only(&'static [EntityKind]),
+}
+
+impl MetadataKinds {
+    fn contains(self, kind: EntityKind) -> bool {
+        match self {
+            Self::All => true,
+            Self::Only(kinds) => kinds.contains(&kind),
+        }
+    }
+
+    fn overlaps(self, other: Self) -> bool {
+        match (self, other) {
+            (Self::All, _) | (_, Self::All) => true,
+            (Self::Only(kinds), other) => kinds.iter().any(|&kind| other.contains(kind)),
+        }
+    }
+}
+
+struct MetadataEntry {
+    kinds: MetadataKinds,
+    index: u8,
+    component: TypeId,
+    name: &'static str,
+    encode_if_not_default: fn(EntityRef<'_>, &mut MetadataChanges),
+}
+
+/// The metadata components which are sent to clients when an entity is spawned for them. Use
+/// [`register_metadata`] to add a component.
+#[derive(Resource, Default)]
+pub struct MetadataRegistry {
+    entries: Vec<MetadataEntry>,
+}
+
+impl MetadataRegistry {
+    /// Adds the metadata component `T` for entities of `kinds`.
+    ///
+    /// # Panics
+    /// If `T` is registered already, or if another component uses the same index for one of
+    /// `kinds`
+    pub fn register<T>(&mut self, kinds: MetadataKinds)
+    where
+        T: Component + Clone + PartialEq + Metadata + Default,
+    {
+        let name = std::any::type_name::<T>();
+
+        for entry in &self.entries {
+            assert!(
+                entry.component != TypeId::of::<T>(),
+                "metadata component {name} is registered twice"
+            );
+            assert!(
+                entry.index != T::INDEX || !entry.kinds.overlaps(kinds),
+                "metadata components {} and {name} both use index {}",
+                entry.name,
+                T::INDEX
+            );
+        }
+
+        self.entries.push(MetadataEntry {
+            kinds,
+            index: T::INDEX,
+            component: TypeId::of::<T>(),
+            name,
+            encode_if_not_default: |entity, metadata| {
+                if let Some(component) = entity.get::<T>() {
+                    metadata.encode_if_not_default(component.clone());
+                }
+            },
+        });
+    }
+}
+
+/// Registers the metadata component `T` for entities of `kinds` in the [`MetadataRegistry`] and
+/// sets up a system to send changes of it. Game crates can use this to sync their own metadata,
+/// such as the profession of a villager.
+///
+/// # Panics
+/// See [`MetadataRegistry::register`]
+pub fn register_metadata<T>(app: &mut App, kinds: MetadataKinds)
 where
     T: Component + Clone + PartialEq + Metadata + Default + Debug,
 {
+    app.world_mut()
+        .get_resource_or_init::<MetadataRegistry>()
+        .register::<T>(kinds);
+
     track_prev::<T>(app);
 
     // TODO: This will silently ignore changes to the metadata between this system's execution and
@@ -46,11 +145,11 @@ where
 
 fn initialize_entity(
     entity: On<'_, '_, Insert, EntityKind>,
-    query: Query<'_, '_, &EntityKind>,
+    query: Query<'_, '_, (&EntityKind, Has<ConnectionId>)>,
     mut commands: Commands<'_, '_>,
 ) {
-    let kind = match query.get(entity.entity) {
-        Ok(kind) => *kind,
+    let (kind, is_connected) = match query.get(entity.entity) {
+        Ok((kind, is_connected)) => (*kind, is_connected),
         Err(e) => {
             error!("failed to initialize entity: query failed: {e}");
             return;
@@ -78,10 +177,31 @@ fn initialize_entity(
                 living_entity::default_components(),
                 player::default_components(),
             ));
+
+            // Real players report their skin parts in their client settings, but NPCs have no
+            // client to do so
+            if !is_connected {
+                entity.insert(DisplayedSkinParts::ALL);
+            }
         }
         EntityKind::Item => {
             entity.insert(item::default_components());
         }
+        EntityKind::ItemFrame | EntityKind::GlowItemFrame => {
+            entity.insert(item_frame::default_components());
+        }
+        EntityKind::ArmorStand => {
+            entity.insert((
+                living_entity::default_components(),
+                armor_stand::default_components(),
+            ));
+        }
+        EntityKind::Painting => {
+            entity.insert(painting::default_components());
+        }
+        EntityKind::Boat | EntityKind::ChestBoat => {
+            entity.insert(boat::default_components());
+        }
         _ => {}
     }
 }
@@ -90,16 +210,25 @@ pub struct MetadataPlugin;
 
 impl Plugin for MetadataPlugin {
     fn build(&self, app: &mut App) {
+        const LIVING: &[EntityKind] = &[EntityKind::Player, EntityKind::ArmorStand];
+        const ITEM_FRAMES: &[EntityKind] = &[EntityKind::ItemFrame, EntityKind::GlowItemFrame];
+        const BOATS: &[EntityKind] = &[EntityKind::Boat, EntityKind::ChestBoat];
+
         app.add_observer(initialize_entity);
-        component_and_track::<EntityFlags>(app);
-        component_and_track::<Pose>(app);
-
-        entity::register(app);
-        display::register(app);
-        block_display::register(app);
-        item::register(app);
-        living_entity::register(app);
-        player::register(app);
+        app.init_resource::<MetadataRegistry>();
+        register_metadata::<EntityFlags>(app, MetadataKinds::All);
+        register_metadata::<Pose>(app, MetadataKinds::All);
+
+        entity::register(app, MetadataKinds::All);
+        display::register(app, MetadataKinds::Only(&[EntityKind::BlockDisplay]));
+        block_display::register(app, MetadataKinds::Only(&[EntityKind::BlockDisplay]));
+        item::register(app, MetadataKinds::Only(&[EntityKind::Item]));
+        item_frame::register(app, MetadataKinds::Only(ITEM_FRAMES));
+        living_entity::register(app, MetadataKinds::Only(LIVING));
+        armor_stand::register(app, MetadataKinds::Only(&[EntityKind::ArmorStand]));
+        painting::register(app, MetadataKinds::Only(&[EntityKind::Painting]));
+        boat::register(app, MetadataKinds::Only(BOATS));
+        player::register(app, MetadataKinds::Only(&[EntityKind::Player]));
     }
 }
 
@@ -188,9 +317,9 @@ macro_rules! define_and_register_components {
             $crate::define_metadata_component!($index, $name -> $type);
         )*
 
-        pub fn register(app: &mut bevy_app::App) {
+        pub fn register(app: &mut bevy_app::App, kinds: $crate::simulation::metadata::MetadataKinds) {
             $(
-                $crate::simulation::metadata::component_and_track::<$name>(app);
+                $crate::simulation::metadata::register_metadata::<$name>(app, kinds);
             )*
         }
 
@@ -202,14 +331,6 @@ macro_rules! define_and_register_components {
                 )*
             )
         }
-
-        pub fn encode_non_default_components(entity: bevy_ecs::world::EntityRef<'_>, metadata: &mut $crate::simulation::metadata::MetadataChanges) {
-            $(
-                if let Some(component) = entity.get::<$name>() {
-                    metadata.encode_if_not_default(component.clone());
-                }
-            )*
-        }
     };
 }
 
@@ -238,34 +359,20 @@ impl MetadataChanges {
         r#type.encode(&mut self.0).unwrap();
     }
 
-    pub fn encode_non_default_components(&mut self, entity: EntityRef<'_>) {
-        let kind = entity
+    /// Encodes the registered metadata components of `entity` which differ from their defaults
+    pub fn encode_non_default_components(
+        &mut self,
+        entity: EntityRef<'_>,
+        registry: &MetadataRegistry,
+    ) {
+        let kind = *entity
             .get::<EntityKind>()
             .expect("entity must have EntityKind component");
 
-        if let Some(component) = entity.get::<EntityFlags>() {
-            self.encode_if_not_default(*component);
-        }
-
-        if let Some(component) = entity.get::<Pose>() {
-            self.encode_if_not_default(*component);
-        }
-
-        entity::encode_non_default_components(entity, self);
-
-        match kind {
-            EntityKind::BlockDisplay => {
-                display::encode_non_default_components(entity, self);
-                block_display::encode_non_default_components(entity, self);
+        for entry in &registry.entries {
+            if entry.kinds.contains(kind) {
+                (entry.encode_if_not_default)(entity, self);
             }
-            EntityKind::Player => {
-                living_entity::encode_non_default_components(entity, self);
-                player::encode_non_default_components(entity, self);
-            }
-            EntityKind::Item => {
-                item::encode_non_default_components(entity, self);
-            }
-            _ => {}
         }
     }
 }
@@ -297,3 +404,77 @@ pub(crate) fn get_and_clear_metadata(metadata: &mut MetadataChanges) -> Option<M
 
     Some(MetadataView(metadata))
 }
+
+#[cfg(test)]
+mod tests {
+    use valence_bytes::CowBytes;
+    use valence_protocol::{RawBytes, packets::play::EntityTrackerUpdateS2c};
+
+    use super::*;
+
+    crate::define_metadata_component!(18, VillagerLevel -> VarInt);
+    crate::define_metadata_component!(18, OtherLevel -> VarInt);
+
+    impl Default for VillagerLevel {
+        fn default() -> Self {
+            Self::new(VarInt(1))
+        }
+    }
+
+    impl Default for OtherLevel {
+        fn default() -> Self {
+            Self::new(VarInt(1))
+        }
+    }
+
+    #[test]
+    fn custom_metadata_is_encoded_for_registered_kinds() {
+        let mut app = App::new();
+        app.add_plugins(MetadataPlugin);
+        register_metadata::<VillagerLevel>(&mut app, MetadataKinds::Only(&[EntityKind::Villager]));
+
+        let world = app.world_mut();
+        let villager = world.spawn(EntityKind::Villager).id();
+        world
+            .entity_mut(villager)
+            .insert(VillagerLevel::new(VarInt(3)));
+        let pig = world.spawn(EntityKind::Pig).id();
+        world.entity_mut(pig).insert(VillagerLevel::new(VarInt(3)));
+
+        let world = app.world();
+        let registry = world.resource::<MetadataRegistry>();
+        let mut metadata = MetadataChanges::default();
+
+        metadata.encode_non_default_components(world.entity(pig), registry);
+        assert!(get_and_clear_metadata(&mut metadata).is_none());
+
+        metadata.encode_non_default_components(world.entity(villager), registry);
+        let view = get_and_clear_metadata(&mut metadata).unwrap();
+
+        let mut bytes = Vec::new();
+        EntityTrackerUpdateS2c {
+            entity_id: VarInt(5),
+            tracked_values: RawBytes(CowBytes::Borrowed(&view)),
+        }
+        .encode(&mut bytes)
+        .unwrap();
+
+        // Entity id, then index 18 with type VarInt (1) and value 3, then the end marker
+        assert_eq!(bytes, [5, 18, 1, 3, 0xff]);
+    }
+
+    #[test]
+    #[should_panic(expected = "both use index 18")]
+    fn duplicate_indices_panic() {
+        let mut registry = MetadataRegistry::default();
+        registry.register::<VillagerLevel>(MetadataKinds::Only(&[EntityKind::Villager]));
+        registry.register::<OtherLevel>(MetadataKinds::All);
+    }
+
+    #[test]
+    fn same_index_for_different_kinds_is_allowed() {
+        let mut registry = MetadataRegistry::default();
+        registry.register::<VillagerLevel>(MetadataKinds::Only(&[EntityKind::Villager]));
+        registry.register::<OtherLevel>(MetadataKinds::Only(&[EntityKind::Pig]));
+    }
+}
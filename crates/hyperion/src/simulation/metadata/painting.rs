@@ -0,0 +1,68 @@
+// Extends Entity.
+//
+// Index	Type	Meaning	Default
+// 8	Painting Variant (24)	Variant	minecraft:kebab
+
+use valence_protocol::{Encode, VarInt};
+
+use super::Metadata;
+use crate::define_and_register_components;
+
+/// The names of the painting variants in the order of their IDs in the
+/// `minecraft:painting_variant` registry
+const VARIANTS: [&str; 30] = [
+    "kebab",
+    "aztec",
+    "alban",
+    "aztec2",
+    "bomb",
+    "plant",
+    "wasteland",
+    "pool",
+    "courbet",
+    "sea",
+    "sunset",
+    "creebet",
+    "wanderer",
+    "graham",
+    "match",
+    "bust",
+    "stage",
+    "void",
+    "skull_and_roses",
+    "wither",
+    "fighters",
+    "pointer",
+    "pigscene",
+    "burning_skull",
+    "skeleton",
+    "earth",
+    "wind",
+    "water",
+    "fire",
+    "donkey_kong",
+];
+
+/// An ID in the `minecraft:painting_variant` registry
+#[derive(Encode, Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct PaintingVariantId(pub VarInt);
+
+impl PaintingVariantId {
+    /// Looks up a variant by its resource identifier, such as `minecraft:kebab`
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.strip_prefix("minecraft:").unwrap_or(name);
+        let id = VARIANTS.iter().position(|&variant| variant == name)?;
+        Some(Self(VarInt(i32::try_from(id).ok()?)))
+    }
+}
+
+define_and_register_components! {
+    8, PaintingVariant -> PaintingVariantId,
+}
+
+impl Default for PaintingVariant {
+    fn default() -> Self {
+        Self::new(PaintingVariantId::default())
+    }
+}
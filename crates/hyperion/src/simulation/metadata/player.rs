@@ -53,6 +53,11 @@ impl Default for Score {
     }
 }
 
+impl DisplayedSkinParts {
+    /// Renders the cape and every outer skin layer
+    pub const ALL: Self = Self::new(0x7F);
+}
+
 impl Default for DisplayedSkinParts {
     fn default() -> Self {
         Self::new(0)
@@ -35,7 +35,7 @@ use valence_generated::block::BlockState;
 use valence_protocol::{ItemStack, VarInt};
 use valence_text::Text;
 
-use crate::simulation::metadata::entity::Pose;
+use crate::simulation::metadata::{entity::Pose, painting::PaintingVariantId};
 
 pub trait MetadataType {
     const INDEX: i32;
@@ -62,6 +62,7 @@ impl_metadata_type! {
     8 => bool,
     14 => BlockState,
     20 => Pose,
+    24 => PaintingVariantId,
     26 => glam::Vec3,
     27 => glam::Quat,
 }
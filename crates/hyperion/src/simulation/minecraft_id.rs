@@ -0,0 +1,210 @@
+//! [`MinecraftIdRegistry`], which assigns the ids that clients use to refer to entities.
+
+use std::sync::Mutex;
+
+use anyhow::bail;
+use bevy_ecs::{
+    entity::{Entities, Entity, EntityHash},
+    resource::Resource,
+};
+#[cfg(feature = "reflect")]
+use {bevy_ecs::reflect::ReflectResource, bevy_reflect::Reflect};
+
+/// How often the ids of entities which were despawned without being reported to
+/// [`MinecraftIdRegistry::despawned`] are freed
+const SWEEP_INTERVAL_TICKS: u32 = 100;
+
+#[derive(Default, Debug)]
+struct Allocator {
+    /// The next id to try to allocate
+    next: i32,
+    /// Entities that were despawned this tick, whose ids are freed at the end of the tick
+    despawned: Vec<Entity>,
+    ticks_since_sweep: u32,
+}
+
+/// Assigns each entity the id used for it in packets.
+///
+/// Entity indices are reused as soon as an entity is despawned, so they cannot be used as ids
+/// directly: a packet from a client which still refers to a despawned player, such as an attack,
+/// would otherwise target whichever entity reused its index. Instead, ids are allocated from a
+/// counter the first time an entity's id is requested, and are only reused once the counter
+/// wraps around.
+///
+/// The ids of despawned entities stay valid until the end of the tick, so that despawn observers
+/// can still send packets destroying the entity. Ids are looked up without locking, only
+/// allocating an id takes a lock.
+#[derive(Resource, Default, Debug)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Resource))]
+pub struct MinecraftIdRegistry {
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    ids: papaya::HashMap<Entity, i32, EntityHash>,
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    entities: papaya::HashMap<i32, Entity>,
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    allocator: Mutex<Allocator>,
+}
+
+impl MinecraftIdRegistry {
+    /// Returns the Minecraft id of `entity`, allocating one if it has none yet
+    pub fn minecraft_id(&self, entity: Entity) -> i32 {
+        if let Some(&id) = self.ids.pin().get(&entity) {
+            return id;
+        }
+
+        let mut allocator = self.allocator.lock().unwrap();
+
+        // Another thread may have allocated an id while this one waited for the lock
+        let ids = self.ids.pin();
+        if let Some(&id) = ids.get(&entity) {
+            return id;
+        }
+
+        let entities = self.entities.pin();
+        let id = loop {
+            let id = allocator.next;
+            // Ids stay non-negative because some packets send an id plus one as an optional id
+            allocator.next = if id == i32::MAX - 1 { 0 } else { id + 1 };
+
+            if !entities.contains_key(&id) {
+                break id;
+            }
+        };
+
+        entities.insert(id, entity);
+        ids.insert(entity, id);
+        id
+    }
+
+    /// Returns the entity with the Minecraft id `id`.
+    ///
+    /// # Errors
+    /// If no entity has this id, for example because the entity it belonged to was despawned
+    pub fn entity(&self, id: i32) -> anyhow::Result<Entity> {
+        match self.entities.pin().get(&id) {
+            Some(&entity) => Ok(entity),
+            None => bail!("minecraft id {id} does not belong to an entity"),
+        }
+    }
+
+    /// Frees the id of `entity` at the end of the tick
+    pub(crate) fn despawned(&mut self, entity: Entity) {
+        self.allocator.get_mut().unwrap().despawned.push(entity);
+    }
+
+    /// Frees the ids of entities despawned this tick. Every [`SWEEP_INTERVAL_TICKS`], the ids of
+    /// entities which no longer exist in `entities` are freed as well, since ids can be requested
+    /// for entities whose despawn is not reported, such as channels.
+    pub(crate) fn free_despawned(&mut self, entities: &Entities) {
+        let allocator = self.allocator.get_mut().unwrap();
+        let ids = self.ids.pin();
+        let by_id = self.entities.pin();
+
+        for entity in allocator.despawned.drain(..) {
+            if let Some(id) = ids.remove(&entity) {
+                by_id.remove(id);
+            }
+        }
+
+        allocator.ticks_since_sweep += 1;
+        if allocator.ticks_since_sweep < SWEEP_INTERVAL_TICKS {
+            return;
+        }
+        allocator.ticks_since_sweep = 0;
+
+        let dead: Vec<_> = ids
+            .iter()
+            .filter(|(entity, _)| !entities.contains(**entity))
+            .map(|(&entity, &id)| (entity, id))
+            .collect();
+        for (entity, id) in dead {
+            ids.remove(&entity);
+            by_id.remove(&id);
+        }
+    }
+
+    /// The number of entities which have an id
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_ecs::world::World;
+
+    use super::*;
+
+    #[test]
+    fn ids_are_stable() {
+        let mut world = World::new();
+        let [a, b] = std::array::from_fn(|_| world.spawn_empty().id());
+        let registry = MinecraftIdRegistry::default();
+
+        let id = registry.minecraft_id(a);
+        assert_eq!(registry.minecraft_id(a), id);
+        assert_ne!(registry.minecraft_id(b), id);
+        assert_eq!(registry.entity(id).unwrap(), a);
+    }
+
+    #[test]
+    fn stale_ids_do_not_resolve_after_index_reuse() {
+        let mut world = World::new();
+        let mut registry = MinecraftIdRegistry::default();
+
+        let player = world.spawn_empty().id();
+        let stale_id = registry.minecraft_id(player);
+
+        world.despawn(player);
+        registry.despawned(player);
+        // The id stays valid until the end of the tick
+        assert_eq!(registry.entity(stale_id).unwrap(), player);
+        registry.free_despawned(world.entities());
+
+        let arrow = world.spawn_empty().id();
+        assert_eq!(arrow.index(), player.index());
+        assert_ne!(arrow, player);
+
+        assert_ne!(registry.minecraft_id(arrow), stale_id);
+        assert!(registry.entity(stale_id).is_err());
+    }
+
+    #[test]
+    fn ids_in_use_are_skipped_when_the_counter_wraps() {
+        let mut world = World::new();
+        let [a, b, c] = std::array::from_fn(|_| world.spawn_empty().id());
+        let registry = MinecraftIdRegistry::default();
+
+        assert_eq!(registry.minecraft_id(a), 0);
+        registry.allocator.lock().unwrap().next = i32::MAX - 1;
+        assert_eq!(registry.minecraft_id(b), i32::MAX - 1);
+        assert_eq!(registry.minecraft_id(c), 1);
+    }
+
+    #[test]
+    fn ids_of_unreported_despawns_are_swept() {
+        let mut world = World::new();
+        let mut registry = MinecraftIdRegistry::default();
+
+        let kept = world.spawn_empty().id();
+        let channel = world.spawn_empty().id();
+        registry.minecraft_id(kept);
+        let id = registry.minecraft_id(channel);
+        world.despawn(channel);
+
+        for _ in 1..SWEEP_INTERVAL_TICKS {
+            registry.free_despawned(world.entities());
+        }
+        assert_eq!(registry.entity(id).unwrap(), channel);
+
+        registry.free_despawned(world.entities());
+        assert!(registry.entity(id).is_err());
+        assert_eq!(registry.len(), 1);
+    }
+}
@@ -1,13 +1,10 @@
-use std::{
-    collections::{HashMap, hash_map::Entry},
-    hash::Hash,
-};
+use std::{collections::HashMap, hash::Hash, sync::Arc};
 
-use bevy_app::{App, Plugin};
+use bevy_app::{App, Last, Plugin};
 use bevy_ecs::{
     component::Component,
-    entity::Entity,
-    lifecycle::{Add, Insert, Remove},
+    entity::{Entities, Entity},
+    lifecycle::{Add, Despawn, Insert, Remove},
     message::Message,
     name::Name,
     observer::On,
@@ -18,6 +15,7 @@ use bevy_ecs::{
 use bytemuck::{Pod, Zeroable};
 use geometry::aabb::Aabb;
 use glam::{DVec3, I16Vec2, IVec3, Vec3};
+use hyperion_utils::{Args, Localizer};
 use rustc_hash::FxHashMap;
 use serde::{Deserialize, Serialize};
 use tracing::{error, info};
@@ -37,30 +35,77 @@ use {
 };
 
 use crate::{
-    Global,
-    net::{Compose, ConnectionId},
+    GameRng, Tick,
+    net::{Compose, ConnectionId, SendResultExt},
     simulation::{
+        afk::AfkPlugin,
+        blocks::{
+            scheduled::ScheduledUpdatePlugin, schematic::SchematicPlugin, snapshot::RestorePlugin,
+        },
         command::CommandPlugin,
+        death_drops::DeathDropsPlugin,
         entity_kind::EntityKind,
+        game_rules::GameRulesPlugin,
         handlers::HandlersPlugin,
+        hunger::HungerPlugin,
         inventory::InventoryPlugin,
+        item_use::ItemUsePlugin,
+        keep_alive::KeepAlivePlugin,
         metadata::{Metadata, MetadataPlugin},
+        minecraft_id::MinecraftIdRegistry,
         packet::PacketPlugin,
+        redstone::RedstonePlugin,
+        sign::SignPlugin,
+        skin::SkinFetchPlugin,
+        statistics::{Statistics, StatisticsPlugin},
+        team::TeamsPlugin,
+        tick_rate::TickRatePlugin,
+        uuid_hash::UuidBuildHasher,
     },
 };
 
+pub mod advancement;
+pub mod afk;
 pub mod animation;
 pub mod blocks;
 pub mod command;
+pub mod cooldown;
+pub mod death_drops;
 pub mod entity_kind;
 pub mod event;
+pub mod game_rules;
 pub mod handlers;
+pub mod history;
+pub mod hunger;
+mod ign_map;
 pub mod inventory;
+pub mod item_use;
+pub mod join;
+pub mod keep_alive;
+pub mod loading;
+pub mod max_health;
 pub mod metadata;
+pub mod minecraft_id;
+pub mod npc;
 pub mod packet;
 pub mod packet_state;
+pub mod persistence;
+pub mod redstone;
+pub mod region;
+pub mod registry;
+pub mod session;
+pub mod sign;
 pub mod skin;
+pub mod statistics;
+pub mod team;
+pub mod tick_rate;
 pub mod util;
+pub mod uuid_hash;
+pub mod vanish;
+pub mod vehicle;
+pub mod world;
+
+pub use ign_map::IgnMap;
 
 #[derive(Resource, Default, Debug)]
 #[cfg_attr(feature = "reflect", derive(Reflect), reflect(Resource))]
@@ -88,11 +133,12 @@ impl std::ops::DerefMut for StreamLookup {
 #[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
 pub struct PlayerUuidLookup {
     /// The UUID of all players
-    inner: HashMap<Uuid, Entity>,
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    inner: HashMap<Uuid, Entity, UuidBuildHasher>,
 }
 
 impl std::ops::Deref for PlayerUuidLookup {
-    type Target = HashMap<Uuid, Entity>;
+    type Target = HashMap<Uuid, Entity, UuidBuildHasher>;
 
     fn deref(&self) -> &Self::Target {
         &self.inner
@@ -109,11 +155,19 @@ impl std::ops::DerefMut for PlayerUuidLookup {
 #[derive(Clone)]
 pub struct EgressComm {
     pub(crate) tx: tokio::sync::mpsc::UnboundedSender<bytes::Bytes>,
+    /// Wakes the task writing to the proxy, which writes everything sent through `tx` so far
+    pub(crate) flush: Arc<tokio::sync::Notify>,
+    /// The features negotiated with the proxy. Messages needing other features are not sent.
+    pub(crate) features: hyperion_proto::Features,
 }
 
 impl From<tokio::sync::mpsc::UnboundedSender<bytes::Bytes>> for EgressComm {
     fn from(tx: tokio::sync::mpsc::UnboundedSender<bytes::Bytes>) -> Self {
-        Self { tx }
+        Self {
+            tx,
+            flush: Arc::default(),
+            features: hyperion_proto::Features::ALL,
+        }
     }
 }
 
@@ -131,24 +185,6 @@ impl std::ops::DerefMut for EgressComm {
     }
 }
 
-#[derive(Resource, Debug, Default)]
-#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Resource))]
-pub struct IgnMap(#[cfg_attr(feature = "reflect", reflect(ignore))] FxHashMap<String, Entity>);
-
-impl std::ops::Deref for IgnMap {
-    type Target = FxHashMap<String, Entity>;
-
-    fn deref(&self) -> &Self::Target {
-        &self.0
-    }
-}
-
-impl std::ops::DerefMut for IgnMap {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
-    }
-}
-
 #[derive(Component, Debug, Default)]
 #[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
 pub struct RaycastTravel;
@@ -319,10 +355,26 @@ impl Xp {
 
 pub const FULL_HEALTH: f32 = 20.0;
 
+/// The block interaction sequences of a player which have not been acknowledged yet.
+///
+/// The interaction handlers push the sequence of every dig, placement and item use. Once per tick,
+/// after the block updates of the tick have been sent, the highest pending sequence is
+/// acknowledged with a single packet, which acknowledges every lower sequence as well. Block
+/// updates which correct a rejected interaction therefore arrive before the acknowledgment, as long
+/// as they are sent during `FixedUpdate`.
 #[derive(Component, Debug, Default)]
 #[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
 pub struct ConfirmBlockSequences(pub Vec<i32>);
 
+impl ConfirmBlockSequences {
+    /// Removes every pending sequence and returns the highest one
+    pub fn take_highest(&mut self) -> Option<i32> {
+        let highest = self.0.iter().max().copied();
+        self.0.clear();
+        highest
+    }
+}
+
 impl std::ops::Deref for ConfirmBlockSequences {
     type Target = Vec<i32>;
 
@@ -346,10 +398,10 @@ pub struct ImmuneStatus {
 }
 
 impl ImmuneStatus {
+    /// Whether the player is still immune during `tick`
     #[must_use]
-    #[expect(missing_docs)]
-    pub const fn is_invincible(&self, global: &Global) -> bool {
-        global.tick < self.until
+    pub const fn is_invincible(&self, tick: &Tick) -> bool {
+        tick.0 < self.until
     }
 }
 
@@ -363,6 +415,12 @@ impl Uuid {
     pub fn new_v4() -> Self {
         Self(uuid::Uuid::new_v4())
     }
+
+    /// Creates a random version 4 UUID from `rng`, such as a stream of the [`GameRng`]
+    #[must_use]
+    pub fn from_rng(rng: &mut fastrand::Rng) -> Self {
+        Self(uuid::Builder::from_random_bytes(rng.u128(..).to_le_bytes()).into_uuid())
+    }
 }
 
 impl std::ops::Deref for Uuid {
@@ -561,24 +619,14 @@ impl Position {
     }
 }
 
-#[derive(Component, Debug, Copy, Clone)]
+/// The chunk that a client's view is centered on
+#[derive(Component, Default, Debug, Copy, Clone)]
 #[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
 pub struct ChunkPosition {
+    /// The center chunk last sent to the client, or [`None`] if no chunks have been sent since
+    /// the player joined or changed worlds
     #[cfg_attr(feature = "reflect", reflect(ignore))]
-    pub position: I16Vec2, // TODO: Reflect this once glam is updated everywhere
-}
-
-const SANE_MAX_RADIUS: i16 = 128;
-
-impl ChunkPosition {
-    #[must_use]
-    #[expect(missing_docs)]
-    pub const fn null() -> Self {
-        // todo: huh
-        Self {
-            position: I16Vec2::new(SANE_MAX_RADIUS, SANE_MAX_RADIUS),
-        }
-    }
+    pub position: Option<I16Vec2>, // TODO: Reflect this once glam is updated everywhere
 }
 
 #[must_use]
@@ -646,6 +694,12 @@ impl Velocity {
     }
 }
 
+/// The `data` field of the spawn packet of an entity which is not a player. Its meaning depends on
+/// the [`EntityKind`], such as the facing direction of item frames and paintings.
+#[derive(Component, Default, Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+pub struct ObjectData(pub i32);
+
 #[derive(Component, Default, Debug, Copy, Clone, PartialEq)]
 #[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
 pub struct PendingTeleportation {
@@ -657,10 +711,12 @@ pub struct PendingTeleportation {
 }
 
 impl PendingTeleportation {
+    /// Creates a teleportation to `destination`. Its teleport ID is drawn from the [`GameRng`]
+    /// once it is inserted.
     #[must_use]
-    pub fn new(destination: Vec3) -> Self {
+    pub const fn new(destination: Vec3) -> Self {
         Self {
-            teleport_id: fastrand::i32(..),
+            teleport_id: 0,
             destination,
             ttl: 20,
         }
@@ -700,6 +756,12 @@ pub struct MovementTracking {
     pub server_velocity: DVec3,
     pub sprinting: bool,
     pub was_on_ground: bool,
+    /// The number of movement packets which were merged into another one when they were
+    /// received since the last sync
+    pub coalesced_movement_packets: u16,
+    /// The lowest height the player landed at in a movement packet which was merged into another
+    /// one since the last sync, which is kept to apply fall damage
+    pub coalesced_landing_y: Option<f32>,
 }
 
 #[derive(Component, Default, Debug, Copy, Clone)]
@@ -715,6 +777,7 @@ fn initialize_player(
     compose: Res<'_, Compose>,
     name_query: Query<'_, '_, &Name>,
     connection_id_query: Query<'_, '_, &ConnectionId>,
+    localizer: Localizer<'_, '_>,
     mut commands: Commands<'_, '_>,
 ) {
     commands.entity(now_playing.entity).insert((
@@ -723,6 +786,7 @@ fn initialize_player(
         Flight::default(),
         FlyingSpeed::default(),
         hyperion_inventory::CursorItem::default(),
+        Statistics::default(),
     ));
 
     let Ok(name) = name_query.get(now_playing.entity) else {
@@ -730,9 +794,10 @@ fn initialize_player(
         return;
     };
 
-    if let Some(other) = ign_map.insert(name.to_string(), now_playing.entity) {
+    if let Some(other) = ign_map.insert(name.as_str(), now_playing.entity) {
         // Another player with the same username is already connected to the server.
-        // Disconnect the previous player with the same username.
+        // Disconnect the previous player with the same username. Usernames which only differ by
+        // case belong to different accounts, so they are not affected.
         // There are some Minecraft accounts with the same username, but this is an extremely
         // rare edge case which is not worth handling.
 
@@ -745,12 +810,14 @@ fn initialize_player(
         };
 
         let pkt = play::DisconnectS2c {
-            reason: "A different player with the same username as your account has joined on a \
-                     different device"
+            reason: localizer
+                .tr(other, "kick.duplicate_login", &Args::new())
                 .into_cow_text(),
         };
 
-        compose.unicast(&pkt, other_connection_id).unwrap();
+        compose
+            .unicast(&pkt, other_connection_id)
+            .unwrap_or_disconnected();
         compose.io_buf().shutdown(other_connection_id);
     }
 }
@@ -768,11 +835,11 @@ fn remove_player(
         }
     };
 
-    match ign_map.entry(name.to_string()) {
-        Entry::Occupied(entry) => {
-            if *entry.get() == not_playing.entity {
+    match ign_map.get(name.as_str()) {
+        Some(entity) => {
+            if entity == not_playing.entity {
                 // This entry points to the same entity that got disconnected
-                entry.remove();
+                ign_map.remove(name.as_str());
             } else {
                 info!(
                     "skipped removing player '{name}' from ign map on disconnect: a different \
@@ -781,7 +848,7 @@ fn remove_player(
                 );
             }
         }
-        Entry::Vacant(_) => {
+        None => {
             error!(
                 "failed to remove player '{name}' from ign map on disconnect: player is not in \
                  ign map"
@@ -798,17 +865,34 @@ fn initialize_uuid(known_entitykind: On<'_, '_, Add, EntityKind>, mut commands:
 
         // This doesn't use insert_if_new to avoid the cost of generating a random uuid if it is not needed
         if entity.get::<Uuid>().is_none() {
-            entity.insert(Uuid::new_v4());
+            // Entities are spawned from parallel systems in an unspecified order, so the UUID is
+            // keyed by the entity instead of being drawn from a shared stream
+            let mut rng = entity.world().resource::<GameRng>().fork_keyed("uuid", e);
+            entity.insert(Uuid::from_rng(&mut rng));
         }
     });
 }
 
+fn free_minecraft_id(
+    despawned: On<'_, '_, Despawn, EntityKind>,
+    mut ids: ResMut<'_, MinecraftIdRegistry>,
+) {
+    ids.despawned(despawned.entity);
+}
+
+fn free_despawned_minecraft_ids(mut ids: ResMut<'_, MinecraftIdRegistry>, entities: &Entities) {
+    ids.free_despawned(entities);
+}
+
 fn send_pending_teleportation(
     now_teleporting: On<'_, '_, Insert, PendingTeleportation>,
-    query: Query<'_, '_, (&PendingTeleportation, &Yaw, &Pitch, &ConnectionId)>,
+    mut query: Query<'_, '_, (&mut PendingTeleportation, &Yaw, &Pitch, &ConnectionId)>,
     compose: Res<'_, Compose>,
+    game_rng: Res<'_, GameRng>,
+    tick: Res<'_, Tick>,
 ) {
-    let (pending_teleportation, yaw, pitch, &connection) = match query.get(now_teleporting.entity) {
+    let entity = now_teleporting.entity;
+    let (mut pending_teleportation, yaw, pitch, &connection) = match query.get_mut(entity) {
         Ok(data) => data,
         Err(e) => {
             error!("failed to send pending teleportation: query failed: {e}");
@@ -816,6 +900,16 @@ fn send_pending_teleportation(
         }
     };
 
+    // Teleportations are inserted from parallel systems in an unspecified order, so the ID is
+    // keyed by the teleportation instead of being drawn from a shared stream
+    let destination = pending_teleportation
+        .destination
+        .to_array()
+        .map(f32::to_bits);
+    pending_teleportation.teleport_id = game_rng
+        .fork_keyed("teleport_id", (entity, tick.0, destination))
+        .i32(..);
+
     let pkt = play::PlayerPositionLookS2c {
         position: pending_teleportation.destination.as_dvec3(),
         yaw: **yaw,
@@ -824,7 +918,7 @@ fn send_pending_teleportation(
         teleport_id: VarInt(pending_teleportation.teleport_id),
     };
 
-    compose.unicast(&pkt, connection).unwrap();
+    compose.unicast(&pkt, connection).unwrap_or_disconnected();
 }
 
 fn update_flight(
@@ -844,7 +938,9 @@ fn update_flight(
         fov_modifier: 0.0,
     };
 
-    compose.unicast(&pkt, connection_id).unwrap();
+    compose
+        .unicast(&pkt, connection_id)
+        .unwrap_or_disconnected();
 }
 
 pub struct SimPlugin;
@@ -856,13 +952,42 @@ impl Plugin for SimPlugin {
         app.add_observer(send_pending_teleportation);
         app.add_observer(update_flight);
         app.add_observer(initialize_uuid);
+        app.add_observer(free_minecraft_id);
+        app.add_observer(session::remove_session);
+
+        app.init_resource::<MinecraftIdRegistry>();
+        app.init_resource::<session::PlayerSessions>();
+        app.init_resource::<registry::RegistryCodec>();
+        app.add_systems(Last, free_despawned_minecraft_ids);
+        app.init_resource::<world::Worlds>();
 
         app.add_plugins((
             CommandPlugin,
             HandlersPlugin,
+            HungerPlugin,
             PacketPlugin,
             InventoryPlugin,
+            ItemUsePlugin,
             MetadataPlugin,
+            RedstonePlugin,
+            RestorePlugin,
+            ScheduledUpdatePlugin,
+            SchematicPlugin,
+            SignPlugin,
+            SkinFetchPlugin,
+            StatisticsPlugin,
+            TeamsPlugin,
+        ));
+        app.add_plugins((
+            AfkPlugin,
+            DeathDropsPlugin,
+            GameRulesPlugin,
+            KeepAlivePlugin,
+            max_health::MaxHealthPlugin,
+            region::RegionPlugin,
+            TickRatePlugin,
+            vanish::VanishPlugin,
+            vehicle::VehiclePlugin,
         ));
 
         app.add_message::<RequestSubscribeChannelPackets>();
@@ -876,6 +1001,7 @@ impl Plugin for SimPlugin {
         app.add_message::<event::ToggleDoor>();
         app.add_message::<event::SwingArm>();
         app.add_message::<event::ReleaseUseItem>();
+        app.add_message::<event::FoodConsumed>();
         app.add_message::<event::PostureUpdate>();
         app.add_message::<event::BlockInteract>();
         app.add_message::<event::ProjectileEntityEvent>();
@@ -884,7 +1010,15 @@ impl Plugin for SimPlugin {
         app.add_message::<event::DropItemStackEvent>();
         app.add_message::<event::UpdateSelectedSlotEvent>();
         app.add_message::<event::HitGroundEvent>();
+        app.add_message::<event::StarvationEvent>();
         app.add_message::<event::InteractEvent>();
+        app.add_message::<event::OpenSignEditor>();
+        app.add_message::<event::SignChanged>();
+        app.add_message::<event::ActivateBlock>();
+        app.add_message::<event::ScheduledBlockUpdate>();
+        app.add_message::<event::SlotChanged>();
+        app.add_message::<event::PlayerWentAfk>();
+        app.add_message::<event::PlayerReturned>();
     }
 }
 
@@ -0,0 +1,197 @@
+//! Spawning NPCs which look like players. See [`NpcPlayerBuilder`].
+
+use bevy_ecs::{component::Component, entity::Entity, name::Name, system::Commands};
+use glam::Vec3;
+#[cfg(feature = "reflect")]
+use {bevy_ecs::reflect::ReflectComponent, bevy_reflect::Reflect};
+
+use crate::{
+    ingress::auth::offline_uuid,
+    net::Channel,
+    simulation::{
+        EntitySize, Pitch, Position, Uuid, Velocity, Yaw, entity_kind::EntityKind,
+        skin::PlayerSkin, world::WorldId,
+    },
+};
+
+/// The maximum length of a player name accepted by the client
+pub const MAX_NAME_LEN: usize = 16;
+
+/// Whether an NPC stays in the tab list of the players which can see it. NPCs which are not
+/// listed, including NPCs without this component, are only in the player list until the client
+/// has loaded their skin.
+#[derive(Component, Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+pub struct NpcTabList(pub bool);
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum NpcPlayerError {
+    #[error("NPC name {name:?} is longer than {MAX_NAME_LEN} characters")]
+    NameTooLong { name: String },
+}
+
+/// Builds an NPC which looks like a player:
+///
+/// ```no_run
+/// # use bevy_ecs::system::Commands;
+/// # use hyperion::simulation::{npc::NpcPlayerBuilder, skin::PlayerSkin};
+/// # fn spawn(mut commands: Commands<'_, '_>, skin: PlayerSkin) -> anyhow::Result<()> {
+/// let npc = NpcPlayerBuilder::new("Shopkeeper")
+///     .skin(skin)
+///     .position(glam::Vec3::new(0.5, 64.0, 0.5))
+///     .tablist(false)
+///     .build(&mut commands)?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Players which can see the NPC are sent its spawn packets once it is spawned, like for any
+/// other entity with a [`Channel`].
+#[derive(Clone, Debug)]
+#[must_use]
+pub struct NpcPlayerBuilder {
+    name: String,
+    uuid: Option<uuid::Uuid>,
+    skin: Option<PlayerSkin>,
+    position: Vec3,
+    yaw: f32,
+    pitch: f32,
+    world: WorldId,
+    tablist: bool,
+}
+
+impl NpcPlayerBuilder {
+    /// Starts building an NPC with the name `name`, which is shown above its head.
+    ///
+    /// By default, the NPC has the default skin of the client, is not in the tab list and stands
+    /// at the origin of the primary world.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            uuid: None,
+            skin: None,
+            position: Vec3::ZERO,
+            yaw: 0.0,
+            pitch: 0.0,
+            world: WorldId::PRIMARY,
+            tablist: false,
+        }
+    }
+
+    /// Sets the UUID of the NPC. Otherwise, the offline UUID of its name is used, so NPCs with
+    /// the same name need different UUIDs.
+    pub const fn uuid(mut self, uuid: uuid::Uuid) -> Self {
+        self.uuid = Some(uuid);
+        self
+    }
+
+    pub fn skin(mut self, skin: PlayerSkin) -> Self {
+        self.skin = Some(skin);
+        self
+    }
+
+    pub const fn position(mut self, position: Vec3) -> Self {
+        self.position = position;
+        self
+    }
+
+    /// Sets the direction the NPC looks in, in degrees
+    pub const fn rotation(mut self, yaw: f32, pitch: f32) -> Self {
+        self.yaw = yaw;
+        self.pitch = pitch;
+        self
+    }
+
+    pub const fn world(mut self, world: WorldId) -> Self {
+        self.world = world;
+        self
+    }
+
+    /// Whether the NPC is shown in the tab list. See [`NpcTabList`].
+    pub const fn tablist(mut self, tablist: bool) -> Self {
+        self.tablist = tablist;
+        self
+    }
+
+    /// Spawns the NPC
+    ///
+    /// # Errors
+    /// If the name of the NPC is longer than [`MAX_NAME_LEN`] characters
+    pub fn build(self, commands: &mut Commands<'_, '_>) -> Result<Entity, NpcPlayerError> {
+        if self.name.chars().count() > MAX_NAME_LEN {
+            return Err(NpcPlayerError::NameTooLong { name: self.name });
+        }
+
+        let uuid = self.uuid.unwrap_or_else(|| offline_uuid(&self.name));
+
+        // Everything is inserted at once, since the observers of the channel need the other
+        // components
+        let mut npc = commands.spawn((
+            EntityKind::Player,
+            Name::new(self.name),
+            Uuid(uuid),
+            Position::from(self.position),
+            Yaw::new(self.yaw),
+            Pitch::new(self.pitch),
+            Velocity::default(),
+            EntitySize::default(),
+            NpcTabList(self.tablist),
+            self.world,
+            Channel,
+        ));
+
+        // The skin is only read once the spawn packets are sent, which is after the commands have
+        // been applied
+        if let Some(skin) = self.skin {
+            npc.insert(skin);
+        }
+
+        Ok(npc.id())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_ecs::world::{CommandQueue, World};
+
+    use super::*;
+
+    #[test]
+    fn names_are_limited_to_16_characters() {
+        let world = World::new();
+        let mut queue = CommandQueue::default();
+        let mut commands = Commands::new(&mut queue, &world);
+
+        assert!(
+            NpcPlayerBuilder::new("Sixteen_Letters_")
+                .build(&mut commands)
+                .is_ok()
+        );
+        assert_eq!(
+            NpcPlayerBuilder::new("Seventeen_Letters").build(&mut commands),
+            Err(NpcPlayerError::NameTooLong {
+                name: "Seventeen_Letters".to_owned()
+            })
+        );
+    }
+
+    #[test]
+    fn npcs_get_offline_uuids() {
+        let mut world = World::new();
+        let mut queue = CommandQueue::default();
+        let mut commands = Commands::new(&mut queue, &world);
+
+        let npc = NpcPlayerBuilder::new("Notch")
+            .tablist(true)
+            .build(&mut commands)
+            .unwrap();
+        queue.apply(&mut world);
+
+        let npc = world.entity(npc);
+        assert_eq!(npc.get::<Uuid>().unwrap().0, offline_uuid("Notch"));
+        assert_eq!(npc.get::<EntityKind>(), Some(&EntityKind::Player));
+        assert_eq!(npc.get::<NpcTabList>(), Some(&NpcTabList(true)));
+        assert!(npc.contains::<Channel>());
+        assert!(!npc.contains::<PlayerSkin>());
+    }
+}
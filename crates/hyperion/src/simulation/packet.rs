@@ -3,7 +3,6 @@ use std::cmp::Ordering;
 use bevy_app::{App, Plugin};
 use bevy_ecs::{entity::Entity, message::Message};
 use hyperion_packet_macros::for_each_state;
-use hyperion_utils::EntityExt;
 
 use crate::net::ConnectionId;
 
@@ -36,12 +35,6 @@ impl<T> Packet<T> {
         self.connection_id
     }
 
-    /// Minecraft id of the player who sent this packet. This is included for convenience; it is
-    /// the same Minecraft id in the [`Packet::sender`] entity.
-    pub fn minecraft_id(&self) -> i32 {
-        self.sender().minecraft_id()
-    }
-
     /// Unique monotonically-increasing packet id
     pub const fn id(&self) -> u64 {
         self.id
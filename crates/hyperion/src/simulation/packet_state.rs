@@ -35,6 +35,7 @@ pub struct Login;
 /// - [`crate::simulation::skin::PlayerSkin`]
 /// - [`crate::simulation::Position`]
 /// - [`crate::simulation::Player`]
+/// - [`crate::simulation::join::PlayerGameMode`]
 #[derive(Component)]
 #[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
 pub struct Play;
@@ -0,0 +1,752 @@
+//! Saving and restoring player state across restarts.
+//!
+//! [`PlayerPersistencePlugin`] stores a [`PlayerSnapshot`] for every player in the [`LocalDb`] when
+//! they disconnect and periodically while they are connected. The snapshot is restored when the
+//! player joins again, before the spawn packets are sent, so the player appears where they left
+//! off, in the world and game mode they left in.
+
+use bevy_app::{App, FixedUpdate, Plugin};
+use bevy_ecs::{
+    component::Component,
+    entity::Entity,
+    lifecycle::{Add, Remove},
+    observer::On,
+    query::{With, Without},
+    system::{Commands, Query, Res},
+};
+use glam::Vec3;
+use hyperion_inventory::{ItemSlot, PlayerInventory};
+use rkyv::util::AlignedVec;
+use thiserror::Error;
+use tracing::{error, warn};
+use valence_protocol::{GameMode, packets::play};
+use valence_server::{ItemKind, ItemStack};
+
+use crate::{
+    Tick,
+    net::{Compose, ConnectionId},
+    simulation::{
+        Pitch, Position, Uuid, Xp, Yaw,
+        join::PlayerGameMode,
+        max_health::MaxHealth,
+        metadata::living_entity::Health,
+        packet_state,
+        session::{PlayerSessions, SessionGeneration},
+        skin::PlayerSkin,
+        statistics::{StatisticCategory, StatisticId, Statistics},
+        world::{WorldId, Worlds},
+    },
+    storage::{LocalDb, PlayerDataHandler},
+};
+
+/// Identifies encoded snapshots
+const MAGIC: [u8; 2] = *b"HP";
+
+/// The snapshot format version written by this version of Hyperion. Increment this and add a
+/// migration in [`PlayerSnapshot::from_bytes`] when changing [`PlayerSnapshot`].
+const CURRENT_VERSION: u16 = 3;
+
+const HEADER_LEN: usize = MAGIC.len() + size_of::<u16>();
+
+/// How often connected players are saved
+const AUTOSAVE_INTERVAL_TICKS: i64 = 20 * 60;
+
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    #[error("snapshot is too short to contain a header")]
+    MissingHeader,
+    #[error("snapshot has an invalid header")]
+    BadMagic,
+    #[error("unsupported snapshot version {0}")]
+    UnsupportedVersion(u16),
+    #[error("failed to decode snapshot: {0}")]
+    Decode(#[from] rkyv::rancor::Error),
+    #[error("failed to decode item nbt: {0}")]
+    Nbt(#[from] valence_nbt::Error),
+}
+
+/// An item in a [`PlayerSnapshot`].
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    rkyv::Archive,
+    rkyv::Serialize,
+    rkyv::Deserialize
+)]
+pub struct SavedItem {
+    pub slot: u16,
+    pub item: u16,
+    pub count: i8,
+    /// The item nbt in the binary nbt format
+    pub nbt: Option<Vec<u8>>,
+}
+
+/// A statistic in a [`PlayerSnapshot`].
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    rkyv::Archive,
+    rkyv::Serialize,
+    rkyv::Deserialize
+)]
+pub struct SavedStatistic {
+    pub category: u8,
+    pub id: u16,
+    pub value: i32,
+}
+
+/// The saved state of a player.
+///
+/// Active effects are not included because Hyperion does not track them as components.
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    rkyv::Archive,
+    rkyv::Serialize,
+    rkyv::Deserialize
+)]
+pub struct PlayerSnapshot {
+    pub position: [f32; 3],
+    pub yaw: f32,
+    pub pitch: f32,
+    pub health: Option<f32>,
+    pub xp: u16,
+    /// The selected hotbar slot from 0 to 8
+    pub selected_slot: u16,
+    pub items: Vec<SavedItem>,
+    pub statistics: Vec<SavedStatistic>,
+    /// The protocol id of the [`PlayerGameMode`], if the player had one
+    pub game_mode: Option<u8>,
+    /// The raw [`WorldId`]. Secondary worlds only keep their id across restarts if they are
+    /// created in the same order.
+    pub world: u16,
+}
+
+/// The snapshot format before the game mode and world were saved
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+struct PlayerSnapshotV2 {
+    position: [f32; 3],
+    yaw: f32,
+    pitch: f32,
+    health: Option<f32>,
+    xp: u16,
+    selected_slot: u16,
+    items: Vec<SavedItem>,
+    statistics: Vec<SavedStatistic>,
+}
+
+/// The snapshot format before statistics were saved
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+struct PlayerSnapshotV1 {
+    position: [f32; 3],
+    yaw: f32,
+    pitch: f32,
+    health: Option<f32>,
+    xp: u16,
+    selected_slot: u16,
+    items: Vec<SavedItem>,
+}
+
+impl From<PlayerSnapshotV1> for PlayerSnapshotV2 {
+    fn from(v1: PlayerSnapshotV1) -> Self {
+        Self {
+            position: v1.position,
+            yaw: v1.yaw,
+            pitch: v1.pitch,
+            health: v1.health,
+            xp: v1.xp,
+            selected_slot: v1.selected_slot,
+            items: v1.items,
+            statistics: Vec::new(),
+        }
+    }
+}
+
+impl From<PlayerSnapshotV2> for PlayerSnapshot {
+    fn from(v2: PlayerSnapshotV2) -> Self {
+        Self {
+            position: v2.position,
+            yaw: v2.yaw,
+            pitch: v2.pitch,
+            health: v2.health,
+            xp: v2.xp,
+            selected_slot: v2.selected_slot,
+            items: v2.items,
+            statistics: v2.statistics,
+            game_mode: None,
+            world: WorldId::PRIMARY.inner(),
+        }
+    }
+}
+
+const fn game_mode_to_raw(game_mode: GameMode) -> u8 {
+    match game_mode {
+        GameMode::Survival => 0,
+        GameMode::Creative => 1,
+        GameMode::Adventure => 2,
+        GameMode::Spectator => 3,
+    }
+}
+
+const fn game_mode_from_raw(raw: u8) -> Option<GameMode> {
+    match raw {
+        0 => Some(GameMode::Survival),
+        1 => Some(GameMode::Creative),
+        2 => Some(GameMode::Adventure),
+        3 => Some(GameMode::Spectator),
+        _ => None,
+    }
+}
+
+impl PlayerSnapshot {
+    /// Encodes the snapshot with a version header.
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let body = rkyv::to_bytes::<rkyv::rancor::Error>(self).unwrap();
+
+        let mut bytes = Vec::with_capacity(HEADER_LEN + body.len());
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&CURRENT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&body);
+        bytes
+    }
+
+    /// Decodes a snapshot produced by [`PlayerSnapshot::to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        let Some((header, body)) = bytes.split_at_checked(HEADER_LEN) else {
+            return Err(SnapshotError::MissingHeader);
+        };
+
+        let (magic, version) = header.split_at(MAGIC.len());
+        if magic != MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+
+        let version = u16::from_le_bytes([version[0], version[1]]);
+
+        // Database entries are not guaranteed to be aligned
+        let mut aligned = AlignedVec::<16>::with_capacity(body.len());
+        aligned.extend_from_slice(body);
+
+        match version {
+            1 => {
+                let v1 = rkyv::from_bytes::<PlayerSnapshotV1, rkyv::rancor::Error>(&aligned)?;
+                Ok(PlayerSnapshotV2::from(v1).into())
+            }
+            2 => Ok(rkyv::from_bytes::<PlayerSnapshotV2, rkyv::rancor::Error>(&aligned)?.into()),
+            CURRENT_VERSION => Ok(rkyv::from_bytes::<Self, rkyv::rancor::Error>(&aligned)?),
+            version => Err(SnapshotError::UnsupportedVersion(version)),
+        }
+    }
+
+    #[expect(clippy::too_many_arguments)]
+    fn capture(
+        position: &Position,
+        yaw: &Yaw,
+        pitch: &Pitch,
+        health: Option<&Health>,
+        xp: Option<&Xp>,
+        inventory: &PlayerInventory,
+        statistics: Option<&Statistics>,
+        game_mode: Option<&PlayerGameMode>,
+        world: Option<&WorldId>,
+    ) -> Self {
+        let items = inventory
+            .items()
+            .map(|(slot, stack)| SavedItem {
+                slot,
+                item: stack.item.to_raw(),
+                count: stack.count,
+                nbt: stack.nbt.as_ref().map(|nbt| {
+                    let mut bytes = Vec::new();
+                    valence_nbt::to_binary(nbt, &mut bytes, "").unwrap();
+                    bytes
+                }),
+            })
+            .collect();
+
+        Self {
+            position: position.to_array(),
+            yaw: **yaw,
+            pitch: **pitch,
+            health: health.map(|health| **health),
+            xp: xp.map_or(0, |xp| xp.amount),
+            selected_slot: u16::from(inventory.selected_hotbar_index()),
+            items,
+            statistics: statistics
+                .into_iter()
+                .flat_map(Statistics::iter)
+                .map(|(id, value)| SavedStatistic {
+                    category: id.category().to_raw(),
+                    id: id.id(),
+                    value,
+                })
+                .collect(),
+            game_mode: game_mode.map(|game_mode| game_mode_to_raw(game_mode.0)),
+            world: world.copied().unwrap_or_default().inner(),
+        }
+    }
+
+    fn restore_statistics(&self) -> Statistics {
+        self.statistics
+            .iter()
+            .filter_map(|saved| {
+                let Some(category) = StatisticCategory::from_raw(saved.category) else {
+                    warn!(
+                        "skipping saved statistic with unknown category {}",
+                        saved.category
+                    );
+                    return None;
+                };
+                Some((StatisticId::new(category, saved.id), saved.value))
+            })
+            .collect()
+    }
+
+    fn restore_inventory(&self, inventory: &mut PlayerInventory) -> Result<(), SnapshotError> {
+        inventory.clear(|_| true);
+
+        for item in &self.items {
+            let Some(kind) = ItemKind::from_raw(item.item) else {
+                warn!("skipping saved item with unknown id {}", item.item);
+                continue;
+            };
+
+            let nbt = match &item.nbt {
+                Some(bytes) => Some(valence_nbt::from_binary(&mut bytes.as_slice())?.0),
+                None => None,
+            };
+
+            let slot = ItemSlot {
+                readonly: false,
+                stack: ItemStack::new(kind, item.count, nbt),
+                changed: true,
+            };
+
+            if let Err(e) = inventory.set_slot(item.slot, slot) {
+                warn!("skipping saved item in slot {}: {e}", item.slot);
+            }
+        }
+
+        // Slots which do not fit into a `u8` are rejected like any other slot outside the hotbar
+        let selected = u8::try_from(self.selected_slot).unwrap_or(u8::MAX);
+        if let Err(e) = inventory.set_selected_hotbar_index(selected) {
+            warn!(
+                "failed to restore selected slot {}: {e}",
+                self.selected_slot
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Clamps saved health to `(0, max]`. Saved data may come from an older version or a different
+/// maximum, and a player restored with no health would be stuck dead.
+fn clamp_health(saved: f32, max: f32) -> f32 {
+    if saved.is_nan() {
+        return max;
+    }
+    saved.clamp(f32::MIN_POSITIVE, max)
+}
+
+/// Snapshot data which will be applied once the player inventory has been created.
+#[derive(Component, Debug)]
+struct PendingRestore(PlayerSnapshot);
+
+fn restore_player(
+    added_skin: On<'_, '_, Add, PlayerSkin>,
+    handler: Res<'_, PlayerDataHandler>,
+    worlds: Res<'_, Worlds>,
+    query: Query<'_, '_, &Uuid, With<ConnectionId>>,
+    mut commands: Commands<'_, '_>,
+) {
+    let entity = added_skin.entity;
+    let Ok(uuid) = query.get(entity) else {
+        return;
+    };
+
+    let snapshot = match handler.find(uuid.0) {
+        Ok(Some(Ok(snapshot))) => snapshot,
+        Ok(None) => return,
+        Ok(Some(Err(e))) => {
+            warn!("ignoring corrupt saved data for player {}: {e}", uuid.0);
+            return;
+        }
+        Err(e) => {
+            error!("failed to load saved data for player {}: {e}", uuid.0);
+            return;
+        }
+    };
+
+    let mut player = commands.entity(entity);
+
+    // The position is meaningless in a world which was not created again after the restart
+    let world = WorldId::from_inner(snapshot.world);
+    if world == WorldId::PRIMARY || worlds.contains(world) {
+        player.insert((
+            world,
+            Position::from(Vec3::from_array(snapshot.position)),
+            Yaw::new(snapshot.yaw),
+            Pitch::new(snapshot.pitch),
+        ));
+    } else {
+        warn!(
+            "not restoring the position of player {}: world {world:?} does not exist",
+            uuid.0
+        );
+    }
+
+    if let Some(raw) = snapshot.game_mode {
+        match game_mode_from_raw(raw) {
+            Some(game_mode) => {
+                player.insert(PlayerGameMode(game_mode));
+            }
+            None => warn!("skipping saved game mode with unknown id {raw}"),
+        }
+    }
+
+    player.insert(PendingRestore(snapshot));
+}
+
+fn apply_pending_restore(
+    compose: Res<'_, Compose>,
+    mut query: Query<
+        '_,
+        '_,
+        (
+            Entity,
+            &PendingRestore,
+            &ConnectionId,
+            &mut PlayerInventory,
+            Option<&mut Health>,
+            Option<&MaxHealth>,
+            Option<&mut Xp>,
+            Option<&mut Statistics>,
+        ),
+        With<packet_state::Play>,
+    >,
+    mut commands: Commands<'_, '_>,
+) {
+    for (entity, restore, &connection_id, mut inventory, health, max_health, xp, statistics) in
+        &mut query
+    {
+        let snapshot = &restore.0;
+
+        if let Err(e) = snapshot.restore_inventory(&mut inventory) {
+            warn!("failed to restore inventory of {entity}: {e}");
+        }
+
+        let pkt = play::UpdateSelectedSlotS2c {
+            slot: u8::try_from(snapshot.selected_slot).unwrap_or_default(),
+        };
+        if let Err(e) = compose.unicast(&pkt, connection_id) {
+            error!("failed to send selected slot: {e}");
+        }
+
+        if let (Some(mut health), Some(saved)) = (health, snapshot.health) {
+            let max = max_health.copied().unwrap_or_default();
+            **health = clamp_health(saved, *max);
+        }
+
+        if let Some(mut xp) = xp {
+            xp.amount = snapshot.xp;
+        }
+
+        // Statistics recorded since the player joined are kept
+        let restored = snapshot.restore_statistics();
+        match statistics {
+            Some(mut statistics) => statistics.merge(&restored),
+            None => {
+                commands.entity(entity).insert(restored);
+            }
+        }
+
+        commands.entity(entity).remove::<PendingRestore>();
+    }
+}
+
+type SnapshotQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static Uuid,
+        &'static Position,
+        &'static Yaw,
+        &'static Pitch,
+        Option<&'static Health>,
+        Option<&'static Xp>,
+        &'static PlayerInventory,
+        Option<&'static Statistics>,
+        Option<&'static PlayerGameMode>,
+        Option<&'static WorldId>,
+    ),
+    (With<packet_state::Play>, Without<PendingRestore>),
+>;
+
+/// Whether `entity` belongs to the current session of its player, or is not part of a session.
+/// A newer session may have restored and changed the saved state already, which must not be
+/// overwritten.
+fn is_current_session(
+    entity: Entity,
+    uuid: &Uuid,
+    sessions: &PlayerSessions,
+    generations: &Query<'_, '_, &SessionGeneration>,
+) -> bool {
+    generations
+        .get(entity)
+        .ok()
+        .is_none_or(|&generation| sessions.is_current(uuid.0, generation))
+}
+
+fn save_on_disconnect(
+    removed: On<'_, '_, Remove, packet_state::Play>,
+    handler: Res<'_, PlayerDataHandler>,
+    sessions: Res<'_, PlayerSessions>,
+    query: SnapshotQuery<'_, '_>,
+    generations: Query<'_, '_, &SessionGeneration>,
+) {
+    let Ok((_, uuid, position, yaw, pitch, health, xp, inventory, statistics, game_mode, world)) =
+        query.get(removed.entity)
+    else {
+        return;
+    };
+
+    if !is_current_session(removed.entity, uuid, &sessions, &generations) {
+        warn!("not saving player {}: a newer session replaced it", uuid.0);
+        return;
+    }
+
+    let snapshot = PlayerSnapshot::capture(
+        position, yaw, pitch, health, xp, inventory, statistics, game_mode, world,
+    );
+
+    if let Err(e) = handler.insert_many([(uuid.0, &snapshot)]) {
+        error!("failed to save player {}: {e}", uuid.0);
+    }
+}
+
+fn autosave(
+    tick: Res<'_, Tick>,
+    handler: Res<'_, PlayerDataHandler>,
+    sessions: Res<'_, PlayerSessions>,
+    query: SnapshotQuery<'_, '_>,
+    generations: Query<'_, '_, &SessionGeneration>,
+) {
+    // Players were just restored at tick 0, so the first save is after one full interval
+    if tick.0 <= 0 || tick.0 % AUTOSAVE_INTERVAL_TICKS != 0 {
+        return;
+    }
+
+    let snapshots = query
+        .iter()
+        .filter(|(entity, uuid, ..)| is_current_session(*entity, uuid, &sessions, &generations))
+        .map(
+            |(
+                _,
+                uuid,
+                position,
+                yaw,
+                pitch,
+                health,
+                xp,
+                inventory,
+                statistics,
+                game_mode,
+                world,
+            )| {
+                (
+                    uuid.0,
+                    PlayerSnapshot::capture(
+                        position, yaw, pitch, health, xp, inventory, statistics, game_mode, world,
+                    ),
+                )
+            },
+        )
+        .collect::<Vec<_>>();
+
+    if snapshots.is_empty() {
+        return;
+    }
+
+    let entries = snapshots.iter().map(|(uuid, snapshot)| (*uuid, snapshot));
+    if let Err(e) = handler.insert_many(entries) {
+        error!("failed to autosave {} players: {e}", snapshots.len());
+    }
+}
+
+/// Saves and restores player state. This must be added after [`crate::HyperionCore`].
+pub struct PlayerPersistencePlugin;
+
+impl Plugin for PlayerPersistencePlugin {
+    fn build(&self, app: &mut App) {
+        let db = app.world().resource::<LocalDb>();
+        let handler = PlayerDataHandler::new(db).expect("failed to load player data handler");
+        app.insert_resource(handler);
+
+        app.add_observer(restore_player);
+        app.add_observer(save_on_disconnect);
+        app.add_systems(FixedUpdate, (apply_pending_restore, autosave));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::statistics::CustomStatistic;
+
+    fn snapshot() -> PlayerSnapshot {
+        PlayerSnapshot {
+            position: [1.5, 64.0, -20.25],
+            yaw: 90.0,
+            pitch: -12.5,
+            health: Some(13.0),
+            xp: 42,
+            selected_slot: 4,
+            items: vec![SavedItem {
+                slot: 36,
+                item: ItemKind::DiamondSword.to_raw(),
+                count: 1,
+                nbt: None,
+            }],
+            statistics: vec![SavedStatistic {
+                category: 8,
+                id: 33,
+                value: 12,
+            }],
+            game_mode: Some(game_mode_to_raw(GameMode::Creative)),
+            world: 2,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let snapshot = snapshot();
+        let bytes = snapshot.to_bytes();
+        assert_eq!(&bytes[..2], b"HP");
+        assert_eq!(PlayerSnapshot::from_bytes(&bytes).unwrap(), snapshot);
+    }
+
+    #[test]
+    fn unaligned_round_trip() {
+        let snapshot = snapshot();
+        let mut bytes = vec![0];
+        bytes.extend_from_slice(&snapshot.to_bytes());
+        assert_eq!(PlayerSnapshot::from_bytes(&bytes[1..]).unwrap(), snapshot);
+    }
+
+    #[test]
+    fn corrupt_snapshots_are_errors() {
+        assert!(matches!(
+            PlayerSnapshot::from_bytes(b"H"),
+            Err(SnapshotError::MissingHeader)
+        ));
+        assert!(matches!(
+            PlayerSnapshot::from_bytes(b"XX\x01\x00"),
+            Err(SnapshotError::BadMagic)
+        ));
+        assert!(matches!(
+            PlayerSnapshot::from_bytes(b"HP\x09\x00"),
+            Err(SnapshotError::UnsupportedVersion(9))
+        ));
+
+        let mut bytes = snapshot().to_bytes();
+        bytes.truncate(bytes.len() / 2);
+        assert!(PlayerSnapshot::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn version_1_is_migrated() {
+        let v1 = PlayerSnapshotV1 {
+            position: [0.0, 100.0, 0.0],
+            yaw: 0.0,
+            pitch: 0.0,
+            health: None,
+            xp: 3,
+            selected_slot: 0,
+            items: Vec::new(),
+        };
+
+        let mut bytes = b"HP\x01\x00".to_vec();
+        bytes.extend_from_slice(&rkyv::to_bytes::<rkyv::rancor::Error>(&v1).unwrap());
+
+        let snapshot = PlayerSnapshot::from_bytes(&bytes).unwrap();
+        assert_eq!(snapshot.xp, 3);
+        assert!(snapshot.statistics.is_empty());
+        assert_eq!(snapshot.game_mode, None);
+        assert_eq!(snapshot.world, WorldId::PRIMARY.inner());
+    }
+
+    #[test]
+    fn version_2_is_migrated() {
+        let current = snapshot();
+        let v2 = PlayerSnapshotV2 {
+            position: current.position,
+            yaw: current.yaw,
+            pitch: current.pitch,
+            health: current.health,
+            xp: current.xp,
+            selected_slot: current.selected_slot,
+            items: current.items.clone(),
+            statistics: current.statistics.clone(),
+        };
+
+        let mut bytes = b"HP\x02\x00".to_vec();
+        bytes.extend_from_slice(&rkyv::to_bytes::<rkyv::rancor::Error>(&v2).unwrap());
+
+        // Players saved before the game mode and world keep the ones chosen when joining
+        let snapshot = PlayerSnapshot::from_bytes(&bytes).unwrap();
+        assert_eq!(snapshot, PlayerSnapshot {
+            game_mode: None,
+            world: WorldId::PRIMARY.inner(),
+            ..current
+        });
+    }
+
+    #[test]
+    fn game_modes_round_trip() {
+        for game_mode in [
+            GameMode::Survival,
+            GameMode::Creative,
+            GameMode::Adventure,
+            GameMode::Spectator,
+        ] {
+            assert_eq!(
+                game_mode_from_raw(game_mode_to_raw(game_mode)),
+                Some(game_mode)
+            );
+        }
+        assert_eq!(game_mode_from_raw(4), None);
+    }
+
+    #[test]
+    fn restored_health_is_clamped() {
+        assert_eq!(clamp_health(13.0, 20.0), 13.0);
+        assert_eq!(clamp_health(35.0, 20.0), 20.0);
+        assert_eq!(clamp_health(0.0, 20.0), f32::MIN_POSITIVE);
+        assert_eq!(clamp_health(-4.0, 20.0), f32::MIN_POSITIVE);
+        assert_eq!(clamp_health(f32::NAN, 40.0), 40.0);
+    }
+
+    #[test]
+    fn statistics_round_trip() {
+        let snapshot = snapshot();
+        let statistics = snapshot.restore_statistics();
+        assert_eq!(statistics.get(CustomStatistic::PlayerKills), 12);
+    }
+
+    #[test]
+    fn restored_statistics_are_merged() {
+        let mut statistics = Statistics::default();
+        statistics.increment(CustomStatistic::PlayerKills);
+        statistics.merge(&snapshot().restore_statistics());
+        assert_eq!(statistics.get(CustomStatistic::PlayerKills), 13);
+    }
+}
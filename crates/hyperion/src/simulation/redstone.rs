@@ -0,0 +1,573 @@
+//! A small subset of redstone, which is enough for levers opening iron doors and pressure plates
+//! lighting redstone lamps.
+//!
+//! Power sources are levers, buttons, pressure plates and redstone blocks. A source powers the
+//! blocks next to it. Levers and buttons also power the block they are attached to and pressure
+//! plates the block below them, which in turn powers the blocks next to it if it is a full block.
+//!
+//! Powered iron doors and iron trapdoors open, redstone lamps light up, and note blocks play their
+//! note when they become powered.
+//!
+//! Redstone dust, repeaters, comparators, observers, pistons and every other component are not
+//! supported. Power does not travel further than described above and there is no signal strength
+//! apart from the power level of weighted pressure plates.
+//!
+//! Changed blocks are set with [`Blocks::set_block`], so they are sent to players like any other
+//! block change.
+
+use bevy_app::{App, FixedPostUpdate, Plugin};
+use bevy_ecs::{
+    message::MessageReader,
+    query::Without,
+    resource::Resource,
+    schedule::IntoScheduleConfigs,
+    system::{Query, Res, ResMut},
+};
+use geometry::aabb::Aabb;
+use glam::{IVec3, Vec3};
+use rustc_hash::FxHashSet;
+use tracing::error;
+use valence_generated::block::{BlockKind, BlockState, PropName, PropValue};
+use valence_ident::Ident;
+use valence_protocol::ident;
+#[cfg(feature = "reflect")]
+use {bevy_ecs::reflect::ReflectResource, bevy_reflect::Reflect};
+
+use crate::{
+    Tick,
+    net::{Compose, agnostic},
+    simulation::{
+        EntitySize, Position,
+        blocks::{Blocks, scheduled::ScheduledBlockUpdates},
+        event,
+        vanish::Vanished,
+        world::{WorldBlocksMut, WorldId},
+    },
+    spatial::{Indexed, SpatialIndex},
+};
+
+const NEIGHBOURS: [IVec3; 6] = [
+    IVec3::NEG_Y,
+    IVec3::Y,
+    IVec3::NEG_Z,
+    IVec3::Z,
+    IVec3::NEG_X,
+    IVec3::X,
+];
+
+/// Returns whether `block` does something when a player clicks it, which is the case for levers
+/// and buttons
+#[must_use]
+pub fn is_activatable(block: BlockState) -> bool {
+    let kind = block.to_kind();
+    kind == BlockKind::Lever || is_button(kind)
+}
+
+fn is_button(kind: BlockKind) -> bool {
+    kind.to_str().ends_with("_button")
+}
+
+fn is_pressure_plate(kind: BlockKind) -> bool {
+    kind.to_str().ends_with("_pressure_plate")
+}
+
+/// How many ticks a button stays pressed
+fn button_ticks(kind: BlockKind) -> i64 {
+    match kind {
+        BlockKind::StoneButton | BlockKind::PolishedBlackstoneButton => 20,
+        _ => 30,
+    }
+}
+
+/// Returns whether `block` is a source which currently emits power
+fn is_active_source(block: BlockState) -> bool {
+    if block.to_kind() == BlockKind::RedstoneBlock {
+        return true;
+    }
+
+    if block.get(PropName::Powered) == Some(PropValue::True) {
+        return is_activatable(block) || is_pressure_plate(block.to_kind());
+    }
+
+    // Weighted pressure plates
+    block
+        .get(PropName::Power)
+        .and_then(PropValue::to_u16)
+        .is_some_and(|power| power > 0)
+}
+
+/// Returns the position of the block a source at `position` is attached to, which it powers
+/// through
+fn attached_to(block: BlockState, position: IVec3) -> Option<IVec3> {
+    let kind = block.to_kind();
+
+    if is_pressure_plate(kind) {
+        return Some(position + IVec3::NEG_Y);
+    }
+
+    if !is_activatable(block) {
+        return None;
+    }
+
+    let offset = match block.get(PropName::Face)? {
+        PropValue::Floor => IVec3::NEG_Y,
+        PropValue::Ceiling => IVec3::Y,
+        // Levers and buttons on walls face away from the wall
+        _ => match block.get(PropName::Facing)? {
+            PropValue::North => IVec3::Z,
+            PropValue::South => IVec3::NEG_Z,
+            PropValue::West => IVec3::X,
+            PropValue::East => IVec3::NEG_X,
+            _ => return None,
+        },
+    };
+
+    Some(position + offset)
+}
+
+/// Returns whether power can pass through `block`, which is the case for full blocks
+fn conducts(block: BlockState) -> bool {
+    let mut shapes = block.collision_shapes();
+    let Some(shape) = shapes.next() else {
+        return false;
+    };
+
+    shapes.next().is_none()
+        && shape.min().as_vec3() == Vec3::ZERO
+        && shape.max().as_vec3() == Vec3::ONE
+}
+
+/// Returns whether the block at `position` is powered by a source attached to it
+fn is_strongly_powered(blocks: &Blocks, position: IVec3) -> bool {
+    NEIGHBOURS.iter().any(|&offset| {
+        let source = position + offset;
+        blocks.get_block(source).is_some_and(|block| {
+            is_active_source(block) && attached_to(block, source) == Some(position)
+        })
+    })
+}
+
+/// Returns whether the block at `position` receives power from any of its neighbours
+#[must_use]
+pub fn is_powered(blocks: &Blocks, position: IVec3) -> bool {
+    NEIGHBOURS.iter().any(|&offset| {
+        let neighbour = position + offset;
+        let Some(block) = blocks.get_block(neighbour) else {
+            return false;
+        };
+
+        is_active_source(block) || (conducts(block) && is_strongly_powered(blocks, neighbour))
+    })
+}
+
+/// Sets the block at `position` if it changed
+fn set_if_changed(blocks: &mut Blocks, position: IVec3, old: BlockState, new: BlockState) {
+    if old == new {
+        return;
+    }
+
+    if let Err(e) = blocks.set_block(position, new) {
+        error!("failed to set powered block: {e:?}");
+    }
+}
+
+/// Applies the power the block at `position` receives to it if it reacts to power. Note blocks
+/// which became powered are added to `notes`.
+fn apply_power(blocks: &mut Blocks, position: IVec3, notes: &mut Vec<(IVec3, BlockState)>) {
+    let Some(block) = blocks.get_block(position) else {
+        return;
+    };
+
+    match block.to_kind() {
+        BlockKind::IronDoor => {
+            let other_half = match block.get(PropName::Half) {
+                Some(PropValue::Upper) => position + IVec3::NEG_Y,
+                _ => position + IVec3::Y,
+            };
+
+            let powered = is_powered(blocks, position) || is_powered(blocks, other_half);
+            let value = PropValue::from_bool(powered);
+
+            for half in [position, other_half] {
+                let Some(old) = blocks.get_block(half) else {
+                    continue;
+                };
+                if old.to_kind() != BlockKind::IronDoor {
+                    continue;
+                }
+
+                let new = old.set(PropName::Powered, value).set(PropName::Open, value);
+                set_if_changed(blocks, half, old, new);
+            }
+        }
+        BlockKind::IronTrapdoor => {
+            let value = PropValue::from_bool(is_powered(blocks, position));
+            let new = block
+                .set(PropName::Powered, value)
+                .set(PropName::Open, value);
+            set_if_changed(blocks, position, block, new);
+        }
+        BlockKind::RedstoneLamp => {
+            let value = PropValue::from_bool(is_powered(blocks, position));
+            set_if_changed(blocks, position, block, block.set(PropName::Lit, value));
+        }
+        BlockKind::NoteBlock => {
+            let powered = is_powered(blocks, position);
+            let was_powered = block.get(PropName::Powered) == Some(PropValue::True);
+            if powered && !was_powered {
+                notes.push((position, block));
+            }
+
+            let new = block.set(PropName::Powered, PropValue::from_bool(powered));
+            set_if_changed(blocks, position, block, new);
+        }
+        _ => {}
+    }
+}
+
+/// Updates the blocks which may receive power from the block at `position` after it changed.
+///
+/// This covers the neighbours of the block and their neighbours, which includes every block a
+/// source at `position` can power.
+pub fn update_around(blocks: &mut Blocks, position: IVec3, notes: &mut Vec<(IVec3, BlockState)>) {
+    apply_power(blocks, position, notes);
+
+    for offset in NEIGHBOURS {
+        let neighbour = position + offset;
+        apply_power(blocks, neighbour, notes);
+
+        for offset in NEIGHBOURS {
+            apply_power(blocks, neighbour + offset, notes);
+        }
+    }
+}
+
+/// Flips a lever or presses a button at `position`. Returns whether the block was a lever or
+/// an unpressed button.
+pub fn activate(
+    blocks: &mut Blocks,
+    position: IVec3,
+    notes: &mut Vec<(IVec3, BlockState)>,
+) -> bool {
+    let Some(block) = blocks.get_block(position) else {
+        return false;
+    };
+
+    let powered = block.get(PropName::Powered) == Some(PropValue::True);
+    let new = match block.to_kind() {
+        BlockKind::Lever => block.set(PropName::Powered, PropValue::from_bool(!powered)),
+        kind if is_button(kind) && !powered => block.set(PropName::Powered, PropValue::True),
+        _ => return false,
+    };
+
+    set_if_changed(blocks, position, block, new);
+    update_around(blocks, position, notes);
+    true
+}
+
+/// The sound of a note block with the given instrument
+fn note_sound(instrument: PropValue) -> Option<Ident> {
+    let sound = match instrument {
+        PropValue::Harp => ident!("minecraft:block.note_block.harp"),
+        PropValue::Basedrum => ident!("minecraft:block.note_block.basedrum"),
+        PropValue::Snare => ident!("minecraft:block.note_block.snare"),
+        PropValue::Hat => ident!("minecraft:block.note_block.hat"),
+        PropValue::Bass => ident!("minecraft:block.note_block.bass"),
+        PropValue::Flute => ident!("minecraft:block.note_block.flute"),
+        PropValue::Bell => ident!("minecraft:block.note_block.bell"),
+        PropValue::Guitar => ident!("minecraft:block.note_block.guitar"),
+        PropValue::Chime => ident!("minecraft:block.note_block.chime"),
+        PropValue::Xylophone => ident!("minecraft:block.note_block.xylophone"),
+        PropValue::IronXylophone => ident!("minecraft:block.note_block.iron_xylophone"),
+        PropValue::CowBell => ident!("minecraft:block.note_block.cow_bell"),
+        PropValue::Didgeridoo => ident!("minecraft:block.note_block.didgeridoo"),
+        PropValue::Bit => ident!("minecraft:block.note_block.bit"),
+        PropValue::Banjo => ident!("minecraft:block.note_block.banjo"),
+        PropValue::Pling => ident!("minecraft:block.note_block.pling"),
+        // Mob head instruments are not supported
+        _ => return None,
+    };
+
+    Some(sound)
+}
+
+fn play_notes(compose: &Compose, notes: &mut Vec<(IVec3, BlockState)>) {
+    for (position, block) in notes.drain(..) {
+        let Some(sound) = block.get(PropName::Instrument).and_then(note_sound) else {
+            continue;
+        };
+
+        let note = block
+            .get(PropName::Note)
+            .and_then(PropValue::to_u16)
+            .unwrap_or(0);
+        let pitch = 2.0_f32.powf((f32::from(note) - 12.0) / 12.0);
+
+        let sound = agnostic::sound(sound, position.as_vec3() + Vec3::splat(0.5))
+            .volume(3.0)
+            .pitch(pitch)
+            .build();
+
+        let chunk = (position.xz() >> 4).as_i16vec2();
+        compose.broadcast_local(&sound, chunk).send().unwrap();
+    }
+}
+
+fn activate_blocks(
+    mut events: MessageReader<'_, '_, event::ActivateBlock>,
+    mut worlds: WorldBlocksMut<'_>,
+    mut scheduled: ResMut<'_, ScheduledBlockUpdates>,
+    tick: Res<'_, Tick>,
+    compose: Res<'_, Compose>,
+) {
+    let mut notes = Vec::new();
+
+    for event in events.read() {
+        let Some(blocks) = worlds.get_mut(Some(&event.world)) else {
+            continue;
+        };
+
+        if !activate(blocks, event.position, &mut notes) {
+            continue;
+        }
+
+        if let Some(block) = blocks.get_block(event.position)
+            && is_button(block.to_kind())
+        {
+            let release = tick.0 + button_ticks(block.to_kind());
+            scheduled.schedule(event.world, event.position, release);
+        }
+    }
+
+    play_notes(&compose, &mut notes);
+}
+
+fn release_buttons(
+    mut updates: MessageReader<'_, '_, event::ScheduledBlockUpdate>,
+    mut worlds: WorldBlocksMut<'_>,
+    compose: Res<'_, Compose>,
+) {
+    let mut notes = Vec::new();
+
+    for update in updates.read() {
+        let Some(blocks) = worlds.get_mut(Some(&update.world)) else {
+            continue;
+        };
+
+        let Some(block) = blocks.get_block(update.position) else {
+            continue;
+        };
+
+        if !is_button(block.to_kind()) || block.get(PropName::Powered) != Some(PropValue::True) {
+            continue;
+        }
+
+        set_if_changed(
+            blocks,
+            update.position,
+            block,
+            block.set(PropName::Powered, PropValue::False),
+        );
+        update_around(blocks, update.position, &mut notes);
+    }
+
+    play_notes(&compose, &mut notes);
+}
+
+/// The pressure plates which are currently pressed
+#[derive(Resource, Default, Debug)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Resource))]
+pub struct PressedPlates {
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    plates: FxHashSet<(WorldId, IVec3)>,
+}
+
+/// Presses and releases pressure plates depending on the entities standing on them
+fn update_pressure_plates(
+    mut pressed: ResMut<'_, PressedPlates>,
+    index: Res<'_, SpatialIndex>,
+    entities: Query<'_, '_, (&Position, Option<&WorldId>), (Indexed, Without<Vanished>)>,
+    bounds: Query<'_, '_, (&Position, &EntitySize)>,
+    mut worlds: WorldBlocksMut<'_>,
+    compose: Res<'_, Compose>,
+) {
+    let mut candidates = pressed.plates.clone();
+    for (position, world) in &entities {
+        let feet = position.floor().as_ivec3();
+        candidates.insert((world.copied().unwrap_or_default(), feet));
+    }
+
+    let mut notes = Vec::new();
+
+    for (world, position) in candidates {
+        let Some(blocks) = worlds.get_mut(Some(&world)) else {
+            continue;
+        };
+
+        let Some(block) = blocks.get_block(position) else {
+            pressed.plates.remove(&(world, position));
+            continue;
+        };
+
+        if !is_pressure_plate(block.to_kind()) {
+            pressed.plates.remove(&(world, position));
+            continue;
+        }
+
+        let min = position.as_vec3() + Vec3::new(0.0625, 0.0, 0.0625);
+        let max = position.as_vec3() + Vec3::new(0.9375, 0.25, 0.9375);
+        let count = index
+            .get_collisions(Aabb::new(min, max), bounds)
+            .filter(|&entity| {
+                entities
+                    .get(entity)
+                    .is_ok_and(|(_, id)| id.copied().unwrap_or_default() == world)
+            })
+            .count();
+
+        let new = if block.get(PropName::Power).is_some() {
+            let power = u16::try_from(count.min(15)).unwrap_or(15);
+            let Some(power) = PropValue::from_u16(power) else {
+                continue;
+            };
+            block.set(PropName::Power, power)
+        } else {
+            block.set(PropName::Powered, PropValue::from_bool(count > 0))
+        };
+
+        if count > 0 {
+            pressed.plates.insert((world, position));
+        } else {
+            pressed.plates.remove(&(world, position));
+        }
+
+        if new != block {
+            set_if_changed(blocks, position, block, new);
+            update_around(blocks, position, &mut notes);
+        }
+    }
+
+    play_notes(&compose, &mut notes);
+}
+
+/// Updates powered blocks around blocks which were placed or destroyed
+fn update_changed_blocks(
+    mut placed: MessageReader<'_, '_, event::PlaceBlock>,
+    mut destroyed: MessageReader<'_, '_, event::DestroyBlock>,
+    mut worlds: WorldBlocksMut<'_>,
+    compose: Res<'_, Compose>,
+) {
+    let changed = placed
+        .read()
+        .map(|event| (event.world, event.position))
+        .chain(destroyed.read().map(|event| (event.world, event.position)));
+
+    let mut notes = Vec::new();
+
+    for (world, position) in changed {
+        let Some(blocks) = worlds.get_mut(Some(&world)) else {
+            continue;
+        };
+
+        update_around(blocks, position, &mut notes);
+    }
+
+    play_notes(&compose, &mut notes);
+}
+
+pub struct RedstonePlugin;
+
+impl Plugin for RedstonePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PressedPlates>();
+
+        // Blocks are placed, destroyed and activated by game code during `FixedUpdate`
+        app.add_systems(
+            FixedPostUpdate,
+            (
+                update_changed_blocks,
+                activate_blocks,
+                release_buttons,
+                update_pressure_plates,
+            )
+                .chain(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A flat world with an iron door and a lever on the floor next to it
+    fn door_and_lever() -> (Blocks, IVec3, IVec3) {
+        let mut blocks = Blocks::flat(-60, BlockState::GRASS_BLOCK);
+
+        let door = IVec3::new(4, -60, 4);
+        let lever = door + IVec3::NEG_X;
+
+        blocks.set_block(door, BlockState::IRON_DOOR).unwrap();
+        blocks
+            .set_block(
+                door + IVec3::Y,
+                BlockState::IRON_DOOR.set(PropName::Half, PropValue::Upper),
+            )
+            .unwrap();
+        blocks
+            .set_block(
+                lever,
+                BlockState::LEVER.set(PropName::Face, PropValue::Floor),
+            )
+            .unwrap();
+
+        (blocks, door, lever)
+    }
+
+    fn is_open(blocks: &Blocks, position: IVec3) -> bool {
+        blocks.get_block(position).unwrap().get(PropName::Open) == Some(PropValue::True)
+    }
+
+    #[test]
+    fn lever_toggles_adjacent_iron_door() {
+        let (mut blocks, door, lever) = door_and_lever();
+        let mut notes = Vec::new();
+
+        assert!(!is_open(&blocks, door));
+
+        assert!(activate(&mut blocks, lever, &mut notes));
+        assert!(is_open(&blocks, door));
+        assert!(is_open(&blocks, door + IVec3::Y));
+
+        assert!(activate(&mut blocks, lever, &mut notes));
+        assert!(!is_open(&blocks, door));
+        assert!(!is_open(&blocks, door + IVec3::Y));
+        assert!(notes.is_empty());
+    }
+
+    #[test]
+    fn power_passes_through_attached_blocks() {
+        let (mut blocks, door, _) = door_and_lever();
+        let mut notes = Vec::new();
+
+        // A lever on the other side of a stone block next to a note block
+        let stone = door + IVec3::new(0, 0, 3);
+        let lever = stone + IVec3::NEG_Z;
+        let note_block = stone + IVec3::Z;
+        blocks.set_block(stone, BlockState::STONE).unwrap();
+        blocks
+            .set_block(note_block, BlockState::NOTE_BLOCK)
+            .unwrap();
+        blocks
+            .set_block(
+                lever,
+                BlockState::LEVER
+                    .set(PropName::Face, PropValue::Wall)
+                    .set(PropName::Facing, PropValue::North),
+            )
+            .unwrap();
+
+        assert!(activate(&mut blocks, lever, &mut notes));
+        assert_eq!(notes.len(), 1);
+        assert!(is_powered(&blocks, note_block));
+    }
+}
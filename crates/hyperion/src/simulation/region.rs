@@ -0,0 +1,518 @@
+//! Protected regions, such as a lobby where only admins may build.
+//!
+//! [`ProtectedRegions`] holds [`Region`]s by their ID. Each region flag allows an action for some
+//! [`RegionGroup`]s, or is left unset. Where regions overlap, the region with the highest
+//! [priority](Region::priority) which sets a flag decides. If several regions with that priority
+//! set it, the action is only allowed if all of them allow it.
+//!
+//! The block handlers check [`RegionFlag::Build`] and [`RegionFlag::Interact`] before they send
+//! [`PlaceBlock`](event::PlaceBlock), [`DestroyBlock`](event::DestroyBlock),
+//! [`ToggleDoor`](event::ToggleDoor) and [`ActivateBlock`](event::ActivateBlock). A denied change
+//! is only acknowledged, which makes the client undo it, and the player is told why. Players who
+//! walk into a region they may not enter are pushed back. Whether players may damage each other
+//! is up to the damage code of the game, which should check [`ProtectedRegions::allows_pvp`].
+//!
+//! Regions only apply to the primary world and are stored in the [`LocalDb`]. They are indexed by
+//! the chunks they cover, so looking up the regions at a position does not check every region.
+
+use std::collections::BTreeMap;
+
+use bevy_app::{App, FixedPostUpdate, Plugin};
+use bevy_ecs::{
+    component::Component,
+    entity::Entity,
+    query::{Changed, With, Without},
+    resource::Resource,
+    system::{Commands, Query, Res},
+};
+use geometry::ray::Ray;
+use glam::{IVec2, IVec3, Vec3};
+use hyperion_utils::{Args, Localizer, Prev};
+use rustc_hash::FxHashMap;
+use tracing::{error, warn};
+use valence_protocol::packets::play::GameMessageS2c;
+use valence_text::IntoText;
+#[cfg(feature = "reflect")]
+use {
+    bevy_ecs::reflect::{ReflectComponent, ReflectResource},
+    bevy_reflect::Reflect,
+};
+
+use crate::{
+    net::{Compose, ConnectionId, SendResultExt},
+    simulation::{
+        PendingTeleportation, Position, blocks::Blocks, event, get_direction_from_rotation,
+        packet_state, world::WorldId,
+    },
+    storage::{GroupSet, LocalDb, Region, RegionFlags, RegionStorage},
+};
+
+/// How far away players can select corners, measured from their eyes
+const SELECT_REACH: f32 = 64.0;
+
+/// The height of the eyes of a standing player above its feet
+const EYE_HEIGHT: f32 = 1.62;
+
+/// Regions covering more chunks than this are checked at every position instead of being added
+/// to each of their chunks in the index
+const MAX_INDEXED_CHUNKS: i64 = 4096;
+
+/// The group of a player when checking region flags, from 0 to 7. Players without this component
+/// are in group 0. Permission plugins keep it in sync with their own groups.
+#[derive(Component, Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+pub struct RegionGroup(pub u8);
+
+/// The flags of a region which allow an action per [`RegionGroup`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "reflect", derive(Reflect))]
+pub enum RegionFlag {
+    /// Placing and breaking blocks
+    Build,
+    /// Using doors, buttons and levers
+    Interact,
+    /// Walking into the region
+    Entry,
+}
+
+impl RegionFlag {
+    /// The groups which a region allows the action for, or [`None`] if it leaves it to other
+    /// regions
+    #[must_use]
+    pub const fn get(self, flags: &RegionFlags) -> Option<GroupSet> {
+        match self {
+            Self::Build => flags.build,
+            Self::Interact => flags.interact,
+            Self::Entry => flags.entry,
+        }
+    }
+
+    pub const fn get_mut(self, flags: &mut RegionFlags) -> &mut Option<GroupSet> {
+        match self {
+            Self::Build => &mut flags.build,
+            Self::Interact => &mut flags.interact,
+            Self::Entry => &mut flags.entry,
+        }
+    }
+
+    /// The key of the message shown to players who are denied the action
+    #[must_use]
+    pub const fn denied_message(self) -> &'static str {
+        match self {
+            Self::Build => "region.build_denied",
+            Self::Interact => "region.interact_denied",
+            Self::Entry => "region.entry_denied",
+        }
+    }
+}
+
+/// The blocks a player selected as the corners of a region, see the
+/// [module documentation](self)
+#[derive(Component, Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+pub struct RegionSelection {
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    // TODO: Reflect this once glam is updated everywhere
+    corners: [Option<IVec3>; 2],
+}
+
+impl RegionSelection {
+    /// Selects `corner`, replacing the older of the last two selected corners
+    pub const fn push(&mut self, corner: IVec3) {
+        self.corners = [self.corners[1], Some(corner)];
+    }
+
+    /// The last two selected corners, if two have been selected
+    #[must_use]
+    pub const fn corners(&self) -> Option<(IVec3, IVec3)> {
+        match self.corners {
+            [Some(a), Some(b)] => Some((a, b)),
+            _ => None,
+        }
+    }
+}
+
+/// The block a player at `position` looks at, which they select as a corner
+#[must_use]
+pub fn targeted_block(blocks: &Blocks, position: Vec3, yaw: f32, pitch: f32) -> Option<IVec3> {
+    let eye = position + Vec3::Y * EYE_HEIGHT;
+    let ray = Ray::new(eye, get_direction_from_rotation(yaw, pitch)) * SELECT_REACH;
+    blocks
+        .first_collision(ray)
+        .map(|collision| collision.location)
+}
+
+/// The chunk column of the block at `position`
+const fn chunk_of(position: IVec3) -> IVec2 {
+    IVec2::new(position.x >> 4, position.z >> 4)
+}
+
+/// The regions which contain blocks of each chunk column
+#[derive(Debug, Default)]
+struct RegionIndex {
+    chunks: FxHashMap<IVec2, Vec<Region>>,
+    /// The regions covering more than [`MAX_INDEXED_CHUNKS`] chunks
+    large: Vec<Region>,
+}
+
+impl RegionIndex {
+    fn new<'a>(regions: impl Iterator<Item = &'a Region>) -> Self {
+        let mut index = Self::default();
+
+        for region in regions {
+            let (min, max) = (chunk_of(region.min), chunk_of(region.max));
+            let chunks = (i64::from(max.x) - i64::from(min.x) + 1)
+                * (i64::from(max.y) - i64::from(min.y) + 1);
+
+            if chunks > MAX_INDEXED_CHUNKS {
+                index.large.push(*region);
+                continue;
+            }
+
+            for x in min.x..=max.x {
+                for z in min.y..=max.y {
+                    index
+                        .chunks
+                        .entry(IVec2::new(x, z))
+                        .or_default()
+                        .push(*region);
+                }
+            }
+        }
+
+        index
+    }
+
+    /// The regions which may contain `position`
+    fn candidates(&self, position: IVec3) -> impl Iterator<Item = &Region> {
+        self.chunks
+            .get(&chunk_of(position))
+            .into_iter()
+            .flatten()
+            .chain(&self.large)
+    }
+}
+
+/// Every protected region by its ID. See the [module documentation](self).
+#[derive(Resource, Debug, Default)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(opaque))]
+pub struct ProtectedRegions {
+    regions: BTreeMap<String, Region>,
+    index: RegionIndex,
+    /// Where changes are persisted. Regions are only kept in memory without it.
+    storage: Option<RegionStorage>,
+}
+
+impl ProtectedRegions {
+    /// Loads the regions stored in `db`. Changes are written back to it.
+    pub fn load(db: &LocalDb) -> anyhow::Result<Self> {
+        let storage = RegionStorage::new(db)?;
+        let regions = storage.load()?;
+
+        Ok(Self {
+            index: RegionIndex::new(regions.values()),
+            regions,
+            storage: Some(storage),
+        })
+    }
+
+    #[must_use]
+    pub fn get(&self, id: &str) -> Option<&Region> {
+        self.regions.get(id)
+    }
+
+    /// The regions in the order of their IDs
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Region)> {
+        self.regions
+            .iter()
+            .map(|(id, region)| (id.as_str(), region))
+    }
+
+    /// Defines the region `id` as `region`, replacing the region defined as `id` before
+    pub fn define(&mut self, id: &str, region: Region) -> anyhow::Result<()> {
+        if let Some(storage) = &self.storage {
+            storage.put(id, &region)?;
+        }
+
+        self.regions.insert(id.to_owned(), region);
+        self.index = RegionIndex::new(self.regions.values());
+        Ok(())
+    }
+
+    /// Removes the region `id` and returns it
+    pub fn remove(&mut self, id: &str) -> anyhow::Result<Option<Region>> {
+        if let Some(storage) = &self.storage {
+            storage.delete(id)?;
+        }
+
+        let removed = self.regions.remove(id);
+        self.index = RegionIndex::new(self.regions.values());
+        Ok(removed)
+    }
+
+    /// The values which the highest priority regions containing `position` set with `get`. Empty
+    /// if no region sets it.
+    fn deciding<T>(
+        &self,
+        world: WorldId,
+        position: IVec3,
+        get: impl Fn(&RegionFlags) -> Option<T>,
+    ) -> impl Iterator<Item = T> {
+        let mut values = Vec::new();
+        let mut priority = i32::MIN;
+
+        if world != WorldId::PRIMARY {
+            return values.into_iter();
+        }
+
+        for region in self.index.candidates(position) {
+            if !region.contains(position) || region.priority < priority {
+                continue;
+            }
+
+            let Some(value) = get(&region.flags) else {
+                continue;
+            };
+
+            if region.priority > priority {
+                values.clear();
+                priority = region.priority;
+            }
+
+            values.push(value);
+        }
+
+        values.into_iter()
+    }
+
+    /// Whether players in `group` may do what `flag` controls at the block `position` in `world`
+    #[must_use]
+    pub fn allows(
+        &self,
+        flag: RegionFlag,
+        group: RegionGroup,
+        world: WorldId,
+        position: IVec3,
+    ) -> bool {
+        self.deciding(world, position, |flags| flag.get(flags))
+            .all(|groups| groups.contains(group.0))
+    }
+
+    /// Whether players may damage each other at the block `position` in `world`. The damage code
+    /// of the game should check this for both the attacker and the victim.
+    #[must_use]
+    pub fn allows_pvp(&self, world: WorldId, position: IVec3) -> bool {
+        self.deciding(world, position, |flags| flags.pvp)
+            .all(|pvp| pvp)
+    }
+}
+
+/// The block an entity at `position` stands in
+#[must_use]
+pub fn block_position(position: Vec3) -> IVec3 {
+    position.floor().as_ivec3()
+}
+
+/// Tells `player` why an action was denied with the translated message `key`, such as
+/// [`RegionFlag::denied_message`] or `region.pvp_denied`
+pub fn send_denied_message(
+    compose: &Compose,
+    localizer: &Localizer<'_, '_>,
+    player: Entity,
+    connection_id: ConnectionId,
+    key: &str,
+) {
+    let pkt = GameMessageS2c {
+        chat: localizer.tr(player, key, &Args::new()).into_cow_text(),
+        overlay: false,
+    };
+
+    compose
+        .unicast(&pkt, connection_id)
+        .unwrap_or_disconnected();
+}
+
+fn push_back_intruders(
+    regions: Res<'_, ProtectedRegions>,
+    query: Query<
+        '_,
+        '_,
+        (
+            Entity,
+            &ConnectionId,
+            &Position,
+            &Prev<Position>,
+            Option<&RegionGroup>,
+            Option<&WorldId>,
+        ),
+        (
+            With<packet_state::Play>,
+            Without<PendingTeleportation>,
+            Changed<Position>,
+        ),
+    >,
+    compose: Res<'_, Compose>,
+    localizer: Localizer<'_, '_>,
+    mut commands: Commands<'_, '_>,
+) {
+    if regions.regions.is_empty() {
+        return;
+    }
+
+    for (player, &connection_id, position, prev, group, world) in &query {
+        let group = group.copied().unwrap_or_default();
+        let world = world.copied().unwrap_or_default();
+        let allows = |position: Vec3| {
+            regions.allows(RegionFlag::Entry, group, world, block_position(position))
+        };
+
+        // Players who are already inside, for example because they were teleported there or the
+        // region was defined around them, may leave it freely
+        if allows(**position) || !allows(***prev) {
+            continue;
+        }
+
+        commands
+            .entity(player)
+            .insert(PendingTeleportation::new(***prev));
+        send_denied_message(
+            &compose,
+            &localizer,
+            player,
+            connection_id,
+            RegionFlag::Entry.denied_message(),
+        );
+    }
+}
+
+/// Adds the [`ProtectedRegions`] and pushes players out of regions they may not enter. This must
+/// be added after [`crate::HyperionCore`] has inserted the [`LocalDb`].
+pub struct RegionPlugin;
+
+impl Plugin for RegionPlugin {
+    fn build(&self, app: &mut App) {
+        let regions = match app.world().get_resource::<LocalDb>() {
+            Some(db) => ProtectedRegions::load(db).unwrap_or_else(|e| {
+                error!("failed to load protected regions, they will not be saved: {e}");
+                ProtectedRegions::default()
+            }),
+            None => {
+                warn!("no LocalDb, protected regions will not be saved");
+                ProtectedRegions::default()
+            }
+        };
+
+        app.insert_resource(regions);
+        app.add_systems(FixedPostUpdate, push_back_intruders);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NORMAL: RegionGroup = RegionGroup(1);
+    const ADMIN: RegionGroup = RegionGroup(3);
+
+    fn lobby() -> Region {
+        let mut lobby = Region::new(IVec3::new(-10, 0, -10), IVec3::new(10, 100, 10));
+        lobby.flags.build = Some(GroupSet::NONE.with(ADMIN.0));
+        lobby.flags.pvp = Some(false);
+        lobby
+    }
+
+    #[test]
+    fn normal_players_are_denied_where_admins_are_allowed() {
+        let mut regions = ProtectedRegions::default();
+        regions.define("lobby", lobby()).unwrap();
+
+        let inside = IVec3::new(0, 64, 0);
+        let outside = IVec3::new(11, 64, 0);
+        let build =
+            |group, world, position| regions.allows(RegionFlag::Build, group, world, position);
+
+        assert!(!build(NORMAL, WorldId::PRIMARY, inside));
+        assert!(build(ADMIN, WorldId::PRIMARY, inside));
+        assert!(build(NORMAL, WorldId::PRIMARY, outside));
+        assert!(build(RegionGroup::default(), WorldId::PRIMARY, outside));
+
+        // Flags which the region leaves unset are allowed
+        assert!(regions.allows(RegionFlag::Interact, NORMAL, WorldId::PRIMARY, inside));
+        assert!(!regions.allows_pvp(WorldId::PRIMARY, inside));
+        assert!(regions.allows_pvp(WorldId::PRIMARY, outside));
+    }
+
+    #[test]
+    fn overlapping_regions_resolve_by_priority() {
+        let mut regions = ProtectedRegions::default();
+        regions.define("lobby", lobby()).unwrap();
+
+        let mut arena = Region::new(IVec3::new(0, 0, 0), IVec3::new(20, 100, 20));
+        arena.priority = 1;
+        arena.flags.build = Some(GroupSet::ALL);
+        regions.define("arena", arena).unwrap();
+
+        let overlap = IVec3::new(5, 64, 5);
+        assert!(regions.allows(RegionFlag::Build, NORMAL, WorldId::PRIMARY, overlap));
+
+        // The arena does not set pvp, so the lobby still decides it
+        assert!(!regions.allows_pvp(WorldId::PRIMARY, overlap));
+
+        // Regions with the same priority only allow what all of them allow
+        arena.priority = 0;
+        regions.define("arena", arena).unwrap();
+        assert!(!regions.allows(RegionFlag::Build, NORMAL, WorldId::PRIMARY, overlap));
+        assert!(regions.allows(RegionFlag::Build, ADMIN, WorldId::PRIMARY, overlap));
+
+        assert_eq!(regions.remove("lobby").unwrap(), Some(lobby()));
+        assert!(regions.allows(RegionFlag::Build, NORMAL, WorldId::PRIMARY, overlap));
+        assert!(regions.allows_pvp(WorldId::PRIMARY, overlap));
+    }
+
+    #[test]
+    fn large_and_small_regions_are_found_in_every_chunk() {
+        let mut regions = ProtectedRegions::default();
+        regions.define("lobby", lobby()).unwrap();
+
+        let mut world = Region::new(IVec3::splat(-100_000), IVec3::splat(100_000));
+        world.priority = -1;
+        world.flags.build = Some(GroupSet::NONE);
+        regions.define("world", world).unwrap();
+
+        assert!(regions.index.large.contains(&world));
+        assert!(!regions.index.large.contains(&lobby()));
+
+        // The lobby spans chunks -1 and 0 on both axes
+        for position in [IVec3::new(-10, 64, -10), IVec3::new(10, 64, 10)] {
+            assert!(regions.allows(RegionFlag::Build, ADMIN, WorldId::PRIMARY, position));
+        }
+        assert!(!regions.allows(
+            RegionFlag::Build,
+            ADMIN,
+            WorldId::PRIMARY,
+            IVec3::new(20, 64, 0)
+        ));
+
+        regions.remove("world").unwrap();
+        assert!(regions.index.large.is_empty());
+        assert!(regions.allows(
+            RegionFlag::Build,
+            NORMAL,
+            WorldId::PRIMARY,
+            IVec3::new(20, 64, 0)
+        ));
+    }
+
+    #[test]
+    fn selections_keep_the_last_two_corners() {
+        let mut selection = RegionSelection::default();
+        assert_eq!(selection.corners(), None);
+
+        selection.push(IVec3::X);
+        assert_eq!(selection.corners(), None);
+
+        selection.push(IVec3::Y);
+        selection.push(IVec3::Z);
+        assert_eq!(selection.corners(), Some((IVec3::Y, IVec3::Z)));
+    }
+}
@@ -0,0 +1,664 @@
+//! The registry codec sent to players in the join packet.
+//!
+//! By default this is the vanilla 1.20.1 codec from `data/registries.nbt`. Game code may modify the
+//! [`RegistryCodec`] resource before players join to add or replace dimension types and biomes.
+//! Players that join after a modification receive the new codec. Players that have already joined
+//! keep the codec they received until they rejoin.
+
+use std::borrow::Cow;
+
+use bevy_ecs::resource::Resource;
+#[cfg(feature = "reflect")]
+use bevy_reflect::Reflect;
+use thiserror::Error;
+use valence_nbt::{Compound, List, Value, compound};
+use valence_protocol::Ident;
+
+use crate::simulation::util::registry_codec_raw;
+
+const DIMENSION_TYPE_KEY: &str = "minecraft:dimension_type";
+const BIOME_KEY: &str = "minecraft:worldgen/biome";
+
+/// The lowest `min_y` accepted by the vanilla client.
+pub const DIMENSION_MIN_Y: i32 = -2032;
+
+/// The highest block y coordinate accepted by the vanilla client.
+pub const DIMENSION_MAX_Y: i32 = 2031;
+
+/// The maximum height of a dimension accepted by the vanilla client.
+pub const DIMENSION_MAX_HEIGHT: i32 = DIMENSION_MAX_Y - DIMENSION_MIN_Y + 1;
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum RegistryCodecError {
+    #[error("invalid registry entry name: {0}")]
+    InvalidName(String),
+    #[error("dimension height {0} must be a positive multiple of 16 no larger than 4064")]
+    InvalidHeight(i32),
+    #[error("dimension min_y {0} must be a multiple of 16 in the range -2032..=2016")]
+    InvalidMinY(i32),
+    #[error("dimension spans y {min_y}..{max_y}, which exceeds the maximum y of 2031")]
+    DimensionTooTall { min_y: i32, max_y: i32 },
+    #[error("dimension logical height {logical_height} is larger than its height {height}")]
+    InvalidLogicalHeight { logical_height: i32, height: i32 },
+    #[error("dimension ambient light {0} must be finite")]
+    InvalidAmbientLight(f32),
+    #[error("dimension coordinate scale {0} must be in the range 0.00001..=30000000")]
+    InvalidCoordinateScale(f64),
+    #[error("biome {field} color {value:#x} does not fit in 24 bits")]
+    InvalidColor { field: &'static str, value: u32 },
+    #[error("biome {field} {value} must be finite")]
+    InvalidBiomeValue { field: &'static str, value: f32 },
+    #[error("registry {0} is missing or malformed")]
+    MalformedRegistry(&'static str),
+    #[error("dimension type {0} is not registered")]
+    UnknownDimensionType(String),
+}
+
+/// A dimension type entry in the `minecraft:dimension_type` registry.
+///
+/// Defaults match the vanilla overworld.
+#[derive(Clone, Debug, PartialEq)]
+#[must_use]
+pub struct DimensionType {
+    height: i32,
+    min_y: i32,
+    logical_height: i32,
+    ambient_light: f32,
+    has_skylight: bool,
+    has_ceiling: bool,
+    coordinate_scale: f64,
+    ultrawarm: bool,
+    natural: bool,
+    fixed_time: Option<i64>,
+    effects: String,
+}
+
+impl Default for DimensionType {
+    fn default() -> Self {
+        Self {
+            height: 384,
+            min_y: -64,
+            logical_height: 384,
+            ambient_light: 0.0,
+            has_skylight: true,
+            has_ceiling: false,
+            coordinate_scale: 1.0,
+            ultrawarm: false,
+            natural: true,
+            fixed_time: None,
+            effects: "minecraft:overworld".to_string(),
+        }
+    }
+}
+
+impl DimensionType {
+    /// Sets the total height of the dimension. This also sets the logical height.
+    pub const fn height(mut self, height: i32) -> Self {
+        self.height = height;
+        self.logical_height = height;
+        self
+    }
+
+    pub const fn min_y(mut self, min_y: i32) -> Self {
+        self.min_y = min_y;
+        self
+    }
+
+    /// Sets the height that portals and chorus fruit are limited to.
+    pub const fn logical_height(mut self, logical_height: i32) -> Self {
+        self.logical_height = logical_height;
+        self
+    }
+
+    /// Sets the base light level, from `0.0` (vanilla overworld) to `1.0` (fully lit).
+    pub const fn ambient_light(mut self, ambient_light: f32) -> Self {
+        self.ambient_light = ambient_light;
+        self
+    }
+
+    pub const fn has_skylight(mut self, has_skylight: bool) -> Self {
+        self.has_skylight = has_skylight;
+        self
+    }
+
+    pub const fn has_ceiling(mut self, has_ceiling: bool) -> Self {
+        self.has_ceiling = has_ceiling;
+        self
+    }
+
+    pub const fn coordinate_scale(mut self, coordinate_scale: f64) -> Self {
+        self.coordinate_scale = coordinate_scale;
+        self
+    }
+
+    pub const fn ultrawarm(mut self, ultrawarm: bool) -> Self {
+        self.ultrawarm = ultrawarm;
+        self
+    }
+
+    pub const fn natural(mut self, natural: bool) -> Self {
+        self.natural = natural;
+        self
+    }
+
+    /// Freezes the time of day as seen by the client.
+    pub const fn fixed_time(mut self, fixed_time: i64) -> Self {
+        self.fixed_time = Some(fixed_time);
+        self
+    }
+
+    /// Sets the sky renderer used by the client, such as `minecraft:the_nether`.
+    pub fn effects(mut self, effects: impl Into<String>) -> Self {
+        self.effects = effects.into();
+        self
+    }
+
+    /// Checks that the client will accept this dimension type.
+    pub fn validate(&self) -> Result<(), RegistryCodecError> {
+        if self.height < 16 || self.height > DIMENSION_MAX_HEIGHT || self.height % 16 != 0 {
+            return Err(RegistryCodecError::InvalidHeight(self.height));
+        }
+
+        if self.min_y < DIMENSION_MIN_Y || self.min_y > DIMENSION_MAX_Y - 15 || self.min_y % 16 != 0
+        {
+            return Err(RegistryCodecError::InvalidMinY(self.min_y));
+        }
+
+        let max_y = self.min_y + self.height;
+        if max_y > DIMENSION_MAX_Y + 1 {
+            return Err(RegistryCodecError::DimensionTooTall {
+                min_y: self.min_y,
+                max_y,
+            });
+        }
+
+        if self.logical_height < 0 || self.logical_height > self.height {
+            return Err(RegistryCodecError::InvalidLogicalHeight {
+                logical_height: self.logical_height,
+                height: self.height,
+            });
+        }
+
+        if !self.ambient_light.is_finite() {
+            return Err(RegistryCodecError::InvalidAmbientLight(self.ambient_light));
+        }
+
+        if !(0.000_01..=30_000_000.0).contains(&self.coordinate_scale) {
+            return Err(RegistryCodecError::InvalidCoordinateScale(
+                self.coordinate_scale,
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn to_nbt(&self) -> Compound {
+        let infiniburn = if self.ultrawarm {
+            "#minecraft:infiniburn_nether"
+        } else {
+            "#minecraft:infiniburn_overworld"
+        };
+
+        let mut element = compound! {
+            "piglin_safe" => false,
+            "has_raids" => self.natural,
+            "monster_spawn_light_level" => 0_i32,
+            "monster_spawn_block_light_limit" => 0_i32,
+            "natural" => self.natural,
+            "ambient_light" => self.ambient_light,
+            "infiniburn" => infiniburn.to_string(),
+            "respawn_anchor_works" => !self.natural,
+            "has_skylight" => self.has_skylight,
+            "bed_works" => self.natural,
+            "effects" => self.effects.clone(),
+            "min_y" => self.min_y,
+            "height" => self.height,
+            "logical_height" => self.logical_height,
+            "coordinate_scale" => self.coordinate_scale,
+            "ultrawarm" => self.ultrawarm,
+            "has_ceiling" => self.has_ceiling,
+        };
+
+        if let Some(fixed_time) = self.fixed_time {
+            element.insert("fixed_time", fixed_time);
+        }
+
+        element
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum Precipitation {
+    #[default]
+    Rain,
+    /// Rain is rendered as snow when the biome temperature is low enough
+    Snow,
+    None,
+}
+
+/// A biome entry in the `minecraft:worldgen/biome` registry.
+///
+/// Defaults match the vanilla plains biome.
+#[derive(Clone, Debug, PartialEq)]
+#[must_use]
+pub struct BiomeEntry {
+    precipitation: Precipitation,
+    temperature: f32,
+    downfall: f32,
+    fog_color: u32,
+    sky_color: u32,
+    water_color: u32,
+    water_fog_color: u32,
+    grass_color: Option<u32>,
+    foliage_color: Option<u32>,
+}
+
+impl Default for BiomeEntry {
+    fn default() -> Self {
+        Self {
+            precipitation: Precipitation::Rain,
+            temperature: 0.8,
+            downfall: 0.4,
+            fog_color: 0x00c0_d8ff,
+            sky_color: 0x0078_a7ff,
+            water_color: 0x003f_76e4,
+            water_fog_color: 0x0005_0533,
+            grass_color: None,
+            foliage_color: None,
+        }
+    }
+}
+
+impl BiomeEntry {
+    pub const fn precipitation(mut self, precipitation: Precipitation) -> Self {
+        self.precipitation = precipitation;
+        self
+    }
+
+    pub const fn temperature(mut self, temperature: f32) -> Self {
+        self.temperature = temperature;
+        self
+    }
+
+    pub const fn downfall(mut self, downfall: f32) -> Self {
+        self.downfall = downfall;
+        self
+    }
+
+    /// All colors are `0xRRGGBB`.
+    pub const fn fog_color(mut self, color: u32) -> Self {
+        self.fog_color = color;
+        self
+    }
+
+    pub const fn sky_color(mut self, color: u32) -> Self {
+        self.sky_color = color;
+        self
+    }
+
+    pub const fn water_color(mut self, color: u32) -> Self {
+        self.water_color = color;
+        self
+    }
+
+    pub const fn water_fog_color(mut self, color: u32) -> Self {
+        self.water_fog_color = color;
+        self
+    }
+
+    pub const fn grass_color(mut self, color: u32) -> Self {
+        self.grass_color = Some(color);
+        self
+    }
+
+    pub const fn foliage_color(mut self, color: u32) -> Self {
+        self.foliage_color = Some(color);
+        self
+    }
+
+    /// Checks that the client will accept this biome.
+    pub fn validate(&self) -> Result<(), RegistryCodecError> {
+        let colors = [
+            ("fog_color", Some(self.fog_color)),
+            ("sky_color", Some(self.sky_color)),
+            ("water_color", Some(self.water_color)),
+            ("water_fog_color", Some(self.water_fog_color)),
+            ("grass_color", self.grass_color),
+            ("foliage_color", self.foliage_color),
+        ];
+
+        for (field, color) in colors {
+            if let Some(value) = color
+                && value > 0x00ff_ffff
+            {
+                return Err(RegistryCodecError::InvalidColor { field, value });
+            }
+        }
+
+        for (field, value) in [
+            ("temperature", self.temperature),
+            ("downfall", self.downfall),
+        ] {
+            if !value.is_finite() {
+                return Err(RegistryCodecError::InvalidBiomeValue { field, value });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn to_nbt(&self) -> Compound {
+        #[expect(
+            clippy::cast_possible_wrap,
+            reason = "colors are validated to fit in 24 bits"
+        )]
+        let color = |color: u32| color as i32;
+
+        let mut effects = compound! {
+            "fog_color" => color(self.fog_color),
+            "sky_color" => color(self.sky_color),
+            "water_color" => color(self.water_color),
+            "water_fog_color" => color(self.water_fog_color),
+        };
+
+        if let Some(grass_color) = self.grass_color {
+            effects.insert("grass_color", color(grass_color));
+        }
+
+        if let Some(foliage_color) = self.foliage_color {
+            effects.insert("foliage_color", color(foliage_color));
+        }
+
+        // Snow is determined by the client from the temperature
+        let temperature = match self.precipitation {
+            Precipitation::Snow => self.temperature.min(0.0),
+            Precipitation::Rain | Precipitation::None => self.temperature,
+        };
+
+        compound! {
+            "has_precipitation" => self.precipitation != Precipitation::None,
+            "temperature" => temperature,
+            "downfall" => self.downfall,
+            "effects" => effects,
+        }
+    }
+}
+
+/// The registry codec sent in [`valence_protocol::packets::play::GameJoinS2c`].
+#[derive(Resource, Debug, Clone)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(opaque))]
+pub struct RegistryCodec {
+    compound: Compound,
+    join_dimension_type: Ident<Cow<'static, str>>,
+}
+
+impl Default for RegistryCodec {
+    fn default() -> Self {
+        Self {
+            compound: registry_codec_raw().clone(),
+            join_dimension_type: Ident::new(Cow::Borrowed("minecraft:overworld"))
+                .expect("minecraft:overworld is a valid identifier"),
+        }
+    }
+}
+
+impl RegistryCodec {
+    /// The raw codec as it is sent to the client
+    #[must_use]
+    pub const fn compound(&self) -> &Compound {
+        &self.compound
+    }
+
+    /// The dimension type that joining players are placed in
+    #[must_use]
+    pub const fn join_dimension_type(&self) -> &Ident<Cow<'static, str>> {
+        &self.join_dimension_type
+    }
+
+    /// Sets the dimension type that joining players are placed in. The dimension type must
+    /// already be registered.
+    ///
+    /// Note that the chunks sent by Hyperion are [`crate::CHUNK_HEIGHT_SPAN`] blocks tall, so the
+    /// dimension type should have a matching height.
+    pub fn set_join_dimension_type(&mut self, name: &str) -> Result<(), RegistryCodecError> {
+        let name = parse_name(name)?;
+
+        if !self
+            .dimension_type_names()
+            .any(|other| other == name.as_str())
+        {
+            return Err(RegistryCodecError::UnknownDimensionType(name.to_string()));
+        }
+
+        self.join_dimension_type = name;
+        Ok(())
+    }
+
+    /// Adds a dimension type, replacing any existing one with the same name.
+    pub fn insert_dimension_type(
+        &mut self,
+        name: &str,
+        dimension_type: &DimensionType,
+    ) -> Result<(), RegistryCodecError> {
+        let name = parse_name(name)?;
+        dimension_type.validate()?;
+        self.insert(DIMENSION_TYPE_KEY, name.as_str(), dimension_type.to_nbt())
+    }
+
+    /// Adds a biome, replacing any existing one with the same name.
+    pub fn insert_biome(
+        &mut self,
+        name: &str,
+        biome: &BiomeEntry,
+    ) -> Result<(), RegistryCodecError> {
+        let name = parse_name(name)?;
+        biome.validate()?;
+        self.insert(BIOME_KEY, name.as_str(), biome.to_nbt())
+    }
+
+    /// The names of all registered dimension types
+    pub fn dimension_type_names(&self) -> impl Iterator<Item = &str> {
+        self.entries_ref(DIMENSION_TYPE_KEY)
+            .into_iter()
+            .flatten()
+            .filter_map(entry_name)
+    }
+
+    /// The names of all registered biomes
+    pub fn biome_names(&self) -> impl Iterator<Item = &str> {
+        self.entries_ref(BIOME_KEY)
+            .into_iter()
+            .flatten()
+            .filter_map(entry_name)
+    }
+
+    fn insert(
+        &mut self,
+        registry: &'static str,
+        name: &str,
+        element: Compound,
+    ) -> Result<(), RegistryCodecError> {
+        let entries = self.entries(registry)?;
+
+        if let Some(entry) = entries
+            .iter_mut()
+            .find(|entry| entry_name(entry) == Some(name))
+        {
+            entry.insert("element", element);
+            return Ok(());
+        }
+
+        let id = entries
+            .iter()
+            .filter_map(|entry| match entry.get("id") {
+                Some(Value::Int(id)) => Some(*id),
+                _ => None,
+            })
+            .max()
+            .map_or(0, |id| id + 1);
+
+        entries.push(compound! {
+            "name" => name.to_string(),
+            "id" => id,
+            "element" => element,
+        });
+
+        Ok(())
+    }
+
+    fn entries(
+        &mut self,
+        registry: &'static str,
+    ) -> Result<&mut Vec<Compound>, RegistryCodecError> {
+        let Some(Value::Compound(registry_compound)) = self.compound.get_mut(registry) else {
+            return Err(RegistryCodecError::MalformedRegistry(registry));
+        };
+
+        let value = registry_compound
+            .entry("value")
+            .or_insert_with(|| Value::List(List::Compound(Vec::new())));
+
+        // An empty list is not necessarily tagged as a compound list
+        if matches!(value, Value::List(list) if list.is_empty()) {
+            *value = Value::List(List::Compound(Vec::new()));
+        }
+
+        match value {
+            Value::List(List::Compound(entries)) => Ok(entries),
+            _ => Err(RegistryCodecError::MalformedRegistry(registry)),
+        }
+    }
+
+    fn entries_ref(&self, registry: &str) -> Option<&Vec<Compound>> {
+        let Some(Value::Compound(registry)) = self.compound.get(registry) else {
+            return None;
+        };
+
+        match registry.get("value") {
+            Some(Value::List(List::Compound(entries))) => Some(entries),
+            _ => None,
+        }
+    }
+}
+
+fn parse_name(name: &str) -> Result<Ident<Cow<'static, str>>, RegistryCodecError> {
+    Ident::new(Cow::Owned(name.to_string()))
+        .map_err(|_| RegistryCodecError::InvalidName(name.to_string()))
+}
+
+fn entry_name(entry: &Compound) -> Option<&str> {
+    match entry.get("name") {
+        Some(Value::String(name)) => Some(name.as_str()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vanilla_dimension_types() {
+        let codec = RegistryCodec::default();
+        let names: Vec<_> = codec.dimension_type_names().collect();
+        assert!(names.contains(&"minecraft:overworld"));
+        assert!(names.contains(&"minecraft:the_nether"));
+    }
+
+    #[test]
+    fn test_insert_dimension_type() {
+        let mut codec = RegistryCodec::default();
+        let arena = DimensionType::default()
+            .has_skylight(false)
+            .ambient_light(0.5)
+            .effects("minecraft:the_nether");
+
+        codec
+            .insert_dimension_type("hyperion:arena", &arena)
+            .unwrap();
+        codec.set_join_dimension_type("hyperion:arena").unwrap();
+
+        assert!(
+            codec
+                .dimension_type_names()
+                .any(|name| name == "hyperion:arena")
+        );
+        assert_eq!(codec.join_dimension_type().as_str(), "hyperion:arena");
+    }
+
+    #[test]
+    fn test_replace_dimension_type_keeps_count() {
+        let mut codec = RegistryCodec::default();
+        let before = codec.dimension_type_names().count();
+
+        codec
+            .insert_dimension_type(
+                "minecraft:overworld",
+                &DimensionType::default().fixed_time(6000),
+            )
+            .unwrap();
+
+        assert_eq!(codec.dimension_type_names().count(), before);
+    }
+
+    #[test]
+    fn test_invalid_dimension_type() {
+        let mut codec = RegistryCodec::default();
+
+        assert_eq!(
+            codec.insert_dimension_type("hyperion:bad", &DimensionType::default().height(100)),
+            Err(RegistryCodecError::InvalidHeight(100))
+        );
+        assert_eq!(
+            codec.insert_dimension_type("hyperion:bad", &DimensionType::default().min_y(-4096)),
+            Err(RegistryCodecError::InvalidMinY(-4096))
+        );
+        assert!(
+            codec
+                .insert_dimension_type(
+                    "hyperion:bad",
+                    &DimensionType::default().min_y(2000).height(256)
+                )
+                .is_err()
+        );
+        assert!(
+            codec
+                .insert_dimension_type("Not A Name", &DimensionType::default())
+                .is_err()
+        );
+        assert!(
+            !codec
+                .dimension_type_names()
+                .any(|name| name == "hyperion:bad")
+        );
+    }
+
+    #[test]
+    fn test_unknown_join_dimension_type() {
+        let mut codec = RegistryCodec::default();
+        assert!(codec.set_join_dimension_type("hyperion:missing").is_err());
+        assert_eq!(codec.join_dimension_type().as_str(), "minecraft:overworld");
+    }
+
+    #[test]
+    fn test_insert_biome() {
+        let mut codec = RegistryCodec::default();
+        let biome = BiomeEntry::default()
+            .fog_color(0x00ff_0000)
+            .precipitation(Precipitation::None);
+
+        codec.insert_biome("hyperion:red_fog", &biome).unwrap();
+        assert!(codec.biome_names().any(|name| name == "hyperion:red_fog"));
+
+        assert_eq!(
+            codec.insert_biome(
+                "hyperion:bad",
+                &BiomeEntry::default().sky_color(0x0100_0000)
+            ),
+            Err(RegistryCodecError::InvalidColor {
+                field: "sky_color",
+                value: 0x0100_0000
+            })
+        );
+    }
+}
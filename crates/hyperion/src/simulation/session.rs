@@ -0,0 +1,178 @@
+//! Sessions of players, which tie the UUID of a player to the entity currently playing as it.
+//!
+//! Clients which crash and rejoin automatically often connect again before the proxy notices
+//! that their old connection is gone, so a new session may start while the entity of the old one
+//! still exists. [`start_session`] ends the old session synchronously before the new one starts,
+//! which keeps everything observing players in order:
+//!
+//! - the old entity leaves the play state and is removed from the lookup maps before the new one
+//!   joins,
+//! - the proxy removes the channel of the old entity before the channel of the new one is added,
+//!   so viewers never see both of them,
+//! - the state of the old session is saved before the new session restores it.
+//!
+//! Every session has a [`SessionGeneration`], which is larger for later sessions. Saves of a
+//! session which is no longer the current one are skipped, so the newest state always wins.
+
+use std::collections::HashMap;
+
+use bevy_ecs::{
+    component::Component,
+    entity::Entity,
+    lifecycle::Remove,
+    observer::On,
+    query::With,
+    resource::Resource,
+    system::{Query, ResMut},
+    world::World,
+};
+use hyperion_utils::{Args, Locale, Translations, localization::DEFAULT_LANGUAGE};
+use tracing::info;
+use valence_protocol::packets::play;
+use valence_text::IntoText;
+#[cfg(feature = "reflect")]
+use {
+    bevy_ecs::reflect::{ReflectComponent, ReflectResource},
+    bevy_reflect::Reflect,
+};
+
+use crate::{
+    net::{Compose, ConnectionId, SendResultExt},
+    simulation::{StreamLookup, Uuid, uuid_hash::UuidBuildHasher},
+};
+
+/// Orders the sessions of players. A session started later has a larger generation.
+#[derive(Component, Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+pub struct SessionGeneration(pub u64);
+
+/// The current session of every player, keyed by UUID. See the [module documentation](self).
+#[derive(Resource, Default, Debug)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Resource))]
+pub struct PlayerSessions {
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    sessions: HashMap<uuid::Uuid, (Entity, SessionGeneration), UuidBuildHasher>,
+    last_generation: u64,
+}
+
+impl PlayerSessions {
+    /// The entity and generation of the current session of `uuid`
+    #[must_use]
+    pub fn get(&self, uuid: uuid::Uuid) -> Option<(Entity, SessionGeneration)> {
+        self.sessions.get(&uuid).copied()
+    }
+
+    /// Whether `generation` is the current session of `uuid`, or newer than it. This is also the
+    /// case if `uuid` has no session, such as for players which joined before sessions were
+    /// tracked.
+    #[must_use]
+    pub fn is_current(&self, uuid: uuid::Uuid, generation: SessionGeneration) -> bool {
+        self.get(uuid)
+            .is_none_or(|(_, current)| generation >= current)
+    }
+
+    fn begin(&mut self, uuid: uuid::Uuid, player: Entity) -> SessionGeneration {
+        self.last_generation += 1;
+        let generation = SessionGeneration(self.last_generation);
+        self.sessions.insert(uuid, (player, generation));
+        generation
+    }
+
+    fn end(&mut self, uuid: uuid::Uuid, player: Entity) {
+        if self.get(uuid).is_some_and(|(current, _)| current == player) {
+            self.sessions.remove(&uuid);
+        }
+    }
+}
+
+/// Starts a new session of `uuid` for `player`, ending the previous session of `uuid` first if
+/// its entity still exists
+pub fn start_session(world: &mut World, player: Entity, uuid: uuid::Uuid) -> SessionGeneration {
+    let previous = world.get_resource_or_init::<PlayerSessions>().get(uuid);
+
+    if let Some((previous, generation)) = previous
+        && previous != player
+    {
+        info!(
+            "ending session {} of {uuid}, which joined again",
+            generation.0
+        );
+        end_session(world, previous);
+    }
+
+    let generation = world.resource_mut::<PlayerSessions>().begin(uuid, player);
+    world.entity_mut(player).insert(generation);
+    generation
+}
+
+/// Disconnects `player` and despawns it immediately instead of waiting for the proxy to report
+/// the disconnect
+fn end_session(world: &mut World, player: Entity) {
+    let Ok(entity) = world.get_entity(player) else {
+        return;
+    };
+
+    if let Some(&connection_id) = entity.get::<ConnectionId>() {
+        let locale = entity
+            .get::<Locale>()
+            .map_or(DEFAULT_LANGUAGE, Locale::as_str);
+        let reason =
+            world
+                .resource::<Translations>()
+                .tr(locale, "kick.duplicate_login", &Args::new());
+
+        let compose = world.resource::<Compose>();
+        compose
+            .unicast(
+                &play::DisconnectS2c {
+                    reason: reason.into_cow_text(),
+                },
+                connection_id,
+            )
+            .unwrap_or_disconnected();
+        compose.io_buf().shutdown(connection_id);
+
+        // The disconnect reported by the proxy later on refers to an entity which is gone
+        if let Some(mut streams) = world.get_resource_mut::<StreamLookup>() {
+            streams.remove(&connection_id.inner());
+        }
+    }
+
+    world.despawn(player);
+}
+
+pub(crate) fn remove_session(
+    removed: On<'_, '_, Remove, SessionGeneration>,
+    query: Query<'_, '_, &Uuid, With<SessionGeneration>>,
+    mut sessions: ResMut<'_, PlayerSessions>,
+) {
+    if let Ok(uuid) = query.get(removed.entity) {
+        sessions.end(uuid.0, removed.entity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn later_sessions_are_newer() {
+        let mut world = World::new();
+        world.init_resource::<Translations>();
+        let uuid = uuid::Uuid::from_u128(7);
+        let first = world.spawn(Uuid(uuid)).id();
+        let second = world.spawn(Uuid(uuid)).id();
+
+        let first_generation = start_session(&mut world, first, uuid);
+        let second_generation = start_session(&mut world, second, uuid);
+        assert!(second_generation > first_generation);
+
+        let sessions = world.resource::<PlayerSessions>();
+        assert_eq!(sessions.get(uuid), Some((second, second_generation)));
+        assert!(!sessions.is_current(uuid, first_generation));
+        assert!(sessions.is_current(uuid, second_generation));
+
+        // The first session was ended by the second one
+        assert!(world.get_entity(first).is_err());
+    }
+}
@@ -0,0 +1,313 @@
+//! Editing the text of signs.
+//!
+//! After a player places a sign and the game accepts the placement, the sign editor is opened for
+//! the player with [`event::OpenSignEditor`]. Game code can send the same message to let players
+//! edit existing signs. The text the player sends back is sanitized with [`sanitize_sign_line`],
+//! stored in the block entity of the sign, and sent to nearby players. Game code can react to it
+//! with [`event::SignChanged`], such as to create shop signs.
+
+use std::borrow::Cow;
+
+use bevy_app::{App, FixedPostUpdate, FixedUpdate, Plugin};
+use bevy_ecs::{
+    component::Component,
+    message::{MessageReader, MessageWriter},
+    schedule::IntoScheduleConfigs,
+    system::{Commands, Query, Res},
+};
+use glam::IVec3;
+use tracing::{debug, error, warn};
+use valence_generated::block::{BlockEntityKind, BlockState};
+use valence_protocol::{
+    BlockPos,
+    packets::play::{BlockEntityUpdateS2c, SignEditorOpenS2c},
+};
+#[cfg(feature = "reflect")]
+use {bevy_ecs::reflect::ReflectComponent, bevy_reflect::Reflect};
+
+use crate::{
+    Tick, ingress,
+    net::{Compose, ConnectionId, SendResultExt},
+    simulation::{
+        blocks::block_entity::{BlockEntity, BlockEntityData},
+        event,
+        packet::play,
+        world::{WorldBlocks, WorldBlocksMut, WorldId},
+    },
+};
+
+/// The maximum number of characters of a line of a sign. Longer lines are cut off.
+///
+/// The client limits lines by their width instead, which allows at most about 45 characters.
+pub const MAX_SIGN_LINE_CHARS: usize = 64;
+
+/// How many ticks a player has to finish editing a sign after the editor was opened
+pub const SIGN_EDIT_TIMEOUT_TICKS: i64 = 2 * 60 * 20;
+
+/// The sign a player is allowed to edit. This is inserted by [`event::OpenSignEditor`] and removed
+/// once the player sends the text of the sign.
+#[derive(Component, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+pub struct EditingSign {
+    pub position: IVec3,
+    /// Whether the front or the back of the sign is edited
+    pub front: bool,
+    /// The tick after which the text of the sign is no longer accepted
+    pub expires: i64,
+}
+
+/// Removes control characters and formatting codes from a line the client sent and cuts it off
+/// after [`MAX_SIGN_LINE_CHARS`] characters.
+///
+/// The line is always treated as plain text. Clients which send JSON text components, such as
+/// ones with click events, get the JSON displayed as text instead.
+#[must_use]
+pub fn sanitize_sign_line(line: &str) -> String {
+    let line: String = line
+        .chars()
+        .filter(|&c| !c.is_control() && c != '§')
+        .take(MAX_SIGN_LINE_CHARS)
+        .collect();
+
+    line.trim_end().to_owned()
+}
+
+/// Encodes a plain text line as the JSON text component stored in a sign
+fn json_text(line: &str) -> String {
+    serde_json::Value::String(line.to_owned()).to_string()
+}
+
+/// The block entity of an empty sign of the given kind
+fn empty_sign(kind: BlockEntityKind) -> BlockEntity {
+    let id = match kind {
+        BlockEntityKind::HangingSign => "minecraft:hanging_sign",
+        _ => "minecraft:sign",
+    };
+
+    BlockEntity {
+        id: id.to_owned(),
+        data: BlockEntityData::Sign(Box::default()),
+    }
+}
+
+/// Returns the kind of block entity of `block` if it is a sign
+fn sign_kind(block: BlockState) -> Option<BlockEntityKind> {
+    block
+        .block_entity_kind()
+        .filter(|kind| matches!(kind, BlockEntityKind::Sign | BlockEntityKind::HangingSign))
+}
+
+/// Opens the sign editor for players whose sign placement was accepted by the game
+fn open_editor_for_placed_signs(
+    mut placed: MessageReader<'_, '_, event::PlaceBlock>,
+    blocks: WorldBlocks<'_>,
+    mut open: MessageWriter<'_, event::OpenSignEditor>,
+) {
+    for event in placed.read() {
+        if sign_kind(event.block).is_none() {
+            continue;
+        }
+
+        // The game may have rejected the placement
+        let placed_block = blocks
+            .get(Some(&event.world))
+            .and_then(|blocks| blocks.get_block(event.position));
+
+        if placed_block != Some(event.block) {
+            continue;
+        }
+
+        open.write(event::OpenSignEditor {
+            player: event.from,
+            position: event.position,
+            front: true,
+        });
+    }
+}
+
+fn open_sign_editors(
+    mut open: MessageReader<'_, '_, event::OpenSignEditor>,
+    query: Query<'_, '_, &ConnectionId>,
+    compose: Res<'_, Compose>,
+    tick: Res<'_, Tick>,
+    mut commands: Commands<'_, '_>,
+) {
+    for event in open.read() {
+        let Ok(&connection_id) = query.get(event.player) else {
+            continue;
+        };
+
+        let position = event.position;
+        let pkt = SignEditorOpenS2c {
+            location: BlockPos::new(position.x, position.y, position.z),
+            is_front_text: event.front,
+        };
+
+        compose
+            .unicast(&pkt, connection_id)
+            .unwrap_or_disconnected();
+
+        commands.entity(event.player).insert(EditingSign {
+            position,
+            front: event.front,
+            expires: tick.0 + SIGN_EDIT_TIMEOUT_TICKS,
+        });
+    }
+}
+
+fn update_signs(
+    mut packets: MessageReader<'_, '_, play::UpdateSign>,
+    query: Query<'_, '_, (&EditingSign, Option<&WorldId>)>,
+    mut blocks: WorldBlocksMut<'_>,
+    compose: Res<'_, Compose>,
+    tick: Res<'_, Tick>,
+    mut changed: MessageWriter<'_, event::SignChanged>,
+    mut commands: Commands<'_, '_>,
+) {
+    for packet in packets.read() {
+        let player = packet.sender();
+        let position = IVec3::new(packet.position.x, packet.position.y, packet.position.z);
+
+        let Ok((editing, world)) = query.get(player) else {
+            debug!("{player} sent sign text without editing a sign");
+            continue;
+        };
+
+        if editing.position != position
+            || editing.front != packet.is_front_text
+            || editing.expires < tick.0
+        {
+            debug!("{player} sent sign text for a sign it may not edit");
+            continue;
+        }
+
+        commands.entity(player).remove::<EditingSign>();
+
+        let Some(blocks) = blocks.get_mut(world) else {
+            continue;
+        };
+
+        let Some(kind) = blocks.get_block(position).and_then(sign_kind) else {
+            // The sign has been removed while the player was editing it
+            continue;
+        };
+
+        let lines = packet
+            .lines
+            .each_ref()
+            .map(|line| sanitize_sign_line(line.0));
+
+        let block_entity = match blocks.block_entity_or_insert_with(position, || empty_sign(kind)) {
+            Ok(block_entity) => block_entity,
+            Err(e) => {
+                error!("failed to store sign text: {e:?}");
+                continue;
+            }
+        };
+
+        if !matches!(block_entity.data, BlockEntityData::Sign(_)) {
+            warn!("replacing {} block entity of sign", block_entity.id);
+            *block_entity = empty_sign(kind);
+        }
+
+        let BlockEntityData::Sign(sign) = &mut block_entity.data else {
+            continue;
+        };
+
+        if sign.waxed {
+            continue;
+        }
+
+        let text = if editing.front {
+            &mut sign.front
+        } else {
+            &mut sign.back
+        };
+        text.messages = lines.each_ref().map(|line| json_text(line));
+
+        let pkt = BlockEntityUpdateS2c {
+            position: packet.position,
+            kind,
+            data: Cow::Owned(sign.to_nbt()),
+        };
+
+        let chunk = (position.xz() >> 4).as_i16vec2();
+        compose.broadcast_local(&pkt, chunk).send().unwrap();
+
+        changed.write(event::SignChanged {
+            player,
+            position,
+            lines,
+        });
+    }
+}
+
+pub struct SignPlugin;
+
+impl Plugin for SignPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(FixedUpdate, update_signs.after(ingress::decode::play));
+
+        // Placements are accepted by game code during `FixedUpdate`
+        app.add_systems(
+            FixedPostUpdate,
+            (open_editor_for_placed_signs, open_sign_editors).chain(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::I16Vec2;
+    use valence_generated::block::{PropName, PropValue};
+    use valence_nbt::Compound;
+
+    use super::*;
+    use crate::simulation::blocks::{
+        Blocks, ChunkBounds,
+        block_entity::{BlockEntity, BlockEntityData},
+    };
+
+    #[test]
+    fn breaking_a_sign_removes_its_text() {
+        let bounds = ChunkBounds::new(I16Vec2::ZERO, I16Vec2::ZERO);
+        let mut blocks = Blocks::from_fn(bounds, |_| BlockState::AIR);
+        let position = IVec3::new(1, 0, 1);
+        let sign = || BlockEntity {
+            id: "minecraft:sign".to_owned(),
+            data: BlockEntityData::Opaque(Compound::new()),
+        };
+
+        blocks.set_block(position, BlockState::OAK_SIGN).unwrap();
+        blocks.set_block_entity(position, Some(sign())).unwrap();
+
+        // Other states of the same block keep the block entity
+        let waterlogged = BlockState::OAK_SIGN.set(PropName::Waterlogged, PropValue::True);
+        blocks.set_block(position, waterlogged).unwrap();
+        assert_eq!(blocks.block_entity(position), Some(&sign()));
+
+        blocks.set_block(position, BlockState::AIR).unwrap();
+        assert_eq!(blocks.block_entity(position), None);
+    }
+
+    #[test]
+    fn lines_are_sanitized() {
+        assert_eq!(sanitize_sign_line("Shop: 5 gold  "), "Shop: 5 gold");
+        assert_eq!(sanitize_sign_line("§cred\nline"), "credline");
+        assert_eq!(
+            sanitize_sign_line(&"a".repeat(400)).len(),
+            MAX_SIGN_LINE_CHARS
+        );
+    }
+
+    #[test]
+    fn json_lines_are_plain_text() {
+        let line = sanitize_sign_line(r#"{"text":"hi","clickEvent":{"action":"run_command"}}"#);
+        let json = json_text(&line);
+
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&json).unwrap(),
+            serde_json::Value::String(line)
+        );
+    }
+}
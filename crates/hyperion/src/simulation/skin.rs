@@ -1,18 +1,32 @@
 //! Constructs for obtaining a player's skin.
 use anyhow::Context;
 use base64::{Engine as _, engine::general_purpose};
-use bevy_ecs::component::Component;
+use bevy_app::{App, FixedUpdate, Plugin};
+use bevy_ecs::{
+    component::Component,
+    entity::Entity,
+    message::{Message, MessageReader},
+    resource::Resource,
+    system::{Res, ResMut},
+    world::World,
+};
 use rkyv::Archive;
-use tracing::info;
+use rustc_hash::FxHashMap;
+use tracing::{info, warn};
 #[cfg(feature = "reflect")]
 use {bevy_ecs::reflect::ReflectComponent, bevy_reflect::Reflect};
 
-use crate::{storage::SkinHandler, util::mojang::MojangClient};
+use crate::{
+    command_channel::CommandChannel, runtime::AsyncRuntime, simulation::event::SetSkin,
+    storage::SkinHandler, util::mojang::MojangClient,
+};
 
 /// A signed player skin.
 #[derive(
     Debug,
     Clone,
+    PartialEq,
+    Eq,
     Archive,
     Component,
     rkyv::Deserialize,
@@ -98,3 +112,162 @@ impl PlayerSkin {
         Ok(None)
     }
 }
+
+/// The player whose skin should be fetched
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SkinSource {
+    Username(String),
+    Uuid(uuid::Uuid),
+}
+
+impl SkinSource {
+    /// Usernames are case-insensitive, so they are compared by their lowercase form
+    fn normalized(&self) -> Self {
+        match self {
+            Self::Username(username) => Self::Username(username.to_lowercase()),
+            Self::Uuid(uuid) => Self::Uuid(*uuid),
+        }
+    }
+}
+
+/// Fetches a skin in the background and inserts it as a [`PlayerSkin`] on `entity`.
+///
+/// Once the skin has been fetched, a [`SetSkin`] message is written so players who can already
+/// see the entity are sent the new skin. If the skin cannot be fetched, [`SkinFetchFailed`] is
+/// inserted instead.
+#[derive(Message, Debug, Clone)]
+pub struct FetchSkin {
+    pub entity: Entity,
+    pub source: SkinSource,
+}
+
+/// Marks an entity whose last [`FetchSkin`] request failed
+#[derive(Component, Debug, Clone, Copy)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+pub struct SkinFetchFailed;
+
+/// The entities waiting for each skin that is currently being fetched
+#[derive(Resource, Debug, Default)]
+struct PendingSkinFetches(FxHashMap<SkinSource, Vec<Entity>>);
+
+impl PendingSkinFetches {
+    /// Adds `entity` to the entities waiting for `source`. Returns whether a fetch needs to be
+    /// started, which is only the case if no other entity is waiting for the same skin.
+    fn add(&mut self, source: SkinSource, entity: Entity) -> bool {
+        let waiting = self.0.entry(source).or_default();
+        waiting.push(entity);
+        waiting.len() == 1
+    }
+}
+
+async fn fetch(
+    source: &SkinSource,
+    mojang: &MojangClient,
+    skins: &SkinHandler,
+) -> anyhow::Result<PlayerSkin> {
+    let uuid = match source {
+        SkinSource::Username(username) => mojang.get_uuid(username).await?,
+        SkinSource::Uuid(uuid) => *uuid,
+    };
+
+    PlayerSkin::from_uuid(uuid, mojang, skins)
+        .await?
+        .with_context(|| format!("{uuid} has no skin"))
+}
+
+fn fetch_skins(
+    mut events: MessageReader<'_, '_, FetchSkin>,
+    mut pending: ResMut<'_, PendingSkinFetches>,
+    runtime: Res<'_, AsyncRuntime>,
+    mojang: Res<'_, MojangClient>,
+    skins: Res<'_, SkinHandler>,
+    command_channel: Res<'_, CommandChannel>,
+) {
+    for event in events.read() {
+        let source = event.source.normalized();
+
+        if !pending.add(source.clone(), event.entity) {
+            continue;
+        }
+
+        let mojang = mojang.as_ref().clone();
+        let skins = skins.as_ref().clone();
+        let command_channel = command_channel.as_ref().clone();
+
+        runtime.spawn(async move {
+            let result = fetch(&source, &mojang, &skins).await;
+
+            // The source would stay pending forever if this was dropped
+            command_channel
+                .send_priority(move |world: &mut World| {
+                    finish_fetch(world, &source, result);
+                })
+                .await;
+        });
+    }
+}
+
+fn finish_fetch(world: &mut World, source: &SkinSource, result: anyhow::Result<PlayerSkin>) {
+    let waiting = world
+        .resource_mut::<PendingSkinFetches>()
+        .0
+        .remove(source)
+        .unwrap_or_default();
+
+    if let Err(e) = &result {
+        warn!("failed to fetch skin of {source:?}: {e}");
+    }
+
+    for entity in waiting {
+        let Ok(mut entity_mut) = world.get_entity_mut(entity) else {
+            // The entity was despawned while its skin was being fetched
+            continue;
+        };
+
+        match &result {
+            Ok(skin) => {
+                entity_mut.insert(skin.clone()).remove::<SkinFetchFailed>();
+                world.write_message(SetSkin {
+                    skin: skin.clone(),
+                    by: entity,
+                });
+            }
+            Err(_) => {
+                entity_mut.insert(SkinFetchFailed);
+            }
+        }
+    }
+}
+
+/// Handles [`FetchSkin`] messages
+pub struct SkinFetchPlugin;
+
+impl Plugin for SkinFetchPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PendingSkinFetches>();
+        app.add_message::<FetchSkin>();
+        app.add_systems(FixedUpdate, fetch_skins);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identical_fetches_are_coalesced() {
+        let mut world = World::new();
+        let a = world.spawn_empty().id();
+        let b = world.spawn_empty().id();
+
+        let mut pending = PendingSkinFetches::default();
+        let source = SkinSource::Username("Notch".to_owned()).normalized();
+
+        assert!(pending.add(source, a));
+        assert!(!pending.add(SkinSource::Username("notch".to_owned()).normalized(), b));
+        assert!(pending.add(SkinSource::Uuid(uuid::Uuid::nil()), a));
+
+        let waiting = &pending.0[&SkinSource::Username("notch".to_owned())];
+        assert_eq!(waiting, &[a, b]);
+    }
+}
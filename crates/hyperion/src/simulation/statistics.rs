@@ -0,0 +1,556 @@
+//! Player statistics shown in the statistics screen of the client.
+//!
+//! Every player has a [`Statistics`] component which is updated from the events Hyperion already
+//! produces: block updates, attacks, health changes, and movement. Game code can read and modify
+//! it like any other component, for example to build a kill leaderboard with [`leaderboard`].
+
+use bevy_app::{App, FixedPostUpdate, FixedUpdate, Plugin};
+use bevy_ecs::{
+    component::Component,
+    entity::Entity,
+    message::MessageReader,
+    query::{Has, Without},
+    schedule::IntoScheduleConfigs,
+    system::{Commands, Query, Res},
+};
+use glam::Vec2;
+use hyperion_utils::Prev;
+use rustc_hash::FxHashMap;
+use tracing::{error, warn};
+use valence_generated::{block::BlockKind, item::ItemKind};
+use valence_protocol::{
+    VarInt,
+    packets::play::{ClientStatusC2s, StatisticsS2c, statistics_s2c::Statistic as StatisticEntry},
+};
+#[cfg(feature = "reflect")]
+use {bevy_ecs::reflect::ReflectComponent, bevy_reflect::Reflect};
+
+use crate::{
+    ingress,
+    net::{Compose, ConnectionId},
+    simulation::{
+        Flight, MovementTracking, PendingTeleportation, Position, event,
+        metadata::{entity::Pose, living_entity::Health},
+        packet::play,
+    },
+};
+
+/// Movement of more than this many blocks in one tick is a teleport rather than walking
+const MAX_WALK_DISTANCE: f32 = 8.0;
+
+/// The statistic categories of the protocol.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum StatisticCategory {
+    /// Blocks mined, by block kind
+    Mined = 0,
+    /// Items crafted, by item kind
+    Crafted = 1,
+    /// Items used or blocks placed, by item kind
+    Used = 2,
+    /// Tools broken, by item kind
+    Broken = 3,
+    /// Items picked up, by item kind
+    PickedUp = 4,
+    /// Items dropped, by item kind
+    Dropped = 5,
+    /// Entities killed, by entity kind
+    Killed = 6,
+    /// Deaths caused by an entity, by entity kind
+    KilledBy = 7,
+    /// General statistics, see [`CustomStatistic`]
+    Custom = 8,
+}
+
+impl StatisticCategory {
+    #[must_use]
+    pub const fn from_raw(raw: u8) -> Option<Self> {
+        Some(match raw {
+            0 => Self::Mined,
+            1 => Self::Crafted,
+            2 => Self::Used,
+            3 => Self::Broken,
+            4 => Self::PickedUp,
+            5 => Self::Dropped,
+            6 => Self::Killed,
+            7 => Self::KilledBy,
+            8 => Self::Custom,
+            _ => return None,
+        })
+    }
+
+    #[must_use]
+    pub const fn to_raw(self) -> u8 {
+        self as u8
+    }
+}
+
+/// Statistics in the [`StatisticCategory::Custom`] category. The discriminants are the ids of the
+/// `minecraft:custom_stat` registry in 1.20.1.
+///
+/// Distances are measured in centimeters, durations in ticks, and damage in tenths of a heart
+/// point.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[repr(u16)]
+#[expect(missing_docs, reason = "self explanatory")]
+pub enum CustomStatistic {
+    LeaveGame = 0,
+    PlayTime = 1,
+    TotalWorldTime = 2,
+    TimeSinceDeath = 3,
+    TimeSinceRest = 4,
+    SneakTime = 5,
+    WalkOneCm = 6,
+    CrouchOneCm = 7,
+    SprintOneCm = 8,
+    WalkOnWaterOneCm = 9,
+    FallOneCm = 10,
+    ClimbOneCm = 11,
+    FlyOneCm = 12,
+    WalkUnderWaterOneCm = 13,
+    MinecartOneCm = 14,
+    BoatOneCm = 15,
+    PigOneCm = 16,
+    HorseOneCm = 17,
+    AviateOneCm = 18,
+    SwimOneCm = 19,
+    StriderOneCm = 20,
+    Jump = 21,
+    Drop = 22,
+    DamageDealt = 23,
+    DamageDealtAbsorbed = 24,
+    DamageDealtResisted = 25,
+    DamageBlockedByShield = 26,
+    DamageTaken = 27,
+    DamageAbsorbed = 28,
+    DamageResisted = 29,
+    Deaths = 30,
+    MobKills = 31,
+    AnimalsBred = 32,
+    PlayerKills = 33,
+    FishCaught = 34,
+}
+
+/// Identifies a single statistic.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct StatisticId {
+    category: StatisticCategory,
+    id: u16,
+}
+
+impl StatisticId {
+    /// Creates a statistic id from the id of a registry entry. The registry depends on the
+    /// category.
+    #[must_use]
+    pub const fn new(category: StatisticCategory, id: u16) -> Self {
+        Self { category, id }
+    }
+
+    #[must_use]
+    pub const fn mined(block: BlockKind) -> Self {
+        Self::new(StatisticCategory::Mined, block.to_raw())
+    }
+
+    #[must_use]
+    pub const fn used(item: ItemKind) -> Self {
+        Self::new(StatisticCategory::Used, item.to_raw())
+    }
+
+    #[must_use]
+    pub const fn custom(statistic: CustomStatistic) -> Self {
+        Self::new(StatisticCategory::Custom, statistic as u16)
+    }
+
+    #[must_use]
+    pub const fn category(self) -> StatisticCategory {
+        self.category
+    }
+
+    #[must_use]
+    pub const fn id(self) -> u16 {
+        self.id
+    }
+}
+
+impl From<CustomStatistic> for StatisticId {
+    fn from(statistic: CustomStatistic) -> Self {
+        Self::custom(statistic)
+    }
+}
+
+/// The statistics of a player. Statistics which were never recorded are zero.
+#[derive(Component, Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+pub struct Statistics {
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    values: FxHashMap<StatisticId, i32>,
+}
+
+impl Statistics {
+    #[must_use]
+    pub fn get(&self, id: impl Into<StatisticId>) -> i32 {
+        self.values.get(&id.into()).copied().unwrap_or_default()
+    }
+
+    pub fn set(&mut self, id: impl Into<StatisticId>, value: i32) {
+        self.values.insert(id.into(), value);
+    }
+
+    /// Adds `amount` to a statistic, saturating at the bounds of an `i32`
+    pub fn add(&mut self, id: impl Into<StatisticId>, amount: i32) {
+        let value = self.values.entry(id.into()).or_default();
+        *value = value.saturating_add(amount);
+    }
+
+    pub fn increment(&mut self, id: impl Into<StatisticId>) {
+        self.add(id, 1);
+    }
+
+    /// Adds every statistic of `other` to these statistics
+    pub fn merge(&mut self, other: &Self) {
+        for (id, value) in other.iter() {
+            self.add(id, value);
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (StatisticId, i32)> + '_ {
+        self.values.iter().map(|(&id, &value)| (id, value))
+    }
+
+    /// Creates the packet answering a statistics request. The statistics are sorted so the packet
+    /// is deterministic.
+    #[must_use]
+    pub fn to_packet(&self) -> StatisticsS2c {
+        let mut statistics = self
+            .iter()
+            .map(|(id, value)| StatisticEntry {
+                category_id: VarInt(i32::from(id.category.to_raw())),
+                statistic_id: VarInt(i32::from(id.id)),
+                value: VarInt(value),
+            })
+            .collect::<Vec<_>>();
+
+        statistics.sort_unstable_by_key(|entry| (entry.category_id.0, entry.statistic_id.0));
+
+        StatisticsS2c { statistics }
+    }
+}
+
+impl FromIterator<(StatisticId, i32)> for Statistics {
+    fn from_iter<T: IntoIterator<Item = (StatisticId, i32)>>(iter: T) -> Self {
+        Self {
+            values: iter.into_iter().collect(),
+        }
+    }
+}
+
+/// Returns the `count` players with the highest value of a statistic, from highest to lowest.
+///
+/// ```ignore
+/// fn kill_hologram(query: Query<'_, '_, (Entity, &Statistics)>) {
+///     let top = leaderboard(&query, CustomStatistic::PlayerKills, 10);
+///     // ...
+/// }
+/// ```
+pub fn leaderboard<'a>(
+    players: impl IntoIterator<Item = (Entity, &'a Statistics)>,
+    id: impl Into<StatisticId>,
+    count: usize,
+) -> Vec<(Entity, i32)> {
+    let id = id.into();
+
+    let mut entries = players
+        .into_iter()
+        .map(|(entity, statistics)| (entity, statistics.get(id)))
+        .collect::<Vec<_>>();
+
+    entries.sort_unstable_by(|(a_entity, a), (b_entity, b)| b.cmp(a).then(a_entity.cmp(b_entity)));
+    entries.truncate(count);
+    entries
+}
+
+/// Converts damage in heart points to the unit used by the damage statistics
+#[expect(
+    clippy::cast_possible_truncation,
+    reason = "damage values are far below the range of an i32"
+)]
+fn damage_statistic(damage: f32) -> i32 {
+    (damage * 10.0).round() as i32
+}
+
+/// The entity which last attacked this entity. The attacker is credited with the kill if this
+/// entity dies.
+#[derive(Component, Copy, Clone, Debug)]
+struct LastAttacker(Entity);
+
+fn answer_statistics_requests(
+    mut packets: MessageReader<'_, '_, play::ClientStatus>,
+    compose: Res<'_, Compose>,
+    query: Query<'_, '_, &Statistics>,
+) {
+    for packet in packets.read() {
+        if !matches!(**packet, ClientStatusC2s::RequestStats) {
+            continue;
+        }
+
+        let statistics = match query.get(packet.sender()) {
+            Ok(statistics) => statistics,
+            Err(e) => {
+                error!("failed to answer statistics request: query failed: {e}");
+                continue;
+            }
+        };
+
+        if let Err(e) = compose.unicast(&statistics.to_packet(), packet.connection_id()) {
+            error!("failed to send statistics: {e}");
+        }
+    }
+}
+
+fn record_block_statistics(
+    mut destroyed: MessageReader<'_, '_, event::DestroyBlock>,
+    mut placed: MessageReader<'_, '_, event::PlaceBlock>,
+    mut query: Query<'_, '_, &mut Statistics>,
+) {
+    for event in destroyed.read() {
+        let Ok(mut statistics) = query.get_mut(event.from) else {
+            continue;
+        };
+
+        if event.block.is_air() {
+            continue;
+        }
+
+        statistics.increment(StatisticId::mined(event.block.to_kind()));
+    }
+
+    for event in placed.read() {
+        let Ok(mut statistics) = query.get_mut(event.from) else {
+            continue;
+        };
+
+        let item = event.block.to_kind().to_item_kind();
+        if item != ItemKind::Air {
+            statistics.increment(StatisticId::used(item));
+        }
+    }
+}
+
+fn record_attacks(
+    mut attacks: MessageReader<'_, '_, event::AttackEntity>,
+    mut query: Query<'_, '_, &mut Statistics>,
+    mut commands: Commands<'_, '_>,
+) {
+    for attack in attacks.read() {
+        if let Ok(mut statistics) = query.get_mut(attack.origin) {
+            statistics.add(
+                CustomStatistic::DamageDealt,
+                damage_statistic(attack.damage),
+            );
+        }
+
+        if let Ok(mut target) = commands.get_entity(attack.target) {
+            target.insert(LastAttacker(attack.origin));
+        }
+    }
+}
+
+fn record_play_time(mut query: Query<'_, '_, &mut Statistics>) {
+    for mut statistics in &mut query {
+        statistics.increment(CustomStatistic::PlayTime);
+        statistics.increment(CustomStatistic::TimeSinceDeath);
+    }
+}
+
+fn record_movement(
+    mut query: Query<
+        '_,
+        '_,
+        (
+            &Position,
+            &Prev<Position>,
+            &MovementTracking,
+            &Flight,
+            &Pose,
+            &mut Statistics,
+        ),
+        Without<PendingTeleportation>,
+    >,
+) {
+    for (position, prev, tracking, flight, pose, mut statistics) in &mut query {
+        let delta = **position - ***prev;
+        let horizontal = Vec2::new(delta.x, delta.z).length();
+
+        if horizontal == 0.0 || delta.abs().max_element() >= MAX_WALK_DISTANCE {
+            continue;
+        }
+
+        let statistic = if flight.is_flying {
+            CustomStatistic::FlyOneCm
+        } else if *pose == Pose::Sneaking {
+            CustomStatistic::CrouchOneCm
+        } else if tracking.sprinting {
+            CustomStatistic::SprintOneCm
+        } else {
+            CustomStatistic::WalkOneCm
+        };
+
+        #[expect(
+            clippy::cast_possible_truncation,
+            reason = "the distance is below MAX_WALK_DISTANCE"
+        )]
+        let centimeters = (horizontal * 100.0).round() as i32;
+        statistics.add(statistic, centimeters);
+    }
+}
+
+fn record_health(
+    victims: Query<
+        '_,
+        '_,
+        (
+            Entity,
+            &Health,
+            &Prev<Health>,
+            Option<&LastAttacker>,
+            Has<ConnectionId>,
+        ),
+    >,
+    mut statistics: Query<'_, '_, &mut Statistics>,
+    mut commands: Commands<'_, '_>,
+) {
+    for (entity, health, prev, attacker, is_player) in &victims {
+        let damage = **prev - **health;
+        if damage <= 0.0 {
+            continue;
+        }
+
+        let died = !prev.is_dead() && health.is_dead();
+
+        if let Ok(mut victim) = statistics.get_mut(entity) {
+            victim.add(CustomStatistic::DamageTaken, damage_statistic(damage));
+
+            if died {
+                victim.increment(CustomStatistic::Deaths);
+                victim.set(CustomStatistic::TimeSinceDeath, 0);
+            }
+        }
+
+        if !died {
+            continue;
+        }
+
+        if let Some(&LastAttacker(attacker)) = attacker {
+            commands.entity(entity).remove::<LastAttacker>();
+
+            if attacker == entity {
+                continue;
+            }
+
+            match statistics.get_mut(attacker) {
+                Ok(mut attacker) => attacker.increment(if is_player {
+                    CustomStatistic::PlayerKills
+                } else {
+                    CustomStatistic::MobKills
+                }),
+                Err(e) => warn!("failed to credit kill of {entity}: query failed: {e}"),
+            }
+        }
+    }
+}
+
+pub struct StatisticsPlugin;
+
+impl Plugin for StatisticsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            FixedUpdate,
+            (
+                answer_statistics_requests,
+                record_block_statistics,
+                record_attacks,
+                record_play_time,
+            )
+                .after(ingress::decode::play),
+        );
+
+        // Movement and damage are recorded after the game has processed this tick
+        app.add_systems(FixedPostUpdate, (record_movement, record_health));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use valence_protocol::Encode;
+
+    use super::*;
+
+    #[test]
+    fn missing_statistics_are_zero() {
+        let mut statistics = Statistics::default();
+        assert_eq!(statistics.get(CustomStatistic::Jump), 0);
+
+        statistics.increment(CustomStatistic::Jump);
+        statistics.add(CustomStatistic::Jump, 2);
+        assert_eq!(statistics.get(CustomStatistic::Jump), 3);
+
+        statistics.add(CustomStatistic::Jump, i32::MAX);
+        assert_eq!(statistics.get(CustomStatistic::Jump), i32::MAX);
+    }
+
+    #[test]
+    fn packet_encoding() {
+        let statistics = [
+            (StatisticId::custom(CustomStatistic::Jump), 5),
+            (StatisticId::mined(BlockKind::Stone), 300),
+        ]
+        .into_iter()
+        .collect::<Statistics>();
+
+        let mut bytes = Vec::new();
+        statistics.to_packet().encode(&mut bytes).unwrap();
+
+        let stone = u8::try_from(BlockKind::Stone.to_raw()).unwrap();
+
+        // Entry count, then category, statistic id, and value for each entry sorted by category.
+        // 300 is encoded as a two byte VarInt.
+        assert_eq!(bytes, [2, 0, stone, 0xac, 0x02, 8, 21, 5]);
+    }
+
+    #[test]
+    fn raw_categories_round_trip() {
+        for raw in 0..=8 {
+            let category = StatisticCategory::from_raw(raw).unwrap();
+            assert_eq!(category.to_raw(), raw);
+        }
+        assert_eq!(StatisticCategory::from_raw(9), None);
+    }
+
+    #[test]
+    fn leaderboard_is_sorted() {
+        let mut world = bevy_ecs::world::World::new();
+        let [a, b, c] = std::array::from_fn(|_| world.spawn_empty().id());
+
+        let with_kills = |kills| {
+            [(StatisticId::custom(CustomStatistic::PlayerKills), kills)]
+                .into_iter()
+                .collect::<Statistics>()
+        };
+
+        let players = [
+            (a, with_kills(2)),
+            (b, with_kills(7)),
+            (c, Statistics::default()),
+        ];
+
+        let top = leaderboard(
+            players
+                .iter()
+                .map(|(entity, statistics)| (*entity, statistics)),
+            CustomStatistic::PlayerKills,
+            2,
+        );
+
+        assert_eq!(top, [(b, 7), (a, 2)]);
+    }
+}
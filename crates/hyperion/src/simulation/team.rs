@@ -0,0 +1,466 @@
+//! Teams of players, which are shown to clients through scoreboard teams. See [`Team`].
+//!
+//! A team is an entity with a [`Team`] component. Players join it by inserting a [`TeamMember`]
+//! pointing at the team, and switch teams by inserting a different one. [`TeamsPlugin`] keeps
+//! every client up to date, including the ones joining later. Players which are not in a team
+//! stay in the team without name tags that every player starts in.
+//!
+//! Vanished players are invisible, which still lets their teammates see them as translucent if
+//! [`Team::see_invisible_teammates`] is set.
+
+use std::{borrow::Cow, io::Write};
+
+use bevy_app::{App, FixedPostUpdate, Plugin};
+use bevy_ecs::{
+    change_detection::{DetectChanges, Ref},
+    component::Component,
+    entity::Entity,
+    lifecycle::{Insert, Remove, Replace},
+    name::Name,
+    observer::On,
+    query::With,
+    resource::Resource,
+    schedule::IntoScheduleConfigs,
+    system::{Query, Res, ResMut, SystemParam},
+};
+use rustc_hash::FxHashMap;
+use valence_bytes::{CowUtf8Bytes, Utf8Bytes};
+use valence_protocol::{
+    Packet,
+    packets::play::{
+        TeamS2c,
+        team_s2c::{CollisionRule, Mode, NameTagVisibility, TeamColor, TeamFlags},
+    },
+};
+use valence_text::IntoText;
+#[cfg(feature = "reflect")]
+use {bevy_ecs::reflect::ReflectComponent, bevy_reflect::Reflect};
+
+use crate::{
+    PacketBundle,
+    egress::resync::PlayerResynced,
+    net::{Compose, ConnectionId, DataBundle, SendResultExt},
+    simulation::join::PlayerJoined,
+};
+
+/// The team players are in while they are not in a [`Team`]. It hides their name tags.
+pub(crate) const NO_TAG_TEAM: &str = "no_tag";
+
+/// A team of players. See the [module documentation](self).
+#[derive(Component, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+pub struct Team {
+    /// The name which identifies the team to clients. This must be unique and at most 16
+    /// characters long.
+    pub id: String,
+    /// The color of the names of the members
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    pub color: TeamColor,
+    /// Whether members can damage each other. See [`Teams::can_damage`].
+    pub friendly_fire: bool,
+    /// Whether members see invisible members as translucent
+    pub see_invisible_teammates: bool,
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    pub name_tag_visibility: NameTagVisibility,
+}
+
+impl Team {
+    /// Creates a team with friendly fire and visible name tags, like vanilla
+    #[must_use]
+    pub fn new(id: impl Into<String>, color: TeamColor) -> Self {
+        Self {
+            id: id.into(),
+            color,
+            friendly_fire: true,
+            see_invisible_teammates: true,
+            name_tag_visibility: NameTagVisibility::Always,
+        }
+    }
+
+    fn flags(&self) -> TeamFlags {
+        TeamFlags::new()
+            .with_friendly_fire(self.friendly_fire)
+            .with_see_invisible_teammates(self.see_invisible_teammates)
+    }
+
+    fn create_packet<'a>(&'a self, entities: Vec<CowUtf8Bytes<'a>>) -> TeamS2c<'a> {
+        TeamS2c {
+            team_name: CowUtf8Bytes::Borrowed(&self.id),
+            mode: Mode::CreateTeam {
+                team_display_name: Cow::Owned(self.id.as_str().into_text()),
+                friendly_flags: self.flags(),
+                name_tag_visibility: self.name_tag_visibility,
+                collision_rule: CollisionRule::Always,
+                team_color: self.color,
+                team_prefix: Cow::default(),
+                team_suffix: Cow::default(),
+                entities,
+            },
+        }
+    }
+
+    fn update_packet(&self) -> TeamS2c<'_> {
+        TeamS2c {
+            team_name: CowUtf8Bytes::Borrowed(&self.id),
+            mode: Mode::UpdateTeamInfo {
+                team_display_name: Cow::Owned(self.id.as_str().into_text()),
+                friendly_flags: self.flags(),
+                name_tag_visibility: self.name_tag_visibility,
+                collision_rule: CollisionRule::Always,
+                team_color: self.color,
+                team_prefix: Cow::default(),
+                team_suffix: Cow::default(),
+            },
+        }
+    }
+}
+
+/// The [`Team`] a player is in
+#[derive(Component, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+#[relationship(relationship_target = TeamMembers)]
+pub struct TeamMember(pub Entity);
+
+/// The players in a [`Team`]. This is kept up to date from [`TeamMember`].
+#[derive(Component, Default, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+#[relationship_target(relationship = TeamMember)]
+pub struct TeamMembers(Vec<Entity>);
+
+impl std::ops::Deref for TeamMembers {
+    type Target = [Entity];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// Checks of the teams of entities
+#[derive(SystemParam)]
+pub struct Teams<'w, 's> {
+    teams: Query<'w, 's, &'static Team>,
+    members: Query<'w, 's, &'static TeamMember>,
+}
+
+impl Teams<'_, '_> {
+    /// The team `entity` is in
+    #[must_use]
+    pub fn team_of(&self, entity: Entity) -> Option<(Entity, &Team)> {
+        let &TeamMember(team) = self.members.get(entity).ok()?;
+        let data = self.teams.get(team).ok()?;
+        Some((team, data))
+    }
+
+    /// Whether `a` and `b` are in the same team
+    #[must_use]
+    pub fn are_teammates(&self, a: Entity, b: Entity) -> bool {
+        match (self.team_of(a), self.team_of(b)) {
+            (Some((a, _)), Some((b, _))) => a == b,
+            _ => false,
+        }
+    }
+
+    /// Whether `attacker` may damage `target`, which is only prevented if both are in the same
+    /// team without [`Team::friendly_fire`]
+    #[must_use]
+    pub fn can_damage(&self, attacker: Entity, target: Entity) -> bool {
+        match (self.team_of(attacker), self.team_of(target)) {
+            (Some((a, team)), Some((b, _))) if a == b => team.friendly_fire,
+            _ => true,
+        }
+    }
+}
+
+/// A change of the team of a player which clients have not been told about yet
+#[derive(Debug)]
+struct MembershipChange {
+    name: String,
+    /// The id of the team clients know the player to be in, or `None` if it is in
+    /// [`NO_TAG_TEAM`]
+    synced_team: Option<String>,
+}
+
+#[derive(Resource, Default, Debug)]
+struct PendingMemberships(FxHashMap<Entity, MembershipChange>);
+
+/// Moves a player from one team to another in a single broadcast
+struct MoveMember<'a> {
+    name: &'a str,
+    from: Option<&'a str>,
+    to: Option<&'a str>,
+}
+
+impl PacketBundle for &MoveMember<'_> {
+    fn encode_including_ids(self, mut w: impl Write) -> anyhow::Result<()> {
+        if let Some(from) = self.from {
+            TeamS2c {
+                team_name: CowUtf8Bytes::Borrowed(from),
+                mode: Mode::RemoveEntities {
+                    entities: vec![CowUtf8Bytes::Borrowed(self.name)],
+                },
+            }
+            .encode_with_id(&mut w)?;
+        }
+
+        if let Some(to) = self.to {
+            TeamS2c {
+                team_name: CowUtf8Bytes::Borrowed(to),
+                mode: Mode::AddEntities {
+                    entities: vec![CowUtf8Bytes::Borrowed(self.name)],
+                },
+            }
+            .encode_with_id(&mut w)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn record_joined_team(
+    insert: On<'_, '_, Insert, TeamMember>,
+    names: Query<'_, '_, &Name>,
+    mut pending: ResMut<'_, PendingMemberships>,
+) {
+    let Ok(name) = names.get(insert.entity) else {
+        return;
+    };
+
+    pending
+        .0
+        .entry(insert.entity)
+        .or_insert_with(|| MembershipChange {
+            name: name.to_string(),
+            synced_team: None,
+        });
+}
+
+fn record_left_team(
+    replace: On<'_, '_, Replace, TeamMember>,
+    names: Query<'_, '_, &Name>,
+    members: Query<'_, '_, &TeamMember>,
+    teams: Query<'_, '_, &Team>,
+    mut pending: ResMut<'_, PendingMemberships>,
+) {
+    let Ok(name) = names.get(replace.entity) else {
+        return;
+    };
+
+    let synced_team = members
+        .get(replace.entity)
+        .and_then(|&TeamMember(team)| teams.get(team))
+        .ok()
+        .map(|team| team.id.clone());
+
+    // Only the team from before the first change of this tick is known to clients
+    pending
+        .0
+        .entry(replace.entity)
+        .or_insert_with(|| MembershipChange {
+            name: name.to_string(),
+            synced_team,
+        });
+}
+
+fn remove_team(
+    remove: On<'_, '_, Remove, Team>,
+    teams: Query<'_, '_, (&Team, Option<&TeamMembers>)>,
+    names: Query<'_, '_, &Name>,
+    compose: Res<'_, Compose>,
+) {
+    let Ok((team, members)) = teams.get(remove.entity) else {
+        return;
+    };
+
+    let pkt = TeamS2c {
+        team_name: CowUtf8Bytes::Borrowed(&team.id),
+        mode: Mode::RemoveTeam,
+    };
+    compose.broadcast(&pkt).send().unwrap();
+
+    // Clients forget the members together with the team, so their name tags are hidden again
+    let entities: Vec<_> = member_names(members, &names).collect();
+    if entities.is_empty() {
+        return;
+    }
+
+    let pkt = TeamS2c {
+        team_name: Utf8Bytes::from_static(NO_TAG_TEAM).into(),
+        mode: Mode::AddEntities { entities },
+    };
+    compose.broadcast(&pkt).send().unwrap();
+}
+
+fn member_names<'a>(
+    members: Option<&'a TeamMembers>,
+    names: &'a Query<'_, '_, &Name>,
+) -> impl Iterator<Item = CowUtf8Bytes<'a>> {
+    members
+        .into_iter()
+        .flat_map(|members| members.iter())
+        .filter_map(|&member| names.get(member).ok())
+        .map(|name| CowUtf8Bytes::Borrowed(name.as_str()))
+}
+
+fn sync_teams(teams: Query<'_, '_, Ref<'_, Team>>, compose: Res<'_, Compose>) {
+    for team in &teams {
+        if team.is_added() {
+            compose
+                .broadcast(&team.create_packet(Vec::new()))
+                .send()
+                .unwrap();
+        } else if team.is_changed() {
+            compose.broadcast(&team.update_packet()).send().unwrap();
+        }
+    }
+}
+
+fn sync_members(
+    mut pending: ResMut<'_, PendingMemberships>,
+    teams: Teams<'_, '_>,
+    players: Query<'_, '_, (), With<Name>>,
+    compose: Res<'_, Compose>,
+) {
+    for (player, change) in pending.0.drain() {
+        let team = teams.team_of(player).map(|(_, team)| team.id.as_str());
+        if team == change.synced_team.as_deref() {
+            continue;
+        }
+
+        // Despawned players are only removed from their old team
+        let to = players
+            .contains(player)
+            .then_some(team.unwrap_or(NO_TAG_TEAM));
+
+        let pkt = MoveMember {
+            name: &change.name,
+            from: change.synced_team.as_deref(),
+            to,
+        };
+
+        compose.broadcast(&pkt).send().unwrap();
+    }
+}
+
+/// Sends every team and its members to players joining later
+fn send_teams_to_joined(
+    joined: On<'_, '_, PlayerJoined>,
+    teams: Query<'_, '_, (&Team, Option<&TeamMembers>)>,
+    names: Query<'_, '_, &Name>,
+    connections: Query<'_, '_, &ConnectionId>,
+    compose: Res<'_, Compose>,
+) {
+    let Ok(&connection_id) = connections.get(joined.entity) else {
+        return;
+    };
+
+    let mut bundle = DataBundle::new(&compose);
+
+    for (team, members) in &teams {
+        let entities = member_names(members, &names).collect();
+        bundle.add_packet(&team.create_packet(entities)).unwrap();
+    }
+
+    bundle.unicast(connection_id).unwrap_or_disconnected();
+}
+
+/// Sends every team and its members again to players being resynced. The teams are removed first
+/// because clients reject teams they already know.
+fn resend_teams_to_resynced(
+    resynced: On<'_, '_, PlayerResynced>,
+    teams: Query<'_, '_, (&Team, Option<&TeamMembers>)>,
+    names: Query<'_, '_, &Name>,
+    connections: Query<'_, '_, &ConnectionId>,
+    compose: Res<'_, Compose>,
+) {
+    let Ok(&connection_id) = connections.get(resynced.entity) else {
+        return;
+    };
+
+    let mut bundle = DataBundle::new(&compose);
+
+    for (team, members) in &teams {
+        bundle
+            .add_packet(&TeamS2c {
+                team_name: CowUtf8Bytes::Borrowed(&team.id),
+                mode: Mode::RemoveTeam,
+            })
+            .unwrap();
+
+        let entities = member_names(members, &names).collect();
+        bundle.add_packet(&team.create_packet(entities)).unwrap();
+    }
+
+    bundle.unicast(connection_id).unwrap_or_disconnected();
+}
+
+pub struct TeamsPlugin;
+
+impl Plugin for TeamsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PendingMemberships>();
+        app.add_observer(record_joined_team);
+        app.add_observer(record_left_team);
+        app.add_observer(remove_team);
+        app.add_observer(send_teams_to_joined);
+        app.add_observer(resend_teams_to_resynced);
+        app.add_systems(FixedPostUpdate, (sync_teams, sync_members).chain());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_ecs::{system::RunSystemOnce, world::World};
+
+    use super::*;
+
+    fn world_with_players() -> (World, Entity, Entity, Entity) {
+        let mut world = World::new();
+        world.init_resource::<PendingMemberships>();
+        world.add_observer(record_joined_team);
+        world.add_observer(record_left_team);
+
+        let mut red = Team::new("red", TeamColor::Red);
+        red.friendly_fire = false;
+        let red = world.spawn(red).id();
+        let blue = world.spawn(Team::new("blue", TeamColor::Blue)).id();
+        let player = world.spawn(Name::from("player")).id();
+
+        (world, red, blue, player)
+    }
+
+    #[test]
+    fn friendly_fire_is_checked_for_teammates() {
+        let (mut world, red, blue, player) = world_with_players();
+        let teammate = world.spawn((Name::from("teammate"), TeamMember(red))).id();
+        let enemy = world.spawn((Name::from("enemy"), TeamMember(blue))).id();
+        let enemy_teammate = world.spawn((Name::from("other"), TeamMember(blue))).id();
+        world.entity_mut(player).insert(TeamMember(red));
+
+        world
+            .run_system_once(move |teams: Teams<'_, '_>| {
+                assert!(teams.are_teammates(player, teammate));
+                assert!(!teams.can_damage(player, teammate));
+                assert!(teams.can_damage(player, enemy));
+                assert!(teams.can_damage(enemy, enemy_teammate));
+            })
+            .unwrap();
+
+        assert_eq!(
+            *world.get::<TeamMembers>(red).unwrap(),
+            TeamMembers(vec![teammate, player])
+        );
+    }
+
+    #[test]
+    fn changes_within_a_tick_remember_the_synced_team() {
+        let (mut world, red, blue, player) = world_with_players();
+        world.entity_mut(player).insert(TeamMember(red));
+        world.resource_mut::<PendingMemberships>().0.clear();
+
+        world.entity_mut(player).insert(TeamMember(blue));
+        world.entity_mut(player).remove::<TeamMember>();
+
+        let pending = world.resource::<PendingMemberships>();
+        assert_eq!(pending.0[&player].synced_team.as_deref(), Some("red"));
+    }
+}
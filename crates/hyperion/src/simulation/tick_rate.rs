@@ -0,0 +1,257 @@
+//! Ticking distant entities less often. See [`TickRate`].
+//!
+//! Every second, the [`TickRate`] of each entity which has one is set from its distance to the
+//! nearest player, found through the [`SpatialIndex`]. Entities must therefore also be
+//! [`Spatial`](crate::spatial::Spatial) to be ticked while players are nearby. Players which move
+//! wake up the entities close to them immediately instead of waiting for the next scan.
+//!
+//! Expensive per-entity systems skip entities for which [`should_tick`] is false. Entities
+//! without a [`TickRate`] are ticked every tick. These systems, and the scans setting the tick
+//! rates, are in the [`EntitySimulation`] set, so nothing is ticked while the server is idle.
+
+use bevy_app::{App, FixedUpdate, Plugin};
+use bevy_ecs::{
+    change_detection::DetectChangesMut,
+    component::Component,
+    entity::Entity,
+    query::{Changed, With},
+    resource::Resource,
+    schedule::IntoScheduleConfigs,
+    system::{Query, Res},
+};
+use geometry::aabb::Aabb;
+use glam::Vec3;
+#[cfg(feature = "reflect")]
+use {
+    bevy_ecs::reflect::{ReflectComponent, ReflectResource},
+    bevy_reflect::Reflect,
+};
+
+use crate::{
+    Tick,
+    activity::EntitySimulation,
+    ingress,
+    simulation::{EntitySize, Player, Position, world::WorldId},
+    spatial::SpatialIndex,
+};
+
+/// How often the tick rates are recalculated, in ticks
+pub const SCAN_INTERVAL_TICKS: i64 = 20;
+
+/// How often an entity is ticked by the expensive per-entity systems, such as AI and physics
+#[derive(Component, Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+pub enum TickRate {
+    /// Not ticked at all
+    Frozen,
+    /// Ticked once every 20 ticks
+    Every20,
+    /// Ticked once every 4 ticks
+    Every4,
+    /// Ticked every tick
+    #[default]
+    Every,
+}
+
+impl TickRate {
+    /// The number of ticks between two ticks of the entity, or `None` if it is frozen
+    #[must_use]
+    pub const fn period(self) -> Option<i64> {
+        match self {
+            Self::Frozen => None,
+            Self::Every20 => Some(20),
+            Self::Every4 => Some(4),
+            Self::Every => Some(1),
+        }
+    }
+}
+
+/// The distances to the nearest player up to which entities get each [`TickRate`]. Entities
+/// further away than `every_20_ticks` are frozen.
+#[derive(Resource, Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Resource))]
+pub struct TickRateDistances {
+    pub every_tick: f32,
+    pub every_4_ticks: f32,
+    pub every_20_ticks: f32,
+}
+
+impl Default for TickRateDistances {
+    fn default() -> Self {
+        Self {
+            every_tick: 32.0,
+            every_4_ticks: 64.0,
+            every_20_ticks: 100.0,
+        }
+    }
+}
+
+impl TickRateDistances {
+    /// The tick rate of an entity `distance` blocks away from the nearest player
+    #[must_use]
+    pub fn rate_at(&self, distance: f32) -> TickRate {
+        if distance <= self.every_tick {
+            TickRate::Every
+        } else if distance <= self.every_4_ticks {
+            TickRate::Every4
+        } else if distance <= self.every_20_ticks {
+            TickRate::Every20
+        } else {
+            TickRate::Frozen
+        }
+    }
+}
+
+/// Whether an entity with the tick rate `rate` is ticked at `global_tick`.
+///
+/// Entities with the same tick rate are spread across the ticks of their period by their id, so
+/// that they do not all tick at once.
+#[must_use]
+pub fn should_tick(rate: Option<&TickRate>, global_tick: i64, entity: Entity) -> bool {
+    let Some(period) = rate.map_or(Some(1), |rate| rate.period()) else {
+        return false;
+    };
+
+    let phase = i64::from(entity.index().index()) % period;
+    global_tick.rem_euclid(period) == phase
+}
+
+/// The box around `center` which contains every point up to `distance` away
+fn cube(center: Vec3, distance: f32) -> Aabb {
+    Aabb::new(
+        center - Vec3::splat(distance),
+        center + Vec3::splat(distance),
+    )
+}
+
+/// Sets the tick rate of every entity from its distance to the nearest player in its world
+fn scan_tick_rates(
+    tick: Res<'_, Tick>,
+    distances: Res<'_, TickRateDistances>,
+    index: Res<'_, SpatialIndex>,
+    players: Query<'_, '_, (&Position, Option<&WorldId>), With<Player>>,
+    bounds: Query<'_, '_, (&Position, &EntitySize)>,
+    mut entities: Query<'_, '_, (&mut TickRate, &Position, Option<&WorldId>)>,
+) {
+    if tick.0 % SCAN_INTERVAL_TICKS != 0 {
+        return;
+    }
+
+    for (mut rate, ..) in &mut entities {
+        rate.set_if_neq(TickRate::Frozen);
+    }
+
+    for (player, player_world) in &players {
+        let area = cube(**player, distances.every_20_ticks);
+
+        for entity in index.get_collisions(area, bounds) {
+            let Ok((mut rate, position, world)) = entities.get_mut(entity) else {
+                continue;
+            };
+
+            if world.copied().unwrap_or_default() != player_world.copied().unwrap_or_default() {
+                continue;
+            }
+
+            let nearby = distances.rate_at(position.distance(**player));
+            if nearby > *rate {
+                *rate = nearby;
+            }
+        }
+    }
+}
+
+/// Wakes up the entities close to players which moved this tick
+fn wake_nearby(
+    distances: Res<'_, TickRateDistances>,
+    index: Res<'_, SpatialIndex>,
+    players: Query<'_, '_, (&Position, Option<&WorldId>), (With<Player>, Changed<Position>)>,
+    bounds: Query<'_, '_, (&Position, &EntitySize)>,
+    mut entities: Query<'_, '_, (&mut TickRate, &Position, Option<&WorldId>)>,
+) {
+    for (player, player_world) in &players {
+        let area = cube(**player, distances.every_tick);
+
+        for entity in index.get_collisions(area, bounds) {
+            let Ok((mut rate, position, world)) = entities.get_mut(entity) else {
+                continue;
+            };
+
+            if world.copied().unwrap_or_default() == player_world.copied().unwrap_or_default()
+                && *rate != TickRate::Every
+                && position.distance(**player) <= distances.every_tick
+            {
+                *rate = TickRate::Every;
+            }
+        }
+    }
+}
+
+/// Recalculates the [`TickRate`] of entities every [`SCAN_INTERVAL_TICKS`] and wakes up entities
+/// when players come close
+pub struct TickRatePlugin;
+
+impl Plugin for TickRatePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TickRateDistances>();
+        app.add_systems(
+            FixedUpdate,
+            (scan_tick_rates, wake_nearby)
+                .chain()
+                .in_set(EntitySimulation)
+                .after(ingress::decode::play),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_ecs::world::World;
+
+    use super::*;
+
+    #[test]
+    fn entities_tick_once_per_period() {
+        let mut world = World::new();
+        let entities: Vec<Entity> = (0..100).map(|_| world.spawn_empty().id()).collect();
+
+        for rate in [TickRate::Every, TickRate::Every4, TickRate::Every20] {
+            let period = rate.period().unwrap();
+            for &entity in &entities {
+                let ticks = (0..period * 3)
+                    .filter(|&tick| should_tick(Some(&rate), tick, entity))
+                    .count();
+                assert_eq!(ticks, 3);
+            }
+        }
+
+        for tick in 0..40 {
+            assert!(should_tick(None, tick, entities[0]));
+            assert!(!should_tick(Some(&TickRate::Frozen), tick, entities[0]));
+        }
+    }
+
+    #[test]
+    fn entities_are_staggered() {
+        let mut world = World::new();
+        let entities: Vec<Entity> = (0..100).map(|_| world.spawn_empty().id()).collect();
+
+        for tick in 0..20 {
+            let ticked = entities
+                .iter()
+                .filter(|&&entity| should_tick(Some(&TickRate::Every20), tick, entity))
+                .count();
+            assert_eq!(ticked, 5);
+        }
+    }
+
+    #[test]
+    fn rate_decreases_with_distance() {
+        let distances = TickRateDistances::default();
+        assert_eq!(distances.rate_at(0.0), TickRate::Every);
+        assert_eq!(distances.rate_at(32.0), TickRate::Every);
+        assert_eq!(distances.rate_at(50.0), TickRate::Every4);
+        assert_eq!(distances.rate_at(100.0), TickRate::Every20);
+        assert_eq!(distances.rate_at(100.5), TickRate::Frozen);
+    }
+}
@@ -119,13 +119,19 @@ pub const fn ceil_log2(x: u32) -> u32 {
 /// Create a heightmap for the highest solid block at each position in the chunk.
 #[must_use]
 pub fn heightmap(max_height: u32, current_height: u32) -> Vec<u64> {
+    heightmap_from_fn(max_height, |_, _| current_height + 1)
+}
+
+/// Builds a heightmap where `height(x, z)` returns the value stored for each column of a chunk.
+#[must_use]
+pub fn heightmap_from_fn(max_height: u32, mut height: impl FnMut(u32, u32) -> u32) -> Vec<u64> {
     let bits = ceil_log2(max_height + 1);
     let mut data = BitStorage::new(bits as usize, 16 * 16, None).unwrap();
 
-    for x in 0_usize..16 {
-        for z in 0_usize..16 {
-            let index = x + z * 16;
-            data.set(index, u64::from(current_height) + 1);
+    for x in 0_u32..16 {
+        for z in 0_u32..16 {
+            let index = (x + z * 16) as usize;
+            data.set(index, u64::from(height(x, z)));
         }
     }
 
@@ -0,0 +1,172 @@
+//! [`UuidHasher`], a fast hasher for maps keyed by UUIDs.
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::OnceLock,
+};
+
+/// The multiplier used by `FxHasher`
+const SEED: u64 = 0xf135_7aea_2e62_a9c5;
+
+/// A [`Hasher`] for maps keyed by UUIDs.
+///
+/// UUIDs are short, so each 64-bit word written to the hasher is only folded into the hash with a
+/// multiply and the result mixed once, instead of being run through `SipHash`. Writes of any
+/// length are accepted, which includes the length prefix that the [`Hash`](std::hash::Hash) impl
+/// of [`uuid::Uuid`] writes before its bytes.
+///
+/// Clients can choose their UUID in offline mode and with some proxies, so [`UuidBuildHasher`]
+/// starts every hash from a random key of the server. This is not a cryptographic hash, but
+/// colliding UUIDs cannot be computed without knowing the key.
+#[derive(Default, Clone, Copy, Debug)]
+pub struct UuidHasher {
+    hash: u64,
+}
+
+/// A [`BuildHasher`] for [`UuidHasher`] with the random key of the server
+#[derive(Clone, Copy, Debug)]
+pub struct UuidBuildHasher {
+    key: u64,
+}
+
+impl Default for UuidBuildHasher {
+    fn default() -> Self {
+        static KEY: OnceLock<u64> = OnceLock::new();
+
+        // Every map shares the key, so that hashes stay comparable within the server
+        let key = *KEY.get_or_init(|| RandomState::new().hash_one(0_u64));
+        Self { key }
+    }
+}
+
+impl BuildHasher for UuidBuildHasher {
+    type Hasher = UuidHasher;
+
+    fn build_hasher(&self) -> UuidHasher {
+        UuidHasher { hash: self.key }
+    }
+}
+
+impl Hasher for UuidHasher {
+    fn finish(&self) -> u64 {
+        // The multiply only carries entropy upwards, so the hash is finalized with the murmur3
+        // mixer to spread it into the low bits that hash maps use to pick a bucket
+        let mut hash = self.hash;
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+        hash ^ (hash >> 33)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        let (chunks, tail) = bytes.as_chunks::<8>();
+        for chunk in chunks {
+            self.write_u64(u64::from_le_bytes(*chunk));
+        }
+
+        if !tail.is_empty() {
+            let mut last = [0; 8];
+            last[..tail.len()].copy_from_slice(tail);
+            // Include the tail length so that trailing zero bytes change the hash
+            self.write_u64(u64::from_le_bytes(last) ^ ((tail.len() as u64) << 59));
+        }
+    }
+
+    fn write_u8(&mut self, i: u8) {
+        self.write_u64(u64::from(i));
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.write_u64(u64::from(i));
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write_u64(u64::from(i));
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.hash = (self.hash.rotate_left(26) ^ i).wrapping_mul(SEED);
+    }
+
+    #[expect(clippy::cast_possible_truncation)]
+    fn write_u128(&mut self, i: u128) {
+        self.write_u64(i as u64);
+        self.write_u64((i >> 64) as u64);
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, hash::Hash};
+
+    use super::*;
+    use crate::simulation::Uuid;
+
+    fn hash(value: impl Hash) -> u64 {
+        UuidBuildHasher::default().hash_one(value)
+    }
+
+    #[test]
+    fn writes_of_any_length_are_hashed() {
+        let bytes: Vec<u8> = (1..=20).collect();
+        let hashes: HashSet<_> = (0..=bytes.len())
+            .map(|len| {
+                let mut hasher = UuidHasher::default();
+                hasher.write(&bytes[..len]);
+                hasher.finish()
+            })
+            .collect();
+        assert_eq!(hashes.len(), bytes.len() + 1);
+
+        let mut a = UuidHasher::default();
+        a.write(&[1, 2, 3]);
+        let mut b = UuidHasher::default();
+        b.write(&[1, 2, 3, 0]);
+        assert_ne!(a.finish(), b.finish());
+    }
+
+    #[test]
+    fn distinct_uuids_have_distinct_hashes() {
+        let a = uuid::Uuid::from_u128(1);
+        let b = uuid::Uuid::from_u128(1 << 64);
+        assert_ne!(hash(a), hash(b));
+        assert_ne!(hash(Uuid(a)), hash(Uuid(b)));
+        assert_eq!(hash(Uuid(a)), hash(a));
+
+        let uuids: Vec<_> = (0..1000).map(|_| Uuid::new_v4()).collect();
+        let hashes: HashSet<_> = uuids.iter().map(hash).collect();
+        assert_eq!(hashes.len(), uuids.len());
+    }
+
+    #[test]
+    fn hashes_are_keyed() {
+        let uuid = uuid::Uuid::from_u128(42);
+        let keyed = UuidBuildHasher { key: 1 }.hash_one(uuid);
+        assert_ne!(keyed, UuidBuildHasher { key: 2 }.hash_one(uuid));
+        assert_eq!(keyed, UuidBuildHasher { key: 1 }.hash_one(uuid));
+    }
+
+    #[test]
+    fn bucket_collision_rate_is_close_to_random() {
+        const UUIDS: usize = 100_000;
+        const BUCKETS: u64 = 1 << 20;
+
+        let buckets: HashSet<_> = (0..UUIDS).map(|_| hash(Uuid::new_v4()) % BUCKETS).collect();
+        let collisions = UUIDS - buckets.len();
+
+        // Uniformly random hashes collide about 4.6% of the time with this many buckets
+        assert!(collisions < UUIDS * 6 / 100, "{collisions} collisions");
+
+        // Sequential UUIDs, such as offline UUIDs from a counter in tests, must not all collide
+        let buckets: HashSet<_> = (0..UUIDS as u128)
+            .map(|i| hash(uuid::Uuid::from_u128(i)) % BUCKETS)
+            .collect();
+        assert!(UUIDS - buckets.len() < UUIDS * 6 / 100);
+    }
+}
@@ -0,0 +1,202 @@
+//! Hiding players from other players, such as staff watching a game. See [`Vanished`].
+//!
+//! A vanished player is hidden from every player without [`SeesVanished`]:
+//!
+//! - their channel is hidden, so the proxy stops sending their spawn, movement, and metadata to
+//!   other players, see
+//!   [`IoBuf::set_see_hidden_channels`](crate::net::IoBuf::set_see_hidden_channels),
+//! - they are removed from the player list of everyone but themselves, and no join message is
+//!   broadcast for them,
+//! - they do not press pressure plates and projectiles pass through them. They are still in the
+//!   [`SpatialIndex`], so lookups which should ignore them filter them out, such as with
+//!   [`SpatialIndex::first_ray_collision_where`].
+//!
+//! Players with [`SeesVanished`] still see vanished players, so staff can see each other while
+//! vanished. `hyperion-permission` gives it to moderators and admins. Vanished players are also
+//! [invisible](EntityFlags::INVISIBLE), so players which see them only see them as translucent if
+//! they are in the same [team](super::team::Team).
+//!
+//! Systems outside of the core which should ignore vanished players, such as mob targeting or
+//! leave messages, filter them out with `Without<Vanished>`.
+//!
+//! [`SpatialIndex`]: crate::spatial::SpatialIndex
+//! [`SpatialIndex::first_ray_collision_where`]: crate::spatial::SpatialIndex::first_ray_collision_where
+
+use std::borrow::Cow;
+
+use bevy_app::{App, Plugin};
+use bevy_ecs::{
+    component::Component,
+    entity::Entity,
+    lifecycle::{Add, Remove},
+    observer::On,
+    query::{Has, With},
+    system::{Commands, Query, Res},
+    world::World,
+};
+#[cfg(feature = "reflect")]
+use {bevy_ecs::reflect::ReflectComponent, bevy_reflect::Reflect};
+
+use crate::{
+    egress::player_join::{PlayerListActions, PlayerListEntry, PlayerListS2c},
+    net::{Compose, ConnectionId, SendResultExt},
+    simulation::{Uuid, metadata::entity::EntityFlags, packet_state},
+};
+
+/// Hides a player from the players without [`SeesVanished`]. See the
+/// [module documentation](self).
+#[derive(Component, Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+pub struct Vanished;
+
+/// Lets a player see [`Vanished`] players
+#[derive(Component, Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+pub struct SeesVanished;
+
+/// The player list packet which shows or hides the players in `uuids`
+fn listed_packet(
+    uuids: impl IntoIterator<Item = uuid::Uuid>,
+    listed: bool,
+) -> PlayerListS2c<'static> {
+    let entries = uuids
+        .into_iter()
+        .map(|player_uuid| PlayerListEntry {
+            player_uuid,
+            listed,
+            ..Default::default()
+        })
+        .collect::<Vec<_>>();
+
+    PlayerListS2c {
+        actions: PlayerListActions::default().with_update_listed(true),
+        entries: Cow::Owned(entries),
+    }
+}
+
+fn vanish(
+    vanished: On<'_, '_, Add, Vanished>,
+    compose: Res<'_, Compose>,
+    mut query: Query<'_, '_, (&Uuid, Option<&ConnectionId>, Option<&mut EntityFlags>)>,
+    viewers: Query<'_, '_, &ConnectionId, With<SeesVanished>>,
+) {
+    let Ok((uuid, connection_id, flags)) = query.get_mut(vanished.entity) else {
+        return;
+    };
+
+    // The player still sees themselves in the player list
+    let viewers = viewers
+        .iter()
+        .chain(connection_id)
+        .copied()
+        .collect::<Vec<_>>();
+    compose
+        .broadcast(&listed_packet([uuid.0], false))
+        .exclude_many(&viewers)
+        .send()
+        .unwrap();
+
+    if let Some(mut flags) = flags {
+        *flags |= EntityFlags::INVISIBLE;
+    }
+}
+
+fn reappear(reappeared: On<'_, '_, Remove, Vanished>, mut commands: Commands<'_, '_>) {
+    let entity = reappeared.entity;
+
+    // Vanished is also removed when the player is despawned, in which case there is nobody to
+    // show again
+    commands.queue(move |world: &mut World| {
+        let Ok(player) = world.get_entity(entity) else {
+            return;
+        };
+        if player.contains::<Vanished>() {
+            return;
+        }
+        let Some(&uuid) = player.get::<Uuid>() else {
+            return;
+        };
+
+        world
+            .resource::<Compose>()
+            .broadcast(&listed_packet([uuid.0], true))
+            .send()
+            .unwrap();
+
+        if let Some(mut flags) = world.get_mut::<EntityFlags>(entity) {
+            *flags &= !EntityFlags::INVISIBLE;
+        }
+    });
+}
+
+fn start_seeing_vanished(
+    added: On<'_, '_, Add, SeesVanished>,
+    compose: Res<'_, Compose>,
+    viewers: Query<'_, '_, (&ConnectionId, Has<packet_state::Play>)>,
+    vanished: Query<'_, '_, &Uuid, With<Vanished>>,
+) {
+    let Ok((&connection_id, playing)) = viewers.get(added.entity) else {
+        return;
+    };
+
+    compose
+        .io_buf()
+        .set_see_hidden_channels(connection_id, true);
+
+    // Players which are still joining are sent the vanished players with the player list
+    if playing && !vanished.is_empty() {
+        let packet = listed_packet(vanished.iter().map(|uuid| uuid.0), true);
+        compose
+            .unicast(&packet, connection_id)
+            .unwrap_or_disconnected();
+    }
+}
+
+fn stop_seeing_vanished(removed: On<'_, '_, Remove, SeesVanished>, mut commands: Commands<'_, '_>) {
+    let entity = removed.entity;
+
+    // SeesVanished is also removed when the player is despawned, in which case the connection is
+    // gone
+    commands.queue(move |world: &mut World| {
+        let Ok(viewer) = world.get_entity(entity) else {
+            return;
+        };
+        if viewer.contains::<SeesVanished>() {
+            return;
+        }
+        let Some(&connection_id) = viewer.get::<ConnectionId>() else {
+            return;
+        };
+        let playing = viewer.contains::<packet_state::Play>();
+
+        // A vanished viewer still sees themselves
+        let vanished = world
+            .query_filtered::<(Entity, &Uuid), With<Vanished>>()
+            .iter(world)
+            .filter(|&(vanished, _)| vanished != entity)
+            .map(|(_, uuid)| uuid.0)
+            .collect::<Vec<_>>();
+
+        let compose = world.resource::<Compose>();
+        compose
+            .io_buf()
+            .set_see_hidden_channels(connection_id, false);
+
+        if playing && !vanished.is_empty() {
+            compose
+                .unicast(&listed_packet(vanished, false), connection_id)
+                .unwrap_or_disconnected();
+        }
+    });
+}
+
+pub struct VanishPlugin;
+
+impl Plugin for VanishPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_observer(vanish);
+        app.add_observer(reappear);
+        app.add_observer(start_seeing_vanished);
+        app.add_observer(stop_seeing_vanished);
+    }
+}
@@ -0,0 +1,834 @@
+//! Vehicles which players ride, currently boats. See [`Riding`].
+//!
+//! Players place boats by using a boat item while looking at water or the ground, and get in by
+//! interacting with them. The first passenger of a boat controls it: its client simulates the
+//! boat and reports where it moved to with vehicle move packets, which are checked in
+//! [`VehicleController`] before the boat and its passengers are moved and the other players are
+//! sent the new position. Moves which take the boat further than it can travel in a tick, lift it
+//! out of the water or pass through blocks are rejected, and the controlling client is moved back.
+//!
+//! Players get out by sneaking, after which they are placed on a free block next to the vehicle.
+
+use bevy_app::{App, FixedUpdate, Plugin};
+use bevy_ecs::{
+    change_detection::DetectChangesMut,
+    component::Component,
+    entity::Entity,
+    lifecycle::{Insert, Remove},
+    message::MessageReader,
+    observer::On,
+    query::{With, Without},
+    schedule::{IntoScheduleConfigs, SystemSet},
+    system::{Commands, Local, Query, Res},
+    world::World,
+};
+use geometry::aabb::Aabb;
+use glam::{IVec3, Vec3};
+use hyperion_inventory::PlayerInventory;
+use rustc_hash::FxHashMap;
+use tracing::error;
+use valence_generated::{block::BlockKind, item::ItemKind};
+use valence_protocol::{
+    ByteAngle, GameMode, VarInt,
+    packets::play::{self as play_s2c, player_interact_entity_c2s::EntityInteraction},
+};
+use valence_server::ItemStack;
+#[cfg(feature = "reflect")]
+use {bevy_ecs::reflect::ReflectComponent, bevy_reflect::Reflect};
+
+use crate::{
+    Blocks, GameRng, ingress,
+    net::{Channel, Compose, SendResultExt, batch::BatchOrder},
+    simulation::{
+        EntitySize, MovementTracking, PendingTeleportation, Pitch, Position, Uuid, Velocity, Yaw,
+        entity_kind::EntityKind,
+        event,
+        handlers::{has_block_collision, is_grounded},
+        item_use::{hand_slot, held_item},
+        join::PlayerGameMode,
+        metadata::boat::{BoatType, LeftPaddleTurning, RightPaddleTurning},
+        minecraft_id::MinecraftIdRegistry,
+        packet::play,
+        world::{WorldBlocks, WorldId, Worlds},
+    },
+};
+
+/// How far away players can place boats, measured from their eyes
+const PLACE_REACH: f32 = 5.0;
+
+/// How far away players can get into vehicles
+const MOUNT_REACH: f32 = 6.0;
+
+/// The height of the eyes of a standing player above its feet
+const EYE_HEIGHT: f32 = 1.62;
+
+/// The furthest a boat can move horizontally during a tick
+const MAX_MOVE: f32 = 10.0;
+
+/// The furthest a boat can rise during a tick while it floats
+const MAX_RISE: f32 = 0.5;
+
+/// The furthest a boat can fall during a tick, a little more than the terminal velocity of
+/// falling entities
+const MAX_FALL: f32 = 4.0;
+
+/// The distance between the positions a boat is checked for collisions at along a move. This is
+/// shorter than the boat is wide and high, so the checked positions overlap and a move cannot
+/// skip over a block.
+const SWEEP_STEP: f32 = 0.25;
+
+/// The size of a boat
+pub const BOAT_SIZE: EntitySize = EntitySize {
+    half_width: 0.6875,
+    height: 0.5625,
+};
+
+/// The systems which move vehicles and their passengers. Systems which read the position of
+/// vehicles or their passengers should run after this.
+#[derive(SystemSet, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct VehicleController;
+
+/// The vehicle a player rides
+#[derive(Component, Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+#[relationship(relationship_target = Passengers)]
+pub struct Riding(pub Entity);
+
+/// The players riding a vehicle, in the order they got in. The first passenger controls the
+/// vehicle. This is kept up to date from [`Riding`].
+#[derive(Component, Default, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+#[relationship_target(relationship = Riding)]
+pub struct Passengers(Vec<Entity>);
+
+impl std::ops::Deref for Passengers {
+    type Target = [Entity];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl Passengers {
+    /// The passenger controlling the vehicle
+    #[must_use]
+    pub fn controller(&self) -> Option<Entity> {
+        self.0.first().copied()
+    }
+}
+
+/// The number of passengers a vehicle of `kind` has room for
+#[must_use]
+pub const fn max_passengers(kind: EntityKind) -> usize {
+    match kind {
+        EntityKind::Boat => 2,
+        EntityKind::ChestBoat => 1,
+        _ => 0,
+    }
+}
+
+/// The kind of boat placed by `item` and its [`BoatType`], or [`None`] if `item` is not a boat
+#[must_use]
+pub const fn boat_kind(item: ItemKind) -> Option<(EntityKind, i32)> {
+    let boat = match item {
+        ItemKind::OakBoat => (EntityKind::Boat, 0),
+        ItemKind::SpruceBoat => (EntityKind::Boat, 1),
+        ItemKind::BirchBoat => (EntityKind::Boat, 2),
+        ItemKind::JungleBoat => (EntityKind::Boat, 3),
+        ItemKind::AcaciaBoat => (EntityKind::Boat, 4),
+        ItemKind::CherryBoat => (EntityKind::Boat, 5),
+        ItemKind::DarkOakBoat => (EntityKind::Boat, 6),
+        ItemKind::MangroveBoat => (EntityKind::Boat, 7),
+        ItemKind::BambooRaft => (EntityKind::Boat, 8),
+        ItemKind::OakChestBoat => (EntityKind::ChestBoat, 0),
+        ItemKind::SpruceChestBoat => (EntityKind::ChestBoat, 1),
+        ItemKind::BirchChestBoat => (EntityKind::ChestBoat, 2),
+        ItemKind::JungleChestBoat => (EntityKind::ChestBoat, 3),
+        ItemKind::AcaciaChestBoat => (EntityKind::ChestBoat, 4),
+        ItemKind::CherryChestBoat => (EntityKind::ChestBoat, 5),
+        ItemKind::DarkOakChestBoat => (EntityKind::ChestBoat, 6),
+        ItemKind::MangroveChestBoat => (EntityKind::ChestBoat, 7),
+        ItemKind::BambooChestRaft => (EntityKind::ChestBoat, 8),
+        _ => return None,
+    };
+    Some(boat)
+}
+
+/// The direction a player with `yaw` and `pitch` looks in
+fn look_direction(yaw: f32, pitch: f32) -> Vec3 {
+    let (yaw, pitch) = (yaw.to_radians(), pitch.to_radians());
+    Vec3::new(
+        -yaw.sin() * pitch.cos(),
+        -pitch.sin(),
+        yaw.cos() * pitch.cos(),
+    )
+}
+
+fn is_water(blocks: &Blocks, position: IVec3) -> bool {
+    blocks
+        .get_block(position)
+        .is_some_and(|state| state.to_kind() == BlockKind::Water)
+}
+
+/// Whether a boat at `position` floats on water
+fn is_floating(position: Vec3, blocks: &Blocks) -> bool {
+    is_water(blocks, position.floor().as_ivec3())
+        || is_water(
+            blocks,
+            (position - Vec3::new(0.0, 0.1, 0.0)).floor().as_ivec3(),
+        )
+}
+
+/// Where a boat is placed by a player with its eyes at `eye` looking in `direction`: on top of
+/// the first water or solid block within reach. Returns [`None`] if there is no such block or the
+/// boat would not fit there.
+fn boat_placement(eye: Vec3, direction: Vec3, blocks: &Blocks) -> Option<Vec3> {
+    const STEP: f32 = 0.05;
+
+    let direction = direction.normalize_or_zero();
+    if direction == Vec3::ZERO {
+        return None;
+    }
+
+    let mut distance = 0.0;
+    while distance <= PLACE_REACH {
+        let point = eye + direction * distance;
+        let cell = point.floor().as_ivec3();
+        let solid = blocks.collides(&Aabb::new(point - 0.001, point + 0.001));
+
+        if solid || is_water(blocks, cell) {
+            #[expect(
+                clippy::cast_precision_loss,
+                reason = "block coordinates are far below 2^24"
+            )]
+            let position = Vec3::new(point.x, (cell.y + 1) as f32, point.z);
+            return (!has_block_collision(&position, BOAT_SIZE, blocks)).then_some(position);
+        }
+
+        distance += STEP;
+    }
+
+    None
+}
+
+/// How far a boat has moved during the current tick. The limits on how far boats move apply to
+/// the sum of all moves in a tick, so sending several vehicle moves does not make a boat faster.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+struct TickTravel {
+    horizontal: f32,
+    rise: f32,
+    fall: f32,
+}
+
+/// Whether a boat whose box is moved from `from` to `to` in a straight line passes through a
+/// block on the way
+fn sweeps_through_blocks(from: Vec3, to: Vec3, blocks: &Blocks) -> bool {
+    #[expect(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        reason = "the distance is limited by the maximum move of a tick"
+    )]
+    let steps = ((to - from).length() / SWEEP_STEP).ceil().max(1.0) as u32;
+
+    (1..=steps).any(|step| {
+        #[expect(clippy::cast_precision_loss, reason = "there are few steps")]
+        let position = from.lerp(to, step as f32 / steps as f32);
+        has_block_collision(&position, BOAT_SIZE, blocks)
+    })
+}
+
+/// Checks a vehicle move of a boat from `from` to `to` after it already moved `travelled` during
+/// the tick. Returns how far the boat moved during the tick including this move, or [`None`] if
+/// the move is not allowed.
+fn check_boat_move(
+    from: Vec3,
+    to: Vec3,
+    travelled: TickTravel,
+    blocks: &Blocks,
+) -> Option<TickTravel> {
+    if !to.is_finite() {
+        return None;
+    }
+
+    let delta = to - from;
+    let travelled = TickTravel {
+        horizontal: travelled.horizontal + delta.x.hypot(delta.z),
+        rise: travelled.rise + delta.y.max(0.0),
+        fall: travelled.fall + (-delta.y).max(0.0),
+    };
+
+    if travelled.horizontal > MAX_MOVE || travelled.fall > MAX_FALL {
+        return None;
+    }
+
+    // Boats only rise while they float up to the surface, so they cannot climb walls
+    if travelled.rise > MAX_RISE || (delta.y > 0.001 && !is_floating(from, blocks)) {
+        return None;
+    }
+
+    // Boats which are stuck in blocks, such as after the blocks were placed, may move out of them
+    if has_block_collision(&from, BOAT_SIZE, blocks) {
+        return Some(travelled);
+    }
+
+    (!sweeps_through_blocks(from, to, blocks)).then_some(travelled)
+}
+
+/// Where a player getting out of a vehicle at `vehicle` is placed: on a free block next to it,
+/// or on top of the vehicle if every block around it is taken
+fn dismount_position(vehicle: Vec3, vehicle_size: EntitySize, blocks: &Blocks) -> Vec3 {
+    let player = EntitySize::default();
+    let distance = vehicle_size.half_width + player.half_width + 0.1;
+    let offsets = [
+        (1.0, 0.0),
+        (-1.0, 0.0),
+        (0.0, 1.0),
+        (0.0, -1.0),
+        (1.0, 1.0),
+        (-1.0, 1.0),
+        (1.0, -1.0),
+        (-1.0, -1.0),
+    ];
+
+    for dy in [0.0, 1.0] {
+        for (dx, dz) in offsets {
+            let mut candidate =
+                vehicle + Vec3::new(dx, 0.0, dz).normalize() * distance + Vec3::Y * dy;
+            candidate.y = candidate.y.floor();
+
+            if !has_block_collision(&candidate, player, blocks) && is_grounded(&candidate, blocks) {
+                return candidate;
+            }
+        }
+    }
+
+    vehicle + Vec3::Y * vehicle_size.height
+}
+
+/// The packet which shows the passengers of `vehicle`
+pub(crate) fn passengers_packet(
+    vehicle: Entity,
+    passengers: &[Entity],
+    ids: &MinecraftIdRegistry,
+) -> play_s2c::EntityPassengersSetS2c {
+    play_s2c::EntityPassengersSetS2c {
+        entity_id: VarInt(ids.minecraft_id(vehicle)),
+        passengers: passengers
+            .iter()
+            .map(|&passenger| VarInt(ids.minecraft_id(passenger)))
+            .collect(),
+    }
+}
+
+/// Sends the current passengers of `vehicle` to the players which see it
+fn send_passengers(world: &World, vehicle: Entity) {
+    if world.get_entity(vehicle).is_err() {
+        return;
+    }
+
+    let passengers = world.get::<Passengers>(vehicle).map_or(&[][..], |p| &**p);
+    let packet = passengers_packet(vehicle, passengers, world.resource::<MinecraftIdRegistry>());
+    world
+        .resource::<Compose>()
+        .broadcast_channel(&packet, vehicle.into())
+        .send()
+        .unwrap();
+}
+
+fn place_boats(
+    mut events: MessageReader<'_, '_, event::ItemInteract>,
+    mut players: Query<
+        '_,
+        '_,
+        (
+            &mut PlayerInventory,
+            &Position,
+            &Yaw,
+            &Pitch,
+            Option<&WorldId>,
+            Option<&PlayerGameMode>,
+        ),
+    >,
+    blocks: WorldBlocks<'_>,
+    mut commands: Commands<'_, '_>,
+) {
+    for event in events.read() {
+        let Ok((mut inventory, position, yaw, pitch, world_id, game_mode)) =
+            players.get_mut(event.entity)
+        else {
+            continue;
+        };
+
+        let held = held_item(&inventory, event.hand);
+        let Some((kind, boat_type)) = boat_kind(held.item) else {
+            continue;
+        };
+
+        let Some(blocks) = blocks.get(world_id) else {
+            continue;
+        };
+
+        let eye = **position + Vec3::Y * EYE_HEIGHT;
+        let Some(boat_position) = boat_placement(eye, look_direction(**yaw, **pitch), blocks)
+        else {
+            continue;
+        };
+
+        if game_mode.is_none_or(|mode| mode.0 != GameMode::Creative) {
+            let slot = hand_slot(&inventory, event.hand);
+            let remaining = if held.count > 1 {
+                ItemStack::new(held.item, held.count - 1, held.nbt.clone())
+            } else {
+                ItemStack::EMPTY
+            };
+
+            if let Err(e) = inventory.set(slot, remaining) {
+                error!("failed to place boat: {e}");
+                continue;
+            }
+        }
+
+        let world_id = world_id.copied().unwrap_or(WorldId::PRIMARY);
+        let boat_yaw = **yaw;
+        commands.queue(move |world: &mut World| {
+            let uuid = Uuid::from_rng(world.resource_mut::<GameRng>().rng());
+            let mut boat = world.spawn((
+                kind,
+                uuid,
+                Position::from(boat_position),
+                Velocity::default(),
+                Yaw::new(boat_yaw),
+                Pitch::default(),
+                BOAT_SIZE,
+                world_id,
+                Channel,
+            ));
+
+            // The type is inserted separately, since inserting the EntityKind resets the metadata
+            // to its defaults
+            boat.insert(BoatType::new(VarInt(boat_type)));
+        });
+    }
+}
+
+fn mount_vehicles(
+    mut packets: MessageReader<'_, '_, play::PlayerInteractEntity>,
+    ids: Res<'_, MinecraftIdRegistry>,
+    players: Query<'_, '_, (&Position, Option<&WorldId>), Without<Riding>>,
+    vehicles: Query<
+        '_,
+        '_,
+        (
+            &EntityKind,
+            &Position,
+            Option<&WorldId>,
+            Option<&Passengers>,
+        ),
+    >,
+    mut commands: Commands<'_, '_>,
+) {
+    for packet in packets.read() {
+        let EntityInteraction::Interact(_) = packet.interact else {
+            continue;
+        };
+
+        let Ok(vehicle) = ids.entity(packet.entity_id.0) else {
+            continue;
+        };
+
+        let Ok((player_position, player_world)) = players.get(packet.sender()) else {
+            continue;
+        };
+
+        let Ok((&kind, vehicle_position, vehicle_world, passengers)) = vehicles.get(vehicle) else {
+            continue;
+        };
+
+        let room = max_passengers(kind);
+        if room == 0
+            || passengers.is_some_and(|passengers| passengers.len() >= room)
+            || player_world.copied().unwrap_or(WorldId::PRIMARY)
+                != vehicle_world.copied().unwrap_or(WorldId::PRIMARY)
+            || player_position.distance(**vehicle_position) > MOUNT_REACH
+        {
+            continue;
+        }
+
+        commands.entity(packet.sender()).insert(Riding(vehicle));
+    }
+}
+
+fn dismount_vehicles(
+    mut packets: MessageReader<'_, '_, play::PlayerInput>,
+    riders: Query<'_, '_, (), With<Riding>>,
+    mut commands: Commands<'_, '_>,
+) {
+    for packet in packets.read() {
+        if packet.flags.unmount() && riders.contains(packet.sender()) {
+            commands.entity(packet.sender()).remove::<Riding>();
+        }
+    }
+}
+
+fn get_in(
+    added: On<'_, '_, Insert, Riding>,
+    mut riders: Query<'_, '_, (&Riding, &mut Position, &mut MovementTracking)>,
+    vehicles: Query<'_, '_, &Position, Without<Riding>>,
+    mut commands: Commands<'_, '_>,
+) {
+    let Ok((&Riding(vehicle), mut position, mut tracking)) = riders.get_mut(added.entity) else {
+        return;
+    };
+
+    // The rider is carried along with the vehicle, which must not be mistaken for the rider
+    // moving on its own
+    if let Ok(vehicle_position) = vehicles.get(vehicle) {
+        **position = **vehicle_position;
+        tracking.last_tick_position = **vehicle_position;
+    }
+
+    commands.queue(move |world: &mut World| send_passengers(world, vehicle));
+}
+
+fn get_out(
+    removed: On<'_, '_, Remove, Riding>,
+    riders: Query<'_, '_, &Riding>,
+    mut commands: Commands<'_, '_>,
+) {
+    let rider = removed.entity;
+    let Ok(&Riding(vehicle)) = riders.get(rider) else {
+        return;
+    };
+
+    // Riding is also removed when the rider or the vehicle is despawned
+    commands.queue(move |world: &mut World| {
+        send_passengers(world, vehicle);
+
+        if world
+            .get_entity(rider)
+            .is_ok_and(|rider| rider.contains::<Riding>())
+        {
+            return;
+        }
+
+        let Some(&rider_position) = world.get::<Position>(rider) else {
+            return;
+        };
+        let vehicle_position = world
+            .get::<Position>(vehicle)
+            .map_or(*rider_position, |p| **p);
+        let vehicle_size = world
+            .get::<EntitySize>(vehicle)
+            .copied()
+            .unwrap_or(BOAT_SIZE);
+        let blocks = match world.get::<WorldId>(rider).copied().unwrap_or_default() {
+            WorldId::PRIMARY => Some(world.resource::<Blocks>()),
+            id => world.resource::<Worlds>().get(id),
+        };
+        let Some(blocks) = blocks else {
+            return;
+        };
+        let destination = dismount_position(vehicle_position, vehicle_size, blocks);
+
+        world
+            .entity_mut(rider)
+            .insert(PendingTeleportation::new(destination));
+    });
+}
+
+fn steer_boats(
+    mut packets: MessageReader<'_, '_, play::VehicleMove>,
+    compose: Res<'_, Compose>,
+    ids: Res<'_, MinecraftIdRegistry>,
+    blocks: WorldBlocks<'_>,
+    riders: Query<'_, '_, &Riding>,
+    mut vehicles: Query<
+        '_,
+        '_,
+        (
+            &EntityKind,
+            &Passengers,
+            &mut Position,
+            &mut Yaw,
+            &mut Velocity,
+            Option<&WorldId>,
+        ),
+        Without<Riding>,
+    >,
+    mut passengers: Query<'_, '_, (&mut Position, &mut MovementTracking), With<Riding>>,
+    mut travelled: Local<'_, FxHashMap<Entity, TickTravel>>,
+) {
+    travelled.clear();
+
+    for packet in packets.read() {
+        let Ok(&Riding(vehicle)) = riders.get(packet.sender()) else {
+            continue;
+        };
+
+        let Ok((kind, vehicle_passengers, mut position, mut yaw, mut velocity, world_id)) =
+            vehicles.get_mut(vehicle)
+        else {
+            continue;
+        };
+
+        if !matches!(kind, EntityKind::Boat | EntityKind::ChestBoat)
+            || vehicle_passengers.controller() != Some(packet.sender())
+        {
+            continue;
+        }
+
+        let Some(blocks) = blocks.get(world_id) else {
+            continue;
+        };
+
+        let destination = packet.position.as_vec3();
+        let travelled_before = travelled.get(&vehicle).copied().unwrap_or_default();
+
+        let Some(travelled_after) =
+            check_boat_move(**position, destination, travelled_before, blocks)
+        else {
+            let correction = play_s2c::VehicleMoveS2c {
+                position: position.as_dvec3(),
+                yaw: **yaw,
+                pitch: 0.0,
+            };
+            compose
+                .unicast(&correction, packet.connection_id())
+                .unwrap_or_disconnected();
+            continue;
+        };
+
+        travelled.insert(vehicle, travelled_after);
+        velocity.0 = destination - **position;
+        **position = destination;
+        **yaw = packet.yaw;
+
+        for &passenger in &**vehicle_passengers {
+            let Ok((mut passenger_position, mut tracking)) = passengers.get_mut(passenger) else {
+                continue;
+            };
+            **passenger_position = destination;
+            tracking.last_tick_position = destination;
+        }
+
+        let entity_id = VarInt(ids.minecraft_id(vehicle));
+        let packet_out = play_s2c::EntityPositionS2c {
+            entity_id,
+            position: destination.as_dvec3(),
+            yaw: ByteAngle::from_degrees(**yaw),
+            pitch: ByteAngle::from_degrees(0.0),
+            on_ground: false,
+        };
+
+        compose
+            .broadcast_channel(&packet_out, vehicle.into())
+            .exclude(packet.connection_id())
+            .batched(BatchOrder::Position)
+            .send()
+            .unwrap();
+    }
+}
+
+fn turn_paddles(
+    mut packets: MessageReader<'_, '_, play::BoatPaddleState>,
+    riders: Query<'_, '_, &Riding>,
+    mut vehicles: Query<'_, '_, (&Passengers, &mut LeftPaddleTurning, &mut RightPaddleTurning)>,
+) {
+    for packet in packets.read() {
+        let Ok(&Riding(vehicle)) = riders.get(packet.sender()) else {
+            continue;
+        };
+
+        let Ok((passengers, mut left, mut right)) = vehicles.get_mut(vehicle) else {
+            continue;
+        };
+
+        if passengers.controller() != Some(packet.sender()) {
+            continue;
+        }
+
+        left.set_if_neq(LeftPaddleTurning::new(packet.left_paddle_turning));
+        right.set_if_neq(RightPaddleTurning::new(packet.right_paddle_turning));
+    }
+}
+
+/// Lets players place, ride and steer boats. See the [module documentation](self).
+pub struct VehiclePlugin;
+
+impl Plugin for VehiclePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_observer(get_in);
+        app.add_observer(get_out);
+        app.configure_sets(FixedUpdate, VehicleController.after(ingress::decode::play));
+        app.add_systems(
+            FixedUpdate,
+            (
+                place_boats,
+                mount_vehicles,
+                dismount_vehicles,
+                steer_boats.in_set(VehicleController),
+                turn_paddles,
+            )
+                .after(ingress::decode::play),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::I16Vec2;
+    use valence_protocol::BlockState;
+
+    use super::*;
+    use crate::simulation::blocks::ChunkBounds;
+
+    /// A lake of water from y = 0 to y = 3 for x < 8, and solid ground up to y = 4 next to it
+    fn lake() -> Blocks {
+        Blocks::from_fn(
+            ChunkBounds::new(I16Vec2::new(-1, -1), I16Vec2::new(0, 0)),
+            |position| match (position.x, position.y) {
+                (_, y) if y < 0 => BlockState::STONE,
+                (x, y) if x < 8 && y < 3 => BlockState::WATER,
+                (x, y) if x >= 8 && y < 4 => BlockState::STONE,
+                _ => BlockState::AIR,
+            },
+        )
+    }
+
+    #[test]
+    fn boats_are_placed_on_water() {
+        let blocks = lake();
+
+        let eye = Vec3::new(0.5, 5.0, 0.5);
+        let down = look_direction(0.0, 90.0);
+        let placed = boat_placement(eye, down, &blocks).unwrap();
+        assert!(placed.abs_diff_eq(Vec3::new(0.5, 3.0, 0.5), 1e-4));
+
+        let far_away = Vec3::new(0.5, 20.0, 0.5);
+        assert_eq!(boat_placement(far_away, down, &blocks), None);
+    }
+
+    fn is_valid_boat_move(from: Vec3, to: Vec3, blocks: &Blocks) -> bool {
+        check_boat_move(from, to, TickTravel::default(), blocks).is_some()
+    }
+
+    #[test]
+    fn boats_cannot_climb_walls() {
+        let blocks = lake();
+        let afloat = Vec3::new(4.0, 2.9, 4.0);
+
+        assert!(is_valid_boat_move(
+            afloat,
+            Vec3::new(5.0, 2.9, 4.0),
+            &blocks
+        ));
+        assert!(is_valid_boat_move(
+            afloat,
+            Vec3::new(4.0, 3.0, 4.0),
+            &blocks
+        ));
+
+        // Into the shore and onto it
+        assert!(!is_valid_boat_move(
+            afloat,
+            Vec3::new(7.5, 2.9, 4.0),
+            &blocks
+        ));
+        assert!(!is_valid_boat_move(
+            afloat,
+            Vec3::new(8.5, 4.0, 4.0),
+            &blocks
+        ));
+
+        // Boats on land do not rise
+        let landed = Vec3::new(10.0, 4.0, 4.0);
+        assert!(!is_valid_boat_move(
+            landed,
+            Vec3::new(10.0, 4.4, 4.0),
+            &blocks
+        ));
+
+        // Teleporting
+        assert!(!is_valid_boat_move(
+            afloat,
+            Vec3::new(-8.0, 2.9, 4.0),
+            &blocks
+        ));
+    }
+
+    #[test]
+    fn boats_cannot_pass_through_walls() {
+        // A lake with a wall sticking out of the water at x = 6
+        let blocks = Blocks::from_fn(
+            ChunkBounds::new(I16Vec2::new(-1, -1), I16Vec2::new(0, 0)),
+            |position| match (position.x, position.y) {
+                (_, y) if y < 0 => BlockState::STONE,
+                (6, 3) => BlockState::STONE,
+                (_, y) if y < 3 => BlockState::WATER,
+                _ => BlockState::AIR,
+            },
+        );
+        let afloat = Vec3::new(4.0, 2.9, 4.0);
+
+        assert!(is_valid_boat_move(
+            afloat,
+            Vec3::new(4.0, 2.9, 12.0),
+            &blocks
+        ));
+        assert!(!is_valid_boat_move(
+            afloat,
+            Vec3::new(9.0, 2.9, 4.0),
+            &blocks
+        ));
+    }
+
+    #[test]
+    fn moves_are_limited_per_tick() {
+        let blocks = lake();
+        let start = Vec3::new(-10.0, 2.9, 4.0);
+        let step = Vec3::new(3.0, 0.0, 0.0);
+
+        // Three moves stay within the limit, but a fourth in the same tick does not
+        let mut position = start;
+        let mut travelled = TickTravel::default();
+        for _ in 0..3 {
+            travelled = check_boat_move(position, position + step, travelled, &blocks).unwrap();
+            position += step;
+        }
+        assert_eq!(
+            check_boat_move(position, position + step, travelled, &blocks),
+            None
+        );
+        assert!(
+            check_boat_move(position, position + step, TickTravel::default(), &blocks).is_some()
+        );
+
+        // Falling is limited as well
+        let in_the_air = Vec3::new(4.0, 10.0, 4.0);
+        assert!(is_valid_boat_move(
+            in_the_air,
+            in_the_air - Vec3::Y * 3.0,
+            &blocks
+        ));
+        assert!(!is_valid_boat_move(
+            in_the_air,
+            in_the_air - Vec3::Y * 6.0,
+            &blocks
+        ));
+    }
+
+    #[test]
+    fn passengers_get_out_next_to_the_vehicle() {
+        let blocks = lake();
+        let landed = Vec3::new(10.0, 4.0, 4.0);
+
+        let destination = dismount_position(landed, BOAT_SIZE, &blocks);
+        assert!(destination.distance(landed) < 1.5);
+        assert!(!has_block_collision(
+            &destination,
+            EntitySize::default(),
+            &blocks
+        ));
+        assert!(is_grounded(&destination, &blocks));
+    }
+}
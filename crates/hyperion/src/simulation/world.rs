@@ -0,0 +1,263 @@
+//! Support for running several worlds in one server.
+//!
+//! The [`Blocks`] resource is the primary world ([`WorldId::PRIMARY`]). Additional worlds are stored
+//! in the [`Worlds`] resource. Every player has a [`WorldId`] component which decides which world
+//! the player receives chunks, block updates, and local broadcasts from. Use [`transfer_player`] to
+//! move a player to a different world.
+
+use std::borrow::Cow;
+
+use bevy_ecs::{
+    component::Component,
+    entity::Entity,
+    resource::Resource,
+    system::{Res, ResMut, SystemParam},
+    world::World,
+};
+use glam::Vec3;
+use rustc_hash::FxHashMap;
+use tracing::error;
+use valence_protocol::{
+    GameMode, Ident, VarInt, game_mode::OptGameMode, packets::play::PlayerRespawnS2c,
+};
+#[cfg(feature = "reflect")]
+use {
+    bevy_ecs::reflect::{ReflectComponent, ReflectResource},
+    bevy_reflect::Reflect,
+};
+
+use crate::{
+    egress::sync_chunks::ChunkSendQueue,
+    net::{Compose, ConnectionId},
+    simulation::{
+        ChunkPosition, Flight, FlyingSpeed, MovementTracking, PendingTeleportation, Position,
+        blocks::{Blocks, fake::FakeBlocks},
+        join::PlayerGameMode,
+        registry::RegistryCodec,
+    },
+};
+
+/// Identifies the world that a player or other world-owning entity is in.
+#[derive(
+    Component, Debug, Copy, Clone, PartialEq, Eq, Hash, Default, PartialOrd, Ord
+)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+pub struct WorldId(u16);
+
+impl WorldId {
+    /// The world backed by the [`Blocks`] resource
+    pub const PRIMARY: Self = Self(0);
+
+    #[must_use]
+    pub const fn inner(self) -> u16 {
+        self.0
+    }
+
+    /// The inverse of [`WorldId::inner`], for ids which were saved before
+    #[must_use]
+    pub(crate) const fn from_inner(id: u16) -> Self {
+        Self(id)
+    }
+
+    /// The dimension name sent to the client. Every world needs a distinct name so that the client
+    /// discards its chunks when moving between worlds.
+    #[must_use]
+    pub fn dimension_name(self) -> Ident<Cow<'static, str>> {
+        let name = if self == Self::PRIMARY {
+            Cow::Borrowed("minecraft:overworld")
+        } else {
+            Cow::Owned(format!("hyperion:world_{}", self.0))
+        };
+
+        Ident::new(name).expect("world dimension names are always valid identifiers")
+    }
+}
+
+/// Secondary worlds. The primary world is stored in the [`Blocks`] resource.
+#[derive(Resource, Default)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Resource))]
+pub struct Worlds {
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    worlds: FxHashMap<WorldId, Blocks>,
+    next_id: u16,
+}
+
+impl Worlds {
+    /// Adds a new world and returns its id.
+    ///
+    /// # Panics
+    /// If more than [`u16::MAX`] worlds are created
+    pub fn insert(&mut self, blocks: Blocks) -> WorldId {
+        self.next_id = self.next_id.checked_add(1).expect("too many worlds");
+        let id = WorldId(self.next_id);
+        self.worlds.insert(id, blocks);
+        id
+    }
+
+    /// Removes a world. Players in this world must be transferred elsewhere beforehand.
+    pub fn remove(&mut self, id: WorldId) -> Option<Blocks> {
+        self.worlds.remove(&id)
+    }
+
+    #[must_use]
+    pub fn get(&self, id: WorldId) -> Option<&Blocks> {
+        self.worlds.get(&id)
+    }
+
+    #[must_use]
+    pub fn get_mut(&mut self, id: WorldId) -> Option<&mut Blocks> {
+        self.worlds.get_mut(&id)
+    }
+
+    #[must_use]
+    pub fn contains(&self, id: WorldId) -> bool {
+        self.worlds.contains_key(&id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (WorldId, &Blocks)> {
+        self.worlds.iter().map(|(&id, blocks)| (id, blocks))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (WorldId, &mut Blocks)> {
+        self.worlds.iter_mut().map(|(&id, blocks)| (id, blocks))
+    }
+}
+
+/// Read-only access to the blocks of every world, including the primary world.
+#[derive(SystemParam)]
+pub struct WorldBlocks<'w> {
+    primary: Res<'w, Blocks>,
+    worlds: Res<'w, Worlds>,
+}
+
+impl WorldBlocks<'_> {
+    /// Returns the blocks of the given world. A missing [`WorldId`] refers to the primary world.
+    #[must_use]
+    pub fn get(&self, id: Option<&WorldId>) -> Option<&Blocks> {
+        match id.copied().unwrap_or_default() {
+            WorldId::PRIMARY => Some(&self.primary),
+            id => self.worlds.get(id),
+        }
+    }
+}
+
+/// Mutable access to the blocks of every world, including the primary world.
+#[derive(SystemParam)]
+pub struct WorldBlocksMut<'w> {
+    primary: ResMut<'w, Blocks>,
+    worlds: ResMut<'w, Worlds>,
+}
+
+impl WorldBlocksMut<'_> {
+    /// Returns the blocks of the given world. A missing [`WorldId`] refers to the primary world.
+    #[must_use]
+    pub fn get_mut(&mut self, id: Option<&WorldId>) -> Option<&mut Blocks> {
+        match id.copied().unwrap_or_default() {
+            WorldId::PRIMARY => Some(&mut self.primary),
+            id => self.worlds.get_mut(id),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum TransferError {
+    #[error("world {0:?} does not exist")]
+    UnknownWorld(WorldId),
+
+    #[error("entity {0} is not a connected player")]
+    NotAPlayer(Entity),
+}
+
+/// Moves a player to `target` at `position`.
+///
+/// This sends a respawn packet so that the client unloads all chunks of the previous world, then
+/// resends chunks, abilities, and the player position for the new world. This can be called from
+/// a system through `commands.queue`.
+pub fn transfer_player(
+    world: &mut World,
+    player: Entity,
+    target: WorldId,
+    position: Vec3,
+) -> Result<(), TransferError> {
+    if target != WorldId::PRIMARY && !world.resource::<Worlds>().contains(target) {
+        return Err(TransferError::UnknownWorld(target));
+    }
+
+    let Some(&connection_id) = world.get::<ConnectionId>(player) else {
+        return Err(TransferError::NotAPlayer(player));
+    };
+
+    let current = world.get::<WorldId>(player).copied().unwrap_or_default();
+
+    if current != target {
+        // The client takes the game mode from the respawn packet
+        let game_mode = world
+            .get::<PlayerGameMode>(player)
+            .map_or(GameMode::Survival, |game_mode| game_mode.0);
+
+        let pkt = PlayerRespawnS2c {
+            dimension_type_name: world
+                .resource::<RegistryCodec>()
+                .join_dimension_type()
+                .clone(),
+            dimension_name: target.dimension_name(),
+            hashed_seed: 0,
+            game_mode,
+            previous_game_mode: OptGameMode(Some(game_mode)),
+            is_debug: false,
+            is_flat: false,
+            copy_metadata: true,
+            last_death_location: None,
+            portal_cooldown: VarInt::default(),
+        };
+
+        if let Err(e) = world.resource::<Compose>().unicast(&pkt, connection_id) {
+            error!("failed to send respawn packet for world transfer: {e}");
+        }
+    }
+
+    let mut entity = world.entity_mut(player);
+
+    if let Some(mut tracking) = entity.get_mut::<MovementTracking>() {
+        tracking.last_tick_position = position;
+        tracking.fall_start_y = position.y;
+    }
+
+    entity.insert((target, Position::from(position)));
+
+    if current != target {
+        // Forces all chunks of the new world to be sent again
+        entity.insert(ChunkPosition::default());
+        entity.remove::<FakeBlocks>();
+
+        if let Some(mut queue) = entity.get_mut::<ChunkSendQueue>() {
+            queue.reset();
+        }
+
+        // The client resets its abilities on respawn
+        if let (Some(&flight), Some(&flying_speed)) =
+            (entity.get::<Flight>(), entity.get::<FlyingSpeed>())
+        {
+            entity.insert((flight, flying_speed));
+        }
+    }
+
+    entity.insert(PendingTeleportation::new(position));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dimension_names_are_distinct() {
+        assert_eq!(
+            WorldId::PRIMARY.dimension_name().as_str(),
+            "minecraft:overworld"
+        );
+        assert_eq!(WorldId(1).dimension_name().as_str(), "hyperion:world_1");
+        assert_ne!(WorldId(1).dimension_name(), WorldId(2).dimension_name());
+    }
+}
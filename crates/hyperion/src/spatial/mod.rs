@@ -4,6 +4,7 @@ use bevy_ecs::{
     entity::Entity,
     query::With,
     resource::Resource,
+    schedule::IntoScheduleConfigs,
     system::{Query, ResMut},
 };
 use geometry::{aabb::Aabb, ray::Ray};
@@ -16,36 +17,46 @@ use {
     bevy_reflect::Reflect,
 };
 
-use super::simulation::{
-    EntitySize, Position, aabb,
-    blocks::{Blocks, RayCollision},
+use super::{
+    activity::server_active,
+    simulation::{
+        EntitySize, Position, aabb,
+        blocks::{Blocks, RayCollision},
+    },
+    timings::{TickTimings, TimedSection},
 };
 
 pub struct SpatialPlugin;
 
+/// The entities in the [`SpatialIndex`].
+///
+/// [`Vanished`](crate::simulation::vanish::Vanished) entities are indexed as well, so that staff
+/// tooling can still find them. Lookups which should ignore them, such as projectiles and pressure
+/// plates, filter them out themselves.
+pub type Indexed = (With<Position>, With<EntitySize>, With<Spatial>);
+
 #[derive(Resource, Debug, Default)]
 #[cfg_attr(feature = "reflect", derive(Reflect), reflect(Resource))]
 pub struct SpatialIndex {
-    /// The bounding boxes of all entities with the [`Spatial`] component
+    /// The bounding boxes of all entities matching [`Indexed`]
     #[cfg_attr(feature = "reflect", reflect(ignore))]
     query: bvh_region::Bvh<Entity>,
 }
 
+/// The first entity or block hit by `ray`. The ray passes through entities for which `accept`
+/// returns `false`, such as the owner of a projectile.
 #[must_use]
 pub fn get_first_collision(
     ray: Ray,
     index: &SpatialIndex,
     blocks: &Blocks,
     query: Query<'_, '_, (&Position, &EntitySize)>,
-    owner: Option<Entity>,
+    accept: impl Fn(Entity) -> bool,
 ) -> Option<Either<Entity, RayCollision>> {
     // Check for collisions with entities
-    let entity = index.first_ray_collision(ray, query);
+    let entity = index.first_ray_collision_where(ray, query, accept);
     let block = blocks.first_collision(ray);
 
-    // make sure the entity is not the owner
-    let entity = entity.filter(|(entity, _)| owner.is_none_or(|owner| *entity != owner));
-
     // check which one is closest to the Ray don't forget to account for entity size
     entity.map_or_else(
         || block.map(Either::Right),
@@ -105,18 +116,35 @@ impl SpatialIndex {
         &self,
         ray: Ray,
         query: Query<'a, 'a, (&Position, &EntitySize)>,
+    ) -> Option<(Entity, NotNan<f32>)> {
+        self.first_ray_collision_where(ray, query, |_| true)
+    }
+
+    /// Like [`SpatialIndex::first_ray_collision`], but the ray passes through entities for which
+    /// `accept` returns `false`
+    #[must_use]
+    pub fn first_ray_collision_where<'a>(
+        &self,
+        ray: Ray,
+        query: Query<'a, 'a, (&Position, &EntitySize)>,
+        accept: impl Fn(Entity) -> bool,
     ) -> Option<(Entity, NotNan<f32>)> {
         let get_aabb = get_aabb_func(query);
-        let (entity, distance) = self.query.first_ray_collision(ray, get_aabb)?;
+        let (entity, distance) = self
+            .query
+            .first_ray_collision_where(ray, get_aabb, |entity| accept(*entity))?;
         Some((*entity, distance))
     }
 }
 
 fn recalculate_spatial_index(
     mut index: ResMut<'_, SpatialIndex>,
-    entity_query: Query<'_, '_, Entity, (With<Position>, With<EntitySize>, With<Spatial>)>,
+    entity_query: Query<'_, '_, Entity, Indexed>,
     component_query: Query<'_, '_, (&Position, &EntitySize)>,
+    timings: Res<'_, TickTimings>,
 ) {
+    let _timing = timings.time(TimedSection::SpatialIndex);
+
     // todo(perf): re-use allocations?
     let all_entities = entity_query.iter().collect();
     let get_aabb = get_aabb_func(component_query);
@@ -132,6 +160,9 @@ pub struct Spatial;
 impl Plugin for SpatialPlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(SpatialIndex::default());
-        app.add_systems(FixedPreUpdate, recalculate_spatial_index);
+        app.add_systems(
+            FixedPreUpdate,
+            recalculate_spatial_index.run_if(server_active),
+        );
     }
 }
@@ -0,0 +1,315 @@
+//! A persistent record of privileged actions for moderation.
+
+use std::{
+    ops::Range,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bevy_app::{App, Plugin};
+use bevy_ecs::resource::Resource;
+#[cfg(feature = "reflect")]
+use bevy_reflect::Reflect;
+use heed::{Database, RwTxn, byteorder::BigEndian, types};
+use rkyv::util::AlignedVec;
+use tracing::warn;
+use uuid::Uuid;
+
+use super::LocalDb;
+
+/// Keys are allocated in steps of this many per millisecond, so several entries recorded in the
+/// same millisecond keep their order.
+const KEYS_PER_MILLISECOND: u64 = 1000;
+
+/// The default maximum number of entries. With typical entries this uses a few megabytes of the
+/// [`LocalDb`] map.
+pub const DEFAULT_MAX_AUDIT_ENTRIES: u64 = 20_000;
+
+/// The kind of privileged action that was performed.
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    rkyv::Archive,
+    rkyv::Serialize,
+    rkyv::Deserialize
+)]
+pub enum AuditAction {
+    Ban,
+    Unban,
+    Kick,
+    Teleport,
+    GroupChange,
+    Command,
+    /// An action defined by game code
+    Other(String),
+}
+
+/// A single privileged action.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditEntry {
+    /// The time the action was performed at
+    pub timestamp: SystemTime,
+    /// The player who performed the action. This is [`None`] for the console or automated
+    /// actions.
+    pub actor: Option<Uuid>,
+    pub action: AuditAction,
+    /// The player the action was performed on
+    pub target: Option<Uuid>,
+    /// Free-form information such as the reason of a ban
+    pub detail: String,
+}
+
+impl AuditEntry {
+    /// Creates an entry for an action performed now.
+    #[must_use]
+    pub fn now(
+        actor: Option<Uuid>,
+        action: AuditAction,
+        target: Option<Uuid>,
+        detail: impl Into<String>,
+    ) -> Self {
+        Self {
+            timestamp: SystemTime::now(),
+            actor,
+            action,
+            target,
+            detail: detail.into(),
+        }
+    }
+
+    fn involves(&self, uuid: Uuid) -> bool {
+        self.actor == Some(uuid) || self.target == Some(uuid)
+    }
+}
+
+/// Encoded form of [`AuditEntry`]
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+struct SavedEntry {
+    timestamp_ms: u64,
+    actor: Option<u128>,
+    action: AuditAction,
+    target: Option<u128>,
+    detail: String,
+}
+
+impl From<&AuditEntry> for SavedEntry {
+    fn from(entry: &AuditEntry) -> Self {
+        Self {
+            timestamp_ms: millis_since_epoch(entry.timestamp),
+            actor: entry.actor.map(Uuid::as_u128),
+            action: entry.action.clone(),
+            target: entry.target.map(Uuid::as_u128),
+            detail: entry.detail.clone(),
+        }
+    }
+}
+
+impl From<SavedEntry> for AuditEntry {
+    fn from(entry: SavedEntry) -> Self {
+        Self {
+            timestamp: UNIX_EPOCH + std::time::Duration::from_millis(entry.timestamp_ms),
+            actor: entry.actor.map(Uuid::from_u128),
+            action: entry.action,
+            target: entry.target.map(Uuid::from_u128),
+            detail: entry.detail,
+        }
+    }
+}
+
+pub(super) fn millis_since_epoch(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .ok()
+        .and_then(|duration| u64::try_from(duration.as_millis()).ok())
+        .unwrap_or_default()
+}
+
+/// Returns the first key for entries recorded at `timestamp_ms`
+const fn key_at(timestamp_ms: u64) -> u64 {
+    timestamp_ms.saturating_mul(KEYS_PER_MILLISECOND)
+}
+
+/// Returns the key for a new entry. Keys always increase, even if the clock goes backwards.
+fn next_key(last: Option<u64>, timestamp_ms: u64) -> u64 {
+    let key = key_at(timestamp_ms);
+    match last {
+        Some(last) if last >= key => last + 1,
+        _ => key,
+    }
+}
+
+fn encode(entry: &AuditEntry) -> Vec<u8> {
+    rkyv::to_bytes::<rkyv::rancor::Error>(&SavedEntry::from(entry))
+        .unwrap()
+        .to_vec()
+}
+
+fn decode(bytes: &[u8]) -> Result<AuditEntry, rkyv::rancor::Error> {
+    // Database entries are not guaranteed to be aligned
+    let mut aligned = AlignedVec::<16>::with_capacity(bytes.len());
+    aligned.extend_from_slice(bytes);
+
+    Ok(rkyv::from_bytes::<SavedEntry, rkyv::rancor::Error>(&aligned)?.into())
+}
+
+/// An append-only log of privileged actions stored in the [`LocalDb`].
+///
+/// Entries are keyed by the time they were recorded at, so they can be scanned in order. Once the
+/// log holds more than its maximum number of entries, the oldest entries are removed.
+#[derive(Resource, Debug, Clone)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(opaque))]
+pub struct AuditLog {
+    db: LocalDb,
+    // Big endian keys are sorted by value in LMDB
+    entries: Database<types::U64<BigEndian>, types::Bytes>,
+    max_entries: u64,
+}
+
+impl AuditLog {
+    /// Creates a new [`AuditLog`] from a given [`LocalDb`] which keeps at most `max_entries`.
+    pub fn new(db: &LocalDb, max_entries: u64) -> anyhow::Result<Self> {
+        let entries = db.write(|wtxn| db.create_database(wtxn, Some("audit-log")))?;
+
+        Ok(Self {
+            db: db.clone(),
+            entries,
+            max_entries,
+        })
+    }
+
+    /// Appends an entry to the log.
+    pub fn record(&self, entry: AuditEntry) -> anyhow::Result<()> {
+        let bytes = encode(&entry);
+        let timestamp_ms = millis_since_epoch(entry.timestamp);
+
+        self.db.write(|wtxn| {
+            let last = self.entries.last(wtxn)?.map(|(key, _)| key);
+            let key = next_key(last, timestamp_ms);
+
+            self.entries.put(wtxn, &key, &bytes)?;
+            self.prune(wtxn)
+        })
+    }
+
+    /// Removes the oldest entries until at most `max_entries` remain
+    fn prune(&self, wtxn: &mut RwTxn<'_>) -> heed::Result<()> {
+        let len = self.entries.len(wtxn)?;
+        if len <= self.max_entries {
+            return Ok(());
+        }
+
+        let excess = len - self.max_entries;
+        let cutoff = self
+            .entries
+            .iter(wtxn)?
+            .nth(usize::try_from(excess - 1).unwrap_or(usize::MAX))
+            .transpose()?
+            .map(|(key, _)| key);
+
+        if let Some(cutoff) = cutoff {
+            self.entries.delete_range(wtxn, &(..=cutoff))?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the `count` most recent entries, newest first.
+    pub fn recent(&self, count: usize) -> anyhow::Result<Vec<AuditEntry>> {
+        let rtxn = self.db.read_txn()?;
+
+        self.entries
+            .rev_iter(&rtxn)?
+            .take(count)
+            .filter_map(|result| decode_result(result.map(|(_, bytes)| bytes)))
+            .collect()
+    }
+
+    /// Returns all entries in which the player was the actor or the target, oldest first.
+    pub fn entries_for(&self, uuid: Uuid) -> anyhow::Result<Vec<AuditEntry>> {
+        let rtxn = self.db.read_txn()?;
+
+        self.entries
+            .iter(&rtxn)?
+            .filter_map(|result| decode_result(result.map(|(_, bytes)| bytes)))
+            .filter(|entry| !matches!(entry, Ok(entry) if !entry.involves(uuid)))
+            .collect()
+    }
+
+    /// Returns all entries recorded within `range`, oldest first.
+    pub fn between(&self, range: Range<SystemTime>) -> anyhow::Result<Vec<AuditEntry>> {
+        let rtxn = self.db.read_txn()?;
+
+        let keys = key_at(millis_since_epoch(range.start))..key_at(millis_since_epoch(range.end));
+
+        self.entries
+            .range(&rtxn, &keys)?
+            .filter_map(|result| decode_result(result.map(|(_, bytes)| bytes)))
+            .collect()
+    }
+}
+
+/// Skips entries which cannot be decoded rather than failing the whole query
+fn decode_result(result: heed::Result<&[u8]>) -> Option<anyhow::Result<AuditEntry>> {
+    match result {
+        Ok(bytes) => match decode(bytes) {
+            Ok(entry) => Some(Ok(entry)),
+            Err(e) => {
+                warn!("skipping corrupt audit log entry: {e}");
+                None
+            }
+        },
+        Err(e) => Some(Err(e.into())),
+    }
+}
+
+/// Adds the [`AuditLog`] resource. This must be added after [`crate::HyperionCore`].
+pub struct AuditPlugin {
+    /// The maximum number of entries kept in the log
+    pub max_entries: u64,
+}
+
+impl Default for AuditPlugin {
+    fn default() -> Self {
+        Self {
+            max_entries: DEFAULT_MAX_AUDIT_ENTRIES,
+        }
+    }
+}
+
+impl Plugin for AuditPlugin {
+    fn build(&self, app: &mut App) {
+        let db = app.world().resource::<LocalDb>();
+        let log = AuditLog::new(db, self.max_entries).expect("failed to load audit log");
+        app.insert_resource(log);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_are_monotonic() {
+        assert_eq!(next_key(None, 5), 5000);
+        assert_eq!(next_key(Some(4999), 5), 5000);
+        assert_eq!(next_key(Some(5000), 5), 5001);
+        // The clock went backwards
+        assert_eq!(next_key(Some(9000), 5), 9001);
+    }
+
+    #[test]
+    fn entry_round_trip() {
+        let entry = AuditEntry {
+            timestamp: UNIX_EPOCH + std::time::Duration::from_millis(1_700_000_000_123),
+            actor: Some(Uuid::from_u128(1)),
+            action: AuditAction::Other("reset arena".to_owned()),
+            target: None,
+            detail: "arena 3".to_owned(),
+        };
+
+        let decoded = decode(&encode(&entry)).unwrap();
+        assert_eq!(decoded, entry);
+        assert!(decoded.involves(Uuid::from_u128(1)));
+        assert!(!decoded.involves(Uuid::from_u128(2)));
+    }
+}
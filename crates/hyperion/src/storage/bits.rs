@@ -112,6 +112,11 @@ impl BitStorage {
         self.data
     }
 
+    #[must_use]
+    pub fn data(&self) -> &[u64] {
+        &self.data
+    }
+
     /// Create a new `BitStorage` with the given number of bits per entry.
     /// `size` is the number of entries in the `BitStorage`.
     pub fn new(bits: usize, size: usize, data: Option<Vec<u64>>) -> Result<Self, BitStorageError> {
@@ -1,38 +1,188 @@
 //! Constructs for connecting and working with a `Heed` database.
 
-use std::path::Path;
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, PoisonError, RwLock, RwLockReadGuard},
+};
 
 use bevy_ecs::resource::Resource;
 #[cfg(feature = "reflect")]
 use bevy_reflect::Reflect;
 use byteorder::NativeEndian;
-use heed::{Database, Env, EnvOpenOptions, types};
+use heed::{Database, Env, EnvOpenOptions, MdbError, RoTxn, RwTxn, WithTls, types};
+use tracing::warn;
 use uuid::Uuid;
 
-use crate::simulation::skin::{ArchivedPlayerSkin, PlayerSkin};
+use crate::simulation::{
+    advancement::AdvancementProgress,
+    persistence::{PlayerSnapshot, SnapshotError},
+    skin::{ArchivedPlayerSkin, PlayerSkin},
+};
 
 /// A wrapper around a `Heed` database
 #[derive(Resource, Debug, Clone)]
 #[cfg_attr(feature = "reflect", derive(Reflect), reflect(opaque))]
 pub struct LocalDb {
     env: Env,
+    /// Held shared by every transaction and exclusively while the memory map is resized, because
+    /// resizing with open transactions is undefined behavior
+    resize: Arc<RwLock<()>>,
 }
 
-impl LocalDb {
-    /// Creates a new [`LocalDb`]
-    pub fn new() -> anyhow::Result<Self> {
-        let path = Path::new("db").join("heed.mdb");
+/// A read transaction of a [`LocalDb`], which prevents the memory map from being resized while it
+/// is open.
+pub struct ReadTxn<'a> {
+    // Dropped before the guard, so the transaction is closed when the map can be resized
+    txn: RoTxn<'a, WithTls>,
+    _resize: RwLockReadGuard<'a, ()>,
+}
+
+impl<'a> std::ops::Deref for ReadTxn<'a> {
+    type Target = RoTxn<'a, WithTls>;
 
-        std::fs::create_dir_all(&path)?;
+    fn deref(&self) -> &Self::Target {
+        &self.txn
+    }
+}
+
+/// Builder for a [`LocalDb`] with custom environment options
+#[derive(Debug, Clone)]
+#[must_use]
+pub struct LocalDbBuilder {
+    path: PathBuf,
+    map_size: usize,
+    max_dbs: u32,
+}
+
+impl Default for LocalDbBuilder {
+    fn default() -> Self {
+        Self {
+            path: Path::new("db").join("heed.mdb"),
+            map_size: 10 * 1024 * 1024, // 10MB
+            max_dbs: 16, // todo: why is this needed/configurable? ideally would be infinite...
+        }
+    }
+}
+
+impl LocalDbBuilder {
+    /// The initial size of the memory map in bytes. This must be a multiple of the OS page size.
+    /// The map grows automatically when it is full.
+    pub const fn map_size(mut self, map_size: usize) -> Self {
+        self.map_size = map_size;
+        self
+    }
+
+    /// The maximum number of named databases
+    pub const fn max_dbs(mut self, max_dbs: u32) -> Self {
+        self.max_dbs = max_dbs;
+        self
+    }
+
+    /// The directory containing the database files
+    pub fn path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.path = path.into();
+        self
+    }
+
+    /// Opens the [`LocalDb`], creating it if needed
+    pub fn build(self) -> anyhow::Result<LocalDb> {
+        std::fs::create_dir_all(&self.path)?;
 
         let env = unsafe {
             EnvOpenOptions::new()
-                .map_size(10 * 1024 * 1024) // 10MB
-                .max_dbs(8) // todo: why is this needed/configurable? ideally would be infinite...
-                .open(&path)?
+                .map_size(self.map_size)
+                .max_dbs(self.max_dbs)
+                .open(&self.path)?
         };
 
-        Ok(Self { env })
+        Ok(LocalDb {
+            env,
+            resize: Arc::default(),
+        })
+    }
+}
+
+/// Space usage of a [`LocalDb`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalDbStats {
+    /// Bytes used by pages which are in use
+    pub used_bytes: u64,
+    /// The current size of the memory map
+    pub total_bytes: u64,
+}
+
+impl LocalDb {
+    /// Creates a new [`LocalDb`] with the default options in `db/heed.mdb`
+    pub fn new() -> anyhow::Result<Self> {
+        Self::builder().build()
+    }
+
+    pub fn builder() -> LocalDbBuilder {
+        LocalDbBuilder::default()
+    }
+
+    /// Returns how much of the memory map is in use
+    pub fn stats(&self) -> anyhow::Result<LocalDbStats> {
+        Ok(LocalDbStats {
+            used_bytes: self.env.non_free_pages_size()?,
+            total_bytes: u64::try_from(self.env.info().map_size)?,
+        })
+    }
+
+    /// Opens a read transaction. Use this instead of [`Env::read_txn`], which does not stop the
+    /// memory map from being resized while the transaction is open.
+    pub fn read_txn(&self) -> heed::Result<ReadTxn<'_>> {
+        let resize = self.resize.read().unwrap_or_else(PoisonError::into_inner);
+        Ok(ReadTxn {
+            txn: self.env.read_txn()?,
+            _resize: resize,
+        })
+    }
+
+    /// Runs `f` in a write transaction and commits it.
+    ///
+    /// If the memory map is full, the transaction is aborted, the map size is doubled, and `f` is
+    /// run again in a new transaction.
+    pub fn write<T>(
+        &self,
+        mut f: impl FnMut(&mut RwTxn<'_>) -> heed::Result<T>,
+    ) -> anyhow::Result<T> {
+        match self.try_write(&mut f) {
+            Err(heed::Error::Mdb(MdbError::MapFull)) => {
+                self.grow()?;
+                Ok(self.try_write(&mut f)?)
+            }
+            result => Ok(result?),
+        }
+    }
+
+    fn try_write<T>(
+        &self,
+        f: &mut impl FnMut(&mut RwTxn<'_>) -> heed::Result<T>,
+    ) -> heed::Result<T> {
+        let _resize = self.resize.read().unwrap_or_else(PoisonError::into_inner);
+        let mut wtxn = self.env.write_txn()?;
+        let value = f(&mut wtxn)?;
+        wtxn.commit()?;
+        Ok(value)
+    }
+
+    /// Doubles the size of the memory map. This waits until all open transactions are closed.
+    fn grow(&self) -> anyhow::Result<()> {
+        let _resize = self.resize.write().unwrap_or_else(PoisonError::into_inner);
+
+        let map_size = self.env.info().map_size;
+        let new_size = map_size
+            .checked_mul(2)
+            .ok_or_else(|| anyhow::anyhow!("LocalDb map size cannot grow past {map_size}"))?;
+
+        warn!("LocalDb map is full, growing it from {map_size} to {new_size} bytes");
+
+        // SAFETY: Every transaction holds the resize lock, which is held exclusively here, so no
+        // transaction is open in this process.
+        unsafe { self.env.resize(new_size)? };
+
+        Ok(())
     }
 }
 
@@ -48,23 +198,17 @@ impl std::ops::Deref for LocalDb {
 #[derive(Resource, Debug, Clone)]
 #[cfg_attr(feature = "reflect", derive(Reflect), reflect(opaque))]
 pub struct SkinHandler {
-    env: Env,
+    db: LocalDb,
     skins: Database<types::U128<NativeEndian>, types::Bytes>,
 }
 
 impl SkinHandler {
     /// Creates a new [`SkinHandler`] from a given [`LocalDb`].
     pub fn new(db: &LocalDb) -> anyhow::Result<Self> {
-        // We open the default unnamed database
-        let skins = {
-            let mut wtxn = db.write_txn()?;
-            let db = db.create_database(&mut wtxn, Some("uuid-to-skins"))?;
-            wtxn.commit()?;
-            db
-        };
+        let skins = db.write(|wtxn| db.create_database(wtxn, Some("uuid-to-skins")))?;
 
         Ok(Self {
-            env: db.env.clone(),
+            db: db.clone(),
             skins,
         })
     }
@@ -75,7 +219,7 @@ impl SkinHandler {
 
         let uuid = uuid.as_u128();
 
-        let rtxn = self.env.read_txn()?;
+        let rtxn = self.db.read_txn()?;
         let skin = self.skins.get(&rtxn, &uuid);
 
         let Some(skin) = skin? else {
@@ -91,13 +235,179 @@ impl SkinHandler {
     pub fn insert(&self, uuid: Uuid, skin: &PlayerSkin) -> anyhow::Result<()> {
         let uuid = uuid.as_u128();
 
-        let mut wtxn = self.env.write_txn()?;
-
         let skin = rkyv::to_bytes::<rkyv::rancor::Error>(skin).unwrap();
 
-        self.skins.put(&mut wtxn, &uuid, &skin)?;
-        wtxn.commit()?;
+        self.db.write(|wtxn| self.skins.put(wtxn, &uuid, &skin))
+    }
+}
 
-        Ok(())
+/// A handler for saved player data
+#[derive(Resource, Debug, Clone)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(opaque))]
+pub struct PlayerDataHandler {
+    db: LocalDb,
+    players: Database<types::U128<NativeEndian>, types::Bytes>,
+}
+
+impl PlayerDataHandler {
+    /// Creates a new [`PlayerDataHandler`] from a given [`LocalDb`].
+    pub fn new(db: &LocalDb) -> anyhow::Result<Self> {
+        let players = db.write(|wtxn| db.create_database(wtxn, Some("uuid-to-player-data")))?;
+
+        Ok(Self {
+            db: db.clone(),
+            players,
+        })
+    }
+
+    /// Finds the [`PlayerSnapshot`] of a player by their UUID.
+    ///
+    /// The inner result is an error if the saved entry exists but could not be decoded.
+    pub fn find(
+        &self,
+        uuid: Uuid,
+    ) -> anyhow::Result<Option<Result<PlayerSnapshot, SnapshotError>>> {
+        let uuid = uuid.as_u128();
+
+        let rtxn = self.db.read_txn()?;
+        let Some(bytes) = self.players.get(&rtxn, &uuid)? else {
+            return Ok(None);
+        };
+
+        Ok(Some(PlayerSnapshot::from_bytes(bytes)))
+    }
+
+    /// Inserts the [`PlayerSnapshot`]s of several players in a single transaction.
+    pub fn insert_many<'a>(
+        &self,
+        snapshots: impl IntoIterator<Item = (Uuid, &'a PlayerSnapshot)>,
+    ) -> anyhow::Result<()> {
+        let entries = snapshots
+            .into_iter()
+            .map(|(uuid, snapshot)| (uuid.as_u128(), snapshot.to_bytes()))
+            .collect::<Vec<_>>();
+
+        self.db.write(|wtxn| {
+            for (uuid, bytes) in &entries {
+                self.players.put(wtxn, uuid, bytes)?;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// A handler for the advancement progress of players
+#[derive(Resource, Debug, Clone)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(opaque))]
+pub struct AdvancementHandler {
+    db: LocalDb,
+    progress: Database<types::U128<NativeEndian>, types::Bytes>,
+}
+
+impl AdvancementHandler {
+    /// Creates a new [`AdvancementHandler`] from a given [`LocalDb`].
+    pub fn new(db: &LocalDb) -> anyhow::Result<Self> {
+        let progress = db.write(|wtxn| db.create_database(wtxn, Some("uuid-to-advancements")))?;
+
+        Ok(Self {
+            db: db.clone(),
+            progress,
+        })
+    }
+
+    /// Finds the [`AdvancementProgress`] of a player by their UUID.
+    pub fn find(&self, uuid: Uuid) -> anyhow::Result<Option<AdvancementProgress>> {
+        let uuid = uuid.as_u128();
+
+        let rtxn = self.db.read_txn()?;
+        let Some(bytes) = self.progress.get(&rtxn, &uuid)? else {
+            return Ok(None);
+        };
+
+        Ok(Some(AdvancementProgress::from_bytes(bytes)?))
+    }
+
+    /// Inserts the [`AdvancementProgress`] of a player into the database.
+    pub fn insert(&self, uuid: Uuid, progress: &AdvancementProgress) -> anyhow::Result<()> {
+        let bytes = progress.to_bytes();
+
+        self.db
+            .write(|wtxn| self.progress.put(wtxn, &uuid.as_u128(), &bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn map_grows_when_full() {
+        let path = std::env::temp_dir().join(format!("hyperion-localdb-{}", fastrand::u64(..)));
+        let db = LocalDb::builder()
+            .map_size(64 * 1024)
+            .path(&path)
+            .build()
+            .unwrap();
+        let skins = SkinHandler::new(&db).unwrap();
+
+        let skin = |i: u128| PlayerSkin {
+            textures: format!("{i}").repeat(2048 / format!("{i}").len()),
+            signature: format!("signature-{i}"),
+        };
+
+        for i in 0..128 {
+            skins.insert(Uuid::from_u128(i), &skin(i)).unwrap();
+        }
+
+        for i in 0..128 {
+            let found = skins.find(Uuid::from_u128(i)).unwrap().unwrap();
+            assert_eq!(found.textures, skin(i).textures);
+            assert_eq!(found.signature, skin(i).signature);
+        }
+
+        let stats = db.stats().unwrap();
+        assert!(stats.total_bytes > 64 * 1024);
+        assert!(stats.used_bytes <= stats.total_bytes);
+
+        drop(skins);
+        drop(db);
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn map_does_not_grow_while_reading() {
+        let path = std::env::temp_dir().join(format!("hyperion-localdb-{}", fastrand::u64(..)));
+        let db = LocalDb::builder()
+            .map_size(64 * 1024)
+            .path(&path)
+            .build()
+            .unwrap();
+        let skins = SkinHandler::new(&db).unwrap();
+
+        let rtxn = db.read_txn().unwrap();
+
+        let writer = std::thread::spawn({
+            let skins = skins.clone();
+            move || {
+                for i in 0..128 {
+                    let skin = PlayerSkin {
+                        textures: "a".repeat(2048),
+                        signature: format!("signature-{i}"),
+                    };
+                    skins.insert(Uuid::from_u128(i), &skin).unwrap();
+                }
+            }
+        });
+
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        assert_eq!(db.info().map_size, 64 * 1024);
+
+        drop(rtxn);
+        writer.join().unwrap();
+        assert!(db.info().map_size > 64 * 1024);
+
+        drop(skins);
+        drop(db);
+        std::fs::remove_dir_all(path).unwrap();
     }
 }
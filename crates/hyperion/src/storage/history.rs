@@ -0,0 +1,485 @@
+//! A persistent record of which players have joined before and when.
+//!
+//! Looking up and updating a player happens in memory in a single step, so two joins of the same
+//! player can never both see it as new. The updated records are then committed to the
+//! [`LocalDb`] in batches off the tick thread by [`PlayerHistory::commit_in_background`], since a
+//! commit per join would stall the tick when hundreds of players join at once.
+//!
+//! A join is durable once its batch has been committed, which is usually within a tick. If the
+//! server crashes before that, the join is forgotten together with everything else the player did
+//! since, so the player is treated as new again on the next join. Anything which must only happen
+//! once per player should wait for [`PlayerHistory::is_committed`].
+//!
+//! The name each player last joined with is recorded as well, so players can be looked up by name
+//! with [`PlayerHistory::find_by_name`] while they are offline.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bevy_ecs::resource::Resource;
+#[cfg(feature = "reflect")]
+use bevy_reflect::Reflect;
+use byteorder::NativeEndian;
+use heed::{Database, types};
+use tracing::error;
+use uuid::Uuid;
+
+use super::{LocalDb, audit::millis_since_epoch};
+use crate::AsyncRuntime;
+
+const RECORD_LEN: usize = 3 * size_of::<u64>();
+
+/// What is known about a player who has joined before.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlayerRecord {
+    /// The time the player joined for the first time
+    pub first_joined: SystemTime,
+    /// The time the player joined or left at, whichever was last
+    pub last_seen: SystemTime,
+    /// The number of times the player has joined, including the current join
+    pub joins: u64,
+}
+
+impl PlayerRecord {
+    /// Whether the join this record was returned for is the first join of the player
+    #[must_use]
+    pub const fn is_first_join(&self) -> bool {
+        self.joins == 1
+    }
+
+    fn joined(previous: Option<Self>, now: SystemTime) -> Self {
+        match previous {
+            Some(previous) => Self {
+                last_seen: now,
+                joins: previous.joins.saturating_add(1),
+                ..previous
+            },
+            None => Self {
+                first_joined: now,
+                last_seen: now,
+                joins: 1,
+            },
+        }
+    }
+
+    fn encode(&self) -> [u8; RECORD_LEN] {
+        let mut bytes = [0; RECORD_LEN];
+        let fields = [
+            millis_since_epoch(self.first_joined),
+            millis_since_epoch(self.last_seen),
+            self.joins,
+        ];
+        for (chunk, field) in bytes.chunks_exact_mut(size_of::<u64>()).zip(fields) {
+            chunk.copy_from_slice(&field.to_le_bytes());
+        }
+        bytes
+    }
+
+    fn decode(bytes: &[u8]) -> anyhow::Result<Self> {
+        let Ok(bytes) = <[u8; RECORD_LEN]>::try_from(bytes) else {
+            anyhow::bail!(
+                "player record has {} bytes instead of {RECORD_LEN}",
+                bytes.len()
+            );
+        };
+
+        let field = |index: usize| {
+            let start = index * size_of::<u64>();
+            let mut field = [0; size_of::<u64>()];
+            field.copy_from_slice(&bytes[start..start + size_of::<u64>()]);
+            u64::from_le_bytes(field)
+        };
+
+        Ok(Self {
+            first_joined: UNIX_EPOCH + Duration::from_millis(field(0)),
+            last_seen: UNIX_EPOCH + Duration::from_millis(field(1)),
+            joins: field(2),
+        })
+    }
+}
+
+/// A record which has not been committed yet
+#[derive(Debug, Clone)]
+struct Pending {
+    record: PlayerRecord,
+    /// The name the player joined with, if they joined since the record was last committed
+    name: Option<Arc<str>>,
+    /// Whether the record changed since it was last taken for a commit
+    dirty: bool,
+}
+
+/// A changed record taken for a commit
+type BatchEntry = (u128, PlayerRecord, Option<Arc<str>>);
+
+/// The join history of every player, stored in the [`LocalDb`]. See the
+/// [module documentation](self).
+#[derive(Resource, Debug, Clone)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(opaque))]
+pub struct PlayerHistory {
+    db: LocalDb,
+    players: Database<types::U128<NativeEndian>, types::Bytes>,
+    /// The UUID of the player who last joined with each name
+    names: Database<types::Str, types::U128<NativeEndian>>,
+    /// Records which are newer than the database. Records stay here until they have been
+    /// committed, so lookups never miss a join which is being committed.
+    pending: Arc<Mutex<HashMap<u128, Pending>>>,
+    /// Whether a commit is running in the background. Only one runs at a time so that an older
+    /// batch can never overwrite a newer one.
+    committing: Arc<AtomicBool>,
+}
+
+impl PlayerHistory {
+    /// Creates a new [`PlayerHistory`] from a given [`LocalDb`].
+    pub fn new(db: &LocalDb) -> anyhow::Result<Self> {
+        let players = db.write(|wtxn| db.create_database(wtxn, Some("seen-players")))?;
+        let names = db.write(|wtxn| db.create_database(wtxn, Some("seen-player-names")))?;
+
+        Ok(Self {
+            db: db.clone(),
+            players,
+            names,
+            pending: Arc::default(),
+            committing: Arc::default(),
+        })
+    }
+
+    fn find_committed(&self, uuid: u128) -> anyhow::Result<Option<PlayerRecord>> {
+        let rtxn = self.db.read_txn()?;
+        self.players
+            .get(&rtxn, &uuid)?
+            .map(PlayerRecord::decode)
+            .transpose()
+    }
+
+    /// Finds the [`PlayerRecord`] of a player by their UUID. Returns [`None`] if the player has
+    /// never joined.
+    pub fn find(&self, uuid: Uuid) -> anyhow::Result<Option<PlayerRecord>> {
+        let uuid = uuid.as_u128();
+
+        if let Some(pending) = self.pending.lock().unwrap().get(&uuid) {
+            return Ok(Some(pending.record));
+        }
+
+        self.find_committed(uuid)
+    }
+
+    /// Finds the UUID and [`PlayerRecord`] of the player who last joined with `name`. Returns
+    /// [`None`] if nobody has joined with this name.
+    pub fn find_by_name(&self, name: &str) -> anyhow::Result<Option<(Uuid, PlayerRecord)>> {
+        let pending = self
+            .pending
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(_, pending)| pending.name.as_deref() == Some(name))
+            .map(|(&uuid, pending)| (uuid, pending.record));
+
+        if let Some((uuid, record)) = pending {
+            return Ok(Some((Uuid::from_u128(uuid), record)));
+        }
+
+        let rtxn = self.db.read_txn()?;
+        let Some(uuid) = self.names.get(&rtxn, name)? else {
+            return Ok(None);
+        };
+        drop(rtxn);
+
+        Ok(self
+            .find(Uuid::from_u128(uuid))?
+            .map(|record| (Uuid::from_u128(uuid), record)))
+    }
+
+    /// Whether every change to the record of `uuid` has been committed
+    #[must_use]
+    pub fn is_committed(&self, uuid: Uuid) -> bool {
+        !self.pending.lock().unwrap().contains_key(&uuid.as_u128())
+    }
+
+    /// Applies `f` to the current record of `uuid` and stores the record it returns to be
+    /// committed, together with `name` if it is set
+    fn update<T>(
+        &self,
+        uuid: Uuid,
+        name: Option<&str>,
+        f: impl FnOnce(Option<PlayerRecord>) -> (Option<PlayerRecord>, T),
+    ) -> anyhow::Result<T> {
+        let uuid = uuid.as_u128();
+
+        // The lock is held across the lookup so the check and the update are one step
+        let mut pending = self.pending.lock().unwrap();
+        let current = match pending.get(&uuid) {
+            Some(pending) => Some(pending.record),
+            None => self.find_committed(uuid)?,
+        };
+
+        let (record, value) = f(current);
+        if let Some(record) = record {
+            let entry = pending.entry(uuid).or_insert(Pending {
+                record,
+                name: None,
+                dirty: true,
+            });
+            entry.record = record;
+            entry.dirty = true;
+            if let Some(name) = name {
+                entry.name = Some(name.into());
+            }
+        }
+
+        Ok(value)
+    }
+
+    /// Records that a player joined with `name` at `now` and returns their updated record, which
+    /// tells whether this is their first join.
+    pub fn record_join(
+        &self,
+        uuid: Uuid,
+        name: &str,
+        now: SystemTime,
+    ) -> anyhow::Result<PlayerRecord> {
+        self.update(uuid, Some(name), |previous| {
+            let record = PlayerRecord::joined(previous, now);
+            (Some(record), record)
+        })
+    }
+
+    /// Records that a player was last seen at `now`. Players who have never joined are ignored.
+    pub fn record_seen(&self, uuid: Uuid, now: SystemTime) -> anyhow::Result<()> {
+        self.update(uuid, None, |previous| {
+            let record = previous.map(|previous| PlayerRecord {
+                last_seen: now,
+                ..previous
+            });
+            (record, ())
+        })
+    }
+
+    /// The number of records which have not been committed yet
+    #[must_use]
+    pub fn uncommitted(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+
+    /// Takes the records which changed since the last batch
+    fn take_batch(&self) -> Vec<BatchEntry> {
+        self.pending
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .filter(|(_, pending)| pending.dirty)
+            .map(|(&uuid, pending)| {
+                pending.dirty = false;
+                (uuid, pending.record, pending.name.clone())
+            })
+            .collect()
+    }
+
+    fn write_batch(&self, batch: &[BatchEntry]) -> anyhow::Result<()> {
+        let result = self.db.write(|wtxn| {
+            for (uuid, record, name) in batch {
+                self.players.put(wtxn, uuid, &record.encode())?;
+                if let Some(name) = name {
+                    self.names.put(wtxn, name, uuid)?;
+                }
+            }
+            Ok(())
+        });
+
+        let mut pending = self.pending.lock().unwrap();
+        for (uuid, ..) in batch {
+            let Some(entry) = pending.get_mut(uuid) else {
+                continue;
+            };
+
+            // Records which changed while the batch was written are kept for the next batch
+            if entry.dirty {
+                continue;
+            }
+
+            if result.is_ok() {
+                pending.remove(uuid);
+            } else {
+                entry.dirty = true;
+            }
+        }
+
+        result
+    }
+
+    /// Commits all changed records in a single transaction on the current thread. Returns the
+    /// number of committed records.
+    pub fn commit(&self) -> anyhow::Result<usize> {
+        let batch = self.take_batch();
+        if batch.is_empty() {
+            return Ok(0);
+        }
+
+        self.write_batch(&batch)?;
+        Ok(batch.len())
+    }
+
+    /// Commits all changed records in a single transaction on a blocking thread of `runtime`.
+    /// Does nothing if the previous commit is still running, in which case the records are
+    /// committed in a later batch.
+    pub fn commit_in_background(&self, runtime: &AsyncRuntime) {
+        if self.committing.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        let batch = self.take_batch();
+        if batch.is_empty() {
+            self.committing.store(false, Ordering::Release);
+            return;
+        }
+
+        let history = self.clone();
+        runtime.spawn_blocking(move || {
+            if let Err(e) = history.write_batch(&batch) {
+                error!("failed to commit {} player records: {e}", batch.len());
+            }
+            history.committing.store(false, Ordering::Release);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open(path: &std::path::Path) -> (LocalDb, PlayerHistory) {
+        let db = LocalDb::builder().path(path).build().unwrap();
+        let history = PlayerHistory::new(&db).unwrap();
+        (db, history)
+    }
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn record_round_trip() {
+        let record = PlayerRecord {
+            first_joined: UNIX_EPOCH + Duration::from_millis(1_700_000_000_123),
+            last_seen: UNIX_EPOCH + Duration::from_millis(1_700_000_500_456),
+            joins: 7,
+        };
+
+        assert_eq!(PlayerRecord::decode(&record.encode()).unwrap(), record);
+        assert!(PlayerRecord::decode(&[0; 5]).is_err());
+    }
+
+    #[test]
+    fn joins_are_counted_and_committed() {
+        let path = std::env::temp_dir().join(format!("hyperion-history-{}", fastrand::u64(..)));
+        let (db, history) = open(&path);
+        let uuid = Uuid::from_u128(1);
+
+        assert_eq!(history.find(uuid).unwrap(), None);
+
+        let first = history.record_join(uuid, "alice", at(100)).unwrap();
+        assert!(first.is_first_join());
+
+        // The join is seen before it has been committed
+        let second = history.record_join(uuid, "alice", at(200)).unwrap();
+        assert!(!second.is_first_join());
+        assert_eq!(second.first_joined, at(100));
+        assert_eq!(history.uncommitted(), 1);
+
+        history.record_seen(uuid, at(300)).unwrap();
+        history.record_seen(Uuid::from_u128(2), at(300)).unwrap();
+        assert_eq!(history.find(Uuid::from_u128(2)).unwrap(), None);
+
+        assert_eq!(history.commit().unwrap(), 1);
+        assert_eq!(history.uncommitted(), 0);
+        assert_eq!(history.commit().unwrap(), 0);
+
+        drop(history);
+        drop(db);
+
+        let (db, history) = open(&path);
+        assert_eq!(
+            history.find(uuid).unwrap(),
+            Some(PlayerRecord {
+                first_joined: at(100),
+                last_seen: at(300),
+                joins: 2,
+            })
+        );
+
+        drop(history);
+        drop(db);
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn players_are_found_by_the_name_they_last_joined_with() {
+        let path = std::env::temp_dir().join(format!("hyperion-history-{}", fastrand::u64(..)));
+        let (db, history) = open(&path);
+        let uuid = Uuid::from_u128(1);
+
+        history.record_join(uuid, "alice", at(100)).unwrap();
+        assert!(!history.is_committed(uuid));
+        assert_eq!(
+            history.find_by_name("alice").unwrap().map(|(uuid, _)| uuid),
+            Some(uuid)
+        );
+        assert_eq!(history.find_by_name("bob").unwrap(), None);
+
+        history.commit().unwrap();
+        assert!(history.is_committed(uuid));
+
+        // The player renames, and someone else takes the old name
+        history.record_join(uuid, "carol", at(200)).unwrap();
+        history
+            .record_join(Uuid::from_u128(2), "alice", at(300))
+            .unwrap();
+        history.commit().unwrap();
+
+        drop(history);
+        drop(db);
+
+        let (db, history) = open(&path);
+        let (found, record) = history.find_by_name("carol").unwrap().unwrap();
+        assert_eq!(found, uuid);
+        assert_eq!(record.joins, 2);
+        assert_eq!(
+            history.find_by_name("alice").unwrap().map(|(uuid, _)| uuid),
+            Some(Uuid::from_u128(2))
+        );
+
+        drop(history);
+        drop(db);
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn changes_during_a_commit_are_kept() {
+        let path = std::env::temp_dir().join(format!("hyperion-history-{}", fastrand::u64(..)));
+        let (db, history) = open(&path);
+        let uuid = Uuid::from_u128(1);
+
+        history.record_join(uuid, "alice", at(100)).unwrap();
+        let batch = history.take_batch();
+
+        // The player leaves while the batch is being written
+        history.record_seen(uuid, at(150)).unwrap();
+        history.write_batch(&batch).unwrap();
+
+        assert_eq!(history.uncommitted(), 1);
+        assert_eq!(history.find(uuid).unwrap().unwrap().last_seen, at(150));
+
+        assert_eq!(history.commit().unwrap(), 1);
+        assert_eq!(history.uncommitted(), 0);
+        assert_eq!(history.find(uuid).unwrap().unwrap().last_seen, at(150));
+
+        drop(history);
+        drop(db);
+        std::fs::remove_dir_all(path).unwrap();
+    }
+}
@@ -1,7 +1,13 @@
+mod audit;
 mod bits;
 mod buf;
 mod db;
+mod history;
+mod region;
 
+pub use audit::*;
 pub use bits::*;
 pub use buf::*;
 pub use db::*;
+pub use history::*;
+pub use region::*;
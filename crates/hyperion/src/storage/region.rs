@@ -0,0 +1,256 @@
+//! The definitions of protected regions, see
+//! [`ProtectedRegions`](crate::simulation::region::ProtectedRegions).
+
+use std::collections::BTreeMap;
+
+use glam::IVec3;
+use heed::{Database, types};
+
+use super::LocalDb;
+
+const REGION_LEN: usize = 7 * size_of::<i32>() + 3 * 2 + 1;
+
+/// A set of groups, such as those allowed to build in a region. Groups are numbered from 0 to 7,
+/// see [`RegionGroup`](crate::simulation::region::RegionGroup).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct GroupSet(u8);
+
+impl GroupSet {
+    /// Every group
+    pub const ALL: Self = Self(u8::MAX);
+    /// The number of groups a set can contain
+    pub const GROUPS: u8 = 8;
+    /// No group
+    pub const NONE: Self = Self(0);
+
+    fn bit(group: u8) -> u8 {
+        1_u8.checked_shl(u32::from(group)).unwrap_or(0)
+    }
+
+    #[must_use]
+    pub fn contains(self, group: u8) -> bool {
+        self.0 & Self::bit(group) != 0
+    }
+
+    #[must_use]
+    pub fn with(self, group: u8) -> Self {
+        Self(self.0 | Self::bit(group))
+    }
+
+    #[must_use]
+    pub fn without(self, group: u8) -> Self {
+        Self(self.0 & !Self::bit(group))
+    }
+
+    /// The groups in the set, in ascending order
+    pub fn iter(self) -> impl Iterator<Item = u8> {
+        (0..Self::GROUPS).filter(move |&group| self.contains(group))
+    }
+}
+
+/// What a region allows. Flags which are [`None`] are left to the regions with a lower priority,
+/// and are allowed if no region sets them.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct RegionFlags {
+    /// The groups which may place and break blocks
+    pub build: Option<GroupSet>,
+    /// The groups which may use doors, buttons and levers
+    pub interact: Option<GroupSet>,
+    /// The groups which may walk into the region
+    pub entry: Option<GroupSet>,
+    /// Whether players may damage each other
+    pub pvp: Option<bool>,
+}
+
+/// A box of blocks with flags
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Region {
+    /// The corner with the smallest coordinates
+    pub min: IVec3,
+    /// The corner with the largest coordinates. Both corners are part of the region.
+    pub max: IVec3,
+    /// Where regions overlap, the flags of the region with the highest priority apply
+    pub priority: i32,
+    pub flags: RegionFlags,
+}
+
+impl Region {
+    /// A region between two opposite corners, which sets no flags
+    #[must_use]
+    pub fn new(a: IVec3, b: IVec3) -> Self {
+        Self {
+            min: a.min(b),
+            max: a.max(b),
+            priority: 0,
+            flags: RegionFlags::default(),
+        }
+    }
+
+    /// Whether the block at `position` is part of the region
+    #[must_use]
+    pub fn contains(&self, position: IVec3) -> bool {
+        position.cmpge(self.min).all() && position.cmple(self.max).all()
+    }
+
+    fn encode(&self) -> [u8; REGION_LEN] {
+        let mut bytes = [0; REGION_LEN];
+        let numbers = [
+            self.min.x,
+            self.min.y,
+            self.min.z,
+            self.max.x,
+            self.max.y,
+            self.max.z,
+            self.priority,
+        ];
+
+        let (numbers_bytes, flags_bytes) = bytes.split_at_mut(numbers.len() * size_of::<i32>());
+        for (chunk, number) in numbers_bytes
+            .chunks_exact_mut(size_of::<i32>())
+            .zip(numbers)
+        {
+            chunk.copy_from_slice(&number.to_le_bytes());
+        }
+
+        let groups = [self.flags.build, self.flags.interact, self.flags.entry];
+        for (chunk, groups) in flags_bytes.chunks_exact_mut(2).zip(groups) {
+            if let Some(groups) = groups {
+                chunk.copy_from_slice(&[1, groups.0]);
+            }
+        }
+
+        bytes[REGION_LEN - 1] = match self.flags.pvp {
+            None => 0,
+            Some(false) => 1,
+            Some(true) => 2,
+        };
+
+        bytes
+    }
+
+    fn decode(bytes: &[u8]) -> anyhow::Result<Self> {
+        let Ok(bytes) = <[u8; REGION_LEN]>::try_from(bytes) else {
+            anyhow::bail!("region has {} bytes instead of {REGION_LEN}", bytes.len());
+        };
+
+        let number = |index: usize| {
+            let start = index * size_of::<i32>();
+            let mut number = [0; size_of::<i32>()];
+            number.copy_from_slice(&bytes[start..start + size_of::<i32>()]);
+            i32::from_le_bytes(number)
+        };
+
+        let groups = |index: usize| -> anyhow::Result<Option<GroupSet>> {
+            let start = 7 * size_of::<i32>() + index * 2;
+            match bytes[start] {
+                0 => Ok(None),
+                1 => Ok(Some(GroupSet(bytes[start + 1]))),
+                other => anyhow::bail!("invalid region flag {other}"),
+            }
+        };
+
+        let pvp = match bytes[REGION_LEN - 1] {
+            0 => None,
+            1 => Some(false),
+            2 => Some(true),
+            other => anyhow::bail!("invalid region pvp flag {other}"),
+        };
+
+        Ok(Self {
+            min: IVec3::new(number(0), number(1), number(2)),
+            max: IVec3::new(number(3), number(4), number(5)),
+            priority: number(6),
+            flags: RegionFlags {
+                build: groups(0)?,
+                interact: groups(1)?,
+                entry: groups(2)?,
+                pvp,
+            },
+        })
+    }
+}
+
+/// Stores [`Region`]s in the [`LocalDb`] by their ID
+#[derive(Debug, Clone)]
+pub struct RegionStorage {
+    db: LocalDb,
+    regions: Database<types::Str, types::Bytes>,
+}
+
+impl RegionStorage {
+    pub fn new(db: &LocalDb) -> anyhow::Result<Self> {
+        let regions = db.write(|wtxn| db.create_database(wtxn, Some("regions")))?;
+
+        Ok(Self {
+            db: db.clone(),
+            regions,
+        })
+    }
+
+    /// Loads every stored region
+    pub fn load(&self) -> anyhow::Result<BTreeMap<String, Region>> {
+        let rtxn = self.db.read_txn()?;
+        let mut regions = BTreeMap::new();
+
+        for entry in self.regions.iter(&rtxn)? {
+            let (id, bytes) = entry?;
+            regions.insert(id.to_owned(), Region::decode(bytes)?);
+        }
+
+        Ok(regions)
+    }
+
+    /// Stores `region` under `id`, replacing the region stored under it before
+    pub fn put(&self, id: &str, region: &Region) -> anyhow::Result<()> {
+        self.db
+            .write(|wtxn| self.regions.put(wtxn, id, &region.encode()))
+    }
+
+    /// Removes the region stored under `id`. Returns whether there was one.
+    pub fn delete(&self, id: &str) -> anyhow::Result<bool> {
+        self.db.write(|wtxn| self.regions.delete(wtxn, id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn regions_round_trip() {
+        let path = std::env::temp_dir().join(format!("hyperion-regions-{}", fastrand::u64(..)));
+        let db = LocalDb::builder().path(&path).build().unwrap();
+        let storage = RegionStorage::new(&db).unwrap();
+
+        let mut lobby = Region::new(IVec3::new(10, 80, -5), IVec3::new(-10, 60, 5));
+        lobby.priority = -3;
+        lobby.flags = RegionFlags {
+            build: Some(GroupSet::NONE.with(3)),
+            interact: None,
+            entry: Some(GroupSet::ALL),
+            pvp: Some(false),
+        };
+        assert_eq!(lobby.min, IVec3::new(-10, 60, -5));
+        assert!(lobby.contains(IVec3::new(10, 80, 5)));
+        assert!(!lobby.contains(IVec3::new(11, 80, 5)));
+
+        storage.put("lobby", &lobby).unwrap();
+        storage
+            .put("arena", &Region::new(IVec3::ZERO, IVec3::ONE))
+            .unwrap();
+        assert!(storage.delete("arena").unwrap());
+        assert!(!storage.delete("arena").unwrap());
+        drop(storage);
+
+        let storage = RegionStorage::new(&db).unwrap();
+        assert_eq!(
+            storage.load().unwrap(),
+            BTreeMap::from([("lobby".to_owned(), lobby)])
+        );
+        assert!(Region::decode(&[0; 5]).is_err());
+
+        drop(storage);
+        drop(db);
+        std::fs::remove_dir_all(path).unwrap();
+    }
+}
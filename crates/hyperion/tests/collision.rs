@@ -9,9 +9,12 @@ use bevy_ecs::{entity::Entity, world::World};
 use glam::Vec3;
 use hyperion::{
     HyperionCore,
-    simulation::{EntitySize, Owner, Pitch, Position, Velocity, Yaw, entity_kind::EntityKind},
+    simulation::{
+        EntitySize, Owner, Pitch, Position, Velocity, Yaw, blocks::Blocks, entity_kind::EntityKind,
+    },
     spatial::Spatial,
 };
+use valence_generated::block::BlockState;
 
 #[test]
 fn test_get_first_collision() {
@@ -31,9 +34,11 @@ fn test_get_first_collision() {
 
     let mut app = App::new();
 
-    app.add_plugins((HyperionCore, hyperion_genmap::GenMapPlugin));
+    app.add_plugins(HyperionCore);
 
     let world = app.world_mut();
+    // The arrows hit the ground right below them
+    world.insert_resource(Blocks::flat(20, BlockState::STONE));
 
     // Create a player entity
     let player = world
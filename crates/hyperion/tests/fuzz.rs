@@ -0,0 +1,124 @@
+//! Bounded runs of the fuzzing entry points in `hyperion::net::fuzz`. Set `PROPTEST_CASES` to run
+//! more iterations.
+
+use hyperion::net::fuzz::{decode_packets, decode_proxy_messages};
+use hyperion_proto::{
+    PlayerConnect, PlayerDisconnect, PlayerDisconnectReason, PlayerPackets, ProxyToServerMessage,
+    RequestSubscribeChannelPackets, StreamBacklog,
+};
+use proptest::prelude::*;
+use valence_protocol::CompressionThreshold;
+
+/// The corpus in `tests/data/fuzz` with the compression threshold it was framed with
+const CORPUS: &[(&[u8], i32)] = &[
+    (include_bytes!("data/fuzz/login.bin"), -1),
+    (include_bytes!("data/fuzz/play.bin"), 256),
+    (include_bytes!("data/fuzz/play_compressed.bin"), 0),
+];
+
+/// The number of packets in each file of [`CORPUS`]
+const CORPUS_PACKETS: [usize; 3] = [2, 6, 6];
+
+/// Messages a proxy would send for a player logging in
+fn proxy_messages() -> Vec<u8> {
+    let messages = [
+        ProxyToServerMessage::PlayerConnect(PlayerConnect {
+            stream: 1,
+            ip: Some(std::net::Ipv4Addr::LOCALHOST.into()),
+        }),
+        ProxyToServerMessage::PlayerPackets(PlayerPackets {
+            stream: 1,
+            data: CORPUS[0].0,
+        }),
+        ProxyToServerMessage::RequestSubscribeChannelPackets(RequestSubscribeChannelPackets {
+            channels: &[1, 2, 3],
+        }),
+        ProxyToServerMessage::StreamBacklog(StreamBacklog {
+            stream: 1,
+            bytes: 4096,
+        }),
+        ProxyToServerMessage::PlayerDisconnect(PlayerDisconnect {
+            stream: 1,
+            reason: PlayerDisconnectReason::LostConnection,
+        }),
+    ];
+
+    let mut data = Vec::new();
+    for message in &messages {
+        let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(message).unwrap();
+        data.extend_from_slice(&(bytes.len() as u64).to_be_bytes());
+        data.extend_from_slice(&bytes);
+    }
+    data
+}
+
+/// Flips bytes of `data` at the given indices, which are wrapped around its length
+fn mutate(mut data: Vec<u8>, flips: &[(usize, u8)]) -> Vec<u8> {
+    if data.is_empty() {
+        return data;
+    }
+    let len = data.len();
+    for &(index, mask) in flips {
+        data[index % len] ^= mask;
+    }
+    data
+}
+
+#[test]
+fn corpus_decodes() {
+    for (&(data, threshold), &packets) in CORPUS.iter().zip(&CORPUS_PACKETS) {
+        let threshold = CompressionThreshold(threshold);
+        assert_eq!(decode_packets(data, &[], threshold), packets);
+
+        // The packets are the same no matter how the reads are split
+        for split in 0..=data.len() {
+            assert_eq!(decode_packets(data, &[split], threshold), packets);
+        }
+    }
+
+    assert_eq!(decode_proxy_messages(&proxy_messages()), 5);
+}
+
+#[test]
+fn oversized_proxy_messages_are_rejected() {
+    let mut data = u64::MAX.to_be_bytes().to_vec();
+    data.extend_from_slice(&[0; 64]);
+    assert_eq!(decode_proxy_messages(&data), 0);
+}
+
+proptest! {
+    #[test]
+    fn mutated_packets(
+        seed in 0..CORPUS.len(),
+        flips in prop::collection::vec((any::<usize>(), any::<u8>()), 0..8),
+        splits in prop::collection::vec(any::<usize>(), 0..8),
+        threshold in prop_oneof![Just(-1), Just(0), Just(256), any::<i32>()],
+    ) {
+        let data = mutate(CORPUS[seed].0.to_vec(), &flips);
+        let _ = decode_packets(&data, &splits, CompressionThreshold(threshold));
+    }
+
+    #[test]
+    fn arbitrary_packets(
+        data in prop::collection::vec(any::<u8>(), 0..512),
+        splits in prop::collection::vec(any::<usize>(), 0..8),
+        threshold in prop_oneof![Just(-1), Just(0), Just(256)],
+    ) {
+        let _ = decode_packets(&data, &splits, CompressionThreshold(threshold));
+    }
+
+    #[test]
+    fn mutated_proxy_messages(
+        flips in prop::collection::vec((any::<usize>(), any::<u8>()), 0..8),
+        truncate in any::<usize>(),
+    ) {
+        let mut data = mutate(proxy_messages(), &flips);
+        data.truncate(truncate % (data.len() + 1));
+        let _ = decode_proxy_messages(&data);
+    }
+
+    #[test]
+    fn arbitrary_proxy_messages(data in prop::collection::vec(any::<u8>(), 0..512)) {
+        let _ = decode_proxy_messages(&data);
+    }
+}
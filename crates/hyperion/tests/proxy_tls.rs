@@ -0,0 +1,111 @@
+use std::sync::Arc;
+
+use hyperion::Crypto;
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, ExtendedKeyUsagePurpose, IsCa, KeyPair,
+};
+use rustls::{HandshakeKind, ProtocolVersion};
+use rustls_pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+/// Creates a certificate for `usage` signed by the certificate authority
+fn signed_cert(
+    usage: ExtendedKeyUsagePurpose,
+    ca: &Certificate,
+    ca_key: &KeyPair,
+) -> (CertificateDer<'static>, PrivateKeyDer<'static>) {
+    let key = KeyPair::generate().unwrap();
+    let mut params = CertificateParams::new(vec!["localhost".to_owned()]).unwrap();
+    params.extended_key_usages = vec![usage];
+    let cert = params.signed_by(&key, ca, ca_key).unwrap();
+
+    (
+        cert.der().clone(),
+        PrivatePkcs8KeyDer::from(key.serialize_der()).into(),
+    )
+}
+
+/// Connects a proxy to a server configured by `configure` twice, and returns the handshake kinds
+/// seen by the proxy and by the server
+async fn connect_twice(
+    configure: impl FnOnce(Crypto) -> Crypto,
+) -> (Vec<Option<HandshakeKind>>, Vec<Option<HandshakeKind>>) {
+    let ca_key = KeyPair::generate().unwrap();
+    let mut ca_params = CertificateParams::new(Vec::new()).unwrap();
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let ca = ca_params.self_signed(&ca_key).unwrap();
+
+    let (server_cert, server_key) = signed_cert(ExtendedKeyUsagePurpose::ServerAuth, &ca, &ca_key);
+    let (proxy_cert, proxy_key) = signed_cert(ExtendedKeyUsagePurpose::ClientAuth, &ca, &ca_key);
+
+    let crypto = configure(
+        Crypto::from_der(ca.der().clone(), server_cert, server_key)
+            .with_protocol_versions(&[&rustls::version::TLS13]),
+    );
+    let acceptor = TlsAcceptor::from(Arc::new(crypto.server_config().unwrap()));
+
+    // The proxy keeps one client config for every reconnect
+    let connector = TlsConnector::from(Arc::new(
+        hyperion_proxy::tls_client_config(ca.der().clone(), proxy_cert, proxy_key).unwrap(),
+    ));
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = tokio::spawn(async move {
+        let mut kinds = Vec::new();
+        for _ in 0..2 {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut stream = acceptor.accept(socket).await.unwrap();
+            kinds.push(stream.get_ref().1.handshake_kind());
+
+            // The client receives the session ticket while reading this
+            stream.write_all(b"ok").await.unwrap();
+            stream.shutdown().await.unwrap();
+        }
+        kinds
+    });
+
+    let mut client_kinds = Vec::new();
+    for _ in 0..2 {
+        let socket = TcpStream::connect(addr).await.unwrap();
+        let server_name = ServerName::try_from("localhost").unwrap();
+        let mut stream = connector.connect(server_name, socket).await.unwrap();
+
+        let mut buf = Vec::new();
+        stream.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, b"ok");
+
+        let connection = stream.get_ref().1;
+        assert_eq!(
+            connection.protocol_version(),
+            Some(ProtocolVersion::TLSv1_3)
+        );
+        client_kinds.push(connection.handshake_kind());
+    }
+
+    (client_kinds, server.await.unwrap())
+}
+
+#[tokio::test]
+async fn reconnecting_proxy_resumes_its_session() {
+    let (client_kinds, server_kinds) = connect_twice(|crypto| crypto).await;
+
+    let expected = [Some(HandshakeKind::Full), Some(HandshakeKind::Resumed)];
+    assert_eq!(client_kinds, expected);
+    assert_eq!(server_kinds, expected);
+}
+
+#[tokio::test]
+async fn resumption_can_be_disabled() {
+    let (client_kinds, server_kinds) =
+        connect_twice(|crypto| crypto.with_session_cache_size(0)).await;
+
+    let expected = [Some(HandshakeKind::Full), Some(HandshakeKind::Full)];
+    assert_eq!(client_kinds, expected);
+    assert_eq!(server_kinds, expected);
+}
@@ -0,0 +1,156 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+
+use bevy_ecs::entity::Entity;
+use hyperion::{
+    replay::{Capture, Playback, Record, RecordKind, headless_app},
+    simulation::{IgnMap, StreamLookup, Uuid, session::PlayerSessions},
+    util::mojang::StubProfiles,
+};
+use serial_test::serial;
+use tracing::{
+    Event, Level, Metadata, Subscriber,
+    span::{Attributes, Id, Record as SpanRecord},
+};
+
+/// The stream of the only connection in `place_block.capture`
+const OLD: u64 = 1;
+/// The stream the player rejoins with
+const NEW: u64 = 2;
+
+/// Counts the events logged at `ERROR`
+#[derive(Default)]
+struct ErrorCounter {
+    errors: Arc<AtomicUsize>,
+    next_span: AtomicU64,
+}
+
+impl Subscriber for ErrorCounter {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, _: &Attributes<'_>) -> Id {
+        Id::from_u64(self.next_span.fetch_add(1, Ordering::Relaxed) + 1)
+    }
+
+    fn record(&self, _: &Id, _: &SpanRecord<'_>) {}
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        if *event.metadata().level() == Level::ERROR {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn enter(&self, _: &Id) {}
+
+    fn exit(&self, _: &Id) {}
+}
+
+/// Builds a capture in which the player of `place_block.capture` joins, and then joins again on
+/// stream [`NEW`] while the old connection on stream [`OLD`] is still connected. The old
+/// connection disconnects in the tick of the `disconnect_after`th record of the new one, after
+/// that record, or after the last record of the new one if this is [`None`].
+fn rejoin_capture(disconnect_after: Option<usize>) -> Capture {
+    let mut capture = Capture::load(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/data/place_block.capture"
+    ))
+    .unwrap();
+
+    capture
+        .records
+        .retain(|record| record.kind != RecordKind::Disconnect);
+    let session = capture.records.clone();
+    let first = session.first().unwrap().tick;
+    let rejoin = session.last().unwrap().tick + 20;
+    let disconnect_after = disconnect_after.unwrap_or(session.len() - 1);
+
+    for (i, record) in session.into_iter().enumerate() {
+        let tick = rejoin + record.tick - first;
+        capture.records.push(Record {
+            tick,
+            stream: NEW,
+            kind: record.kind,
+        });
+
+        if i == disconnect_after {
+            capture.records.push(Record {
+                tick,
+                stream: OLD,
+                kind: RecordKind::Disconnect,
+            });
+        }
+    }
+
+    capture
+}
+
+/// Replays `capture` and returns the playback, the number of errors logged and the entities of the
+/// old and new connection
+fn replay(capture: Capture) -> (Playback, usize, Entity, Entity) {
+    let mut playback = Playback::new(headless_app(StubProfiles::default()), capture);
+    let counter = ErrorCounter::default();
+    let errors = counter.errors.clone();
+
+    let old = tracing::subscriber::with_default(counter, || {
+        let mut old = None;
+        while !playback.is_finished() {
+            playback.step();
+            old = old.or_else(|| {
+                playback
+                    .world()
+                    .resource::<StreamLookup>()
+                    .get(&OLD)
+                    .copied()
+            });
+        }
+        playback.run_for(5);
+        old.expect("the old connection should have been spawned")
+    });
+
+    let new = *playback
+        .world()
+        .resource::<StreamLookup>()
+        .get(&NEW)
+        .expect("the new connection should still be connected");
+
+    (playback, errors.load(Ordering::Relaxed), old, new)
+}
+
+fn assert_rejoined(playback: &Playback, errors: usize, old: Entity, new: Entity) {
+    assert_eq!(errors, 0, "errors were logged");
+
+    let world = playback.world();
+    assert!(world.get_entity(old).is_err(), "old session was not ended");
+    assert!(
+        !world.resource::<StreamLookup>().contains_key(&OLD),
+        "old connection is still looked up"
+    );
+
+    let uuid = world.get::<Uuid>(new).unwrap().0;
+    let (current, _) = world.resource::<PlayerSessions>().get(uuid).unwrap();
+    assert_eq!(current, new);
+    assert_eq!(world.resource::<IgnMap>().get("replay_bot"), Some(new));
+}
+
+#[test]
+#[serial]
+fn disconnect_and_reconnect_in_the_same_tick() {
+    // The new connection connects in the same tick the old one disconnects in
+    let (playback, errors, old, new) = replay(rejoin_capture(Some(0)));
+    assert_rejoined(&playback, errors, old, new);
+}
+
+#[test]
+#[serial]
+fn rejoin_before_disconnect() {
+    // The new connection logs in before the proxy reports that the old one is gone, which only
+    // happens in the tick of the last packet of the new connection
+    let (playback, errors, old, new) = replay(rejoin_capture(None));
+    assert_rejoined(&playback, errors, old, new);
+}
@@ -0,0 +1,113 @@
+use bevy_app::FixedUpdate;
+use bevy_ecs::{message::MessageReader, system::ResMut};
+use glam::{I16Vec2, IVec3};
+use hyperion::{
+    net::filter::PacketTarget,
+    replay::{Capture, Playback, headless_app},
+    runtime::AsyncRuntime,
+    simulation::{
+        StreamLookup,
+        blocks::{Blocks, generator::FlatGenerator},
+        event::PlaceBlock,
+    },
+    util::mojang::StubProfiles,
+};
+use serial_test::serial;
+use valence_generated::block::BlockState;
+use valence_protocol::packets::{login::LoginSuccessS2c, play::PlayerPositionLookS2c};
+
+/// The stream of the only connection in `place_block.capture`
+const STREAM: u64 = 1;
+
+fn place_block_capture() -> Capture {
+    Capture::load(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/data/place_block.capture"
+    ))
+    .unwrap()
+}
+
+/// Places blocks the way a game would, since the core only reports them
+fn place_blocks(mut events: MessageReader<'_, '_, PlaceBlock>, mut blocks: ResMut<'_, Blocks>) {
+    for event in events.read() {
+        blocks.set_block(event.position, event.block).unwrap();
+    }
+}
+
+#[test]
+#[serial]
+fn login_walk_and_place_block() {
+    let capture = place_block_capture();
+
+    let mut app = headless_app(StubProfiles::default());
+
+    // The chunk the block is placed in is loaded up front so that the capture does not depend on
+    // how long generating it takes
+    let world = app.world_mut();
+    let runtime = world.resource::<AsyncRuntime>();
+    let mut blocks = Blocks::generated(runtime, FlatGenerator::default());
+    blocks.block_and_load(I16Vec2::ZERO, runtime);
+    world.insert_resource(blocks);
+
+    app.add_systems(FixedUpdate, place_blocks);
+
+    let mut playback = Playback::new(app, capture);
+
+    let placed = IVec3::new(5, -60, 0);
+    assert_eq!(
+        playback.world().resource::<Blocks>().get_block(placed),
+        Some(BlockState::AIR)
+    );
+
+    playback.run_to_end(5);
+
+    assert_eq!(
+        playback.world().resource::<Blocks>().get_block(placed),
+        Some(BlockState::STONE)
+    );
+
+    // The player disconnected at the end of the capture
+    assert!(
+        !playback
+            .world()
+            .resource::<StreamLookup>()
+            .contains_key(&STREAM)
+    );
+
+    let connection = Playback::connection(STREAM);
+    let egress = playback.egress();
+    let login = egress
+        .iter()
+        .filter(|packet| packet.target == PacketTarget::Connection(connection))
+        .find_map(|packet| packet.decode::<LoginSuccessS2c<'_>>())
+        .expect("the player should have been logged in")
+        .unwrap();
+    assert_eq!(login.username.0, "replay_bot");
+}
+
+/// Replays `place_block.capture` with the [`GameRng`](hyperion::GameRng) seed `seed` and returns
+/// the IDs of the teleports the server sent
+fn replayed_teleport_ids(seed: u64) -> Vec<i32> {
+    let mut capture = place_block_capture();
+    capture.seed = seed;
+
+    let mut playback = Playback::new(headless_app(StubProfiles::default()), capture);
+    playback.run_to_end(5);
+
+    playback
+        .egress()
+        .iter()
+        .filter_map(|packet| packet.decode::<PlayerPositionLookS2c>())
+        .map(|packet| packet.unwrap().teleport_id.0)
+        .collect()
+}
+
+#[test]
+#[serial]
+fn same_seed_sends_same_teleport_ids() {
+    let ids = replayed_teleport_ids(7);
+    assert!(!ids.is_empty(), "the player should have been teleported");
+
+    assert_eq!(replayed_teleport_ids(7), ids);
+    assert_ne!(replayed_teleport_ids(8), ids);
+}
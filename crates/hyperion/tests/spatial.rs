@@ -8,11 +8,14 @@ use std::collections::HashSet;
 
 use approx::assert_relative_eq;
 use bevy_app::{App, FixedMain};
-use bevy_ecs::system::{Query, Res};
+use bevy_ecs::{
+    query::With,
+    system::{Query, Res},
+};
 use geometry::{aabb::Aabb, ray::Ray};
 use glam::Vec3;
 use hyperion::{
-    simulation::{EntitySize, Position},
+    simulation::{EntitySize, Position, vanish::Vanished},
     spatial,
 };
 use spatial::{Spatial, SpatialIndex, SpatialPlugin};
@@ -74,3 +77,51 @@ fn spatial() {
     );
     app.world_mut().run_system(system).unwrap();
 }
+
+#[test]
+fn vanished_entities_are_filtered_by_lookups() {
+    let mut app = App::new();
+    app.add_plugins(SpatialPlugin);
+
+    let zombie = app
+        .world_mut()
+        .spawn((EntitySize::default(), Position::new(5.0, 0.0, 0.0), Spatial))
+        .id();
+
+    let staff = app
+        .world_mut()
+        .spawn((
+            EntitySize::default(),
+            Position::new(2.0, 0.0, 0.0),
+            Spatial,
+            Vanished,
+        ))
+        .id();
+
+    FixedMain::run_fixed_main(app.world_mut());
+
+    let system = app.register_system(
+        move |spatial: Res<'_, SpatialIndex>,
+              query: Query<'_, '_, (&Position, &EntitySize)>,
+              vanished: Query<'_, '_, (), With<Vanished>>| {
+            // Vanished entities are still indexed, so staff tooling can find them
+            let big_aabb = Aabb::new(Vec3::splat(-100.0), Vec3::splat(100.0));
+            let collisions: HashSet<_> = spatial.get_collisions(big_aabb, query).collect();
+            assert!(
+                collisions.contains(&staff),
+                "vanished entity was not indexed"
+            );
+
+            let ray = Ray::new(Vec3::new(0.0, 0.5, 0.0), Vec3::new(1.0, 0.0, 0.0));
+            let (first, _) = spatial.first_ray_collision(ray, query).unwrap();
+            assert_eq!(first, staff);
+
+            // Lookups which ignore vanished entities pass through them
+            let (first, _) = spatial
+                .first_ray_collision_where(ray, query, |entity| !vanished.contains(entity))
+                .unwrap();
+            assert_eq!(first, zombie);
+        },
+    );
+    app.world_mut().run_system(system).unwrap();
+}
@@ -83,6 +83,9 @@ impl Fragment {
     }
 }
 
+/// The bit offset of the 6th byte of a VarInt, which is one byte longer than the longest VarInt
+const MAX_LEN_BIT_OFFSET: u32 = 35;
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 enum SendState {
     ReadLen { current_len: u32, bit_offset: u32 },
@@ -123,21 +126,36 @@ impl Sender {
         while let Some(first_byte) = data.first() {
             match self.send_state {
                 SendState::ReadLen {
-                    mut current_len,
+                    current_len,
                     bit_offset,
                 } => {
                     // TODO: Optimize this when the entire len is available in data
                     const DATA_MASK: u8 = 0b0111_1111;
                     const CONTINUE_BIT: u8 = !DATA_MASK;
 
-                    current_len |= u32::from(first_byte & DATA_MASK) << bit_offset;
+                    // A VarInt has at most 5 bytes. Longer lengths are rejected instead of
+                    // shifting by 32 bits or more, which would overflow.
+                    if bit_offset >= MAX_LEN_BIT_OFFSET {
+                        self.send_state = SendState::Closed;
+                        result = Err(SendError::TooLargePacket);
+                        break;
+                    }
+
+                    let len =
+                        u64::from(current_len) | (u64::from(first_byte & DATA_MASK) << bit_offset);
 
-                    if current_len >= MAX_PACKET_SIZE as u32 {
+                    if len >= MAX_PACKET_SIZE as u64 {
                         self.send_state = SendState::Closed;
                         result = Err(SendError::TooLargePacket);
                         break;
                     }
 
+                    #[expect(
+                        clippy::cast_possible_truncation,
+                        reason = "len is less than MAX_PACKET_SIZE, so it fits in a u32"
+                    )]
+                    let current_len = len as u32;
+
                     if first_byte & CONTINUE_BIT == 0 {
                         // Stop reading size
                         let Some(remaining) = NonZeroU32::new(current_len) else {
@@ -222,3 +222,38 @@ proptest! {
         assert!(receiver.try_recv().is_none());
     }
 }
+
+#[test]
+fn test_packet_channel_overlong_len() {
+    // Continuation bits without any length bits never exceed the maximum packet size, but a
+    // VarInt of more than 5 bytes is still rejected
+    let (mut sender, _receiver) = packet_channel::channel(16);
+    assert_eq!(sender.send(&[0x80; 5]), Ok(()));
+    assert_eq!(sender.send(&[0x80]), Err(SendError::TooLargePacket));
+
+    // Length bits which do not fit in a u32 are not silently discarded
+    let (mut sender, _receiver) = packet_channel::channel(16);
+    assert_eq!(
+        sender.send(&[0x80, 0x80, 0x80, 0x80, 0x10]),
+        Err(SendError::TooLargePacket)
+    );
+}
+
+proptest! {
+    #[test]
+    fn test_packet_channel_arbitrary_bytes(
+        data in prop::collection::vec(any::<u8>(), 0..256),
+        splits in prop::collection::vec(0..256usize, 0..10),
+        default_fragment_size in 0..10usize,
+    ) {
+        let mut splits: Vec<usize> = splits.into_iter().map(|split| split.min(data.len())).collect();
+        splits.sort_unstable();
+
+        let (mut sender, mut receiver) = packet_channel::channel(default_fragment_size);
+        for_each_segment(&data, &splits, |slice| {
+            // Malformed data is rejected, but never panics
+            let _ = sender.send(slice);
+        });
+        while receiver.try_recv().is_some() {}
+    }
+}
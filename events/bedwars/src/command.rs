@@ -2,16 +2,20 @@ use bevy_ecs::world::World;
 use hyperion_clap::MinecraftCommand;
 
 use crate::command::{
-    bow::BowCommand, chest::ChestCommand, fly::FlyCommand, gui::GuiCommand,
-    raycast::RaycastCommand, shoot::ShootCommand, speed::SpeedCommand, vanish::VanishCommand,
-    xp::XpCommand,
+    bow::BowCommand, chest::ChestCommand, clear::ClearCommand, fly::FlyCommand, give::GiveCommand,
+    gui::GuiCommand, raycast::RaycastCommand, rename::RenameCommand, resync::ResyncCommand,
+    shoot::ShootCommand, speed::SpeedCommand, vanish::VanishCommand, xp::XpCommand,
 };
 
 mod bow;
 mod chest;
+mod clear;
 mod fly;
+mod give;
 mod gui;
 mod raycast;
+mod rename;
+mod resync;
 mod shoot;
 mod speed;
 mod vanish;
@@ -22,9 +26,13 @@ pub fn register(world: &mut World) {
     FlyCommand::register(world);
     GuiCommand::register(world);
     RaycastCommand::register(world);
+    RenameCommand::register(world);
+    ResyncCommand::register(world);
     ShootCommand::register(world);
     SpeedCommand::register(world);
     VanishCommand::register(world);
     XpCommand::register(world);
     ChestCommand::register(world);
+    GiveCommand::register(world);
+    ClearCommand::register(world);
 }
@@ -0,0 +1,45 @@
+use bevy_ecs::{
+    entity::Entity,
+    system::{Commands, SystemState},
+    world::{EntityWorldMut, World},
+};
+use clap::Parser;
+use hyperion_clap::{CommandPermission, MinecraftCommand, hyperion_command::CommandCaller};
+use hyperion_inventory::PlayerInventory;
+use tracing::error;
+use valence_protocol::ItemKind;
+
+use super::give::parse_item;
+
+#[derive(Parser, CommandPermission, Debug)]
+#[command(name = "clear")]
+#[command_permission(group = "Admin")]
+pub struct ClearCommand {
+    #[arg(
+        value_parser = parse_item,
+        help = "Only remove this item, such as minecraft:diamond"
+    )]
+    item: Option<ItemKind>,
+}
+
+impl MinecraftCommand for ClearCommand {
+    type State = SystemState<Commands<'static, 'static>>;
+
+    fn execute(self, world: &World, state: &mut Self::State, caller: Entity) {
+        let mut commands = state.get(world);
+        commands
+            .entity(caller)
+            .queue(move |mut player: EntityWorldMut<'_>| {
+                let Some(mut inventory) = player.get_mut::<PlayerInventory>() else {
+                    error!("clear command failed: player is missing PlayerInventory component");
+                    return;
+                };
+
+                let removed =
+                    inventory.clear(|stack| self.item.is_none_or(|item| stack.item == item));
+
+                CommandCaller::Player(caller)
+                    .reply(player.world(), format!("Removed {removed} items"));
+            });
+    }
+}
@@ -1,14 +1,13 @@
 use bevy_ecs::{
     entity::Entity,
-    system::{Commands, Query, Res, SystemState},
+    system::{Commands, Query, SystemState},
     world::World,
 };
 use clap::Parser;
-use hyperion::{
-    net::{Compose, ConnectionId, DataBundle, agnostic},
-    simulation::Flight,
+use hyperion::simulation::Flight;
+use hyperion_clap::{
+    CommandPermission, CommandReply, MinecraftCommand, hyperion_command::CommandCaller,
 };
-use hyperion_clap::{CommandPermission, MinecraftCommand};
 use tracing::error;
 
 #[derive(Parser, CommandPermission, Debug)]
@@ -18,15 +17,14 @@ pub struct FlyCommand;
 
 impl MinecraftCommand for FlyCommand {
     type State = SystemState<(
-        Res<'static, Compose>,
-        Query<'static, 'static, (&'static ConnectionId, &'static Flight)>,
+        Query<'static, 'static, &'static Flight>,
         Commands<'static, 'static>,
     )>;
 
     fn execute(self, world: &World, state: &mut Self::State, caller: Entity) {
-        let (compose, query, mut commands) = state.get(world);
+        let (query, mut commands) = state.get(world);
 
-        let (&connection_id, &(mut flight)) = match query.get(caller) {
+        let &(mut flight) = match query.get(caller) {
             Ok(data) => data,
             Err(e) => {
                 error!("fly command failed: query failed: {e}");
@@ -37,17 +35,12 @@ impl MinecraftCommand for FlyCommand {
         flight.allow = !flight.allow;
         flight.is_flying = flight.allow && flight.is_flying;
 
-        let allow_flight = flight.allow;
-
-        let chat_packet = if allow_flight {
-            agnostic::chat("§aFlying enabled")
+        let reply = CommandReply::new(world, CommandCaller::Player(caller));
+        if flight.allow {
+            reply.reply("§aFlying enabled");
         } else {
-            agnostic::chat("§cFlying disabled")
-        };
-
-        let mut bundle = DataBundle::new(&compose);
-        bundle.add_packet(&chat_packet).unwrap();
-        bundle.unicast(connection_id).unwrap();
+            reply.reply("§cFlying disabled");
+        }
 
         commands.entity(caller).insert(flight);
     }
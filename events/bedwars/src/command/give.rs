@@ -0,0 +1,84 @@
+use bevy_ecs::{
+    entity::Entity,
+    system::{Commands, SystemState},
+    world::{EntityWorldMut, World},
+};
+use clap::Parser;
+use hyperion::simulation::inventory::give_or_drop;
+use hyperion_clap::{CommandPermission, MinecraftCommand, hyperion_command::CommandCaller};
+use tracing::error;
+use valence_nbt::{Compound, Value, snbt};
+use valence_protocol::{ItemKind, ItemStack};
+
+#[derive(Parser, CommandPermission, Debug)]
+#[command(name = "give")]
+#[command_permission(group = "Admin")]
+pub struct GiveCommand {
+    #[arg(value_parser = parse_item, help = "The item to give, such as minecraft:diamond")]
+    item: ItemKind,
+
+    #[arg(
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u16).range(1..=6400),
+        help = "How many items to give"
+    )]
+    count: u16,
+
+    #[arg(value_parser = parse_nbt, help = "The NBT of the item, such as {Unbreakable:1b}")]
+    nbt: Option<Compound>,
+}
+
+/// Parses an item id with an optional `minecraft:` namespace
+pub(super) fn parse_item(id: &str) -> Result<ItemKind, String> {
+    ItemKind::from_str(id.strip_prefix("minecraft:").unwrap_or(id))
+        .ok_or_else(|| format!("unknown item {id}"))
+}
+
+/// Parses the SNBT of an item, which has to be a compound
+fn parse_nbt(nbt: &str) -> Result<Compound, String> {
+    match snbt::from_snbt_str(nbt) {
+        Ok(Value::Compound(compound)) => Ok(compound),
+        Ok(_) => Err("item NBT must be a compound".to_owned()),
+        Err(e) => Err(format!("invalid item NBT: {e}")),
+    }
+}
+
+impl MinecraftCommand for GiveCommand {
+    type State = SystemState<Commands<'static, 'static>>;
+
+    fn execute(self, world: &World, state: &mut Self::State, caller: Entity) {
+        let mut commands = state.get(world);
+        commands
+            .entity(caller)
+            .queue(move |mut player: EntityWorldMut<'_>| {
+                let max_stack = u16::from(self.item.max_stack().unsigned_abs());
+                let mut remaining = self.count;
+                let mut dropped = 0;
+
+                // Items are given one stack at a time since a stack holds at most 127 items
+                while remaining > 0 {
+                    let count = remaining.min(max_stack);
+                    remaining -= count;
+
+                    let stack =
+                        ItemStack::new(self.item, i8::try_from(count).unwrap(), self.nbt.clone());
+
+                    let Some(result) = give_or_drop(&mut player, stack) else {
+                        error!("give command failed: player is missing PlayerInventory component");
+                        return;
+                    };
+
+                    if let Some(remainder) = result.remainder {
+                        dropped += u16::from(remainder.count.unsigned_abs());
+                    }
+                }
+
+                let mut message = format!("Gave {} {}", self.count, self.item.to_str());
+                if dropped > 0 {
+                    message += &format!(", {dropped} of which were dropped on the ground");
+                }
+
+                CommandCaller::Player(caller).reply(player.world(), message);
+            });
+    }
+}
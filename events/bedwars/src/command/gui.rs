@@ -5,7 +5,9 @@ use bevy_ecs::{
 };
 use clap::Parser;
 use hyperion::simulation::entity_kind::EntityKind;
-use hyperion_clap::{CommandPermission, MinecraftCommand};
+use hyperion_clap::{
+    CommandPermission, MinecraftCommand, admin_bypass, hyperion_command::CommandLimits,
+};
 use hyperion_gui::Gui;
 use hyperion_inventory::Inventory;
 use tracing::debug;
@@ -25,6 +27,14 @@ impl MinecraftCommand for GuiCommand {
         Commands<'static, 'static>,
     )>;
 
+    fn limits() -> Option<CommandLimits> {
+        Some(CommandLimits {
+            cooldown_ticks: 20,
+            bypass: admin_bypass,
+            ..CommandLimits::default()
+        })
+    }
+
     fn execute(self, world: &World, state: &mut Self::State, caller: Entity) {
         let (query, mut commands) = state.get(world);
 
@@ -0,0 +1,55 @@
+use bevy_ecs::{
+    entity::Entity,
+    system::{Commands, Query, Res, SystemState},
+    world::World,
+};
+use clap::Parser;
+use hyperion::net::{Compose, ConnectionId, SendResultExt, agnostic};
+use hyperion_clap::{CommandPermission, MinecraftCommand};
+use hyperion_gui::TextPrompt;
+use hyperion_inventory::PlayerInventory;
+use tracing::error;
+use valence_protocol::Hand;
+use valence_text::IntoText;
+
+use crate::plugin::rename::RenamingHeldItem;
+
+#[derive(Parser, CommandPermission, Debug)]
+#[command(name = "rename")]
+#[command_permission(group = "Normal")]
+pub struct RenameCommand;
+
+impl MinecraftCommand for RenameCommand {
+    type State = SystemState<(
+        Res<'static, Compose>,
+        Query<'static, 'static, (&'static ConnectionId, &'static PlayerInventory)>,
+        Commands<'static, 'static>,
+    )>;
+
+    fn execute(self, world: &World, state: &mut Self::State, caller: Entity) {
+        let (compose, query, mut commands) = state.get(world);
+
+        let (&connection_id, inventory) = match query.get(caller) {
+            Ok(data) => data,
+            Err(e) => {
+                error!("rename command failed: query failed: {e}");
+                return;
+            }
+        };
+
+        if inventory.held(Hand::Main).stack.is_empty() {
+            let chat = agnostic::chat("§cHold the item you want to rename");
+            compose
+                .unicast(&chat, connection_id)
+                .unwrap_or_disconnected();
+            return;
+        }
+
+        commands.entity(caller).insert(RenamingHeldItem);
+        commands.queue(TextPrompt::open(
+            caller,
+            "Rename item".into_text(),
+            String::new(),
+        ));
+    }
+}
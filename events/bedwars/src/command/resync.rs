@@ -0,0 +1,44 @@
+use bevy_ecs::{
+    entity::Entity,
+    system::{Commands, Query, Res, SystemState},
+    world::World,
+};
+use clap::Parser;
+use hyperion::{
+    egress::resync::resync_player,
+    net::{Compose, ConnectionId, SendResultExt, agnostic},
+};
+use hyperion_clap::{CommandPermission, MinecraftCommand};
+use tracing::error;
+
+#[derive(Parser, CommandPermission, Debug)]
+#[command(name = "resync")]
+#[command_permission(group = "Normal")]
+pub struct ResyncCommand;
+
+impl MinecraftCommand for ResyncCommand {
+    type State = SystemState<(
+        Res<'static, Compose>,
+        Query<'static, 'static, &'static ConnectionId>,
+        Commands<'static, 'static>,
+    )>;
+
+    fn execute(self, world: &World, state: &mut Self::State, caller: Entity) {
+        let (compose, query, mut commands) = state.get(world);
+
+        let &connection_id = match query.get(caller) {
+            Ok(data) => data,
+            Err(e) => {
+                error!("resync command failed: query failed: {e}");
+                return;
+            }
+        };
+
+        let chat = agnostic::chat("§aResending the world");
+        compose
+            .unicast(&chat, connection_id)
+            .unwrap_or_disconnected();
+
+        commands.queue(resync_player(caller));
+    }
+}
@@ -1,15 +1,13 @@
 use bevy_ecs::{
     entity::Entity,
-    system::{Commands, Query, Res, SystemState},
+    system::{Commands, SystemState},
     world::World,
 };
 use clap::Parser;
-use hyperion::{
-    net::{Compose, ConnectionId, agnostic},
-    simulation::FlyingSpeed,
+use hyperion::simulation::FlyingSpeed;
+use hyperion_clap::{
+    CommandPermission, CommandReply, MinecraftCommand, hyperion_command::CommandCaller,
 };
-use hyperion_clap::{CommandPermission, MinecraftCommand};
-use tracing::error;
 
 #[derive(Parser, CommandPermission, Debug)]
 #[command(name = "speed")]
@@ -19,26 +17,13 @@ pub struct SpeedCommand {
 }
 
 impl MinecraftCommand for SpeedCommand {
-    type State = SystemState<(
-        Query<'static, 'static, &'static ConnectionId>,
-        Res<'static, Compose>,
-        Commands<'static, 'static>,
-    )>;
+    type State = SystemState<Commands<'static, 'static>>;
 
     fn execute(self, world: &World, state: &mut Self::State, caller: Entity) {
-        let (query, compose, mut commands) = state.get(world);
+        let mut commands = state.get(world);
 
-        let &connection_id = match query.get(caller) {
-            Ok(connection_id) => connection_id,
-            Err(e) => {
-                error!("speed command failed: query failed: {e}");
-                return;
-            }
-        };
-
-        let msg = format!("Setting speed to {}", self.amount);
-        let chat = agnostic::chat(msg);
-        compose.unicast(&chat, connection_id).unwrap();
+        CommandReply::new(world, CommandCaller::Player(caller))
+            .reply(format!("Setting speed to {}", self.amount));
 
         commands
             .entity(caller)
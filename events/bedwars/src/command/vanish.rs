@@ -1,16 +1,18 @@
 use bevy_ecs::{
     entity::Entity,
     name::Name,
+    query::Has,
     system::{Commands, Query, Res, SystemState},
     world::World,
 };
 use clap::Parser;
-use hyperion::net::{Compose, ConnectionId};
+use hyperion::{
+    net::{Compose, ConnectionId, SendResultExt},
+    simulation::vanish::Vanished,
+};
 use hyperion_clap::{CommandPermission, MinecraftCommand};
 use tracing::error;
 
-use crate::plugin::vanish::Vanished;
-
 #[derive(Parser, CommandPermission, Debug)]
 #[command(name = "vanish")]
 #[command_permission(group = "Admin")]
@@ -18,15 +20,7 @@ pub struct VanishCommand;
 
 impl MinecraftCommand for VanishCommand {
     type State = SystemState<(
-        Query<
-            'static,
-            'static,
-            (
-                &'static ConnectionId,
-                &'static Name,
-                Option<&'static Vanished>,
-            ),
-        >,
+        Query<'static, 'static, (&'static ConnectionId, &'static Name, Has<Vanished>)>,
         Res<'static, Compose>,
         Commands<'static, 'static>,
     )>;
@@ -34,7 +28,7 @@ impl MinecraftCommand for VanishCommand {
     fn execute(self, world: &World, state: &mut Self::State, caller: Entity) {
         let (query, compose, mut commands) = state.get(world);
 
-        let (&connection_id, name, vanished) = match query.get(caller) {
+        let (&connection_id, name, was_vanished) = match query.get(caller) {
             Ok(data) => data,
             Err(e) => {
                 error!("vanish command failed: query failed: {e}");
@@ -42,14 +36,20 @@ impl MinecraftCommand for VanishCommand {
             }
         };
 
-        let is_vanished = !vanished.is_some_and(Vanished::is_vanished);
+        let is_vanished = !was_vanished;
 
-        commands.entity(caller).insert(Vanished::new(is_vanished));
+        if is_vanished {
+            commands.entity(caller).insert(Vanished);
+        } else {
+            commands.entity(caller).remove::<Vanished>();
+        }
 
         let packet = hyperion::net::agnostic::chat(format!(
             "§7[Admin] §f{name} §7is now {}",
             if is_vanished { "vanished" } else { "visible" }
         ));
-        compose.unicast(&packet, connection_id).unwrap();
+        compose
+            .unicast(&packet, connection_id)
+            .unwrap_or_disconnected();
     }
 }
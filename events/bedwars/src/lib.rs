@@ -2,8 +2,26 @@
 use std::net::SocketAddr;
 
 use bevy_app::{App, Plugin};
-use bevy_ecs::{component::Component, lifecycle::Add, observer::On, system::Commands};
-use hyperion::{Crypto, Endpoint, HyperionCore, simulation::packet_state, spatial::Spatial};
+use bevy_ecs::{
+    component::Component,
+    entity::Entity,
+    lifecycle::Add,
+    observer::On,
+    resource::Resource,
+    system::{Commands, Res},
+    world::World,
+};
+use hyperion::{
+    Crypto, Endpoint, HyperionCore,
+    simulation::{
+        game_rules::GameRules,
+        join::PlayerConfiguring,
+        packet_state,
+        team::{self, TeamMember},
+    },
+    spatial::Spatial,
+};
+use valence_protocol::packets::play::team_s2c::TeamColor;
 use valence_text::IntoText;
 #[cfg(feature = "reflect")]
 use {bevy_ecs::reflect::ReflectComponent, bevy_reflect::Reflect};
@@ -11,8 +29,8 @@ use {bevy_ecs::reflect::ReflectComponent, bevy_reflect::Reflect};
 use crate::{
     plugin::{
         attack::AttackPlugin, block::BlockPlugin, bow::BowPlugin, chat::ChatPlugin,
-        damage::DamagePlugin, regeneration::RegenerationPlugin, spawn::SpawnPlugin,
-        stats::StatsPlugin, vanish::VanishPlugin,
+        damage::DamagePlugin, lobby::LobbyPlugin, regeneration::RegenerationPlugin,
+        rename::RenamePlugin, spawn::SpawnPlugin, stats::StatsPlugin,
     },
     skin::SkinPlugin,
 };
@@ -44,6 +62,25 @@ pub enum Team {
 }
 
 impl Team {
+    const ALL: [Self; 16] = [
+        Self::Black,
+        Self::Blue,
+        Self::Brown,
+        Self::Cyan,
+        Self::Gray,
+        Self::Green,
+        Self::LightBlue,
+        Self::LightGray,
+        Self::Lime,
+        Self::Magenta,
+        Self::Orange,
+        Self::Pink,
+        Self::Purple,
+        Self::Red,
+        Self::White,
+        Self::Yellow,
+    ];
+
     const fn name(self) -> &'static str {
         match self {
             Self::Black => "Black",
@@ -66,6 +103,30 @@ impl Team {
     }
 }
 
+impl From<Team> for TeamColor {
+    fn from(team: Team) -> Self {
+        // Name tags only support the 16 legacy colors
+        match team {
+            Team::Black => Self::Black,
+            Team::Blue => Self::DarkBlue,
+            Team::Brown => Self::Gold,
+            Team::Cyan => Self::DarkCyan,
+            Team::Gray => Self::DarkGray,
+            Team::Green => Self::DarkGreen,
+            Team::LightBlue => Self::Cyan,
+            Team::LightGray => Self::Gray,
+            Team::Lime => Self::BrightGreen,
+            Team::Magenta => Self::Pink,
+            Team::Orange => Self::Gold,
+            Team::Pink => Self::Pink,
+            Team::Purple => Self::Purple,
+            Team::Red => Self::Red,
+            Team::White => Self::White,
+            Team::Yellow => Self::Yellow,
+        }
+    }
+}
+
 impl From<Team> for valence_text::Color {
     fn from(team: Team) -> Self {
         // Source: https://minecraft.wiki/w/Wool/DV
@@ -97,13 +158,40 @@ impl From<Team> for valence_text::Text {
     }
 }
 
+/// The [`team::Team`] entity of each [`Team`]
+#[derive(Resource)]
+struct TeamEntities([Entity; 16]);
+
+impl TeamEntities {
+    fn spawn(world: &mut World) -> Self {
+        Self(Team::ALL.map(|team| {
+            let mut core_team = team::Team::new(team.name(), team.into());
+            core_team.friendly_fire = false;
+            world.spawn(core_team).id()
+        }))
+    }
+
+    const fn get(&self, team: Team) -> Entity {
+        self.0[team as usize]
+    }
+}
+
+fn assign_team(
+    configuring: On<'_, '_, PlayerConfiguring>,
+    teams: Res<'_, TeamEntities>,
+    mut commands: Commands<'_, '_>,
+) {
+    let team = Team::Red;
+    commands
+        .entity(configuring.entity)
+        .insert((team, TeamMember(teams.get(team))));
+}
+
 fn initialize_player(
     now_playing: On<'_, '_, Add, packet_state::Play>,
     mut commands: Commands<'_, '_>,
 ) {
-    commands
-        .entity(now_playing.entity)
-        .insert((Spatial, Team::Red));
+    commands.entity(now_playing.entity).insert(Spatial);
 }
 
 pub struct BedwarsPlugin;
@@ -117,18 +205,31 @@ impl Plugin for BedwarsPlugin {
                 BowPlugin,
                 ChatPlugin,
                 DamagePlugin,
+                LobbyPlugin,
                 RegenerationPlugin,
+                RenamePlugin,
                 SkinPlugin,
                 SpawnPlugin,
                 StatsPlugin,
-                VanishPlugin,
             ),
+            hyperion::simulation::history::PlayerHistoryPlugin,
+            hyperion::storage::AuditPlugin::default(),
             hyperion_clap::ClapCommandPlugin,
+            hyperion_clap::hyperion_command::ConsolePlugin,
             hyperion_genmap::GenMapPlugin,
+            hyperion_gui::TextPromptPlugin,
             hyperion_item::ItemPlugin,
             hyperion_permission::PermissionPlugin,
             hyperion_proxy_module::HyperionProxyPlugin,
         ));
+        // Players respawn without the death screen
+        app.insert_resource(GameRules {
+            immediate_respawn: true,
+            ..GameRules::default()
+        });
+        let teams = TeamEntities::spawn(app.world_mut());
+        app.insert_resource(teams);
+        app.add_observer(assign_team);
         app.add_observer(initialize_player);
 
         command::register(app.world_mut());
@@ -3,7 +3,8 @@ pub mod block;
 pub mod bow;
 pub mod chat;
 pub mod damage;
+pub mod lobby;
 pub mod regeneration;
+pub mod rename;
 pub mod spawn;
 pub mod stats;
-pub mod vanish;
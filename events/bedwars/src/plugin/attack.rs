@@ -9,27 +9,35 @@ use bevy_ecs::{
     name::Name,
     observer::On,
     schedule::IntoScheduleConfigs,
-    system::{Commands, ParamSet, Query, Res, ResMut},
-    world::World,
+    system::{Commands, Query, Res, ResMut},
 };
 use glam::{DVec3, IVec3, Vec3};
 use hyperion::{
-    ingress,
-    net::{Compose, ConnectionId, agnostic},
+    Tick, ingress,
+    net::{Compose, ConnectionId, SendResultExt, agnostic},
     runtime::AsyncRuntime,
     simulation::{
-        PendingTeleportation, Position, Velocity, Yaw, blocks::Blocks, event,
-        metadata::living_entity::Health, packet::play, packet_state,
+        PendingTeleportation, Position, Velocity, Yaw,
+        blocks::Blocks,
+        event,
+        game_rules::{GameRules, PlayerRespawn},
+        metadata::living_entity::Health,
+        minecraft_id::MinecraftIdRegistry,
+        packet::play,
+        packet_state,
+        region::{self, ProtectedRegions, block_position},
+        team::Teams,
+        world::WorldId,
     },
 };
 use hyperion_inventory::PlayerInventory;
-use hyperion_utils::{EntityExt, Prev};
+use hyperion_utils::{Localizer, Prev};
 use tracing::error;
 use valence_protocol::{
-    BlockKind, ItemKind, ItemStack, Particle, VarInt, ident,
+    BlockKind, Hand, ItemKind, ItemStack, Particle, VarInt, ident,
     packets::play::{
         DamageTiltS2c, DeathMessageS2c, EntityDamageS2c, GameMessageS2c, ParticleS2c,
-        client_status_c2s::ClientStatusC2s, player_interact_entity_c2s::EntityInteraction,
+        player_interact_entity_c2s::EntityInteraction,
     },
     text::IntoText,
 };
@@ -113,7 +121,8 @@ fn handle_melee_attacks(
     mut packets: MessageReader<'_, '_, play::PlayerInteractEntity>,
     origin_query: Query<'_, '_, (&Position, &PlayerInventory, &CombatStats)>,
     target_query: Query<'_, '_, (&Prev<Position>, &Position)>,
-    mut world_and_writer: ParamSet<'_, '_, (&World, MessageWriter<'_, event::AttackEntity>)>,
+    ids: Res<'_, MinecraftIdRegistry>,
+    mut writer: MessageWriter<'_, event::AttackEntity>,
 ) {
     for packet in packets.read() {
         if packet.interact != EntityInteraction::Attack {
@@ -124,7 +133,7 @@ fn handle_melee_attacks(
         let origin = packet.sender();
 
         // Player who is being attacked by the attacker
-        let target = match Entity::from_minecraft_id(packet.entity_id.0, world_and_writer.p0()) {
+        let target = match ids.entity(packet.entity_id.0) {
             Ok(target) => target,
             Err(e) => {
                 error!("handle melee attack failed: target id is invalid: {e}");
@@ -156,7 +165,7 @@ fn handle_melee_attacks(
         let damage_after_protection =
             get_inflicted_damage(damage_after_armor, combat_stats.protection);
 
-        world_and_writer.p1().write(event::AttackEntity {
+        writer.write(event::AttackEntity {
             origin,
             target,
             direction: (target_pos - origin_pos).normalize(),
@@ -178,32 +187,51 @@ fn handle_melee_attacks(
     }
 }
 
+/// Whether the protected regions let players damage each other where an entity at `position` in
+/// `world` is
+pub fn pvp_allowed(
+    regions: &ProtectedRegions,
+    position: Position,
+    world: Option<&WorldId>,
+) -> bool {
+    regions.allows_pvp(
+        world.copied().unwrap_or_default(),
+        block_position(*position),
+    )
+}
+
 fn handle_attacks(
     mut events: MessageReader<'_, '_, event::AttackEntity>,
     compose: Res<'_, Compose>,
-    mut origin_query: Query<'_, '_, (&Team, &Name, &ConnectionId)>,
+    tick: Res<'_, Tick>,
+    ids: Res<'_, MinecraftIdRegistry>,
+    rules: Res<'_, GameRules>,
+    regions: Res<'_, ProtectedRegions>,
+    localizer: Localizer<'_, '_>,
+    mut origin_query: Query<'_, '_, (&Name, &ConnectionId, &Position, Option<&WorldId>)>,
     mut target_query: Query<
         '_,
         '_,
         (
-            &Team,
             &Position,
             &Yaw,
             &ConnectionId,
+            Option<&WorldId>,
             &mut ImmuneUntil,
             &mut Health,
             &mut Velocity,
         ),
     >,
+    teams: Teams<'_, '_>,
 ) {
-    let current_tick = compose.global().tick;
+    let current_tick = tick.0;
 
     for event in events.read() {
         if event.damage <= 0.0 {
             continue;
         }
 
-        let (origin_team, origin_name, &origin_connection) =
+        let (origin_name, &origin_connection, &origin_pos, origin_world) =
             match origin_query.get_mut(event.origin) {
                 Ok(data) => data,
                 Err(e) => {
@@ -213,10 +241,10 @@ fn handle_attacks(
             };
 
         let (
-            target_team,
             &target_pos,
             &target_yaw,
             &target_connection,
+            target_world,
             mut target_immune_until,
             mut target_health,
             mut target_velocity,
@@ -228,18 +256,33 @@ fn handle_attacks(
             }
         };
 
-        if origin_team == target_team {
+        if !teams.can_damage(event.origin, event.target) {
             let msg = "§cCannot attack teammates";
             let pkt_msg = GameMessageS2c {
                 chat: msg.into_cow_text(),
                 overlay: false,
             };
 
-            compose.unicast(&pkt_msg, origin_connection).unwrap();
+            compose
+                .unicast(&pkt_msg, origin_connection)
+                .unwrap_or_disconnected();
 
             continue;
         }
 
+        if !pvp_allowed(&regions, origin_pos, origin_world)
+            || !pvp_allowed(&regions, target_pos, target_world)
+        {
+            region::send_denied_message(
+                &compose,
+                &localizer,
+                event.origin,
+                origin_connection,
+                "region.pvp_denied",
+            );
+            continue;
+        }
+
         if check_and_update_immunity(current_tick, &mut target_immune_until) {
             // no damage; the target is immune
             continue;
@@ -252,13 +295,14 @@ fn handle_attacks(
             .seed(fastrand::i64(..))
             .build();
 
-        compose.broadcast(&sound).send().unwrap();
+        compose.broadcast(&sound).low_priority().send().unwrap();
 
         // Broadcast particles
         if let Some(particles) = &event.particles {
             compose
                 .broadcast(particles)
                 .exclude(origin_connection)
+                .low_priority()
                 .send()
                 .unwrap();
         }
@@ -270,25 +314,32 @@ fn handle_attacks(
         // let's ignore that for now
         #[expect(clippy::cast_possible_truncation)]
         let pkt_hurt = DamageTiltS2c {
-            entity_id: VarInt(event.target.minecraft_id()),
+            entity_id: VarInt(ids.minecraft_id(event.target)),
             yaw: delta_z
                 .atan2(delta_x)
                 .mul_add(57.295_776_367_187_5_f64, -f64::from(*target_yaw)) as f32,
         };
 
-        compose.unicast(&pkt_hurt, target_connection).unwrap();
+        compose
+            .unicast(&pkt_hurt, target_connection)
+            .unwrap_or_disconnected();
 
         target_health.damage(event.damage);
 
         if target_health.is_dead() {
             // Even if enable_respawn_screen is false, the client needs this to send ClientCommandC2s and initiate its respawn
+            let message = if rules.show_death_messages {
+                format!("You were killed by {origin_name}")
+            } else {
+                String::new()
+            };
             let pkt_death_screen = DeathMessageS2c {
-                player_id: VarInt(event.target.minecraft_id()),
-                message: format!("You were killed by {origin_name}").into_cow_text(),
+                player_id: VarInt(ids.minecraft_id(event.target)),
+                message: message.into_cow_text(),
             };
             compose
                 .unicast(&pkt_death_screen, target_connection)
-                .unwrap();
+                .unwrap_or_disconnected();
         } else {
             // Calculate velocity change based on attack direction
             let knockback_xz = 8.0;
@@ -305,10 +356,10 @@ fn handle_attacks(
 
         // EntityDamageS2c: display red outline when taking damage (play arrow hit sound?)
         let pkt_damage_event = EntityDamageS2c {
-            entity_id: VarInt(event.target.minecraft_id()),
-            source_cause_id: VarInt(event.origin.minecraft_id() + 1), // this is an OptVarint
-            source_direct_id: VarInt(event.origin.minecraft_id() + 1), /* if hit by a projectile, it should be the projectile's entity id */
-            source_type_id: VarInt(31),                                // 31 = player_attack
+            entity_id: VarInt(ids.minecraft_id(event.target)),
+            source_cause_id: VarInt(ids.minecraft_id(event.origin) + 1), // this is an OptVarint
+            source_direct_id: VarInt(ids.minecraft_id(event.origin) + 1), /* if hit by a projectile, it should be the projectile's entity id */
+            source_type_id: VarInt(31),                                   // 31 = player_attack
             source_pos: None,
         };
         compose.broadcast(&pkt_damage_event).send().unwrap();
@@ -316,46 +367,40 @@ fn handle_attacks(
 }
 
 fn handle_respawn(
-    mut packets: MessageReader<'_, '_, play::ClientStatus>,
+    respawn: On<'_, '_, PlayerRespawn>,
     query: Query<'_, '_, &Team>,
     candidates_query: Query<'_, '_, (Entity, &Position, &Team)>,
     mut blocks: ResMut<'_, Blocks>,
     runtime: Res<'_, AsyncRuntime>,
     mut commands: Commands<'_, '_>,
 ) {
-    for packet in packets.read() {
-        if !matches!(**packet, ClientStatusC2s::PerformRespawn) {
-            continue;
+    let team = match query.get(respawn.entity) {
+        Ok(team) => team,
+        Err(e) => {
+            error!("handle respawn failed: query failed: {e}");
+            return;
         }
+    };
+
+    let pos_vec = candidates_query
+        .iter()
+        .filter(|(candidate_entity, _, candidate_team)| {
+            team == *candidate_team && *candidate_entity != respawn.entity
+        })
+        .map(|(_, &pos, _)| pos)
+        .collect::<Vec<_>>();
+
+    let respawn_pos = if let Some(random_mate) = fastrand::choice(pos_vec) {
+        // Spawn the player near a teammate
+        get_respawn_pos(&blocks, &random_mate).as_vec3()
+    } else {
+        // There are no other teammates, so spawn the player in a random location
+        find_spawn_position(&mut blocks, &runtime, &avoid_blocks())
+    };
 
-        let team = match query.get(packet.sender()) {
-            Ok(team) => team,
-            Err(e) => {
-                error!("handle respawn failed: query failed: {e}");
-                continue;
-            }
-        };
-
-        let pos_vec = candidates_query
-            .iter()
-            .filter(|(candidate_entity, _, candidate_team)| {
-                team == *candidate_team && *candidate_entity != packet.sender()
-            })
-            .map(|(_, &pos, _)| pos)
-            .collect::<Vec<_>>();
-
-        let respawn_pos = if let Some(random_mate) = fastrand::choice(pos_vec) {
-            // Spawn the player near a teammate
-            get_respawn_pos(&blocks, &random_mate).as_vec3()
-        } else {
-            // There are no other teammates, so spawn the player in a random location
-            find_spawn_position(&mut blocks, &runtime, &avoid_blocks())
-        };
-
-        commands
-            .entity(packet.sender())
-            .insert(PendingTeleportation::new(respawn_pos));
-    }
+    commands
+        .entity(respawn.entity)
+        .insert(PendingTeleportation::new(respawn_pos));
 }
 
 #[allow(clippy::cast_possible_truncation)]
@@ -364,12 +409,11 @@ impl Plugin for AttackPlugin {
     #[allow(clippy::cast_sign_loss)]
     fn build(&self, app: &mut App) {
         app.add_observer(initialize_player);
+        app.add_observer(handle_respawn);
         app.add_systems(
             FixedUpdate,
-            (
-                (handle_melee_attacks, handle_attacks).chain(),
-                handle_respawn,
-            )
+            (handle_melee_attacks, handle_attacks)
+                .chain()
                 .after(ingress::decode::play),
         );
     }
@@ -468,7 +512,7 @@ const fn calculate_toughness(item: &ItemStack) -> f32 {
 
 // TODO: split this up into separate functions
 fn calculate_stats(inventory: &PlayerInventory, critical_hit: bool) -> CombatStats {
-    let hand = inventory.get_cursor();
+    let hand = inventory.held(Hand::Main);
     let multiplier = if critical_hit { 1.5 } else { 1.0 };
     let damage = calculate_damage(&hand.stack) * multiplier;
     let armor = calculate_armor(&inventory.get_helmet().stack)
@@ -1,15 +1,15 @@
 use bevy_app::{App, FixedUpdate, Plugin};
 use bevy_ecs::{
     message::MessageReader,
-    system::{Query, Res, ResMut},
+    system::{Query, Res},
 };
 use glam::IVec3;
 use hyperion::{
     chat,
-    net::{Compose, ConnectionId},
+    net::{Compose, ConnectionId, SendResultExt},
     simulation::{
-        blocks::{Blocks, EntityAndSequence},
         event,
+        world::{WorldBlocks, WorldBlocksMut},
     },
 };
 use tracing::error;
@@ -22,15 +22,10 @@ use valence_protocol::{
 fn handle_destroyed_blocks(
     mut events: MessageReader<'_, '_, event::DestroyBlock>,
     compose: Res<'_, Compose>,
-    mut blocks: ResMut<'_, Blocks>,
+    worlds: WorldBlocks<'_>,
     query: Query<'_, '_, &ConnectionId>,
 ) {
     for event in events.read() {
-        blocks.to_confirm.push(EntityAndSequence {
-            entity: event.from,
-            sequence: event.sequence,
-        });
-
         let &connection_id = match query.get(event.from) {
             Ok(data) => data,
             Err(e) => {
@@ -39,7 +34,12 @@ fn handle_destroyed_blocks(
             }
         };
 
-        let current = blocks.get_block(event.position).unwrap();
+        let Some(current) = worlds
+            .get(Some(&event.world))
+            .and_then(|blocks| blocks.get_block(event.position))
+        else {
+            continue;
+        };
 
         // make sure the player knows the block was placed back
         let pkt = play::BlockUpdateS2c {
@@ -47,21 +47,24 @@ fn handle_destroyed_blocks(
             block_id: current,
         };
 
-        compose.unicast(&pkt, connection_id).unwrap();
+        compose
+            .unicast(&pkt, connection_id)
+            .unwrap_or_disconnected();
     }
 }
 
 fn handle_placed_blocks(
     mut events: MessageReader<'_, '_, event::PlaceBlock>,
-    mut blocks: ResMut<'_, Blocks>,
+    mut worlds: WorldBlocksMut<'_>,
     compose: Res<'_, Compose>,
     query: Query<'_, '_, &ConnectionId>,
 ) {
     for event::PlaceBlock {
+        world,
         position,
         block,
         from,
-        sequence,
+        ..
     } in events.read()
     {
         let &connection_id = match query.get(*from) {
@@ -73,34 +76,34 @@ fn handle_placed_blocks(
         };
 
         if block.collision_shapes().len() == 0 {
-            blocks
-                .to_confirm
-                .push(EntityAndSequence::new(*from, *sequence));
-
             // so we send update to player
 
             let msg = chat!("§cYou can't place this block");
 
-            compose.unicast(&msg, connection_id).unwrap();
+            compose
+                .unicast(&msg, connection_id)
+                .unwrap_or_disconnected();
 
             continue;
         }
 
-        blocks.set_block(*position, *block).unwrap();
+        let Some(blocks) = worlds.get_mut(Some(world)) else {
+            continue;
+        };
 
-        blocks.to_confirm.push(EntityAndSequence {
-            entity: *from,
-            sequence: *sequence,
-        });
+        blocks.set_block(*position, *block).unwrap();
     }
 }
 
 fn handle_toggled_doors(
     mut events: MessageReader<'_, '_, event::ToggleDoor>,
-    mut blocks: ResMut<'_, Blocks>,
+    mut worlds: WorldBlocksMut<'_>,
 ) {
     for event in events.read() {
         let position = event.position;
+        let Some(blocks) = worlds.get_mut(Some(&event.world)) else {
+            continue;
+        };
 
         // The block is fetched again instead of sending the expected block state
         // through the ToggleDoor event to avoid potential duplication bugs if the
@@ -147,11 +150,6 @@ fn handle_toggled_doors(
             let other_half = other_half.set(PropName::Open, open);
             blocks.set_block(other_half_position, other_half).unwrap();
         }
-
-        blocks.to_confirm.push(EntityAndSequence {
-            entity: event.from,
-            sequence: event.sequence,
-        });
     }
 }
 
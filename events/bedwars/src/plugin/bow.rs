@@ -6,23 +6,22 @@ use bevy_ecs::{
     lifecycle::Add,
     message::{MessageReader, MessageWriter},
     observer::On,
-    schedule::IntoScheduleConfigs,
-    system::{Commands, Query},
+    system::{Commands, Query, Res},
 };
 use glam::Vec3;
 use hyperion::{
     net::Channel,
     simulation::{
-        Owner, Pitch, Position, Uuid, Velocity, Yaw,
-        entity_kind::EntityKind,
-        event, get_direction_from_rotation,
-        metadata::living_entity::{ArrowsInEntity, HandStates},
-        packet_state,
+        Owner, Pitch, Position, Uuid, Velocity, Yaw, entity_kind::EntityKind, event,
+        get_direction_from_rotation, item_use, metadata::living_entity::ArrowsInEntity,
+        packet_state, region::ProtectedRegions, team::Teams, world::WorldId,
     },
 };
 use hyperion_inventory::PlayerInventory;
 use tracing::{debug, error};
-use valence_protocol::{ItemKind, ItemStack, ident};
+use valence_protocol::{Hand, ItemKind, ItemStack, ident};
+
+use super::attack::pvp_allowed;
 
 #[derive(Component)]
 pub struct LastFireTime {
@@ -43,67 +42,13 @@ impl LastFireTime {
     }
 }
 
-#[derive(Component, Default)]
-pub struct BowCharging {
-    pub start_time: Option<SystemTime>,
-}
-
-impl BowCharging {
-    #[must_use]
-    pub fn now() -> Self {
-        Self {
-            start_time: Some(SystemTime::now()),
-        }
-    }
-
-    #[must_use]
-    pub fn get_charge(&self) -> Option<f32> {
-        let elapsed = self.start_time?.elapsed().unwrap_or(Duration::ZERO);
-        let secs = elapsed.as_secs_f32();
-        // Minecraft bow charge mechanics:
-        // - Takes 1.2 second to fully charge
-        // - Minimum charge is 0.000001
-        // - Maximum charge is 1.0
-        Some(secs.clamp(0.01, 1.2))
-    }
-
-    pub const fn reset(&mut self) {
-        self.start_time = None;
-    }
-}
-
 fn initialize_player(
     now_playing: On<'_, '_, Add, packet_state::Play>,
     mut commands: Commands<'_, '_>,
 ) {
     commands
         .entity(now_playing.entity)
-        .insert((LastFireTime::now(), BowCharging::default()));
-}
-
-fn handle_bow_use(
-    mut events: MessageReader<'_, '_, event::ItemInteract>,
-    query: Query<'_, '_, &PlayerInventory>,
-    mut commands: Commands<'_, '_>,
-) {
-    for event in events.read() {
-        let inventory = match query.get(event.entity) {
-            Ok(inventory) => inventory,
-            Err(e) => {
-                error!("failed to handle bow use: query failed: {e}");
-                continue;
-            }
-        };
-
-        let cursor = inventory.get_cursor();
-        if cursor.stack.item != ItemKind::Bow {
-            return;
-        }
-
-        commands
-            .entity(event.entity)
-            .insert((BowCharging::now(), HandStates::new(1)));
-    }
+        .insert(LastFireTime::now());
 }
 
 fn handle_bow_release(
@@ -117,13 +62,12 @@ fn handle_bow_release(
             &Position,
             &Yaw,
             &Pitch,
-            &mut BowCharging,
         ),
     >,
     mut commands: Commands<'_, '_>,
 ) {
     for event in events.read() {
-        let (mut last_fire_time, mut inventory, position, yaw, pitch, mut bow_charging) =
+        let (mut last_fire_time, mut inventory, position, yaw, pitch) =
             match query.get_mut(event.from) {
                 Ok(data) => data,
                 Err(e) => {
@@ -132,7 +76,7 @@ fn handle_bow_release(
                 }
             };
 
-        if inventory.get_cursor().stack.item != ItemKind::Bow {
+        if inventory.held(Hand::Main).stack.item != ItemKind::Bow {
             continue;
         }
 
@@ -141,6 +85,12 @@ fn handle_bow_release(
             continue;
         }
 
+        // Get how charged the bow is. Like vanilla, barely charged bows do not shoot.
+        let power = item_use::bow_power(event.use_ticks);
+        if power < 0.1 {
+            continue;
+        }
+
         // Check if the player has enough arrows in their inventory
         let items: Vec<(u16, &ItemStack)> = inventory.items().collect();
         let mut has_arrow = false;
@@ -165,23 +115,15 @@ fn handle_bow_release(
         // Update the last fire time
         *last_fire_time = LastFireTime::now();
 
-        // Get how charged the bow is
-        let Some(charge) = bow_charging.get_charge() else {
-            error!("player attempted to release a non-charged bow");
-            continue;
-        };
-
-        bow_charging.reset();
-
         debug!(
-            "Player {:?} fired an arrow with charge {}",
-            event.from, charge
+            "Player {:?} fired an arrow with power {}",
+            event.from, power
         );
 
         // Calculate the direction vector from the player's rotation
         let direction = get_direction_from_rotation(**yaw, **pitch);
-        // Calculate the velocity of the arrow based on the charge (3.0 is max velocity)
-        let velocity = direction * (charge * 3.0);
+        // Calculate the velocity of the arrow based on the power (3.0 is max velocity)
+        let velocity = direction * (power * 3.0);
 
         let spawn_pos = Vec3::new(position.x, position.y + 1.62, position.z) + direction * 0.5;
 
@@ -203,9 +145,12 @@ fn handle_bow_release(
 fn arrow_entity_hit(
     mut events: MessageReader<'_, '_, event::ProjectileEntityEvent>,
     arrow_query: Query<'_, '_, (&Velocity, &Owner)>,
-    mut player_query: Query<'_, '_, &mut ArrowsInEntity>,
+    mut player_query: Query<'_, '_, (&mut ArrowsInEntity, &Position, Option<&WorldId>)>,
+    owner_query: Query<'_, '_, (&Position, Option<&WorldId>)>,
     mut commands: Commands<'_, '_>,
     mut writer: MessageWriter<'_, event::AttackEntity>,
+    teams: Teams<'_, '_>,
+    regions: Res<'_, ProtectedRegions>,
 ) {
     for event in events.read() {
         let (velocity, owner) = match arrow_query.get(event.projectile) {
@@ -216,7 +161,7 @@ fn arrow_entity_hit(
             }
         };
 
-        let mut arrows = match player_query.get_mut(event.client) {
+        let (mut arrows, &target_pos, target_world) = match player_query.get_mut(event.client) {
             Ok(data) => data,
             Err(e) => {
                 tracing::error!("arrow entity hit failed: player query failed: {e}");
@@ -230,10 +175,25 @@ fn arrow_entity_hit(
             continue;
         }
 
-        arrows.0 += 1;
-
         commands.entity(event.projectile).despawn();
 
+        // Arrows break on teammates without friendly fire instead of sticking in them
+        if !teams.can_damage(owner.entity, event.client) {
+            continue;
+        }
+
+        // Arrows also break where the protected regions do not allow the shooter or the target
+        // to be damaged
+        let owner_allowed = owner_query
+            .get(owner.entity)
+            .is_ok_and(|(&position, world)| pvp_allowed(&regions, position, world));
+
+        if !owner_allowed || !pvp_allowed(&regions, target_pos, target_world) {
+            continue;
+        }
+
+        arrows.0 += 1;
+
         writer.write(event::AttackEntity {
             origin: owner.entity,
             target: event.client,
@@ -270,11 +230,7 @@ impl Plugin for BowPlugin {
         app.add_observer(initialize_player);
         app.add_systems(
             FixedUpdate,
-            (
-                (handle_bow_use, handle_bow_release).chain(),
-                arrow_entity_hit,
-                arrow_block_hit,
-            ),
+            (handle_bow_release, arrow_entity_hit, arrow_block_hit),
         );
     }
 }
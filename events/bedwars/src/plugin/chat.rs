@@ -1,6 +1,5 @@
 use bevy_app::{App, FixedUpdate, Plugin};
 use bevy_ecs::{
-    component::Component,
     lifecycle::Add,
     message::MessageReader,
     name::Name,
@@ -9,28 +8,23 @@ use bevy_ecs::{
     system::{Commands, Query, Res},
 };
 use hyperion::{
-    ingress,
-    net::{Compose, ConnectionId},
-    simulation::{Position, packet, packet_state},
+    Tick, ingress,
+    net::{Compose, ConnectionId, SendResultExt},
+    simulation::{Position, cooldown::CooldownPrunePlugin, packet, packet_state},
 };
+use hyperion_utils::{CooldownKey, Cooldowns, RemainingTicks};
 use tracing::error;
 use valence_protocol::{
     packets::play,
     text::{Color, IntoText, Text},
 };
-#[cfg(feature = "reflect")]
-use {bevy_ecs::reflect::ReflectComponent, bevy_reflect::Reflect};
 
 use crate::Team;
 
 const CHAT_COOLDOWN_SECONDS: i64 = 3; // 3 seconds
 const CHAT_COOLDOWN_TICKS: i64 = CHAT_COOLDOWN_SECONDS * 20; // Convert seconds to ticks
 
-#[derive(Component, Default)]
-#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
-pub struct ChatCooldown {
-    pub expires: i64,
-}
+pub const CHAT_COOLDOWN: CooldownKey = CooldownKey::new("bedwars:chat");
 
 pub fn initialize_cooldown(
     now_playing: On<'_, '_, Add, packet_state::Play>,
@@ -38,18 +32,19 @@ pub fn initialize_cooldown(
 ) {
     commands
         .entity(now_playing.entity)
-        .insert(ChatCooldown::default());
+        .insert(Cooldowns::default());
 }
 
 pub fn handle_chat_messages(
     mut packets: MessageReader<'_, '_, packet::play::ChatMessage>,
     compose: Res<'_, Compose>,
-    mut query: Query<'_, '_, (&Name, &Position, &mut ChatCooldown, &ConnectionId, &Team)>,
+    tick: Res<'_, Tick>,
+    mut query: Query<'_, '_, (&Name, &Position, &mut Cooldowns, &ConnectionId, &Team)>,
 ) {
-    let current_tick = compose.global().tick;
+    let current_tick = tick.0;
 
     for packet in packets.read() {
-        let (name, position, mut cooldown, io, team) = match query.get_mut(packet.sender()) {
+        let (name, position, mut cooldowns, io, team) = match query.get_mut(packet.sender()) {
             Ok(data) => data,
             Err(e) => {
                 error!("could not process chat message: query failed: {e}");
@@ -58,8 +53,9 @@ pub fn handle_chat_messages(
         };
 
         // Check if player is still on cooldown
-        if cooldown.expires > current_tick {
-            let remaining_ticks = cooldown.expires - current_tick;
+        if let Err(RemainingTicks(remaining_ticks)) =
+            cooldowns.try_use(CHAT_COOLDOWN, CHAT_COOLDOWN_TICKS, current_tick)
+        {
             #[expect(clippy::cast_precision_loss)]
             let remaining_secs = remaining_ticks as f32 / 20.0;
 
@@ -72,12 +68,10 @@ pub fn handle_chat_messages(
                 overlay: false,
             };
 
-            compose.unicast(&packet, *io).unwrap();
+            compose.unicast(&packet, *io).unwrap_or_disconnected();
             continue;
         }
 
-        cooldown.expires = current_tick + CHAT_COOLDOWN_TICKS;
-
         let chat = Text::default()
             + "<".color(Color::DARK_GRAY)
             + name.as_str().to_owned().color(*team)
@@ -98,6 +92,7 @@ pub struct ChatPlugin;
 
 impl Plugin for ChatPlugin {
     fn build(&self, app: &mut App) {
+        app.add_plugins(CooldownPrunePlugin);
         app.add_observer(initialize_cooldown);
         app.add_systems(
             FixedUpdate,
@@ -4,10 +4,15 @@ use bevy_ecs::{
     system::{Query, Res},
 };
 use hyperion::{
-    net::{Compose, ConnectionId, agnostic},
-    simulation::{Position, event::HitGroundEvent, metadata::living_entity::Health},
+    net::{Compose, ConnectionId, SendResultExt, agnostic},
+    simulation::{
+        Position,
+        event::{HitGroundEvent, StarvationEvent},
+        game_rules::GameRules,
+        metadata::living_entity::Health,
+        minecraft_id::MinecraftIdRegistry,
+    },
 };
-use hyperion_utils::EntityExt;
 use tracing::error;
 use valence_protocol::{VarInt, packets::play, text::IntoText};
 use valence_server::ident;
@@ -16,6 +21,8 @@ fn apply_natural_damages(
     mut events: MessageReader<'_, '_, HitGroundEvent>,
     mut query: Query<'_, '_, (&mut Health, &ConnectionId, &Position)>,
     compose: Res<'_, Compose>,
+    ids: Res<'_, MinecraftIdRegistry>,
+    rules: Res<'_, GameRules>,
 ) {
     for event in events.read() {
         if event.fall_distance <= 3. {
@@ -40,7 +47,7 @@ fn apply_natural_damages(
         health.damage(damage);
 
         let pkt_damage_event = play::EntityDamageS2c {
-            entity_id: VarInt(event.client.minecraft_id()),
+            entity_id: VarInt(ids.minecraft_id(event.client)),
             source_cause_id: VarInt(0),
             source_direct_id: VarInt(0),
             source_type_id: VarInt(10), // 10 = fall damage
@@ -60,16 +67,21 @@ fn apply_natural_damages(
         .seed(fastrand::i64(..))
         .build();
 
-        compose.unicast(&pkt_damage_event, connection_id).unwrap();
+        compose
+            .unicast(&pkt_damage_event, connection_id)
+            .unwrap_or_disconnected();
         compose
             .broadcast_local(&sound, position.to_chunk())
+            .low_priority()
             .send()
             .unwrap();
 
         if health.is_dead() {
             let pkt_death_screen = play::DeathMessageS2c {
-                player_id: VarInt(event.client.minecraft_id()),
-                message: (if event.fall_distance < 5.0 {
+                player_id: VarInt(ids.minecraft_id(event.client)),
+                message: (if !rules.show_death_messages {
+                    ""
+                } else if event.fall_distance < 5.0 {
                     "You hit the ground too hard"
                 } else {
                     "You fell from a high place"
@@ -77,8 +89,46 @@ fn apply_natural_damages(
                 .to_string()
                 .into_cow_text(),
             };
-            compose.unicast(&pkt_death_screen, connection_id).unwrap();
+            compose
+                .unicast(&pkt_death_screen, connection_id)
+                .unwrap_or_disconnected();
+        }
+    }
+}
+
+fn apply_starvation_damage(
+    mut events: MessageReader<'_, '_, StarvationEvent>,
+    mut query: Query<'_, '_, (&mut Health, &ConnectionId)>,
+    compose: Res<'_, Compose>,
+    ids: Res<'_, MinecraftIdRegistry>,
+) {
+    for event in events.read() {
+        let (mut health, &connection_id) = match query.get_mut(event.client) {
+            Ok(data) => data,
+            Err(e) => {
+                error!("failed to apply starvation damage: query failed: {e}");
+                continue;
+            }
+        };
+
+        // Like the normal difficulty, starvation does not kill
+        if **health <= 1.0 {
+            continue;
         }
+
+        health.damage(event.damage.min(**health - 1.0));
+
+        let pkt_damage_event = play::EntityDamageS2c {
+            entity_id: VarInt(ids.minecraft_id(event.client)),
+            source_cause_id: VarInt(0),
+            source_direct_id: VarInt(0),
+            source_type_id: VarInt(35), // 35 = starve
+            source_pos: Option::None,
+        };
+
+        compose
+            .unicast(&pkt_damage_event, connection_id)
+            .unwrap_or_disconnected();
     }
 }
 
@@ -86,6 +136,9 @@ pub struct DamagePlugin;
 
 impl Plugin for DamagePlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(FixedUpdate, apply_natural_damages);
+        app.add_systems(
+            FixedUpdate,
+            (apply_natural_damages, apply_starvation_damage),
+        );
     }
 }
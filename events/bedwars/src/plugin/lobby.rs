@@ -0,0 +1,40 @@
+use bevy_app::{App, Plugin, Startup};
+use bevy_ecs::system::Commands;
+use glam::Vec3;
+use hyperion::simulation::npc::NpcPlayerBuilder;
+use tracing::error;
+
+use crate::Team;
+
+/// Where the first NPC of the row stands
+const FIRST_NPC: Vec3 = Vec3::new(-8.5, 64.0, 10.5);
+
+/// The distance between two NPCs of the row
+const NPC_SPACING: f32 = 2.0;
+
+/// Spawns a row of NPCs in the lobby, one named after each team
+fn spawn_lobby_npcs(mut commands: Commands<'_, '_>) {
+    for (i, team) in Team::ALL.into_iter().enumerate() {
+        #[expect(clippy::cast_precision_loss)]
+        let position = FIRST_NPC + Vec3::X * (i as f32 * NPC_SPACING);
+
+        // Facing north, towards the spawn
+        let npc = NpcPlayerBuilder::new(team.name())
+            .position(position)
+            .rotation(180.0, 0.0)
+            .tablist(false)
+            .build(&mut commands);
+
+        if let Err(e) = npc {
+            error!("failed to spawn lobby NPC: {e}");
+        }
+    }
+}
+
+pub struct LobbyPlugin;
+
+impl Plugin for LobbyPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, spawn_lobby_npcs);
+    }
+}
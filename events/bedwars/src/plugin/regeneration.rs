@@ -6,15 +6,18 @@ use bevy_ecs::{
     system::{Commands, Query, Res},
 };
 use hyperion::{
-    net::Compose,
-    simulation::{metadata::living_entity::Health, packet_state},
+    Tick,
+    simulation::{
+        hunger::{Hunger, HungerConfig},
+        max_health::MaxHealth,
+        metadata::living_entity::Health,
+        packet_state,
+    },
 };
 use hyperion_utils::Prev;
 #[cfg(feature = "reflect")]
 use {bevy_ecs::reflect::ReflectComponent, bevy_reflect::Reflect};
 
-const MAX_HEALTH: f32 = 20.0;
-
 pub struct RegenerationPlugin;
 
 #[derive(Component, Default, Copy, Clone, Debug)]
@@ -33,12 +36,23 @@ fn initialize_player(
 }
 
 fn regenerate(
-    query: Query<'_, '_, (&mut LastDamaged, &Prev<Health>, &mut Health)>,
-    compose: Res<'_, Compose>,
+    query: Query<
+        '_,
+        '_,
+        (
+            &mut LastDamaged,
+            &Prev<Health>,
+            &mut Health,
+            Option<&MaxHealth>,
+            Option<&Hunger>,
+        ),
+    >,
+    tick: Res<'_, Tick>,
+    hunger_config: Res<'_, HungerConfig>,
 ) {
-    let current_tick = compose.global().tick;
+    let current_tick = tick.0;
 
-    for (mut last_damaged, prev_health, mut health) in query {
+    for (mut last_damaged, prev_health, mut health, max_health, hunger) in query {
         if *health < **prev_health {
             last_damaged.tick = current_tick;
         }
@@ -49,6 +63,11 @@ fn regenerate(
             return;
         }
 
+        // Players regenerate only while they have enough food
+        if hunger_config.enabled && hunger.is_some_and(|hunger| !hunger.can_regenerate()) {
+            continue;
+        }
+
         // Calculate regeneration rate based on time since last damage
         let base_regen = 0.01; // Base regeneration per tick
         let ramp_factor = 0.0001_f32; // Increase in regeneration per tick
@@ -60,8 +79,7 @@ fn regenerate(
             .min(max_regen);
 
         // Apply regeneration, capped at max health
-        health.heal(regen_rate);
-        **health = health.min(MAX_HEALTH);
+        health.heal(regen_rate, max_health.copied().unwrap_or_default());
     }
 }
 
@@ -71,3 +89,41 @@ impl Plugin for RegenerationPlugin {
         app.add_systems(FixedPostUpdate, regenerate);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bevy_app::FixedPreUpdate;
+    use hyperion_utils::track_prev;
+
+    use super::*;
+
+    #[test]
+    fn regenerates_up_to_the_maximum_of_each_entity() {
+        let mut app = App::new();
+        track_prev::<Health>(&mut app);
+        app.init_resource::<Tick>();
+        app.init_resource::<HungerConfig>();
+        app.add_systems(FixedPostUpdate, regenerate);
+
+        let world = app.world_mut();
+        let boss = world
+            .spawn((LastDamaged::default(), Health::new(100.0), MaxHealth(100.0)))
+            .id();
+        let boosted = world
+            .spawn((LastDamaged::default(), Health::new(40.0), MaxHealth(40.0)))
+            .id();
+
+        world.get_mut::<Health>(boss).unwrap().damage(90.0);
+        world.get_mut::<Health>(boosted).unwrap().damage(30.0);
+
+        // Long enough to heal well past the default maximum of 20
+        for _ in 0..2000 {
+            world.resource_mut::<Tick>().0 += 1;
+            world.run_schedule(FixedPreUpdate);
+            world.run_schedule(FixedPostUpdate);
+        }
+
+        assert_eq!(world.get::<Health>(boss), Some(&Health::new(100.0)));
+        assert_eq!(world.get::<Health>(boosted), Some(&Health::new(40.0)));
+    }
+}
@@ -0,0 +1,53 @@
+use bevy_app::{App, FixedUpdate, Plugin};
+use bevy_ecs::{
+    component::Component,
+    message::MessageReader,
+    query::{Has, With},
+    system::{Commands, Query},
+};
+use hyperion_gui::{TextPrompt, TextPromptCancelled, TextPromptSubmitted, text_prompt::set_name};
+use hyperion_inventory::PlayerInventory;
+use valence_protocol::Hand;
+#[cfg(feature = "reflect")]
+use {bevy_ecs::reflect::ReflectComponent, bevy_reflect::Reflect};
+
+pub struct RenamePlugin;
+
+/// Marks players whose [`TextPrompt`] renames the item they are holding
+#[derive(Component, Default, Debug)]
+#[cfg_attr(feature = "reflect", derive(Reflect), reflect(Component))]
+pub struct RenamingHeldItem;
+
+fn rename_held_item(
+    mut submitted: MessageReader<'_, '_, TextPromptSubmitted>,
+    mut cancelled: MessageReader<'_, '_, TextPromptCancelled>,
+    mut renaming: Query<'_, '_, &mut PlayerInventory, With<RenamingHeldItem>>,
+    prompts: Query<'_, '_, Has<TextPrompt>>,
+    mut commands: Commands<'_, '_>,
+) {
+    for event in cancelled.read() {
+        // The player may have opened another rename prompt in place of the cancelled one
+        if matches!(prompts.get(event.player), Ok(false)) {
+            commands.entity(event.player).remove::<RenamingHeldItem>();
+        }
+    }
+
+    for event in submitted.read() {
+        let Ok(mut inventory) = renaming.get_mut(event.player) else {
+            continue;
+        };
+
+        commands.entity(event.player).remove::<RenamingHeldItem>();
+
+        let slot = inventory.held_mut(Hand::Main);
+        if !slot.stack.is_empty() {
+            set_name(&mut slot.stack, &event.text);
+        }
+    }
+}
+
+impl Plugin for RenamePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(FixedUpdate, rename_held_item);
+    }
+}
@@ -1,14 +1,12 @@
 use bevy_app::{App, Plugin};
 use bevy_ecs::{
-    event::EntityEvent,
     observer::On,
-    system::{Commands, Res, ResMut},
+    system::{Res, ResMut},
 };
 use glam::{I16Vec2, IVec2, IVec3, Vec3};
 use hyperion::{
-    InitializePlayerPosition,
     runtime::AsyncRuntime,
-    simulation::{Position, blocks::Blocks},
+    simulation::{blocks::Blocks, join::PlayerSpawning},
 };
 use roaring::RoaringBitmap;
 use tracing::info;
@@ -47,15 +45,10 @@ impl Plugin for SpawnPlugin {
         let avoid_blocks = avoid_blocks();
 
         app.add_observer(
-            move |init_position: On<'_, '_, InitializePlayerPosition>,
+            move |mut spawning: On<'_, '_, PlayerSpawning>,
                   mut blocks: ResMut<'_, Blocks>,
-                  runtime: Res<'_, AsyncRuntime>,
-                  mut commands: Commands<'_, '_>| {
-                let position =
-                    Position::from(find_spawn_position(&mut blocks, &runtime, &avoid_blocks));
-                commands
-                    .entity(init_position.event_target())
-                    .insert(position);
+                  runtime: Res<'_, AsyncRuntime>| {
+                spawning.spawn_position = find_spawn_position(&mut blocks, &runtime, &avoid_blocks);
             },
         );
     }
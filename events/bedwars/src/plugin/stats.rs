@@ -5,7 +5,7 @@ use bevy_ecs::{
     resource::Resource,
     system::{Res, ResMut},
 };
-use hyperion::net::Compose;
+use hyperion::{ServerInfo, net::Compose};
 use tracing::info_span;
 use valence_protocol::{packets::play, text::IntoText};
 #[cfg(feature = "reflect")]
@@ -43,6 +43,7 @@ impl Plugin for StatsPlugin {
         app.add_systems(
             Update,
             move |compose: Res<'_, Compose>,
+                  info: Res<'_, ServerInfo>,
                   start: Res<'_, UpdateStart>,
                   mut elapsed: ResMut<'_, TicksElapsed>| {
                 if elapsed.0 == 0 {
@@ -55,10 +56,7 @@ impl Plugin for StatsPlugin {
 
                 let span = info_span!("stats");
                 let _enter = span.enter();
-                let player_count = compose
-                    .global()
-                    .player_count
-                    .load(std::sync::atomic::Ordering::Relaxed);
+                let player_count = info.player_count();
 
                 #[expect(clippy::cast_precision_loss)]
                 let ms_per_tick = start.0.elapsed().as_secs_f32() * 1000.0 / (ticks_elapsed as f32);
@@ -7,10 +7,9 @@ use bevy_ecs::{
 };
 use hyperion::{
     egress::player_join::{PlayerListActions, PlayerListEntry, PlayerListS2c},
-    net::{Compose, ConnectionId, DataBundle},
-    simulation::event,
+    net::{Compose, ConnectionId, DataBundle, SendResultExt},
+    simulation::{event, minecraft_id::MinecraftIdRegistry},
 };
-use hyperion_utils::EntityExt;
 use tracing::error;
 use valence_bytes::Utf8Bytes;
 use valence_ident::ident;
@@ -31,10 +30,11 @@ impl Plugin for SkinPlugin {
 fn on_set_skin(
     mut events: MessageReader<'_, '_, event::SetSkin>,
     compose: Res<'_, Compose>,
-    query: Query<'_, '_, (&ConnectionId, &hyperion::simulation::Uuid)>,
+    ids: Res<'_, MinecraftIdRegistry>,
+    query: Query<'_, '_, (Option<&ConnectionId>, &hyperion::simulation::Uuid)>,
 ) {
     for event in events.read() {
-        let (&connection_id, uuid) = match query.get(event.by) {
+        let (connection_id, uuid) = match query.get(event.by) {
             Ok(data) => data,
             Err(e) => {
                 error!("failed to set skin: query failed: {e}");
@@ -42,7 +42,12 @@ fn on_set_skin(
             }
         };
 
-        let minecraft_id = event.by.minecraft_id();
+        // Hyperion respawns NPCs with their new skin itself
+        let Some(&connection_id) = connection_id else {
+            continue;
+        };
+
+        let minecraft_id = ids.minecraft_id(event.by);
         let mut bundle = DataBundle::new(&compose);
         // Remove player info
         bundle
@@ -100,6 +105,6 @@ fn on_set_skin(
             })
             .unwrap();
 
-        bundle.unicast(connection_id).unwrap();
+        bundle.unicast(connection_id).unwrap_or_disconnected();
     }
 }